@@ -43,4 +43,14 @@ pub enum Commands {
         #[clap(long, default_value_t = false)]
         sequential_keys: bool,
     },
+    /// Lease expiry args, every lease has one key attached and all of them expire
+    /// at about the same time
+    LeaseExpiry {
+        /// Total number of leases
+        #[clap(long, default_value_t = 10000)]
+        total: usize,
+        /// Ttl of the leases in seconds
+        #[clap(long, default_value_t = 5)]
+        ttl: i64,
+    },
 }
@@ -1,13 +1,16 @@
 use std::fmt::Debug;
 
 use anyhow::Result;
-use etcd_client::{Client as EtcdClient, ConnectOptions};
+use etcd_client::{Client as EtcdClient, ConnectOptions, GetOptions};
 use thiserror::Error;
 #[cfg(test)]
-use xline_client::types::kv::{RangeRequest, RangeResponse};
+use xline_client::types::kv::RangeResponse;
 use xline_client::{
     error::XlineClientError,
-    types::kv::{PutRequest, PutResponse},
+    types::{
+        kv::{PutRequest, PutResponse, RangeRequest},
+        lease::LeaseGrantRequest,
+    },
     Client, ClientOptions,
 };
 use xlineapi::command::Command;
@@ -110,6 +113,66 @@ impl BenchClient {
         }
     }
 
+    /// Grant a lease by `XlineClient` or `EtcdClient`, returns the id of the lease
+    ///
+    /// # Errors
+    ///
+    /// If `XlineClient` or `EtcdClient` failed to send request
+    #[inline]
+    pub(crate) async fn lease_grant(&mut self, ttl: i64) -> Result<i64, BenchClientError> {
+        match self.kv_client {
+            KVClient::Xline(ref mut xline_client) => {
+                let response = xline_client
+                    .lease_client()
+                    .grant(LeaseGrantRequest::new(ttl))
+                    .await?;
+                Ok(response.id)
+            }
+            KVClient::Etcd(ref mut etcd_client) => {
+                let response = etcd_client.lease_grant(ttl, None).await?;
+                Ok(response.id())
+            }
+        }
+    }
+
+    /// Count the keys with the given prefix by `XlineClient` or `EtcdClient`, returns
+    /// the count and the revision of the store
+    ///
+    /// # Errors
+    ///
+    /// If `XlineClient` or `EtcdClient` failed to send request
+    #[inline]
+    pub(crate) async fn count_prefix(
+        &mut self,
+        prefix: &[u8],
+    ) -> Result<(i64, i64), BenchClientError> {
+        match self.kv_client {
+            KVClient::Xline(ref mut xline_client) => {
+                let response = xline_client
+                    .kv_client()
+                    .range(
+                        RangeRequest::new(prefix)
+                            .with_prefix()
+                            .with_count_only(true),
+                    )
+                    .await?;
+                Ok((response.count, response.header.map_or(0, |h| h.revision)))
+            }
+            KVClient::Etcd(ref mut etcd_client) => {
+                let response = etcd_client
+                    .get(
+                        prefix,
+                        Some(GetOptions::new().with_prefix().with_count_only()),
+                    )
+                    .await?;
+                Ok((
+                    response.count(),
+                    response.header().map_or(0, |h| h.revision()),
+                ))
+            }
+        }
+    }
+
     /// Send `RangeRequest` by `XlineClient` or `EtcdClient`
     ///
     /// # Errors
@@ -169,7 +232,7 @@ mod convert {
                 create_revision: kv.create_revision(),
                 mod_revision: kv.mod_revision(),
                 version: kv.version(),
-                value: kv.value().to_vec(),
+                value: kv.value().to_vec().into(),
                 lease: kv.lease(),
             }),
         }
@@ -194,7 +257,7 @@ mod convert {
                     create_revision: kv.create_revision(),
                     mod_revision: kv.mod_revision(),
                     version: kv.version(),
-                    value: kv.value().to_vec(),
+                    value: kv.value().to_vec().into(),
                     lease: kv.lease(),
                 })
                 .collect(),
@@ -209,6 +272,7 @@ mod convert {
 #[allow(clippy::unwrap_used)]
 #[allow(clippy::indexing_slicing)]
 mod test {
+    use clippy_utilities::OverflowArithmetic;
     use xline_client::types::kv::RangeRequest;
     use xline_test_utils::Cluster;
 
@@ -229,7 +293,7 @@ mod test {
         let _put_response = client.put(request).await;
         let range_request = RangeRequest::new("put");
         let response = client.get(range_request).await.unwrap();
-        assert_eq!(response.kvs[0].value, b"123");
+        assert_eq!(response.kvs[0].value, b"123".as_slice());
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -246,6 +310,29 @@ mod test {
         let _put_response = client.put(request).await;
         let range_request = RangeRequest::new("put");
         let response = client.get(range_request).await.unwrap();
-        assert_eq!(response.kvs[0].value, b"123");
+        assert_eq!(response.kvs[0].value, b"123".as_slice());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_count_keys_of_lease() {
+        let mut cluster = Cluster::new(3).await;
+        cluster.start().await;
+        let config = ClientOptions::default();
+        let mut client = BenchClient::new(cluster.all_client_addrs(), true, config)
+            .await
+            .unwrap();
+
+        let lease = client.lease_grant(60).await.unwrap();
+        let put_response = client
+            .put(PutRequest::new("lease/1", "123").with_lease(lease))
+            .await
+            .unwrap();
+        let _put_response = client.put(PutRequest::new("other", "123")).await.unwrap();
+        let (count, revision) = client.count_prefix(b"lease/").await.unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            revision,
+            put_response.header.unwrap().revision.overflow_add(1)
+        );
     }
 }
@@ -18,12 +18,18 @@ use tokio::{
     },
     time::{Duration, Instant},
 };
-use tracing::debug;
+use tracing::{debug, info};
 use utils::config::ClientConfig;
 use xline_client::{types::kv::PutRequest, ClientOptions};
 
 use crate::{args::Commands, bench_client::BenchClient, Benchmark};
 
+/// Prefix of the keys attached to the leases of the lease expiry benchmark
+const LEASE_KEY_PREFIX: &str = "lease_expiry/";
+
+/// Interval of checking whether the keys of the expired leases are removed
+const EXPIRY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Result of request
 #[derive(Debug)]
 struct CmdResult {
@@ -153,6 +159,9 @@ impl CommandRunner {
                 )
                 .await
             }
+            Commands::LeaseExpiry { total, ttl } => {
+                self.lease_expiry_bench(clients, total, ttl).await
+            }
         }
     }
 
@@ -252,6 +261,86 @@ impl CommandRunner {
         Ok(stats)
     }
 
+    /// Run lease expiry benchmark
+    ///
+    /// Every lease is granted with one key attached, then the benchmark waits until
+    /// the keys of all the expired leases are removed. Revoking leases with keys takes
+    /// one revision per revoke proposal, so the revisions taken tell how many proposals
+    /// the expiry needs. Run it against an otherwise idle cluster.
+    async fn lease_expiry_bench(
+        &mut self,
+        clients: Vec<BenchClient>,
+        total: usize,
+        ttl: i64,
+    ) -> Result<Stats> {
+        let count = Arc::new(AtomicUsize::new(0));
+        let b = Arc::new(Barrier::new(clients.len().overflow_add(1)));
+        let (tx, rx) = mpsc::channel(clients.len());
+
+        let mut handles = Vec::with_capacity(clients.len());
+        for mut client in clients {
+            let c = Arc::clone(&b);
+            let count_clone = Arc::clone(&count);
+            let tx_clone = tx.clone();
+            let handle = tokio::spawn(async move {
+                _ = c.wait().await;
+                loop {
+                    let idx = count_clone.fetch_add(1, Ordering::Relaxed);
+                    if idx >= total {
+                        break;
+                    }
+                    let start = Instant::now();
+                    let result = match client.lease_grant(ttl).await {
+                        Ok(lease) => client
+                            .put(
+                                PutRequest::new(format!("{LEASE_KEY_PREFIX}{idx}"), vec![])
+                                    .with_lease(lease),
+                            )
+                            .await
+                            .map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    let cmd_result = CmdResult {
+                        elapsed: start.elapsed(),
+                        error: result.err().map(|e| format!("{e:?}")),
+                    };
+                    assert!(
+                        tx_clone.send(cmd_result).await.is_ok(),
+                        "failed to send cmd result"
+                    );
+                }
+                client
+            });
+            handles.push(handle);
+        }
+        drop(tx);
+        let stats = self.collecter(rx, b).await;
+        let mut clients = Vec::with_capacity(handles.len());
+        for handle in handles {
+            clients.push(handle.await?);
+        }
+        let Some(checker) = clients.first_mut() else {
+            unreachable!("the benchmark runs with at least one client")
+        };
+
+        let granted = Instant::now();
+        let (remaining, start_revision) = checker.count_prefix(LEASE_KEY_PREFIX.as_bytes()).await?;
+        debug!("waiting for the keys of {remaining} leases to be removed");
+        loop {
+            let (keys, revision) = checker.count_prefix(LEASE_KEY_PREFIX.as_bytes()).await?;
+            if keys == 0 {
+                info!(
+                    "the keys of {total} leases with a ttl of {ttl} secs are removed {:.4} secs after the last grant, taking {} revisions",
+                    granted.elapsed().as_secs_f64(),
+                    revision.overflow_sub(start_revision)
+                );
+                break;
+            }
+            tokio::time::sleep(EXPIRY_POLL_INTERVAL).await;
+        }
+        Ok(stats)
+    }
+
     /// Collect `CmdResult` and process them to `Stats`
     #[allow(
         clippy::as_conversions,
@@ -261,7 +350,7 @@ impl CommandRunner {
     )]
     async fn collecter(&mut self, mut rx: Receiver<CmdResult>, barrier: Arc<Barrier>) -> Stats {
         let bar_len = match self.args.command {
-            Commands::Put { total, .. } => total,
+            Commands::Put { total, .. } | Commands::LeaseExpiry { total, .. } => total,
         };
         let bar = Arc::new(ProgressBar::new(bar_len.numeric_cast()));
 
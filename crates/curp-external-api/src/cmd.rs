@@ -43,12 +43,30 @@ pub trait Command: pri::Serializable + ConflictCheck + PbCodec {
     /// After_sync result
     type ASR: pri::Serializable + PbCodec;
 
+    /// Conflict metadata of the command, computed once when the command is built and
+    /// carried along with it in the log, so that receivers don't derive it again
+    type ConflictMeta: pri::Serializable;
+
     /// Get keys of the command
     fn keys(&self) -> Vec<Self::K>;
 
+    /// Get the conflict metadata of the command
+    fn conflict_meta(&self) -> &Self::ConflictMeta;
+
+    /// Attach the conflict metadata carried along with the command after it's decoded
+    #[must_use]
+    fn with_conflict_meta(self, meta: Self::ConflictMeta) -> Self;
+
     /// Returns `true` if the command is read-only
     fn is_read_only(&self) -> bool;
 
+    /// Type of the command, used to label its metrics. It must be one of a small set of
+    /// static names, so that labeling a command costs no allocation.
+    #[inline]
+    fn kind(&self) -> &'static str {
+        "command"
+    }
+
     /// Prepare the command
     ///
     /// # Errors
@@ -145,6 +163,72 @@ where
         prepare_res: C::PR,
     ) -> Result<C::ASR, C::Error>;
 
+    /// Execute the after_sync callback of a proposal, the id of the proposal is its
+    /// `(client id, sequence number)`. The executor should persist the `record` with the
+    /// writes of the proposal, see [`CommandExecutor::applied_proposals`]. It calls
+    /// [`CommandExecutor::after_sync`] by default.
+    ///
+    /// # Errors
+    /// This function may return an error if there is a problem executing the after_sync callback.
+    async fn after_sync_proposal(
+        &self,
+        cmd: &C,
+        _propose_id: (u64, u64),
+        index: LogIndex,
+        prepare_res: C::PR,
+        _record: ProposalRecord<'_, C>,
+    ) -> Result<C::ASR, C::Error> {
+        self.after_sync(cmd, index, prepare_res).await
+    }
+
+    /// Execute the after_sync callback of the command at `pos` of a batched entry, the
+    /// commands of a batch are after synced in order and the entry is applied once the
+    /// `last` one is. The executor should record the position with the writes of the
+    /// command, so that the applied commands are skipped if the batch is replayed, see
+    /// [`CommandExecutor::applied_in_batches`]. By default it calls
+    /// [`CommandExecutor::after_sync_proposal`] with the index of the entry before the
+    /// batch, unless it's the last command.
+    ///
+    /// # Errors
+    /// This function may return an error if there is a problem executing the after_sync callback.
+    async fn after_sync_in_batch(
+        &self,
+        cmd: &C,
+        propose_id: (u64, u64),
+        index: LogIndex,
+        _pos: usize,
+        last: bool,
+        prepare_res: C::PR,
+        record: ProposalRecord<'_, C>,
+    ) -> Result<C::ASR, C::Error> {
+        let applied = if last { index } else { index.saturating_sub(1) };
+        self.after_sync_proposal(cmd, propose_id, applied, prepare_res, record)
+            .await
+    }
+
+    /// The position of the last applied command of each batched entry that is partially
+    /// applied, as `(index, pos)`
+    ///
+    /// # Errors
+    /// Returns an error if the retrieval fails.
+    fn applied_in_batches(&self) -> Result<Vec<(LogIndex, usize)>, C::Error> {
+        Ok(Vec::new())
+    }
+
+    /// The proposals applied from log entry `from` on, in log order, with the results
+    /// persisted by [`CommandExecutor::after_sync_proposal`]
+    ///
+    /// # Errors
+    /// Returns an error if the retrieval fails.
+    fn applied_proposals(&self, _from: LogIndex) -> Result<Vec<AppliedProposal<C>>, C::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Release the prepare result of a command whose after sync will never be called,
+    /// because its execution failed or its speculatively executed entry is removed from
+    /// the log before being committed
+    fn release(&self, _cmd: &C, _prepare_res: C::PR) {}
+
     /// Set the index of the last log entry that has been successfully applied to the command executor
     ///
     /// # Errors
@@ -171,6 +255,84 @@ where
 
     /// Trigger the barrier of the given trigger id (based on propose id) and log index.
     fn trigger(&self, id: InflightId, index: LogIndex);
+
+    /// The latest checkpoint of the state hash, as `(revision, hash)`, `None` if the
+    /// executor doesn't maintain one
+    fn state_hash(&self) -> Option<(i64, u64)> {
+        None
+    }
+
+    /// The checkpoint of the state hash at the given revision, `None` if it's not
+    /// retained
+    fn state_hash_at(&self, _revision: i64) -> Option<u64> {
+        None
+    }
+
+    /// Called on the leader when the state hashes of `members` diverge from the
+    /// majority at `revision`
+    fn on_state_divergence(&self, _revision: i64, _members: Vec<u64>) {}
+
+    /// The reason the storage of the executor failed, `None` if it's healthy
+    ///
+    /// Unlike an error of a command, a storage failure may leave the state machine
+    /// inconsistent with the log. It poisons the node: the entries are no longer
+    /// applied, and the one that failed is never acknowledged.
+    fn storage_failure(&self) -> Option<String> {
+        None
+    }
+}
+
+/// What an executor persists with the writes of a proposal, so that the proposal is known
+/// to be applied, and its result could be returned to a retry, after a restart
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ProposalRecord<'a, C: Command> {
+    /// Execution result of the proposal, `None` if it's not known here
+    pub er: Option<&'a C::ER>,
+    /// The records of the proposals applied before this log index are no longer needed
+    pub retain_from: LogIndex,
+}
+
+impl<'a, C: Command> ProposalRecord<'a, C> {
+    /// Create a new `ProposalRecord`
+    #[inline]
+    #[must_use]
+    pub fn new(er: Option<&'a C::ER>, retain_from: LogIndex) -> Self {
+        Self { er, retain_from }
+    }
+}
+
+/// A proposal applied by an executor, restored from its [`ProposalRecord`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct AppliedProposal<C: Command> {
+    /// Index of the log entry of the proposal
+    pub index: LogIndex,
+    /// Id of the proposal, as `(client id, sequence number)`
+    pub propose_id: (u64, u64),
+    /// Execution result, `None` if it's not recorded
+    pub er: Option<C::ER>,
+    /// After sync result
+    pub asr: Result<C::ASR, C::Error>,
+}
+
+impl<C: Command> AppliedProposal<C> {
+    /// Create a new `AppliedProposal`
+    #[inline]
+    #[must_use]
+    pub fn new(
+        index: LogIndex,
+        propose_id: (u64, u64),
+        er: Option<C::ER>,
+        asr: Result<C::ASR, C::Error>,
+    ) -> Self {
+        Self {
+            index,
+            propose_id,
+            er,
+            asr,
+        }
+    }
 }
 
 /// Codec for encoding and decoding data into/from the Protobuf format
@@ -11,6 +11,10 @@ pub trait RoleChange: Clone + Send + Sync + 'static {
     /// The `on_calibrate` will be invoked when the current server has been calibrated.
     /// It means that the current server's role will change from Leader to Follower.
     fn on_calibrate(&self);
+
+    /// The `on_term_change` will be invoked when the term of the current server changes.
+    #[inline]
+    fn on_term_change(&self, _term: u64) {}
 }
 
 mock! {
@@ -1,7 +1,7 @@
 use std::{
     fmt::Display,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicBool, AtomicI64, Ordering},
         Arc,
     },
     time::Duration,
@@ -188,10 +188,21 @@ impl Command for TestCommand {
 
     type ASR = LogIndexResult;
 
+    type ConflictMeta = Vec<u32>;
+
     fn keys(&self) -> Vec<Self::K> {
         self.keys.clone()
     }
 
+    fn conflict_meta(&self) -> &Self::ConflictMeta {
+        &self.keys
+    }
+
+    fn with_conflict_meta(mut self, meta: Self::ConflictMeta) -> Self {
+        self.keys = meta;
+        self
+    }
+
     fn is_read_only(&self) -> bool {
         match self.cmd_type {
             TestCommandType::Get => true,
@@ -233,6 +244,7 @@ pub struct TestCE {
     pub store: Arc<Engine>,
     exe_sender: mpsc::UnboundedSender<(TestCommand, TestCommandResult)>,
     after_sync_sender: mpsc::UnboundedSender<(TestCommand, LogIndex)>,
+    storage_fault: Arc<AtomicBool>,
 }
 
 #[async_trait]
@@ -313,6 +325,9 @@ impl CommandExecutor<TestCommand> for TestCE {
         if cmd.as_should_fail {
             return Err(ExecuteError("fail".to_owned()));
         }
+        if self.storage_fault.load(Ordering::Relaxed) {
+            return Err(ExecuteError("injected storage fault".to_owned()));
+        }
         self.after_sync_sender
             .send((cmd.clone(), index))
             .expect("failed to send after sync msg");
@@ -378,7 +393,7 @@ impl CommandExecutor<TestCommand> for TestCE {
 
     async fn snapshot(&self) -> Result<Snapshot, <TestCommand as Command>::Error> {
         self.store
-            .get_snapshot("", &[TEST_TABLE])
+            .get_snapshot("", &[TEST_TABLE, REVISION_TABLE])
             .map_err(|e| ExecuteError(e.to_string()))
     }
 
@@ -406,13 +421,19 @@ impl CommandExecutor<TestCommand> for TestCE {
             .map_err(|e| ExecuteError(e.to_string()))?;
         snapshot.rewind().unwrap();
         self.store
-            .apply_snapshot(snapshot, &[TEST_TABLE])
+            .apply_snapshot(snapshot, &[TEST_TABLE, REVISION_TABLE])
             .await
             .unwrap();
         Ok(())
     }
 
     fn trigger(&self, _id: InflightId, _index: LogIndex) {}
+
+    fn storage_failure(&self) -> Option<String> {
+        self.storage_fault
+            .load(Ordering::Relaxed)
+            .then(|| "injected storage fault".to_owned())
+    }
 }
 
 impl TestCE {
@@ -440,6 +461,11 @@ impl TestCE {
             store,
             exe_sender,
             after_sync_sender,
+            storage_fault: Arc::new(AtomicBool::new(false)),
         }
     }
+
+    pub fn inject_storage_fault(&self) {
+        self.storage_fault.store(true, Ordering::Relaxed);
+    }
 }
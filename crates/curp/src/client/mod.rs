@@ -72,6 +72,31 @@ pub trait ClientApi {
         use_fast_path: bool,
     ) -> Result<ProposeResponse<Self::Cmd>, Self::Error>;
 
+    /// Send propose to the whole cluster without waiting for the execution, return the
+    /// propose id as the ticket to fetch the result with [`ClientApi::wait_synced`]
+    ///
+    /// It returns once the leader has persisted the log entry of the cmd and a super
+    /// quorum of the servers have accepted it speculatively, so the cmd survives leader
+    /// changes. If the cmd conflicts with others, it degrades to waiting for the after
+    /// sync before returning.
+    async fn propose_async(
+        &self,
+        cmd: &Self::Cmd,
+        token: Option<&String>,
+    ) -> Result<ProposeId, Self::Error>;
+
+    /// Wait for the result of a cmd proposed before, return `ResultExpired` if the result
+    /// has been evicted from the result cache, or `SessionExpired` if the client session
+    /// of the cmd has expired
+    async fn wait_synced(
+        &self,
+        propose_id: ProposeId,
+    ) -> Result<ProposeResponse<Self::Cmd>, Self::Error>;
+
+    /// Cancel a cmd proposed before, return `true` if it will never be executed, or
+    /// `false` if it has been appended to the log and will be executed
+    async fn cancel(&self, propose_id: ProposeId) -> Result<bool, Self::Error>;
+
     /// Send propose configuration changes to the cluster
     async fn propose_conf_change(
         &self,
@@ -135,6 +160,14 @@ trait RepeatableClientApi: ClientApi {
         use_fast_path: bool,
     ) -> Result<ProposeResponse<Self::Cmd>, Self::Error>;
 
+    /// Send propose to the whole cluster without waiting for the execution
+    async fn propose_async(
+        &self,
+        propose_id: ProposeId,
+        cmd: &Self::Cmd,
+        token: Option<&String>,
+    ) -> Result<(), Self::Error>;
+
     /// Send propose configuration changes to the cluster
     async fn propose_conf_change(
         &self,
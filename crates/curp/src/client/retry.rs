@@ -1,14 +1,14 @@
-use std::{ops::SubAssign, time::Duration};
+use std::{ops::SubAssign, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use futures::Future;
 use tokio::task::JoinHandle;
-use tracing::warn;
+use tracing::{debug, info_span, warn, Instrument};
 
 use super::{ClientApi, LeaderStateUpdate, ProposeResponse, RepeatableClientApi};
 use crate::{
     members::ServerId,
-    rpc::{ConfChange, CurpError, FetchClusterResponse, Member, ReadState, Redirect},
+    rpc::{ConfChange, CurpError, FetchClusterResponse, Member, ProposeId, ReadState, Redirect},
 };
 
 /// Backoff config
@@ -100,7 +100,7 @@ impl Backoff {
 #[derive(Debug)]
 pub(super) struct Retry<Api> {
     /// Inner client
-    inner: Api,
+    inner: Arc<Api>,
     /// Retry config
     config: RetryConfig,
     /// Background task handle
@@ -122,7 +122,7 @@ where
     /// Create a retry client
     pub(super) fn new(inner: Api, config: RetryConfig, bg_handle: Option<JoinHandle<()>>) -> Self {
         Self {
-            inner,
+            inner: Arc::new(inner),
             config,
             bg_handle,
         }
@@ -148,7 +148,10 @@ where
                 | CurpError::InvalidConfig(())
                 | CurpError::NodeNotExists(())
                 | CurpError::NodeAlreadyExists(())
-                | CurpError::LearnerNotCatchUp(()) => {
+                | CurpError::LearnerNotCatchUp(())
+                | CurpError::ResultExpired(())
+                | CurpError::SessionExpired(())
+                | CurpError::Canceled(()) => {
                     return Err(tonic::Status::from(err));
                 }
 
@@ -159,7 +162,7 @@ where
                 | CurpError::LeaderTransfer(_) => {}
 
                 // update leader state if we got a rpc transport error
-                CurpError::RpcTransport(()) => {
+                CurpError::RpcTransport(()) | CurpError::Unavailable(()) => {
                     if let Err(e) = self.inner.fetch_leader_id(true).await {
                         warn!("fetch leader failed, error {e:?}");
                     }
@@ -195,6 +198,55 @@ where
             last_err.unwrap_or_else(|| unreachable!("last error must be set"))
         )))
     }
+
+    /// Start a guard canceling the proposal if it's abandoned
+    fn cancel_on_drop(&self, propose_id: ProposeId) -> CancelOnDrop<Api> {
+        CancelOnDrop {
+            inner: Arc::clone(&self.inner),
+            propose_id,
+            done: false,
+        }
+    }
+}
+
+/// Cancels a proposal if its request future is dropped before it completes, so the
+/// servers reclaim the resources of the proposal if it's not appended yet
+struct CancelOnDrop<Api: ClientApi<Error = CurpError> + Send + Sync + 'static> {
+    /// Inner client
+    inner: Arc<Api>,
+    /// The propose id of the proposal
+    propose_id: ProposeId,
+    /// Whether the proposal has completed
+    done: bool,
+}
+
+impl<Api: ClientApi<Error = CurpError> + Send + Sync + 'static> CancelOnDrop<Api> {
+    /// The proposal has completed, there's nothing to cancel
+    fn disarm(mut self) {
+        self.done = true;
+    }
+}
+
+impl<Api: ClientApi<Error = CurpError> + Send + Sync + 'static> Drop for CancelOnDrop<Api> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        // dropped outside a runtime, the proposal is left to complete
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let inner = Arc::clone(&self.inner);
+        let propose_id = self.propose_id;
+        let _ignore = handle.spawn(async move {
+            match inner.cancel(propose_id).await {
+                Ok(canceled) => {
+                    debug!("abandoned cmd({propose_id}) canceled before executed: {canceled}");
+                }
+                Err(e) => warn!("cancel abandoned cmd({propose_id}) failed: {e:?}"),
+            }
+        });
+    }
 }
 
 #[async_trait]
@@ -217,10 +269,48 @@ where
         use_fast_path: bool,
     ) -> Result<ProposeResponse<Self::Cmd>, tonic::Status> {
         let propose_id = self.inner.gen_propose_id()?;
-        self.retry::<_, _>(|client| {
-            RepeatableClientApi::propose(client, propose_id, cmd, token, use_fast_path)
-        })
-        .await
+        let guard = self.cancel_on_drop(propose_id);
+        let res = self
+            .retry::<_, _>(|client| {
+                RepeatableClientApi::propose(client, propose_id, cmd, token, use_fast_path)
+            })
+            .instrument(info_span!("client_propose", propose_id = %propose_id))
+            .await;
+        guard.disarm();
+        res
+    }
+
+    /// Send propose to the whole cluster without waiting for the execution, the retries
+    /// reuse the same propose id so the cmd is proposed at most once
+    async fn propose_async(
+        &self,
+        cmd: &Self::Cmd,
+        token: Option<&String>,
+    ) -> Result<ProposeId, tonic::Status> {
+        let propose_id = self.inner.gen_propose_id()?;
+        let guard = self.cancel_on_drop(propose_id);
+        let res = self
+            .retry::<_, _>(|client| {
+                RepeatableClientApi::propose_async(client, propose_id, cmd, token)
+            })
+            .instrument(info_span!("client_propose_async", propose_id = %propose_id))
+            .await;
+        guard.disarm();
+        res.map(|()| propose_id)
+    }
+
+    /// Wait for the result of a cmd proposed before
+    async fn wait_synced(
+        &self,
+        propose_id: ProposeId,
+    ) -> Result<ProposeResponse<Self::Cmd>, tonic::Status> {
+        self.retry::<_, _>(|client| client.wait_synced(propose_id))
+            .await
+    }
+
+    /// Cancel a cmd proposed before
+    async fn cancel(&self, propose_id: ProposeId) -> Result<bool, tonic::Status> {
+        self.retry::<_, _>(|client| client.cancel(propose_id)).await
     }
 
     /// Send propose configuration changes to the cluster
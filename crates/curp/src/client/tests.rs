@@ -300,6 +300,9 @@ async fn test_unary_fast_round_return_early_err() {
         CurpError::node_not_exist(),
         CurpError::learner_not_catch_up(),
         CurpError::expired_client_id(),
+        CurpError::result_expired(),
+        CurpError::session_expired(),
+        CurpError::canceled(),
         CurpError::redirect(Some(1), 0),
     ] {
         assert!(early_err.should_abort_fast_round());
@@ -623,6 +626,27 @@ async fn test_unary_propose_return_early_err() {
     }
 }
 
+#[traced_test]
+#[tokio::test]
+async fn test_unary_cancel_should_only_be_sent_to_leader() {
+    // record how many servers got the request
+    let counter = Arc::new(Mutex::new(0));
+    let connects = init_mocked_connects(5, |id, conn| {
+        let counter_c = Arc::clone(&counter);
+        conn.expect_cancel().return_once(move |_req, _timeout| {
+            counter_c.lock().unwrap().add_assign(1);
+            if id == 0 {
+                Ok(tonic::Response::new(CancelResponse { canceled: true }))
+            } else {
+                Err(CurpError::redirect(Some(0), 1))
+            }
+        });
+    });
+    let unary = init_unary_client(connects, None, Some(0), 1, 0, None);
+    assert!(unary.cancel(ProposeId(0, 0)).await.unwrap());
+    assert_eq!(*counter.lock().unwrap(), 1);
+}
+
 // Tests for retry layer
 
 #[traced_test]
@@ -634,6 +658,9 @@ async fn test_retry_propose_return_no_retry_error() {
         CurpError::node_already_exists(),
         CurpError::node_not_exist(),
         CurpError::learner_not_catch_up(),
+        CurpError::result_expired(),
+        CurpError::session_expired(),
+        CurpError::canceled(),
     ] {
         // record how many times rpc was invoked.
         let counter = Arc::new(Mutex::new(0));
@@ -777,6 +804,15 @@ impl ConnectApi for MockedStreamConnectApi {
         unreachable!("please use MockedConnectApi")
     }
 
+    /// Send `CancelRequest`
+    async fn cancel(
+        &self,
+        _request: CancelRequest,
+        _timeout: Duration,
+    ) -> Result<tonic::Response<CancelResponse>, CurpError> {
+        unreachable!("please use MockedConnectApi")
+    }
+
     /// Send `ShutdownRequest`
     async fn shutdown(
         &self,
@@ -11,9 +11,10 @@ use crate::{
     members::ServerId,
     quorum, recover_quorum,
     rpc::{
-        connect::ConnectApi, ConfChange, CurpError, FetchClusterRequest, FetchClusterResponse,
-        FetchReadStateRequest, Member, MoveLeaderRequest, ProposeConfChangeRequest, ProposeId,
-        ProposeRequest, PublishRequest, ReadState, ShutdownRequest, WaitSyncedRequest,
+        connect::ConnectApi, CancelRequest, ConfChange, CurpError, FetchClusterRequest,
+        FetchClusterResponse, FetchReadStateRequest, Member, MoveLeaderRequest,
+        ProposeConfChangeRequest, ProposeId, ProposeRequest, PublishRequest, ReadState,
+        ShutdownRequest, WaitSyncedRequest,
     },
     super_quorum,
 };
@@ -176,6 +177,78 @@ impl<C: Command> Unary<C> {
         Err(CurpError::wrong_cluster_version())
     }
 
+    /// Send async proposal to all servers, return `true` if the leader has persisted the
+    /// log entry and a super quorum of the servers have accepted the cmd, `false` if only
+    /// the leader has accepted it
+    pub(super) async fn async_round(
+        &self,
+        propose_id: ProposeId,
+        cmd: &C,
+        token: Option<&String>,
+    ) -> Result<bool, CurpError> {
+        let leader_id = match self.state.leader_id().await {
+            Some(id) => id,
+            None => <Unary<C> as ClientApi>::fetch_leader_id(self, false).await?,
+        };
+        let req = ProposeRequest::new_async(propose_id, cmd, self.state.cluster_version().await);
+        let timeout = self.config.propose_timeout;
+
+        let mut responses = self
+            .state
+            .for_each_server(|conn| {
+                let req_c = req.clone();
+                let token_c = token.cloned();
+                async move { (conn.id(), conn.propose(req_c, token_c, timeout).await) }
+            })
+            .await;
+        let super_quorum = super_quorum(responses.len());
+
+        let mut err: Option<CurpError> = None;
+        let mut ok_cnt = 0;
+        let (mut leader_persisted, mut leader_accepted) = (false, false);
+
+        while let Some((id, resp)) = responses.next().await {
+            match resp {
+                Ok(_resp) => {
+                    ok_cnt.add_assign(1);
+                    if id == leader_id {
+                        leader_persisted = true;
+                        leader_accepted = true;
+                    }
+                }
+                Err(e) => {
+                    warn!("async propose cmd({propose_id}) to server({id}) error: {e:?}");
+                    // the leader has appended the cmd, either in this round or in the one
+                    // retried
+                    if id == leader_id
+                        && matches!(e, CurpError::KeyConflict(()) | CurpError::Duplicated(()))
+                    {
+                        leader_accepted = true;
+                        continue;
+                    }
+                    if e.should_abort_fast_round() {
+                        return Err(e);
+                    }
+                    if err
+                        .as_ref()
+                        .map_or(true, |old| old.priority() <= e.priority())
+                    {
+                        err = Some(e);
+                    }
+                }
+            }
+            if leader_persisted && ok_cnt >= super_quorum {
+                debug!("async round for cmd({}) succeed", propose_id);
+                return Ok(true);
+            }
+        }
+
+        if leader_accepted {
+            return Ok(false);
+        }
+        Err(err.unwrap_or_else(CurpError::wrong_cluster_version))
+    }
+
     /// Wait synced result from server
     pub(super) async fn slow_round(
         &self,
@@ -223,6 +296,30 @@ impl<C: Command> ClientApi for Unary<C> {
         RepeatableClientApi::propose(self, propose_id, cmd, token, use_fast_path).await
     }
 
+    /// Send propose to the whole cluster without waiting for the execution
+    async fn propose_async(&self, cmd: &C, token: Option<&String>) -> Result<ProposeId, CurpError> {
+        let propose_id = self.gen_propose_id()?;
+        RepeatableClientApi::propose_async(self, propose_id, cmd, token).await?;
+        Ok(propose_id)
+    }
+
+    /// Wait for the result of a cmd proposed before
+    async fn wait_synced(&self, propose_id: ProposeId) -> Result<ProposeResponse<C>, CurpError> {
+        let sr = self.slow_round(propose_id).await?;
+        Ok(sr.map(|(asr, er)| (er, Some(asr))))
+    }
+
+    /// Send cancel to the leader, it answers once its decision is committed
+    async fn cancel(&self, propose_id: ProposeId) -> Result<bool, CurpError> {
+        let req = CancelRequest::new(propose_id, self.state.cluster_version().await);
+        let timeout = self.config.wait_synced_timeout;
+        let resp = self
+            .map_leader(|conn| async move { conn.cancel(req, timeout).await })
+            .await?
+            .into_inner();
+        Ok(resp.canceled)
+    }
+
     /// Send propose configuration changes to the cluster
     async fn propose_conf_change(
         &self,
@@ -494,6 +591,21 @@ impl<C: Command> RepeatableClientApi for Unary<C> {
         Ok(res)
     }
 
+    /// Send propose to the whole cluster without waiting for the execution
+    async fn propose_async(
+        &self,
+        propose_id: ProposeId,
+        cmd: &Self::Cmd,
+        token: Option<&String>,
+    ) -> Result<(), Self::Error> {
+        if !self.async_round(propose_id, cmd, token).await? {
+            // without a super quorum, the cmd may be lost on leader changes until it's
+            // committed, degrade to waiting for the after sync
+            let _sr = self.slow_round(propose_id).await?;
+        }
+        Ok(())
+    }
+
     /// Send propose configuration changes to the cluster
     async fn propose_conf_change(
         &self,
@@ -168,6 +168,7 @@
 )]
 
 pub use curp_external_api::{InflightId, LogIndex};
+pub use log_entry::LogEntry;
 
 /// Client side, sending requests and determining requests' state
 pub mod client;
@@ -3,8 +3,9 @@ use std::{
     sync::Arc,
 };
 
+use clippy_utilities::OverflowArithmetic;
 use curp_external_api::{cmd::Command, InflightId, LogIndex};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
     members::ServerId,
@@ -14,6 +15,7 @@ use crate::{
 /// Log entry
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq))]
+#[serde(bound = "C: Command")]
 pub struct LogEntry<C> {
     /// Term
     pub(crate) term: u64,
@@ -23,11 +25,24 @@ pub struct LogEntry<C> {
     pub(crate) propose_id: ProposeId,
     /// Entry data
     pub(crate) entry_data: EntryData<C>,
+    /// Position of a command unpacked from a batched entry, never persisted
+    #[serde(skip)]
+    pub(crate) batch: Option<BatchPos>,
+}
+
+/// Position of a command unpacked from a batched entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BatchPos {
+    /// Position of the command in the batch
+    pub(crate) pos: usize,
+    /// Whether it's the last command of the batch to be applied
+    pub(crate) last: bool,
 }
 
 /// Entry data of a `LogEntry`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq))]
+#[serde(bound = "C: Command")]
 pub(crate) enum EntryData<C> {
     /// Empty entry
     Empty,
@@ -39,6 +54,89 @@ pub(crate) enum EntryData<C> {
     Shutdown,
     /// `SetNodeState` entry
     SetNodeState(ServerId, String, Vec<String>),
+    /// Batched `Command`s, applied in order
+    Commands(Vec<(ProposeId, Arc<C>)>),
+    /// `SetClusterVersion` entry, sets the cluster server version negotiated by the leader
+    SetClusterVersion(u32),
+    /// `ExpireSessions` entry, drops the idle client sessions found by the leader, up to
+    /// the largest seq nums the leader saw in them
+    ExpireSessions(Vec<(u64, u64)>),
+    /// `Cancel` entry, the cmd of the propose id is not executed if it's appended later
+    Cancel(ProposeId),
+    /// `Command` entry carrying the conflict metadata of the command
+    MetaCommand(#[serde(with = "meta_cmd")] Arc<C>),
+    /// Batched `Command`s carrying the conflict metadata of each command
+    MetaCommands(#[serde(with = "meta_cmds")] Vec<(ProposeId, Arc<C>)>),
+}
+
+impl<C> EntryData<C> {
+    /// Carry the conflict metadata of the commands in the entry, so that the members
+    /// receiving it don't compute the metadata again
+    pub(crate) fn with_conflict_meta(self) -> Self {
+        match self {
+            EntryData::Command(cmd) => EntryData::MetaCommand(cmd),
+            EntryData::Commands(cmds) => EntryData::MetaCommands(cmds),
+            data @ (EntryData::Empty
+            | EntryData::ConfChange(_)
+            | EntryData::Shutdown
+            | EntryData::SetNodeState(_, _, _)
+            | EntryData::SetClusterVersion(_)
+            | EntryData::ExpireSessions(_)
+            | EntryData::Cancel(_)
+            | EntryData::MetaCommand(_)
+            | EntryData::MetaCommands(_)) => data,
+        }
+    }
+}
+
+/// Serialization of a command along with its conflict metadata
+mod meta_cmd {
+    use super::{Arc, Command, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Serialize the metadata ahead of the command
+    pub(super) fn serialize<C: Command, S: Serializer>(
+        cmd: &Arc<C>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (cmd.conflict_meta(), cmd.as_ref()).serialize(serializer)
+    }
+
+    /// Deserialize the command and attach the metadata to it
+    pub(super) fn deserialize<'de, C: Command, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<C>, D::Error> {
+        let (meta, cmd) = <(C::ConflictMeta, C)>::deserialize(deserializer)?;
+        Ok(Arc::new(cmd.with_conflict_meta(meta)))
+    }
+}
+
+/// Serialization of batched commands along with their conflict metadata
+mod meta_cmds {
+    use super::{Arc, Command, Deserialize, Deserializer, ProposeId, Serializer};
+
+    /// Serialize the metadata of each command ahead of it
+    #[allow(clippy::ptr_arg)] // serde passes the field as is
+    pub(super) fn serialize<C: Command, S: Serializer>(
+        cmds: &Vec<(ProposeId, Arc<C>)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            cmds.iter()
+                .map(|&(id, ref cmd)| (id, cmd.conflict_meta(), cmd.as_ref())),
+        )
+    }
+
+    /// Deserialize the commands and attach the metadata to each of them
+    pub(super) fn deserialize<'de, C: Command, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(ProposeId, Arc<C>)>, D::Error> {
+        Ok(
+            Vec::<(ProposeId, C::ConflictMeta, C)>::deserialize(deserializer)?
+                .into_iter()
+                .map(|(id, meta, cmd)| (id, Arc::new(cmd.with_conflict_meta(meta))))
+                .collect(),
+        )
+    }
 }
 
 impl<C> From<Arc<C>> for EntryData<C> {
@@ -47,6 +145,12 @@ impl<C> From<Arc<C>> for EntryData<C> {
     }
 }
 
+impl<C> From<Vec<(ProposeId, Arc<C>)>> for EntryData<C> {
+    fn from(cmds: Vec<(ProposeId, Arc<C>)>) -> Self {
+        EntryData::Commands(cmds)
+    }
+}
+
 impl<C> From<Vec<ConfChange>> for EntryData<C> {
     fn from(value: Vec<ConfChange>) -> Self {
         Self::ConfChange(value)
@@ -84,13 +188,129 @@ where
             index,
             propose_id,
             entry_data: entry_data.into(),
+            batch: None,
         }
     }
 
+    /// Create a new command `LogEntry`
+    #[inline]
+    #[must_use]
+    pub fn new_command(index: LogIndex, term: u64, propose_id: ProposeId, cmd: Arc<C>) -> Self {
+        Self::new(index, term, propose_id, cmd)
+    }
+
     /// Get the inflight id of this log entry
     pub(super) fn inflight_id(&self) -> InflightId {
         propose_id_to_inflight_id(self.propose_id)
     }
+
+    /// Get the index of this log entry
+    #[inline]
+    #[must_use]
+    pub fn index(&self) -> LogIndex {
+        self.index
+    }
+
+    /// Get the term of this log entry
+    #[inline]
+    #[must_use]
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Get the command of this log entry, return `None` if it's not a command entry
+    #[inline]
+    #[must_use]
+    pub fn command(&self) -> Option<&Arc<C>> {
+        if let EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) = self.entry_data {
+            Some(cmd)
+        } else {
+            None
+        }
+    }
+
+    /// Split a batched entry into one command entry per command, each of which has the
+    /// term and index of the batch, other entries are returned as is
+    pub(super) fn unpack(self: &Arc<Self>) -> Vec<Arc<Self>> {
+        let (EntryData::Commands(ref cmds) | EntryData::MetaCommands(ref cmds)) = self.entry_data
+        else {
+            return vec![Arc::clone(self)];
+        };
+        let len = cmds.len();
+        cmds.iter()
+            .enumerate()
+            .map(|(pos, &(propose_id, ref cmd))| {
+                let mut entry = Self::new(self.index, self.term, propose_id, Arc::clone(cmd));
+                entry.batch = Some(BatchPos {
+                    pos,
+                    last: pos.overflow_add(1) == len,
+                });
+                Arc::new(entry)
+            })
+            .collect()
+    }
+
+    /// Mark a command unpacked from a batched entry as the last one to be applied, the
+    /// ones after it are skipped
+    pub(super) fn mark_last_in_batch(self: &Arc<Self>) -> Arc<Self> {
+        let Some(pos) = self.batch.filter(|b| !b.last) else {
+            return Arc::clone(self);
+        };
+        let mut entry = Self::clone(self);
+        entry.batch = Some(BatchPos { last: true, ..pos });
+        Arc::new(entry)
+    }
+
+    /// The index applied once this entry is after synced, a batched entry is applied
+    /// only after its last command
+    pub(super) fn applied_index(&self) -> LogIndex {
+        match self.batch {
+            Some(BatchPos { last: false, .. }) => self.index.overflow_sub(1),
+            Some(_) | None => self.index,
+        }
+    }
+
+    /// Check if this is an empty entry
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        matches!(self.entry_data, EntryData::Empty)
+    }
+
+    /// Get a short description of the entry type
+    #[inline]
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self.entry_data {
+            EntryData::Empty => "Empty",
+            EntryData::Command(_) => "Command",
+            EntryData::ConfChange(_) => "ConfChange",
+            EntryData::Shutdown => "Shutdown",
+            EntryData::SetNodeState(_, _, _) => "SetNodeState",
+            EntryData::Commands(_) => "Commands",
+            EntryData::SetClusterVersion(_) => "SetClusterVersion",
+            EntryData::ExpireSessions(_) => "ExpireSessions",
+            EntryData::Cancel(_) => "Cancel",
+            EntryData::MetaCommand(_) => "Command",
+            EntryData::MetaCommands(_) => "Commands",
+        }
+    }
+
+    /// Get the label of the entry type in metrics, command entries are labeled by the
+    /// kind of their command
+    pub(crate) fn metric_label(&self) -> &'static str {
+        match self.entry_data {
+            EntryData::Empty => "empty",
+            EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) => cmd.kind(),
+            EntryData::ConfChange(_) => "conf_change",
+            EntryData::Shutdown => "shutdown",
+            EntryData::SetNodeState(_, _, _) => "set_node_state",
+            EntryData::Commands(_) | EntryData::MetaCommands(_) => "batch",
+            EntryData::SetClusterVersion(_) => "set_cluster_version",
+            EntryData::ExpireSessions(_) => "expire_sessions",
+            EntryData::Cancel(_) => "cancel",
+        }
+    }
 }
 
 /// Propose id to inflight id
@@ -100,3 +320,130 @@ pub(super) fn propose_id_to_inflight_id(id: ProposeId) -> InflightId {
     id.1.hash(&mut hasher);
     hasher.finish()
 }
+
+#[cfg(test)]
+mod test {
+    use curp_test_utils::test_cmd::TestCommand;
+
+    use super::*;
+
+    /// Offset of the variant tag of `EntryData` in a serialized `LogEntry`, after the
+    /// term, the index and the propose id
+    const ENTRY_DATA_TAG_OFFSET: usize = 32;
+
+    #[test]
+    fn entry_data_encoding_is_stable() {
+        let variants: Vec<EntryData<TestCommand>> = vec![
+            EntryData::Empty,
+            EntryData::Command(Arc::new(TestCommand::new_put(vec![1], 1))),
+            EntryData::ConfChange(vec![ConfChange::default()]),
+            EntryData::Shutdown,
+            EntryData::SetNodeState(1, "node".to_owned(), vec!["url".to_owned()]),
+            EntryData::Commands(vec![(
+                ProposeId(5, 6),
+                Arc::new(TestCommand::new_put(vec![1], 1)),
+            )]),
+            EntryData::SetClusterVersion(1),
+            EntryData::ExpireSessions(vec![(7, 8)]),
+            EntryData::Cancel(ProposeId(9, 10)),
+            EntryData::MetaCommand(Arc::new(TestCommand::new_put(vec![1], 1))),
+            EntryData::MetaCommands(vec![(
+                ProposeId(5, 6),
+                Arc::new(TestCommand::new_put(vec![1], 1)),
+            )]),
+        ];
+        // persisted logs rely on the tags, new variants must be appended
+        for (tag, entry_data) in (0_u32..).zip(variants) {
+            let entry = LogEntry::new(2, 1, ProposeId(3, 4), entry_data);
+            let bytes = bincode::serialize(&entry).unwrap();
+            assert_eq!(
+                bytes[..ENTRY_DATA_TAG_OFFSET],
+                [
+                    [1_u8, 0, 0, 0, 0, 0, 0, 0],
+                    [2, 0, 0, 0, 0, 0, 0, 0],
+                    [3, 0, 0, 0, 0, 0, 0, 0],
+                    [4, 0, 0, 0, 0, 0, 0, 0]
+                ]
+                .concat()
+            );
+            assert_eq!(
+                bytes[ENTRY_DATA_TAG_OFFSET..ENTRY_DATA_TAG_OFFSET + 4],
+                tag.to_le_bytes()
+            );
+            let decoded: LogEntry<TestCommand> = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(decoded, entry);
+        }
+    }
+
+    #[test]
+    fn meta_entries_carry_the_conflict_meta_ahead_of_each_command() {
+        let cmd = Arc::new(TestCommand::new_put(vec![1, 2], 1));
+        let data = EntryData::from(Arc::clone(&cmd)).with_conflict_meta();
+        let entry = LogEntry::new(2, 1, ProposeId(3, 4), data);
+        assert!(matches!(entry.entry_data, EntryData::MetaCommand(_)));
+        let bytes = bincode::serialize(&entry).unwrap();
+        let meta = bincode::serialize(cmd.conflict_meta()).unwrap();
+        let start = ENTRY_DATA_TAG_OFFSET + 4;
+        assert_eq!(bytes[start..start + meta.len()], meta);
+        let decoded: LogEntry<TestCommand> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.command(), Some(&cmd));
+
+        let data = EntryData::from(vec![(ProposeId(1, 1), Arc::clone(&cmd))]).with_conflict_meta();
+        let entry = Arc::new(LogEntry::new(3, 1, ProposeId(1, 1), data));
+        assert!(matches!(entry.entry_data, EntryData::MetaCommands(_)));
+        let bytes = bincode::serialize(&entry).unwrap();
+        let decoded: Arc<LogEntry<TestCommand>> = Arc::new(bincode::deserialize(&bytes).unwrap());
+        assert_eq!(decoded.unpack()[0].command(), Some(&cmd));
+    }
+
+    #[test]
+    fn empty_entry_decodes_from_persisted_bytes() {
+        // a no-op entry of term 1, index 2, propose id (3, 4) written by an older version
+        let bytes: Vec<u8> = [
+            [1, 0, 0, 0, 0, 0, 0, 0].as_slice(),
+            &[2, 0, 0, 0, 0, 0, 0, 0],
+            &[3, 0, 0, 0, 0, 0, 0, 0],
+            &[4, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        let entry: LogEntry<TestCommand> = bincode::deserialize(&bytes).unwrap();
+        assert!(entry.is_empty());
+        assert_eq!(entry.propose_id, ProposeId(3, 4));
+        assert_eq!(entry.kind(), "Empty");
+    }
+
+    #[test]
+    fn batched_entry_unpacks_into_ordered_command_entries() {
+        let cmds = vec![
+            (ProposeId(1, 1), Arc::new(TestCommand::new_put(vec![1], 1))),
+            (ProposeId(1, 2), Arc::new(TestCommand::new_put(vec![2], 2))),
+        ];
+        let entry = Arc::new(LogEntry::new(3, 2, ProposeId(1, 1), cmds.clone()));
+        let unpacked = entry.unpack();
+        assert_eq!(unpacked.len(), 2);
+        for (e, (propose_id, cmd)) in unpacked.iter().zip(cmds) {
+            assert_eq!((e.index, e.term, e.propose_id), (3, 2, propose_id));
+            assert_eq!(e.command(), Some(&cmd));
+        }
+        // the batch is applied only after its last command
+        assert_eq!(
+            unpacked
+                .iter()
+                .map(|e| e.applied_index())
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        let first = unpacked[0].mark_last_in_batch();
+        assert_eq!(first.batch, Some(BatchPos { pos: 0, last: true }));
+        assert_eq!(first.applied_index(), 3);
+
+        let entry = Arc::new(LogEntry::<TestCommand>::new(
+            4,
+            2,
+            ProposeId(1, 3),
+            EntryData::Empty,
+        ));
+        assert_eq!(entry.unpack(), vec![entry]);
+    }
+}
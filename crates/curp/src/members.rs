@@ -2,7 +2,7 @@ use std::{
     collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicU32, AtomicU64, Ordering},
         Arc,
     },
     time::Duration,
@@ -22,6 +22,42 @@ use crate::rpc::{self, FetchClusterRequest, FetchClusterResponse, Member};
 /// Server Id
 pub type ServerId = u64;
 
+/// Version of the features this server supports, bumped whenever a server starts to
+/// emit or apply something the servers before it can't handle
+pub const SERVER_VERSION: u32 = 3;
+
+/// Features gated by the cluster server version, enabled only when every member
+/// supports them, so that no member is sent what it can't apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Feature {
+    /// Log entries batching several commands
+    BatchedEntries,
+    /// Lease revocations deleting the keys of a lease in chunks, across several applies
+    ChunkedLeaseRevoke,
+    /// Expired leases revoked by proposals carrying several leases
+    BatchedLeaseRevoke,
+    /// Idle client sessions expired through the log, the sessions are kept until then
+    SessionExpiry,
+    /// Command entries carrying the conflict metadata of their commands
+    ConflictMeta,
+}
+
+impl Feature {
+    /// The server version from which the feature is supported
+    #[must_use]
+    #[inline]
+    pub fn since(self) -> u32 {
+        match self {
+            Feature::BatchedEntries | Feature::ChunkedLeaseRevoke | Feature::BatchedLeaseRevoke => {
+                1
+            }
+            Feature::SessionExpiry => 2,
+            Feature::ConflictMeta => 3,
+        }
+    }
+}
+
 /// Cluster member
 impl Member {
     /// Create a new `Member`
@@ -82,6 +118,11 @@ pub struct ClusterInfo {
     members: DashMap<ServerId, Member>,
     /// cluster version
     cluster_version: Arc<AtomicU64>,
+    /// The minimum server version of the members, changed by applying the entries
+    /// of the leader negotiating it
+    cluster_server_version: Arc<AtomicU32>,
+    /// Index of the last conf change entry reflected in the members
+    membership_index: Arc<AtomicU64>,
 }
 
 impl ClusterInfo {
@@ -94,6 +135,9 @@ impl ClusterInfo {
             member_id,
             members: members.into_iter().map(|m| (m.id, m)).collect(),
             cluster_version: Arc::new(AtomicU64::new(0)),
+            // every member learns the version at the same index, from the log or a snapshot
+            cluster_server_version: Arc::new(AtomicU32::new(0)),
+            membership_index: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -125,6 +169,9 @@ impl ClusterInfo {
             member_id,
             members,
             cluster_version: Arc::new(AtomicU64::new(0)),
+            // every member learns the version at the same index, from the log or a snapshot
+            cluster_server_version: Arc::new(AtomicU32::new(0)),
+            membership_index: Arc::new(AtomicU64::new(0)),
         };
         cluster_info.gen_cluster_id();
         cluster_info
@@ -166,6 +213,11 @@ impl ClusterInfo {
             member_id,
             members,
             cluster_version: Arc::new(AtomicU64::new(cluster.cluster_version)),
+            // every member learns the version at the same index, from the log or a snapshot
+            cluster_server_version: Arc::new(AtomicU32::new(0)),
+            // the members fetched are not tied to an index, the conf changes in the log
+            // are applied over them
+            membership_index: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -179,11 +231,14 @@ impl ClusterInfo {
             .collect()
     }
 
-    /// Get all members vec
+    /// Get all members vec in ascending order of the ids, the members are copied out
+    /// before they are sorted so that concurrent conf changes can't reorder them
     #[must_use]
     #[inline]
     pub fn all_members_vec(&self) -> Vec<Member> {
-        self.members.iter().map(|t| t.value().clone()).collect()
+        let mut members: Vec<_> = self.members.iter().map(|t| t.value().clone()).collect();
+        members.sort_unstable_by_key(Member::id);
+        members
     }
 
     /// Insert a member
@@ -333,6 +388,42 @@ impl ClusterInfo {
         self.cluster_version.load(Ordering::Relaxed)
     }
 
+    /// Get the cluster server version, the minimum server version of the members
+    #[must_use]
+    #[inline]
+    pub fn cluster_server_version(&self) -> u32 {
+        self.cluster_server_version.load(Ordering::Relaxed)
+    }
+
+    /// Set the cluster server version, it must only be changed by the apply of the
+    /// log, or every member may have different features enabled at the same index
+    #[inline]
+    pub fn set_cluster_server_version(&self, version: u32) {
+        self.cluster_server_version
+            .store(version, Ordering::Relaxed);
+    }
+
+    /// Get the index of the last conf change entry reflected in the members, the conf
+    /// change entries at or before it are not applied again
+    #[must_use]
+    #[inline]
+    pub fn membership_index(&self) -> u64 {
+        self.membership_index.load(Ordering::Relaxed)
+    }
+
+    /// Set the index of the last conf change entry reflected in the members
+    #[inline]
+    pub fn set_membership_index(&self, index: u64) {
+        self.membership_index.store(index, Ordering::Relaxed);
+    }
+
+    /// Whether a feature is supported by every member of the cluster
+    #[must_use]
+    #[inline]
+    pub fn feature_enabled(&self, feature: Feature) -> bool {
+        self.cluster_server_version() >= feature.since()
+    }
+
     /// cluster version decrease
     pub(crate) fn cluster_version_update(&self) {
         let mut hasher = std::collections::hash_map::DefaultHasher::new();
@@ -511,4 +602,32 @@ mod tests {
         assert!(peer_urls.iter().find(|url| ***url == node1_url).is_none());
         assert!(peer_ids.iter().find(|id| **id == node1_id).is_none());
     }
+
+    #[test]
+    fn all_members_vec_should_be_sorted_during_conf_changes() {
+        let all_members = HashMap::from([
+            ("S1".to_owned(), vec!["S1".to_owned()]),
+            ("S2".to_owned(), vec!["S2".to_owned()]),
+            ("S3".to_owned(), vec!["S3".to_owned()]),
+        ]);
+        let cluster = Arc::new(ClusterInfo::from_members_map(all_members, [], "S1"));
+        let voters: Vec<_> = cluster.all_members().into_keys().collect();
+
+        let churn_cluster = Arc::clone(&cluster);
+        let churn = std::thread::spawn(move || {
+            for id in 0..1000 {
+                let _ignore = churn_cluster.insert(Member::new(id, "", vec![], vec![], true));
+                if id % 2 == 0 {
+                    let _ignore = churn_cluster.remove(&id);
+                }
+            }
+        });
+        while !churn.is_finished() {
+            let members = cluster.all_members_vec();
+            assert!(members.windows(2).all(|w| w[0].id < w[1].id));
+            assert!(voters.iter().all(|id| members.iter().any(|m| m.id == *id)));
+        }
+        churn.join().unwrap();
+        assert_eq!(cluster.all_members_vec().len(), voters.len() + 500);
+    }
 }
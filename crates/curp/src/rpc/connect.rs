@@ -8,12 +8,13 @@ use std::{
 
 use async_stream::stream;
 use async_trait::async_trait;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use clippy_utilities::NumericCast;
 use engine::SnapshotApi;
 use futures::{stream::FuturesUnordered, Stream};
 #[cfg(test)]
 use mockall::automock;
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
@@ -30,15 +31,15 @@ use crate::{
             commandpb::protocol_client::ProtocolClient,
             inner_messagepb::inner_protocol_client::InnerProtocolClient,
         },
-        AppendEntriesRequest, AppendEntriesResponse, CurpError, FetchClusterRequest,
-        FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
+        AppendEntriesRequest, AppendEntriesResponse, CancelRequest, CancelResponse, CurpError,
+        FetchClusterRequest, FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
         InstallSnapshotRequest, InstallSnapshotResponse, LeaseKeepAliveMsg, MoveLeaderRequest,
         MoveLeaderResponse, ProposeConfChangeRequest, ProposeConfChangeResponse, ProposeRequest,
         ProposeResponse, Protocol, PublishRequest, PublishResponse, ShutdownRequest,
         ShutdownResponse, TriggerShutdownRequest, TryBecomeLeaderNowRequest, VoteRequest,
         VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
     },
-    snapshot::Snapshot,
+    snapshot::{SnapshotThrottle, SnapshotTransfer},
 };
 
 /// Install snapshot chunk size: 64KB
@@ -186,6 +187,13 @@ pub(crate) trait ConnectApi: Send + Sync + 'static {
         timeout: Duration,
     ) -> Result<tonic::Response<WaitSyncedResponse>, CurpError>;
 
+    /// Send `CancelRequest`
+    async fn cancel(
+        &self,
+        request: CancelRequest,
+        timeout: Duration,
+    ) -> Result<tonic::Response<CancelResponse>, CurpError>;
+
     /// Send `ShutdownRequest`
     async fn shutdown(
         &self,
@@ -242,12 +250,23 @@ pub(crate) trait InnerConnectApi: Send + Sync + 'static {
         timeout: Duration,
     ) -> Result<tonic::Response<VoteResponse>, tonic::Status>;
 
-    /// Send a snapshot
+    /// Send a snapshot from `offset`, throttled by the given throttle
     async fn install_snapshot(
         &self,
         term: u64,
         leader_id: ServerId,
-        snapshot: Snapshot,
+        transfer: Arc<SnapshotTransfer>,
+        offset: u64,
+        throttle: Arc<SnapshotThrottle>,
+    ) -> Result<tonic::Response<InstallSnapshotResponse>, tonic::Status>;
+
+    /// Ask the follower for the offset to resume a snapshot transfer from
+    async fn probe_snapshot(
+        &self,
+        term: u64,
+        leader_id: ServerId,
+        transfer: Arc<SnapshotTransfer>,
+        timeout: Duration,
     ) -> Result<tonic::Response<InstallSnapshotResponse>, tonic::Status>;
 
     /// Trigger follower shutdown
@@ -455,6 +474,20 @@ impl ConnectApi for Connect<ProtocolClient<Channel>> {
         client.wait_synced(req).await.map_err(Into::into)
     }
 
+    /// Send `CancelRequest`
+    #[instrument(skip(self), name = "client cancel")]
+    async fn cancel(
+        &self,
+        request: CancelRequest,
+        timeout: Duration,
+    ) -> Result<tonic::Response<CancelResponse>, CurpError> {
+        let mut client = self.rpc_connect.clone();
+        let mut req = tonic::Request::new(request);
+        req.set_timeout(timeout);
+        req.metadata_mut().inject_current();
+        client.cancel(req).await.map_err(Into::into)
+    }
+
     /// Send `FetchClusterRequest`
     async fn fetch_cluster(
         &self,
@@ -567,12 +600,14 @@ impl InnerConnectApi for Connect<InnerProtocolClient<Channel>> {
         &self,
         term: u64,
         leader_id: ServerId,
-        snapshot: Snapshot,
+        transfer: Arc<SnapshotTransfer>,
+        offset: u64,
+        throttle: Arc<SnapshotThrottle>,
     ) -> Result<tonic::Response<InstallSnapshotResponse>, tonic::Status> {
         #[cfg(feature = "client-metrics")]
-        let start_at = self.before_rpc_with_size(snapshot.inner().size());
+        let start_at = self.before_rpc_with_size(transfer.size().saturating_sub(offset));
 
-        let stream = install_snapshot_stream(term, leader_id, snapshot);
+        let stream = install_snapshot_stream(term, leader_id, transfer, offset, throttle, self.id);
         let mut client = self.rpc_connect.clone();
         let result = client.install_snapshot(stream).await;
 
@@ -582,6 +617,31 @@ impl InnerConnectApi for Connect<InnerProtocolClient<Channel>> {
         result
     }
 
+    async fn probe_snapshot(
+        &self,
+        term: u64,
+        leader_id: ServerId,
+        transfer: Arc<SnapshotTransfer>,
+        timeout: Duration,
+    ) -> Result<tonic::Response<InstallSnapshotResponse>, tonic::Status> {
+        let meta = transfer.meta();
+        let probe = InstallSnapshotRequest {
+            term,
+            leader_id,
+            last_included_index: meta.last_included_index,
+            last_included_term: meta.last_included_term,
+            cluster_server_version: meta.cluster_server_version,
+            membership_index: meta.membership_index,
+            transfer_id: transfer.id(),
+            probe: true,
+            ..InstallSnapshotRequest::default()
+        };
+        let mut client = self.rpc_connect.clone();
+        let mut req = tonic::Request::new(futures::stream::iter([probe]));
+        req.set_timeout(timeout);
+        client.install_snapshot(req).await
+    }
+
     async fn trigger_shutdown(&self) -> Result<(), tonic::Status> {
         #[cfg(feature = "client-metrics")]
         let start_at = self.before_rpc::<TriggerShutdownRequest>();
@@ -630,6 +690,18 @@ impl<T: Protocol> BypassedConnect<T> {
 /// Metadata key of a bypassed request
 const BYPASS_KEY: &str = "bypass";
 
+/// Extension of a request dispatched in process by a bypassed connect, unlike the
+/// metadata it can't be set by remote clients
+#[derive(Debug, Clone, Copy)]
+struct BypassedInProcess;
+
+/// Check whether a request is dispatched in process by a bypassed connect
+#[inline]
+#[must_use]
+pub fn is_bypassed_in_process<T>(request: &tonic::Request<T>) -> bool {
+    request.extensions().get::<BypassedInProcess>().is_some()
+}
+
 /// Inject bypassed message into a request's metadata and check if it is a bypassed request.
 /// A bypass request can skip the check for lease expiration (there will never be a disconnection from oneself).
 pub(crate) trait Bypass {
@@ -683,6 +755,7 @@ where
         let mut req = tonic::Request::new(request);
         req.metadata_mut().inject_bypassed();
         req.metadata_mut().inject_current();
+        let _ignore = req.extensions_mut().insert(BypassedInProcess);
         if let Some(token) = token {
             _ = req.metadata_mut().insert("token", token.parse()?);
         }
@@ -728,6 +801,18 @@ where
         self.server.wait_synced(req).await.map_err(Into::into)
     }
 
+    /// Send `CancelRequest`
+    async fn cancel(
+        &self,
+        request: CancelRequest,
+        _timeout: Duration,
+    ) -> Result<tonic::Response<CancelResponse>, CurpError> {
+        let mut req = tonic::Request::new(request);
+        req.metadata_mut().inject_bypassed();
+        req.metadata_mut().inject_current();
+        self.server.cancel(req).await.map_err(Into::into)
+    }
+
     /// Send `ShutdownRequest`
     async fn shutdown(
         &self,
@@ -799,58 +884,97 @@ fn heartbeat_stream(client_id: u64, interval: Duration) -> impl Stream<Item = Le
     }
 }
 
-/// Generate install snapshot stream
+/// Generate install snapshot stream from `offset`, the reads and the chunks sent to `to`
+/// are throttled and the progress is tracked by the throttle
+///
+/// The bytes before `offset` are already staged by the follower, they are read but not
+/// sent, so that the digest of the whole snapshot is still sent with the last chunk.
 fn install_snapshot_stream(
     term: u64,
     leader_id: ServerId,
-    snapshot: Snapshot,
+    transfer: Arc<SnapshotTransfer>,
+    offset: u64,
+    throttle: Arc<SnapshotThrottle>,
+    to: ServerId,
 ) -> impl Stream<Item = InstallSnapshotRequest> {
     stream! {
-        let meta = snapshot.meta;
-        let mut snapshot = snapshot.into_inner();
-        let mut offset = 0;
+        let meta = transfer.meta();
+        let mut snapshot = transfer.lock().await;
+        let size = snapshot.size();
+        let mut pos = 0;
         if let Err(e) = snapshot.rewind() {
             error!("snapshot seek failed, {e}");
             return;
         }
+        let mut hasher = Sha256::new();
+        let tracker = throttle.track(to, size.saturating_sub(offset));
         #[allow(clippy::arithmetic_side_effects)] // can't overflow
-        while offset < snapshot.size() {
-            let len: u64 =
-                std::cmp::min(snapshot.size() - offset, SNAPSHOT_CHUNK_SIZE).numeric_cast();
+        while pos < size {
+            let len: u64 = std::cmp::min(size - pos, SNAPSHOT_CHUNK_SIZE).numeric_cast();
+            let chunk_offset = pos;
+            pos += len;
+            // the chunks are the same in every stream of a transfer, but a resume in the
+            // middle of one is allowed anyway
+            let skip = offset.saturating_sub(chunk_offset).min(len);
+            throttle.throttle(len, len - skip).await;
             let mut data = BytesMut::with_capacity(len.numeric_cast());
             if let Err(e) = snapshot.read_buf_exact(&mut data).await {
                 error!("read snapshot error, {e}");
                 break;
             }
+            hasher.update(&data);
+            if pos <= offset {
+                continue;
+            }
+            let data = data.freeze().slice(skip.numeric_cast::<usize>()..);
+            let len = len - skip;
+            let done = pos == size;
             yield InstallSnapshotRequest {
                 term,
                 leader_id,
                 last_included_index: meta.last_included_index,
                 last_included_term: meta.last_included_term,
-                offset,
-                data: data.freeze(),
-                done: (offset + len) == snapshot.size(),
+                offset: chunk_offset + skip,
+                data,
+                done,
+                result_cache: if done { transfer.results() } else { Bytes::new() },
+                cluster_server_version: meta.cluster_server_version,
+                membership_index: meta.membership_index,
+                members: if done { transfer.members() } else { Vec::new() },
+                transfer_id: transfer.id(),
+                probe: false,
+                digest: if done {
+                    Bytes::from(hasher.finalize_reset().to_vec())
+                } else {
+                    Bytes::new()
+                },
             };
 
-            offset += len;
-        }
-        // TODO: Shall we clean snapshot after stream generation complete
-        if let Err(e) = snapshot.clean().await {
-            error!("snapshot clean error, {e}");
+            if let Some(progress) = tracker.advance(len) {
+                debug!(
+                    "sent {} bytes of snapshot to {to}, rate {} B/s, eta {:?}",
+                    progress.sent(),
+                    progress.rate(),
+                    progress.eta()
+                );
+            }
         }
+        drop(tracker);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use bytes::Bytes;
-    use engine::{EngineType, Snapshot as EngineSnapshot};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    use engine::{Engine, EngineType, Snapshot as EngineSnapshot, StorageEngine, WriteOperation};
     use futures::{pin_mut, StreamExt};
     use test_macros::abort_on_panic;
+    use tokio::time::Instant;
     use tracing_test::traced_test;
 
     use super::*;
-    use crate::snapshot::SnapshotMeta;
+    use crate::snapshot::{Snapshot, SnapshotMeta};
 
     #[traced_test]
     #[tokio::test]
@@ -865,13 +989,21 @@ mod tests {
         let stream = install_snapshot_stream(
             0,
             123,
-            Snapshot::new(
-                SnapshotMeta {
-                    last_included_index: 1,
-                    last_included_term: 1,
-                },
-                snapshot,
-            ),
+            Arc::new(SnapshotTransfer::new(
+                Snapshot::new(
+                    SnapshotMeta {
+                        last_included_index: 1,
+                        last_included_term: 1,
+                        cluster_server_version: 0,
+                        membership_index: 0,
+                    },
+                    snapshot,
+                )
+                .with_results(Bytes::from_static(b"results")),
+            )),
+            0,
+            Arc::new(SnapshotThrottle::new(0, 0, 1)),
+            456,
         );
         pin_mut!(stream);
         let mut sum = 0;
@@ -882,7 +1014,135 @@ mod tests {
             assert_eq!(req.last_included_term, 1);
             sum += req.data.len() as u64;
             assert_eq!(sum == SNAPSHOT_SIZE, req.done);
+            assert_eq!(req.done, !req.result_cache.is_empty());
         }
         assert_eq!(sum, SNAPSHOT_SIZE);
     }
+
+    #[traced_test]
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn install_snapshot_stream_should_resume_from_offset() {
+        let data: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut snapshot = EngineSnapshot::new_for_receiving(EngineType::Memory).unwrap();
+        snapshot.write_all(Bytes::from(data.clone())).await.unwrap();
+        let transfer = Arc::new(SnapshotTransfer::new(Snapshot::new(
+            SnapshotMeta {
+                last_included_index: 1,
+                last_included_term: 1,
+                cluster_server_version: 0,
+                membership_index: 0,
+            },
+            snapshot,
+        )));
+        let throttle = Arc::new(SnapshotThrottle::new(0, 0, 1));
+        let collect = |offset: u64| {
+            let stream = install_snapshot_stream(
+                0,
+                123,
+                Arc::clone(&transfer),
+                offset,
+                Arc::clone(&throttle),
+                456,
+            );
+            stream.collect::<Vec<_>>()
+        };
+
+        let full = collect(0).await;
+        // in the middle of the second chunk
+        let offset = SNAPSHOT_CHUNK_SIZE + 100;
+        let resumed = collect(offset).await;
+        assert_eq!(resumed[0].offset, offset);
+        let mut next = offset;
+        let mut received = vec![];
+        for req in &resumed {
+            assert_eq!(req.offset, next);
+            assert_eq!(req.transfer_id, transfer.id());
+            next += req.data.len() as u64;
+            received.extend_from_slice(&req.data);
+        }
+        assert_eq!(received, data[offset as usize..]);
+        let digest = |reqs: &[InstallSnapshotRequest]| reqs.last().unwrap().digest.clone();
+        assert_eq!(digest(&resumed), digest(&full));
+        assert_eq!(digest(&full).as_ref(), Sha256::digest(&data).as_slice());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_install_snapshot_stream_should_honor_rate_limits() {
+        const RATE: u64 = 1024 * 1024;
+        let engine = Engine::new(EngineType::Memory, &["kv"]).unwrap();
+        let puts = (0..1024_u64)
+            .map(|i| WriteOperation::new_put("kv", i.to_be_bytes().to_vec(), vec![1; 512]))
+            .collect();
+        engine.write_batch(puts, false).unwrap();
+        let snapshot = engine.get_snapshot("", &["kv"]).unwrap();
+        let size = snapshot.size();
+        let throttle = Arc::new(SnapshotThrottle::new(RATE, RATE, 1));
+        let engine = Arc::new(engine);
+        let stream = install_snapshot_stream(
+            0,
+            123,
+            Arc::new(SnapshotTransfer::new(Snapshot::new(
+                SnapshotMeta {
+                    last_included_index: 1,
+                    last_included_term: 1,
+                    cluster_server_version: 0,
+                    membership_index: 0,
+                },
+                snapshot,
+            ))),
+            0,
+            Arc::clone(&throttle),
+            456,
+        );
+
+        // puts into the engine being sent, running alongside the transfer
+        let applied = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let workload = tokio::spawn({
+            let engine = Arc::clone(&engine);
+            let applied = Arc::clone(&applied);
+            let stop = Arc::clone(&stop);
+            async move {
+                let mut i = 1024_u64;
+                while !stop.load(Ordering::Relaxed) {
+                    let put = WriteOperation::new_put("kv", i.to_be_bytes().to_vec(), vec![0; 64]);
+                    engine.write_batch(vec![put], false).unwrap();
+                    let _prev = applied.fetch_add(1, Ordering::Relaxed);
+                    i += 1;
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        });
+
+        let start = Instant::now();
+        pin_mut!(stream);
+        let mut sum = 0;
+        let mut last_applied = applied.load(Ordering::Relaxed);
+        while let Some(req) = stream.next().await {
+            sum += req.data.len() as u64;
+            let progress = throttle.progress();
+            assert_eq!(progress.len(), 1);
+            assert_eq!(progress[0].0, 456);
+            assert_eq!(progress[0].1.sent() + req.data.len() as u64, sum);
+            // the 1ms sleep of the workload is always due before the wait of a chunk
+            let now_applied = applied.load(Ordering::Relaxed);
+            assert!(
+                now_applied > last_applied,
+                "puts are blocked by the transfer"
+            );
+            last_applied = now_applied;
+        }
+        stop.store(true, Ordering::Relaxed);
+        workload.await.unwrap();
+
+        assert_eq!(sum, size);
+        assert!(throttle.progress().is_empty());
+        // every byte of the snapshot is paced once against each limit
+        let paced_until = throttle.paced_until().unwrap();
+        assert!(paced_until >= start + Duration::from_nanos(size * 1_000_000_000 / RATE));
+        assert!(Instant::now() >= paced_until);
+    }
 }
@@ -25,6 +25,8 @@ pub use self::proto::{
         propose_conf_change_request::{ConfChange, ConfChangeType},
         protocol_client,
         protocol_server::{Protocol, ProtocolServer},
+        CancelRequest,
+        CancelResponse,
         CmdResult,
         FetchClusterRequest,
         FetchClusterResponse,
@@ -48,7 +50,12 @@ pub use self::proto::{
     },
     inner_messagepb::inner_protocol_server::InnerProtocolServer,
 };
-use crate::{cmd::Command, log_entry::LogEntry, members::ServerId, LogIndex};
+use crate::{
+    cmd::Command,
+    log_entry::LogEntry,
+    members::{ServerId, SERVER_VERSION},
+    LogIndex,
+};
 
 /// Metrics
 #[cfg(feature = "client-metrics")]
@@ -56,6 +63,7 @@ mod metrics;
 
 /// Rpc connect
 pub(crate) mod connect;
+pub use connect::is_bypassed_in_process;
 pub(crate) use connect::{connect, connects, inner_connects};
 
 // Skip for generated code
@@ -143,6 +151,17 @@ impl ProposeRequest {
             propose_id: Some(propose_id.into()),
             command: cmd.encode(),
             cluster_version,
+            wait_persisted: false,
+        }
+    }
+
+    /// Create a new async `Propose` request, the leader answers it once the log entry of
+    /// the cmd is persisted, without the execution result
+    #[inline]
+    pub fn new_async<C: Command>(propose_id: ProposeId, cmd: &C, cluster_version: u64) -> Self {
+        Self {
+            wait_persisted: true,
+            ..Self::new(propose_id, cmd, cluster_version)
         }
     }
 
@@ -220,6 +239,24 @@ impl WaitSyncedRequest {
     }
 }
 
+impl CancelRequest {
+    /// Create a `Cancel` request
+    pub(crate) fn new(id: ProposeId, cluster_version: u64) -> Self {
+        Self {
+            propose_id: Some(id.into()),
+            cluster_version,
+        }
+    }
+
+    /// Get the `propose_id` reference
+    pub(crate) fn propose_id(&self) -> ProposeId {
+        self.propose_id
+            .clone()
+            .unwrap_or_else(|| unreachable!("propose id should be set in cancel request"))
+            .into()
+    }
+}
+
 impl WaitSyncedResponse {
     /// Create a success response
     fn new_success<C: Command>(asr: &C::ASR, er: &C::ER) -> Self {
@@ -338,14 +375,6 @@ impl AppendEntriesRequest {
             leader_commit,
         })
     }
-
-    /// Get log entries
-    pub(crate) fn entries<C: Command>(&self) -> bincode::Result<Vec<LogEntry<C>>> {
-        self.entries
-            .iter()
-            .map(|entry| bincode::deserialize(entry))
-            .collect()
-    }
 }
 
 impl AppendEntriesResponse {
@@ -355,6 +384,9 @@ impl AppendEntriesResponse {
             term,
             success: false,
             hint_index,
+            server_version: SERVER_VERSION,
+            hash_revision: 0,
+            state_hash: 0,
         }
     }
 
@@ -364,8 +396,20 @@ impl AppendEntriesResponse {
             term,
             success: true,
             hint_index: 0,
+            server_version: SERVER_VERSION,
+            hash_revision: 0,
+            state_hash: 0,
         }
     }
+
+    /// Piggyback the state hash checkpointed at a revision
+    pub(crate) fn with_state_hash(mut self, report: Option<(i64, u64)>) -> Self {
+        if let Some((revision, hash)) = report {
+            self.hash_revision = revision;
+            self.state_hash = hash;
+        }
+        self
+    }
 }
 
 impl VoteRequest {
@@ -434,9 +478,32 @@ impl VoteResponse {
 }
 
 impl InstallSnapshotResponse {
-    /// Create a new snapshot response
+    /// Create a new snapshot response, the snapshot is not installed
     pub(crate) fn new(term: u64) -> Self {
-        Self { term }
+        Self {
+            term,
+            success: false,
+            next_offset: 0,
+        }
+    }
+
+    /// Create a new snapshot response of an installed snapshot
+    pub(crate) fn installed(term: u64) -> Self {
+        Self {
+            term,
+            success: true,
+            next_offset: 0,
+        }
+    }
+
+    /// Create a new snapshot response of a probe, the transfer should be resumed from
+    /// `next_offset`
+    pub(crate) fn resume(term: u64, next_offset: u64) -> Self {
+        Self {
+            term,
+            success: true,
+            next_offset,
+        }
     }
 }
 
@@ -639,6 +706,26 @@ impl CurpError {
         Self::ExpiredClientId(())
     }
 
+    /// `ResultExpired` error
+    pub(crate) fn result_expired() -> Self {
+        Self::ResultExpired(())
+    }
+
+    /// `SessionExpired` error
+    pub(crate) fn session_expired() -> Self {
+        Self::SessionExpired(())
+    }
+
+    /// `Canceled` error
+    pub(crate) fn canceled() -> Self {
+        Self::Canceled(())
+    }
+
+    /// `Unavailable` error
+    pub(crate) fn unavailable() -> Self {
+        Self::Unavailable(())
+    }
+
     /// `InvalidConfig` error
     pub(crate) fn invalid_config() -> Self {
         Self::InvalidConfig(())
@@ -690,6 +777,9 @@ impl CurpError {
                 | CurpError::NodeNotExists(())
                 | CurpError::LearnerNotCatchUp(())
                 | CurpError::ExpiredClientId(())
+                | CurpError::ResultExpired(())
+                | CurpError::SessionExpired(())
+                | CurpError::Canceled(())
                 | CurpError::Redirect(_)
         )
     }
@@ -704,6 +794,9 @@ impl CurpError {
                 | CurpError::NodeNotExists(())
                 | CurpError::LearnerNotCatchUp(())
                 | CurpError::ExpiredClientId(())
+                | CurpError::ResultExpired(())
+                | CurpError::SessionExpired(())
+                | CurpError::Canceled(())
                 | CurpError::Redirect(_)
                 | CurpError::WrongClusterVersion(())
         )
@@ -719,9 +812,13 @@ impl CurpError {
             | CurpError::NodeNotExists(())
             | CurpError::LearnerNotCatchUp(())
             | CurpError::ExpiredClientId(())
+            | CurpError::ResultExpired(())
+            | CurpError::SessionExpired(())
+            | CurpError::Canceled(())
             | CurpError::Redirect(_)
             | CurpError::WrongClusterVersion(()) => CurpErrorPriority::High,
             CurpError::RpcTransport(())
+            | CurpError::Unavailable(())
             | CurpError::Internal(_)
             | CurpError::KeyConflict(())
             | CurpError::LeaderTransfer(_) => CurpErrorPriority::Low,
@@ -786,6 +883,18 @@ impl From<CurpError> for tonic::Status {
                 tonic::Code::FailedPrecondition,
                 "Expired client ID error: The client ID has expired, we cannot tell if this request is duplicated.",
             ),
+            CurpError::ResultExpired(()) => (
+                tonic::Code::FailedPrecondition,
+                "Result expired error: The result of this request has been evicted, do not retry it blindly.",
+            ),
+            CurpError::SessionExpired(()) => (
+                tonic::Code::FailedPrecondition,
+                "Session expired error: The client session of this request has expired, do not retry it blindly.",
+            ),
+            CurpError::Canceled(()) => (
+                tonic::Code::Cancelled,
+                "Canceled error: The request has been canceled before it was executed.",
+            ),
             CurpError::InvalidConfig(()) => (
                 tonic::Code::InvalidArgument,
                 "Invalid config error: The provided configuration is invalid.",
@@ -819,6 +928,11 @@ impl From<CurpError> for tonic::Status {
                 "Internal error: An internal error occurred.",
             ),
             CurpError::RpcTransport(()) => (tonic::Code::Cancelled, "Rpc error: Request cancelled"),
+            // the client takes it as a transport error and retries on another member
+            CurpError::Unavailable(()) => (
+                tonic::Code::Unavailable,
+                "Unavailable error: The storage of the node failed, retry on another node.",
+            ),
             CurpError::LeaderTransfer(_) => (
                 tonic::Code::FailedPrecondition,
                 "Leader transfer error: A leader transfer error occurred.",
@@ -1,15 +1,32 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 
+use bytes::Bytes;
+use clippy_utilities::OverflowArithmetic;
 use event_listener::{Event, EventListener};
 use indexmap::{IndexMap, IndexSet};
 use parking_lot::RwLock;
-use utils::parking_lot_lock::RwLockMap;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::oneshot, time::Instant};
+use tracing::{error, info_span, warn, Span};
+use utils::{config::ResultCacheConfig, parking_lot_lock::RwLockMap};
 
-use crate::{cmd::Command, rpc::ProposeId};
+use super::result_cache::ResultCache;
+use crate::{
+    cmd::{AppliedProposal, Command},
+    rpc::{CurpError, ProposeId},
+    LogIndex,
+};
 
 /// Ref to the cmd board
 pub(super) type CmdBoardRef<C> = Arc<RwLock<CommandBoard<C>>>;
 
+/// Max number of canceled cmds remembered to reject their late proposals
+const MAX_CANCELED: usize = 4096;
+
 /// Command board is a buffer to track cmd states and store notifiers for requests that need to wait for a cmd
 #[derive(Debug)]
 pub(super) struct CommandBoard<C: Command> {
@@ -21,6 +38,10 @@ pub(super) struct CommandBoard<C: Command> {
     shutdown_notifier: Event,
     /// Store all notifiers for conf change results
     conf_notifier: HashMap<ProposeId, Event>,
+    /// Notifiers of the cmds waiting for their log entries to be persisted
+    persist_notifiers: HashMap<ProposeId, oneshot::Sender<()>>,
+    /// Notifiers of the cancels waiting for their log entries to be committed
+    cancel_notifiers: HashMap<ProposeId, oneshot::Sender<()>>,
     /// Store all conf change propose ids
     pub(super) conf_buffer: IndexSet<ProposeId>,
     /// The cmd has been received before, this is used for dedup
@@ -29,6 +50,107 @@ pub(super) struct CommandBoard<C: Command> {
     pub(super) er_buffer: IndexMap<ProposeId, Result<C::ER, C::Error>>,
     /// Store all after sync results
     pub(super) asr_buffer: IndexMap<ProposeId, Result<C::ASR, C::Error>>,
+    /// Spans of the traced proposals on the leader, kept until the cmd is after synced
+    spans: HashMap<ProposeId, ProposeSpans>,
+    /// Timelines of the cmds executed or committed but not yet after synced
+    timelines: HashMap<ProposeId, CmdTimeline>,
+    /// Index of the completed results kept for clients to re-fetch
+    results: ResultCache,
+    /// Cmds dispatched for after sync in the latest log entries, a cmd appended again
+    /// by a retry is only applied once
+    applied: HashMap<ProposeId, LogIndex>,
+    /// Log indexes of the `applied` cmds, in log order
+    applied_order: VecDeque<(LogIndex, ProposeId)>,
+    /// Cmds canceled before they were appended, they must never be appended
+    canceled: HashSet<ProposeId>,
+    /// The `canceled` cmds in the order they were canceled
+    canceled_order: VecDeque<ProposeId>,
+    /// Whether the storage of the executor failed, no more cmd is after synced then
+    poisoned: bool,
+}
+
+/// A completed result carried in snapshots
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResult<C: Command> {
+    /// Propose id of the cmd
+    id: ProposeId,
+    /// Milliseconds passed since the cmd completed
+    age_millis: u64,
+    /// Execution result
+    er: Result<C::ER, C::Error>,
+    /// After sync result, `None` if the execution failed
+    asr: Option<Result<C::ASR, C::Error>>,
+}
+
+/// Completed results carried in snapshots
+#[derive(Debug, Serialize, Deserialize)]
+struct ResultsSnapshot<C: Command> {
+    /// Client ids with their largest evicted seq nums
+    sessions: Vec<(u64, Option<u64>)>,
+    /// Results, the oldest first
+    results: Vec<CachedResult<C>>,
+    /// Client ids of the expired sessions with their largest seq nums
+    expired: Vec<(u64, u64)>,
+    /// Log indexes of the applied cmds included in the snapshot, in log order
+    applied: Vec<(LogIndex, ProposeId)>,
+}
+
+/// When a cmd went through the stages before its after sync on this server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct CmdTimeline {
+    /// When the speculative execution started, `None` if it's not executed here
+    pub(super) executed_at: Option<Instant>,
+    /// How long the speculative execution took
+    pub(super) execute: Option<Duration>,
+    /// When the log entry of the cmd was committed
+    pub(super) committed_at: Option<Instant>,
+}
+
+/// Latencies of a cmd whose after sync has completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CmdLatency {
+    /// How long the speculative execution took, `None` if it's not executed here
+    pub(super) execute: Option<Duration>,
+    /// From the start of the execution to the commit of the log entry
+    pub(super) commit: Option<Duration>,
+    /// From the commit of the log entry to the start of the after sync, the cmd may be
+    /// waiting for conflicting cmds
+    pub(super) apply_wait: Option<Duration>,
+    /// How long the after sync took
+    pub(super) apply: Duration,
+    /// From the earliest known stage to the completion of the after sync
+    pub(super) total: Duration,
+}
+
+impl CmdTimeline {
+    /// Latencies of the cmd whose after sync started at `as_started_at` and completed at
+    /// `as_finished_at`
+    pub(super) fn latency(&self, as_started_at: Instant, as_finished_at: Instant) -> CmdLatency {
+        let since =
+            |from: Option<Instant>, to: Instant| from.map(|at| to.saturating_duration_since(at));
+        let started_at = self
+            .executed_at
+            .or(self.committed_at)
+            .map_or(as_started_at, |at| at.min(as_started_at));
+        CmdLatency {
+            execute: self.execute,
+            commit: self
+                .committed_at
+                .and_then(|committed_at| since(self.executed_at, committed_at)),
+            apply_wait: since(self.committed_at, as_started_at),
+            apply: as_finished_at.saturating_duration_since(as_started_at),
+            total: as_finished_at.saturating_duration_since(started_at),
+        }
+    }
+}
+
+/// Spans that outlive the propose request of a cmd
+#[derive(Debug)]
+struct ProposeSpans {
+    /// The span of the propose request
+    propose: Span,
+    /// The span covering the replication of the cmd
+    replicate: Span,
 }
 
 impl<C: Command> CommandBoard<C> {
@@ -42,7 +164,17 @@ impl<C: Command> CommandBoard<C> {
             er_buffer: IndexMap::new(),
             asr_buffer: IndexMap::new(),
             conf_notifier: HashMap::new(),
+            persist_notifiers: HashMap::new(),
+            cancel_notifiers: HashMap::new(),
             conf_buffer: IndexSet::new(),
+            spans: HashMap::new(),
+            timelines: HashMap::new(),
+            results: ResultCache::default(),
+            applied: HashMap::new(),
+            applied_order: VecDeque::new(),
+            canceled: HashSet::new(),
+            canceled_order: VecDeque::new(),
+            poisoned: false,
         }
     }
 
@@ -54,15 +186,362 @@ impl<C: Command> CommandBoard<C> {
         self.asr_notifiers.drain().for_each(|(_, event)| {
             let _ignore = event.notify(usize::MAX);
         });
+        // dropping the senders tells the waiters their entries may never be persisted
+        self.persist_notifiers.clear();
+        self.cancel_notifiers.clear();
     }
 
-    /// Clear
+    /// Clear the results of the uncompleted cmds, the completed results stay valid
+    /// across leader changes so clients could re-fetch them from the new leader
     pub(super) fn clear(&mut self) {
-        self.er_buffer.clear();
-        self.asr_buffer.clear();
+        let asr_buffer = &self.asr_buffer;
+        self.er_buffer
+            .retain(|id, er| er.is_err() || asr_buffer.contains_key(id));
+        self.spans.clear();
+        self.timelines.clear();
         self.release_notifiers();
     }
 
+    /// Evict the completed results out of retention, return the number of evicted results,
+    /// `expire_idle` drops the idle sessions as well
+    pub(super) fn evict_results(
+        &mut self,
+        cfg: &ResultCacheConfig,
+        now: Instant,
+        expire_idle: bool,
+    ) -> usize {
+        let evicted = self.results.evict(cfg, now, expire_idle);
+        for id in &evicted {
+            let _ignore_er = self.er_buffer.swap_remove(id);
+            let _ignore_asr = self.asr_buffer.swap_remove(id);
+        }
+        evicted.len()
+    }
+
+    /// Refresh the session of a client proposing a cmd, return `false` if the session
+    /// of the cmd has expired
+    pub(super) fn touch_session(&mut self, id: ProposeId, now: Instant) -> bool {
+        self.results.touch(id, now)
+    }
+
+    /// The clients whose sessions have been idle for longer than `ttl`, with the largest
+    /// seq nums they used
+    pub(super) fn idle_sessions(&self, ttl: Duration, now: Instant) -> Vec<(u64, u64)> {
+        self.results.idle_sessions(ttl, now)
+    }
+
+    /// Expire the sessions of the clients up to the given seq nums together with their
+    /// cached results, return the number of the dropped results
+    pub(super) fn expire_sessions(&mut self, sessions: &[(u64, u64)], now: Instant) -> usize {
+        let expired = self.results.expire_sessions(sessions, now);
+        for id in &expired {
+            let _ignore_er = self.er_buffer.swap_remove(id);
+            let _ignore_asr = self.asr_buffer.swap_remove(id);
+        }
+        expired.len()
+    }
+
+    /// Record a cmd dispatched for after sync in log[index], return `true` if it has been
+    /// applied in the last `window` log entries
+    ///
+    /// The window is counted in log entries, a retry of a recorded cmd is not appended
+    /// again by the leader
+    pub(super) fn record_applied(&mut self, id: ProposeId, index: LogIndex, window: u64) -> bool {
+        while let Some(&(applied_index, applied_id)) = self.applied_order.front() {
+            if applied_index.overflow_add(window) > index {
+                break;
+            }
+            let _ignore = self.applied_order.pop_front();
+            let _ignore = self.applied.remove(&applied_id);
+        }
+        if self.applied.contains_key(&id) {
+            return true;
+        }
+        let _ignore = self.applied.insert(id, index);
+        self.applied_order.push_back((index, id));
+        false
+    }
+
+    /// Check whether a cmd has been dispatched for after sync
+    pub(super) fn is_applied(&self, id: ProposeId) -> bool {
+        self.applied.contains_key(&id)
+    }
+
+    /// Check whether the result of a cmd has been evicted
+    pub(super) fn is_result_expired(&self, id: ProposeId) -> bool {
+        !self.er_buffer.contains_key(&id) && self.results.is_expired(id)
+    }
+
+    /// Check whether a cmd is known to have been appended, by its results or its apply
+    pub(super) fn is_appended(&self, id: ProposeId) -> bool {
+        self.er_buffer.contains_key(&id)
+            || self.asr_buffer.contains_key(&id)
+            || self.applied.contains_key(&id)
+            || self.results.is_expired(id)
+    }
+
+    /// Mark a cmd as canceled and wake up its waiters, only the latest `MAX_CANCELED`
+    /// ones are remembered
+    pub(super) fn cancel(&mut self, id: ProposeId) {
+        if self.canceled.insert(id) {
+            self.canceled_order.push_back(id);
+            if self.canceled_order.len() > MAX_CANCELED {
+                if let Some(oldest) = self.canceled_order.pop_front() {
+                    let _ignore = self.canceled.remove(&oldest);
+                }
+            }
+        }
+        self.notify_er(&id);
+        self.notify_asr(&id);
+        // the entry of the cmd will never be persisted
+        let _ignore = self.persist_notifiers.remove(&id);
+    }
+
+    /// Check whether a cmd has been canceled
+    pub(super) fn is_canceled(&self, id: ProposeId) -> bool {
+        self.canceled.contains(&id)
+    }
+
+    /// Watch the commit of the cancel entry of a propose id
+    pub(super) fn watch_cancel_committed(&mut self, entry_id: ProposeId) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let _ignore = self.cancel_notifiers.insert(entry_id, tx);
+        rx
+    }
+
+    /// The cancel entry of a cmd is committed, the cmd is canceled on every server then
+    pub(super) fn commit_cancel(&mut self, entry_id: ProposeId, id: ProposeId) {
+        self.cancel(id);
+        if let Some(tx) = self.cancel_notifiers.remove(&entry_id) {
+            let _ignore = tx.send(());
+        }
+    }
+
+    /// Mark the board as poisoned by a storage failure, the cmds waiting for their after
+    /// sync results are answered with `Unavailable`
+    pub(super) fn poison(&mut self) {
+        self.poisoned = true;
+        self.asr_notifiers.drain().for_each(|(_, event)| {
+            let _ignore = event.notify(usize::MAX);
+        });
+    }
+
+    /// Check whether the board is poisoned by a storage failure
+    pub(super) fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Check whether a cmd was proposed in a session that has expired since
+    pub(super) fn is_session_expired(&self, id: ProposeId) -> bool {
+        !self.er_buffer.contains_key(&id) && self.results.is_session_expired(id)
+    }
+
+    /// Get the number of client sessions and results in the result cache
+    pub(super) fn results_occupancy(&self) -> (usize, usize) {
+        (self.results.sessions_len(), self.results.len())
+    }
+
+    /// Encode the expired sessions and the newest completed results into at most
+    /// `max_size` bytes, together with the cmds applied up to `last_included_index`
+    pub(super) fn encode_results(&self, max_size: u64, last_included_index: LogIndex) -> Bytes {
+        let now = Instant::now();
+        let newest_first = self.results.newest_first();
+        // three length prefixes of the sequences
+        let mut size = 24_u64;
+        // client id and the largest seq num of each expired session
+        let mut expired = Vec::new();
+        for marker in self.results.expired_sessions() {
+            if size.overflow_add(16) > max_size {
+                break;
+            }
+            size = size.overflow_add(16);
+            expired.push(marker);
+        }
+        let mut sessions: HashMap<u64, Option<u64>> = HashMap::new();
+        let mut results = Vec::new();
+        let mut left_out = newest_first.into_iter();
+        for (id, completed) in left_out.by_ref() {
+            let (Some(er), asr) = (self.er_buffer.get(&id), self.asr_buffer.get(&id)) else {
+                continue;
+            };
+            let result = CachedResult::<C> {
+                id,
+                age_millis: u64::try_from(now.duration_since(completed).as_millis())
+                    .unwrap_or(u64::MAX),
+                er: er.clone(),
+                asr: asr.cloned(),
+            };
+            let mut item_size = match bincode::serialized_size(&result) {
+                Ok(item_size) => item_size,
+                Err(e) => {
+                    error!("failed to get the size of the result of cmd({id}), {e}");
+                    continue;
+                }
+            };
+            if !sessions.contains_key(&id.0) {
+                // client id and the optional evicted seq num
+                item_size = item_size.overflow_add(17);
+            }
+            if size.overflow_add(item_size) > max_size {
+                break;
+            }
+            size = size.overflow_add(item_size);
+            let _ignore = sessions
+                .entry(id.0)
+                .or_insert_with(|| self.results.evicted_seq_num(id.0));
+            results.push(result);
+        }
+        // the results left out of the snapshot are regarded as evicted
+        for (ProposeId(client_id, seq_num), _) in left_out {
+            if let Some(evicted) = sessions.get_mut(&client_id) {
+                *evicted = (*evicted).max(Some(seq_num));
+            }
+        }
+        results.reverse();
+        // the cmds applied after the snapshot will be applied again on the restored nodes
+        let applied = self
+            .applied_order
+            .iter()
+            .take_while(|&&(index, _)| index <= last_included_index)
+            .copied()
+            .collect();
+        let snapshot = ResultsSnapshot::<C> {
+            sessions: sessions.into_iter().collect(),
+            results,
+            expired,
+            applied,
+        };
+        match bincode::serialize(&snapshot) {
+            Ok(bytes) => bytes.into(),
+            Err(e) => {
+                error!("failed to encode the cached results, {e}");
+                Bytes::new()
+            }
+        }
+    }
+
+    /// Restore the completed results encoded by `encode_results`
+    pub(super) fn restore_results(&mut self, encoded: &[u8]) {
+        if encoded.is_empty() {
+            return;
+        }
+        let snapshot: ResultsSnapshot<C> = match bincode::deserialize(encoded) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("failed to decode the cached results, {e}");
+                return;
+            }
+        };
+        self.applied = snapshot
+            .applied
+            .iter()
+            .map(|&(index, id)| (id, index))
+            .collect();
+        self.applied_order = snapshot.applied.into();
+        let now = Instant::now();
+        for (client_id, max_seq_num) in snapshot.expired {
+            self.results
+                .mark_session_expired(client_id, max_seq_num, now);
+        }
+        for (client_id, evicted) in snapshot.sessions {
+            if let Some(seq_num) = evicted {
+                self.results
+                    .mark_evicted(ProposeId(client_id, seq_num), now);
+            }
+        }
+        for result in snapshot.results {
+            if self.er_buffer.contains_key(&result.id) {
+                continue;
+            }
+            let completed = now
+                .checked_sub(Duration::from_millis(result.age_millis))
+                .unwrap_or(now);
+            let _ignore_er = self.er_buffer.insert(result.id, result.er);
+            if let Some(asr) = result.asr {
+                let _ignore_asr = self.asr_buffer.insert(result.id, asr);
+            }
+            self.results.record(result.id, completed);
+        }
+    }
+
+    /// Restore the cmds applied in the latest log entries, in log order, with the results
+    /// persisted by the executor
+    pub(super) fn restore_applied(&mut self, proposals: Vec<AppliedProposal<C>>) {
+        let now = Instant::now();
+        for proposal in proposals {
+            let id = ProposeId(proposal.propose_id.0, proposal.propose_id.1);
+            if self.applied.insert(id, proposal.index).is_none() {
+                self.applied_order.push_back((proposal.index, id));
+            }
+            let Some(er) = proposal.er else {
+                continue;
+            };
+            let _ignore_er = self.er_buffer.insert(id, Ok(er));
+            let _ignore_asr = self.asr_buffer.insert(id, proposal.asr);
+            self.results.record(id, now);
+        }
+    }
+
+    /// Keep the current span as the parent span of the later stages of a cmd on the
+    /// leader, returns `false` if the cmd is not traced or is already tracked
+    ///
+    /// The spans are closed once the cmd is after synced, untracked, or the leader
+    /// retires.
+    pub(super) fn track_span(&mut self, id: ProposeId) -> bool {
+        let propose = Span::current();
+        if propose.is_disabled() || self.spans.contains_key(&id) {
+            return false;
+        }
+        let replicate = info_span!(parent: &propose, "curp_replicate", propose_id = %id);
+        let _ignore = self.spans.insert(id, ProposeSpans { propose, replicate });
+        true
+    }
+
+    /// Stop tracking the spans of a cmd
+    pub(super) fn untrack_span(&mut self, id: ProposeId) {
+        let _ignore = self.spans.remove(&id);
+    }
+
+    /// Get the span for the speculative execution of a cmd
+    pub(super) fn execute_span(&self, id: ProposeId) -> Span {
+        match self.spans.get(&id) {
+            Some(spans) => info_span!(parent: &spans.propose, "curp_execute", propose_id = %id),
+            None => info_span!("curp_execute", propose_id = %id),
+        }
+    }
+
+    /// Get the span for the after sync of a cmd, this closes the replication span of it
+    pub(super) fn after_sync_span(&mut self, id: ProposeId) -> Span {
+        match self.spans.remove(&id) {
+            Some(spans) => {
+                drop(spans.replicate);
+                info_span!(parent: &spans.propose, "curp_after_sync", propose_id = %id)
+            }
+            None => info_span!("curp_after_sync", propose_id = %id),
+        }
+    }
+
+    /// Record the speculative execution of a cmd
+    pub(super) fn record_execute(&mut self, id: ProposeId, started_at: Instant, elapsed: Duration) {
+        let timeline = self.timelines.entry(id).or_default();
+        timeline.executed_at = Some(started_at);
+        timeline.execute = Some(elapsed);
+    }
+
+    /// Record the commit of the log entry of a cmd
+    pub(super) fn record_commit(&mut self, id: ProposeId, committed_at: Instant) {
+        // no after sync follows a failed execution
+        if self.er_buffer.get(&id).is_some_and(Result::is_err) {
+            return;
+        }
+        self.timelines.entry(id).or_default().committed_at = Some(committed_at);
+    }
+
+    /// Take the timeline of a cmd once its after sync has completed
+    pub(super) fn take_timeline(&mut self, id: ProposeId) -> CmdTimeline {
+        self.timelines.remove(&id).unwrap_or_default()
+    }
+
     /// Insert er to internal buffer
     pub(super) fn insert_er(&mut self, id: ProposeId, er: Result<C::ER, C::Error>) {
         let er_ok = er.is_ok();
@@ -75,6 +554,7 @@ impl<C: Command> CommandBoard<C> {
 
         // wait_synced response is also ready when execution fails
         if !er_ok {
+            self.results.record(id, Instant::now());
             self.notify_asr(&id);
         }
     }
@@ -86,6 +566,7 @@ impl<C: Command> CommandBoard<C> {
             "asr should not be inserted twice"
         );
 
+        self.results.record(id, Instant::now());
         self.notify_asr(&id);
     }
 
@@ -103,7 +584,7 @@ impl<C: Command> CommandBoard<C> {
     fn er_listener(&mut self, id: ProposeId) -> EventListener {
         let event = self.er_notifiers.entry(id).or_default();
         let listener = event.listen();
-        if self.er_buffer.contains_key(&id) {
+        if self.er_buffer.contains_key(&id) || self.canceled.contains(&id) {
             let _ignore = event.notify(usize::MAX);
         }
         listener
@@ -118,7 +599,7 @@ impl<C: Command> CommandBoard<C> {
     fn asr_listener(&mut self, id: ProposeId) -> EventListener {
         let event = self.asr_notifiers.entry(id).or_default();
         let listener = event.listen();
-        if self.asr_buffer.contains_key(&id) {
+        if self.asr_buffer.contains_key(&id) || self.canceled.contains(&id) {
             let _ignore = event.notify(usize::MAX);
         }
         listener
@@ -148,6 +629,36 @@ impl<C: Command> CommandBoard<C> {
         }
     }
 
+    /// Watch the persistence of the log entry of a cmd, the receiver gets notified once
+    /// the entry is persisted, or dropped if the notifiers are released before
+    ///
+    /// Must be called before the cmd is appended to the log, or the notification may be
+    /// missed.
+    pub(super) fn watch_persisted(&mut self, id: ProposeId) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let _ignore = self.persist_notifiers.insert(id, tx);
+        rx
+    }
+
+    /// Stop watching the persistence of the log entry of a cmd
+    pub(super) fn unwatch_persisted(&mut self, id: ProposeId) {
+        let _ignore = self.persist_notifiers.remove(&id);
+    }
+
+    /// Notify the cmds waiting for the persistence of their log entries, the write lock
+    /// is only taken if some cmd is waiting
+    pub(super) fn notify_persisted(cb: &CmdBoardRef<C>, ids: impl IntoIterator<Item = ProposeId>) {
+        if cb.map_read(|cb_r| cb_r.persist_notifiers.is_empty()) {
+            return;
+        }
+        let mut cb_w = cb.write();
+        for id in ids {
+            if let Some(tx) = cb_w.persist_notifiers.remove(&id) {
+                let _ignore = tx.send(());
+            }
+        }
+    }
+
     /// Notify `shutdown` requests
     pub(super) fn notify_shutdown(&mut self) {
         let _ignore = self.shutdown_notifier.notify(usize::MAX);
@@ -160,11 +671,21 @@ impl<C: Command> CommandBoard<C> {
         }
     }
 
-    /// Wait for an execution result
-    pub(super) async fn wait_for_er(cb: &CmdBoardRef<C>, id: ProposeId) -> Result<C::ER, C::Error> {
+    /// Wait for an execution result, return `Canceled` if the cmd is canceled before
+    /// it's appended
+    pub(super) async fn wait_for_er(
+        cb: &CmdBoardRef<C>,
+        id: ProposeId,
+    ) -> Result<Result<C::ER, C::Error>, CurpError> {
         loop {
-            if let Some(er) = cb.map_read(|cb_r| cb_r.er_buffer.get(&id).cloned()) {
-                return er;
+            {
+                let cb_r = cb.read();
+                if let Some(er) = cb_r.er_buffer.get(&id) {
+                    return Ok(er.clone());
+                }
+                if cb_r.is_canceled(id) {
+                    return Err(CurpError::canceled());
+                }
             }
             let listener = cb.write().er_listener(id);
             listener.await;
@@ -177,17 +698,24 @@ impl<C: Command> CommandBoard<C> {
         listener.await;
     }
 
-    /// Wait for an after sync result
+    /// Wait for an after sync result, return `SessionExpired` if the session of the cmd
+    /// has expired, `ResultExpired` if the result has been evicted, `Canceled` if the
+    /// cmd is canceled before it's appended, or `Unavailable` if it will never be after
+    /// synced because of a storage failure
     pub(super) async fn wait_for_er_asr(
         cb: &CmdBoardRef<C>,
         id: ProposeId,
-    ) -> (Result<C::ER, C::Error>, Option<Result<C::ASR, C::Error>>) {
+    ) -> Result<(Result<C::ER, C::Error>, Option<Result<C::ASR, C::Error>>), CurpError> {
         loop {
             {
                 let cb_r = cb.read();
                 match (cb_r.er_buffer.get(&id), cb_r.asr_buffer.get(&id)) {
-                    (Some(er), None) if er.is_err() => return (er.clone(), None),
-                    (Some(er), Some(asr)) => return (er.clone(), Some(asr.clone())),
+                    (Some(er), None) if er.is_err() => return Ok((er.clone(), None)),
+                    (Some(er), Some(asr)) => return Ok((er.clone(), Some(asr.clone()))),
+                    _ if cb_r.is_session_expired(id) => return Err(CurpError::session_expired()),
+                    _ if cb_r.is_result_expired(id) => return Err(CurpError::result_expired()),
+                    _ if cb_r.is_canceled(id) => return Err(CurpError::canceled()),
+                    _ if cb_r.is_poisoned() => return Err(CurpError::unavailable()),
                     _ => {}
                 }
             }
@@ -207,3 +735,225 @@ impl<C: Command> CommandBoard<C> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use curp_test_utils::test_cmd::{TestCommand, TestCommandResult};
+    use test_macros::abort_on_panic;
+
+    use super::*;
+
+    fn complete(board: &mut CommandBoard<TestCommand>, id: ProposeId) {
+        board.insert_er(
+            id,
+            Ok(TestCommandResult::new(
+                vec![u32::try_from(id.1).unwrap()],
+                vec![],
+            )),
+        );
+        board.insert_asr(id, Ok(id.1.into()));
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn wait_for_evicted_result_should_return_expired() {
+        let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        let cfg = ResultCacheConfig {
+            results_per_client: 1,
+            ..Default::default()
+        };
+        complete(&mut board.write(), ProposeId(1, 1));
+        complete(&mut board.write(), ProposeId(1, 2));
+        assert_eq!(board.write().evict_results(&cfg, Instant::now(), false), 1);
+
+        let (er, asr) = CommandBoard::wait_for_er_asr(&board, ProposeId(1, 2))
+            .await
+            .unwrap();
+        assert_eq!(er.unwrap().values, vec![2]);
+        assert_eq!(asr.unwrap().unwrap(), 2.into());
+        let err = CommandBoard::wait_for_er_asr(&board, ProposeId(1, 1))
+            .await
+            .unwrap_err();
+        assert_eq!(err, CurpError::result_expired());
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn retry_in_expired_session_should_return_session_expired() {
+        let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        assert!(board.write().touch_session(ProposeId(1, 1), Instant::now()));
+        complete(&mut board.write(), ProposeId(1, 1));
+        assert_eq!(board.write().expire_sessions(&[(1, 1)], Instant::now()), 1);
+        assert!(board.map_read(|cb_r| cb_r.er_buffer.is_empty() && cb_r.asr_buffer.is_empty()));
+
+        // the expired sessions are carried in snapshots
+        let mut restored = CommandBoard::<TestCommand>::new();
+        restored.restore_results(&board.read().encode_results(u64::MAX, 0));
+        let restored: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(restored));
+        for board in [board, restored] {
+            assert!(!board.write().touch_session(ProposeId(1, 1), Instant::now()));
+            let err = CommandBoard::wait_for_er_asr(&board, ProposeId(1, 1))
+                .await
+                .unwrap_err();
+            assert_eq!(err, CurpError::session_expired());
+        }
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn cancel_should_wake_up_waiters() {
+        let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        let id = ProposeId(1, 1);
+        let persisted = board.write().watch_persisted(id);
+        let waiter = tokio::spawn({
+            let board = Arc::clone(&board);
+            async move { CommandBoard::wait_for_er(&board, id).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        board.write().cancel(id);
+
+        assert_eq!(waiter.await.unwrap().unwrap_err(), CurpError::canceled());
+        assert!(persisted.await.is_err());
+        let err = CommandBoard::wait_for_er_asr(&board, id).await.unwrap_err();
+        assert_eq!(err, CurpError::canceled());
+        assert!(!board.read().is_appended(id));
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn poison_should_answer_uncompleted_cmds_with_unavailable() {
+        let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        complete(&mut board.write(), ProposeId(1, 1));
+        let waiter = tokio::spawn({
+            let board = Arc::clone(&board);
+            async move { CommandBoard::wait_for_er_asr(&board, ProposeId(1, 2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        board.write().poison();
+
+        assert_eq!(waiter.await.unwrap().unwrap_err(), CurpError::unavailable());
+        // the completed results were durably applied before the failure
+        let (_er, asr) = CommandBoard::wait_for_er_asr(&board, ProposeId(1, 1))
+            .await
+            .unwrap();
+        assert_eq!(asr.unwrap().unwrap(), 1.into());
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn persist_watchers_should_be_notified_or_released() {
+        let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        let persisted = board.write().watch_persisted(ProposeId(1, 1));
+        let released = board.write().watch_persisted(ProposeId(1, 2));
+        CommandBoard::notify_persisted(&board, [ProposeId(1, 1), ProposeId(1, 3)]);
+        persisted.await.unwrap();
+
+        // the leader retires before the entry is persisted
+        board.write().clear();
+        assert!(released.await.is_err());
+    }
+
+    #[test]
+    fn completed_results_should_survive_clear() {
+        let mut board = CommandBoard::<TestCommand>::new();
+        complete(&mut board, ProposeId(1, 1));
+        board.insert_er(ProposeId(1, 2), Ok(TestCommandResult::default()));
+
+        board.clear();
+        assert!(board.er_buffer.contains_key(&ProposeId(1, 1)));
+        assert!(board.asr_buffer.contains_key(&ProposeId(1, 1)));
+        assert!(!board.er_buffer.contains_key(&ProposeId(1, 2)));
+    }
+
+    #[test]
+    fn applied_cmds_should_be_deduplicated_within_window() {
+        let mut board = CommandBoard::<TestCommand>::new();
+        assert!(!board.record_applied(ProposeId(1, 1), 1, 3));
+        assert!(!board.record_applied(ProposeId(1, 2), 2, 3));
+        assert!(board.record_applied(ProposeId(1, 1), 3, 3));
+        assert!(board.is_applied(ProposeId(1, 2)));
+        // log[1] is out of the window
+        assert!(!board.record_applied(ProposeId(1, 1), 4, 3));
+        assert!(!board.is_applied(ProposeId(2, 1)));
+    }
+
+    #[test]
+    fn applied_cmds_should_survive_snapshot() {
+        let mut board = CommandBoard::<TestCommand>::new();
+        for index in 1..=3 {
+            assert!(!board.record_applied(ProposeId(1, index), index, 100));
+        }
+        let encoded = board.encode_results(u64::MAX, 2);
+
+        let mut restored = CommandBoard::<TestCommand>::new();
+        restored.restore_results(&encoded);
+        assert!(restored.record_applied(ProposeId(1, 2), 4, 100));
+        // applied after the snapshot was taken, the restored node should apply it
+        assert!(!restored.record_applied(ProposeId(1, 3), 5, 100));
+    }
+
+    #[test]
+    fn encoded_results_should_be_bounded() {
+        const MAX_SIZE: u64 = 64 * 1024;
+        let mut board = CommandBoard::<TestCommand>::new();
+        for client_id in 1..=5000 {
+            for seq_num in 1..=3 {
+                complete(&mut board, ProposeId(client_id, seq_num));
+            }
+        }
+        let newest = ProposeId(5001, 1);
+        complete(&mut board, newest);
+
+        let encoded = board.encode_results(MAX_SIZE, 0);
+        assert!(!encoded.is_empty());
+        assert!(encoded.len() as u64 <= MAX_SIZE, "{} bytes", encoded.len());
+
+        let mut restored = CommandBoard::<TestCommand>::new();
+        restored.restore_results(&encoded);
+        let (sessions, results) = restored.results_occupancy();
+        assert!(sessions > 0 && sessions < 5001);
+        assert!(results > 0 && results < 15001);
+        assert!(restored.asr_buffer.contains_key(&newest));
+        assert_eq!(
+            restored.er_buffer.len(),
+            restored.asr_buffer.len(),
+            "every restored result should be complete"
+        );
+        assert!(!restored.er_buffer.contains_key(&ProposeId(1, 1)));
+        // a result left out of a restored session is reported expired
+        let left_out = (1..=5000)
+            .flat_map(|client_id| (1..=3).map(move |seq_num| ProposeId(client_id, seq_num)))
+            .find(|&id| {
+                !restored.er_buffer.contains_key(&id)
+                    && restored.results.evicted_seq_num(id.0).is_some()
+            });
+        if let Some(id) = left_out {
+            assert!(restored.is_result_expired(id));
+        }
+    }
+
+    #[test]
+    fn timeline_latency_should_cover_every_stage() {
+        let executed_at = Instant::now();
+        let timeline = CmdTimeline {
+            executed_at: Some(executed_at),
+            execute: Some(Duration::from_millis(10)),
+            committed_at: Some(executed_at + Duration::from_millis(30)),
+        };
+        let as_started_at = executed_at + Duration::from_millis(50);
+        let latency = timeline.latency(as_started_at, as_started_at + Duration::from_millis(20));
+        assert_eq!(latency.execute, Some(Duration::from_millis(10)));
+        assert_eq!(latency.commit, Some(Duration::from_millis(30)));
+        assert_eq!(latency.apply_wait, Some(Duration::from_millis(20)));
+        assert_eq!(latency.apply, Duration::from_millis(20));
+        assert_eq!(latency.total, Duration::from_millis(70));
+
+        // a cmd only applied here has no execute or commit stage
+        let latency =
+            CmdTimeline::default().latency(as_started_at, as_started_at + Duration::from_millis(5));
+        assert_eq!(latency.execute, None);
+        assert_eq!(latency.commit, None);
+        assert_eq!(latency.apply_wait, None);
+        assert_eq!(latency.total, Duration::from_millis(5));
+    }
+}
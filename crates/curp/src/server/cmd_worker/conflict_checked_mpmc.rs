@@ -96,13 +96,22 @@ impl<C: Command> Vertex<C> {
                 VertexInner::Entry { entry: entry1, .. },
                 VertexInner::Entry { entry: entry2, .. },
             ) => {
-                let EntryData::Command(ref cmd1) = entry1.entry_data else {
+                let (EntryData::Command(ref cmd1) | EntryData::MetaCommand(ref cmd1)) =
+                    entry1.entry_data
+                else {
                     return true;
                 };
-                let EntryData::Command(ref cmd2) = entry2.entry_data else {
+                let (EntryData::Command(ref cmd2) | EntryData::MetaCommand(ref cmd2)) =
+                    entry2.entry_data
+                else {
                     return true;
                 };
-                cmd1.is_conflict(cmd2)
+                // the commands of a batch are applied in order, the batch is applied
+                // only after its last command
+                let same_batch = entry1.batch.is_some()
+                    && entry2.batch.is_some()
+                    && entry1.index == entry2.index;
+                same_batch || cmd1.is_conflict(cmd2)
             }
             _ => true,
         }
@@ -159,6 +168,8 @@ enum AsState<C: Command> {
     AfterSyncing,
     /// Has been after synced
     AfterSynced,
+    /// Removed from the log before being committed, it will never be after synced
+    Discarded(Option<C::PR>),
 }
 
 impl<C: Command> AsState<C> {
@@ -169,7 +180,7 @@ impl<C: Command> AsState<C> {
             Self::NotSynced(ref mut pre_res) | Self::AfterSyncReady(ref mut pre_res) => {
                 *pre_res = Some(res);
             }
-            Self::AfterSyncing | Self::AfterSynced => {
+            Self::AfterSyncing | Self::AfterSynced | Self::Discarded(_) => {
                 unreachable!("Pre-execute result cannot be set in the {:?} stage", *self)
             }
         }
@@ -341,14 +352,15 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
                 ) => {
                     assert!(prepare.is_none(), "The prepare result of a given cmd can only be calculated when exe_state change from ExecuteReady to Executing");
                     let prepare_err = match entry.entry_data {
-                        EntryData::Command(ref cmd) => {
+                        EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) => {
                             match self.cmd_executor.prepare(cmd.as_ref()) {
                                 Ok(pre_res) => {
                                     as_st.set_prepare_result(pre_res);
                                     None
                                 }
                                 Err(err) => {
-                                    self.cmd_executor.trigger(entry.inflight_id(), entry.index);
+                                    self.cmd_executor
+                                        .trigger(entry.inflight_id(), entry.applied_index());
                                     Some(err)
                                 }
                             }
@@ -356,7 +368,13 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
                         EntryData::ConfChange(_)
                         | EntryData::Shutdown
                         | EntryData::Empty
-                        | EntryData::SetNodeState(_, _, _) => None,
+                        | EntryData::SetNodeState(_, _, _)
+                        | EntryData::SetClusterVersion(_)
+                        | EntryData::ExpireSessions(_)
+                        | EntryData::Cancel(_) => None,
+                        EntryData::Commands(_) | EntryData::MetaCommands(_) => {
+                            unreachable!("batched commands should be unpacked before execution")
+                        }
                     };
                     *exe_st = ExeState::Executing;
                     let task = Task {
@@ -379,9 +397,31 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
                     }
                     false
                 }
-                (ExeState::Executed(false), AsState::AfterSyncReady(_))
-                | (ExeState::Executed(_), AsState::AfterSynced) => true,
-                (ExeState::Executing | ExeState::Executed(_), AsState::NotSynced(_))
+                (ExeState::Executed(false), AsState::AfterSyncReady(prepare)) => {
+                    if let (
+                        &EntryData::Command(ref cmd) | &EntryData::MetaCommand(ref cmd),
+                        Some(prepare),
+                    ) = (&entry.entry_data, prepare)
+                    {
+                        self.cmd_executor.release(cmd.as_ref(), prepare);
+                    }
+                    true
+                }
+                (ExeState::Executed(_), AsState::AfterSynced) => true,
+                // the prepare result of a discarded cmd is released once it's executed
+                (ExeState::ExecuteReady, AsState::Discarded(_)) => true,
+                (ExeState::Executed(_), AsState::Discarded(prepare)) => {
+                    if let (
+                        &EntryData::Command(ref cmd) | &EntryData::MetaCommand(ref cmd),
+                        Some(prepare),
+                    ) = (&entry.entry_data, prepare)
+                    {
+                        self.cmd_executor.release(cmd.as_ref(), prepare);
+                    }
+                    true
+                }
+                (ExeState::Executing, AsState::Discarded(_))
+                | (ExeState::Executing | ExeState::Executed(_), AsState::NotSynced(_))
                 | (ExeState::Executing, AsState::AfterSyncReady(_) | AsState::AfterSyncing)
                 | (ExeState::Executed(true), AsState::AfterSyncing) => false,
                 (exe_st, as_st) => {
@@ -463,11 +503,20 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
                 if let Some(vid) = self.cmd_vid.get(&entry.propose_id).copied() {
                     let v = self.get_vertex_mut(vid);
                     match v.inner {
-                        VertexInner::Entry { ref mut as_st, .. } => {
-                            let AsState::NotSynced(ref mut prepare) = *as_st else {
+                        VertexInner::Entry {
+                            entry: ref mut v_entry,
+                            ref mut as_st,
+                            ..
+                        } => {
+                            // a discarded cmd may still be committed at another index
+                            let (AsState::NotSynced(ref mut prepare)
+                            | AsState::Discarded(ref mut prepare)) = *as_st
+                            else {
                                 unreachable!("after sync state should be AsState::NotSynced but found {as_st:?}");
                             };
                             *as_st = AsState::AfterSyncReady(prepare.take());
+                            // the committed entry knows whether it completes a batch
+                            *v_entry = entry;
                         }
                         _ => unreachable!("impossible vertex type"),
                     }
@@ -491,6 +540,21 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
                     new_vid
                 }
             }
+            CEEvent::Discard(entries) => {
+                for entry in entries {
+                    let Some(vid) = self.cmd_vid.get(&entry.propose_id).copied() else {
+                        continue;
+                    };
+                    if let VertexInner::Entry { ref mut as_st, .. } = self.get_vertex_mut(vid).inner
+                    {
+                        if let AsState::NotSynced(ref mut prepare) = *as_st {
+                            *as_st = AsState::Discarded(prepare.take());
+                        }
+                    }
+                    self.update_graph(vid);
+                }
+                return;
+            }
             CEEvent::Reset(snapshot, finish_tx) => {
                 // since a reset is needed, all other vertices doesn't matter anymore, so delete them all
                 self.cmd_vid.clear();
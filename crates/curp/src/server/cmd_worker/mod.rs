@@ -7,14 +7,17 @@ use async_trait::async_trait;
 use clippy_utilities::NumericCast;
 #[cfg(test)]
 use mockall::automock;
-use tokio::sync::oneshot;
-use tracing::{debug, error, info, warn};
+use tokio::{sync::oneshot, time::Instant};
+use tracing::{debug, error, info, warn, Instrument};
 use utils::task_manager::{tasks::TaskName, Listener, TaskManager};
 
 use self::conflict_checked_mpmc::Task;
-use super::raw_curp::RawCurp;
+use super::{
+    metrics::{self, EntryStage},
+    raw_curp::RawCurp,
+};
 use crate::{
-    cmd::{Command, CommandExecutor},
+    cmd::{Command, CommandExecutor, ProposalRecord},
     log_entry::{EntryData, LogEntry},
     role_change::RoleChange,
     rpc::{ConfChangeType, PoolEntry},
@@ -31,6 +34,9 @@ pub(super) enum CEEvent<C> {
     SpecExeReady(Arc<LogEntry<C>>),
     /// The cmd is ready for after sync
     ASReady(Arc<LogEntry<C>>),
+    /// The speculatively executed cmds are removed from the log, they will never be
+    /// after synced
+    Discard(Vec<Arc<LogEntry<C>>>),
     /// Reset the command executor, send(()) when finishes
     Reset(Option<Snapshot>, oneshot::Sender<()>),
     /// Take a snapshot
@@ -42,6 +48,7 @@ impl<C: Command> Debug for CEEvent<C> {
         match *self {
             Self::SpecExeReady(ref entry) => f.debug_tuple("SpecExeReady").field(entry).finish(),
             Self::ASReady(ref entry) => f.debug_tuple("ASReady").field(entry).finish(),
+            Self::Discard(ref entries) => f.debug_tuple("Discard").field(entries).finish(),
             Self::Reset(ref ss, _) => {
                 if ss.is_none() {
                     write!(f, "Reset(None)")
@@ -89,11 +96,56 @@ async fn handle_task<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     curp: &RawCurp<C, RC>,
 ) {
     let succeeded = match task.take() {
-        TaskType::SpecExe(entry, pre_err) => worker_exe(entry, pre_err, ce, curp).await,
-        TaskType::AS(entry, prepare) => worker_as(entry, prepare, ce, curp).await,
+        TaskType::SpecExe(entry, pre_err) => {
+            let span = curp.cmd_board().read().execute_span(entry.propose_id);
+            worker_exe(entry, pre_err, ce, curp).instrument(span).await
+        }
+        TaskType::AS(entry, prepare) => {
+            // the state machine may no longer match the log, no more entry is applied
+            if let Some(reason) = ce.storage_failure() {
+                debug!(
+                    "{} skips the after sync of log[{}], the storage failed: {reason}",
+                    curp.id(),
+                    entry.index
+                );
+                curp.cmd_board().write().poison();
+                report_done(task, false, done_tx, curp);
+                return;
+            }
+            if curp.cmd_board().read().is_poisoned() {
+                debug!(
+                    "{} skips the after sync of log[{}], it has stopped applying",
+                    curp.id(),
+                    entry.index
+                );
+                report_done(task, false, done_tx, curp);
+                return;
+            }
+            let span = curp.cmd_board().write().after_sync_span(entry.propose_id);
+            let entry_type = entry.metric_label();
+            let start = Instant::now();
+            let succeeded = worker_as(Arc::clone(&entry), prepare, ce, curp)
+                .instrument(span.clone())
+                .await;
+            metrics::get()
+                .entry_stages
+                .record(EntryStage::Apply, entry_type, start.elapsed());
+            span.in_scope(|| check_slow_apply(&entry, start, curp));
+            succeeded
+        }
         TaskType::Reset(snapshot, finish_tx) => worker_reset(snapshot, finish_tx, ce, curp).await,
         TaskType::Snapshot(meta, tx) => worker_snapshot(meta, tx, ce, curp).await,
     };
+    report_done(task, succeeded, done_tx, curp);
+}
+
+/// Mark a task done
+fn report_done<C: Command, RC: RoleChange>(
+    task: Task<C>,
+    succeeded: bool,
+    done_tx: &flume::Sender<(Task<C>, bool)>,
+    curp: &RawCurp<C, RC>,
+) {
     if let Err(e) = done_tx.send((task, succeeded)) {
         if !curp.is_shutdown() {
             error!("can't mark a task done, the channel could be closed, {e}");
@@ -101,6 +153,33 @@ async fn handle_task<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     }
 }
 
+/// Warn about a cmd whose after sync completes too late after it entered the pipeline
+fn check_slow_apply<C: Command, RC: RoleChange>(
+    entry: &LogEntry<C>,
+    as_started_at: Instant,
+    curp: &RawCurp<C, RC>,
+) {
+    let (EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd)) = entry.entry_data else {
+        return;
+    };
+    let timeline = curp.cmd_board().write().take_timeline(entry.propose_id);
+    let threshold = curp.cfg().slow_apply_threshold;
+    let latency = timeline.latency(as_started_at, Instant::now());
+    if threshold.is_zero() || latency.total < threshold {
+        return;
+    }
+    warn!(
+        propose_id = %entry.propose_id,
+        kind = cmd.kind(),
+        total = ?latency.total,
+        execute = ?latency.execute,
+        commit = ?latency.commit,
+        apply_wait = ?latency.apply_wait,
+        apply = ?latency.apply,
+        "slow apply, expected to take less than {threshold:?}"
+    );
+}
+
 /// Cmd worker execute handler
 async fn worker_exe<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     entry: Arc<LogEntry<C>>,
@@ -111,14 +190,28 @@ async fn worker_exe<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     let (cb, sp, ucp) = (curp.cmd_board(), curp.spec_pool(), curp.uncommitted_pool());
     let id = curp.id();
     let success = match entry.entry_data {
-        EntryData::Command(ref cmd) => {
+        EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) => {
+            let start = Instant::now();
             let er = if let Some(err_msg) = pre_err {
                 Err(err_msg)
             } else {
                 ce.execute(cmd).await
             };
+            let elapsed = start.elapsed();
+            metrics::get()
+                .entry_stages
+                .record(EntryStage::Execute, entry.metric_label(), elapsed);
             let er_ok = er.is_ok();
-            cb.write().insert_er(entry.propose_id, er);
+            {
+                let mut cb_w = cb.write();
+                // an after sync only follows a successful execution
+                if er_ok {
+                    cb_w.record_execute(entry.propose_id, start, elapsed);
+                } else {
+                    let _ignore = cb_w.take_timeline(entry.propose_id);
+                }
+                cb_w.insert_er(entry.propose_id, er);
+            }
             if !er_ok {
                 sp.lock()
                     .remove(PoolEntry::new(entry.propose_id, Arc::clone(cmd)));
@@ -134,10 +227,16 @@ async fn worker_exe<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
         EntryData::ConfChange(_)
         | EntryData::Shutdown
         | EntryData::Empty
-        | EntryData::SetNodeState(_, _, _) => true,
+        | EntryData::SetNodeState(_, _, _)
+        | EntryData::SetClusterVersion(_)
+        | EntryData::ExpireSessions(_)
+        | EntryData::Cancel(_) => true,
+        EntryData::Commands(_) | EntryData::MetaCommands(_) => {
+            unreachable!("batched commands should be unpacked before execution")
+        }
     };
     if !success {
-        ce.trigger(entry.inflight_id(), entry.index);
+        ce.trigger(entry.inflight_id(), entry.applied_index());
     }
     success
 }
@@ -152,11 +251,51 @@ async fn worker_as<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     let (cb, sp, ucp) = (curp.cmd_board(), curp.spec_pool(), curp.uncommitted_pool());
     let id = curp.id();
     let success = match entry.entry_data {
-        EntryData::Command(ref cmd) => {
+        EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) => {
             let Some(prepare) = prepare else {
                 unreachable!("prepare should always be Some(_) when entry is a command");
             };
-            let asr = ce.after_sync(cmd.as_ref(), entry.index, prepare).await;
+            let propose_id = (entry.propose_id.0, entry.propose_id.1);
+            // persisted with the writes of the cmd, so that a retry is neither appended
+            // again nor answered with a conflict after a restart
+            let er = cb
+                .read()
+                .er_buffer
+                .get(&entry.propose_id)
+                .and_then(|er| er.as_ref().ok().cloned());
+            let retain_from = entry
+                .index
+                .saturating_sub(curp.cfg().result_cache.dedup_window);
+            let record = ProposalRecord::new(er.as_ref(), retain_from);
+            let asr = match entry.batch {
+                Some(batch) => {
+                    ce.after_sync_in_batch(
+                        cmd.as_ref(),
+                        propose_id,
+                        entry.index,
+                        batch.pos,
+                        batch.last,
+                        prepare,
+                        record,
+                    )
+                    .await
+                }
+                None => {
+                    ce.after_sync_proposal(cmd.as_ref(), propose_id, entry.index, prepare, record)
+                        .await
+                }
+            };
+            if asr.is_err() {
+                // the writes of the entry may not be durable, it must not be acknowledged
+                if let Some(reason) = ce.storage_failure() {
+                    error!(
+                        "{id} stops applying, the storage failed at log[{}]: {reason}",
+                        entry.index
+                    );
+                    cb.write().poison();
+                    return false;
+                }
+            }
             let asr_ok = asr.is_ok();
             cb.write().insert_asr(entry.propose_id, asr);
             sp.lock()
@@ -178,6 +317,7 @@ async fn worker_as<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
             true
         }
         EntryData::ConfChange(ref conf_change) => {
+            utils::fail_point_async!("curp_before_apply_conf_change");
             if let Err(e) = ce.set_last_applied(entry.index) {
                 error!("failed to set last_applied, {e}");
                 return false;
@@ -238,9 +378,41 @@ async fn worker_as<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
                 .set_node_state(node_id, name.clone(), client_urls.clone());
             true
         }
+        EntryData::SetClusterVersion(version) => {
+            // the entries after it may not be applied the same way as the others
+            if !curp.set_cluster_server_version(version) {
+                error!("{id} stops applying at log[{}]", entry.index);
+                cb.write().poison();
+                return false;
+            }
+            if let Err(e) = ce.set_last_applied(entry.index) {
+                error!("failed to set last_applied, {e}");
+                return false;
+            }
+            true
+        }
+        EntryData::ExpireSessions(ref sessions) => {
+            curp.expire_sessions(sessions);
+            if let Err(e) = ce.set_last_applied(entry.index) {
+                error!("failed to set last_applied, {e}");
+                return false;
+            }
+            true
+        }
+        // the cancel is recorded when the entry is committed
+        EntryData::Cancel(_) => {
+            if let Err(e) = ce.set_last_applied(entry.index) {
+                error!("failed to set last_applied, {e}");
+                return false;
+            }
+            true
+        }
         EntryData::Empty => true,
+        EntryData::Commands(_) | EntryData::MetaCommands(_) => {
+            unreachable!("batched commands should be unpacked before after sync")
+        }
     };
-    ce.trigger(entry.inflight_id(), entry.index);
+    ce.trigger(entry.inflight_id(), entry.applied_index());
     success
 }
 
@@ -252,8 +424,10 @@ async fn worker_reset<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
     curp: &RawCurp<C, RC>,
 ) -> bool {
     let id = curp.id();
-    if let Some(snapshot) = snapshot {
+    if let Some(mut snapshot) = snapshot {
         let meta = snapshot.meta;
+        let results = snapshot.take_results();
+        let members = snapshot.take_members();
         #[allow(clippy::expect_used)] // only in debug
         if let Err(e) = ce
             .reset(Some((snapshot.into_inner(), meta.last_included_index)))
@@ -269,6 +443,12 @@ async fn worker_reset<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
             );
             debug!("{id}'s command executor has been reset by a snapshot");
             curp.reset_by_snapshot(meta);
+            if !curp.set_cluster_server_version(meta.cluster_server_version) {
+                error!("{id} stops applying after the snapshot");
+                curp.cmd_board().write().poison();
+            }
+            curp.reset_membership_by_snapshot(meta.membership_index, members);
+            curp.cmd_board().write().restore_results(&results);
         }
     } else {
         if let Err(e) = ce.reset(None).await {
@@ -282,7 +462,7 @@ async fn worker_reset<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
 
 /// Cmd worker snapshot handler
 async fn worker_snapshot<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
-    meta: SnapshotMeta,
+    mut meta: SnapshotMeta,
     tx: oneshot::Sender<Snapshot>,
     ce: &CE,
     curp: &RawCurp<C, RC>,
@@ -294,11 +474,26 @@ async fn worker_snapshot<C: Command, CE: CommandExecutor<C>, RC: RoleChange>(
                     .is_ok_and(|last_applied| last_applied <= meta.last_included_index),
                 " the `last_as` should always be less than or equal to the `last_exe`"
             ); // sanity check
-            let snapshot = Snapshot::new(meta, snapshot);
+            let results = curp.cmd_board().read().encode_results(
+                curp.cfg().result_cache.max_snapshot_size,
+                meta.last_included_index,
+            );
+            // the snapshot is taken once the entries before it are applied, and so is
+            // the cluster server version
+            meta.cluster_server_version = curp.cluster().cluster_server_version();
+            // the conf changes are applied once appended, the ones after the last included
+            // index are replayed by the follower installing the snapshot
+            let (membership_index, members) = curp.membership_at(meta.last_included_index);
+            meta.membership_index = membership_index;
+            let snapshot = Snapshot::new(meta, snapshot)
+                .with_results(results)
+                .with_members(members);
             debug!("{} takes a snapshot, {snapshot:?}", curp.id());
             if tx.send(snapshot).is_err() {
                 error!("snapshot oneshot closed");
             }
+            // the entries covered by the snapshot are no longer needed after a restart
+            curp.compact_storage_log(meta.last_included_index);
             true
         }
         Err(e) => {
@@ -325,6 +520,10 @@ pub(crate) trait CEEventTxApi<C: Command>: Send + Sync + 'static {
     /// Send after sync event to the background cmd worker so that after sync can be called
     fn send_after_sync(&self, entry: Arc<LogEntry<C>>);
 
+    /// Send the speculatively executed cmds removed from the log, so that the background
+    /// cmd worker stops waiting for their after sync
+    fn send_discard(&self, entries: Vec<Arc<LogEntry<C>>>);
+
     /// Send reset
     fn send_reset(&self, snapshot: Option<Snapshot>) -> oneshot::Receiver<()>;
 
@@ -356,6 +555,10 @@ impl<C: Command> CEEventTxApi<C> for CEEventTx<C> {
         self.send_event(event);
     }
 
+    fn send_discard(&self, entries: Vec<Arc<LogEntry<C>>>) {
+        self.send_event(CEEvent::Discard(entries));
+    }
+
     fn send_reset(&self, snapshot: Option<Snapshot>) -> oneshot::Receiver<()> {
         let (tx, rx) = oneshot::channel();
         let event = CEEvent::Reset(snapshot, tx);
@@ -425,7 +628,11 @@ mod tests {
     use utils::config::EngineConfig;
 
     use super::*;
-    use crate::{log_entry::LogEntry, rpc::ProposeId};
+    use crate::{
+        log_entry::LogEntry,
+        rpc::{CurpError, ProposeId},
+        server::cmd_board::CommandBoard,
+    };
 
     // This should happen in fast path in most cases
     #[traced_test]
@@ -470,6 +677,63 @@ mod tests {
         task_manager.shutdown(true).await;
     }
 
+    #[traced_test]
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn slow_execution_should_be_warned_as_slow_apply() {
+        let (er_tx, mut er_rx) = mpsc::unbounded_channel();
+        let (as_tx, mut as_rx) = mpsc::unbounded_channel();
+        let ce = Arc::new(TestCE::new(
+            "S1".to_owned(),
+            er_tx,
+            as_tx,
+            EngineConfig::Memory,
+        ));
+        let task_manager = Arc::new(TaskManager::new());
+        let (ce_event_tx, task_rx, done_tx) =
+            conflict_checked_mpmc::channel(Arc::clone(&ce), Arc::clone(&task_manager));
+        start_cmd_workers(
+            Arc::clone(&ce),
+            Arc::new(RawCurp::new_test(
+                3,
+                ce_event_tx.clone(),
+                mock_role_change(),
+                Arc::clone(&task_manager),
+            )),
+            task_rx,
+            done_tx,
+        );
+
+        let fast = Arc::new(LogEntry::new(
+            1,
+            1,
+            ProposeId(0, 0),
+            Arc::new(TestCommand::new_put(vec![1], 1)),
+        ));
+        ce_event_tx.send_sp_exe(Arc::clone(&fast));
+        let _er = er_rx.recv().await.unwrap();
+        ce_event_tx.send_after_sync(fast);
+        assert_eq!(as_rx.recv().await.unwrap().1, 1);
+        sleep_millis(10).await;
+        assert!(!logs_contain("slow apply"));
+
+        // the default threshold is 100ms
+        let slow = Arc::new(LogEntry::new(
+            2,
+            1,
+            ProposeId(0, 1),
+            Arc::new(TestCommand::new_put(vec![2], 2).set_exe_dur(Duration::from_millis(150))),
+        ));
+        ce_event_tx.send_sp_exe(Arc::clone(&slow));
+        let _er = er_rx.recv().await.unwrap();
+        ce_event_tx.send_after_sync(slow);
+        assert_eq!(as_rx.recv().await.unwrap().1, 2);
+        sleep_millis(10).await;
+        assert!(logs_contain("slow apply"));
+        assert!(logs_contain("propose_id=0#1"));
+        task_manager.shutdown(true).await;
+    }
+
     // When the execution takes more time than sync, `as` should be called after exe has finished
     #[traced_test]
     #[tokio::test]
@@ -657,6 +921,57 @@ mod tests {
         task_manager.shutdown(true).await;
     }
 
+    // When the storage fails, the node stops applying instead of failing the cmd
+    #[traced_test]
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn storage_failure_should_stop_applies_without_acking() {
+        let (er_tx, _er_rx) = mpsc::unbounded_channel();
+        let (as_tx, mut as_rx) = mpsc::unbounded_channel();
+        let ce = Arc::new(TestCE::new(
+            "S1".to_owned(),
+            er_tx,
+            as_tx,
+            EngineConfig::Memory,
+        ));
+        let task_manager = Arc::new(TaskManager::new());
+        let (ce_event_tx, task_rx, done_tx) =
+            conflict_checked_mpmc::channel(Arc::clone(&ce), Arc::clone(&task_manager));
+        let curp = Arc::new(RawCurp::new_test(
+            3,
+            ce_event_tx.clone(),
+            mock_role_change(),
+            Arc::clone(&task_manager),
+        ));
+        start_cmd_workers(Arc::clone(&ce), Arc::clone(&curp), task_rx, done_tx);
+
+        let put = |index, key| {
+            Arc::new(LogEntry::new(
+                index,
+                1,
+                ProposeId(0, index),
+                Arc::new(TestCommand::new_put(vec![key], key)),
+            ))
+        };
+        ce_event_tx.send_after_sync(put(1, 1));
+        assert_eq!(as_rx.recv().await.unwrap().1, 1);
+
+        ce.inject_storage_fault();
+        ce_event_tx.send_after_sync(put(2, 2));
+        ce_event_tx.send_after_sync(put(3, 3));
+        sleep_millis(100).await;
+
+        assert!(as_rx.try_recv().is_err());
+        for seq in [2, 3] {
+            let err = CommandBoard::wait_for_er_asr(&curp.cmd_board(), ProposeId(0, seq))
+                .await
+                .unwrap_err();
+            assert_eq!(err, CurpError::unavailable());
+        }
+        assert_eq!(ce.last_applied().unwrap(), 1);
+        task_manager.shutdown(true).await;
+    }
+
     // If cmd1 and cmd2 conflict, order will be (cmd1 exe) -> (cmd1 as) -> (cmd2 exe) -> (cmd2 as)
     #[traced_test]
     #[tokio::test]
@@ -720,6 +1035,61 @@ mod tests {
         task_manager.shutdown(true).await;
     }
 
+    #[traced_test]
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn discarded_cmd_will_not_block_conflicting_cmds() {
+        let (er_tx, mut er_rx) = mpsc::unbounded_channel();
+        let (as_tx, mut as_rx) = mpsc::unbounded_channel();
+        let ce = Arc::new(TestCE::new(
+            "S1".to_owned(),
+            er_tx,
+            as_tx,
+            EngineConfig::Memory,
+        ));
+        let task_manager = Arc::new(TaskManager::new());
+        let (ce_event_tx, task_rx, done_tx) =
+            conflict_checked_mpmc::channel(Arc::clone(&ce), Arc::clone(&task_manager));
+        start_cmd_workers(
+            Arc::clone(&ce),
+            Arc::new(RawCurp::new_test(
+                3,
+                ce_event_tx.clone(),
+                mock_role_change(),
+                Arc::clone(&task_manager),
+            )),
+            task_rx,
+            done_tx,
+        );
+
+        let entry1 = Arc::new(LogEntry::new(
+            1,
+            1,
+            ProposeId(0, 0),
+            Arc::new(TestCommand::new_put(vec![1], 1)),
+        ));
+        let entry2 = Arc::new(LogEntry::new(
+            1,
+            2,
+            ProposeId(0, 1),
+            Arc::new(TestCommand::new_put(vec![1], 2)),
+        ));
+        ce_event_tx.send_sp_exe(Arc::clone(&entry1));
+        assert!(er_rx.recv().await.is_some());
+
+        // entry1 is overwritten by a new leader before it's committed
+        ce_event_tx.send_discard(vec![entry1]);
+        ce_event_tx.send_after_sync(entry2);
+
+        assert!(er_rx.recv().await.is_some());
+        let (cmd, index) = as_rx.recv().await.unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(cmd, TestCommand::new_put(vec![1], 2));
+        sleep_millis(100).await;
+        assert!(as_rx.try_recv().is_err());
+        task_manager.shutdown(true).await;
+    }
+
     #[traced_test]
     #[tokio::test]
     #[abort_on_panic]
@@ -837,6 +1207,8 @@ mod tests {
             .send_snapshot(SnapshotMeta {
                 last_included_index: 1,
                 last_included_term: 0,
+                cluster_server_version: 0,
+                membership_index: 0,
             })
             .await
             .unwrap();
@@ -11,7 +11,11 @@ mod tests;
 #[doc(hidden)]
 pub mod test_pools;
 
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    sync::Arc,
+};
 
 use crate::rpc::{ConfChange, PoolEntry, PoolEntryInner, ProposeId};
 
@@ -121,6 +125,95 @@ impl<C> From<CommandEntry<C>> for PoolEntry<C> {
     }
 }
 
+/// Generations of the entries in a conflict pool, an entry is tagged with the term
+/// it's inserted at
+///
+/// The leader of a term recovers the speculative pools of a quorum into its log before
+/// its no-op. Once the no-op is committed, the entries inserted in the former terms and
+/// absent from the log will never complete.
+#[derive(Debug, Default)]
+pub(super) struct Generations {
+    /// The generation of the entries inserted now
+    current: u64,
+    /// The entries of the generations below it are stale
+    live: u64,
+    /// Whether some entries may be stale
+    has_stale: bool,
+    /// Generations of the entries
+    tags: HashMap<ProposeId, u64>,
+}
+
+impl Generations {
+    /// Sets the generation of the entries inserted from now on
+    fn set_current(&mut self, generation: u64) {
+        self.current = generation;
+    }
+
+    /// Marks the entries of the generations below `live` as stale, except the ones in
+    /// the log, which are removed once they are applied
+    fn retire(&mut self, live: u64, in_log: &HashSet<ProposeId>) {
+        if live <= self.live {
+            return;
+        }
+        self.live = live;
+        self.has_stale = false;
+        for (id, generation) in &mut self.tags {
+            if *generation >= live {
+                continue;
+            }
+            if in_log.contains(id) {
+                *generation = live;
+            } else {
+                self.has_stale = true;
+            }
+        }
+    }
+
+    /// Whether some entries may be stale
+    fn has_stale(&self) -> bool {
+        self.has_stale
+    }
+
+    /// Whether the entry is stale
+    fn is_stale(&self, id: ProposeId) -> bool {
+        self.tags
+            .get(&id)
+            .is_some_and(|&generation| generation < self.live)
+    }
+
+    /// All stale entries have been evicted
+    fn swept(&mut self) {
+        self.has_stale = false;
+    }
+
+    /// Tags an entry with the current generation
+    fn tag(&mut self, id: ProposeId) {
+        let _prev = self.tags.insert(id, self.current);
+    }
+
+    /// Whether the entry is tagged
+    fn contains(&self, id: ProposeId) -> bool {
+        self.tags.contains_key(&id)
+    }
+
+    /// Removes the tag of an entry
+    fn untag(&mut self, id: ProposeId) {
+        let _prev = self.tags.remove(&id);
+    }
+
+    /// Removes all tags
+    fn clear(&mut self) {
+        self.tags.clear();
+        self.has_stale = false;
+    }
+
+    /// The number of tagged entries
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.tags.len()
+    }
+}
+
 /// Conf change entry type
 #[derive(Clone, PartialEq)]
 pub(super) struct ConfChangeEntry {
@@ -1,7 +1,9 @@
+use std::collections::HashSet;
+
 use curp_external_api::conflict::{ConflictPoolOp, SpeculativePoolOp};
 
-use super::{CommandEntry, ConfChangeEntry, ConflictPoolEntry};
-use crate::rpc::PoolEntry;
+use super::{CommandEntry, ConfChangeEntry, ConflictPoolEntry, Generations};
+use crate::rpc::{PoolEntry, ProposeId};
 
 /// A speculative pool object
 pub type SpObject<C> = Box<dyn SpeculativePoolOp<Entry = CommandEntry<C>> + Send + 'static>;
@@ -12,6 +14,8 @@ pub(crate) struct SpeculativePool<C> {
     command_sps: Vec<SpObject<C>>,
     /// Conf change speculative pool
     conf_change_sp: ConfChangeSp,
+    /// Generations the entries are inserted at
+    generations: Generations,
 }
 
 impl<C> SpeculativePool<C> {
@@ -20,11 +24,53 @@ impl<C> SpeculativePool<C> {
         Self {
             command_sps,
             conf_change_sp: ConfChangeSp::default(),
+            generations: Generations::default(),
+        }
+    }
+
+    /// Sets the generation of the entries inserted from now on
+    pub(crate) fn set_generation(&mut self, generation: u64) {
+        self.generations.set_current(generation);
+    }
+
+    /// Marks the entries inserted before the generation and absent from the log as
+    /// stale, they are ignored and evicted by the next insertion or sweep
+    pub(crate) fn retire_generations(&mut self, live: u64, in_log: &HashSet<ProposeId>) {
+        self.generations.retire(live, in_log);
+    }
+
+    /// Evicts all stale entries, returns the number of evicted entries
+    pub(crate) fn sweep(&mut self) -> usize {
+        if !self.generations.has_stale() {
+            return 0;
+        }
+        let stale: Vec<_> = self
+            .all()
+            .into_iter()
+            .filter(|entry| self.generations.is_stale(entry.id))
+            .collect();
+        let evicted = stale.len();
+        for entry in stale {
+            self.remove(entry);
         }
+        self.generations.swept();
+        evicted
     }
 
     /// Inserts an entry into the pool
     pub(crate) fn insert(&mut self, entry: PoolEntry<C>) -> Option<PoolEntry<C>> {
+        // stale entries never complete, they must not reject the new ones
+        let _evicted = self.sweep();
+        let id = entry.id;
+        let rejected = self.insert_entry(entry);
+        if rejected.is_none() {
+            self.generations.tag(id);
+        }
+        rejected
+    }
+
+    /// Inserts an entry into the pools
+    fn insert_entry(&mut self, entry: PoolEntry<C>) -> Option<PoolEntry<C>> {
         if !self.conf_change_sp.is_empty() {
             return Some(entry);
         }
@@ -56,6 +102,7 @@ impl<C> SpeculativePool<C> {
     // TODO: Use reference instead of clone
     /// Removes an entry from the pool
     pub(crate) fn remove(&mut self, entry: PoolEntry<C>) {
+        self.generations.untag(entry.id);
         match ConflictPoolEntry::from(entry) {
             ConflictPoolEntry::Command(c) => {
                 for csp in &mut self.command_sps {
@@ -68,6 +115,18 @@ impl<C> SpeculativePool<C> {
         }
     }
 
+    /// Removes the entry of a propose id from the pool, returns `false` if it's absent
+    pub(crate) fn remove_by_id(&mut self, id: ProposeId) -> bool {
+        if !self.generations.contains(id) {
+            return false;
+        }
+        let Some(entry) = self.all().into_iter().find(|entry| entry.id == id) else {
+            return false;
+        };
+        self.remove(entry);
+        true
+    }
+
     /// Returns all entries in the pool
     pub(crate) fn all(&self) -> Vec<PoolEntry<C>> {
         let mut entries = Vec::new();
@@ -86,6 +145,12 @@ impl<C> SpeculativePool<C> {
             .fold(0, |sum, pool| sum + pool.len())
             + self.conf_change_sp.len()
     }
+
+    /// The number of entries whose generations are tracked
+    #[cfg(test)]
+    pub(crate) fn tracked(&self) -> usize {
+        self.generations.len()
+    }
 }
 
 /// Speculative pool for conf change entries
@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, collections::HashSet, sync::Arc};
 
 use curp_external_api::conflict::{ConflictPoolOp, SpeculativePoolOp, UncommittedPoolOp};
 
@@ -267,3 +267,61 @@ fn ucp_should_returns_all_conflict_entries() {
         assert_eq!(all, vec![e.clone(), e.clone(), conf_change.clone()]);
     }
 }
+
+#[test]
+fn stale_generations_should_be_evicted_from_sp() {
+    let mut sp = SpeculativePool::new(vec![Box::new(TestSp::default())]);
+    sp.set_generation(1);
+    for i in 0..10 {
+        assert!(sp
+            .insert(PoolEntry::new(ProposeId(0, i), Arc::new(i)))
+            .is_none());
+    }
+    // the leader of term 2 is known after the term change
+    sp.set_generation(2);
+    assert!(sp
+        .insert(PoolEntry::new(ProposeId(1, 0), Arc::new(10)))
+        .is_none());
+    // entry 9 is in the log of the new leader
+    sp.retire_generations(2, &HashSet::from([ProposeId(0, 9)]));
+    assert_eq!(sp.len(), 11);
+
+    // entries of the former terms no longer reject the conflicting ones
+    assert!(sp
+        .insert(PoolEntry::new(ProposeId(1, 1), Arc::new(0)))
+        .is_none());
+    assert_eq!(sp.len(), 3);
+    assert_eq!(sp.tracked(), 3);
+    assert!(sp
+        .insert(PoolEntry::new(ProposeId(1, 3), Arc::new(9)))
+        .is_some());
+    // entries of the live term still do
+    assert!(sp
+        .insert(PoolEntry::new(ProposeId(1, 2), Arc::new(10)))
+        .is_some());
+    assert_eq!(sp.sweep(), 0);
+}
+
+#[test]
+fn stale_generations_should_be_evicted_from_ucp() {
+    let mut ucp = UncommittedPool::new(vec![Box::new(TestUcp::default())]);
+    ucp.set_generation(1);
+    for i in 0..10 {
+        assert!(!ucp.insert(PoolEntry::new(ProposeId(0, i), Arc::new(i))));
+    }
+    ucp.set_generation(2);
+    ucp.retire_generations(2, &HashSet::new());
+
+    // stale entries are ignored by lookups before they are swept
+    let entry = PoolEntry::new(ProposeId(1, 0), Arc::new(0));
+    assert!(ucp.all_conflict(entry.clone()).is_empty());
+    assert_eq!(ucp.sweep(), 10);
+    assert!(ucp.is_empty());
+    assert_eq!(ucp.tracked(), 0);
+
+    assert!(!ucp.insert(entry.clone()));
+    assert_eq!(ucp.all_conflict(entry.clone()), vec![entry]);
+    // retiring an older generation changes nothing
+    ucp.retire_generations(1, &HashSet::new());
+    assert_eq!(ucp.sweep(), 0);
+}
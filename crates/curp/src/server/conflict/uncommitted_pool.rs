@@ -1,7 +1,9 @@
+use std::collections::HashSet;
+
 use curp_external_api::conflict::{ConflictPoolOp, UncommittedPoolOp};
 
-use super::{CommandEntry, ConfChangeEntry, ConflictPoolEntry};
-use crate::rpc::PoolEntry;
+use super::{CommandEntry, ConfChangeEntry, ConflictPoolEntry, Generations};
+use crate::rpc::{PoolEntry, ProposeId};
 
 /// An uncommitted pool object
 pub type UcpObject<C> = Box<dyn UncommittedPoolOp<Entry = CommandEntry<C>> + Send + 'static>;
@@ -12,6 +14,8 @@ pub(crate) struct UncommittedPool<C> {
     command_ucps: Vec<UcpObject<C>>,
     /// Conf change uncommitted pools
     conf_change_ucp: ConfChangeUcp,
+    /// Generations the entries are inserted at
+    generations: Generations,
 }
 
 impl<C> UncommittedPool<C> {
@@ -20,11 +24,44 @@ impl<C> UncommittedPool<C> {
         Self {
             command_ucps,
             conf_change_ucp: ConfChangeUcp::default(),
+            generations: Generations::default(),
+        }
+    }
+
+    /// Sets the generation of the entries inserted from now on
+    pub(crate) fn set_generation(&mut self, generation: u64) {
+        self.generations.set_current(generation);
+    }
+
+    /// Marks the entries inserted before the generation and absent from the log as
+    /// stale, they are ignored and evicted by the next insertion or sweep
+    pub(crate) fn retire_generations(&mut self, live: u64, in_log: &HashSet<ProposeId>) {
+        self.generations.retire(live, in_log);
+    }
+
+    /// Evicts all stale entries, returns the number of evicted entries
+    pub(crate) fn sweep(&mut self) -> usize {
+        if !self.generations.has_stale() {
+            return 0;
         }
+        let stale: Vec<_> = self
+            .entries()
+            .into_iter()
+            .filter(|entry| self.generations.is_stale(entry.id))
+            .collect();
+        let evicted = stale.len();
+        for entry in stale {
+            self.remove(entry);
+        }
+        self.generations.swept();
+        evicted
     }
 
     /// Insert an entry into the pool
     pub(crate) fn insert(&mut self, entry: PoolEntry<C>) -> bool {
+        // stale entries never complete, they must not conflict with the new ones
+        let _evicted = self.sweep();
+        self.generations.tag(entry.id);
         let mut conflict = false;
 
         conflict |= !self.conf_change_ucp.is_empty();
@@ -50,6 +87,7 @@ impl<C> UncommittedPool<C> {
 
     /// Removes an entry from the pool
     pub(crate) fn remove(&mut self, entry: PoolEntry<C>) {
+        self.generations.untag(entry.id);
         match ConflictPoolEntry::from(entry) {
             ConflictPoolEntry::Command(c) => {
                 for cucp in &mut self.command_ucps {
@@ -62,8 +100,30 @@ impl<C> UncommittedPool<C> {
         }
     }
 
+    /// Removes the entry of a propose id from the pool, returns `false` if it's absent
+    pub(crate) fn remove_by_id(&mut self, id: ProposeId) -> bool {
+        if !self.generations.contains(id) {
+            return false;
+        }
+        let Some(entry) = self.entries().into_iter().find(|entry| entry.id == id) else {
+            return false;
+        };
+        self.remove(entry);
+        true
+    }
+
     /// Returns all entries in the pool that conflict with the given entry
     pub(crate) fn all_conflict(&self, entry: PoolEntry<C>) -> Vec<PoolEntry<C>> {
+        let mut conflicts = self.all_conflict_entries(entry);
+        if self.generations.has_stale() {
+            conflicts.retain(|e| !self.generations.is_stale(e.id));
+        }
+        conflicts
+    }
+
+    /// Returns all entries in the pool that conflict with the given entry, the stale
+    /// ones included
+    fn all_conflict_entries(&self, entry: PoolEntry<C>) -> Vec<PoolEntry<C>> {
         match ConflictPoolEntry::from(entry) {
             // A command entry conflict with other conflict entries plus all conf change entries
             ConflictPoolEntry::Command(ref c) => self
@@ -98,6 +158,11 @@ impl<C> UncommittedPool<C> {
     #[cfg(test)]
     /// Gets all entries in the pool
     pub(crate) fn all(&self) -> Vec<PoolEntry<C>> {
+        self.entries()
+    }
+
+    /// Gets all entries in the pool, the stale ones included
+    fn entries(&self) -> Vec<PoolEntry<C>> {
         let mut entries = Vec::new();
         for csp in &self.command_ucps {
             entries.extend(csp.all().into_iter().map(Into::into));
@@ -118,6 +183,13 @@ impl<C> UncommittedPool<C> {
             ucp.clear();
         }
         self.conf_change_ucp.clear();
+        self.generations.clear();
+    }
+
+    /// The number of entries whose generations are tracked
+    #[cfg(test)]
+    pub(crate) fn tracked(&self) -> usize {
+        self.generations.len()
     }
 }
 
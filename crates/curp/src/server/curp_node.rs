@@ -1,18 +1,18 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
-use engine::{SnapshotAllocator, SnapshotApi};
-use event_listener::Event;
+use engine::SnapshotAllocator;
+use event_listener::{Event, EventListener};
 use futures::{pin_mut, stream::FuturesUnordered, Stream, StreamExt};
 use madsim::rand::{thread_rng, Rng};
 use parking_lot::{Mutex, RwLock};
 use tokio::{
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, Semaphore},
     time::MissedTickBehavior,
 };
 #[cfg(not(madsim))]
@@ -32,7 +32,7 @@ use super::{
         spec_pool_new::{SpObject, SpeculativePool},
         uncommitted_pool::{UcpObject, UncommittedPool},
     },
-    gc::gc_cmd_board,
+    gc::{gc_cmd_board, gc_spec_pool},
     lease_manager::LeaseManager,
     raw_curp::{AppendEntries, RawCurp, Vote},
     storage::StorageApi,
@@ -41,23 +41,40 @@ use crate::{
     cmd::{Command, CommandExecutor},
     log_entry::{EntryData, LogEntry},
     members::{ClusterInfo, ServerId},
+    quorum,
     role_change::RoleChange,
     rpc::{
         self,
         connect::{InnerConnectApi, InnerConnectApiWrapper},
-        AppendEntriesRequest, AppendEntriesResponse, ConfChange, ConfChangeType, CurpError,
-        FetchClusterRequest, FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
-        InstallSnapshotRequest, InstallSnapshotResponse, LeaseKeepAliveMsg, MoveLeaderRequest,
-        MoveLeaderResponse, ProposeConfChangeRequest, ProposeConfChangeResponse, ProposeRequest,
-        ProposeResponse, PublishRequest, PublishResponse, ShutdownRequest, ShutdownResponse,
-        TriggerShutdownRequest, TriggerShutdownResponse, TryBecomeLeaderNowRequest,
-        TryBecomeLeaderNowResponse, VoteRequest, VoteResponse, WaitSyncedRequest,
-        WaitSyncedResponse,
+        AppendEntriesRequest, AppendEntriesResponse, CancelRequest, CancelResponse, ConfChange,
+        ConfChangeType, CurpError, FetchClusterRequest, FetchClusterResponse,
+        FetchReadStateRequest, FetchReadStateResponse, InstallSnapshotRequest,
+        InstallSnapshotResponse, LeaseKeepAliveMsg, MoveLeaderRequest, MoveLeaderResponse,
+        ProposeConfChangeRequest, ProposeConfChangeResponse, ProposeRequest, ProposeResponse,
+        PublishRequest, PublishResponse, ShutdownRequest, ShutdownResponse, TriggerShutdownRequest,
+        TriggerShutdownResponse, TryBecomeLeaderNowRequest, TryBecomeLeaderNowResponse,
+        VoteRequest, VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
     },
-    server::{cmd_worker::CEEventTxApi, metrics, raw_curp::SyncAction, storage::db::DB},
-    snapshot::{Snapshot, SnapshotMeta},
+    server::{
+        cmd_worker::CEEventTxApi,
+        metrics::{self, EntryStage},
+        raw_curp::SyncAction,
+        snapshot_receiver::{Received, SnapshotReceiver},
+        storage::db::DB,
+    },
+    snapshot::{Snapshot, SnapshotThrottle, SnapshotTransfer},
 };
 
+/// Entries of an `AppendEntriesRequest` larger than this in total are decoded on the
+/// blocking pool
+const OFFLOAD_DECODE_THRESHOLD: usize = 256 * 1024;
+
+/// Max number of `AppendEntriesRequest`s decoded on the blocking pool at the same time
+const MAX_OFFLOADED_DECODES: usize = 2;
+
+/// Max number of streams sending a snapshot, the later ones resume the former ones
+const SNAPSHOT_TRANSFER_ATTEMPTS: u32 = 3;
+
 /// `CurpNode` represents a single node of curp cluster
 pub(super) struct CurpNode<C: Command, RC: RoleChange> {
     /// `RawCurp` state machine
@@ -68,8 +85,10 @@ pub(super) struct CurpNode<C: Command, RC: RoleChange> {
     ce_event_tx: Arc<dyn CEEventTxApi<C>>,
     /// Storage
     storage: Arc<dyn StorageApi<Command = C>>,
-    /// Snapshot allocator
-    snapshot_allocator: Box<dyn SnapshotAllocator>,
+    /// Receiver of the snapshots sent by the leader
+    snapshot_receiver: SnapshotReceiver,
+    /// Permits of the decodes on the blocking pool
+    decode_permits: Arc<Semaphore>,
 }
 
 /// Handlers for clients
@@ -82,18 +101,84 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         let id = req.propose_id();
         self.check_cluster_version(req.cluster_version)?;
         let cmd: Arc<C> = Arc::new(req.cmd()?);
+        // watch before the entry is appended, the persist task may write it right away
+        let persisted = (req.wait_persisted && self.curp.is_leader())
+            .then(|| self.cmd_board.write().watch_persisted(id));
+        // the later stages of the cmd run in the cmd workers of the leader, keep the span
+        // before they start, the followers only after sync the cmd once it's committed
+        let tracked = self.curp.is_leader() && self.cmd_board.write().track_span(id);
         // handle proposal
-        let sp_exec = self.curp.handle_propose(id, Arc::clone(&cmd))?;
+        let sp_exec = self
+            .curp
+            .handle_propose(id, Arc::clone(&cmd))
+            .map_err(|e| {
+                if tracked {
+                    self.cmd_board.write().untrack_span(id);
+                }
+                if persisted.is_some() {
+                    self.cmd_board.write().unwatch_persisted(id);
+                }
+                e
+            })?;
+
+        // the leader answers async proposals once the entry is persisted, the result is
+        // left to `WaitSynced`
+        if let Some(persisted) = persisted {
+            // `handle_propose` only returns `false` if the leadership is lost in between, the
+            // notifiers are also dropped once it's lost
+            if !sp_exec || persisted.await.is_err() {
+                let mut cb_w = self.cmd_board.write();
+                cb_w.unwatch_persisted(id);
+                if tracked {
+                    cb_w.untrack_span(id);
+                }
+                if cb_w.is_canceled(id) {
+                    return Err(CurpError::canceled());
+                }
+                return Err(CurpError::internal(
+                    "leadership lost before the log entry is persisted",
+                ));
+            }
+            return Ok(ProposeResponse::new_empty());
+        }
 
         // if speculatively executed, wait for the result and return
         if sp_exec {
-            let er_res = CommandBoard::wait_for_er(&self.cmd_board, id).await;
-            return Ok(ProposeResponse::new_result::<C>(&er_res));
+            let start = Instant::now();
+            // a canceled cmd is never appended, so it's never after synced
+            let er_res = CommandBoard::wait_for_er(&self.cmd_board, id)
+                .await
+                .map_err(|e| {
+                    if tracked {
+                        self.cmd_board.write().untrack_span(id);
+                    }
+                    e
+                })?;
+            let resp = ProposeResponse::new_result::<C>(&er_res);
+            metrics::get()
+                .entry_stages
+                .record(EntryStage::Respond, cmd.kind(), start.elapsed());
+            return Ok(resp);
         }
 
         Ok(ProposeResponse::new_empty())
     }
 
+    /// Handle `Cancel` requests, a cancel is only confirmed once its log entry is
+    /// committed
+    pub(super) async fn cancel(&self, req: CancelRequest) -> Result<CancelResponse, CurpError> {
+        self.check_cluster_version(req.cluster_version)?;
+        let Some(committed) = self.curp.handle_cancel(req.propose_id())? else {
+            return Ok(CancelResponse { canceled: false });
+        };
+        if committed.await.is_err() {
+            return Err(CurpError::internal(
+                "leadership lost before the cancel is committed",
+            ));
+        }
+        Ok(CancelResponse { canceled: true })
+    }
+
     /// Handle `Shutdown` requests
     pub(super) async fn shutdown(
         &self,
@@ -153,11 +238,12 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
 /// Handlers for peers
 impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
     /// Handle `AppendEntries` requests
-    pub(super) fn append_entries(
+    pub(super) async fn append_entries(
         &self,
-        req: &AppendEntriesRequest,
+        mut req: AppendEntriesRequest,
     ) -> Result<AppendEntriesResponse, CurpError> {
-        let entries = req.entries()?;
+        let entries =
+            Self::decode_entries(&self.decode_permits, std::mem::take(&mut req.entries)).await?;
 
         let result = self.curp.handle_append_entries(
             req.term,
@@ -168,13 +254,47 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
             req.leader_commit,
         );
         let resp = match result {
-            Ok(term) => AppendEntriesResponse::new_accept(term),
+            Ok(term) => AppendEntriesResponse::new_accept(term)
+                .with_state_hash(self.curp.state_hash_report()),
             Err((term, hint)) => AppendEntriesResponse::new_reject(term, hint),
         };
 
         Ok(resp)
     }
 
+    /// Decode the entries of an `AppendEntriesRequest`. Large batches are decoded on the
+    /// blocking pool so that they don't stall the heartbeats and other io tasks on the
+    /// runtime, the entries are decoded in a single task so they keep their order. The
+    /// permits bound how many of them occupy the blocking pool at the same time.
+    async fn decode_entries(
+        permits: &Arc<Semaphore>,
+        entries: Vec<Vec<u8>>,
+    ) -> Result<Vec<LogEntry<C>>, CurpError> {
+        let size = entries.iter().map(Vec::len).fold(0, usize::overflow_add);
+        let decode = move || {
+            entries
+                .iter()
+                .map(|entry| bincode::deserialize(entry))
+                .collect::<bincode::Result<Vec<LogEntry<C>>>>()
+        };
+        let decoded = if size < OFFLOAD_DECODE_THRESHOLD {
+            decode()
+        } else {
+            let permit = Arc::clone(permits)
+                .acquire_owned()
+                .await
+                .map_err(|e| CurpError::internal(format!("failed to decode entries: {e}")))?;
+            // the permit is held until the decode finishes, even if the request is dropped
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                decode()
+            })
+            .await
+            .map_err(|e| CurpError::internal(format!("failed to decode entries: {e}")))?
+        };
+        Ok(decoded?)
+    }
+
     /// Handle `Vote` requests
     pub(super) async fn vote(&self, req: VoteRequest) -> Result<VoteResponse, CurpError> {
         let result = if req.is_pre_vote {
@@ -230,7 +350,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         if self.curp.get_transferee().is_some() {
             return Err(CurpError::leader_transfer("leader transferring"));
         }
-        let (er, asr) = CommandBoard::wait_for_er_asr(&self.cmd_board, id).await;
+        let (er, asr) = CommandBoard::wait_for_er_asr(&self.cmd_board, id).await?;
         debug!("{} wait synced for cmd({id}) finishes", self.curp.id());
         Ok(WaitSyncedResponse::new_from_result::<C>(er, asr))
     }
@@ -262,83 +382,92 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
     }
 
     /// Handle `InstallSnapshot` stream
-    #[allow(clippy::arithmetic_side_effects)] // can't overflow
+    ///
+    /// The snapshot is only applied once it's completely received and verified, an
+    /// interrupted transfer is staged for the leader to resume it
     pub(super) async fn install_snapshot<E: std::error::Error + 'static>(
         &self,
         req_stream: impl Stream<Item = Result<InstallSnapshotRequest, E>>,
     ) -> Result<InstallSnapshotResponse, CurpError> {
-        metrics::get().apply_snapshot_in_progress.add(1, &[]);
         let start = Instant::now();
-        pin_mut!(req_stream);
-        let mut snapshot = self
-            .snapshot_allocator
-            .allocate_new_snapshot()
-            .await
-            .map_err(|err| {
-                error!("failed to allocate a new snapshot, error: {err}");
-                CurpError::internal(format!("failed to allocate a new snapshot, error: {err}"))
-            })?;
-        while let Some(req) = req_stream.next().await {
-            let req = req?;
-            if !self.curp.verify_install_snapshot(
-                req.term,
-                req.leader_id,
-                req.last_included_index,
-                req.last_included_term,
-            ) {
-                return Ok(InstallSnapshotResponse::new(self.curp.term()));
-            }
-            let req_data_len = req.data.len().numeric_cast::<u64>();
-            snapshot.write_all(req.data).await.map_err(|err| {
-                error!("can't write snapshot data, {err:?}");
-                err
-            })?;
-            if req.done {
-                debug_assert_eq!(
-                    snapshot.size(),
-                    req.offset + req_data_len,
-                    "snapshot corrupted"
-                );
-                let meta = SnapshotMeta {
-                    last_included_index: req.last_included_index,
-                    last_included_term: req.last_included_term,
-                };
-                let snapshot = Snapshot::new(meta, snapshot);
-                info!(
-                    "{} successfully received a snapshot, {snapshot:?}",
-                    self.curp.id(),
-                );
-                self.ce_event_tx
-                    .send_reset(Some(snapshot))
-                    .await
-                    .map_err(|err| {
-                        error!("failed to reset the command executor by snapshot, {err}");
-                        CurpError::internal(format!(
-                            "failed to reset the command executor by snapshot, {err}"
-                        ))
-                    })?;
-                metrics::get().apply_snapshot_in_progress.add(-1, &[]);
-                metrics::get()
-                    .snapshot_install_total_duration_seconds
-                    .record(start.elapsed().as_secs(), &[]);
-                return Ok(InstallSnapshotResponse::new(self.curp.term()));
+        let received = self
+            .snapshot_receiver
+            .receive(req_stream, |req| {
+                self.curp.verify_install_snapshot(
+                    req.term,
+                    req.leader_id,
+                    req.last_included_index,
+                    req.last_included_term,
+                )
+            })
+            .await?;
+        let snapshot = match received {
+            Received::Complete(snapshot) => snapshot,
+            Received::Rejected => return Ok(InstallSnapshotResponse::new(self.curp.term())),
+            Received::Probed(next_offset) => {
+                return Ok(InstallSnapshotResponse::resume(
+                    self.curp.term(),
+                    next_offset,
+                ))
             }
-        }
-        Err(CurpError::internal(
-            "failed to receive a complete snapshot".to_owned(),
-        ))
+        };
+        info!(
+            "{} successfully received a snapshot, {snapshot:?}",
+            self.curp.id(),
+        );
+        metrics::get().apply_snapshot_in_progress.add(1, &[]);
+        let reset = self.ce_event_tx.send_reset(Some(snapshot)).await;
+        metrics::get().apply_snapshot_in_progress.add(-1, &[]);
+        reset.map_err(|err| {
+            error!("failed to reset the command executor by snapshot, {err}");
+            CurpError::internal(format!(
+                "failed to reset the command executor by snapshot, {err}"
+            ))
+        })?;
+        metrics::get()
+            .snapshot_install_total_duration_seconds
+            .record(start.elapsed().as_secs(), &[]);
+        Ok(InstallSnapshotResponse::installed(self.curp.term()))
     }
 
     /// Handle `FetchReadState` requests
+    ///
+    /// The read state is only returned once the leadership of this server has been
+    /// confirmed by a quorum after reading it, so that a stale leader can't serve it
     #[allow(clippy::needless_pass_by_value)] // To keep type consistent with other request handlers
-    pub(super) fn fetch_read_state(
+    #[allow(clippy::arithmetic_side_effects)] // won't overflow
+    pub(super) async fn fetch_read_state(
         &self,
         req: FetchReadStateRequest,
     ) -> Result<FetchReadStateResponse, CurpError> {
         self.check_cluster_version(req.cluster_version)?;
-        let cmd = req.cmd()?;
-        let state = self.curp.handle_fetch_read_state(Arc::new(cmd));
-        Ok(FetchReadStateResponse::new(state))
+        let cmd = Arc::new(req.cmd()?);
+        let term = self.curp.leader_term()?;
+        // the leadership must be acknowledged before a new leader could have been elected
+        let deadline = tokio::time::Instant::now()
+            + self.curp.cfg().heartbeat_interval
+                * u32::from(self.curp.cfg().follower_timeout_ticks);
+        let ack_event = self.curp.ack_event();
+        let wait_ack = move |listener: EventListener| async move {
+            tokio::time::timeout_at(deadline, listener)
+                .await
+                .map_err(|_elapsed| CurpError::redirect(None, term))
+        };
+        let (state, read_at) = loop {
+            let listener = ack_event.listen();
+            if let Some(started) = self.curp.start_read_index(Arc::clone(&cmd), term)? {
+                break started;
+            }
+            wait_ack(listener).await?;
+        };
+        let _ignore = self.curp.heartbeat_event().notify(usize::MAX);
+        loop {
+            let listener = ack_event.listen();
+            if self.curp.confirm_read_index(term, read_at)? {
+                return Ok(FetchReadStateResponse::new(state));
+            }
+            wait_ack(listener).await?;
+        }
     }
 
     /// Handle `MoveLeader` requests
@@ -399,6 +528,15 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
     /// Tick periodically
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)]
     async fn election_task(curp: Arc<RawCurp<C, RC>>, shutdown_listener: Listener) {
+        // don't campaign before the peers are reachable, requests from the leader and
+        // candidates are still served as a follower meanwhile
+        tokio::select! {
+            _ = Self::warm_up_peers(curp.as_ref()) => {}
+            _ = shutdown_listener.wait() => {
+                debug!("election task exits");
+                return;
+            }
+        }
         let heartbeat_interval = curp.cfg().heartbeat_interval;
         // wait for some random time before tick starts to minimize vote split possibility
         let rand = thread_rng()
@@ -416,6 +554,9 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                     return;
                 }
             }
+            // a new leader, or the leader of a single node cluster, appends the version
+            // no follower reports
+            curp.negotiate_cluster_server_version();
             if let Some(pre_vote_or_vote) = curp.tick_election() {
                 // bcast pre vote or vote, if it is a pre vote and success, it will return Some(vote)
                 // then we need to bcast normal vote, and bcast normal vote always return None
@@ -432,6 +573,65 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         }
     }
 
+    /// Wait until a quorum of voters is reachable or `peer_warmup_timeout` elapses
+    async fn warm_up_peers(curp: &RawCurp<C, RC>) {
+        let warmup_timeout = curp.cfg().peer_warmup_timeout;
+        // the node itself counts toward the quorum
+        let needed = quorum(curp.cluster().voters_len()).saturating_sub(1);
+        if warmup_timeout.is_zero() || needed == 0 {
+            return;
+        }
+        let voters_connects = curp.voters_connects();
+        let mut unreachable: HashSet<ServerId> = voters_connects.iter().map(|c| c.id()).collect();
+        let mut probes: FuturesUnordered<_> = voters_connects
+            .into_iter()
+            .map(|connect| Self::probe_peer(curp, connect))
+            .collect();
+        let wait_quorum = async {
+            let mut reached = 0;
+            while reached < needed {
+                let Some(id) = probes.next().await else {
+                    break;
+                };
+                let _ignore = unreachable.remove(&id);
+                reached = reached.overflow_add(1);
+            }
+        };
+        if tokio::time::timeout(warmup_timeout, wait_quorum)
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} failed to reach a quorum of peers in {warmup_timeout:?}",
+                curp.id()
+            );
+        }
+        if !unreachable.is_empty() {
+            warn!(
+                "{} starts election ticks with unreachable peers: {unreachable:?}",
+                curp.id()
+            );
+            metrics::get()
+                .peers_unreachable_at_startup
+                .add(unreachable.len().numeric_cast(), &[]);
+        }
+    }
+
+    /// Probe a peer until it responds, return its id
+    async fn probe_peer(curp: &RawCurp<C, RC>, connect: Arc<dyn InnerConnectApi>) -> ServerId {
+        let heartbeat_interval = curp.cfg().heartbeat_interval;
+        let rpc_timeout = curp.cfg().rpc_timeout;
+        loop {
+            // a pre vote of term 0 never changes the state of the receiver
+            let req = VoteRequest::new(0, curp.id(), 0, 0, true);
+            match connect.vote(req, rpc_timeout).await {
+                Ok(_resp) => return connect.id(),
+                Err(e) => debug!("probe peer {} failed, {e}", connect.id()),
+            }
+            tokio::time::sleep(heartbeat_interval).await;
+        }
+    }
+
     /// Handler of conf change
     async fn conf_change_handler(
         curp: Arc<RawCurp<C, RC>>,
@@ -523,6 +723,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         let connect_id = connect.id();
         let batch_timeout = curp.cfg().batch_timeout;
         let leader_event = curp.leader_event();
+        let heartbeat_event = curp.heartbeat_event();
 
         if !curp.is_leader() {
             tokio::select! {
@@ -546,6 +747,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                 },
                 _ = remove_event.listen() => return,
                 _now = ticker.tick() => hb_opt = false,
+                _ = heartbeat_event.listen() => hb_opt = false,
                 res = tokio::time::timeout(batch_timeout, sync_event.listen()) => {
                     if let Err(_e) = res {
                         hb_opt = true;
@@ -572,9 +774,44 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         debug!("{} to {} sync follower task exits", curp.id(), connect.id());
     }
 
+    /// Append the batched commands once the first of them has waited for the max batch delay
+    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // tokio select internal triggered
+    async fn propose_batch_task(curp: Arc<RawCurp<C, RC>>, shutdown_listener: Listener) {
+        let max_delay = curp.cfg().propose_batch_max_delay;
+        let batch_event = curp.batch_event();
+        let mut listener = batch_event.listen();
+        loop {
+            tokio::select! {
+                _ = listener => {}
+                _ = shutdown_listener.wait() => break,
+            }
+            tokio::time::sleep(max_delay).await;
+            // listen before appending so that a batch started right after it isn't missed
+            listener = batch_event.listen();
+            curp.handle_batch_timeout();
+        }
+        debug!("propose batch task exits");
+    }
+
+    /// Expire the idle client sessions periodically, only the leader appends them
+    async fn session_expiry_task(curp: Arc<RawCurp<C, RC>>, shutdown_listener: Listener) {
+        let interval = curp.cfg().gc_interval;
+        #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)]
+        // introduced by tokio select
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown_listener.wait() => break,
+            }
+            curp.handle_session_expiry();
+        }
+        debug!("session expiry task exits");
+    }
+
     /// Log persist task
     pub(super) async fn log_persist_task(
         mut log_rx: mpsc::UnboundedReceiver<Arc<LogEntry<C>>>,
+        cmd_board: CmdBoardRef<C>,
         storage: Arc<dyn StorageApi<Command = C>>,
         shutdown_listener: Listener,
     ) {
@@ -586,20 +823,40 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                     let Some(e) = e else {
                         return;
                     };
-                    if let Err(err) = storage.put_log_entry(e.as_ref()).await {
-                        error!("storage error, {err}");
-                    }
+                    Self::persist_log_entry(&cmd_board, storage.as_ref(), e.as_ref()).await;
                 }
                 _ = shutdown_listener.wait() => break,
             }
         }
         while let Ok(e) = log_rx.try_recv() {
-            if let Err(err) = storage.put_log_entry(e.as_ref()).await {
-                error!("storage error, {err}");
-            }
+            Self::persist_log_entry(&cmd_board, storage.as_ref(), e.as_ref()).await;
         }
         debug!("log persist task exits");
     }
+
+    /// Write a log entry to the storage and record the latency of the write, the cmds
+    /// waiting for the entry to be persisted are notified once it's written
+    async fn persist_log_entry(
+        cmd_board: &CmdBoardRef<C>,
+        storage: &dyn StorageApi<Command = C>,
+        entry: &LogEntry<C>,
+    ) {
+        let start = Instant::now();
+        if let Err(err) = storage.put_log_entry(entry).await {
+            error!("storage error, {err}");
+        } else if let EntryData::Commands(ref cmds) | EntryData::MetaCommands(ref cmds) =
+            entry.entry_data
+        {
+            CommandBoard::notify_persisted(cmd_board, cmds.iter().map(|&(id, _)| id));
+        } else {
+            CommandBoard::notify_persisted(cmd_board, [entry.propose_id]);
+        }
+        metrics::get().entry_stages.record(
+            EntryStage::LogPersist,
+            entry.metric_label(),
+            start.elapsed(),
+        );
+    }
 }
 
 // utils
@@ -635,6 +892,19 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         let last_applied = cmd_executor
             .last_applied()
             .map_err(|e| CurpError::internal(format!("get applied index error, {e}")))?;
+        let applied_in_batches = cmd_executor.applied_in_batches().map_err(|e| {
+            CurpError::internal(format!("get applied positions in batches error, {e}"))
+        })?;
+        // a retry of a cmd applied before the restart is neither appended again nor
+        // answered with a conflict
+        let applied_proposals = cmd_executor
+            .applied_proposals(
+                last_applied
+                    .saturating_sub(curp_cfg.result_cache.dedup_window)
+                    .saturating_add(1),
+            )
+            .map_err(|e| CurpError::internal(format!("get applied proposals error, {e}")))?;
+        cmd_board.write().restore_applied(applied_proposals);
         let (ce_event_tx, task_rx, done_tx) =
             conflict_checked_mpmc::channel(Arc::clone(&cmd_executor), Arc::clone(&task_manager));
         let ce_event_tx: Arc<dyn CEEventTxApi<C>> = Arc::new(ce_event_tx);
@@ -655,12 +925,14 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                 .task_manager(Arc::clone(&task_manager))
                 .connects(connects)
                 .last_applied(last_applied)
+                .applied_in_batches(applied_in_batches)
                 .voted_for(voted_for)
                 .entries(entries)
                 .curp_storage(Arc::clone(&storage))
                 .client_tls_config(client_tls_config)
                 .spec_pool(Arc::new(Mutex::new(SpeculativePool::new(sps))))
                 .uncommitted_pool(Arc::new(Mutex::new(UncommittedPool::new(ucps))))
+                .cmd_executor(Arc::clone(&cmd_executor) as Arc<dyn CommandExecutor<C>>)
                 .build_raw_curp()
                 .map_err(|e| CurpError::internal(format!("build raw curp failed, {e}")))?,
         );
@@ -670,23 +942,47 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         start_cmd_workers(cmd_executor, Arc::clone(&curp), task_rx, done_tx);
 
         task_manager.spawn(TaskName::GcCmdBoard, |n| {
-            gc_cmd_board(Arc::clone(&cmd_board), curp_cfg.gc_interval, n)
+            gc_cmd_board(
+                Arc::clone(&cmd_board),
+                curp_cfg.gc_interval,
+                curp_cfg.result_cache,
+                Arc::clone(&cluster_info),
+                n,
+            )
         });
+        task_manager.spawn(TaskName::GcSpecPool, |n| {
+            gc_spec_pool(
+                curp.spec_pool(),
+                curp.uncommitted_pool(),
+                curp.pools_retired_event(),
+                n,
+            )
+        });
+
+        Self::run_bg_tasks(
+            Arc::clone(&curp),
+            Arc::clone(&cmd_board),
+            Arc::clone(&storage),
+            log_rx,
+        );
 
-        Self::run_bg_tasks(Arc::clone(&curp), Arc::clone(&storage), log_rx);
+        let snapshot_receiver = SnapshotReceiver::new(snapshot_allocator);
+        snapshot_receiver.clean_stale().await;
 
         Ok(Self {
             curp,
             cmd_board,
             ce_event_tx,
             storage,
-            snapshot_allocator,
+            snapshot_receiver,
+            decode_permits: Arc::new(Semaphore::new(MAX_OFFLOADED_DECODES)),
         })
     }
 
     /// Run background tasks for Curp server
     fn run_bg_tasks(
         curp: Arc<RawCurp<C, RC>>,
+        cmd_board: CmdBoardRef<C>,
         storage: Arc<impl StorageApi<Command = C> + 'static>,
         log_rx: mpsc::UnboundedReceiver<Arc<LogEntry<C>>>,
     ) {
@@ -714,11 +1010,19 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         }
 
         task_manager.spawn(TaskName::ConfChange, |n| {
-            Self::conf_change_handler(curp, remove_events, n)
+            Self::conf_change_handler(Arc::clone(&curp), remove_events, n)
         });
         task_manager.spawn(TaskName::LogPersist, |n| {
-            Self::log_persist_task(log_rx, storage, n)
+            Self::log_persist_task(log_rx, cmd_board, storage, n)
+        });
+        task_manager.spawn(TaskName::ExpireSessions, |n| {
+            Self::session_expiry_task(Arc::clone(&curp), n)
         });
+        if curp.cfg().propose_batch_max_size > 1 {
+            task_manager.spawn(TaskName::ProposeBatch, |n| {
+                Self::propose_batch_task(curp, n)
+            });
+        }
     }
 
     /// Candidate or pre candidate broadcasts votes
@@ -806,6 +1110,8 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         let last_sent_index = (!ae.entries.is_empty())
             .then(|| ae.prev_log_index + ae.entries.len().numeric_cast::<u64>());
         let is_heartbeat = ae.entries.is_empty();
+        let sent = ae.entries.len();
+        let sent_at = Instant::now();
         let req = AppendEntriesRequest::new(
             ae.term,
             ae.leader_id,
@@ -821,10 +1127,22 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
             debug!("{} send append_entries to {}", curp.id(), connect.id());
         }
 
-        let resp = connect
-            .append_entries(req, curp.cfg().rpc_timeout)
-            .await?
-            .into_inner();
+        let resp = match connect.append_entries(req, curp.cfg().rpc_timeout).await {
+            Ok(resp) => resp.into_inner(),
+            Err(status) => {
+                // a follower that can't persist the entries in time gets smaller batches,
+                // the connection errors don't tell anything about its disk
+                if !is_heartbeat
+                    && matches!(
+                        status.code(),
+                        tonic::Code::Cancelled | tonic::Code::DeadlineExceeded
+                    )
+                {
+                    curp.handle_append_entries_timeout(connect.id(), sent);
+                }
+                return Err(status.into());
+            }
+        };
 
         let Ok(ae_succeed) = curp.handle_append_entries_resp(
             connect.id(),
@@ -835,6 +1153,9 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         ) else {
             return Ok((true, false));
         };
+        curp.handle_server_version(connect.id(), resp.server_version);
+        curp.handle_state_hash(connect.id(), resp.hash_revision, resp.state_hash);
+        curp.record_ack(connect.id(), sent_at);
 
         Ok((false, ae_succeed))
     }
@@ -847,14 +1168,78 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         curp: &RawCurp<C, RC>,
         snapshot: Snapshot,
     ) -> Result<bool, CurpError> {
-        let meta = snapshot.meta;
-        let resp = connect
-            .install_snapshot(curp.term(), curp.id(), snapshot)
-            .await?
-            .into_inner();
-        Ok(curp
-            .handle_snapshot_resp(connect.id(), meta, resp.term)
-            .is_err())
+        let transfer = Arc::new(SnapshotTransfer::new(snapshot));
+        let throttle = curp.snapshot_throttle();
+        // snapshots beyond the max concurrent transfers wait here for their turn
+        let _permit = throttle.acquire_transfer().await;
+        let result = Self::transfer_snapshot(connect, curp, &transfer, &throttle).await;
+        transfer.clean().await;
+        result
+    }
+
+    /// Transfer a snapshot, a broken stream is resumed from the offset the follower
+    /// reports to have staged, up to a few times
+    async fn transfer_snapshot(
+        connect: &(impl InnerConnectApi + ?Sized),
+        curp: &RawCurp<C, RC>,
+        transfer: &Arc<SnapshotTransfer>,
+        throttle: &Arc<SnapshotThrottle>,
+    ) -> Result<bool, CurpError> {
+        let mut offset = 0;
+        let mut attempts = 1;
+        loop {
+            let err = match connect
+                .install_snapshot(
+                    curp.term(),
+                    curp.id(),
+                    Arc::clone(transfer),
+                    offset,
+                    Arc::clone(throttle),
+                )
+                .await
+            {
+                Ok(resp) => {
+                    let resp = resp.into_inner();
+                    return Ok(curp
+                        .handle_snapshot_resp(
+                            connect.id(),
+                            transfer.meta(),
+                            resp.term,
+                            resp.success,
+                        )
+                        .is_err());
+                }
+                Err(err) => err,
+            };
+            if attempts >= SNAPSHOT_TRANSFER_ATTEMPTS {
+                return Err(err.into());
+            }
+            attempts = attempts.overflow_add(1);
+            warn!(
+                "snapshot transfer to {} is interrupted at {offset}, {err}",
+                connect.id()
+            );
+            let resp = connect
+                .probe_snapshot(
+                    curp.term(),
+                    curp.id(),
+                    Arc::clone(transfer),
+                    curp.cfg().rpc_timeout,
+                )
+                .await?
+                .into_inner();
+            if !resp.success {
+                return Ok(curp
+                    .handle_snapshot_resp(connect.id(), transfer.meta(), resp.term, false)
+                    .is_err());
+            }
+            offset = resp.next_offset;
+            info!(
+                "resume snapshot transfer {} to {} from {offset}",
+                transfer.id(),
+                connect.id()
+            );
+        }
     }
 
     /// Check cluster version and return new cluster
@@ -986,12 +1371,73 @@ mod tests {
     use curp_test_utils::{mock_role_change, sleep_secs, test_cmd::TestCommand};
     use tracing_test::traced_test;
 
+    use curp_test_utils::TestRoleChange;
+    use utils::failpoint::{FailAction, FailScenario};
+
     use super::*;
     use crate::{
-        rpc::{connect::MockInnerConnectApi, ConfChange},
+        rpc::{connect::MockInnerConnectApi, ConfChange, ProposeId},
         server::cmd_worker::MockCEEventTxApi,
     };
 
+    /// An `AppendEntriesRequest` with entries large enough to be decoded on the blocking pool
+    fn large_append_entries() -> AppendEntriesRequest {
+        let entries = (1..=20_000)
+            .map(|i| {
+                Arc::new(LogEntry::new(
+                    i,
+                    1,
+                    ProposeId(0, i),
+                    Arc::new(TestCommand::new_put(vec![i.numeric_cast()], 0)),
+                ))
+            })
+            .collect();
+        let req = AppendEntriesRequest::new(1, 0, 0, 0, entries, 0).unwrap();
+        let size = req.entries.iter().map(Vec::len).sum::<usize>();
+        assert!(
+            size >= OFFLOAD_DECODE_THRESHOLD,
+            "entries should be offloaded"
+        );
+        req
+    }
+
+    #[tokio::test]
+    async fn decode_large_entries_should_keep_order() {
+        let req = large_append_entries();
+        let permits = Arc::new(Semaphore::new(MAX_OFFLOADED_DECODES));
+        let decoded =
+            CurpNode::<TestCommand, TestRoleChange>::decode_entries(&permits, req.entries)
+                .await
+                .unwrap();
+        assert_eq!(decoded.len(), 20_000);
+        for (entry, i) in decoded.iter().zip(1..) {
+            assert_eq!(entry.index, i);
+            assert_eq!(entry.propose_id, ProposeId(0, i));
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_large_entries_should_wait_for_a_permit() {
+        let permits = Arc::new(Semaphore::new(1));
+        let held = Arc::clone(&permits).acquire_owned().await.unwrap();
+        let decode = tokio::spawn({
+            let permits = Arc::clone(&permits);
+            let req = large_append_entries();
+            async move {
+                CurpNode::<TestCommand, TestRoleChange>::decode_entries(&permits, req.entries)
+                    .await
+                    .unwrap()
+                    .len()
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!decode.is_finished(), "decode should wait for the permit");
+
+        drop(held);
+        assert_eq!(decode.await.unwrap(), 20_000);
+        assert_eq!(permits.available_permits(), 1);
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn sync_task_will_send_hb() {
@@ -1023,6 +1469,116 @@ mod tests {
         task_manager.shutdown(true).await;
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn slow_follower_should_get_shrunk_batches_without_affecting_others() {
+        /// Point making the append entries to the slow follower time out
+        const SLOW_FOLLOWER: &str = "test_slow_follower_append_entries";
+        let scenario = FailScenario::setup();
+        let task_manager = Arc::new(TaskManager::new());
+        let curp = {
+            let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+            exe_tx.expect_send_after_sync().returning(|_| ());
+            Arc::new(RawCurp::new_test(
+                3,
+                exe_tx,
+                mock_role_change(),
+                Arc::clone(&task_manager),
+            ))
+        };
+        for i in 1..=100 {
+            let _index = curp.push_cmd(ProposeId(0, i), Arc::new(TestCommand::default()));
+        }
+        let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+        let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+
+        // the number of entries and bytes of each append entries sent to the slow
+        // follower, and whether it timed out
+        let sent = Arc::new(Mutex::new(vec![]));
+        let mut slow_connect = MockInnerConnectApi::default();
+        let slow_sent = Arc::clone(&sent);
+        slow_connect
+            .expect_append_entries()
+            .returning(move |req, _| {
+                let timed_out = utils::failpoint::eval(SLOW_FOLLOWER);
+                if !req.entries.is_empty() {
+                    let bytes: usize = req.entries.iter().map(Vec::len).sum();
+                    slow_sent.lock().push((req.entries.len(), bytes, timed_out));
+                }
+                if timed_out {
+                    return Err(tonic::Status::cancelled("Timeout expired"));
+                }
+                Ok(tonic::Response::new(AppendEntriesResponse::new_accept(0)))
+            });
+        slow_connect.expect_id().return_const(s1_id);
+        let mut healthy_connect = MockInnerConnectApi::default();
+        healthy_connect
+            .expect_append_entries()
+            .returning(|_, _| Ok(tonic::Response::new(AppendEntriesResponse::new_accept(0))));
+        healthy_connect.expect_id().return_const(s2_id);
+
+        scenario.cfg(SLOW_FOLLOWER, FailAction::Return);
+        for connect in [slow_connect, healthy_connect] {
+            let curp = Arc::clone(&curp);
+            task_manager.spawn(TaskName::SyncFollower, |n| {
+                CurpNode::sync_follower_task(
+                    curp,
+                    InnerConnectApiWrapper::new_from_arc(Arc::new(connect)),
+                    Arc::new(Event::new()),
+                    Arc::new(Event::new()),
+                    n,
+                )
+            });
+        }
+        let window = |id| {
+            curp.replication_windows()
+                .into_iter()
+                .find_map(|(to, limit, degraded)| (to == id).then_some((limit, degraded)))
+                .unwrap()
+        };
+        let wait_until = |cond: &dyn Fn() -> bool| {
+            let cond_met = tokio::time::timeout(Duration::from_secs(10), async {
+                while !cond() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            });
+            async move { cond_met.await.is_ok() }
+        };
+        assert!(wait_until(&|| window(s1_id) == (Some(1), true)).await);
+        assert!(wait_until(&|| curp.get_match_index(s2_id) == Some(100)).await);
+        assert_eq!(window(s2_id), (None, false));
+        assert_eq!(curp.get_match_index(s1_id), Some(0));
+        // a few probes are sent to the follower while it stays slow
+        assert!(wait_until(&|| sent.lock().len() >= 10).await);
+        // the batches for the slow follower only shrink, down to a single entry
+        let timed_out: Vec<_> = sent
+            .lock()
+            .iter()
+            .filter_map(|&(len, bytes, timed_out)| timed_out.then_some((len, bytes)))
+            .collect();
+        let lens: Vec<_> = timed_out.iter().map(|&(len, _)| len).collect();
+        assert_eq!(lens[..6], [100, 50, 25, 12, 6, 3]);
+        assert!(lens[6..].iter().all(|&len| len == 1));
+        // the memory the leader holds for the slow follower is bounded by a single entry
+        // however long it stays slow, instead of a full batch resent on every timeout
+        let full_batch_bytes = timed_out[0].1;
+        assert!(timed_out[6..]
+            .iter()
+            .all(|&(_, bytes)| bytes * 100 <= full_batch_bytes));
+
+        // the follower recovers, and it's caught up by growing batches
+        scenario.remove(SLOW_FOLLOWER);
+        assert!(wait_until(&|| curp.get_match_index(s1_id) == Some(100)).await);
+        let acked: Vec<_> = sent
+            .lock()
+            .iter()
+            .filter_map(|&(len, _, timed_out)| (!timed_out).then_some(len))
+            .collect();
+        assert_eq!(acked, [1, 2, 4, 8, 16, 32, 37]);
+        assert_eq!(window(s1_id), (None, false));
+        task_manager.shutdown(true).await;
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn tick_task_will_bcast_votes() {
@@ -1,19 +1,56 @@
-use std::time::Duration;
-
-use utils::task_manager::Listener;
-
-use crate::{cmd::Command, server::cmd_board::CmdBoardRef};
-
-// TODO: Speculative pool GC
+use std::{sync::Arc, time::Duration};
+
+use clippy_utilities::NumericCast;
+use event_listener::Event;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use tracing::debug;
+use utils::{config::ResultCacheConfig, task_manager::Listener};
+
+use super::{
+    conflict::{spec_pool_new::SpeculativePool, uncommitted_pool::UncommittedPool},
+    metrics,
+};
+use crate::{
+    cmd::Command,
+    members::{ClusterInfo, Feature},
+    server::cmd_board::CmdBoardRef,
+};
+
+/// Evicts the stale entries of the conflict pools once the no-op of a new term is
+/// committed, so the memory is reclaimed even if no entry is inserted afterwards
+pub(super) async fn gc_spec_pool<C: Command>(
+    spec_pool: Arc<Mutex<SpeculativePool<C>>>,
+    uncommitted_pool: Arc<Mutex<UncommittedPool<C>>>,
+    retired_event: Arc<Event>,
+    shutdown_listener: Listener,
+) {
+    #[allow(clippy::ignored_unit_patterns)] // introduced by tokio select
+    loop {
+        let retired = retired_event.listen();
+        let evicted_sp = spec_pool.lock().sweep();
+        let evicted_ucp = uncommitted_pool.lock().sweep();
+        if evicted_sp > 0 || evicted_ucp > 0 {
+            debug!("evicted {evicted_sp} stale entries from sp, {evicted_ucp} from ucp");
+        }
+        tokio::select! {
+            _ = retired => {}
+            _ = shutdown_listener.wait() => break,
+        }
+    }
+}
 
 /// Cleanup cmd board
+///
+/// Idle client sessions are dropped here until every member supports expiring them
+/// through the log.
 pub(super) async fn gc_cmd_board<C: Command>(
     cmd_board: CmdBoardRef<C>,
     interval: Duration,
+    result_cache_cfg: ResultCacheConfig,
+    cluster_info: Arc<ClusterInfo>,
     shutdown_listener: Listener,
 ) {
-    let mut last_check_len_er = 0;
-    let mut last_check_len_asr = 0;
     let mut last_check_len_sync = 0;
     let mut last_check_len_conf = 0;
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)]
@@ -23,21 +60,17 @@ pub(super) async fn gc_cmd_board<C: Command>(
             _ = tokio::time::sleep(interval) => {}
             _ = shutdown_listener.wait() => break,
         }
+        let expire_idle = !cluster_info.feature_enabled(Feature::SessionExpiry);
         let mut board = cmd_board.write();
 
-        // last_check_len_xxx should always be smaller than board.xxx_.len(), the check is just for precaution
-
-        if last_check_len_er <= board.er_buffer.len() {
-            let new_er_buffer = board.er_buffer.split_off(last_check_len_er);
-            board.er_buffer = new_er_buffer;
-            last_check_len_er = board.er_buffer.len();
+        let evicted = board.evict_results(&result_cache_cfg, Instant::now(), expire_idle);
+        if evicted > 0 {
+            metrics::get()
+                .result_cache_evictions
+                .add(evicted.numeric_cast(), &[]);
         }
 
-        if last_check_len_asr <= board.asr_buffer.len() {
-            let new_asr_buffer = board.asr_buffer.split_off(last_check_len_asr);
-            board.asr_buffer = new_asr_buffer;
-            last_check_len_asr = board.asr_buffer.len();
-        }
+        // last_check_len_xxx should always be smaller than board.xxx_.len(), the check is just for precaution
 
         if last_check_len_sync <= board.sync.len() {
             let new_sync = board.sync.split_off(last_check_len_sync);
@@ -55,14 +88,18 @@ pub(super) async fn gc_cmd_board<C: Command>(
 
 #[cfg(test)]
 mod tests {
-    use std::{sync::Arc, time::Duration};
+    use std::{collections::HashMap, sync::Arc, time::Duration};
 
     use curp_test_utils::test_cmd::{TestCommand, TestCommandResult};
     use parking_lot::RwLock;
     use test_macros::abort_on_panic;
-    use utils::task_manager::{tasks::TaskName, TaskManager};
+    use utils::{
+        config::ResultCacheConfig,
+        task_manager::{tasks::TaskName, TaskManager},
+    };
 
     use crate::{
+        members::{ClusterInfo, SERVER_VERSION},
         rpc::ProposeId,
         server::{
             cmd_board::{CmdBoardRef, CommandBoard},
@@ -75,48 +112,54 @@ mod tests {
     async fn cmd_board_gc_test() {
         let task_manager = TaskManager::new();
         let board: CmdBoardRef<TestCommand> = Arc::new(RwLock::new(CommandBoard::new()));
+        let result_cache_cfg = ResultCacheConfig {
+            retention: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let cluster_info = Arc::new(ClusterInfo::from_members_map(
+            HashMap::from([("S0".to_owned(), vec!["S0".to_owned()])]),
+            [],
+            "S0",
+        ));
+        cluster_info.set_cluster_server_version(SERVER_VERSION);
         task_manager.spawn(TaskName::GcCmdBoard, |n| {
-            gc_cmd_board(Arc::clone(&board), Duration::from_millis(500), n)
+            gc_cmd_board(
+                Arc::clone(&board),
+                Duration::from_millis(200),
+                result_cache_cfg,
+                cluster_info,
+                n,
+            )
         });
 
         tokio::time::sleep(Duration::from_millis(100)).await;
         board
             .write()
-            .er_buffer
-            .insert(ProposeId(1, 1), Ok(TestCommandResult::default()));
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        board
-            .write()
-            .er_buffer
-            .insert(ProposeId(2, 2), Ok(TestCommandResult::default()));
-        board
-            .write()
-            .asr_buffer
-            .insert(ProposeId(1, 1), Ok(0.into()));
+            .insert_er(ProposeId(1, 1), Ok(TestCommandResult::default()));
+        board.write().insert_asr(ProposeId(1, 1), Ok(0.into()));
         tokio::time::sleep(Duration::from_millis(100)).await;
         board
             .write()
-            .asr_buffer
-            .insert(ProposeId(2, 2), Ok(0.into()));
+            .insert_er(ProposeId(2, 2), Ok(TestCommandResult::default()));
+        board.write().insert_asr(ProposeId(2, 2), Ok(0.into()));
 
         // at 600ms
         tokio::time::sleep(Duration::from_millis(400)).await;
         board
             .write()
-            .er_buffer
-            .insert(ProposeId(3, 3), Ok(TestCommandResult::default()));
-        board
-            .write()
-            .asr_buffer
-            .insert(ProposeId(3, 3), Ok(0.into()));
+            .insert_er(ProposeId(3, 3), Ok(TestCommandResult::default()));
+        board.write().insert_asr(ProposeId(3, 3), Ok(0.into()));
 
-        // at 1100ms, the first two kv should be removed
+        // at 1100ms, the first two results should be evicted
         tokio::time::sleep(Duration::from_millis(500)).await;
         let board = board.write();
         assert_eq!(board.er_buffer.len(), 1);
         assert_eq!(*board.er_buffer.get_index(0).unwrap().0, ProposeId(3, 3));
         assert_eq!(board.asr_buffer.len(), 1);
         assert_eq!(*board.asr_buffer.get_index(0).unwrap().0, ProposeId(3, 3));
+        assert!(board.is_result_expired(ProposeId(1, 1)));
+        assert!(board.is_result_expired(ProposeId(2, 2)));
+        assert!(!board.is_result_expired(ProposeId(3, 3)));
         task_manager.shutdown(true).await;
     }
 }
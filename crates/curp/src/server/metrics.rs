@@ -1,8 +1,11 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
 use curp_external_api::{cmd::Command, role_change::RoleChange};
-use opentelemetry::metrics::{Counter, Histogram, MetricsError, UpDownCounter};
+use opentelemetry::{
+    metrics::{Counter, Histogram, MetricsError, UpDownCounter},
+    KeyValue,
+};
 use utils::define_metrics;
 
 use super::raw_curp::RawCurp;
@@ -40,7 +43,79 @@ define_metrics! {
     client_id_revokes: Counter<u64> = meter()
         .u64_counter("client_id_renews")
         .with_description("The total number of client id revokes times.")
-        .init()
+        .init(),
+    result_cache_evictions: Counter<u64> = meter()
+        .u64_counter("result_cache_evictions")
+        .with_description("The total number of propose results evicted from the result cache.")
+        .init(),
+    peers_unreachable_at_startup: Counter<u64> = meter()
+        .u64_counter("peers_unreachable_at_startup")
+        .with_description("The total number of voters not yet reachable when this member is allowed to campaign at startup.")
+        .init(),
+    batched_commands: Histogram<u64> = meter()
+        .u64_histogram("batched_commands")
+        .with_description("The distributions of the number of commands appended in a single log entry.")
+        .init(),
+    entry_stages: EntryStageMetrics = EntryStageMetrics::new(meter()
+        .f64_histogram("entry_stage_duration_seconds")
+        .with_description("The latency distributions of each stage a log entry goes through, by stage and entry type.")
+        .init())
+}
+
+/// A stage a log entry goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryStage {
+    /// Speculatively executing the command of the entry
+    Execute,
+    /// Writing the entry to the log storage
+    LogPersist,
+    /// From appending the entry to the leader's log to committing it on a quorum
+    Commit,
+    /// Applying the entry to the state machine
+    Apply,
+    /// Waiting for the execution result of a speculatively executed command until its
+    /// response is ready
+    Respond,
+}
+
+impl EntryStage {
+    /// The label of the stage
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            EntryStage::Execute => "execute",
+            EntryStage::LogPersist => "log_persist",
+            EntryStage::Commit => "commit",
+            EntryStage::Apply => "apply",
+            EntryStage::Respond => "respond",
+        }
+    }
+}
+
+/// Latency of the stages of log entries, labeled by the stage and the entry type
+///
+/// Both labels are static strings, so recording a latency never allocates.
+#[derive(Debug)]
+pub(crate) struct EntryStageMetrics {
+    /// The histogram of latencies in seconds
+    histogram: Histogram<f64>,
+}
+
+impl EntryStageMetrics {
+    /// Create a new `EntryStageMetrics` recording to the given histogram
+    pub(crate) fn new(histogram: Histogram<f64>) -> Self {
+        Self { histogram }
+    }
+
+    /// Record the latency of a stage of an entry of `entry_type`
+    pub(crate) fn record(&self, stage: EntryStage, entry_type: &'static str, elapsed: Duration) {
+        self.histogram.record(
+            elapsed.as_secs_f64(),
+            &[
+                KeyValue::new("stage", stage.as_str()),
+                KeyValue::new("entry_type", entry_type),
+            ],
+        );
+    }
 }
 
 impl Metrics {
@@ -59,6 +134,13 @@ impl Metrics {
             proposals_committed,
             proposals_applied,
             proposals_pending,
+            result_cache_sessions,
+            result_cache_results,
+            snapshot_send_bytes,
+            snapshot_send_rate,
+            snapshot_send_eta_seconds,
+            follower_degraded,
+            follower_replication_window,
         ) = (
             meter
                 .u64_observable_gauge("has_leader")
@@ -96,6 +178,34 @@ impl Metrics {
                 .u64_observable_gauge("proposals_pending")
                 .with_description("The current number of pending proposals to commit.")
                 .init(),
+            meter
+                .u64_observable_gauge("result_cache_sessions")
+                .with_description("The number of client sessions in the result cache.")
+                .init(),
+            meter
+                .u64_observable_gauge("result_cache_results")
+                .with_description("The number of propose results in the result cache.")
+                .init(),
+            meter
+                .u64_observable_gauge("snapshot_send_bytes")
+                .with_description("The bytes sent of each ongoing snapshot transfer to a follower.")
+                .init(),
+            meter
+                .u64_observable_gauge("snapshot_send_rate")
+                .with_description("The average rate in bytes per second of each ongoing snapshot transfer to a follower.")
+                .init(),
+            meter
+                .u64_observable_gauge("snapshot_send_eta_seconds")
+                .with_description("The estimated seconds to finish each ongoing snapshot transfer to a follower.")
+                .init(),
+            meter
+                .u64_observable_gauge("follower_degraded")
+                .with_description("Whether or not each follower is degraded by consecutive append entries timeouts if this member is the leader. 1 if is, 0 otherwise.")
+                .init(),
+            meter
+                .u64_observable_gauge("follower_replication_window")
+                .with_description("The max number of entries sent to each follower in an append entries if this member is the leader. 0 if it's only limited by the batch size.")
+                .init(),
         );
 
         _ = meter.register_callback(
@@ -106,6 +216,13 @@ impl Metrics {
                 server_id.as_any(),
                 sp_cnt.as_any(),
                 online_clients.as_any(),
+                result_cache_sessions.as_any(),
+                result_cache_results.as_any(),
+                snapshot_send_bytes.as_any(),
+                snapshot_send_rate.as_any(),
+                snapshot_send_eta_seconds.as_any(),
+                follower_degraded.as_any(),
+                follower_replication_window.as_any(),
             ],
             move |observer| {
                 let (leader_id, _, leader) = curp.leader();
@@ -133,9 +250,115 @@ impl Metrics {
                     last_log_index.overflow_sub(commit_index),
                     &[],
                 );
+
+                let (sessions, results) = curp.cmd_board().read().results_occupancy();
+                observer.observe_u64(&result_cache_sessions, sessions.numeric_cast(), &[]);
+                observer.observe_u64(&result_cache_results, results.numeric_cast(), &[]);
+
+                for (to, progress) in curp.snapshot_throttle().progress() {
+                    let attrs = [KeyValue::new("to", to.to_string())];
+                    observer.observe_u64(&snapshot_send_bytes, progress.sent(), &attrs);
+                    observer.observe_u64(&snapshot_send_rate, progress.rate(), &attrs);
+                    if let Some(eta) = progress.eta() {
+                        observer.observe_u64(&snapshot_send_eta_seconds, eta.as_secs(), &attrs);
+                    }
+                }
+
+                if leader {
+                    for (to, limit, degraded) in curp.replication_windows() {
+                        let attrs = [KeyValue::new("to", to.to_string())];
+                        observer.observe_u64(&follower_degraded, u64::from(degraded), &attrs);
+                        let limit = limit.map_or(0, |l| l.numeric_cast());
+                        observer.observe_u64(&follower_replication_window, limit, &attrs);
+                    }
+                }
             },
         )?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use curp_test_utils::test_cmd::TestCommand;
+    use opentelemetry::metrics::SyncHistogram;
+
+    use super::*;
+    use crate::{
+        log_entry::LogEntry,
+        rpc::{ConfChange, ProposeId},
+    };
+
+    /// A histogram that keeps the labels of every recorded value
+    #[derive(Debug, Default)]
+    struct RecordingHistogram {
+        /// The `(stage, entry_type)` of each recorded value
+        records: Mutex<Vec<(String, String)>>,
+    }
+
+    impl SyncHistogram<f64> for RecordingHistogram {
+        fn record(&self, _value: f64, attributes: &[KeyValue]) {
+            let label = |key: &str| {
+                attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == key)
+                    .map(|kv| kv.value.as_str().into_owned())
+                    .unwrap()
+            };
+            self.records
+                .lock()
+                .unwrap()
+                .push((label("stage"), label("entry_type")));
+        }
+    }
+
+    #[test]
+    fn entry_stages_should_be_recorded_by_stage_and_entry_type() {
+        let recorder = Arc::new(RecordingHistogram::default());
+        let metrics = EntryStageMetrics::new(Histogram::new(Arc::clone(&recorder) as _));
+        let entries: Vec<LogEntry<TestCommand>> = vec![
+            LogEntry::new_command(
+                1,
+                1,
+                ProposeId(0, 1),
+                Arc::new(TestCommand::new_put(vec![1], 1)),
+            ),
+            LogEntry::new(2, 1, ProposeId(0, 2), vec![ConfChange::default()]),
+            LogEntry::new(
+                3,
+                1,
+                ProposeId(0, 3),
+                vec![
+                    (ProposeId(0, 4), Arc::new(TestCommand::new_put(vec![2], 2))),
+                    (ProposeId(0, 5), Arc::new(TestCommand::new_put(vec![3], 3))),
+                ],
+            ),
+        ];
+        for entry in &entries {
+            for stage in [
+                EntryStage::Execute,
+                EntryStage::LogPersist,
+                EntryStage::Commit,
+                EntryStage::Apply,
+            ] {
+                metrics.record(stage, entry.metric_label(), Duration::from_millis(1));
+            }
+        }
+
+        let records = recorder.records.lock().unwrap();
+        assert_eq!(records.len(), 12);
+        for (entry_type, stage) in ["command", "conf_change", "batch"]
+            .into_iter()
+            .flat_map(|t| ["execute", "log_persist", "commit", "apply"].map(|s| (t, s)))
+        {
+            let count = records
+                .iter()
+                .filter(|&&(ref s, ref t)| s == stage && t == entry_type)
+                .count();
+            assert_eq!(count, 1, "{stage} of {entry_type}");
+        }
+    }
+}
@@ -19,13 +19,14 @@ use crate::{
     members::{ClusterInfo, ServerId},
     role_change::RoleChange,
     rpc::{
-        AppendEntriesRequest, AppendEntriesResponse, FetchClusterRequest, FetchClusterResponse,
-        FetchReadStateRequest, FetchReadStateResponse, InstallSnapshotRequest,
-        InstallSnapshotResponse, LeaseKeepAliveMsg, MoveLeaderRequest, MoveLeaderResponse,
-        ProposeConfChangeRequest, ProposeConfChangeResponse, ProposeRequest, ProposeResponse,
-        PublishRequest, PublishResponse, ShutdownRequest, ShutdownResponse, TriggerShutdownRequest,
-        TriggerShutdownResponse, TryBecomeLeaderNowRequest, TryBecomeLeaderNowResponse,
-        VoteRequest, VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
+        AppendEntriesRequest, AppendEntriesResponse, CancelRequest, CancelResponse,
+        FetchClusterRequest, FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
+        InstallSnapshotRequest, InstallSnapshotResponse, LeaseKeepAliveMsg, MoveLeaderRequest,
+        MoveLeaderResponse, ProposeConfChangeRequest, ProposeConfChangeResponse, ProposeRequest,
+        ProposeResponse, PublishRequest, PublishResponse, ShutdownRequest, ShutdownResponse,
+        TriggerShutdownRequest, TriggerShutdownResponse, TryBecomeLeaderNowRequest,
+        TryBecomeLeaderNowResponse, VoteRequest, VoteResponse, WaitSyncedRequest,
+        WaitSyncedResponse,
     },
 };
 
@@ -38,12 +39,21 @@ mod raw_curp;
 /// Command board is the buffer to store command execution result
 mod cmd_board;
 
+/// Retention of the completed propose results
+mod result_cache;
+
 /// Conflict pools
 pub mod conflict;
 
 /// Background garbage collection for Curp server
 mod gc;
 
+/// Gossip of the state hashes of the members
+mod state_hash;
+
+/// Receiver of the snapshots sent by the leader
+mod snapshot_receiver;
+
 /// Curp Node
 mod curp_node;
 
@@ -77,7 +87,11 @@ impl<C: Command, RC: RoleChange> Clone for Rpc<C, RC> {
 
 #[tonic::async_trait]
 impl<C: Command, RC: RoleChange> crate::rpc::Protocol for Rpc<C, RC> {
-    #[instrument(skip_all, name = "curp_propose")]
+    #[instrument(
+        skip_all,
+        name = "curp_propose",
+        fields(propose_id = %request.get_ref().propose_id())
+    )]
     async fn propose(
         &self,
         request: tonic::Request<ProposeRequest>,
@@ -132,6 +146,17 @@ impl<C: Command, RC: RoleChange> crate::rpc::Protocol for Rpc<C, RC> {
         ))
     }
 
+    #[instrument(skip_all, name = "curp_cancel")]
+    async fn cancel(
+        &self,
+        request: tonic::Request<CancelRequest>,
+    ) -> Result<tonic::Response<CancelResponse>, tonic::Status> {
+        request.metadata().extract_span();
+        Ok(tonic::Response::new(
+            self.inner.cancel(request.into_inner()).await?,
+        ))
+    }
+
     #[instrument(skip_all, name = "curp_fetch_cluster")]
     async fn fetch_cluster(
         &self,
@@ -148,7 +173,7 @@ impl<C: Command, RC: RoleChange> crate::rpc::Protocol for Rpc<C, RC> {
         request: tonic::Request<FetchReadStateRequest>,
     ) -> Result<tonic::Response<FetchReadStateResponse>, tonic::Status> {
         Ok(tonic::Response::new(
-            self.inner.fetch_read_state(request.into_inner())?,
+            self.inner.fetch_read_state(request.into_inner()).await?,
         ))
     }
 
@@ -183,7 +208,7 @@ impl<C: Command, RC: RoleChange> crate::rpc::InnerProtocol for Rpc<C, RC> {
         request: tonic::Request<AppendEntriesRequest>,
     ) -> Result<tonic::Response<AppendEntriesResponse>, tonic::Status> {
         Ok(tonic::Response::new(
-            self.inner.append_entries(request.get_ref())?,
+            self.inner.append_entries(request.into_inner()).await?,
         ))
     }
 
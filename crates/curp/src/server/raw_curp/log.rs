@@ -6,6 +6,7 @@ use std::{
     fmt::Debug,
     ops::{Bound, Range, RangeBounds, RangeInclusive},
     sync::Arc,
+    time::Instant,
     vec,
 };
 
@@ -18,7 +19,7 @@ use crate::{
     cmd::Command,
     log_entry::{EntryData, LogEntry},
     rpc::ProposeId,
-    server::metrics,
+    server::metrics::{self, EntryStage},
     snapshot::SnapshotMeta,
     LogIndex,
 };
@@ -30,6 +31,8 @@ struct Entry<C: Command> {
     inner: Arc<LogEntry<C>>,
     /// The serialized size of the inner `LogEntry`
     size: u64,
+    /// When the entry was appended to this log
+    appended_at: Instant,
 }
 
 /// Enum representing a range of values in the log.
@@ -119,6 +122,20 @@ pub(super) struct Log<C: Command> {
     log_tx: mpsc::UnboundedSender<Arc<LogEntry<C>>>,
     /// Entries to keep in memory
     entries_cap: usize,
+    /// The term in which the pending commands were proposed
+    pending_term: u64,
+    /// Commands waiting to be appended as a single batched entry
+    pending: Vec<PendingCommand<C>>,
+}
+
+/// A command waiting to be batched with the ones proposed after it
+pub(super) struct PendingCommand<C> {
+    /// The propose id
+    pub(super) propose_id: ProposeId,
+    /// The command
+    pub(super) cmd: Arc<C>,
+    /// Whether the command conflicts with others and won't be speculatively executed
+    pub(super) conflict: bool,
 }
 
 /// Context of fallback conf change entry
@@ -208,7 +225,11 @@ impl<C: Command> Log<C> {
             warn!("entry_size of an entry > batch_limit, which may be too small.",);
         }
 
-        self.entries.push_back(Entry { inner, size });
+        self.entries.push_back(Entry {
+            inner,
+            size,
+            appended_at: Instant::now(),
+        });
         self.batch_end.push_back(0); // placeholder
         self.cur_batch_size += size;
 
@@ -313,6 +334,8 @@ impl<C: Command> Debug for Log<C> {
 type ConfChangeEntries<C> = Vec<Arc<LogEntry<C>>>;
 /// Fallback indexes type
 type FallbackIndexes = HashSet<LogIndex>;
+/// Speculatively executed entries removed from the log
+type DiscardedEntries<C> = Vec<Arc<LogEntry<C>>>;
 
 impl<C: Command> Log<C> {
     /// Create a new log
@@ -335,6 +358,8 @@ impl<C: Command> Log<C> {
             log_tx,
             fallback_contexts: HashMap::new(),
             entries_cap,
+            pending_term: 0,
+            pending: Vec::new(),
         }
     }
 
@@ -370,16 +395,19 @@ impl<C: Command> Log<C> {
     }
 
     /// Try to append log entries, hand back the entries if they can't be appended
-    /// and return conf change entries if any
+    /// and return conf change entries if any, along with the speculatively executed
+    /// entries that are truncated
     #[allow(clippy::unwrap_in_result)]
     pub(super) fn try_append_entries(
         &mut self,
         entries: Vec<LogEntry<C>>,
         prev_log_index: LogIndex,
         prev_log_term: u64,
-    ) -> Result<(ConfChangeEntries<C>, FallbackIndexes), Vec<LogEntry<C>>> {
+    ) -> Result<(ConfChangeEntries<C>, FallbackIndexes, DiscardedEntries<C>), Vec<LogEntry<C>>>
+    {
         let mut conf_changes = vec![];
         let mut need_fallback_indexes = HashSet::new();
+        let mut discarded = vec![];
         // check if entries can be appended
         if self.get(prev_log_index).map_or_else(
             || (self.base_index, self.base_term) != (prev_log_index, prev_log_term),
@@ -405,9 +433,13 @@ impl<C: Command> Log<C> {
             if matches!(e.inner.entry_data, EntryData::ConfChange(_)) {
                 let _ig = need_fallback_indexes.insert(e.inner.index);
             }
+            if e.inner.index <= self.last_exe {
+                discarded.extend(e.inner.unpack());
+            }
         }
         // Truncate entries
         self.truncate(pi);
+        self.last_exe = self.last_exe.min(self.last_log_index());
         // Push the remaining entries and record the conf change entries
         for entry in entries
             .into_iter()
@@ -426,7 +458,7 @@ impl<C: Command> Log<C> {
             self.send_persist(entry);
         }
 
-        Ok((conf_changes, need_fallback_indexes))
+        Ok((conf_changes, need_fallback_indexes, discarded))
     }
 
     /// Send log entries to persist task
@@ -488,10 +520,106 @@ impl<C: Command> Log<C> {
     pub(super) fn get_cmd_ids(&self) -> HashSet<ProposeId> {
         self.entries
             .iter()
-            .map(|entry| entry.inner.propose_id)
+            .flat_map(|entry| match entry.inner.entry_data {
+                EntryData::Commands(ref cmds) | EntryData::MetaCommands(ref cmds) => {
+                    cmds.iter().map(|&(id, _)| id).collect()
+                }
+                EntryData::Empty
+                | EntryData::Command(_)
+                | EntryData::MetaCommand(_)
+                | EntryData::ConfChange(_)
+                | EntryData::Shutdown
+                | EntryData::SetNodeState(_, _, _)
+                | EntryData::SetClusterVersion(_)
+                | EntryData::ExpireSessions(_) => vec![entry.inner.propose_id],
+                // a canceled cmd must not be recovered
+                EntryData::Cancel(id) => vec![entry.inner.propose_id, id],
+            })
             .collect()
     }
 
+    /// Add a command to the pending batch and return the number of pending commands,
+    /// the commands left over from an earlier term must be taken by `take_stale_pending`
+    /// first
+    pub(super) fn push_pending(
+        &mut self,
+        term: u64,
+        propose_id: ProposeId,
+        cmd: Arc<C>,
+        conflict: bool,
+    ) -> usize {
+        debug_assert!(
+            self.pending.is_empty() || self.pending_term == term,
+            "the stale pending commands should be taken first"
+        );
+        self.pending_term = term;
+        self.pending.push(PendingCommand {
+            propose_id,
+            cmd,
+            conflict,
+        });
+        self.pending.len()
+    }
+
+    /// Take the pending commands left over from a term earlier than the given one
+    pub(super) fn take_stale_pending(&mut self, term: u64) -> Vec<PendingCommand<C>> {
+        if self.pending_term == term {
+            return Vec::new();
+        }
+        self.pending_term = term;
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Take all the pending commands
+    pub(super) fn take_pending(&mut self) -> Vec<PendingCommand<C>> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Remove a cmd from the pending batch, return it if it was pending
+    pub(super) fn remove_pending(&mut self, propose_id: ProposeId) -> Option<PendingCommand<C>> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|p| p.propose_id == propose_id)?;
+        Some(self.pending.remove(pos))
+    }
+
+    /// Check whether a cmd is in the log entries kept in memory, the latest first
+    pub(super) fn contains_cmd(&self, propose_id: ProposeId) -> bool {
+        self.entries
+            .iter()
+            .rev()
+            .any(|entry| Self::entry_contains_cmd(&entry.inner, propose_id))
+    }
+
+    /// Check whether a cmd is in the uncommitted log entries, the committed ones are
+    /// recorded by the command board
+    pub(super) fn contains_uncommitted_cmd(&self, propose_id: ProposeId) -> bool {
+        self.entries
+            .iter()
+            .rev()
+            .take_while(|entry| entry.inner.index > self.commit_index)
+            .any(|entry| Self::entry_contains_cmd(&entry.inner, propose_id))
+    }
+
+    /// Check whether a cmd is in the entry
+    fn entry_contains_cmd(entry: &LogEntry<C>, propose_id: ProposeId) -> bool {
+        match entry.entry_data {
+            EntryData::Commands(ref cmds) | EntryData::MetaCommands(ref cmds) => {
+                cmds.iter().any(|&(id, _)| id == propose_id)
+            }
+            EntryData::Empty
+            | EntryData::Command(_)
+            | EntryData::MetaCommand(_)
+            | EntryData::ConfChange(_)
+            | EntryData::Shutdown
+            | EntryData::SetNodeState(_, _, _)
+            | EntryData::SetClusterVersion(_)
+            | EntryData::ExpireSessions(_)
+            | EntryData::Cancel(_) => entry.propose_id == propose_id,
+        }
+    }
+
     /// Get previous log entry's term and index
     pub(super) fn get_prev_entry_info(&self, i: LogIndex) -> (LogIndex, u64) {
         assert!(i > 0, "log[0] has no previous log");
@@ -513,15 +641,32 @@ impl<C: Command> Log<C> {
         self.last_exe = meta.last_included_index;
         self.commit_index = meta.last_included_index;
         self.clear();
+        // the entries to fall back are gone, the members are reset by the snapshot
+        self.fallback_contexts.clear();
     }
 
     /// Restore log entries, provided entries must be in order
+    ///
+    /// The entries before the first one are removed with a snapshot, the first one
+    /// becomes the base if it's applied. Otherwise the log is reset to the snapshot at
+    /// `last_as` and the term of the base is unknown, so an append entries right after it
+    /// is rejected and the leader sends the snapshot again.
     pub(super) fn restore_entries(
         &mut self,
         entries: Vec<LogEntry<C>>,
     ) -> Result<(), bincode::Error> {
+        self.base_index = entries
+            .first()
+            .map_or(self.last_as, |entry| entry.index - 1);
+        self.base_term = 0;
         // restore batch index
         self.restore(entries)?;
+        if self.base_index > 0 && self.base_index < self.last_as {
+            if let Some(entry) = self.pop_front() {
+                self.base_index = entry.index;
+                self.base_term = entry.term;
+            }
+        }
         self.compact();
         Ok(())
     }
@@ -564,6 +709,16 @@ impl<C: Command> Log<C> {
             commit_index,
             self.commit_index
         );
+        let first_uncommitted = self.commit_index.max(self.base_index) + 1;
+        for i in first_uncommitted..=commit_index {
+            if let Some(entry) = self.entries.get(self.li_to_pi(i)) {
+                metrics::get().entry_stages.record(
+                    EntryStage::Commit,
+                    entry.inner.metric_label(),
+                    entry.appended_at.elapsed(),
+                );
+            }
+        }
         self.commit_index = commit_index;
         self.fallback_contexts.retain(|&idx, c| {
             if idx > self.commit_index {
@@ -807,6 +962,36 @@ mod tests {
         assert_eq!(log.batch_end.len(), 10);
     }
 
+    #[test]
+    fn recover_log_should_start_after_the_snapshot() {
+        let entries = |range: std::ops::RangeInclusive<u64>| {
+            range
+                .map(|i| LogEntry::new(i, 1, ProposeId(0, i), Arc::new(TestCommand::default())))
+                .collect::<Vec<LogEntry<TestCommand>>>()
+        };
+        let restored = |entries, last_as| {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut log =
+                Log::<TestCommand>::new(tx, default_batch_max_size(), default_log_entries_cap());
+            log.last_as = last_as;
+            log.restore_entries(entries).unwrap();
+            log
+        };
+
+        // the head is removed with a snapshot taken at 25
+        let log = restored(entries(21..=30), 25);
+        assert_eq!((log.base_index, log.base_term), (21, 1));
+        assert_eq!(log.entries.front().unwrap().inner.index, 22);
+        assert_eq!(log.last_log_index(), 30);
+
+        // the log is reset to a snapshot installed at 25
+        let log = restored(entries(26..=30), 25);
+        assert_eq!((log.base_index, log.base_term), (25, 0));
+        assert_eq!(log.get(26).unwrap().index, 26);
+        let log = restored(vec![], 25);
+        assert_eq!(log.last_log_index(), 25);
+    }
+
     #[test]
     fn compact_test() {
         let (log_tx, _log_rx) = mpsc::unbounded_channel();
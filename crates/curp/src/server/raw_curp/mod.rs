@@ -11,12 +11,13 @@
 
 use std::{
     cmp::min,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
     sync::{
-        atomic::{AtomicU64, AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
         Arc,
     },
+    time::Instant,
 };
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
@@ -30,7 +31,7 @@ use tokio::sync::{broadcast, mpsc, oneshot};
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
 use tracing::{
-    debug, error,
+    debug, error, info,
     log::{log_enabled, Level},
     trace, warn,
 };
@@ -43,20 +44,21 @@ use utils::{
 };
 
 use self::{
-    log::Log,
-    state::{CandidateState, LeaderState, State},
+    log::{Log, PendingCommand},
+    state::{CandidateState, LeaderState, ReplicationWindow, State, DEGRADE_AFTER_TIMEOUTS},
 };
 use super::{
     cmd_worker::CEEventTxApi,
     conflict::{spec_pool_new::SpeculativePool, uncommitted_pool::UncommittedPool},
     lease_manager::LeaseManagerRef,
+    state_hash::StateHashGossip,
     storage::StorageApi,
     DB,
 };
 use crate::{
-    cmd::Command,
+    cmd::{Command, CommandExecutor},
     log_entry::{EntryData, LogEntry},
-    members::{ClusterInfo, ServerId},
+    members::{ClusterInfo, Feature, ServerId, SERVER_VERSION},
     quorum, recover_quorum,
     role_change::RoleChange,
     rpc::{
@@ -65,11 +67,11 @@ use crate::{
         ReadState,
     },
     server::{
-        cmd_board::CmdBoardRef,
+        cmd_board::{CmdBoardRef, CommandBoard},
         metrics,
         raw_curp::{log::FallbackContext, state::VoteResult},
     },
-    snapshot::{Snapshot, SnapshotMeta},
+    snapshot::{Snapshot, SnapshotMeta, SnapshotThrottle},
     LogIndex,
 };
 
@@ -86,8 +88,8 @@ mod tests;
 /// Default Size of channel
 const CHANGE_CHANNEL_SIZE: usize = 128;
 
-/// Max gap between leader and learner when promoting a learner
-const MAX_PROMOTE_GAP: u64 = 500;
+/// The max number of client sessions expired by one log entry
+const MAX_EXPIRED_SESSIONS_PER_ENTRY: usize = 10_000;
 
 /// The curp state machine
 pub struct RawCurp<C: Command, RC: RoleChange> {
@@ -149,6 +151,12 @@ pub(super) struct RawCurpArgs<C: Command, RC: RoleChange> {
     spec_pool: Arc<Mutex<SpeculativePool<C>>>,
     /// Uncommitted pool
     uncommitted_pool: Arc<Mutex<UncommittedPool<C>>>,
+    /// The executor the state hashes are gossiped of, `None` disables the gossip
+    #[builder(setter(strip_option), default)]
+    cmd_executor: Option<Arc<dyn CommandExecutor<C>>>,
+    /// The position of the last applied command of each partially applied batched entry
+    #[builder(default)]
+    applied_in_batches: Vec<(LogIndex, usize)>,
 }
 
 impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
@@ -168,6 +176,10 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
             args.cfg.log_entries_cap,
         ));
 
+        let state_hash = Arc::new(StateHashGossip::new(
+            args.cfg.state_hash_interval,
+            args.cmd_executor,
+        ));
         let ctx = Context::builder()
             .cluster_info(args.cluster_info)
             .cb(args.cmd_board)
@@ -181,6 +193,7 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
             .client_tls_config(args.client_tls_config)
             .spec_pool(args.spec_pool)
             .uncommitted_pool(args.uncommitted_pool)
+            .state_hash(state_hash)
             .build()
             .map_err(|e| match e {
                 ContextBuilderError::UninitializedField(s) => {
@@ -201,6 +214,8 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
         if args.is_leader {
             let mut st_w = raw_curp.st.write();
             st_w.term = 1;
+            raw_curp.ctx.role_change.on_term_change(st_w.term);
+            raw_curp.set_pool_generation(st_w.term);
             raw_curp.become_leader(&mut st_w);
         }
 
@@ -210,10 +225,19 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
             st_w.voted_for = Some(server_id);
         }
 
-        if !args.entries.is_empty() {
+        // the log of a reset to a snapshot may be empty, it starts after the applied index
+        if !args.entries.is_empty() || args.last_applied.is_some_and(|applied| applied > 0) {
             let last_applied = args.last_applied.ok_or_else(|| {
                 RawCurpBuilderError::ValidationError("last_applied is not set".to_owned())
             })?;
+            if let Some(first) = args.entries.first() {
+                if first.index > last_applied + 1 {
+                    return Err(RawCurpBuilderError::ValidationError(format!(
+                        "the log starts at {} after the applied index {last_applied}",
+                        first.index
+                    )));
+                }
+            }
             let mut log_w = raw_curp.log.write();
             log_w.last_as = last_applied;
             log_w.last_exe = last_applied;
@@ -221,6 +245,11 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
             log_w
                 .restore_entries(args.entries)
                 .map_err(|e| RawCurpBuilderError::ValidationError(e.to_string()))?;
+            *raw_curp.ctx.applied_in_batches.lock() = args
+                .applied_in_batches
+                .into_iter()
+                .filter(|&(index, _)| index > last_applied)
+                .collect();
         }
 
         Ok(raw_curp)
@@ -313,6 +342,9 @@ struct Context<C: Command, RC: RoleChange> {
     /// Election tick
     #[builder(setter(skip))]
     election_tick: AtomicU8,
+    /// Whether the campaign has been disabled at runtime
+    #[builder(setter(skip))]
+    campaign_disabled: AtomicBool,
     /// Tx to send cmds to execute and do after sync
     cmd_tx: Arc<dyn CEEventTxApi<C>>,
     /// Followers sync event trigger
@@ -320,6 +352,21 @@ struct Context<C: Command, RC: RoleChange> {
     /// Become leader event
     #[builder(setter(skip))]
     leader_event: Arc<Event>,
+    /// Event of a new batch of commands being started
+    #[builder(setter(skip))]
+    batch_event: Arc<Event>,
+    /// Event to send heartbeats to the followers right away
+    #[builder(setter(skip))]
+    heartbeat_event: Arc<Event>,
+    /// Event of a follower acknowledging an append entries
+    #[builder(setter(skip))]
+    ack_event: Arc<Event>,
+    /// Event of some entries in the conflict pools becoming stale
+    #[builder(setter(skip))]
+    pools_retired_event: Arc<Event>,
+    /// Throttle of sending snapshots
+    #[builder(setter(skip))]
+    snapshot_throttle: Arc<SnapshotThrottle>,
     /// Leader change callback
     role_change: RC,
     /// Conf change tx, used to update sync tasks
@@ -333,12 +380,30 @@ struct Context<C: Command, RC: RoleChange> {
     /// last conf change idx
     #[builder(setter(skip))]
     last_conf_change_idx: AtomicU64,
+    /// The term and the cluster server version last appended by this node as leader,
+    /// so that the version is not appended again before it's applied
+    #[builder(setter(skip))]
+    appended_server_version: Mutex<(u64, u32)>,
+    /// The index of the last `ExpireSessions` entry appended by this node as leader, so
+    /// that the sessions are not expired again before it's applied
+    #[builder(setter(skip))]
+    session_expiry_index: AtomicU64,
     /// Curp storage
     curp_storage: Arc<DB<C>>,
     /// Speculative pool
     spec_pool: Arc<Mutex<SpeculativePool<C>>>,
     /// Uncommitted pool
     uncommitted_pool: Arc<Mutex<UncommittedPool<C>>>,
+    /// Gossip of the state hashes
+    state_hash: Arc<StateHashGossip<C>>,
+    /// The position of the last command applied before a restart of each partially
+    /// applied batched entry, removed once the entry is applied again
+    #[builder(setter(skip))]
+    applied_in_batches: Mutex<HashMap<LogIndex, usize>>,
+    /// The members after each conf change entry not applied yet, along with the members
+    /// before the first of them, as `(membership index, members)`
+    #[builder(setter(skip))]
+    membership_history: Mutex<VecDeque<(LogIndex, Vec<Member>)>>,
 }
 
 impl<C: Command, RC: RoleChange> Context<C, RC> {
@@ -352,15 +417,21 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
     /// Build the context from the builder
     pub(super) fn build(&mut self) -> Result<Context<C, RC>, ContextBuilderError> {
         let (change_tx, change_rx) = flume::bounded(CHANGE_CHANNEL_SIZE);
+        let cfg = match self.cfg.take() {
+            Some(value) => value,
+            None => return Err(ContextBuilderError::UninitializedField("cfg")),
+        };
+        let snapshot_throttle = Arc::new(SnapshotThrottle::new(
+            cfg.snapshot_read_rate_limit,
+            cfg.snapshot_send_rate_limit,
+            cfg.snapshot_max_concurrent_transfers,
+        ));
         Ok(Context {
             cluster_info: match self.cluster_info.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cluster_info")),
             },
-            cfg: match self.cfg.take() {
-                Some(value) => value,
-                None => return Err(ContextBuilderError::UninitializedField("cfg")),
-            },
+            cfg,
             cb: match self.cb.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cb")),
@@ -371,6 +442,7 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
             },
             leader_tx: broadcast::channel(1).0,
             election_tick: AtomicU8::new(0),
+            campaign_disabled: AtomicBool::new(false),
             cmd_tx: match self.cmd_tx.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cmd_tx")),
@@ -380,6 +452,11 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
                 None => return Err(ContextBuilderError::UninitializedField("sync_events")),
             },
             leader_event: Arc::new(Event::new()),
+            batch_event: Arc::new(Event::new()),
+            heartbeat_event: Arc::new(Event::new()),
+            ack_event: Arc::new(Event::new()),
+            pools_retired_event: Arc::new(Event::new()),
+            snapshot_throttle,
             role_change: match self.role_change.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("role_change")),
@@ -391,6 +468,8 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
                 None => return Err(ContextBuilderError::UninitializedField("connects")),
             },
             last_conf_change_idx: AtomicU64::new(0),
+            appended_server_version: Mutex::new((0, 0)),
+            session_expiry_index: AtomicU64::new(0),
             curp_storage: match self.curp_storage.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("curp_storage")),
@@ -407,6 +486,12 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("uncommitted_pool")),
             },
+            state_hash: match self.state_hash.take() {
+                Some(value) => value,
+                None => return Err(ContextBuilderError::UninitializedField("state_hash")),
+            },
+            applied_in_batches: Mutex::new(HashMap::new()),
+            membership_history: Mutex::new(VecDeque::new()),
         })
     }
 }
@@ -419,6 +504,7 @@ impl<C: Command, RC: RoleChange> Debug for Context<C, RC> {
             .field("cb", &self.cb)
             .field("leader_tx", &self.leader_tx)
             .field("election_tick", &self.election_tick)
+            .field("campaign_disabled", &self.campaign_disabled)
             .field("cmd_tx", &"CEEventTxApi")
             .field("sync_events", &self.sync_events)
             .field("leader_event", &self.leader_event)
@@ -439,6 +525,10 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if tick < timeout {
             return None;
         }
+        if st_r.role == Role::Follower && (self.no_campaign() || !self.is_voter()) {
+            self.reset_election_tick();
+            return None;
+        }
         let mut st_w = RwLockUpgradableReadGuard::upgrade(st_r);
         let mut cst_l = self.cst.lock();
         let log_r = self.log.upgradable_read();
@@ -458,18 +548,24 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
 // Curp handlers
 impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
     /// Handle `propose` request
-    /// Return `true` if the leader speculatively executed the command
+    /// Return `true` if the leader speculatively executed the command, or has the cached
+    /// result of it
     pub(super) fn handle_propose(
         &self,
         propose_id: ProposeId,
         cmd: Arc<C>,
     ) -> Result<bool, CurpError> {
         debug!("{} gets proposal for cmd({})", self.id(), propose_id);
-        let mut conflict = self
+        let session_live = self
+            .ctx
+            .cb
+            .map_write(|mut cb_w| cb_w.touch_session(propose_id, tokio::time::Instant::now()));
+        let sp_rejected = self
             .ctx
             .spec_pool
             .map_lock(|mut sp_l| sp_l.insert(PoolEntry::new(propose_id, Arc::clone(&cmd))))
             .is_some();
+        let mut conflict = sp_rejected;
 
         let st_r = self.st.read();
         // Non-leader doesn't need to sync or execute
@@ -485,6 +581,24 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if self.lst.get_transferee().is_some() {
             return Err(CurpError::LeaderTransfer("leader transferring".to_owned()));
         }
+        // the session of the cmd has expired, its result is lost if it was completed
+        if !session_live {
+            metrics::get()
+                .proposals_failed
+                .add(1, &[KeyValue::new("reason", "session expired")]);
+            return Err(CurpError::session_expired());
+        }
+        // the cmd may have been completed and its result evicted, don't execute it again
+        if self
+            .ctx
+            .cb
+            .map_read(|cb_r| cb_r.is_result_expired(propose_id))
+        {
+            metrics::get()
+                .proposals_failed
+                .add(1, &[KeyValue::new("reason", "result expired")]);
+            return Err(CurpError::result_expired());
+        }
         if !self
             .ctx
             .cb
@@ -496,6 +610,33 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             return Err(CurpError::duplicated());
         }
 
+        // the cmd may have been appended in an earlier term, it's never appended again, so
+        // that every log entry is applied and no replica has to tell a retry apart. The
+        // client gets the result of the one appended.
+        let (applied, completed) = self.ctx.cb.map_read(|cb_r| {
+            (
+                cb_r.is_applied(propose_id),
+                cb_r.er_buffer.contains_key(&propose_id),
+            )
+        });
+        if applied || self.log.read().contains_uncommitted_cmd(propose_id) {
+            if !sp_rejected {
+                let _ignore = self
+                    .ctx
+                    .spec_pool
+                    .map_lock(|mut sp_l| sp_l.remove_by_id(propose_id));
+            }
+            // the result is cached, the entry of the cmd has been persisted long ago
+            if applied && completed {
+                self.ctx.cb.write().untrack_span(propose_id);
+                CommandBoard::notify_persisted(&self.ctx.cb, [propose_id]);
+                return Ok(true);
+            }
+            metrics::get()
+                .proposals_failed
+                .add(1, &[KeyValue::new("reason", "already appended")]);
+            return Err(CurpError::key_conflict());
+        }
         // leader also needs to check if the cmd conflicts un-synced commands
         conflict |= self
             .ctx
@@ -503,15 +644,45 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             .map_lock(|mut ucp_l| ucp_l.insert(PoolEntry::new(propose_id, Arc::clone(&cmd))));
 
         let mut log_w = self.log.write();
-        let entry = log_w.push(st_r.term, propose_id, cmd).map_err(|e| {
+        // the cmd may have been canceled since the checks above, it must not be appended
+        if self.ctx.cb.map_read(|cb_r| cb_r.is_canceled(propose_id)) {
+            if !sp_rejected {
+                let _ignore = self
+                    .ctx
+                    .spec_pool
+                    .map_lock(|mut sp_l| sp_l.remove_by_id(propose_id));
+            }
+            self.ctx
+                .uncommitted_pool
+                .map_lock(|mut ucp_l| ucp_l.remove(PoolEntry::new(propose_id, cmd)));
             metrics::get()
                 .proposals_failed
-                .add(1, &[KeyValue::new("reason", "log serialize failed")]);
-            e
-        })?;
-        debug!("{} gets new log[{}]", self.id(), entry.index);
+                .add(1, &[KeyValue::new("reason", "canceled")]);
+            return Err(CurpError::canceled());
+        }
+        let batch_max_size = self.cfg().propose_batch_max_size;
+        if batch_max_size > 1 {
+            let stale = log_w.take_stale_pending(st_r.term);
+            self.drop_pending(stale);
+            let pending = log_w.push_pending(st_r.term, propose_id, cmd, conflict);
+            if pending == 1 {
+                let _ignore = self.ctx.batch_event.notify(1);
+            }
+            if pending >= batch_max_size {
+                self.flush_batch(&mut log_w, st_r.term)?;
+            }
+        } else {
+            let data = self.cmd_entry_data(cmd);
+            let entry = log_w.push(st_r.term, propose_id, data).map_err(|e| {
+                metrics::get()
+                    .proposals_failed
+                    .add(1, &[KeyValue::new("reason", "log serialize failed")]);
+                e
+            })?;
+            debug!("{} gets new log[{}]", self.id(), entry.index);
 
-        self.entry_process(&mut log_w, entry, conflict, st_r.term);
+            self.entry_process(&mut log_w, entry, conflict, st_r.term);
+        }
 
         if conflict {
             metrics::get()
@@ -523,6 +694,55 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         Ok(true)
     }
 
+    /// Handle `cancel` request, return a receiver resolved once the cancel entry is
+    /// committed, or `None` if the cmd has been appended and will be executed
+    ///
+    /// The decision is made under the log lock, which is also taken by the proposal to
+    /// append the cmd, so the cmd is either appended before or rejected afterwards. The
+    /// decision is replicated by a `Cancel` entry, the servers drop their speculative
+    /// copies and skip a later appended cmd once it's committed.
+    pub(super) fn handle_cancel(
+        &self,
+        propose_id: ProposeId,
+    ) -> Result<Option<oneshot::Receiver<()>>, CurpError> {
+        let st_r = self.st.read();
+        // the followers keep their speculative copies until the cancel entry is committed
+        if st_r.role != Role::Leader {
+            return Err(CurpError::redirect(st_r.leader_id, st_r.term));
+        }
+        let mut log_w = self.log.write();
+        if let Some(pending) = log_w.remove_pending(propose_id) {
+            let _ignore = self
+                .ctx
+                .spec_pool
+                .map_lock(|mut sp_l| sp_l.remove_by_id(propose_id));
+            self.ctx
+                .uncommitted_pool
+                .map_lock(|mut ucp_l| ucp_l.remove(PoolEntry::new(propose_id, pending.cmd)));
+        } else if log_w.contains_cmd(propose_id)
+            || self.ctx.cb.map_read(|cb_r| cb_r.is_appended(propose_id))
+        {
+            return Ok(None);
+        } else {
+            // not proposed yet, or on its way to the log
+        }
+        self.flush_batch(&mut log_w, st_r.term)?;
+        let entry_id = ProposeId(rand::random(), 0);
+        let committed = self.ctx.cb.map_write(|mut cb_w| {
+            // late proposals are rejected here before the cancel entry is committed
+            cb_w.cancel(propose_id);
+            cb_w.watch_cancel_committed(entry_id)
+        });
+        let entry = log_w.push(st_r.term, entry_id, EntryData::Cancel(propose_id))?;
+        debug!(
+            "{} cancels cmd({propose_id}) in log[{}]",
+            self.id(),
+            entry.index
+        );
+        self.entry_process(&mut log_w, entry, true, st_r.term);
+        Ok(Some(committed))
+    }
+
     /// Handle `shutdown` request
     pub(super) fn handle_shutdown(&self, propose_id: ProposeId) -> Result<(), CurpError> {
         let st_r = self.st.read();
@@ -533,6 +753,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             return Err(CurpError::LeaderTransfer("leader transferring".to_owned()));
         }
         let mut log_w = self.log.write();
+        self.flush_batch(&mut log_w, st_r.term)?;
         let entry = log_w
             .push(st_r.term, propose_id, EntryData::Shutdown)
             .map_err(|e| {
@@ -581,6 +802,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             .insert(PoolEntry::new(propose_id, conf_changes.clone()));
 
         let mut log_w = self.log.write();
+        self.flush_batch(&mut log_w, st_r.term)?;
         let entry = log_w
             .push(st_r.term, propose_id, conf_changes.clone())
             .map_err(|e| {
@@ -590,14 +812,16 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
                 e
             })?;
         debug!("{} gets new log[{}]", self.id(), entry.index);
-        let (addrs, name, is_learner) = self.apply_conf_change(conf_changes);
+        let fallback_info = self.apply_conf_change_entry(entry.index, conf_changes);
         self.ctx
             .last_conf_change_idx
             .store(entry.index, Ordering::Release);
-        let _ig = log_w.fallback_contexts.insert(
-            entry.index,
-            FallbackContext::new(Arc::clone(&entry), addrs, name, is_learner),
-        );
+        if let Some((addrs, name, is_learner)) = fallback_info {
+            let _ig = log_w.fallback_contexts.insert(
+                entry.index,
+                FallbackContext::new(Arc::clone(&entry), addrs, name, is_learner),
+            );
+        }
         self.entry_process(&mut log_w, entry, conflict, st_r.term);
         Ok(())
     }
@@ -617,6 +841,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             return Err(CurpError::leader_transfer("leader transferring"));
         }
         let mut log_w = self.log.write();
+        self.flush_batch(&mut log_w, st_r.term)?;
         let entry = log_w.push(st_r.term, req.propose_id(), req).map_err(|e| {
             metrics::get()
                 .proposals_failed
@@ -628,6 +853,184 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         Ok(())
     }
 
+    /// Handle the server version reported by a follower
+    ///
+    /// A member older than the cluster can't be added as a voter, but one added
+    /// directly or downgraded can only be warned about.
+    pub(super) fn handle_server_version(&self, follower_id: ServerId, server_version: u32) {
+        let prev = self.lst.update_server_version(follower_id, server_version);
+        let cluster_version = self.cluster().cluster_server_version();
+        if prev != Some(server_version) && server_version < cluster_version {
+            warn!(
+                "{follower_id} runs server version {server_version}, older than the cluster server version {cluster_version}"
+            );
+        }
+        self.negotiate_cluster_server_version();
+    }
+
+    /// Negotiate the cluster server version, the minimum server version of the members
+    ///
+    /// The leader appends it to the log when it rises, including the version a new
+    /// cluster bootstraps with, so that every member enables the new features at the
+    /// same index. It never decreases.
+    pub(super) fn negotiate_cluster_server_version(&self) {
+        let cluster_version = self.cluster().cluster_server_version();
+        let st_r = self.st.read();
+        if st_r.role != Role::Leader {
+            return;
+        }
+        let version = self
+            .lst
+            .min_server_version()
+            .map_or(SERVER_VERSION, |v| v.min(SERVER_VERSION));
+        let mut appended = self.ctx.appended_server_version.lock();
+        if version <= cluster_version || *appended >= (st_r.term, version) {
+            return;
+        }
+        let mut log_w = self.log.write();
+        if let Err(e) = self.flush_batch(&mut log_w, st_r.term) {
+            warn!("{} failed to append batched commands, {e:?}", self.id());
+            return;
+        }
+        let propose_id = ProposeId(rand::random(), 0);
+        match log_w.push(st_r.term, propose_id, EntryData::SetClusterVersion(version)) {
+            Ok(entry) => {
+                info!(
+                    "{} negotiates the cluster server version {version} in log[{}]",
+                    self.id(),
+                    entry.index
+                );
+                *appended = (st_r.term, version);
+                self.entry_process(&mut log_w, entry, true, st_r.term);
+            }
+            Err(e) => warn!(
+                "{} failed to append the cluster server version, {e:?}",
+                self.id()
+            ),
+        }
+    }
+
+    /// The state hash to piggyback on an append entries response, `None` if it's not due
+    pub(super) fn state_hash_report(&self) -> Option<(i64, u64)> {
+        self.ctx.state_hash.report()
+    }
+
+    /// Handle the state hash reported by a follower, only the voters are compared
+    pub(super) fn handle_state_hash(&self, follower_id: ServerId, revision: i64, hash: u64) {
+        if !self.is_leader()
+            || !self
+                .cluster()
+                .get(&follower_id)
+                .is_some_and(|m| !m.is_learner)
+        {
+            return;
+        }
+        self.ctx.state_hash.compare(
+            self.id(),
+            follower_id,
+            revision,
+            hash,
+            quorum(self.ctx.cluster_info.voters_len()),
+        );
+    }
+
+    /// Set the cluster server version applied from the log or a snapshot, and persist it,
+    /// return false if it's newer than this server, which can't apply the entries after
+    /// it the same way as the others
+    pub(super) fn set_cluster_server_version(&self, version: u32) -> bool {
+        if version > SERVER_VERSION {
+            error!(
+                "{} runs server version {SERVER_VERSION}, older than the cluster server version {version}",
+                self.id()
+            );
+            return false;
+        }
+        if self.cluster().cluster_server_version() == version {
+            return true;
+        }
+        info!("{} sets the cluster server version to {version}", self.id());
+        self.cluster().set_cluster_server_version(version);
+        if let Err(e) = self.ctx.curp_storage.put_cluster_server_version(version) {
+            error!("failed to persist the cluster server version, {e}");
+        }
+        true
+    }
+
+    /// Append the pending batched commands once the batch delay has expired
+    pub(super) fn handle_batch_timeout(&self) {
+        let st_r = self.st.read();
+        let mut log_w = self.log.write();
+        // the commands left by a retired leader will never be appended
+        if st_r.role != Role::Leader {
+            let pending = log_w.take_pending();
+            self.drop_pending(pending);
+            return;
+        }
+        if let Err(e) = self.flush_batch(&mut log_w, st_r.term) {
+            warn!("{} failed to append batched commands, {e:?}", self.id());
+        }
+    }
+
+    /// Append the client sessions idle for longer than the session ttl to the log, so
+    /// that every member drops them at the same index
+    pub(super) fn handle_session_expiry(&self) {
+        let st_r = self.st.read();
+        if st_r.role != Role::Leader || !self.cluster().feature_enabled(Feature::SessionExpiry) {
+            return;
+        }
+        // the sessions expired by the last entry stay in the cache until it's applied
+        if self.ctx.session_expiry_index.load(Ordering::Relaxed) > self.log.read().last_as {
+            return;
+        }
+        let mut sessions = self.ctx.cb.map_read(|cb_r| {
+            cb_r.idle_sessions(
+                self.cfg().result_cache.session_ttl,
+                tokio::time::Instant::now(),
+            )
+        });
+        if sessions.is_empty() {
+            return;
+        }
+        sessions.truncate(MAX_EXPIRED_SESSIONS_PER_ENTRY);
+        let mut log_w = self.log.write();
+        if let Err(e) = self.flush_batch(&mut log_w, st_r.term) {
+            warn!("{} failed to append batched commands, {e:?}", self.id());
+            return;
+        }
+        let propose_id = ProposeId(rand::random(), 0);
+        let expired = sessions.len();
+        match log_w.push(st_r.term, propose_id, EntryData::ExpireSessions(sessions)) {
+            Ok(entry) => {
+                debug!(
+                    "{} expires {expired} idle client sessions in log[{}]",
+                    self.id(),
+                    entry.index
+                );
+                self.ctx
+                    .session_expiry_index
+                    .store(entry.index, Ordering::Relaxed);
+                self.entry_process(&mut log_w, entry, true, st_r.term);
+            }
+            Err(e) => warn!(
+                "{} failed to append the expired client sessions, {e:?}",
+                self.id()
+            ),
+        }
+    }
+
+    /// Drop the client sessions expired by an applied log entry
+    pub(super) fn expire_sessions(&self, sessions: &[(u64, u64)]) {
+        let dropped = self
+            .ctx
+            .cb
+            .map_write(|mut cb_w| cb_w.expire_sessions(sessions, tokio::time::Instant::now()));
+        debug!(
+            "{} expires {} client sessions, dropping {dropped} cached results",
+            self.id(),
+            sessions.len()
+        );
+    }
+
     /// Handle `lease_keep_alive` message
     pub(super) fn handle_lease_keep_alive(&self, client_id: u64) -> Option<u64> {
         let mut lm_w = self.ctx.lm.write();
@@ -692,14 +1095,20 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
 
         // append log entries
         let mut log_w = self.log.write();
-        let (cc_entries, fallback_indexes) = log_w
+        let (cc_entries, fallback_indexes, discarded) = log_w
             .try_append_entries(entries, prev_log_index, prev_log_term)
             .map_err(|_ig| (term, log_w.commit_index + 1))?;
+        // entries executed speculatively as the leader are overwritten by the new leader
+        if !discarded.is_empty() {
+            self.ctx.cmd_tx.send_discard(discarded);
+        }
         // fallback overwritten conf change entries
         for idx in fallback_indexes.iter().sorted().rev() {
-            let info = log_w.fallback_contexts.remove(idx).unwrap_or_else(|| {
-                unreachable!("fall_back_infos should contain the entry need to fallback")
-            });
+            self.rewind_membership_index(*idx);
+            let Some(info) = log_w.fallback_contexts.remove(idx) else {
+                // skipped on apply, there's nothing to fall back
+                continue;
+            };
             let EntryData::ConfChange(ref conf_change) = info.origin_entry.entry_data else {
                 unreachable!("the entry in the fallback_info should be conf change entry");
             };
@@ -711,11 +1120,14 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             let EntryData::ConfChange(ref cc) = e.entry_data else {
                 unreachable!("cc_entry should be conf change entry");
             };
-            let (addrs, name, is_learner) = self.apply_conf_change(cc.clone());
-            let _ig = log_w.fallback_contexts.insert(
-                e.index,
-                FallbackContext::new(Arc::clone(&e), addrs, name, is_learner),
-            );
+            if let Some((addrs, name, is_learner)) =
+                self.apply_conf_change_entry(e.index, cc.clone())
+            {
+                let _ig = log_w.fallback_contexts.insert(
+                    e.index,
+                    FallbackContext::new(Arc::clone(&e), addrs, name, is_learner),
+                );
+            }
         }
         // update commit index
         let prev_commit_index = log_w.commit_index;
@@ -764,6 +1176,12 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         };
 
         self.lst.update_match_index(follower_id, last_sent_index);
+        if self
+            .lst
+            .update_window(follower_id, ReplicationWindow::on_success)
+        {
+            info!("follower {follower_id} recovers, its replication window is lifted");
+        }
 
         // check if commit_index needs to be updated
         let log_r = self.log.upgradable_read();
@@ -872,7 +1290,13 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
                     EntryData::Empty
                     | EntryData::Command(_)
                     | EntryData::Shutdown
-                    | EntryData::SetNodeState(_, _, _) => false,
+                    | EntryData::SetNodeState(_, _, _)
+                    | EntryData::Commands(_)
+                    | EntryData::SetClusterVersion(_)
+                    | EntryData::ExpireSessions(_)
+                    | EntryData::Cancel(_)
+                    | EntryData::MetaCommand(_)
+                    | EntryData::MetaCommands(_) => false,
                 });
         // extra check to shutdown removed node
         if !contains_candidate && !remove_candidate_is_not_committed {
@@ -950,10 +1374,12 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         let mut log_w = self.log.write();
 
         let prev_last_log_index = log_w.last_log_index();
+        self.recover_from_spec_pools(&st_w, &mut log_w, spec_pools);
+        // the no-op follows the recovered entries, once it's committed the entries of the
+        // former terms absent from the log will never complete
         // TODO: Generate client id in the same way as client
         let propose_id = ProposeId(rand::random(), 0);
         let _ignore = log_w.push(st_w.term, propose_id, EntryData::Empty);
-        self.recover_from_spec_pools(&st_w, &mut log_w, spec_pools);
         self.recover_ucp_from_log(&log_w);
         let last_log_index = log_w.last_log_index();
 
@@ -1031,13 +1457,15 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         validate
     }
 
-    /// Handle `install_snapshot` resp
+    /// Handle `install_snapshot` resp, the match index only advances if the follower
+    /// reports the snapshot installed
     /// Return Err(()) if the current node isn't a leader or current term is less than the given term
     pub(super) fn handle_snapshot_resp(
         &self,
         follower_id: ServerId,
         meta: SnapshotMeta,
         term: u64,
+        installed: bool,
     ) -> Result<(), ()> {
         // validate term
         let (cur_term, cur_role) = self.st.map_read(|st_r| (st_r.term, st_r.role));
@@ -1049,8 +1477,10 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if cur_role != Role::Leader {
             return Err(());
         }
-        self.lst
-            .update_match_index(follower_id, meta.last_included_index.numeric_cast());
+        if installed {
+            self.lst
+                .update_match_index(follower_id, meta.last_included_index.numeric_cast());
+        }
         Ok(())
     }
 
@@ -1074,6 +1504,104 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         }
     }
 
+    /// Get the term of this server if it is the leader
+    ///
+    /// # Errors
+    ///
+    /// Return `CurpError::Redirect` if it is not the leader
+    pub(super) fn leader_term(&self) -> Result<u64, CurpError> {
+        let st_r = self.st.read();
+        if st_r.role != Role::Leader {
+            return Err(CurpError::redirect(st_r.leader_id, st_r.term));
+        }
+        Ok(st_r.term)
+    }
+
+    /// Start a read index in `term`, return the read state of `cmd` and when it was read,
+    /// or `None` if the commit index of the leader may still be behind the one of the
+    /// cluster, which it catches up with once it has committed an entry in its term or
+    /// all the entries in its log
+    ///
+    /// # Errors
+    ///
+    /// Return `CurpError::Redirect` if it is no longer the leader of `term`
+    pub(super) fn start_read_index(
+        &self,
+        cmd: Arc<C>,
+        term: u64,
+    ) -> Result<Option<(ReadState, Instant)>, CurpError> {
+        let cur_term = self.leader_term()?;
+        if cur_term != term {
+            return Err(CurpError::redirect(Some(self.id()), cur_term));
+        }
+        let caught_up = {
+            let log_r = self.log.read();
+            log_r.commit_index == log_r.last_log_index()
+                || log_r
+                    .get(log_r.commit_index)
+                    .map_or(log_r.base_term, |entry| entry.term)
+                    == term
+        };
+        if !caught_up {
+            return Ok(None);
+        }
+        let state = self.handle_fetch_read_state(cmd);
+        Ok(Some((state, Instant::now())))
+    }
+
+    /// Check whether a quorum of voters has acknowledged the leadership of `term` since
+    /// `read_at`, after which the read state read at that time is up to date
+    ///
+    /// # Errors
+    ///
+    /// Return `CurpError::Redirect` if it is no longer the leader of `term`
+    pub(super) fn confirm_read_index(
+        &self,
+        term: u64,
+        read_at: Instant,
+    ) -> Result<bool, CurpError> {
+        let cur_term = self.leader_term()?;
+        if cur_term != term {
+            return Err(CurpError::redirect(Some(self.id()), cur_term));
+        }
+        let acked_cnt = self
+            .lst
+            .iter()
+            .filter(|f| !f.is_learner && f.acked_at.is_some_and(|acked_at| acked_at >= read_at))
+            .count();
+        Ok(acked_cnt + 1 >= quorum(self.ctx.cluster_info.voters_len()))
+    }
+
+    /// Handle the timeout of an append entries of `sent` entries to a follower, the
+    /// entries sent to it later are limited by its shrunk window
+    pub(super) fn handle_append_entries_timeout(&self, follower_id: ServerId, sent: usize) {
+        if self
+            .lst
+            .update_window(follower_id, |window| window.on_timeout(sent))
+        {
+            warn!(
+                "follower {follower_id} is degraded after {DEGRADE_AFTER_TIMEOUTS} consecutive append entries timeouts"
+            );
+        }
+    }
+
+    /// The replication windows of the followers, `None` if a window is not limited
+    pub(super) fn replication_windows(&self) -> Vec<(ServerId, Option<usize>, bool)> {
+        self.lst
+            .iter()
+            .map(|status| {
+                let window = status.window;
+                (*status.key(), window.limit(), window.is_degraded())
+            })
+            .collect()
+    }
+
+    /// Record that a follower has acknowledged an append entries sent at `sent_at`
+    pub(super) fn record_ack(&self, follower_id: ServerId, sent_at: Instant) {
+        self.lst.update_acked_at(follower_id, sent_at);
+        let _ignore = self.ctx.ack_event.notify(usize::MAX);
+    }
+
     /// Handle `move_leader`
     pub(super) fn handle_move_leader(&self, target_id: ServerId) -> Result<bool, CurpError> {
         debug!("{} received move leader to {}", self.id(), target_id);
@@ -1121,7 +1649,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if st_w.role == Role::Leader {
             return None;
         }
-        if self.cluster().self_member().is_learner() {
+        if !self.is_voter() || self.no_campaign() {
             return None;
         }
         let mut cst_l = self.cst.lock();
@@ -1182,28 +1710,47 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             );
             return None;
         };
+        let window = self.lst.get_window(follower_id).unwrap_or_default();
         let log_r = self.log.read();
-        if next_index <= log_r.base_index {
-            // the log has already been compacted
-            let entry = log_r.get(log_r.last_exe).unwrap_or_else(|| {
-                unreachable!(
-                    "log entry {} should not have been compacted yet, needed for snapshot",
-                    log_r.last_as
-                )
-            });
+        // a degraded follower that lags behind the log retention will need a snapshot
+        // once the log is compacted anyway, so it's sent right away
+        let beyond_retention = window.is_degraded()
+            && next_index.saturating_add(self.cfg().log_entries_cap.numeric_cast())
+                <= log_r.last_exe;
+        if next_index <= log_r.base_index || beyond_retention {
+            // the log has already been compacted, or will be
+            let last_exe = log_r.last_exe;
+            let (last_included_index, last_included_term) = if last_exe == log_r.base_index {
+                // the log has been reset by a snapshot and nothing is executed since then
+                (log_r.base_index, log_r.base_term)
+            } else {
+                let entry = log_r.get(last_exe).unwrap_or_else(|| {
+                    unreachable!(
+                        "log entry {last_exe} should not have been compacted yet, needed for snapshot"
+                    )
+                });
+                (entry.index, entry.term)
+            };
             // TODO: buffer a local snapshot: if a follower is down for a long time,
             // the leader will take a snapshot itself every time `sync` is called in effort to
             // calibrate it. Since taking a snapshot will block the leader's execute workers, we should
             // not take snapshot so often. A better solution would be to keep a snapshot cache.
             Some(SyncAction::Snapshot(self.ctx.cmd_tx.send_snapshot(
                 SnapshotMeta {
-                    last_included_index: entry.index,
-                    last_included_term: entry.term,
+                    last_included_index,
+                    last_included_term,
+                    // replaced by the version and the membership applied when the
+                    // snapshot is taken
+                    cluster_server_version: self.cluster().cluster_server_version(),
+                    membership_index: self.cluster().membership_index(),
                 },
             )))
         } else {
             let (prev_log_index, prev_log_term) = log_r.get_prev_entry_info(next_index);
-            let entries = log_r.get_from(next_index);
+            let mut entries = log_r.get_from(next_index);
+            if let Some(limit) = window.limit() {
+                entries.truncate(limit);
+            }
             let ae = AppendEntries {
                 term,
                 leader_id: self.id(),
@@ -1226,15 +1773,102 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         self.st.read().role == Role::Leader
     }
 
+    /// Whether the current node never starts an election
+    fn no_campaign(&self) -> bool {
+        self.cfg().no_campaign || self.ctx.campaign_disabled.load(Ordering::Acquire)
+    }
+
+    /// Stop campaigning for leadership, a leader also tries to move its leadership to an
+    /// up-to-date follower
+    #[inline]
+    pub async fn disable_campaign(&self) {
+        self.ctx.campaign_disabled.store(true, Ordering::Release);
+        if !self.is_leader() {
+            return;
+        }
+        let Some(target) = self.pick_new_leader() else {
+            warn!(
+                "{} stops campaigning, but no other node can be the leader now",
+                self.id()
+            );
+            return;
+        };
+        if !self.handle_move_leader(target).unwrap_or_default() {
+            return;
+        }
+        let Some(connect) = self.connects().get(&target).map(|c| c.value().clone()) else {
+            return;
+        };
+        if let Err(e) = connect
+            .try_become_leader_now(self.cfg().wait_synced_timeout)
+            .await
+        {
+            warn!(
+                "{} send try become leader now to {} failed: {:?}",
+                self.id(),
+                target,
+                e
+            );
+        }
+    }
+
+    /// Check whether the current node is a voting member, learners and removed members
+    /// never start an election
+    fn is_voter(&self) -> bool {
+        self.ctx
+            .cluster_info
+            .get(&self.id())
+            .is_some_and(|m| !m.is_learner)
+    }
+
     /// Get leader event
     pub(super) fn leader_event(&self) -> Arc<Event> {
         Arc::clone(&self.ctx.leader_event)
     }
 
-    /// Reset log base
+    /// Get the event of a new batch of commands being started
+    pub(super) fn batch_event(&self) -> Arc<Event> {
+        Arc::clone(&self.ctx.batch_event)
+    }
+
+    /// Get the event to send heartbeats to the followers right away
+    pub(super) fn heartbeat_event(&self) -> Arc<Event> {
+        Arc::clone(&self.ctx.heartbeat_event)
+    }
+
+    /// Get the event of a follower acknowledging an append entries
+    pub(super) fn ack_event(&self) -> Arc<Event> {
+        Arc::clone(&self.ctx.ack_event)
+    }
+
+    /// Get the event of some entries in the conflict pools becoming stale
+    pub(super) fn pools_retired_event(&self) -> Arc<Event> {
+        Arc::clone(&self.ctx.pools_retired_event)
+    }
+
+    /// Get the throttle of sending snapshots
+    pub(super) fn snapshot_throttle(&self) -> Arc<SnapshotThrottle> {
+        Arc::clone(&self.ctx.snapshot_throttle)
+    }
+
+    /// Reset log base, the log entries in the storage are dropped as well
     pub(super) fn reset_by_snapshot(&self, meta: SnapshotMeta) {
         let mut log_w = self.log.write();
         log_w.reset_by_snapshot_meta(meta);
+        if let Err(e) = self.ctx.curp_storage.reset_log(meta.last_included_index) {
+            error!("{} failed to reset the log in the storage, {e}", self.id());
+        }
+    }
+
+    /// Remove the log entries covered by a snapshot taken at `snapshot_index` from the
+    /// storage
+    pub(super) fn compact_storage_log(&self, snapshot_index: LogIndex) {
+        if let Err(e) = self.ctx.curp_storage.compact_log(snapshot_index) {
+            error!(
+                "{} failed to compact the log in the storage, {e}",
+                self.id()
+            );
+        }
     }
 
     /// Get current term
@@ -1340,12 +1974,24 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
                     .get_match_index(node_id)
                     .unwrap_or_else(|| unreachable!("learner should exist here"));
                 let leader_index = self.log.read().last_log_index();
-                if leader_index.overflow_sub(learner_index) > MAX_PROMOTE_GAP {
+                if leader_index.overflow_sub(learner_index) > self.cfg().learner_promote_gap {
                     metrics::get()
                         .learner_promote_failed
                         .add(1, &[KeyValue::new("reason", "learner not catch up")]);
                     return Err(CurpError::learner_not_catch_up());
                 }
+                // a voter must apply the entries the same way as the others
+                let cluster_version = self.cluster().cluster_server_version();
+                if self
+                    .lst
+                    .get_server_version(node_id)
+                    .is_some_and(|v| v < cluster_version)
+                {
+                    metrics::get()
+                        .learner_promote_failed
+                        .add(1, &[KeyValue::new("reason", "learner too old")]);
+                    return Err(CurpError::invalid_config());
+                }
             }
         }
         let mut all_nodes = HashSet::new();
@@ -1370,6 +2016,198 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         self.switch_config(conf_change)
     }
 
+    /// Apply the conf changes of the log entry at `index`, returns the info to fall
+    /// them back, `None` if the entry changes nothing
+    ///
+    /// The entries already reflected in the members, like the ones replayed after a
+    /// snapshot is installed, and the entries not applicable to the members are skipped,
+    /// so that the membership is the same on every member whatever it has replayed.
+    pub(super) fn apply_conf_change_entry(
+        &self,
+        index: LogIndex,
+        changes: Vec<ConfChange>,
+    ) -> Option<(Vec<String>, String, bool)> {
+        if index <= self.cluster().membership_index() {
+            debug!(
+                "{} skips conf change entry {index} already reflected in the members",
+                self.id()
+            );
+            return None;
+        }
+        let before = self.membership();
+        let info = self
+            .conf_change_applicable(&changes)
+            .then(|| self.apply_conf_change(changes));
+        self.set_membership_index(index);
+        let after = self.membership();
+        let mut history = self.ctx.membership_history.lock();
+        if history.is_empty() {
+            history.push_back(before);
+        }
+        history.push_back(after);
+        info
+    }
+
+    /// Check if the conf changes are applicable to the members, and the resulting
+    /// membership is consistent
+    fn conf_change_applicable(&self, changes: &[ConfChange]) -> bool {
+        let Some(conf_change) = changes.first() else {
+            return false;
+        };
+        let node_id = conf_change.node_id;
+        let known = self.ctx.cluster_info.contains(node_id);
+        let mut config = self.cst.map_lock(|cst_l| cst_l.config.clone());
+        let applicable = match conf_change.change_type() {
+            ConfChangeType::Add => {
+                !known && !config.contains(node_id) && config.insert(node_id, false)
+            }
+            ConfChangeType::AddLearner => {
+                !known && !config.contains(node_id) && config.insert(node_id, true)
+            }
+            ConfChangeType::Remove => known && config.contains(node_id) && config.remove(node_id),
+            ConfChangeType::Update => known,
+            ConfChangeType::Promote => {
+                known && config.learners.remove(&node_id) && config.insert(node_id, false)
+            }
+        };
+        if !applicable {
+            warn!(
+                "{} skips conf change {conf_change:?} not applicable to the members",
+                self.id()
+            );
+            return false;
+        }
+        if config.voters().is_empty() || !config.voters().is_disjoint(&config.learners) {
+            warn!(
+                "{} skips conf change {conf_change:?} leading to an inconsistent membership",
+                self.id()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Set the index of the last conf change entry reflected in the members, and
+    /// persist it
+    fn set_membership_index(&self, index: LogIndex) {
+        self.cluster().set_membership_index(index);
+        if let Err(e) = self.ctx.curp_storage.put_membership_index(index) {
+            error!("failed to persist the membership index, {e}");
+        }
+    }
+
+    /// Rewind the membership index before the conf change entry at `index`, which is
+    /// overwritten and fallen back
+    fn rewind_membership_index(&self, index: LogIndex) {
+        if self.cluster().membership_index() >= index {
+            self.set_membership_index(index.overflow_sub(1));
+        }
+        self.ctx
+            .membership_history
+            .lock()
+            .retain(|&(i, _)| i < index);
+    }
+
+    /// The members and the index of the last conf change entry reflected in them
+    pub(super) fn membership(&self) -> (LogIndex, Vec<Member>) {
+        // the conf changes are applied with the cst lock held
+        let _cst_l = self.cst.lock();
+        (
+            self.cluster().membership_index(),
+            self.cluster().all_members_vec(),
+        )
+    }
+
+    /// The members and the index of the last conf change entry reflected in them, as of
+    /// the entry at `index`, the conf change entries after it are excluded
+    pub(super) fn membership_at(&self, index: LogIndex) -> (LogIndex, Vec<Member>) {
+        let recorded = self
+            .ctx
+            .membership_history
+            .lock()
+            .iter()
+            .rev()
+            .find(|&&(i, _)| i <= index)
+            .cloned();
+        recorded.unwrap_or_else(|| self.membership())
+    }
+
+    /// Drop the recorded members older than the ones as of the applied index
+    fn prune_membership_history(&self, applied: LogIndex) {
+        let mut history = self.ctx.membership_history.lock();
+        while history.get(1).is_some_and(|&(i, _)| i <= applied) {
+            let _ignore = history.pop_front();
+        }
+        // the current members are as of the applied index
+        if history.len() == 1 {
+            history.clear();
+        }
+    }
+
+    /// Reset the members to the ones of an installed snapshot, the conf change entries
+    /// reflected in them are skipped when they are replayed
+    pub(super) fn reset_membership_by_snapshot(&self, index: LogIndex, members: Vec<Member>) {
+        if members.is_empty() {
+            // sent by a leader not recording the members in snapshots
+            return;
+        }
+        let mut cst_l = self.cst.lock();
+        let ids: HashSet<_> = members.iter().map(|m| m.id).collect();
+        for id in self.ctx.cluster_info.all_members().into_keys() {
+            if ids.contains(&id) {
+                continue;
+            }
+            if cst_l.config.contains(id) {
+                _ = cst_l.config.remove(id);
+            }
+            self.lst.remove(id);
+            _ = self.ctx.sync_events.remove(&id);
+            _ = self.ctx.connects.remove(&id);
+            let _ig1 = self.ctx.curp_storage.remove_member(id);
+            let _ig2 = self.ctx.cluster_info.remove(&id);
+        }
+        for member in members {
+            let id = member.id;
+            let is_learner = member.is_learner;
+            let known = self.ctx.cluster_info.get(&id).map(|m| m.is_learner);
+            match known {
+                None => {
+                    self.lst.insert(id, is_learner);
+                    _ = self.ctx.sync_events.insert(id, Arc::new(Event::new()));
+                    let _ig = self.ctx.cluster_info.insert(member);
+                }
+                Some(was_learner) => {
+                    _ = self.ctx.cluster_info.update(&id, member.peer_urls);
+                    match (was_learner, is_learner) {
+                        (true, false) => {
+                            _ = self.ctx.cluster_info.promote(id);
+                            self.lst.promote(id);
+                        }
+                        (false, true) => {
+                            self.ctx.cluster_info.demote(id);
+                            self.lst.demote(id);
+                        }
+                        (true, true) | (false, false) => {}
+                    }
+                }
+            }
+            if cst_l.config.contains(id) {
+                _ = cst_l.config.remove(id);
+            }
+            _ = cst_l.config.insert(id, is_learner);
+            if let Some(m) = self.ctx.cluster_info.get(&id) {
+                let _ig = self.ctx.curp_storage.put_member(&m);
+            }
+        }
+        self.set_membership_index(index);
+        self.ctx.membership_history.lock().clear();
+        self.ctx.cluster_info.cluster_version_update();
+        info!(
+            "{} resets the members to the ones of a snapshot at conf change entry {index}",
+            self.id()
+        );
+    }
+
     /// Fallback conf change
     pub(super) fn fallback_conf_change(
         &self,
@@ -1581,6 +2419,8 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         assert_ne!(prev_role, Role::Leader, "leader can't start election");
 
         st.term += 1;
+        self.ctx.role_change.on_term_change(st.term);
+        self.set_pool_generation(st.term);
         st.role = Role::Candidate;
         st.voted_for = Some(self.id());
         st.leader_id = None;
@@ -1647,6 +2487,8 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             metrics::get().leader_changes.add(1, &[]);
         }
         st.term = term;
+        self.ctx.role_change.on_term_change(term);
+        self.set_pool_generation(term);
         self.lst.reset_transferee();
         st.role = Role::Follower;
         st.voted_for = None;
@@ -1734,7 +2576,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             let _ig_spec = sp_l.insert(entry.clone()); // may have been inserted before
             #[allow(clippy::expect_used)]
             let entry = log
-                .push(term, entry.id, entry.inner)
+                .push(term, entry.id, self.cmd_entry_data(entry.inner))
                 .expect("cmd {cmd:?} cannot be serialized");
             debug!(
                 "{} recovers speculatively executed cmd({}) in log[{}]",
@@ -1755,13 +2597,23 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             });
             let propose_id = entry.propose_id;
             match entry.entry_data {
-                EntryData::Command(ref cmd) => {
+                EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) => {
                     let _ignore = ucp_l.insert(PoolEntry::new(propose_id, Arc::clone(cmd)));
                 }
                 EntryData::ConfChange(ref conf_change) => {
                     let _ignore = ucp_l.insert(PoolEntry::new(propose_id, conf_change.clone()));
                 }
-                EntryData::Shutdown | EntryData::Empty | EntryData::SetNodeState(_, _, _) => {}
+                EntryData::Commands(ref cmds) | EntryData::MetaCommands(ref cmds) => {
+                    for &(id, ref cmd) in cmds {
+                        let _ignore = ucp_l.insert(PoolEntry::new(id, Arc::clone(cmd)));
+                    }
+                }
+                EntryData::Shutdown
+                | EntryData::Empty
+                | EntryData::SetNodeState(_, _, _)
+                | EntryData::SetClusterVersion(_)
+                | EntryData::ExpireSessions(_)
+                | EntryData::Cancel(_) => {}
             }
         }
     }
@@ -1769,13 +2621,83 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
     /// Apply new logs
     fn apply(&self, log: &mut Log<C>) {
         for i in (log.last_as + 1)..=log.commit_index {
-            let entry = log.get(i).unwrap_or_else(|| {
-                unreachable!(
-                    "system corrupted, apply log[{i}] when we only have {} log entries",
-                    log.last_log_index()
-                )
-            });
-            self.ctx.cmd_tx.send_after_sync(Arc::clone(entry));
+            let entries = log
+                .get(i)
+                .unwrap_or_else(|| {
+                    unreachable!(
+                        "system corrupted, apply log[{i}] when we only have {} log entries",
+                        log.last_log_index()
+                    )
+                })
+                .unpack();
+            let applied_in_batch = self.ctx.applied_in_batches.lock().remove(&i);
+            let mut to_sync = Vec::with_capacity(entries.len());
+            for entry in entries {
+                // the commands of a batch applied before a restart are not applied again
+                if let (Some(batch), Some(applied)) = (entry.batch, applied_in_batch) {
+                    if batch.pos <= applied {
+                        if let EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) =
+                            entry.entry_data
+                        {
+                            self.ctx
+                                .spec_pool
+                                .lock()
+                                .remove(PoolEntry::new(entry.propose_id, Arc::clone(cmd)));
+                            self.ctx
+                                .uncommitted_pool
+                                .lock()
+                                .remove(PoolEntry::new(entry.propose_id, Arc::clone(cmd)));
+                        }
+                        continue;
+                    }
+                }
+                if entry.is_empty() {
+                    self.retire_pool_generations(entry.term, &log.get_cmd_ids());
+                }
+                if let EntryData::Cancel(id) = entry.entry_data {
+                    self.commit_cancel(entry.propose_id, id);
+                }
+                if let EntryData::Command(ref cmd) | EntryData::MetaCommand(ref cmd) =
+                    entry.entry_data
+                {
+                    // a cmd appended again after its cancel is committed is never applied
+                    if self.ctx.cb.read().is_canceled(entry.propose_id) {
+                        debug!(
+                            "{} skips canceled cmd({}) in log[{i}]",
+                            self.id(),
+                            entry.propose_id
+                        );
+                        self.ctx
+                            .spec_pool
+                            .lock()
+                            .remove(PoolEntry::new(entry.propose_id, Arc::clone(cmd)));
+                        self.ctx
+                            .uncommitted_pool
+                            .lock()
+                            .remove(PoolEntry::new(entry.propose_id, Arc::clone(cmd)));
+                        continue;
+                    }
+                    // recorded so that a retry is not appended again, the entry is applied
+                    // anyway, a replica recovered from an older snapshot may not know it
+                    let _ignore = self.ctx.cb.write().record_applied(
+                        entry.propose_id,
+                        i,
+                        self.cfg().result_cache.dedup_window,
+                    );
+                    self.ctx
+                        .cb
+                        .write()
+                        .record_commit(entry.propose_id, tokio::time::Instant::now());
+                }
+                to_sync.push(entry);
+            }
+            // the skipped commands of a batch can't complete it
+            if let Some(last) = to_sync.pop() {
+                to_sync.push(last.mark_last_in_batch());
+            }
+            for entry in to_sync {
+                self.ctx.cmd_tx.send_after_sync(entry);
+            }
             log.last_as = i;
             if log.last_exe < log.last_as {
                 log.last_exe = log.last_as;
@@ -1787,9 +2709,44 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
                 i
             );
         }
+        self.prune_membership_history(log.last_as);
         log.compact();
     }
 
+    /// The cancel entry of a cmd is committed, its speculative copy is dropped so the next
+    /// leader won't recover it
+    fn commit_cancel(&self, entry_id: ProposeId, id: ProposeId) {
+        let _ignore = self
+            .ctx
+            .spec_pool
+            .map_lock(|mut sp_l| sp_l.remove_by_id(id));
+        let _ignore = self
+            .ctx
+            .uncommitted_pool
+            .map_lock(|mut ucp_l| ucp_l.remove_by_id(id));
+        self.ctx
+            .cb
+            .map_write(|mut cb_w| cb_w.commit_cancel(entry_id, id));
+        debug!("{} committed the cancel of cmd({id})", self.id());
+    }
+
+    /// Tags the entries inserted into the conflict pools from now on with the term
+    fn set_pool_generation(&self, term: u64) {
+        self.ctx.spec_pool.lock().set_generation(term);
+        self.ctx.uncommitted_pool.lock().set_generation(term);
+    }
+
+    /// The no-op of the term is committed, the entries of the former terms absent from
+    /// the log will never complete, so they are stale in the conflict pools
+    fn retire_pool_generations(&self, term: u64, in_log: &HashSet<ProposeId>) {
+        self.ctx.spec_pool.lock().retire_generations(term, in_log);
+        self.ctx
+            .uncommitted_pool
+            .lock()
+            .retire_generations(term, in_log);
+        let _ignore = self.ctx.pools_retired_event.notify(usize::MAX);
+    }
+
     /// When leader retires, it should reset state
     fn leader_retires(&self) {
         debug!("leader {} retires", self.id());
@@ -1876,6 +2833,105 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         fallback_info
     }
 
+    /// Drop the pending commands that will never be appended, along with their entries
+    /// in the conflict pools
+    fn drop_pending(&self, dropped: Vec<PendingCommand<C>>) {
+        if dropped.is_empty() {
+            return;
+        }
+        debug!("{} drops {} pending cmds", self.id(), dropped.len());
+        let mut sp_l = self.ctx.spec_pool.lock();
+        let mut ucp_l = self.ctx.uncommitted_pool.lock();
+        for p in dropped {
+            let _ignore = sp_l.remove_by_id(p.propose_id);
+            ucp_l.remove(PoolEntry::new(p.propose_id, p.cmd));
+        }
+    }
+
+    /// Entry data of the commands to append, they carry their conflict metadata once all
+    /// members can decode it
+    fn cmd_entry_data(&self, data: impl Into<EntryData<C>>) -> EntryData<C> {
+        let data = data.into();
+        if self.cluster().feature_enabled(Feature::ConflictMeta) {
+            data.with_conflict_meta()
+        } else {
+            data
+        }
+    }
+
+    /// Append the pending commands as a single log entry, a lone command is appended as a
+    /// plain command entry
+    fn flush_batch(
+        &self,
+        log_w: &mut RwLockWriteGuard<'_, Log<C>>,
+        term: u64,
+    ) -> Result<(), CurpError> {
+        let stale = log_w.take_stale_pending(term);
+        self.drop_pending(stale);
+        let pending = log_w.take_pending();
+        if !self.cluster().feature_enabled(Feature::BatchedEntries) {
+            // some members can't apply batched entries, append the commands one by one
+            for p in pending {
+                let data = self.cmd_entry_data(p.cmd);
+                let entry = log_w.push(term, p.propose_id, data).map_err(|e| {
+                    metrics::get()
+                        .proposals_failed
+                        .add(1, &[KeyValue::new("reason", "log serialize failed")]);
+                    e
+                })?;
+                self.entry_process(log_w, entry, p.conflict, term);
+            }
+            return Ok(());
+        }
+        let Some(first) = pending.first() else {
+            return Ok(());
+        };
+        let propose_id = first.propose_id;
+        let entry = if pending.len() == 1 {
+            log_w.push(
+                term,
+                propose_id,
+                self.cmd_entry_data(Arc::clone(&first.cmd)),
+            )
+        } else {
+            let cmds: Vec<_> = pending
+                .iter()
+                .map(|p| (p.propose_id, Arc::clone(&p.cmd)))
+                .collect();
+            log_w.push(term, propose_id, self.cmd_entry_data(cmds))
+        }
+        .map_err(|e| {
+            metrics::get()
+                .proposals_failed
+                .add(1, &[KeyValue::new("reason", "log serialize failed")]);
+            e
+        })?;
+        debug!(
+            "{} gets new log[{}] of {} batched cmds",
+            self.id(),
+            entry.index,
+            pending.len()
+        );
+        metrics::get()
+            .batched_commands
+            .record(pending.len().numeric_cast(), &[]);
+
+        // every command in the batch is speculatively executed on its own, conflicting
+        // ones are left to the after sync
+        let mut any_exe = false;
+        for (e, p) in entry.unpack().into_iter().zip(pending) {
+            if !p.conflict {
+                any_exe = true;
+                self.ctx.cmd_tx.send_sp_exe(e);
+            }
+        }
+        if any_exe {
+            log_w.last_exe = entry.index;
+        }
+        self.entry_process(log_w, entry, true, term);
+        Ok(())
+    }
+
     /// Entry process shared by `handle_xxx`
     fn entry_process(
         &self,
@@ -1,8 +1,10 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
 };
 
+use clippy_utilities::OverflowArithmetic;
 use dashmap::{
     mapref::{
         multiple::RefMulti,
@@ -53,6 +55,67 @@ pub(super) struct CandidateState<C> {
     pub(super) votes_received: HashMap<ServerId, bool>,
 }
 
+/// Consecutive append entries timeouts after which a follower is degraded
+pub(super) const DEGRADE_AFTER_TIMEOUTS: u32 = 3;
+
+/// Adaptive limit of the entries sent to a follower in an append entries
+///
+/// It's halved on each timeout down to a single entry, the probe mode, and doubled on
+/// each acknowledged append entries until it's lifted again, so a follower on a slow
+/// disk doesn't keep the leader encoding and resending huge batches.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(super) struct ReplicationWindow {
+    /// Max number of entries of an append entries, `None` if only the batch size
+    /// limits it
+    limit: Option<usize>,
+    /// Number of entries sent when the window started shrinking, the window is lifted
+    /// once it grows back to it
+    full: usize,
+    /// Consecutive timeouts of the append entries
+    timeouts: u32,
+    /// Whether the follower timed out repeatedly and isn't recovered yet
+    degraded: bool,
+}
+
+impl ReplicationWindow {
+    /// Max number of entries of an append entries, `None` if it's not limited
+    pub(super) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Whether the follower is degraded
+    pub(super) fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Shrink the window after an append entries of `sent` entries timed out
+    pub(super) fn on_timeout(&mut self, sent: usize) {
+        self.timeouts = self.timeouts.saturating_add(1);
+        let current = self.limit.unwrap_or_else(|| {
+            self.full = sent;
+            sent
+        });
+        self.limit = Some(current.overflow_div(2).max(1));
+        if self.timeouts >= DEGRADE_AFTER_TIMEOUTS {
+            self.degraded = true;
+        }
+    }
+
+    /// Grow the window after an append entries with entries is acknowledged
+    pub(super) fn on_success(&mut self) {
+        self.timeouts = 0;
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let grown = limit.saturating_mul(2);
+        if grown >= self.full {
+            *self = Self::default();
+        } else {
+            self.limit = Some(grown);
+        }
+    }
+}
+
 /// Status of a follower
 #[derive(Debug, Copy, Clone)]
 pub(super) struct FollowerStatus {
@@ -62,6 +125,13 @@ pub(super) struct FollowerStatus {
     pub(super) match_index: LogIndex,
     /// This node is a learner or not
     pub(super) is_learner: bool,
+    /// When the latest append entries acknowledged by the follower was sent
+    pub(super) acked_at: Option<Instant>,
+    /// Server version reported by the follower, 0 until it answers or if it's older
+    /// than the versioning
+    pub(super) server_version: u32,
+    /// Limit of the entries sent to that follower in an append entries
+    pub(super) window: ReplicationWindow,
 }
 
 impl Default for FollowerStatus {
@@ -70,6 +140,9 @@ impl Default for FollowerStatus {
             next_index: 1,
             match_index: 0,
             is_learner: false,
+            acked_at: None,
+            server_version: 0,
+            window: ReplicationWindow::default(),
         }
     }
 }
@@ -81,6 +154,9 @@ impl FollowerStatus {
             next_index,
             match_index,
             is_learner,
+            acked_at: None,
+            server_version: 0,
+            window: ReplicationWindow::default(),
         }
     }
 }
@@ -196,6 +272,56 @@ impl LeaderState {
         debug!("follower {id}'s match_index updated to {index}");
     }
 
+    /// Update the time when the latest append entries acknowledged by the server was sent
+    pub(super) fn update_acked_at(&self, id: ServerId, sent_at: Instant) {
+        let Some(mut status) = self.get_status_mut(id) else {
+            return;
+        };
+        if status.acked_at.map_or(true, |acked_at| acked_at < sent_at) {
+            status.acked_at = Some(sent_at);
+        }
+    }
+
+    /// Update the server version reported by the server, return the previous one
+    pub(super) fn update_server_version(&self, id: ServerId, server_version: u32) -> Option<u32> {
+        let mut status = self.get_status_mut(id)?;
+        Some(std::mem::replace(
+            &mut status.server_version,
+            server_version,
+        ))
+    }
+
+    /// Get the server version reported by the server
+    pub(super) fn get_server_version(&self, id: ServerId) -> Option<u32> {
+        self.get_status(id).map(|s| s.server_version)
+    }
+
+    /// Get the replication window of the server
+    pub(super) fn get_window(&self, id: ServerId) -> Option<ReplicationWindow> {
+        self.get_status(id).map(|s| s.window)
+    }
+
+    /// Update the replication window of the server, return whether it becomes degraded
+    /// or recovers by the update
+    pub(super) fn update_window(
+        &self,
+        id: ServerId,
+        f: impl FnOnce(&mut ReplicationWindow),
+    ) -> bool {
+        let Some(mut status) = self.get_status_mut(id) else {
+            return false;
+        };
+        let was_degraded = status.window.is_degraded();
+        f(&mut status.window);
+        status.window.is_degraded() != was_degraded
+    }
+
+    /// The minimum server version reported by the followers, `None` if there's no
+    /// follower
+    pub(super) fn min_server_version(&self) -> Option<u32> {
+        self.statuses.iter().map(|s| s.server_version).min()
+    }
+
     /// Create a `Iterator` for all statuses
     pub(super) fn iter(&self) -> impl Iterator<Item = RefMulti<'_, ServerId, FollowerStatus>> {
         self.statuses.iter()
@@ -361,6 +487,7 @@ pub(super) enum VoteResult {
 #[cfg(test)]
 mod test {
 
+    use clippy_utilities::NumericCast;
     use curp_test_utils::test_cmd::TestCommand;
 
     use super::*;
@@ -381,4 +508,38 @@ mod test {
         cst.votes_received = HashMap::from([(1, true), (2, true), (3, false), (4, false)]);
         assert_eq!(cst.check_vote(), VoteResult::Pending);
     }
+
+    #[test]
+    fn replication_window_should_shrink_to_probe_and_recover_gradually() {
+        let mut window = ReplicationWindow::default();
+        let mut limits = vec![];
+        for _ in 0..8 {
+            window.on_timeout(window.limit().unwrap_or(100));
+            limits.push(window.limit().unwrap());
+            assert_eq!(
+                window.is_degraded(),
+                limits.len() >= DEGRADE_AFTER_TIMEOUTS.numeric_cast()
+            );
+        }
+        assert_eq!(limits, [50, 25, 12, 6, 3, 1, 1, 1]);
+
+        let mut limits = vec![];
+        while let Some(limit) = window.limit() {
+            assert!(window.is_degraded());
+            limits.push(limit);
+            window.on_success();
+        }
+        assert_eq!(limits, [1, 2, 4, 8, 16, 32, 64]);
+        assert_eq!(window, ReplicationWindow::default());
+    }
+
+    #[test]
+    fn replication_window_should_not_degrade_on_sporadic_timeouts() {
+        let mut window = ReplicationWindow::default();
+        for _ in 0..10 {
+            window.on_timeout(window.limit().unwrap_or(100));
+            window.on_success();
+        }
+        assert!(!window.is_degraded());
+    }
 }
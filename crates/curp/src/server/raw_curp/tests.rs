@@ -1,6 +1,10 @@
 use std::{cmp::Reverse, ops::Add, time::Duration};
 
-use curp_test_utils::{mock_role_change, test_cmd::TestCommand, TestRoleChange, TEST_CLIENT_ID};
+use curp_test_utils::{
+    mock_role_change,
+    test_cmd::{LogIndexResult, TestCommand, TestCommandResult},
+    TestRoleChange, TEST_CLIENT_ID,
+};
 use test_macros::abort_on_panic;
 use tokio::{
     sync::oneshot,
@@ -9,11 +13,12 @@ use tokio::{
 use tracing_test::traced_test;
 use utils::config::{
     default_candidate_timeout_ticks, default_follower_timeout_ticks, default_heartbeat_interval,
-    CurpConfigBuilder,
+    CurpConfigBuilder, ResultCacheConfig,
 };
 
 use super::*;
 use crate::{
+    cmd::AppliedProposal,
     rpc::{connect::MockInnerConnectApi, Redirect},
     server::{
         cmd_board::CommandBoard,
@@ -37,17 +42,34 @@ impl RawCurp<TestCommand, TestRoleChange> {
             && self.cst.lock().config.contains(id)
     }
 
-    #[allow(clippy::mem_forget)] // we should prevent the channel from being dropped
     pub(crate) fn new_test<Tx: CEEventTxApi<TestCommand>>(
         n: u64,
         exe_tx: Tx,
         role_change: TestRoleChange,
         task_manager: Arc<TaskManager>,
+    ) -> Self {
+        let curp_config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .build()
+            .unwrap();
+        Self::new_test_with_config(n, exe_tx, role_change, task_manager, curp_config)
+    }
+
+    /// Build a test curp with the given config
+    #[allow(clippy::mem_forget)] // we should prevent the channel from being dropped
+    pub(crate) fn new_test_with_config<Tx: CEEventTxApi<TestCommand>>(
+        n: u64,
+        exe_tx: Tx,
+        role_change: TestRoleChange,
+        task_manager: Arc<TaskManager>,
+        curp_config: CurpConfig,
     ) -> Self {
         let all_members: HashMap<_, _> = (0..n)
             .map(|i| (format!("S{i}"), vec![format!("S{i}")]))
             .collect();
         let cluster_info = Arc::new(ClusterInfo::from_members_map(all_members, [], "S0"));
+        // as if the cluster server version has been negotiated
+        cluster_info.set_cluster_server_version(SERVER_VERSION);
         let cmd_board = Arc::new(RwLock::new(CommandBoard::new()));
         let lease_manager = Arc::new(RwLock::new(LeaseManager::new()));
         let (log_tx, log_rx) = mpsc::unbounded_channel();
@@ -68,11 +90,7 @@ impl RawCurp<TestCommand, TestRoleChange> {
                 )
             })
             .collect();
-        let curp_config = CurpConfigBuilder::default()
-            .log_entries_cap(10)
-            .build()
-            .unwrap();
-        let curp_storage = Arc::new(DB::open(&curp_config.engine_cfg).unwrap());
+        let curp_storage = Arc::new(DB::open(&curp_config).unwrap());
 
         // grant a infinity expiry lease for test client id
         lease_manager.write().expiry_queue.push(
@@ -194,6 +212,527 @@ fn leader_handle_propose_will_reject_duplicated() {
     assert!(matches!(res, Err(CurpError::Duplicated(()))));
 }
 
+#[traced_test]
+#[test]
+fn leader_will_apply_retried_cmd_once() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(1).returning(|_| {});
+        exe_tx.expect_send_after_sync().times(1).returning(|_| {});
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    let cmd = Arc::new(TestCommand::new_put(vec![1], 1));
+    assert!(curp.handle_propose(id, Arc::clone(&cmd)).unwrap());
+    assert!(curp
+        .handle_append_entries_resp(s1_id, Some(1), 0, true, 2)
+        .unwrap());
+
+    // the new leader has not seen the proposal, but the retry is not appended again
+    curp.ctx.cb.write().sync.clear();
+    let res = curp.handle_propose(id, cmd);
+    assert!(matches!(res, Err(CurpError::KeyConflict(()))));
+    assert_eq!(curp.log.read().last_log_index(), 1);
+}
+
+#[traced_test]
+#[test]
+fn leader_will_not_append_retried_cmd_in_uncommitted_entries() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(1).returning(|_| {});
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    let cmd = Arc::new(TestCommand::new_put(vec![1], 1));
+    assert!(curp.handle_propose(id, Arc::clone(&cmd)).unwrap());
+
+    curp.ctx.cb.write().sync.clear();
+    let res = curp.handle_propose(id, cmd);
+    assert!(matches!(res, Err(CurpError::KeyConflict(()))));
+    assert_eq!(curp.log.read().last_log_index(), 1);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn leader_will_answer_retried_cmd_restored_from_storage_with_cached_result() {
+    let task_manager = Arc::new(TaskManager::new());
+    // neither executed nor after synced again
+    let curp = RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    );
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    let er = TestCommandResult::new(vec![], vec![1]);
+    // the cmd was applied in log[3] before the restart
+    curp.ctx
+        .cb
+        .write()
+        .restore_applied(vec![AppliedProposal::new(
+            3,
+            (TEST_CLIENT_ID, 0),
+            Some(er.clone()),
+            Ok(LogIndexResult::from(3)),
+        )]);
+
+    let cmd = Arc::new(TestCommand::new_put(vec![1], 1));
+    assert!(curp.handle_propose(id, cmd).unwrap());
+    assert_eq!(curp.log.read().last_log_index(), 0);
+    let cached = CommandBoard::wait_for_er(&curp.ctx.cb, id).await.unwrap();
+    assert_eq!(cached.unwrap(), er);
+    assert!(curp.ctx.cb.read().asr_buffer.get(&id).unwrap().is_ok());
+}
+
+#[traced_test]
+#[test]
+fn leader_will_batch_cmds_into_one_entry() {
+    let task_manager = Arc::new(TaskManager::new());
+    let applied = Arc::new(Mutex::new(vec![]));
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(2).returning(|_| {});
+        let applied_c = Arc::clone(&applied);
+        exe_tx
+            .expect_send_after_sync()
+            .times(3)
+            .returning(move |e| applied_c.lock().push((e.index, e.propose_id)));
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let ids = [0, 1, 2].map(|seq| ProposeId(TEST_CLIENT_ID, seq));
+
+    assert!(curp
+        .handle_propose(ids[0], Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+    assert!(curp
+        .handle_propose(ids[1], Arc::new(TestCommand::new_put(vec![2], 2)))
+        .unwrap());
+    assert_eq!(curp.log.read().last_log_index(), 0);
+    // conflicts with the first one, it's batched but not speculatively executed
+    let res = curp.handle_propose(ids[2], Arc::new(TestCommand::new_put(vec![1], 3)));
+    assert!(matches!(res, Err(CurpError::KeyConflict(()))));
+    assert_eq!(curp.log.read().last_log_index(), 1);
+    assert_eq!(curp.log.read().get(1).unwrap().kind(), "Commands");
+
+    assert!(curp
+        .handle_append_entries_resp(s1_id, Some(1), 0, true, 2)
+        .unwrap());
+    assert_eq!(*applied.lock(), ids.map(|id| (1, id)));
+}
+
+#[traced_test]
+#[test]
+fn batched_cmds_applied_before_restart_will_be_skipped() {
+    let task_manager = Arc::new(TaskManager::new());
+    let applied = Arc::new(Mutex::new(vec![]));
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().returning(|_| {});
+        let applied_c = Arc::clone(&applied);
+        exe_tx
+            .expect_send_after_sync()
+            .returning(move |e| applied_c.lock().push((e.propose_id, e.applied_index())));
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let ids = [0, 1, 2].map(|seq| ProposeId(TEST_CLIENT_ID, seq));
+    for (id, key) in ids.into_iter().zip(1..) {
+        assert!(curp
+            .handle_propose(id, Arc::new(TestCommand::new_put(vec![key], key)))
+            .unwrap());
+    }
+    assert_eq!(curp.log.read().last_log_index(), 1);
+    // the first command was applied before a restart
+    let _ignore = curp.ctx.applied_in_batches.lock().insert(1, 0);
+
+    assert!(curp
+        .handle_append_entries_resp(s1_id, Some(1), 0, true, 2)
+        .unwrap());
+    // the batch is applied only after its last command
+    assert_eq!(*applied.lock(), vec![(ids[1], 0), (ids[2], 1)]);
+    assert!(curp.ctx.applied_in_batches.lock().is_empty());
+}
+
+#[traced_test]
+#[test]
+fn retired_leader_will_drop_pending_cmds() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    assert!(curp
+        .handle_propose(
+            ProposeId(TEST_CLIENT_ID, 0),
+            Arc::new(TestCommand::new_put(vec![1], 1))
+        )
+        .unwrap());
+    assert_eq!(curp.ctx.spec_pool.lock().len(), 1);
+
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 2);
+    curp.handle_batch_timeout();
+    assert!(curp.ctx.spec_pool.lock().all().is_empty());
+    assert!(curp.ctx.uncommitted_pool.lock().all().is_empty());
+    assert_eq!(curp.log.read().last_log_index(), 0);
+}
+
+#[traced_test]
+#[test]
+fn leader_will_cancel_cmds_not_appended_yet() {
+    let task_manager = Arc::new(TaskManager::new());
+    let executed = Arc::new(Mutex::new(vec![]));
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        let executed_c = Arc::clone(&executed);
+        exe_tx
+            .expect_send_sp_exe()
+            .returning(move |e| executed_c.lock().push(e.propose_id));
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    let ids = [0, 1, 2].map(|seq| ProposeId(TEST_CLIENT_ID, seq));
+
+    // canceled while it's waiting in the pending batch
+    assert!(curp
+        .handle_propose(ids[0], Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+    assert!(curp.handle_cancel(ids[0]).unwrap().is_some());
+    assert!(curp.ctx.spec_pool.lock().all().is_empty());
+    assert!(curp.ctx.uncommitted_pool.lock().all().is_empty());
+
+    // canceled before the proposal arrives, e.g. the proposal is delayed on the way
+    assert!(curp.handle_cancel(ids[1]).unwrap().is_some());
+    let res = curp.handle_propose(ids[1], Arc::new(TestCommand::new_put(vec![1], 2)));
+    assert!(matches!(res, Err(CurpError::Canceled(()))));
+    assert!(curp.ctx.spec_pool.lock().all().is_empty());
+    assert!(curp.ctx.uncommitted_pool.lock().all().is_empty());
+
+    // the keys of the canceled cmds are free
+    assert!(curp
+        .handle_propose(ids[2], Arc::new(TestCommand::new_put(vec![1], 3)))
+        .unwrap());
+    curp.handle_batch_timeout();
+    let log_r = curp.log.read();
+    assert_eq!(log_r.last_log_index(), 3);
+    assert_eq!(log_r.get(1).unwrap().kind(), "Cancel");
+    assert_eq!(log_r.get(2).unwrap().kind(), "Cancel");
+    assert_eq!(log_r.get(3).unwrap().propose_id, ids[2]);
+    assert_eq!(*executed.lock(), vec![ids[2]]);
+}
+
+#[traced_test]
+#[test]
+fn leader_will_not_cancel_appended_cmds() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(1).returning(|_| {});
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    assert!(curp
+        .handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+    assert!(curp.handle_cancel(id).unwrap().is_none());
+    assert_eq!(curp.log.read().last_log_index(), 1);
+    assert!(!curp.ctx.cb.read().is_canceled(id));
+}
+
+#[traced_test]
+#[test]
+fn leader_will_confirm_cancel_once_committed() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_after_sync().times(1).returning(|_| {});
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+
+    let mut committed = curp.handle_cancel(id).unwrap().unwrap();
+    assert!(committed.try_recv().is_err());
+    assert!(curp
+        .handle_append_entries_resp(s1_id, Some(1), 0, true, 2)
+        .unwrap());
+    assert!(committed.try_recv().is_ok());
+
+    let res = curp.handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)));
+    assert!(matches!(res, Err(CurpError::Canceled(()))));
+}
+
+#[traced_test]
+#[test]
+fn overwritten_speculative_cmds_will_be_discarded() {
+    let task_manager = Arc::new(TaskManager::new());
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(1).returning(|_| {});
+        exe_tx
+            .expect_send_discard()
+            .times(1)
+            .withf(move |entries| entries.len() == 1 && entries[0].propose_id == id)
+            .returning(|_| {});
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    assert!(curp
+        .handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+
+    // the new leader never got the entry, and overwrites it with its no-op
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let noop = LogEntry::new(1, 2, ProposeId(TEST_CLIENT_ID, 100), EntryData::Empty);
+    let result = curp.handle_append_entries(2, s2_id, 0, 0, vec![noop], 0);
+    assert_eq!(result, Ok(2));
+    assert_eq!(curp.log.read().last_exe, 0);
+}
+
+#[traced_test]
+#[test]
+fn follower_will_drop_speculative_cmd_once_the_cancel_is_committed() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        exe_tx.expect_send_after_sync().times(1).returning(|_| {});
+        Arc::new(RawCurp::new_test(
+            3,
+            exe_tx,
+            mock_role_change(),
+            task_manager,
+        ))
+    };
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    assert!(!curp
+        .handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+    let res = curp.handle_cancel(id);
+    assert!(matches!(res, Err(CurpError::Redirect(_))));
+    // the leader has not decided yet
+    assert_eq!(curp.ctx.spec_pool.lock().len(), 1);
+
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let cancel = LogEntry::new(1, 1, ProposeId(TEST_CLIENT_ID, 100), EntryData::Cancel(id));
+    let result = curp.handle_append_entries(1, s2_id, 0, 0, vec![cancel], 1);
+    assert!(result.is_ok());
+    assert!(curp.ctx.spec_pool.lock().all().is_empty());
+    assert!(curp.ctx.cb.read().is_canceled(id));
+}
+
+#[traced_test]
+#[test]
+fn leader_will_append_pending_cmds_before_other_entries() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().times(2).returning(|_| {});
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+
+    assert!(curp
+        .handle_propose(
+            ProposeId(TEST_CLIENT_ID, 0),
+            Arc::new(TestCommand::new_put(vec![1], 1))
+        )
+        .unwrap());
+    curp.handle_batch_timeout();
+    assert_eq!(curp.log.read().get(1).unwrap().kind(), "Command");
+
+    assert!(curp
+        .handle_propose(
+            ProposeId(TEST_CLIENT_ID, 1),
+            Arc::new(TestCommand::new_put(vec![2], 2))
+        )
+        .unwrap());
+    curp.handle_shutdown(ProposeId(TEST_CLIENT_ID, 2)).unwrap();
+    let log_r = curp.log.read();
+    assert_eq!(log_r.get(2).unwrap().kind(), "Command");
+    assert_eq!(log_r.get(3).unwrap().kind(), "Shutdown");
+}
+
+#[traced_test]
+#[test]
+fn leader_will_not_batch_cmds_until_all_members_support_it() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().returning(|_| {});
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .propose_batch_max_size(3)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    // the cluster was running an old version
+    curp.cluster().set_cluster_server_version(0);
+    curp.handle_server_version(s1_id, SERVER_VERSION);
+    curp.handle_server_version(s2_id, 0);
+    assert_eq!(curp.log.read().last_log_index(), 0);
+    assert!(!curp.cluster().feature_enabled(Feature::BatchedEntries));
+
+    for seq in [0_u32, 1] {
+        assert!(curp
+            .handle_propose(
+                ProposeId(TEST_CLIENT_ID, seq.into()),
+                Arc::new(TestCommand::new_put(vec![seq], seq))
+            )
+            .unwrap());
+    }
+    curp.handle_batch_timeout();
+    {
+        let log_r = curp.log.read();
+        assert_eq!(log_r.get(1).unwrap().kind(), "Command");
+        assert_eq!(log_r.get(2).unwrap().kind(), "Command");
+        // old members can't decode the conflict metadata
+        assert!(matches!(
+            log_r.get(1).unwrap().entry_data,
+            EntryData::Command(_)
+        ));
+    }
+
+    // the old member is upgraded, the new version is appended only once
+    curp.handle_server_version(s2_id, SERVER_VERSION);
+    curp.handle_server_version(s2_id, SERVER_VERSION);
+    assert_eq!(curp.log.read().last_log_index(), 3);
+    assert_eq!(
+        curp.log.read().get(3).unwrap().entry_data,
+        EntryData::SetClusterVersion(SERVER_VERSION)
+    );
+
+    curp.set_cluster_server_version(SERVER_VERSION);
+    assert!(curp.cluster().feature_enabled(Feature::BatchedEntries));
+    for seq in [2_u32, 3] {
+        assert!(curp
+            .handle_propose(
+                ProposeId(TEST_CLIENT_ID, seq.into()),
+                Arc::new(TestCommand::new_put(vec![seq], seq))
+            )
+            .unwrap());
+    }
+    curp.handle_batch_timeout();
+    assert_eq!(curp.log.read().get(4).unwrap().kind(), "Commands");
+    assert!(matches!(
+        curp.log.read().get(4).unwrap().entry_data,
+        EntryData::MetaCommands(_)
+    ));
+}
+
+#[traced_test]
+#[test]
+fn single_leader_will_append_the_bootstrap_server_version() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_after_sync().times(1).returning(|_| {});
+        RawCurp::new_test(1, exe_tx, mock_role_change(), task_manager)
+    };
+    // a new cluster starts without any version
+    curp.cluster().set_cluster_server_version(0);
+
+    curp.negotiate_cluster_server_version();
+    curp.negotiate_cluster_server_version();
+    assert_eq!(curp.log.read().last_log_index(), 1);
+    assert_eq!(
+        curp.log.read().get(1).unwrap().entry_data,
+        EntryData::SetClusterVersion(SERVER_VERSION)
+    );
+    assert!(!curp.set_cluster_server_version(SERVER_VERSION + 1));
+    assert!(curp.set_cluster_server_version(SERVER_VERSION));
+}
+
+#[traced_test]
+#[test]
+fn leader_will_expire_idle_sessions_through_log() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().returning(|_| {});
+        let config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .result_cache(ResultCacheConfig {
+                session_ttl: Duration::from_millis(50),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, config)
+    };
+    let id = ProposeId(TEST_CLIENT_ID, 0);
+    assert!(curp
+        .handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)))
+        .unwrap());
+    // the session is still active
+    curp.handle_session_expiry();
+    assert_eq!(curp.log.read().last_log_index(), 1);
+
+    std::thread::sleep(Duration::from_millis(100));
+    curp.handle_session_expiry();
+    assert_eq!(
+        curp.log.read().get(2).unwrap().entry_data,
+        EntryData::ExpireSessions(vec![(TEST_CLIENT_ID, 0)])
+    );
+    // the sessions are not expired again before the entry is applied
+    curp.handle_session_expiry();
+    assert_eq!(curp.log.read().last_log_index(), 2);
+
+    curp.expire_sessions(&[(TEST_CLIENT_ID, 0)]);
+    assert_eq!(curp.ctx.cb.read().results_occupancy(), (0, 0));
+    assert_eq!(
+        curp.handle_propose(id, Arc::new(TestCommand::new_put(vec![1], 1)))
+            .unwrap_err(),
+        CurpError::session_expired()
+    );
+    // new proposals of the client start a new session
+    assert!(curp
+        .handle_propose(
+            ProposeId(TEST_CLIENT_ID, 1),
+            Arc::new(TestCommand::new_put(vec![2], 2))
+        )
+        .unwrap());
+}
+
 #[traced_test]
 #[test]
 fn follower_handle_propose_will_succeed() {
@@ -368,6 +907,72 @@ fn handle_ae_will_reject_wrong_term() {
     assert_eq!(result.unwrap_err().0, 1);
 }
 
+#[traced_test]
+#[test]
+fn follower_will_retire_stale_pool_entries_after_the_noop_is_committed() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        exe_tx.expect_send_after_sync().times(2).returning(|_| {});
+        Arc::new(RawCurp::new_test(
+            3,
+            exe_tx,
+            mock_role_change(),
+            task_manager,
+        ))
+    };
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+    curp.ctx.spec_pool.map_lock(|mut sp| {
+        for i in 0..10 {
+            let cmd = Arc::new(TestCommand::new_put(vec![i], i));
+            assert!(sp
+                .insert(PoolEntry::new(ProposeId(TEST_CLIENT_ID, i.into()), cmd))
+                .is_none());
+        }
+    });
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let conflicting = |seq| {
+        PoolEntry::new(
+            ProposeId(TEST_CLIENT_ID, seq),
+            Arc::new(TestCommand::new_put(vec![0, 1], 10)),
+        )
+    };
+
+    // hearing from the new leader retires nothing, the entries may still be recovered
+    let result = curp.handle_append_entries(2, s2_id, 0, 0, vec![], 0);
+    assert!(result.is_ok());
+    curp.ctx
+        .spec_pool
+        .map_lock(|mut sp| assert!(sp.insert(conflicting(10)).is_some()));
+
+    // the new leader recovers cmd 1 before its no-op
+    let recovered = LogEntry::new(
+        1,
+        2,
+        ProposeId(TEST_CLIENT_ID, 1),
+        Arc::new(TestCommand::new_put(vec![1], 1)),
+    );
+    let noop = LogEntry::new(2, 2, ProposeId(TEST_CLIENT_ID, 100), EntryData::Empty);
+    let result = curp.handle_append_entries(2, s2_id, 0, 0, vec![recovered, noop], 2);
+    assert!(result.is_ok());
+
+    curp.ctx.spec_pool.map_lock(|mut sp| {
+        // cmd 1 is in the log, it keeps conflicting until it's applied
+        assert!(sp.insert(conflicting(11)).is_some());
+        assert!(sp
+            .insert(PoolEntry::new(
+                ProposeId(TEST_CLIENT_ID, 12),
+                Arc::new(TestCommand::new_put(vec![0], 12)),
+            ))
+            .is_none());
+        assert_eq!(sp.len(), 2);
+        assert_eq!(sp.tracked(), 2);
+    });
+}
+
 #[traced_test]
 #[test]
 fn handle_ae_will_reject_wrong_log() {
@@ -408,38 +1013,88 @@ fn handle_ae_will_reject_wrong_log() {
 #[traced_test]
 #[tokio::test]
 #[abort_on_panic]
-async fn follower_will_not_start_election_when_heartbeats_are_received() {
+async fn follower_will_not_start_election_when_heartbeats_are_received() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        Arc::new(RawCurp::new_test(
+            3,
+            exe_tx,
+            mock_role_change(),
+            task_manager,
+        ))
+    };
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+
+    let curp_c = Arc::clone(&curp);
+    let handle = tokio::spawn(async move {
+        loop {
+            sleep(default_heartbeat_interval()).await;
+            let action = curp_c.tick_election();
+            assert!(matches!(action, None));
+        }
+    });
+
+    for _ in 0..default_follower_timeout_ticks() * 5 {
+        sleep(default_heartbeat_interval()).await;
+        curp.reset_election_tick();
+    }
+
+    assert!(!handle.is_finished());
+    handle.abort();
+}
+
+#[traced_test]
+#[tokio::test]
+#[abort_on_panic]
+async fn follower_will_not_start_election_if_no_campaign() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp_config = CurpConfigBuilder::default()
+        .log_entries_cap(10)
+        .no_campaign(true)
+        .build()
+        .unwrap();
+    let curp = Arc::new(RawCurp::new_test_with_config(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+        curp_config,
+    ));
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+
+    for _ in 0..default_follower_timeout_ticks() * 5 {
+        sleep(default_heartbeat_interval()).await;
+        assert!(curp.tick_election().is_none());
+        assert_eq!(curp.role(), Role::Follower);
+    }
+    assert!(curp.handle_try_become_leader_now().is_none());
+    assert_eq!(curp.role(), Role::Follower);
+}
+
+#[traced_test]
+#[tokio::test]
+#[abort_on_panic]
+async fn follower_will_not_start_election_after_campaign_disabled() {
     let task_manager = Arc::new(TaskManager::new());
-    let curp = {
-        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
-        exe_tx
-            .expect_send_reset()
-            .returning(|_| oneshot::channel().1);
-        Arc::new(RawCurp::new_test(
-            3,
-            exe_tx,
-            mock_role_change(),
-            task_manager,
-        ))
-    };
+    let curp = Arc::new(RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    ));
     curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
-
-    let curp_c = Arc::clone(&curp);
-    let handle = tokio::spawn(async move {
-        loop {
-            sleep(default_heartbeat_interval()).await;
-            let action = curp_c.tick_election();
-            assert!(matches!(action, None));
-        }
-    });
+    curp.disable_campaign().await;
 
     for _ in 0..default_follower_timeout_ticks() * 5 {
         sleep(default_heartbeat_interval()).await;
-        curp.reset_election_tick();
+        assert!(curp.tick_election().is_none());
+        assert_eq!(curp.role(), Role::Follower);
     }
-
-    assert!(!handle.is_finished());
-    handle.abort();
+    assert!(curp.handle_try_become_leader_now().is_none());
 }
 
 #[traced_test]
@@ -818,6 +1473,171 @@ fn leader_retires_should_cleanup() {
     assert!(ucp_l.is_empty(), "ucp should be empty");
 }
 
+/*************** tests for log compaction **************/
+
+#[traced_test]
+#[test]
+fn leader_should_send_snapshot_to_follower_lagging_past_compaction() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_snapshot()
+            .returning(|_| oneshot::channel().1);
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let term = curp.term();
+    {
+        let mut log_w = curp.log.write();
+        for i in 1..=20 {
+            let cmd = Arc::new(TestCommand::default());
+            log_w.push(term, ProposeId(TEST_CLIENT_ID, i), cmd).unwrap();
+        }
+        log_w.last_as = 20;
+        log_w.last_exe = 20;
+        log_w.commit_index = 20;
+        log_w.compact();
+        assert_eq!((log_w.base_index, log_w.base_term), (10, term));
+    }
+
+    // the follower asks for entries that have been compacted
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    assert_eq!(curp.lst.get_next_index(s1_id), Some(1));
+    assert!(matches!(curp.sync(s1_id), Some(SyncAction::Snapshot(_))));
+
+    // after the snapshot is installed, the follower catches up by entries
+    let meta = SnapshotMeta {
+        last_included_index: 20,
+        last_included_term: term,
+        cluster_server_version: 0,
+        membership_index: 0,
+    };
+    // a snapshot the follower didn't install is sent again
+    curp.handle_snapshot_resp(s1_id, meta, term, false).unwrap();
+    assert_eq!(curp.lst.get_next_index(s1_id), Some(1));
+    curp.handle_snapshot_resp(s1_id, meta, term, true).unwrap();
+    assert_eq!(curp.lst.get_next_index(s1_id), Some(21));
+    let index = curp.push_cmd(
+        ProposeId(TEST_CLIENT_ID, 21),
+        Arc::new(TestCommand::default()),
+    );
+    let Some(SyncAction::AppendEntries(ae)) = curp.sync(s1_id) else {
+        panic!("the follower should be calibrated by append entries");
+    };
+    assert_eq!((ae.prev_log_index, ae.prev_log_term), (20, term));
+    assert_eq!(ae.entries.len(), 1);
+    assert_eq!(ae.entries[0].index, index);
+}
+
+#[traced_test]
+#[test]
+fn degraded_follower_should_get_limited_entries_or_a_snapshot() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_snapshot()
+            .times(1)
+            .returning(|_| oneshot::channel().1);
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    for i in 1..=8 {
+        let _index = curp.push_cmd(
+            ProposeId(TEST_CLIENT_ID, i),
+            Arc::new(TestCommand::default()),
+        );
+    }
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let sent = |id| match curp.sync(id) {
+        Some(SyncAction::AppendEntries(ae)) => ae.entries.len(),
+        _ => panic!("the follower should be synced by append entries"),
+    };
+
+    let mut sizes = vec![];
+    for _ in 0..DEGRADE_AFTER_TIMEOUTS {
+        let size = sent(s1_id);
+        sizes.push(size);
+        curp.handle_append_entries_timeout(s1_id, size);
+    }
+    assert_eq!(sizes, [8, 4, 2]);
+    assert_eq!(sent(s1_id), 1);
+    assert!(curp.lst.get_window(s1_id).unwrap().is_degraded());
+    // the other follower is not affected
+    assert_eq!(sent(s2_id), 8);
+    assert!(!curp.lst.get_window(s2_id).unwrap().is_degraded());
+
+    // the degraded follower lags behind the log retention
+    for i in 9..=12 {
+        let _index = curp.push_cmd(
+            ProposeId(TEST_CLIENT_ID, i),
+            Arc::new(TestCommand::default()),
+        );
+    }
+    curp.log.write().last_exe = 12;
+    assert!(matches!(curp.sync(s1_id), Some(SyncAction::Snapshot(_))));
+    assert_eq!(sent(s2_id), 12);
+}
+
+#[traced_test]
+#[test]
+fn leader_reset_by_snapshot_should_send_snapshot_at_compaction_point() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_snapshot()
+            .withf(|meta| meta.last_included_index == 20 && meta.last_included_term == 1)
+            .returning(|_| oneshot::channel().1);
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    curp.reset_by_snapshot(SnapshotMeta {
+        last_included_index: 20,
+        last_included_term: 1,
+        cluster_server_version: 0,
+        membership_index: 0,
+    });
+
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    assert!(matches!(curp.sync(s1_id), Some(SyncAction::Snapshot(_))));
+}
+
+#[traced_test]
+#[test]
+fn follower_should_accept_entries_at_compaction_point() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+    curp.reset_by_snapshot(SnapshotMeta {
+        last_included_index: 20,
+        last_included_term: 1,
+        cluster_server_version: 0,
+        membership_index: 0,
+    });
+
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let entry = || {
+        LogEntry::new(
+            21,
+            1,
+            ProposeId(TEST_CLIENT_ID, 21),
+            Arc::new(TestCommand::default()),
+        )
+    };
+    // the consistency check fails when the previous entry doesn't match the compaction point
+    let result = curp.handle_append_entries(1, s2_id, 20, 0, vec![entry()], 20);
+    assert!(result.is_err());
+    let result = curp.handle_append_entries(1, s2_id, 20, 1, vec![entry()], 20);
+    assert_eq!(result, Ok(1));
+    assert_eq!(curp.log.read().last_log_index(), 21);
+}
+
 /*************** tests for other small functions **************/
 
 #[traced_test]
@@ -928,6 +1748,7 @@ fn add_learner_node_and_promote_should_success() {
     curp.apply_conf_change(changes);
     assert!(curp.check_learner(1, true));
 
+    let _ignore = curp.lst.update_server_version(1, SERVER_VERSION);
     let changes = vec![ConfChange::promote(1)];
     assert!(curp.check_new_config(&changes).is_ok());
     let infos = curp.apply_conf_change(changes.clone());
@@ -936,6 +1757,95 @@ fn add_learner_node_and_promote_should_success() {
     assert!(curp.check_learner(1, true));
 }
 
+#[traced_test]
+#[test]
+fn promote_learner_far_behind_should_be_rejected() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        let curp_config = CurpConfigBuilder::default()
+            .learner_promote_gap(5)
+            .build()
+            .unwrap();
+        Arc::new(RawCurp::new_test_with_config(
+            3,
+            exe_tx,
+            mock_role_change(),
+            task_manager,
+            curp_config,
+        ))
+    };
+    let changes = vec![ConfChange::add_learner(
+        1,
+        vec!["http://127.0.0.1:4567".to_owned()],
+    )];
+    assert!(curp.check_new_config(&changes).is_ok());
+    let _ignore = curp.apply_conf_change(changes);
+    let mut last_index = 0;
+    for i in 0..10 {
+        last_index = curp.push_cmd(
+            ProposeId(TEST_CLIENT_ID, i),
+            Arc::new(TestCommand::default()),
+        );
+    }
+
+    let changes = vec![ConfChange::promote(1)];
+    assert_eq!(
+        curp.check_new_config(&changes),
+        Err(CurpError::learner_not_catch_up())
+    );
+
+    // the learner catches up within the gap, but runs an older server version
+    curp.lst.update_match_index(1, last_index - 5);
+    let _ignore = curp.lst.update_server_version(1, SERVER_VERSION - 1);
+    assert_eq!(
+        curp.check_new_config(&changes),
+        Err(CurpError::invalid_config())
+    );
+
+    let _ignore = curp.lst.update_server_version(1, SERVER_VERSION);
+    assert!(curp.check_new_config(&changes).is_ok());
+    let _ignore = curp.apply_conf_change(changes);
+    assert!(curp.check_learner(1, false));
+}
+
+#[traced_test]
+#[test]
+fn learner_and_removed_member_should_not_start_election() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        Arc::new(RawCurp::new_test(
+            3,
+            exe_tx,
+            mock_role_change(),
+            task_manager,
+        ))
+    };
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 1);
+    let self_id = curp.id();
+    let self_addrs = curp.cluster().self_peer_urls();
+    let _ignore = curp.apply_conf_change(vec![ConfChange::remove(self_id)]);
+    let _ignore = curp.apply_conf_change(vec![ConfChange::add_learner(self_id, self_addrs)]);
+    assert!(curp.cluster().self_member().is_learner);
+
+    let tick_many = || {
+        for _ in 0..100 {
+            assert!(curp.tick_election().is_none());
+        }
+        assert_eq!(curp.role(), Role::Follower);
+    };
+    tick_many();
+    assert!(curp.handle_try_become_leader_now().is_none());
+
+    // removing the learner doesn't start an election either
+    let _ignore = curp.apply_conf_change(vec![ConfChange::remove(self_id)]);
+    tick_many();
+}
+
 #[traced_test]
 #[test]
 fn add_exists_node_should_return_node_already_exists_error() {
@@ -1010,6 +1920,140 @@ fn remove_non_exists_node_should_return_node_not_exists_error() {
     assert!(matches!(resp, Err(CurpError::NodeNotExists(()))));
 }
 
+/// The members, voters and learners of a curp, in a comparable form
+fn membership_of(
+    curp: &RawCurp<TestCommand, TestRoleChange>,
+) -> (Vec<Member>, Vec<ServerId>, Vec<ServerId>) {
+    let members = curp
+        .cluster()
+        .all_members_vec()
+        .into_iter()
+        .sorted_by_key(|m| m.id)
+        .collect();
+    let cst_l = curp.cst.lock();
+    let voters = cst_l.config.voters().iter().copied().sorted().collect();
+    let learners = cst_l.config.learners.iter().copied().sorted().collect();
+    (members, voters, learners)
+}
+
+#[traced_test]
+#[test]
+fn conf_change_replayed_after_snapshot_should_lead_to_same_membership() {
+    let new_curp = || {
+        RawCurp::new_test(
+            3,
+            MockCEEventTxApi::<TestCommand>::default(),
+            mock_role_change(),
+            Arc::new(TaskManager::new()),
+        )
+    };
+    let replayed = new_curp();
+    let s1_id = replayed.cluster().get_id_by_name("S1").unwrap();
+    let entries = [
+        (2, vec![ConfChange::add_learner(1, vec!["new".to_owned()])]),
+        (4, vec![ConfChange::promote(1)]),
+        (5, vec![ConfChange::remove(s1_id)]),
+        (7, vec![ConfChange::add(2, vec!["other".to_owned()])]),
+    ];
+    for (index, changes) in entries.clone() {
+        assert!(replayed.apply_conf_change_entry(index, changes).is_some());
+    }
+    let expected = membership_of(&replayed);
+    assert!(!expected.0.iter().any(|m| m.id == s1_id));
+    assert_eq!(expected.1.len(), 4);
+    assert!(expected.2.is_empty());
+
+    // the snapshot is taken after each of the entries, and all the entries are replayed
+    // after it's installed, in whatever overlap
+    for taken_after in 0..=entries.len() {
+        let leader = new_curp();
+        for (index, changes) in entries[..taken_after].iter().cloned() {
+            let _ig = leader.apply_conf_change_entry(index, changes);
+        }
+        let (membership_index, members) = leader.membership();
+
+        let follower = new_curp();
+        follower.reset_membership_by_snapshot(membership_index, members);
+        for (index, changes) in entries.iter().cloned() {
+            let _ig = follower.apply_conf_change_entry(index, changes);
+        }
+        assert_eq!(membership_of(&follower), expected, "{taken_after}");
+        // replaying again changes nothing
+        for (index, changes) in entries.iter().cloned() {
+            assert!(follower.apply_conf_change_entry(index, changes).is_none());
+        }
+        assert_eq!(membership_of(&follower), expected, "{taken_after}");
+    }
+}
+
+#[traced_test]
+#[test]
+fn snapshot_membership_should_exclude_conf_changes_after_it() {
+    let curp = RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        Arc::new(TaskManager::new()),
+    );
+    let initial = curp.membership();
+    let _ig =
+        curp.apply_conf_change_entry(2, vec![ConfChange::add_learner(1, vec!["new".to_owned()])]);
+    let added = curp.membership();
+    let _ig = curp.apply_conf_change_entry(4, vec![ConfChange::promote(1)]);
+    assert_eq!(curp.membership().0, 4);
+
+    // the conf changes are not applied yet
+    assert_eq!(curp.membership_at(1), initial);
+    assert_eq!(curp.membership_at(3), added);
+    assert_eq!(curp.membership_at(4), curp.membership());
+
+    // an overwritten conf change is excluded
+    curp.rewind_membership_index(4);
+    assert_eq!(curp.membership_at(5).0, 2);
+
+    curp.prune_membership_history(2);
+    assert!(curp.ctx.membership_history.lock().is_empty());
+}
+
+#[traced_test]
+#[test]
+fn conf_change_not_applicable_should_be_no_op() {
+    let curp = RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        Arc::new(TaskManager::new()),
+    );
+    let self_id = curp.id();
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let before = membership_of(&curp);
+    // a removal of a member unknown, a duplicate add and a promotion of a voter
+    assert!(curp
+        .apply_conf_change_entry(1, vec![ConfChange::remove(12345)])
+        .is_none());
+    assert!(curp
+        .apply_conf_change_entry(2, vec![ConfChange::add(s1_id, vec!["S1".to_owned()])])
+        .is_none());
+    assert!(curp
+        .apply_conf_change_entry(3, vec![ConfChange::promote(s2_id)])
+        .is_none());
+    assert_eq!(membership_of(&curp), before);
+    assert_eq!(curp.cluster().membership_index(), 3);
+
+    // the last voter is never removed
+    assert!(curp
+        .apply_conf_change_entry(4, vec![ConfChange::remove(s1_id)])
+        .is_some());
+    assert!(curp
+        .apply_conf_change_entry(5, vec![ConfChange::remove(s2_id)])
+        .is_some());
+    assert!(curp
+        .apply_conf_change_entry(6, vec![ConfChange::remove(self_id)])
+        .is_none());
+    assert_eq!(membership_of(&curp).1, vec![self_id]);
+}
+
 #[traced_test]
 #[test]
 fn update_node_should_update_the_address_of_node() {
@@ -1240,3 +2284,86 @@ fn leader_will_reset_transferee_after_it_become_follower() {
     curp.update_to_term_and_become_follower(&mut *curp.st.write(), 2);
     assert!(curp.get_transferee().is_none());
 }
+
+/*************** tests for read index **************/
+
+#[traced_test]
+#[test]
+fn read_index_should_be_confirmed_by_acks_sent_after_reading() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = RawCurp::new_test(
+        5,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    );
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let s3_id = curp.cluster().get_id_by_name("S3").unwrap();
+    let term = curp.leader_term().unwrap();
+
+    let sent_before_reading = std::time::Instant::now();
+    let (state, read_at) = curp
+        .start_read_index(Arc::new(TestCommand::new_get(vec![1])), term)
+        .unwrap()
+        .unwrap();
+    assert_eq!(state, ReadState::CommitIndex(0));
+
+    curp.record_ack(s1_id, sent_before_reading);
+    curp.record_ack(s2_id, sent_before_reading);
+    assert!(!curp.confirm_read_index(term, read_at).unwrap());
+
+    curp.record_ack(s1_id, std::time::Instant::now());
+    assert!(!curp.confirm_read_index(term, read_at).unwrap());
+    curp.record_ack(s3_id, std::time::Instant::now());
+    assert!(curp.confirm_read_index(term, read_at).unwrap());
+}
+
+#[traced_test]
+#[test]
+fn read_index_should_wait_for_entries_of_previous_terms_to_be_committed() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    );
+    let term = curp.leader_term().unwrap();
+    let _index = curp.push_cmd(
+        ProposeId(TEST_CLIENT_ID, 0),
+        Arc::new(TestCommand::new_put(vec![1], 1)),
+    );
+
+    assert!(curp
+        .start_read_index(Arc::new(TestCommand::new_get(vec![1])), term)
+        .unwrap()
+        .is_none());
+}
+
+#[traced_test]
+#[test]
+fn read_index_should_fail_after_leadership_is_lost() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = RawCurp::new_test(
+        3,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    );
+    let term = curp.leader_term().unwrap();
+    let (_state, read_at) = curp
+        .start_read_index(Arc::new(TestCommand::new_get(vec![1])), term)
+        .unwrap()
+        .unwrap();
+
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), term + 1);
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    curp.record_ack(s1_id, std::time::Instant::now());
+
+    assert!(matches!(
+        curp.confirm_read_index(term, read_at),
+        Err(CurpError::Redirect(_))
+    ));
+    assert!(matches!(curp.leader_term(), Err(CurpError::Redirect(_))));
+}
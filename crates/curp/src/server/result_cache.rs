@@ -0,0 +1,420 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use clippy_utilities::OverflowArithmetic;
+use tokio::time::Instant;
+use utils::config::ResultCacheConfig;
+
+use crate::rpc::ProposeId;
+
+/// Completed propose results of a client session
+#[derive(Debug)]
+struct Session {
+    /// Seq nums of the cached results with their completion time, in completion order
+    results: VecDeque<(u64, Instant)>,
+    /// The largest seq num whose result has been evicted
+    evicted: Option<u64>,
+    /// The largest seq num proposed or completed by the client
+    max_seq_num: u64,
+    /// When the client last proposed or completed a cmd
+    last_active: Instant,
+}
+
+impl Session {
+    /// Create an empty session
+    fn new(now: Instant) -> Self {
+        Self {
+            results: VecDeque::new(),
+            evicted: None,
+            max_seq_num: 0,
+            last_active: now,
+        }
+    }
+
+    /// The largest seq num the client is known to have used
+    fn last_seq_num(&self) -> u64 {
+        self.max_seq_num.max(self.evicted.unwrap_or(0))
+    }
+}
+
+/// Index of the cached propose results, grouped by client sessions
+///
+/// It only tracks which results are cached, the results themselves are kept in the
+/// `CommandBoard`. Results are evicted in completion order, so a result is reported
+/// expired once a result of a larger seq num of the same client has been evicted.
+///
+/// A session is created by the first propose of a client and refreshed by the later
+/// ones. Once every member supports session expiry, idle sessions with cached results
+/// are only dropped by `expire_sessions`, applied from the log, so that all nodes agree
+/// on the sessions that expired. Until then, and for sessions holding no result, which
+/// a node may have created for proposals that were never applied, `evict` drops them.
+#[derive(Debug, Default)]
+pub(super) struct ResultCache {
+    /// Sessions indexed by client ids
+    sessions: HashMap<u64, Session>,
+    /// Clients whose sessions expired, with the largest seq num they had and when they
+    /// expired
+    expired_sessions: HashMap<u64, (u64, Instant)>,
+    /// Number of cached results
+    len: usize,
+}
+
+impl ResultCache {
+    /// Record a completed result
+    pub(super) fn record(&mut self, id: ProposeId, completed: Instant) {
+        let ProposeId(client_id, seq_num) = id;
+        let session = self
+            .sessions
+            .entry(client_id)
+            .or_insert_with(|| Session::new(completed));
+        session.results.push_back((seq_num, completed));
+        session.max_seq_num = session.max_seq_num.max(seq_num);
+        session.last_active = session.last_active.max(completed);
+        self.len = self.len.overflow_add(1);
+    }
+
+    /// Refresh the session of a client proposing a cmd, return `false` without
+    /// refreshing it if the session of the cmd has expired
+    pub(super) fn touch(&mut self, id: ProposeId, now: Instant) -> bool {
+        if self.is_session_expired(id) {
+            return false;
+        }
+        let ProposeId(client_id, seq_num) = id;
+        let session = self
+            .sessions
+            .entry(client_id)
+            .or_insert_with(|| Session::new(now));
+        session.max_seq_num = session.max_seq_num.max(seq_num);
+        session.last_active = session.last_active.max(now);
+        true
+    }
+
+    /// Mark the results of a client up to `seq_num` as evicted
+    pub(super) fn mark_evicted(&mut self, id: ProposeId, now: Instant) {
+        let ProposeId(client_id, seq_num) = id;
+        let session = self
+            .sessions
+            .entry(client_id)
+            .or_insert_with(|| Session::new(now));
+        session.evicted = session.evicted.max(Some(seq_num));
+    }
+
+    /// Check whether the result of a cmd has been evicted, the caller should check that
+    /// the result is not present first
+    pub(super) fn is_expired(&self, id: ProposeId) -> bool {
+        let ProposeId(client_id, seq_num) = id;
+        self.sessions
+            .get(&client_id)
+            .is_some_and(|session| session.evicted.is_some_and(|evicted| seq_num <= evicted))
+    }
+
+    /// Check whether a cmd was proposed in a session that has expired since
+    pub(super) fn is_session_expired(&self, id: ProposeId) -> bool {
+        let ProposeId(client_id, seq_num) = id;
+        self.expired_sessions
+            .get(&client_id)
+            .is_some_and(|&(max_seq_num, _)| seq_num <= max_seq_num)
+    }
+
+    /// The clients whose sessions have been idle for longer than `ttl`, with the largest
+    /// seq nums they used
+    pub(super) fn idle_sessions(&self, ttl: Duration, now: Instant) -> Vec<(u64, u64)> {
+        self.sessions
+            .iter()
+            .filter(|&(_, session)| now.saturating_duration_since(session.last_active) > ttl)
+            .map(|(&client_id, session)| (client_id, session.last_seq_num()))
+            .collect()
+    }
+
+    /// Expire the sessions of the clients up to the largest seq nums the leader saw when
+    /// it found them idle, return the ids of the dropped results
+    ///
+    /// The results and the later retries of the cmds up to those seq nums are dropped
+    /// and reported expired on every node, as the seq nums come from the log. A session
+    /// that has gone on with a larger seq num since keeps its newer results, the client
+    /// is not idle any more. The markers are dropped by `evict`.
+    pub(super) fn expire_sessions(
+        &mut self,
+        sessions: &[(u64, u64)],
+        now: Instant,
+    ) -> Vec<ProposeId> {
+        let mut expired = Vec::new();
+        for &(client_id, last_seen) in sessions {
+            self.mark_session_expired(client_id, last_seen, now);
+            let Some(session) = self.sessions.get_mut(&client_id) else {
+                continue;
+            };
+            let (dropped, kept) = session
+                .results
+                .drain(..)
+                .partition::<VecDeque<_>, _>(|&(seq_num, _)| seq_num <= last_seen);
+            session.results = kept;
+            expired.extend(
+                dropped
+                    .into_iter()
+                    .map(|(seq_num, _)| ProposeId(client_id, seq_num)),
+            );
+            if session.last_seq_num() <= last_seen {
+                let _ignore = self.sessions.remove(&client_id);
+            }
+        }
+        self.len = self.len.overflow_sub(expired.len());
+        expired
+    }
+
+    /// Mark the session of a client expired up to `max_seq_num`
+    pub(super) fn mark_session_expired(&mut self, client_id: u64, max_seq_num: u64, now: Instant) {
+        let marker = self
+            .expired_sessions
+            .entry(client_id)
+            .or_insert((max_seq_num, now));
+        *marker = (marker.0.max(max_seq_num), now);
+    }
+
+    /// The clients whose sessions expired, with the largest seq nums they had
+    pub(super) fn expired_sessions(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.expired_sessions
+            .iter()
+            .map(|(&client_id, &(max_seq_num, _))| (client_id, max_seq_num))
+    }
+
+    /// Evict the results out of retention and the markers of the sessions expired for
+    /// longer than the session ttl, return the ids of the evicted results
+    ///
+    /// The sessions idle for longer than the session ttl are dropped as well if they
+    /// hold no result, or if `expire_idle` is set because the sessions can't be expired
+    /// through the log yet.
+    pub(super) fn evict(
+        &mut self,
+        cfg: &ResultCacheConfig,
+        now: Instant,
+        expire_idle: bool,
+    ) -> Vec<ProposeId> {
+        let Self {
+            ref mut sessions,
+            ref mut expired_sessions,
+            ref mut len,
+        } = *self;
+        let mut evicted = Vec::new();
+        sessions.retain(|&client_id, session| {
+            if now.saturating_duration_since(session.last_active) <= cfg.session_ttl
+                || !(expire_idle || session.results.is_empty())
+            {
+                return true;
+            }
+            let marker = expired_sessions
+                .entry(client_id)
+                .or_insert((session.last_seq_num(), now));
+            *marker = (marker.0.max(session.last_seq_num()), now);
+            evicted.extend(
+                session
+                    .results
+                    .drain(..)
+                    .map(|(seq_num, _)| ProposeId(client_id, seq_num)),
+            );
+            false
+        });
+        for (&client_id, session) in sessions.iter_mut() {
+            while let Some(&(seq_num, completed)) = session.results.front() {
+                if session.results.len() <= cfg.results_per_client
+                    && now.duration_since(completed) <= cfg.retention
+                {
+                    break;
+                }
+                let _ignore = session.results.pop_front();
+                session.evicted = session.evicted.max(Some(seq_num));
+                evicted.push(ProposeId(client_id, seq_num));
+            }
+        }
+        // the markers only need to outlive the retries of the expired clients
+        expired_sessions.retain(|_, &mut (_, at)| now.duration_since(at) <= cfg.session_ttl);
+        *len = len.overflow_sub(evicted.len());
+        evicted
+    }
+
+    /// Ids of all cached results with their completion time, the newest first
+    pub(super) fn newest_first(&self) -> Vec<(ProposeId, Instant)> {
+        let mut results: Vec<_> =
+            self.sessions
+                .iter()
+                .flat_map(|(&client_id, session)| {
+                    session.results.iter().map(move |&(seq_num, completed)| {
+                        (ProposeId(client_id, seq_num), completed)
+                    })
+                })
+                .collect();
+        results.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+        results
+    }
+
+    /// The largest evicted seq num of a client
+    pub(super) fn evicted_seq_num(&self, client_id: u64) -> Option<u64> {
+        self.sessions
+            .get(&client_id)
+            .and_then(|session| session.evicted)
+    }
+
+    /// Number of client sessions
+    pub(super) fn sessions_len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Number of cached results
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(results_per_client: usize) -> ResultCacheConfig {
+        ResultCacheConfig {
+            results_per_client,
+            retention: Duration::from_secs(10),
+            session_ttl: Duration::from_secs(60),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn evict_keeps_last_k_results_per_client() {
+        let now = Instant::now();
+        let mut cache = ResultCache::default();
+        for seq_num in 1..=5 {
+            cache.record(ProposeId(1, seq_num), now);
+        }
+        cache.record(ProposeId(2, 1), now);
+
+        let evicted = cache.evict(&config(2), now, false);
+        assert_eq!(
+            evicted,
+            vec![ProposeId(1, 1), ProposeId(1, 2), ProposeId(1, 3)]
+        );
+        assert_eq!(cache.len(), 3);
+        assert!(cache.is_expired(ProposeId(1, 3)));
+        assert!(!cache.is_expired(ProposeId(1, 4)));
+        assert!(!cache.is_expired(ProposeId(1, 6)));
+        assert!(!cache.is_expired(ProposeId(2, 1)));
+    }
+
+    #[test]
+    fn evict_drops_results_out_of_retention() {
+        let now = Instant::now();
+        let mut cache = ResultCache::default();
+        cache.record(ProposeId(1, 1), now);
+        cache.record(ProposeId(1, 2), now + Duration::from_secs(8));
+
+        let evicted = cache.evict(&config(10), now + Duration::from_secs(12), false);
+        assert_eq!(evicted, vec![ProposeId(1, 1)]);
+        assert!(cache.is_expired(ProposeId(1, 1)));
+        assert!(!cache.is_expired(ProposeId(1, 2)));
+    }
+
+    #[test]
+    fn idle_sessions_should_expire_after_ttl() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let mut cache = ResultCache::default();
+        cache.record(ProposeId(1, 1), now);
+        cache.record(ProposeId(1, 2), now);
+        assert!(cache.touch(ProposeId(2, 1), now));
+        // proposing refreshes the session
+        assert!(cache.touch(ProposeId(2, 2), now + Duration::from_secs(55)));
+
+        assert!(cache
+            .idle_sessions(ttl, now + Duration::from_secs(60))
+            .is_empty());
+        assert_eq!(
+            cache.idle_sessions(ttl, now + Duration::from_secs(61)),
+            vec![(1, 2)]
+        );
+        // eviction doesn't drop sessions holding results on its own
+        let cfg = ResultCacheConfig {
+            retention: Duration::from_secs(120),
+            ..config(10)
+        };
+        let _evicted = cache.evict(&cfg, now + Duration::from_secs(61), false);
+        assert_eq!(cache.sessions_len(), 2);
+
+        let expired = cache.expire_sessions(&[(1, 2)], now + Duration::from_secs(61));
+        assert_eq!(expired.len(), 2);
+        assert_eq!((cache.sessions_len(), cache.len()), (1, 0));
+        assert!(cache.is_session_expired(ProposeId(1, 2)));
+        assert!(!cache.is_expired(ProposeId(1, 2)));
+        assert!(!cache.touch(ProposeId(1, 1), now + Duration::from_secs(62)));
+        // new proposals of the client start a new session
+        assert!(!cache.is_session_expired(ProposeId(1, 3)));
+        assert!(cache.touch(ProposeId(1, 3), now + Duration::from_secs(62)));
+
+        // the marker is dropped after another ttl
+        let _evicted = cache.evict(&config(10), now + Duration::from_secs(122), false);
+        assert!(!cache.is_session_expired(ProposeId(1, 2)));
+    }
+
+    #[test]
+    fn expire_sessions_should_keep_results_newer_than_last_seen() {
+        let now = Instant::now();
+        let mut cache = ResultCache::default();
+        cache.record(ProposeId(1, 1), now);
+        cache.record(ProposeId(1, 2), now);
+        // the client proposes again after the leader found it idle at seq num 2
+        assert!(cache.touch(ProposeId(1, 3), now + Duration::from_secs(61)));
+        cache.record(ProposeId(1, 3), now + Duration::from_secs(61));
+
+        let expired = cache.expire_sessions(&[(1, 2)], now + Duration::from_secs(61));
+        assert_eq!(expired, vec![ProposeId(1, 1), ProposeId(1, 2)]);
+        assert_eq!((cache.sessions_len(), cache.len()), (1, 1));
+        assert!(cache.is_session_expired(ProposeId(1, 2)));
+        assert!(!cache.is_session_expired(ProposeId(1, 3)));
+        assert!(cache.touch(ProposeId(1, 4), now + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn evict_should_drop_idle_sessions() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(61);
+        let mut cache = ResultCache::default();
+        cache.record(ProposeId(1, 1), now);
+        // a session created by a proposal that never completed holds no result
+        assert!(cache.touch(ProposeId(2, 1), now));
+
+        let cfg = ResultCacheConfig {
+            retention: Duration::from_secs(120),
+            ..config(10)
+        };
+        assert!(cache.evict(&cfg, later, false).is_empty());
+        assert_eq!(cache.sessions_len(), 1);
+        assert!(cache.is_session_expired(ProposeId(2, 1)));
+
+        // without session expiry through the log, idle sessions are dropped locally
+        assert_eq!(cache.evict(&cfg, later, true), vec![ProposeId(1, 1)]);
+        assert_eq!((cache.sessions_len(), cache.len()), (0, 0));
+        assert!(cache.is_session_expired(ProposeId(1, 1)));
+    }
+
+    #[test]
+    fn expired_sessions_should_be_reclaimed() {
+        const CLIENTS: u64 = 100_000;
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let mut cache = ResultCache::default();
+        for client_id in 1..=CLIENTS {
+            assert!(cache.touch(ProposeId(client_id, 1), now));
+            cache.record(ProposeId(client_id, 1), now);
+        }
+        assert_eq!((cache.sessions_len(), cache.len()), (100_000, 100_000));
+
+        let later = now + Duration::from_secs(61);
+        let idle = cache.idle_sessions(ttl, later);
+        assert_eq!(idle.len(), 100_000);
+        assert_eq!(cache.expire_sessions(&idle, later).len(), 100_000);
+        assert_eq!((cache.sessions_len(), cache.len()), (0, 0));
+
+        let _evicted = cache.evict(&config(10), later + Duration::from_secs(61), false);
+        assert_eq!(cache.expired_sessions().count(), 0);
+    }
+}
@@ -0,0 +1,386 @@
+use std::fmt::Debug;
+
+use bytes::Bytes;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use engine::{Snapshot as EngineSnapshot, SnapshotAllocator, SnapshotApi};
+use futures::{pin_mut, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::{debug, error, warn};
+
+use crate::{
+    rpc::{CurpError, InstallSnapshotRequest, Member},
+    snapshot::{Snapshot, SnapshotMeta},
+};
+
+/// The outcome of a stream of a snapshot transfer
+#[derive(Debug)]
+pub(super) enum Received {
+    /// The snapshot is completely received and verified
+    Complete(Snapshot),
+    /// The stream is rejected by the verification of the sender, or the received
+    /// snapshot is corrupted
+    Rejected,
+    /// The stream is a probe, the transfer should be resumed from the offset
+    Probed(u64),
+}
+
+/// The part of a snapshot transfer received so far
+struct Staging {
+    /// Id of the transfer
+    transfer_id: u64,
+    /// Metadata of the snapshot
+    meta: SnapshotMeta,
+    /// Bytes received, which is the offset of the next chunk
+    offset: u64,
+    /// Digest of the bytes received
+    hasher: Sha256,
+    /// The snapshot the chunks are written to
+    snapshot: EngineSnapshot,
+}
+
+/// Receives the snapshots sent by the leader
+///
+/// The chunks of a transfer are staged by the allocator as they arrive and kept when the
+/// stream breaks, so that the leader could resume the same transfer from the next offset
+/// after probing it. A chunk out of sequence is never written, and a snapshot is only
+/// handed over once all of it is received and the digest matches the one of the leader.
+pub(super) struct SnapshotReceiver {
+    /// Snapshot allocator
+    allocator: Box<dyn SnapshotAllocator>,
+    /// The transfer being received, it's locked by the stream receiving it
+    staging: Mutex<Option<Staging>>,
+}
+
+impl Debug for SnapshotReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotReceiver").finish()
+    }
+}
+
+impl SnapshotReceiver {
+    /// New `SnapshotReceiver`
+    pub(super) fn new(allocator: Box<dyn SnapshotAllocator>) -> Self {
+        Self {
+            allocator,
+            staging: Mutex::new(None),
+        }
+    }
+
+    /// Clean up the snapshots left partially received by a previous run
+    pub(super) async fn clean_stale(&self) {
+        if let Err(e) = self.allocator.clean_stale().await {
+            warn!("failed to clean up the stale snapshots, {e}");
+        }
+    }
+
+    /// Receive a stream of a snapshot transfer, `verify` checks whether the sender is
+    /// allowed to install the snapshot
+    ///
+    /// The staged data is kept if the stream breaks, and discarded if it turns out to be
+    /// invalid or another transfer starts.
+    pub(super) async fn receive<E: std::error::Error + 'static>(
+        &self,
+        req_stream: impl Stream<Item = Result<InstallSnapshotRequest, E>>,
+        verify: impl Fn(&InstallSnapshotRequest) -> bool,
+    ) -> Result<Received, CurpError> {
+        pin_mut!(req_stream);
+        let mut staging = self.staging.lock().await;
+        while let Some(req) = req_stream.next().await {
+            let req = req?;
+            if !verify(&req) {
+                return Ok(Received::Rejected);
+            }
+            let staged_offset = staging
+                .as_ref()
+                .filter(|s| s.transfer_id == req.transfer_id)
+                .map_or(0, |s| s.offset);
+            if req.probe {
+                return Ok(Received::Probed(staged_offset));
+            }
+            if req.offset == 0 {
+                Self::discard(&mut staging).await;
+                *staging = Some(self.allocate(&req).await?);
+            } else if req.offset != staged_offset {
+                return Err(CurpError::internal(format!(
+                    "snapshot chunk at {} is out of sequence, expect {staged_offset}",
+                    req.offset
+                )));
+            } else {
+                // resumed
+            }
+            let Some(staged) = staging.as_mut() else {
+                unreachable!("the staging must be allocated here");
+            };
+            let data_len = req.data.len().numeric_cast::<u64>();
+            staged.hasher.update(&req.data);
+            if let Err(err) = staged.snapshot.write_all(req.data).await {
+                error!("can't write snapshot data, {err:?}");
+                Self::discard(&mut staging).await;
+                return Err(err.into());
+            }
+            staged.offset = staged.offset.overflow_add(data_len);
+            if !req.done {
+                continue;
+            }
+            let Some(staged) = staging.take() else {
+                unreachable!("the staging must exist here");
+            };
+            return Ok(
+                Self::verify_complete(staged, &req.digest, req.result_cache, req.members).await,
+            );
+        }
+        Err(CurpError::internal(
+            "failed to receive a complete snapshot".to_owned(),
+        ))
+    }
+
+    /// Allocate the staging of a new transfer
+    async fn allocate(&self, req: &InstallSnapshotRequest) -> Result<Staging, CurpError> {
+        let snapshot = self
+            .allocator
+            .allocate_new_snapshot()
+            .await
+            .map_err(|err| {
+                error!("failed to allocate a new snapshot, error: {err}");
+                CurpError::internal(format!("failed to allocate a new snapshot, error: {err}"))
+            })?;
+        debug!("start receiving snapshot transfer {}", req.transfer_id);
+        Ok(Staging {
+            transfer_id: req.transfer_id,
+            meta: SnapshotMeta {
+                last_included_index: req.last_included_index,
+                last_included_term: req.last_included_term,
+                cluster_server_version: req.cluster_server_version,
+                membership_index: req.membership_index,
+            },
+            offset: 0,
+            hasher: Sha256::new(),
+            snapshot,
+        })
+    }
+
+    /// Check a completely received snapshot, it's discarded if it's truncated or the
+    /// digest doesn't match
+    async fn verify_complete(
+        mut staged: Staging,
+        digest: &[u8],
+        results: Bytes,
+        members: Vec<Member>,
+    ) -> Received {
+        let size = staged.snapshot.size();
+        if staged.offset != size || staged.hasher.finalize_reset().as_slice() != digest {
+            error!(
+                "snapshot transfer {} is corrupted, received {} of {size} bytes",
+                staged.transfer_id, staged.offset
+            );
+            if let Err(e) = staged.snapshot.clean().await {
+                error!("snapshot clean error, {e}");
+            }
+            return Received::Rejected;
+        }
+        Received::Complete(
+            Snapshot::new(staged.meta, staged.snapshot)
+                .with_results(results)
+                .with_members(members),
+        )
+    }
+
+    /// Discard the staged data
+    async fn discard(staging: &mut Option<Staging>) {
+        if let Some(mut staged) = staging.take() {
+            debug!(
+                "discard snapshot transfer {} at {}",
+                staged.transfer_id, staged.offset
+            );
+            if let Err(e) = staged.snapshot.clean().await {
+                error!("snapshot clean error, {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, fmt::Display};
+
+    use engine::{MemorySnapshotAllocator, RocksSnapshotAllocator};
+
+    use super::*;
+
+    /// A reset of the stream
+    #[derive(Debug)]
+    struct Reset;
+
+    impl Display for Reset {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "stream reset")
+        }
+    }
+
+    impl Error for Reset {}
+
+    const CHUNK: usize = 1024;
+
+    fn chunks(transfer_id: u64, data: &[u8]) -> Vec<InstallSnapshotRequest> {
+        let digest = Bytes::from(Sha256::digest(data).to_vec());
+        let count = data.chunks(CHUNK).count();
+        data.chunks(CHUNK)
+            .enumerate()
+            .map(|(i, chunk)| InstallSnapshotRequest {
+                term: 1,
+                leader_id: 1,
+                last_included_index: 10,
+                last_included_term: 1,
+                offset: (i * CHUNK) as u64,
+                data: Bytes::copy_from_slice(chunk),
+                done: i + 1 == count,
+                transfer_id,
+                digest: if i + 1 == count {
+                    digest.clone()
+                } else {
+                    Bytes::new()
+                },
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn probe(transfer_id: u64) -> InstallSnapshotRequest {
+        InstallSnapshotRequest {
+            term: 1,
+            leader_id: 1,
+            transfer_id,
+            probe: true,
+            ..Default::default()
+        }
+    }
+
+    /// A stream of the requests from `from`, reset after `len` of them
+    fn stream(
+        reqs: &[InstallSnapshotRequest],
+        from: usize,
+        len: Option<usize>,
+    ) -> impl Stream<Item = Result<InstallSnapshotRequest, Reset>> {
+        let reqs: Vec<_> = reqs.iter().skip(from).cloned().map(Ok).collect();
+        let reset = len.map(|_| Err(Reset));
+        let len = len.unwrap_or(reqs.len());
+        futures::stream::iter(reqs.into_iter().take(len).chain(reset))
+    }
+
+    async fn probe_offset(receiver: &SnapshotReceiver, transfer_id: u64) -> u64 {
+        let probe = futures::stream::iter([Ok::<_, Reset>(probe(transfer_id))]);
+        match receiver.receive(probe, |_| true).await.unwrap() {
+            Received::Probed(offset) => offset,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    async fn read_all(snapshot: Snapshot) -> Vec<u8> {
+        let mut inner = snapshot.into_inner();
+        inner.rewind().unwrap();
+        let mut buf = bytes::BytesMut::with_capacity(inner.size() as usize);
+        inner.read_buf_exact(&mut buf).await.unwrap();
+        buf.to_vec()
+    }
+
+    #[tokio::test]
+    async fn reset_streams_should_be_resumed_until_installed() {
+        let data: Vec<u8> = (0..10 * CHUNK + 10).map(|i| (i % 251) as u8).collect();
+        let reqs = chunks(7, &data);
+        for reset_at in [0, 1, 5, 9] {
+            let receiver = SnapshotReceiver::new(Box::<MemorySnapshotAllocator>::default());
+            let result = receiver
+                .receive(stream(&reqs, 0, Some(reset_at)), |_| true)
+                .await;
+            assert!(result.is_err(), "a reset stream is never installed");
+            let offset = probe_offset(&receiver, 7).await;
+            assert_eq!(offset, (reset_at * CHUNK) as u64);
+            // another transfer knows nothing staged
+            assert_eq!(probe_offset(&receiver, 8).await, 0);
+            // reset again right after resuming
+            let from = offset as usize / CHUNK;
+            let result = receiver
+                .receive(stream(&reqs, from, Some(1)), |_| true)
+                .await;
+            assert!(result.is_err());
+            let offset = probe_offset(&receiver, 7).await;
+            let from = offset as usize / CHUNK;
+            match receiver
+                .receive(stream(&reqs, from, None), |_| true)
+                .await
+                .unwrap()
+            {
+                Received::Complete(snapshot) => {
+                    assert_eq!(snapshot.meta.last_included_index, 10);
+                    assert_eq!(read_all(snapshot).await, data);
+                }
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_sequence_chunks_should_not_be_written() {
+        let data: Vec<u8> = (0..4 * CHUNK).map(|i| (i % 251) as u8).collect();
+        let reqs = chunks(7, &data);
+        let receiver = SnapshotReceiver::new(Box::<MemorySnapshotAllocator>::default());
+        let _ignore = receiver.receive(stream(&reqs, 0, Some(1)), |_| true).await;
+        // a chunk is lost
+        let result = receiver.receive(stream(&reqs, 2, None), |_| true).await;
+        assert!(result.is_err());
+        assert_eq!(probe_offset(&receiver, 7).await, CHUNK as u64);
+    }
+
+    #[tokio::test]
+    async fn truncated_or_corrupted_snapshot_should_not_be_installed() {
+        let data: Vec<u8> = (0..4 * CHUNK).map(|i| (i % 251) as u8).collect();
+        let receiver = SnapshotReceiver::new(Box::<MemorySnapshotAllocator>::default());
+
+        let mut truncated = chunks(7, &data);
+        truncated.truncate(2);
+        truncated[1].done = true;
+        let received = receiver
+            .receive(stream(&truncated, 0, None), |_| true)
+            .await;
+        assert!(matches!(received.unwrap(), Received::Rejected));
+
+        let mut corrupted = chunks(8, &data);
+        corrupted[2].data = Bytes::from(vec![0; CHUNK]);
+        let received = receiver
+            .receive(stream(&corrupted, 0, None), |_| true)
+            .await;
+        assert!(matches!(received.unwrap(), Received::Rejected));
+        // nothing is left to resume
+        assert_eq!(probe_offset(&receiver, 8).await, 0);
+    }
+
+    #[tokio::test]
+    async fn rejected_stream_should_not_touch_the_staging() {
+        let data: Vec<u8> = (0..4 * CHUNK).map(|i| (i % 251) as u8).collect();
+        let reqs = chunks(7, &data);
+        let receiver = SnapshotReceiver::new(Box::<MemorySnapshotAllocator>::default());
+        let _ignore = receiver.receive(stream(&reqs, 0, Some(2)), |_| true).await;
+        let received = receiver.receive(stream(&reqs, 0, None), |_| false).await;
+        assert!(matches!(received.unwrap(), Received::Rejected));
+        assert_eq!(probe_offset(&receiver, 7).await, 2 * CHUNK as u64);
+    }
+
+    #[tokio::test]
+    async fn stale_staging_should_be_cleaned_at_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let allocator = RocksSnapshotAllocator::new(dir.path());
+        let _stale = allocator.allocate_new_snapshot().await.unwrap();
+        let other = dir.path().join("other");
+        std::fs::create_dir(&other).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let receiver = SnapshotReceiver::new(Box::new(allocator));
+        receiver.clean_stale().await;
+        let left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(left, vec![other]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use tracing::error;
+
+use crate::{
+    cmd::{Command, CommandExecutor},
+    members::ServerId,
+};
+
+/// Gossip of the state hashes of the members
+///
+/// Followers piggyback the latest checkpoint of their state hash on the responses of
+/// the heartbeats once per interval, the leader compares it with its own checkpoint
+/// at the same revision. A member disagreeing with a quorum is reported as diverged,
+/// and so is the leader when a quorum disagrees with it.
+pub(super) struct StateHashGossip<C: Command> {
+    /// The executor maintaining the state hash, `None` disables the gossip
+    ce: Option<Arc<dyn CommandExecutor<C>>>,
+    /// The interval between two reports of a follower
+    interval: Duration,
+    /// The instant the state hash was last reported by this member
+    last_report: Mutex<Option<Instant>>,
+    /// The comparisons done by this member as leader
+    comparisons: Mutex<Comparisons>,
+}
+
+impl<C: Command> Debug for StateHashGossip<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateHashGossip")
+            .field("enabled", &self.ce.is_some())
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl<C: Command> StateHashGossip<C> {
+    /// New `StateHashGossip`, a zero interval disables it
+    pub(super) fn new(interval: Duration, ce: Option<Arc<dyn CommandExecutor<C>>>) -> Self {
+        Self {
+            ce: ce.filter(|_| !interval.is_zero()),
+            interval,
+            last_report: Mutex::new(None),
+            comparisons: Mutex::new(Comparisons::default()),
+        }
+    }
+
+    /// The state hash to be reported, `None` if it's not due
+    pub(super) fn report(&self) -> Option<(i64, u64)> {
+        let ce = self.ce.as_ref()?;
+        let mut last_report = self.last_report.lock();
+        if last_report.is_some_and(|at| at.elapsed() < self.interval) {
+            return None;
+        }
+        let report = ce.state_hash()?;
+        *last_report = Some(Instant::now());
+        Some(report)
+    }
+
+    /// Compare the state hash reported by a voter with the one of the leader, the
+    /// diverged members are reported to the executor
+    pub(super) fn compare(
+        &self,
+        leader_id: ServerId,
+        member: ServerId,
+        revision: i64,
+        hash: u64,
+        quorum: usize,
+    ) {
+        let Some(ref ce) = self.ce else {
+            return;
+        };
+        if revision <= 0 {
+            return;
+        }
+        // the leader may not retain the checkpoint, of a lagging follower for example
+        let Some(own) = ce.state_hash_at(revision) else {
+            return;
+        };
+        let diverged = self
+            .comparisons
+            .lock()
+            .record(leader_id, member, own == hash, quorum);
+        if !diverged.is_empty() {
+            error!(
+                "state machines of {diverged:?} diverged from the majority at revision {revision}"
+            );
+            ce.on_state_divergence(revision, diverged);
+        }
+    }
+}
+
+/// The latest comparisons of the state hashes of the voters with the leader
+#[derive(Debug, Default)]
+struct Comparisons {
+    /// Whether each voter agrees with the leader at its last reported revision
+    agreements: HashMap<ServerId, bool>,
+    /// The members already reported as diverged
+    diverged: HashSet<ServerId>,
+}
+
+impl Comparisons {
+    /// Record a comparison, returns the members newly found diverged
+    fn record(
+        &mut self,
+        leader_id: ServerId,
+        member: ServerId,
+        agrees: bool,
+        quorum: usize,
+    ) -> Vec<ServerId> {
+        let _prev = self.agreements.insert(member, agrees);
+        // the leader always agrees with itself
+        let agreed = self
+            .agreements
+            .values()
+            .filter(|&&a| a)
+            .count()
+            .wrapping_add(1);
+        let disagreed = self.agreements.values().filter(|&&a| !a).count();
+        let minority: Vec<_> = if agreed >= quorum {
+            self.agreements
+                .iter()
+                .filter(|&(_, &a)| !a)
+                .map(|(&id, _)| id)
+                .collect()
+        } else if disagreed >= quorum {
+            vec![leader_id]
+        } else {
+            vec![]
+        };
+        minority
+            .into_iter()
+            .filter(|&id| self.diverged.insert(id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use curp_test_utils::test_cmd::TestCommand;
+    use engine::Snapshot;
+
+    use super::*;
+    use crate::{InflightId, LogIndex};
+
+    /// An executor whose state hash at a revision is the sum of the revisions applied
+    /// up to it, a divergent apply is injected at `diverge_at`
+    #[derive(Debug, Default)]
+    struct HashCE {
+        /// The last applied revision
+        revision: AtomicI64,
+        /// The revision of the divergent apply
+        diverge_at: Option<i64>,
+        /// The reported divergences
+        divergences: Mutex<Vec<(i64, Vec<ServerId>)>>,
+    }
+
+    impl HashCE {
+        fn new(diverge_at: Option<i64>) -> Arc<Self> {
+            Arc::new(Self {
+                diverge_at,
+                ..Default::default()
+            })
+        }
+
+        fn apply(&self) {
+            let _prev = self.revision.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn hash(&self, revision: i64) -> u64 {
+            let diverged = self.diverge_at.is_some_and(|at| at <= revision);
+            (1..=revision.unsigned_abs())
+                .sum::<u64>()
+                .wrapping_add(u64::from(diverged))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CommandExecutor<TestCommand> for HashCE {
+        fn prepare(&self, _cmd: &TestCommand) -> Result<i64, <TestCommand as Command>::Error> {
+            unreachable!("no command is executed")
+        }
+
+        async fn execute(
+            &self,
+            _cmd: &TestCommand,
+        ) -> Result<<TestCommand as Command>::ER, <TestCommand as Command>::Error> {
+            unreachable!("no command is executed")
+        }
+
+        async fn after_sync(
+            &self,
+            _cmd: &TestCommand,
+            _index: LogIndex,
+            _revision: i64,
+        ) -> Result<<TestCommand as Command>::ASR, <TestCommand as Command>::Error> {
+            unreachable!("no command is executed")
+        }
+
+        fn set_last_applied(
+            &self,
+            _index: LogIndex,
+        ) -> Result<(), <TestCommand as Command>::Error> {
+            Ok(())
+        }
+
+        fn last_applied(&self) -> Result<LogIndex, <TestCommand as Command>::Error> {
+            Ok(0)
+        }
+
+        async fn snapshot(&self) -> Result<Snapshot, <TestCommand as Command>::Error> {
+            unreachable!("no snapshot is taken")
+        }
+
+        async fn reset(
+            &self,
+            _snapshot: Option<(Snapshot, LogIndex)>,
+        ) -> Result<(), <TestCommand as Command>::Error> {
+            Ok(())
+        }
+
+        fn trigger(&self, _id: InflightId, _index: LogIndex) {}
+
+        fn state_hash(&self) -> Option<(i64, u64)> {
+            let revision = self.revision.load(Ordering::Relaxed);
+            (revision > 0).then(|| (revision, self.hash(revision)))
+        }
+
+        fn state_hash_at(&self, revision: i64) -> Option<u64> {
+            (revision <= self.revision.load(Ordering::Relaxed)).then(|| self.hash(revision))
+        }
+
+        fn on_state_divergence(&self, revision: i64, members: Vec<u64>) {
+            self.divergences.lock().push((revision, members));
+        }
+    }
+
+    fn gossip(interval: Duration, ce: &Arc<HashCE>) -> StateHashGossip<TestCommand> {
+        StateHashGossip::new(interval, Some(Arc::clone(ce) as _))
+    }
+
+    #[tokio::test]
+    async fn divergent_apply_should_be_detected_within_a_few_intervals() {
+        let interval = Duration::from_millis(50);
+        let leader = HashCE::new(None);
+        let followers = [(1, HashCE::new(None)), (2, HashCE::new(Some(3)))];
+        let leader_gossip = gossip(interval, &leader);
+        let follower_gossips: Vec<_> = followers
+            .iter()
+            .map(|(id, ce)| (*id, gossip(interval, ce)))
+            .collect();
+
+        let start = Instant::now();
+        while leader.divergences.lock().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_millis(250),
+                "the divergence is not detected"
+            );
+            leader.apply();
+            for (_, ce) in &followers {
+                ce.apply();
+            }
+            // heartbeats
+            for (id, gossip) in &follower_gossips {
+                if let Some((revision, hash)) = gossip.report() {
+                    leader_gossip.compare(0, *id, revision, hash, 2);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let divergences = leader.divergences.lock();
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].1, vec![2]);
+    }
+
+    #[test]
+    fn zero_interval_should_disable_gossip() {
+        let ce = HashCE::new(None);
+        ce.apply();
+        assert!(gossip(Duration::ZERO, &ce).report().is_none());
+        assert_eq!(gossip(Duration::from_secs(1), &ce).report(), Some((1, 1)));
+    }
+
+    #[test]
+    fn minority_should_be_found_diverged_once() {
+        let mut comparisons = Comparisons::default();
+        // 1 agrees with the leader 0, a quorum of 3 out of 5 is not reached yet
+        assert!(comparisons.record(0, 1, true, 3).is_empty());
+        assert!(comparisons.record(0, 2, false, 3).is_empty());
+        assert_eq!(comparisons.record(0, 3, true, 3), vec![2]);
+        // already reported
+        assert!(comparisons.record(0, 2, false, 3).is_empty());
+        assert_eq!(comparisons.record(0, 4, false, 3), vec![4]);
+    }
+
+    #[test]
+    fn leader_should_be_found_diverged_when_a_quorum_disagrees() {
+        let mut comparisons = Comparisons::default();
+        assert!(comparisons.record(0, 1, false, 2).is_empty());
+        assert_eq!(comparisons.record(0, 2, false, 2), vec![0]);
+    }
+}
@@ -1,16 +1,24 @@
-use std::marker::PhantomData;
+use std::{fmt::Display, marker::PhantomData, path::Path};
 
 use async_trait::async_trait;
 use engine::{Engine, EngineType, StorageEngine, WriteOperation};
+use parking_lot::Mutex;
 use prost::Message;
-use utils::config::EngineConfig;
+use utils::config::{CurpConfig, EngineConfig};
 
-use super::{StorageApi, StorageError};
+use super::{
+    wal::{
+        config::{SyncPolicy, WALConfig},
+        storage::WALStorage,
+    },
+    StorageApi, StorageError,
+};
 use crate::{
     cmd::Command,
     log_entry::LogEntry,
     members::{ClusterInfo, ServerId},
     rpc::Member,
+    LogIndex,
 };
 
 /// Key for persisted state
@@ -19,6 +27,10 @@ const VOTE_FOR: &[u8] = b"VoteFor";
 const CLUSTER_ID: &[u8] = b"ClusterId";
 /// Key for member id
 const MEMBER_ID: &[u8] = b"MemberId";
+/// Key for cluster server version
+const CLUSTER_SERVER_VERSION: &[u8] = b"ClusterServerVersion";
+/// Key for the index of the last conf change entry reflected in the members
+const MEMBERSHIP_INDEX: &[u8] = b"MembershipIndex";
 
 /// Column family name for curp storage
 const CF: &str = "curp";
@@ -27,11 +39,25 @@ const LOGS_CF: &str = "logs";
 /// Column family name for members
 const MEMBERS_CF: &str = "members";
 
+/// Name of the WAL directory under the data directory
+const WAL_DIR: &str = "wal";
+
+/// The vote and the log entries recovered from the storage
+type Recovered<C> = (Option<(u64, ServerId)>, Vec<LogEntry<C>>);
+
 /// `DB` storage implementation
+///
+/// With a persistent engine, the log entries and the vote are kept in a WAL, the
+/// members in the engine.
 #[derive(Debug)]
 pub struct DB<C> {
     /// DB handle
     db: Engine,
+    /// The WAL of the log entries and the vote, `None` with a memory engine
+    wal: Option<Mutex<WALStorage<C>>>,
+    /// The vote and the log entries recovered from the WAL when it's opened, taken by
+    /// `recover`
+    recovered: Mutex<Option<Recovered<C>>>,
     /// Phantom
     phantom: PhantomData<C>,
 }
@@ -43,6 +69,9 @@ impl<C: Command> StorageApi for DB<C> {
 
     #[inline]
     async fn flush_voted_for(&self, term: u64, voted_for: ServerId) -> Result<(), StorageError> {
+        if let Some(ref wal) = self.wal {
+            return wal.lock().persist_vote(term, voted_for).map_err(wal_error);
+        }
         let bytes = bincode::serialize(&(term, voted_for))?;
         let op = WriteOperation::new_put(CF, VOTE_FOR.to_vec(), bytes);
         self.db.write_batch(vec![op], true)?;
@@ -52,6 +81,12 @@ impl<C: Command> StorageApi for DB<C> {
 
     #[inline]
     async fn put_log_entry(&self, entry: &LogEntry<Self::Command>) -> Result<(), StorageError> {
+        if let Some(ref wal) = self.wal {
+            return wal
+                .lock()
+                .append(std::slice::from_ref(entry))
+                .map_err(wal_error);
+        }
         let bytes = bincode::serialize(entry)?;
         let op = WriteOperation::new_put(LOGS_CF, entry.index.to_le_bytes().to_vec(), bytes);
         self.db.write_batch(vec![op], false)?;
@@ -59,6 +94,25 @@ impl<C: Command> StorageApi for DB<C> {
         Ok(())
     }
 
+    #[inline]
+    fn compact_log(&self, snapshot_index: LogIndex) -> Result<(), StorageError> {
+        // the memory engine keeps nothing over a restart
+        let Some(ref wal) = self.wal else {
+            return Ok(());
+        };
+        wal.lock()
+            .truncate_head(snapshot_index.saturating_sub(1))
+            .map_err(wal_error)
+    }
+
+    #[inline]
+    fn reset_log(&self, snapshot_index: LogIndex) -> Result<(), StorageError> {
+        let Some(ref wal) = self.wal else {
+            return Ok(());
+        };
+        wal.lock().reset(snapshot_index).map_err(wal_error)
+    }
+
     #[inline]
     fn put_member(&self, member: &Member) -> Result<(), StorageError> {
         let id = member.id;
@@ -89,6 +143,16 @@ impl<C: Command> StorageApi for DB<C> {
             MEMBER_ID.to_vec(),
             cluster_info.self_id().to_le_bytes().to_vec(),
         ));
+        ops.push(WriteOperation::new_put(
+            CF,
+            CLUSTER_SERVER_VERSION.to_vec(),
+            cluster_info.cluster_server_version().to_le_bytes().to_vec(),
+        ));
+        ops.push(WriteOperation::new_put(
+            CF,
+            MEMBERSHIP_INDEX.to_vec(),
+            cluster_info.membership_index().to_le_bytes().to_vec(),
+        ));
         for m in cluster_info.all_members_vec() {
             ops.push(WriteOperation::new_put(
                 MEMBERS_CF,
@@ -100,6 +164,25 @@ impl<C: Command> StorageApi for DB<C> {
         Ok(())
     }
 
+    #[inline]
+    fn put_cluster_server_version(&self, version: u32) -> Result<(), StorageError> {
+        let op = WriteOperation::new_put(
+            CF,
+            CLUSTER_SERVER_VERSION.to_vec(),
+            version.to_le_bytes().to_vec(),
+        );
+        self.db.write_batch(vec![op], true)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn put_membership_index(&self, index: u64) -> Result<(), StorageError> {
+        let op =
+            WriteOperation::new_put(CF, MEMBERSHIP_INDEX.to_vec(), index.to_le_bytes().to_vec());
+        self.db.write_batch(vec![op], true)?;
+        Ok(())
+    }
+
     #[inline]
     fn recover_cluster_info(&self) -> Result<Option<ClusterInfo>, StorageError> {
         let cluster_id = self.db.get(CF, CLUSTER_ID)?.map(|bytes| {
@@ -118,6 +201,17 @@ impl<C: Command> StorageApi for DB<C> {
                     .unwrap_or_else(|e| unreachable!("cannot decode index from backend, {e:?}")),
             )
         });
+        // a data dir written before the version was negotiated has no version
+        let server_version = self.db.get(CF, CLUSTER_SERVER_VERSION)?.map_or(0, |bytes| {
+            u32::from_le_bytes(bytes.as_slice().try_into().unwrap_or_else(|e| {
+                unreachable!("cannot decode server version from backend, {e:?}")
+            }))
+        });
+        let membership_index = self.db.get(CF, MEMBERSHIP_INDEX)?.map_or(0, |bytes| {
+            u64::from_le_bytes(bytes.as_slice().try_into().unwrap_or_else(|e| {
+                unreachable!("cannot decode membership index from backend, {e:?}")
+            }))
+        });
         let mut members = vec![];
         for (_k, v) in self.db.get_all(MEMBERS_CF)? {
             let member = Member::decode(v.as_ref())?;
@@ -126,7 +220,10 @@ impl<C: Command> StorageApi for DB<C> {
 
         let cluster_info = match (cluster_id, member_id, members.is_empty()) {
             (Some(cluster_id), Some(member_id), false) => {
-                Some(ClusterInfo::new(cluster_id, member_id, members))
+                let cluster_info = ClusterInfo::new(cluster_id, member_id, members);
+                cluster_info.set_cluster_server_version(server_version);
+                cluster_info.set_membership_index(membership_index);
+                Some(cluster_info)
             }
             _ => None,
         };
@@ -138,6 +235,109 @@ impl<C: Command> StorageApi for DB<C> {
     async fn recover(
         &self,
     ) -> Result<(Option<(u64, ServerId)>, Vec<LogEntry<Self::Command>>), StorageError> {
+        if self.wal.is_none() {
+            return self.recover_from_engine();
+        }
+        self.recovered
+            .lock()
+            .take()
+            .ok_or_else(|| wal_error("the WAL has been recovered"))
+    }
+}
+
+impl<C: Command> DB<C> {
+    /// Create a new CURP `DB`, a persistent engine keeps the log entries in a WAL
+    /// under the data directory
+    ///
+    /// The log entries written by an earlier version to the engine are moved to the WAL.
+    ///
+    /// # Errors
+    /// Will return `StorageError` if failed to open the storage
+    #[inline]
+    pub fn open(config: &CurpConfig) -> Result<Self, StorageError> {
+        let (engine_type, wal_dir) = match config.engine_cfg {
+            EngineConfig::Memory => (EngineType::Memory, None),
+            EngineConfig::RocksDB(ref path) => {
+                (EngineType::Rocks(path.clone()), Some(path.join(WAL_DIR)))
+            }
+            _ => unreachable!("Not supported storage type"),
+        };
+        let db = Engine::new(engine_type, &[CF, LOGS_CF, MEMBERS_CF])?;
+        let mut storage = Self {
+            db,
+            wal: None,
+            recovered: Mutex::new(None),
+            phantom: PhantomData,
+        };
+        let Some(wal_dir) = wal_dir else {
+            return Ok(storage);
+        };
+        let sync_policy = match config.wal_sync_bytes {
+            0 => SyncPolicy::Always,
+            bytes => SyncPolicy::Bytes(bytes),
+        };
+        let wal_config = WALConfig::new(wal_dir)
+            .with_max_segment_size(config.wal_max_segment_size)
+            .with_sync_policy(sync_policy);
+        let mut wal = WALStorage::new(wal_config).map_err(wal_error)?;
+        let (mut voted_for, mut entries) = wal.recover().map_err(wal_error)?;
+        if voted_for.is_none() && entries.is_empty() {
+            (voted_for, entries) = storage.migrate_to_wal(&mut wal)?;
+        }
+        storage.wal = Some(Mutex::new(wal));
+        *storage.recovered.get_mut() = Some((voted_for, entries));
+        Ok(storage)
+    }
+
+    /// Read the vote and the log entries from the data directory of a stopped server
+    /// without changing it
+    ///
+    /// Unlike `open`, nothing is migrated, truncated or recycled, and the engine is not
+    /// opened at all, so reading is safe on a copy kept for debugging.
+    ///
+    /// # Errors
+    /// Will return `StorageError` if the data directory has no WAL or it can't be read
+    #[inline]
+    pub fn read_log(data_dir: impl AsRef<Path>) -> Result<Recovered<C>, StorageError> {
+        let wal_dir = data_dir.as_ref().join(WAL_DIR);
+        if !wal_dir.is_dir() {
+            return Err(wal_error(format!(
+                "no WAL in {}, the log written by an earlier version is moved to the WAL \
+                 once a server opens the data directory",
+                data_dir.as_ref().display()
+            )));
+        }
+        WALStorage::read(wal_dir).map_err(wal_error)
+    }
+
+    /// Move the vote and the log entries kept in the engine to the WAL
+    fn migrate_to_wal(&self, wal: &mut WALStorage<C>) -> Result<Recovered<C>, StorageError> {
+        let (voted_for, entries) = self.recover_from_engine()?;
+        if entries.is_empty() && voted_for.is_none() {
+            return Ok((voted_for, entries));
+        }
+        wal.append(&entries).map_err(wal_error)?;
+        wal.sync().map_err(wal_error)?;
+        if let Some((term, id)) = voted_for {
+            wal.persist_vote(term, id).map_err(wal_error)?;
+        }
+        let keys: Vec<_> = self
+            .db
+            .get_all(LOGS_CF)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let ops = keys
+            .iter()
+            .map(|key| WriteOperation::new_delete(LOGS_CF, key))
+            .chain([WriteOperation::new_delete(CF, VOTE_FOR)])
+            .collect();
+        self.db.write_batch(ops, true)?;
+        Ok((voted_for, entries))
+    }
+
+    /// Recover the vote and the log entries kept in the engine
+    fn recover_from_engine(&self) -> Result<Recovered<C>, StorageError> {
         let voted_for = self
             .db
             .get(CF, VOTE_FOR)?
@@ -161,23 +361,9 @@ impl<C: Command> StorageApi for DB<C> {
     }
 }
 
-impl<C> DB<C> {
-    /// Create a new CURP `DB`
-    /// # Errors
-    /// Will return `StorageError` if failed to open the storage
-    #[inline]
-    pub fn open(config: &EngineConfig) -> Result<Self, StorageError> {
-        let engine_type = match *config {
-            EngineConfig::Memory => EngineType::Memory,
-            EngineConfig::RocksDB(ref path) => EngineType::Rocks(path.clone()),
-            _ => unreachable!("Not supported storage type"),
-        };
-        let db = Engine::new(engine_type, &[CF, LOGS_CF, MEMBERS_CF])?;
-        Ok(Self {
-            db,
-            phantom: PhantomData,
-        })
-    }
+/// Convert an error of the WAL into a `StorageError`
+fn wal_error(err: impl Display) -> StorageError {
+    StorageError::WAL(err.to_string())
 }
 
 #[cfg(test)]
@@ -187,15 +373,23 @@ mod tests {
     use curp_test_utils::{sleep_secs, test_cmd::TestCommand};
     use test_macros::abort_on_panic;
     use tokio::fs::remove_dir_all;
+    use utils::config::CurpConfigBuilder;
 
     use super::*;
     use crate::rpc::ProposeId;
 
+    fn curp_config(engine_cfg: EngineConfig) -> CurpConfig {
+        CurpConfigBuilder::default()
+            .engine_cfg(engine_cfg)
+            .build()
+            .unwrap()
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn create_and_recover() -> Result<(), Box<dyn Error>> {
         let db_dir = tempfile::tempdir().unwrap().into_path();
-        let storage_cfg = EngineConfig::RocksDB(db_dir.clone());
+        let storage_cfg = curp_config(EngineConfig::RocksDB(db_dir.clone()));
         {
             let s = DB::<TestCommand>::open(&storage_cfg)?;
             s.flush_voted_for(1, 222).await?;
@@ -222,4 +416,95 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn recover_should_start_after_a_reset_log() -> Result<(), Box<dyn Error>> {
+        let db_dir = tempfile::tempdir().unwrap().into_path();
+        let storage_cfg = curp_config(EngineConfig::RocksDB(db_dir.clone()));
+        let entry = |index| {
+            LogEntry::new(
+                index,
+                3,
+                ProposeId(1, index),
+                Arc::new(TestCommand::default()),
+            )
+        };
+        {
+            let s = DB::<TestCommand>::open(&storage_cfg)?;
+            for index in 1..=3 {
+                s.put_log_entry(&entry(index)).await?;
+            }
+            s.compact_log(2)?;
+            // a snapshot at 10 is installed
+            s.reset_log(10)?;
+            s.put_log_entry(&entry(11)).await?;
+        }
+
+        {
+            let s = DB::<TestCommand>::open(&storage_cfg)?;
+            let (_voted_for, entries) = s.recover().await?;
+            let indexes: Vec<_> = entries.iter().map(|e| e.index).collect();
+            assert_eq!(indexes, [11]);
+        }
+
+        remove_dir_all(db_dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn log_entries_in_engine_should_be_moved_to_wal() -> Result<(), Box<dyn Error>> {
+        let db_dir = tempfile::tempdir().unwrap().into_path();
+        {
+            // written by a version keeping the log entries in the engine
+            let db = Engine::new(
+                EngineType::Rocks(db_dir.clone()),
+                &[CF, LOGS_CF, MEMBERS_CF],
+            )?;
+            let mut ops = vec![WriteOperation::new_put(
+                CF,
+                VOTE_FOR.to_vec(),
+                bincode::serialize(&(3_u64, 111_u64))?,
+            )];
+            for index in 1..=3 {
+                let entry = LogEntry::new(
+                    index,
+                    3,
+                    ProposeId(1, index),
+                    Arc::new(TestCommand::default()),
+                );
+                ops.push(WriteOperation::new_put(
+                    LOGS_CF,
+                    index.to_le_bytes().to_vec(),
+                    bincode::serialize(&entry)?,
+                ));
+            }
+            db.write_batch(ops, true)?;
+        }
+
+        let storage_cfg = curp_config(EngineConfig::RocksDB(db_dir.clone()));
+        {
+            let s = DB::<TestCommand>::open(&storage_cfg)?;
+            let (voted_for, entries) = s.recover().await?;
+            assert_eq!(voted_for, Some((3, 111)));
+            assert_eq!(entries.len(), 3);
+            assert!(s.db.get_all(LOGS_CF)?.is_empty());
+            let entry = LogEntry::new(4, 3, ProposeId(1, 4), Arc::new(TestCommand::default()));
+            s.put_log_entry(&entry).await?;
+        }
+
+        {
+            let s = DB::<TestCommand>::open(&storage_cfg)?;
+            let (voted_for, entries) = s.recover().await?;
+            assert_eq!(voted_for, Some((3, 111)));
+            let indexes: Vec<_> = entries.iter().map(|e| e.index).collect();
+            assert_eq!(indexes, [1, 2, 3, 4]);
+        }
+
+        remove_dir_all(db_dir).await?;
+
+        Ok(())
+    }
 }
@@ -7,6 +7,7 @@ use crate::{
     log_entry::LogEntry,
     members::{ClusterInfo, ServerId},
     rpc::Member,
+    LogIndex,
 };
 
 /// Storage layer error
@@ -20,6 +21,9 @@ pub enum StorageError {
     /// Rocksdb error
     #[error("internal error, {0}")]
     Internal(#[from] EngineError),
+    /// WAL error
+    #[error("wal error, {0}")]
+    WAL(String),
 }
 
 impl From<bincode::Error> for StorageError {
@@ -67,6 +71,18 @@ pub trait StorageApi: Send + Sync {
     /// Return `StorageError` when it failed to store the cluster info to underlying database.
     fn put_cluster_info(&self, cluster_info: &ClusterInfo) -> Result<(), StorageError>;
 
+    /// Put the cluster server version into storage
+    ///
+    /// # Errors
+    /// Return `StorageError` when it failed to store the version to underlying database.
+    fn put_cluster_server_version(&self, version: u32) -> Result<(), StorageError>;
+
+    /// Put the index of the last conf change entry reflected in the members into storage
+    ///
+    /// # Errors
+    /// Return `StorageError` when it failed to store the index to underlying database.
+    fn put_membership_index(&self, index: u64) -> Result<(), StorageError>;
+
     /// Recover `ClusterInfo` from storage
     ///
     /// # Errors
@@ -79,6 +95,20 @@ pub trait StorageApi: Send + Sync {
     /// Return `StorageError` when it failed to store the given log entry info to underlying database.
     async fn put_log_entry(&self, entry: &LogEntry<Self::Command>) -> Result<(), StorageError>;
 
+    /// Remove the log entries covered by a snapshot at `snapshot_index`, the entry at the
+    /// index is kept so that its term is known after a restart
+    ///
+    /// # Errors
+    /// Return `StorageError` when it failed to remove the log entries from underlying database.
+    fn compact_log(&self, snapshot_index: LogIndex) -> Result<(), StorageError>;
+
+    /// Drop all log entries for an installed snapshot at `snapshot_index`, the next entry
+    /// put is the one after it
+    ///
+    /// # Errors
+    /// Return `StorageError` when it failed to remove the log entries from underlying database.
+    fn reset_log(&self, snapshot_index: LogIndex) -> Result<(), StorageError>;
+
     /// Recover from persisted storage
     ///
     /// # Errors
@@ -1,15 +1,13 @@
 use std::{io, marker::PhantomData};
 
 use clippy_utilities::NumericCast;
-use curp_external_api::LogIndex;
-use serde::{de::DeserializeOwned, Serialize};
+use curp_external_api::{cmd::Command, LogIndex};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use super::{
     error::{CorruptType, WALError},
     framed::{Decoder, Encoder},
-    util::{get_checksum, validate_data},
 };
 use crate::log_entry::LogEntry;
 
@@ -35,6 +33,9 @@ trait FrameEncoder {
 }
 
 /// The WAL codec
+///
+/// The checksum of every commit frame is seeded with the id of the segment, so that the
+/// stale frames left in a recycled segment file never pass the validation.
 #[allow(clippy::upper_case_acronyms)] // The WAL needs to be all upper cases
 #[derive(Debug)]
 pub(super) struct WAL<C, H = Sha256> {
@@ -42,6 +43,8 @@ pub(super) struct WAL<C, H = Sha256> {
     frames: Vec<DataFrameOwned<C>>,
     /// The hasher state for decoding
     hasher: H,
+    /// The seeded hasher state every checksum starts from
+    seed: H,
 }
 
 /// Union type of WAL frames
@@ -87,11 +90,22 @@ struct CommitFrame {
 }
 
 impl<C> WAL<C> {
-    /// Creates a new WAL codec
+    /// Creates a new WAL codec whose checksums are not seeded
     pub(super) fn new() -> Self {
         Self {
             frames: Vec::new(),
             hasher: Sha256::new(),
+            seed: Sha256::new(),
+        }
+    }
+
+    /// Creates a new WAL codec whose checksums are seeded with `seed`
+    pub(super) fn with_seed(seed: u64) -> Self {
+        let seed = Sha256::new_with_prefix(seed.to_le_bytes());
+        Self {
+            frames: Vec::new(),
+            hasher: seed.clone(),
+            seed,
         }
     }
 
@@ -112,14 +126,21 @@ impl<C> WAL<C> {
 
 impl<C> Encoder<Vec<DataFrame<'_, C>>> for WAL<C>
 where
-    C: Serialize,
+    C: Command,
 {
     type Error = io::Error;
 
     /// Encodes a frame
     fn encode(&mut self, frames: Vec<DataFrame<'_, C>>) -> Result<Vec<u8>, Self::Error> {
         let mut frame_data: Vec<_> = frames.into_iter().flat_map(|f| f.encode()).collect();
-        let commit_frame = CommitFrame::new_from_data(&frame_data);
+        let commit_frame = CommitFrame {
+            checksum: self
+                .seed
+                .clone()
+                .chain_update(&frame_data)
+                .finalize()
+                .to_vec(),
+        };
         frame_data.extend_from_slice(&commit_frame.encode());
 
         Ok(frame_data)
@@ -128,7 +149,7 @@ where
 
 impl<C> Decoder for WAL<C>
 where
-    C: Serialize + DeserializeOwned,
+    C: Command,
 {
     type Item = Vec<DataFrameOwned<C>>;
 
@@ -150,8 +171,8 @@ where
                     self.hasher.update(decoded_bytes);
                 }
                 WALFrame::Commit(commit) => {
-                    let checksum = self.hasher.clone().finalize();
-                    self.hasher.reset();
+                    let checksum =
+                        std::mem::replace(&mut self.hasher, self.seed.clone()).finalize();
                     if commit.validate(&checksum) {
                         return Ok((self.frames.drain(..).collect(), cursor));
                     }
@@ -170,7 +191,7 @@ where
 )]
 impl<C> WALFrame<C>
 where
-    C: DeserializeOwned,
+    C: Command,
 {
     /// Decodes a frame from the buffer
     ///
@@ -253,6 +274,7 @@ where
 
 impl<C> DataFrameOwned<C> {
     /// Converts `DataFrameOwned` to `DataFrame`
+    #[cfg(test)]
     pub(super) fn get_ref(&self) -> DataFrame<'_, C> {
         match *self {
             DataFrameOwned::Entry(ref entry) => DataFrame::Entry(entry),
@@ -272,7 +294,7 @@ impl<C> FrameType for DataFrame<'_, C> {
 
 impl<C> FrameEncoder for DataFrame<'_, C>
 where
-    C: Serialize,
+    C: Command,
 {
     #[allow(clippy::arithmetic_side_effects)] // The integer shift is safe
     fn encode(&self) -> Vec<u8> {
@@ -299,13 +321,6 @@ where
 }
 
 impl CommitFrame {
-    /// Creates a commit frame of data
-    fn new_from_data(data: &[u8]) -> Self {
-        Self {
-            checksum: get_checksum(data).to_vec(),
-        }
-    }
-
     /// Validates the checksum
     fn validate(&self, checksum: &[u8]) -> bool {
         *checksum == self.checksum
@@ -362,6 +377,24 @@ mod tests {
         assert_eq!(index, 1);
     }
 
+    #[test]
+    fn frame_of_other_seed_will_be_rejected() {
+        let entry = LogEntry::<TestCommand>::new(1, 1, ProposeId(1, 2), EntryData::Empty);
+        let data_frame = DataFrameOwned::Entry(entry);
+        let encoded = WAL::<TestCommand>::with_seed(1)
+            .encode(vec![data_frame.get_ref()])
+            .unwrap();
+
+        assert!(WAL::<TestCommand>::with_seed(1).decode(&encoded).is_ok());
+        let err = WAL::<TestCommand>::with_seed(2)
+            .decode(&encoded)
+            .unwrap_err();
+        assert!(
+            matches!(err, WALError::Corrupted(CorruptType::Checksum)),
+            "error {err} not match"
+        );
+    }
+
     #[tokio::test]
     async fn frame_zero_write_will_be_detected() {
         let mut codec = WAL::<TestCommand>::new();
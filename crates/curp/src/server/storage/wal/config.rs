@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+
+/// Size in bytes per segment, default is 64MiB
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Max number of the files of removed segments kept for reuse, default is 2
+const DEFAULT_MAX_RECYCLED_SEGMENTS: usize = 2;
+
+/// The fsync policy of the WAL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncPolicy {
+    /// Fsync after every append
+    Always,
+    /// Fsync once the given number of bytes have been appended since the last fsync
+    Bytes(u64),
+}
+
+/// The config for WAL
+#[derive(Debug, Clone)]
+pub(crate) struct WALConfig {
+    /// The path of this config
+    pub(super) dir: PathBuf,
+    /// The maximum size of this segment
+    ///
+    /// NOTE: This is a soft limit, the actual size may larger than this
+    pub(super) max_segment_size: u64,
+    /// The fsync policy
+    pub(super) sync_policy: SyncPolicy,
+    /// Max number of the files of removed segments kept for reuse, zero disables the
+    /// recycling
+    pub(super) max_recycled_segments: usize,
+}
+
+impl WALConfig {
+    /// Creates a new `WALConfig`
+    pub(crate) fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            max_segment_size: DEFAULT_SEGMENT_SIZE,
+            sync_policy: SyncPolicy::Always,
+            max_recycled_segments: DEFAULT_MAX_RECYCLED_SEGMENTS,
+        }
+    }
+
+    /// Sets the `max_segment_size`
+    pub(crate) fn with_max_segment_size(self, size: u64) -> Self {
+        Self {
+            max_segment_size: size,
+            ..self
+        }
+    }
+
+    /// Sets the `sync_policy`
+    pub(crate) fn with_sync_policy(self, sync_policy: SyncPolicy) -> Self {
+        Self {
+            sync_policy,
+            ..self
+        }
+    }
+}
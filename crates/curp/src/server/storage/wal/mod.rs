@@ -1,8 +1,9 @@
-#![allow(unused)] // TODO: remove this until used
-
 /// The WAL codec
 mod codec;
 
+/// The config for `WALStorage`
+pub(crate) mod config;
+
 /// WAL errors
 mod error;
 
@@ -21,11 +22,18 @@ mod util;
 /// Framed traits
 mod framed;
 
+/// The WAL storage
+pub(crate) mod storage;
+
 /// The magic of the WAL file
 const WAL_MAGIC: u32 = 0xd86e_0be2;
 
-/// The current WAL version
-const WAL_VERSION: u8 = 0x00;
+/// The current WAL version, the checksums of the frames are seeded with the segment id
+const WAL_VERSION: u8 = 0x01;
+
+/// The WAL version whose frame checksums are not seeded, still read to recover the
+/// segments written by it
+const WAL_VERSION_UNSEEDED: u8 = 0x00;
 
 /// The wal file extension
 const WAL_FILE_EXT: &str = ".wal";
@@ -1,4 +1,5 @@
 use std::{
+    collections::VecDeque,
     io,
     path::{Path, PathBuf},
     sync::{
@@ -14,12 +15,21 @@ use event_listener::Event;
 use thiserror::Error;
 use tracing::error;
 
-use super::util::LockedFile;
+use super::util::{get_file_paths_with_ext, LockedFile};
 
 /// The temp file extension
 const TEMP_FILE_EXT: &str = ".tmp";
 
+/// The extension of the files recycled from the removed segments
+const RECYCLED_FILE_EXT: &str = ".recycled";
+
 /// The file pipeline, used for pipelining the creation of temp file
+///
+/// Files are pre-allocated to the segment size ahead of time by a background task, so
+/// that a fresh file is always ready when a segment rolls. The files of the removed
+/// segments are recycled and handed out first: overwriting the blocks that are already
+/// written doesn't extend the file, nor convert the pre-allocated extents, so the
+/// fsyncs of the appends need no metadata journaling.
 pub(super) struct FilePipeline {
     /// The directory where the temp files are created
     dir: PathBuf,
@@ -34,14 +44,26 @@ pub(super) struct FilePipeline {
     stopped: Arc<AtomicBool>,
     /// Join handle of the allocation task
     file_alloc_task_handle: Option<JoinHandle<()>>,
+    /// The recycled files, which are handed out before the pre-allocated ones
+    recycled: VecDeque<LockedFile>,
+    /// Max number of the recycled files
+    max_recycled: usize,
+    /// The number used to name the next recycled file
+    recycled_count: u64,
 }
 
 impl FilePipeline {
-    /// Creates a new `FilePipeline`
-    pub(super) fn new(dir: PathBuf, file_size: u64) -> Self {
+    /// Creates a new `FilePipeline`, `max_recycled` files of the removed segments are
+    /// kept for reuse
+    pub(super) fn new(dir: PathBuf, file_size: u64, max_recycled: usize) -> Self {
         if let Err(e) = Self::clean_up(&dir) {
             error!("Failed to clean up tmp files: {e}");
         }
+        let (recycled, recycled_count) =
+            Self::recover_recycled(&dir, max_recycled).unwrap_or_else(|e| {
+                error!("Failed to recover recycled files: {e}");
+                (VecDeque::new(), 0)
+            });
 
         let (file_tx, file_rx) = flume::bounded(1);
         let dir_c = dir.clone();
@@ -80,6 +102,9 @@ impl FilePipeline {
                 file_iter: Some(file_rx.into_iter()),
                 stopped,
                 file_alloc_task_handle: Some(file_alloc_task_handle),
+                recycled,
+                max_recycled,
+                recycled_count,
             }
         }
 
@@ -115,6 +140,9 @@ impl FilePipeline {
                 file_iter: Some(file_rx.into_iter()),
                 stopped,
                 file_alloc_task_handle: None,
+                recycled,
+                max_recycled,
+                recycled_count,
             }
         }
     }
@@ -124,6 +152,46 @@ impl FilePipeline {
         self.stopped.store(true, Ordering::Relaxed);
     }
 
+    /// Number of files that could still be recycled
+    pub(super) fn recycle_room(&self) -> usize {
+        self.max_recycled.saturating_sub(self.recycled.len())
+    }
+
+    /// Recycles the file of a removed segment, the file is renamed so that it's no
+    /// longer recognized as a segment
+    pub(super) fn recycle(&mut self, file: LockedFile) -> io::Result<()> {
+        let file = file.rename(format!("{}{RECYCLED_FILE_EXT}", self.recycled_count))?;
+        self.recycled_count = self.recycled_count.wrapping_add(1);
+        self.recycled.push_back(file);
+        Ok(())
+    }
+
+    /// Recovers the files recycled before a restart, the ones beyond `max_recycled`
+    /// are removed
+    fn recover_recycled(
+        dir: &PathBuf,
+        max_recycled: usize,
+    ) -> io::Result<(VecDeque<LockedFile>, u64)> {
+        let mut recycled = VecDeque::new();
+        let mut recycled_count = 0;
+        for path in get_file_paths_with_ext(dir, RECYCLED_FILE_EXT)? {
+            if let Some(n) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(RECYCLED_FILE_EXT))
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                recycled_count = recycled_count.max(n.wrapping_add(1));
+            }
+            if recycled.len() < max_recycled {
+                recycled.push_back(LockedFile::open_rw(path)?);
+            } else {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok((recycled, recycled_count))
+    }
+
     /// Allocates a a new tempfile
     fn alloc(dir: &PathBuf, file_size: u64, file_count: &mut usize) -> io::Result<LockedFile> {
         let fpath = PathBuf::from(dir).join(format!("{file_count}{TEMP_FILE_EXT}"));
@@ -150,6 +218,10 @@ impl FilePipeline {
 impl Drop for FilePipeline {
     fn drop(&mut self) {
         self.stop();
+        // The recycled files are kept for the next start
+        for file in self.recycled.drain(..) {
+            drop(file.into_std());
+        }
         // Drops the file rx so that the allocation task could exit
         drop(self.file_iter.take());
         if let Some(Err(e)) = self.file_alloc_task_handle.take().map(JoinHandle::join) {
@@ -165,6 +237,9 @@ impl Iterator for FilePipeline {
         if self.stopped.load(Ordering::Relaxed) {
             return None;
         }
+        if let Some(file) = self.recycled.pop_front() {
+            return Some(Ok(file));
+        }
         self.file_iter
             .as_mut()
             .unwrap_or_else(|| unreachable!("Option is always `Some`"))
@@ -178,6 +253,7 @@ impl std::fmt::Debug for FilePipeline {
         f.debug_struct("FilePipeline")
             .field("dir", &self.dir)
             .field("file_size", &self.file_size)
+            .field("recycled", &self.recycled.len())
             .finish()
     }
 }
@@ -191,7 +267,7 @@ mod tests {
     async fn file_pipeline_is_ok() {
         let file_size = 1024;
         let dir = tempfile::tempdir().unwrap();
-        let mut pipeline = FilePipeline::new(dir.as_ref().into(), file_size);
+        let mut pipeline = FilePipeline::new(dir.as_ref().into(), file_size, 0);
 
         let check_size = |mut file: LockedFile| {
             let file = file.into_std();
@@ -204,4 +280,27 @@ mod tests {
         pipeline.stop();
         assert!(pipeline.next().is_none());
     }
+
+    #[tokio::test]
+    async fn recycled_files_are_kept_and_handed_out_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pipeline = FilePipeline::new(dir.as_ref().into(), 1024, 1);
+        let mut file = LockedFile::open_rw(dir.path().join("segment.wal")).unwrap();
+        file.preallocate(4096).unwrap();
+        pipeline.recycle(file).unwrap();
+        assert_eq!(pipeline.recycle_room(), 0);
+        drop(pipeline);
+
+        let recycled = get_file_paths_with_ext(dir.path(), RECYCLED_FILE_EXT).unwrap();
+        assert_eq!(recycled.len(), 1);
+        let mut pipeline = FilePipeline::new(dir.as_ref().into(), 1024, 1);
+        assert_eq!(pipeline.recycle_room(), 0);
+        let file = pipeline.next().unwrap().unwrap();
+        assert_eq!(file.path(), recycled[0]);
+        assert_eq!(file.into_std().metadata().unwrap().len(), 4096);
+        assert_eq!(pipeline.recycle_room(), 1);
+        // the pre-allocated ones follow
+        let file = pipeline.next().unwrap().unwrap();
+        assert!(file.path().to_str().unwrap().ends_with(TEMP_FILE_EXT));
+    }
 }
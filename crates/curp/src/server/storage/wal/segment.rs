@@ -1,28 +1,29 @@
 use std::{
     fs::File,
-    io::{self, Read, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     iter,
+    path::Path,
     pin::Pin,
     sync::Arc,
     task::Poll,
 };
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
-use curp_external_api::LogIndex;
+use curp_external_api::{cmd::Command, LogIndex};
 use futures::{ready, FutureExt, SinkExt};
-use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
     sync::Mutex,
 };
 use tokio_stream::StreamExt;
+use tracing::warn;
 
 use super::{
     codec::{DataFrame, DataFrameOwned, WAL},
     error::{CorruptType, WALError},
     framed::{Decoder, Encoder},
     util::{get_checksum, parse_u64, validate_data, LockedFile},
-    WAL_FILE_EXT, WAL_MAGIC, WAL_VERSION,
+    WAL_FILE_EXT, WAL_MAGIC, WAL_VERSION, WAL_VERSION_UNSEEDED,
 };
 use crate::log_entry::LogEntry;
 
@@ -44,22 +45,28 @@ pub(super) struct WALSegment {
     size: u64,
     /// The highest index of the segment
     seal_index: LogIndex,
+    /// The version of the segment file
+    version: u8,
 }
 
 impl WALSegment {
     /// Creates a new `WALSegment`
+    ///
+    /// The header is written before the file is renamed, so that a segment file always
+    /// has a valid header, even if the file is recycled from an old segment
     pub(super) fn create(
-        tmp_file: LockedFile,
+        mut tmp_file: LockedFile,
         base_index: LogIndex,
         segment_id: u64,
         size_limit: u64,
     ) -> io::Result<Self> {
-        let segment_name = Self::segment_name(segment_id, base_index);
-        let mut locked_file = tmp_file.rename(segment_name)?;
-        let mut file = locked_file.into_std();
-        file.write_all(&Self::gen_header(base_index, segment_id))?;
+        let file = tmp_file.file();
+        file.rewind()?;
+        file.write_all(&Self::gen_header(base_index, segment_id, WAL_VERSION))?;
         file.flush()?;
         file.sync_data()?;
+        let segment_name = Self::segment_name(segment_id, base_index);
+        let file = tmp_file.rename(segment_name)?.into_std();
 
         Ok(Self {
             base_index,
@@ -69,36 +76,97 @@ impl WALSegment {
             size: WAL_HEADER_SIZE.numeric_cast(),
             // For convenience we set it to largest u64 value that represent not sealed
             seal_index: u64::MAX,
+            version: WAL_VERSION,
         })
     }
 
     /// Open an existing WAL segment file
     pub(super) fn open(mut locked_file: LockedFile, size_limit: u64) -> Result<Self, WALError> {
-        let mut file = locked_file.into_std();
-        let size = file.metadata()?.len();
+        Self::from_file(locked_file.into_std(), size_limit)
+    }
+
+    /// Open an existing WAL segment file for reading only, the file is not locked
+    pub(super) fn open_read_only(path: impl AsRef<Path>) -> Result<Self, WALError> {
+        Self::from_file(File::open(path)?, u64::MAX)
+    }
+
+    /// Read the header of an opened segment file
+    fn from_file(mut file: File, size_limit: u64) -> Result<Self, WALError> {
         let mut buf = vec![0; WAL_HEADER_SIZE];
         file.read_exact(&mut buf)?;
-        let (base_index, segment_id) = Self::parse_header(&buf)?;
+        let (base_index, segment_id, version) = Self::parse_header(&buf)?;
 
         Ok(Self {
             base_index,
             segment_id,
             size_limit,
             file,
-            size,
+            // The size will be updated after reading the frames
+            size: WAL_HEADER_SIZE.numeric_cast(),
             // Index 0 means the seal_index hasn't been read yet
             seal_index: 0,
+            version,
         })
     }
 
     /// Recover log entries from a `WALSegment`
-    pub(super) fn recover_segment_logs<C>(
-        &mut self,
-    ) -> Result<impl Iterator<Item = LogEntry<C>>, WALError>
+    ///
+    /// Reading stops at the first torn or corrupted frame batch, or the first batch that
+    /// skips an index, which could be stale data left in a recycled file. Anything after
+    /// the seal of the segment is ignored, otherwise the segment is truncated there.
+    /// Returns the recovered entries and whether the segment has been truncated.
+    pub(super) fn recover_segment_logs<C>(&mut self) -> Result<(Vec<LogEntry<C>>, bool), WALError>
     where
-        C: Serialize + DeserializeOwned + 'static,
+        C: Command,
     {
-        let frame_batches = self.read_all(WAL::<C>::new())?;
+        let (entries, torn) = self.read_segment_logs()?;
+        if torn {
+            warn!(
+                "truncating segment {} at offset {}",
+                self.segment_id, self.size
+            );
+            self.truncate()?;
+        }
+        Ok((entries, torn))
+    }
+
+    /// Read log entries from a `WALSegment` without changing the file
+    ///
+    /// Works like `recover_segment_logs`, but a torn tail is left in place. Returns the
+    /// read entries and whether the segment has a torn tail.
+    pub(super) fn read_segment_logs<C>(&mut self) -> Result<(Vec<LogEntry<C>>, bool), WALError>
+    where
+        C: Command,
+    {
+        // Batches may start from an earlier index to replace the conflicting entries,
+        // but never later than the next index
+        let mut next_index = self.base_index;
+        let is_continuous = |frames: &Vec<DataFrameOwned<C>>| {
+            let mut indices = frames.iter().filter_map(|f| {
+                if let DataFrameOwned::Entry(ref e) = *f {
+                    Some(e.index)
+                } else {
+                    None
+                }
+            });
+            let Some(first) = indices.next() else {
+                return true;
+            };
+            if first > next_index {
+                return false;
+            }
+            let mut last = first;
+            for index in indices {
+                if index != last.overflow_add(1) {
+                    return false;
+                }
+                last = index;
+            }
+            next_index = last.overflow_add(1);
+            true
+        };
+        let codec = self.codec::<C>();
+        let (frame_batches, tail_invalid) = self.read_all(codec, is_continuous)?;
         // The highest_index of this segment
         let mut highest_index = u64::MAX;
         // We get the last frame batch to check it's type
@@ -114,43 +182,73 @@ impl WALSegment {
         // Update seal index
         self.update_seal_index(highest_index);
 
+        let torn = tail_invalid && !self.is_sealed();
+
         // Get log entries that index is no larger than `highest_index`
-        Ok(frame_batches.into_iter().flatten().filter_map(move |f| {
-            if let DataFrameOwned::Entry(e) = f {
-                (e.index <= highest_index).then_some(e)
-            } else {
-                None
-            }
-        }))
+        let entries = frame_batches
+            .into_iter()
+            .flatten()
+            .filter_map(|f| {
+                if let DataFrameOwned::Entry(e) = f {
+                    (e.index <= highest_index).then_some(e)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Ok((entries, torn))
     }
 
     /// Seal the current segment
     ///
     /// After the seal, the log index in this segment should be less than `next_index`
-    pub(super) fn seal<C: Serialize>(&mut self, next_index: LogIndex) -> io::Result<()> {
-        self.write_sync(vec![DataFrame::SealIndex(next_index)], WAL::<C>::new())?;
+    pub(super) fn seal<C: Command>(&mut self, next_index: LogIndex) -> io::Result<()> {
+        self.write_sync(vec![DataFrame::SealIndex(next_index)], self.codec::<C>())?;
         self.update_seal_index(next_index);
         Ok(())
     }
 
     /// Writes an item to the segment
-    pub(super) fn write_sync<U, Item>(&mut self, item: Item, mut encoder: U) -> io::Result<()>
+    pub(super) fn write_sync<U, Item>(&mut self, item: Item, encoder: U) -> io::Result<()>
+    where
+        U: Encoder<Item, Error = io::Error>,
+    {
+        let _n = self.write(item, encoder)?;
+        self.sync()
+    }
+
+    /// Writes an item to the segment without syncing it to the disk, returns the number
+    /// of bytes written
+    pub(super) fn write<U, Item>(&mut self, item: Item, mut encoder: U) -> io::Result<u64>
     where
         U: Encoder<Item, Error = io::Error>,
     {
         let encoded_bytes = encoder.encode(item)?;
         self.file.write_all(&encoded_bytes)?;
-        self.file.flush()?;
-        self.file.sync_data()?;
-        self.update_size(encoded_bytes.len().numeric_cast());
+        let n = encoded_bytes.len().numeric_cast();
+        self.update_size(n);
 
-        Ok(())
+        Ok(n)
+    }
+
+    /// Syncs the written data to the disk
+    pub(super) fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()
     }
 
     /// Read all items from the segment
+    ///
+    /// Reading stops at the first torn or corrupted item, or the first one not accepted.
+    /// Returns the items and whether anything other than zeros is left after them.
     #[allow(clippy::indexing_slicing)]
     #[allow(clippy::arithmetic_side_effects)] // only used for slice indices
-    fn read_all<U, Item>(&mut self, mut decoder: U) -> Result<Vec<Item>, WALError>
+    fn read_all<U, Item>(
+        &mut self,
+        mut decoder: U,
+        mut accept: impl FnMut(&Item) -> bool,
+    ) -> Result<(Vec<Item>, bool), WALError>
     where
         U: Decoder<Item = Item, Error = WALError>,
     {
@@ -159,23 +257,49 @@ impl WALSegment {
         let mut pos = 0;
         let mut entries = Vec::new();
         while pos < buf.len() {
-            let (item, n) = match decoder.decode(&buf[pos..]) {
-                Ok(d) => d,
-                Err(WALError::MaybeEnded) => {
-                    if !buf[pos..].iter().all(|b| *b == 0) {
-                        return Err(WALError::Corrupted(CorruptType::Codec(
-                            "Read zero".to_owned(),
-                        )));
-                    }
-                    return Ok(entries);
+            match decoder.decode(&buf[pos..]) {
+                Ok((item, n)) if accept(&item) => {
+                    entries.push(item);
+                    pos += n;
                 }
+                Ok(_) | Err(WALError::MaybeEnded | WALError::Corrupted(_)) => break,
                 Err(e) => return Err(e),
-            };
-            entries.push(item);
-            pos += n;
-            self.update_size(n.numeric_cast());
+            }
+        }
+        self.size = WAL_HEADER_SIZE.overflow_add(pos).numeric_cast();
+        let _offset = self.file.seek(SeekFrom::Start(self.size))?;
+        // A torn write leaves some non-zero bytes of an incomplete batch, so does a
+        // recycled file
+        let tail_invalid = !buf[pos..].iter().all(|b| *b == 0);
+
+        Ok((entries, tail_invalid))
+    }
+
+    /// Drops all data after the current size, the dropped range is filled with zeros
+    /// so that the file keeps its length
+    fn truncate(&mut self) -> io::Result<()> {
+        let file_len = self.file.metadata()?.len();
+        self.file.set_len(self.size)?;
+        self.file.set_len(file_len.max(self.size))?;
+        self.file.sync_all()
+    }
+
+    /// The codec of the frames of this segment, seeded with the segment id unless the
+    /// segment is written by the unseeded version
+    pub(super) fn codec<C>(&self) -> WAL<C> {
+        if self.version == WAL_VERSION_UNSEEDED {
+            WAL::new()
+        } else {
+            WAL::with_seed(self.segment_id)
         }
-        Ok(entries)
+    }
+
+    /// Converts the segment back to its file in `dir`, so that the file could be recycled
+    pub(super) fn into_locked_file(self, dir: impl AsRef<Path>) -> LockedFile {
+        let path = dir
+            .as_ref()
+            .join(Self::segment_name(self.segment_id, self.base_index));
+        LockedFile::from_std(self.file, path)
     }
 
     /// Updates the size of this segment
@@ -188,11 +312,6 @@ impl WALSegment {
         self.seal_index = index;
     }
 
-    /// Get the size of the segment
-    pub(super) fn size(&self) -> u64 {
-        self.size
-    }
-
     /// Checks if the segment is full
     pub(super) fn is_full(&self) -> bool {
         self.size >= self.size_limit
@@ -208,11 +327,9 @@ impl WALSegment {
         self.base_index
     }
 
-    /// Checks if the segment is redundant
-    ///
-    /// The segment is redundant if the `seal_index` is less than the `base_index`
-    pub(super) fn is_redundant(&self) -> bool {
-        self.seal_index < self.base_index
+    /// Checks if the segment has been sealed
+    pub(super) fn is_sealed(&self) -> bool {
+        self.seal_index != u64::MAX
     }
 
     /// Gets the file name of the WAL segment
@@ -235,24 +352,25 @@ impl WALSegment {
     /// |------+------+------+------+------+------+------+------|
     /// | Checksum (32bytes) ...                                |
     /// |------+------+------+------+------+------+------+------|
-    fn gen_header(base_index: LogIndex, segment_id: u64) -> Vec<u8> {
+    fn gen_header(base_index: LogIndex, segment_id: u64, version: u8) -> Vec<u8> {
         let mut buf = vec![];
         buf.extend(WAL_MAGIC.to_le_bytes());
         buf.extend(vec![0; 3]);
-        buf.push(WAL_VERSION);
+        buf.push(version);
         buf.extend(base_index.to_le_bytes());
         buf.extend(segment_id.to_le_bytes());
         buf.extend(get_checksum(&buf));
         buf
     }
 
-    /// Parse the header from the given buffer
+    /// Parse the header from the given buffer, returns the base index, the segment id
+    /// and the version
     #[allow(
         clippy::unwrap_used, // Unwraps are used to convert slice to const length and is safe
         clippy::arithmetic_side_effects, // Arithmetics cannot overflow
         clippy::indexing_slicing // Index slicings are checked
     )]
-    fn parse_header(src: &[u8]) -> Result<(LogIndex, u64), WALError> {
+    fn parse_header(src: &[u8]) -> Result<(LogIndex, u64, u8), WALError> {
         let mut offset = 0;
         let mut next_field = |len: usize| {
             offset += len;
@@ -264,10 +382,13 @@ impl WALSegment {
         if src.len() != WAL_HEADER_SIZE
             || next_field(4) != WAL_MAGIC.to_le_bytes()
             || next_field(3) != [0; 3]
-            || next_field(1) != [WAL_VERSION]
         {
             return parse_error;
         }
+        let version = next_field(1)[0];
+        if version != WAL_VERSION && version != WAL_VERSION_UNSEEDED {
+            return parse_error;
+        }
         let base_index = parse_u64(next_field(8));
         let segment_id = parse_u64(next_field(8));
         let checksum = next_field(32);
@@ -276,7 +397,7 @@ impl WALSegment {
             return parse_error;
         }
 
-        Ok((base_index, segment_id))
+        Ok((base_index, segment_id, version))
     }
 }
 
@@ -318,15 +439,23 @@ mod tests {
 
         for idx in 0..100 {
             for id in 0..100 {
-                let header = WALSegment::gen_header(idx, id);
-                let (idx_parsed, id_parsed) = WALSegment::parse_header(&header).unwrap();
+                let header = WALSegment::gen_header(idx, id, WAL_VERSION);
+                let (idx_parsed, id_parsed, version) = WALSegment::parse_header(&header).unwrap();
                 assert_eq!(idx, idx_parsed);
                 assert_eq!(id, id_parsed);
+                assert_eq!(version, WAL_VERSION);
                 for pos in 0..8 * 4 {
                     assert!(WALSegment::parse_header(&corrupt(header.clone(), pos)).is_err());
                 }
             }
         }
+        let header = WALSegment::gen_header(1, 1, WAL_VERSION_UNSEEDED);
+        assert_eq!(
+            WALSegment::parse_header(&header).unwrap(),
+            (1, 1, WAL_VERSION_UNSEEDED)
+        );
+        let header = WALSegment::gen_header(1, 1, WAL_VERSION.overflow_add(1));
+        assert!(WALSegment::parse_header(&header).is_err());
     }
 
     #[test]
@@ -342,14 +471,15 @@ mod tests {
         wal_path.push(segment_name);
         let file = LockedFile::open_rw(&tmp_path).unwrap();
         let mut segment = WALSegment::create(file, BASE_INDEX, SEGMENT_ID, SIZE_LIMIT).unwrap();
-        segment.seal::<()>(20).unwrap();
-        segment.seal::<()>(30).unwrap();
-        segment.seal::<()>(40).unwrap();
+        segment.seal::<TestCommand>(20).unwrap();
+        segment.seal::<TestCommand>(30).unwrap();
+        segment.seal::<TestCommand>(40).unwrap();
         drop(segment);
 
         let file = LockedFile::open_rw(wal_path).unwrap();
         let mut segment = WALSegment::open(file, SIZE_LIMIT).unwrap();
-        let _ignore = segment.recover_segment_logs::<()>().unwrap();
+        let _ignore = segment.recover_segment_logs::<TestCommand>().unwrap();
+        assert!(segment.is_sealed());
         assert_eq!(segment.seal_index, 40);
     }
 
@@ -380,18 +510,85 @@ mod tests {
 
         segment.write_sync(
             frames.iter().map(DataFrameOwned::get_ref).collect(),
-            WAL::new(),
+            segment.codec(),
         );
 
         drop(segment);
 
         let file = LockedFile::open_rw(wal_path).unwrap();
         let mut segment = WALSegment::open(file, SIZE_LIMIT).unwrap();
-        let recovered: Vec<_> = segment
-            .recover_segment_logs::<TestCommand>()
-            .unwrap()
-            .map(|e| DataFrameOwned::Entry(e))
-            .collect();
+        let (entries, truncated) = segment.recover_segment_logs::<TestCommand>().unwrap();
+        let recovered: Vec<_> = entries.into_iter().map(DataFrameOwned::Entry).collect();
         assert_eq!(frames, recovered);
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn segment_recovery_stops_at_skipped_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = LockedFile::open_rw(dir.path().join("test.tmp")).unwrap();
+        let mut segment = WALSegment::create(file, 1, 1, 1024).unwrap();
+        let entries: Vec<_> = [1, 2, 3, 2, 3, 7, 8]
+            .into_iter()
+            .map(|i| {
+                LogEntry::<TestCommand>::new(i, 1, crate::rpc::ProposeId(0, 0), EntryData::Empty)
+            })
+            .collect();
+        // the entries from 2 are replaced, but the last batch skips the indices 4 to 6
+        for batch in [&entries[..3], &entries[3..5], &entries[5..]] {
+            let frames = batch.iter().map(DataFrame::Entry).collect();
+            segment
+                .write_sync(frames, segment.codec::<TestCommand>())
+                .unwrap();
+        }
+        drop(segment);
+
+        let path = dir.path().join(WALSegment::segment_name(1, 1));
+        let mut segment = WALSegment::open(LockedFile::open_rw(path).unwrap(), 1024).unwrap();
+        let (recovered, truncated) = segment.recover_segment_logs::<TestCommand>().unwrap();
+        assert_eq!(recovered, entries[..5]);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn segment_of_unseeded_version_should_be_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(WALSegment::segment_name(3, 1));
+        let entries: Vec<_> = (1..=10)
+            .map(|i| {
+                LogEntry::<TestCommand>::new(i, 1, crate::rpc::ProposeId(0, 0), EntryData::Empty)
+            })
+            .collect();
+        {
+            // a segment written before the checksums were seeded
+            let mut file = LockedFile::open_rw(&path).unwrap();
+            let file = file.file();
+            file.write_all(&WALSegment::gen_header(1, 3, WAL_VERSION_UNSEEDED))
+                .unwrap();
+            let mut codec = WAL::<TestCommand>::new();
+            for batch in entries.chunks(3) {
+                let frames = batch.iter().map(DataFrame::Entry).collect();
+                file.write_all(&codec.encode(frames).unwrap()).unwrap();
+            }
+            file.sync_all().unwrap();
+        }
+
+        let mut segment = WALSegment::open(LockedFile::open_rw(&path).unwrap(), 1024).unwrap();
+        let (recovered, truncated) = segment.recover_segment_logs::<TestCommand>().unwrap();
+        assert_eq!(recovered, entries);
+        assert!(!truncated);
+        // the entries appended later are encoded the same way as the segment
+        let entry = LogEntry::new(11, 1, crate::rpc::ProposeId(0, 0), EntryData::Empty);
+        segment
+            .write_sync(
+                vec![DataFrame::Entry(&entry)],
+                segment.codec::<TestCommand>(),
+            )
+            .unwrap();
+        drop(segment);
+
+        let mut segment = WALSegment::open(LockedFile::open_rw(&path).unwrap(), 1024).unwrap();
+        let (recovered, _) = segment.recover_segment_logs::<TestCommand>().unwrap();
+        assert_eq!(recovered.len(), 11);
     }
 }
@@ -0,0 +1,739 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+use clippy_utilities::OverflowArithmetic;
+use curp_external_api::{cmd::Command, LogIndex};
+use tracing::warn;
+
+use super::{
+    codec::DataFrame,
+    config::{SyncPolicy, WALConfig},
+    error::{CorruptType, WALError},
+    pipeline::FilePipeline,
+    remover::SegmentRemover,
+    segment::WALSegment,
+    util::{
+        get_checksum, get_file_paths_with_ext, is_exist, parse_u64, sync_parent_dir, validate_data,
+        LockedFile,
+    },
+    WAL_FILE_EXT,
+};
+use crate::{log_entry::LogEntry, members::ServerId};
+
+/// The name of the file that stores the term and the vote
+const HARD_STATE_FILE_NAME: &str = "hardstate";
+
+/// The name of the file used to replace the hard state file
+const HARD_STATE_NEW_FILE_NAME: &str = "hardstate.new";
+
+/// The size of the hard state file in bytes
+const HARD_STATE_SIZE: usize = 48;
+
+/// The WAL storage
+///
+/// Log entries are appended to the last segment, and a new segment is rolled once the
+/// last one is full. An entry whose index is not larger than the last index replaces
+/// all entries from its index on.
+#[derive(Debug)]
+pub(crate) struct WALStorage<C> {
+    /// The config of WAL files
+    config: WALConfig,
+    /// The pipeline that pre-allocates files
+    pipeline: FilePipeline,
+    /// WAL segments, ordered by segment ids
+    segments: Vec<WALSegment>,
+    /// The next segment id
+    next_segment_id: u64,
+    /// The next log index
+    next_log_index: LogIndex,
+    /// Bytes appended since the last fsync
+    unsynced_bytes: u64,
+    /// The index of the snapshot the log is reset to, the entries up to it are the ones
+    /// appended before the reset and are not written
+    snapshot_index: LogIndex,
+    /// The phantom data
+    _phantom: PhantomData<C>,
+}
+
+impl<C> WALStorage<C>
+where
+    C: Command,
+{
+    /// Creates a new `WALStorage`
+    pub(crate) fn new(config: WALConfig) -> io::Result<Self> {
+        if !is_exist(&config.dir) {
+            std::fs::create_dir_all(&config.dir)?;
+        }
+        // Finish the removal interrupted by a crash
+        SegmentRemover::recover(&config.dir)?;
+        let pipeline = FilePipeline::new(
+            config.dir.clone(),
+            config.max_segment_size,
+            config.max_recycled_segments,
+        );
+        Ok(Self {
+            config,
+            pipeline,
+            segments: vec![],
+            next_segment_id: 0,
+            next_log_index: 0,
+            unsynced_bytes: 0,
+            snapshot_index: 0,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Recovers the term, the vote and all log entries from the WAL files
+    ///
+    /// Recovery stops at the first torn or corrupted record, the record and everything
+    /// after it are removed so that new entries could be appended. The entries before a
+    /// snapshot may have been removed, so the log doesn't have to start at index 1.
+    pub(crate) fn recover(
+        &mut self,
+    ) -> Result<(Option<(u64, ServerId)>, Vec<LogEntry<C>>), WALError> {
+        let hard_state = self.recover_hard_state()?;
+
+        let mut segments = get_file_paths_with_ext(&self.config.dir, WAL_FILE_EXT)?
+            .into_iter()
+            .map(|path| {
+                LockedFile::open_rw(path)
+                    .map_err(WALError::from)
+                    .and_then(|file| WALSegment::open(file, self.config.max_segment_size))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        segments.sort_unstable();
+
+        let mut logs: Vec<LogEntry<C>> = Vec::new();
+        let mut valid = segments.len();
+        for (i, segment) in segments.iter_mut().enumerate() {
+            Self::skip_reset_logs(&mut logs, segment);
+            let (entries, truncated) = segment.recover_segment_logs::<C>()?;
+            for entry in entries {
+                Self::push_log(&mut logs, entry)?;
+            }
+            if truncated {
+                valid = i.overflow_add(1);
+                break;
+            }
+        }
+        // Segments after a truncated one would leave a hole in the log
+        let invalid = segments.split_off(valid);
+        if !invalid.is_empty() {
+            warn!("removing {} segments after the corruption", invalid.len());
+            SegmentRemover::new_removal(&self.config.dir, invalid.iter())?;
+        }
+
+        self.next_segment_id = segments.last().map_or(0, |s| s.id().overflow_add(1));
+        self.next_log_index = logs.last().map_or_else(
+            || segments.last().map_or(0, WALSegment::base_index),
+            |e| e.index.overflow_add(1),
+        );
+        self.segments = segments;
+
+        Ok((hard_state, logs))
+    }
+
+    /// Reads the term, the vote and all log entries from the WAL files in `dir` without
+    /// changing them
+    ///
+    /// Nothing is created, locked, truncated or removed. Reading stops at the first torn
+    /// or corrupted record. The segments of an interrupted removal are the oldest ones,
+    /// so they are read as well.
+    pub(crate) fn read(
+        dir: impl AsRef<Path>,
+    ) -> Result<(Option<(u64, ServerId)>, Vec<LogEntry<C>>), WALError> {
+        let dir = dir.as_ref();
+        let hard_state = read_hard_state(dir)?;
+
+        let mut segments = get_file_paths_with_ext(dir, WAL_FILE_EXT)?
+            .into_iter()
+            .map(WALSegment::open_read_only)
+            .collect::<Result<Vec<_>, _>>()?;
+        segments.sort_unstable();
+
+        let mut logs: Vec<LogEntry<C>> = Vec::new();
+        for segment in &mut segments {
+            Self::skip_reset_logs(&mut logs, segment);
+            let (entries, torn) = segment.read_segment_logs::<C>()?;
+            for entry in entries {
+                Self::push_log(&mut logs, entry)?;
+            }
+            if torn {
+                break;
+            }
+        }
+
+        Ok((hard_state, logs))
+    }
+
+    /// Appends log entries to the WAL, the entries are synced according to the sync policy
+    pub(crate) fn append(&mut self, entries: &[LogEntry<C>]) -> io::Result<()> {
+        let covered = entries
+            .iter()
+            .take_while(|e| e.index <= self.snapshot_index)
+            .count();
+        let entries = entries.get(covered..).unwrap_or_default();
+        let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+            return Ok(());
+        };
+        self.maybe_roll(first.index)?;
+        let frames = entries.iter().map(DataFrame::Entry).collect();
+        let segment = self
+            .segments
+            .last_mut()
+            .unwrap_or_else(|| unreachable!("there should be at least one segment after roll"));
+        let n = segment.write(frames, segment.codec::<C>())?;
+        self.unsynced_bytes = self.unsynced_bytes.overflow_add(n);
+        self.next_log_index = last.index.overflow_add(1);
+
+        match self.config.sync_policy {
+            SyncPolicy::Always => self.sync(),
+            SyncPolicy::Bytes(bytes) if self.unsynced_bytes >= bytes => self.sync(),
+            SyncPolicy::Bytes(_) => Ok(()),
+        }
+    }
+
+    /// Syncs all appended entries to the disk
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        utils::fail_point!(
+            "wal_before_fsync",
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "injected wal fsync failure"
+            ))
+        );
+        if let Some(segment) = self.segments.last_mut() {
+            segment.sync()?;
+        }
+        self.unsynced_bytes = 0;
+        utils::fail_point!("wal_after_fsync");
+        Ok(())
+    }
+
+    /// Removes the segments whose entries are all no larger than `compact_index`
+    ///
+    /// The last segment is always kept. The files of the oldest removed segments are
+    /// recycled for the new segments, the rest are deleted. Either way the segments are
+    /// removed from the oldest, so that a crash never leaves a hole in the log.
+    #[allow(clippy::indexing_slicing)] // windows of 2 always have two elements
+    pub(crate) fn truncate_head(&mut self, compact_index: LogIndex) -> io::Result<()> {
+        let num_redundant = self
+            .segments
+            .windows(2)
+            .take_while(|w| w[1].base_index() <= compact_index.overflow_add(1))
+            .count();
+        self.remove_head(num_redundant)
+    }
+
+    /// Drops all log entries for a snapshot at `snapshot_index`, the next entry appended
+    /// is the one after it. The entries up to the index still queued to be appended are
+    /// ignored.
+    ///
+    /// A new segment is rolled for the next entry before the old ones are removed, so
+    /// that a crash in between leaves a segment starting after the dropped entries.
+    pub(crate) fn reset(&mut self, snapshot_index: LogIndex) -> io::Result<()> {
+        if let Some(segment) = self.segments.last_mut() {
+            if !segment.is_sealed() {
+                segment.seal::<C>(self.next_log_index.overflow_sub(1))?;
+            }
+        }
+        let next_index = snapshot_index.overflow_add(1);
+        self.maybe_roll(next_index)?;
+        self.next_log_index = next_index;
+        self.snapshot_index = snapshot_index;
+        self.remove_head(self.segments.len().overflow_sub(1))
+    }
+
+    /// Removes the first `num_redundant` segments, which are recycled if there's room
+    fn remove_head(&mut self, num_redundant: usize) -> io::Result<()> {
+        if num_redundant == 0 {
+            return Ok(());
+        }
+        let num_recycled = num_redundant.min(self.pipeline.recycle_room());
+        for segment in self.segments.drain(..num_recycled) {
+            self.pipeline
+                .recycle(segment.into_locked_file(&self.config.dir))?;
+        }
+        let num_removed = num_redundant.overflow_sub(num_recycled);
+        if num_removed > 0 {
+            SegmentRemover::new_removal(&self.config.dir, self.segments.iter().take(num_removed))?;
+            let _removed = self.segments.drain(..num_removed);
+        }
+        Ok(())
+    }
+
+    /// Persists the term and the vote, they are flushed on disk before returning
+    pub(crate) fn persist_vote(&self, term: u64, voted_for: ServerId) -> io::Result<()> {
+        let mut data: Vec<_> = term
+            .to_le_bytes()
+            .into_iter()
+            .chain(voted_for.to_le_bytes())
+            .collect();
+        data.extend(get_checksum(&data));
+
+        let new_path = self.config.dir.join(HARD_STATE_NEW_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&new_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        let path = self.config.dir.join(HARD_STATE_FILE_NAME);
+        std::fs::rename(new_path, &path)?;
+        sync_parent_dir(path)
+    }
+
+    /// Recovers the term and the vote
+    fn recover_hard_state(&self) -> Result<Option<(u64, ServerId)>, WALError> {
+        read_hard_state(&self.config.dir)
+    }
+
+    /// Seals the last segment and opens a new one if there's no room for new entries
+    fn maybe_roll(&mut self, first_index: LogIndex) -> io::Result<()> {
+        if let Some(segment) = self.segments.last_mut() {
+            if !segment.is_full() && !segment.is_sealed() {
+                return Ok(());
+            }
+            if !segment.is_sealed() {
+                segment.seal::<C>(self.next_log_index.overflow_sub(1))?;
+            }
+        }
+        let file = self
+            .pipeline
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "file pipeline stopped"))??;
+        let segment = WALSegment::create(
+            file,
+            first_index,
+            self.next_segment_id,
+            self.config.max_segment_size,
+        )?;
+        self.segments.push(segment);
+        self.next_segment_id = self.next_segment_id.overflow_add(1);
+        self.unsynced_bytes = 0;
+        Ok(())
+    }
+
+    /// Drops the recovered entries if `segment` starts after them, the segment is rolled
+    /// by a reset to a snapshot whose removal of the old segments is interrupted
+    fn skip_reset_logs(logs: &mut Vec<LogEntry<C>>, segment: &WALSegment) {
+        if logs
+            .last()
+            .is_some_and(|last| segment.base_index() > last.index.overflow_add(1))
+        {
+            logs.clear();
+        }
+    }
+
+    /// Pushes a recovered entry to the log, it replaces the entries from its index on
+    #[allow(clippy::indexing_slicing)] // the log is checked to be non-empty
+    fn push_log(logs: &mut Vec<LogEntry<C>>, entry: LogEntry<C>) -> Result<(), WALError> {
+        if let Some(last) = logs.last() {
+            if entry.index > last.index.overflow_add(1) {
+                return Err(WALError::Corrupted(CorruptType::LogNotContinue));
+            }
+            let first_index = logs[0].index;
+            if entry.index < first_index {
+                logs.clear();
+            } else if entry.index <= last.index {
+                logs.truncate(
+                    (entry.index.overflow_sub(first_index))
+                        .try_into()
+                        .unwrap_or_else(|_| {
+                            unreachable!("the offset should be smaller than the length of the log")
+                        }),
+                );
+            }
+        }
+        logs.push(entry);
+        Ok(())
+    }
+}
+
+/// Reads the term and the vote from the hard state file in `dir`
+#[allow(clippy::indexing_slicing)] // the size is checked
+fn read_hard_state(dir: &Path) -> Result<Option<(u64, ServerId)>, WALError> {
+    let path = dir.join(HARD_STATE_FILE_NAME);
+    if !is_exist(&path) {
+        return Ok(None);
+    }
+    let mut buf = Vec::with_capacity(HARD_STATE_SIZE);
+    let _n = std::fs::File::open(path)?.read_to_end(&mut buf)?;
+    if buf.len() != HARD_STATE_SIZE || !validate_data(&buf[..16], &buf[16..]) {
+        return Err(WALError::Corrupted(CorruptType::Checksum));
+    }
+    Ok(Some((parse_u64(&buf[..8]), parse_u64(&buf[8..16]))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ops::Range,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use curp_test_utils::test_cmd::TestCommand;
+    use utils::failpoint::{FailAction, FailScenario};
+
+    use super::*;
+    use crate::{
+        log_entry::EntryData,
+        rpc::{ConfChange, ProposeId},
+    };
+
+    fn entry(index: LogIndex, term: u64) -> LogEntry<TestCommand> {
+        let entry_data = match index % 4 {
+            0 => EntryData::Command(Arc::new(TestCommand::new_put(vec![index as u32], 0))),
+            1 => EntryData::ConfChange(vec![ConfChange::add(index, vec!["addr".to_owned()])]),
+            2 => EntryData::Shutdown,
+            _ => EntryData::Empty,
+        };
+        LogEntry::new(index, term, ProposeId(index, 0), entry_data)
+    }
+
+    fn open(dir: &Path, max_segment_size: u64) -> WALStorage<TestCommand> {
+        let config = WALConfig::new(dir).with_max_segment_size(max_segment_size);
+        WALStorage::new(config).unwrap()
+    }
+
+    fn segment_paths(dir: &Path) -> Vec<PathBuf> {
+        let mut paths = get_file_paths_with_ext(dir, WAL_FILE_EXT).unwrap();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn wal_append_recover_is_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<_> = (1..=100).map(|i| entry(i, 1)).collect();
+        {
+            let mut storage = open(dir.path(), 1024);
+            let (hard_state, logs) = storage.recover().unwrap();
+            assert!(hard_state.is_none());
+            assert!(logs.is_empty());
+            for chunk in entries.chunks(3) {
+                storage.append(chunk).unwrap();
+            }
+            storage.persist_vote(3, 2).unwrap();
+        }
+        assert!(segment_paths(dir.path()).len() > 1, "segments should roll");
+
+        let mut storage = open(dir.path(), 1024);
+        let (hard_state, logs) = storage.recover().unwrap();
+        assert_eq!(hard_state, Some((3, 2)));
+        assert_eq!(logs, entries);
+
+        storage.append(&[entry(101, 1)]).unwrap();
+        drop(storage);
+        let (_, logs) = open(dir.path(), 1024).recover().unwrap();
+        assert_eq!(logs.len(), 101);
+    }
+
+    #[test]
+    fn wal_append_fails_if_fsync_fails() {
+        let scenario = FailScenario::setup();
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = open(dir.path(), 1024);
+        let _ignore = storage.recover().unwrap();
+        scenario.cfg_local("wal_after_fsync", FailAction::Delay(Duration::ZERO));
+        storage.append(&[entry(1, 1)]).unwrap();
+        assert_eq!(scenario.hits("wal_after_fsync"), 1);
+
+        scenario.cfg_local("wal_before_fsync", FailAction::Return);
+        assert!(storage.append(&[entry(2, 1)]).is_err());
+        assert_eq!(scenario.hits("wal_after_fsync"), 1);
+
+        scenario.remove("wal_before_fsync");
+        storage.append(&[entry(3, 1)]).unwrap();
+        assert_eq!(scenario.hits("wal_after_fsync"), 2);
+    }
+
+    #[test]
+    fn wal_recover_truncates_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 1024 * 1024);
+            let _ignore = storage.recover().unwrap();
+            storage.append(&[entry(1, 1), entry(2, 1)]).unwrap();
+            storage.append(&[entry(3, 1), entry(4, 1)]).unwrap();
+        }
+        // Tear the last batch by discarding the tail of its commit frame
+        let path = segment_paths(dir.path()).pop().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let end = data.iter().rposition(|b| *b != 0).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((end - 10) as u64).unwrap();
+
+        let mut storage = open(dir.path(), 1024 * 1024);
+        let (_, logs) = storage.recover().unwrap();
+        assert_eq!(logs, vec![entry(1, 1), entry(2, 1)]);
+
+        // The torn record is overwritten by new entries
+        storage.append(&[entry(3, 2)]).unwrap();
+        drop(storage);
+        let (_, logs) = open(dir.path(), 1024 * 1024).recover().unwrap();
+        assert_eq!(logs, vec![entry(1, 1), entry(2, 1), entry(3, 2)]);
+    }
+
+    #[test]
+    fn wal_read_leaves_torn_tail_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 1024 * 1024);
+            let _ignore = storage.recover().unwrap();
+            storage.persist_vote(1, 2).unwrap();
+            storage.append(&[entry(1, 1), entry(2, 1)]).unwrap();
+            storage.append(&[entry(3, 1), entry(4, 1)]).unwrap();
+        }
+        let path = segment_paths(dir.path()).pop().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let end = data.iter().rposition(|b| *b != 0).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((end - 10) as u64).unwrap();
+        let torn = std::fs::read(&path).unwrap();
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+
+        let (hard_state, logs) = WALStorage::<TestCommand>::read(dir.path()).unwrap();
+        assert_eq!(hard_state, Some((1, 2)));
+        assert_eq!(logs, vec![entry(1, 1), entry(2, 1)]);
+        assert_eq!(std::fs::read(&path).unwrap(), torn);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), files);
+    }
+
+    #[test]
+    fn wal_recover_stops_at_corrupted_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            for i in 1..=30 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+        }
+        let paths = segment_paths(dir.path());
+        assert!(paths.len() > 2);
+        // Flip a byte in the first record of the second segment
+        let mut data = std::fs::read(&paths[1]).unwrap();
+        data[56 + 8] ^= 0xff;
+        std::fs::write(&paths[1], data).unwrap();
+
+        let mut storage = open(dir.path(), 512);
+        let (_, logs) = storage.recover().unwrap();
+        assert!(!logs.is_empty() && logs.len() < 30);
+        assert!(logs.iter().zip(1..).all(|(e, i)| e.index == i));
+        assert_eq!(segment_paths(dir.path()).len(), 2);
+
+        let next = logs.len() as u64 + 1;
+        storage.append(&[entry(next, 1)]).unwrap();
+        drop(storage);
+        let (_, recovered) = open(dir.path(), 512).recover().unwrap();
+        assert_eq!(recovered.last().unwrap().index, next);
+    }
+
+    #[test]
+    fn wal_conflicting_entries_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 1024 * 1024);
+            let _ignore = storage.recover().unwrap();
+            storage
+                .append(&[entry(1, 1), entry(2, 1), entry(3, 1)])
+                .unwrap();
+            storage.append(&[entry(2, 2)]).unwrap();
+        }
+        let (_, logs) = open(dir.path(), 1024 * 1024).recover().unwrap();
+        assert_eq!(logs, vec![entry(1, 1), entry(2, 2)]);
+    }
+
+    #[test]
+    fn wal_truncate_head_removes_old_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = open(dir.path(), 512);
+        let _ignore = storage.recover().unwrap();
+        for i in 1..=30 {
+            storage.append(&[entry(i, 1)]).unwrap();
+        }
+        let num_segments = segment_paths(dir.path()).len();
+        storage.truncate_head(20).unwrap();
+        assert!(segment_paths(dir.path()).len() < num_segments);
+        storage.truncate_head(30).unwrap();
+        assert_eq!(segment_paths(dir.path()).len(), 1);
+        drop(storage);
+
+        let (_, logs) = open(dir.path(), 512).recover().unwrap();
+        assert!(logs.first().unwrap().index > 20);
+        assert_eq!(logs.last().unwrap().index, 30);
+    }
+
+    #[test]
+    fn wal_reset_starts_the_log_after_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            for i in 1..=30 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+            storage.reset(50).unwrap();
+            assert_eq!(segment_paths(dir.path()).len(), 1);
+            storage.append(&[entry(31, 1)]).unwrap();
+        }
+        let mut storage = open(dir.path(), 512);
+        let (_, logs) = storage.recover().unwrap();
+        assert!(logs.is_empty());
+        storage.append(&[entry(51, 2), entry(52, 2)]).unwrap();
+        drop(storage);
+
+        let (_, logs) = open(dir.path(), 512).recover().unwrap();
+        assert_eq!(logs, vec![entry(51, 2), entry(52, 2)]);
+    }
+
+    #[test]
+    fn wal_recover_after_interrupted_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            for i in 1..=30 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+            // crash after the segment of the next entry is rolled, before the old
+            // segments are removed
+            let segment = storage.segments.last_mut().unwrap();
+            segment.seal::<TestCommand>(30).unwrap();
+            storage.maybe_roll(51).unwrap();
+            storage.next_log_index = 51;
+            storage.append(&[entry(51, 2)]).unwrap();
+        }
+        let (_, logs) = open(dir.path(), 512).recover().unwrap();
+        assert_eq!(logs, vec![entry(51, 2)]);
+        let (_, read) = WALStorage::<TestCommand>::read(dir.path()).unwrap();
+        assert_eq!(read, logs);
+    }
+
+    fn recycled_paths(dir: &Path) -> Vec<PathBuf> {
+        get_file_paths_with_ext(dir, ".recycled").unwrap()
+    }
+
+    #[test]
+    fn wal_reuses_recycled_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            for i in 1..=30 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+            storage.truncate_head(20).unwrap();
+            assert_eq!(recycled_paths(dir.path()).len(), 2);
+            // the new segments are rolled on the recycled files, over their stale data
+            for i in 31..=60 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+            assert!(recycled_paths(dir.path()).is_empty());
+        }
+
+        let mut storage = open(dir.path(), 512);
+        let (_, logs) = storage.recover().unwrap();
+        assert!(logs.first().unwrap().index > 20);
+        assert!(logs.iter().zip(logs[0].index..).all(|(e, i)| e.index == i));
+        assert_eq!(logs.last().unwrap().index, 60);
+    }
+
+    #[test]
+    fn wal_recover_from_recycled_segment_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            for i in 1..=30 {
+                storage.append(&[entry(i, 1)]).unwrap();
+            }
+            storage.truncate_head(20).unwrap();
+            // crash right after a recycled segment is rolled, before any entry is
+            // appended to it, the segment only has a fresh header before the stale data
+            let segment = storage.segments.last_mut().unwrap();
+            segment.seal::<TestCommand>(30).unwrap();
+            storage.maybe_roll(31).unwrap();
+            assert_eq!(recycled_paths(dir.path()).len(), 1);
+        }
+
+        let mut storage = open(dir.path(), 512);
+        let (_, logs) = storage.recover().unwrap();
+        assert_eq!(logs.last().unwrap().index, 30);
+        // none of the sealed segments is dropped for the stale data after their seals
+        assert!(logs.first().unwrap().index <= 21);
+        storage.append(&[entry(31, 1)]).unwrap();
+        drop(storage);
+
+        let (_, recovered) = open(dir.path(), 512).recover().unwrap();
+        assert_eq!(recovered.len(), logs.len() + 1);
+        assert_eq!(recovered.last().unwrap().index, 31);
+    }
+
+    #[test]
+    fn wal_recover_from_fresh_segment_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<_> = (1..=30).map(|i| entry(i, 1)).collect();
+        {
+            let mut storage = open(dir.path(), 512);
+            let _ignore = storage.recover().unwrap();
+            storage.append(&entries).unwrap();
+            // crash right after a new segment is rolled, before any entry is appended to it
+            let segment = storage.segments.last_mut().unwrap();
+            segment.seal::<TestCommand>(30).unwrap();
+            storage.maybe_roll(31).unwrap();
+        }
+
+        let mut storage = open(dir.path(), 512);
+        let (_, logs) = storage.recover().unwrap();
+        assert_eq!(logs, entries);
+        storage.append(&[entry(31, 1), entry(32, 1)]).unwrap();
+        drop(storage);
+
+        let (_, recovered) = open(dir.path(), 512).recover().unwrap();
+        assert_eq!(recovered.len(), 32);
+        assert!(recovered.iter().zip(1..).all(|(e, i)| e.index == i));
+    }
+
+    /// Measures the append latency across segment rolls, run it with
+    /// `cargo test -p curp --release wal_append_latency -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn wal_append_latency() {
+        fn percentiles(mut latencies: Vec<Duration>) -> (Duration, Duration) {
+            latencies.sort();
+            let at = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+            (at(50), at(99))
+        }
+
+        const ROUNDS: u64 = 10_000;
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = open(dir.path(), 64 * 1024);
+        let _ignore = storage.recover().unwrap();
+        let append = |storage: &mut WALStorage<TestCommand>, indices: Range<u64>| {
+            indices
+                .map(|i| {
+                    let entry = entry(i, 1);
+                    let start = std::time::Instant::now();
+                    storage.append(&[entry]).unwrap();
+                    start.elapsed()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (p50, p99) = percentiles(append(&mut storage, 1..ROUNDS + 1));
+        println!("fresh segments: p50 {p50:?}, p99 {p99:?}");
+        // the compacted segments are recycled for the next rolls
+        storage.truncate_head(ROUNDS).unwrap();
+        let (p50, p99) = percentiles(append(&mut storage, ROUNDS + 1..2 * ROUNDS + 1));
+        println!("recycled segments: p50 {p50:?}, p99 {p99:?}");
+    }
+}
@@ -33,6 +33,14 @@ impl LockedFile {
         })
     }
 
+    /// Wraps a file that is already locked by this process
+    pub(super) fn from_std(file: StdFile, path: impl AsRef<Path>) -> Self {
+        Self {
+            file: Some(file),
+            path: path.as_ref().into(),
+        }
+    }
+
     /// Pre-allocates the file
     pub(super) fn preallocate(&mut self, size: u64) -> io::Result<()> {
         if size == 0 {
@@ -58,7 +66,7 @@ impl LockedFile {
 
         Ok(Self {
             file: self.file.take(),
-            path: PathBuf::from(new_name.as_ref()),
+            path: new_path,
         })
     }
 
@@ -71,7 +79,7 @@ impl LockedFile {
     }
 
     /// Gets the file wrapped inside an `Option`
-    fn file(&mut self) -> &mut StdFile {
+    pub(super) fn file(&mut self) -> &mut StdFile {
         self.file
             .as_mut()
             .unwrap_or_else(|| unreachable!("File should always exist after creation"))
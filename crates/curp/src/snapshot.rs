@@ -1,31 +1,145 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
 
-use engine::Snapshot as EngineSnapshot;
+use bytes::Bytes;
+use clippy_utilities::OverflowArithmetic;
+use engine::{Snapshot as EngineSnapshot, SnapshotApi};
+use parking_lot::Mutex;
+use tokio::{
+    sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard, Semaphore, SemaphorePermit},
+    time::Instant,
+};
+use tracing::error;
+
+use crate::{members::ServerId, rpc::Member};
 
 /// Snapshot
-#[derive(Debug)]
 pub(crate) struct Snapshot {
     /// Snapshot metadata
     pub(crate) meta: SnapshotMeta,
     /// Snapshot
     inner: EngineSnapshot,
+    /// Encoded propose results cached when the snapshot was taken
+    results: Bytes,
+    /// The members when the snapshot was taken
+    members: Vec<Member>,
 }
 
 impl Snapshot {
     /// Create a new snapshot
     pub(crate) fn new(meta: SnapshotMeta, inner: EngineSnapshot) -> Self {
-        Self { meta, inner }
+        Self {
+            meta,
+            inner,
+            results: Bytes::new(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Attach the encoded propose results
+    pub(crate) fn with_results(mut self, results: Bytes) -> Self {
+        self.results = results;
+        self
+    }
+
+    /// Take the encoded propose results
+    pub(crate) fn take_results(&mut self) -> Bytes {
+        std::mem::take(&mut self.results)
+    }
+
+    /// Attach the members
+    pub(crate) fn with_members(mut self, members: Vec<Member>) -> Self {
+        self.members = members;
+        self
+    }
+
+    /// Take the members
+    pub(crate) fn take_members(&mut self) -> Vec<Member> {
+        std::mem::take(&mut self.members)
     }
 
     /// Into inner snapshot
     pub(crate) fn into_inner(self) -> EngineSnapshot {
         self.inner
     }
+}
 
-    /// Get the inner snapshot ref
-    #[cfg(feature = "client-metrics")]
-    pub(crate) fn inner(&self) -> &EngineSnapshot {
-        &self.inner
+impl Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("meta", &self.meta)
+            .field("inner", &self.inner)
+            .field("results_len", &self.results.len())
+            .field("members", &self.members)
+            .finish()
+    }
+}
+
+/// A snapshot sent to a follower, shared by the streams of the transfer so that an
+/// interrupted stream is resumed by the next one rather than restarted
+#[derive(Debug)]
+pub(crate) struct SnapshotTransfer {
+    /// Id of the transfer, the follower only resumes the data staged for the same id
+    id: u64,
+    /// Snapshot metadata
+    meta: SnapshotMeta,
+    /// Encoded propose results cached when the snapshot was taken
+    results: Bytes,
+    /// The members when the snapshot was taken
+    members: Vec<Member>,
+    /// Size of the snapshot
+    size: u64,
+    /// The snapshot, locked by the stream sending it
+    inner: AsyncMutex<EngineSnapshot>,
+}
+
+impl SnapshotTransfer {
+    /// Start a new transfer of a snapshot
+    pub(crate) fn new(mut snapshot: Snapshot) -> Self {
+        Self {
+            id: rand::random(),
+            meta: snapshot.meta,
+            results: snapshot.take_results(),
+            members: snapshot.take_members(),
+            size: snapshot.inner.size(),
+            inner: AsyncMutex::new(snapshot.into_inner()),
+        }
+    }
+
+    /// Id of the transfer
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Snapshot metadata
+    pub(crate) fn meta(&self) -> SnapshotMeta {
+        self.meta
+    }
+
+    /// Encoded propose results cached when the snapshot was taken
+    pub(crate) fn results(&self) -> Bytes {
+        self.results.clone()
+    }
+
+    /// The members when the snapshot was taken
+    pub(crate) fn members(&self) -> Vec<Member> {
+        self.members.clone()
+    }
+
+    /// Size of the snapshot
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Lock the snapshot to read it
+    pub(crate) async fn lock(&self) -> AsyncMutexGuard<'_, EngineSnapshot> {
+        self.inner.lock().await
+    }
+
+    /// Clean the snapshot once the transfer is over
+    pub(crate) async fn clean(&self) {
+        if let Err(e) = self.inner.lock().await.clean().await {
+            error!("snapshot clean error, {e}");
+        }
     }
 }
 
@@ -36,4 +150,229 @@ pub(crate) struct SnapshotMeta {
     pub(crate) last_included_index: u64,
     /// Last included term
     pub(crate) last_included_term: u64,
+    /// The cluster server version applied up to the last included index
+    pub(crate) cluster_server_version: u32,
+    /// Index of the last conf change entry reflected in the members of the snapshot,
+    /// which may be beyond the last included index as conf changes are applied once
+    /// appended
+    pub(crate) membership_index: u64,
+}
+
+/// Paces the bytes passing through it to a max rate
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Max bytes per second
+    bytes_per_sec: u64,
+    /// When all bytes acquired so far are allowed to pass
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// New `RateLimiter`, `None` if the rate is unlimited
+    pub(crate) fn new(bytes_per_sec: u64) -> Option<Self> {
+        (bytes_per_sec > 0).then(|| Self {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Reserve `bytes` more bytes at `now`, returns when they are allowed to pass
+    pub(crate) fn reserve(&self, bytes: u64, now: Instant) -> Instant {
+        // rounded up so the bytes never pass faster than the rate
+        let nanos = u128::from(bytes)
+            .overflow_mul(1_000_000_000)
+            .div_ceil(u128::from(self.bytes_per_sec));
+        let cost = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+        let mut next = self.next.lock();
+        let start = (*next).max(now);
+        *next = start.checked_add(cost).unwrap_or(start);
+        *next
+    }
+
+    /// When all bytes reserved so far are allowed to pass
+    pub(crate) fn next(&self) -> Instant {
+        *self.next.lock()
+    }
+}
+
+/// Progress of sending a snapshot
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransferProgress {
+    /// Size of the snapshot
+    total: u64,
+    /// Bytes sent
+    sent: u64,
+    /// When the transfer started
+    started_at: Instant,
+}
+
+impl TransferProgress {
+    /// Bytes sent
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Average rate in bytes per second since the transfer started
+    pub(crate) fn rate(&self) -> u64 {
+        let rate = u128::from(self.sent)
+            .overflow_mul(1000)
+            .checked_div(self.started_at.elapsed().as_millis())
+            .unwrap_or(0);
+        u64::try_from(rate).unwrap_or(u64::MAX)
+    }
+
+    /// Estimated time to send the rest of the snapshot, `None` if nothing has been sent
+    pub(crate) fn eta(&self) -> Option<Duration> {
+        let remaining = self.total.saturating_sub(self.sent);
+        let millis = remaining.overflow_mul(1000).checked_div(self.rate())?;
+        Some(Duration::from_millis(millis))
+    }
+}
+
+/// Limits the resources used by the leader to send snapshots, so that sending a large
+/// snapshot won't starve the foreground traffic
+#[derive(Debug)]
+pub(crate) struct SnapshotThrottle {
+    /// Limit of reading snapshots
+    read: Option<RateLimiter>,
+    /// Limit of sending snapshots
+    send: Option<RateLimiter>,
+    /// Permits of sending snapshots at the same time
+    transfers: Semaphore,
+    /// Progress of the ongoing transfers, indexed by the receiver
+    progress: Mutex<HashMap<ServerId, TransferProgress>>,
+}
+
+impl SnapshotThrottle {
+    /// New `SnapshotThrottle`, a zero rate means unlimited
+    pub(crate) fn new(read_rate: u64, send_rate: u64, max_transfers: usize) -> Self {
+        Self {
+            read: RateLimiter::new(read_rate),
+            send: RateLimiter::new(send_rate),
+            transfers: Semaphore::new(max_transfers.max(1)),
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for the turn to send a snapshot, the turn ends when the permit is dropped
+    pub(crate) async fn acquire_transfer(&self) -> SemaphorePermit<'_> {
+        match self.transfers.acquire().await {
+            Ok(permit) => permit,
+            Err(_e) => unreachable!("the transfer semaphore is never closed"),
+        }
+    }
+
+    /// Wait until a chunk of `read` bytes is allowed to be read and `send` bytes of it
+    /// are allowed to be sent
+    pub(crate) async fn throttle(&self, read: u64, send: u64) {
+        if let Some(until) = self.reserve(read, send, Instant::now()) {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+
+    /// Reserve a chunk from both limits at `now`, returns when the chunk is allowed to
+    /// pass, `None` if both are unlimited
+    ///
+    /// The chunk waits for the later of the two limits instead of one after the other,
+    /// so the transfer runs at the tighter rate rather than slower than both.
+    pub(crate) fn reserve(&self, read: u64, send: u64, now: Instant) -> Option<Instant> {
+        let read_until = self.read.as_ref().map(|limiter| limiter.reserve(read, now));
+        let send_until = self.send.as_ref().map(|limiter| limiter.reserve(send, now));
+        read_until.max(send_until)
+    }
+
+    /// When all bytes reserved so far are allowed to pass, `None` if both are unlimited
+    pub(crate) fn paced_until(&self) -> Option<Instant> {
+        let read_until = self.read.as_ref().map(RateLimiter::next);
+        let send_until = self.send.as_ref().map(RateLimiter::next);
+        read_until.max(send_until)
+    }
+
+    /// Start tracking the progress of sending a snapshot of `total` bytes to `to`
+    pub(crate) fn track(self: &Arc<Self>, to: ServerId, total: u64) -> TransferTracker {
+        let _prev = self.progress.lock().insert(
+            to,
+            TransferProgress {
+                total,
+                sent: 0,
+                started_at: Instant::now(),
+            },
+        );
+        TransferTracker {
+            throttle: Arc::clone(self),
+            to,
+        }
+    }
+
+    /// Progress of the ongoing transfers
+    pub(crate) fn progress(&self) -> Vec<(ServerId, TransferProgress)> {
+        self.progress
+            .lock()
+            .iter()
+            .map(|(id, progress)| (*id, *progress))
+            .collect()
+    }
+}
+
+/// Tracks the progress of sending a snapshot, the progress is removed when it's dropped
+#[derive(Debug)]
+pub(crate) struct TransferTracker {
+    /// The throttle holding the progress
+    throttle: Arc<SnapshotThrottle>,
+    /// Receiver of the snapshot
+    to: ServerId,
+}
+
+impl TransferTracker {
+    /// Record `bytes` more bytes sent, returns the updated progress
+    pub(crate) fn advance(&self, bytes: u64) -> Option<TransferProgress> {
+        let mut progress = self.throttle.progress.lock();
+        let entry = progress.get_mut(&self.to)?;
+        entry.sent = entry.sent.saturating_add(bytes);
+        Some(*entry)
+    }
+}
+
+impl Drop for TransferTracker {
+    fn drop(&mut self) {
+        let _ignore = self.throttle.progress.lock().remove(&self.to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: u64 = 64 * 1024;
+
+    /// Time taken to pace 16 chunks of `read` and `send` bytes reserved at once
+    fn pace(throttle: &SnapshotThrottle, read: u64, send: u64) -> Duration {
+        let now = Instant::now();
+        let until = (0..16)
+            .map(|_| throttle.reserve(read, send, now).unwrap_or(now))
+            .last()
+            .unwrap();
+        until - now
+    }
+
+    #[test]
+    fn throttle_should_pace_chunks_at_the_tighter_rate() {
+        // the same limits don't add up
+        let throttle = SnapshotThrottle::new(1024 * 1024, 1024 * 1024, 1);
+        assert_eq!(pace(&throttle, CHUNK, CHUNK), Duration::from_secs(1));
+        let throttle = SnapshotThrottle::new(512 * 1024, 1024 * 1024, 1);
+        assert_eq!(pace(&throttle, CHUNK, CHUNK), Duration::from_secs(2));
+        let throttle = SnapshotThrottle::new(0, 256 * 1024, 1);
+        assert_eq!(pace(&throttle, CHUNK, CHUNK), Duration::from_secs(4));
+        let throttle = SnapshotThrottle::new(0, 0, 1);
+        assert_eq!(pace(&throttle, CHUNK, CHUNK), Duration::ZERO);
+    }
+
+    #[test]
+    fn throttle_should_not_pace_skipped_chunks_against_the_send_rate() {
+        let throttle = SnapshotThrottle::new(0, 1024 * 1024, 1);
+        assert_eq!(pace(&throttle, CHUNK, 0), Duration::ZERO);
+        let throttle = SnapshotThrottle::new(1024 * 1024, 256 * 1024, 1);
+        assert_eq!(pace(&throttle, CHUNK, 0), Duration::from_secs(1));
+    }
 }
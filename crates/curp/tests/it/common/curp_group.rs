@@ -50,7 +50,7 @@ pub mod commandpb {
 
 pub use commandpb::{
     protocol_client::ProtocolClient, FetchClusterRequest, FetchClusterResponse, ProposeRequest,
-    ProposeResponse,
+    ProposeResponse, WaitSyncedRequest,
 };
 
 /// `BOTTOM_TASKS` are tasks which not dependent on other tasks in the task group.
@@ -88,6 +88,19 @@ impl CurpGroup {
         Self::new_with_configs(configs, "S0".to_owned()).await
     }
 
+    pub async fn new_with_curp_config(n_nodes: usize, config: CurpConfig) -> Self {
+        let config = Arc::new(config);
+        let configs = (0..n_nodes)
+            .map(|i| {
+                (
+                    format!("S{i}"),
+                    (Arc::clone(&config), EngineConfig::default()),
+                )
+            })
+            .collect();
+        Self::new_with_configs(configs, "S0".to_owned()).await
+    }
+
     pub async fn new_rocks(n_nodes: usize, path: PathBuf) -> Self {
         let configs = (0..n_nodes)
             .map(|i| {
@@ -139,7 +152,7 @@ impl CurpGroup {
 
             let role_change_cb = TestRoleChange::default();
             let role_change_arc = role_change_cb.get_inner_arc();
-            let curp_storage = Arc::new(DB::open(&config.engine_cfg).unwrap());
+            let curp_storage = Arc::new(DB::open(&config).unwrap());
             let server = Arc::new(
                 Rpc::new(
                     cluster_info,
@@ -271,7 +284,7 @@ impl CurpGroup {
         let id = cluster_info.self_id();
         let role_change_cb = TestRoleChange::default();
         let role_change_arc = role_change_cb.get_inner_arc();
-        let curp_storage = Arc::new(DB::open(&config.engine_cfg).unwrap());
+        let curp_storage = Arc::new(DB::open(&config).unwrap());
         let server = Arc::new(
             Rpc::new(
                 cluster_info,
@@ -1,6 +1,6 @@
 //! Integration test for the curp server
 
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 use clippy_utilities::NumericCast;
 use curp::{
@@ -12,14 +12,19 @@ use curp_test_utils::{
     init_logger, sleep_millis, sleep_secs,
     test_cmd::{TestCommand, TestCommandResult, TestCommandType},
 };
+use futures::StreamExt;
 use madsim::rand::{thread_rng, Rng};
 use test_macros::abort_on_panic;
 use tokio::net::TcpListener;
-use utils::{config::ClientConfig, timestamp};
+use tracing::info;
+use utils::{
+    config::{ClientConfig, CurpConfigBuilder, ResultCacheConfig},
+    timestamp,
+};
 
 use crate::common::curp_group::{
     commandpb::ProposeId, CurpGroup, FetchClusterRequest, ProposeRequest, ProposeResponse,
-    DEFAULT_SHUTDOWN_TIMEOUT,
+    WaitSyncedRequest, DEFAULT_SHUTDOWN_TIMEOUT,
 };
 
 #[tokio::test(flavor = "multi_thread")]
@@ -133,6 +138,7 @@ async fn fast_round_is_slower_than_slow_round() {
             }),
             command: bincode::serialize(&cmd).unwrap(),
             cluster_version: 0,
+            wait_persisted: false,
         }))
         .await
         .unwrap();
@@ -154,6 +160,7 @@ async fn fast_round_is_slower_than_slow_round() {
             }),
             command: bincode::serialize(&cmd).unwrap(),
             cluster_version: 0,
+            wait_persisted: false,
         }))
         .await
         .unwrap()
@@ -183,6 +190,7 @@ async fn concurrent_cmd_order() {
             }),
             command: bincode::serialize(&cmd0).unwrap(),
             cluster_version: 0,
+            wait_persisted: false,
         })
         .await
         .expect("propose failed");
@@ -197,6 +205,7 @@ async fn concurrent_cmd_order() {
             }),
             command: bincode::serialize(&cmd1).unwrap(),
             cluster_version: 0,
+            wait_persisted: false,
         })
         .await;
     assert!(response.is_err());
@@ -208,6 +217,7 @@ async fn concurrent_cmd_order() {
             }),
             command: bincode::serialize(&cmd2).unwrap(),
             cluster_version: 0,
+            wait_persisted: false,
         })
         .await;
     assert!(response.is_err());
@@ -627,3 +637,237 @@ async fn move_leader_should_move_leadership_to_target_node() {
     assert_eq!(target, new_leader);
     assert_ne!(old_leader, new_leader);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn completed_result_should_be_refetched_after_leader_change() {
+    init_logger();
+    let group = CurpGroup::new(3).await;
+    let client = group.new_client().await;
+    let cmd = TestCommand::new_put(vec![0], 0);
+    let propose_id = ProposeId {
+        client_id: 1,
+        seq_num: 1,
+    };
+    let wait_synced_req = || {
+        tonic::Request::new(WaitSyncedRequest {
+            propose_id: Some(propose_id.clone()),
+            cluster_version: 0,
+        })
+    };
+
+    let old_leader = group.get_leader().await.0;
+    let mut leader_connect = group.get_connect(&old_leader).await;
+    let _resp = leader_connect
+        .propose(tonic::Request::new(ProposeRequest {
+            propose_id: Some(propose_id.clone()),
+            command: bincode::serialize(&cmd).unwrap(),
+            cluster_version: 0,
+            wait_persisted: false,
+        }))
+        .await
+        .unwrap();
+    let synced = leader_connect
+        .wait_synced(wait_synced_req())
+        .await
+        .unwrap()
+        .into_inner();
+
+    // wait for the followers to apply the cmd
+    sleep_secs(1).await;
+    let target = *group.nodes.keys().find(|&id| &old_leader != id).unwrap();
+    client.move_leader(target).await.unwrap();
+    assert_eq!(group.get_leader().await.0, target);
+
+    let mut new_leader_connect = group.get_connect(&target).await;
+    let refetched = new_leader_connect
+        .wait_synced(wait_synced_req())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(refetched, synced);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn async_propose_should_return_before_execution() {
+    init_logger();
+    let group = CurpGroup::new(3).await;
+    let client = group.new_client().await;
+    let cmd = TestCommand::new_put(vec![0], 0).set_exe_dur(Duration::from_secs(2));
+
+    let start = std::time::Instant::now();
+    let ticket = client.propose_async(&cmd, None).await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let (er, asr) = client.wait_synced(ticket).await.unwrap().unwrap();
+    assert_eq!(er, TestCommandResult::new(vec![], vec![]));
+    assert!(asr.is_some());
+    // the result stays in the result cache for later waits
+    let (refetched, _asr) = client.wait_synced(ticket).await.unwrap().unwrap();
+    assert_eq!(refetched, er);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn async_propose_result_should_be_fetched_after_leader_change() {
+    init_logger();
+    let group = CurpGroup::new(3).await;
+    let client = group.new_client().await;
+    let cmd = TestCommand::new_put(vec![0], 0).set_exe_dur(Duration::from_millis(500));
+
+    let ticket = client.propose_async(&cmd, None).await.unwrap();
+    let old_leader = group.get_leader().await.0;
+    let target = *group.nodes.keys().find(|&id| &old_leader != id).unwrap();
+    client.move_leader(target).await.unwrap();
+    assert_eq!(group.get_leader().await.0, target);
+
+    let (er, asr) = client.wait_synced(ticket).await.unwrap().unwrap();
+    assert_eq!(er, TestCommandResult::new(vec![], vec![]));
+    assert!(asr.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn async_propose_ticket_should_be_unknown_after_eviction() {
+    init_logger();
+    let config = CurpConfigBuilder::default()
+        .gc_interval(Duration::from_millis(100))
+        .result_cache(ResultCacheConfig {
+            results_per_client: 1,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let group = CurpGroup::new_with_curp_config(3, config).await;
+    let client = group.new_client().await;
+
+    let ticket = client
+        .propose_async(&TestCommand::new_put(vec![0], 0), None)
+        .await
+        .unwrap();
+    let _synced = client.wait_synced(ticket).await.unwrap().unwrap();
+    // the next result of the same client evicts the previous one
+    let _er = client
+        .propose(&TestCommand::new_put(vec![1], 1), None, false)
+        .await
+        .unwrap()
+        .unwrap();
+    sleep_millis(500).await;
+
+    let err = client.wait_synced(ticket).await.unwrap_err();
+    assert_eq!(CurpError::from(err), CurpError::ResultExpired(()));
+}
+
+/// Propose `n` small non-conflicting cmds through a few concurrent streams, return the
+/// elapsed time and the number of log entries the leader applied them in
+async fn propose_small_cmds(group: &mut CurpGroup, n: u32) -> (Duration, usize) {
+    /// Number of proposals in flight
+    const CONCURRENCY: usize = 256;
+
+    let leader = group.get_leader().await.0;
+    let client = group.new_client().await;
+    let start = std::time::Instant::now();
+    futures::stream::iter(0..n)
+        .map(|i| {
+            let client = &client;
+            async move {
+                client
+                    .propose(&TestCommand::new_put(vec![i], i), None, true)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = start.elapsed();
+
+    let as_rx = &mut group.nodes.get_mut(&leader).unwrap().as_rx;
+    let mut indexes = HashSet::new();
+    for _ in 0..n {
+        let (_cmd, index) = as_rx.recv().await.unwrap();
+        let _ignore = indexes.insert(index);
+    }
+    (elapsed, indexes.len())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn batched_proposals_should_use_fewer_log_entries() {
+    init_logger();
+    const N: u32 = 10_000;
+
+    let mut unbatched = CurpGroup::new(3).await;
+    let (unbatched_elapsed, unbatched_entries) = propose_small_cmds(&mut unbatched, N).await;
+    assert_eq!(unbatched_entries, N.numeric_cast::<usize>());
+
+    let config = CurpConfigBuilder::default()
+        .propose_batch_max_size(64)
+        .propose_batch_max_delay(Duration::from_millis(1))
+        .build()
+        .unwrap();
+    let mut batched = CurpGroup::new_with_curp_config(3, config).await;
+    let (batched_elapsed, batched_entries) = propose_small_cmds(&mut batched, N).await;
+    assert!(
+        batched_entries < unbatched_entries,
+        "{batched_entries} batched entries, {unbatched_entries} unbatched entries"
+    );
+
+    info!(
+        "{N} proposals, unbatched: {unbatched_elapsed:?} in {unbatched_entries} entries, \
+        batched: {batched_elapsed:?} in {batched_entries} entries"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn abandoned_propose_should_be_canceled_before_appended() {
+    init_logger();
+    // the proposal waits in the pending batch long after it's abandoned
+    let config = CurpConfigBuilder::default()
+        .propose_batch_max_size(64)
+        .propose_batch_max_delay(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let group = CurpGroup::new_with_curp_config(3, config).await;
+    let client = group.new_client().await;
+
+    let abandoned = tokio::time::timeout(
+        Duration::from_millis(200),
+        client.propose(&TestCommand::new_put(vec![0], 0), None, true),
+    )
+    .await;
+    assert!(abandoned.is_err());
+    sleep_secs(2).await;
+
+    let (er, _asr) = client
+        .propose(&TestCommand::new_get(vec![0]), None, true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(er, TestCommandResult::new(vec![], vec![]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn abandoned_propose_should_execute_once_appended() {
+    init_logger();
+    let group = CurpGroup::new(3).await;
+    let client = group.new_client().await;
+    // the proposal is appended right away, but its execution outlasts the request
+    let cmd = TestCommand::new_put(vec![0], 0).set_exe_dur(Duration::from_secs(1));
+
+    let abandoned =
+        tokio::time::timeout(Duration::from_millis(300), client.propose(&cmd, None, true)).await;
+    assert!(abandoned.is_err());
+    sleep_secs(2).await;
+
+    let (er, _asr) = client
+        .propose(&TestCommand::new_get(vec![0]), None, true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(er.values, vec![0]);
+}
@@ -0,0 +1,42 @@
+#![cfg(bench)]
+#![feature(test)]
+
+extern crate engine;
+extern crate test;
+
+use std::{hint::black_box, path::PathBuf};
+
+use engine::{Engine, EngineType, StorageEngine, WriteOperation};
+use test::Bencher;
+
+/// Size of the values in the scanned table
+const VALUE_SIZE: usize = 1024 * 1024;
+
+/// Number of keys in the scanned table
+const KEYS: u32 = 64;
+
+fn rocks_engine(name: &str) -> (Engine, PathBuf) {
+    let dir = PathBuf::from(format!("/tmp/scan_keys_bench_{name}"));
+    let engine = Engine::new(EngineType::Rocks(dir.join("rocks_engine")), &["kv"]).unwrap();
+    let batch = (0..KEYS)
+        .map(|i| WriteOperation::new_put("kv", i.to_be_bytes().to_vec(), vec![0; VALUE_SIZE]))
+        .collect();
+    engine.write_batch(batch, true).unwrap();
+    (engine, dir)
+}
+
+#[bench]
+fn bench_get_all_with_large_values(b: &mut Bencher) {
+    let (engine, dir) = rocks_engine("get_all");
+    b.iter(|| black_box(engine.get_all("kv").unwrap().len()));
+    drop(engine);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[bench]
+fn bench_scan_keys_with_large_values(b: &mut Bencher) {
+    let (engine, dir) = rocks_engine("scan_keys");
+    b.iter(|| black_box(engine.scan_keys("kv", b"", b"").unwrap().len()));
+    drop(engine);
+    std::fs::remove_dir_all(dir).unwrap();
+}
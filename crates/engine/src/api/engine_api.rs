@@ -38,6 +38,15 @@ pub trait StorageEngine: Send + Sync + 'static + std::fmt::Debug {
     #[allow(clippy::type_complexity)] // it's clear that (Vec<u8>, Vec<u8>) is a key-value pair
     fn get_all(&self, table: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, EngineError>;
 
+    /// Get the keys of the given table in the range `[from, to)` in order, an empty `to`
+    /// means the end of the table. The values are never copied out, prefer it to
+    /// `get_all` when only the keys are needed
+    ///
+    /// # Errors
+    /// Return `EngineError::TableNotFound` if the given table does not exist
+    /// Return `EngineError` if met some errors
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError>;
+
     /// Commit a batch of write operations
     /// If sync is true, the write will be flushed from the operating system
     /// buffer cache before the write is considered complete. If this
@@ -53,4 +53,9 @@ pub trait SnapshotApi: Send + Sync + std::fmt::Debug {
 pub trait SnapshotAllocator: Send + Sync {
     /// Allocate a new snapshot
     async fn allocate_new_snapshot(&self) -> Result<Snapshot, Box<dyn Error>>;
+
+    /// Clean up the snapshots left partially received by a previous run
+    async fn clean_stale(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
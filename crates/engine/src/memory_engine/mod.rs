@@ -3,7 +3,7 @@ mod transaction;
 
 use std::{
     cmp::Ordering,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{Cursor, Seek},
     path::Path,
     sync::Arc,
@@ -30,6 +30,10 @@ type MemoryTable = HashMap<Vec<u8>, Vec<u8>>;
 pub struct MemoryEngine {
     /// The inner storage engine of `MemoryStorage`
     inner: Arc<RwLock<HashMap<String, MemoryTable>>>,
+    /// Tables whose reads are forced to fail with a corruption error
+    faulty_tables: Arc<RwLock<HashSet<String>>>,
+    /// Tables whose writes are forced to fail with an I/O error
+    write_faulty_tables: Arc<RwLock<HashSet<String>>>,
 }
 
 impl MemoryEngine {
@@ -42,6 +46,8 @@ impl MemoryEngine {
         }
         Self {
             inner: Arc::new(RwLock::new(inner)),
+            faulty_tables: Arc::default(),
+            write_faulty_tables: Arc::default(),
         }
     }
 
@@ -51,8 +57,61 @@ impl MemoryEngine {
     pub(crate) fn new_from_db(db: HashMap<String, HashMap<Vec<u8>, Vec<u8>>>) -> Self {
         Self {
             inner: Arc::new(RwLock::new(db)),
+            faulty_tables: Arc::default(),
+            write_faulty_tables: Arc::default(),
         }
     }
+
+    /// Serialize all the tables of the engine
+    #[cfg(madsim)]
+    pub(crate) fn dump(&self) -> Result<Vec<u8>, EngineError> {
+        bincode::serialize(&*self.inner.read()).map_err(|e| {
+            EngineError::UnderlyingError(format!("serialize memory engine failed: {e:?}"))
+        })
+    }
+
+    /// Make all following reads of `table` fail as if the data were corrupted
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub(crate) fn inject_read_fault(&self, table: &str) {
+        let _ignore = self.faulty_tables.write().insert(table.to_owned());
+    }
+
+    /// Check whether reads of `table` should fail
+    fn check_read_fault(&self, table: &str) -> Result<(), EngineError> {
+        if self.faulty_tables.read().contains(table) {
+            return Err(EngineError::Corruption(format!(
+                "injected read fault on table {table}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make all following batches writing to `table` fail as if the disk failed, none
+    /// of the writes of a failed batch is applied
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub(crate) fn inject_write_fault(&self, table: &str) {
+        let _ignore = self.write_faulty_tables.write().insert(table.to_owned());
+    }
+
+    /// Check whether a batch of writes should fail
+    fn check_write_fault(&self, wr_ops: &[WriteOperation<'_>]) -> Result<(), EngineError> {
+        let faulty = self.write_faulty_tables.read();
+        if faulty.is_empty() {
+            return Ok(());
+        }
+        for op in wr_ops {
+            let (WriteOperation::Put { table, .. }
+            | WriteOperation::Delete { table, .. }
+            | WriteOperation::DeleteRange { table, .. }) = *op;
+            if faulty.contains(table) {
+                return Err(EngineError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("injected write fault on table {table}"),
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -75,6 +134,7 @@ impl StorageEngine for MemoryEngine {
 
     #[inline]
     fn get(&self, table: &str, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>, EngineError> {
+        self.check_read_fault(table)?;
         let inner = self.inner.read();
         let table = inner
             .get(table)
@@ -88,6 +148,7 @@ impl StorageEngine for MemoryEngine {
         table: &str,
         keys: &[impl AsRef<[u8]>],
     ) -> Result<Vec<Option<Vec<u8>>>, EngineError> {
+        self.check_read_fault(table)?;
         let inner = self.inner.read();
         let table = inner
             .get(table)
@@ -101,6 +162,7 @@ impl StorageEngine for MemoryEngine {
 
     #[inline]
     fn get_all(&self, table: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, EngineError> {
+        self.check_read_fault(table)?;
         let inner = self.inner.read();
         let table = inner
             .get(table)
@@ -113,8 +175,25 @@ impl StorageEngine for MemoryEngine {
         Ok(values)
     }
 
+    #[inline]
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError> {
+        self.check_read_fault(table)?;
+        let inner = self.inner.read();
+        let table = inner
+            .get(table)
+            .ok_or_else(|| EngineError::TableNotFound(table.to_owned()))?;
+        let mut keys = table
+            .keys()
+            .filter(|key| key.as_slice() >= from && (to.is_empty() || key.as_slice() < to))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+
     #[inline]
     fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, _sync: bool) -> Result<(), EngineError> {
+        self.check_write_fault(&wr_ops)?;
         let mut inner = self.inner.write();
         for op in wr_ops {
             match op {
@@ -155,11 +234,14 @@ impl StorageEngine for MemoryEngine {
     fn get_snapshot(
         &self,
         _path: impl AsRef<Path>,
-        _tables: &[&'static str],
+        tables: &[&'static str],
     ) -> Result<Self::Snapshot, EngineError> {
         let inner_r = self.inner.read();
-        let db = &*inner_r;
-        let data = bincode::serialize(db).map_err(|e| {
+        let db: HashMap<&String, &MemoryTable> = inner_r
+            .iter()
+            .filter(|&(name, _)| tables.contains(&name.as_str()))
+            .collect();
+        let data = bincode::serialize(&db).map_err(|e| {
             EngineError::UnderlyingError(format!("serialize memory engine failed: {e:?}"))
         })?;
         Ok(MemorySnapshot::new(data))
@@ -169,15 +251,21 @@ impl StorageEngine for MemoryEngine {
     async fn apply_snapshot(
         &self,
         snapshot: Self::Snapshot,
-        _tables: &[&'static str],
+        tables: &[&'static str],
     ) -> Result<(), EngineError> {
-        let mut inner = self.inner.write();
-        let db = &mut *inner;
         let data = snapshot.into_inner();
-        let new_db = bincode::deserialize(&data).map_err(|e| {
-            EngineError::UnderlyingError(format!("deserialize memory engine failed: {e:?}"))
-        })?;
-        *db = new_db;
+        let mut new_db: HashMap<String, MemoryTable> =
+            bincode::deserialize(&data).map_err(|e| {
+                EngineError::UnderlyingError(format!("deserialize memory engine failed: {e:?}"))
+            })?;
+        // only the given tables are replaced, the others are kept
+        let mut inner = self.inner.write();
+        for table in tables {
+            let _ignore = inner.insert(
+                (*table).to_owned(),
+                new_db.remove(*table).unwrap_or_default(),
+            );
+        }
         Ok(())
     }
 
@@ -98,6 +98,14 @@ where
         self.engine.get_all(table)
     }
 
+    /// Get the keys of the given table in the range `[from, to)`
+    /// # Errors
+    /// Return `EngineError::TableNotFound` if the given table does not exist
+    /// Return `EngineError` if met some errors
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError> {
+        self.engine.scan_keys(table, from, to)
+    }
+
     /// Commit a batch of write operations
     /// If sync is true, the write will be flushed from the operating system
     /// buffer cache before the write is considered complete. If this
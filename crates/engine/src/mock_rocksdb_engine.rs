@@ -56,7 +56,7 @@ impl RocksEngine {
     ///
     /// Return `EngineError` when get snapshot failed or encounter fs error.
     fn fs_sync(&self) -> Result<(), EngineError> {
-        let db = self.inner.get_snapshot("", &[])?.into_inner();
+        let db = self.inner.dump()?;
         let mut path = self.path.clone();
         path.push(Path::new("persistent"));
         fs::write(path, db)?;
@@ -103,6 +103,11 @@ impl StorageEngine for RocksEngine {
         self.inner.get_all(table)
     }
 
+    #[inline]
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError> {
+        self.inner.scan_keys(table, from, to)
+    }
+
     #[inline]
     fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, sync: bool) -> Result<(), EngineError> {
         self.inner.write_batch(wr_ops, sync)?;
@@ -63,6 +63,46 @@ impl Engine {
             }
         }
     }
+
+    /// Make all following reads of `table` fail with `EngineError::Corruption`, only for
+    /// fault-injection tests
+    ///
+    /// # Errors
+    ///
+    /// Return `EngineError::InvalidArgument` if the engine is not a `MemoryEngine`
+    #[cfg(any(test, feature = "fault-injection"))]
+    #[inline]
+    pub fn inject_read_fault(&self, table: &str) -> Result<(), EngineError> {
+        match *self {
+            Engine::Memory(ref e) => {
+                e.inject_read_fault(table);
+                Ok(())
+            }
+            Engine::Rocks(ref _e) => Err(EngineError::InvalidArgument(
+                "Rocks engine does not support read fault injection".to_owned(),
+            )),
+        }
+    }
+
+    /// Make all following batches writing to `table` fail with `EngineError::IoError`, only for
+    /// fault-injection tests
+    ///
+    /// # Errors
+    ///
+    /// Return `EngineError::InvalidArgument` if the engine is not a `MemoryEngine`
+    #[cfg(any(test, feature = "fault-injection"))]
+    #[inline]
+    pub fn inject_write_fault(&self, table: &str) -> Result<(), EngineError> {
+        match *self {
+            Engine::Memory(ref e) => {
+                e.inject_write_fault(table);
+                Ok(())
+            }
+            Engine::Rocks(ref _e) => Err(EngineError::InvalidArgument(
+                "Rocks engine does not support write fault injection".to_owned(),
+            )),
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -106,6 +146,14 @@ impl StorageEngine for Engine {
         }
     }
 
+    #[inline]
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError> {
+        match *self {
+            Engine::Memory(ref e) => e.scan_keys(table, from, to),
+            Engine::Rocks(ref e) => e.scan_keys(table, from, to),
+        }
+    }
+
     #[inline]
     fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, sync: bool) -> Result<(), EngineError> {
         match *self {
@@ -306,6 +354,107 @@ mod test {
         std::fs::remove_dir_all(dir).unwrap();
     }
 
+    #[test]
+    fn injected_read_fault_should_fail_reads_with_corruption() {
+        let engine = Engine::new(EngineType::Memory, &TESTTABLES).unwrap();
+        let put = WriteOperation::new_put("kv", b"hello".to_vec(), b"world".to_vec());
+        engine.write_batch(vec![put], false).unwrap();
+        engine.inject_read_fault("kv").unwrap();
+
+        assert!(matches!(
+            engine.get("kv", b"hello"),
+            Err(EngineError::Corruption(_))
+        ));
+        assert!(matches!(
+            engine.get_multi("kv", &[b"hello"]),
+            Err(EngineError::Corruption(_))
+        ));
+        assert!(matches!(
+            engine.get_all("kv"),
+            Err(EngineError::Corruption(_))
+        ));
+        assert!(matches!(
+            engine.scan_keys("kv", b"", b""),
+            Err(EngineError::Corruption(_))
+        ));
+        assert!(engine.get_all("lease").is_ok());
+    }
+
+    #[test]
+    fn injected_write_fault_should_fail_the_whole_batch() {
+        let engine = Engine::new(EngineType::Memory, &TESTTABLES).unwrap();
+        engine.inject_write_fault("kv").unwrap();
+        let batch = vec![
+            WriteOperation::new_put("lease", b"hello".to_vec(), b"world".to_vec()),
+            WriteOperation::new_put("kv", b"hello".to_vec(), b"world".to_vec()),
+        ];
+
+        assert!(matches!(
+            engine.write_batch(batch, true),
+            Err(EngineError::IoError(_))
+        ));
+        assert!(engine.get("lease", b"hello").unwrap().is_none());
+        let put = WriteOperation::new_put("lease", b"hello".to_vec(), b"world".to_vec());
+        engine.write_batch(vec![put], true).unwrap();
+        assert!(engine.get("lease", b"hello").unwrap().is_some());
+    }
+
+    #[test]
+    fn fault_injection_should_be_rejected_by_rocks() {
+        let dir = PathBuf::from("/tmp/fault_injection_should_be_rejected_by_rocks");
+        let engine = Engine::new(EngineType::Rocks(dir.join("rocks_engine")), &TESTTABLES).unwrap();
+        assert!(matches!(
+            engine.inject_read_fault("kv"),
+            Err(EngineError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            engine.inject_write_fault("kv"),
+            Err(EngineError::InvalidArgument(_))
+        ));
+        drop(engine);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scan_keys_should_visit_the_same_keys_as_get_all() {
+        let dir = PathBuf::from("/tmp/scan_keys_should_visit_the_same_keys_as_get_all");
+        let rocks_engine_path = dir.join("rocks_engine");
+        let engines = vec![
+            Engine::new(EngineType::Memory, &TESTTABLES).unwrap(),
+            Engine::new(EngineType::Rocks(rocks_engine_path), &TESTTABLES).unwrap(),
+        ];
+        for engine in engines {
+            let batch = (0..100_u32)
+                .rev()
+                .map(|i| WriteOperation::new_put("kv", i.to_be_bytes().to_vec(), vec![0; 64]))
+                .collect();
+            engine.write_batch(batch, false).unwrap();
+
+            let all_keys: Vec<_> = engine
+                .get_all("kv")
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            assert_eq!(all_keys.len(), 100);
+            assert_eq!(engine.scan_keys("kv", b"", b"").unwrap(), all_keys);
+
+            let (from, to) = (10_u32.to_be_bytes(), 20_u32.to_be_bytes());
+            assert_eq!(
+                engine.scan_keys("kv", &from, &to).unwrap(),
+                all_keys[10..20].to_vec()
+            );
+            assert_eq!(
+                engine.scan_keys("kv", &from, b"").unwrap(),
+                all_keys[10..].to_vec()
+            );
+            assert!(engine.scan_keys("kv", &to, &from).unwrap().is_empty());
+            assert!(engine.scan_keys("lease", b"", b"").unwrap().is_empty());
+            assert!(engine.scan_keys("hello", b"", b"").is_err());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[test]
     fn write_batch_should_success() {
         let dir = PathBuf::from("/tmp/write_batch_should_success");
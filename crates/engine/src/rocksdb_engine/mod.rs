@@ -15,7 +15,7 @@ use bytes::{Buf, Bytes, BytesMut};
 use clippy_utilities::{NumericCast, OverflowArithmetic};
 use rocksdb::{
     Direction, Error as RocksError, ErrorKind as RocksErrorKind, IteratorMode,
-    OptimisticTransactionDB, Options, SstFileWriter,
+    OptimisticTransactionDB, Options, ReadOptions, SstFileWriter,
 };
 use serde::{Deserialize, Serialize};
 use tokio::{fs::File, io::AsyncWriteExt};
@@ -199,6 +199,32 @@ impl StorageEngine for RocksEngine {
         }
     }
 
+    #[inline]
+    fn scan_keys(&self, table: &str, from: &[u8], to: &[u8]) -> Result<Vec<Vec<u8>>, EngineError> {
+        let Some(cf) = self.inner.cf_handle(table) else {
+            return Err(EngineError::TableNotFound(table.to_owned()));
+        };
+        if !to.is_empty() && from >= to {
+            return Ok(vec![]);
+        }
+        let mut opts = ReadOptions::default();
+        // a scan touches each block once, don't let it evict the hot ones
+        opts.fill_cache(false);
+        opts.set_iterate_lower_bound(from);
+        if !to.is_empty() {
+            opts.set_iterate_upper_bound(to);
+        }
+        let mut iter = self.inner.raw_iterator_cf_opt(&cf, opts);
+        iter.seek_to_first();
+        let mut keys = Vec::new();
+        while let Some(key) = iter.key() {
+            keys.push(key.to_vec());
+            iter.next();
+        }
+        iter.status()?;
+        Ok(keys)
+    }
+
     #[inline]
     fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, _sync: bool) -> Result<(), EngineError> {
         let mut retry_interval = 10;
@@ -616,6 +642,13 @@ impl RocksSnapshot {
                 let old = self.current_file.take();
                 if let Some(mut old_f) = old {
                     old_f.flush().await?;
+                    utils::fail_point_async!(
+                        "snapshot_before_rename",
+                        Err(io::Error::new(
+                            ErrorKind::Other,
+                            "injected failure before the snapshot rename"
+                        ))
+                    );
                     let path = self.current_file_path(false);
                     let tmp_path = self.current_file_path(true);
                     fs::rename(tmp_path, path)?;
@@ -675,9 +708,19 @@ impl SnapshotApi for RocksSnapshot {
 
     #[inline]
     async fn clean(&mut self) -> io::Result<()> {
+        // a partially received snapshot misses some files, and the current one is
+        // staged as a temp file
+        let _ignore = self.current_file.take();
         for snap_file in &self.snap_files {
-            let path = self.dir.join(&snap_file.filename);
-            tokio::fs::remove_file(path).await?;
+            for filename in [
+                snap_file.filename.clone(),
+                format!("{}.tmp", snap_file.filename),
+            ] {
+                match tokio::fs::remove_file(self.dir.join(filename)).await {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    Ok(()) | Err(_) => {}
+                }
+            }
         }
         Ok(())
     }
@@ -688,6 +731,7 @@ mod test {
     use std::env::temp_dir;
 
     use test_macros::abort_on_panic;
+    use utils::failpoint::{FailAction, FailScenario};
 
     use super::*;
 
@@ -738,4 +782,51 @@ mod test {
         assert!(size2 > size1);
         fs::remove_dir_all(path).unwrap();
     }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_failed_snapshot_rename_should_not_leave_a_final_file() {
+        let scenario = FailScenario::setup();
+        let dir = temp_dir().join("test_failed_snapshot_rename");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let engine = RocksEngine::new(dir.join("engine"), &TEST_TABLES).unwrap();
+        engine
+            .write_batch(
+                vec![WriteOperation::new_put(
+                    "t1",
+                    b"key".to_vec(),
+                    b"value".to_vec(),
+                )],
+                true,
+            )
+            .unwrap();
+        let mut snapshot = engine
+            .get_snapshot(dir.join("snapshot"), &TEST_TABLES)
+            .unwrap();
+        let mut buf = BytesMut::with_capacity(snapshot.size().numeric_cast());
+        snapshot.read_buf_exact(&mut buf).await.unwrap();
+        let data = buf.freeze();
+
+        // the receiver fails right before renaming the first received file
+        scenario.cfg_local("snapshot_before_rename", FailAction::Return);
+        let mut received = RocksSnapshot::new_for_receiving(dir.join("received")).unwrap();
+        assert!(received.write_all(data.clone()).await.is_err());
+        let filename = received.snap_files[0].filename.clone();
+        let tmp_path = received.dir.join(format!("{filename}.tmp"));
+        assert!(!received.dir.join(&filename).exists());
+        assert!(tmp_path.exists());
+        received.clean().await.unwrap();
+        assert!(!tmp_path.exists());
+
+        // a retry receives the whole snapshot
+        scenario.remove("snapshot_before_rename");
+        let mut received = RocksSnapshot::new_for_receiving(dir.join("received")).unwrap();
+        received.write_all(data).await.unwrap();
+        let target = RocksEngine::new(dir.join("target"), &TEST_TABLES).unwrap();
+        target.apply_snapshot(received, &TEST_TABLES).await.unwrap();
+        assert_eq!(target.get("t1", b"key").unwrap(), Some(b"value".to_vec()));
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
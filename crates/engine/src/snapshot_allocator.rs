@@ -1,20 +1,63 @@
-use std::{env::temp_dir, error::Error};
+use std::{env::temp_dir, error::Error, fs, io, path::PathBuf};
 
 use crate::{api::snapshot_api::SnapshotAllocator, EngineType, Snapshot};
 
+/// Prefix of the directories the received snapshots are staged in
+const STAGING_PREFIX: &str = "snapshot-";
+
 /// Rocks snapshot allocator
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 #[allow(clippy::module_name_repetitions)]
-pub struct RocksSnapshotAllocator;
+pub struct RocksSnapshotAllocator {
+    /// The directory dedicated to staging the received snapshots, `None` stages them
+    /// in the shared temp directory, where nothing is cleaned up
+    staging_dir: Option<PathBuf>,
+}
+
+impl RocksSnapshotAllocator {
+    /// New `RocksSnapshotAllocator` staging the received snapshots in `staging_dir`, which
+    /// must not be shared with other nodes as its stale snapshots are cleaned up
+    #[inline]
+    #[must_use]
+    pub fn new(staging_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            staging_dir: Some(staging_dir.into()),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 impl SnapshotAllocator for RocksSnapshotAllocator {
     #[inline]
     async fn allocate_new_snapshot(&self) -> Result<Snapshot, Box<dyn Error>> {
-        let tmp_path = temp_dir().join(format!("snapshot-{}", uuid::Uuid::new_v4()));
+        let dir = self.staging_dir.clone().unwrap_or_else(temp_dir);
+        let tmp_path = dir.join(format!("{STAGING_PREFIX}{}", uuid::Uuid::new_v4()));
         Ok(Snapshot::new_for_receiving(EngineType::Rocks(tmp_path))?)
     }
+
+    #[inline]
+    async fn clean_stale(&self) -> Result<(), Box<dyn Error>> {
+        let Some(ref dir) = self.staging_dir else {
+            return Ok(());
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(STAGING_PREFIX))
+            {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Memory snapshot allocator
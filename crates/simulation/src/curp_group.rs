@@ -2,7 +2,8 @@ use std::{collections::HashMap, error::Error, path::PathBuf, sync::Arc, time::Du
 
 use async_trait::async_trait;
 pub use curp::rpc::{
-    protocol_client::ProtocolClient, PbProposeId, ProposeRequest, ProposeResponse,
+    protocol_client::ProtocolClient, FetchReadStateRequest, PbProposeId, ProposeRequest,
+    ProposeResponse,
 };
 use curp::{
     client::{ClientApi, ClientBuilder},
@@ -117,7 +118,7 @@ impl CurpGroup {
                                 .build()
                                 .unwrap(),
                         );
-                        let curp_storage = Arc::new(DB::open(&curp_config.engine_cfg).unwrap());
+                        let curp_storage = Arc::new(DB::open(&curp_config).unwrap());
                         let cluster_info = match curp_storage.recover_cluster_info().unwrap() {
                             Some(cl) => Arc::new(cl),
                             None => Arc::clone(&cluster_info),
@@ -446,6 +447,21 @@ impl SimProtocolClient {
             .await
             .unwrap()
     }
+
+    #[inline]
+    pub async fn fetch_read_state(
+        &self,
+        req: FetchReadStateRequest,
+    ) -> Result<tonic::Response<curp::rpc::FetchReadStateResponse>, tonic::Status> {
+        let addr = self.addr.clone();
+        self.handle
+            .spawn(async move {
+                let mut client = ProtocolClient::connect(addr).await.unwrap();
+                client.fetch_read_state(req).await
+            })
+            .await
+            .unwrap()
+    }
 }
 
 pub struct SimClient<C: Command> {
@@ -5,8 +5,8 @@ use madsim::runtime::NodeHandle;
 use tonic::transport::Channel;
 use tracing::debug;
 use utils::config::{
-    AuthConfig, ClientConfig, ClusterConfig, CompactConfig, CurpConfig, InitialClusterState,
-    ServerTimeout, StorageConfig, TlsConfig,
+    AuthConfig, ClientConfig, ClusterConfig, ClusterConfigBuilder, CompactConfig, CurpConfig,
+    InitialClusterState, ServerTimeout, StorageConfig, TlsConfig,
 };
 use xline::server::XlineServer;
 use xline_client::{
@@ -48,7 +48,7 @@ impl XlineGroup {
                 let name = format!("S{i}");
                 let client_url = format!("192.168.1.{}:2379", i + 1);
                 let peer_url = format!("192.168.1.{}:2380", i + 1);
-                let cluster_config = ClusterConfig::new(
+                let cluster_config = ClusterConfigBuilder::from(ClusterConfig::new(
                     name.clone(),
                     vec!["0.0.0.0:2380".to_owned()],
                     vec![format!("192.168.1.{}:2380", i + 1)],
@@ -60,7 +60,10 @@ impl XlineGroup {
                     ClientConfig::default(),
                     ServerTimeout::default(),
                     InitialClusterState::New,
-                );
+                ))
+                .watch_audit_sample_percent(100)
+                .build()
+                .unwrap();
 
                 let handle = handle
                     .create_node()
@@ -1,6 +1,6 @@
 use curp::members::ServerId;
 use curp_test_utils::{init_logger, sleep_secs, test_cmd::TestCommand};
-use simulation::curp_group::CurpGroup;
+use simulation::curp_group::{CurpGroup, FetchReadStateRequest};
 
 /// Wait some time for the election to finish, and get the leader to ensure that the election is
 /// completed.
@@ -192,3 +192,49 @@ async fn conflict_should_detected_in_new_leader() {
         vec![0]
     );
 }
+
+// Read index
+#[madsim::test]
+async fn partitioned_stale_leader_should_not_serve_read_index() {
+    init_logger();
+
+    let group = CurpGroup::new(5).await;
+    let client = group.new_client().await;
+    let (leader1, _term) = group.get_leader().await;
+    let _ig = client
+        .propose(TestCommand::new_put(vec![0], 0), true)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // partition the leader from the other servers, it can still be reached by the client
+    for id in group.nodes.keys().filter(|id| **id != leader1) {
+        group.clog_link_nodes(leader1, *id);
+    }
+    let read_state_req = FetchReadStateRequest {
+        command: bincode::serialize(&TestCommand::new_get(vec![0])).unwrap(),
+        cluster_version: 0,
+    };
+    let connect = group.get_connect(&leader1).await;
+
+    // the leader can't confirm its leadership even before a new leader is elected
+    assert!(connect
+        .fetch_read_state(read_state_req.clone())
+        .await
+        .is_err());
+
+    let (leader2, _term) = wait_for_election(&group).await;
+    assert_ne!(leader1, leader2);
+    let _ig = client
+        .propose(TestCommand::new_put(vec![0], 1), true)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the stale leader must not serve the read, but the new one does
+    assert!(connect.fetch_read_state(read_state_req).await.is_err());
+    assert!(client
+        .fetch_read_state(&TestCommand::new_get(vec![0]))
+        .await
+        .is_ok());
+}
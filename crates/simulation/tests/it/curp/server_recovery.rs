@@ -111,6 +111,25 @@ async fn follower_crash_and_recovery() {
     assert_eq!(asr.1, 3);
 }
 
+#[madsim::test]
+async fn restarted_follower_should_not_change_term() {
+    init_logger();
+
+    let mut group = CurpGroup::new(3).await;
+    let (leader, term) = group.get_leader().await;
+    let follower = *group.nodes.keys().find(|&id| id != &leader).unwrap();
+    group.crash(follower).await;
+
+    // the remaining two nodes are still a healthy quorum
+    assert_eq!(group.get_leader().await, (leader, term));
+
+    group.restart(follower).await;
+    sleep_secs(15).await;
+
+    assert_eq!(group.get_leader().await, (leader, term));
+    assert_eq!(group.get_term_checked().await, term);
+}
+
 #[madsim::test]
 async fn leader_and_follower_both_crash_and_recovery() {
     init_logger();
@@ -193,6 +212,7 @@ async fn new_leader_will_recover_spec_cmds_cond1() {
         }),
         command: bincode::serialize(&cmd1).unwrap(),
         cluster_version: 0,
+        wait_persisted: false,
     };
     for id in group
         .all_members
@@ -304,6 +324,7 @@ async fn old_leader_will_keep_original_states() {
         }),
         command: bincode::serialize(&cmd1).unwrap(),
         cluster_version: 0,
+        wait_persisted: false,
     };
     let mut leader1_connect = group.get_connect(&leader1).await;
     leader1_connect.propose(req1).await.unwrap();
@@ -78,24 +78,50 @@ pub mod bytes_format {
     }
 }
 
+/// urls deserialization formatter
+pub mod urls_format {
+    use serde::{Deserialize, Deserializer};
+
+    use crate::parse_url;
+
+    /// deserializes and normalizes a list of urls
+    #[allow(single_use_lifetimes)] //  the false positive case blocks us
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|url| parse_url(url).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
 /// Cluster configuration object, including cluster relevant configuration fields
+///
+/// The fields not set by `ClusterConfigBuilder` are the default ones.
 #[allow(clippy::module_name_repetitions)]
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters, Builder)]
+#[builder(default)]
 pub struct ClusterConfig {
     /// Get xline server name
     #[getset(get = "pub")]
     name: String,
     /// Xline server peer listen urls
     #[getset(get = "pub")]
+    #[serde(deserialize_with = "urls_format::deserialize")]
     peer_listen_urls: Vec<String>,
     /// Xline server peer advertise urls
     #[getset(get = "pub")]
+    #[serde(deserialize_with = "urls_format::deserialize")]
     peer_advertise_urls: Vec<String>,
     /// Xline server client listen urls
     #[getset(get = "pub")]
+    #[serde(deserialize_with = "urls_format::deserialize")]
     client_listen_urls: Vec<String>,
     /// Xline server client advertise urls
     #[getset(get = "pub")]
+    #[serde(deserialize_with = "urls_format::deserialize")]
     client_advertise_urls: Vec<String>,
     /// All the nodes in the xline cluster
     #[getset(get = "pub")]
@@ -115,10 +141,42 @@ pub struct ClusterConfig {
     #[getset(get = "pub")]
     #[serde(default = "ServerTimeout::default")]
     server_timeout: ServerTimeout,
+    /// Xline server limit settings
+    #[getset(get = "pub")]
+    #[serde(default = "ServerLimits::default")]
+    server_limits: ServerLimits,
     /// Xline server initial state
     #[getset(get = "pub")]
     #[serde(with = "state_format", default = "InitialClusterState::default")]
     initial_cluster_state: InitialClusterState,
+    /// Serve reads only and reject all mutating requests
+    #[getset(get = "pub")]
+    #[serde(default)]
+    read_only: bool,
+    /// Max number of mutating requests the rpc servers propose at the same time, the
+    /// requests beyond it are rejected with "too many requests", 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_inflight_proposals")]
+    max_inflight_proposals: usize,
+    /// Max bytes of the watch events buffered for all watchers, the most backlogged
+    /// watchers are resynced from the backend beyond it, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_watch_memory_budget")]
+    watch_memory_budget: u64,
+    /// Max number of keys a single delete range or txn request may affect, the requests
+    /// beyond it are rejected and should be split, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_keys_per_request")]
+    max_keys_per_request: usize,
+    /// Percentage of the watchers whose deliveries are audited against the applied
+    /// revisions, for the chaos and soak tests, 0 disables the audit
+    #[getset(get = "pub")]
+    #[serde(default)]
+    watch_audit_sample_percent: u8,
+    /// Socket options of the client and peer listeners
+    #[getset(get = "pub")]
+    #[serde(default = "ListenerConfig::default")]
+    listener_config: ListenerConfig,
 }
 
 impl Default for ClusterConfig {
@@ -138,7 +196,14 @@ impl Default for ClusterConfig {
             curp_config: CurpConfig::default(),
             client_config: ClientConfig::default(),
             server_timeout: ServerTimeout::default(),
+            server_limits: ServerLimits::default(),
             initial_cluster_state: InitialClusterState::default(),
+            read_only: false,
+            max_inflight_proposals: default_max_inflight_proposals(),
+            watch_memory_budget: default_watch_memory_budget(),
+            max_keys_per_request: default_max_keys_per_request(),
+            watch_audit_sample_percent: 0,
+            listener_config: ListenerConfig::default(),
         }
     }
 }
@@ -202,12 +267,40 @@ impl ClusterConfig {
             client_config,
             server_timeout,
             initial_cluster_state,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<ClusterConfig> for ClusterConfigBuilder {
+    /// A builder starting from the fields of an existing config
+    #[inline]
+    fn from(config: ClusterConfig) -> Self {
+        Self {
+            name: Some(config.name),
+            peer_listen_urls: Some(config.peer_listen_urls),
+            peer_advertise_urls: Some(config.peer_advertise_urls),
+            client_listen_urls: Some(config.client_listen_urls),
+            client_advertise_urls: Some(config.client_advertise_urls),
+            peers: Some(config.peers),
+            is_leader: Some(config.is_leader),
+            curp_config: Some(config.curp_config),
+            client_config: Some(config.client_config),
+            server_timeout: Some(config.server_timeout),
+            server_limits: Some(config.server_limits),
+            initial_cluster_state: Some(config.initial_cluster_state),
+            read_only: Some(config.read_only),
+            max_inflight_proposals: Some(config.max_inflight_proposals),
+            watch_memory_budget: Some(config.watch_memory_budget),
+            max_keys_per_request: Some(config.max_keys_per_request),
+            watch_audit_sample_percent: Some(config.watch_audit_sample_percent),
+            listener_config: Some(config.listener_config),
         }
     }
 }
 
 /// Compaction configuration
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Getters)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
 #[allow(clippy::module_name_repetitions)]
 pub struct CompactConfig {
     /// The max number of historical versions processed in a single compact operation
@@ -221,6 +314,11 @@ pub struct CompactConfig {
     /// The auto compactor config
     #[getset(get = "pub")]
     auto_compact_config: Option<AutoCompactConfig>,
+    /// The key prefixes whose history is kept by compactions, they take effect when
+    /// a data dir is recovered for the first time and must be the same on all members
+    #[getset(get = "pub")]
+    #[serde(default)]
+    exempt_prefixes: Vec<String>,
 }
 
 impl Default for CompactConfig {
@@ -230,6 +328,7 @@ impl Default for CompactConfig {
             compact_batch_size: default_compact_batch_size(),
             compact_sleep_interval: default_compact_sleep_interval(),
             auto_compact_config: None,
+            exempt_prefixes: Vec::new(),
         }
     }
 }
@@ -242,11 +341,13 @@ impl CompactConfig {
         compact_batch_size: usize,
         compact_sleep_interval: Duration,
         auto_compact_config: Option<AutoCompactConfig>,
+        exempt_prefixes: Vec<String>,
     ) -> Self {
         Self {
             compact_batch_size,
             compact_sleep_interval,
             auto_compact_config,
+            exempt_prefixes,
         }
     }
 }
@@ -336,6 +437,159 @@ pub struct CurpConfig {
     #[builder(default = "default_log_entries_cap()")]
     #[serde(default = "default_log_entries_cap")]
     pub log_entries_cap: usize,
+
+    /// Max gap between the last log index of the leader and the match index of a learner
+    /// for the learner to be promoted
+    #[builder(default = "default_learner_promote_gap()")]
+    #[serde(default = "default_learner_promote_gap")]
+    pub learner_promote_gap: u64,
+
+    /// How long a starting node waits for a quorum of its peers to become reachable
+    /// before it is allowed to campaign, zero means no wait
+    #[builder(default = "default_peer_warmup_timeout()")]
+    #[serde(with = "duration_format", default = "default_peer_warmup_timeout")]
+    pub peer_warmup_timeout: Duration,
+
+    /// The maximum number of commands the leader coalesces into a single log entry,
+    /// batching is disabled when it's not greater than 1
+    #[builder(default = "default_propose_batch_max_size()")]
+    #[serde(default = "default_propose_batch_max_size")]
+    pub propose_batch_max_size: usize,
+
+    /// How long a batched command may wait for more commands before it's appended
+    #[builder(default = "default_propose_batch_max_delay()")]
+    #[serde(with = "duration_format", default = "default_propose_batch_max_delay")]
+    pub propose_batch_max_delay: Duration,
+
+    /// Max rate in bytes per second of reading a snapshot to send it, 0 means unlimited
+    #[builder(default = "default_snapshot_read_rate_limit()")]
+    #[serde(default = "default_snapshot_read_rate_limit")]
+    pub snapshot_read_rate_limit: u64,
+
+    /// Max rate in bytes per second of sending snapshots to followers, 0 means unlimited
+    #[builder(default = "default_snapshot_send_rate_limit()")]
+    #[serde(default = "default_snapshot_send_rate_limit")]
+    pub snapshot_send_rate_limit: u64,
+
+    /// Max number of snapshots sent at the same time, others wait for their turn
+    #[builder(default = "default_snapshot_max_concurrent_transfers()")]
+    #[serde(default = "default_snapshot_max_concurrent_transfers")]
+    pub snapshot_max_concurrent_transfers: usize,
+
+    /// Never start an election, the node only follows the elected leader
+    #[builder(default = "false")]
+    #[serde(default)]
+    pub no_campaign: bool,
+
+    /// A warning is logged for a command whose after sync completes later than this
+    /// after it's executed or committed, zero means never
+    #[builder(default = "default_slow_apply_threshold()")]
+    #[serde(with = "duration_format", default = "default_slow_apply_threshold")]
+    pub slow_apply_threshold: Duration,
+
+    /// Interval of the followers reporting their state hashes to the leader, which
+    /// compares them to detect diverged state machines, zero disables it
+    #[builder(default = "default_state_hash_interval()")]
+    #[serde(with = "duration_format", default = "default_state_hash_interval")]
+    pub state_hash_interval: Duration,
+
+    /// Size in bytes of a segment of the WAL of the log entries
+    #[builder(default = "default_wal_max_segment_size()")]
+    #[serde(default = "default_wal_max_segment_size")]
+    pub wal_max_segment_size: u64,
+
+    /// Bytes appended to the WAL of the log entries between two fsyncs, zero fsyncs
+    /// every append
+    #[builder(default = "default_wal_sync_bytes()")]
+    #[serde(default = "default_wal_sync_bytes")]
+    pub wal_sync_bytes: u64,
+
+    /// Retention of the completed propose results
+    #[builder(default = "ResultCacheConfig::default()")]
+    #[serde(default = "ResultCacheConfig::default")]
+    pub result_cache: ResultCacheConfig,
+}
+
+/// Retention of the completed propose results that clients may re-fetch
+///
+/// A result is kept while it's one of the last `results_per_client` results of its
+/// client and younger than `retention`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ResultCacheConfig {
+    /// Max number of results kept for each client
+    #[serde(default = "default_results_per_client")]
+    pub results_per_client: usize,
+
+    /// Results older than it are evicted
+    #[serde(with = "duration_format", default = "default_result_retention")]
+    pub retention: Duration,
+
+    /// Client sessions without proposals for longer than it are dropped together with
+    /// their results, through the log once every member supports session expiry and
+    /// by each member on its own before that. The retries of the cmds of a dropped
+    /// session are rejected for another ttl, so it should exceed the time a client
+    /// keeps retrying a proposal.
+    #[serde(with = "duration_format", default = "default_session_ttl")]
+    pub session_ttl: Duration,
+
+    /// Max serialized size of the results carried in a snapshot, the oldest results
+    /// are left out first
+    #[serde(with = "bytes_format", default = "default_result_snapshot_size")]
+    pub max_snapshot_size: u64,
+
+    /// Number of the latest log entries in which a retried cmd is not appended again
+    #[serde(default = "default_dedup_window")]
+    pub dedup_window: u64,
+}
+
+impl Default for ResultCacheConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            results_per_client: default_results_per_client(),
+            retention: default_result_retention(),
+            session_ttl: default_session_ttl(),
+            max_snapshot_size: default_result_snapshot_size(),
+            dedup_window: default_dedup_window(),
+        }
+    }
+}
+
+/// default number of results kept for each client
+#[must_use]
+#[inline]
+pub const fn default_results_per_client() -> usize {
+    128
+}
+
+/// default retention of the propose results
+#[must_use]
+#[inline]
+pub const fn default_result_retention() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// default ttl of the idle client sessions
+#[must_use]
+#[inline]
+pub const fn default_session_ttl() -> Duration {
+    Duration::from_secs(300)
+}
+
+/// default max size of the results carried in a snapshot
+#[must_use]
+#[inline]
+#[allow(clippy::arithmetic_side_effects)]
+pub const fn default_result_snapshot_size() -> u64 {
+    4 * 1024 * 1024
+}
+
+/// default number of the latest log entries in which the cmds are deduplicated
+#[must_use]
+#[inline]
+pub const fn default_dedup_window() -> u64 {
+    65536
 }
 
 /// default heartbeat interval
@@ -487,6 +741,84 @@ pub const fn default_log_entries_cap() -> usize {
     5000
 }
 
+/// default max gap of log entries between the leader and a learner to be promoted
+#[must_use]
+#[inline]
+pub const fn default_learner_promote_gap() -> u64 {
+    500
+}
+
+/// default peer warmup timeout
+#[must_use]
+#[inline]
+pub const fn default_peer_warmup_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+/// default max number of commands in a batched log entry, batching is disabled by default
+#[must_use]
+#[inline]
+pub const fn default_propose_batch_max_size() -> usize {
+    1
+}
+
+/// default max delay of a batched command
+#[must_use]
+#[inline]
+pub const fn default_propose_batch_max_delay() -> Duration {
+    Duration::from_millis(1)
+}
+
+/// default threshold of slow applies
+#[must_use]
+#[inline]
+pub const fn default_slow_apply_threshold() -> Duration {
+    Duration::from_millis(100)
+}
+
+/// default interval of reporting the state hashes
+#[must_use]
+#[inline]
+pub const fn default_state_hash_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// default size of a WAL segment
+#[must_use]
+#[inline]
+pub const fn default_wal_max_segment_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+/// default bytes appended to the WAL between two fsyncs, every append is synced by
+/// default
+#[must_use]
+#[inline]
+pub const fn default_wal_sync_bytes() -> u64 {
+    0
+}
+
+/// default max rate of reading a snapshot, unlimited by default
+#[must_use]
+#[inline]
+pub const fn default_snapshot_read_rate_limit() -> u64 {
+    0
+}
+
+/// default max rate of sending snapshots, unlimited by default
+#[must_use]
+#[inline]
+pub const fn default_snapshot_send_rate_limit() -> u64 {
+    0
+}
+
+/// default max number of snapshots sent at the same time
+#[must_use]
+#[inline]
+pub const fn default_snapshot_max_concurrent_transfers() -> usize {
+    1
+}
+
 /// default watch progress notify interval
 #[must_use]
 #[inline]
@@ -494,6 +826,139 @@ pub const fn default_watch_progress_notify_interval() -> Duration {
     Duration::from_secs(600)
 }
 
+/// default max number of inflight proposals of the rpc servers
+#[must_use]
+#[inline]
+pub const fn default_max_inflight_proposals() -> usize {
+    4096
+}
+
+/// default max bytes of the buffered watch events: 256MB
+#[must_use]
+#[inline]
+pub const fn default_watch_memory_budget() -> u64 {
+    256 * 1024 * 1024
+}
+
+/// default max number of keys affected by a single request, 0 means unlimited
+#[must_use]
+#[inline]
+pub const fn default_max_keys_per_request() -> usize {
+    0
+}
+
+/// default number of acceptors of a listen address
+#[must_use]
+#[inline]
+pub const fn default_listener_acceptors() -> usize {
+    1
+}
+
+/// default max number of pending connections of a listener
+#[must_use]
+#[inline]
+pub const fn default_listener_backlog() -> u32 {
+    1024
+}
+
+/// default whether `TCP_NODELAY` is set on the accepted sockets
+#[must_use]
+#[inline]
+pub const fn default_tcp_nodelay() -> bool {
+    true
+}
+
+/// default interval of the TCP keepalive probes, zero means disabled
+#[must_use]
+#[inline]
+pub const fn default_tcp_keepalive() -> Duration {
+    Duration::ZERO
+}
+
+/// default lease checkpoint interval
+#[must_use]
+#[inline]
+pub const fn default_lease_checkpoint_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+/// default lease ttl applied to grants without a positive ttl, zero means the min lease ttl
+#[must_use]
+#[inline]
+pub const fn default_lease_default_ttl() -> Duration {
+    Duration::ZERO
+}
+
+/// default number of election timeouts by which leases are extended on promotion
+#[must_use]
+#[inline]
+pub const fn default_lease_promote_extend_multiplier() -> u32 {
+    1
+}
+
+/// default lease expiry persist interval, zero means disabled
+#[must_use]
+#[inline]
+pub const fn default_lease_expiry_persist_interval() -> Duration {
+    Duration::ZERO
+}
+
+/// default max number of keys deleted by a single apply of a lease revocation
+#[must_use]
+#[inline]
+pub const fn default_lease_revoke_chunk_size() -> usize {
+    10_000
+}
+
+/// default max number of expired leases revoked in one pass of the revoker
+#[must_use]
+#[inline]
+pub const fn default_lease_revoke_batch_size() -> usize {
+    100
+}
+
+/// default max number of keys attached to a lease, 0 means unlimited
+#[must_use]
+#[inline]
+pub const fn default_max_keys_per_lease() -> usize {
+    0
+}
+
+/// default max rate of watch creations per second of a client, 0 means unlimited
+#[must_use]
+#[inline]
+pub const fn default_watch_create_rate() -> u32 {
+    0
+}
+
+/// default max number of watch creations a client could make in a burst
+#[must_use]
+#[inline]
+pub const fn default_watch_create_burst() -> u32 {
+    100
+}
+
+/// default max rate of lease grants per second of a client, 0 means unlimited
+#[must_use]
+#[inline]
+pub const fn default_lease_grant_rate() -> u32 {
+    0
+}
+
+/// default max number of lease grants a client could make in a burst
+#[must_use]
+#[inline]
+pub const fn default_lease_grant_burst() -> u32 {
+    100
+}
+
+/// default max number of active leases granted by a client, 0 means unlimited
+#[must_use]
+#[inline]
+pub const fn default_max_leases_per_client() -> usize {
+    0
+}
+
 impl Default for CurpConfig {
     #[inline]
     fn default() -> Self {
@@ -510,6 +975,19 @@ impl Default for CurpConfig {
             cmd_workers: default_cmd_workers(),
             gc_interval: default_gc_interval(),
             log_entries_cap: default_log_entries_cap(),
+            learner_promote_gap: default_learner_promote_gap(),
+            peer_warmup_timeout: default_peer_warmup_timeout(),
+            propose_batch_max_size: default_propose_batch_max_size(),
+            propose_batch_max_delay: default_propose_batch_max_delay(),
+            snapshot_read_rate_limit: default_snapshot_read_rate_limit(),
+            snapshot_send_rate_limit: default_snapshot_send_rate_limit(),
+            snapshot_max_concurrent_transfers: default_snapshot_max_concurrent_transfers(),
+            no_campaign: false,
+            slow_apply_threshold: default_slow_apply_threshold(),
+            state_hash_interval: default_state_hash_interval(),
+            wal_max_segment_size: default_wal_max_segment_size(),
+            wal_sync_bytes: default_wal_sync_bytes(),
+            result_cache: ResultCacheConfig::default(),
         }
     }
 }
@@ -608,8 +1086,73 @@ impl Default for ClientConfig {
     }
 }
 
-/// Xline server settings
+/// Socket options of the client and peer listeners
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ListenerConfig {
+    /// Whether `SO_REUSEPORT` is set on the listeners, so that multiple acceptors can
+    /// be bound to the same address, it's ignored on the platforms without it
+    #[getset(get = "pub")]
+    #[serde(default)]
+    reuse_port: bool,
+    /// Number of acceptors bound to each listen address, each with its own accept
+    /// queue, more than one requires `reuse_port`
+    #[getset(get = "pub")]
+    #[serde(default = "default_listener_acceptors")]
+    acceptors: usize,
+    /// Max number of pending connections in the accept queue of an acceptor
+    #[getset(get = "pub")]
+    #[serde(default = "default_listener_backlog")]
+    backlog: u32,
+    /// Whether `TCP_NODELAY` is set on the accepted sockets
+    #[getset(get = "pub")]
+    #[serde(default = "default_tcp_nodelay")]
+    tcp_nodelay: bool,
+    /// Interval of the TCP keepalive probes of the accepted sockets, zero means disabled
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_tcp_keepalive")]
+    tcp_keepalive: Duration,
+}
+
+impl ListenerConfig {
+    /// Create a new `ListenerConfig`
+    #[must_use]
+    #[inline]
+    pub fn new(
+        reuse_port: bool,
+        acceptors: usize,
+        backlog: u32,
+        tcp_nodelay: bool,
+        tcp_keepalive: Duration,
+    ) -> Self {
+        Self {
+            reuse_port,
+            acceptors,
+            backlog,
+            tcp_nodelay,
+            tcp_keepalive,
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            reuse_port: false,
+            acceptors: default_listener_acceptors(),
+            backlog: default_listener_backlog(),
+            tcp_nodelay: default_tcp_nodelay(),
+            tcp_keepalive: default_tcp_keepalive(),
+        }
+    }
+}
+
+/// Xline server settings
+///
+/// The fields not set by `ServerTimeoutBuilder` are the default ones.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Getters, Builder)]
+#[builder(default)]
 pub struct ServerTimeout {
     /// Range request retry timeout settings
     #[getset(get = "pub")]
@@ -630,6 +1173,34 @@ pub struct ServerTimeout {
         default = "default_watch_progress_notify_interval"
     )]
     watch_progress_notify_interval: Duration,
+    /// How often the leader checkpoints the remaining ttl of leases to followers
+    #[getset(get = "pub")]
+    #[serde(
+        with = "duration_format",
+        default = "default_lease_checkpoint_interval"
+    )]
+    lease_checkpoint_interval: Duration,
+    /// Whether the checkpointed remaining ttl is persisted to the backend
+    #[getset(get = "pub")]
+    #[serde(default)]
+    lease_checkpoint_persist: bool,
+    /// How often the leader persists the remaining lease ttls it observes to a
+    /// local table, zero means disabled
+    #[getset(get = "pub")]
+    #[serde(
+        with = "duration_format",
+        default = "default_lease_expiry_persist_interval"
+    )]
+    lease_expiry_persist_interval: Duration,
+    /// Ttl of leases granted without a positive ttl, zero means the min lease ttl derived
+    /// from the election timeout
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_lease_default_ttl")]
+    lease_default_ttl: Duration,
+    /// Leases are extended by this many election timeouts when a new leader is elected
+    #[getset(get = "pub")]
+    #[serde(default = "default_lease_promote_extend_multiplier")]
+    lease_promote_extend_multiplier: u32,
 }
 
 impl ServerTimeout {
@@ -647,6 +1218,25 @@ impl ServerTimeout {
             compact_timeout,
             sync_victims_interval,
             watch_progress_notify_interval,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<ServerTimeout> for ServerTimeoutBuilder {
+    /// A builder starting from the fields of an existing config
+    #[inline]
+    fn from(timeout: ServerTimeout) -> Self {
+        Self {
+            range_retry_timeout: Some(timeout.range_retry_timeout),
+            compact_timeout: Some(timeout.compact_timeout),
+            sync_victims_interval: Some(timeout.sync_victims_interval),
+            watch_progress_notify_interval: Some(timeout.watch_progress_notify_interval),
+            lease_checkpoint_interval: Some(timeout.lease_checkpoint_interval),
+            lease_checkpoint_persist: Some(timeout.lease_checkpoint_persist),
+            lease_expiry_persist_interval: Some(timeout.lease_expiry_persist_interval),
+            lease_default_ttl: Some(timeout.lease_default_ttl),
+            lease_promote_extend_multiplier: Some(timeout.lease_promote_extend_multiplier),
         }
     }
 }
@@ -659,6 +1249,94 @@ impl Default for ServerTimeout {
             compact_timeout: default_compact_timeout(),
             sync_victims_interval: default_sync_victims_interval(),
             watch_progress_notify_interval: default_watch_progress_notify_interval(),
+            lease_checkpoint_interval: default_lease_checkpoint_interval(),
+            lease_checkpoint_persist: false,
+            lease_expiry_persist_interval: default_lease_expiry_persist_interval(),
+            lease_default_ttl: default_lease_default_ttl(),
+            lease_promote_extend_multiplier: default_lease_promote_extend_multiplier(),
+        }
+    }
+}
+
+/// Xline server limits on sizes and rates
+///
+/// The fields not set by `ServerLimitsBuilder` are the default ones.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Getters, Builder)]
+#[builder(default)]
+pub struct ServerLimits {
+    /// Max number of keys deleted by a single apply of a lease revocation, 0 means
+    /// unlimited. The keys left are deleted by follow-up applies, each with its own
+    /// revision, so watchers see the deletions in multiple revisions. It must be the
+    /// same on all members.
+    #[getset(get = "pub")]
+    #[serde(default = "default_lease_revoke_chunk_size")]
+    lease_revoke_chunk_size: usize,
+    /// Max number of expired leases the leader revokes in one pass, 0 means unlimited.
+    /// The earliest expired leases are revoked first, the others stay queued for the
+    /// next pass.
+    #[getset(get = "pub")]
+    #[serde(default = "default_lease_revoke_batch_size")]
+    lease_revoke_batch_size: usize,
+    /// Max number of keys attached to a lease, 0 means unlimited. A put attaching a new
+    /// key to a lease that already has this many keys fails. It must be the same on all
+    /// members.
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_keys_per_lease")]
+    max_keys_per_lease: usize,
+    /// Max rate of watch creations per second of a client, identified by its user when
+    /// auth is enabled or by its address otherwise, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_watch_create_rate")]
+    watch_create_rate: u32,
+    /// Max number of watch creations a client could make in a burst
+    #[getset(get = "pub")]
+    #[serde(default = "default_watch_create_burst")]
+    watch_create_burst: u32,
+    /// Max rate of lease grants per second of a client, identified by its user when
+    /// auth is enabled or by its address otherwise, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_lease_grant_rate")]
+    lease_grant_rate: u32,
+    /// Max number of lease grants a client could make in a burst
+    #[getset(get = "pub")]
+    #[serde(default = "default_lease_grant_burst")]
+    lease_grant_burst: u32,
+    /// Max number of active leases granted by a client, 0 means unlimited. A grant
+    /// exceeding it fails when it's applied. It must be the same on all members.
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_leases_per_client")]
+    max_leases_per_client: usize,
+}
+
+impl From<ServerLimits> for ServerLimitsBuilder {
+    /// A builder starting from the fields of an existing config
+    #[inline]
+    fn from(limits: ServerLimits) -> Self {
+        Self {
+            lease_revoke_chunk_size: Some(limits.lease_revoke_chunk_size),
+            lease_revoke_batch_size: Some(limits.lease_revoke_batch_size),
+            max_keys_per_lease: Some(limits.max_keys_per_lease),
+            watch_create_rate: Some(limits.watch_create_rate),
+            watch_create_burst: Some(limits.watch_create_burst),
+            lease_grant_rate: Some(limits.lease_grant_rate),
+            lease_grant_burst: Some(limits.lease_grant_burst),
+            max_leases_per_client: Some(limits.max_leases_per_client),
+        }
+    }
+}
+
+impl Default for ServerLimits {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            lease_revoke_chunk_size: default_lease_revoke_chunk_size(),
+            lease_revoke_batch_size: default_lease_revoke_batch_size(),
+            max_keys_per_lease: default_max_keys_per_lease(),
+            watch_create_rate: default_watch_create_rate(),
+            watch_create_burst: default_watch_create_burst(),
+            lease_grant_rate: default_lease_grant_rate(),
+            lease_grant_burst: default_lease_grant_burst(),
+            max_leases_per_client: default_max_leases_per_client(),
         }
     }
 }
@@ -714,14 +1392,30 @@ pub struct StorageConfig {
     /// Quota
     #[serde(default = "default_quota")]
     pub quota: u64,
+    /// Request journal
+    #[serde(default = "JournalConfig::default")]
+    pub journal: JournalConfig,
+    /// Encryption of the kv values at rest
+    #[serde(default = "EncryptionConfig::default")]
+    pub encryption: EncryptionConfig,
 }
 
 impl StorageConfig {
     /// Create a new storage config
     #[inline]
     #[must_use]
-    pub fn new(engine: EngineConfig, quota: u64) -> Self {
-        Self { engine, quota }
+    pub fn new(
+        engine: EngineConfig,
+        quota: u64,
+        journal: JournalConfig,
+        encryption: EncryptionConfig,
+    ) -> Self {
+        Self {
+            engine,
+            quota,
+            journal,
+            encryption,
+        }
     }
 }
 
@@ -731,10 +1425,123 @@ impl Default for StorageConfig {
         Self {
             engine: EngineConfig::default(),
             quota: default_quota(),
+            journal: JournalConfig::default(),
+            encryption: EncryptionConfig::default(),
         }
     }
 }
 
+/// Encryption configuration of the kv values at rest
+///
+/// Only the values of the kv pairs are encrypted, the keys and the other tables are
+/// not. The keys are versioned, the values are written with the latest version and
+/// read with the version recorded in them, so a key is rotated by adding a version,
+/// the values of the older ones are rewritten offline by `xline-reencrypt`. The
+/// encryption is off unless a key source is given.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Getters)]
+pub struct EncryptionConfig {
+    /// File of the keys, one `<version> <hex of the 32 bytes key>` per line
+    #[getset(get = "pub")]
+    #[serde(default)]
+    key_file: Option<PathBuf>,
+    /// Url of the KMS the keys are fetched from
+    #[getset(get = "pub")]
+    #[serde(default)]
+    kms_url: Option<String>,
+}
+
+impl EncryptionConfig {
+    /// Create a new `EncryptionConfig`
+    #[must_use]
+    #[inline]
+    pub fn new(key_file: Option<PathBuf>, kms_url: Option<String>) -> Self {
+        Self { key_file, kms_url }
+    }
+
+    /// Whether the encryption is on
+    #[must_use]
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.key_file.is_some() || self.kms_url.is_some()
+    }
+}
+
+/// Request journal configuration
+///
+/// The journal is an append-only file of the mutating requests applied by the member,
+/// which `xline-replay --journal` re-issues against another cluster to reproduce a
+/// reported sequence of operations. It's off unless a path is given, and the path
+/// must be out of the data directory, so the journal is never part of the backups.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct JournalConfig {
+    /// Path of the journal file, the journal is off if it's `None`
+    #[getset(get = "pub")]
+    #[serde(default)]
+    path: Option<PathBuf>,
+    /// Max size of the journal in bytes, the oldest records are dropped beyond it
+    #[getset(get = "pub")]
+    #[serde(default = "default_journal_max_size")]
+    max_size: u64,
+    /// Max age of the records, the older records are dropped
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_journal_max_age")]
+    max_age: Duration,
+    /// Whether the values of the puts and the passwords are redacted, a journal with
+    /// the values redacted replays the sequence of the keys but not their contents
+    #[getset(get = "pub")]
+    #[serde(default)]
+    redact_values: bool,
+}
+
+impl JournalConfig {
+    /// Create a new `JournalConfig`
+    #[must_use]
+    #[inline]
+    pub fn new(
+        path: Option<PathBuf>,
+        max_size: u64,
+        max_age: Duration,
+        redact_values: bool,
+    ) -> Self {
+        Self {
+            path,
+            max_size,
+            max_age,
+            redact_values,
+        }
+    }
+}
+
+impl Default for JournalConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_size: default_journal_max_size(),
+            max_age: default_journal_max_age(),
+            redact_values: false,
+        }
+    }
+}
+
+/// Default max size of the request journal: 256MB
+#[must_use]
+#[inline]
+pub const fn default_journal_max_size() -> u64 {
+    // 256 * 1024 * 1024
+    0x1000_0000
+}
+
+/// Default max age of the request journal records: 7 days
+#[must_use]
+#[inline]
+pub const fn default_journal_max_age() -> Duration {
+    // 7 * 24 * 60 * 60
+    Duration::from_secs(604_800)
+}
+
 /// Default quota: 8GB
 #[inline]
 #[must_use]
@@ -901,6 +1708,11 @@ pub struct TraceConfig {
     #[getset(get = "pub")]
     #[serde(with = "level_format", default = "default_log_level")]
     jaeger_level: LevelConfig,
+    /// The OTLP collector endpoint that spans are exported to, the exporter is
+    /// disabled when it's not set and `jaeger_online` is off
+    #[getset(get = "pub")]
+    #[serde(default)]
+    otlp_endpoint: Option<String>,
 }
 
 impl Default for TraceConfig {
@@ -911,6 +1723,7 @@ impl Default for TraceConfig {
             jaeger_offline: false,
             jaeger_output_dir: "".into(),
             jaeger_level: default_log_level(),
+            otlp_endpoint: None,
         }
     }
 }
@@ -924,12 +1737,14 @@ impl TraceConfig {
         jaeger_offline: bool,
         jaeger_output_dir: PathBuf,
         jaeger_level: LevelConfig,
+        otlp_endpoint: Option<String>,
     ) -> Self {
         Self {
             jaeger_online,
             jaeger_offline,
             jaeger_output_dir,
             jaeger_level,
+            otlp_endpoint,
         }
     }
 }
@@ -944,20 +1759,77 @@ pub struct AuthConfig {
     /// The private key file
     #[getset(get = "pub")]
     auth_private_key: Option<PathBuf>,
+    /// The external webhook validating tokens, tokens are validated locally if unset
+    #[getset(get = "pub")]
+    auth_hook: Option<AuthHookConfig>,
 }
 
 impl AuthConfig {
     /// Generate a new `AuthConfig` object
     #[must_use]
     #[inline]
-    pub fn new(auth_public_key: Option<PathBuf>, auth_private_key: Option<PathBuf>) -> Self {
+    pub fn new(
+        auth_public_key: Option<PathBuf>,
+        auth_private_key: Option<PathBuf>,
+        auth_hook: Option<AuthHookConfig>,
+    ) -> Self {
         Self {
             auth_public_key,
             auth_private_key,
+            auth_hook,
         }
     }
 }
 
+/// Xline external auth hook configuration object
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct AuthHookConfig {
+    /// The url tokens are posted to
+    #[getset(get = "pub")]
+    url: String,
+    /// How long the identity returned for a token is cached
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_auth_hook_cache_ttl")]
+    cache_ttl: Duration,
+    /// The timeout of a call to the hook
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_auth_hook_timeout")]
+    timeout: Duration,
+    /// Fall back to local validation when the hook fails instead of rejecting the request
+    #[getset(get = "pub")]
+    #[serde(default)]
+    fail_open: bool,
+}
+
+impl AuthHookConfig {
+    /// Generate a new `AuthHookConfig` object
+    #[must_use]
+    #[inline]
+    pub fn new(url: String, cache_ttl: Duration, timeout: Duration, fail_open: bool) -> Self {
+        Self {
+            url,
+            cache_ttl,
+            timeout,
+            fail_open,
+        }
+    }
+}
+
+/// default auth hook cache ttl
+#[must_use]
+#[inline]
+pub const fn default_auth_hook_cache_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// default auth hook timeout
+#[must_use]
+#[inline]
+pub const fn default_auth_hook_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
 /// Xline tls configuration object
 #[allow(clippy::module_name_repetitions)]
 #[non_exhaustive]
@@ -1206,12 +2078,32 @@ mod tests {
             peer_advertise_urls = ['127.0.0.1:2380']
             client_listen_urls = ['127.0.0.1:2379']
             client_advertise_urls = ['127.0.0.1:2379']
+            read_only = true
+            max_inflight_proposals = 128
+            watch_memory_budget = 67108864
+            max_keys_per_request = 10000
+            watch_audit_sample_percent = 10
 
             [cluster.server_timeout]
             range_retry_timeout = '3s'
             compact_timeout = '5s'
             sync_victims_interval = '20ms'
             watch_progress_notify_interval = '1s'
+            lease_checkpoint_interval = '60s'
+            lease_checkpoint_persist = true
+            lease_expiry_persist_interval = '500ms'
+            lease_default_ttl = '10s'
+            lease_promote_extend_multiplier = 2
+
+            [cluster.server_limits]
+            lease_revoke_chunk_size = 1000
+            lease_revoke_batch_size = 500
+            max_keys_per_lease = 100000
+            watch_create_rate = 10
+            watch_create_burst = 20
+            lease_grant_rate = 50
+            lease_grant_burst = 10
+            max_leases_per_client = 1000
 
             [cluster.peers]
             node1 = ['127.0.0.1:2378', '127.0.0.1:2379']
@@ -1224,16 +2116,37 @@ mod tests {
             rpc_timeout = '100ms'
             retry_timeout = '100ms'
 
+            [cluster.curp_config.result_cache]
+            results_per_client = 16
+            session_ttl = '10m'
+
             [cluster.client_config]
             initial_retry_timeout = '5s'
             max_retry_timeout = '50s'
 
+            [cluster.listener_config]
+            reuse_port = true
+            acceptors = 4
+            backlog = 4096
+            tcp_nodelay = false
+            tcp_keepalive = '30s'
+
             [storage]
             engine = { type = 'memory'}
 
+            [storage.journal]
+            path = '/var/log/xline/journal'
+            max_size = 67108864
+            max_age = '1h'
+            redact_values = true
+
+            [storage.encryption]
+            key_file = '/etc/xline/value.keys'
+
             [compact]
             compact_batch_size = 123
             compact_sleep_interval = '5ms'
+            exempt_prefixes = ['/audit/', '/history/']
 
             [compact.auto_compact_config]
             mode = 'periodic'
@@ -1249,11 +2162,17 @@ mod tests {
             jaeger_offline = false
             jaeger_output_dir = './jaeger_jsons'
             jaeger_level = 'info'
+            otlp_endpoint = 'http://127.0.0.1:4317'
 
             [auth]
             auth_public_key = './public_key.pem'
             auth_private_key = './private_key.pem'
 
+            [auth.auth_hook]
+            url = 'http://127.0.0.1:8080/auth'
+            cache_ttl = '30s'
+            fail_open = true
+
             [tls]
             peer_cert_path = './cert.pem'
             peer_key_path = './key.pem'
@@ -1274,6 +2193,11 @@ mod tests {
             .heartbeat_interval(Duration::from_millis(200))
             .wait_synced_timeout(Duration::from_millis(100))
             .rpc_timeout(Duration::from_millis(100))
+            .result_cache(ResultCacheConfig {
+                results_per_client: 16,
+                session_ttl: Duration::from_secs(600),
+                ..Default::default()
+            })
             .build()
             .unwrap();
 
@@ -1287,16 +2211,35 @@ mod tests {
             default_client_id_keep_alive_interval(),
         );
 
-        let server_timeout = ServerTimeout::new(
+        let server_timeout = ServerTimeoutBuilder::from(ServerTimeout::new(
             Duration::from_secs(3),
             Duration::from_secs(5),
             Duration::from_millis(20),
             Duration::from_secs(1),
-        );
+        ))
+        .lease_checkpoint_interval(Duration::from_secs(60))
+        .lease_checkpoint_persist(true)
+        .lease_expiry_persist_interval(Duration::from_millis(500))
+        .lease_default_ttl(Duration::from_secs(10))
+        .lease_promote_extend_multiplier(2)
+        .build()
+        .unwrap();
+
+        let server_limits = ServerLimitsBuilder::default()
+            .lease_revoke_chunk_size(1000)
+            .lease_revoke_batch_size(500)
+            .max_keys_per_lease(100_000)
+            .watch_create_rate(10)
+            .watch_create_burst(20)
+            .lease_grant_rate(50)
+            .lease_grant_burst(10)
+            .max_leases_per_client(1000)
+            .build()
+            .unwrap();
 
         assert_eq!(
             config.cluster,
-            ClusterConfig::new(
+            ClusterConfigBuilder::from(ClusterConfig::new(
                 "node1".to_owned(),
                 vec!["127.0.0.1:2380".to_owned()],
                 vec!["127.0.0.1:2380".to_owned()],
@@ -1314,13 +2257,38 @@ mod tests {
                 curp_config,
                 client_config,
                 server_timeout,
-                InitialClusterState::New
-            )
+                InitialClusterState::New,
+            ))
+            .server_limits(server_limits)
+            .read_only(true)
+            .max_inflight_proposals(128)
+            .watch_memory_budget(64 * 1024 * 1024)
+            .max_keys_per_request(10000)
+            .watch_audit_sample_percent(10)
+            .listener_config(ListenerConfig::new(
+                true,
+                4,
+                4096,
+                false,
+                Duration::from_secs(30)
+            ))
+            .build()
+            .unwrap()
         );
 
         assert_eq!(
             config.storage,
-            StorageConfig::new(EngineConfig::Memory, default_quota())
+            StorageConfig::new(
+                EngineConfig::Memory,
+                default_quota(),
+                JournalConfig::new(
+                    Some(PathBuf::from("/var/log/xline/journal")),
+                    64 * 1024 * 1024,
+                    Duration::from_secs(60 * 60),
+                    true
+                ),
+                EncryptionConfig::new(Some(PathBuf::from("/etc/xline/value.keys")), None)
+            )
         );
 
         assert_eq!(
@@ -1337,7 +2305,8 @@ mod tests {
                 false,
                 false,
                 PathBuf::from("./jaeger_jsons"),
-                LevelConfig::INFO
+                LevelConfig::INFO,
+                Some("http://127.0.0.1:4317".to_owned())
             )
         );
 
@@ -1348,7 +2317,8 @@ mod tests {
                 compact_sleep_interval: Duration::from_millis(5),
                 auto_compact_config: Some(AutoCompactConfig::Periodic(Duration::from_secs(
                     10 * 60 * 60
-                )))
+                ))),
+                exempt_prefixes: vec!["/audit/".to_owned(), "/history/".to_owned()],
             }
         );
 
@@ -1357,6 +2327,12 @@ mod tests {
             AuthConfig {
                 auth_private_key: Some(PathBuf::from("./private_key.pem")),
                 auth_public_key: Some(PathBuf::from("./public_key.pem")),
+                auth_hook: Some(AuthHookConfig::new(
+                    "http://127.0.0.1:8080/auth".to_owned(),
+                    Duration::from_secs(30),
+                    default_auth_hook_timeout(),
+                    true
+                )),
             }
         );
 
@@ -1463,7 +2439,8 @@ mod tests {
                 false,
                 false,
                 PathBuf::from("./jaeger_jsons"),
-                LevelConfig::INFO
+                LevelConfig::INFO,
+                None
             )
         );
         assert_eq!(config.compact, CompactConfig::default());
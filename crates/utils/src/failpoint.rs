@@ -0,0 +1,365 @@
+//! Failpoints are named places in the code where tests inject failures with precise
+//! timing, they are compiled only with the `failpoints` feature, which is enabled by
+//! the dev-dependencies of the crates using them.
+//!
+//! The points of the workspace are:
+//!
+//! | name                                 | place                                                      |
+//! |--------------------------------------|------------------------------------------------------------|
+//! | `wal_before_fsync`                   | before the WAL syncs the appended entries                  |
+//! | `wal_after_fsync`                    | after the WAL syncs the appended entries                   |
+//! | `lease_revoke_before_cascade_delete` | after a lease is marked revoking, before its keys are deleted |
+//! | `lease_before_revoke_batch`          | before the leader takes the next batch of expired leases   |
+//! | `lease_before_write_deletion`        | before a batch of tombstones of a revoked lease is written |
+//! | `lease_before_keep_alive_response`   | before a keep alive response is sent to the client         |
+//! | `curp_before_apply_conf_change`      | before the after sync of a conf change entry               |
+//! | `snapshot_before_rename`             | before a received snapshot file is renamed to its final name |
+//!
+//! A point is placed with [`fail_point!`](crate::fail_point) in sync code and
+//! [`fail_point_async!`](crate::fail_point_async) in async code, and activated by a
+//! test through a [`FailScenario`].
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    thread::{self, ThreadId},
+    time::Duration,
+};
+
+use event_listener::Event;
+
+/// Action taken when an active failpoint is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FailAction {
+    /// Panic at the point
+    Panic,
+    /// Sleep for the duration before going on, a zero delay only counts the hits
+    Delay(Duration),
+    /// Return the error of the point from its function, the points without an error
+    /// to return go on
+    Return,
+    /// Block until the point is removed
+    Pause,
+}
+
+/// Gate the paused callers of a point wait on
+#[derive(Debug)]
+struct Gate {
+    /// Whether the point is removed
+    released: AtomicBool,
+    /// Notified when the point is removed
+    event: Event,
+}
+
+impl Gate {
+    /// Release the paused callers
+    fn release(&self) {
+        self.released.store(true, Ordering::Release);
+        let _ignore = self.event.notify(usize::MAX);
+    }
+
+    /// Block the current thread until the gate is released
+    fn wait(&self) {
+        loop {
+            let listener = self.event.listen();
+            if self.released.load(Ordering::Acquire) {
+                return;
+            }
+            listener.wait();
+        }
+    }
+
+    /// Wait until the gate is released
+    async fn wait_async(&self) {
+        loop {
+            let listener = self.event.listen();
+            if self.released.load(Ordering::Acquire) {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// An active failpoint
+#[derive(Debug)]
+struct FailPoint {
+    /// The action
+    action: FailAction,
+    /// Only the callers on this thread are affected, all callers are if it's `None`
+    thread: Option<ThreadId>,
+    /// Number of the times the point is reached since it's activated
+    hits: u64,
+    /// The gate of the paused callers
+    gate: Arc<Gate>,
+}
+
+/// Registry of the active failpoints of the process
+#[derive(Debug)]
+struct Registry {
+    /// The active points
+    points: Mutex<HashMap<String, FailPoint>>,
+    /// Notified whenever a point is reached
+    hit: Event,
+}
+
+/// The registry of the process
+fn registry() -> &'static Registry {
+    /// The registry
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| Registry {
+        points: Mutex::new(HashMap::new()),
+        hit: Event::new(),
+    })
+}
+
+/// Lock the active points, a panic of a point never happens with the lock held
+fn points() -> MutexGuard<'static, HashMap<String, FailPoint>> {
+    registry()
+        .points
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Record a hit of the point, returns its action if it's active for the caller
+fn hit(name: &str) -> Option<(FailAction, Arc<Gate>)> {
+    let mut points = points();
+    let point = points.get_mut(name)?;
+    if point
+        .thread
+        .is_some_and(|thread| thread != thread::current().id())
+    {
+        return None;
+    }
+    point.hits = point.hits.saturating_add(1);
+    let action = (point.action, Arc::clone(&point.gate));
+    drop(points);
+    let _ignore = registry().hit.notify(usize::MAX);
+    Some(action)
+}
+
+/// Evaluate the point in sync code, a pause blocks the current thread, returns whether
+/// the function of the point should return its error
+///
+/// # Panics
+///
+/// Panics if the point is activated with [`FailAction::Panic`]
+#[inline]
+#[must_use]
+#[allow(clippy::panic)] // the action of the point
+pub fn eval(name: &str) -> bool {
+    let Some((action, gate)) = hit(name) else {
+        return false;
+    };
+    match action {
+        FailAction::Panic => panic!("failpoint {name} panics"),
+        FailAction::Delay(delay) => {
+            thread::sleep(delay);
+            false
+        }
+        FailAction::Return => true,
+        FailAction::Pause => {
+            gate.wait();
+            false
+        }
+    }
+}
+
+/// Evaluate the point in async code, returns whether the function of the point should
+/// return its error
+///
+/// # Panics
+///
+/// Panics if the point is activated with [`FailAction::Panic`]
+#[inline]
+#[allow(clippy::panic)] // the action of the point
+pub async fn eval_async(name: &str) -> bool {
+    let Some((action, gate)) = hit(name) else {
+        return false;
+    };
+    match action {
+        FailAction::Panic => panic!("failpoint {name} panics"),
+        FailAction::Delay(delay) => {
+            tokio::time::sleep(delay).await;
+            false
+        }
+        FailAction::Return => true,
+        FailAction::Pause => {
+            gate.wait_async().await;
+            false
+        }
+    }
+}
+
+/// Remove all points, the paused callers go on
+fn remove_all() {
+    for (_name, point) in points().drain() {
+        point.gate.release();
+    }
+}
+
+/// The failpoints of a test
+///
+/// The scenarios are exclusive to each other, a test holds its scenario while it runs
+/// and all points are removed once the scenario is dropped. A point activated for all
+/// callers affects every test of the process, such tests should be in a test binary of
+/// their own, the others activate the points only for the callers on their threads.
+#[derive(Debug)]
+pub struct FailScenario {
+    /// Guard of the exclusive scenarios
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl FailScenario {
+    /// Set up a scenario, waits for the one of another test to finish
+    #[inline]
+    #[must_use]
+    pub fn setup() -> Self {
+        /// Lock of the exclusive scenarios
+        static SCENARIO: Mutex<()> = Mutex::new(());
+        let guard = SCENARIO.lock().unwrap_or_else(PoisonError::into_inner);
+        remove_all();
+        Self { _guard: guard }
+    }
+
+    /// Activate the point for all callers, replaces its previous action
+    #[inline]
+    pub fn cfg(&self, name: &str, action: FailAction) {
+        Self::activate(name, action, None);
+    }
+
+    /// Activate the point only for the callers on the current thread, like the futures
+    /// polled by the main future of a `#[tokio::test]`, replaces its previous action
+    #[inline]
+    pub fn cfg_local(&self, name: &str, action: FailAction) {
+        Self::activate(name, action, Some(thread::current().id()));
+    }
+
+    /// Activate the point
+    fn activate(name: &str, action: FailAction, thread: Option<ThreadId>) {
+        let point = FailPoint {
+            action,
+            thread,
+            hits: 0,
+            gate: Arc::new(Gate {
+                released: AtomicBool::new(false),
+                event: Event::new(),
+            }),
+        };
+        if let Some(prev) = points().insert(name.to_owned(), point) {
+            prev.gate.release();
+        }
+    }
+
+    /// Remove the point, the callers paused at it go on
+    #[inline]
+    pub fn remove(&self, name: &str) {
+        if let Some(point) = points().remove(name) {
+            point.gate.release();
+        }
+    }
+
+    /// Number of the times the point is reached since it's activated, 0 if it's not
+    /// active
+    #[inline]
+    #[must_use]
+    pub fn hits(&self, name: &str) -> u64 {
+        points().get(name).map_or(0, |point| point.hits)
+    }
+
+    /// Wait until the point is reached `times` times since it's activated
+    #[inline]
+    pub async fn reached(&self, name: &str, times: u64) {
+        loop {
+            let listener = registry().hit.listen();
+            if self.hits(name) >= times {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+impl Drop for FailScenario {
+    #[inline]
+    fn drop(&mut self) {
+        remove_all();
+    }
+}
+
+/// Place a failpoint in sync code, the function returns `$err` if the point is
+/// activated with [`FailAction::Return`]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {
+        let _ignore = $crate::failpoint::eval($name);
+    };
+    ($name:expr, $err:expr) => {
+        if $crate::failpoint::eval($name) {
+            return $err;
+        }
+    };
+}
+
+/// Place a failpoint in async code, the function returns `$err` if the point is
+/// activated with [`FailAction::Return`]
+#[macro_export]
+macro_rules! fail_point_async {
+    ($name:expr) => {
+        let _ignore = $crate::failpoint::eval_async($name).await;
+    };
+    ($name:expr, $err:expr) => {
+        if $crate::failpoint::eval_async($name).await {
+            return $err;
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn point() -> Result<(), &'static str> {
+        crate::fail_point!("test_point", Err("injected"));
+        Ok(())
+    }
+
+    #[test]
+    fn return_action_should_return_the_error_of_the_point() {
+        let scenario = FailScenario::setup();
+        assert_eq!(point(), Ok(()));
+        scenario.cfg_local("test_point", FailAction::Return);
+        assert_eq!(point(), Err("injected"));
+        assert_eq!(scenario.hits("test_point"), 1);
+        // not active for the other threads
+        assert_eq!(thread::spawn(|| point()).join().unwrap(), Ok(()));
+        scenario.remove("test_point");
+        assert_eq!(point(), Ok(()));
+        assert_eq!(scenario.hits("test_point"), 0);
+    }
+
+    #[tokio::test]
+    async fn pause_action_should_block_until_the_point_is_removed() {
+        async fn paused() -> bool {
+            crate::fail_point_async!("test_paused", true);
+            false
+        }
+
+        let scenario = FailScenario::setup();
+        scenario.cfg_local("test_paused", FailAction::Pause);
+        let released = async {
+            scenario.reached("test_paused", 1).await;
+            scenario.remove("test_paused");
+        };
+        let (returned, ()) = tokio::join!(paused(), released);
+        assert!(!returned);
+
+        scenario.cfg_local("test_paused", FailAction::Delay(Duration::from_millis(10)));
+        assert!(!paused().await);
+        assert_eq!(scenario.hits("test_paused"), 1);
+    }
+}
@@ -188,6 +188,9 @@ pub struct ServerTlsConfig;
 pub mod barrier;
 /// configuration
 pub mod config;
+/// Failpoints injected by the tests
+#[cfg(feature = "failpoints")]
+pub mod failpoint;
 /// Interval tree implementation
 pub mod interval_map;
 /// utils for metrics
@@ -237,6 +240,22 @@ macro_rules! write_vec {
     };
 }
 
+/// Place a failpoint in sync code, a no-op without the `failpoints` feature
+#[cfg(not(feature = "failpoints"))]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {};
+    ($name:expr, $err:expr) => {};
+}
+
+/// Place a failpoint in async code, a no-op without the `failpoints` feature
+#[cfg(not(feature = "failpoints"))]
+#[macro_export]
+macro_rules! fail_point_async {
+    ($name:expr) => {};
+    ($name:expr, $err:expr) => {};
+}
+
 /// Get current timestamp in seconds
 #[must_use]
 #[inline]
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{collections::HashMap, net::Ipv6Addr, path::PathBuf, time::Duration};
 
 use clippy_utilities::OverflowArithmetic;
 use regex::Regex;
@@ -277,6 +277,66 @@ pub fn parse_metrics_push_protocol(s: &str) -> Result<MetricsPushProtocol, Confi
     }
 }
 
+/// Parse and normalize a peer or client url into `scheme://host:port`, or `host:port` if
+/// it has no scheme
+///
+/// The scheme is optional and must be http or https if given, a port is required, the
+/// scheme and host are lowercased, IPv6 hosts are bracketed and trailing slashes are removed.
+/// # Errors
+/// Return error naming the url and the violated rule when the url is invalid
+#[inline]
+pub fn parse_url(s: &str) -> Result<String, ConfigParseError> {
+    let invalid = |rule: &str| ConfigParseError::InvalidValue(format!("invalid url `{s}`: {rule}"));
+    let (prefix, rest) = match s.split_once("://") {
+        Some((scheme, rest)) => {
+            let scheme = scheme.to_ascii_lowercase();
+            if scheme != "http" && scheme != "https" {
+                return Err(invalid("scheme should be http or https"));
+            }
+            (format!("{scheme}://"), rest)
+        }
+        None => (String::new(), s),
+    };
+    let authority = rest.trim_end_matches('/');
+    if authority.contains(['/', '?', '#']) {
+        return Err(invalid("should not contain a path, query or fragment"));
+    }
+    if authority.contains('@') {
+        return Err(invalid("should not contain user info"));
+    }
+    let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        let Some((host, port)) = bracketed.split_once(']') else {
+            return Err(invalid("unclosed bracket around IPv6 host"));
+        };
+        let Some(port) = port.strip_prefix(':') else {
+            return Err(invalid("missing port"));
+        };
+        (host, port)
+    } else {
+        let Some((host, port)) = authority.rsplit_once(':') else {
+            return Err(invalid("missing port"));
+        };
+        (host, port)
+    };
+    let host = if host.contains(':') {
+        let ip: Ipv6Addr = host.parse().map_err(|_e| invalid("invalid IPv6 host"))?;
+        format!("[{ip}]")
+    } else if !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        host.to_ascii_lowercase()
+    } else {
+        return Err(invalid("invalid host"));
+    };
+    if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid("invalid port"));
+    }
+    let port: u16 = port.parse().map_err(|_e| invalid("port out of range"))?;
+    Ok(format!("{prefix}{host}:{port}"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -424,4 +484,49 @@ mod test {
         assert!(parse_log_file(".../path/with-spaces/log_file.log-123.456-789").is_err());
         assert!(parse_log_file("~~/path/with-spaces/log_file.log-123.456-789").is_err());
     }
+
+    #[test]
+    fn test_parse_url() {
+        let good = [
+            ("http://127.0.0.1:2380", "http://127.0.0.1:2380"),
+            ("https://127.0.0.1:2380", "https://127.0.0.1:2380"),
+            ("HTTP://LocalHost:2380", "http://localhost:2380"),
+            (
+                "http://node-1.xline.svc:2380/",
+                "http://node-1.xline.svc:2380",
+            ),
+            ("http://127.0.0.1:2380//", "http://127.0.0.1:2380"),
+            ("http://[::1]:2380", "http://[::1]:2380"),
+            ("http://[0:0::1]:2380", "http://[::1]:2380"),
+            ("http://::1:2380", "http://[::1]:2380"),
+            ("http://FE80::ABCD:2380", "http://[fe80::abcd]:2380"),
+            ("127.0.0.1:2380", "127.0.0.1:2380"),
+            ("LocalHost:2380/", "localhost:2380"),
+            ("[::1]:2380", "[::1]:2380"),
+        ];
+        for (url, expected) in good {
+            assert_eq!(parse_url(url).unwrap(), expected, "url: {url}");
+        }
+        let bad = [
+            ("localhost", "missing port"),
+            ("ftp://127.0.0.1:2380", "scheme should be http or https"),
+            ("http://127.0.0.1:2380/path", "path, query or fragment"),
+            ("http://127.0.0.1:2380?a=b", "path, query or fragment"),
+            ("http://user@127.0.0.1:2380", "user info"),
+            ("http://127.0.0.1", "missing port"),
+            ("http://[::1]", "missing port"),
+            ("http://[::1:2380", "unclosed bracket"),
+            ("http://::g:2380", "invalid IPv6 host"),
+            ("http://:2380", "invalid host"),
+            ("http://local_host:2380", "invalid host"),
+            ("http://127.0.0.1:", "invalid port"),
+            ("http://127.0.0.1:+80", "invalid port"),
+            ("http://127.0.0.1:65536", "port out of range"),
+        ];
+        for (url, rule) in bad {
+            let err = parse_url(url).unwrap_err().to_string();
+            assert!(err.contains(url), "error `{err}` should name `{url}`");
+            assert!(err.contains(rule), "error `{err}` should contain `{rule}`");
+        }
+    }
 }
@@ -12,9 +12,25 @@ pub const USER_TABLE: &str = "user";
 pub const ROLE_TABLE: &str = "role";
 /// Alarm table name
 pub const ALARM_TABLE: &str = "alarm";
+/// Lease expiry table name, it holds the remaining time of leases observed by the
+/// local node and is never replicated
+pub const LEASE_EXPIRY_TABLE: &str = "lease_expiry";
 
 /// Xline Server Storage Table
-pub const XLINE_TABLES: [&str; 7] = [
+pub const XLINE_TABLES: [&str; 8] = [
+    META_TABLE,
+    KV_TABLE,
+    LEASE_TABLE,
+    AUTH_TABLE,
+    USER_TABLE,
+    ROLE_TABLE,
+    ALARM_TABLE,
+    LEASE_EXPIRY_TABLE,
+];
+
+/// Tables replicated to every node, node local tables are excluded from the snapshots,
+/// the reset and the hash of the storage
+pub const REPLICATED_TABLES: [&str; 7] = [
     META_TABLE,
     KV_TABLE,
     LEASE_TABLE,
@@ -49,8 +49,14 @@ enum_with_iter! {
     GcSpecPool,
     GcCmdBoard,
     RevokeExpiredLeases,
+    CheckpointLeases,
+    PersistLeaseExpiries,
     SyncVictims,
     AutoCompactor,
+    CorruptionGuard,
+    CorruptionReport,
+    ProposeBatch,
+    ExpireSessions,
 }
 
 /// All edges of task graph, the first item in each pair must be shut down before the second item
@@ -194,7 +194,7 @@ impl ClusterClient {
             .into_inner())
     }
 
-    /// List all members in the cluster.
+    /// List all members in the cluster in ascending order of the ids.
     ///
     /// # Errors
     ///
@@ -1,17 +1,629 @@
-// TODO: Remove these when the placeholder is implemented.
-#![allow(missing_copy_implementations)]
-#![allow(clippy::new_without_default)]
+use std::{collections::VecDeque, fmt::Debug, sync::Arc};
+
+use tonic::transport::Channel;
+use xlineapi::{
+    command::{Command, CommandResponse, KeyRange, SyncResponse},
+    Compare, CompareResult, CompareTarget, DeleteRangeRequest, Event, EventType, KeyValue,
+    PutRequest, RangeRequest, RangeResponse, Request, RequestOp, RequestWrapper, Response,
+    SortOrder, SortTarget, TargetUnion, TxnRequest, TxnResponse,
+};
+
+use crate::{
+    clients::{lock::wait_delete, watch::WatchClient},
+    error::{Result, XlineClientError},
+    types::{
+        election::LeaderKey,
+        watch::{WatchEvent, WatchRequest, WatchStreaming},
+    },
+    CurpClient,
+};
 
 /// Client for Election operations.
-#[derive(Clone, Debug)]
-#[non_exhaustive]
-pub struct ElectionClient;
+///
+/// Candidates of an election put a key attached to their lease under the prefix of the
+/// election, the candidate whose key has the smallest create revision is the leader.
+#[derive(Clone)]
+pub struct ElectionClient {
+    /// The client running the CURP protocol, communicate with all servers.
+    curp_client: Arc<CurpClient>,
+    /// The watch client
+    watch_client: WatchClient,
+    /// Auth token
+    token: Option<String>,
+}
+
+impl Debug for ElectionClient {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ElectionClient")
+            .field("watch_client", &self.watch_client)
+            .field("token", &self.token)
+            .finish()
+    }
+}
 
 impl ElectionClient {
-    /// Create a new election client
+    /// Creates a new `ElectionClient`
+    #[inline]
+    pub fn new(curp_client: Arc<CurpClient>, channel: Channel, token: Option<String>) -> Self {
+        Self {
+            curp_client,
+            watch_client: WatchClient::new(channel, token.clone()),
+            token,
+        }
+    }
+
+    /// Puts a candidate with `value` attached to `lease` into the election `name`, and
+    /// waits until it becomes the leader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{clients::Session, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///     let session = Session::new(client.lease_client(), 10).await?;
+    ///
+    ///     let leader = client
+    ///         .election_client()
+    ///         .campaign("election", "member-1", session.lease_id())
+    ///         .await?;
+    ///     println!("leader key: {:?}", String::from_utf8_lossy(leader.key()));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn campaign(
+        &self,
+        name: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        lease: i64,
+    ) -> Result<LeaderKey> {
+        let name = name.into();
+        let value = value.into();
+        let prefix = Self::prefix(&name);
+        let mut key = prefix.clone();
+        key.extend_from_slice(format!("{lease:x}").as_bytes());
+
+        let txn = Self::create_campaign_txn(&key, &value, lease);
+        let (cmd_res, sync_res) = self.propose(txn, false).await?;
+        let mut txn_res = Into::<TxnResponse>::into(cmd_res.into_inner());
+        let leader = if txn_res.succeeded {
+            let rev = sync_res
+                .unwrap_or_else(|| unreachable!("sync_res always has value when use slow path"))
+                .revision();
+            LeaderKey {
+                name,
+                key,
+                rev,
+                lease,
+            }
+        } else {
+            // the lease is already a candidate, campaign again with the new value
+            let kv = txn_res
+                .responses
+                .pop()
+                .and_then(|r| {
+                    if let Some(Response::ResponseRange(res)) = r.response {
+                        res.kvs.into_iter().next()
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| {
+                    XlineClientError::ElectionError(String::from("candidate key not found"))
+                })?;
+            let leader = LeaderKey {
+                name,
+                key,
+                rev: kv.create_revision,
+                lease,
+            };
+            if kv.value != value {
+                self.proclaim(&leader, value).await?;
+            }
+            leader
+        };
+
+        if let Err(e) = wait_delete(
+            &self.curp_client,
+            self.token.as_ref(),
+            &self.watch_client,
+            &prefix,
+            leader.rev,
+        )
+        .await
+        {
+            let _ignore = self.resign(&leader).await;
+            return Err(e);
+        }
+        Ok(leader)
+    }
+
+    /// Updates the value of the leader without starting a new election.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `leader` is no longer the leader, or the inner
+    /// CURP client encountered a propose failure
+    #[inline]
+    pub async fn proclaim(&self, leader: &LeaderKey, value: impl Into<Vec<u8>>) -> Result<()> {
+        let put = RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: leader.key.clone(),
+                value: value.into(),
+                lease: leader.lease,
+                ..Default::default()
+            })),
+        };
+        let txn = Self::create_leader_txn(leader, put);
+        let (cmd_res, _sync_res) = self.propose(txn, true).await?;
+        if !Into::<TxnResponse>::into(cmd_res.into_inner()).succeeded {
+            return Err(XlineClientError::ElectionError(String::from(
+                "election: not leader",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Releases the leadership of `leader`, so that the next candidate can be elected.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn resign(&self, leader: &LeaderKey) -> Result<()> {
+        let delete = RequestOp {
+            request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                key: leader.key.clone(),
+                ..Default::default()
+            })),
+        };
+        let txn = Self::create_leader_txn(leader, delete);
+        let _res = self.propose(txn, true).await?;
+        Ok(())
+    }
+
+    /// Gets the key and value of the current leader of the election `name`, `None` if
+    /// there's no leader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn leader(&self, name: impl Into<Vec<u8>>) -> Result<Option<KeyValue>> {
+        let prefix = Self::prefix(&name.into());
+        let res = self.get_leader(&prefix, 0).await?;
+        Ok(res.kvs.into_iter().next())
+    }
+
+    /// Observes the leadership of the election `name`.
+    ///
+    /// The current leader is the first message if there's one when subscribing, then a
+    /// message is emitted exactly once for every leadership change or proclaim. Nothing
+    /// is emitted while there's no leader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///
+    ///     let mut observer = client.election_client().observe("election").await?;
+    ///     loop {
+    ///         let leader = observer.message().await?;
+    ///         println!("leader: {:?}", String::from_utf8_lossy(&leader.value));
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub async fn observe(&self, name: impl Into<Vec<u8>>) -> Result<ElectionObserver> {
+        let mut observer = ElectionObserver {
+            client: self.clone(),
+            prefix: Self::prefix(&name.into()),
+            state: ObserveState::default(),
+            pending: VecDeque::new(),
+            revision: 0,
+            stream: None,
+        };
+        observer.resync().await?;
+        Ok(observer)
+    }
+
+    /// The prefix of the keys of the election `name`
+    fn prefix(name: &[u8]) -> Vec<u8> {
+        let mut prefix = name.to_vec();
+        prefix.push(b'/');
+        prefix
+    }
+
+    /// Get the key with the smallest create revision under `prefix` at `revision`
+    async fn get_leader(&self, prefix: &[u8], revision: i64) -> Result<RangeResponse> {
+        #[allow(clippy::as_conversions)] // this cast is always safe
+        let get_req = RangeRequest {
+            key: prefix.to_vec(),
+            range_end: KeyRange::get_prefix(prefix),
+            revision,
+            sort_order: SortOrder::Ascend as i32,
+            sort_target: SortTarget::Create as i32,
+            limit: 1,
+            ..Default::default()
+        };
+        let (cmd_res, _sync_res) = self.propose(get_req, true).await?;
+        Ok(cmd_res.into_inner().into())
+    }
+
+    /// Propose request and get result with fast/slow path
+    async fn propose<T>(
+        &self,
+        request: T,
+        use_fast_path: bool,
+    ) -> Result<(CommandResponse, Option<SyncResponse>)>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let request = request.into();
+        let cmd = Command::new(request);
+        self.curp_client
+            .propose(&cmd, self.token.as_ref(), use_fast_path)
+            .await?
+            .map_err(Into::into)
+    }
+
+    /// Create txn that puts the candidate key if it doesn't exist, or gets it otherwise
+    fn create_campaign_txn(key: &[u8], value: &[u8], lease: i64) -> TxnRequest {
+        #[allow(clippy::as_conversions)] // this cast is always safe
+        let cmp = Compare {
+            result: CompareResult::Equal as i32,
+            target: CompareTarget::Create as i32,
+            key: key.to_vec(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::CreateRevision(0)),
+        };
+        let put = RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.to_vec(),
+                value: value.to_vec(),
+                lease,
+                ..Default::default()
+            })),
+        };
+        let get = RequestOp {
+            request: Some(Request::RequestRange(RangeRequest {
+                key: key.to_vec(),
+                ..Default::default()
+            })),
+        };
+        TxnRequest {
+            compare: vec![cmp],
+            success: vec![put],
+            failure: vec![get],
+        }
+    }
+
+    /// Create txn that runs `op` only if `leader` still holds the leadership
+    fn create_leader_txn(leader: &LeaderKey, op: RequestOp) -> TxnRequest {
+        #[allow(clippy::as_conversions)] // this cast is always safe
+        let cmp = Compare {
+            result: CompareResult::Equal as i32,
+            target: CompareTarget::Create as i32,
+            key: leader.key.clone(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::CreateRevision(leader.rev)),
+        };
+        TxnRequest {
+            compare: vec![cmp],
+            success: vec![op],
+            failure: vec![],
+        }
+    }
+}
+
+/// The stream of the leaders of an election, see [`ElectionClient::observe`]
+///
+/// The underlying watch is reopened when it's broken, right after the revision every event
+/// up to which has been handled. The leader is fetched again only if that revision is
+/// unknown or compacted, so that no leadership is emitted twice.
+pub struct ElectionObserver {
+    /// The election client
+    client: ElectionClient,
+    /// The prefix of the election
+    prefix: Vec<u8>,
+    /// The leadership already emitted
+    state: ObserveState,
+    /// Leaders not yet returned by `message`
+    pending: VecDeque<KeyValue>,
+    /// Every event of the prefix up to this revision has been handled, 0 if the leader
+    /// needs to be fetched again
+    revision: i64,
+    /// The watch of the prefix, `None` if it needs to be reopened
+    stream: Option<WatchStreaming>,
+}
+
+impl Debug for ElectionObserver {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ElectionObserver")
+            .field("prefix", &self.prefix)
+            .field("state", &self.state)
+            .field("pending", &self.pending)
+            .field("revision", &self.revision)
+            .finish()
+    }
+}
+
+impl ElectionObserver {
+    /// Waits for the next leader.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the leader can't be fetched after the watch
+    /// is broken
     #[inline]
-    #[must_use]
-    pub fn new() -> Self {
-        Self
+    pub async fn message(&mut self) -> Result<KeyValue> {
+        loop {
+            if let Some(kv) = self.pending.pop_front() {
+                return Ok(kv);
+            }
+            let Some(stream) = self.stream.as_mut() else {
+                if self.revision == 0 {
+                    self.resync().await?;
+                } else if self.watch_from(self.revision).await.is_err() {
+                    self.revision = 0;
+                }
+                continue;
+            };
+            let (watch_id, events) = match stream.message().await {
+                Ok(Some(WatchEvent::Events { watch_id, events })) => (watch_id, events),
+                Ok(Some(WatchEvent::Progress { revision, .. })) => {
+                    self.revision = self.revision.max(revision);
+                    continue;
+                }
+                // the revision the watch is resumed from is compacted
+                Ok(Some(WatchEvent::Canceled { .. })) => {
+                    self.stream = None;
+                    self.revision = 0;
+                    continue;
+                }
+                Ok(None) | Err(_) => {
+                    self.stream = None;
+                    continue;
+                }
+            };
+            let progress_revision = stream.progress_revision(watch_id);
+            for event in &events {
+                match self.state.on_event(event) {
+                    EventOutcome::Emit(kv) => self.pending.push_back(kv),
+                    EventOutcome::LeaderDeleted(revision) => {
+                        let next = self
+                            .client
+                            .get_leader(&self.prefix, revision)
+                            .await
+                            .map(|res| res.kvs.into_iter().next());
+                        match next {
+                            Ok(next) => self.pending.extend(self.state.on_synced(next)),
+                            // the revision may have been compacted, fetch the current
+                            // leader and watch from there
+                            Err(_) => {
+                                self.stream = None;
+                                self.revision = 0;
+                                break;
+                            }
+                        }
+                    }
+                    EventOutcome::Ignore => {}
+                }
+            }
+            if self.stream.is_some() {
+                self.revision = self.revision.max(progress_revision);
+            }
+        }
+    }
+
+    /// Fetch the current leader and watch the prefix from the revision right after
+    async fn resync(&mut self) -> Result<()> {
+        let res = self.client.get_leader(&self.prefix, 0).await?;
+        let revision = res.header.as_ref().map_or(0, |header| header.revision);
+        self.pending
+            .extend(self.state.on_synced(res.kvs.into_iter().next()));
+        self.revision = revision;
+        self.watch_from(revision).await
+    }
+
+    /// Watch the prefix from the revision right after the given one
+    async fn watch_from(&mut self, revision: i64) -> Result<()> {
+        let (_watcher, stream) = self
+            .client
+            .watch_client
+            .clone()
+            .watch(
+                WatchRequest::new(self.prefix.clone())
+                    .with_prefix()
+                    .with_start_revision(revision.saturating_add(1))
+                    .with_progress_notify(),
+            )
+            .await?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+/// What an event of the election prefix means to an observer
+#[derive(Debug, PartialEq)]
+enum EventOutcome {
+    /// The leader changed or proclaimed a new value
+    Emit(KeyValue),
+    /// The key of the leader is deleted at the revision, the next leader needs to be fetched
+    LeaderDeleted(i64),
+    /// The event doesn't change the leadership
+    Ignore,
+}
+
+/// The leadership an observer has emitted
+#[derive(Debug, Default)]
+struct ObserveState {
+    /// The last emitted leader, `None` if there's no leader
+    leader: Option<KeyValue>,
+}
+
+impl ObserveState {
+    /// The leader is fetched, returns it if it hasn't been emitted yet
+    fn on_synced(&mut self, leader: Option<KeyValue>) -> Option<KeyValue> {
+        let Some(kv) = leader else {
+            self.leader = None;
+            return None;
+        };
+        if self.is_emitted(&kv) {
+            return None;
+        }
+        self.leader = Some(kv.clone());
+        Some(kv)
+    }
+
+    /// Whether `kv` is the emitted leader or an earlier state of it
+    fn is_emitted(&self, kv: &KeyValue) -> bool {
+        self.leader.as_ref().is_some_and(|leader| {
+            leader.key == kv.key
+                && leader.create_revision == kv.create_revision
+                && leader.mod_revision >= kv.mod_revision
+        })
+    }
+
+    /// Handle an event of the election prefix
+    fn on_event(&mut self, event: &Event) -> EventOutcome {
+        let Some(kv) = event.kv.as_ref() else {
+            return EventOutcome::Ignore;
+        };
+        #[allow(clippy::as_conversions)] // this cast is always safe
+        let is_delete = event.r#type == EventType::Delete as i32;
+        let Some(leader) = self.leader.as_ref() else {
+            if is_delete {
+                return EventOutcome::Ignore;
+            }
+            // the first candidate after a gap without leader
+            self.leader = Some(kv.clone());
+            return EventOutcome::Emit(kv.clone());
+        };
+        if leader.key != kv.key {
+            // a candidate joining or leaving while there's a leader
+            return EventOutcome::Ignore;
+        }
+        if is_delete {
+            self.leader = None;
+            return EventOutcome::LeaderDeleted(kv.mod_revision);
+        }
+        // a replay of an emitted state, or the key created again after the deletion of
+        // the leader is handled
+        if self.is_emitted(kv) || leader.create_revision != kv.create_revision {
+            return EventOutcome::Ignore;
+        }
+        self.leader = Some(kv.clone());
+        EventOutcome::Emit(kv.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kv(key: &str, value: &str, create_revision: i64, mod_revision: i64) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: value.into(),
+            create_revision,
+            mod_revision,
+            ..Default::default()
+        }
+    }
+
+    #[allow(clippy::as_conversions)] // this cast is always safe
+    fn put(kv: KeyValue) -> Event {
+        Event {
+            r#type: EventType::Put as i32,
+            kv: Some(kv),
+            prev_kv: None,
+        }
+    }
+
+    #[allow(clippy::as_conversions)] // this cast is always safe
+    fn delete(key: &str, mod_revision: i64) -> Event {
+        Event {
+            r#type: EventType::Delete as i32,
+            kv: Some(kv(key, "", 0, mod_revision)),
+            prev_kv: None,
+        }
+    }
+
+    #[test]
+    fn replayed_boundary_revision_should_not_be_emitted_twice() {
+        let mut state = ObserveState::default();
+        let leader = kv("e/1", "v1", 2, 3);
+        assert_eq!(state.on_synced(Some(leader.clone())), Some(leader.clone()));
+
+        // a reconnected watch replays the revision already emitted
+        assert_eq!(state.on_event(&put(leader.clone())), EventOutcome::Ignore);
+        assert_eq!(
+            state.on_event(&put(kv("e/1", "v0", 2, 2))),
+            EventOutcome::Ignore
+        );
+        assert_eq!(state.on_synced(Some(leader)), None);
+
+        let proclaimed = kv("e/1", "v2", 2, 4);
+        assert_eq!(
+            state.on_event(&put(proclaimed.clone())),
+            EventOutcome::Emit(proclaimed.clone())
+        );
+        assert_eq!(state.on_event(&put(proclaimed)), EventOutcome::Ignore);
+    }
+
+    #[test]
+    fn leadership_handoff_should_emit_next_leader_once() {
+        let mut state = ObserveState::default();
+        let first = kv("e/1", "v1", 2, 2);
+        let second = kv("e/2", "v2", 3, 3);
+        assert_eq!(state.on_synced(Some(first)), Some(kv("e/1", "v1", 2, 2)));
+
+        // a candidate joining doesn't change the leader
+        assert_eq!(state.on_event(&put(second.clone())), EventOutcome::Ignore);
+        assert_eq!(
+            state.on_event(&delete("e/1", 4)),
+            EventOutcome::LeaderDeleted(4)
+        );
+        assert_eq!(state.on_synced(Some(second.clone())), Some(second.clone()));
+        assert_eq!(state.on_synced(Some(second)), None);
+
+        // nothing is emitted while there's no leader
+        assert_eq!(
+            state.on_event(&delete("e/2", 5)),
+            EventOutcome::LeaderDeleted(5)
+        );
+        assert_eq!(state.on_synced(None), None);
+        assert_eq!(state.on_event(&delete("e/3", 6)), EventOutcome::Ignore);
+
+        let third = kv("e/4", "v4", 7, 7);
+        assert_eq!(
+            state.on_event(&put(third.clone())),
+            EventOutcome::Emit(third)
+        );
     }
 }
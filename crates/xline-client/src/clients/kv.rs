@@ -1,17 +1,34 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
 
 use tonic::transport::Channel;
 use xlineapi::{
-    command::Command, CompactionResponse, DeleteRangeResponse, PutResponse, RangeResponse,
-    RequestWrapper, TxnResponse,
+    command::Command, execute_error::ExecuteError, CompactionResponse, DeleteRangeResponse,
+    KeyValue, PutResponse, RangeResponse, RequestWrapper, ResponseWrapper, Ticket, TxnResponse,
+    WaitAppliedRequest,
 };
 
 use crate::{
-    error::Result,
-    types::kv::{CompactionRequest, DeleteRangeRequest, PutRequest, RangeRequest, TxnRequest},
+    clients::watch::WatchClient,
+    error::{Result, XlineClientError},
+    types::{
+        kv::{
+            CompactionRequest, DeleteRangeRequest, PutRequest, RangeRequest, ReadConsistency,
+            TxnRequest,
+        },
+        watch::{WatchRequest, WatchStreaming, Watcher},
+    },
     AuthService, CurpClient,
 };
 
+/// Max attempts of `get_prefix_and_watch` when the watch races with a compaction
+const GET_AND_WATCH_ATTEMPTS: usize = 3;
+
 /// Client for KV operations.
 #[derive(Clone)]
 pub struct KvClient {
@@ -23,8 +40,14 @@ pub struct KvClient {
     /// The lease RPC client, only communicate with one server at a time
     #[cfg(madsim)]
     kv_client: xlineapi::KvClient<Channel>,
+    /// The watch client, used to watch from the revision of a range
+    watch_client: WatchClient,
     /// The auth token
     token: Option<String>,
+    /// How ranges are served
+    read_consistency: ReadConsistency,
+    /// The highest revision of the writes of the client, shared by its clones
+    high_water: Arc<AtomicI64>,
 }
 
 impl Debug for KvClient {
@@ -33,7 +56,10 @@ impl Debug for KvClient {
         f.debug_struct("KvClient")
             .field("kv_client", &self.kv_client)
             .field("kv_client", &self.kv_client)
+            .field("watch_client", &self.watch_client)
             .field("token", &self.token)
+            .field("read_consistency", &self.read_consistency)
+            .field("high_water", &self.high_water)
             .finish()
     }
 }
@@ -45,17 +71,65 @@ impl KvClient {
         curp_client: Arc<CurpClient>,
         channel: Channel,
         token: Option<String>,
+        read_consistency: ReadConsistency,
     ) -> Self {
         Self {
             curp_client,
             kv_client: xlineapi::KvClient::new(AuthService::new(
-                channel,
+                channel.clone(),
                 token.as_ref().and_then(|t| t.parse().ok().map(Arc::new)),
             )),
+            watch_client: WatchClient::new(channel, token.clone()),
             token,
+            read_consistency,
+            high_water: Arc::new(AtomicI64::new(0)),
         }
     }
 
+    /// Propose a mutation, its revision raises the high-water mark of the client
+    async fn propose_mutation(&self, request: RequestWrapper) -> Result<ResponseWrapper> {
+        let cmd = Command::new(request);
+        // the revision of a mutation is only known once it's synced
+        let use_fast_path = !matches!(self.read_consistency, ReadConsistency::BoundedStaleness(_));
+        let (cmd_res, sync_res) = self
+            .curp_client
+            .propose(&cmd, self.token.as_ref(), use_fast_path)
+            .await??;
+        let mut res_wrapper = cmd_res.into_inner();
+        if let Some(sync_res) = sync_res {
+            res_wrapper.update_revision(sync_res.revision());
+            let _prev = self
+                .high_water
+                .fetch_max(sync_res.revision(), Ordering::Relaxed);
+        }
+        Ok(res_wrapper)
+    }
+
+    /// Range through the consensus protocol
+    async fn propose_range(&self, request: xlineapi::RangeRequest) -> Result<RangeResponse> {
+        let cmd = Command::new(RequestWrapper::from(request));
+        let (cmd_res, _sync_res) = self
+            .curp_client
+            .propose(&cmd, self.token.as_ref(), true)
+            .await??;
+        Ok(cmd_res.into_inner().into())
+    }
+
+    /// Range on the member the client is connected to
+    async fn local_range(
+        &self,
+        request: xlineapi::RangeRequest,
+    ) -> std::result::Result<RangeResponse, tonic::Status> {
+        let mut kv_client = self.kv_client.clone();
+        kv_client
+            .range(xlineapi::RangeRequest {
+                serializable: true,
+                ..request
+            })
+            .await
+            .map(tonic::Response::into_inner)
+    }
+
     /// Put a key-value into the store
     ///
     /// # Errors
@@ -84,16 +158,85 @@ impl KvClient {
     #[inline]
     pub async fn put(&self, request: PutRequest) -> Result<PutResponse> {
         let request = RequestWrapper::from(xlineapi::PutRequest::from(request));
-        let cmd = Command::new(request);
-        let (cmd_res, _sync_res) = self
-            .curp_client
-            .propose(&cmd, self.token.as_ref(), true)
-            .await??;
-        Ok(cmd_res.into_inner().into())
+        Ok(self.propose_mutation(request).await?.into())
+    }
+
+    /// Put a key-value into the store without waiting for it to be applied, return the
+    /// ticket to fetch the `PutResponse` later with [`KvClient::wait_applied`]
+    ///
+    /// The put is accepted once it's durable and survives leader changes, the requests on
+    /// the same keys are applied in the order they are accepted. If it conflicts with a
+    /// request not applied yet, it returns only after it's applied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the put is rejected before it's accepted,
+    /// errors raised when it's applied, like permission denied, are returned by
+    /// [`KvClient::wait_applied`]
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::PutRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let ticket = client.put_async(PutRequest::new("key1", "value1")).await?;
+    ///     let resp = client.wait_applied(ticket).await?;
+    ///     println!("applied at revision {}", resp.header.unwrap().revision);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn put_async(&self, request: PutRequest) -> Result<Ticket> {
+        let mut request = xlineapi::PutRequest::from(request);
+        request.r#async = true;
+        let mut kv_client = self.kv_client.clone();
+        kv_client
+            .put(request)
+            .await?
+            .into_inner()
+            .ticket
+            .ok_or_else(|| {
+                XlineClientError::InternalError("no ticket in the async put response".to_owned())
+            })
+    }
+
+    /// Wait for a put submitted by [`KvClient::put_async`] to be applied, and get its
+    /// `PutResponse`
+    ///
+    /// It could be called any number of times, on any server, until the result of the put
+    /// is evicted from the result cache of the cluster.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the put failed when it's applied, or a
+    /// `NotFound` rpc error if the ticket is unknown because its result has been evicted
+    #[inline]
+    pub async fn wait_applied(&self, ticket: Ticket) -> Result<PutResponse> {
+        let mut kv_client = self.kv_client.clone();
+        kv_client
+            .wait_applied(WaitAppliedRequest {
+                ticket: Some(ticket),
+            })
+            .await
+            .map(tonic::Response::into_inner)
+            .map_err(Into::into)
     }
 
     /// Get a range of keys from the store
     ///
+    /// How the range is served depends on the [`ReadConsistency`] of the client, a
+    /// bounded staleness range sees every write of the client before it
+    ///
     /// # Errors
     ///
     /// This function will return an error if the inner CURP client encountered a propose failure
@@ -127,13 +270,101 @@ impl KvClient {
     /// ```
     #[inline]
     pub async fn range(&self, request: RangeRequest) -> Result<RangeResponse> {
-        let request = RequestWrapper::from(xlineapi::RangeRequest::from(request));
-        let cmd = Command::new(request);
-        let (cmd_res, _sync_res) = self
-            .curp_client
-            .propose(&cmd, self.token.as_ref(), true)
-            .await??;
-        Ok(cmd_res.into_inner().into())
+        let request = xlineapi::RangeRequest::from(request);
+        match self.read_consistency {
+            ReadConsistency::Linearizable => self.propose_range(request).await,
+            ReadConsistency::Serializable => Ok(self.local_range(request).await?),
+            ReadConsistency::BoundedStaleness(wait_timeout) => {
+                let local = self.local_range(xlineapi::RangeRequest {
+                    min_revision: self.high_water.load(Ordering::Relaxed),
+                    ..request.clone()
+                });
+                match tokio::time::timeout(wait_timeout, local).await {
+                    Ok(Ok(resp)) => Ok(resp),
+                    // the member lags behind the writes of the client
+                    Ok(Err(status)) if status.code() == tonic::Code::DeadlineExceeded => {
+                        self.propose_range(request).await
+                    }
+                    Ok(Err(status)) => Err(status.into()),
+                    Err(_elapsed) => self.propose_range(request).await,
+                }
+            }
+        }
+    }
+
+    /// Get all keys with the given prefix and watch the prefix from the revision right after
+    /// the returned keys, the events of the stream start exactly where the returned keys end,
+    /// no event is missed or duplicated.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure,
+    /// or the watch can't be created
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::watch::WatchEvent, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let (kvs, mut watcher, mut stream) = client.get_prefix_and_watch("prefix").await?;
+    ///     println!("got {} keys", kvs.len());
+    ///
+    ///     while let Some(event) = stream.message().await? {
+    ///         if let WatchEvent::Events { events, .. } = event {
+    ///             println!("got {} events", events.len());
+    ///         }
+    ///     }
+    ///     watcher.cancel()?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn get_prefix_and_watch(
+        &self,
+        prefix: impl Into<Vec<u8>>,
+    ) -> Result<(Vec<KeyValue>, Watcher, WatchStreaming)> {
+        let prefix = prefix.into();
+        for attempt in 1..=GET_AND_WATCH_ATTEMPTS {
+            let resp = self
+                .range(RangeRequest::new(prefix.clone()).with_prefix())
+                .await?;
+            let revision = resp.header.as_ref().map_or(0, |h| h.revision);
+            let (mut watcher, stream) = self
+                .watch_client
+                .clone()
+                .watch(
+                    WatchRequest::new(prefix.clone())
+                        .with_prefix()
+                        .with_start_revision(revision.wrapping_add(1)),
+                )
+                .await?;
+            // Compaction never moves backward, so the watch is not compacted if the
+            // revision of the keys is still readable after the watch is created
+            let check = RangeRequest::new(prefix.clone())
+                .with_prefix()
+                .with_revision(revision)
+                .with_count_only(true);
+            match self.range(check).await {
+                Ok(_) => return Ok((resp.kvs, watcher, stream)),
+                Err(XlineClientError::ExecuteError(ExecuteError::RevisionCompacted(_, _)))
+                    if attempt < GET_AND_WATCH_ATTEMPTS =>
+                {
+                    watcher.cancel()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("the last attempt always returns")
     }
 
     /// Delete a range of keys from the store
@@ -165,12 +396,7 @@ impl KvClient {
     #[inline]
     pub async fn delete(&self, request: DeleteRangeRequest) -> Result<DeleteRangeResponse> {
         let request = RequestWrapper::from(xlineapi::DeleteRangeRequest::from(request));
-        let cmd = Command::new(request);
-        let (cmd_res, _sync_res) = self
-            .curp_client
-            .propose(&cmd, self.token.as_ref(), true)
-            .await??;
-        Ok(cmd_res.into_inner().into())
+        Ok(self.propose_mutation(request).await?.into())
     }
 
     /// Creates a transaction, which can provide serializable writes
@@ -223,6 +449,9 @@ impl KvClient {
         };
         let mut res_wrapper = cmd_res.into_inner();
         res_wrapper.update_revision(sync_res.revision());
+        let _prev = self
+            .high_water
+            .fetch_max(sync_res.revision(), Ordering::Relaxed);
         Ok(res_wrapper.into())
     }
 
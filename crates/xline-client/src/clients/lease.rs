@@ -1,18 +1,22 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use futures::channel::mpsc::channel;
-use tonic::{transport::Channel, Streaming};
+use futures::channel::mpsc::{channel, Sender};
+use tonic::{transport::Channel, Code, Status, Streaming};
 use xlineapi::{
-    command::Command, LeaseGrantResponse, LeaseKeepAliveResponse, LeaseLeasesResponse,
-    LeaseRevokeResponse, LeaseTimeToLiveResponse, RequestWrapper,
+    command::Command, execute_error::ExecuteError, LeaseGrantResponse, LeaseKeepAliveResponse,
+    LeaseLeasesResponse, LeaseRevokeResponse, LeaseTimeToLiveResponse, RequestWrapper,
 };
 
 use crate::{
     error::{Result, XlineClientError},
     lease_gen::LeaseIdGenerator,
     types::lease::{
-        LeaseGrantRequest, LeaseKeepAliveRequest, LeaseKeeper, LeaseRevokeRequest,
-        LeaseTimeToLiveRequest,
+        LeaseGrantRequest, LeaseKeepAliveRequest, LeaseKeepAliveStream, LeaseKeeper,
+        LeaseRevokeRequest, LeaseTimeToLiveRequest,
     },
     AuthService, CurpClient,
 };
@@ -108,6 +112,58 @@ impl LeaseClient {
         Ok(cmd_res.into_inner().into())
     }
 
+    /// Creates a lease which expires at `deadline` unless it's kept alive, this is an
+    /// Xline extension.
+    ///
+    /// The deadline is converted to a ttl with the clock of the leader, the ttl in the
+    /// response is the effective one, which is rounded up to seconds and bounded by the
+    /// min and max lease ttl. A keep alive renews the lease to this ttl rather than
+    /// extending it relative to the deadline.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the deadline is in the past or the grant
+    /// fails
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// use xline_client::{Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .lease_client();
+    ///
+    ///     let deadline = SystemTime::now() + Duration::from_secs(3600);
+    ///     let resp = client.grant_until(deadline).await?;
+    ///     println!("lease id: {}, ttl: {}", resp.id, resp.ttl);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn grant_until(&mut self, deadline: SystemTime) -> Result<LeaseGrantResponse> {
+        let deadline_ms = deadline
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| XlineClientError::InvalidArgs(e.to_string()))?
+            .as_millis()
+            .try_into()
+            .map_err(|_e| XlineClientError::InvalidArgs(String::from("deadline is too far")))?;
+        let request = xlineapi::LeaseGrantRequest {
+            id: self.id_gen.next(),
+            deadline_ms,
+            ..Default::default()
+        };
+        Ok(self.lease_client.lease_grant(request).await?.into_inner())
+    }
+
     /// Revokes a lease. All keys attached to the lease will expire and be deleted.
     ///
     /// # Errors
@@ -137,12 +193,18 @@ impl LeaseClient {
     /// ```
     #[inline]
     pub async fn revoke(&mut self, request: LeaseRevokeRequest) -> Result<LeaseRevokeResponse> {
-        let res = self.lease_client.lease_revoke(request.inner).await?;
+        let id = request.inner.id;
+        let res = self
+            .lease_client
+            .lease_revoke(request.inner)
+            .await
+            .map_err(|e| lease_error(e, id))?;
         Ok(res.into_inner())
     }
 
     /// Keeps the lease alive by streaming keep alive requests from the client
     /// to the server and streaming keep alive responses from the server to the client.
+    /// The stream is reopened when it's broken, e.g. after a leader change.
     ///
     /// # Errors
     ///
@@ -179,29 +241,43 @@ impl LeaseClient {
     pub async fn keep_alive(
         &mut self,
         request: LeaseKeepAliveRequest,
-    ) -> Result<(LeaseKeeper, Streaming<LeaseKeepAliveResponse>)> {
+    ) -> Result<(LeaseKeeper, LeaseKeepAliveStream)> {
+        let (sender, stream, resp) = self.open_keep_alive(request.inner.id).await?;
+        let keeper = LeaseKeeper::new(resp.id, sender);
+        let stream = LeaseKeepAliveStream::new(self.clone(), &keeper, stream);
+        Ok((keeper, stream))
+    }
+
+    /// Opens a keep alive stream and sends the first keep alive request, returns the
+    /// request sender, the response stream and the first response
+    pub(crate) async fn open_keep_alive(
+        &mut self,
+        id: i64,
+    ) -> Result<(
+        Sender<xlineapi::LeaseKeepAliveRequest>,
+        Streaming<LeaseKeepAliveResponse>,
+        LeaseKeepAliveResponse,
+    )> {
         let (mut sender, receiver) = channel::<xlineapi::LeaseKeepAliveRequest>(100);
 
         sender
-            .try_send(request.into())
+            .try_send(LeaseKeepAliveRequest::new(id).into())
             .map_err(|e| XlineClientError::LeaseError(e.to_string()))?;
 
         let mut stream = self
             .lease_client
             .lease_keep_alive(receiver)
-            .await?
+            .await
+            .map_err(|e| lease_error(e, id))?
             .into_inner();
 
-        let id = match stream.message().await? {
-            Some(resp) => resp.id,
-            None => {
-                return Err(XlineClientError::LeaseError(String::from(
-                    "failed to create lease keeper",
-                )));
-            }
+        let Some(resp) = stream.message().await.map_err(|e| lease_error(e, id))? else {
+            return Err(XlineClientError::LeaseError(String::from(
+                "failed to create lease keeper",
+            )));
         };
 
-        Ok((LeaseKeeper::new(id, sender), stream))
+        Ok((sender, stream, resp))
     }
 
     /// Retrieves lease information.
@@ -238,14 +314,16 @@ impl LeaseClient {
         &mut self,
         request: LeaseTimeToLiveRequest,
     ) -> Result<LeaseTimeToLiveResponse> {
+        let id = request.inner.id;
         Ok(self
             .lease_client
             .lease_time_to_live(xlineapi::LeaseTimeToLiveRequest::from(request))
-            .await?
+            .await
+            .map_err(|e| lease_error(e, id))?
             .into_inner())
     }
 
-    /// Lists all existing leases.
+    /// Lists all existing leases in ascending order of the ids.
     ///
     /// # Errors
     ///
@@ -283,3 +361,38 @@ impl LeaseClient {
         Ok(cmd_res.into_inner().into())
     }
 }
+
+/// Maps the status of a lease RPC to a typed error
+///
+/// An expired lease is reported with `DeadlineExceeded` as well, which is told apart
+/// from an RPC running out of its deadline by the message.
+fn lease_error(status: Status, id: i64) -> XlineClientError<Command> {
+    match status.code() {
+        Code::NotFound => ExecuteError::LeaseNotFound(id).into(),
+        Code::DeadlineExceeded => {
+            let expired = ExecuteError::LeaseExpired(id);
+            if status.message() == expired.to_string() {
+                expired.into()
+            } else {
+                XlineClientError::Timeout
+            }
+        }
+        _ => status.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deadline_exceeded_should_only_map_to_lease_expired_for_expired_leases() {
+        let status = Status::from(ExecuteError::LeaseExpired(1));
+        assert!(matches!(
+            lease_error(status, 1),
+            XlineClientError::ExecuteError(ExecuteError::LeaseExpired(1))
+        ));
+        let status = Status::deadline_exceeded("Timeout expired");
+        assert!(matches!(lease_error(status, 1), XlineClientError::Timeout));
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt::Debug,
     pin::Pin,
     sync::{
@@ -9,6 +10,7 @@ use std::{
 
 use clippy_utilities::OverflowArithmetic;
 use futures::{Future, FutureExt};
+use tokio::sync::Mutex;
 use tonic::transport::Channel;
 use xlineapi::{
     command::{Command, CommandResponse, KeyRange, SyncResponse},
@@ -19,13 +21,12 @@ use xlineapi::{
 };
 
 use crate::{
-    clients::{lease::LeaseClient, watch::WatchClient},
+    clients::{lease::LeaseClient, session::Session, watch::WatchClient},
     error::{Result, XlineClientError},
     lease_gen::LeaseIdGenerator,
     types::{
-        lease::LeaseGrantRequest,
         lock::{LockRequest, UnlockRequest},
-        watch::WatchRequest,
+        watch::{WatchEvent, WatchRequest},
     },
     CurpClient,
 };
@@ -41,6 +42,8 @@ pub struct LockClient {
     watch_client: WatchClient,
     /// Auth token
     token: Option<String>,
+    /// Sessions created for locks acquired without a lease, indexed by the lock key
+    sessions: Arc<Mutex<HashMap<Vec<u8>, Session>>>,
 }
 
 impl Debug for LockClient {
@@ -70,6 +73,7 @@ impl LockClient {
             lease_client: LeaseClient::new(curp_client, channel.clone(), token.clone(), id_gen),
             watch_client: WatchClient::new(channel, token.clone()),
             token,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -80,6 +84,9 @@ impl LockClient {
     /// lock ownership. The lock is held until Unlock is called on the key or the
     /// lease associate with the owner expires.
     ///
+    /// If the request has no lease, a [`Session`] keeping a lease of `ttl` seconds
+    /// alive is created for the lock, and closed when the lock is unlocked.
+    ///
     /// # Errors
     ///
     /// This function will return an error if the inner CURP client encountered a propose failure
@@ -117,12 +124,11 @@ impl LockClient {
     #[inline]
     pub async fn lock(&self, request: LockRequest) -> Result<LockResponse> {
         let mut lease_id = request.inner.lease;
+        let mut session = None;
         if lease_id == 0 {
-            let resp = self
-                .lease_client
-                .grant(LeaseGrantRequest::new(request.ttl))
-                .await?;
-            lease_id = resp.id;
+            let new_session = Session::new(self.lease_client.clone(), request.ttl).await?;
+            lease_id = new_session.lease_id();
+            session = Some(new_session);
         }
         let prefix = format!(
             "{}/",
@@ -133,13 +139,24 @@ impl LockClient {
         let lock_inner = self.lock_inner(prefix, key.clone(), lease_id, &lock_success);
         tokio::pin!(lock_inner);
 
-        LockFuture {
+        let res = LockFuture {
             key,
             lock_success: &lock_success,
             lock_client: self,
             lock_inner,
         }
-        .await
+        .await;
+        if let Some(session) = session {
+            match res {
+                Ok(ref resp) => {
+                    let _prev = self.sessions.lock().await.insert(resp.key.clone(), session);
+                }
+                Err(_) => {
+                    let _ignore = session.close().await;
+                }
+            }
+        }
+        res
     }
 
     /// The inner lock logic
@@ -176,7 +193,14 @@ impl LockClient {
         {
             owner_res.header
         } else {
-            self.wait_delete(prefix, my_rev).await?;
+            wait_delete(
+                &self.curp_client,
+                self.token.as_ref(),
+                &self.watch_client,
+                prefix.as_bytes(),
+                my_rev,
+            )
+            .await?;
             let range_req = RangeRequest {
                 key: key.as_bytes().to_vec(),
                 ..Default::default()
@@ -242,6 +266,10 @@ impl LockClient {
     #[inline]
     pub async fn unlock(&self, request: UnlockRequest) -> Result<UnlockResponse> {
         let header = self.delete_key(&request.inner.key).await?;
+        let session = self.sessions.lock().await.remove(&request.inner.key);
+        if let Some(session) = session {
+            session.close().await?;
+        }
         Ok(UnlockResponse { header })
     }
 
@@ -306,43 +334,6 @@ impl LockClient {
         }
     }
 
-    /// Wait until last key deleted
-    async fn wait_delete(&self, pfx: String, my_rev: i64) -> Result<()> {
-        let rev = my_rev.overflow_sub(1);
-        let mut watch_client = self.watch_client.clone();
-        loop {
-            let range_end = KeyRange::get_prefix(pfx.as_bytes());
-            #[allow(clippy::as_conversions)] // this cast is always safe
-            let get_req = RangeRequest {
-                key: pfx.as_bytes().to_vec(),
-                range_end,
-                limit: 1,
-                sort_order: SortOrder::Descend as i32,
-                sort_target: SortTarget::Create as i32,
-                max_create_revision: rev,
-                ..Default::default()
-            };
-
-            let (cmd_res, _sync_res) = self.propose(get_req, false).await?;
-            let response = Into::<RangeResponse>::into(cmd_res.into_inner());
-            let last_key = match response.kvs.first() {
-                Some(kv) => kv.key.clone(),
-                None => return Ok(()),
-            };
-            let (_, mut response_stream) = watch_client.watch(WatchRequest::new(last_key)).await?;
-            while let Some(watch_res) = response_stream.message().await? {
-                #[allow(clippy::as_conversions)] // this cast is always safe
-                if watch_res
-                    .events
-                    .iter()
-                    .any(|e| e.r#type == EventType::Delete as i32)
-                {
-                    break;
-                }
-            }
-        }
-    }
-
     /// Delete key
     async fn delete_key(&self, key: &[u8]) -> Result<Option<ResponseHeader>> {
         let del_req = DeleteRangeRequest {
@@ -355,6 +346,53 @@ impl LockClient {
     }
 }
 
+/// Wait until all keys under `pfx` created before `my_rev` are deleted
+pub(super) async fn wait_delete(
+    curp_client: &CurpClient,
+    token: Option<&String>,
+    watch_client: &WatchClient,
+    pfx: &[u8],
+    my_rev: i64,
+) -> Result<()> {
+    let rev = my_rev.overflow_sub(1);
+    let mut watch_client = watch_client.clone();
+    loop {
+        let range_end = KeyRange::get_prefix(pfx);
+        #[allow(clippy::as_conversions)] // this cast is always safe
+        let get_req = RangeRequest {
+            key: pfx.to_vec(),
+            range_end,
+            limit: 1,
+            sort_order: SortOrder::Descend as i32,
+            sort_target: SortTarget::Create as i32,
+            max_create_revision: rev,
+            ..Default::default()
+        };
+
+        let cmd = Command::new(get_req.into());
+        let (cmd_res, _sync_res) = curp_client.propose(&cmd, token, false).await??;
+        let response = Into::<RangeResponse>::into(cmd_res.into_inner());
+        let last_key = match response.kvs.first() {
+            Some(kv) => kv.key.clone(),
+            None => return Ok(()),
+        };
+        let (_, mut response_stream) = watch_client.watch(WatchRequest::new(last_key)).await?;
+        while let Some(event) = response_stream.message().await? {
+            match event {
+                #[allow(clippy::as_conversions)] // this cast is always safe
+                WatchEvent::Events { events, .. }
+                    if events.iter().any(|e| e.r#type == EventType::Delete as i32) =>
+                {
+                    break;
+                }
+                // the key may be deleted while the watcher is canceled, check it again
+                WatchEvent::Canceled { .. } => break,
+                WatchEvent::Events { .. } | WatchEvent::Progress { .. } => {}
+            }
+        }
+    }
+}
+
 /// The future that will do the lock operation
 /// This exists because we need to do some clean up after the lock operation has failed or being cancelled
 struct LockFuture<'a> {
@@ -2,7 +2,9 @@ use std::{fmt::Debug, sync::Arc};
 
 use tonic::{transport::Channel, Streaming};
 use xlineapi::{
-    AlarmRequest, AlarmResponse, SnapshotRequest, SnapshotResponse, StatusRequest, StatusResponse,
+    AlarmRequest, AlarmResponse, CompactionExemptionRequest, CompactionExemptionResponse,
+    HashKvRequest, HashKvResponse, SnapshotRequest, SnapshotResponse, StatusRequest,
+    StatusResponse, TimeToRevisionRequest, TimeToRevisionResponse,
 };
 
 use crate::{error::Result, AuthService};
@@ -139,4 +141,125 @@ impl MaintenanceClient {
             .await?
             .into_inner())
     }
+
+    /// Sends a hash kv request, the hashed revision is returned as the revision of the
+    /// response header, so that hashes of different members can be compared at the same
+    /// revision
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner RPC client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{Client, ClientOptions};
+    /// use xlineapi::HashKvRequest;
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     // the name and address of all curp members
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .maintenance_client();
+    ///
+    ///     let resp = client.hash_kv(HashKvRequest { revision: 0 }).await?;
+    ///     println!("hash: {}", resp.hash);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn hash_kv(&mut self, request: HashKvRequest) -> Result<HashKvResponse> {
+        Ok(self.inner.hash_kv(request).await?.into_inner())
+    }
+
+    /// Resolves a wall time to the latest revision recorded at or before it by the
+    /// server, the resolution of the lookup is returned together with the revision
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner RPC client encountered a propose failure,
+    /// or no revision is recorded at or before the time
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::maintenance::TimeToRevisionRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     // the name and address of all curp members
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .maintenance_client();
+    ///
+    ///     let resp = client
+    ///         .time_to_revision(TimeToRevisionRequest {
+    ///             time_ms: 1_700_000_000_000,
+    ///         })
+    ///         .await?;
+    ///     println!(
+    ///         "revision: {}, within {}ms",
+    ///         resp.revision, resp.resolution_ms
+    ///     );
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn time_to_revision(
+        &mut self,
+        request: TimeToRevisionRequest,
+    ) -> Result<TimeToRevisionResponse> {
+        Ok(self.inner.time_to_revision(request).await?.into_inner())
+    }
+
+    /// Adds and removes the key prefixes whose history is kept by compactions, the
+    /// exempt prefixes after the update are returned, it's only allowed for root
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner RPC client encountered a propose failure,
+    /// or the user is not root
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::maintenance::CompactionExemptionRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     // the name and address of all curp members
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .maintenance_client();
+    ///
+    ///     let resp = client
+    ///         .compaction_exemption(CompactionExemptionRequest {
+    ///             add: vec![b"/audit/".to_vec()],
+    ///             remove: vec![],
+    ///         })
+    ///         .await?;
+    ///     println!("exempt prefixes: {:?}", resp.prefixes);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn compaction_exemption(
+        &mut self,
+        request: CompactionExemptionRequest,
+    ) -> Result<CompactionExemptionResponse> {
+        Ok(self.inner.compaction_exemption(request).await?.into_inner())
+    }
 }
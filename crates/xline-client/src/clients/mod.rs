@@ -1,10 +1,11 @@
 pub use auth::AuthClient;
 pub use cluster::ClusterClient;
-pub use election::ElectionClient;
+pub use election::{ElectionClient, ElectionObserver};
 pub use kv::KvClient;
 pub use lease::LeaseClient;
 pub use lock::LockClient;
 pub use maintenance::MaintenanceClient;
+pub use session::Session;
 pub use watch::WatchClient;
 
 /// Auth client.
@@ -21,5 +22,7 @@ mod lease;
 mod lock;
 /// Maintenance client.
 mod maintenance;
+/// Session.
+mod session;
 /// Watch client.
 mod watch;
@@ -0,0 +1,208 @@
+use std::{cmp::min, fmt::Debug, time::Duration};
+
+use clippy_utilities::NumericCast;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    clients::lease::LeaseClient,
+    error::{Result, XlineClientError},
+    types::lease::{
+        LeaseGrantRequest, LeaseKeepAliveRequest, LeaseKeepAliveStream, LeaseKeeper,
+        LeaseRevokeRequest,
+    },
+};
+
+/// Interval to retry a failed keep alive
+const KEEP_ALIVE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A lease that is kept alive in the background for as long as the session is open.
+///
+/// Keys attached to the lease of a session, such as locks, leader keys or ephemeral
+/// registrations, exist until the session is closed or its lease is lost.
+pub struct Session {
+    /// The lease client
+    lease_client: LeaseClient,
+    /// The id of the lease
+    lease_id: i64,
+    /// The ttl of the lease in seconds
+    ttl: i64,
+    /// Becomes `true` once the lease is lost
+    done_rx: watch::Receiver<bool>,
+    /// The keep alive task
+    keep_alive: JoinHandle<()>,
+}
+
+impl Debug for Session {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("lease_id", &self.lease_id)
+            .field("ttl", &self.ttl)
+            .field("done", &*self.done_rx.borrow())
+            .finish()
+    }
+}
+
+impl Session {
+    /// Grants a lease of `ttl` seconds and starts keeping it alive.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lease can't be granted
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{clients::Session, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///
+    ///     let session = Session::new(client.lease_client(), 10).await?;
+    ///     println!("lease id: {}", session.lease_id());
+    ///
+    ///     session.close().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn new(lease_client: LeaseClient, ttl: i64) -> Result<Self> {
+        let resp = lease_client.grant(LeaseGrantRequest::new(ttl)).await?;
+        let (done_tx, done_rx) = watch::channel(false);
+        let keep_alive = tokio::spawn(Self::keep_alive_task(
+            lease_client.clone(),
+            resp.id,
+            resp.ttl,
+            done_tx,
+        ));
+        Ok(Self {
+            lease_client,
+            lease_id: resp.id,
+            ttl: resp.ttl,
+            done_rx,
+            keep_alive,
+        })
+    }
+
+    /// The id of the lease of this session.
+    #[inline]
+    #[must_use]
+    pub fn lease_id(&self) -> i64 {
+        self.lease_id
+    }
+
+    /// The ttl in seconds granted to the lease of this session.
+    #[inline]
+    #[must_use]
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    /// Resolves once the lease of this session is lost, either because the server
+    /// revoked or expired it, or because it couldn't be kept alive within its ttl.
+    #[inline]
+    pub async fn done(&self) {
+        let mut done_rx = self.done_rx.clone();
+        while !*done_rx.borrow_and_update() {
+            if done_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Stops keeping the lease alive and revokes it, keys attached to it are deleted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lease can't be revoked
+    #[inline]
+    pub async fn close(self) -> Result<()> {
+        self.keep_alive.abort();
+        if *self.done_rx.borrow() {
+            return Ok(());
+        }
+        let _resp = self
+            .lease_client
+            .clone()
+            .revoke(LeaseRevokeRequest::new(self.lease_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Keeps the lease alive until it's lost
+    ///
+    /// The lease is considered lost once no keep alive has succeeded within the ttl
+    /// since it was sent, even if the server can't be reached to confirm it.
+    async fn keep_alive_task(
+        mut lease_client: LeaseClient,
+        lease_id: i64,
+        ttl: i64,
+        done_tx: watch::Sender<bool>,
+    ) {
+        let mut deadline = Instant::now() + Duration::from_secs(ttl.numeric_cast());
+        let mut keeper = None;
+        loop {
+            let sent_at = Instant::now();
+            #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)]
+            // introduced by tokio select
+            let next = tokio::select! {
+                res = Self::renew(&mut lease_client, &mut keeper, lease_id) => match res {
+                    // the lease has been revoked or has expired on the server
+                    Ok(ttl) if ttl <= 0 => break,
+                    Ok(ttl) => {
+                        let ttl = Duration::from_secs(ttl.numeric_cast());
+                        deadline = sent_at + ttl;
+                        sent_at + ttl / 3
+                    }
+                    Err(_e) => min(Instant::now() + KEEP_ALIVE_RETRY_INTERVAL, deadline),
+                },
+                _ = sleep_until(deadline) => break,
+            };
+            sleep_until(next).await;
+        }
+        let _ignore = done_tx.send(true);
+    }
+
+    /// Sends a keep alive and returns the ttl in the response, a broken keep alive
+    /// stream is reopened on the next call
+    async fn renew(
+        lease_client: &mut LeaseClient,
+        keeper: &mut Option<(LeaseKeeper, LeaseKeepAliveStream)>,
+        lease_id: i64,
+    ) -> Result<i64> {
+        let (mut lease_keeper, mut stream) = match keeper.take() {
+            Some(keeper) => keeper,
+            None => {
+                lease_client
+                    .keep_alive(LeaseKeepAliveRequest::new(lease_id))
+                    .await?
+            }
+        };
+        lease_keeper.keep_alive()?;
+        let ttl = stream
+            .message()
+            .await?
+            .map(|resp| resp.ttl)
+            .ok_or_else(|| {
+                XlineClientError::LeaseError(String::from("keep alive stream closed"))
+            })?;
+        *keeper = Some((lease_keeper, stream));
+        Ok(ttl)
+    }
+}
+
+impl Drop for Session {
+    #[inline]
+    fn drop(&mut self) {
+        self.keep_alive.abort();
+    }
+}
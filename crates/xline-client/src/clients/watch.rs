@@ -54,7 +54,10 @@ impl WatchClient {
     ///
     /// ```no_run
     /// use xline_client::{
-    ///     types::{kv::PutRequest, watch::WatchRequest},
+    ///     types::{
+    ///         kv::PutRequest,
+    ///         watch::{WatchEvent, WatchRequest},
+    ///     },
     ///     Client, ClientOptions,
     /// };
     /// use anyhow::Result;
@@ -70,14 +73,14 @@ impl WatchClient {
     ///     let (mut watcher, mut stream) = watch_client.watch(WatchRequest::new("key1")).await?;
     ///     kv_client.put(PutRequest::new("key1", "value1")).await?;
     ///
-    ///     let resp = stream.message().await?.unwrap();
-    ///     let kv = resp.events[0].kv.as_ref().unwrap();
-    ///
-    ///     println!(
-    ///         "got key: {}, value: {}",
-    ///         String::from_utf8_lossy(&kv.key),
-    ///         String::from_utf8_lossy(&kv.value)
-    ///     );
+    ///     if let Some(WatchEvent::Events { events, .. }) = stream.message().await? {
+    ///         let kv = events[0].kv.as_ref().unwrap();
+    ///         println!(
+    ///             "got key: {}, value: {}",
+    ///             String::from_utf8_lossy(&kv.key),
+    ///             String::from_utf8_lossy(&kv.value)
+    ///         );
+    ///     }
     ///
     ///     // cancel the watch
     ///     watcher.cancel()?;
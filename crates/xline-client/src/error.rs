@@ -72,6 +72,9 @@ pub enum XlineClientError<C: CurpCommand> {
     /// Error in lease client
     #[error("Lease client error: {0}")]
     LeaseError(String),
+    /// Error in election client
+    #[error("Election client error: {0}")]
+    ElectionError(String),
     /// Request Timeout
     #[error("Request timeout")]
     Timeout,
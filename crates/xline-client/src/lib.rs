@@ -183,6 +183,7 @@ use crate::{
         MaintenanceClient, WatchClient,
     },
     error::XlineClientBuildError,
+    types::kv::ReadConsistency,
 };
 
 /// Sub-clients for each type of API
@@ -241,12 +242,27 @@ impl Client {
         let channel = Self::build_channel(addrs.clone(), options.tls_config.as_ref()).await?;
         let curp_client = Arc::new(
             CurpClientBuilder::new(options.client_config, false)
-                .tls_config(options.tls_config)
+                .tls_config(options.tls_config.clone())
                 .discover_from(addrs)
                 .await?
                 .build::<Command>()
                 .await?,
         ) as Arc<CurpClient>;
+        Self::from_transport(curp_client, channel, options).await
+    }
+
+    /// New `Client` over an established transport, used to talk to a server running
+    /// in the same process
+    ///
+    /// # Errors
+    ///
+    /// If the authentication of the user in `options` fails.
+    #[inline]
+    pub async fn from_transport(
+        curp_client: Arc<CurpClient>,
+        channel: Channel,
+        options: ClientOptions,
+    ) -> Result<Self, XlineClientBuildError> {
         let id_gen = Arc::new(lease_gen::LeaseIdGenerator::new());
 
         let token = match options.user {
@@ -262,7 +278,12 @@ impl Client {
             None => None,
         };
 
-        let kv = KvClient::new(Arc::clone(&curp_client), channel.clone(), token.clone());
+        let kv = KvClient::new(
+            Arc::clone(&curp_client),
+            channel.clone(),
+            token.clone(),
+            options.read_consistency,
+        );
         let lease = LeaseClient::new(
             Arc::clone(&curp_client),
             channel.clone(),
@@ -275,11 +296,12 @@ impl Client {
             token.clone(),
             id_gen,
         );
+        let election =
+            ElectionClient::new(Arc::clone(&curp_client), channel.clone(), token.clone());
         let auth = AuthClient::new(curp_client, channel.clone(), token.clone());
         let maintenance = MaintenanceClient::new(channel.clone(), token.clone());
         let cluster = ClusterClient::new(channel.clone(), token.clone());
         let watch = WatchClient::new(channel, token);
-        let election = ElectionClient::new();
 
         Ok(Self {
             kv,
@@ -376,6 +398,8 @@ pub struct ClientOptions {
     tls_config: Option<ClientTlsConfig>,
     /// config for the curp client
     client_config: ClientConfig,
+    /// How the ranges of the kv client are served
+    read_consistency: ReadConsistency,
 }
 
 impl ClientOptions {
@@ -391,6 +415,7 @@ impl ClientOptions {
             user,
             tls_config,
             client_config,
+            read_consistency: ReadConsistency::default(),
         }
     }
 
@@ -415,6 +440,13 @@ impl ClientOptions {
         &self.client_config
     }
 
+    /// Get `read_consistency`
+    #[inline]
+    #[must_use]
+    pub fn read_consistency(&self) -> ReadConsistency {
+        self.read_consistency
+    }
+
     /// Set `user`
     #[inline]
     #[must_use]
@@ -435,6 +467,16 @@ impl ClientOptions {
         }
     }
 
+    /// Set `read_consistency`
+    #[inline]
+    #[must_use]
+    pub fn with_read_consistency(self, read_consistency: ReadConsistency) -> Self {
+        Self {
+            read_consistency,
+            ..self
+        }
+    }
+
     /// Set `tls_config`
     #[inline]
     #[must_use]
@@ -0,0 +1,44 @@
+pub use xlineapi::KeyValue;
+
+/// The key of a leader, returned by a successful campaign
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaderKey {
+    /// The name of the election
+    pub(crate) name: Vec<u8>,
+    /// The key of the leader under the election prefix
+    pub(crate) key: Vec<u8>,
+    /// The create revision of the key, it identifies the leadership
+    pub(crate) rev: i64,
+    /// The lease the key is attached to
+    pub(crate) lease: i64,
+}
+
+impl LeaderKey {
+    /// The name of the election
+    #[inline]
+    #[must_use]
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// The key of the leader
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The create revision of the key of the leader
+    #[inline]
+    #[must_use]
+    pub fn rev(&self) -> i64 {
+        self.rev
+    }
+
+    /// The lease the key of the leader is attached to
+    #[inline]
+    #[must_use]
+    pub fn lease(&self) -> i64 {
+        self.lease
+    }
+}
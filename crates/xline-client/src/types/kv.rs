@@ -1,9 +1,26 @@
-use xlineapi::command::KeyRange;
+use std::time::Duration;
+
+use xlineapi::{command::KeyRange, PbKeyRange};
 pub use xlineapi::{
     CompactionResponse, CompareResult, CompareTarget, DeleteRangeResponse, PutResponse,
-    RangeResponse, Response, ResponseOp, SortOrder, SortTarget, TargetUnion, TxnResponse,
+    RangeResponse, Response, ResponseOp, SortOrder, SortTarget, TargetUnion, Ticket, TxnResponse,
 };
 
+/// How the ranges of a `KvClient` are served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ReadConsistency {
+    /// Ranges go through the consensus protocol and see every committed write
+    #[default]
+    Linearizable,
+    /// Ranges are served by the member the client is connected to, they may be stale
+    Serializable,
+    /// Ranges are served by the member the client is connected to once it has applied
+    /// the writes of the client, or go through the consensus protocol if it doesn't
+    /// within the duration
+    BoundedStaleness(Duration),
+}
+
 /// Request type for `Put`
 #[derive(Debug, PartialEq)]
 pub struct PutRequest {
@@ -138,12 +155,9 @@ impl RangeRequest {
     #[inline]
     #[must_use]
     pub fn with_prefix(mut self) -> Self {
-        if self.inner.key.is_empty() {
-            self.inner.key = vec![0];
-            self.inner.range_end = vec![0];
-        } else {
-            self.inner.range_end = KeyRange::get_prefix(&self.inner.key);
-        }
+        let range: PbKeyRange = KeyRange::prefix(std::mem::take(&mut self.inner.key)).into();
+        self.inner.key = range.key;
+        self.inner.range_end = range.range_end;
         self
     }
 
@@ -151,10 +165,9 @@ impl RangeRequest {
     #[inline]
     #[must_use]
     pub fn with_from_key(mut self) -> Self {
-        if self.inner.key.is_empty() {
-            self.inner.key = vec![0];
-        }
-        self.inner.range_end = vec![0];
+        let range: PbKeyRange = KeyRange::from_key(std::mem::take(&mut self.inner.key)).into();
+        self.inner.key = range.key;
+        self.inner.range_end = range.range_end;
         self
     }
 
@@ -268,6 +281,56 @@ impl RangeRequest {
         self
     }
 
+    /// Gets the key with the lowest create revision under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_first_create(self) -> Self {
+        self.with_top(SortTarget::Create, SortOrder::Ascend)
+    }
+
+    /// Gets the key with the highest create revision under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_last_create(self) -> Self {
+        self.with_top(SortTarget::Create, SortOrder::Descend)
+    }
+
+    /// Gets the lexically first key under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_first_key(self) -> Self {
+        self.with_top(SortTarget::Key, SortOrder::Ascend)
+    }
+
+    /// Gets the lexically last key under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_last_key(self) -> Self {
+        self.with_top(SortTarget::Key, SortOrder::Descend)
+    }
+
+    /// Gets the key with the lowest mod revision under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_first_rev(self) -> Self {
+        self.with_top(SortTarget::Mod, SortOrder::Ascend)
+    }
+
+    /// Gets the key with the highest mod revision under the prefix of the key
+    #[inline]
+    #[must_use]
+    pub fn with_last_rev(self) -> Self {
+        self.with_top(SortTarget::Mod, SortOrder::Descend)
+    }
+
+    /// Gets the first key under the prefix of the key in the given order of the target
+    fn with_top(self, sort_target: SortTarget, sort_order: SortOrder) -> Self {
+        self.with_prefix()
+            .with_sort_target(sort_target)
+            .with_sort_order(sort_order)
+            .with_limit(1)
+    }
+
     /// Get `key`
     #[inline]
     #[must_use]
@@ -392,12 +455,9 @@ impl DeleteRangeRequest {
     #[inline]
     #[must_use]
     pub fn with_prefix(mut self) -> Self {
-        if self.inner.key.is_empty() {
-            self.inner.key = vec![0];
-            self.inner.range_end = vec![0];
-        } else {
-            self.inner.range_end = KeyRange::get_prefix(&self.inner.key);
-        }
+        let range: PbKeyRange = KeyRange::prefix(std::mem::take(&mut self.inner.key)).into();
+        self.inner.key = range.key;
+        self.inner.range_end = range.range_end;
         self
     }
 
@@ -405,10 +465,9 @@ impl DeleteRangeRequest {
     #[inline]
     #[must_use]
     pub fn with_from_key(mut self) -> Self {
-        if self.inner.key.is_empty() {
-            self.inner.key = vec![0];
-        }
-        self.inner.range_end = vec![0];
+        let range: PbKeyRange = KeyRange::from_key(std::mem::take(&mut self.inner.key)).into();
+        self.inner.key = range.key;
+        self.inner.range_end = range.range_end;
         self
     }
 
@@ -551,7 +610,9 @@ impl Compare {
     #[inline]
     #[must_use]
     pub fn with_prefix(mut self) -> Self {
-        self.0.range_end = KeyRange::get_prefix(&self.0.key);
+        let range: PbKeyRange = KeyRange::prefix(std::mem::take(&mut self.0.key)).into();
+        self.0.key = range.key;
+        self.0.range_end = range.range_end;
         self
     }
 }
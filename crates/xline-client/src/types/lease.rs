@@ -1,18 +1,25 @@
+use std::sync::Arc;
+
 use futures::channel::mpsc::Sender;
+use parking_lot::Mutex;
+use tonic::Streaming;
 pub use xlineapi::{
     LeaseGrantResponse, LeaseKeepAliveResponse, LeaseLeasesResponse, LeaseRevokeResponse,
     LeaseStatus, LeaseTimeToLiveResponse,
 };
 
-use crate::error::{Result, XlineClientError};
+use crate::{
+    clients::LeaseClient,
+    error::{Result, XlineClientError},
+};
 
 /// The lease keep alive handle.
 #[derive(Debug)]
 pub struct LeaseKeeper {
     /// lease id
     id: i64,
-    /// sender to send keep alive request
-    sender: Sender<xlineapi::LeaseKeepAliveRequest>,
+    /// sender to send keep alive request, replaced when the stream is reopened
+    sender: Arc<Mutex<Sender<xlineapi::LeaseKeepAliveRequest>>>,
 }
 
 impl LeaseKeeper {
@@ -20,7 +27,10 @@ impl LeaseKeeper {
     #[inline]
     #[must_use]
     pub fn new(id: i64, sender: Sender<xlineapi::LeaseKeepAliveRequest>) -> Self {
-        Self { id, sender }
+        Self {
+            id,
+            sender: Arc::new(Mutex::new(sender)),
+        }
     }
 
     /// The lease id which user want to keep alive.
@@ -38,11 +48,61 @@ impl LeaseKeeper {
     #[inline]
     pub fn keep_alive(&mut self) -> Result<()> {
         self.sender
+            .lock()
             .try_send(LeaseKeepAliveRequest::new(self.id).into())
             .map_err(|e| XlineClientError::LeaseError(e.to_string()))
     }
 }
 
+/// The stream of lease keep alive responses.
+///
+/// The underlying gRPC stream is bound to one server and is broken when that server
+/// stops serving it, e.g. after a leader change. It's then reopened transparently and
+/// the keeper sends its later requests to the new stream.
+#[derive(Debug)]
+pub struct LeaseKeepAliveStream {
+    /// The lease client to reopen the stream
+    client: LeaseClient,
+    /// lease id
+    id: i64,
+    /// The sender shared with the keeper
+    sender: Arc<Mutex<Sender<xlineapi::LeaseKeepAliveRequest>>>,
+    /// The response stream
+    inner: Streaming<LeaseKeepAliveResponse>,
+}
+
+impl LeaseKeepAliveStream {
+    /// Creates a new `LeaseKeepAliveStream` that shares the sender of the keeper
+    pub(crate) fn new(
+        client: LeaseClient,
+        keeper: &LeaseKeeper,
+        inner: Streaming<LeaseKeepAliveResponse>,
+    ) -> Self {
+        Self {
+            client,
+            id: keeper.id,
+            sender: Arc::clone(&keeper.sender),
+            inner,
+        }
+    }
+
+    /// Receives the next keep alive response, the stream is reopened once if it's broken
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the stream is broken and can't be reopened
+    #[inline]
+    pub async fn message(&mut self) -> Result<Option<LeaseKeepAliveResponse>> {
+        if let Ok(Some(resp)) = self.inner.message().await {
+            return Ok(Some(resp));
+        }
+        let (sender, inner, resp) = self.client.open_keep_alive(self.id).await?;
+        *self.sender.lock() = sender;
+        self.inner = inner;
+        Ok(Some(resp))
+    }
+}
+
 /// Request for `LeaseGrant`
 #[derive(Debug, PartialEq)]
 pub struct LeaseGrantRequest {
@@ -96,7 +156,10 @@ impl LeaseRevokeRequest {
     #[must_use]
     pub fn new(id: i64) -> Self {
         Self {
-            inner: xlineapi::LeaseRevokeRequest { id },
+            inner: xlineapi::LeaseRevokeRequest {
+                id,
+                ..Default::default()
+            },
         }
     }
 }
@@ -164,6 +227,17 @@ impl LeaseTimeToLiveRequest {
         self.inner.keys = keys;
         self
     }
+
+    /// `serializable` is true to serve the request from the local state of the member
+    /// that receives it without consensus. A follower reports the latest remaining ttl
+    /// checkpointed by the leader, or the granted ttl if there is none. This is an Xline
+    /// extension.
+    #[inline]
+    #[must_use]
+    pub fn with_serializable(mut self, serializable: bool) -> Self {
+        self.inner.serializable = serializable;
+        self
+    }
 }
 
 impl From<LeaseTimeToLiveRequest> for xlineapi::LeaseTimeToLiveRequest {
@@ -1 +1,4 @@
-pub use xlineapi::SnapshotResponse;
+pub use xlineapi::{
+    CompactionExemptionRequest, CompactionExemptionResponse, SnapshotResponse,
+    TimeToRevisionRequest, TimeToRevisionResponse,
+};
@@ -2,6 +2,8 @@
 pub mod auth;
 /// Cluster type definitions.
 pub mod cluster;
+/// Election type definitions.
+pub mod election;
 /// Kv type definitions.
 pub mod kv;
 /// Lease type definitions
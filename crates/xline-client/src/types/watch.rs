@@ -1,7 +1,4 @@
-use std::{
-    fmt::Debug,
-    ops::{Deref, DerefMut},
-};
+use std::{collections::HashMap, fmt::Debug, time::Duration};
 
 use futures::channel::mpsc::Sender;
 use xlineapi::{command::KeyRange, RequestUnion, WatchCancelRequest, WatchProgressRequest};
@@ -175,6 +172,17 @@ impl WatchRequest {
         self
     }
 
+    /// Set `progress_notify` with a progress notify interval of this watcher instead of the
+    /// server-wide one, the server bounds it below to a minimum. This is an Xline extension.
+    #[inline]
+    #[must_use]
+    pub fn with_progress_notify_interval(mut self, interval: Duration) -> Self {
+        self.inner.progress_notify = true;
+        self.inner.progress_notify_interval_ms =
+            u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+        self
+    }
+
     /// `filters` filter the events on server side before it sends back to the watcher.
     #[inline]
     #[must_use]
@@ -203,6 +211,27 @@ impl WatchRequest {
         self
     }
 
+    /// If `coalesce` is set, while the stream is blocked by flow control, events of the
+    /// same key are coalesced to the latest one. Responses of coalesced events have
+    /// `coalesced` set along with the covered revision range. This is an Xline extension.
+    #[inline]
+    #[must_use]
+    pub const fn with_coalesce(mut self) -> Self {
+        self.inner.coalesce = true;
+        self
+    }
+
+    /// If `send_initial_state` is set, the current kvs in the range are sent as PUT events
+    /// with their mod revisions after the watcher is created, followed by a progress
+    /// notification at the revision of the snapshot, then the events after it. It can't
+    /// be set along with a start revision. This is an Xline extension.
+    #[inline]
+    #[must_use]
+    pub const fn with_initial_state(mut self) -> Self {
+        self.inner.send_initial_state = true;
+        self
+    }
+
     /// fragment enables splitting large revisions into multiple watch responses.
     #[inline]
     #[must_use]
@@ -239,11 +268,46 @@ impl From<WatchFilterType> for i32 {
     }
 }
 
-/// Watch response stream
+/// An item of a watch stream
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum WatchEvent {
+    /// Events of the watched keys
+    Events {
+        /// The watcher the events are sent to
+        watch_id: i64,
+        /// The events
+        events: Vec<Event>,
+    },
+    /// Every event up to the revision has been delivered. The revision is safe to
+    /// persist, the watch is resumed from it with a start revision right after it.
+    Progress {
+        /// The watcher the progress is of, -1 for all the watchers of the stream
+        watch_id: i64,
+        /// The revision
+        revision: i64,
+    },
+    /// The watcher is canceled by the server
+    Canceled {
+        /// The canceled watcher
+        watch_id: i64,
+        /// Why the watcher is canceled
+        reason: String,
+        /// The compacted revision if the start revision of the watcher is compacted, 0 otherwise
+        compact_revision: i64,
+    },
+}
+
+/// Watch stream
+///
+/// Besides the events, it keeps the revision every event up to which has been delivered
+/// of each watcher, see [`WatchStreaming::progress_revision`].
 #[derive(Debug)]
 pub struct WatchStreaming {
     /// Inner tonic stream
     inner: tonic::Streaming<WatchResponse>,
+    /// Progress of the watchers of the stream
+    progress: Progress,
     /// A sender of WatchResponse, used to keep response stream alive
     _sender: Sender<xlineapi::WatchRequest>,
 }
@@ -258,23 +322,217 @@ impl WatchStreaming {
     ) -> Self {
         Self {
             inner,
+            progress: Progress::default(),
             _sender: sender,
         }
     }
-}
 
-impl Deref for WatchStreaming {
-    type Target = tonic::Streaming<WatchResponse>;
+    /// Receives the next item of the stream, `None` if the stream is closed.
+    ///
+    /// The responses creating the watchers added by [`Watcher::watch`] are skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the stream is broken
+    #[inline]
+    pub async fn message(&mut self) -> Result<Option<WatchEvent>> {
+        while let Some(resp) = self.inner.message().await? {
+            if let Some(event) = watch_event(resp, &mut self.progress) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
 
+    /// The revision every event of a watcher up to which has been delivered, 0 if it's
+    /// unknown yet.
+    ///
+    /// It's advanced by the progress notifications and by the events of complete
+    /// revisions, a broken watch is resumed from the revision right after it.
     #[inline]
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+    #[must_use]
+    pub fn progress_revision(&self, watch_id: i64) -> i64 {
+        self.progress.revision(watch_id)
     }
 }
 
-impl DerefMut for WatchStreaming {
-    #[inline]
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
+/// Progress of the watchers of a stream
+#[derive(Debug, Default)]
+struct Progress {
+    /// Every event of a watcher up to the revision has been delivered
+    watchers: HashMap<i64, i64>,
+    /// Every event of all the watchers up to the revision has been delivered
+    all: i64,
+}
+
+impl Progress {
+    /// Advance the progress of a watcher, or of all the watchers with `watch_id` -1
+    fn advance(&mut self, watch_id: i64, revision: i64) {
+        let progress = if watch_id == -1 {
+            &mut self.all
+        } else {
+            self.watchers.entry(watch_id).or_default()
+        };
+        *progress = (*progress).max(revision);
+    }
+
+    /// The progress revision of a watcher
+    fn revision(&self, watch_id: i64) -> i64 {
+        self.watchers
+            .get(&watch_id)
+            .map_or(self.all, |&revision| revision.max(self.all))
+    }
+}
+
+/// Convert a response into an item of the stream and advance the progress of its
+/// watcher, `None` if the response is not an item
+fn watch_event(resp: WatchResponse, progress: &mut Progress) -> Option<WatchEvent> {
+    let watch_id = resp.watch_id;
+    if resp.canceled {
+        let _prev = progress.watchers.remove(&watch_id);
+        return Some(WatchEvent::Canceled {
+            watch_id,
+            reason: resp.cancel_reason,
+            compact_revision: resp.compact_revision,
+        });
+    }
+    if resp.created {
+        return None;
+    }
+    if resp.events.is_empty() {
+        let revision = resp.header.map_or(0, |header| header.revision);
+        progress.advance(watch_id, revision);
+        return Some(WatchEvent::Progress { watch_id, revision });
+    }
+    // the rest of a fragmented revision is still on the way
+    if !resp.fragment {
+        let revision = resp
+            .events
+            .last()
+            .and_then(|event| event.kv.as_ref())
+            .map_or(0, |kv| kv.mod_revision);
+        progress.advance(watch_id, revision);
+    }
+    Some(WatchEvent::Events {
+        watch_id,
+        events: resp.events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use xlineapi::ResponseHeader;
+
+    use super::*;
+
+    fn put_event(key: &str, mod_revision: i64) -> Event {
+        Event {
+            kv: Some(KeyValue {
+                key: key.into(),
+                mod_revision,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn header(revision: i64) -> Option<ResponseHeader> {
+        Some(ResponseHeader {
+            revision,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn responses_should_be_converted_into_typed_events() {
+        let mut progress = Progress::default();
+        let created = WatchResponse {
+            watch_id: 1,
+            created: true,
+            header: header(1),
+            ..Default::default()
+        };
+        assert_eq!(watch_event(created, &mut progress), None);
+        assert_eq!(progress.revision(1), 0);
+
+        let events = vec![put_event("a", 2), put_event("b", 3)];
+        let resp = WatchResponse {
+            watch_id: 1,
+            header: header(3),
+            events: events.clone(),
+            ..Default::default()
+        };
+        assert_eq!(
+            watch_event(resp, &mut progress),
+            Some(WatchEvent::Events {
+                watch_id: 1,
+                events
+            })
+        );
+        assert_eq!(progress.revision(1), 3);
+        // the progress of a watcher doesn't advance the others
+        assert_eq!(progress.revision(2), 0);
+
+        // a fragment doesn't complete its revision
+        let resp = WatchResponse {
+            watch_id: 1,
+            header: header(4),
+            events: vec![put_event("c", 4)],
+            fragment: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            watch_event(resp, &mut progress),
+            Some(WatchEvent::Events { watch_id: 1, .. })
+        ));
+        assert_eq!(progress.revision(1), 3);
+
+        let resp = WatchResponse {
+            watch_id: 2,
+            header: header(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            watch_event(resp, &mut progress),
+            Some(WatchEvent::Progress {
+                watch_id: 2,
+                revision: 5
+            })
+        );
+        assert_eq!(progress.revision(1), 3);
+        assert_eq!(progress.revision(2), 5);
+
+        let resp = WatchResponse {
+            watch_id: -1,
+            header: header(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            watch_event(resp, &mut progress),
+            Some(WatchEvent::Progress {
+                watch_id: -1,
+                revision: 4
+            })
+        );
+        assert_eq!(progress.revision(1), 4);
+        assert_eq!(progress.revision(2), 5);
+
+        let canceled = WatchResponse {
+            watch_id: 2,
+            header: header(6),
+            canceled: true,
+            cancel_reason: "compacted".to_owned(),
+            compact_revision: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            watch_event(canceled, &mut progress),
+            Some(WatchEvent::Canceled {
+                watch_id: 2,
+                reason: "compacted".to_owned(),
+                compact_revision: 5,
+            })
+        );
+        assert_eq!(progress.revision(1), 4);
     }
 }
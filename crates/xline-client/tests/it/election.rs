@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use test_macros::abort_on_panic;
+use xline_client::{clients::ElectionObserver, error::Result, types::lease::LeaseGrantRequest};
+
+use super::common::get_cluster_client;
+
+/// Assert the observer emits the values in order, and nothing more
+async fn assert_emissions(observer: &mut ElectionObserver, values: &[&str]) -> Result<()> {
+    for value in values {
+        let kv = tokio::time::timeout(Duration::from_secs(5), observer.message())
+            .await
+            .expect("observer should emit the next leader")?;
+        assert_eq!(kv.value, value.as_bytes());
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), observer.message())
+            .await
+            .is_err(),
+        "observer should not emit more than {values:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn observe_should_emit_each_proclaim_once() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let election = client.election_client();
+    let lease = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+
+    let leader = election.campaign("election", "v0", lease).await?;
+    // the current leader is emitted right after subscribing
+    let mut observer = election.observe("election").await?;
+    let values: Vec<_> = (1..10).map(|i| format!("v{i}")).collect();
+    for value in &values {
+        election.proclaim(&leader, value.as_str()).await?;
+    }
+
+    let expected: Vec<_> = ["v0"]
+        .into_iter()
+        .chain(values.iter().map(String::as_str))
+        .collect();
+    assert_emissions(&mut observer, &expected).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn observe_should_follow_leadership_handoff() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let election = client.election_client();
+    let mut leases = vec![];
+    for _ in 0..3 {
+        leases.push(
+            client
+                .lease_client()
+                .grant(LeaseGrantRequest::new(60))
+                .await?
+                .id,
+        );
+    }
+
+    // nothing is emitted before there's a leader
+    let mut observer = election.observe("election").await?;
+    assert_emissions(&mut observer, &[]).await?;
+
+    let first = election.campaign("election", "first", leases[0]).await?;
+    let election_c = election.clone();
+    let second_lease = leases[1];
+    let second = tokio::spawn(async move {
+        election_c
+            .campaign("election", "second", second_lease)
+            .await
+    });
+    // the second candidate waits for the first one to resign
+    assert_emissions(&mut observer, &["first"]).await?;
+
+    election.resign(&first).await?;
+    let second = second.await.unwrap()?;
+    assert_emissions(&mut observer, &["second"]).await?;
+
+    // the gap without leader emits nothing
+    election.resign(&second).await?;
+    assert_emissions(&mut observer, &[]).await?;
+    assert!(election.leader("election").await?.is_none());
+
+    let _third = election.campaign("election", "third", leases[2]).await?;
+    assert_emissions(&mut observer, &["third"]).await
+}
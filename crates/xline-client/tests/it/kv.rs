@@ -1,12 +1,19 @@
 //! The following tests are originally from `etcd-client`
+use std::{collections::BTreeMap, time::Duration};
+
 use test_macros::abort_on_panic;
 use xline_client::{
     error::Result,
-    types::kv::{
-        CompactionRequest, Compare, CompareResult, DeleteRangeRequest, PutRequest, RangeRequest,
-        TxnOp, TxnRequest,
+    types::{
+        kv::{
+            CompactionRequest, Compare, CompareResult, DeleteRangeRequest, PutRequest,
+            RangeRequest, ReadConsistency, TxnOp, TxnRequest,
+        },
+        watch::{EventType, WatchEvent},
     },
+    Client, ClientOptions,
 };
+use xline_test_utils::Cluster;
 
 use super::common::get_cluster_client;
 
@@ -27,7 +34,7 @@ async fn put_should_success_in_normal_path() -> Result<()> {
         assert!(prev_kv.is_some());
         let prev_kv = prev_kv.unwrap();
         assert_eq!(prev_kv.key, b"put");
-        assert_eq!(prev_kv.value, b"123");
+        assert_eq!(prev_kv.value, b"123".as_slice());
     }
 
     // overwrite again with prev key
@@ -38,12 +45,108 @@ async fn put_should_success_in_normal_path() -> Result<()> {
         assert!(prev_kv.is_some());
         let prev_kv = prev_kv.unwrap();
         assert_eq!(prev_kv.key, b"put");
-        assert_eq!(prev_kv.value, b"456");
+        assert_eq!(prev_kv.value, b"456".as_slice());
+    }
+
+    Ok(())
+}
+
+/// A kv client connected to a follower only, its writes are proposed to the leader
+async fn follower_kv_client(
+    cluster: &Cluster,
+    read_consistency: ReadConsistency,
+) -> xline_client::clients::KvClient {
+    // member 0 is the initial leader
+    let options = ClientOptions::default().with_read_consistency(read_consistency);
+    Client::connect([cluster.get_client_url(2)], options)
+        .await
+        .unwrap()
+        .kv_client()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn bounded_staleness_range_should_see_own_writes_on_follower() -> Result<()> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = follower_kv_client(
+        &cluster,
+        ReadConsistency::BoundedStaleness(Duration::from_secs(3)),
+    )
+    .await;
+
+    for i in 0..20 {
+        let value = i.to_string();
+        let put_rev = client
+            .put(PutRequest::new("ryw", value.as_str()))
+            .await?
+            .header
+            .unwrap()
+            .revision;
+        let resp = client.range(RangeRequest::new("ryw")).await?;
+        assert_eq!(resp.kvs[0].value, value.as_bytes());
+        assert!(resp.header.unwrap().revision >= put_rev);
+    }
+
+    let _ignore = client.delete(DeleteRangeRequest::new("ryw")).await?;
+    assert!(client.range(RangeRequest::new("ryw")).await?.kvs.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn bounded_staleness_range_should_fall_back_to_linearizable() -> Result<()> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    // no member answers within a zero wait
+    let client =
+        follower_kv_client(&cluster, ReadConsistency::BoundedStaleness(Duration::ZERO)).await;
+
+    for i in 0..5 {
+        let value = i.to_string();
+        let _ignore = client.put(PutRequest::new("ryw", value.as_str())).await?;
+        let resp = client.range(RangeRequest::new("ryw")).await?;
+        assert_eq!(resp.kvs[0].value, value.as_bytes());
     }
 
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn async_puts_should_be_applied_in_order() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+
+    let mut tickets = vec![];
+    for i in 0..5 {
+        let request = PutRequest::new("async", i.to_string()).with_prev_kv(true);
+        tickets.push(client.put_async(request).await?);
+    }
+
+    let mut last_revision = 0;
+    for (i, ticket) in tickets.into_iter().enumerate() {
+        let resp = client.wait_applied(ticket.clone()).await?;
+        let revision = resp.header.unwrap().revision;
+        assert!(revision > last_revision);
+        last_revision = revision;
+        let prev_value = resp.prev_kv.map(|kv| kv.value);
+        let expected = i.checked_sub(1).map(|prev| prev.to_string().into_bytes());
+        assert_eq!(prev_value, expected);
+
+        // the result could be fetched again
+        let refetched = client.wait_applied(ticket).await?;
+        assert_eq!(refetched.header.unwrap().revision, revision);
+    }
+
+    let resp = client.range(RangeRequest::new("async")).await?;
+    assert_eq!(resp.kvs[0].value, b"4");
+    assert_eq!(resp.kvs[0].mod_revision, last_revision);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
 async fn range_should_fetches_previously_put_keys() -> Result<()> {
@@ -62,7 +165,7 @@ async fn range_should_fetches_previously_put_keys() -> Result<()> {
         assert!(!resp.more);
         assert_eq!(resp.kvs.len(), 1);
         assert_eq!(resp.kvs[0].key, b"get11");
-        assert_eq!(resp.kvs[0].value, b"11");
+        assert_eq!(resp.kvs[0].value, b"11".as_slice());
     }
 
     // get from key
@@ -73,9 +176,9 @@ async fn range_should_fetches_previously_put_keys() -> Result<()> {
         assert!(resp.more);
         assert_eq!(resp.kvs.len(), 2);
         assert_eq!(resp.kvs[0].key, b"get11");
-        assert_eq!(resp.kvs[0].value, b"11");
+        assert_eq!(resp.kvs[0].value, b"11".as_slice());
         assert_eq!(resp.kvs[1].key, b"get20");
-        assert_eq!(resp.kvs[1].value, b"20");
+        assert_eq!(resp.kvs[1].value, b"20".as_slice());
     }
 
     // get prefix keys
@@ -87,9 +190,9 @@ async fn range_should_fetches_previously_put_keys() -> Result<()> {
         assert!(!resp.more);
         assert_eq!(resp.kvs.len(), 2);
         assert_eq!(resp.kvs[0].key, b"get10");
-        assert_eq!(resp.kvs[0].value, b"10");
+        assert_eq!(resp.kvs[0].value, b"10".as_slice());
         assert_eq!(resp.kvs[1].key, b"get11");
-        assert_eq!(resp.kvs[1].value, b"11");
+        assert_eq!(resp.kvs[1].value, b"11".as_slice());
     }
 
     Ok(())
@@ -198,14 +301,14 @@ async fn txn_should_execute_as_expected() -> Result<()> {
 
         match op_responses[0].response.as_ref().unwrap() {
             xlineapi::Response::ResponsePut(resp) => {
-                assert_eq!(resp.prev_kv.as_ref().unwrap().value, b"01")
+                assert_eq!(resp.prev_kv.as_ref().unwrap().value, b"01".as_slice())
             }
             _ => panic!("expect put response)"),
         }
 
         let resp = client.range(RangeRequest::new("txn01")).await?;
         assert_eq!(resp.kvs[0].key, b"txn01");
-        assert_eq!(resp.kvs[0].value, b"02");
+        assert_eq!(resp.kvs[0].value, b"02".as_slice());
     }
 
     // transaction 2
@@ -225,7 +328,7 @@ async fn txn_should_execute_as_expected() -> Result<()> {
 
         match op_responses[0].response.as_ref().unwrap() {
             xlineapi::Response::ResponseRange(resp) => {
-                assert_eq!(resp.kvs[0].value, b"02")
+                assert_eq!(resp.kvs[0].value, b"02".as_slice())
             }
             _ => panic!("expect range response)"),
         }
@@ -247,11 +350,11 @@ async fn compact_should_remove_previous_revision() -> Result<()> {
     let rev0_resp = client
         .range(RangeRequest::new("compact").with_revision(2))
         .await?;
-    assert_eq!(rev0_resp.kvs[0].value, b"0");
+    assert_eq!(rev0_resp.kvs[0].value, b"0".as_slice());
     let rev1_resp = client
         .range(RangeRequest::new("compact").with_revision(3))
         .await?;
-    assert_eq!(rev1_resp.kvs[0].value, b"1");
+    assert_eq!(rev1_resp.kvs[0].value, b"1".as_slice());
 
     client.compact(CompactionRequest::new(3)).await?;
 
@@ -266,7 +369,76 @@ async fn compact_should_remove_previous_revision() -> Result<()> {
     let rev1_resp = client
         .range(RangeRequest::new("compact").with_revision(3))
         .await?;
-    assert_eq!(rev1_resp.kvs[0].value, b"1");
+    assert_eq!(rev1_resp.kvs[0].value, b"1".as_slice());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn get_prefix_and_watch_should_not_miss_or_duplicate_events() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+    for i in 0..10 {
+        client
+            .put(PutRequest::new(format!("gaw/{i}"), "init"))
+            .await?;
+    }
+
+    let writer = tokio::spawn({
+        let client = client.clone();
+        async move {
+            let mut last_revision = 0;
+            for i in 0..50 {
+                let resp = if i % 5 == 0 {
+                    client
+                        .delete(DeleteRangeRequest::new(format!("gaw/{}", i % 10)))
+                        .await?
+                        .header
+                } else {
+                    client
+                        .put(PutRequest::new(format!("gaw/{}", i % 10), i.to_string()))
+                        .await?
+                        .header
+                };
+                last_revision = resp.unwrap().revision;
+            }
+            Result::Ok(last_revision)
+        }
+    });
+
+    let (kvs, mut watcher, mut stream) = client.get_prefix_and_watch("gaw/").await?;
+    let last_revision = writer.await.unwrap()?;
+    let mut view: BTreeMap<_, _> = kvs.into_iter().map(|kv| (kv.key, kv.value)).collect();
+    let mut revision = 0;
+    while revision < last_revision {
+        let WatchEvent::Events { events, .. } = stream.message().await?.unwrap() else {
+            continue;
+        };
+        for event in events {
+            let kv = event.kv.as_ref().unwrap();
+            assert!(kv.mod_revision > revision, "event is duplicated");
+            revision = kv.mod_revision;
+            match event.r#type() {
+                EventType::Put => {
+                    let _prev = view.insert(kv.key.clone(), kv.value.clone());
+                }
+                EventType::Delete => {
+                    let _prev = view.remove(&kv.key);
+                }
+            }
+        }
+    }
+    watcher.cancel()?;
+
+    let snapshot: BTreeMap<_, _> = client
+        .range(RangeRequest::new("gaw/").with_prefix())
+        .await?
+        .kvs
+        .into_iter()
+        .map(|kv| (kv.key, kv.value))
+        .collect();
+    assert_eq!(view, snapshot);
 
     Ok(())
 }
@@ -1,7 +1,12 @@
+use std::time::{Duration, SystemTime};
+
 use xline_client::{
     error::Result,
-    types::lease::{
-        LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest, LeaseTimeToLiveRequest,
+    types::{
+        kv::{PutRequest, RangeRequest},
+        lease::{
+            LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest, LeaseTimeToLiveRequest,
+        },
     },
 };
 
@@ -103,3 +108,85 @@ async fn leases_should_include_granted_in_normal_path() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attached_key_should_be_deleted_after_keeper_stops() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut lease_client = client.lease_client();
+    let kv_client = client.kv_client();
+
+    let resp = lease_client.grant(LeaseGrantRequest::new(2)).await?;
+    assert_ne!(resp.id, 0);
+    let id = resp.id;
+    let _resp = kv_client
+        .put(PutRequest::new("lease_key", "value").with_lease(id))
+        .await?;
+
+    let (mut keeper, mut stream) = lease_client
+        .keep_alive(LeaseKeepAliveRequest::new(id))
+        .await?;
+    let keep_alive = tokio::spawn(async move {
+        loop {
+            let _ignore = keeper.keep_alive();
+            let _ignore = stream.message().await;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    });
+
+    // the lease outlives its ttl while it's kept alive
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let resp = kv_client.range(RangeRequest::new("lease_key")).await?;
+    assert_eq!(resp.kvs.len(), 1);
+
+    keep_alive.abort();
+    let mut deleted = false;
+    for _ in 0..20 {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let resp = kv_client.range(RangeRequest::new("lease_key")).await?;
+        if resp.kvs.is_empty() {
+            deleted = true;
+            break;
+        }
+    }
+    assert!(deleted, "the key should be deleted once the lease expires");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grant_until_should_convert_deadline_to_ttl() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut client = client.lease_client();
+
+    let resp = client
+        .grant_until(SystemTime::now() + Duration::from_secs(60))
+        .await?;
+    assert!((59..=60).contains(&resp.ttl), "ttl {}", resp.ttl);
+    let id = resp.id;
+    let granted_ttl = resp.ttl;
+
+    // a keep alive renews the lease to the converted ttl
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let (mut keeper, mut stream) = client.keep_alive(LeaseKeepAliveRequest::new(id)).await?;
+    keeper.keep_alive()?;
+    let resp = stream.message().await?.unwrap();
+    assert_eq!(resp.ttl, granted_ttl);
+
+    client.revoke(LeaseRevokeRequest::new(id)).await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grant_until_should_reject_past_deadline() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut client = client.lease_client();
+
+    let res = client
+        .grant_until(SystemTime::now() - Duration::from_secs(1))
+        .await;
+    assert!(res.is_err());
+    assert!(client.leases().await?.leases.is_empty());
+
+    Ok(())
+}
@@ -4,6 +4,7 @@ use test_macros::abort_on_panic;
 use xline_client::{
     error::Result,
     types::lock::{LockRequest, UnlockRequest},
+    Client, ClientOptions,
 };
 
 use super::common::get_cluster_client;
@@ -58,13 +59,19 @@ async fn lock_contention_should_occur_when_acquire_by_two() -> Result<()> {
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
 async fn lock_should_timeout_when_ttl_is_set() -> Result<()> {
-    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let (cluster, client) = get_cluster_client().await.unwrap();
     let client = client.lock_client();
 
-    let _resp = client
+    // the session of the lock stops keeping its lease alive once the holder is dropped
+    let holder = Client::connect(cluster.all_client_addrs(), ClientOptions::default())
+        .await
+        .unwrap()
+        .lock_client();
+    let _resp = holder
         .lock(LockRequest::new("lock-test").with_ttl(1))
         .await
         .unwrap();
+    drop(holder);
 
     let resp = tokio::time::timeout(
         Duration::from_secs(2),
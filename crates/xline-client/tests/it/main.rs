@@ -1,7 +1,9 @@
 mod auth;
 mod common;
+mod election;
 mod kv;
 mod lease;
 mod lock;
 mod maintenance;
+mod session;
 mod watch;
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use test_macros::abort_on_panic;
+use xline_client::{
+    clients::Session,
+    error::Result,
+    types::lease::{LeaseRevokeRequest, LeaseTimeToLiveRequest},
+};
+
+use super::common::get_cluster_client;
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn session_should_keep_lease_alive_until_closed() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut client = client.lease_client();
+
+    let session = Session::new(client.clone(), 2).await?;
+    let lease_id = session.lease_id();
+
+    // the lease outlives its ttl while the session is open
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let resp = client
+        .time_to_live(LeaseTimeToLiveRequest::new(lease_id))
+        .await?;
+    assert!(resp.ttl > 0);
+
+    session.close().await?;
+
+    let resp = client.leases().await?;
+    assert!(resp.leases.iter().all(|status| status.id != lease_id));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn session_should_be_done_when_lease_is_revoked() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut client = client.lease_client();
+
+    let session = Session::new(client.clone(), 60).await?;
+    client
+        .revoke(LeaseRevokeRequest::new(session.lease_id()))
+        .await?;
+
+    tokio::time::timeout(Duration::from_secs(30), session.done())
+        .await
+        .expect("session should be done after its lease is revoked");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn session_should_be_done_when_cluster_is_unreachable() -> Result<()> {
+    let (mut cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.lease_client();
+
+    let session = Session::new(client, 2).await?;
+    cluster.stop().await;
+
+    tokio::time::timeout(Duration::from_secs(5), session.done())
+        .await
+        .expect("session should be done once its ttl passes without a keep alive");
+
+    Ok(())
+}
@@ -3,7 +3,7 @@ use xline_client::{
     error::Result,
     types::{
         kv::PutRequest,
-        watch::{EventType, WatchRequest},
+        watch::{EventType, WatchEvent, WatchRequest},
     },
 };
 
@@ -19,20 +19,24 @@ async fn watch_should_receive_consistent_events() -> Result<()> {
 
     kv_client.put(PutRequest::new("watch01", "01")).await?;
 
-    let resp = stream.message().await?.unwrap();
-    assert_eq!(resp.watch_id, watcher.watch_id());
-    assert_eq!(resp.events.len(), 1);
+    let Some(WatchEvent::Events { events, .. }) = stream.message().await? else {
+        panic!("the put event should be received");
+    };
+    assert_eq!(events.len(), 1);
 
-    let kv = resp.events[0].kv.as_ref().unwrap();
+    let kv = events[0].kv.as_ref().unwrap();
     assert_eq!(kv.key, b"watch01");
-    assert_eq!(kv.value, b"01");
-    assert_eq!(resp.events[0].r#type(), EventType::Put);
+    assert_eq!(kv.value, b"01".as_slice());
+    assert_eq!(events[0].r#type(), EventType::Put);
+    assert_eq!(
+        stream.progress_revision(watcher.watch_id()),
+        kv.mod_revision
+    );
 
     watcher.cancel()?;
 
-    let resp = stream.message().await?.unwrap();
-    assert_eq!(resp.watch_id, watcher.watch_id());
-    assert!(resp.canceled);
+    let event = stream.message().await?.unwrap();
+    assert!(matches!(event, WatchEvent::Canceled { .. }));
 
     Ok(())
 }
@@ -48,13 +52,61 @@ async fn watch_stream_should_work_after_watcher_dropped() -> Result<()> {
 
     kv_client.put(PutRequest::new("watch01", "01")).await?;
 
-    let resp = stream.message().await?.unwrap();
-    assert_eq!(resp.events.len(), 1);
+    let Some(WatchEvent::Events { events, .. }) = stream.message().await? else {
+        panic!("the put event should be received");
+    };
+    assert_eq!(events.len(), 1);
 
-    let kv = resp.events[0].kv.as_ref().unwrap();
+    let kv = events[0].kv.as_ref().unwrap();
     assert_eq!(kv.key, b"watch01");
-    assert_eq!(kv.value, b"01");
-    assert_eq!(resp.events[0].r#type(), EventType::Put);
+    assert_eq!(kv.value, b"01".as_slice());
+    assert_eq!(events[0].r#type(), EventType::Put);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn progress_should_be_a_resume_point() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut watch_client = client.watch_client();
+    let kv_client = client.kv_client();
+
+    let (mut watcher, mut stream) = watch_client.watch(WatchRequest::new("watch01")).await?;
+    kv_client.put(PutRequest::new("watch01", "01")).await?;
+    kv_client.put(PutRequest::new("watch02", "02")).await?;
+    let Some(WatchEvent::Events { .. }) = stream.message().await? else {
+        panic!("the put event should be received");
+    };
+
+    // the progress follows the revisions dispatched to the watcher
+    let mut revision = 0;
+    for _ in 0..10 {
+        watcher.request_progress()?;
+        let Some(WatchEvent::Progress { revision: r, .. }) = stream.message().await? else {
+            panic!("the progress should be received");
+        };
+        revision = r;
+        if revision >= 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(
+        revision >= 3,
+        "the progress {revision} should cover both puts"
+    );
+    assert_eq!(stream.progress_revision(watcher.watch_id()), revision);
+
+    // nothing is missed or replayed by a watch resumed from the progress
+    let (_watcher, mut resumed) = watch_client
+        .watch(WatchRequest::new("watch01").with_start_revision(revision + 1))
+        .await?;
+    kv_client.put(PutRequest::new("watch01", "03")).await?;
+    let Some(WatchEvent::Events { events, .. }) = resumed.message().await? else {
+        panic!("the put event should be received");
+    };
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kv.as_ref().unwrap().value, b"03".as_slice());
 
     Ok(())
 }
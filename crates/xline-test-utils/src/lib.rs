@@ -10,8 +10,10 @@ use tokio::{
 };
 use tonic::transport::ClientTlsConfig;
 use utils::config::{
-    default_quota, AuthConfig, ClusterConfig, CompactConfig, EngineConfig, InitialClusterState,
-    LogConfig, MetricsConfig, StorageConfig, TlsConfig, TraceConfig, XlineServerConfig,
+    default_journal_max_age, default_journal_max_size, default_quota, AuthConfig, ClusterConfig,
+    ClusterConfigBuilder, CompactConfig, EncryptionConfig, EngineConfig, InitialClusterState,
+    JournalConfig, LogConfig, MetricsConfig, StorageConfig, TlsConfig, TraceConfig,
+    XlineServerConfig,
 };
 use xline::server::XlineServer;
 use xline_client::types::auth::{
@@ -112,7 +114,7 @@ impl Cluster {
                 XlineServer::new(
                     config.cluster().clone(),
                     config.storage().clone(),
-                    *config.compact(),
+                    config.compact().clone(),
                     config.auth().clone(),
                     config.tls().clone(),
                 )
@@ -179,7 +181,7 @@ impl Cluster {
         let server = XlineServer::new(
             config.cluster().clone(),
             config.storage().clone(),
-            *config.compact(),
+            config.compact().clone(),
             config.auth().clone(),
             config.tls().clone(),
         )
@@ -239,12 +241,30 @@ impl Cluster {
         self.all_members_client_urls.clone()
     }
 
+    /// Stop a member of the cluster started by `start`, the others keep running
+    pub async fn stop_node(&self, idx: usize) {
+        self.servers[idx].stop().await;
+    }
+
+    /// Stop all servers of the cluster, clients can no longer reach it afterwards
+    pub async fn stop(&mut self) {
+        let _ignore = join_all(self.servers.drain(..).map(|xline| async move {
+            xline.stop().await;
+        }))
+        .await;
+    }
+
     pub fn default_config_with_quota_and_rocks_path(
         path: PathBuf,
         quota: u64,
     ) -> XlineServerConfig {
         let cluster = ClusterConfig::default();
-        let storage = StorageConfig::new(EngineConfig::RocksDB(path), quota);
+        let storage = StorageConfig::new(
+            EngineConfig::RocksDB(path),
+            quota,
+            JournalConfig::default(),
+            EncryptionConfig::default(),
+        );
         let log = LogConfig::default();
         let trace = TraceConfig::default();
         let auth = AuthConfig::default();
@@ -263,6 +283,54 @@ impl Cluster {
         Self::default_config_with_quota_and_rocks_path(path, default_quota())
     }
 
+    /// Default config with the cluster config set by `build`, e.g.
+    /// `Cluster::config_with(|cluster| cluster.read_only(true))`
+    pub fn config_with(
+        build: impl FnOnce(&mut ClusterConfigBuilder) -> &mut ClusterConfigBuilder,
+    ) -> XlineServerConfig {
+        let cluster = build(&mut ClusterConfigBuilder::default())
+            .build()
+            .unwrap_or_else(|e| panic!("invalid cluster config: {e}"));
+        let base = XlineServerConfig::default();
+        XlineServerConfig::new(
+            cluster,
+            base.storage().clone(),
+            base.log().clone(),
+            base.trace().clone(),
+            base.auth().clone(),
+            base.compact().clone(),
+            base.tls().clone(),
+            base.metrics().clone(),
+        )
+    }
+
+    /// Default config that journals the applied requests to the path
+    pub fn journal_config(path: PathBuf) -> XlineServerConfig {
+        let base = XlineServerConfig::default();
+        let journal = JournalConfig::new(
+            Some(path),
+            default_journal_max_size(),
+            default_journal_max_age(),
+            false,
+        );
+        let storage = StorageConfig::new(
+            base.storage().engine.clone(),
+            base.storage().quota,
+            journal,
+            base.storage().encryption.clone(),
+        );
+        XlineServerConfig::new(
+            base.cluster().clone(),
+            storage,
+            base.log().clone(),
+            base.trace().clone(),
+            base.auth().clone(),
+            base.compact().clone(),
+            base.tls().clone(),
+            base.metrics().clone(),
+        )
+    }
+
     pub fn default_quota_config(quota: u64) -> XlineServerConfig {
         let path = temp_dir().join(random_id());
         Self::default_config_with_quota_and_rocks_path(path, quota)
@@ -277,27 +345,24 @@ impl Cluster {
         is_leader: bool,
         initial_cluster_state: InitialClusterState,
     ) -> XlineServerConfig {
-        let old_cluster = base_config.cluster();
-        let new_cluster = ClusterConfig::new(
-            name,
-            vec![peer_url.clone()],
-            vec![peer_url],
-            vec![client_url.clone()],
-            vec![client_url],
-            peers,
-            is_leader,
-            old_cluster.curp_config().clone(),
-            *old_cluster.client_config(),
-            *old_cluster.server_timeout(),
-            initial_cluster_state,
-        );
+        let new_cluster = ClusterConfigBuilder::from(base_config.cluster().clone())
+            .name(name)
+            .peer_listen_urls(vec![peer_url.clone()])
+            .peer_advertise_urls(vec![peer_url])
+            .client_listen_urls(vec![client_url.clone()])
+            .client_advertise_urls(vec![client_url])
+            .peers(peers)
+            .is_leader(is_leader)
+            .initial_cluster_state(initial_cluster_state)
+            .build()
+            .unwrap_or_else(|e| panic!("invalid cluster config: {e}"));
         XlineServerConfig::new(
             new_cluster,
             base_config.storage().clone(),
             base_config.log().clone(),
             base_config.trace().clone(),
             base_config.auth().clone(),
-            *base_config.compact(),
+            base_config.compact().clone(),
             base_config.tls().clone(),
             base_config.metrics().clone(),
         )
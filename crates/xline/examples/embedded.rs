@@ -0,0 +1,38 @@
+use anyhow::Result;
+use utils::config::XlineServerConfig;
+use xline::embedded::XlineEmbedded;
+use xline_client::types::{
+    kv::{PutRequest, RangeRequest},
+    watch::{WatchEvent, WatchRequest},
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // a single node server living in this process, no port is bound
+    let xline = XlineEmbedded::start(XlineServerConfig::default()).await?;
+    // the same client as the one of a remote cluster
+    let client = xline.client();
+
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(WatchRequest::new("key1"))
+        .await?;
+
+    let kv_client = client.kv_client();
+    kv_client.put(PutRequest::new("key1", "value1")).await?;
+    let resp = kv_client.range(RangeRequest::new("key1")).await?;
+    if let Some(kv) = resp.kvs.first() {
+        println!(
+            "got key: {}, value: {}",
+            String::from_utf8_lossy(&kv.key),
+            String::from_utf8_lossy(&kv.value)
+        );
+    }
+
+    if let Some(WatchEvent::Events { events, .. }) = stream.message().await? {
+        println!("watched {} events", events.len());
+    }
+
+    xline.stop().await;
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! this binary rewrites the kv values of a stopped node with the current key of a
+//! key file, it encrypts a data dir for the first time, and finishes a key rotation
+//! so that the retired keys can be removed from the key file
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use clap::Parser;
+use utils::config::EngineConfig;
+use xline::storage::{
+    db::DB,
+    value_transformer::{AesGcm, KeyFile, KeyProvider},
+};
+
+/// Reencrypt args
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+#[clap(about = "Rewrite the kv values of a stopped node with the current key")]
+struct ReencryptArgs {
+    /// Backend data directory of the node
+    #[clap(long)]
+    data_dir: PathBuf,
+    /// Key file of the node, the highest version is the current key
+    #[clap(long)]
+    key_file: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = ReencryptArgs::parse();
+    let transformer = AesGcm::new(KeyFile::new(args.key_file).keys()?)?;
+    let version = transformer.current_version();
+    let count = DB::reencrypt(&EngineConfig::RocksDB(args.data_dir), Arc::new(transformer))?;
+    println!("rewrote {count} values with key version {version}");
+    Ok(())
+}
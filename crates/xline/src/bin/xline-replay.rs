@@ -0,0 +1,104 @@
+//! this binary replays the curp log of a node against a fresh store stack,
+//! it's only used for debugging divergent state machines, with `--journal` it
+//! re-issues the requests of a request journal against a running cluster instead
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+use xline::{
+    journal::{read_journal, replay_journal},
+    replay::{format_trace, load_log, parse_trace, replay, Reference},
+};
+
+/// Replay args
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+#[clap(about = "Replay the curp log of a node against a fresh store")]
+struct ReplayArgs {
+    /// Curp data directory containing the persisted log
+    #[clap(long, required_unless_present = "journal")]
+    curp_dir: Option<PathBuf>,
+    /// Request journal to re-issue against the target
+    #[clap(long, conflicts_with = "curp_dir", requires = "target")]
+    journal: Option<PathBuf>,
+    /// Client endpoint of the cluster the journal is replayed against
+    #[clap(long, requires = "journal")]
+    target: Option<String>,
+    /// Snapshot file to start from, entries included in it are skipped
+    #[clap(long)]
+    snapshot: Option<PathBuf>,
+    /// Hash trace to compare against
+    #[clap(long, conflicts_with = "compare_dir")]
+    trace: Option<PathBuf>,
+    /// Backend data directory of another node to compare against
+    #[clap(long)]
+    compare_dir: Option<PathBuf>,
+    /// Write the per-entry hash trace to this file
+    #[clap(long)]
+    record_trace: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = ReplayArgs::parse();
+    if let (Some(journal), Some(target)) = (args.journal, args.target) {
+        return replay_journal_to(journal, target).await;
+    }
+    let Some(curp_dir) = args.curp_dir else {
+        unreachable!("curp_dir is required without journal");
+    };
+    let reference = if let Some(path) = args.trace {
+        Some(Reference::Trace(parse_trace(
+            &tokio::fs::read_to_string(path).await?,
+        )?))
+    } else {
+        args.compare_dir.map(Reference::DataDir)
+    };
+    let entries = load_log(curp_dir)?;
+    println!("loaded {} log entries", entries.len());
+    let report = replay(&entries, args.snapshot.as_deref(), reference).await?;
+    if let Some(path) = args.record_trace {
+        tokio::fs::write(path, format_trace(&report.trace)).await?;
+    }
+    if let Some(&index) = report.unreached.first() {
+        println!(
+            "the log ends before the reference, {} hashes from index {index} were never compared",
+            report.unreached.len()
+        );
+    }
+    match (report.trace.last(), report.mismatch) {
+        (_, Some(mismatch)) => {
+            println!(
+                "first mismatch at index {}: expected hash {:08x}, got {:08x}",
+                mismatch.index, mismatch.expected, mismatch.actual
+            );
+            println!("entry: {}", mismatch.summary);
+            std::process::exit(1);
+        }
+        (_, None) if !report.unreached.is_empty() => std::process::exit(1),
+        (Some(&(index, hash)), None) => {
+            println!("replayed up to index {index} without mismatch, hash {hash:08x}");
+        }
+        (None, None) => println!("nothing to replay"),
+    }
+    Ok(())
+}
+
+/// Re-issue the requests of the journal against the target
+async fn replay_journal_to(journal: PathBuf, target: String) -> Result<()> {
+    let records = read_journal(journal)?;
+    println!("loaded {} journal records", records.len());
+    let report = replay_journal(&records, target).await?;
+    println!("replayed {} records", report.replayed);
+    for (request_type, count) in &report.skipped {
+        println!("skipped {count} {request_type}");
+    }
+    if let Some(divergence) = report.divergence {
+        println!(
+            "first divergence at index {}: {} recorded at revision {}, replayed at {}",
+            divergence.index, divergence.request_type, divergence.expected, divergence.actual
+        );
+        std::process::exit(1);
+    }
+    Ok(())
+}
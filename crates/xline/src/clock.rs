@@ -0,0 +1,193 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clippy_utilities::NumericCast;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// Max drift of the wall clock from the monotonic clock between two readings before
+/// it's considered as a jump
+const JUMP_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Source of the wall and the monotonic time
+pub(crate) trait TimeProvider: Debug + Send + Sync {
+    /// The wall time, it may jump in both directions
+    fn wall(&self) -> SystemTime;
+
+    /// The monotonic time, it never goes backwards
+    fn monotonic(&self) -> Instant;
+}
+
+/// The time of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SystemTimeProvider;
+
+impl TimeProvider for SystemTimeProvider {
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The single time source of the server
+///
+/// Timestamps handed out to clients or compared across restarts are taken from the
+/// wall clock, jumps of it are logged and counted here. Expiries, of leases included,
+/// are measured with the monotonic clock only, so they are never affected by a jump.
+#[derive(Debug)]
+pub(crate) struct Clock {
+    /// The provider of the time
+    provider: Arc<dyn TimeProvider>,
+    /// The last reading of the wall and the monotonic clock
+    last: Mutex<(SystemTime, Instant)>,
+    /// The number of detected jumps of the wall clock
+    jumps: AtomicU64,
+}
+
+impl Clock {
+    /// New `Clock`
+    pub(crate) fn new(provider: Arc<dyn TimeProvider>) -> Self {
+        let last = (provider.wall(), provider.monotonic());
+        Self {
+            provider,
+            last: Mutex::new(last),
+            jumps: AtomicU64::new(0),
+        }
+    }
+
+    /// New `Clock` of the system time
+    pub(crate) fn system() -> Self {
+        Self::new(Arc::new(SystemTimeProvider))
+    }
+
+    /// The wall time, a jump since the last reading is logged and counted
+    pub(crate) fn wall(&self) -> SystemTime {
+        let mut last = self.last.lock();
+        let (wall, monotonic) = (self.provider.wall(), self.provider.monotonic());
+        let elapsed = monotonic.saturating_duration_since(last.1);
+        let expected = last.0.checked_add(elapsed).unwrap_or(last.0);
+        match wall.duration_since(expected) {
+            Ok(ahead) if ahead > JUMP_THRESHOLD => self.on_jump("forward", ahead),
+            Err(e) if e.duration() > JUMP_THRESHOLD => self.on_jump("backward", e.duration()),
+            Ok(_) | Err(_) => {}
+        }
+        *last = (wall, monotonic);
+        wall
+    }
+
+    /// Seconds since the unix epoch of the wall time, zero if it's before the epoch
+    pub(crate) fn unix_secs(&self) -> u64 {
+        self.since_epoch().as_secs()
+    }
+
+    /// Milliseconds since the unix epoch of the wall time, zero if it's before the epoch
+    pub(crate) fn unix_millis(&self) -> u64 {
+        self.since_epoch().as_millis().numeric_cast()
+    }
+
+    /// The number of detected jumps of the wall clock
+    #[cfg(test)]
+    pub(crate) fn jumps(&self) -> u64 {
+        self.jumps.load(Ordering::Relaxed)
+    }
+
+    /// Duration since the unix epoch of the wall time
+    fn since_epoch(&self) -> Duration {
+        self.wall().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    /// Record a jump of the wall clock
+    fn on_jump(&self, direction: &'static str, by: Duration) {
+        warn!("wall clock jumped {direction} by {by:?}, monotonic expiries are not affected");
+        let _prev = self.jumps.fetch_add(1, Ordering::Relaxed);
+        Metrics::get()
+            .clock_jumps_total
+            .add(1, &[KeyValue::new("direction", direction)]);
+    }
+}
+
+/// A time provider controlled by tests
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct MockTimeProvider {
+    /// The current wall and monotonic time
+    now: Mutex<(SystemTime, Instant)>,
+}
+
+#[cfg(test)]
+impl MockTimeProvider {
+    /// New `MockTimeProvider` starting at the given wall time
+    pub(crate) fn new(wall: SystemTime) -> Arc<Self> {
+        Arc::new(Self {
+            now: Mutex::new((wall, Instant::now())),
+        })
+    }
+
+    /// Let the time pass, on both clocks
+    pub(crate) fn advance(&self, by: Duration) {
+        let mut now = self.now.lock();
+        *now = (now.0 + by, now.1 + by);
+    }
+
+    /// Move the wall clock forward without the monotonic clock
+    pub(crate) fn jump_forward(&self, by: Duration) {
+        self.now.lock().0 += by;
+    }
+
+    /// Move the wall clock backward without the monotonic clock
+    pub(crate) fn jump_backward(&self, by: Duration) {
+        self.now.lock().0 -= by;
+    }
+}
+
+#[cfg(test)]
+impl TimeProvider for MockTimeProvider {
+    fn wall(&self) -> SystemTime {
+        self.now.lock().0
+    }
+
+    fn monotonic(&self) -> Instant {
+        self.now.lock().1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clock_should_detect_wall_jumps() {
+        let time = MockTimeProvider::new(UNIX_EPOCH + Duration::from_secs(1000));
+        let clock = Clock::new(Arc::clone(&time));
+        assert_eq!(clock.unix_secs(), 1000);
+
+        time.advance(Duration::from_secs(10));
+        assert_eq!(clock.unix_secs(), 1010);
+        assert_eq!(clock.jumps(), 0);
+
+        time.jump_forward(Duration::from_secs(60));
+        assert_eq!(clock.unix_secs(), 1070);
+        assert_eq!(clock.jumps(), 1);
+
+        time.jump_backward(Duration::from_secs(120));
+        assert_eq!(clock.unix_secs(), 950);
+        assert_eq!(clock.jumps(), 2);
+
+        // small drifts are not jumps
+        time.jump_forward(Duration::from_millis(500));
+        assert_eq!(clock.unix_millis(), 950_500);
+        assert_eq!(clock.jumps(), 2);
+    }
+}
@@ -3,7 +3,7 @@ use curp::{
     server::{SpObject, UcpObject},
 };
 use utils::interval_map::Interval;
-use xlineapi::{command::Command, interval::BytesAffine, RequestBackend, RequestWrapper};
+use xlineapi::{command::Command, interval::BytesAffine, RequestBackend};
 
 use self::{
     spec_pool::{ExclusiveSpecPool, KvSpecPool, LeaseSpecPool},
@@ -20,17 +20,19 @@ pub(crate) mod uncommitted_pool;
 mod tests;
 
 /// Returns command intervals
-fn intervals<C>(entry: &C) -> Vec<Interval<BytesAffine>>
+fn intervals<C>(entry: &C) -> &[Interval<BytesAffine>]
 where
     C: AsRef<Command>,
 {
-    entry
-        .as_ref()
-        .keys()
-        .iter()
-        .cloned()
-        .map(Into::into)
-        .collect()
+    entry.as_ref().conflict_meta().intervals()
+}
+
+/// Returns command lease ids
+fn lease_ids<C>(entry: &C) -> &[i64]
+where
+    C: AsRef<Command>,
+{
+    entry.as_ref().conflict_meta().lease_ids()
 }
 
 /// Filter kv commands
@@ -38,28 +40,15 @@ fn filter_kv<C>(entry: C) -> Option<C>
 where
     C: AsRef<Command>,
 {
-    matches!(entry.as_ref().request().backend(), RequestBackend::Kv).then_some(entry)
+    matches!(entry.as_ref().conflict_meta().backend(), RequestBackend::Kv).then_some(entry)
 }
 
 /// Returns `true` if this command conflicts with all other commands
-fn is_exclusive_cmd(cmd: &Command) -> bool {
-    matches!(
-        *cmd.request(),
-        RequestWrapper::CompactionRequest(_)
-            | RequestWrapper::AuthEnableRequest(_)
-            | RequestWrapper::AuthDisableRequest(_)
-            | RequestWrapper::AuthRoleAddRequest(_)
-            | RequestWrapper::AuthRoleDeleteRequest(_)
-            | RequestWrapper::AuthRoleGrantPermissionRequest(_)
-            | RequestWrapper::AuthRoleRevokePermissionRequest(_)
-            | RequestWrapper::AuthUserAddRequest(_)
-            | RequestWrapper::AuthUserChangePasswordRequest(_)
-            | RequestWrapper::AuthUserDeleteRequest(_)
-            | RequestWrapper::AuthUserGrantRoleRequest(_)
-            | RequestWrapper::AuthUserRevokeRoleRequest(_)
-            | RequestWrapper::AuthenticateRequest(_)
-            | RequestWrapper::AlarmRequest(_)
-    )
+fn is_exclusive_cmd<C>(entry: &C) -> bool
+where
+    C: AsRef<Command>,
+{
+    entry.as_ref().conflict_meta().is_exclusive()
 }
 
 /// Xline speculative pools wrapper
@@ -7,12 +7,9 @@ use std::collections::HashMap;
 use curp::server::conflict::CommandEntry;
 use curp_external_api::conflict::{ConflictPoolOp, SpeculativePoolOp};
 use utils::interval_map::IntervalMap;
-use xlineapi::{
-    command::{get_lease_ids, Command},
-    interval::BytesAffine,
-};
+use xlineapi::{command::Command, interval::BytesAffine};
 
-use super::{filter_kv, intervals, is_exclusive_cmd};
+use super::{filter_kv, intervals, is_exclusive_cmd, lease_ids};
 
 /// Speculative pool for KV commands.
 #[derive(Debug, Default)]
@@ -30,7 +27,7 @@ impl ConflictPoolOp for KvSpecPool {
         };
 
         for interval in intervals(&entry) {
-            let _ignore = self.map.remove(&interval);
+            let _ignore = self.map.remove(interval);
         }
     }
 
@@ -55,12 +52,11 @@ impl SpeculativePoolOp for KvSpecPool {
     fn insert_if_not_conflict(&mut self, entry: Self::Entry) -> Option<Self::Entry> {
         let entry = filter_kv(entry)?;
 
-        let intervals = intervals(&entry);
-        if intervals.iter().any(|i| self.map.overlap(i)) {
+        if intervals(&entry).iter().any(|i| self.map.overlap(i)) {
             return Some(entry);
         }
-        for interval in intervals {
-            let _ignore = self.map.insert(interval, entry.clone());
+        for interval in intervals(&entry) {
+            let _ignore = self.map.insert(interval.clone(), entry.clone());
         }
         None
     }
@@ -81,9 +77,8 @@ impl ConflictPoolOp for LeaseSpecPool {
     }
 
     fn remove(&mut self, entry: Self::Entry) {
-        let ids = get_lease_ids(entry.request());
-        for id in ids {
-            let _ignore = self.leases.remove(&id);
+        for id in lease_ids(&entry) {
+            let _ignore = self.leases.remove(id);
         }
     }
 
@@ -102,13 +97,13 @@ impl ConflictPoolOp for LeaseSpecPool {
 
 impl SpeculativePoolOp for LeaseSpecPool {
     fn insert_if_not_conflict(&mut self, entry: Self::Entry) -> Option<Self::Entry> {
-        let ids = get_lease_ids(entry.request());
-        for id in ids.clone() {
-            if self.leases.contains_key(&id) {
-                return Some(entry);
-            }
+        if lease_ids(&entry)
+            .iter()
+            .any(|id| self.leases.contains_key(id))
+        {
+            return Some(entry);
         }
-        for id in ids {
+        for &id in lease_ids(&entry) {
             let _ignore = self.leases.insert(id, entry.clone());
         }
         None
@@ -1,10 +1,18 @@
 use std::sync::Arc;
 
 use curp::{rpc::ProposeId, server::conflict::CommandEntry};
-use curp_external_api::conflict::{ConflictPoolOp, SpeculativePoolOp, UncommittedPoolOp};
+use curp_external_api::{
+    cmd::PbCodec,
+    conflict::{ConflictPoolOp, SpeculativePoolOp, UncommittedPoolOp},
+};
+use itertools::Itertools;
+use utils::interval_map::Interval;
 use xlineapi::{
-    command::Command, AuthEnableRequest, AuthRoleAddRequest, DeleteRangeRequest, LeaseGrantRequest,
-    LeaseRevokeRequest, PutRequest, RequestWrapper,
+    command::{get_lease_ids, Command},
+    interval::BytesAffine,
+    AuthEnableRequest, AuthRoleAddRequest, AuthStatusRequest, CompactionRequest,
+    DeleteRangeRequest, LeaseGrantRequest, LeaseLeasesRequest, LeaseRevokeRequest, PutRequest,
+    RangeRequest, Request, RequestBackend, RequestOp, RequestWrapper, TxnRequest,
 };
 
 use super::spec_pool::{KvSpecPool, LeaseSpecPool};
@@ -189,6 +197,159 @@ fn exclusive_ucp_operations_are_ok() {
     assert_eq!(ucp.len(), 0);
 }
 
+/// The conflict decision of the pools holding `a` on `b`, derived from the requests
+/// themselves like the pools did before they checked the conflict metadata
+fn request_conflicts(a: &Command, b: &Command) -> bool {
+    let exclusive = |cmd: &Command| {
+        matches!(
+            *cmd.request(),
+            RequestWrapper::CompactionRequest(_)
+                | RequestWrapper::AuthEnableRequest(_)
+                | RequestWrapper::AuthRoleAddRequest(_)
+        )
+    };
+    let is_kv = |cmd: &Command| cmd.request().backend() == RequestBackend::Kv;
+    let intervals = |cmd: &Command| {
+        cmd.request()
+            .keys()
+            .into_iter()
+            .map(Interval::<BytesAffine>::from)
+            .collect_vec()
+    };
+    let kv_conflict = is_kv(a)
+        && is_kv(b)
+        && intervals(a)
+            .iter()
+            .cartesian_product(intervals(b).iter())
+            .any(|(i, j)| i.overlap(j));
+    let lease_conflict = !get_lease_ids(a.request()).is_disjoint(&get_lease_ids(b.request()));
+    kv_conflict || lease_conflict || exclusive(a) || exclusive(b)
+}
+
+/// Requests of all kinds, on overlapping keys and leases
+fn generated_requests() -> Vec<RequestWrapper> {
+    let ranges = [
+        ("a", ""),
+        ("b", ""),
+        ("a", "c"),
+        ("b", "d"),
+        ("c", "\0"),
+        ("\0", "\0"),
+    ]
+    .map(|(key, range_end)| (key.as_bytes().to_vec(), range_end.as_bytes().to_vec()));
+    let put = |key: &[u8], lease| PutRequest {
+        key: key.to_vec(),
+        value: vec![0; 64 * 1024],
+        lease,
+        ..Default::default()
+    };
+    let mut requests = vec![];
+    for (key, range_end) in &ranges {
+        requests.push(RequestWrapper::RangeRequest(RangeRequest {
+            key: key.clone(),
+            range_end: range_end.clone(),
+            ..Default::default()
+        }));
+        requests.push(RequestWrapper::DeleteRangeRequest(DeleteRangeRequest {
+            key: key.clone(),
+            range_end: range_end.clone(),
+            ..Default::default()
+        }));
+        if range_end.is_empty() {
+            for lease in 0..3 {
+                requests.push(RequestWrapper::PutRequest(put(key, lease)));
+            }
+        }
+    }
+    for ((key, _), (other, other_end)) in ranges.iter().tuple_windows() {
+        let nested = TxnRequest {
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(put(key, 2))),
+            }],
+            ..Default::default()
+        };
+        requests.push(RequestWrapper::TxnRequest(TxnRequest {
+            success: vec![RequestOp {
+                request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                    key: other.clone(),
+                    range_end: other_end.clone(),
+                    ..Default::default()
+                })),
+            }],
+            failure: vec![RequestOp {
+                request: Some(Request::RequestTxn(nested)),
+            }],
+            ..Default::default()
+        }));
+    }
+    for id in 1..3 {
+        requests.push(RequestWrapper::LeaseGrantRequest(LeaseGrantRequest {
+            id,
+            ..Default::default()
+        }));
+        requests.push(RequestWrapper::LeaseRevokeRequest(LeaseRevokeRequest {
+            id,
+            ..Default::default()
+        }));
+    }
+    requests.push(RequestWrapper::LeaseLeasesRequest(LeaseLeasesRequest {}));
+    requests.push(RequestWrapper::CompactionRequest(CompactionRequest {
+        revision: 3,
+        ..Default::default()
+    }));
+    requests.push(RequestWrapper::AuthEnableRequest(AuthEnableRequest {}));
+    requests.push(RequestWrapper::AuthStatusRequest(AuthStatusRequest {}));
+    requests.push(RequestWrapper::AuthRoleAddRequest(
+        AuthRoleAddRequest::default(),
+    ));
+    requests
+}
+
+#[test]
+fn meta_based_conflicts_should_equal_request_based_conflicts() {
+    let mut gen = EntryGenerator::default();
+    let entries = generated_requests()
+        .into_iter()
+        .map(|req| gen.gen_entry(req))
+        .collect_vec();
+    for (a, b) in entries.iter().cartesian_product(entries.iter()) {
+        // the metadata is carried along with the encoded command
+        let decoded = Command::decode(&b.as_ref().encode()).unwrap();
+        assert_eq!(decoded.conflict_meta(), b.conflict_meta());
+        let b = gen.gen_entry(decoded.request().clone());
+
+        let expected = request_conflicts(a, &b);
+        let mut sps: [Box<dyn SpeculativePoolOp<Entry = CommandEntry<Command>>>; 3] = [
+            Box::<KvSpecPool>::default(),
+            Box::<LeaseSpecPool>::default(),
+            Box::<ExclusiveSpecPool>::default(),
+        ];
+        for sp in &mut sps {
+            let _ignore = sp.insert_if_not_conflict(a.clone());
+        }
+        let sp_conflict = sps
+            .iter_mut()
+            .any(|sp| sp.insert_if_not_conflict(b.clone()).is_some());
+        assert_eq!(sp_conflict, expected, "a: {a:?}, b: {b:?}");
+
+        let mut ucps: [Box<dyn UncommittedPoolOp<Entry = CommandEntry<Command>>>; 3] = [
+            Box::<KvUncomPool>::default(),
+            Box::<LeaseUncomPool>::default(),
+            Box::<ExclusiveUncomPool>::default(),
+        ];
+        for ucp in &mut ucps {
+            let _ignore = ucp.insert(a.clone());
+        }
+        let ucp_conflict = ucps.iter().any(|ucp| ucp.all_conflict(&b).contains(a))
+            || b.conflict_meta().is_exclusive();
+        assert_eq!(ucp_conflict, expected, "a: {a:?}, b: {b:?}");
+        let inserted = ucps
+            .iter_mut()
+            .fold(false, |c, ucp| ucp.insert(b.clone()) | c);
+        assert_eq!(inserted, expected, "a: {a:?}, b: {b:?}");
+    }
+}
+
 fn compare_commands(mut a: Vec<CommandEntry<Command>>, mut b: Vec<CommandEntry<Command>>) {
     a.sort_unstable();
     b.sort_unstable();
@@ -226,6 +387,7 @@ impl EntryGenerator {
     fn gen_lease_revoke(&mut self, id: i64) -> CommandEntry<Command> {
         self.gen_entry(RequestWrapper::LeaseRevokeRequest(LeaseRevokeRequest {
             id,
+            ..Default::default()
         }))
     }
 
@@ -8,12 +8,9 @@ use curp::server::conflict::CommandEntry;
 use curp_external_api::conflict::{ConflictPoolOp, UncommittedPoolOp};
 use itertools::Itertools;
 use utils::interval_map::IntervalMap;
-use xlineapi::{
-    command::{get_lease_ids, Command},
-    interval::BytesAffine,
-};
+use xlineapi::{command::Command, interval::BytesAffine};
 
-use super::{filter_kv, intervals, is_exclusive_cmd};
+use super::{filter_kv, intervals, is_exclusive_cmd, lease_ids};
 
 /// Uncommitted pool for KV commands.
 #[derive(Debug, Default)]
@@ -29,14 +26,13 @@ impl ConflictPoolOp for KvUncomPool {
         let Some(entry) = filter_kv(entry) else {
             return;
         };
-        let intervals = intervals(&entry);
-        for interval in intervals {
+        for interval in intervals(&entry) {
             if self
                 .map
-                .get_mut(&interval)
+                .get_mut(interval)
                 .map_or(false, |m| m.remove_cmd(&entry))
             {
-                let _ignore = self.map.remove(&interval);
+                let _ignore = self.map.remove(interval);
             }
         }
     }
@@ -68,10 +64,12 @@ impl UncommittedPoolOp for KvUncomPool {
             return false;
         };
 
-        let intervals = intervals(&entry);
-        let conflict = intervals.iter().any(|i| self.map.overlap(i));
-        for interval in intervals {
-            let e = self.map.entry(interval).or_insert(Commands::default());
+        let conflict = intervals(&entry).iter().any(|i| self.map.overlap(i));
+        for interval in intervals(&entry) {
+            let e = self
+                .map
+                .entry(interval.clone())
+                .or_insert(Commands::default());
             e.push_cmd(entry.clone());
         }
         conflict
@@ -81,10 +79,9 @@ impl UncommittedPoolOp for KvUncomPool {
         let Some(entry) = filter_kv(entry) else {
             return vec![];
         };
-        let intervals = intervals(entry);
-        intervals
-            .into_iter()
-            .flat_map(|i| self.map.find_all_overlap(&i))
+        intervals(entry)
+            .iter()
+            .flat_map(|i| self.map.find_all_overlap(i))
             .flat_map(|(_, v)| v.all())
             .unique()
             .collect()
@@ -102,8 +99,7 @@ impl ConflictPoolOp for LeaseUncomPool {
     type Entry = CommandEntry<Command>;
 
     fn remove(&mut self, entry: Self::Entry) {
-        let ids = get_lease_ids(entry.request());
-        for id in ids {
+        for &id in lease_ids(&entry) {
             if let hash_map::Entry::Occupied(mut e) = self.leases.entry(id) {
                 if e.get_mut().remove_cmd(&entry) {
                     let _ignore = e.remove_entry();
@@ -140,8 +136,7 @@ impl ConflictPoolOp for LeaseUncomPool {
 impl UncommittedPoolOp for LeaseUncomPool {
     fn insert(&mut self, entry: Self::Entry) -> bool {
         let mut conflict = false;
-        let ids = get_lease_ids(entry.request());
-        for id in ids {
+        for &id in lease_ids(&entry) {
             match self.leases.entry(id) {
                 hash_map::Entry::Occupied(mut e) => {
                     e.get_mut().push_cmd(entry.clone());
@@ -157,9 +152,9 @@ impl UncommittedPoolOp for LeaseUncomPool {
     }
 
     fn all_conflict(&self, entry: &Self::Entry) -> Vec<Self::Entry> {
-        let ids = get_lease_ids(entry.request());
-        ids.into_iter()
-            .flat_map(|id| self.leases.get(&id).map(Commands::all).unwrap_or_default())
+        lease_ids(entry)
+            .iter()
+            .flat_map(|id| self.leases.get(id).map(Commands::all).unwrap_or_default())
             .collect()
     }
 }
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use anyhow::Result;
+use tokio::{io::DuplexStream, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use utils::config::{ClusterConfig, ClusterConfigBuilder, InitialClusterState, XlineServerConfig};
+use xline_client::{Client, ClientOptions};
+
+use crate::server::XlineServer;
+
+/// Buffer size of an in-process connection
+const CONN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Max number of in-process connections waiting to be accepted
+const PENDING_CONNS: usize = 16;
+
+/// An Xline server embedded in the current process
+///
+/// It runs as a single node cluster: the curp log is appended and applied locally
+/// with a quorum of one, no peer is ever dialed and no port is bound. Clients talk
+/// to it through in-memory connections, with the same `Client` as a remote cluster.
+///
+/// The consensus layer is the regular curp server, not a local-only shim, so the
+/// curp transport is still compiled in even though it never leaves the process.
+#[derive(Debug)]
+pub struct XlineEmbedded {
+    /// The embedded server
+    server: Arc<XlineServer>,
+    /// Client of the embedded server
+    client: Client,
+}
+
+impl XlineEmbedded {
+    /// Start an embedded server, the cluster part of `config` except the timeouts
+    /// and the limits is overridden to a single node cluster
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` when the server fails to start
+    #[inline]
+    pub async fn start(config: XlineServerConfig) -> Result<Self> {
+        Self::start_with_options(config, ClientOptions::default()).await
+    }
+
+    /// Start an embedded server, its client is built with `options`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` when the server fails to start or the client fails to
+    /// authenticate
+    #[inline]
+    pub async fn start_with_options(
+        config: XlineServerConfig,
+        options: ClientOptions,
+    ) -> Result<Self> {
+        let server = Arc::new(
+            XlineServer::new(
+                local_cluster_config(config.cluster()),
+                config.storage().clone(),
+                config.compact().clone(),
+                config.auth().clone(),
+                config.tls().clone(),
+            )
+            .await?,
+        );
+        let (conn_tx, conn_rx) = mpsc::channel(PENDING_CONNS);
+        let curp_client = server.start_local(ReceiverStream::new(conn_rx)).await?;
+        let channel = in_process_channel(conn_tx);
+        let client = Client::from_transport(curp_client, channel, options).await?;
+        Ok(Self { server, client })
+    }
+
+    /// Gets the client of the embedded server
+    #[inline]
+    #[must_use]
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    /// Stop the embedded server, its clients can no longer reach it afterwards
+    #[inline]
+    pub async fn stop(&self) {
+        self.server.stop().await;
+    }
+}
+
+/// The single node cluster config of an embedded server
+fn local_cluster_config(base: &ClusterConfig) -> ClusterConfig {
+    let peers = HashMap::from([(base.name().clone(), base.peer_advertise_urls().clone())]);
+    let Ok(config) = ClusterConfigBuilder::from(base.clone())
+        .peers(peers)
+        .is_leader(true)
+        .initial_cluster_state(InitialClusterState::New)
+        .read_only(false)
+        .build()
+    else {
+        unreachable!("the unset fields of the cluster config have defaults")
+    };
+    config
+}
+
+/// A channel dialing the embedded server through in-memory connections, the
+/// server half of each connection is handed over through `conn_tx`
+fn in_process_channel(conn_tx: mpsc::Sender<io::Result<DuplexStream>>) -> Channel {
+    Endpoint::from_static("http://embedded.xline").connect_with_connector_lazy(tower::service_fn(
+        move |_: Uri| {
+            let conn_tx = conn_tx.clone();
+            async move {
+                let (client, server) = tokio::io::duplex(CONN_BUFFER_SIZE);
+                conn_tx.send(Ok(server)).await.map_err(|_e| {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "embedded server stopped")
+                })?;
+                Ok::<_, io::Error>(client)
+            }
+        },
+    ))
+}
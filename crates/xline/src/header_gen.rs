@@ -1,19 +1,20 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 use curp::members::ServerId;
-use parking_lot::Mutex;
 
 use crate::{revision_number::RevisionNumberGenerator, rpc::ResponseHeader};
 
 /// Generator of `ResponseHeader`
 #[derive(Debug)]
 pub(crate) struct HeaderGenerator {
-    /// Id of the cluster
-    cluster_id: u64,
-    /// Id of the member
-    member_id: u64,
+    /// Header carrying the ids of the cluster and the member, the term and the
+    /// revision are stamped into a copy of it
+    template: ResponseHeader,
     /// Term of curp
-    term: Arc<Mutex<u64>>,
+    term: AtomicU64,
     /// Revision of kv store
     general_revision: Arc<RevisionNumberGenerator>,
     /// Revision of auth store
@@ -24,9 +25,12 @@ impl HeaderGenerator {
     /// New `HeaderGenerator`
     pub(crate) fn new(cluster_id: u64, member_id: ServerId) -> Self {
         Self {
-            cluster_id,
-            member_id,
-            term: Arc::new(Mutex::new(0)),
+            template: ResponseHeader {
+                cluster_id,
+                member_id,
+                ..ResponseHeader::default()
+            },
+            term: AtomicU64::new(0),
             general_revision: Arc::new(RevisionNumberGenerator::default()),
             auth_revision: Arc::new(RevisionNumberGenerator::default()),
         }
@@ -34,28 +38,32 @@ impl HeaderGenerator {
 
     /// Generate `ResponseHeader`
     pub(crate) fn gen_header(&self) -> ResponseHeader {
+        self.gen_header_with_revision(self.general_revision())
+    }
+
+    /// Generate `ResponseHeader` with a revision already known by the caller, e.g.
+    /// the revision a request is applied at, which the counter may have moved past
+    pub(crate) fn gen_header_with_revision(&self, revision: i64) -> ResponseHeader {
         ResponseHeader {
-            cluster_id: self.cluster_id,
-            member_id: self.member_id,
-            raft_term: *self.term.lock(),
-            revision: self.general_revision(),
+            raft_term: self.term(),
+            revision,
+            ..self.template
         }
     }
 
     /// Generate `ResponseHeader` for auth request
     pub(crate) fn gen_auth_header(&self) -> ResponseHeader {
-        ResponseHeader {
-            cluster_id: self.cluster_id,
-            member_id: self.member_id,
-            raft_term: *self.term.lock(),
-            revision: self.auth_revision(),
-        }
+        self.gen_header_with_revision(self.auth_revision())
     }
 
-    /// Set term
-    #[allow(dead_code)] // Will be used in the future
+    /// Set term, updated by curp when the term changes
     pub(crate) fn set_term(&self, term: u64) {
-        *self.term.lock() = term;
+        self.term.store(term, Ordering::Relaxed);
+    }
+
+    /// Get term
+    pub(crate) fn term(&self) -> u64 {
+        self.term.load(Ordering::Relaxed)
     }
 
     /// Get general revision
@@ -78,3 +86,36 @@ impl HeaderGenerator {
         Arc::clone(&self.auth_revision)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_should_carry_the_given_revision() {
+        let header_gen = HeaderGenerator::new(1, 2);
+        header_gen.set_term(3);
+        let applied = header_gen.general_revision_arc().next();
+        // the counter is bumped again before the response of `applied` is built
+        let _next = header_gen.general_revision_arc().next();
+
+        let header = header_gen.gen_header_with_revision(applied);
+        assert_eq!(
+            header,
+            ResponseHeader {
+                cluster_id: 1,
+                member_id: 2,
+                raft_term: 3,
+                revision: applied,
+            }
+        );
+        assert_eq!(header_gen.gen_header().revision, applied.wrapping_add(1));
+
+        header_gen.set_term(4);
+        assert_eq!(header_gen.gen_header_with_revision(applied).raft_term, 4);
+        assert_eq!(
+            header_gen.gen_auth_header().revision,
+            header_gen.auth_revision()
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Journal of the mutating requests applied by a member, used to reproduce a reported
+//! sequence of operations deterministically.
+//!
+//! Every mutating request applied successfully is appended to the journal as a line of
+//! JSON, along with the id of its proposal, the index of its log entry and the revision
+//! it's applied at. The journal is made of the active file and the previous one, which
+//! has the `.old` suffix. The active file replaces the previous one once it reaches half
+//! of the max size or half of the max age, so the records beyond the bounds are dropped
+//! a file at a time. The journal is kept out of the data directory, so it's never part
+//! of the snapshots or the backups.
+//!
+//! `xline-replay --journal` re-issues the recorded requests in the order of their log
+//! entries against another cluster, see [`replay_journal`].
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use clippy_utilities::OverflowArithmetic;
+use curp::LogIndex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utils::config::JournalConfig;
+use xlineapi::{
+    command::Command, KvClient, LeaseClient, LeaseRevokeRequest, PutRequest, Request,
+    RequestWrapper, TxnRequest,
+};
+
+use crate::clock::Clock;
+
+/// Suffix of the previous journal file
+const OLD_SUFFIX: &str = "old";
+
+/// Max number of records waiting for the writer, the records beyond it are dropped
+/// rather than holding up the apply
+const JOURNAL_CHANNEL_SIZE: usize = 1024;
+
+/// A mutating request applied by the member
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct JournalRecord {
+    /// Index of the log entry of the request
+    pub index: LogIndex,
+    /// Id of the proposal of the request, as `<client id>#<sequence number>`
+    pub propose_id: String,
+    /// Revision the request is applied at
+    pub revision: i64,
+    /// Type of the request
+    pub request_type: String,
+    /// User the request is authenticated as, `None` if auth is disabled
+    pub username: Option<String>,
+    /// Time the request is applied at, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// Whether the values of the request are redacted
+    pub redacted: bool,
+    /// The decoded request
+    pub request: RequestWrapper,
+}
+
+/// The active journal file
+#[derive(Debug)]
+struct ActiveFile {
+    /// The file, opened for appending
+    file: File,
+    /// Size of the file
+    size: u64,
+    /// Time the file is opened at
+    opened: Instant,
+}
+
+/// Journal of the applied requests, the records are written by a dedicated thread so
+/// that the apply never waits for the file
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// Whether the values are redacted
+    redact_values: bool,
+    /// Time source of the timestamps
+    clock: Arc<Clock>,
+    /// Sender of the encoded records to the writer, `None` once the journal is closed
+    tx: Option<SyncSender<Vec<u8>>>,
+    /// The writer thread, joined once the journal is closed
+    writer: Option<JoinHandle<()>>,
+}
+
+/// Writer of the journal files
+#[derive(Debug)]
+struct JournalWriter {
+    /// Path of the active file
+    path: PathBuf,
+    /// Max size of each of the two files
+    max_file_size: u64,
+    /// Max age of each of the two files
+    max_file_age: Duration,
+    /// The active file
+    active: ActiveFile,
+}
+
+impl Journal {
+    /// Open the journal of the config, returns `None` if journaling is off
+    ///
+    /// # Errors
+    ///
+    /// Return error if the path is in one of the data directories or the journal
+    /// cannot be opened
+    pub(crate) fn open(
+        config: &JournalConfig,
+        data_dirs: &[&Path],
+        clock: Arc<Clock>,
+    ) -> Result<Option<Self>> {
+        let Some(ref path) = *config.path() else {
+            return Ok(None);
+        };
+        if let Some(dir) = data_dirs.iter().find(|dir| path.starts_with(dir)) {
+            return Err(anyhow!(
+                "the journal {} must be out of the data directory {}",
+                path.display(),
+                dir.display()
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = JournalWriter {
+            path: path.clone(),
+            max_file_size: config.max_size().checked_div(2).unwrap_or_default(),
+            max_file_age: config.max_age().checked_div(2).unwrap_or_default(),
+            active: ActiveFile::open(path)?,
+        };
+        let (tx, rx) = mpsc::sync_channel(JOURNAL_CHANNEL_SIZE);
+        let writer = thread::Builder::new()
+            .name("journal-writer".to_owned())
+            .spawn(move || writer.run(&rx))?;
+        Ok(Some(Self {
+            redact_values: *config.redact_values(),
+            clock,
+            tx: Some(tx),
+            writer: Some(writer),
+        }))
+    }
+
+    /// Record a command applied successfully, the failures are logged rather than
+    /// failing the apply
+    pub(crate) fn record(
+        &self,
+        cmd: &Command,
+        propose_id: (u64, u64),
+        index: LogIndex,
+        revision: i64,
+    ) {
+        if !is_journaled(cmd.request()) {
+            return;
+        }
+        if let Err(e) = self.append(cmd, propose_id, index, revision) {
+            warn!("failed to journal log[{index}]: {e}");
+        }
+    }
+
+    /// Encode the record of the command and pass it to the writer
+    fn append(
+        &self,
+        cmd: &Command,
+        (client_id, seq_num): (u64, u64),
+        index: LogIndex,
+        revision: i64,
+    ) -> Result<()> {
+        let mut request = cmd.request().clone();
+        redact(&mut request, self.redact_values);
+        let record = JournalRecord {
+            index,
+            propose_id: format!("{client_id}#{seq_num}"),
+            revision,
+            request_type: request_type(&request)?,
+            username: cmd.auth_info().map(|info| info.username.clone()),
+            timestamp: self.clock.unix_millis(),
+            redacted: self.redact_values,
+            request,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        let Some(ref tx) = self.tx else {
+            return Err(anyhow!("the journal is closed"));
+        };
+        tx.try_send(line).map_err(|e| match e {
+            TrySendError::Full(_) => anyhow!("the journal writer is behind, the record is dropped"),
+            TrySendError::Disconnected(_) => anyhow!("the journal writer has stopped"),
+        })
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        // the writer drains the pending records once the sender is dropped
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                warn!("the journal writer panicked");
+            }
+        }
+    }
+}
+
+impl ActiveFile {
+    /// Open the active file for appending
+    fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+}
+
+impl JournalWriter {
+    /// Write the records until the journal is closed
+    fn run(mut self, rx: &Receiver<Vec<u8>>) {
+        for line in rx {
+            if let Err(e) = self.write(&line) {
+                warn!("failed to write the journal: {e}");
+            }
+        }
+    }
+
+    /// Write a record, the active file is rotated first if it's out of the bounds
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        if self.active.size >= self.max_file_size
+            || self.active.opened.elapsed() >= self.max_file_age
+        {
+            fs::rename(&self.path, old_path(&self.path))?;
+            self.active = ActiveFile::open(&self.path)?;
+        }
+        self.active.file.write_all(line)?;
+        self.active.size = self.active.size.saturating_add(line.len().try_into()?);
+        Ok(())
+    }
+}
+
+/// Whether the request is journaled, the authentications carry the passwords in plain
+/// text and don't change the state, so they are never journaled
+fn is_journaled(request: &RequestWrapper) -> bool {
+    !request.is_read_only() && !matches!(*request, RequestWrapper::AuthenticateRequest(_))
+}
+
+/// Type of the request, the same as the tag of its serialization
+fn request_type(request: &RequestWrapper) -> Result<String> {
+    let serde_json::Value::Object(map) = serde_json::to_value(request)? else {
+        return Err(anyhow!("the request is not serialized as a map"));
+    };
+    map.into_iter()
+        .next()
+        .map(|(tag, _)| tag)
+        .ok_or_else(|| anyhow!("the request is serialized as an empty map"))
+}
+
+/// Redact the request, the hashed passwords are always dropped and the values of the
+/// puts are replaced by as many `*` if `values` is set
+fn redact(request: &mut RequestWrapper, values: bool) {
+    #[allow(clippy::wildcard_enum_match_arm)] // the others have nothing to redact
+    match *request {
+        RequestWrapper::AuthUserAddRequest(ref mut req) => req.hashed_password.clear(),
+        RequestWrapper::AuthUserChangePasswordRequest(ref mut req) => {
+            req.hashed_password.clear();
+        }
+        RequestWrapper::PutRequest(ref mut req) if values => redact_put(req),
+        RequestWrapper::TxnRequest(ref mut req) if values => redact_txn(req),
+        _ => {}
+    }
+}
+
+/// Redact the value of a put
+fn redact_put(req: &mut PutRequest) {
+    req.value.fill(b'*');
+}
+
+/// Redact the values of the puts of a txn and its nested txns
+fn redact_txn(req: &mut TxnRequest) {
+    for request in req
+        .success
+        .iter_mut()
+        .chain(req.failure.iter_mut())
+        .filter_map(|op| op.request.as_mut())
+    {
+        match *request {
+            Request::RequestPut(ref mut put) => redact_put(put),
+            Request::RequestTxn(ref mut txn) => redact_txn(txn),
+            Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+        }
+    }
+}
+
+/// Path of the previous journal file
+fn old_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(OLD_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Read the records of a journal in the order of their log entries, the previous file
+/// is read along with the active one
+///
+/// # Errors
+///
+/// Return error if the journal cannot be read or a record other than the last one of a
+/// file is malformed, the last one may be torn by a crash and is skipped then
+#[inline]
+pub fn read_journal(path: impl AsRef<Path>) -> Result<Vec<JournalRecord>> {
+    let path = path.as_ref();
+    let mut records = Vec::new();
+    for file in [old_path(path), path.to_owned()] {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = content.lines().filter(|l| !l.trim().is_empty()).peekable();
+        while let Some(line) = lines.next() {
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(_) if lines.peek().is_none() => {
+                    warn!("skipped the torn last record of {}", file.display());
+                }
+                Err(e) => return Err(anyhow!("malformed record in {}: {e}", file.display())),
+            }
+        }
+    }
+    records.sort_by_key(|record: &JournalRecord| record.index);
+    Ok(records)
+}
+
+/// The first record whose replayed revision differs from the recorded one
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Divergence {
+    /// Index of the log entry of the record
+    pub index: LogIndex,
+    /// Recorded revision
+    pub expected: i64,
+    /// Replayed revision
+    pub actual: i64,
+    /// Type of the request
+    pub request_type: String,
+}
+
+/// Result of a replay of a journal
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct JournalReplayReport {
+    /// Number of the records replayed
+    pub replayed: usize,
+    /// Number of the records skipped, by the type of the request
+    pub skipped: BTreeMap<String, usize>,
+    /// The first record replayed at another revision, if any
+    pub divergence: Option<Divergence>,
+}
+
+/// Re-issue the recorded requests one by one against the target endpoint
+///
+/// The kv and the lease requests are replayed, the others, such as the auth requests,
+/// the lease checkpoints and the alarms, are skipped. The continuations of a chunked
+/// lease revocation are skipped too, the target continues the revocations itself. The
+/// first replayed record fixes the offset of the revisions of the target to the
+/// recorded ones, a record replayed at another offset means that the target applies
+/// the sequence differently from the member that recorded it.
+///
+/// # Errors
+///
+/// Return error if the target cannot be reached or a request fails
+#[inline]
+pub async fn replay_journal(
+    records: &[JournalRecord],
+    target: String,
+) -> Result<JournalReplayReport> {
+    let mut kv = KvClient::connect(target.clone()).await?;
+    let mut lease = LeaseClient::connect(target).await?;
+    let mut offset = None;
+    let mut report = JournalReplayReport::default();
+    let mut revoked = HashSet::new();
+    for record in records {
+        let revision = match record.request {
+            RequestWrapper::PutRequest(ref req) => kv.put(req.clone()).await?.into_inner().header,
+            RequestWrapper::DeleteRangeRequest(ref req) => {
+                kv.delete_range(req.clone()).await?.into_inner().header
+            }
+            RequestWrapper::TxnRequest(ref req) => kv.txn(req.clone()).await?.into_inner().header,
+            RequestWrapper::CompactionRequest(ref req) => {
+                kv.compact(req.clone()).await?.into_inner().header
+            }
+            RequestWrapper::LeaseGrantRequest(ref req) => {
+                lease.lease_grant(req.clone()).await?.into_inner().header
+            }
+            RequestWrapper::LeaseRevokeRequest(ref req) if revoked.insert(req.id) => {
+                lease.lease_revoke(req.clone()).await?.into_inner().header
+            }
+            // the target has no batched revocation for clients, the leases are revoked
+            // one by one
+            RequestWrapper::LeaseRevokeBatchRequest(ref req)
+                if req.ids.iter().any(|id| !revoked.contains(id)) =>
+            {
+                let mut header = None;
+                for &id in &req.ids {
+                    if revoked.insert(id) {
+                        let request = LeaseRevokeRequest {
+                            id,
+                            ..Default::default()
+                        };
+                        header = lease.lease_revoke(request).await?.into_inner().header;
+                    }
+                }
+                header
+            }
+            RequestWrapper::LeaseRevokeRequest(_)
+            | RequestWrapper::LeaseRevokeBatchRequest(_)
+            | RequestWrapper::RangeRequest(_)
+            | RequestWrapper::AuthEnableRequest(_)
+            | RequestWrapper::AuthDisableRequest(_)
+            | RequestWrapper::AuthStatusRequest(_)
+            | RequestWrapper::AuthRoleAddRequest(_)
+            | RequestWrapper::AuthRoleDeleteRequest(_)
+            | RequestWrapper::AuthRoleGetRequest(_)
+            | RequestWrapper::AuthRoleGrantPermissionRequest(_)
+            | RequestWrapper::AuthRoleListRequest(_)
+            | RequestWrapper::AuthRoleRevokePermissionRequest(_)
+            | RequestWrapper::AuthUserAddRequest(_)
+            | RequestWrapper::AuthUserChangePasswordRequest(_)
+            | RequestWrapper::AuthUserDeleteRequest(_)
+            | RequestWrapper::AuthUserGetRequest(_)
+            | RequestWrapper::AuthUserGrantRoleRequest(_)
+            | RequestWrapper::AuthUserListRequest(_)
+            | RequestWrapper::AuthUserRevokeRoleRequest(_)
+            | RequestWrapper::AuthenticateRequest(_)
+            | RequestWrapper::LeaseLeasesRequest(_)
+            | RequestWrapper::LeaseCheckpointRequest(_)
+            | RequestWrapper::CompactionExemptionRequest(_)
+            | RequestWrapper::AlarmRequest(_) => {
+                let skipped = report
+                    .skipped
+                    .entry(record.request_type.clone())
+                    .or_default();
+                *skipped = skipped.saturating_add(1);
+                continue;
+            }
+        }
+        .map_or(0, |header| header.revision);
+        report.replayed = report.replayed.saturating_add(1);
+        let record_offset = revision.overflow_sub(record.revision);
+        if *offset.get_or_insert(record_offset) != record_offset && report.divergence.is_none() {
+            report.divergence = Some(Divergence {
+                index: record.index,
+                expected: record.revision,
+                actual: revision,
+                request_type: record.request_type.clone(),
+            });
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use super::*;
+    use crate::rpc::RequestOp;
+
+    fn clock() -> Arc<Clock> {
+        Arc::new(Clock::system())
+    }
+
+    fn journal_dir() -> PathBuf {
+        temp_dir().join(format!("xline-journal-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn put(key: &str, value: &str) -> RequestWrapper {
+        RequestWrapper::from(PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        })
+    }
+
+    fn config(path: PathBuf, max_size: u64, redact_values: bool) -> JournalConfig {
+        JournalConfig::new(
+            Some(path),
+            max_size,
+            Duration::from_secs(3600),
+            redact_values,
+        )
+    }
+
+    #[test]
+    fn journal_should_be_rotated_in_bounds() {
+        let dir = journal_dir();
+        let path = dir.join("journal");
+        let journal = Journal::open(&config(path.clone(), 4096, false), &[], clock())
+            .unwrap()
+            .unwrap();
+        for index in 1..=200 {
+            let cmd = Command::new(put(&format!("key{index:03}"), "value"));
+            journal.record(&cmd, (7, index), index, index.try_into().unwrap());
+            // reads are not journaled
+            let cmd = Command::new(RequestWrapper::from(xlineapi::RangeRequest::default()));
+            journal.record(&cmd, (7, index), index, 0);
+        }
+        drop(journal);
+
+        let size = |path: &Path| fs::metadata(path).map_or(0, |m| m.len());
+        assert!(size(&path) + size(&old_path(&path)) <= 4096 + 1024);
+        let records = read_journal(&path).unwrap();
+        assert!(!records.is_empty() && records.len() < 200);
+        // the newest records are kept in order
+        assert_eq!(records.last().unwrap().index, 200);
+        assert!(records.windows(2).all(|w| w[1].index == w[0].index + 1));
+        let last = records.last().unwrap();
+        assert_eq!(last.propose_id, "7#200");
+        assert_eq!(last.request_type, "PutRequest");
+        assert_eq!(last.request, put("key200", "value"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn values_should_be_redacted() {
+        let dir = journal_dir();
+        let path = dir.join("journal");
+        let journal = Journal::open(&config(path.clone(), 1 << 20, true), &[], clock())
+            .unwrap()
+            .unwrap();
+        let txn = RequestWrapper::from(TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(PutRequest {
+                    key: "foo".into(),
+                    value: "secret".into(),
+                    ..Default::default()
+                })),
+            }],
+            failure: vec![],
+        });
+        journal.record(&Command::new(txn), (1, 1), 1, 2);
+        let user_add = RequestWrapper::from(xlineapi::AuthUserAddRequest {
+            name: "user".into(),
+            hashed_password: "hash".into(),
+            ..Default::default()
+        });
+        journal.record(&Command::new(user_add), (1, 2), 2, 1);
+        let authenticate = RequestWrapper::from(xlineapi::AuthenticateRequest {
+            name: "user".into(),
+            password: "password".into(),
+        });
+        journal.record(&Command::new(authenticate), (1, 3), 3, 1);
+        drop(journal);
+
+        let records = read_journal(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.redacted));
+        let RequestWrapper::TxnRequest(ref txn) = records[0].request else {
+            panic!("unexpected request {:?}", records[0].request);
+        };
+        let Some(Request::RequestPut(ref put)) = txn.success[0].request else {
+            panic!("unexpected op {:?}", txn.success[0]);
+        };
+        assert_eq!(put.key, b"foo");
+        assert_eq!(put.value, b"******");
+        let RequestWrapper::AuthUserAddRequest(ref user_add) = records[1].request else {
+            panic!("unexpected request {:?}", records[1].request);
+        };
+        assert!(user_add.hashed_password.is_empty());
+        assert!(!fs::read_to_string(&path).unwrap().contains("password"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn journal_in_data_dir_should_be_rejected() {
+        let data_dir = journal_dir().join("data");
+        let path = data_dir.join("journal");
+        assert!(Journal::open(&config(path, 1024, false), &[&data_dir], clock()).is_err());
+        let off = JournalConfig::default();
+        assert!(Journal::open(&off, &[&data_dir], clock())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn torn_last_record_should_be_skipped() {
+        let dir = journal_dir();
+        let path = dir.join("journal");
+        let journal = Journal::open(&config(path.clone(), 1 << 20, false), &[], clock())
+            .unwrap()
+            .unwrap();
+        journal.record(&Command::new(put("foo", "bar")), (1, 1), 1, 2);
+        drop(journal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"index":2,"propose"#).unwrap();
+        assert_eq!(read_journal(&path).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
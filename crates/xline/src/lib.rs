@@ -164,6 +164,8 @@
     )
 )]
 
+/// Time source of the server
+mod clock;
 /// Header generator
 mod header_gen;
 /// Unique id generator
@@ -176,8 +178,15 @@ mod rpc {
 }
 /// Command conflict implementation
 mod conflict;
+/// Xline server embedded in the current process
+#[cfg(all(feature = "embedded", not(madsim)))]
+pub mod embedded;
+/// Client request journal
+pub mod journal;
 /// Xline metrics
 pub mod metrics;
+/// Offline log replay
+pub mod replay;
 /// restore module, only for test
 pub mod restore;
 /// Revision check
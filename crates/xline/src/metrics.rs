@@ -1,11 +1,28 @@
-use clippy_utilities::NumericCast;
+use std::{fmt, sync::Arc};
+
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use opentelemetry::{
-    metrics::{Counter, MetricsError},
+    metrics::{CallbackRegistration, Counter, Histogram, Meter, MetricsError},
     KeyValue,
 };
+use tokio::{sync::Semaphore, time::Instant};
 use tracing::error;
 use utils::define_metrics;
 
+use crate::{
+    server::{Accounting, ClientRates},
+    storage::{kvwatcher::WatchMemory, lease_store::LeaseCollection},
+};
+
+/// Number of clients whose lease grant rates are reported
+const TOP_LEASE_GRANT_CLIENTS: usize = 10;
+
+/// Number of clients whose throttled watch creation rates are reported
+const TOP_THROTTLED_WATCH_CLIENTS: usize = 10;
+
+/// Number of identities whose usage is reported, the rest are reported as one
+const TOP_ACCOUNTED_IDENTITIES: usize = 10;
+
 define_metrics! {
     "xline",
     slow_read_indexes_total: Counter<u64> = meter()
@@ -19,6 +36,42 @@ define_metrics! {
     lease_expired_total: Counter<u64> = meter()
         .u64_counter("lease_expired")
         .with_description("The total number of expired leases.")
+        .init(),
+    proposals_rejected_total: Counter<u64> = meter()
+        .u64_counter("proposals_rejected")
+        .with_description("The total number of mutating requests rejected as too many proposals are in flight.")
+        .init(),
+    watch_events_evicted_total: Counter<u64> = meter()
+        .u64_counter("watch_events_evicted")
+        .with_description("The total number of buffered watch events evicted as the watch memory budget is exceeded.")
+        .init(),
+    watch_watchers_victimized_total: Counter<u64> = meter()
+        .u64_counter("watch_watchers_victimized")
+        .with_description("The total number of watchers moved to victims as the watch memory budget is exceeded.")
+        .init(),
+    watch_audit_violations_total: Counter<u64> = meter()
+        .u64_counter("watch_audit_violations")
+        .with_description("The total number of audited watch deliveries violating the applied revisions, by violation.")
+        .init(),
+    watch_creations_throttled_total: Counter<u64> = meter()
+        .u64_counter("watch_creations_throttled")
+        .with_description("The total number of watch creations rejected as their clients create watchers too fast.")
+        .init(),
+    lease_grants_throttled_total: Counter<u64> = meter()
+        .u64_counter("lease_grants_throttled")
+        .with_description("The total number of lease grants rejected as their clients grant leases too fast.")
+        .init(),
+    sub_revision_mismatches_total: Counter<u64> = meter()
+        .u64_counter("sub_revision_mismatches")
+        .with_description("The total number of txns whose synced sub revisions mismatch the ones implied by their responses.")
+        .init(),
+    clock_jumps_total: Counter<u64> = meter()
+        .u64_counter("clock_jumps")
+        .with_description("The total number of detected jumps of the wall clock, by direction.")
+        .init(),
+    request_affected_keys: Histogram<u64> = meter()
+        .u64_histogram("request_affected_keys")
+        .with_description("The distribution of the number of keys affected by a single delete range or txn request.")
         .init()
 }
 
@@ -94,6 +147,222 @@ impl Metrics {
     }
 }
 
+/// Register the gauge of the mutating requests the rpc servers are proposing, measured
+/// by the permits taken from the admission semaphore
+pub(crate) fn register_inflight_proposals(semaphore: &Arc<Semaphore>, max_inflight: usize) {
+    let meter = meter();
+    let inflight = meter
+        .u64_observable_gauge("inflight_proposals")
+        .with_description("The number of mutating requests being proposed by the rpc servers.")
+        .init();
+    let semaphore = Arc::downgrade(semaphore);
+    if let Err(e) = meter.register_callback(&[inflight.as_any()], move |observer| {
+        if let Some(semaphore) = semaphore.upgrade() {
+            let used = max_inflight.overflow_sub(semaphore.available_permits());
+            observer.observe_u64(&inflight, used.numeric_cast(), &[]);
+        }
+    }) {
+        error!("failed to register inflight proposals callback: {e}");
+    }
+}
+
+/// Register the gauge of the bytes of the watch events buffered for all watchers
+pub(crate) fn register_watch_memory(memory: &Arc<WatchMemory>) {
+    let meter = meter();
+    let used = meter
+        .u64_observable_gauge("watch_memory_bytes")
+        .with_description("The bytes of the watch events buffered for all watchers.")
+        .init();
+    let memory = Arc::downgrade(memory);
+    if let Err(e) = meter.register_callback(&[used.as_any()], move |observer| {
+        if let Some(memory) = memory.upgrade() {
+            observer.observe_u64(&used, memory.used(), &[]);
+        }
+    }) {
+        error!("failed to register watch memory callback: {e}");
+    }
+}
+
+/// Register the gauge of the lease grant rates of the clients granting the most leases
+pub(crate) fn register_lease_grant_rates(rates: &Arc<ClientRates>) {
+    let meter = meter();
+    let rate = meter
+        .f64_observable_gauge("lease_grant_rate")
+        .with_description(
+            "The lease grants per second of the clients granting the most leases, by client.",
+        )
+        .init();
+    let rates = Arc::downgrade(rates);
+    if let Err(e) = meter.register_callback(&[rate.as_any()], move |observer| {
+        if let Some(rates) = rates.upgrade() {
+            for (client, value) in rates.top(TOP_LEASE_GRANT_CLIENTS, Instant::now()) {
+                observer.observe_f64(&rate, value, &[KeyValue::new("client", client)]);
+            }
+        }
+    }) {
+        error!("failed to register lease grant rates callback: {e}");
+    }
+}
+
+/// Register the gauge of the throttled watch creation rates of the clients throttled the
+/// most, the label is bounded by reporting those clients only
+pub(crate) fn register_watch_creation_throttled_rates(rates: &Arc<ClientRates>) {
+    let meter = meter();
+    let rate = meter
+        .f64_observable_gauge("watch_creation_throttled_rate")
+        .with_description(
+            "The throttled watch creations per second of the clients throttled the most, by client.",
+        )
+        .init();
+    let rates = Arc::downgrade(rates);
+    if let Err(e) = meter.register_callback(&[rate.as_any()], move |observer| {
+        if let Some(rates) = rates.upgrade() {
+            for (client, value) in rates.top(TOP_THROTTLED_WATCH_CLIENTS, Instant::now()) {
+                observer.observe_f64(&rate, value, &[KeyValue::new("client", client)]);
+            }
+        }
+    }) {
+        error!("failed to register watch creation throttled rates callback: {e}");
+    }
+}
+
+/// Register the gauges of the usage of the identities with the most requests
+pub(crate) fn register_usage_accounting(accounting: &Arc<Accounting>) {
+    let meter = meter();
+    let requests = meter
+        .u64_observable_gauge("identity_requests")
+        .with_description("The number of kv and lease requests, by identity.")
+        .init();
+    let bytes_written = meter
+        .u64_observable_gauge("identity_bytes_written")
+        .with_description("The bytes of the keys and values written, by identity.")
+        .init();
+    let watch_events = meter
+        .u64_observable_gauge("identity_watch_events")
+        .with_description("The number of watch events delivered, by identity.")
+        .init();
+    let active_leases = meter
+        .u64_observable_gauge("identity_active_leases")
+        .with_description("The number of active leases, by identity.")
+        .init();
+    let active_watchers = meter
+        .u64_observable_gauge("identity_active_watchers")
+        .with_description("The number of active watchers, by identity.")
+        .init();
+    let accounting = Arc::downgrade(accounting);
+    if let Err(e) = meter.register_callback(
+        &[
+            requests.as_any(),
+            bytes_written.as_any(),
+            watch_events.as_any(),
+            active_leases.as_any(),
+            active_watchers.as_any(),
+        ],
+        move |observer| {
+            let Some(accounting) = accounting.upgrade() else {
+                return;
+            };
+            for stats in accounting.top(TOP_ACCOUNTED_IDENTITIES) {
+                let attrs = [KeyValue::new("identity", stats.identity)];
+                observer.observe_u64(&requests, stats.requests, &attrs);
+                observer.observe_u64(&bytes_written, stats.bytes_written, &attrs);
+                observer.observe_u64(&watch_events, stats.watch_events, &attrs);
+                observer.observe_u64(&active_leases, stats.active_leases, &attrs);
+                observer.observe_u64(&active_watchers, stats.active_watchers, &attrs);
+            }
+        },
+    ) {
+        error!("failed to register usage accounting callback: {e}");
+    }
+}
+
+/// Lease metrics, fed from the replicated state of a lease store
+///
+/// The names mirror etcd's so that existing dashboards keep working.
+pub(crate) struct LeaseMetrics {
+    /// The total number of granted leases
+    pub(crate) granted_total: Counter<u64>,
+    /// The total number of revoked leases
+    pub(crate) revoked_total: Counter<u64>,
+    /// The total number of keep alive requests
+    pub(crate) renewed_total: Counter<u64>,
+    /// The ttl of leases at grant time
+    pub(crate) ttl: Histogram<u64>,
+    /// The registration of the lease count callback, unregistered on drop
+    lease_count_callback: Option<Box<dyn CallbackRegistration>>,
+}
+
+impl fmt::Debug for LeaseMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeaseMetrics")
+            .field("granted_total", &self.granted_total)
+            .field("revoked_total", &self.revoked_total)
+            .field("renewed_total", &self.renewed_total)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LeaseMetrics {
+    /// New `LeaseMetrics` on the global xline meter
+    pub(crate) fn new(lease_collection: &Arc<LeaseCollection>) -> Self {
+        Self::with_meter(meter(), lease_collection)
+    }
+
+    /// New `LeaseMetrics` on the given meter
+    pub(crate) fn with_meter(meter: &Meter, lease_collection: &Arc<LeaseCollection>) -> Self {
+        let lease_count = meter
+            .u64_observable_gauge("etcd_debugging_lease_count")
+            .with_description("The number of current leases.")
+            .init();
+        // the callback should not keep a dropped store alive
+        let collection = Arc::downgrade(lease_collection);
+        let registration = meter
+            .register_callback(&[lease_count.as_any()], move |observer| {
+                if let Some(collection) = collection.upgrade() {
+                    observer.observe_u64(
+                        &lease_count,
+                        collection.lease_count().numeric_cast(),
+                        &[],
+                    );
+                }
+            })
+            .map_err(|e| error!("failed to register lease count callback: {e}"))
+            .ok();
+        Self {
+            granted_total: meter
+                .u64_counter("etcd_debugging_lease_granted")
+                .with_description("The total number of granted leases.")
+                .init(),
+            revoked_total: meter
+                .u64_counter("etcd_debugging_lease_revoked")
+                .with_description("The total number of revoked leases.")
+                .init(),
+            renewed_total: meter
+                .u64_counter("etcd_debugging_lease_renewed")
+                .with_description("The number of renewed leases seen by the leader.")
+                .init(),
+            ttl: meter
+                .u64_histogram("etcd_debugging_lease_ttl_total")
+                .with_description("Bucketed histogram of lease TTLs.")
+                .init(),
+            lease_count_callback: registration,
+        }
+    }
+}
+
+impl Drop for LeaseMetrics {
+    fn drop(&mut self) {
+        // the meter outlives the store, a restarted store in the same process would
+        // otherwise leave one more callback behind
+        if let Some(mut registration) = self.lease_count_callback.take() {
+            if let Err(e) = registration.unregister() {
+                error!("failed to unregister lease count callback: {e}");
+            }
+        }
+    }
+}
+
 /// Get the actual fd used on macOS
 #[allow(
     clippy::as_conversions,
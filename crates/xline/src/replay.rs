@@ -0,0 +1,763 @@
+//! Deterministic offline replay of the curp log against a fresh store stack.
+//!
+//! The replayer is a debugging aid for divergence reports: it applies the
+//! persisted log of a node entry by entry, hashes the backend after every
+//! applied entry and compares the result against a reference, which is either
+//! a previously recorded hash trace or the backend of another node.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::Write as _,
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use curp::{cmd::CommandExecutor as _, server::DB as CurpDB, LogEntry, LogIndex};
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use utils::{barrier::IdBarrier, config::EngineConfig, table_names::META_TABLE};
+use xlineapi::command::Command;
+
+use crate::{
+    clock::Clock,
+    header_gen::HeaderGenerator,
+    rpc::RequestWrapper,
+    server::{
+        command::{CommandExecutor, APPLIED_INDEX_KEY},
+        IndexBarrier,
+    },
+    storage::{
+        compact::CompactTask,
+        db::DB,
+        index::{Index, IndexOperate},
+        kv_store::KvStoreInner,
+        kvwatcher::KvUpdates,
+        lease_store::LeaseCollection,
+        AlarmStore, AuthStore, KvStore, LeaseStore,
+    },
+};
+
+/// Channel size of the kv updates, updates are drained after every entry
+const KV_UPDATE_CHANNEL_SIZE: usize = 16;
+
+/// Default curp data directory under the data directory of a node
+const CURP_DIR: &str = "curp";
+
+/// State hash of the backend after an entry is applied
+pub type HashTrace = Vec<(LogIndex, u32)>;
+
+/// Parse a hash trace, one `<index> <hash in hex>` pair per line
+///
+/// # Errors
+///
+/// Return error if the trace is malformed
+#[inline]
+pub fn parse_trace(content: &str) -> Result<HashTrace> {
+    content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let mut parts = l.split_whitespace();
+            let (Some(index), Some(hash), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(anyhow!("malformed trace line: {l}"));
+            };
+            Ok((index.parse()?, u32::from_str_radix(hash, 16)?))
+        })
+        .collect()
+}
+
+/// Format a hash trace, the output can be parsed by `parse_trace`
+#[inline]
+#[must_use]
+pub fn format_trace(trace: &[(LogIndex, u32)]) -> String {
+    trace.iter().fold(String::new(), |mut out, &(index, hash)| {
+        let _ignore = writeln!(out, "{index} {hash:08x}");
+        out
+    })
+}
+
+/// The reference to compare the replayed state against
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Reference {
+    /// A previously recorded hash trace
+    Trace(HashTrace),
+    /// The data directory of another node, it must not be in use
+    ///
+    /// The log of the node, kept in the `curp` directory under it, is replayed along
+    /// so that the state is compared at every index, and the backend of the node is
+    /// compared at its applied index.
+    DataDir(PathBuf),
+}
+
+/// The first log entry whose replayed state differs from the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Mismatch {
+    /// Index of the entry
+    pub index: LogIndex,
+    /// Expected state hash
+    pub expected: u32,
+    /// Replayed state hash
+    pub actual: u32,
+    /// Decoded summary of the entry
+    pub summary: String,
+}
+
+/// Result of a replay
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ReplayReport {
+    /// State hash after each applied entry
+    pub trace: HashTrace,
+    /// The first mismatching entry, if any
+    pub mismatch: Option<Mismatch>,
+    /// Indices the reference has a hash for but no replayed entry reached, the replayed
+    /// state can't be compared there and the log is likely missing entries
+    pub unreached: Vec<LogIndex>,
+}
+
+/// Load all persisted log entries from a curp data directory
+///
+/// The directory is only read, it's left as it is for later investigations.
+///
+/// # Errors
+///
+/// Return error if the log cannot be read
+#[inline]
+pub fn load_log(curp_dir: impl AsRef<Path>) -> Result<Vec<LogEntry<Command>>> {
+    let (_voted_for, entries) = CurpDB::<Command>::read_log(curp_dir)?;
+    Ok(entries)
+}
+
+/// Decoded summary of a log entry
+#[inline]
+#[must_use]
+pub fn summary(entry: &LogEntry<Command>) -> String {
+    let detail = entry
+        .command()
+        .map(|cmd| format!(": {:?}", cmd.request()))
+        .unwrap_or_default();
+    format!(
+        "index {}, term {}, {}{detail}",
+        entry.index(),
+        entry.term(),
+        entry.kind()
+    )
+}
+
+/// Replay the log and compare the state against the reference
+///
+/// When replaying from a snapshot, entries already included in it are skipped.
+///
+/// # Errors
+///
+/// Return error if the stores cannot be built or an entry fails to apply
+#[inline]
+pub async fn replay(
+    entries: &[LogEntry<Command>],
+    snapshot: Option<&Path>,
+    reference: Option<Reference>,
+) -> Result<ReplayReport> {
+    let mut replayer = Replayer::new(snapshot).await?;
+    let start = replayer.last_applied()?;
+    let mut expected = Expected::new(reference, snapshot, start).await?;
+    let mut trace = HashTrace::new();
+    let mut mismatch = None;
+    let mut unreached: BTreeSet<LogIndex> = expected
+        .indices()
+        .into_iter()
+        .filter(|&i| i > start)
+        .collect();
+    for entry in entries.iter().filter(|e| e.index() > start) {
+        replayer.apply(entry).await?;
+        let actual = replayer.hash()?;
+        trace.push((entry.index(), actual));
+        if let Some(expected) = expected.at(entry.index()).await? {
+            let _reached = unreached.remove(&entry.index());
+            if mismatch.is_none() && expected != actual {
+                mismatch = Some(Mismatch {
+                    index: entry.index(),
+                    expected,
+                    actual,
+                    summary: summary(entry),
+                });
+            }
+        }
+    }
+    Ok(ReplayReport {
+        trace,
+        mismatch,
+        unreached: unreached.into_iter().collect(),
+    })
+}
+
+/// The state hashes a replay is compared against
+#[derive(Debug)]
+enum Expected {
+    /// Nothing to compare against
+    None,
+    /// Hashes of a recorded trace
+    Trace(HashMap<LogIndex, u32>),
+    /// The log of another node, replayed in lockstep so that every index is compared
+    Node {
+        /// Replayer of the node's log
+        replayer: Box<Replayer>,
+        /// Entries of the node's log not replayed yet
+        entries: VecDeque<LogEntry<Command>>,
+        /// Applied index and state hash of the node's backend, the backend itself is
+        /// expected at its applied index
+        target: (LogIndex, u32),
+    },
+}
+
+impl Expected {
+    /// Build the expected state of a reference, the entries up to `start` are in the
+    /// snapshot and never compared
+    async fn new(
+        reference: Option<Reference>,
+        snapshot: Option<&Path>,
+        start: LogIndex,
+    ) -> Result<Self> {
+        match reference {
+            None => Ok(Self::None),
+            Some(Reference::Trace(trace)) => Ok(Self::Trace(trace.into_iter().collect())),
+            Some(Reference::DataDir(dir)) => {
+                let entries = load_log(dir.join(CURP_DIR))?
+                    .into_iter()
+                    .filter(|e| e.index() > start)
+                    .collect();
+                let db = DB::open(&EngineConfig::RocksDB(dir))?;
+                Ok(Self::Node {
+                    replayer: Box::new(Replayer::new(snapshot).await?),
+                    entries,
+                    target: (last_applied(&db)?, db.hash()?),
+                })
+            }
+        }
+    }
+
+    /// Indices the reference has a state hash for
+    fn indices(&self) -> BTreeSet<LogIndex> {
+        match *self {
+            Self::None => BTreeSet::new(),
+            Self::Trace(ref trace) => trace.keys().copied().collect(),
+            Self::Node {
+                ref entries,
+                target,
+                ..
+            } => entries
+                .iter()
+                .map(LogEntry::index)
+                .chain(iter::once(target.0))
+                .collect(),
+        }
+    }
+
+    /// The expected state hash after the entry at `index` is applied, the log of the
+    /// reference node is replayed up to `index`
+    async fn at(&mut self, index: LogIndex) -> Result<Option<u32>> {
+        match *self {
+            Self::None => Ok(None),
+            Self::Trace(ref trace) => Ok(trace.get(&index).copied()),
+            Self::Node {
+                ref mut replayer,
+                ref mut entries,
+                target,
+            } => {
+                let mut reached = false;
+                while let Some(entry) = entries.pop_front() {
+                    if entry.index() > index {
+                        entries.push_front(entry);
+                        break;
+                    }
+                    replayer.apply(&entry).await?;
+                    reached = entry.index() == index;
+                }
+                if target.0 == index {
+                    return Ok(Some(target.1));
+                }
+                reached.then(|| replayer.hash()).transpose()
+            }
+        }
+    }
+}
+
+/// Read the applied index of a backend
+fn last_applied(db: &DB) -> Result<LogIndex> {
+    let Some(bytes) = db.get_value(META_TABLE, APPLIED_INDEX_KEY)? else {
+        return Ok(0);
+    };
+    let buf: [u8; 8] = bytes
+        .try_into()
+        .map_err(|e| anyhow!("cannot decode applied index, {e:?}"))?;
+    Ok(LogIndex::from_le_bytes(buf))
+}
+
+/// Store stack used to replay log entries
+#[derive(Debug)]
+struct Replayer {
+    /// The executor that applies commands
+    ce: CommandExecutor,
+    /// Backend of the replayed stores
+    db: Arc<DB>,
+    /// The replayed kv store
+    kv_storage: Arc<KvStore>,
+    /// Receiver of kv updates, nobody watches during a replay
+    kv_update_rx: mpsc::Receiver<KvUpdates>,
+    /// Compactions done by the inline compactor
+    compact_done_rx: mpsc::UnboundedReceiver<i64>,
+    /// Directory of the backend, removed on drop
+    work_dir: Option<PathBuf>,
+}
+
+impl Replayer {
+    /// Build a fresh store stack, restored from the snapshot if any
+    async fn new(snapshot: Option<&Path>) -> Result<Self> {
+        let (db, work_dir) = if let Some(snapshot) = snapshot {
+            let dir = std::env::temp_dir().join(format!("xline-replay-{}", uuid::Uuid::new_v4()));
+            crate::restore::restore(snapshot, &dir).await?;
+            (DB::open(&EngineConfig::RocksDB(dir.clone()))?, Some(dir))
+        } else {
+            (DB::open(&EngineConfig::Memory)?, None)
+        };
+
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let index = Arc::new(Index::new());
+        let (kv_update_tx, kv_update_rx) = mpsc::channel(KV_UPDATE_CHANNEL_SIZE);
+        let (compact_task_tx, mut compact_task_rx) = mpsc::channel::<CompactTask>(1);
+        let (compact_done_tx, compact_done_rx) = mpsc::unbounded_channel();
+        let kv_store_inner = Arc::new(KvStoreInner::new(Arc::clone(&index), Arc::clone(&db)));
+        let kv_storage = Arc::new(KvStore::new(
+            kv_store_inner,
+            Arc::clone(&header_gen),
+            kv_update_tx.clone(),
+            compact_task_tx,
+            Arc::clone(&lease_collection),
+        ));
+        // Compaction runs in the background on a real node, here every compaction
+        // finishes before the next entry is applied so that the hashes are stable.
+        let _handle = tokio::spawn({
+            let kv_storage = Arc::clone(&kv_storage);
+            let index = Arc::clone(&index);
+            async move {
+                while let Some((revision, exempt, event)) = compact_task_rx.recv().await {
+                    let revisions = index
+                        .compact(revision, &exempt)
+                        .into_iter()
+                        .map(|key_rev| key_rev.as_revision().encode_to_vec())
+                        .collect::<Vec<_>>();
+                    if let Err(e) = kv_storage
+                        .compact(&revisions)
+                        .and_then(|()| kv_storage.compact_finished(revision))
+                    {
+                        panic!("failed to compact revision {revision}: {e}");
+                    }
+                    if let Some(event) = event {
+                        let _ignore = event.notify(usize::MAX);
+                    }
+                    if compact_done_tx.send(revision).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        let lease_storage = Arc::new(LeaseStore::new(
+            Arc::clone(&lease_collection),
+            Arc::clone(&header_gen),
+            Arc::clone(&db),
+            index,
+            kv_update_tx,
+            false,
+            false,
+        ));
+        let auth_storage = Arc::new(AuthStore::new(
+            lease_collection,
+            None,
+            Arc::clone(&header_gen),
+            Arc::clone(&db),
+            None,
+            Arc::new(Clock::system()),
+        ));
+        let alarm_storage = Arc::new(AlarmStore::new(Arc::clone(&header_gen), Arc::clone(&db)));
+        // lease storage must recover before kv storage
+        lease_storage.recover()?;
+        kv_storage.recover().await?;
+        lease_storage.resume_revokes()?;
+        auth_storage.recover()?;
+        alarm_storage.recover()?;
+
+        let ce = CommandExecutor::new(
+            Arc::clone(&kv_storage),
+            auth_storage,
+            lease_storage,
+            alarm_storage,
+            Arc::clone(&db),
+            Arc::new(IndexBarrier::new()),
+            Arc::new(IdBarrier::new()),
+            header_gen.general_revision_arc(),
+            header_gen.auth_revision_arc(),
+            Arc::new(DashMap::new()),
+            u64::MAX,
+            Arc::new(Clock::system()),
+            None,
+        )?;
+        let mut replayer = Self {
+            ce,
+            db,
+            kv_storage,
+            kv_update_rx,
+            compact_done_rx,
+            work_dir,
+        };
+        replayer.drain();
+        Ok(replayer)
+    }
+
+    /// Applied index of the replayed backend
+    fn last_applied(&self) -> Result<LogIndex> {
+        Ok(self.ce.last_applied()?)
+    }
+
+    /// State hash of the replayed backend
+    fn hash(&self) -> Result<u32> {
+        Ok(self.db.hash()?)
+    }
+
+    /// Apply an entry the same way the curp command worker does
+    async fn apply(&mut self, entry: &LogEntry<Command>) -> Result<()> {
+        // No more entry is applied once the storage failed
+        if let Some(reason) = self.ce.storage_failure() {
+            return Err(anyhow!(
+                "the storage failed before index {}: {reason}",
+                entry.index()
+            ));
+        }
+        if let Some(cmd) = entry.command() {
+            // Entries failed in prepare or execute are never after synced
+            if let Ok(revision) = self.ce.prepare(cmd) {
+                if self.ce.execute(cmd).await.is_ok()
+                    && self
+                        .ce
+                        .after_sync(cmd, entry.index(), revision)
+                        .await
+                        .is_ok()
+                {
+                    self.wait_synced(revision).await;
+                }
+            }
+            if matches!(*cmd.request(), RequestWrapper::CompactionRequest(_)) {
+                let _ignore = self.compact_done_rx.recv().await;
+            }
+        } else if !entry.is_empty() {
+            self.ce.set_last_applied(entry.index())?;
+        }
+        self.drain();
+        Ok(())
+    }
+
+    /// Wait for the writes of the revision done in the background, such as the
+    /// tombstones of revoked leases, the kv updates are drained meanwhile
+    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    async fn wait_synced(&mut self, revision: i64) {
+        let kv_storage = Arc::clone(&self.kv_storage);
+        let synced = kv_storage.wait_synced(revision);
+        tokio::pin!(synced);
+        loop {
+            tokio::select! {
+                () = &mut synced => return,
+                update = self.kv_update_rx.recv() => {
+                    if update.is_none() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drain pending kv updates and finished compactions
+    fn drain(&mut self) {
+        while self.kv_update_rx.try_recv().is_ok() {}
+        while self.compact_done_rx.try_recv().is_ok() {}
+    }
+}
+
+impl Drop for Replayer {
+    fn drop(&mut self) {
+        if let Some(ref dir) = self.work_dir {
+            let _ignore = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Fault injection used to verify that the replayer localizes divergences
+#[cfg(feature = "replay-fault")]
+pub mod fault {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use curp::LogIndex;
+
+    use crate::{rpc::PbLease, storage::db::WriteOp};
+
+    /// The index to inject a fault at, 0 means disabled
+    static FAULT_INDEX: AtomicU64 = AtomicU64::new(0);
+
+    /// Inject a fault when the entry at `index` is after synced
+    #[inline]
+    pub fn set_fault_index(index: LogIndex) {
+        FAULT_INDEX.store(index, Ordering::Relaxed);
+    }
+
+    /// Append a bogus write to the ops of the faulty entry
+    pub(crate) fn inject(index: LogIndex, ops: &mut Vec<WriteOp<'_>>) {
+        if index != 0 && FAULT_INDEX.load(Ordering::Relaxed) == index {
+            ops.push(WriteOp::PutLease(PbLease {
+                id: i64::MAX,
+                ttl: 1,
+                remaining_ttl: 1,
+                owner: String::new(),
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn trace_format_and_parse_should_be_consistent() {
+        let trace = vec![(1, 0xdead_beef), (2, 0), (10, 0x0123_4567)];
+        let formatted = format_trace(&trace);
+        assert_eq!(formatted, "1 deadbeef\n2 00000000\n10 01234567\n");
+        assert_eq!(parse_trace(&formatted).unwrap(), trace);
+        assert!(parse_trace("1 2 3").is_err());
+        assert!(parse_trace("x 2").is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_should_report_the_unreached_reference() {
+        use curp::rpc::ProposeId;
+
+        use crate::rpc::PutRequest;
+
+        let entries = (1..=3)
+            .map(|i| {
+                let req = RequestWrapper::from(PutRequest {
+                    key: format!("key{i}").into_bytes(),
+                    value: b"value".to_vec(),
+                    ..Default::default()
+                });
+                LogEntry::new_command(i, 1, ProposeId(0, i), Arc::new(Command::new(req)))
+            })
+            .collect::<Vec<_>>();
+        let expected = replay(&entries, None, None).await.unwrap();
+        assert!(expected.unreached.is_empty());
+
+        let report = replay(&entries[..1], None, Some(Reference::Trace(expected.trace)))
+            .await
+            .unwrap();
+        assert!(report.mismatch.is_none());
+        assert_eq!(report.unreached, vec![2, 3]);
+    }
+
+    /// Contents of all files under a directory, keyed by their relative paths
+    fn dir_contents(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut contents = BTreeMap::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(d) = dirs.pop() {
+            for entry in std::fs::read_dir(d).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let data = std::fs::read(&path).unwrap();
+                    let _prev =
+                        contents.insert(path.strip_prefix(dir).unwrap().to_path_buf(), data);
+                }
+            }
+        }
+        contents
+    }
+
+    /// A put of `key<index>` at `index`
+    fn put_entry(index: LogIndex, value: &[u8]) -> LogEntry<Command> {
+        use curp::rpc::ProposeId;
+
+        use crate::rpc::PutRequest;
+
+        let req = RequestWrapper::from(PutRequest {
+            key: format!("key{index}").into_bytes(),
+            value: value.to_vec(),
+            ..Default::default()
+        });
+        LogEntry::new_command(index, 1, ProposeId(0, index), Arc::new(Command::new(req)))
+    }
+
+    /// Persist the log entries to a curp data directory the way a server does
+    async fn write_log(curp_dir: &Path, entries: &[LogEntry<Command>]) {
+        use curp::server::StorageApi as _;
+        use utils::config::CurpConfigBuilder;
+
+        let config = CurpConfigBuilder::default()
+            .engine_cfg(EngineConfig::RocksDB(curp_dir.to_path_buf()))
+            .build()
+            .unwrap();
+        let storage = CurpDB::<Command>::open(&config).unwrap();
+        let _recovered = storage.recover().await.unwrap();
+        storage.flush_voted_for(1, 1).await.unwrap();
+        for entry in entries {
+            storage.put_log_entry(entry).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn load_log_should_leave_the_data_dir_unchanged() {
+        let entries = (1..=3).map(|i| put_entry(i, b"value")).collect::<Vec<_>>();
+        let data_dir = std::env::temp_dir().join(format!("xline-replay-{}", uuid::Uuid::new_v4()));
+        let copy_dir = std::env::temp_dir().join(format!("xline-replay-{}", uuid::Uuid::new_v4()));
+        write_log(&data_dir, &entries).await;
+        for (path, data) in dir_contents(&data_dir) {
+            let dst = copy_dir.join(path);
+            std::fs::create_dir_all(dst.parent().unwrap()).unwrap();
+            std::fs::write(dst, data).unwrap();
+        }
+        let before = dir_contents(&copy_dir);
+
+        let loaded = load_log(&copy_dir).unwrap();
+        assert_eq!(
+            loaded.iter().map(LogEntry::index).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        let report = replay(&loaded, None, None).await.unwrap();
+        assert_eq!(report.trace.len(), 3);
+        assert_eq!(
+            report.trace,
+            replay(&entries, None, None).await.unwrap().trace
+        );
+        assert_eq!(dir_contents(&copy_dir), before);
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+        std::fs::remove_dir_all(copy_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_should_compare_the_data_dir_at_every_index() {
+        let entries = (1..=5).map(|i| put_entry(i, b"value")).collect::<Vec<_>>();
+        let mut divergent = entries.clone();
+        divergent[2] = put_entry(3, b"other");
+        let data_dir = std::env::temp_dir().join(format!("xline-replay-{}", uuid::Uuid::new_v4()));
+
+        // The backend of the node has applied nothing, only its log is compared
+        write_log(&data_dir.join(CURP_DIR), &entries).await;
+        let report = replay(&entries, None, Some(Reference::DataDir(data_dir.clone())))
+            .await
+            .unwrap();
+        assert!(report.mismatch.is_none());
+        assert!(report.unreached.is_empty());
+
+        let report = replay(&divergent, None, Some(Reference::DataDir(data_dir.clone())))
+            .await
+            .unwrap();
+        assert_eq!(report.mismatch.unwrap().index, 3);
+        let report = replay(
+            &entries[..2],
+            None,
+            Some(Reference::DataDir(data_dir.clone())),
+        )
+        .await
+        .unwrap();
+        assert!(report.mismatch.is_none());
+        assert_eq!(report.unreached, vec![3, 4, 5]);
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_writes_should_never_be_acknowledged() {
+        use curp::rpc::ProposeId;
+        use utils::table_names::KV_TABLE;
+
+        use crate::rpc::PutRequest;
+
+        let put = |i: u64| {
+            let req = RequestWrapper::from(PutRequest {
+                key: format!("key{i}").into_bytes(),
+                value: b"value".to_vec(),
+                ..Default::default()
+            });
+            LogEntry::new_command(i, 1, ProposeId(0, i), Arc::new(Command::new(req)))
+        };
+        let mut replayer = Replayer::new(None).await.unwrap();
+        replayer.apply(&put(1)).await.unwrap();
+        assert!(replayer.ce.storage_failure().is_none());
+        // a corruption detected by a read doesn't stop the applies
+        replayer.db.mark_corrupted("read fault".to_owned());
+        assert!(replayer.ce.storage_failure().is_none());
+
+        replayer.db.inject_write_fault(KV_TABLE);
+        let entry = put(2);
+        let cmd = entry.command().unwrap();
+        let revision = replayer.ce.prepare(cmd).unwrap();
+        let _er = replayer.ce.execute(cmd).await.unwrap();
+        assert!(replayer.ce.after_sync(cmd, 2, revision).await.is_err());
+
+        // the whole batch is lost, the applied index included, and the node is poisoned
+        assert_eq!(replayer.last_applied().unwrap(), 1);
+        assert_eq!(replayer.db.get_all(KV_TABLE).unwrap().len(), 1);
+        assert!(replayer.ce.storage_failure().is_some());
+        assert!(replayer.apply(&put(3)).await.is_err());
+        assert_eq!(replayer.last_applied().unwrap(), 1);
+    }
+
+    #[cfg(feature = "replay-fault")]
+    #[tokio::test]
+    async fn replay_should_localize_the_first_divergent_entry() {
+        use curp::rpc::ProposeId;
+
+        use crate::rpc::PutRequest;
+
+        let entries = (1..=5)
+            .map(|i| {
+                let req = RequestWrapper::from(PutRequest {
+                    key: format!("key{i}").into_bytes(),
+                    value: b"value".to_vec(),
+                    ..Default::default()
+                });
+                LogEntry::new_command(i, 1, ProposeId(0, i), Arc::new(Command::new(req)))
+            })
+            .collect::<Vec<_>>();
+
+        let expected = replay(&entries, None, None).await.unwrap();
+        assert_eq!(expected.trace.len(), 5);
+        assert!(expected.mismatch.is_none());
+        let again = replay(
+            &entries,
+            None,
+            Some(Reference::Trace(expected.trace.clone())),
+        )
+        .await
+        .unwrap();
+        assert!(again.mismatch.is_none(), "replay should be deterministic");
+
+        fault::set_fault_index(3);
+        let report = replay(&entries, None, Some(Reference::Trace(expected.trace)))
+            .await
+            .unwrap();
+        fault::set_fault_index(0);
+        let mismatch = report.mismatch.unwrap();
+        assert_eq!(mismatch.index, 3);
+        assert!(
+            mismatch
+                .summary
+                .starts_with("index 3, term 1, Command: PutRequest"),
+            "{}",
+            mismatch.summary
+        );
+    }
+}
@@ -5,7 +5,7 @@ use bytes::BytesMut;
 use clippy_utilities::NumericCast;
 use engine::{Engine, EngineType, Snapshot, SnapshotApi, StorageEngine};
 use tokio_util::io::read_buf;
-use utils::table_names::XLINE_TABLES;
+use utils::table_names::{REPLICATED_TABLES, XLINE_TABLES};
 
 use crate::server::MAINTENANCE_SNAPSHOT_CHUNK_SIZE;
 
@@ -32,7 +32,7 @@ pub async fn restore<P: AsRef<Path>, D: Into<PathBuf>>(
 
     let restore_rocks_engine = Engine::new(EngineType::Rocks(data_dir.into()), &XLINE_TABLES)?;
     restore_rocks_engine
-        .apply_snapshot(rocks_snapshot, &XLINE_TABLES)
+        .apply_snapshot(rocks_snapshot, &REPLICATED_TABLES)
         .await?;
     Ok(())
 }
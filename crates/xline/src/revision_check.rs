@@ -1,6 +1,9 @@
 use xlineapi::execute_error::ExecuteError;
 
-use crate::rpc::{CompactionRequest, RangeRequest, Request, TxnRequest};
+use crate::{
+    rpc::{CompactionRequest, RangeRequest, Request, TxnRequest},
+    storage::compact::ExemptPrefixes,
+};
 
 /// A union of requests that need revision check
 pub(crate) enum RevisionRequest<'a> {
@@ -38,6 +41,15 @@ pub(crate) trait RevisionCheck {
         compacted_revision: i64,
         current_revision: i64,
     ) -> Result<(), ExecuteError>;
+
+    /// check if the request is valid given the compacted and current revision, a range
+    /// under the exempt prefixes is compacted only below the revision it is kept since
+    fn check_revision_exempt(
+        self,
+        compacted_revision: i64,
+        current_revision: i64,
+        exempt: &ExemptPrefixes,
+    ) -> Result<(), ExecuteError>;
 }
 
 impl<'a, T> RevisionCheck for &'a T
@@ -48,6 +60,19 @@ where
         self,
         compacted_revision: i64,
         current_revision: i64,
+    ) -> Result<(), ExecuteError> {
+        self.check_revision_exempt(
+            compacted_revision,
+            current_revision,
+            &ExemptPrefixes::default(),
+        )
+    }
+
+    fn check_revision_exempt(
+        self,
+        compacted_revision: i64,
+        current_revision: i64,
+        exempt: &ExemptPrefixes,
     ) -> Result<(), ExecuteError> {
         debug_assert!(
             compacted_revision <= current_revision,
@@ -71,6 +96,9 @@ where
                 if r.revision > current_revision {
                     Err(ExecuteError::RevisionTooLarge(r.revision, current_revision))
                 } else {
+                    // the error carries the earliest revision the range can be read at
+                    let compacted_revision =
+                        exempt.compacted_revision_of(&r.key, &r.range_end, compacted_revision);
                     (r.revision >= compacted_revision || r.revision <= 0)
                         .then_some(())
                         .ok_or(ExecuteError::RevisionCompacted(
@@ -84,10 +112,18 @@ where
                     if let Some(ref req) = op.request {
                         match *req {
                             Request::RequestRange(ref req) => {
-                                req.check_revision(compacted_revision, current_revision)?;
+                                req.check_revision_exempt(
+                                    compacted_revision,
+                                    current_revision,
+                                    exempt,
+                                )?;
                             }
                             Request::RequestTxn(ref req) => {
-                                req.check_revision(compacted_revision, current_revision)?;
+                                req.check_revision_exempt(
+                                    compacted_revision,
+                                    current_revision,
+                                    exempt,
+                                )?;
                             }
                             Request::RequestPut(_) | Request::RequestDeleteRange(_) => (),
                         }
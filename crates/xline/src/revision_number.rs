@@ -1,5 +1,17 @@
+//! Allocation of the revisions
+//!
+//! The apply of one log entry reserves exactly one main revision up front through
+//! [`RevisionNumberGenerator::reserve`], or none if the entry doesn't mutate the state
+//! or fails before it's applied. The writes are checked against the state before the
+//! revision is reserved, so an entry whose execution fails reserves none. All the writes of the entry, such as the operations
+//! of a txn or the keys of a range deletion, share that main revision and take their
+//! sub revisions from the [`SubRevisions`] local to the apply, so the entries applied
+//! in parallel never share a main revision and a store never allocates one itself.
+
 use std::sync::atomic::{AtomicI64, Ordering};
 
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+
 /// Revision number
 #[derive(Debug)]
 pub(crate) struct RevisionNumberGenerator(AtomicI64);
@@ -20,6 +32,16 @@ impl RevisionNumberGenerator {
         self.0.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
     }
 
+    /// Reserve the main revision of a log entry, returns `None` without reserving
+    /// if the entry doesn't mutate the state
+    pub(crate) fn reserve(&self, mutating: bool) -> Option<i64> {
+        mutating.then(|| {
+            let revision = self.next();
+            debug_assert!(revision > 0, "revision {revision} overflows");
+            revision
+        })
+    }
+
     /// Set the revision number
     pub(crate) fn set(&self, rev: i64) {
         self.0.store(rev, Ordering::Relaxed);
@@ -32,3 +54,61 @@ impl Default for RevisionNumberGenerator {
         RevisionNumberGenerator::new(1)
     }
 }
+
+/// Sub revisions of the writes of a log entry under its main revision
+#[derive(Debug)]
+pub(crate) struct SubRevisions {
+    /// The main revision reserved by the entry
+    revision: i64,
+    /// The next sub revision to take
+    next: i64,
+}
+
+impl SubRevisions {
+    /// New `SubRevisions` of the main revision
+    pub(crate) fn new(revision: i64) -> Self {
+        debug_assert!(
+            revision > 0,
+            "writes are applied without a reserved revision"
+        );
+        Self { revision, next: 0 }
+    }
+
+    /// The main revision
+    pub(crate) fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// The sub revision the next write starts at
+    pub(crate) fn next(&self) -> i64 {
+        self.next
+    }
+
+    /// Mark the `n` sub revisions from the next one as taken by a write
+    pub(crate) fn take(&mut self, n: usize) {
+        self.next = self.next.overflow_add(n.numeric_cast());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entries_should_reserve_one_revision_at_most() {
+        let generator = RevisionNumberGenerator::default();
+        assert_eq!(generator.reserve(false), None);
+        assert_eq!(generator.reserve(true), Some(2));
+        assert_eq!(generator.reserve(false), None);
+        assert_eq!(generator.reserve(true), Some(3));
+        assert_eq!(generator.get(), 3);
+
+        let mut sub_revisions = SubRevisions::new(3);
+        assert_eq!(sub_revisions.next(), 0);
+        sub_revisions.take(1);
+        sub_revisions.take(0);
+        sub_revisions.take(3);
+        assert_eq!(sub_revisions.next(), 4);
+        assert_eq!(sub_revisions.revision(), 3);
+    }
+}
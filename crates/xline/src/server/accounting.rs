@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::RwLock;
+use xlineapi::AuthInfo;
+
+use crate::{
+    rpc::IdentityUsage,
+    storage::{lease_store::LeaseCollection, AuthStore},
+};
+
+/// Identity of all requests when auth is disabled
+pub(crate) const ANONYMOUS_IDENTITY: &str = "anonymous";
+
+/// Identity aggregating the ones outside the top ranked
+pub(crate) const OTHER_IDENTITY: &str = "other";
+
+/// Usage counters of an identity
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    /// Number of kv and lease requests
+    requests: AtomicU64,
+    /// Bytes of the keys and values written
+    bytes_written: AtomicU64,
+    /// Number of watch events delivered
+    watch_events: AtomicU64,
+    /// Number of active watchers
+    active_watchers: AtomicU64,
+}
+
+impl Usage {
+    /// Record a request
+    pub(crate) fn record_request(&self) {
+        let _prev = self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the bytes written by a succeeded request
+    pub(crate) fn record_written(&self, bytes: usize) {
+        let _prev = self
+            .bytes_written
+            .fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// Record watch events delivered to a watcher
+    pub(crate) fn record_watch_events(&self, events: usize) {
+        let _prev = self
+            .watch_events
+            .fetch_add(u64::try_from(events).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// Record a watcher created
+    pub(crate) fn add_watcher(&self) {
+        let _prev = self.active_watchers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a watcher canceled
+    pub(crate) fn remove_watcher(&self) {
+        let _prev = self
+            .active_watchers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    /// Snapshot of the counters
+    fn stats(&self, identity: String) -> UsageStats {
+        UsageStats {
+            identity,
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            watch_events: self.watch_events.load(Ordering::Relaxed),
+            active_leases: 0,
+            active_watchers: self.active_watchers.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Usage of an identity at a point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UsageStats {
+    /// The identity
+    pub(crate) identity: String,
+    /// Number of kv and lease requests
+    pub(crate) requests: u64,
+    /// Bytes of the keys and values written
+    pub(crate) bytes_written: u64,
+    /// Number of watch events delivered
+    pub(crate) watch_events: u64,
+    /// Number of active leases
+    pub(crate) active_leases: u64,
+    /// Number of active watchers
+    pub(crate) active_watchers: u64,
+}
+
+impl UsageStats {
+    /// Empty usage of an identity
+    fn empty(identity: String) -> Self {
+        Self {
+            identity,
+            requests: 0,
+            bytes_written: 0,
+            watch_events: 0,
+            active_leases: 0,
+            active_watchers: 0,
+        }
+    }
+
+    /// Add the usage of another identity
+    fn merge(&mut self, other: &Self) {
+        self.requests = self.requests.saturating_add(other.requests);
+        self.bytes_written = self.bytes_written.saturating_add(other.bytes_written);
+        self.watch_events = self.watch_events.saturating_add(other.watch_events);
+        self.active_leases = self.active_leases.saturating_add(other.active_leases);
+        self.active_watchers = self.active_watchers.saturating_add(other.active_watchers);
+    }
+}
+
+impl From<UsageStats> for IdentityUsage {
+    fn from(stats: UsageStats) -> Self {
+        Self {
+            identity: stats.identity,
+            requests: stats.requests,
+            bytes_written: stats.bytes_written,
+            watch_events: stats.watch_events,
+            active_leases: stats.active_leases,
+            active_watchers: stats.active_watchers,
+        }
+    }
+}
+
+/// Usage accounting of each authenticated user of this node
+///
+/// The counters are keyed by the username, not the token, so that they survive token
+/// refreshes. All requests go to a single anonymous bucket when auth is disabled, which
+/// takes no lock. The number of buckets is bounded by the users created by root.
+/// Active leases are not counted here but read from the owners of the leases.
+#[derive(Debug)]
+pub(crate) struct Accounting {
+    /// Auth storage, by which the owners of the leases are interpreted
+    auth_storage: Arc<AuthStore>,
+    /// Lease collection, by which the active leases are counted
+    lease_collection: Arc<LeaseCollection>,
+    /// Usage of the requests without a user
+    anonymous: Arc<Usage>,
+    /// Usage of each user
+    users: RwLock<HashMap<String, Arc<Usage>>>,
+}
+
+impl Accounting {
+    /// New `Accounting`
+    pub(crate) fn new(
+        auth_storage: Arc<AuthStore>,
+        lease_collection: Arc<LeaseCollection>,
+    ) -> Self {
+        Self {
+            auth_storage,
+            lease_collection,
+            anonymous: Arc::new(Usage::default()),
+            users: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The usage of the user of a request, the anonymous one if there's no user
+    pub(crate) fn usage(&self, auth_info: Option<&AuthInfo>) -> Arc<Usage> {
+        let Some(auth_info) = auth_info else {
+            return Arc::clone(&self.anonymous);
+        };
+        if let Some(usage) = self.users.read().get(&auth_info.username) {
+            return Arc::clone(usage);
+        }
+        Arc::clone(
+            self.users
+                .write()
+                .entry(auth_info.username.clone())
+                .or_default(),
+        )
+    }
+
+    /// Record a request of the user
+    pub(crate) fn record_request(&self, auth_info: Option<&AuthInfo>) {
+        self.usage(auth_info).record_request();
+    }
+
+    /// The usage of all identities, in descending order of the requests
+    pub(crate) fn stats(&self) -> Vec<UsageStats> {
+        let mut stats: HashMap<String, UsageStats> = self
+            .users
+            .read()
+            .iter()
+            .map(|(user, usage)| (user.clone(), usage.stats(user.clone())))
+            .collect();
+        let mut anonymous = self.anonymous.stats(ANONYMOUS_IDENTITY.to_owned());
+        if self.auth_storage.is_enabled() {
+            // the leases granted before auth is enabled are owned by peer addresses
+            for (owner, count) in self.lease_collection.owner_lease_counts() {
+                stats
+                    .entry(owner.clone())
+                    .or_insert_with(|| UsageStats::empty(owner))
+                    .active_leases = u64::try_from(count).unwrap_or(u64::MAX);
+            }
+        } else {
+            anonymous.active_leases =
+                u64::try_from(self.lease_collection.lease_count()).unwrap_or(u64::MAX);
+        }
+        if anonymous != UsageStats::empty(ANONYMOUS_IDENTITY.to_owned()) {
+            let _prev = stats.insert(ANONYMOUS_IDENTITY.to_owned(), anonymous);
+        }
+        let mut stats: Vec<_> = stats.into_values().collect();
+        stats.sort_unstable_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.identity.cmp(&b.identity))
+        });
+        stats
+    }
+
+    /// The usage of the `n` identities with the most requests, the rest are summed up
+    /// as the other identity, so that the cardinality of the metrics is bounded
+    pub(crate) fn top(&self, n: usize) -> Vec<UsageStats> {
+        let mut stats = self.stats();
+        if stats.len() <= n {
+            return stats;
+        }
+        let mut other = UsageStats::empty(OTHER_IDENTITY.to_owned());
+        for rest in stats.drain(n..) {
+            other.merge(&rest);
+        }
+        stats.push(other);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::config::EngineConfig;
+
+    use super::*;
+    use crate::{clock::Clock, header_gen::HeaderGenerator, storage::db::DB};
+
+    fn user(name: &str) -> AuthInfo {
+        AuthInfo {
+            username: name.to_owned(),
+            auth_revision: 1,
+            external: false,
+            roles: vec![],
+        }
+    }
+
+    fn new_accounting() -> (Accounting, Arc<LeaseCollection>) {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let auth_storage = Arc::new(AuthStore::new(
+            Arc::clone(&lease_collection),
+            None,
+            Arc::new(HeaderGenerator::new(0, 0)),
+            db,
+            None,
+            Arc::new(Clock::system()),
+        ));
+        (
+            Accounting::new(auth_storage, Arc::clone(&lease_collection)),
+            lease_collection,
+        )
+    }
+
+    #[test]
+    fn usage_should_be_split_by_user() {
+        let (accounting, _leases) = new_accounting();
+        let (alice, bob) = (user("alice"), user("bob"));
+        for i in 0..300 {
+            let usage = accounting.usage(Some(&alice));
+            usage.record_request();
+            usage.record_written(10);
+            if i % 3 == 0 {
+                let usage = accounting.usage(Some(&bob));
+                usage.record_request();
+                usage.record_written(100);
+            }
+        }
+        // a refreshed token of the same user shares the same bucket
+        let refreshed = AuthInfo {
+            auth_revision: 2,
+            ..user("bob")
+        };
+        let watch = accounting.usage(Some(&refreshed));
+        watch.add_watcher();
+        watch.record_watch_events(5);
+
+        let stats = accounting.stats();
+        assert_eq!(stats.len(), 2);
+        let total: u64 = stats.iter().map(|s| s.requests).sum();
+        let alice_percent = stats[0].requests * 100 / total;
+        assert_eq!(stats[0].identity, "alice");
+        assert!((74..=76).contains(&alice_percent), "share {alice_percent}%");
+        assert_eq!(stats[0].bytes_written, 3000);
+        assert_eq!(stats[1].identity, "bob");
+        assert_eq!(stats[1].bytes_written, 10000);
+        assert_eq!(stats[1].watch_events, 5);
+        assert_eq!(stats[1].active_watchers, 1);
+
+        let top = accounting.top(1);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[1].identity, OTHER_IDENTITY);
+        assert_eq!(top[1].requests, 100);
+    }
+
+    #[test]
+    fn requests_without_user_should_be_anonymous() {
+        let (accounting, leases) = new_accounting();
+        assert!(accounting.stats().is_empty());
+        accounting.record_request(None);
+        let _lease = leases.grant(1, 10, false);
+        let stats = accounting.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].identity, ANONYMOUS_IDENTITY);
+        assert_eq!(stats[0].requests, 1);
+        // all leases are anonymous when auth is disabled
+        assert_eq!(stats[0].active_leases, 1);
+    }
+}
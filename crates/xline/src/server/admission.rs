@@ -0,0 +1,404 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use curp::{
+    client::ClientApi,
+    members::ServerId,
+    rpc::{ConfChange, FetchClusterResponse, Member, ProposeId, ReadState},
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use xlineapi::{
+    command::{Command, CommandResponse, CurpClient, SyncResponse},
+    execute_error::ExecuteError,
+};
+
+use super::read_only::{is_read_only, read_only_error};
+use crate::metrics::{self, Metrics};
+
+/// Error message returned for mutating requests rejected by the admission control,
+/// the same as the one of etcd
+pub(crate) const TOO_MANY_REQUESTS_ERR_MSG: &str = "etcdserver: too many requests";
+
+/// Build the error returned for mutating requests rejected by the admission control,
+/// clients treat `ResourceExhausted` as retriable
+pub(crate) fn too_many_requests_error() -> tonic::Status {
+    tonic::Status::resource_exhausted(TOO_MANY_REQUESTS_ERR_MSG)
+}
+
+/// Consensus client handed to the rpc servers, which admits the proposals that may
+/// mutate the cluster
+///
+/// In read-only mode, any proposal that would mutate the cluster is rejected before it
+/// leaves the node. Otherwise a mutating request has to take a permit before it is
+/// proposed and holds it until the proposal returns, a request finding no permit left
+/// is rejected at once. The rpc servers build the command by moving the request in, so
+/// nothing is encoded or copied until the permit is taken. Reads and cluster metadata
+/// queries bypass both. The internal client of the node is not wrapped, so the node can
+/// still publish itself and raise alarms.
+pub(crate) struct AdmissionClient {
+    /// The wrapped client
+    inner: Arc<CurpClient>,
+    /// Whether the read-only mode is enabled, it could be switched on at runtime
+    read_only: Arc<AtomicBool>,
+    /// Permits of the mutating requests, `None` if they are unlimited
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl AdmissionClient {
+    /// New `AdmissionClient` which admits at most `max_inflight` mutating requests, 0
+    /// means unlimited
+    pub(crate) fn new(
+        inner: Arc<CurpClient>,
+        read_only: Arc<AtomicBool>,
+        max_inflight: usize,
+    ) -> Self {
+        let permits = (max_inflight > 0).then(|| {
+            let permits = Arc::new(Semaphore::new(max_inflight));
+            metrics::register_inflight_proposals(&permits, max_inflight);
+            permits
+        });
+        Self {
+            inner,
+            read_only,
+            permits,
+        }
+    }
+
+    /// Reject the request in read-only mode
+    fn check_writable(&self) -> Result<(), tonic::Status> {
+        if self.read_only.load(Ordering::Relaxed) {
+            return Err(read_only_error());
+        }
+        Ok(())
+    }
+
+    /// Admit a command, the returned permit is held until its proposal returns
+    fn admit(&self, cmd: &Command) -> Result<Option<SemaphorePermit<'_>>, tonic::Status> {
+        if is_read_only(cmd.request()) {
+            return Ok(None);
+        }
+        self.check_writable()?;
+        let Some(ref permits) = self.permits else {
+            return Ok(None);
+        };
+        let Ok(permit) = permits.try_acquire() else {
+            Metrics::get().proposals_rejected_total.add(1, &[]);
+            return Err(too_many_requests_error());
+        };
+        Ok(Some(permit))
+    }
+}
+
+#[async_trait]
+impl ClientApi for AdmissionClient {
+    /// The client error
+    type Error = tonic::Status;
+
+    /// The command type
+    type Cmd = Command;
+
+    /// Mutating commands are proposed only if they are admitted
+    async fn propose(
+        &self,
+        cmd: &Command,
+        token: Option<&String>,
+        use_fast_path: bool,
+    ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status> {
+        let _permit = self.admit(cmd)?;
+        self.inner.propose(cmd, token, use_fast_path).await
+    }
+
+    /// Async proposals hold a permit until the cmd is accepted
+    async fn propose_async(
+        &self,
+        cmd: &Command,
+        token: Option<&String>,
+    ) -> Result<ProposeId, tonic::Status> {
+        let _permit = self.admit(cmd)?;
+        self.inner.propose_async(cmd, token).await
+    }
+
+    /// Waiting for a result leaves the cluster untouched, let it through
+    async fn wait_synced(
+        &self,
+        propose_id: ProposeId,
+    ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status> {
+        self.inner.wait_synced(propose_id).await
+    }
+
+    /// Canceling a proposal never mutates the cluster, let it through
+    async fn cancel(&self, propose_id: ProposeId) -> Result<bool, tonic::Status> {
+        self.inner.cancel(propose_id).await
+    }
+
+    /// Configuration changes are rejected in read-only mode
+    async fn propose_conf_change(
+        &self,
+        changes: Vec<ConfChange>,
+    ) -> Result<Vec<Member>, tonic::Status> {
+        self.check_writable()?;
+        self.inner.propose_conf_change(changes).await
+    }
+
+    /// Shutdown is rejected in read-only mode
+    async fn propose_shutdown(&self) -> Result<(), tonic::Status> {
+        self.check_writable()?;
+        self.inner.propose_shutdown().await
+    }
+
+    /// Publish only updates the metadata of a node, let it through
+    async fn propose_publish(
+        &self,
+        node_id: ServerId,
+        node_name: String,
+        node_client_urls: Vec<String>,
+    ) -> Result<(), tonic::Status> {
+        self.inner
+            .propose_publish(node_id, node_name, node_client_urls)
+            .await
+    }
+
+    /// Leader transfer is rejected in read-only mode
+    async fn move_leader(&self, node_id: ServerId) -> Result<(), tonic::Status> {
+        self.check_writable()?;
+        self.inner.move_leader(node_id).await
+    }
+
+    /// Send fetch read state from leader
+    async fn fetch_read_state(&self, cmd: &Command) -> Result<ReadState, tonic::Status> {
+        self.inner.fetch_read_state(cmd).await
+    }
+
+    /// Send fetch cluster requests to all servers
+    async fn fetch_cluster(
+        &self,
+        linearizable: bool,
+    ) -> Result<FetchClusterResponse, tonic::Status> {
+        self.inner.fetch_cluster(linearizable).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::rpc::{PutRequest, RangeRequest, RequestWrapper};
+
+    /// A client whose proposals wait until they are released
+    #[derive(Default)]
+    struct BlockingClient {
+        /// Notified to release the pending proposals
+        release: Notify,
+        /// Bytes of the values of the pending proposals
+        held: AtomicUsize,
+        /// Max bytes of the values ever pending at the same time
+        max_held: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ClientApi for BlockingClient {
+        type Error = tonic::Status;
+
+        type Cmd = Command;
+
+        async fn propose(
+            &self,
+            cmd: &Command,
+            _token: Option<&String>,
+            _use_fast_path: bool,
+        ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status>
+        {
+            let bytes = if let RequestWrapper::PutRequest(ref req) = *cmd.request() {
+                req.value.len()
+            } else {
+                0
+            };
+            let held = self.held.fetch_add(bytes, Ordering::Relaxed) + bytes;
+            let _prev = self.max_held.fetch_max(held, Ordering::Relaxed);
+            self.release.notified().await;
+            let _prev = self.held.fetch_sub(bytes, Ordering::Relaxed);
+            Err(tonic::Status::unavailable("released"))
+        }
+
+        async fn propose_async(
+            &self,
+            _cmd: &Command,
+            _token: Option<&String>,
+        ) -> Result<ProposeId, tonic::Status> {
+            unreachable!()
+        }
+
+        async fn wait_synced(
+            &self,
+            _propose_id: ProposeId,
+        ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status>
+        {
+            unreachable!()
+        }
+
+        async fn cancel(&self, _propose_id: ProposeId) -> Result<bool, tonic::Status> {
+            unreachable!()
+        }
+
+        async fn propose_conf_change(
+            &self,
+            _changes: Vec<ConfChange>,
+        ) -> Result<Vec<Member>, tonic::Status> {
+            unreachable!()
+        }
+
+        async fn propose_shutdown(&self) -> Result<(), tonic::Status> {
+            unreachable!()
+        }
+
+        async fn propose_publish(
+            &self,
+            _node_id: ServerId,
+            _node_name: String,
+            _node_client_urls: Vec<String>,
+        ) -> Result<(), tonic::Status> {
+            unreachable!()
+        }
+
+        async fn move_leader(&self, _node_id: ServerId) -> Result<(), tonic::Status> {
+            unreachable!()
+        }
+
+        async fn fetch_read_state(&self, _cmd: &Command) -> Result<ReadState, tonic::Status> {
+            unreachable!()
+        }
+
+        async fn fetch_cluster(
+            &self,
+            _linearizable: bool,
+        ) -> Result<FetchClusterResponse, tonic::Status> {
+            unreachable!()
+        }
+    }
+
+    fn put_cmd() -> Command {
+        put_cmd_of(b"bar".to_vec())
+    }
+
+    fn put_cmd_of(value: Vec<u8>) -> Command {
+        Command::new(RequestWrapper::from(PutRequest {
+            key: b"foo".to_vec(),
+            value,
+            ..Default::default()
+        }))
+    }
+
+    fn admission_client(inner: &Arc<BlockingClient>, max_inflight: usize) -> Arc<AdmissionClient> {
+        Arc::new(AdmissionClient::new(
+            Arc::clone(inner) as Arc<CurpClient>,
+            Arc::new(AtomicBool::new(false)),
+            max_inflight,
+        ))
+    }
+
+    fn available_permits(client: &AdmissionClient) -> usize {
+        client.permits.as_ref().unwrap().available_permits()
+    }
+
+    fn range_cmd() -> Command {
+        Command::new(RequestWrapper::from(RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn mutations_beyond_the_limit_should_be_rejected() {
+        let inner = Arc::new(BlockingClient::default());
+        let client = admission_client(&inner, 2);
+        let pending: Vec<_> = (0..2)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                tokio::spawn(async move { client.propose(&put_cmd(), None, true).await })
+            })
+            .collect();
+        while available_permits(&client) > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let err = client.propose(&put_cmd(), None, true).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(err.message(), TOO_MANY_REQUESTS_ERR_MSG);
+
+        let read = tokio::spawn({
+            let client = Arc::clone(&client);
+            async move { client.propose(&range_cmd(), None, true).await }
+        });
+        while !read.is_finished() {
+            inner.release.notify_waiters();
+            tokio::task::yield_now().await;
+        }
+        let err = read.await.unwrap().unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+        for handle in pending {
+            let err = handle.await.unwrap().unwrap_err();
+            assert_eq!(err.code(), tonic::Code::Unavailable);
+        }
+        assert_eq!(available_permits(&client), 2);
+    }
+
+    #[tokio::test]
+    async fn flood_of_puts_should_hold_bounded_memory() {
+        const VALUE_SIZE: usize = 64 * 1024;
+        let inner = Arc::new(BlockingClient::default());
+        let client = admission_client(&inner, 4);
+        let handles: Vec<_> = (0..64)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                tokio::spawn(async move {
+                    client
+                        .propose(&put_cmd_of(vec![b'x'; VALUE_SIZE]), None, true)
+                        .await
+                })
+            })
+            .collect();
+        while handles.iter().filter(|h| h.is_finished()).count() < 60 {
+            tokio::task::yield_now().await;
+        }
+
+        // only the admitted puts are held, however many are flooded
+        assert_eq!(available_permits(&client), 0);
+        assert_eq!(inner.max_held.load(Ordering::Relaxed), 4 * VALUE_SIZE);
+        while handles.iter().any(|h| !h.is_finished()) {
+            inner.release.notify_waiters();
+            tokio::task::yield_now().await;
+        }
+        let mut rejected = 0;
+        for handle in handles {
+            let err = handle.await.unwrap().unwrap_err();
+            if err.code() == tonic::Code::ResourceExhausted {
+                rejected += 1;
+            } else {
+                assert_eq!(err.code(), tonic::Code::Unavailable);
+            }
+        }
+        assert_eq!(rejected, 60);
+        assert_eq!(inner.held.load(Ordering::Relaxed), 0);
+        assert_eq!(available_permits(&client), 4);
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_should_reject_mutations_without_taking_permits() {
+        let inner = Arc::new(BlockingClient::default());
+        let client = admission_client(&inner, 1);
+        client.read_only.store(true, Ordering::Relaxed);
+
+        let err = client.propose(&put_cmd(), None, true).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        let err = client.propose_shutdown().await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(available_permits(&client), 1);
+        assert_eq!(inner.max_held.load(Ordering::Relaxed), 0);
+    }
+}
@@ -56,7 +56,10 @@ impl AuthServer {
     where
         T: Into<RequestWrapper>,
     {
-        let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
         let request = request.into_inner().into();
         let cmd = Command::new_with_auth_info(request, auth_info);
         let res = self.client.propose(&cmd, None, use_fast_path).await??;
@@ -3,10 +3,11 @@ use std::sync::Arc;
 use curp::{
     cmd::PbCodec,
     rpc::{
-        FetchClusterRequest, FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
-        LeaseKeepAliveMsg, MoveLeaderRequest, MoveLeaderResponse, ProposeConfChangeRequest,
-        ProposeConfChangeResponse, ProposeRequest, ProposeResponse, Protocol, PublishRequest,
-        PublishResponse, ShutdownRequest, ShutdownResponse, WaitSyncedRequest, WaitSyncedResponse,
+        is_bypassed_in_process, CancelRequest, CancelResponse, FetchClusterRequest,
+        FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse, LeaseKeepAliveMsg,
+        MoveLeaderRequest, MoveLeaderResponse, ProposeConfChangeRequest, ProposeConfChangeResponse,
+        ProposeRequest, ProposeResponse, Protocol, PublishRequest, PublishResponse,
+        ShutdownRequest, ShutdownResponse, WaitSyncedRequest, WaitSyncedResponse,
     },
 };
 use tracing::debug;
@@ -16,6 +17,7 @@ use super::xline_server::CurpServer;
 use crate::storage::AuthStore;
 
 /// Auth wrapper
+#[derive(Clone)]
 pub(crate) struct AuthWrapper {
     /// Curp server
     curp_server: CurpServer,
@@ -43,14 +45,23 @@ impl Protocol for AuthWrapper {
             "AuthWrapper received propose request: {}",
             request.get_ref().propose_id()
         );
-        if let Some(auth_info) = self.auth_store.try_get_auth_info_from_request(&request)? {
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        // the auth info of a cmd from a remote client is never trusted, only the servers
+        // of this node set it without a token or a cert
+        if auth_info.is_some() || !is_bypassed_in_process(&request) {
             let mut command: Command = request
                 .get_ref()
                 .cmd()
                 .map_err(|e| tonic::Status::internal(e.to_string()))?;
-            command.set_auth_info(auth_info);
+            match auth_info {
+                Some(auth_info) => command.set_auth_info(auth_info),
+                None => command.clear_auth_info(),
+            }
             request.get_mut().command = command.encode();
-        };
+        }
         self.curp_server.propose(request).await
     }
 
@@ -82,6 +93,13 @@ impl Protocol for AuthWrapper {
         self.curp_server.wait_synced(request).await
     }
 
+    async fn cancel(
+        &self,
+        request: tonic::Request<CancelRequest>,
+    ) -> Result<tonic::Response<CancelResponse>, tonic::Status> {
+        self.curp_server.cancel(request).await
+    }
+
     async fn fetch_cluster(
         &self,
         request: tonic::Request<FetchClusterRequest>,
@@ -1,5 +1,6 @@
-use std::sync::Arc;
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
 
+use clippy_utilities::NumericCast;
 use curp::{
     members::ClusterInfo,
     rpc::{
@@ -8,8 +9,9 @@ use curp::{
     },
 };
 use itertools::Itertools;
+use tokio::net::lookup_host;
 use tonic::{Request, Response, Status};
-use utils::timestamp;
+use utils::{parse_url, timestamp};
 use xlineapi::{
     command::CurpClient, Cluster, Member, MemberAddRequest, MemberAddResponse, MemberListRequest,
     MemberListResponse, MemberPromoteRequest, MemberPromoteResponse, MemberRemoveRequest,
@@ -48,6 +50,58 @@ impl ClusterServer {
             })
             .collect())
     }
+
+    /// Normalize the peer urls of a member to add or update, the urls must be valid,
+    /// distinct, and not used by any other member than the one being updated
+    async fn validate_peer_urls(
+        &self,
+        urls: Vec<String>,
+        updating: Option<u64>,
+    ) -> Result<Vec<String>, Status> {
+        let mut normalized = Vec::with_capacity(urls.len());
+        for url in urls {
+            let url = parse_url(&url).map_err(|e| Status::invalid_argument(e.to_string()))?;
+            if normalized.contains(&url) {
+                return Err(Status::invalid_argument(format!(
+                    "invalid url `{url}`: duplicated in the request"
+                )));
+            }
+            normalized.push(url);
+        }
+        let mut resolved = Vec::with_capacity(normalized.len());
+        for url in &normalized {
+            resolved.push(resolve(url).await);
+        }
+        let members = self.client.fetch_cluster(true).await?.members;
+        for member in members.iter().filter(|m| Some(m.id) != updating) {
+            let mut existing = HashSet::new();
+            for url in &member.peer_urls {
+                existing.extend(resolve(url).await);
+            }
+            for (url, addrs) in normalized.iter().zip(&resolved) {
+                let same_url = member
+                    .peer_urls
+                    .iter()
+                    .any(|u| parse_url(u).is_ok_and(|u| u == *url));
+                if same_url || addrs.iter().any(|addr| existing.contains(addr)) {
+                    return Err(Status::invalid_argument(format!(
+                        "invalid url `{url}`: already used by member {}",
+                        member.id
+                    )));
+                }
+            }
+        }
+        Ok(normalized)
+    }
+}
+
+/// Resolve the socket addresses of a url, unresolvable urls have none
+async fn resolve(url: &str) -> Vec<SocketAddr> {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    lookup_host(authority.trim_end_matches('/'))
+        .await
+        .map(Iterator::collect)
+        .unwrap_or_default()
 }
 
 #[tonic::async_trait]
@@ -62,7 +116,12 @@ impl Cluster for ClusterServer {
         } else {
             i32::from(Add)
         };
-        let peer_url_ls = req.peer_ur_ls.into_iter().sorted().collect_vec();
+        let peer_url_ls = self
+            .validate_peer_urls(req.peer_ur_ls, None)
+            .await?
+            .into_iter()
+            .sorted()
+            .collect_vec();
         // calculate node id based on addresses and current timestamp
         let node_id = ClusterInfo::calculate_member_id(peer_url_ls.clone(), "", Some(timestamp()));
         let members = self
@@ -104,11 +163,14 @@ impl Cluster for ClusterServer {
         request: Request<MemberUpdateRequest>,
     ) -> Result<Response<MemberUpdateResponse>, Status> {
         let req = request.into_inner();
+        let address = self
+            .validate_peer_urls(req.peer_ur_ls, Some(req.id))
+            .await?;
         let members = self
             .propose_conf_change(vec![ConfChange {
                 change_type: i32::from(Update),
                 node_id: req.id,
-                address: req.peer_ur_ls,
+                address,
             }])
             .await?;
         let resp = MemberUpdateResponse {
@@ -118,15 +180,19 @@ impl Cluster for ClusterServer {
         Ok(Response::new(resp))
     }
 
+    /// MemberList lists all members in ascending order of the ids, the members
+    /// fetched from a member of an older version are sorted here
     async fn member_list(
         &self,
         request: Request<MemberListRequest>,
     ) -> Result<Response<MemberListResponse>, Status> {
         let req = request.into_inner();
         let header = self.header_gen.gen_header();
-        let members = self.client.fetch_cluster(req.linearizable).await?.members;
+        let mut members = self.client.fetch_cluster(req.linearizable).await?.members;
+        members.sort_unstable_by_key(|member| member.id);
         let resp = MemberListResponse {
             header: Some(header),
+            count: members.len().numeric_cast(),
             members: members
                 .into_iter()
                 .map(|member| Member {
@@ -1,63 +1,113 @@
 use std::{fmt::Debug, sync::Arc};
 
-use clippy_utilities::OverflowArithmetic;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use curp::{
-    cmd::{Command as CurpCommand, CommandExecutor as CurpCommandExecutor},
+    cmd::{
+        AppliedProposal, Command as CurpCommand, CommandExecutor as CurpCommandExecutor, PbCodec,
+        ProposalRecord,
+    },
     members::ServerId,
     InflightId, LogIndex,
 };
 use dashmap::DashMap;
 use engine::Snapshot;
 use event_listener::Event;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use tracing::warn;
 use utils::{barrier::IdBarrier, table_names::META_TABLE};
 use xlineapi::{
-    command::{Command, CurpClient},
+    command::{Command, CommandResponse, CurpClient, KeyRange, SyncResponse},
     execute_error::ExecuteError,
     AlarmAction, AlarmRequest, AlarmType,
 };
 
-use super::barriers::IndexBarrier;
+use super::{barriers::IndexBarrier, state_hash::StateHasher, time_index::TimeIndex};
 use crate::{
+    clock::Clock,
+    journal::Journal,
     revision_number::RevisionNumberGenerator,
     rpc::{RequestBackend, RequestWrapper},
     storage::{
-        db::{WriteOp, DB},
-        AlarmStore, AuthStore, KvStore, LeaseStore,
+        db::{
+            applied_proposals_from_key, KeyRevisionPair, WriteOp, APPLIED_IN_BATCH_PREFIX,
+            APPLIED_PROPOSAL_PREFIX, DB,
+        },
+        kv_store::SyncGuard,
+        AlarmStore, ApplyError, ApplyFence, AuthStore, KvStore, LeaseStore,
     },
 };
 
 /// Key of applied index
 pub(crate) const APPLIED_INDEX_KEY: &str = "applied_index";
+/// Number of log entries between two prunings of the results of the applied proposals
+const APPLIED_PROPOSALS_PRUNE_INTERVAL: u64 = 1024;
+/// Flag of the recorded results of a proposal that the execution result is included
+const ER_RECORDED: u8 = 1;
+/// Flag of the recorded results of a proposal that the after sync succeeded
+const ASR_SUCCEEDED: u8 = 1 << 1;
 
-/// Range start and end to get all keys
-const UNBOUNDED: &[u8] = &[0_u8];
-/// Range end to get one key
-const ONE_KEY: &[u8] = &[];
-
-/// Type of `KeyRange`
-pub(crate) enum RangeType {
-    /// `KeyRange` contains only one key
-    OneKey,
-    /// `KeyRange` contains all keys
-    AllKeys,
-    /// `KeyRange` contains the keys in the range
-    Range,
-}
-
-impl RangeType {
-    /// Get `RangeType` by given `key` and `range_end`
-    #[inline]
-    pub(crate) fn get_range_type(key: &[u8], range_end: &[u8]) -> Self {
-        if range_end == ONE_KEY {
-            RangeType::OneKey
-        } else if key == UNBOUNDED && range_end == UNBOUNDED {
-            RangeType::AllKeys
-        } else {
-            RangeType::Range
+/// Encode the results of an applied proposal, the flags come first, then the length of
+/// the execution result before it
+fn encode_proposal_results(
+    er: Option<&CommandResponse>,
+    asr: Result<&SyncResponse, &ExecuteError>,
+) -> Vec<u8> {
+    let mut flags = 0;
+    let mut bytes = Vec::new();
+    if let Some(er) = er {
+        flags |= ER_RECORDED;
+        let er = er.encode();
+        bytes.extend_from_slice(&er.len().numeric_cast::<u64>().to_le_bytes());
+        bytes.extend_from_slice(&er);
+    }
+    match asr {
+        Ok(asr) => {
+            flags |= ASR_SUCCEEDED;
+            bytes.extend_from_slice(&asr.encode());
         }
+        Err(e) => bytes.extend_from_slice(&e.encode()),
     }
+    bytes.insert(0, flags);
+    bytes
+}
+
+/// Decode the results of an applied proposal encoded by `encode_proposal_results`
+fn decode_proposal_results(
+    bytes: &[u8],
+) -> Result<(Option<CommandResponse>, Result<SyncResponse, ExecuteError>), ExecuteError> {
+    let corrupted = |reason: &dyn std::fmt::Display| {
+        ExecuteError::DbError(format!(
+            "Failed to decode the results of an applied proposal, error: {reason}"
+        ))
+    };
+    let (&flags, rest) = bytes
+        .split_first()
+        .ok_or_else(|| corrupted(&"empty value"))?;
+    let (er, rest) = if flags & ER_RECORDED == 0 {
+        (None, rest)
+    } else {
+        let len = rest
+            .get(..8)
+            .and_then(|len| <[u8; 8]>::try_from(len).ok())
+            .map(u64::from_le_bytes)
+            .ok_or_else(|| corrupted(&"no length of the execution result"))?
+            .numeric_cast::<usize>();
+        let er = rest
+            .get(8..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| corrupted(&"truncated execution result"))?;
+        let er = CommandResponse::decode(er).map_err(|e| corrupted(&e))?;
+        (
+            Some(er),
+            rest.get(8_usize.overflow_add(len)..).unwrap_or_default(),
+        )
+    };
+    let asr = if flags & ASR_SUCCEEDED == 0 {
+        Err(ExecuteError::decode(rest).map_err(|e| corrupted(&e))?)
+    } else {
+        Ok(SyncResponse::decode(rest).map_err(|e| corrupted(&e))?)
+    };
+    Ok((er, asr))
 }
 
 /// Command Executor
@@ -87,6 +137,15 @@ pub(crate) struct CommandExecutor {
     quota_checker: Arc<dyn QuotaChecker>,
     /// Alarmer
     alarmer: RwLock<Option<Alarmer>>,
+    /// Incremental hash of the kv state
+    state_hasher: StateHasher,
+    /// Mapping of the wall time to the applied revisions
+    time_index: TimeIndex,
+    /// Journal of the applied requests, `None` if journaling is off
+    journal: Option<Journal>,
+    /// Stops flushing the applied writes once one failed. The state hash is folded under
+    /// its lock, so it's persisted in the order it's folded.
+    apply_fence: Arc<ApplyFence>,
 }
 
 /// Quota checker
@@ -208,8 +267,22 @@ impl Alarmer {
     }
 
     /// Propose alarm request to other nodes
-    async fn alarm(&self, action: AlarmAction, alarm: AlarmType) -> Result<(), tonic::Status> {
-        let request = RequestWrapper::from(AlarmRequest::new(action, self.id, alarm));
+    pub(super) async fn alarm(
+        &self,
+        action: AlarmAction,
+        alarm: AlarmType,
+    ) -> Result<(), tonic::Status> {
+        self.alarm_member(action, self.id, alarm).await
+    }
+
+    /// Propose alarm request naming another member to other nodes
+    pub(super) async fn alarm_member(
+        &self,
+        action: AlarmAction,
+        member: ServerId,
+        alarm: AlarmType,
+    ) -> Result<(), tonic::Status> {
+        let request = RequestWrapper::from(AlarmRequest::new(action, member, alarm));
         let cmd = Command::new(request);
         let _ig = self.client.propose(&cmd, None, true).await?;
         Ok(())
@@ -217,7 +290,7 @@ impl Alarmer {
 }
 
 impl CommandExecutor {
-    /// New `CommandExecutor`
+    /// New `CommandExecutor`, the state hash and the time index are recovered from `db`
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         kv_storage: Arc<KvStore>,
@@ -231,10 +304,15 @@ impl CommandExecutor {
         auth_rev: Arc<RevisionNumberGenerator>,
         compact_events: Arc<DashMap<u64, Arc<Event>>>,
         quota: u64,
-    ) -> Self {
+        clock: Arc<Clock>,
+        journal: Option<Journal>,
+    ) -> Result<Self, ExecuteError> {
         let alarmer = RwLock::new(None);
         let quota_checker = Arc::new(CommandQuotaChecker::new(quota, Arc::clone(&db)));
-        Self {
+        let state_hasher = StateHasher::recover(&db)?;
+        let time_index = TimeIndex::recover(&db, clock)?;
+        let apply_fence = Arc::new(ApplyFence::new(Arc::clone(&db)));
+        Ok(Self {
             kv_storage,
             auth_storage,
             lease_storage,
@@ -247,7 +325,11 @@ impl CommandExecutor {
             compact_events,
             quota_checker,
             alarmer,
-        }
+            state_hasher,
+            time_index,
+            journal,
+            apply_fence,
+        })
     }
 
     /// Set alarmer
@@ -255,6 +337,210 @@ impl CommandExecutor {
         *self.alarmer.write() = Some(alarmer);
     }
 
+    /// Mapping of the wall time to the applied revisions
+    pub(crate) fn time_index(&self) -> &TimeIndex {
+        &self.time_index
+    }
+
+    /// Whether the writes of an entry at the revision are tracked by the synced revision
+    fn syncs_revision(wrapper: &RequestWrapper, revision: i64) -> bool {
+        revision > 0
+            && matches!(
+                wrapper.backend(),
+                RequestBackend::Kv | RequestBackend::Lease
+            )
+    }
+
+    /// Apply a command after it's synced, a command of a batched entry is at `(pos, last)`
+    /// of the batch, which is applied only after its last command. The results of a
+    /// proposal are recorded with its writes.
+    async fn apply(
+        &self,
+        cmd: &Command,
+        index: LogIndex,
+        batch: Option<(usize, bool)>,
+        revision: i64,
+        proposal: Option<((u64, u64), ProposalRecord<'_, Command>)>,
+    ) -> Result<<Command as CurpCommand>::ASR, <Command as CurpCommand>::Error> {
+        let quota_enough = self.quota_checker.check(cmd);
+        let mut ops = match batch {
+            None => vec![WriteOp::PutAppliedIndex(index)],
+            Some((_, true)) => vec![
+                WriteOp::PutAppliedIndex(index),
+                WriteOp::DeleteAppliedInBatch(index),
+            ],
+            Some((pos, false)) => vec![WriteOp::PutAppliedInBatch(index, pos.numeric_cast())],
+        };
+        let wrapper = cmd.request();
+        // The revision stays unsynced until its writes are flushed and indexed
+        let sync_guard =
+            Self::syncs_revision(wrapper, revision).then(|| self.kv_storage.resume_sync(revision));
+        let applied = match wrapper.backend() {
+            RequestBackend::Kv => self.kv_storage.after_sync(wrapper, revision).await,
+            RequestBackend::Auth => self.auth_storage.after_sync(wrapper, revision),
+            RequestBackend::Lease => match self.lease_storage.after_sync(wrapper, revision).await {
+                // requests that don't bump the revision, such as grants, report the
+                // revision they are applied at
+                Ok((_res, ops)) if revision <= 0 => {
+                    Ok((SyncResponse::new(self.kv_storage.synced_revision()), ops))
+                }
+                applied => applied,
+            },
+            RequestBackend::Alarm => Ok(self.alarm_storage.after_sync(wrapper, revision)),
+        };
+        let (res, mut wr_ops) = match applied {
+            Ok(applied) => applied,
+            // nothing is written at the revision of a failed entry, it's folded as empty,
+            // only the failure of the proposal is recorded
+            Err(ApplyError::Execute(e)) if sync_guard.is_some() => {
+                let mut ops = Vec::new();
+                if let Some((propose_id, ref record)) = proposal {
+                    Self::record_proposal(index, propose_id, record, Err(&e), &mut ops);
+                }
+                return Err(match self.flush_applied(index, Some(revision), ops) {
+                    Ok(_) => e,
+                    Err(fold_err) => self.fail_apply(index, fold_err, sync_guard),
+                });
+            }
+            Err(e) => return Err(self.fail_apply(index, e, sync_guard)),
+        };
+        if let RequestWrapper::CompactionRequest(ref compact_req) = *wrapper {
+            if compact_req.physical {
+                if let Some(n) = self.compact_events.get(&cmd.compact_id()) {
+                    let _ignore = n.notify(usize::MAX);
+                }
+            }
+        };
+        if let RequestWrapper::CompactionRequest(ref compact_req) = *wrapper {
+            if compact_req.physical {
+                if let Some(n) = self.compact_events.get(&cmd.compact_id()) {
+                    let _ignore = n.notify(usize::MAX);
+                }
+            }
+        };
+        if let RequestWrapper::CompactionRequest(ref compact_req) = *wrapper {
+            self.time_index.prune(compact_req.revision, &mut ops);
+        }
+        // the tombstones of the deleted keys of revoked leases are written in the
+        // background, only the deletions are persisted with the entry
+        let deletions: Vec<_> = wr_ops
+            .iter()
+            .filter_map(|op| {
+                if let WriteOp::PutLeaseDeletion(ref deletion) = *op {
+                    Some(Arc::clone(deletion))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        ops.append(&mut wr_ops);
+        if sync_guard.is_some() {
+            self.time_index.record(revision, &mut ops);
+        }
+        if let Some((propose_id, ref record)) = proposal {
+            Self::record_proposal(index, propose_id, record, Ok(&res), &mut ops);
+        }
+        #[cfg(feature = "replay-fault")]
+        crate::replay::fault::inject(index, &mut ops);
+        let folded = sync_guard.is_some().then_some(revision);
+        let key_revisions = match self.flush_applied(index, folded, ops) {
+            Ok(key_revisions) => key_revisions,
+            Err(e) => return Err(self.fail_apply(index, e, sync_guard)),
+        };
+        if !key_revisions.is_empty() {
+            self.kv_storage.insert_index(key_revisions);
+        }
+        // the revision is synced once the tombstones are written
+        let _sync_guard = match sync_guard {
+            Some(guard) if !deletions.is_empty() => {
+                self.lease_storage.spawn_key_deletions(
+                    deletions,
+                    self.kv_storage.defer_sync(guard),
+                    Arc::clone(&self.apply_fence),
+                );
+                None
+            }
+            guard => guard,
+        };
+        self.lease_storage.mark_lease_synced(wrapper);
+        if !quota_enough {
+            if let Some(alarmer) = self.alarmer.read().clone() {
+                let _ig = tokio::spawn(async move {
+                    if let Err(e) = alarmer
+                        .alarm(AlarmAction::Activate, AlarmType::Nospace)
+                        .await
+                    {
+                        warn!("{} propose alarm failed: {:?}", alarmer.id, e);
+                    }
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Record the results of a proposal applied in log[index] with its writes, the records
+    /// out of the retention are pruned once in `APPLIED_PROPOSALS_PRUNE_INTERVAL` entries
+    fn record_proposal(
+        index: LogIndex,
+        propose_id: (u64, u64),
+        record: &ProposalRecord<'_, Command>,
+        asr: Result<&SyncResponse, &ExecuteError>,
+        ops: &mut Vec<WriteOp<'_>>,
+    ) {
+        ops.push(WriteOp::PutAppliedProposal(
+            index,
+            propose_id,
+            encode_proposal_results(record.er, asr),
+        ));
+        if index % APPLIED_PROPOSALS_PRUNE_INTERVAL == 0 && record.retain_from > 0 {
+            ops.push(WriteOp::DeleteAppliedProposals(record.retain_from));
+        }
+    }
+
+    /// Flush the writes of the entry at `index`, folding them into the state hash at
+    /// `revision` first if it's tracked
+    ///
+    /// Both are done under the lock the storage failure is recorded with, so that no
+    /// entry is flushed once one failed, however many are applied in parallel, and the
+    /// persisted state hash includes every revision flushed.
+    fn flush_applied(
+        &self,
+        index: LogIndex,
+        revision: Option<i64>,
+        ops: Vec<WriteOp<'_>>,
+    ) -> Result<Vec<KeyRevisionPair>, ApplyError> {
+        self.apply_fence
+            .flush(&format!("log[{index}]"), ops, |ops| {
+                if let Some(revision) = revision {
+                    self.state_hasher.fold(revision, ops);
+                }
+            })
+    }
+
+    /// Handle an error of applying the entry at `index`
+    ///
+    /// A storage error poisons the node instead of failing the request: no entry is
+    /// applied after it, the storage is marked as corrupted, which fences the node and
+    /// raises a `Corrupt` alarm, and the revision of the entry is never reported as synced.
+    fn fail_apply(
+        &self,
+        index: LogIndex,
+        err: ApplyError,
+        sync_guard: Option<SyncGuard<'_>>,
+    ) -> ExecuteError {
+        match err {
+            ApplyError::Execute(e) => e,
+            ApplyError::Storage(reason) => {
+                if let Some(guard) = sync_guard {
+                    guard.abandon();
+                }
+                self.apply_fence
+                    .fail(format!("failed to apply log[{index}]: {reason}"));
+                ExecuteError::DbError(reason)
+            }
+        }
+    }
+
     /// Check if the alarm is activated
     fn check_alarm(&self, cmd: &Command) -> Result<(), ExecuteError> {
         #[allow(clippy::wildcard_enum_match_arm)]
@@ -290,23 +576,36 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
         let wrapper = cmd.request();
         let auth_info = cmd.auth_info();
         self.auth_storage.check_permission(wrapper, auth_info)?;
+        // The only place the revisions are reserved, one per mutating entry however
+        // many writes it has, the entries rejected above reserve none
         let revision = match wrapper.backend() {
-            RequestBackend::Auth => {
-                if wrapper.skip_auth_revision() {
-                    -1
-                } else {
-                    self.auth_rev.next()
+            RequestBackend::Auth => self.auth_rev.reserve(!wrapper.skip_auth_revision()),
+            RequestBackend::Kv => {
+                let mutating = !wrapper.skip_general_revision();
+                if mutating {
+                    self.kv_storage.check_writes(wrapper)?;
                 }
+                self.general_rev.reserve(mutating)
             }
-            RequestBackend::Kv | RequestBackend::Lease => {
-                if wrapper.skip_general_revision() {
-                    -1
-                } else {
-                    self.general_rev.next()
+            RequestBackend::Lease => {
+                let mutating = !wrapper.skip_general_revision();
+                if mutating {
+                    self.lease_storage.check_writes(wrapper)?;
                 }
+                self.general_rev.reserve(mutating)
             }
-            RequestBackend::Alarm => -1,
+            RequestBackend::Alarm => None,
         };
+        // -1 tells the stores that the entry has no revision
+        let Some(revision) = revision else {
+            return Ok(-1);
+        };
+        // Registered in the log order, so that a later entry applied in parallel can't
+        // report this revision as synced before its writes are flushed, it's released
+        // if the entry is never after synced
+        if Self::syncs_revision(wrapper, revision) {
+            self.kv_storage.register_sync(revision);
+        }
         Ok(revision)
     }
 
@@ -329,50 +628,112 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
         index: LogIndex,
         revision: i64,
     ) -> Result<<Command as CurpCommand>::ASR, <Command as CurpCommand>::Error> {
-        let quota_enough = self.quota_checker.check(cmd);
-        let mut ops = vec![WriteOp::PutAppliedIndex(index)];
-        let wrapper = cmd.request();
-        let (res, mut wr_ops) = match wrapper.backend() {
-            RequestBackend::Kv => self.kv_storage.after_sync(wrapper, revision).await?,
-            RequestBackend::Auth => self.auth_storage.after_sync(wrapper, revision)?,
-            RequestBackend::Lease => self.lease_storage.after_sync(wrapper, revision).await?,
-            RequestBackend::Alarm => self.alarm_storage.after_sync(wrapper, revision),
-        };
-        if let RequestWrapper::CompactionRequest(ref compact_req) = *wrapper {
-            if compact_req.physical {
-                if let Some(n) = self.compact_events.get(&cmd.compact_id()) {
-                    let _ignore = n.notify(usize::MAX);
-                }
-            }
-        };
-        if let RequestWrapper::CompactionRequest(ref compact_req) = *wrapper {
-            if compact_req.physical {
-                if let Some(n) = self.compact_events.get(&cmd.compact_id()) {
-                    let _ignore = n.notify(usize::MAX);
-                }
-            }
-        };
-        ops.append(&mut wr_ops);
-        let key_revisions = self.db.flush_ops(ops)?;
-        if !key_revisions.is_empty() {
-            self.kv_storage.insert_index(key_revisions);
+        self.apply(cmd, index, None, revision, None).await
+    }
+
+    async fn after_sync_proposal(
+        &self,
+        cmd: &Command,
+        propose_id: (u64, u64),
+        index: LogIndex,
+        revision: i64,
+        record: ProposalRecord<'_, Command>,
+    ) -> Result<<Command as CurpCommand>::ASR, <Command as CurpCommand>::Error> {
+        let res = self
+            .apply(cmd, index, None, revision, Some((propose_id, record)))
+            .await?;
+        if let Some(ref journal) = self.journal {
+            journal.record(cmd, propose_id, index, res.revision());
         }
-        self.lease_storage.mark_lease_synced(wrapper);
-        if !quota_enough {
-            if let Some(alarmer) = self.alarmer.read().clone() {
-                let _ig = tokio::spawn(async move {
-                    if let Err(e) = alarmer
-                        .alarm(AlarmAction::Activate, AlarmType::Nospace)
-                        .await
-                    {
-                        warn!("{} propose alarm failed: {:?}", alarmer.id, e);
-                    }
-                });
-            }
+        Ok(res)
+    }
+
+    async fn after_sync_in_batch(
+        &self,
+        cmd: &Command,
+        propose_id: (u64, u64),
+        index: LogIndex,
+        pos: usize,
+        last: bool,
+        revision: i64,
+        record: ProposalRecord<'_, Command>,
+    ) -> Result<<Command as CurpCommand>::ASR, <Command as CurpCommand>::Error> {
+        let res = self
+            .apply(
+                cmd,
+                index,
+                Some((pos, last)),
+                revision,
+                Some((propose_id, record)),
+            )
+            .await?;
+        if let Some(ref journal) = self.journal {
+            journal.record(cmd, propose_id, index, res.revision());
         }
         Ok(res)
     }
 
+    fn applied_in_batches(
+        &self,
+    ) -> Result<Vec<(LogIndex, usize)>, <Command as CurpCommand>::Error> {
+        let range_end = KeyRange::get_prefix(APPLIED_IN_BATCH_PREFIX);
+        let mut applied = Vec::new();
+        for key in self
+            .db
+            .scan_keys(META_TABLE, APPLIED_IN_BATCH_PREFIX, &range_end)?
+        {
+            let index = key
+                .strip_prefix(APPLIED_IN_BATCH_PREFIX)
+                .and_then(|index| <[u8; 8]>::try_from(index).ok())
+                .map(u64::from_be_bytes)
+                .ok_or_else(|| {
+                    ExecuteError::DbError(format!("Failed to decode applied in batch key {key:?}"))
+                })?;
+            let Some(pos_bytes) = self.db.get_value(META_TABLE, &key)? else {
+                continue;
+            };
+            let pos = <[u8; 8]>::try_from(pos_bytes.as_slice())
+                .map(u64::from_le_bytes)
+                .map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to decode applied position, error: {e}"))
+                })?;
+            applied.push((index, pos.numeric_cast()));
+        }
+        Ok(applied)
+    }
+
+    fn applied_proposals(
+        &self,
+        from: LogIndex,
+    ) -> Result<Vec<AppliedProposal<Command>>, <Command as CurpCommand>::Error> {
+        let range_end = KeyRange::get_prefix(APPLIED_PROPOSAL_PREFIX);
+        let keys = self
+            .db
+            .scan_keys(META_TABLE, &applied_proposals_from_key(from), &range_end)?;
+        let values = self.db.get_values(META_TABLE, &keys)?;
+        let mut proposals = Vec::with_capacity(keys.len());
+        for (key, value) in keys.into_iter().zip(values) {
+            let decode_key = |start: usize| {
+                key.strip_prefix(APPLIED_PROPOSAL_PREFIX)
+                    .and_then(|rest| rest.get(start..start.overflow_add(8)))
+                    .and_then(|field| <[u8; 8]>::try_from(field).ok())
+                    .map(u64::from_be_bytes)
+                    .ok_or_else(|| {
+                        ExecuteError::DbError(format!(
+                            "Failed to decode applied proposal key {key:?}"
+                        ))
+                    })
+            };
+            let (index, client_id, seq_num) = (decode_key(0)?, decode_key(8)?, decode_key(16)?);
+            let Some(value) = value else {
+                continue;
+            };
+            let (er, asr) = decode_proposal_results(&value)?;
+            proposals.push(AppliedProposal::new(index, (client_id, seq_num), er, asr));
+        }
+        Ok(proposals)
+    }
+
     async fn reset(
         &self,
         snapshot: Option<(Snapshot, LogIndex)>,
@@ -383,7 +744,24 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
         } else {
             None
         };
-        self.db.reset(s).await
+        self.db.reset(s).await?;
+        // the entries prepared before the reset are dropped without being applied
+        self.kv_storage.clear_syncing();
+        self.state_hasher.reset(&self.db)?;
+        self.time_index.reset(&self.db)
+    }
+
+    fn release(&self, cmd: &Command, revision: i64) {
+        // nothing is written at the revision of a failed entry, or of a speculatively
+        // executed one overwritten in the log, it's folded as empty
+        if Self::syncs_revision(cmd.request(), revision) {
+            let guard = self.kv_storage.resume_sync(revision);
+            if let Err(e) = self.flush_applied(0, Some(revision), Vec::new()) {
+                let _ignore = self.fail_apply(0, e, Some(guard));
+                return;
+            }
+            drop(guard);
+        }
     }
 
     async fn snapshot(&self) -> Result<Snapshot, <Command as CurpCommand>::Error> {
@@ -392,7 +770,10 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
     }
 
     fn set_last_applied(&self, index: LogIndex) -> Result<(), <Command as CurpCommand>::Error> {
-        _ = self.db.flush_ops(vec![WriteOp::PutAppliedIndex(index)])?;
+        _ = self
+            .db
+            .flush_ops(vec![WriteOp::PutAppliedIndex(index)])
+            .map_err(|e| self.fail_apply(index, e.into(), None))?;
         Ok(())
     }
 
@@ -410,13 +791,516 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
         self.id_barrier.trigger(&id);
         self.index_barrier.trigger(index);
     }
+
+    fn state_hash(&self) -> Option<(i64, u64)> {
+        self.state_hasher.latest()
+    }
+
+    fn state_hash_at(&self, revision: i64) -> Option<u64> {
+        self.state_hasher.at(revision)
+    }
+
+    fn on_state_divergence(&self, revision: i64, members: Vec<u64>) {
+        let Some(alarmer) = self.alarmer.read().clone() else {
+            return;
+        };
+        let _ig = tokio::spawn(async move {
+            for member in members {
+                if let Err(e) = alarmer
+                    .alarm_member(AlarmAction::Activate, member, AlarmType::Corrupt)
+                    .await
+                {
+                    warn!(
+                        "{} propose corrupt alarm of {member} diverged at revision {revision} failed: {e:?}",
+                        alarmer.id
+                    );
+                }
+            }
+        });
+    }
+
+    fn storage_failure(&self) -> Option<String> {
+        self.apply_fence.failure()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use xlineapi::{LeaseGrantRequest, PutRequest, Request, RequestOp, TxnRequest};
+    use std::time::Duration;
+
+    use clippy_utilities::NumericCast;
+    use test_macros::abort_on_panic;
+    use tokio::{sync::mpsc, time::timeout};
+    use utils::{
+        config::EngineConfig,
+        failpoint::{FailAction, FailScenario},
+    };
+    use xlineapi::{
+        LeaseGrantRequest, LeaseRevokeRequest, PutRequest, RangeRequest, Request, RequestOp,
+        ResponseWrapper, TxnRequest,
+    };
 
     use super::*;
+    use crate::{
+        header_gen::HeaderGenerator,
+        storage::{index::Index, kv_store::KvStoreInner, lease_store::LeaseCollection},
+    };
+
+    /// A command executor on an in-memory store stack, along with its kv store and its
+    /// general revision
+    fn init_executor() -> (
+        Arc<CommandExecutor>,
+        Arc<KvStore>,
+        Arc<RevisionNumberGenerator>,
+    ) {
+        init_executor_on(DB::open(&EngineConfig::Memory).unwrap())
+    }
+
+    /// A command executor on a store stack over `db`, along with its kv store and its
+    /// general revision
+    fn init_executor_on(
+        db: Arc<DB>,
+    ) -> (
+        Arc<CommandExecutor>,
+        Arc<KvStore>,
+        Arc<RevisionNumberGenerator>,
+    ) {
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let index = Arc::new(Index::new());
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(16);
+        let _drain = tokio::spawn(async move { while kv_update_rx.recv().await.is_some() {} });
+        let (compact_tx, _compact_rx) = mpsc::channel(1);
+        let kv_storage = Arc::new(KvStore::new(
+            Arc::new(KvStoreInner::new(Arc::clone(&index), Arc::clone(&db))),
+            Arc::clone(&header_gen),
+            kv_update_tx.clone(),
+            compact_tx,
+            Arc::clone(&lease_collection),
+        ));
+        let lease_storage = Arc::new(LeaseStore::new(
+            Arc::clone(&lease_collection),
+            Arc::clone(&header_gen),
+            Arc::clone(&db),
+            index,
+            kv_update_tx,
+            false,
+            false,
+        ));
+        let auth_storage = Arc::new(AuthStore::new(
+            lease_collection,
+            None,
+            Arc::clone(&header_gen),
+            Arc::clone(&db),
+            None,
+            Arc::new(Clock::system()),
+        ));
+        let alarm_storage = Arc::new(AlarmStore::new(Arc::clone(&header_gen), Arc::clone(&db)));
+        let general_rev = header_gen.general_revision_arc();
+        let ce = CommandExecutor::new(
+            Arc::clone(&kv_storage),
+            auth_storage,
+            lease_storage,
+            alarm_storage,
+            db,
+            Arc::new(IndexBarrier::new()),
+            Arc::new(IdBarrier::new()),
+            Arc::clone(&general_rev),
+            header_gen.auth_revision_arc(),
+            Arc::new(DashMap::new()),
+            u64::MAX,
+            Arc::new(Clock::system()),
+            None,
+        )
+        .unwrap();
+        (Arc::new(ce), kv_storage, general_rev)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn prepare_should_reserve_one_revision_per_mutating_entry() {
+        const ENTRIES: usize = 64;
+        const WRITES: usize = 4;
+        let (ce, kv_storage, general_rev) = init_executor();
+        let initial = general_rev.get();
+        let op = |request| RequestOp {
+            request: Some(request),
+        };
+        // every entry writes keys of its own, so that the entries don't conflict and
+        // are applied in parallel, one in four is read-only and one in eight fails
+        let cmds: Vec<_> = (0..ENTRIES)
+            .map(|i| {
+                let ops = if i % 4 == 0 {
+                    vec![op(Request::RequestRange(RangeRequest {
+                        key: format!("{i:02}/").into_bytes(),
+                        ..Default::default()
+                    }))]
+                } else {
+                    (0..WRITES)
+                        .map(|w| {
+                            op(Request::RequestPut(PutRequest {
+                                key: format!("{i:02}/{w}").into_bytes(),
+                                value: b"v".to_vec(),
+                                // the lease doesn't exist
+                                lease: if i % 8 == 2 { 1 } else { 0 },
+                                ..Default::default()
+                            }))
+                        })
+                        .collect()
+                };
+                Arc::new(Command::new(RequestWrapper::from(TxnRequest {
+                    compare: vec![],
+                    success: ops,
+                    failure: vec![],
+                })))
+            })
+            .collect();
+
+        // the entries are prepared in the log order, then applied in parallel
+        let mut handles = vec![];
+        for (i, cmd) in cmds.into_iter().enumerate() {
+            let Ok(revision) = ce.prepare(&cmd) else {
+                assert_eq!(i % 8, 2, "entry {i} fails to prepare");
+                continue;
+            };
+            let ce = Arc::clone(&ce);
+            handles.push(tokio::spawn(async move {
+                let _er = ce.execute(&cmd).await.unwrap();
+                let index = i.numeric_cast::<u64>().overflow_add(1);
+                let _asr = ce.after_sync(&cmd, index, revision).await.unwrap();
+                (revision > 0).then_some((i, revision))
+            }));
+        }
+        let mut applied = vec![];
+        for handle in handles {
+            applied.extend(handle.await.unwrap());
+        }
+
+        let mutating = ENTRIES - ENTRIES / 4 - ENTRIES / 8;
+        assert_eq!(applied.len(), mutating);
+        assert_eq!(general_rev.get(), initial + mutating.numeric_cast::<i64>());
+        assert_eq!(kv_storage.synced_revision(), general_rev.get());
+        let mut revisions: Vec<_> = applied.iter().map(|&(_, rev)| rev).collect();
+        revisions.sort_unstable();
+        revisions.dedup();
+        assert_eq!(
+            revisions.len(),
+            mutating,
+            "two entries share a main revision"
+        );
+        for (i, rev) in applied {
+            let range = RequestWrapper::from(RangeRequest {
+                key: format!("{i:02}/").into_bytes(),
+                range_end: format!("{i:02}0").into_bytes(),
+                ..Default::default()
+            });
+            let ResponseWrapper::RangeResponse(res) =
+                kv_storage.execute(&range).unwrap().into_inner()
+            else {
+                unreachable!("a range gets a range response");
+            };
+            assert_eq!(res.kvs.len(), WRITES);
+            assert!(res.kvs.iter().all(|kv| kv.mod_revision == rev));
+        }
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn released_revision_should_not_stall_the_synced_revision() {
+        let (ce, kv_storage, _general_rev) = init_executor();
+        let put = |key: &str| {
+            Command::new(RequestWrapper::from(PutRequest {
+                key: key.as_bytes().to_vec(),
+                value: b"v".to_vec(),
+                ..Default::default()
+            }))
+        };
+        // prepared by the speculative execution on the leader, then overwritten in the
+        // log by a new leader, so it's never after synced
+        let discarded = put("a");
+        let discarded_rev = ce.prepare(&discarded).unwrap();
+        let committed = put("b");
+        let committed_rev = ce.prepare(&committed).unwrap();
+        let _er = ce.execute(&committed).await.unwrap();
+        let _asr = ce.after_sync(&committed, 1, committed_rev).await.unwrap();
+        assert!(kv_storage.synced_revision() < committed_rev);
+
+        ce.release(&discarded, discarded_rev);
+        assert_eq!(kv_storage.synced_revision(), committed_rev);
+        let next = put("c");
+        let next_rev = ce.prepare(&next).unwrap();
+        let _er = ce.execute(&next).await.unwrap();
+        let _asr = ce.after_sync(&next, 2, next_rev).await.unwrap();
+        assert_eq!(kv_storage.synced_revision(), next_rev);
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn batch_should_be_applied_after_its_last_cmd() {
+        let (ce, _kv_storage, _general_rev) = init_executor();
+        let cmds: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|key| {
+                Command::new(RequestWrapper::from(PutRequest {
+                    key: key.into(),
+                    value: b"v".to_vec(),
+                    ..Default::default()
+                }))
+            })
+            .collect();
+        for (pos, cmd) in cmds.iter().enumerate() {
+            let revision = ce.prepare(cmd).unwrap();
+            let _er = ce.execute(cmd).await.unwrap();
+            let last = pos == 1;
+            let _asr = ce
+                .after_sync_in_batch(
+                    cmd,
+                    (1, pos.numeric_cast()),
+                    5,
+                    pos,
+                    last,
+                    revision,
+                    ProposalRecord::new(None, 0),
+                )
+                .await
+                .unwrap();
+            if !last {
+                // a crash here replays the batch from its second command
+                assert_eq!(ce.last_applied().unwrap(), 0);
+                assert_eq!(ce.applied_in_batches().unwrap(), vec![(5, 0)]);
+            }
+        }
+        assert_eq!(ce.last_applied().unwrap(), 5);
+        assert!(ce.applied_in_batches().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn applied_proposals_should_be_restored_after_a_restart() {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let (ce, _kv_storage, _general_rev) = init_executor_on(Arc::clone(&db));
+        let last = APPLIED_PROPOSALS_PRUNE_INTERVAL;
+        let mut results = Vec::new();
+        // the record of log[3] is out of the retention once log[last] is applied
+        for (index, key, retain_from) in [(3, "a", 0), (5, "b", 0), (last, "c", 4)] {
+            let cmd = Command::new(RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: b"v".to_vec(),
+                ..Default::default()
+            }));
+            let revision = ce.prepare(&cmd).unwrap();
+            let er = ce.execute(&cmd).await.unwrap();
+            // the result of the last one is not known to the node
+            let recorded = (index != last).then_some(&er);
+            let asr = ce
+                .after_sync_proposal(
+                    &cmd,
+                    (1, index),
+                    index,
+                    revision,
+                    ProposalRecord::new(recorded, retain_from),
+                )
+                .await
+                .unwrap();
+            results.push((
+                index,
+                (1, index),
+                recorded.map(PbCodec::encode),
+                asr.revision(),
+            ));
+        }
+        drop(ce);
+
+        // the executor of the restarted node reads the records from the storage
+        let (ce, _kv_storage, _general_rev) = init_executor_on(db);
+        assert_eq!(ce.last_applied().unwrap(), last);
+        let restored: Vec<_> = ce
+            .applied_proposals(1)
+            .unwrap()
+            .into_iter()
+            .map(|proposal| {
+                (
+                    proposal.index,
+                    proposal.propose_id,
+                    proposal.er.as_ref().map(PbCodec::encode),
+                    proposal.asr.unwrap().revision(),
+                )
+            })
+            .collect();
+        assert_eq!(restored, results.split_off(1));
+        assert_eq!(ce.applied_proposals(6).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn revoke_should_be_synced_once_the_tombstones_are_written() {
+        let scenario = FailScenario::setup();
+        let (ce, kv_storage, _general_rev) = init_executor();
+        let cmds = [
+            RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id: 1,
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: b"foo".to_vec(),
+                value: b"v".to_vec(),
+                lease: 1,
+                ..Default::default()
+            }),
+            RequestWrapper::from(LeaseRevokeRequest {
+                id: 1,
+                ..Default::default()
+            }),
+        ]
+        .map(Command::new);
+        // the tombstones are written on this thread by the current thread runtime
+        scenario.cfg_local("lease_before_write_deletion", FailAction::Pause);
+        let mut revision = 0;
+        for (index, cmd) in (1..).zip(&cmds) {
+            revision = ce.prepare(cmd).unwrap();
+            let _er = ce.execute(cmd).await.unwrap();
+            let _asr = ce.after_sync(cmd, index, revision).await.unwrap();
+        }
+
+        // the revocation is applied before its tombstones are written
+        scenario.reached("lease_before_write_deletion", 1).await;
+        assert_eq!(kv_storage.synced_revision(), revision - 1);
+        scenario.remove("lease_before_write_deletion");
+        timeout(Duration::from_secs(3), kv_storage.wait_synced(revision))
+            .await
+            .unwrap();
+        let range = RequestWrapper::from(RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        });
+        let ResponseWrapper::RangeResponse(res) = kv_storage.execute(&range).unwrap().into_inner()
+        else {
+            unreachable!("a range gets a range response");
+        };
+        assert!(res.kvs.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn revoke_of_many_keys_should_not_delay_the_next_applies() {
+        const KEYS: usize = 50_000;
+        const KEYS_PER_TXN: usize = 1000;
+        let (ce, kv_storage, _general_rev) = init_executor();
+        let grant = |id| {
+            Command::new(RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id,
+                ..Default::default()
+            }))
+        };
+        let mut cmds = vec![grant(1)];
+        cmds.extend((0..KEYS / KEYS_PER_TXN).map(|txn| {
+            let success = (0..KEYS_PER_TXN)
+                .map(|i| RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: format!("key{:05}", txn * KEYS_PER_TXN + i).into_bytes(),
+                        value: b"v".to_vec(),
+                        lease: 1,
+                        ..Default::default()
+                    })),
+                })
+                .collect();
+            Command::new(RequestWrapper::from(TxnRequest {
+                compare: vec![],
+                success,
+                failure: vec![],
+            }))
+        }));
+        cmds.push(Command::new(RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        })));
+        let mut revision = 0;
+        for (index, cmd) in (1..).zip(&cmds) {
+            revision = ce.prepare(cmd).unwrap();
+            let _er = ce.execute(cmd).await.unwrap();
+            let _asr = ce.after_sync(cmd, index, revision).await.unwrap();
+        }
+
+        // the tombstones of the revocation are written while the next entry is applied
+        let next = grant(2);
+        let start = std::time::Instant::now();
+        let next_rev = ce.prepare(&next).unwrap();
+        let _er = ce.execute(&next).await.unwrap();
+        let _asr = ce
+            .after_sync(&next, cmds.len().numeric_cast::<u64>() + 1, next_rev)
+            .await
+            .unwrap();
+        let delay = start.elapsed();
+        timeout(Duration::from_secs(30), kv_storage.wait_synced(revision))
+            .await
+            .unwrap();
+        assert!(
+            delay < Duration::from_millis(100),
+            "the apply after the revocation was delayed by {delay:?}"
+        );
+        let range = RequestWrapper::from(RangeRequest {
+            key: b"key".to_vec(),
+            range_end: b"kez".to_vec(),
+            count_only: true,
+            ..Default::default()
+        });
+        let ResponseWrapper::RangeResponse(res) = kv_storage.execute(&range).unwrap().into_inner()
+        else {
+            unreachable!("a range gets a range response");
+        };
+        assert_eq!(res.count, 0);
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn failed_tombstones_should_stop_the_applies() {
+        let scenario = FailScenario::setup();
+        let (ce, kv_storage, _general_rev) = init_executor();
+        let cmds = [
+            RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id: 1,
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: b"foo".to_vec(),
+                value: b"v".to_vec(),
+                lease: 1,
+                ..Default::default()
+            }),
+            RequestWrapper::from(LeaseRevokeRequest {
+                id: 1,
+                ..Default::default()
+            }),
+        ]
+        .map(Command::new);
+        // the tombstones are written on this thread by the current thread runtime
+        scenario.cfg_local("lease_before_write_deletion", FailAction::Return);
+        let mut revision = 0;
+        for (index, cmd) in (1..).zip(&cmds) {
+            revision = ce.prepare(cmd).unwrap();
+            let _er = ce.execute(cmd).await.unwrap();
+            let _asr = ce.after_sync(cmd, index, revision).await.unwrap();
+        }
+
+        let reason = timeout(Duration::from_secs(3), ce.db.corrupted())
+            .await
+            .unwrap();
+        assert!(reason.contains(&format!("deleted keys of revision {revision}")));
+        assert_eq!(ce.storage_failure(), Some(reason));
+        let next = Command::new(RequestWrapper::from(PutRequest {
+            key: b"bar".to_vec(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        }));
+        let next_rev = ce.prepare(&next).unwrap();
+        let _er = ce.execute(&next).await.unwrap();
+        assert!(ce.after_sync(&next, 4, next_rev).await.is_err());
+        assert_eq!(kv_storage.synced_revision(), revision - 1);
+    }
+
     #[test]
     fn cmd_size_should_return_size_of_command() {
         let put_req1 = PutRequest {
@@ -429,7 +1313,11 @@ mod test {
             value: b"abcdefg".to_vec(),
             ..Default::default()
         };
-        let lease_req = LeaseGrantRequest { id: 123, ttl: 10 };
+        let lease_req = LeaseGrantRequest {
+            id: 123,
+            ttl: 10,
+            ..Default::default()
+        };
         let txn_req = TxnRequest {
             compare: vec![],
             success: vec![
@@ -6,32 +6,64 @@ use std::{
     time::Duration,
 };
 
-use curp::{rpc::ReadState, InflightId};
+use clippy_utilities::NumericCast;
+use curp::{
+    rpc::{CurpError, ReadState},
+    InflightId,
+};
 use dashmap::DashMap;
 use event_listener::Event;
 use futures::future::{join_all, Either};
+use itertools::Itertools;
 use tokio::time::timeout;
-use tracing::{debug, instrument};
-use utils::barrier::IdBarrier;
+use tracing::{debug, error, instrument};
+use utils::{barrier::IdBarrier, tracing::Extract};
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, SyncResponse},
     execute_error::ExecuteError,
-    request_validation::RequestValidator,
-    AuthInfo, ResponseWrapper,
+    request_validation::{RequestValidator, ValidationError},
+    AuthInfo, ResponseWrapper, SUB_REVISIONS_KEY, WITH_SUB_REVISIONS_KEY,
 };
 
-use super::barriers::IndexBarrier;
+use super::{
+    accounting::Accounting,
+    barriers::IndexBarrier,
+    stats_keys::{
+        crosses_stats_prefix, is_stats_key, writes_stats_keys, StatsKeys, STATS_RANGE_ERR_MSG,
+        STATS_READ_ONLY_ERR_MSG,
+    },
+};
 use crate::{
     metrics,
     revision_check::RevisionCheck,
     rpc::{
         CompactionRequest, CompactionResponse, DeleteRangeRequest, DeleteRangeResponse, Kv,
-        PutRequest, PutResponse, RangeRequest, RangeResponse, RequestWrapper, Response, ResponseOp,
-        TxnRequest, TxnResponse,
+        PutRequest, PutResponse, RangeRequest, RangeResponse, Request, RequestOp, RequestWrapper,
+        Response, ResponseOp, Ticket, TxnRequest, TxnResponse, WaitAppliedRequest,
     },
     storage::{AuthStore, KvStore},
 };
 
+/// Error message returned for tickets whose results have been evicted
+const UNKNOWN_TICKET_ERR_MSG: &str = "xline: unknown ticket, the result may have been evicted";
+
+/// Map the `ResultExpired` and `SessionExpired` errors of curp to a `NotFound`
+/// status, the others are returned as is
+fn unknown_ticket_error(status: tonic::Status) -> tonic::Status {
+    if status.code() != tonic::Code::FailedPrecondition || status.details().is_empty() {
+        return status;
+    }
+    let err = CurpError::from(status);
+    if matches!(
+        err,
+        CurpError::ResultExpired(()) | CurpError::SessionExpired(())
+    ) {
+        tonic::Status::not_found(UNKNOWN_TICKET_ERR_MSG)
+    } else {
+        err.into()
+    }
+}
+
 /// KV Server
 pub(crate) struct KvServer {
     /// KV storage
@@ -52,6 +84,12 @@ pub(crate) struct KvServer {
     compact_events: Arc<DashMap<u64, Arc<Event>>>,
     /// Next compact_id
     next_compact_id: AtomicU64,
+    /// Max number of keys affected by a single request, 0 means unlimited
+    max_keys_per_request: usize,
+    /// Usage accounting of the users
+    accounting: Arc<Accounting>,
+    /// Virtual keys of the server statistics
+    stats_keys: StatsKeys,
 }
 
 impl KvServer {
@@ -66,6 +104,9 @@ impl KvServer {
         compact_timeout: Duration,
         client: Arc<CurpClient>,
         compact_events: Arc<DashMap<u64, Arc<Event>>>,
+        max_keys_per_request: usize,
+        accounting: Arc<Accounting>,
+        stats_keys: StatsKeys,
     ) -> Self {
         Self {
             kv_storage,
@@ -77,7 +118,52 @@ impl KvServer {
             client,
             compact_events,
             next_compact_id: AtomicU64::new(0),
+            max_keys_per_request,
+            accounting,
+            stats_keys,
+        }
+    }
+
+    /// Range on the virtual keys of the statistics, the read permission of the keys
+    /// is checked the same as the stored ones
+    fn stats_range(
+        &self,
+        request: RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<RangeResponse, tonic::Status> {
+        self.auth_storage.check_permission(&request, auth_info)?;
+        let RequestWrapper::RangeRequest(ref range_req) = request else {
+            unreachable!("Receive wrong request {request:?} for the statistics");
+        };
+        Ok(self.stats_keys.range(range_req)?)
+    }
+
+    /// Reject a request affecting more keys than `max_keys_per_request` before it's
+    /// proposed. Internal operations like lease revocations are not limited.
+    ///
+    /// The keys are only counted once the caller is known to be permitted to write
+    /// them, so the count isn't revealed to others.
+    fn check_affected_keys(
+        &self,
+        request: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<(), tonic::Status> {
+        self.auth_storage.check_permission(request, auth_info)?;
+        #[allow(clippy::wildcard_enum_match_arm)]
+        let affected = match *request {
+            RequestWrapper::DeleteRangeRequest(ref req) => {
+                self.kv_storage.delete_range_affected_keys(req)
+            }
+            RequestWrapper::TxnRequest(ref req) => self.kv_storage.txn_affected_keys(req),
+            _ => return Ok(()),
+        };
+        metrics::get()
+            .request_affected_keys
+            .record(affected.numeric_cast(), &[]);
+        if self.max_keys_per_request != 0 && affected > self.max_keys_per_request {
+            return Err(ValidationError::TooManyKeys.into());
         }
+        Ok(())
     }
 
     /// Parse `ResponseOp`
@@ -113,6 +199,41 @@ impl KvServer {
         Ok(res)
     }
 
+    /// Propose request without waiting for the execution, return the ticket to fetch
+    /// the result with `WaitApplied`
+    async fn propose_async<T>(
+        &self,
+        request: T,
+        auth_info: Option<AuthInfo>,
+    ) -> Result<Ticket, tonic::Status>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let request = request.into();
+        let cmd = Command::new_with_auth_info(request, auth_info);
+        // the execution errors are only known at `WaitApplied`, reject the requests that
+        // are bound to fail before they are proposed
+        self.auth_storage
+            .check_permission(cmd.request(), cmd.auth_info())?;
+        let propose_id = self.client.propose_async(&cmd, None).await?;
+        Ok(propose_id.into())
+    }
+
+    /// Build the `PutResponse` of an applied put
+    fn put_response(cmd_res: CommandResponse, sync_res: Option<SyncResponse>) -> PutResponse {
+        let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+        if let Some(sync_res) = sync_res {
+            let revision = sync_res.revision();
+            debug!("Get revision {} for PutRequest", revision);
+            Self::update_header_revision(&mut res, revision);
+        }
+        if let Response::ResponsePut(response) = res {
+            response
+        } else {
+            unreachable!("Receive wrong response {res:?} for PutRequest");
+        }
+    }
+
     /// Update revision of `ResponseHeader`
     pub(crate) fn update_header_revision(response: &mut Response, revision: i64) {
         match *response {
@@ -154,6 +275,34 @@ impl KvServer {
             .ok_or(ExecuteError::RevisionCompacted(range_revision, compacted_revision).into())
     }
 
+    /// Check the sub revisions assigned to a txn when it's synced against the ones its
+    /// response implies, a mismatch is reported as an error and counted
+    fn check_sub_revisions(response: &TxnResponse, sub_revisions: &[i64]) {
+        let expected = response.sub_revisions();
+        if expected != sub_revisions {
+            error!(
+                "sub revisions {sub_revisions:?} of the txn synced at revision {} mismatch the ones of its response {expected:?}",
+                response.header.as_ref().map_or(0, |header| header.revision)
+            );
+            metrics::get().sub_revision_mismatches_total.add(1, &[]);
+        }
+    }
+
+    /// Wait until the current node has synced the given revision, a serializable read
+    /// with `min_revision` sees the writes at or below it
+    async fn wait_min_revision(&self, min_revision: i64) -> Result<(), tonic::Status> {
+        timeout(
+            self.range_retry_timeout,
+            self.kv_storage.wait_synced(min_revision),
+        )
+        .await
+        .map_err(|_elapsed| {
+            tonic::Status::deadline_exceeded(format!(
+                "xline: revision {min_revision} is not applied by this member in time"
+            ))
+        })
+    }
+
     /// Wait current node's state machine apply the conflict commands
     async fn wait_read_state(&self, cmd: &Command) -> Result<(), tonic::Status> {
         loop {
@@ -195,27 +344,44 @@ impl Kv for KvServer {
         &self,
         request: tonic::Request<RangeRequest>,
     ) -> Result<tonic::Response<RangeResponse>, tonic::Status> {
+        request.metadata().extract_span();
         let range_req = request.get_ref();
         range_req.validation()?;
         debug!("Receive grpc request: {}", range_req);
-        range_req.check_revision(
-            self.kv_storage.compacted_revision(),
-            self.kv_storage.revision(),
-        )?;
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        if crosses_stats_prefix(&range_req.key, &range_req.range_end) {
+            return Err(tonic::Status::invalid_argument(STATS_RANGE_ERR_MSG));
+        }
+        self.kv_storage.check_revision(range_req)?;
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        self.accounting.record_request(auth_info.as_ref());
         let range_required_revision = range_req.revision;
         let is_serializable = range_req.serializable;
+        let min_revision = range_req.min_revision;
+        let is_stats = is_stats_key(&range_req.key);
         let request = RequestWrapper::from(request.into_inner());
+        if is_stats {
+            // the statistics are of this member, there is nothing to wait for
+            let response = self.stats_range(request, auth_info.as_ref())?;
+            return Ok(tonic::Response::new(response));
+        }
         let cmd = Command::new_with_auth_info(request, auth_info);
         if !is_serializable {
             self.wait_read_state(&cmd).await?;
             // Double check whether the range request is compacted or not since the compaction request
             // may be executed during the process of `wait_read_state` which results in the result of
             // previous `check_range_request` outdated.
-            Self::check_range_compacted(
-                range_required_revision,
-                self.kv_storage.compacted_revision(),
-            )?;
+            if let RequestWrapper::RangeRequest(ref req) = *cmd.request() {
+                Self::check_range_compacted(
+                    range_required_revision,
+                    self.kv_storage
+                        .compacted_revision_of(&req.key, &req.range_end),
+                )?;
+            }
+        } else if min_revision > 0 {
+            self.wait_min_revision(min_revision).await?;
         }
 
         let res = self.do_serializable(&cmd)?;
@@ -234,25 +400,63 @@ impl Kv for KvServer {
         &self,
         request: tonic::Request<PutRequest>,
     ) -> Result<tonic::Response<PutResponse>, tonic::Status> {
+        request.metadata().extract_span();
         let put_req: &PutRequest = request.get_ref();
         put_req.validation()?;
         debug!("Receive grpc request: {}", put_req);
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        if is_stats_key(&put_req.key) {
+            return Err(tonic::Status::invalid_argument(STATS_READ_ONLY_ERR_MSG));
+        }
+        let written = put_req.key.len().saturating_add(put_req.value.len());
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        let usage = self.accounting.usage(auth_info.as_ref());
+        usage.record_request();
+        if put_req.r#async {
+            let ticket = self.propose_async(request.into_inner(), auth_info).await?;
+            usage.record_written(written);
+            // the revision is unknown until the put is applied, don't let the one of the
+            // header be taken for it
+            let header = self.kv_storage.gen_header_with_revision(0);
+            return Ok(tonic::Response::new(PutResponse {
+                header: Some(header),
+                ticket: Some(ticket),
+                ..Default::default()
+            }));
+        }
         let is_fast_path = true;
         let (cmd_res, sync_res) = self
             .propose(request.into_inner(), auth_info, is_fast_path)
             .await?;
-        let mut res = Self::parse_response_op(cmd_res.into_inner().into());
-        if let Some(sync_res) = sync_res {
-            let revision = sync_res.revision();
-            debug!("Get revision {} for PutRequest", revision);
-            Self::update_header_revision(&mut res, revision);
-        }
-        if let Response::ResponsePut(response) = res {
-            Ok(tonic::Response::new(response))
-        } else {
-            unreachable!("Receive wrong response {res:?} for PutRequest");
-        }
+        usage.record_written(written);
+        Ok(tonic::Response::new(Self::put_response(cmd_res, sync_res)))
+    }
+
+    /// WaitApplied waits for an async put to be applied and returns its `PutResponse`.
+    /// Xline extension
+    #[instrument(skip_all)]
+    async fn wait_applied(
+        &self,
+        request: tonic::Request<WaitAppliedRequest>,
+    ) -> Result<tonic::Response<PutResponse>, tonic::Status> {
+        request.metadata().extract_span();
+        // the ticket is only known to the client which put, it's not bound to a user
+        let _auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        let Some(ticket) = request.into_inner().ticket else {
+            return Err(tonic::Status::invalid_argument("ticket is required"));
+        };
+        debug!("Receive WaitApplied request for ticket: {ticket:?}");
+        let (cmd_res, sync_res) = self
+            .client
+            .wait_synced(ticket.into())
+            .await
+            .map_err(unknown_ticket_error)??;
+        Ok(tonic::Response::new(Self::put_response(cmd_res, sync_res)))
     }
 
     /// DeleteRange deletes the given range from the key-value store.
@@ -263,14 +467,22 @@ impl Kv for KvServer {
         &self,
         request: tonic::Request<DeleteRangeRequest>,
     ) -> Result<tonic::Response<DeleteRangeResponse>, tonic::Status> {
+        request.metadata().extract_span();
         let delete_range_req = request.get_ref();
         delete_range_req.validation()?;
         debug!("Receive grpc request: {}", delete_range_req);
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
-        let is_fast_path = true;
-        let (cmd_res, sync_res) = self
-            .propose(request.into_inner(), auth_info, is_fast_path)
+        if is_stats_key(&delete_range_req.key) {
+            return Err(tonic::Status::invalid_argument(STATS_READ_ONLY_ERR_MSG));
+        }
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
             .await?;
+        self.accounting.record_request(auth_info.as_ref());
+        let request = RequestWrapper::from(request.into_inner());
+        self.check_affected_keys(&request, auth_info.as_ref())?;
+        let is_fast_path = true;
+        let (cmd_res, sync_res) = self.propose(request, auth_info, is_fast_path).await?;
         let mut res = Self::parse_response_op(cmd_res.into_inner().into());
         if let Some(sync_res) = sync_res {
             let revision = sync_res.revision();
@@ -293,14 +505,24 @@ impl Kv for KvServer {
         &self,
         request: tonic::Request<TxnRequest>,
     ) -> Result<tonic::Response<TxnResponse>, tonic::Status> {
+        request.metadata().extract_span();
         let txn_req = request.get_ref();
         txn_req.validation()?;
         debug!("Receive grpc request: {}", txn_req);
-        txn_req.check_revision(
-            self.kv_storage.compacted_revision(),
-            self.kv_storage.revision(),
-        )?;
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        if writes_stats_keys(&txn_req.success) || writes_stats_keys(&txn_req.failure) {
+            return Err(tonic::Status::invalid_argument(STATS_READ_ONLY_ERR_MSG));
+        }
+        self.kv_storage.check_revision(txn_req)?;
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        let usage = self.accounting.usage(auth_info.as_ref());
+        usage.record_request();
+        let written = (put_bytes(&txn_req.success), put_bytes(&txn_req.failure));
+        let with_sub_revisions = request.metadata().contains_key(WITH_SUB_REVISIONS_KEY);
+        // the sub revisions are assigned when the txn is synced, read only txns have none
+        let mut sub_revisions = Vec::new();
         let res = if txn_req.is_read_only() {
             debug!("TxnRequest is read only");
             let is_serializable = txn_req.is_serializable();
@@ -311,20 +533,41 @@ impl Kv for KvServer {
             }
             self.do_serializable(&cmd)?
         } else {
-            let is_fast_path = true;
-            let (cmd_res, sync_res) = self
-                .propose(request.into_inner(), auth_info, is_fast_path)
-                .await?;
+            let request = RequestWrapper::from(request.into_inner());
+            self.check_affected_keys(&request, auth_info.as_ref())?;
+            // the sub revisions are only known once the txn is synced
+            let is_fast_path = !with_sub_revisions;
+            let (cmd_res, sync_res) = self.propose(request, auth_info, is_fast_path).await?;
             let mut res = Self::parse_response_op(cmd_res.into_inner().into());
             if let Some(sync_res) = sync_res {
                 let revision = sync_res.revision();
                 debug!("Get revision {} for TxnRequest", revision);
                 Self::update_header_revision(&mut res, revision);
+                sub_revisions = sync_res.sub_revisions().to_vec();
             }
             res
         };
         if let Response::ResponseTxn(response) = res {
-            Ok(tonic::Response::new(response))
+            usage.record_written(if response.succeeded {
+                written.0
+            } else {
+                written.1
+            });
+            if with_sub_revisions {
+                Self::check_sub_revisions(&response, &sub_revisions);
+            }
+            let mut response = tonic::Response::new(response);
+            if with_sub_revisions {
+                let _ignore = response.metadata_mut().insert(
+                    SUB_REVISIONS_KEY,
+                    sub_revisions
+                        .iter()
+                        .join(",")
+                        .parse()
+                        .unwrap_or_else(|e| panic!("metadata value parse error: {e}")),
+                );
+            }
+            Ok(response)
         } else {
             unreachable!("Receive wrong response {res:?} for TxnRequest");
         }
@@ -338,12 +581,17 @@ impl Kv for KvServer {
         &self,
         request: tonic::Request<CompactionRequest>,
     ) -> Result<tonic::Response<CompactionResponse>, tonic::Status> {
+        request.metadata().extract_span();
         debug!("Receive CompactionRequest {:?}", request);
         let compacted_revision = self.kv_storage.compacted_revision();
         let current_revision = self.kv_storage.revision();
         let req = request.get_ref();
         req.check_revision(compacted_revision, current_revision)?;
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        self.accounting.record_request(auth_info.as_ref());
         let physical = req.physical;
         let request = RequestWrapper::from(request.into_inner());
         let cmd = Command::new_with_auth_info(request, auth_info);
@@ -373,10 +621,47 @@ impl Kv for KvServer {
     }
 }
 
+/// Bytes of the keys and values put by the operations, it's unknown which branch of a
+/// nested txn will be taken, so the larger one is counted
+fn put_bytes(ops: &[RequestOp]) -> usize {
+    ops.iter()
+        .filter_map(|op| op.request.as_ref())
+        .map(|request| match *request {
+            Request::RequestPut(ref req) => req.key.len().saturating_add(req.value.len()),
+            Request::RequestTxn(ref req) => put_bytes(&req.success).max(put_bytes(&req.failure)),
+            Request::RequestRange(_) | Request::RequestDeleteRange(_) => 0,
+        })
+        .fold(0, usize::saturating_add)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::rpc::{Request, RequestOp};
+
+    #[test]
+    fn put_bytes_should_count_the_larger_nested_branch() {
+        let put = |key: &[u8], value: &[u8]| RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.to_vec(),
+                value: value.to_vec(),
+                ..Default::default()
+            })),
+        };
+        let ops = vec![
+            put(b"k1", b"value"),
+            RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![],
+                    success: vec![put(b"k2", b"v")],
+                    failure: vec![put(b"k3", b"longer value")],
+                })),
+            },
+            RequestOp {
+                request: Some(Request::RequestRange(RangeRequest::default())),
+            },
+        ];
+        assert_eq!(put_bytes(&ops), 7 + 14);
+    }
 
     #[test]
     fn txn_check() {
@@ -1,13 +1,26 @@
-use std::{pin::Pin, sync::Arc, time::Duration};
+use std::{
+    ops::Add,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use async_stream::{stream, try_stream};
+use async_stream::try_stream;
 use clippy_utilities::NumericCast;
 use curp::members::ClusterInfo;
-use futures::stream::Stream;
-use tokio::time;
+use futures::{future, stream::Stream};
+use parking_lot::Mutex;
+use tokio::{sync::mpsc, time};
+use tokio_stream::wrappers::ReceiverStream;
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
-use tonic::transport::Endpoint;
+use tonic::{
+    metadata::AsciiMetadataValue,
+    transport::{Channel, Endpoint},
+};
 use tracing::{debug, warn};
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
@@ -18,22 +31,46 @@ use utils::{
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, SyncResponse},
     execute_error::ExecuteError,
+    AuthInfo, LEADER_HINT_KEY, RETRY_AFTER_KEY,
 };
 
+use super::{
+    accounting::Accounting,
+    rate_limit::{client_identity, ClientRateLimiter, ClientRates},
+    read_only::read_only_error,
+};
 use crate::{
     id_gen::IdGenerator,
     metrics,
     rpc::{
-        Lease, LeaseClient, LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest,
-        LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeRequest,
-        LeaseRevokeResponse, LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, RequestWrapper,
+        Lease, LeaseCheckpointRequest, LeaseClient, LeaseGrantRequest, LeaseGrantResponse,
+        LeaseKeepAliveRequest, LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse,
+        LeaseRevokeBatchRequest, LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus,
+        LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, RequestWrapper,
     },
-    storage::{AuthStore, LeaseStore},
+    storage::{lease_store, AuthStore, LeaseStore},
 };
 
 /// Default Lease Request Time
 const DEFAULT_LEASE_REQUEST_TIME: Duration = Duration::from_millis(500);
 
+/// How long a keep alive request keeps being retried when it cannot be forwarded to
+/// the leader, e.g. during an election
+const KEEP_ALIVE_FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval between two attempts of forwarding a keep alive request
+const KEEP_ALIVE_FORWARD_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval between two revoke batches, which limits the revoke rate to ten batches per
+/// second and leaves room for other requests
+const REVOKE_BATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Max number of expired leases revoked by a single proposal
+const MAX_LEASES_PER_REVOKE: usize = 128;
+
+/// Window over which the lease grant rates of the clients are measured
+const GRANT_RATE_WINDOW: Duration = Duration::from_secs(10);
+
 /// Lease Server
 pub(crate) struct LeaseServer {
     /// Lease storage
@@ -44,16 +81,23 @@ pub(crate) struct LeaseServer {
     client: Arc<CurpClient>,
     /// Id generator
     id_gen: Arc<IdGenerator>,
-    /// cluster information
-    cluster_info: Arc<ClusterInfo>,
-    /// Client tls config
-    client_tls_config: Option<ClientTlsConfig>,
+    /// Channel to the leader, requests not served locally are forwarded through it
+    leader: Arc<LeaderChannel>,
+    /// Whether the node rejects mutating requests
+    read_only: Arc<AtomicBool>,
+    /// Limiter of lease grants of each client
+    grant_limiter: ClientRateLimiter,
+    /// Lease grant rates of each client
+    grant_rates: Arc<ClientRates>,
+    /// Usage accounting of the users
+    accounting: Arc<Accounting>,
     /// Task manager
     task_manager: Arc<TaskManager>,
 }
 
 impl LeaseServer {
     /// New `LeaseServer`
+    #[allow(clippy::too_many_arguments)] // Consistent with other servers
     pub(crate) fn new(
         lease_storage: Arc<LeaseStore>,
         auth_storage: Arc<AuthStore>,
@@ -61,60 +105,335 @@ impl LeaseServer {
         id_gen: Arc<IdGenerator>,
         cluster_info: Arc<ClusterInfo>,
         client_tls_config: Option<ClientTlsConfig>,
+        checkpoint_interval: Duration,
+        expiry_persist_interval: Duration,
+        revoke_batch_size: usize,
+        read_only: Arc<AtomicBool>,
+        grant_limiter: ClientRateLimiter,
+        accounting: Arc<Accounting>,
         task_manager: &Arc<TaskManager>,
     ) -> Arc<Self> {
+        let grant_rates = Arc::new(ClientRates::new(GRANT_RATE_WINDOW));
+        metrics::register_lease_grant_rates(&grant_rates);
+        let leader = Arc::new(LeaderChannel::new(
+            Arc::clone(&client),
+            cluster_info,
+            client_tls_config,
+        ));
         let lease_server = Arc::new(Self {
             lease_storage,
             auth_storage,
             client,
             id_gen,
-            cluster_info,
-            client_tls_config,
+            leader,
+            read_only,
+            grant_limiter,
+            grant_rates,
+            accounting,
             task_manager: Arc::clone(task_manager),
         });
         task_manager.spawn(TaskName::RevokeExpiredLeases, |n| {
-            Self::revoke_expired_leases_task(Arc::clone(&lease_server), n)
+            Self::revoke_expired_leases_task(Arc::clone(&lease_server), revoke_batch_size, n)
+        });
+        task_manager.spawn(TaskName::CheckpointLeases, |n| {
+            Self::checkpoint_leases_task(Arc::clone(&lease_server), checkpoint_interval, n)
         });
+        if !expiry_persist_interval.is_zero() {
+            task_manager.spawn(TaskName::PersistLeaseExpiries, |n| {
+                Self::persist_lease_expiries_task(
+                    Arc::clone(&lease_server),
+                    expiry_persist_interval,
+                    n,
+                )
+            });
+        }
         lease_server
     }
 
+    /// Generate a lease id that is neither 0 nor held by an existing lease
+    fn next_lease_id(&self) -> i64 {
+        loop {
+            let id = self.id_gen.next();
+            if id != 0 && self.lease_storage.look_up(id).is_none() {
+                return id;
+            }
+            debug!("generated lease id {id} is already in use, retry");
+        }
+    }
+
+    /// Forward a grant to the leader, which converts its deadline to a ttl
+    async fn forward_lease_grant(
+        &self,
+        request: tonic::Request<LeaseGrantRequest>,
+    ) -> Result<tonic::Response<LeaseGrantResponse>, tonic::Status> {
+        let leader_id = self.leader.leader_id().await?;
+        let mut lease_client = LeaseClient::new(self.leader.channel(leader_id)?);
+        lease_client.lease_grant(request).await
+    }
+
     /// Task of revoke expired leases
+    ///
+    /// It sleeps until the earliest expiry instead of polling, and is paused while
+    /// the current node is not the leader. Revocations left unfinished by the chunked
+    /// deletion of their keys are continued before the expired leases are revoked.
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
     async fn revoke_expired_leases_task(
         lease_server: Arc<LeaseServer>,
+        revoke_batch_size: usize,
         shutdown_listener: Listener,
     ) {
         loop {
-            tokio::select! {
-                _ = shutdown_listener.wait() => return ,
-                _ = time::sleep(DEFAULT_LEASE_REQUEST_TIME) => {}
+            // listen before checking the expiry, so that no change will be missed
+            let expiry_listener = lease_server.lease_storage.expiry_listener();
+            let (next_expiry, revoking) = if lease_server.lease_storage.is_primary() {
+                (
+                    lease_server.lease_storage.next_expiry(),
+                    lease_server.lease_storage.revoking_leases(),
+                )
+            } else {
+                (None, vec![])
+            };
+            if revoking.is_empty() {
+                let wait_expiry = async move {
+                    match next_expiry {
+                        Some(expiry) => time::sleep_until(time::Instant::from_std(expiry)).await,
+                        None => future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = shutdown_listener.wait() => return,
+                    _ = expiry_listener => continue,
+                    _ = wait_expiry => {}
+                }
             }
-            // only leader will check expired lease
-            if lease_server.lease_storage.is_primary() {
-                for id in lease_server.lease_storage.find_expired_leases() {
-                    let _handle = tokio::spawn({
-                        let s = Arc::clone(&lease_server);
-                        let token_option = lease_server.auth_storage.root_token();
-                        async move {
-                            let mut request = tonic::Request::new(LeaseRevokeRequest { id });
-                            if let Ok(token) = token_option {
-                                let _ignore = request.metadata_mut().insert(
-                                    "token",
-                                    token.parse().unwrap_or_else(|e| {
-                                        panic!("metadata value parse error: {e}")
-                                    }),
-                                );
-                            }
-                            if let Err(e) = s.lease_revoke(request).await {
-                                warn!("Failed to revoke expired leases: {}", e);
+            if !lease_server.lease_storage.is_primary() {
+                continue;
+            }
+            if !revoking.is_empty() {
+                tokio::select! {
+                    _ = shutdown_listener.wait() => return,
+                    _ = lease_server.continue_revokes(&revoking) => {}
+                }
+            }
+            // the earliest expired leases are revoked batch by batch, the others stay
+            // queued so that grants and renewals are not starved by a burst of expiries
+            for i in 0_usize.. {
+                if i > 0 {
+                    tokio::select! {
+                        _ = shutdown_listener.wait() => return,
+                        _ = time::sleep(REVOKE_BATCH_INTERVAL) => {}
+                    }
+                }
+                if !lease_server.lease_storage.is_primary() {
+                    break;
+                }
+                utils::fail_point_async!("lease_before_revoke_batch");
+                let batch = lease_server
+                    .lease_storage
+                    .find_expired_leases(revoke_batch_size);
+                // the leases are revoked one by one until every member applies the
+                // batched revocations
+                let leases_per_revoke = if lease_server.lease_storage.batched_revoke_enabled() {
+                    MAX_LEASES_PER_REVOKE
+                } else {
+                    1
+                };
+                let revokes: Vec<_> = batch.chunks(leases_per_revoke).collect();
+                let results = future::join_all(
+                    revokes
+                        .iter()
+                        .map(|ids| lease_server.revoke_expired_leases(ids)),
+                )
+                .await;
+                for (&ids, res) in revokes.iter().zip(results) {
+                    if let Err(e) = res {
+                        warn!("Failed to revoke expired leases {ids:?}: {e}");
+                        if lease_server.lease_storage.is_primary() {
+                            for &id in ids {
+                                lease_server
+                                    .lease_storage
+                                    .requeue_expired(id, DEFAULT_LEASE_REQUEST_TIME);
                             }
                         }
-                    });
+                    }
+                }
+                // a batch below the limit has drained the expired leases
+                if revoke_batch_size == 0 || batch.len() < revoke_batch_size {
+                    break;
                 }
             }
         }
     }
 
+    /// Revoke expired leases with the root token, several leases are revoked by a single
+    /// batched revocation, which clients can't propose
+    async fn revoke_expired_leases(&self, ids: &[i64]) -> Result<(), tonic::Status> {
+        if let &[id] = ids {
+            let _res = self.lease_revoke(self.root_revoke_request(id)?).await?;
+        } else {
+            let request = tonic::Request::new(LeaseRevokeBatchRequest {
+                ids: ids.to_vec(),
+                chunk_size: self.revoke_chunk_size(),
+            });
+            let _res = self.propose(self.with_root_token(request)?, false).await?;
+        }
+        metrics::get()
+            .lease_expired_total
+            .add(ids.len().numeric_cast(), &[]);
+        Ok(())
+    }
+
+    /// Delete the next chunk of keys of each lease being revoked, a failed chunk is
+    /// retried after a while. The slow path is used so that the next chunk is not
+    /// proposed before the current one is applied.
+    async fn continue_revokes(&self, ids: &[i64]) {
+        let results =
+            future::join_all(ids.iter().map(|&id| async move {
+                self.propose(self.root_revoke_request(id)?, false).await
+            }))
+            .await;
+        let mut failed = false;
+        for (&id, res) in ids.iter().zip(results) {
+            if let Err(e) = res {
+                warn!("Failed to continue the revocation of lease {id}: {e}");
+                failed = true;
+            }
+        }
+        if failed {
+            time::sleep(DEFAULT_LEASE_REQUEST_TIME).await;
+        }
+    }
+
+    /// The chunk size of the revocations proposed by this member
+    fn revoke_chunk_size(&self) -> u64 {
+        self.lease_storage.revoke_chunk_size().numeric_cast()
+    }
+
+    /// Build a revoke request of a lease with the root token
+    fn root_revoke_request(
+        &self,
+        id: i64,
+    ) -> Result<tonic::Request<LeaseRevokeRequest>, tonic::Status> {
+        self.with_root_token(tonic::Request::new(LeaseRevokeRequest {
+            id,
+            chunk_size: self.revoke_chunk_size(),
+        }))
+    }
+
+    /// Attach the root token to a request proposed by the server itself
+    fn with_root_token<T>(
+        &self,
+        mut request: tonic::Request<T>,
+    ) -> Result<tonic::Request<T>, tonic::Status> {
+        if let Ok(token) = self.auth_storage.root_token() {
+            let value = token.parse().map_err(|e| {
+                tonic::Status::internal(format!("failed to attach the root token: {e}"))
+            })?;
+            let _ignore = request.metadata_mut().insert("token", value);
+        }
+        Ok(request)
+    }
+
+    /// Task of checkpointing remaining ttl of leases, so that a new leader won't
+    /// restart leases from their full ttl
+    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    async fn checkpoint_leases_task(
+        lease_server: Arc<LeaseServer>,
+        interval: Duration,
+        shutdown_listener: Listener,
+    ) {
+        loop {
+            tokio::select! {
+                _ = shutdown_listener.wait() => return,
+                _ = time::sleep(interval) => {}
+            }
+            if !lease_server.lease_storage.is_primary() {
+                continue;
+            }
+            let checkpoints = lease_server.lease_storage.checkpoints();
+            if checkpoints.is_empty() {
+                continue;
+            }
+            let request = tonic::Request::new(LeaseCheckpointRequest { checkpoints });
+            let res = match lease_server.with_root_token(request) {
+                Ok(request) => lease_server.propose(request, true).await.err(),
+                Err(e) => Some(e),
+            };
+            if let Some(e) = res {
+                warn!("Failed to checkpoint leases: {}", e);
+            }
+        }
+    }
+
+    /// Task of persisting the remaining time of leases to the local lease expiry table,
+    /// so that the leader restores them when it's re-elected after a restart
+    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    async fn persist_lease_expiries_task(
+        lease_server: Arc<LeaseServer>,
+        interval: Duration,
+        shutdown_listener: Listener,
+    ) {
+        loop {
+            tokio::select! {
+                _ = shutdown_listener.wait() => return,
+                _ = time::sleep(interval) => {}
+            }
+            if !lease_server.lease_storage.is_primary() {
+                continue;
+            }
+            if let Err(e) = lease_server.lease_storage.persist_expiries() {
+                warn!("Failed to persist lease expiries: {e}");
+            }
+        }
+    }
+
+    /// Build the `LeaseTimeToLive` response of a lease with the given remaining ttl
+    fn time_to_live_response(
+        &self,
+        req: &LeaseTimeToLiveRequest,
+        lease: &lease_store::Lease,
+        remaining: Duration,
+    ) -> LeaseTimeToLiveResponse {
+        let keys = req.keys.then(|| lease.keys()).unwrap_or_default();
+        LeaseTimeToLiveResponse {
+            header: Some(self.lease_storage.gen_header()),
+            id: req.id,
+            ttl: remaining.as_secs().numeric_cast(),
+            granted_ttl: lease.ttl().as_secs().numeric_cast(),
+            keys,
+        }
+    }
+
+    /// Build the `LeaseTimeToLive` response of a follower from the latest checkpoint,
+    /// returns `None` if no checkpoint has been received for the lease yet
+    fn checkpointed_time_to_live(
+        &self,
+        req: &LeaseTimeToLiveRequest,
+    ) -> Option<LeaseTimeToLiveResponse> {
+        let lease = self.lease_storage.look_up(req.id)?;
+        let remaining = lease.checkpointed_remaining()?;
+        Some(self.time_to_live_response(req, &lease, remaining))
+    }
+
+    /// Build the `LeaseTimeToLive` response from the local replicated state without
+    /// consensus, a follower reports the latest checkpoint or the granted ttl
+    fn serializable_time_to_live(
+        &self,
+        req: &LeaseTimeToLiveRequest,
+    ) -> Result<LeaseTimeToLiveResponse, tonic::Status> {
+        let Some(lease) = self.lease_storage.look_up(req.id) else {
+            return Err(ExecuteError::LeaseNotFound(req.id).into());
+        };
+        let remaining = if self.lease_storage.is_primary() {
+            lease.remaining()
+        } else {
+            lease
+                .checkpointed_remaining()
+                .unwrap_or_else(|| lease.ttl())
+        };
+        Ok(self.time_to_live_response(req, &lease, remaining))
+    }
+
     /// Propose request and get result with fast/slow path
     async fn propose<T>(
         &self,
@@ -124,7 +443,10 @@ impl LeaseServer {
     where
         T: Into<RequestWrapper>,
     {
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
         let request = request.into_inner().into();
         // FIXME: get the keys in the conflict pools
         let cmd = Command::new_with_auth_info(request, auth_info);
@@ -132,16 +454,39 @@ impl LeaseServer {
         Ok(res)
     }
 
-    /// Handle keep alive at leader
+    /// Check the permission of a request before it is proposed, the request is
+    /// accounted to its user
+    async fn check_permission<T>(&self, request: &tonic::Request<T>) -> Result<(), tonic::Status>
+    where
+        T: Clone + Into<RequestWrapper>,
+    {
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(request)
+            .await?;
+        self.accounting.record_request(auth_info.as_ref());
+        self.auth_storage
+            .check_permission(&request.get_ref().clone().into(), auth_info.as_ref())?;
+        Ok(())
+    }
+
+    /// Handle keep alive requests of a stream
+    ///
+    /// Renewals are handled locally while the current node is the leader and forwarded to
+    /// the leader otherwise, so the stream keeps working across leader changes.
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
-    fn leader_keep_alive(
+    fn keep_alive_stream(
         &self,
         mut request_stream: tonic::Streaming<LeaseKeepAliveRequest>,
+        auth_info: Option<AuthInfo>,
+        token: Option<AsciiMetadataValue>,
     ) -> Pin<Box<dyn Stream<Item = Result<LeaseKeepAliveResponse, tonic::Status>> + Send>> {
         let shutdown_listener = self
             .task_manager
             .get_shutdown_listener(TaskName::LeaseKeepAlive);
         let lease_storage = Arc::clone(&self.lease_storage);
+        let auth_storage = Arc::clone(&self.auth_storage);
+        let mut forwarder = KeepAliveForwarder::new(Arc::clone(&self.leader), token);
         let stream = try_stream! {
            loop {
                 let keep_alive_req: LeaseKeepAliveRequest = tokio::select! {
@@ -158,72 +503,277 @@ impl LeaseServer {
                     }
                 };
                 debug!("Receive LeaseKeepAliveRequest {:?}", keep_alive_req);
-                let ttl = if lease_storage.is_primary() {
-                    tokio::select! {
-                        _ = shutdown_listener.wait() => {
-                            debug!("Lease keep alive shutdown");
-                            break;
-                        }
-                        _ = lease_storage.wait_synced(keep_alive_req.id) => {
-                        }
-                    };
-                    lease_storage.keep_alive(keep_alive_req.id).map_err(Into::into)
-                } else {
-                    Err(tonic::Status::failed_precondition("current node is not a leader"))
+                auth_storage.check_lease_read_permission(keep_alive_req.id, auth_info.as_ref())?;
+                let res = tokio::select! {
+                    _ = shutdown_listener.wait() => {
+                        debug!("Lease keep alive shutdown");
+                        break;
+                    }
+                    res = Self::handle_keep_alive(&lease_storage, &mut forwarder, keep_alive_req) => {
+                        res
+                    }
                 }?;
-                yield LeaseKeepAliveResponse {
-                    id: keep_alive_req.id,
-                    ttl,
-                    ..LeaseKeepAliveResponse::default()
-                };
+                utils::fail_point_async!("lease_before_keep_alive_response");
+                yield res;
             }
         };
         Box::pin(stream)
     }
 
-    /// Handle keep alive at follower
-    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
-    async fn follower_keep_alive(
-        &self,
-        mut request_stream: tonic::Streaming<LeaseKeepAliveRequest>,
-        leader_addrs: &[String],
-    ) -> Result<
-        Pin<Box<dyn Stream<Item = Result<LeaseKeepAliveResponse, tonic::Status>> + Send>>,
-        tonic::Status,
-    > {
-        let shutdown_listener = self
-            .task_manager
-            .get_shutdown_listener(TaskName::LeaseKeepAlive);
-        let endpoints = build_endpoints(leader_addrs, self.client_tls_config.as_ref())?;
-        let channel = tonic::transport::Channel::balance_list(endpoints.into_iter());
-        let mut lease_client = LeaseClient::new(channel);
-
-        let redirect_stream = stream! {
-            loop {
-                tokio::select! {
-                    _ = shutdown_listener.wait() => {
-                        debug!("Lease keep alive shutdown");
-                        break;
-                    }
-                    res = request_stream.message() => {
-                        if let Ok(Some(keep_alive_req)) = res {
-                            yield keep_alive_req;
-                        } else {
-                            break;
-                        }
-                    }
+    /// Handle a keep alive request locally if the current node is the leader, or forward
+    /// it to the leader. Gives up with a retriable status if it cannot be forwarded within
+    /// `KEEP_ALIVE_FORWARD_TIMEOUT`.
+    async fn handle_keep_alive(
+        lease_storage: &LeaseStore,
+        forwarder: &mut KeepAliveForwarder,
+        req: LeaseKeepAliveRequest,
+    ) -> Result<LeaseKeepAliveResponse, tonic::Status> {
+        let deadline = time::Instant::now().add(KEEP_ALIVE_FORWARD_TIMEOUT);
+        loop {
+            if lease_storage.is_primary() {
+                forwarder.disconnect();
+                lease_storage.wait_synced(req.id).await;
+                // A keep alive for an unknown or expired lease should not terminate
+                // the whole stream, as the stream may be shared by other leases
+                let ttl = match lease_storage.keep_alive(req.id) {
+                    Ok(ttl) => ttl,
+                    Err(ExecuteError::LeaseNotFound(_) | ExecuteError::LeaseExpired(_)) => 0,
+                    // demoted in the meantime, forward it to the new leader
+                    Err(ExecuteError::NotLeader) => continue,
+                    Err(e) => return Err(tonic::Status::from(e)),
+                };
+                return Ok(LeaseKeepAliveResponse {
+                    header: Some(lease_storage.gen_header()),
+                    id: req.id,
+                    ttl,
+                });
+            }
+            match forwarder.forward(req.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(e) if time::Instant::now() >= deadline => {
+                    return Err(keep_alive_forward_failed(
+                        req.id,
+                        &e,
+                        forwarder.leader_hint(),
+                    ));
+                }
+                Err(e) => {
+                    debug!(
+                        "forward keep alive of lease {} failed: {e:?}, retry",
+                        req.id
+                    );
+                    forwarder.disconnect();
+                    time::sleep(KEEP_ALIVE_FORWARD_RETRY_INTERVAL).await;
                 }
             }
+        }
+    }
+}
 
+/// A channel to the current leader, built once and reused until the leader changes
+struct LeaderChannel {
+    /// Consensus client
+    client: Arc<CurpClient>,
+    /// cluster information
+    cluster_info: Arc<ClusterInfo>,
+    /// Client tls config
+    client_tls_config: Option<ClientTlsConfig>,
+    /// The leader the cached channel connects to
+    cached: Mutex<Option<(u64, Channel)>>,
+}
+
+impl LeaderChannel {
+    /// New `LeaderChannel`
+    fn new(
+        client: Arc<CurpClient>,
+        cluster_info: Arc<ClusterInfo>,
+        client_tls_config: Option<ClientTlsConfig>,
+    ) -> Self {
+        Self {
+            client,
+            cluster_info,
+            client_tls_config,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Id of the current leader
+    async fn leader_id(&self) -> Result<u64, tonic::Status> {
+        Ok(self.client.fetch_leader_id(false).await?)
+    }
+
+    /// A channel to the given leader, the cached one is reused if it connects to it
+    fn channel(&self, leader_id: u64) -> Result<Channel, tonic::Status> {
+        let mut cached = self.cached.lock();
+        if let Some((id, ref channel)) = *cached {
+            if id == leader_id {
+                return Ok(channel.clone());
+            }
+        }
+        // the leader may be a member this node hasn't learned of yet, let the caller retry
+        let Some(leader_addrs) = self.cluster_info.client_urls(leader_id) else {
+            return Err(tonic::Status::unavailable(format!(
+                "the address of leader {leader_id} is unknown"
+            )));
         };
+        let endpoints = build_endpoints(&leader_addrs, self.client_tls_config.as_ref())?;
+        let channel = Channel::balance_list(endpoints.into_iter());
+        *cached = Some((leader_id, channel.clone()));
+        Ok(channel)
+    }
+}
+
+/// Forwards keep alive requests of a stream to the current leader
+///
+/// The forwarding stream is opened with the token of the caller, so that the leader checks
+/// the requests against the caller's own permissions.
+struct KeepAliveForwarder {
+    /// Channel to the leader
+    leader: Arc<LeaderChannel>,
+    /// Token of the caller
+    token: Option<AsciiMetadataValue>,
+    /// Forwarding stream to the leader, reused until the leader changes
+    conn: Option<ForwardConn>,
+    /// Id of the leader seen by the last forward
+    last_leader_id: Option<u64>,
+}
+
+/// A keep alive stream to a leader
+struct ForwardConn {
+    /// Id of the leader
+    leader_id: u64,
+    /// Sender of the requests
+    req_tx: mpsc::Sender<LeaseKeepAliveRequest>,
+    /// Responses from the leader
+    resp_stream: tonic::Streaming<LeaseKeepAliveResponse>,
+}
 
-        let stream = lease_client
-            .lease_keep_alive(redirect_stream)
+impl ForwardConn {
+    /// Send a keep alive request to the leader and wait for its response
+    async fn keep_alive(
+        &mut self,
+        req: LeaseKeepAliveRequest,
+    ) -> Result<LeaseKeepAliveResponse, tonic::Status> {
+        self.req_tx
+            .send(req)
+            .await
+            .map_err(|_e| tonic::Status::unavailable("forwarding stream closed"))?;
+        self.resp_stream
+            .message()
             .await?
-            .into_inner();
+            .ok_or_else(|| tonic::Status::unavailable("forwarding stream closed"))
+    }
+}
+
+impl KeepAliveForwarder {
+    /// New `KeepAliveForwarder`
+    fn new(leader: Arc<LeaderChannel>, token: Option<AsciiMetadataValue>) -> Self {
+        Self {
+            leader,
+            token,
+            conn: None,
+            last_leader_id: None,
+        }
+    }
+
+    /// The client urls of the leader seen by the last forward
+    fn leader_hint(&self) -> Option<String> {
+        leader_hint(&self.leader.cluster_info, self.last_leader_id)
+    }
+
+    /// Drop the forwarding stream, a new one will be built on the next forward
+    fn disconnect(&mut self) {
+        self.conn = None;
+    }
+
+    /// Forward a keep alive request to the current leader
+    async fn forward(
+        &mut self,
+        req: LeaseKeepAliveRequest,
+    ) -> Result<LeaseKeepAliveResponse, tonic::Status> {
+        let leader_id = self.leader.leader_id().await?;
+        self.last_leader_id = Some(leader_id);
+        if leader_id == self.leader.cluster_info.self_id() {
+            // the current node won the election but hasn't been promoted yet
+            return Err(ExecuteError::NotLeader.into());
+        }
+        let mut conn = match self.conn.take() {
+            Some(conn) if conn.leader_id == leader_id => conn,
+            _ => self.connect(leader_id).await?,
+        };
+        let res = conn.keep_alive(req).await;
+        // a failed stream is rebuilt on the next forward
+        if res.is_ok() {
+            self.conn = Some(conn);
+        }
+        res
+    }
+
+    /// Open a keep alive stream to the leader
+    async fn connect(&self, leader_id: u64) -> Result<ForwardConn, tonic::Status> {
+        let mut lease_client = LeaseClient::new(self.leader.channel(leader_id)?);
+        let (req_tx, req_rx) = mpsc::channel(1);
+        let mut request = tonic::Request::new(ReceiverStream::new(req_rx));
+        if let Some(token) = self.token.clone() {
+            let _ignore = request.metadata_mut().insert("token", token);
+        }
+        let resp_stream = lease_client.lease_keep_alive(request).await?.into_inner();
+        Ok(ForwardConn {
+            leader_id,
+            req_tx,
+            resp_stream,
+        })
+    }
+}
+
+/// The client urls of a leader, joined by commas
+fn leader_hint(cluster_info: &ClusterInfo, leader_id: Option<u64>) -> Option<String> {
+    leader_id
+        .and_then(|id| cluster_info.client_urls(id))
+        .map(|urls| urls.join(","))
+}
 
-        Ok(Box::pin(stream))
+/// The status a keep alive gives up with when it cannot be forwarded, it carries the
+/// leader hint so that the client can re-dial the leader by itself
+fn keep_alive_forward_failed(
+    lease_id: i64,
+    err: &tonic::Status,
+    leader_hint: Option<String>,
+) -> tonic::Status {
+    let mut status = tonic::Status::unavailable(format!(
+        "failed to forward keep alive of lease {lease_id} to the leader: {}",
+        err.message()
+    ));
+    if let Some(value) = leader_hint.and_then(|hint| hint.parse().ok()) {
+        let _ignore = status.metadata_mut().insert(LEADER_HINT_KEY, value);
     }
+    status
+}
+
+/// The token the caller of a request is authenticated with
+fn caller_token<T>(request: &tonic::Request<T>) -> Option<AsciiMetadataValue> {
+    let metadata = request.metadata();
+    metadata
+        .get("token")
+        .or_else(|| metadata.get("authorization"))
+        .cloned()
+}
+
+/// Convert a lease deadline in unix millis to a ttl in seconds, rounded up so that the
+/// lease doesn't expire before the deadline
+fn ttl_until(deadline_ms: i64, now: SystemTime) -> Result<i64, tonic::Status> {
+    let now_ms: i64 = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|e| panic!("SystemTime before UNIX EPOCH! {e}"))
+        .as_millis()
+        .numeric_cast();
+    let remaining_ms = deadline_ms.saturating_sub(now_ms);
+    if remaining_ms <= 0 {
+        return Err(tonic::Status::invalid_argument(format!(
+            "lease deadline {deadline_ms} is in the past"
+        )));
+    }
+    Ok(remaining_ms.saturating_add(999) / 1000)
 }
 
 /// Build endpoints from addresses
@@ -251,12 +801,36 @@ impl Lease for LeaseServer {
         mut request: tonic::Request<LeaseGrantRequest>,
     ) -> Result<tonic::Response<LeaseGrantResponse>, tonic::Status> {
         debug!("Receive LeaseGrantRequest {:?}", request);
+        let client = client_identity(&self.auth_storage, &request).await;
+        throttle_lease_grant(
+            &self.grant_limiter,
+            &self.grant_rates,
+            &client,
+            time::Instant::now(),
+        )?;
+        // a deadline is converted with the clock of the leader, the ttl is all the other
+        // members see, so the replicated state doesn't depend on clocks
+        if request.get_ref().deadline_ms != 0 && !self.lease_storage.is_primary() {
+            return self.forward_lease_grant(request).await;
+        }
         let lease_grant_req = request.get_mut();
+        if lease_grant_req.deadline_ms != 0 {
+            lease_grant_req.ttl = ttl_until(
+                lease_grant_req.deadline_ms,
+                self.lease_storage.clock().wall(),
+            )?;
+            lease_grant_req.deadline_ms = 0;
+        }
         if lease_grant_req.id == 0 {
-            lease_grant_req.id = self.id_gen.next();
+            lease_grant_req.id = self.next_lease_id();
         }
+        lease_grant_req.ttl = self.lease_storage.normalize_ttl(lease_grant_req.ttl);
+        // the active leases of the owner are checked against the quota when applied
+        lease_grant_req.owner = client;
 
-        let is_fast_path = true;
+        self.check_permission(&request).await?;
+        // the revision in the header is only known after the grant is synced
+        let is_fast_path = false;
         let (res, sync_res) = self.propose(request, is_fast_path).await?;
 
         let mut res: LeaseGrantResponse = res.into_inner().into();
@@ -273,11 +847,17 @@ impl Lease for LeaseServer {
     /// LeaseRevoke revokes a lease. All keys attached to the lease will expire and be deleted.
     async fn lease_revoke(
         &self,
-        request: tonic::Request<LeaseRevokeRequest>,
+        mut request: tonic::Request<LeaseRevokeRequest>,
     ) -> Result<tonic::Response<LeaseRevokeResponse>, tonic::Status> {
         debug!("Receive LeaseRevokeRequest {:?}", request);
+        // the keys attached to the lease are deleted by the revocation, fail fast if the
+        // caller cannot write all of them
+        self.check_permission(&request).await?;
+        // every member deletes the keys in the chunks chosen by the proposer
+        request.get_mut().chunk_size = self.revoke_chunk_size();
 
-        let is_fast_path = true;
+        // the revision of the key deletions is only known after the revocation is synced
+        let is_fast_path = false;
         let (res, sync_res) = self.propose(request, is_fast_path).await?;
 
         let mut res: LeaseRevokeResponse = res.into_inner().into();
@@ -287,7 +867,6 @@ impl Lease for LeaseServer {
             if let Some(header) = res.header.as_mut() {
                 header.revision = revision;
             }
-            metrics::get().lease_expired_total.add(1, &[]);
         }
         Ok(tonic::Response::new(res))
     }
@@ -303,27 +882,16 @@ impl Lease for LeaseServer {
         request: tonic::Request<tonic::Streaming<LeaseKeepAliveRequest>>,
     ) -> Result<tonic::Response<Self::LeaseKeepAliveStream>, tonic::Status> {
         debug!("Receive LeaseKeepAliveRequest {:?}", request);
-        let request_stream = request.into_inner();
-        let stream = loop {
-            if self.lease_storage.is_primary() {
-                break self.leader_keep_alive(request_stream);
-            }
-            let leader_id = self.client.fetch_leader_id(false).await?;
-            // Given that a candidate server may become a leader when it won the election or
-            // a follower when it lost the election. Therefore we need to double check here.
-            // We can directly invoke leader_keep_alive when a candidate becomes a leader.
-            if !self.lease_storage.is_primary() {
-                let leader_addrs = self.cluster_info.client_urls(leader_id).unwrap_or_else(|| {
-                    unreachable!(
-                        "The address of leader {} not found in all_members {:?}",
-                        leader_id, self.cluster_info
-                    )
-                });
-                break self
-                    .follower_keep_alive(request_stream, &leader_addrs)
-                    .await?;
-            }
-        };
+        if self.read_only.load(Ordering::Relaxed) {
+            return Err(read_only_error());
+        }
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        self.accounting.record_request(auth_info.as_ref());
+        let token = caller_token(&request);
+        let stream = self.keep_alive_stream(request.into_inner(), auth_info, token);
         Ok(tonic::Response::new(stream))
     }
 
@@ -333,63 +901,204 @@ impl Lease for LeaseServer {
         request: tonic::Request<LeaseTimeToLiveRequest>,
     ) -> Result<tonic::Response<LeaseTimeToLiveResponse>, tonic::Status> {
         debug!("Receive LeaseTimeToLiveRequest {:?}", request);
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        self.accounting.record_request(auth_info.as_ref());
+        self.auth_storage
+            .check_lease_read_permission(request.get_ref().id, auth_info.as_ref())?;
+        // serializable reads are served by whichever node receives them, this is an
+        // Xline extension
+        if request.get_ref().serializable {
+            let res = self.serializable_time_to_live(request.get_ref())?;
+            return Ok(tonic::Response::new(res));
+        }
         loop {
             if self.lease_storage.is_primary() {
                 let time_to_live_req = request.into_inner();
 
+                // only the confirmation of the leadership matters, a stale leader must
+                // not serve the remaining ttl of its expiry queue
+                let cmd = Command::new(LeaseLeasesRequest::default().into());
+                let _read_state = self.client.fetch_read_state(&cmd).await?;
                 self.lease_storage.wait_synced(time_to_live_req.id).await;
 
                 let Some(lease) = self.lease_storage.look_up(time_to_live_req.id) else {
                     return Err(ExecuteError::LeaseNotFound(time_to_live_req.id).into());
                 };
-
-                let keys = time_to_live_req
-                    .keys
-                    .then(|| lease.keys())
-                    .unwrap_or_default();
-                let res = LeaseTimeToLiveResponse {
-                    header: Some(self.lease_storage.gen_header()),
-                    id: time_to_live_req.id,
-                    ttl: lease.remaining().as_secs().numeric_cast(),
-                    granted_ttl: lease.ttl().as_secs().numeric_cast(),
-                    keys,
-                };
+                let res = self.time_to_live_response(&time_to_live_req, &lease, lease.remaining());
                 return Ok(tonic::Response::new(res));
             }
-            let leader_id = self.client.fetch_leader_id(false).await?;
-            let leader_addrs = self.cluster_info.client_urls(leader_id).unwrap_or_else(|| {
-                unreachable!(
-                    "The address of leader {} not found in all_members {:?}",
-                    leader_id, self.cluster_info
-                )
-            });
+            // the local lease of a follower never expires, only the remaining ttl
+            // checkpointed by the leader is meaningful
+            if let Some(res) = self.checkpointed_time_to_live(request.get_ref()) {
+                return Ok(tonic::Response::new(res));
+            }
+            let leader_id = self.leader.leader_id().await?;
             if !self.lease_storage.is_primary() {
-                let endpoints = build_endpoints(&leader_addrs, self.client_tls_config.as_ref())?;
-                let channel = tonic::transport::Channel::balance_list(endpoints.into_iter());
-                let mut lease_client = LeaseClient::new(channel);
+                let mut lease_client = LeaseClient::new(self.leader.channel(leader_id)?);
                 return lease_client.lease_time_to_live(request).await;
             }
         }
     }
 
-    /// LeaseLeases lists all existing leases.
+    /// LeaseLeases lists all existing leases in ascending order of the ids.
+    ///
+    /// Every member knows the ids of all leases (followers just keep them
+    /// alive forever), so this is served locally without consensus.
     async fn lease_leases(
         &self,
         request: tonic::Request<LeaseLeasesRequest>,
     ) -> Result<tonic::Response<LeaseLeasesResponse>, tonic::Status> {
         debug!("Receive LeaseLeasesRequest {:?}", request);
+        let leases: Vec<_> = self
+            .lease_storage
+            .lease_ids()
+            .into_iter()
+            .map(|id| LeaseStatus { id })
+            .collect();
+        let res = LeaseLeasesResponse {
+            header: Some(self.lease_storage.gen_header()),
+            count: leases.len().numeric_cast(),
+            leases,
+        };
+        Ok(tonic::Response::new(res))
+    }
+}
 
-        let is_fast_path = true;
-        let (res, sync_res) = self.propose(request, is_fast_path).await?;
+/// Take a lease grant from the quota of the client, a throttled grant is rejected
+/// with how long the client should wait
+fn throttle_lease_grant(
+    limiter: &ClientRateLimiter,
+    rates: &ClientRates,
+    client: &str,
+    now: time::Instant,
+) -> Result<(), tonic::Status> {
+    rates.record(client, now);
+    let Err(retry_after) = limiter.acquire(client, now) else {
+        return Ok(());
+    };
+    // the clients are only reported by the top grant rates, so the labels are bounded
+    metrics::get().lease_grants_throttled_total.add(1, &[]);
+    let retry_after_ms = retry_after.as_millis().max(1);
+    let mut status = tonic::Status::resource_exhausted(format!(
+        "too many lease grants, slow down, retry after {retry_after_ms}ms"
+    ));
+    if let Ok(value) = retry_after_ms.to_string().parse() {
+        let _ignore = status.metadata_mut().insert(RETRY_AFTER_KEY, value);
+    }
+    Err(status)
+}
 
-        let mut res: LeaseLeasesResponse = res.into_inner().into();
-        if let Some(sync_res) = sync_res {
-            let revision = sync_res.revision();
-            debug!("Get revision {:?} for LeaseLeasesResponse", revision);
-            if let Some(header) = res.header.as_mut() {
-                header.revision = revision;
+#[cfg(test)]
+mod test {
+    use curp::members::Member;
+
+    use super::*;
+
+    #[test]
+    fn ttl_until_should_round_up_to_seconds() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(ttl_until(1_010_000, now).unwrap(), 10);
+        assert_eq!(ttl_until(1_010_001, now).unwrap(), 11);
+        assert_eq!(ttl_until(1_000_001, now).unwrap(), 1);
+    }
+
+    #[test]
+    fn caller_token_should_be_forwarded_as_is() {
+        let mut request = tonic::Request::new(());
+        assert!(caller_token(&request).is_none());
+        let _ignore = request
+            .metadata_mut()
+            .insert("authorization", "bar".parse().unwrap());
+        assert_eq!(caller_token(&request).unwrap(), "bar");
+        let _ignore = request
+            .metadata_mut()
+            .insert("token", "foo".parse().unwrap());
+        assert_eq!(caller_token(&request).unwrap(), "foo");
+    }
+
+    #[test]
+    fn leader_hint_should_join_the_client_urls_of_the_leader() {
+        let cluster_info = ClusterInfo::new(
+            1,
+            1,
+            vec![
+                Member::new(
+                    1,
+                    "s1",
+                    ["http://s1:2380".to_owned()],
+                    ["http://s1:2379".to_owned()],
+                    false,
+                ),
+                Member::new(
+                    2,
+                    "s2",
+                    ["http://s2:2380".to_owned()],
+                    ["http://s2:2379".to_owned(), "http://s2:2479".to_owned()],
+                    false,
+                ),
+            ],
+        );
+        assert_eq!(
+            leader_hint(&cluster_info, Some(2)).unwrap(),
+            "http://s2:2379,http://s2:2479"
+        );
+        assert!(leader_hint(&cluster_info, Some(3)).is_none());
+        assert!(leader_hint(&cluster_info, None).is_none());
+    }
+
+    #[test]
+    fn keep_alive_forward_failure_should_carry_the_leader_hint() {
+        let err = tonic::Status::unavailable("connection refused");
+        let status = keep_alive_forward_failed(7, &err, Some("http://s2:2379".to_owned()));
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(
+            status.message(),
+            "failed to forward keep alive of lease 7 to the leader: connection refused"
+        );
+        assert_eq!(
+            status.metadata().get(LEADER_HINT_KEY).unwrap(),
+            "http://s2:2379"
+        );
+
+        let status = keep_alive_forward_failed(7, &err, None);
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(status.metadata().get(LEADER_HINT_KEY).is_none());
+    }
+
+    #[test]
+    fn ttl_until_should_reject_past_deadline() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        for deadline_ms in [1_000_000, 999_999, 1] {
+            let err = ttl_until(deadline_ms, now).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn lease_grant_storm_should_not_throttle_other_clients() {
+        let limiter = ClientRateLimiter::new(10, 5);
+        let rates = ClientRates::new(GRANT_RATE_WINDOW);
+        let now = time::Instant::now();
+        let mut throttled = 0;
+        for i in 0..100 {
+            let at = now + Duration::from_millis(i);
+            if let Err(status) = throttle_lease_grant(&limiter, &rates, "storm", at) {
+                assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+                assert!(status.metadata().get(RETRY_AFTER_KEY).is_some());
+                throttled += 1;
+            }
+            if i % 20 == 0 {
+                assert!(throttle_lease_grant(&limiter, &rates, "normal", at).is_ok());
             }
         }
-        Ok(tonic::Response::new(res))
+        // only the burst is granted, the next token is refilled after 100ms
+        assert_eq!(throttled, 95);
+        let top = rates.top(2, now + GRANT_RATE_WINDOW);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, "storm");
+        assert_eq!(top[1].0, "normal");
     }
 }
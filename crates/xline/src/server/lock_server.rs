@@ -1,14 +1,7 @@
 use std::sync::Arc;
 
-use async_stream::stream;
 use clippy_utilities::OverflowArithmetic;
-#[cfg(not(madsim))]
-use tonic::transport::ClientTlsConfig;
-use tonic::transport::{Channel, Endpoint};
 use tracing::debug;
-use utils::build_endpoint;
-#[cfg(madsim)]
-use utils::ClientTlsConfig;
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, KeyRange, SyncResponse},
     execute_error::ExecuteError,
@@ -20,11 +13,10 @@ use crate::{
     rpc::{
         Compare, CompareResult, CompareTarget, DeleteRangeRequest, DeleteRangeResponse,
         LeaseGrantRequest, LeaseGrantResponse, Lock, LockRequest, LockResponse, PutRequest,
-        RangeRequest, RangeResponse, Request, RequestOp, RequestUnion, RequestWrapper, Response,
-        ResponseHeader, SortOrder, SortTarget, TargetUnion, TxnRequest, TxnResponse, UnlockRequest,
-        UnlockResponse, WatchClient, WatchCreateRequest, WatchRequest,
+        RangeRequest, RangeResponse, Request, RequestOp, RequestWrapper, Response, ResponseHeader,
+        SortOrder, SortTarget, TargetUnion, TxnRequest, TxnResponse, UnlockRequest, UnlockResponse,
     },
-    storage::AuthStore,
+    storage::{kvwatcher::KvWatcher, AuthStore},
 };
 
 /// Default session ttl
@@ -38,8 +30,8 @@ pub(super) struct LockServer {
     auth_store: Arc<AuthStore>,
     /// Id Generator
     id_gen: Arc<IdGenerator>,
-    /// Server addresses
-    addrs: Vec<Endpoint>,
+    /// KV watcher, notifies the waiters of the deletions of the lock keys
+    kv_watcher: Arc<KvWatcher>,
 }
 
 impl LockServer {
@@ -48,21 +40,13 @@ impl LockServer {
         client: Arc<CurpClient>,
         auth_store: Arc<AuthStore>,
         id_gen: Arc<IdGenerator>,
-        addrs: &[String],
-        client_tls_config: Option<&ClientTlsConfig>,
+        kv_watcher: Arc<KvWatcher>,
     ) -> Self {
-        let addrs = addrs
-            .iter()
-            .map(|addr| {
-                build_endpoint(addr, client_tls_config)
-                    .unwrap_or_else(|_e| panic!("invalid address: {addr}"))
-            })
-            .collect();
         Self {
             client,
             auth_store,
             id_gen,
-            addrs,
+            kv_watcher,
         }
     }
 
@@ -134,14 +118,16 @@ impl LockServer {
         auth_info: Option<&AuthInfo>,
     ) -> Result<(), tonic::Status> {
         let rev = my_rev.overflow_sub(1);
-        let mut watch_client =
-            WatchClient::new(Channel::balance_list(self.addrs.clone().into_iter()));
+        let range_end = KeyRange::get_prefix(pfx.as_bytes());
         loop {
-            let range_end = KeyRange::get_prefix(pfx.as_bytes());
+            // subscribe before reading the last key, so that its deletion isn't missed
+            let mut events = self
+                .kv_watcher
+                .subscribe_internal(KeyRange::new(pfx.as_bytes(), range_end.clone()));
             #[allow(clippy::as_conversions)] // this cast is always safe
             let get_req = RangeRequest {
                 key: pfx.as_bytes().to_vec(),
-                range_end,
+                range_end: range_end.clone(),
                 limit: 1,
                 sort_order: SortOrder::Descend as i32,
                 sort_target: SortTarget::Create as i32,
@@ -154,22 +140,9 @@ impl LockServer {
                 Some(kv) => kv.key.clone(),
                 None => return Ok(()),
             };
-            let request_stream = stream! {
-                yield WatchRequest {
-                    request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
-                        key: last_key,
-                        ..Default::default()
-                    })),
-                };
-            };
-            let mut response_stream = watch_client.watch(request_stream).await?.into_inner();
-            while let Some(watch_res) = response_stream.message().await? {
-                #[allow(clippy::as_conversions)] // this cast is always safe
-                if watch_res
-                    .events
-                    .iter()
-                    .any(|e| e.r#type == EventType::Delete as i32)
-                {
+            // a closed subscription falls back to read the last key again
+            while let Some(event) = events.recv().await {
+                if event.event_type == EventType::Delete && event.key == last_key {
                     break;
                 }
             }
@@ -197,6 +170,7 @@ impl LockServer {
         let lease_grant_req = LeaseGrantRequest {
             ttl: DEFAULT_SESSION_TTL,
             id: lease_id,
+            ..Default::default()
         };
         let (cmd_res, _) = self.propose(lease_grant_req, auth_info, true).await?;
         let res = Into::<LeaseGrantResponse>::into(cmd_res.into_inner());
@@ -217,7 +191,10 @@ impl Lock for LockServer {
         request: tonic::Request<LockRequest>,
     ) -> Result<tonic::Response<LockResponse>, tonic::Status> {
         debug!("Receive LockRequest {:?}", request);
-        let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
         let lock_req = request.into_inner();
         let lease_id = if lock_req.lease == 0 {
             self.lease_grant(auth_info.clone()).await?
@@ -291,7 +268,10 @@ impl Lock for LockServer {
         request: tonic::Request<UnlockRequest>,
     ) -> Result<tonic::Response<UnlockResponse>, tonic::Status> {
         debug!("Receive UnlockRequest {:?}", request);
-        let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
         let header = self.delete_key(&request.get_ref().key, auth_info).await?;
         Ok(tonic::Response::new(UnlockResponse { header }))
     }
@@ -1,4 +1,11 @@
-use std::{fmt::Debug, pin::Pin, sync::Arc};
+use std::{
+    fmt::Debug,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use async_stream::try_stream;
 use bytes::BytesMut;
@@ -10,17 +17,21 @@ use sha2::{Digest, Sha256};
 use tracing::{debug, error};
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, SyncResponse},
+    execute_error::COMPACT_REVISION_KEY,
+    request_validation::RequestValidator,
     RequestWrapper,
 };
 
-use super::command::CommandExecutor;
+use super::{accounting::Accounting, command::CommandExecutor, read_only::READ_ONLY_ERR_MSG};
 use crate::{
     header_gen::HeaderGenerator,
     rpc::{
-        AlarmRequest, AlarmResponse, DefragmentRequest, DefragmentResponse, DowngradeRequest,
-        DowngradeResponse, HashKvRequest, HashKvResponse, HashRequest, HashResponse, Maintenance,
-        MoveLeaderRequest, MoveLeaderResponse, SnapshotRequest, SnapshotResponse, StatusRequest,
-        StatusResponse,
+        AlarmRequest, AlarmResponse, CompactionExemptionRequest, CompactionExemptionResponse,
+        DebugStatsRequest, DebugStatsResponse, DefragmentRequest, DefragmentResponse,
+        DowngradeRequest, DowngradeResponse, HashKvRequest, HashKvResponse, HashRequest,
+        HashResponse, Maintenance, MoveLeaderRequest, MoveLeaderResponse, SnapshotRequest,
+        SnapshotResponse, StatusRequest, StatusResponse, TimeToRevisionRequest,
+        TimeToRevisionResponse,
     },
     state::State,
     storage::{db::DB, AlarmStore, AuthStore, KvStore},
@@ -51,6 +62,10 @@ pub(crate) struct MaintenanceServer {
     ce: Arc<CommandExecutor>,
     /// Alarm store
     alarm_store: Arc<AlarmStore>,
+    /// Whether the node rejects mutating requests
+    read_only: Arc<AtomicBool>,
+    /// Usage accounting of the users
+    accounting: Arc<Accounting>,
 }
 
 impl MaintenanceServer {
@@ -66,6 +81,8 @@ impl MaintenanceServer {
         raw_curp: Arc<RawCurp<Command, State<Arc<CurpClient>>>>,
         ce: Arc<CommandExecutor>,
         alarm_store: Arc<AlarmStore>,
+        read_only: Arc<AtomicBool>,
+        accounting: Arc<Accounting>,
     ) -> Self {
         Self {
             kv_store,
@@ -77,6 +94,8 @@ impl MaintenanceServer {
             raw_curp,
             ce,
             alarm_store,
+            read_only,
+            accounting,
         }
     }
 
@@ -89,7 +108,10 @@ impl MaintenanceServer {
     where
         T: Into<RequestWrapper> + Debug,
     {
-        let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
         let request = request.into_inner().into();
         let cmd = Command::new_with_auth_info(request, auth_info);
         let res = self.client.propose(&cmd, None, use_fast_path).await??;
@@ -138,6 +160,12 @@ impl Maintenance for MaintenanceServer {
         for a in self.alarm_store.get_all_alarms() {
             errors.push(a.to_string());
         }
+        if let Some(reason) = self.db.corruption() {
+            errors.push(format!("xline: backend corruption detected: {reason}"));
+        }
+        if self.read_only.load(Ordering::Relaxed) {
+            errors.push(READ_ONLY_ERR_MSG.to_owned());
+        }
         let response = StatusResponse {
             header: Some(self.header_gen.gen_header()),
             version: env!("CARGO_PKG_VERSION").to_owned(),
@@ -149,8 +177,14 @@ impl Maintenance for MaintenanceServer {
             errors,
             db_size_in_use: size.numeric_cast(),
             is_learner,
+            cluster_version: self.cluster_info.cluster_server_version(),
         };
-        Ok(tonic::Response::new(response))
+        let mut response = tonic::Response::new(response);
+        let _ignore = response.metadata_mut().insert(
+            COMPACT_REVISION_KEY,
+            self.kv_store.compacted_revision().into(),
+        );
+        Ok(response)
     }
 
     async fn defragment(
@@ -177,12 +211,15 @@ impl Maintenance for MaintenanceServer {
         request: tonic::Request<HashKvRequest>,
     ) -> Result<tonic::Response<HashKvResponse>, tonic::Status> {
         let revision = request.get_ref().revision;
-        let (hash, compact_revision, _hash_revision) = self.kv_store.hash_kv(revision)?;
+        let (hash, compact_revision, hash_revision) = self.kv_store.hash_kv(revision)?;
+        // hash_revision was introduced in etcd 3.6 and xline is currently compatible with
+        // etcd 3.5, so the hashed revision is returned as the revision of the header
+        let mut header = self.header_gen.gen_header();
+        header.revision = hash_revision;
         Ok(tonic::Response::new(HashKvResponse {
-            header: Some(self.header_gen.gen_header()),
+            header: Some(header),
             hash,
             compact_revision,
-            // TODO: hash_revision was introduced in etcd 3.6, and xline is currently compatible with etcd 3.5
         }))
     }
 
@@ -209,6 +246,62 @@ impl Maintenance for MaintenanceServer {
         }))
     }
 
+    /// DebugStats returns the usage of every user of this node, it's only allowed for
+    /// root. Xline extension
+    async fn debug_stats(
+        &self,
+        request: tonic::Request<DebugStatsRequest>,
+    ) -> Result<tonic::Response<DebugStatsResponse>, tonic::Status> {
+        let auth_info = self
+            .auth_store
+            .try_get_auth_info_from_request(&request)
+            .await?;
+        self.auth_store.check_admin(auth_info.as_ref())?;
+        Ok(tonic::Response::new(DebugStatsResponse {
+            header: Some(self.header_gen.gen_header()),
+            usages: self
+                .accounting
+                .stats()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
+
+    /// TimeToRevision resolves a wall time to the latest revision recorded at or before
+    /// it on this node. Xline extension
+    async fn time_to_revision(
+        &self,
+        request: tonic::Request<TimeToRevisionRequest>,
+    ) -> Result<tonic::Response<TimeToRevisionResponse>, tonic::Status> {
+        let time_ms = request.get_ref().time_ms;
+        let time_index = self.ce.time_index();
+        let Some((revision, recorded_at_ms)) = time_index.resolve(time_ms) else {
+            return Err(tonic::Status::not_found(format!(
+                "no revision is recorded at or before {time_ms}ms, it may be compacted"
+            )));
+        };
+        Ok(tonic::Response::new(TimeToRevisionResponse {
+            header: Some(self.header_gen.gen_header()),
+            revision,
+            recorded_at_ms,
+            resolution_ms: time_index.resolution(),
+        }))
+    }
+
+    /// CompactionExemption adds and removes the key prefixes whose history is kept by
+    /// compactions, it's only allowed for root. Xline extension
+    async fn compaction_exemption(
+        &self,
+        request: tonic::Request<CompactionExemptionRequest>,
+    ) -> Result<tonic::Response<CompactionExemptionResponse>, tonic::Status> {
+        request.get_ref().validation()?;
+        // the exemption is replicated, members agree on it once it's applied
+        let is_fast_path = false;
+        let (res, _sync_res) = self.propose(request, is_fast_path).await?;
+        Ok(tonic::Response::new(res.into_inner().into()))
+    }
+
     async fn downgrade(
         &self,
         _request: tonic::Request<DowngradeRequest>,
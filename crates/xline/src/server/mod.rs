@@ -1,3 +1,7 @@
+/// Usage accounting of the users
+mod accounting;
+/// Admission control of proposals
+mod admission;
 /// Xline auth server
 mod auth_server;
 /// Auth Wrapper
@@ -16,10 +20,23 @@ mod lease_server;
 mod lock_server;
 /// Xline maintenance client
 mod maintenance;
+/// Rate limits of clients
+mod rate_limit;
+/// Read-only mode
+mod read_only;
+/// Incremental hash of the state machine
+pub(crate) mod state_hash;
+/// Virtual keys of the server statistics
+mod stats_keys;
+/// Mapping of the wall time to the revisions
+pub(crate) mod time_index;
 /// Xline watch server
 mod watch_server;
 /// Xline server
 mod xline_server;
 
 pub use self::xline_server::XlineServer;
-pub(crate) use self::{auth_server::get_token, maintenance::MAINTENANCE_SNAPSHOT_CHUNK_SIZE};
+pub(crate) use self::{
+    accounting::Accounting, auth_server::get_token, barriers::IndexBarrier,
+    maintenance::MAINTENANCE_SNAPSHOT_CHUNK_SIZE, rate_limit::ClientRates,
+};
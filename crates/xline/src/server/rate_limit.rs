@@ -0,0 +1,210 @@
+use std::{collections::HashMap, future::Future, mem, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::storage::AuthStore;
+
+/// Client rate limiter only keeps idle clients up to this number
+const LIMITER_IDLE_CLIENTS: usize = 1024;
+
+/// Max number of clients whose rates are tracked in a window
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+/// Identity of the client of a request, which is the user when auth is enabled, or
+/// the address of the peer otherwise
+pub(crate) fn client_identity<'a, T>(
+    auth_storage: &'a AuthStore,
+    request: &tonic::Request<T>,
+) -> impl Future<Output = String> + 'a {
+    let auth_info = auth_storage.try_get_auth_info_from_request(request);
+    let addr = request
+        .remote_addr()
+        .map_or_else(|| "unknown".to_owned(), |addr| addr.ip().to_string());
+    async move {
+        if let Ok(Some(auth_info)) = auth_info.await {
+            return auth_info.username;
+        }
+        addr
+    }
+}
+
+/// Limits the rate of requests of each client with a token bucket
+///
+/// The bucket of a client is tracked by the time it will be full again, requests are
+/// allowed as long as that time is less than a burst ahead of now.
+#[derive(Debug)]
+pub(crate) struct ClientRateLimiter {
+    /// Interval at which a token is added, `None` means unlimited
+    interval: Option<Duration>,
+    /// How far the bucket of a client may be drained ahead of now
+    tolerance: Duration,
+    /// Client to the time its bucket is full again
+    buckets: Mutex<HashMap<String, Instant>>,
+}
+
+impl ClientRateLimiter {
+    /// New `ClientRateLimiter` allowing `rate` requests per second with bursts of
+    /// `burst` requests, a zero `rate` means unlimited
+    pub(crate) fn new(rate: u32, burst: u32) -> Self {
+        let interval = Duration::from_secs(1).checked_div(rate);
+        Self {
+            interval,
+            tolerance: interval
+                .unwrap_or_default()
+                .saturating_mul(burst.saturating_sub(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the bucket of the client, returns how long the client should
+    /// wait for the next token if there's none left
+    pub(crate) fn acquire(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let Some(interval) = self.interval else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= LIMITER_IDLE_CLIENTS {
+            // a full bucket is the same as a missing one
+            buckets.retain(|_, full_at| *full_at > now);
+        }
+        let full_at = buckets.get(client).map_or(now, |full_at| now.max(*full_at));
+        let drained = full_at.saturating_duration_since(now);
+        if drained > self.tolerance {
+            return Err(drained.saturating_sub(self.tolerance));
+        }
+        let _prev = buckets.insert(
+            client.to_owned(),
+            full_at.checked_add(interval).unwrap_or(full_at),
+        );
+        Ok(())
+    }
+}
+
+/// Request rates of each client, measured over fixed windows
+#[derive(Debug)]
+pub(crate) struct ClientRates {
+    /// Length of a window
+    window: Duration,
+    /// The counts of the windows
+    inner: Mutex<RatesInner>,
+}
+
+/// The counts of the windows
+#[derive(Debug)]
+struct RatesInner {
+    /// Start of the current window
+    start: Instant,
+    /// Requests of each client in the current window
+    current: HashMap<String, u32>,
+    /// Requests of each client in the last complete window
+    last: HashMap<String, u32>,
+}
+
+impl ClientRates {
+    /// New `ClientRates` measured over windows of `window`
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inner: Mutex::new(RatesInner {
+                start: Instant::now(),
+                current: HashMap::new(),
+                last: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Record a request of the client
+    pub(crate) fn record(&self, client: &str, now: Instant) {
+        let mut inner = self.inner.lock();
+        inner.roll(self.window, now);
+        if let Some(count) = inner.current.get_mut(client) {
+            *count = count.saturating_add(1);
+        } else if inner.current.len() < MAX_TRACKED_CLIENTS {
+            let _prev = inner.current.insert(client.to_owned(), 1);
+        } else {
+            // too many clients in the window, the rest are not tracked
+        }
+    }
+
+    /// The `n` clients with the highest rates in the last complete window, in
+    /// requests per second
+    pub(crate) fn top(&self, n: usize, now: Instant) -> Vec<(String, f64)> {
+        let mut inner = self.inner.lock();
+        inner.roll(self.window, now);
+        let secs = self.window.as_secs_f64();
+        let mut rates: Vec<_> = inner
+            .last
+            .iter()
+            .map(|(client, &count)| (client.clone(), count))
+            .collect();
+        rates.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        rates
+            .into_iter()
+            .take(n)
+            .map(|(client, count)| (client, f64::from(count) / secs))
+            .collect()
+    }
+}
+
+impl RatesInner {
+    /// Move to the window of `now`
+    fn roll(&mut self, window: Duration, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed < window {
+            return;
+        }
+        self.last = if elapsed < window.saturating_mul(2) {
+            mem::take(&mut self.current)
+        } else {
+            // the last window is idle
+            self.current.clear();
+            HashMap::new()
+        };
+        self.start = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_rate_limiter_should_refill_tokens_at_rate() {
+        let limiter = ClientRateLimiter::new(10, 2);
+        let now = Instant::now();
+        assert!(limiter.acquire("a", now).is_ok());
+        assert!(limiter.acquire("a", now).is_ok());
+        assert_eq!(limiter.acquire("a", now), Err(Duration::from_millis(100)));
+        // other clients have their own buckets
+        assert!(limiter.acquire("b", now).is_ok());
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.acquire("a", later).is_ok());
+        assert!(limiter.acquire("a", later).is_err());
+        // an idle client gets its full burst back
+        let idle = now + Duration::from_secs(10);
+        assert!(limiter.acquire("a", idle).is_ok());
+        assert!(limiter.acquire("a", idle).is_ok());
+        assert!(limiter.acquire("a", idle).is_err());
+    }
+
+    #[test]
+    fn client_rates_should_rank_top_clients_of_last_window() {
+        let rates = ClientRates::new(Duration::from_secs(1));
+        let now = Instant::now();
+        for _ in 0..10 {
+            rates.record("storm", now);
+        }
+        rates.record("normal", now);
+        rates.record("other", now);
+        // the current window is not complete yet
+        assert!(rates.top(2, now).is_empty());
+        let next = now + Duration::from_secs(1);
+        assert_eq!(
+            rates.top(2, next),
+            vec![("storm".to_owned(), 10.0), ("normal".to_owned(), 1.0)]
+        );
+        // an idle window resets the rates
+        assert!(rates.top(2, next + Duration::from_secs(2)).is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use curp::server::RawCurp;
+use tracing::{error, warn};
+use utils::task_manager::Listener;
+use xlineapi::{
+    command::{Command, CurpClient},
+    AlarmAction, AlarmType,
+};
+
+use super::command::Alarmer;
+use crate::{rpc::RequestWrapper, state::State, storage::db::DB};
+
+/// Error message returned for mutating requests received in read-only mode
+pub(crate) const READ_ONLY_ERR_MSG: &str = "xline: node is in read-only mode";
+
+/// Build the error returned for mutating requests received in read-only mode
+pub(crate) fn read_only_error() -> tonic::Status {
+    tonic::Status::failed_precondition(READ_ONLY_ERR_MSG)
+}
+
+/// Check whether a request leaves the state machine untouched, unlike
+/// `RequestWrapper::is_read_only`, read-only txns are not treated as mutations
+pub(super) fn is_read_only(request: &RequestWrapper) -> bool {
+    if let RequestWrapper::TxnRequest(ref txn_req) = *request {
+        return txn_req.is_read_only();
+    }
+    request.is_read_only()
+}
+
+/// Fence the node once a backend corruption is detected
+///
+/// The node switches itself to read-only serving, gives up its leadership and stops
+/// campaigning, then raises a `Corrupt` alarm naming itself to the cluster.
+pub(crate) async fn fence_on_corruption(
+    db: Arc<DB>,
+    read_only: Arc<AtomicBool>,
+    raw_curp: Arc<RawCurp<Command, State<Arc<CurpClient>>>>,
+    alarmer: Alarmer,
+    shutdown_listener: Listener,
+) {
+    let reason = tokio::select! {
+        _ = shutdown_listener.wait() => return,
+        reason = db.corrupted() => reason,
+    };
+    error!("fencing the node because of backend corruption: {reason}");
+    read_only.store(true, Ordering::Relaxed);
+    raw_curp.disable_campaign().await;
+    if let Err(e) = alarmer
+        .alarm(AlarmAction::Activate, AlarmType::Corrupt)
+        .await
+    {
+        warn!("propose corrupt alarm failed: {e:?}");
+    }
+}
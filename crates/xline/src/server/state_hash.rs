@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use parking_lot::Mutex;
+use prost::Message;
+use utils::table_names::META_TABLE;
+use xlineapi::execute_error::ExecuteError;
+
+use crate::{
+    rpc::KeyValue,
+    storage::{
+        db::{WriteOp, DB},
+        Revision,
+    },
+};
+
+/// Key of the state hash in the meta table
+pub(crate) const STATE_HASH_KEY: &str = "state_hash";
+
+/// Max number of checkpoints retained for the comparisons
+const MAX_CHECKPOINTS: usize = 1024;
+
+/// Incremental hash of the kv state machine
+///
+/// Every kv mutation, deletes included as they are written as tombstones, adds a
+/// contribution derived from its revision and key-value to the hash. Revisions may be
+/// applied out of order, the hash is checkpointed once all the revisions below are
+/// folded, so that the checkpoint at a revision is the same on every member applying
+/// the same log. Every revision must be folded exactly once, those failed or released
+/// without writes as empty. The latest checkpoint and the contributions folded ahead of
+/// it are persisted with the writes of each revision.
+#[derive(Debug)]
+pub(crate) struct StateHasher {
+    /// The hash state
+    inner: Mutex<Inner>,
+}
+
+/// The hash state
+#[derive(Debug, Default)]
+struct Inner {
+    /// The latest checkpointed revision
+    revision: i64,
+    /// The hash at the latest checkpointed revision
+    hash: u64,
+    /// Contributions of the revisions folded ahead of the checkpoint
+    pending: BTreeMap<i64, u64>,
+    /// The retained checkpoints, in ascending order of the revisions
+    checkpoints: VecDeque<(i64, u64)>,
+}
+
+impl StateHasher {
+    /// Recover the hasher from the persisted checkpoint
+    pub(crate) fn recover(db: &DB) -> Result<Self, ExecuteError> {
+        let hasher = Self {
+            inner: Mutex::new(Inner::default()),
+        };
+        hasher.reset(db)?;
+        Ok(hasher)
+    }
+
+    /// Reset the hasher to the persisted checkpoint, after the db is reset
+    pub(crate) fn reset(&self, db: &DB) -> Result<(), ExecuteError> {
+        let mut inner = self.inner.lock();
+        *inner = Inner::default();
+        if let Some(bytes) = db.get_value(META_TABLE, STATE_HASH_KEY)? {
+            let (revision, hash, pending) = decode_state(&bytes)
+                .ok_or_else(|| ExecuteError::DbError("cannot decode the state hash".to_owned()))?;
+            inner.revision = revision;
+            inner.hash = hash;
+            inner.pending = pending;
+            inner.checkpoints.push_back((revision, hash));
+        }
+        Ok(())
+    }
+
+    /// Fold the writes of a revision, a write of the new state is appended to `ops`
+    ///
+    /// The folds must be ordered as the flushes of their `ops`, so that the persisted
+    /// state includes every revision flushed.
+    pub(crate) fn fold(&self, revision: i64, ops: &mut Vec<WriteOp<'_>>) {
+        #[allow(clippy::wildcard_enum_match_arm)] // only the kv mutations are folded
+        let contribution = ops
+            .iter()
+            .map(|op| match *op {
+                WriteOp::PutKeyValue(ref rev, ref kv) => contribution(rev, kv),
+                // the tombstones of a revoked lease are written later, but they're
+                // still mutations of the revision
+                WriteOp::PutLeaseDeletion(ref deletion) => deletion
+                    .tombstones()
+                    .map(|(rev, kv)| contribution(&rev, &kv))
+                    .fold(0, u64::wrapping_add),
+                _ => 0,
+            })
+            .fold(0, u64::wrapping_add);
+        let mut inner = self.inner.lock();
+        // replayed after a restart
+        if revision <= inner.revision || inner.pending.contains_key(&revision) {
+            return;
+        }
+        let _prev = inner.pending.insert(revision, contribution);
+        loop {
+            let next = inner.revision.wrapping_add(1);
+            let Some(c) = inner.pending.remove(&next) else {
+                break;
+            };
+            inner.revision = next;
+            inner.hash = inner.hash.wrapping_add(c);
+            let checkpoint = (inner.revision, inner.hash);
+            inner.checkpoints.push_back(checkpoint);
+            if inner.checkpoints.len() > MAX_CHECKPOINTS {
+                let _ignore = inner.checkpoints.pop_front();
+            }
+        }
+        ops.push(WriteOp::PutStateHash(encode_state(
+            inner.revision,
+            inner.hash,
+            &inner.pending,
+        )));
+    }
+
+    /// The latest checkpoint
+    pub(crate) fn latest(&self) -> Option<(i64, u64)> {
+        self.inner.lock().checkpoints.back().copied()
+    }
+
+    /// The checkpoint at a revision, if it's retained
+    pub(crate) fn at(&self, revision: i64) -> Option<u64> {
+        let inner = self.inner.lock();
+        inner
+            .checkpoints
+            .binary_search_by_key(&revision, |&(rev, _)| rev)
+            .ok()
+            .and_then(|i| inner.checkpoints.get(i))
+            .map(|&(_, hash)| hash)
+    }
+}
+
+/// The contribution of a kv mutation to the hash
+fn contribution(rev: &Revision, kv: &KeyValue) -> u64 {
+    let high = u64::from(crc32fast::hash(&rev.encode_to_vec()));
+    let low = u64::from(crc32fast::hash(&kv.encode_to_vec()));
+    high.wrapping_shl(32) | low
+}
+
+/// Encode the state to be persisted, the checkpoint followed by the pending contributions
+fn encode_state(revision: i64, hash: u64, pending: &BTreeMap<i64, u64>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(pending.len().wrapping_add(1).wrapping_mul(16));
+    for (rev, h) in std::iter::once((revision, hash)).chain(pending.iter().map(|(&r, &c)| (r, c))) {
+        buf.extend_from_slice(&rev.to_le_bytes());
+        buf.extend_from_slice(&h.to_le_bytes());
+    }
+    buf
+}
+
+/// Decode a persisted state, a checkpoint persisted by an older version has no pending
+/// contributions
+fn decode_state(bytes: &[u8]) -> Option<(i64, u64, BTreeMap<i64, u64>)> {
+    let chunks = bytes.chunks_exact(16);
+    if bytes.is_empty() || !chunks.remainder().is_empty() {
+        return None;
+    }
+    let mut pairs = chunks.map(|chunk| {
+        let (rev, h) = chunk.split_at(8);
+        Some((
+            i64::from_le_bytes(rev.try_into().ok()?),
+            u64::from_le_bytes(h.try_into().ok()?),
+        ))
+    });
+    let (revision, hash) = pairs.next()??;
+    let pending = pairs.collect::<Option<_>>()?;
+    Some((revision, hash, pending))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::config::EngineConfig;
+
+    use super::*;
+
+    fn put(revision: i64, key: &str) -> Vec<WriteOp<'static>> {
+        let kv = KeyValue {
+            key: key.as_bytes().to_vec(),
+            value: b"value".to_vec(),
+            create_revision: revision,
+            mod_revision: revision,
+            version: 1,
+            ..Default::default()
+        };
+        vec![
+            WriteOp::PutAppliedIndex(revision.unsigned_abs()),
+            WriteOp::PutKeyValue(Revision::new(revision, 0), kv),
+        ]
+    }
+
+    fn persisted(ops: &[WriteOp<'_>]) -> Option<(i64, u64, BTreeMap<i64, u64>)> {
+        ops.iter().find_map(|op| {
+            if let WriteOp::PutStateHash(ref bytes) = *op {
+                decode_state(bytes)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn new_hasher() -> (Arc<DB>, StateHasher) {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let hasher = StateHasher::recover(&db).unwrap();
+        (db, hasher)
+    }
+
+    #[test]
+    fn checkpoints_should_not_depend_on_apply_order() {
+        let (_db, in_order) = new_hasher();
+        for rev in 1..=3 {
+            in_order.fold(rev, &mut put(rev, &format!("key{rev}")));
+        }
+        let (_db, out_of_order) = new_hasher();
+        let mut ops = put(2, "key2");
+        out_of_order.fold(2, &mut ops);
+        // revision 1 is not folded yet
+        assert_eq!(out_of_order.latest(), None);
+        assert_eq!(persisted(&ops).map(|(rev, _, _)| rev), Some(0));
+        out_of_order.fold(3, &mut put(3, "key3"));
+        let mut ops = put(1, "key1");
+        out_of_order.fold(1, &mut ops);
+        assert_eq!(persisted(&ops).map(|(rev, _, _)| rev), Some(3));
+
+        assert_eq!(in_order.latest(), out_of_order.latest());
+        for rev in 1..=3 {
+            assert!(in_order.at(rev).is_some());
+            assert_eq!(in_order.at(rev), out_of_order.at(rev));
+        }
+    }
+
+    #[test]
+    fn divergent_apply_should_change_later_checkpoints() {
+        let (_db, healthy) = new_hasher();
+        let (_db, diverged) = new_hasher();
+        for rev in 1..=3 {
+            healthy.fold(rev, &mut put(rev, "key"));
+            let key = if rev == 2 { "other" } else { "key" };
+            diverged.fold(rev, &mut put(rev, key));
+        }
+        assert_eq!(healthy.at(1), diverged.at(1));
+        assert_ne!(healthy.at(2), diverged.at(2));
+        assert_ne!(healthy.at(3), diverged.at(3));
+    }
+
+    #[test]
+    fn checkpoint_should_be_recovered_from_db() {
+        let (db, hasher) = new_hasher();
+        for rev in 1..=2 {
+            let mut ops = put(rev, "key");
+            hasher.fold(rev, &mut ops);
+            let _ignore = db.flush_ops(ops).unwrap();
+        }
+        let recovered = StateHasher::recover(&db).unwrap();
+        assert_eq!(recovered.latest(), hasher.latest());
+        // the revisions replayed after a restart are not folded again
+        let mut ops = put(2, "key");
+        recovered.fold(2, &mut ops);
+        assert_eq!(recovered.latest(), hasher.latest());
+        assert_eq!(ops.len(), 2);
+    }
+
+    #[test]
+    fn revisions_folded_ahead_should_survive_restart() {
+        let (_db, healthy) = new_hasher();
+        for rev in 1..=3 {
+            healthy.fold(rev, &mut put(rev, "key"));
+        }
+
+        let (db, hasher) = new_hasher();
+        for rev in [2, 3] {
+            let mut ops = put(rev, "key");
+            hasher.fold(rev, &mut ops);
+            let _ignore = db.flush_ops(ops).unwrap();
+        }
+        let recovered = StateHasher::recover(&db).unwrap();
+        assert_eq!(recovered.latest(), None);
+        recovered.fold(1, &mut put(1, "key"));
+        assert_eq!(recovered.latest(), healthy.latest());
+    }
+
+    #[test]
+    fn failed_revisions_should_be_folded_as_empty() {
+        let (_db, hasher) = new_hasher();
+        hasher.fold(2, &mut put(2, "key"));
+        assert_eq!(hasher.latest(), None);
+        hasher.fold(1, &mut Vec::new());
+        assert_eq!(hasher.latest().map(|(rev, _)| rev), Some(2));
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use clippy_utilities::NumericCast;
+use curp::server::RawCurp;
+use xlineapi::{
+    command::{Command, CurpClient, KeyRange},
+    execute_error::ExecuteError,
+};
+
+use crate::{
+    rpc::{KeyValue, RangeRequest, RangeResponse, Request, RequestOp},
+    state::State,
+    storage::{db::DB, kvwatcher::KvWatcher, lease_store::LeaseCollection, KvStore},
+};
+
+/// Reserved prefix of the virtual keys of the server statistics
+pub(crate) const STATS_PREFIX: &[u8] = b"/__xline/stats/";
+
+/// Error message returned for writes and watches on the virtual keys
+pub(crate) const STATS_READ_ONLY_ERR_MSG: &str =
+    "xline: keys under the prefix /__xline/stats/ are read-only server statistics";
+
+/// Error message returned for ranges crossing the boundary of the reserved prefix
+pub(crate) const STATS_RANGE_ERR_MSG: &str =
+    "xline: a range can't cross the boundary of the prefix /__xline/stats/";
+
+/// Name of the leader id statistics key
+const LEADER_ID: &str = "leader_id";
+/// Name of the revision statistics key
+const REVISION: &str = "revision";
+/// Name of the lease count statistics key
+const LEASE_COUNT: &str = "lease_count";
+/// Name of the watcher count statistics key
+const WATCHER_COUNT: &str = "watcher_count";
+/// Name of the db size statistics key
+const DB_SIZE: &str = "db_size";
+
+/// Whether the key is under the reserved prefix
+pub(crate) fn is_stats_key(key: &[u8]) -> bool {
+    key.starts_with(STATS_PREFIX)
+}
+
+/// Whether a range crosses the boundary of the reserved prefix
+///
+/// A range is served either from the stored keys or from the statistics, so a
+/// range starting under the prefix must end within it, and a range starting before
+/// it must not end inside it. A range starting before the prefix and ending past it
+/// reads the stored keys only.
+pub(crate) fn crosses_stats_prefix(key: &[u8], range_end: &[u8]) -> bool {
+    let prefix_end = KeyRange::get_prefix(STATS_PREFIX);
+    if is_stats_key(key) {
+        match range_end {
+            [] => false,
+            [0] => true,
+            _ => range_end > prefix_end.as_slice(),
+        }
+    } else {
+        key < STATS_PREFIX && range_end > STATS_PREFIX && range_end < prefix_end.as_slice()
+    }
+}
+
+/// Whether the operations write to any key under the reserved prefix, a delete is
+/// taken as such if its range starts under the prefix
+pub(crate) fn writes_stats_keys(ops: &[RequestOp]) -> bool {
+    ops.iter()
+        .filter_map(|op| op.request.as_ref())
+        .any(|request| match *request {
+            Request::RequestPut(ref req) => is_stats_key(&req.key),
+            Request::RequestDeleteRange(ref req) => is_stats_key(&req.key),
+            Request::RequestTxn(ref req) => {
+                writes_stats_keys(&req.success) || writes_stats_keys(&req.failure)
+            }
+            Request::RequestRange(_) => false,
+        })
+}
+
+/// Read-only virtual keys of the runtime statistics of this member
+///
+/// The keys are never stored, the values are read from the counters of the member
+/// on every range, so they are absent from the snapshots, the compactions and the
+/// `HashKV`. A key is created and modified at the current revision, and its value
+/// is the decimal string of the statistics.
+pub(crate) struct StatsKeys {
+    /// Raw curp, for the leader
+    raw_curp: Arc<RawCurp<Command, State<Arc<CurpClient>>>>,
+    /// KV storage, for the revision
+    kv_storage: Arc<KvStore>,
+    /// Lease collection, for the leases
+    lease_collection: Arc<LeaseCollection>,
+    /// KV watcher, for the watchers
+    kv_watcher: Arc<KvWatcher>,
+    /// Persistent storage, for the size
+    db: Arc<DB>,
+}
+
+impl StatsKeys {
+    /// New `StatsKeys`
+    pub(crate) fn new(
+        raw_curp: Arc<RawCurp<Command, State<Arc<CurpClient>>>>,
+        kv_storage: Arc<KvStore>,
+        lease_collection: Arc<LeaseCollection>,
+        kv_watcher: Arc<KvWatcher>,
+        db: Arc<DB>,
+    ) -> Self {
+        Self {
+            raw_curp,
+            kv_storage,
+            lease_collection,
+            kv_watcher,
+            db,
+        }
+    }
+
+    /// The current statistics, in ascending order of the keys
+    fn stats(&self) -> Result<Vec<(Vec<u8>, String)>, ExecuteError> {
+        let (leader, _term, _) = self.raw_curp.leader();
+        let mut stats = vec![
+            (DB_SIZE, self.db.file_size()?.to_string()),
+            // 0 means this member believes there is no leader
+            (LEADER_ID, leader.unwrap_or(0).to_string()),
+            (LEASE_COUNT, self.lease_collection.lease_count().to_string()),
+            (REVISION, self.kv_storage.revision().to_string()),
+            (WATCHER_COUNT, self.kv_watcher.watcher_count().to_string()),
+        ];
+        stats.sort_unstable_by_key(|&(name, _)| name);
+        Ok(stats
+            .into_iter()
+            .map(|(name, value)| ([STATS_PREFIX, name.as_bytes()].concat(), value))
+            .collect())
+    }
+
+    /// Synthesize the response of a range on the virtual keys
+    ///
+    /// The statistics have no history, so only the current revision can be read.
+    /// The filters and the sorting apply the same as on the stored keys.
+    pub(crate) fn range(&self, req: &RangeRequest) -> Result<RangeResponse, ExecuteError> {
+        let revision = self.kv_storage.revision();
+        if req.revision > revision {
+            return Err(ExecuteError::RevisionTooLarge(req.revision, revision));
+        }
+        if req.revision > 0 && req.revision < revision {
+            return Err(ExecuteError::RevisionCompacted(req.revision, revision));
+        }
+        let key_range = KeyRange::new(req.key.as_slice(), req.range_end.as_slice());
+        let mut kvs: Vec<_> = self
+            .stats()?
+            .into_iter()
+            .filter(|&(ref key, _)| key_range.contains(key))
+            .map(|(key, value)| KeyValue {
+                key,
+                value: value.into_bytes(),
+                create_revision: revision,
+                mod_revision: revision,
+                version: 1,
+                lease: 0,
+            })
+            .collect();
+        KvStore::filter_kvs(
+            &mut kvs,
+            req.max_mod_revision,
+            req.min_mod_revision,
+            req.max_create_revision,
+            req.min_create_revision,
+        );
+        KvStore::sort_kvs(&mut kvs, req.sort_order(), req.sort_target());
+        let count = kvs.len();
+        let limit: usize = req.limit.numeric_cast();
+        let more = limit > 0 && count > limit;
+        if req.count_only {
+            kvs.clear();
+        } else if more {
+            kvs.truncate(limit);
+        }
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        Ok(RangeResponse {
+            header: Some(self.kv_storage.gen_header_with_revision(revision)),
+            kvs,
+            more,
+            count: count.numeric_cast(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::{DeleteRangeRequest, PutRequest, TxnRequest};
+
+    #[test]
+    fn writes_under_the_prefix_should_be_detected() {
+        let put = |key: &[u8]| RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.to_vec(),
+                ..Default::default()
+            })),
+        };
+        let delete = |key: &[u8]| RequestOp {
+            request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                key: key.to_vec(),
+                range_end: vec![0],
+                ..Default::default()
+            })),
+        };
+        assert!(!writes_stats_keys(&[put(b"foo"), delete(b"/__xline/")]));
+        assert!(writes_stats_keys(&[put(b"/__xline/stats/revision")]));
+        assert!(writes_stats_keys(&[delete(b"/__xline/stats/")]));
+        let nested = RequestOp {
+            request: Some(Request::RequestTxn(TxnRequest {
+                compare: vec![],
+                success: vec![put(b"foo")],
+                failure: vec![put(b"/__xline/stats/db_size")],
+            })),
+        };
+        assert!(writes_stats_keys(&[nested]));
+    }
+
+    #[test]
+    fn ranges_crossing_the_prefix_should_be_detected() {
+        assert!(!crosses_stats_prefix(b"/__xline/stats/revision", b""));
+        assert!(!crosses_stats_prefix(
+            b"/__xline/stats/",
+            b"/__xline/stats0"
+        ));
+        assert!(!crosses_stats_prefix(b"foo", b"fop"));
+        assert!(!crosses_stats_prefix(b"", &[0]));
+        assert!(crosses_stats_prefix(b"/__xline/stats/", &[0]));
+        assert!(crosses_stats_prefix(
+            b"/__xline/stats/db_size",
+            b"/__xline/stats1"
+        ));
+        assert!(crosses_stats_prefix(
+            b"/__xline/",
+            b"/__xline/stats/revision"
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use parking_lot::Mutex;
+use utils::table_names::META_TABLE;
+use xlineapi::{command::KeyRange, execute_error::ExecuteError};
+
+use crate::{
+    clock::Clock,
+    storage::db::{WriteOp, DB},
+};
+
+/// Key prefix of the time records in the meta table
+pub(crate) const TIME_REVISION_PREFIX: &[u8] = b"time_revision/";
+
+/// Interval between two time records, which is the resolution of the lookups
+const RECORD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of revisions after which a time record is taken before the interval ends
+const RECORD_REVISIONS: i64 = 10_000;
+
+/// Max number of retained time records, the oldest are dropped once it's exceeded
+const MAX_RECORDS: usize = 65_536;
+
+/// Key of a time record in the meta table, the revision is kept in the key so that
+/// the records are recovered by a scan of the keys
+pub(crate) fn time_revision_key(millis: u64, revision: i64) -> Vec<u8> {
+    let mut key = TIME_REVISION_PREFIX.to_vec();
+    key.extend_from_slice(&millis.to_be_bytes());
+    key.extend_from_slice(&revision.to_be_bytes());
+    key
+}
+
+/// Decode a time record from its key
+fn decode_time_revision_key(key: &[u8]) -> Option<(u64, i64)> {
+    let record = key.strip_prefix(TIME_REVISION_PREFIX)?;
+    if record.len() != 16 {
+        return None;
+    }
+    let (millis, revision) = record.split_at(8);
+    Some((
+        u64::from_be_bytes(millis.try_into().ok()?),
+        i64::from_be_bytes(revision.try_into().ok()?),
+    ))
+}
+
+/// Sparse mapping of the wall time to the revisions
+///
+/// The apply driver records the revision it applies together with the wall time once
+/// per interval, or once per a number of revisions under a heavy load. A time is
+/// resolved to the latest record at or before it, so the resolved revision may lag
+/// behind the one actually current at that time by up to the interval. The records are
+/// local to each member and are pruned by the compactions.
+#[derive(Debug)]
+pub(crate) struct TimeIndex {
+    /// The clock the records are taken with
+    clock: Arc<Clock>,
+    /// Interval between two records
+    interval: Duration,
+    /// The records, from the milliseconds since the unix epoch to the revisions
+    records: Mutex<BTreeMap<u64, i64>>,
+}
+
+impl TimeIndex {
+    /// Recover the index from the persisted records
+    pub(crate) fn recover(db: &DB, clock: Arc<Clock>) -> Result<Self, ExecuteError> {
+        let index = Self {
+            clock,
+            interval: RECORD_INTERVAL,
+            records: Mutex::new(BTreeMap::new()),
+        };
+        index.reset(db)?;
+        Ok(index)
+    }
+
+    /// Reset the index to the persisted records, after the db is reset
+    pub(crate) fn reset(&self, db: &DB) -> Result<(), ExecuteError> {
+        let range_end = KeyRange::get_prefix(TIME_REVISION_PREFIX);
+        let records = db
+            .scan_keys(META_TABLE, TIME_REVISION_PREFIX, &range_end)?
+            .iter()
+            .map(|key| {
+                decode_time_revision_key(key).ok_or_else(|| {
+                    ExecuteError::DbError("cannot decode the time record".to_owned())
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        *self.records.lock() = records;
+        Ok(())
+    }
+
+    /// Record the applied revision if a record is due, the write of the record is
+    /// appended to `ops`
+    pub(crate) fn record(&self, revision: i64, ops: &mut Vec<WriteOp<'_>>) {
+        let now = self.clock.unix_millis();
+        let mut records = self.records.lock();
+        if let Some((&last_at, &last_revision)) = records.last_key_value() {
+            // replayed after a restart, or the wall clock jumped backwards
+            if revision <= last_revision || now <= last_at {
+                return;
+            }
+            let elapsed = Duration::from_millis(now.overflow_sub(last_at));
+            if elapsed < self.interval && revision.overflow_sub(last_revision) < RECORD_REVISIONS {
+                return;
+            }
+        }
+        let _prev = records.insert(now, revision);
+        ops.push(WriteOp::PutTimeRevision(now, revision));
+        if records.len() > MAX_RECORDS {
+            if let Some((oldest_at, oldest_revision)) = records.pop_first() {
+                ops.push(WriteOp::DeleteTimeRevision(oldest_at, oldest_revision));
+            }
+        }
+    }
+
+    /// Drop the records of the revisions below the compacted one, the deletes are
+    /// appended to `ops`
+    pub(crate) fn prune(&self, compacted: i64, ops: &mut Vec<WriteOp<'_>>) {
+        self.records.lock().retain(|&at, &mut revision| {
+            if revision < compacted {
+                ops.push(WriteOp::DeleteTimeRevision(at, revision));
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Resolve a time in milliseconds since the unix epoch to the latest record at or
+    /// before it, returns the revision and the time it's recorded at, `None` if it's
+    /// before the first record
+    pub(crate) fn resolve(&self, at: u64) -> Option<(i64, u64)> {
+        self.records
+            .lock()
+            .range(..=at)
+            .next_back()
+            .map(|(&recorded_at, &revision)| (revision, recorded_at))
+    }
+
+    /// The resolution of the lookups in milliseconds
+    pub(crate) fn resolution(&self) -> u64 {
+        self.interval.as_millis().numeric_cast()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::UNIX_EPOCH;
+
+    use utils::config::EngineConfig;
+
+    use super::*;
+    use crate::clock::MockTimeProvider;
+
+    const START: u64 = 1_000_000;
+
+    fn new_index() -> (Arc<DB>, Arc<MockTimeProvider>, TimeIndex) {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let time = MockTimeProvider::new(UNIX_EPOCH + Duration::from_millis(START));
+        let clock = Arc::new(Clock::new(Arc::clone(&time) as _));
+        let index = TimeIndex::recover(&db, clock).unwrap();
+        (db, time, index)
+    }
+
+    /// Apply a write at each revision in `revisions`, one per second
+    fn apply(
+        db: &DB,
+        time: &MockTimeProvider,
+        index: &TimeIndex,
+        revisions: impl Iterator<Item = i64>,
+    ) {
+        for revision in revisions {
+            let mut ops = vec![WriteOp::PutAppliedIndex(revision.unsigned_abs())];
+            index.record(revision, &mut ops);
+            let _ignore = db.flush_ops(ops).unwrap();
+            time.advance(Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn time_should_resolve_to_nearest_earlier_record() {
+        let (db, time, index) = new_index();
+        // revision 1 at START, 2 at START + 1s, ..., 25 at START + 24s
+        apply(&db, &time, &index, 1..=25);
+        let secs = |s: u64| START + s * 1000;
+        // recorded at revisions 1, 11 and 21
+        assert_eq!(index.resolve(secs(0)), Some((1, secs(0))));
+        assert_eq!(index.resolve(secs(9)), Some((1, secs(0))));
+        assert_eq!(index.resolve(secs(10)), Some((11, secs(10))));
+        assert_eq!(index.resolve(secs(15) + 500), Some((11, secs(10))));
+        // the resolved revision is never ahead of the one current at the time, and
+        // lags behind it by less than the resolution
+        for at in (0..25).map(secs) {
+            let (revision, recorded_at) = index.resolve(at).unwrap();
+            let current = i64::try_from((at - START) / 1000 + 1).unwrap();
+            assert!(revision <= current);
+            assert!(at - recorded_at < index.resolution());
+        }
+    }
+
+    #[test]
+    fn time_out_of_records_should_resolve_to_edges() {
+        let (db, time, index) = new_index();
+        // before any record
+        assert_eq!(index.resolve(START), None);
+        apply(&db, &time, &index, 1..=15);
+        assert_eq!(index.resolve(START - 1), None);
+        assert_eq!(index.resolve(0), None);
+        // after the last record
+        assert_eq!(index.resolve(u64::MAX), Some((11, START + 10_000)));
+    }
+
+    #[test]
+    fn records_should_be_recovered_and_pruned() {
+        let (db, time, index) = new_index();
+        apply(&db, &time, &index, 1..=25);
+        let clock = Arc::new(Clock::new(Arc::clone(&time) as _));
+        let recovered = TimeIndex::recover(&db, clock).unwrap();
+        assert_eq!(recovered.resolve(u64::MAX), Some((21, START + 20_000)));
+        assert_eq!(recovered.resolve(START + 5_000), Some((1, START)));
+
+        let mut ops = vec![];
+        recovered.prune(11, &mut ops);
+        assert_eq!(ops.len(), 1);
+        let _ignore = db.flush_ops(ops).unwrap();
+        // the time of the compacted revisions cannot be resolved
+        assert_eq!(recovered.resolve(START + 5_000), None);
+        assert_eq!(
+            recovered.resolve(START + 10_000),
+            Some((11, START + 10_000))
+        );
+        recovered.reset(&db).unwrap();
+        assert_eq!(recovered.resolve(START + 5_000), None);
+    }
+
+    #[test]
+    fn busy_revisions_and_backward_jumps_should_be_handled() {
+        let (_db, time, index) = new_index();
+        let mut ops = vec![];
+        // a record is taken every `RECORD_REVISIONS` revisions within the interval
+        for revision in 1..=RECORD_REVISIONS + 1 {
+            index.record(revision, &mut ops);
+            time.advance(Duration::from_micros(1));
+        }
+        assert_eq!(ops.len(), 2);
+        let (latest, _at) = index.resolve(u64::MAX).unwrap();
+        assert_eq!(latest, RECORD_REVISIONS + 1);
+
+        // the wall clock jumped backwards, no record is taken until it catches up
+        time.jump_backward(Duration::from_secs(60));
+        ops.clear();
+        time.advance(RECORD_INTERVAL);
+        index.record(RECORD_REVISIONS + 2, &mut ops);
+        assert!(ops.is_empty());
+    }
+}
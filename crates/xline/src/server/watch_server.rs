@@ -1,28 +1,59 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
     sync::Arc,
     time::Duration,
 };
 
+use clippy_utilities::OverflowArithmetic;
 use event_listener::Event;
-use tokio::sync::mpsc;
+use prost::Message;
+use tokio::{sync::mpsc, time::Instant};
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tracing::{debug, warn};
 use utils::task_manager::{tasks::TaskName, Listener, TaskManager};
-use xlineapi::command::KeyRange;
+use xlineapi::{command::KeyRange, request_validation::RequestValidator};
 
+use super::{
+    accounting::{Accounting, Usage},
+    rate_limit::{client_identity, ClientRateLimiter, ClientRates},
+    stats_keys::{is_stats_key, STATS_READ_ONLY_ERR_MSG},
+};
 use crate::{
     header_gen::HeaderGenerator,
+    metrics,
     rpc::{
-        RequestUnion, ResponseHeader, Watch, WatchCancelRequest, WatchCreateRequest,
-        WatchProgressRequest, WatchRequest, WatchResponse,
+        Event as PbEvent, EventType, RequestUnion, ResponseHeader, Watch, WatchCancelRequest,
+        WatchCreateRequest, WatchProgressRequest, WatchRequest, WatchResponse,
+    },
+    storage::{
+        kvwatcher::{KvWatcher, KvWatcherOps, WatchEvent, WatchId, WatchIdGenerator},
+        AuthStore, Revision,
     },
-    storage::kvwatcher::{KvWatcher, KvWatcherOps, WatchEvent, WatchId, WatchIdGenerator},
 };
 
 /// Default channel size
 pub(crate) const CHANNEL_SIZE: usize = 1024;
 
+/// Minimum progress notify interval a watcher could ask for
+pub(crate) const MIN_WATCH_PROGRESS_NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of buckets the smallest progress notify interval is split into
+const PROGRESS_BUCKETS_PER_INTERVAL: u32 = 10;
+
+/// Watch id of the response to a rejected creation, same as the one of etcd
+const INVALID_WATCH_ID: WatchId = -1;
+
+/// Number of kvs of the initial state read from the storage at a time
+const INITIAL_STATE_PAGE_SIZE: usize = 256;
+
+/// Max encoded bytes of the events of an initial state response, well below the 4MiB
+/// limit gRPC clients decode by default. A single larger kv is sent alone.
+const INITIAL_STATE_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Window over which the throttled watch creations of the clients are measured
+const THROTTLED_CREATION_WINDOW: Duration = Duration::from_secs(10);
+
 /// Watch Server
 #[derive(Debug)]
 pub(crate) struct WatchServer {
@@ -34,6 +65,14 @@ pub(crate) struct WatchServer {
     header_gen: Arc<HeaderGenerator>,
     /// Watch progress notify interval
     watch_progress_notify_interval: Duration,
+    /// Limiter of watch creations of each client
+    create_limiter: Arc<ClientRateLimiter>,
+    /// Rates of the throttled watch creations of each client
+    throttled_creations: Arc<ClientRates>,
+    /// Auth storage, by which clients are identified
+    auth_storage: Arc<AuthStore>,
+    /// Usage accounting of the users
+    accounting: Arc<Accounting>,
     /// Task manager
     task_manager: Arc<TaskManager>,
 }
@@ -44,19 +83,29 @@ impl WatchServer {
         watcher: Arc<KvWatcher>,
         header_gen: Arc<HeaderGenerator>,
         watch_progress_notify_interval: Duration,
+        create_limiter: ClientRateLimiter,
+        auth_storage: Arc<AuthStore>,
+        accounting: Arc<Accounting>,
         task_manager: Arc<TaskManager>,
     ) -> Self {
+        let throttled_creations = Arc::new(ClientRates::new(THROTTLED_CREATION_WINDOW));
+        metrics::register_watch_creation_throttled_rates(&throttled_creations);
         Self {
             watcher,
             next_id_gen: Arc::new(WatchIdGenerator::new(1)), // watch_id starts from 1, 0 means auto-generating
             header_gen,
             watch_progress_notify_interval,
+            create_limiter: Arc::new(create_limiter),
+            throttled_creations,
+            auth_storage,
+            accounting,
             task_manager,
         }
     }
 
     /// bg task for handle watch connection
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    #[allow(clippy::too_many_arguments)]
     async fn task<ST, W>(
         next_id_gen: Arc<WatchIdGenerator>,
         kv_watcher: Arc<W>,
@@ -64,6 +113,8 @@ impl WatchServer {
         mut req_rx: ST,
         header_gen: Arc<HeaderGenerator>,
         watch_progress_notify_interval: Duration,
+        create_quota: WatchCreateQuota,
+        usage: Arc<Usage>,
         shutdown_listener: Listener,
     ) where
         ST: Stream<Item = Result<WatchRequest, tonic::Status>> + Unpin,
@@ -71,6 +122,7 @@ impl WatchServer {
     {
         let (event_tx, mut event_rx) = mpsc::channel(CHANNEL_SIZE);
         let stop_notify = Arc::new(Event::new());
+        let flush_tx = res_tx.clone();
         let mut watch_handle = WatchHandle::new(
             kv_watcher,
             res_tx,
@@ -78,11 +130,21 @@ impl WatchServer {
             Arc::clone(&stop_notify),
             next_id_gen,
             header_gen,
+            watch_progress_notify_interval,
+            create_quota,
+            usage,
         );
-        let mut ticker = tokio::time::interval(watch_progress_notify_interval);
+        let progress_timer = tokio::time::sleep(Duration::ZERO);
+        tokio::pin!(progress_timer);
         let stop_listener = stop_notify.listen();
         tokio::pin!(stop_listener);
         loop {
+            let next_progress = watch_handle.next_progress_deadline();
+            if let Some(deadline) = next_progress {
+                if progress_timer.deadline() != deadline {
+                    progress_timer.as_mut().reset(deadline);
+                }
+            }
             tokio::select! {
                 _ = shutdown_listener.wait() => break,
                 req = req_rx.next() => {
@@ -108,9 +170,16 @@ impl WatchServer {
                         panic!("Watch event sender is closed");
                     }
                 }
-                _ = ticker.tick() => {
+                _ = &mut progress_timer, if next_progress.is_some() => {
                     watch_handle.handle_tick_progress().await;
                 }
+                permit = flush_tx.reserve(), if watch_handle.has_coalesced_events() => {
+                    if let Ok(permit) = permit {
+                        watch_handle.flush_coalesced_events(permit);
+                    } else {
+                        break;
+                    }
+                }
                 // To ensure that each iteration invokes the same `stop_listener` and keeps
                 // events losing due to the cancellation of `stop_listener` at bay.
                 _ = &mut stop_listener => {
@@ -143,12 +212,255 @@ where
     header_gen: Arc<HeaderGenerator>,
     /// Previous KV status
     prev_kv: HashSet<WatchId>,
-    /// Progress status
-    ///
-    /// `true` means the next tick should be notified
+    /// Progress notify timer of watchers with `progress_notify` set
+    progress: ProgressTimer,
+    /// Watchers in coalesce mode
+    coalesce: HashSet<WatchId>,
+    /// Events of coalescing watchers buffered while the response stream is blocked
+    coalesce_buffers: HashMap<WatchId, CoalesceBuffer>,
+    /// Watch creation quota of the client of this connection
+    create_quota: WatchCreateQuota,
+    /// Usage of the user of this connection
+    usage: Arc<Usage>,
+}
+
+/// Watch creation quota of a watch connection
+#[derive(Debug, Clone)]
+pub(crate) struct WatchCreateQuota {
+    /// The limiter shared by all connections
+    limiter: Arc<ClientRateLimiter>,
+    /// Rates of the throttled creations shared by all connections
+    throttled: Arc<ClientRates>,
+    /// Identity of the client of the connection
+    client: String,
+}
+
+impl WatchCreateQuota {
+    /// New `WatchCreateQuota`
+    pub(crate) fn new(
+        limiter: Arc<ClientRateLimiter>,
+        throttled: Arc<ClientRates>,
+        client: String,
+    ) -> Self {
+        Self {
+            limiter,
+            throttled,
+            client,
+        }
+    }
+
+    /// Take a watch creation from the quota, returns how long the client should wait
+    /// if it has created too many watchers
+    fn acquire(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.limiter
+            .acquire(&self.client, now)
+            .map_err(|retry_after| {
+                self.throttled.record(&self.client, now);
+                retry_after
+            })
+    }
+}
+
+/// Latest event of each key buffered for a coalescing watcher
+#[derive(Debug)]
+struct CoalesceBuffer {
+    /// Latest event of each key
+    events: HashMap<Vec<u8>, PbEvent>,
+    /// Revision of the first buffered event
+    start_revision: i64,
+    /// Revision of the last buffered event
+    end_revision: i64,
+    /// Whether an event has been replaced by a later one of the same key
+    coalesced: bool,
+}
+
+impl CoalesceBuffer {
+    /// New `CoalesceBuffer`
+    fn new(start_revision: i64) -> Self {
+        Self {
+            events: HashMap::new(),
+            start_revision,
+            end_revision: start_revision,
+            coalesced: false,
+        }
+    }
+
+    /// Buffer events of a revision, an event replaces the previous one of the same key
+    fn push(&mut self, revision: i64, events: Vec<PbEvent>) {
+        self.end_revision = revision;
+        for event in events {
+            let key = event
+                .kv
+                .as_ref()
+                .unwrap_or_else(|| panic!("event.kv can't be None"))
+                .key
+                .clone();
+            if self.events.insert(key, event).is_some() {
+                self.coalesced = true;
+            }
+        }
+    }
+
+    /// Buffered events in revision order
+    fn take_events(&mut self) -> Vec<PbEvent> {
+        let mut events: Vec<_> = self.events.drain().map(|(_, e)| e).collect();
+        events.sort_by_key(|e| e.kv.as_ref().map_or(0, |kv| kv.mod_revision));
+        events
+    }
+}
+
+/// Progress notify timer of all watchers of a watch connection
+///
+/// Deadlines are rounded up to coarse buckets and the watchers due in the same bucket
+/// are notified together, so a single timer serves the connection however many
+/// watchers with different intervals there are.
+#[derive(Debug)]
+struct ProgressTimer {
+    /// Start time of bucket 0
+    start: Instant,
+    /// Span of a bucket in nanoseconds
+    granularity: u64,
+    /// Interval of watchers that don't ask for one
+    default_interval: Duration,
+    /// Progress state of each watcher
+    watchers: HashMap<WatchId, ProgressState>,
+    /// Watchers due in each bucket
+    buckets: BTreeMap<u64, HashSet<WatchId>>,
+}
+
+/// Progress state of a watcher
+#[derive(Debug)]
+struct ProgressState {
+    /// Number of buckets between two notifications
+    interval: u64,
+    /// Bucket of the next notification
+    bucket: u64,
+    /// `true` means the next notification should be sent
     ///
-    /// `false` means the next tick should be skipped
-    progress: HashMap<WatchId, bool>,
+    /// `false` means it should be skipped since events have been sent after the last one
+    idle: bool,
+}
+
+impl ProgressTimer {
+    /// New `ProgressTimer`
+    fn new(default_interval: Duration) -> Self {
+        let granularity = default_interval
+            .min(MIN_WATCH_PROGRESS_NOTIFY_INTERVAL)
+            .checked_div(PROGRESS_BUCKETS_PER_INTERVAL)
+            .unwrap_or_default()
+            .max(Duration::from_millis(1));
+        Self {
+            start: Instant::now(),
+            granularity: u64::try_from(granularity.as_nanos()).unwrap_or(u64::MAX),
+            default_interval,
+            watchers: HashMap::new(),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Number of buckets covering `duration`, rounded up
+    fn buckets_of(&self, duration: Duration) -> u64 {
+        u64::try_from(duration.as_nanos().div_ceil(u128::from(self.granularity)))
+            .unwrap_or(u64::MAX)
+    }
+
+    /// The latest bucket whose deadline has passed
+    fn current_bucket(&self) -> u64 {
+        u64::try_from(
+            self.start
+                .elapsed()
+                .as_nanos()
+                .checked_div(u128::from(self.granularity))
+                .unwrap_or_default(),
+        )
+        .unwrap_or(u64::MAX)
+    }
+
+    /// Register a watcher, `interval` falls back to the default one when unset and is
+    /// bounded below by `MIN_WATCH_PROGRESS_NOTIFY_INTERVAL`
+    fn add(&mut self, watch_id: WatchId, interval: Option<Duration>) {
+        let interval = interval.map_or(self.default_interval, |i| {
+            i.max(MIN_WATCH_PROGRESS_NOTIFY_INTERVAL)
+        });
+        let interval = self.buckets_of(interval).max(1);
+        let bucket = self
+            .buckets_of(self.start.elapsed())
+            .saturating_add(interval);
+        let _ignore = self.buckets.entry(bucket).or_default().insert(watch_id);
+        let prev = self.watchers.insert(
+            watch_id,
+            ProgressState {
+                interval,
+                bucket,
+                idle: true,
+            },
+        );
+        assert!(
+            prev.is_none(),
+            "WatchId {watch_id} already exists in progress"
+        );
+    }
+
+    /// Unregister a watcher
+    fn remove(&mut self, watch_id: WatchId) {
+        let Some(state) = self.watchers.remove(&watch_id) else {
+            return;
+        };
+        if let Some(ids) = self.buckets.get_mut(&state.bucket) {
+            let _ignore = ids.remove(&watch_id);
+            if ids.is_empty() {
+                let _prev = self.buckets.remove(&state.bucket);
+            }
+        }
+    }
+
+    /// Skip the next notification of a watcher since events have been sent to it
+    fn mark_active(&mut self, watch_id: WatchId) {
+        if let Some(state) = self.watchers.get_mut(&watch_id) {
+            state.idle = false;
+        }
+    }
+
+    /// Deadline of the earliest notification
+    fn next_deadline(&self) -> Option<Instant> {
+        let (&bucket, _) = self.buckets.first_key_value()?;
+        self.start.checked_add(Duration::from_nanos(
+            self.granularity.saturating_mul(bucket),
+        ))
+    }
+
+    /// Take the due watchers that have been idle since their last notifications, all
+    /// due watchers are scheduled for their next notifications
+    fn take_due(&mut self) -> Vec<WatchId> {
+        let current = self.current_bucket();
+        let mut due = vec![];
+        while let Some(entry) = self.buckets.first_entry() {
+            if *entry.key() > current {
+                break;
+            }
+            let (bucket, ids) = entry.remove_entry();
+            for watch_id in ids {
+                let Some(state) = self.watchers.get_mut(&watch_id) else {
+                    continue;
+                };
+                if state.idle {
+                    due.push(watch_id);
+                }
+                state.idle = true;
+                // never schedule into the past if the connection has been stalled
+                state.bucket = bucket
+                    .saturating_add(state.interval)
+                    .max(current.saturating_add(1));
+                let _ignore = self
+                    .buckets
+                    .entry(state.bucket)
+                    .or_default()
+                    .insert(watch_id);
+            }
+        }
+        due
+    }
 }
 
 impl<W> WatchHandle<W>
@@ -156,6 +468,7 @@ where
     W: KvWatcherOps,
 {
     /// New `WatchHandle`
+    #[allow(clippy::too_many_arguments)]
     fn new(
         kv_watcher: Arc<W>,
         response_tx: mpsc::Sender<Result<WatchResponse, tonic::Status>>,
@@ -163,6 +476,9 @@ where
         stop_notify: Arc<Event>,
         next_id_gen: Arc<WatchIdGenerator>,
         header_gen: Arc<HeaderGenerator>,
+        progress_notify_interval: Duration,
+        create_quota: WatchCreateQuota,
+        usage: Arc<Usage>,
     ) -> Self {
         Self {
             kv_watcher,
@@ -173,7 +489,11 @@ where
             stop_notify,
             header_gen,
             prev_kv: HashSet::new(),
-            progress: HashMap::new(),
+            progress: ProgressTimer::new(progress_notify_interval),
+            coalesce: HashSet::new(),
+            coalesce_buffers: HashMap::new(),
+            create_quota,
+            usage,
         }
     }
 
@@ -196,6 +516,25 @@ where
 
     /// Handle `WatchCreateRequest`
     async fn handle_watch_create(&mut self, req: WatchCreateRequest) {
+        // only the creation is rejected, the connection and its watchers stay
+        if let Err(retry_after) = self.create_quota.acquire() {
+            metrics::get().watch_creations_throttled_total.add(1, &[]);
+            let response = WatchResponse {
+                header: Some(self.header_gen.gen_header()),
+                watch_id: INVALID_WATCH_ID,
+                created: true,
+                canceled: true,
+                cancel_reason: format!(
+                    "too many watch creations, slow down, retry after {}ms",
+                    retry_after.as_millis().max(1)
+                ),
+                ..WatchResponse::default()
+            };
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
         let Some(watch_id) = self.validate_watch_id(req.watch_id) else {
             let result = Err(tonic::Status::already_exists(format!(
                 "Watch ID {} has already been used",
@@ -206,12 +545,67 @@ where
             }
             return;
         };
+        if req.send_initial_state && req.start_revision != 0 {
+            let response = WatchResponse {
+                header: Some(self.header_gen.gen_header()),
+                watch_id: INVALID_WATCH_ID,
+                created: true,
+                canceled: true,
+                cancel_reason: "send_initial_state can't be set with start_revision".to_owned(),
+                ..WatchResponse::default()
+            };
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
+        // the statistics are not stored, there are no events of them
+        if is_stats_key(&req.key) {
+            let response = WatchResponse {
+                header: Some(self.header_gen.gen_header()),
+                watch_id: INVALID_WATCH_ID,
+                created: true,
+                canceled: true,
+                cancel_reason: STATS_READ_ONLY_ERR_MSG.to_owned(),
+                ..WatchResponse::default()
+            };
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
+        if let Err(e) = req.validation() {
+            let response = WatchResponse {
+                header: Some(self.header_gen.gen_header()),
+                watch_id: INVALID_WATCH_ID,
+                created: true,
+                canceled: true,
+                cancel_reason: format!("invalid watch range: {e}"),
+                ..WatchResponse::default()
+            };
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
 
-        let key_range = KeyRange::new(req.key, req.range_end);
+        // the dispatcher only sees canonical ranges
+        let key_range = KeyRange::canonical(req.key, req.range_end);
+        // live events are delivered from the one after the snapshot, they are queued
+        // while the initial state is being sent
+        let snapshot = req
+            .send_initial_state
+            .then(|| self.kv_watcher.range_snapshot(&key_range));
+        let start_revision = snapshot
+            .as_ref()
+            .map_or(req.start_revision, |&(revision, _)| {
+                revision.overflow_add(1)
+            });
+        let no_put = req.filters.contains(&i32::from(EventType::Put));
         self.kv_watcher.watch(
             watch_id,
             key_range,
-            req.start_revision,
+            start_revision,
             req.filters,
             Arc::clone(&self.stop_notify),
             self.event_tx.clone(),
@@ -222,16 +616,23 @@ where
                 "WatchId {watch_id} already exists in prev_kv",
             );
         }
-        if req.progress_notify {
+        if req.coalesce {
             assert!(
-                self.progress.insert(watch_id, true).is_none(),
-                "WatchId {watch_id} already exists in progress",
+                self.coalesce.insert(watch_id),
+                "WatchId {watch_id} already exists in coalesce",
             );
         }
+        if req.progress_notify {
+            // 0 means the server-wide interval, this is an Xline extension
+            let interval = (req.progress_notify_interval_ms > 0)
+                .then(|| Duration::from_millis(req.progress_notify_interval_ms));
+            self.progress.add(watch_id, interval);
+        }
         assert!(
             self.active_watch_ids.insert(watch_id),
             "WatchId {watch_id} already exists in active_watch_ids",
         );
+        self.usage.add_watcher();
 
         let response = WatchResponse {
             header: Some(self.header_gen.gen_header()),
@@ -241,15 +642,120 @@ where
         };
         if self.response_tx.send(Ok(response)).await.is_err() {
             let _ignore = self.stop_notify.notify(1);
+            return;
         }
+        if let Some((revision, revisions)) = snapshot {
+            let revisions = if no_put { vec![] } else { revisions };
+            self.send_initial_state(watch_id, revision, &revisions)
+                .await;
+        }
+    }
+
+    /// Send the kvs of a range snapshot as PUT events stamped with their mod revisions,
+    /// followed by a progress notification at the snapshot revision
+    ///
+    /// The kvs are read page by page and packed into responses of bounded size, so the
+    /// initial state is never materialized as a whole. The watcher is canceled as
+    /// compacted if the snapshot is compacted before it's sent.
+    async fn send_initial_state(
+        &mut self,
+        watch_id: WatchId,
+        revision: i64,
+        revisions: &[Revision],
+    ) {
+        let header = self.header_gen.gen_header_with_revision(revision);
+        let mut events = vec![];
+        let mut bytes = 0;
+        for page in revisions.chunks(INITIAL_STATE_PAGE_SIZE) {
+            let kvs = match self.kv_watcher.get_kvs(page) {
+                Ok(kvs) => kvs,
+                Err(e) => {
+                    warn!("failed to read the initial state of watcher {watch_id}: {e}");
+                    let _ignore = self.remove_watcher(watch_id);
+                    let response = WatchResponse {
+                        header: Some(header),
+                        watch_id,
+                        canceled: true,
+                        compact_revision: self.kv_watcher.compacted_revision(),
+                        ..WatchResponse::default()
+                    };
+                    if self.response_tx.send(Ok(response)).await.is_err() {
+                        let _ignore = self.stop_notify.notify(1);
+                    }
+                    return;
+                }
+            };
+            for kv in kvs {
+                let event = PbEvent {
+                    kv: Some(kv),
+                    ..PbEvent::default()
+                };
+                let len = event.encoded_len();
+                if !events.is_empty() && bytes.saturating_add(len) > INITIAL_STATE_RESPONSE_BYTES {
+                    if !self
+                        .send_initial_events(watch_id, &header, mem::take(&mut events))
+                        .await
+                    {
+                        return;
+                    }
+                    bytes = 0;
+                }
+                bytes = bytes.saturating_add(len);
+                events.push(event);
+            }
+        }
+        if !events.is_empty() && !self.send_initial_events(watch_id, &header, events).await {
+            return;
+        }
+        let response = WatchResponse {
+            header: Some(header),
+            watch_id,
+            ..WatchResponse::default()
+        };
+        if self.response_tx.send(Ok(response)).await.is_err() {
+            let _ignore = self.stop_notify.notify(1);
+        }
+    }
+
+    /// Send a response of the initial state, returns `false` if the stream is closed
+    async fn send_initial_events(
+        &mut self,
+        watch_id: WatchId,
+        header: &ResponseHeader,
+        events: Vec<PbEvent>,
+    ) -> bool {
+        self.usage.record_watch_events(events.len());
+        let response = WatchResponse {
+            header: Some(header.clone()),
+            watch_id,
+            events,
+            ..WatchResponse::default()
+        };
+        if self.response_tx.send(Ok(response)).await.is_err() {
+            let _ignore = self.stop_notify.notify(1);
+            return false;
+        }
+        true
+    }
+
+    /// Remove a watcher of this connection, returns `false` if it doesn't exist
+    fn remove_watcher(&mut self, watch_id: WatchId) -> bool {
+        if !self.active_watch_ids.remove(&watch_id) {
+            return false;
+        }
+        self.kv_watcher.cancel(watch_id);
+        self.usage.remove_watcher();
+        let _prev = self.prev_kv.remove(&watch_id);
+        let _prev_coalesce = self.coalesce.remove(&watch_id);
+        let _prev_buffer = self.coalesce_buffers.remove(&watch_id);
+        self.progress.remove(watch_id);
+        true
     }
 
     /// Handle `WatchCancelRequest`
     async fn handle_watch_cancel(&mut self, req: WatchCancelRequest) {
         let watch_id = req.watch_id;
-        let result = if self.active_watch_ids.remove(&watch_id) {
-            self.kv_watcher.cancel(watch_id);
-            let _prev = self.active_watch_ids.remove(&watch_id);
+        let result = if self.remove_watcher(watch_id) {
             let response = WatchResponse {
                 header: Some(self.header_gen.gen_header()),
                 watch_id,
@@ -288,11 +794,15 @@ where
     /// Handle watch event
     async fn handle_watch_event(&mut self, mut watch_event: WatchEvent) {
         let watch_id = watch_event.watch_id();
+        if watch_event.is_progress() {
+            self.send_progress(watch_id, watch_event.revision()).await;
+            return;
+        }
         let mut response = WatchResponse {
-            header: Some(ResponseHeader {
-                revision: watch_event.revision(),
-                ..ResponseHeader::default()
-            }),
+            header: Some(
+                self.header_gen
+                    .gen_header_with_revision(watch_event.revision()),
+            ),
             watch_id,
             ..WatchResponse::default()
         };
@@ -304,36 +814,133 @@ where
             if events.is_empty() {
                 return;
             }
-
-            if self.prev_kv.contains(&watch_id) {
-                for ev in &mut events {
-                    if !ev.is_create() {
-                        let kv = ev
-                            .kv
-                            .as_ref()
-                            .unwrap_or_else(|| panic!("event.kv can't be None"));
-                        ev.prev_kv = self.kv_watcher.get_prev_kv(kv);
-                    }
-                }
+            // Keep buffering until the stream is unblocked and the buffer is flushed, so
+            // that events of a watcher are always delivered in order
+            if self.coalesce.contains(&watch_id)
+                && (self.coalesce_buffers.contains_key(&watch_id)
+                    || self.response_tx.capacity() == 0)
+            {
+                let revision = watch_event.revision();
+                self.coalesce_buffers
+                    .entry(watch_id)
+                    .or_insert_with(|| CoalesceBuffer::new(revision))
+                    .push(revision, events);
+                self.progress.mark_active(watch_id);
+                return;
             }
+
+            self.fill_prev_kv(watch_id, &mut events);
+            self.usage.record_watch_events(events.len());
             response.events = events;
         };
 
         if self.response_tx.send(Ok(response)).await.is_err() {
             let _ignore = self.stop_notify.notify(1);
         }
-        if let Some(progress) = self.progress.get_mut(&watch_id) {
-            *progress = false;
+        self.progress.mark_active(watch_id);
+    }
+
+    /// Fill `prev_kv` of events if the watcher requires it
+    fn fill_prev_kv(&self, watch_id: WatchId, events: &mut [PbEvent]) {
+        if !self.prev_kv.contains(&watch_id) {
+            return;
+        }
+        for ev in events {
+            if !ev.is_create() {
+                let kv = ev
+                    .kv
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("event.kv can't be None"));
+                ev.prev_kv = self.kv_watcher.get_prev_kv(kv);
+            }
         }
     }
 
+    /// Whether there are buffered events of coalescing watchers
+    fn has_coalesced_events(&self) -> bool {
+        !self.coalesce_buffers.is_empty()
+    }
+
+    /// Send the buffered events of one coalescing watcher
+    fn flush_coalesced_events(
+        &mut self,
+        permit: mpsc::Permit<'_, Result<WatchResponse, tonic::Status>>,
+    ) {
+        let Some(&watch_id) = self.coalesce_buffers.keys().next() else {
+            return;
+        };
+        let Some(mut buffer) = self.coalesce_buffers.remove(&watch_id) else {
+            return;
+        };
+        let mut events = buffer.take_events();
+        self.fill_prev_kv(watch_id, &mut events);
+        self.usage.record_watch_events(events.len());
+        permit.send(Ok(WatchResponse {
+            header: Some(
+                self.header_gen
+                    .gen_header_with_revision(buffer.end_revision),
+            ),
+            watch_id,
+            events,
+            coalesced: buffer.coalesced,
+            coalesced_start_revision: buffer.start_revision,
+            coalesced_end_revision: buffer.end_revision,
+            ..WatchResponse::default()
+        }));
+    }
+
     /// Handle progress for request
+    ///
+    /// The progress of a connection is the least one of its watchers, it's sent after
+    /// the events queued before it so that none is delivered behind the progress.
     async fn handle_watch_progress(&mut self, _req: WatchProgressRequest) {
+        if self.active_watch_ids.is_empty() {
+            let revision = self.header_gen.gen_header().revision;
+            self.send_progress(-1, revision).await;
+            return;
+        }
+        let revision = self
+            .active_watch_ids
+            .iter()
+            .map(|&watch_id| self.kv_watcher.progress_revision(watch_id))
+            .try_fold(i64::MAX, |least, revision| revision.map(|r| least.min(r)));
+        if let Some(revision) = revision {
+            self.queue_progress(-1, revision);
+        }
+    }
+
+    /// Queue a progress marker behind the events sent to the watchers, it's dropped if
+    /// the events channel is full, as the watchers are not idle then
+    fn queue_progress(&self, watch_id: WatchId, revision: i64) {
+        if self
+            .event_tx
+            .try_send(WatchEvent::progress(watch_id, revision))
+            .is_err()
+        {
+            debug!(
+                watch_id,
+                revision, "events channel is full, skip the progress"
+            );
+        }
+    }
+
+    /// Send a progress notification of a watcher, or of all the watchers with
+    /// `WatchId` -1, the revision is lowered below the events still buffered
+    async fn send_progress(&mut self, watch_id: WatchId, revision: i64) {
+        let revision = self
+            .coalesce_buffers
+            .iter()
+            .filter(|&(&id, _)| watch_id == -1 || id == watch_id)
+            .map(|(_, buffer)| buffer.start_revision.overflow_sub(1))
+            .fold(revision, i64::min);
+        if revision <= 0 {
+            return;
+        }
         if self
             .response_tx
             .send(Ok(WatchResponse {
-                header: Some(self.header_gen.gen_header()),
-                watch_id: -1,
+                header: Some(self.header_gen.gen_header_with_revision(revision)),
+                watch_id,
                 ..Default::default()
             }))
             .await
@@ -343,24 +950,16 @@ where
         }
     }
 
+    /// Deadline of the next progress notification
+    fn next_progress_deadline(&self) -> Option<Instant> {
+        self.progress.next_deadline()
+    }
+
     /// Handle progress from tick
     async fn handle_tick_progress(&mut self) {
-        for (watch_id, progress) in &mut self.progress {
-            if *progress {
-                if self
-                    .response_tx
-                    .send(Ok(WatchResponse {
-                        header: Some(self.header_gen.gen_header()),
-                        watch_id: *watch_id,
-                        ..Default::default()
-                    }))
-                    .await
-                    .is_err()
-                {
-                    let _ignore = self.stop_notify.notify(1);
-                }
-            } else {
-                *progress = true;
+        for watch_id in self.progress.take_due() {
+            if let Some(revision) = self.kv_watcher.progress_revision(watch_id) {
+                self.queue_progress(watch_id, revision);
             }
         }
     }
@@ -373,6 +972,7 @@ where
     fn drop(&mut self) {
         for watch_id in &self.active_watch_ids {
             self.kv_watcher.cancel(*watch_id);
+            self.usage.remove_watcher();
         }
     }
 }
@@ -392,6 +992,18 @@ impl Watch for WatchServer {
         request: tonic::Request<tonic::Streaming<WatchRequest>>,
     ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
         debug!("Receive Watch Connection {:?}", request);
+        let create_quota = WatchCreateQuota::new(
+            Arc::clone(&self.create_limiter),
+            Arc::clone(&self.throttled_creations),
+            client_identity(&self.auth_storage, &request).await,
+        );
+        let auth_info = self
+            .auth_storage
+            .try_get_auth_info_from_request(&request)
+            .await
+            .ok()
+            .flatten();
+        let usage = self.accounting.usage(auth_info.as_ref());
         let req_stream = request.into_inner();
         let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
         self.task_manager.spawn(TaskName::WatchTask, |n| {
@@ -402,6 +1014,8 @@ impl Watch for WatchServer {
                 req_stream,
                 Arc::clone(&self.header_gen),
                 self.watch_progress_notify_interval,
+                create_quota,
+                usage,
                 n,
             )
         });
@@ -428,13 +1042,21 @@ mod test {
 
     use super::*;
     use crate::{
-        rpc::{PutRequest, WatchProgressRequest},
+        rpc::{PutRequest, RangeRequest, ResponseWrapper, WatchProgressRequest},
         storage::{
             compact::COMPACT_CHANNEL_SIZE, db::DB, index::Index, kv_store::KvStoreInner,
             kvwatcher::MockKvWatcherOps, lease_store::LeaseCollection, KvStore,
         },
     };
 
+    fn unlimited_quota() -> WatchCreateQuota {
+        WatchCreateQuota::new(
+            Arc::new(ClientRateLimiter::new(0, 0)),
+            Arc::new(ClientRates::new(THROTTLED_CREATION_WINDOW)),
+            "test".to_owned(),
+        )
+    }
+
     fn is_progress_notify(wr: &WatchResponse) -> bool {
         wr.events.is_empty()
             && !wr.canceled
@@ -455,6 +1077,7 @@ mod test {
             value: value.into(),
             ..Default::default()
         });
+        let _guard = store.begin_sync(revision);
         let (_sync_res, ops) = store.after_sync(&req, revision).await.unwrap();
         let key_revisions = db.flush_ops(ops).unwrap();
         store.insert_index(key_revisions);
@@ -485,6 +1108,8 @@ mod test {
             req_stream,
             header_gen,
             default_watch_progress_notify_interval(),
+            unlimited_quota(),
+            Arc::default(),
             n,
         ));
         req_tx
@@ -505,6 +1130,87 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_malformed_watch_ranges_should_be_canceled(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let task_manager = Arc::new(TaskManager::new());
+        let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+        let (res_tx, mut res_rx) = mpsc::channel(CHANNEL_SIZE);
+        let req_stream: ReceiverStream<Result<WatchRequest, tonic::Status>> =
+            ReceiverStream::new(req_rx);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let watched = Arc::new(Mutex::new(vec![]));
+        let watched_c = Arc::clone(&watched);
+        let mut mock_watcher = MockKvWatcherOps::new();
+        let _ = mock_watcher
+            .expect_watch()
+            .returning(move |_, key_range, _, _, _, _| watched_c.lock().push(key_range));
+        let _ = mock_watcher.expect_cancel().return_const(());
+        let _ = mock_watcher
+            .expect_compacted_revision()
+            .return_const(-1_i64);
+        let n = task_manager.get_shutdown_listener(TaskName::WatchTask);
+        let handle = tokio::spawn(WatchServer::task(
+            Arc::new(WatchIdGenerator::new(1)),
+            Arc::new(mock_watcher),
+            res_tx,
+            req_stream,
+            header_gen,
+            default_watch_progress_notify_interval(),
+            unlimited_quota(),
+            Arc::default(),
+            n,
+        ));
+        let create = |key: &[u8], range_end: &[u8]| WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: key.to_vec(),
+                range_end: range_end.to_vec(),
+                ..Default::default()
+            })),
+        };
+
+        for (req, reason) in [
+            (create(b"", b""), "key is not provided"),
+            (create(b"", b"foo"), "range end is given without a key"),
+            (create(b"foo", b"bar"), "range end is smaller than the key"),
+            (
+                create(b"foo", b"foo"),
+                "range end is equal to the key, the range contains no key",
+            ),
+        ] {
+            req_tx.send(Ok(req)).await?;
+            let res = timeout(Duration::from_secs(1), res_rx.recv())
+                .await?
+                .unwrap()?;
+            assert!(res.created && res.canceled);
+            assert_eq!(res.watch_id, INVALID_WATCH_ID);
+            assert_eq!(res.cancel_reason, format!("invalid watch range: {reason}"));
+        }
+        assert!(watched.lock().is_empty());
+
+        for req in [create(b"", &[0]), create(&[0], b""), create(b"foo", b"fop")] {
+            req_tx.send(Ok(req)).await?;
+            let res = timeout(Duration::from_secs(1), res_rx.recv())
+                .await?
+                .unwrap()?;
+            assert!(res.created && !res.canceled);
+        }
+        assert_eq!(
+            *watched.lock(),
+            vec![
+                KeyRange::all(),
+                KeyRange::canonical([0], ""),
+                KeyRange::prefix("foo"),
+            ]
+        );
+
+        drop(req_tx);
+        timeout(Duration::from_secs(3), handle).await??;
+        task_manager.shutdown(true).await;
+        Ok(())
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     #[allow(clippy::similar_names)] // use num as suffix
@@ -540,6 +1246,8 @@ mod test {
                 req_stream1,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
                 n,
             )
         });
@@ -556,6 +1264,8 @@ mod test {
                 req_stream2,
                 header_gen,
                 default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
                 n,
             )
         });
@@ -577,6 +1287,81 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_watch_creation_storm_should_be_throttled(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let task_manager = Arc::new(TaskManager::new());
+        let mut mock_watcher = MockKvWatcherOps::new();
+        let _ = mock_watcher.expect_watch().times(5).return_const(());
+        let _ = mock_watcher.expect_cancel().return_const(());
+        let kv_watcher = Arc::new(mock_watcher);
+        let next_id_gen = Arc::new(WatchIdGenerator::new(1));
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let limiter = Arc::new(ClientRateLimiter::new(1, 3));
+        let throttled_creations = Arc::new(ClientRates::new(THROTTLED_CREATION_WINDOW));
+
+        let mut conns = Vec::new();
+        for client in ["storm", "normal"] {
+            let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+            let (res_tx, res_rx) = mpsc::channel(CHANNEL_SIZE);
+            let req_stream: ReceiverStream<Result<WatchRequest, tonic::Status>> =
+                ReceiverStream::new(req_rx);
+            let quota = WatchCreateQuota::new(
+                Arc::clone(&limiter),
+                Arc::clone(&throttled_creations),
+                client.to_owned(),
+            );
+            task_manager.spawn(TaskName::WatchTask, |n| {
+                WatchServer::task(
+                    Arc::clone(&next_id_gen),
+                    Arc::clone(&kv_watcher),
+                    res_tx,
+                    req_stream,
+                    Arc::clone(&header_gen),
+                    default_watch_progress_notify_interval(),
+                    quota,
+                    Arc::default(),
+                    n,
+                )
+            });
+            conns.push((req_tx, res_rx));
+        }
+
+        let create = WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: vec![0],
+                ..Default::default()
+            })),
+        };
+        for ((req_tx, res_rx), (creations, throttled)) in conns.iter_mut().zip([(10, 7), (2, 0)]) {
+            for _ in 0..creations {
+                req_tx.send(Ok(create.clone())).await?;
+            }
+            let mut rejected = 0;
+            for _ in 0..creations {
+                let res = timeout(Duration::from_secs(1), res_rx.recv())
+                    .await?
+                    .unwrap()?;
+                assert!(res.created);
+                if res.canceled {
+                    assert_eq!(res.watch_id, INVALID_WATCH_ID);
+                    assert!(res
+                        .cancel_reason
+                        .starts_with("too many watch creations, slow down, retry after"));
+                    rejected += 1;
+                }
+            }
+            assert_eq!(rejected, throttled);
+        }
+        // only the storm is reported, as the client throttling the most
+        let top = throttled_creations.top(2, Instant::now() + THROTTLED_CREATION_WINDOW);
+        assert_eq!(top, vec![("storm".to_owned(), 0.7)]);
+
+        task_manager.shutdown(true).await;
+        Ok(())
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn test_watch_prev_kv() {
@@ -600,6 +1385,8 @@ mod test {
             kv_store_inner,
             kv_update_rx,
             Duration::from_millis(10),
+            0,
+            0,
             &task_manager,
         );
         put(&kv_store, &db, "foo", "old_bar", 2).await;
@@ -627,6 +1414,8 @@ mod test {
                 req_stream,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
                 n,
             )
         });
@@ -649,6 +1438,213 @@ mod test {
         task_manager.shutdown(true).await;
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_watch_initial_state_under_concurrent_writes() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (compact_tx, _compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
+        let index = Arc::new(Index::new());
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let next_id_gen = Arc::new(WatchIdGenerator::new(1));
+        let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+        let kv_store_inner = Arc::new(KvStoreInner::new(index, Arc::clone(&db)));
+        let kv_store = Arc::new(KvStore::new(
+            Arc::clone(&kv_store_inner),
+            Arc::clone(&header_gen),
+            kv_update_tx,
+            compact_tx,
+            lease_collection,
+        ));
+        let kv_watcher = KvWatcher::new_arc(
+            kv_store_inner,
+            kv_update_rx,
+            Duration::from_millis(10),
+            0,
+            0,
+            &task_manager,
+        );
+        for revision in 1..=10 {
+            put(&kv_store, &db, format!("key{revision}"), "init", revision).await;
+        }
+
+        let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+        let (res_tx, mut res_rx) = mpsc::channel(CHANNEL_SIZE);
+        task_manager.spawn(TaskName::WatchTask, |n| {
+            WatchServer::task(
+                Arc::clone(&next_id_gen),
+                Arc::clone(&kv_watcher),
+                res_tx,
+                ReceiverStream::new(req_rx),
+                Arc::clone(&header_gen),
+                default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
+                n,
+            )
+        });
+        let writer = tokio::spawn({
+            let kv_store = Arc::clone(&kv_store);
+            let db = Arc::clone(&db);
+            async move {
+                for revision in 11..=60 {
+                    let key = format!("key{}", revision % 13);
+                    put(&kv_store, &db, key, format!("value{revision}"), revision).await;
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+        req_tx
+            .send(Ok(WatchRequest {
+                request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                    watch_id: 1,
+                    key: "key".into(),
+                    range_end: "kez".into(),
+                    send_initial_state: true,
+                    ..Default::default()
+                })),
+            }))
+            .await
+            .unwrap();
+        assert!(res_rx.recv().await.unwrap().unwrap().created);
+
+        let mut state = BTreeMap::new();
+        let snapshot_revision = loop {
+            let res = res_rx.recv().await.unwrap().unwrap();
+            let revision = res.header.unwrap().revision;
+            if res.events.is_empty() {
+                break revision;
+            }
+            for event in res.events {
+                assert_eq!(event.r#type(), EventType::Put);
+                let kv = event.kv.unwrap();
+                assert!(kv.mod_revision <= revision);
+                let _prev = state.insert(kv.key.clone(), kv);
+            }
+        };
+        assert!(snapshot_revision >= 10);
+        let mut last_revision = snapshot_revision;
+        while last_revision < 60 {
+            let res = timeout(Duration::from_secs(1), res_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            for event in res.events {
+                let kv = event.kv.unwrap();
+                assert!(kv.mod_revision > last_revision, "events are in order");
+                last_revision = kv.mod_revision;
+                let _prev = state.insert(kv.key.clone(), kv);
+            }
+        }
+        writer.await.unwrap();
+
+        let range = RequestWrapper::from(RangeRequest {
+            key: "key".into(),
+            range_end: "kez".into(),
+            ..Default::default()
+        });
+        let ResponseWrapper::RangeResponse(range) = kv_store.execute(&range).unwrap().into_inner()
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(state.into_values().collect::<Vec<_>>(), range.kvs);
+        drop(kv_store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_coalesce_watch_events_while_stream_blocked() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (compact_tx, _compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
+        let index = Arc::new(Index::new());
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let header_gen = Arc::new(HeaderGenerator::new(7, 3));
+        header_gen.set_term(5);
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let next_id_gen = Arc::new(WatchIdGenerator::new(1));
+        let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+        let kv_store_inner = Arc::new(KvStoreInner::new(index, Arc::clone(&db)));
+        let kv_store = Arc::new(KvStore::new(
+            Arc::clone(&kv_store_inner),
+            Arc::clone(&header_gen),
+            kv_update_tx,
+            compact_tx,
+            lease_collection,
+        ));
+        let kv_watcher = KvWatcher::new_arc(
+            kv_store_inner,
+            kv_update_rx,
+            Duration::from_millis(10),
+            0,
+            0,
+            &task_manager,
+        );
+
+        let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+        let req_stream = ReceiverStream::new(req_rx);
+        // a response channel of size 1 blocks as soon as the client stops reading
+        let (res_tx, mut res_rx) = mpsc::channel(1);
+        task_manager.spawn(TaskName::WatchTask, |n| {
+            WatchServer::task(
+                Arc::clone(&next_id_gen),
+                Arc::clone(&kv_watcher),
+                res_tx,
+                req_stream,
+                Arc::clone(&header_gen),
+                default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
+                n,
+            )
+        });
+        req_tx
+            .send(Ok(WatchRequest {
+                request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                    watch_id: 1,
+                    key: "foo".into(),
+                    coalesce: true,
+                    ..Default::default()
+                })),
+            }))
+            .await
+            .unwrap();
+        assert!(res_rx.recv().await.unwrap().unwrap().created);
+
+        for revision in 2..102 {
+            put(&kv_store, &db, "foo", format!("bar{revision}"), revision).await;
+        }
+        sleep(Duration::from_millis(500)).await;
+
+        // the first update fills the channel, the rest are coalesced
+        let res = res_rx.recv().await.unwrap().unwrap();
+        assert!(!res.coalesced);
+        assert_eq!(res.events.len(), 1);
+        let res = timeout(Duration::from_secs(1), res_rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(res.coalesced);
+        assert_eq!(res.coalesced_start_revision, 3);
+        assert_eq!(res.coalesced_end_revision, 101);
+        // the flushed response carries a full header, not a default one
+        let header = res.header.unwrap();
+        assert_eq!(header.cluster_id, 7);
+        assert_eq!(header.member_id, 3);
+        assert_eq!(header.raft_term, 5);
+        assert_eq!(header.revision, 101);
+        assert_eq!(res.events.len(), 1);
+        let kv = res.events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.value, b"bar101".as_slice());
+        assert_eq!(kv.mod_revision, 101);
+
+        drop(kv_store);
+        task_manager.shutdown(true).await;
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn test_watch_progress() -> Result<(), Box<dyn std::error::Error>> {
@@ -664,6 +1660,9 @@ mod test {
         let _ = mock_watcher
             .expect_compacted_revision()
             .return_const(-1_i64);
+        let _ = mock_watcher
+            .expect_progress_revision()
+            .return_const(Some(1_i64));
         let watcher = Arc::new(mock_watcher);
         let next_id = Arc::new(WatchIdGenerator::new(1));
         task_manager.spawn(TaskName::WatchTask, |n| {
@@ -674,6 +1673,8 @@ mod test {
                 req_stream,
                 header_gen,
                 Duration::from_millis(100),
+                unlimited_quota(),
+                Arc::default(),
                 n,
             )
         });
@@ -712,6 +1713,76 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_watch_progress_with_per_watcher_interval(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let task_manager = Arc::new(TaskManager::new());
+        let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+        let (res_tx, mut res_rx) = mpsc::channel(CHANNEL_SIZE);
+        let req_stream: ReceiverStream<Result<WatchRequest, tonic::Status>> =
+            ReceiverStream::new(req_rx);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let mut mock_watcher = MockKvWatcherOps::new();
+        let _ = mock_watcher.expect_watch().times(3).return_const(());
+        let _ = mock_watcher.expect_cancel().times(3).return_const(());
+        let _ = mock_watcher
+            .expect_compacted_revision()
+            .return_const(-1_i64);
+        let _ = mock_watcher
+            .expect_progress_revision()
+            .return_const(Some(1_i64));
+        let watcher = Arc::new(mock_watcher);
+        let next_id = Arc::new(WatchIdGenerator::new(1));
+        task_manager.spawn(TaskName::WatchTask, |n| {
+            WatchServer::task(
+                next_id,
+                Arc::clone(&watcher),
+                res_tx,
+                req_stream,
+                header_gen,
+                default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
+                n,
+            )
+        });
+        // watcher 3 asks for an interval below the server minimum
+        for (watch_id, interval_ms) in [(1, 1000), (2, 60_000), (3, 100)] {
+            req_tx
+                .send(Ok(WatchRequest {
+                    request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                        key: "foo".into(),
+                        progress_notify: true,
+                        progress_notify_interval_ms: interval_ms,
+                        watch_id,
+                        ..Default::default()
+                    })),
+                }))
+                .await?;
+        }
+        let counts = Arc::new(Mutex::new(HashMap::<WatchId, usize>::new()));
+
+        let _ignore = timeout(Duration::from_secs(10), {
+            let counts = Arc::clone(&counts);
+            async move {
+                while let Some(Ok(res)) = res_rx.recv().await {
+                    if is_progress_notify(&res) {
+                        *counts.lock().entry(res.watch_id).or_default() += 1;
+                    }
+                }
+            }
+        })
+        .await;
+        let counts = counts.lock();
+        assert!((9..=10).contains(&counts[&1]), "{counts:?}");
+        assert!(!counts.contains_key(&2), "{counts:?}");
+        assert!((9..=10).contains(&counts[&3]), "{counts:?}");
+        drop(req_tx);
+        task_manager.shutdown(true).await;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn watch_task_should_terminate_when_response_tx_closed(
     ) -> Result<(), Box<dyn std::error::Error>> {
@@ -727,6 +1798,9 @@ mod test {
         let _ = mock_watcher
             .expect_compacted_revision()
             .return_const(-1_i64);
+        let _ = mock_watcher
+            .expect_progress_revision()
+            .return_const(Some(1_i64));
         let watcher = Arc::new(mock_watcher);
         let next_id = Arc::new(WatchIdGenerator::new(1));
         let n = task_manager.get_shutdown_listener(TaskName::WatchTask);
@@ -737,6 +1811,8 @@ mod test {
             req_stream,
             header_gen,
             Duration::from_millis(100),
+            unlimited_quota(),
+            Arc::default(),
             n,
         ));
 
@@ -764,6 +1840,74 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn progress_should_be_stamped_with_delivered_revision(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let task_manager = Arc::new(TaskManager::new());
+        let (req_tx, req_rx) = mpsc::channel(CHANNEL_SIZE);
+        let (res_tx, mut res_rx) = mpsc::channel(CHANNEL_SIZE);
+        let req_stream: ReceiverStream<Result<WatchRequest, tonic::Status>> =
+            ReceiverStream::new(req_rx);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let mut mock_watcher = MockKvWatcherOps::new();
+        let _ = mock_watcher.expect_watch().times(2).return_const(());
+        let _ = mock_watcher.expect_cancel().times(2).return_const(());
+        let _ = mock_watcher
+            .expect_compacted_revision()
+            .return_const(-1_i64);
+        let _ = mock_watcher
+            .expect_progress_revision()
+            .returning(|watch_id| Some(watch_id.overflow_add(6)));
+        let watcher = Arc::new(mock_watcher);
+        let next_id = Arc::new(WatchIdGenerator::new(1));
+        task_manager.spawn(TaskName::WatchTask, |n| {
+            WatchServer::task(
+                next_id,
+                Arc::clone(&watcher),
+                res_tx,
+                req_stream,
+                header_gen,
+                default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
+                n,
+            )
+        });
+        for watch_id in [1, 2] {
+            req_tx
+                .send(Ok(WatchRequest {
+                    request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                        key: "foo".into(),
+                        watch_id,
+                        ..Default::default()
+                    })),
+                }))
+                .await?;
+        }
+        req_tx
+            .send(Ok(WatchRequest {
+                request_union: Some(RequestUnion::ProgressRequest(WatchProgressRequest {})),
+            }))
+            .await?;
+
+        let progress = timeout(Duration::from_secs(3), async {
+            while let Some(Ok(res)) = res_rx.recv().await {
+                if is_progress_notify(&res) {
+                    return res;
+                }
+            }
+            unreachable!("the response stream is closed");
+        })
+        .await?;
+        // the least revision delivered to the watchers of the connection
+        assert_eq!(progress.watch_id, -1);
+        assert_eq!(progress.header.unwrap().revision, 7);
+        drop(req_tx);
+        task_manager.shutdown(true).await;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn watch_compacted_revision_should_fail() {
         let task_manager = Arc::new(TaskManager::new());
@@ -786,6 +1930,8 @@ mod test {
             kv_store_inner,
             kv_update_rx,
             Duration::from_millis(10),
+            0,
+            0,
             &task_manager,
         );
         put(&kv_store, &db, "foo", "old_bar", 2).await;
@@ -814,6 +1960,8 @@ mod test {
                 req_stream,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                unlimited_quota(),
+                Arc::default(),
                 n,
             )
         });
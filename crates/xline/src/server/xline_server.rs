@@ -1,7 +1,12 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
-use clippy_utilities::{NumericCast, OverflowArithmetic};
 use curp::{
     client::ClientBuilder as CurpClientBuilder,
     members::{get_cluster_info_from_remote, ClusterInfo},
@@ -22,6 +27,8 @@ use tonic::transport::{
 };
 use tonic::transport::{server::Router, Server};
 use tracing::{info, warn};
+#[cfg(not(madsim))]
+use utils::config::ListenerConfig;
 use utils::{
     barrier::IdBarrier,
     config::{
@@ -35,6 +42,8 @@ use utils::{ClientTlsConfig, ServerTlsConfig};
 use xlineapi::command::{Command, CurpClient};
 
 use super::{
+    accounting::Accounting,
+    admission::AdmissionClient,
     auth_server::AuthServer,
     auth_wrapper::AuthWrapper,
     barriers::IndexBarrier,
@@ -44,13 +53,18 @@ use super::{
     lease_server::LeaseServer,
     lock_server::LockServer,
     maintenance::MaintenanceServer,
+    rate_limit::ClientRateLimiter,
+    read_only::fence_on_corruption,
+    stats_keys::StatsKeys,
     watch_server::{WatchServer, CHANNEL_SIZE},
 };
 use crate::{
+    clock::Clock,
     conflict::{XlineSpeculativePools, XlineUncommittedPools},
     header_gen::HeaderGenerator,
     id_gen::IdGenerator,
-    metrics::Metrics,
+    journal::Journal,
+    metrics::{self, Metrics},
     rpc::{
         AuthServer as RpcAuthServer, ClusterServer as RpcClusterServer, KvServer as RpcKvServer,
         LeaseServer as RpcLeaseServer, LockServer as RpcLockServer,
@@ -64,8 +78,9 @@ use crate::{
         kv_store::KvStoreInner,
         kvwatcher::KvWatcher,
         lease_store::LeaseCollection,
-        AlarmStore, AuthStore, KvStore, LeaseStore,
+        value_transformer, AlarmStore, AuthHook, AuthStore, KvStore, LeaseStore,
     },
+    utils::DataDirLock,
 };
 
 /// Rpc Server of curp protocol
@@ -93,6 +108,10 @@ pub struct XlineServer {
     task_manager: Arc<TaskManager>,
     /// Curp storage
     curp_storage: Arc<CurpDB<Command>>,
+    /// The single time source of the server
+    clock: Arc<Clock>,
+    /// Lock of the data directory, released when the server is dropped
+    _data_dir_lock: Option<DataDirLock>,
 }
 
 impl XlineServer {
@@ -111,7 +130,8 @@ impl XlineServer {
         let (client_tls_config, server_tls_config) = Self::read_tls_config(&tls_config).await?;
         #[cfg(madsim)]
         let (client_tls_config, server_tls_config) = (None, None);
-        let curp_storage = Arc::new(CurpDB::open(&cluster_config.curp_config().engine_cfg)?);
+        let data_dir_lock = Self::lock_data_dir(&storage_config)?;
+        let curp_storage = Arc::new(CurpDB::open(cluster_config.curp_config())?);
         let cluster_info = Arc::new(
             Self::init_cluster_info(
                 &cluster_config,
@@ -130,9 +150,25 @@ impl XlineServer {
             server_tls_config,
             task_manager: Arc::new(TaskManager::new()),
             curp_storage,
+            clock: Arc::new(Clock::system()),
+            _data_dir_lock: data_dir_lock,
         })
     }
 
+    /// Lock the data directory so that another server cannot start on it
+    fn lock_data_dir(storage_config: &StorageConfig) -> Result<Option<DataDirLock>> {
+        // nodes restarted in the simulation may still be holding the lock
+        if cfg!(madsim) {
+            return Ok(None);
+        }
+        match storage_config.engine {
+            EngineConfig::RocksDB(ref path) => DataDirLock::acquire(path).map(Some),
+            EngineConfig::Memory => Ok(None),
+            #[allow(clippy::unimplemented)]
+            _ => unimplemented!(),
+        }
+    }
+
     /// Init cluster info from cluster config
     async fn init_cluster_info(
         cluster_config: &ClusterConfig,
@@ -183,17 +219,22 @@ impl XlineServer {
 
     /// Construct a `LeaseCollection`
     #[inline]
-    #[allow(clippy::arithmetic_side_effects)] // never overflow
     fn construct_lease_collection(
         heartbeat_interval: Duration,
         candidate_timeout_ticks: u8,
+        default_ttl: Duration,
+        promote_extend_multiplier: u32,
+        max_keys_per_lease: usize,
+        max_leases_per_client: usize,
     ) -> Arc<LeaseCollection> {
-        let min_ttl = 3 * heartbeat_interval * candidate_timeout_ticks.numeric_cast() / 2;
-        // Safe ceiling
-        let min_ttl_secs = min_ttl
-            .as_secs()
-            .overflow_add(u64::from(min_ttl.subsec_nanos() > 0));
-        Arc::new(LeaseCollection::new(min_ttl_secs.numeric_cast()))
+        let election_timeout = heartbeat_interval.saturating_mul(candidate_timeout_ticks.into());
+        Arc::new(
+            LeaseCollection::with_election_timeout(election_timeout)
+                .with_promote_extend_multiplier(promote_extend_multiplier)
+                .with_default_ttl(default_ttl)
+                .with_max_keys(max_keys_per_lease)
+                .with_max_leases_per_owner(max_leases_per_client),
+        )
     }
 
     /// Construct underlying storages, including `KvStore`, `LeaseStore`, `AuthStore`
@@ -216,13 +257,22 @@ impl XlineServer {
         let index = Arc::new(Index::new());
         let (kv_update_tx, kv_update_rx) = channel(CHANNEL_SIZE);
         let kv_store_inner = Arc::new(KvStoreInner::new(Arc::clone(&index), Arc::clone(&db)));
-        let kv_storage = Arc::new(KvStore::new(
-            Arc::clone(&kv_store_inner),
-            Arc::clone(&header_gen),
-            kv_update_tx.clone(),
-            compact_task_tx,
-            Arc::clone(&lease_collection),
-        ));
+        let kv_storage = Arc::new(
+            KvStore::new(
+                Arc::clone(&kv_store_inner),
+                Arc::clone(&header_gen),
+                kv_update_tx.clone(),
+                compact_task_tx,
+                Arc::clone(&lease_collection),
+            )
+            .with_exempt_prefixes(
+                self.compact_config
+                    .exempt_prefixes()
+                    .iter()
+                    .map(|prefix| prefix.clone().into_bytes())
+                    .collect(),
+            ),
+        );
         self.task_manager.spawn(TaskName::CompactBg, |n| {
             compact_bg_task(
                 Arc::clone(&kv_storage),
@@ -233,19 +283,39 @@ impl XlineServer {
                 n,
             )
         });
-        let lease_storage = Arc::new(LeaseStore::new(
-            Arc::clone(&lease_collection),
-            Arc::clone(&header_gen),
-            Arc::clone(&db),
-            index,
-            kv_update_tx,
-            *self.cluster_config.is_leader(),
-        ));
+        let server_timeout = self.cluster_config.server_timeout();
+        let lease_storage = Arc::new(
+            LeaseStore::new(
+                Arc::clone(&lease_collection),
+                Arc::clone(&header_gen),
+                Arc::clone(&db),
+                index,
+                kv_update_tx,
+                *self.cluster_config.is_leader(),
+                *server_timeout.lease_checkpoint_persist(),
+            )
+            .with_revoke_chunk_size(
+                *self
+                    .cluster_config
+                    .server_limits()
+                    .lease_revoke_chunk_size(),
+            )
+            .with_cluster_info(Arc::clone(&self.cluster_info))
+            .with_clock(Arc::clone(&self.clock)),
+        );
+        let auth_hook = self
+            .auth_config
+            .auth_hook()
+            .as_ref()
+            .map(AuthHook::new)
+            .transpose()?;
         let auth_storage = Arc::new(AuthStore::new(
             lease_collection,
             key_pair,
             Arc::clone(&header_gen),
             Arc::clone(&db),
+            auth_hook,
+            Arc::clone(&self.clock),
         ));
         let alarm_storage = Arc::new(AlarmStore::new(header_gen, db));
 
@@ -253,11 +323,14 @@ impl XlineServer {
             kv_store_inner,
             kv_update_rx,
             *self.cluster_config.server_timeout().sync_victims_interval(),
+            *self.cluster_config.watch_memory_budget(),
+            *self.cluster_config.watch_audit_sample_percent(),
             &self.task_manager,
         );
         // lease storage must recover before kv storage
         lease_storage.recover()?;
         kv_storage.recover().await?;
+        lease_storage.resume_revokes()?;
         auth_storage.recover()?;
         alarm_storage.recover()?;
         Ok((
@@ -291,6 +364,18 @@ impl XlineServer {
         db: Arc<DB>,
         key_pair: Option<(EncodingKey, DecodingKey)>,
     ) -> Result<(Router, Router, Arc<CurpClient>)> {
+        let (xline_router, curp_router, curp_client, _auth_wrapper) =
+            self.init_routers(db, key_pair).await?;
+        Ok((xline_router, curp_router, curp_client))
+    }
+
+    /// Init xline and curp router, also returns the auth wrapper of the curp server
+    /// the clients are served by
+    async fn init_routers(
+        &self,
+        db: Arc<DB>,
+        key_pair: Option<(EncodingKey, DecodingKey)>,
+    ) -> Result<(Router, Router, Arc<CurpClient>, AuthWrapper)> {
         let (
             kv_server,
             lock_server,
@@ -302,7 +387,7 @@ impl XlineServer {
             curp_server,
             auth_wrapper,
             curp_client,
-        ) = self.init_servers(db, key_pair).await?;
+        ) = self.init_servers(Arc::clone(&db), key_pair).await?;
         let mut builder = Server::builder();
         #[cfg(not(madsim))]
         if let Some(ref cfg) = self.server_tls_config {
@@ -317,7 +402,7 @@ impl XlineServer {
             .add_service(RpcWatchServer::new(watch_server))
             .add_service(RpcMaintenanceServer::new(maintenance_server))
             .add_service(RpcClusterServer::new(cluster_server))
-            .add_service(ProtocolServer::new(auth_wrapper));
+            .add_service(ProtocolServer::new(auth_wrapper.clone()));
         let curp_router = builder
             .add_service(ProtocolServer::new(curp_server.clone()))
             .add_service(InnerProtocolServer::new(curp_server));
@@ -327,9 +412,29 @@ impl XlineServer {
             reporter
                 .set_service_status("", tonic_health::ServingStatus::Serving)
                 .await;
+            self.task_manager.spawn(TaskName::CorruptionReport, |n| {
+                Self::report_corruption(db, reporter, n)
+            });
             xline_router.add_service(health_server)
         };
-        Ok((xline_router, curp_router, curp_client))
+        Ok((xline_router, curp_router, curp_client, auth_wrapper))
+    }
+
+    /// Report the node as not serving once a backend corruption is detected
+    #[cfg(not(madsim))]
+    async fn report_corruption(
+        db: Arc<DB>,
+        mut reporter: tonic_health::server::HealthReporter,
+        shutdown_listener: utils::task_manager::Listener,
+    ) {
+        tokio::select! {
+            _ = shutdown_listener.wait() => {}
+            _ = db.corrupted() => {
+                reporter
+                    .set_service_status("", tonic_health::ServingStatus::NotServing)
+                    .await;
+            }
+        }
     }
 
     /// Start `XlineServer`
@@ -348,7 +453,7 @@ impl XlineServer {
             .task_manager
             .get_shutdown_listener(TaskName::TonicServer);
         let n2 = n1.clone();
-        let db = DB::open(&self.storage_config.engine)?;
+        let db = self.open_db()?;
         let key_pair = Self::read_key_pair(&self.auth_config).await?;
         let (xline_router, curp_router, curp_client) = self.init_router(db, key_pair).await?;
         let handle = tokio::spawn(async move {
@@ -374,7 +479,7 @@ impl XlineServer {
         IO::ConnectInfo: Clone + Send + Sync + 'static,
         IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
     {
-        let db = DB::open(&self.storage_config.engine)?;
+        let db = self.open_db()?;
         let key_pair = Self::read_key_pair(&self.auth_config).await?;
         let (xline_router, curp_router, curp_client) = self.init_router(db, key_pair).await?;
         self.task_manager
@@ -401,10 +506,12 @@ impl XlineServer {
     pub async fn start(&self) -> Result<()> {
         let client_listen_urls = self.cluster_config.client_listen_urls();
         let peer_listen_urls = self.cluster_config.peer_listen_urls();
-        let xline_incoming = bind_addrs(client_listen_urls)?;
-        let curp_incoming = bind_addrs(peer_listen_urls)?;
+        let listener_config = self.cluster_config.listener_config();
+        let xline_incoming = bind_addrs(client_listen_urls, listener_config)?;
+        let curp_incoming = bind_addrs(peer_listen_urls, listener_config)?;
         info!("start xline server on {:?}", client_listen_urls);
         info!("start curp server on {:?}", peer_listen_urls);
+        info!("listener options: {listener_config:?}");
         self.start_inner(xline_incoming, curp_incoming).await
     }
 
@@ -420,11 +527,56 @@ impl XlineServer {
         xline_listener: tokio::net::TcpListener,
         curp_listener: tokio::net::TcpListener,
     ) -> Result<()> {
-        let xline_incoming = tokio_stream::wrappers::TcpListenerStream::new(xline_listener);
-        let curp_incoming = tokio_stream::wrappers::TcpListenerStream::new(curp_listener);
+        // the listeners are bound by the caller, only the options of the accepted
+        // sockets are applied
+        let listener_config = self.cluster_config.listener_config();
+        let xline_incoming = incoming_from_listener(xline_listener, listener_config)?;
+        let curp_incoming = incoming_from_listener(curp_listener, listener_config)?;
+        info!("listener options: {listener_config:?}");
         self.start_inner(xline_incoming, curp_incoming).await
     }
 
+    /// Start a single node `XlineServer` serving the clients only, the curp server is
+    /// not exposed as there's no peer to talk to. Returns a client bypassing the
+    /// network to the local curp server, authenticated like the remote clients.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` when the server is not a single node cluster, or when
+    /// `init_router` returns an error
+    #[cfg(all(feature = "embedded", not(madsim)))]
+    pub(crate) async fn start_local<I, IO, IE>(&self, xline_incoming: I) -> Result<Arc<CurpClient>>
+    where
+        I: Stream<Item = Result<IO, IE>> + Send + 'static,
+        IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+        IO::ConnectInfo: Clone + Send + Sync + 'static,
+        IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+    {
+        if self.cluster_info.voters_len() != 1 {
+            return Err(anyhow!("a local server must be a single node cluster"));
+        }
+        let db = self.open_db()?;
+        let key_pair = Self::read_key_pair(&self.auth_config).await?;
+        let (xline_router, _curp_router, curp_client, auth_wrapper) =
+            self.init_routers(db, key_pair).await?;
+        self.task_manager
+            .spawn(TaskName::TonicServer, |n| async move {
+                let _ignore = xline_router
+                    .serve_with_incoming_shutdown(xline_incoming, n.wait())
+                    .await;
+            });
+        if let Err(e) = self.publish(curp_client).await {
+            warn!("publish name to cluster failed: {e:?}");
+        };
+        let local_client = CurpClientBuilder::new(*self.cluster_config.client_config(), false)
+            .cluster_version(self.cluster_info.cluster_version())
+            .all_members(self.cluster_info.all_members_peer_urls())
+            .bypass(self.cluster_info.self_id(), auth_wrapper)
+            .build::<Command>()
+            .await?;
+        Ok(Arc::new(local_client))
+    }
+
     /// Init `KvServer`, `LockServer`, `LeaseServer`, `WatchServer` and `CurpServer`
     /// for the Xline Server.
     #[allow(
@@ -453,12 +605,19 @@ impl XlineServer {
         let lease_collection = Self::construct_lease_collection(
             self.cluster_config.curp_config().heartbeat_interval,
             self.cluster_config.curp_config().candidate_timeout_ticks,
+            *self.cluster_config.server_timeout().lease_default_ttl(),
+            *self
+                .cluster_config
+                .server_timeout()
+                .lease_promote_extend_multiplier(),
+            *self.cluster_config.server_limits().max_keys_per_lease(),
+            *self.cluster_config.server_limits().max_leases_per_client(),
         );
 
         let (kv_storage, lease_storage, auth_storage, alarm_storage, watcher) = self
             .construct_underlying_storages(
                 Arc::clone(&db),
-                lease_collection,
+                Arc::clone(&lease_collection),
                 Arc::clone(&header_gen),
                 key_pair,
             )
@@ -467,6 +626,18 @@ impl XlineServer {
         let index_barrier = Arc::new(IndexBarrier::new());
         let id_barrier = Arc::new(IdBarrier::new());
         let compact_events = Arc::new(DashMap::new());
+        let mut data_dirs = vec![];
+        if let EngineConfig::RocksDB(ref path) = self.storage_config.engine {
+            data_dirs.push(path.as_path());
+        }
+        if let EngineConfig::RocksDB(ref path) = self.cluster_config.curp_config().engine_cfg {
+            data_dirs.push(path.as_path());
+        }
+        let journal = Journal::open(
+            &self.storage_config.journal,
+            &data_dirs,
+            Arc::clone(&self.clock),
+        )?;
         let ce = Arc::new(CommandExecutor::new(
             Arc::clone(&kv_storage),
             Arc::clone(&auth_storage),
@@ -479,10 +650,17 @@ impl XlineServer {
             header_gen.auth_revision_arc(),
             Arc::clone(&compact_events),
             self.storage_config.quota,
-        ));
+            Arc::clone(&self.clock),
+            journal,
+        )?);
         let snapshot_allocator: Box<dyn SnapshotAllocator> = match self.storage_config.engine {
             EngineConfig::Memory => Box::<MemorySnapshotAllocator>::default(),
-            EngineConfig::RocksDB(_) => Box::<RocksSnapshotAllocator>::default(),
+            // staged by the data, on the same filesystem the snapshots are ingested into
+            EngineConfig::RocksDB(ref path) => {
+                let mut staging_dir = path.clone().into_os_string();
+                staging_dir.push(".snapshots");
+                Box::new(RocksSnapshotAllocator::new(staging_dir))
+            }
             #[allow(clippy::unimplemented)]
             _ => unimplemented!(),
         };
@@ -504,9 +682,17 @@ impl XlineServer {
 
         let auto_compactor_c = auto_compactor.clone();
 
-        let state = State::new(Arc::clone(&lease_storage), auto_compactor);
+        let state = State::new(
+            Arc::clone(&lease_storage),
+            auto_compactor,
+            Arc::clone(&header_gen),
+        );
 
-        let curp_config = Arc::new(self.cluster_config.curp_config().clone());
+        let read_only = *self.cluster_config.read_only();
+        let mut curp_config = self.cluster_config.curp_config().clone();
+        curp_config.no_campaign |= read_only;
+        let read_only = Arc::new(AtomicBool::new(read_only));
+        let curp_config = Arc::new(curp_config);
 
         let curp_server = CurpServer::new(
             Arc::clone(&self.cluster_info),
@@ -536,15 +722,40 @@ impl XlineServer {
         if let Some(compactor) = auto_compactor_c {
             compactor.set_compactable(Arc::clone(&client)).await;
         }
-        ce.set_alarmer(Alarmer::new(
-            self.cluster_info.self_id(),
-            Arc::clone(&client),
-        ));
+        let alarmer = Alarmer::new(self.cluster_info.self_id(), Arc::clone(&client));
+        ce.set_alarmer(alarmer.clone());
         let raw_curp = curp_server.raw_curp();
+        self.task_manager.spawn(TaskName::CorruptionGuard, |n| {
+            fence_on_corruption(
+                Arc::clone(&db),
+                Arc::clone(&read_only),
+                Arc::clone(&raw_curp),
+                alarmer,
+                n,
+            )
+        });
+        if read_only.load(Ordering::Relaxed) {
+            info!("xline server is running in read-only mode");
+        }
+        let rpc_client = Arc::new(AdmissionClient::new(
+            Arc::clone(&client),
+            Arc::clone(&read_only),
+            *self.cluster_config.max_inflight_proposals(),
+        )) as Arc<CurpClient>;
 
         Metrics::register_callback()?;
+        let stats_keys = StatsKeys::new(
+            Arc::clone(&raw_curp),
+            Arc::clone(&kv_storage),
+            Arc::clone(&lease_collection),
+            Arc::clone(&watcher),
+            Arc::clone(&db),
+        );
+        let accounting = Arc::new(Accounting::new(Arc::clone(&auth_storage), lease_collection));
+        metrics::register_usage_accounting(&accounting);
 
         let server_timeout = self.cluster_config.server_timeout();
+        let server_limits = self.cluster_config.server_limits();
         Ok((
             KvServer::new(
                 Arc::clone(&kv_storage),
@@ -553,44 +764,63 @@ impl XlineServer {
                 id_barrier,
                 *server_timeout.range_retry_timeout(),
                 *server_timeout.compact_timeout(),
-                Arc::clone(&client),
+                Arc::clone(&rpc_client),
                 compact_events,
+                *self.cluster_config.max_keys_per_request(),
+                Arc::clone(&accounting),
+                stats_keys,
             ),
             LockServer::new(
-                Arc::clone(&client),
+                Arc::clone(&rpc_client),
                 Arc::clone(&auth_storage),
                 Arc::clone(&id_gen),
-                &self.cluster_info.self_peer_urls(),
-                self.client_tls_config.as_ref(),
+                Arc::clone(&watcher),
             ),
             LeaseServer::new(
                 lease_storage,
                 Arc::clone(&auth_storage),
-                Arc::clone(&client),
+                Arc::clone(&rpc_client),
                 id_gen,
                 Arc::clone(&self.cluster_info),
                 self.client_tls_config.clone(),
+                *server_timeout.lease_checkpoint_interval(),
+                *server_timeout.lease_expiry_persist_interval(),
+                *server_limits.lease_revoke_batch_size(),
+                Arc::clone(&read_only),
+                ClientRateLimiter::new(
+                    *server_limits.lease_grant_rate(),
+                    *server_limits.lease_grant_burst(),
+                ),
+                Arc::clone(&accounting),
                 &self.task_manager,
             ),
-            AuthServer::new(Arc::clone(&client), Arc::clone(&auth_storage)),
+            AuthServer::new(Arc::clone(&rpc_client), Arc::clone(&auth_storage)),
             WatchServer::new(
                 watcher,
                 Arc::clone(&header_gen),
                 *server_timeout.watch_progress_notify_interval(),
+                ClientRateLimiter::new(
+                    *server_limits.watch_create_rate(),
+                    *server_limits.watch_create_burst(),
+                ),
+                Arc::clone(&auth_storage),
+                Arc::clone(&accounting),
                 Arc::clone(&self.task_manager),
             ),
             MaintenanceServer::new(
                 kv_storage,
                 Arc::clone(&auth_storage),
-                Arc::clone(&client),
+                Arc::clone(&rpc_client),
                 db,
                 Arc::clone(&header_gen),
                 Arc::clone(&self.cluster_info),
                 raw_curp,
                 ce,
                 alarm_storage,
+                read_only,
+                accounting,
             ),
-            ClusterServer::new(Arc::clone(&client), header_gen),
+            ClusterServer::new(rpc_client, header_gen),
             curp_server.clone(),
             AuthWrapper::new(curp_server, auth_storage),
             client,
@@ -614,6 +844,15 @@ impl XlineServer {
         self.task_manager.shutdown(true).await;
     }
 
+    /// Open the storage, with the kv values encrypted if it's configured
+    fn open_db(&self) -> Result<Arc<DB>> {
+        let transformer = value_transformer::from_config(&self.storage_config.encryption)?;
+        Ok(DB::open_with_transformer(
+            &self.storage_config.engine,
+            transformer,
+        )?)
+    }
+
     /// Read key pair from file
     async fn read_key_pair(auth_config: &AuthConfig) -> Result<Option<(EncodingKey, DecodingKey)>> {
         match (
@@ -692,16 +931,20 @@ impl XlineServer {
     }
 }
 
-/// Bind multiple addresses
+/// Bind multiple addresses, each with the configured number of acceptors
 #[cfg(not(madsim))]
 fn bind_addrs(
     addrs: &[String],
+    config: &ListenerConfig,
 ) -> Result<impl Stream<Item = Result<hyper::server::conn::AddrStream, std::io::Error>>> {
     use std::net::ToSocketAddrs;
     if addrs.is_empty() {
         return Err(anyhow!("No address to bind"));
     }
-    let incoming = addrs
+    if *config.acceptors() > 1 && !*config.reuse_port() {
+        return Err(anyhow!("more than one acceptor requires reuse_port"));
+    }
+    let addrs = addrs
         .iter()
         .map(|addr| {
             let address = match addr.split_once("://") {
@@ -710,13 +953,72 @@ fn bind_addrs(
             };
             address.to_socket_addrs()
         })
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten()
-        .map(|addr| {
-            tonic::transport::server::TcpIncoming::new(addr, true, None)
-                .map_err(|e| anyhow::anyhow!("Failed to bind to {}, err: {e}", addr))
-        })
-        .collect::<Result<Vec<_>>>()?;
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut incoming = vec![];
+    for addr in addrs.into_iter().flatten() {
+        let first = bind_listener(addr, config)
+            .map_err(|e| anyhow!("Failed to bind to {addr}, err: {e}"))?;
+        // the other acceptors share the port the first one is bound to
+        let bound = first.local_addr()?;
+        let mut listeners = vec![first];
+        for _ in 1..*config.acceptors() {
+            listeners.push(
+                bind_listener(bound, config)
+                    .map_err(|e| anyhow!("Failed to bind to {bound}, err: {e}"))?,
+            );
+        }
+        for listener in listeners {
+            incoming.push(incoming_from_listener(listener, config)?);
+        }
+    }
     Ok(futures::stream::select_all(incoming))
 }
+
+/// Bind a listener to the address with the socket options of the listeners
+#[cfg(not(madsim))]
+fn bind_listener(
+    addr: std::net::SocketAddr,
+    config: &ListenerConfig,
+) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = if addr.is_ipv4() {
+        tokio::net::TcpSocket::new_v4()?
+    } else {
+        tokio::net::TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(*config.reuse_port())?;
+    socket.bind(addr)?;
+    socket.listen(*config.backlog())
+}
+
+/// Accept the connections of the listener with the socket options of the accepted
+/// sockets
+#[cfg(not(madsim))]
+fn incoming_from_listener(
+    listener: tokio::net::TcpListener,
+    config: &ListenerConfig,
+) -> Result<tonic::transport::server::TcpIncoming> {
+    let keepalive = (!config.tcp_keepalive().is_zero()).then_some(*config.tcp_keepalive());
+    tonic::transport::server::TcpIncoming::from_listener(listener, *config.tcp_nodelay(), keepalive)
+        .map_err(|e| anyhow!("Failed to accept from the listener, err: {e}"))
+}
+
+#[cfg(all(test, not(madsim), unix))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn acceptors_should_share_the_address_with_reuse_port() {
+        let reuse = ListenerConfig::new(true, 4, 128, true, Duration::ZERO);
+        let first = bind_listener("127.0.0.1:0".parse().unwrap(), &reuse).unwrap();
+        let addr = first.local_addr().unwrap();
+        let _second = bind_listener(addr, &reuse).unwrap();
+
+        let exclusive = ListenerConfig::default();
+        assert!(bind_listener(addr, &exclusive).is_err());
+        let too_many = ListenerConfig::new(false, 2, 128, true, Duration::ZERO);
+        assert!(bind_addrs(&["127.0.0.1:0".to_owned()], &too_many).is_err());
+        assert!(bind_addrs(&["127.0.0.1:0".to_owned()], &reuse).is_ok());
+    }
+}
@@ -1,10 +1,13 @@
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use curp::role_change::RoleChange;
 
-use crate::storage::{
-    compact::{Compactable, Compactor},
-    LeaseStore,
+use crate::{
+    header_gen::HeaderGenerator,
+    storage::{
+        compact::{Compactable, Compactor},
+        LeaseStore,
+    },
 };
 
 /// State of current node
@@ -13,6 +16,8 @@ pub(crate) struct State<C: Compactable> {
     lease_storage: Arc<LeaseStore>,
     /// auto compactor
     auto_compactor: Option<Arc<dyn Compactor<C>>>,
+    /// Header generator, which carries the current term
+    header_gen: Arc<HeaderGenerator>,
 }
 
 impl<C: Compactable> Clone for State<C> {
@@ -20,13 +25,14 @@ impl<C: Compactable> Clone for State<C> {
         Self {
             lease_storage: Arc::clone(&self.lease_storage),
             auto_compactor: self.auto_compactor.clone(),
+            header_gen: Arc::clone(&self.header_gen),
         }
     }
 }
 
 impl<C: Compactable> RoleChange for State<C> {
     fn on_election_win(&self) {
-        self.lease_storage.promote(Duration::from_secs(1)); // TODO: extend should be election timeout
+        self.lease_storage.promote();
         if let Some(auto_compactor) = self.auto_compactor.as_ref() {
             auto_compactor.resume();
         }
@@ -38,6 +44,10 @@ impl<C: Compactable> RoleChange for State<C> {
             auto_compactor.pause();
         }
     }
+
+    fn on_term_change(&self, term: u64) {
+        self.header_gen.set_term(term);
+    }
 }
 
 impl<C: Compactable> State<C> {
@@ -45,10 +55,12 @@ impl<C: Compactable> State<C> {
     pub(super) fn new(
         lease_storage: Arc<LeaseStore>,
         auto_compactor: Option<Arc<dyn Compactor<C>>>,
+        header_gen: Arc<HeaderGenerator>,
     ) -> Self {
         Self {
             lease_storage,
             auto_compactor,
+            header_gen,
         }
     }
 }
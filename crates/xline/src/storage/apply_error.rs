@@ -0,0 +1,60 @@
+use std::fmt;
+
+use xlineapi::execute_error::ExecuteError;
+
+/// Error of applying a command to a store
+#[derive(Debug)]
+pub(crate) enum ApplyError {
+    /// The command is rejected by the store, the error is answered to its client
+    Execute(ExecuteError),
+    /// The storage failed, the state machine may no longer match the log
+    Storage(String),
+}
+
+impl From<ExecuteError> for ApplyError {
+    fn from(err: ExecuteError) -> Self {
+        #[allow(clippy::wildcard_enum_match_arm)]
+        match err {
+            // a failure of the storage is never caused by the command
+            ExecuteError::DbError(reason) => Self::Storage(reason),
+            _ => Self::Execute(err),
+        }
+    }
+}
+
+impl From<ApplyError> for ExecuteError {
+    fn from(err: ApplyError) -> Self {
+        match err {
+            ApplyError::Execute(e) => e,
+            ApplyError::Storage(reason) => ExecuteError::DbError(reason),
+        }
+    }
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Execute(ref err) => write!(f, "{err}"),
+            Self::Storage(ref reason) => write!(f, "storage error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_db_errors_are_storage_errors() {
+        assert!(matches!(
+            ApplyError::from(ExecuteError::DbError("io".to_owned())),
+            ApplyError::Storage(reason) if reason == "io"
+        ));
+        assert!(matches!(
+            ApplyError::from(ExecuteError::LeaseNotFound(1)),
+            ApplyError::Execute(ExecuteError::LeaseNotFound(1))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use super::{
+    db::{KeyRevisionPair, WriteOp, DB},
+    ApplyError,
+};
+
+/// Stops flushing the writes of the applies once one of them failed to be persisted
+///
+/// Every write of an apply, including the tombstones of revoked leases written in the
+/// background, is flushed under the lock the failure is recorded with, so that nothing
+/// is flushed after a failure, however many entries are applied in parallel.
+#[derive(Debug)]
+pub(crate) struct ApplyFence {
+    /// The backend the writes are flushed to
+    db: Arc<DB>,
+    /// The reason of the first failed write
+    failure: Mutex<Option<String>>,
+}
+
+impl ApplyFence {
+    /// New `ApplyFence`
+    pub(crate) fn new(db: Arc<DB>) -> Self {
+        Self {
+            db,
+            failure: Mutex::new(None),
+        }
+    }
+
+    /// Flush the writes of `what` unless a write failed before, `before_flush` is called
+    /// with the writes under the lock, so that what it does is ordered as the flushes
+    pub(crate) fn flush<'a>(
+        &self,
+        what: &str,
+        mut ops: Vec<WriteOp<'a>>,
+        before_flush: impl FnOnce(&mut Vec<WriteOp<'a>>),
+    ) -> Result<Vec<KeyRevisionPair>, ApplyError> {
+        let mut failure = self.failure.lock();
+        if let Some(ref reason) = *failure {
+            return Err(ApplyError::Storage(format!(
+                "{what} is not applied after the storage failed: {reason}"
+            )));
+        }
+        before_flush(&mut ops);
+        self.db.flush_ops(ops).map_err(|e| {
+            let err = ApplyError::from(e);
+            if let ApplyError::Storage(ref reason) = err {
+                *failure = Some(format!("failed to apply {what}: {reason}"));
+            }
+            err
+        })
+    }
+
+    /// Poison the node after a write failed: nothing is flushed after it, and the
+    /// storage is marked as corrupted, which fences the node and raises a `Corrupt` alarm
+    pub(crate) fn fail(&self, msg: String) {
+        let _ignore = self.failure.lock().get_or_insert_with(|| msg.clone());
+        self.db.mark_corrupted(msg);
+    }
+
+    /// The reason of the first failed write, if any
+    pub(crate) fn failure(&self) -> Option<String> {
+        self.failure.lock().clone()
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utils::config::AuthHookConfig;
+use xlineapi::execute_error::ExecuteError;
+
+/// The max number of cached tokens, the expired ones are purged first and then the
+/// ones closest to expiry are evicted
+const MAX_CACHED_TOKENS: usize = 10_000;
+
+/// Body posted to the auth hook
+#[derive(Debug, Serialize)]
+struct HookRequest<'a> {
+    /// The token to validate
+    token: &'a str,
+}
+
+/// Identity returned by the auth hook for a valid token
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(super) struct HookIdentity {
+    /// The identity of the token
+    pub(super) username: String,
+    /// The roles of the identity, mapped onto the locally defined roles
+    #[serde(default)]
+    pub(super) roles: Vec<String>,
+}
+
+/// External webhook validating tokens instead of the local users
+///
+/// The token is posted as `{"token": "..."}`, the hook answers `200` with
+/// `{"username": "...", "roles": [...]}` if the token is valid, and `401` or `403`
+/// if it's not. Any other answer, or no answer within the timeout, is a failure of
+/// the hook.
+#[derive(Debug)]
+pub(crate) struct AuthHook {
+    /// The url tokens are posted to
+    url: String,
+    /// Http client, bounded by the hook timeout
+    client: reqwest::Client,
+    /// How long an identity is cached
+    cache_ttl: Duration,
+    /// Fall back to local validation when the hook fails
+    fail_open: bool,
+    /// Identities of the validated tokens, with the instants they expire at
+    cache: Mutex<HashMap<String, (HookIdentity, Instant)>>,
+}
+
+impl AuthHook {
+    /// New `AuthHook`
+    pub(crate) fn new(config: &AuthHookConfig) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(*config.timeout())
+            .build()?;
+        Ok(Self {
+            url: config.url().clone(),
+            client,
+            cache_ttl: *config.cache_ttl(),
+            fail_open: *config.fail_open(),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Validate a token, `None` means the hook failed open and the token should be
+    /// validated locally
+    pub(super) async fn validate(
+        &self,
+        token: &str,
+    ) -> Result<Option<HookIdentity>, tonic::Status> {
+        let cached = self
+            .cache
+            .lock()
+            .get(token)
+            .filter(|&&(_, expire_at)| expire_at > Instant::now())
+            .map(|(identity, _)| identity.clone());
+        if cached.is_some() {
+            return Ok(cached);
+        }
+        match self.call(token).await {
+            Ok(Some(identity)) => {
+                self.insert(token, identity.clone());
+                Ok(Some(identity))
+            }
+            Ok(None) => Err(ExecuteError::InvalidAuthToken.into()),
+            Err(err) if self.fail_open => {
+                warn!("auth hook failed, validating the token locally: {err}");
+                Ok(None)
+            }
+            Err(err) => {
+                warn!("auth hook failed, rejecting the request: {err}");
+                Err(tonic::Status::unavailable(format!(
+                    "auth hook unavailable: {err}"
+                )))
+            }
+        }
+    }
+
+    /// Post the token to the hook, `None` if the hook rejects the token
+    async fn call(&self, token: &str) -> Result<Option<HookIdentity>, reqwest::Error> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(&HookRequest { token })
+            .send()
+            .await?;
+        let status = resp.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Ok(None);
+        }
+        resp.error_for_status()?.json().await.map(Some)
+    }
+
+    /// Cache the identity of a token
+    fn insert(&self, token: &str, identity: HookIdentity) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock();
+        if cache.len() >= MAX_CACHED_TOKENS && !cache.contains_key(token) {
+            cache.retain(|_, &mut (_, expire_at)| expire_at > now);
+        }
+        while cache.len() >= MAX_CACHED_TOKENS && !cache.contains_key(token) {
+            let Some(oldest) = cache
+                .iter()
+                .min_by_key(|&(_, &(_, expire_at))| expire_at)
+                .map(|(t, _)| t.clone())
+            else {
+                break;
+            };
+            let _ignore = cache.remove(&oldest);
+        }
+        let expire_at = now.checked_add(self.cache_ttl).unwrap_or(now);
+        let _ignore = cache.insert(token.to_owned(), (identity, expire_at));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// How the mock hook answers
+    #[derive(Clone, Copy)]
+    enum Mode {
+        /// Answer valid tokens
+        Ok,
+        /// Answer an internal error
+        Fail,
+        /// Answer after a delay longer than the timeout
+        Slow,
+    }
+
+    /// A mock auth hook accepting `valid` only, returns its url and the counter of
+    /// the calls it received
+    fn mock_hook(mode: Mode) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/auth", post(handle))
+            .with_state((mode, Arc::clone(&calls)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        let _handle = tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, calls)
+    }
+
+    async fn handle(
+        State((mode, calls)): State<(Mode, Arc<AtomicUsize>)>,
+        Json(req): Json<Value>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let _prev = calls.fetch_add(1, Ordering::Relaxed);
+        match mode {
+            Mode::Ok => {}
+            Mode::Fail => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            Mode::Slow => tokio::time::sleep(Duration::from_secs(5)).await,
+        }
+        if req["token"] != "valid" {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!({"username": "alice", "roles": ["r1", "r2"]})))
+    }
+
+    fn hook(url: String, cache_ttl: Duration, fail_open: bool) -> AuthHook {
+        let config = AuthHookConfig::new(url, cache_ttl, Duration::from_millis(200), fail_open);
+        AuthHook::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_token_should_be_cached_until_expired() {
+        let (url, calls) = mock_hook(Mode::Ok);
+        let hook = hook(url, Duration::from_millis(500), false);
+        let expected = HookIdentity {
+            username: "alice".to_owned(),
+            roles: vec!["r1".to_owned(), "r2".to_owned()],
+        };
+        for _ in 0..3 {
+            assert_eq!(
+                hook.validate("valid").await.unwrap(),
+                Some(expected.clone())
+            );
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(hook.validate("valid").await.unwrap(), Some(expected));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_token_should_not_be_cached() {
+        let (url, calls) = mock_hook(Mode::Ok);
+        let hook = hook(url, Duration::from_secs(60), true);
+        for _ in 0..2 {
+            let err = hook.validate("invalid").await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_hook_should_fail_closed_or_open() {
+        let (url, _calls) = mock_hook(Mode::Fail);
+        let closed = hook(url.clone(), Duration::from_secs(60), false);
+        let err = closed.validate("valid").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+
+        let open = hook(url, Duration::from_secs(60), true);
+        assert_eq!(open.validate("valid").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn cache_should_be_bounded() {
+        let (url, _calls) = mock_hook(Mode::Ok);
+        let hook = hook(url, Duration::from_secs(60), false);
+        let identity = HookIdentity {
+            username: "alice".to_owned(),
+            roles: vec![],
+        };
+        for i in 0..=MAX_CACHED_TOKENS {
+            hook.insert(&format!("token{i}"), identity.clone());
+        }
+        let cache = hook.cache.lock();
+        assert_eq!(cache.len(), MAX_CACHED_TOKENS);
+        assert!(cache.contains_key(&format!("token{MAX_CACHED_TOKENS}")));
+    }
+
+    #[tokio::test]
+    async fn slow_hook_should_be_bounded_by_timeout() {
+        let (url, _calls) = mock_hook(Mode::Slow);
+        let hook = hook(url, Duration::from_secs(60), false);
+        let start = Instant::now();
+        let err = hook.validate("valid").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,9 +1,12 @@
 /// Storage backend for auth
 mod backend;
+/// External webhook validating tokens
+mod hook;
 /// Structs for permission
 mod perms;
 /// Storage for auth
 mod store;
 
 pub(crate) use backend::{AUTH_ENABLE_KEY, AUTH_REVISION_KEY};
+pub(crate) use hook::AuthHook;
 pub(crate) use store::AuthStore;
@@ -1,14 +1,17 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
 use jsonwebtoken::{
-    errors::Error as JwtError, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    errors::{Error as JwtError, ErrorKind},
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
 };
 use merged_range::MergedRange;
 use serde::{Deserialize, Serialize};
-use utils::timestamp;
 use xlineapi::{command::KeyRange, AuthInfo};
 
-use crate::rpc::{Permission, Type};
+use crate::{
+    clock::Clock,
+    rpc::{Permission, Type},
+};
 
 /// default token ttl
 const DEFAULT_TOKEN_TTL: u64 = 300;
@@ -30,6 +33,8 @@ impl From<TokenClaims> for AuthInfo {
         Self {
             username: value.username,
             auth_revision: value.revision,
+            external: false,
+            roles: vec![],
         }
     }
 }
@@ -55,6 +60,8 @@ pub(super) struct JwtTokenManager {
     encoding_key: EncodingKey,
     /// The key used to verify the token.
     decoding_key: DecodingKey,
+    /// The clock the expiration of tokens is stamped and checked with
+    clock: Arc<Clock>,
 }
 
 impl Debug for JwtTokenManager {
@@ -62,16 +69,22 @@ impl Debug for JwtTokenManager {
         f.debug_struct("JwtTokenManager")
             .field("encoding_key", &"EncodingKey")
             .field("decoding_key", &"DecodingKey")
+            .field("clock", &self.clock)
             .finish()
     }
 }
 
 impl JwtTokenManager {
     /// New `JwtTokenManager`
-    pub(crate) fn new(encoding_key: EncodingKey, decoding_key: DecodingKey) -> Self {
+    pub(crate) fn new(
+        encoding_key: EncodingKey,
+        decoding_key: DecodingKey,
+        clock: Arc<Clock>,
+    ) -> Self {
         Self {
             encoding_key,
             decoding_key,
+            clock,
         }
     }
 }
@@ -82,7 +95,7 @@ impl TokenOperate for JwtTokenManager {
     type Claims = TokenClaims;
 
     fn assign(&self, username: &str, revision: i64) -> Result<String, Self::Error> {
-        let now = timestamp();
+        let now = self.clock.unix_secs();
         let claims = TokenClaims {
             username: username.to_owned(),
             revision,
@@ -94,12 +107,15 @@ impl TokenOperate for JwtTokenManager {
     }
 
     fn verify(&self, token: &str) -> Result<Self::Claims, Self::Error> {
-        jsonwebtoken::decode::<TokenClaims>(
-            token,
-            &self.decoding_key,
-            &Validation::new(Algorithm::RS256),
-        )
-        .map(|d| d.claims)
+        // the expiration is checked with the clock of the server instead of the system one
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.validate_exp = false;
+        let claims =
+            jsonwebtoken::decode::<TokenClaims>(token, &self.decoding_key, &validation)?.claims;
+        if claims.exp.saturating_add(validation.leeway) < self.clock.unix_secs() {
+            return Err(ErrorKind::ExpiredSignature.into());
+        }
+        Ok(claims)
     }
 }
 
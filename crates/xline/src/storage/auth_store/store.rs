@@ -1,6 +1,7 @@
 use std::{
     cmp::Ordering,
     collections::{HashMap, VecDeque},
+    future::Future,
     sync::{
         atomic::{AtomicBool, Ordering as AtomicOrdering},
         Arc,
@@ -25,9 +26,11 @@ use xlineapi::{
 
 use super::{
     backend::{ROOT_ROLE, ROOT_USER},
+    hook::AuthHook,
     perms::{JwtTokenManager, PermissionCache, TokenOperate, UserPermissions},
 };
 use crate::{
+    clock::Clock,
     header_gen::HeaderGenerator,
     revision_number::RevisionNumberGenerator,
     rpc::{
@@ -49,6 +52,7 @@ use crate::{
         auth_store::backend::AuthStoreBackend,
         db::{WriteOp, DB},
         lease_store::{Lease, LeaseCollection},
+        ApplyError,
     },
 };
 
@@ -69,6 +73,8 @@ pub(crate) struct AuthStore {
     permission_cache: RwLock<PermissionCache>,
     /// The manager of token
     token_manager: Option<JwtTokenManager>,
+    /// The external webhook validating tokens instead of the token manager
+    auth_hook: Option<AuthHook>,
 }
 
 impl AuthStore {
@@ -79,6 +85,8 @@ impl AuthStore {
         key_pair: Option<(EncodingKey, DecodingKey)>,
         header_gen: Arc<HeaderGenerator>,
         storage: Arc<DB>,
+        auth_hook: Option<AuthHook>,
+        clock: Arc<Clock>,
     ) -> Self {
         let backend = Arc::new(AuthStoreBackend::new(storage));
         Self {
@@ -89,8 +97,9 @@ impl AuthStore {
             header_gen,
             permission_cache: RwLock::new(PermissionCache::new()),
             token_manager: key_pair.map(|(encoding_key, decoding_key)| {
-                JwtTokenManager::new(encoding_key, decoding_key)
+                JwtTokenManager::new(encoding_key, decoding_key, clock)
             }),
+            auth_hook,
         }
     }
 
@@ -126,25 +135,52 @@ impl AuthStore {
     }
 
     /// Try get auth info from tonic request
+    ///
+    /// The credentials are taken from the request before the returned future is
+    /// polled, so that it doesn't borrow the request.
     pub(crate) fn try_get_auth_info_from_request<T>(
         &self,
         request: &tonic::Request<T>,
-    ) -> Result<Option<AuthInfo>, tonic::Status> {
-        if !self.is_enabled() {
-            return Ok(None);
-        }
-        if let Some(token) = get_token(request.metadata()) {
-            let auth_info = self.verify(&token)?;
-            return Ok(Some(auth_info));
-        }
-        if let Some(cn) = get_cn(request) {
-            let auth_info = AuthInfo {
-                username: cn,
-                auth_revision: self.revision(),
+    ) -> impl Future<Output = Result<Option<AuthInfo>, tonic::Status>> + '_ {
+        let credentials = self
+            .is_enabled()
+            .then(|| (get_token(request.metadata()), get_cn(request)));
+        async move {
+            let Some((token, cn)) = credentials else {
+                return Ok(None);
             };
-            return Ok(Some(auth_info));
+            if let Some(token) = token {
+                let auth_info = self.verify_with_hook(&token).await?;
+                return Ok(Some(auth_info));
+            }
+            if let Some(cn) = cn {
+                let auth_info = AuthInfo {
+                    username: cn,
+                    auth_revision: self.revision(),
+                    external: false,
+                    roles: vec![],
+                };
+                return Ok(Some(auth_info));
+            }
+            Ok(None)
+        }
+    }
+
+    /// Verify token with the auth hook if configured, the returned roles map onto
+    /// the local roles. Fall back to local verification if the hook is unset or
+    /// fails open.
+    async fn verify_with_hook(&self, token: &str) -> Result<AuthInfo, tonic::Status> {
+        if let Some(ref hook) = self.auth_hook {
+            if let Some(identity) = hook.validate(token).await? {
+                return Ok(AuthInfo {
+                    username: identity.username,
+                    auth_revision: self.revision(),
+                    external: true,
+                    roles: identity.roles,
+                });
+            }
         }
-        Ok(None)
+        Ok(self.verify(token)?)
     }
 
     /// create permission cache
@@ -171,8 +207,13 @@ impl AuthStore {
 
     /// get user permissions
     fn get_user_permissions(&self, user: &User, skip_role: Option<&str>) -> UserPermissions {
+        self.get_roles_permissions(&user.roles, skip_role)
+    }
+
+    /// get the permissions granted by roles, the roles not defined locally are skipped
+    fn get_roles_permissions(&self, roles: &[String], skip_role: Option<&str>) -> UserPermissions {
         let mut user_permission = UserPermissions::new();
-        for role_name in &user.roles {
+        for role_name in roles {
             if skip_role.map_or(false, |r| r == role_name) {
                 continue;
             }
@@ -528,7 +569,18 @@ impl AuthStore {
         &self,
         request: &'a RequestWrapper,
         revision: i64,
-    ) -> Result<(SyncResponse, Vec<WriteOp<'a>>), ExecuteError> {
+    ) -> Result<(SyncResponse, Vec<WriteOp<'a>>), ApplyError> {
+        self.sync_request(request, revision)
+            .map(|ops| (SyncResponse::new(revision), ops))
+            .map_err(ApplyError::from)
+    }
+
+    /// Sync an auth request and return the writes of it
+    fn sync_request<'a>(
+        &self,
+        request: &'a RequestWrapper,
+        revision: i64,
+    ) -> Result<Vec<WriteOp<'a>>, ExecuteError> {
         #[allow(clippy::wildcard_enum_match_arm)]
         let ops = match *request {
             RequestWrapper::AuthEnableRequest(ref req) => {
@@ -603,7 +655,7 @@ impl AuthStore {
                 unreachable!("Other request should not be sent to this store");
             }
         };
-        Ok((SyncResponse::new(revision), ops))
+        Ok(ops)
     }
 
     /// Sync `AuthEnableRequest` and return whether authstore is changed.
@@ -919,6 +971,9 @@ impl AuthStore {
                 | RequestWrapper::AuthRoleDeleteRequest(_)
                 | RequestWrapper::AuthUserListRequest(_)
                 | RequestWrapper::AuthRoleListRequest(_)
+                | RequestWrapper::LeaseCheckpointRequest(_)
+                | RequestWrapper::LeaseRevokeBatchRequest(_)
+                | RequestWrapper::CompactionExemptionRequest(_)
         )
     }
 
@@ -939,42 +994,31 @@ impl AuthStore {
         if let RequestWrapper::AuthenticateRequest(_) = *wrapper {
             return Ok(());
         }
-        let Some(auth_info) = auth_info else {
-            // TODO: some requests are allowed without token when auth is enabled
-            return Err(ExecuteError::TokenNotProvided);
-        };
-        let cur_rev = self.revision();
-        if auth_info.auth_revision < cur_rev {
-            return Err(ExecuteError::TokenOldRevision(
-                auth_info.auth_revision,
-                cur_rev,
-            ));
-        }
-        let username = &auth_info.username;
+        let auth_info = self.check_auth_info(auth_info)?;
         if Self::need_admin_permission(wrapper) {
-            self.check_admin_permission(username)?;
+            self.check_admin_permission(auth_info)?;
         } else {
             #[allow(clippy::wildcard_enum_match_arm)]
             match *wrapper {
                 RequestWrapper::RangeRequest(ref range_req) => {
-                    self.check_range_permission(username, range_req)?;
+                    self.check_range_permission(auth_info, range_req)?;
                 }
                 RequestWrapper::PutRequest(ref put_req) => {
-                    self.check_put_permission(username, put_req)?;
+                    self.check_put_permission(auth_info, put_req)?;
                 }
                 RequestWrapper::DeleteRangeRequest(ref del_range_req) => {
-                    self.check_delete_permission(username, del_range_req)?;
+                    self.check_delete_permission(auth_info, del_range_req)?;
                 }
                 RequestWrapper::TxnRequest(ref txn_req) => {
-                    self.check_txn_permission(username, txn_req)?;
+                    self.check_txn_permission(auth_info, txn_req)?;
                 }
                 RequestWrapper::LeaseRevokeRequest(ref lease_revoke_req) => {
-                    self.check_lease_revoke_permission(username, lease_revoke_req)?;
+                    self.check_lease_revoke_permission(auth_info, lease_revoke_req)?;
                 }
                 RequestWrapper::AuthUserGetRequest(ref user_get_req) => {
-                    self.check_admin_permission(username).map_or_else(
+                    self.check_admin_permission(auth_info).map_or_else(
                         |e| {
-                            if user_get_req.name == *username {
+                            if !auth_info.external && user_get_req.name == auth_info.username {
                                 Ok(())
                             } else {
                                 Err(e)
@@ -984,10 +1028,9 @@ impl AuthStore {
                     )?;
                 }
                 RequestWrapper::AuthRoleGetRequest(ref role_get_req) => {
-                    self.check_admin_permission(username).map_or_else(
+                    self.check_admin_permission(auth_info).map_or_else(
                         |e| {
-                            let user = self.backend.get_user(username)?;
-                            if user.has_role(&role_get_req.role) {
+                            if self.has_role(auth_info, &role_get_req.role)? {
                                 Ok(())
                             } else {
                                 Err(e)
@@ -1002,38 +1045,84 @@ impl AuthStore {
         Ok(())
     }
 
+    /// check if the user is permitted to read a lease, which requires read permission on
+    /// every key attached to the lease
+    pub(crate) fn check_lease_read_permission(
+        &self,
+        lease_id: i64,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<(), ExecuteError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let auth_info = self.check_auth_info(auth_info)?;
+        if let Some(lease) = self.look_up(lease_id) {
+            for key in lease.keys() {
+                self.check_op_permission(auth_info, &key, &[], Type::Read)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// check if the auth info is provided and up to date
+    fn check_auth_info<'a>(
+        &self,
+        auth_info: Option<&'a AuthInfo>,
+    ) -> Result<&'a AuthInfo, ExecuteError> {
+        let Some(auth_info) = auth_info else {
+            // TODO: some requests are allowed without token when auth is enabled
+            return Err(ExecuteError::TokenNotProvided);
+        };
+        let cur_rev = self.revision();
+        if auth_info.auth_revision < cur_rev {
+            return Err(ExecuteError::TokenOldRevision(
+                auth_info.auth_revision,
+                cur_rev,
+            ));
+        }
+        Ok(auth_info)
+    }
+
     /// check if range request is permitted
     fn check_range_permission(
         &self,
-        username: &str,
+        auth_info: &AuthInfo,
         req: &RangeRequest,
     ) -> Result<(), ExecuteError> {
-        self.check_op_permission(username, &req.key, &req.range_end, Type::Read)
+        self.check_op_permission(auth_info, &req.key, &req.range_end, Type::Read)
     }
 
     /// check if put request is permitted
-    fn check_put_permission(&self, username: &str, req: &PutRequest) -> Result<(), ExecuteError> {
+    fn check_put_permission(
+        &self,
+        auth_info: &AuthInfo,
+        req: &PutRequest,
+    ) -> Result<(), ExecuteError> {
         if req.prev_kv {
-            self.check_op_permission(username, &req.key, &[], Type::Read)?;
+            self.check_op_permission(auth_info, &req.key, &[], Type::Read)?;
         }
-        self.check_lease(username, req.lease)?;
-        self.check_op_permission(username, &req.key, &[], Type::Write)
+        self.check_lease(auth_info, req.lease)?;
+        self.check_op_permission(auth_info, &req.key, &[], Type::Write)
     }
 
     /// check if delete request is permitted
     fn check_delete_permission(
         &self,
-        username: &str,
+        auth_info: &AuthInfo,
         req: &DeleteRangeRequest,
     ) -> Result<(), ExecuteError> {
         if req.prev_kv {
-            self.check_op_permission(username, &req.key, &req.range_end, Type::Read)?;
+            self.check_op_permission(auth_info, &req.key, &req.range_end, Type::Read)?;
         }
-        self.check_op_permission(username, &req.key, &req.range_end, Type::Write)
+        self.check_op_permission(auth_info, &req.key, &req.range_end, Type::Write)
     }
 
     /// check if txn request is permitted
-    fn check_txn_permission(&self, username: &str, req: &TxnRequest) -> Result<(), ExecuteError> {
+    fn check_txn_permission(
+        &self,
+        auth_info: &AuthInfo,
+        req: &TxnRequest,
+    ) -> Result<(), ExecuteError> {
         let mut check_queue = VecDeque::new();
         let req = RequestOp {
             request: Some(Request::RequestTxn(req.clone())),
@@ -1042,18 +1131,18 @@ impl AuthStore {
         while let Some(req_op) = check_queue.pop_front() {
             match req_op.request {
                 Some(Request::RequestRange(ref range_req)) => {
-                    self.check_range_permission(username, range_req)?;
+                    self.check_range_permission(auth_info, range_req)?;
                 }
                 Some(Request::RequestPut(ref put_req)) => {
-                    self.check_put_permission(username, put_req)?;
+                    self.check_put_permission(auth_info, put_req)?;
                 }
                 Some(Request::RequestDeleteRange(ref del_range_req)) => {
-                    self.check_delete_permission(username, del_range_req)?;
+                    self.check_delete_permission(auth_info, del_range_req)?;
                 }
                 Some(Request::RequestTxn(ref txn_req)) => {
                     for compare in &txn_req.compare {
                         self.check_op_permission(
-                            username,
+                            auth_info,
                             &compare.key,
                             &compare.range_end,
                             Type::Read,
@@ -1072,65 +1161,82 @@ impl AuthStore {
     /// check if lease revoke request is permitted
     fn check_lease_revoke_permission(
         &self,
-        username: &str,
+        auth_info: &AuthInfo,
         req: &LeaseRevokeRequest,
     ) -> Result<(), ExecuteError> {
-        self.check_lease(username, req.id)
+        self.check_lease(auth_info, req.id)
     }
 
     /// check if user can revoke lease
-    fn check_lease(&self, username: &str, lease_id: i64) -> Result<(), ExecuteError> {
+    fn check_lease(&self, auth_info: &AuthInfo, lease_id: i64) -> Result<(), ExecuteError> {
         let lease = self.look_up(lease_id);
         if let Some(lease) = lease {
             let keys = lease.keys();
             for key in keys {
-                self.check_op_permission(username, &key, &[], Type::Write)?;
+                self.check_op_permission(auth_info, &key, &[], Type::Write)?;
             }
         }
         Ok(())
     }
 
+    /// Check if the user of a request outside the commands has admin permission
+    pub(crate) fn check_admin(&self, auth_info: Option<&AuthInfo>) -> Result<(), ExecuteError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let auth_info = self.check_auth_info(auth_info)?;
+        self.check_admin_permission(auth_info)
+    }
+
     /// Check if the user has admin permission
-    fn check_admin_permission(&self, username: &str) -> Result<(), ExecuteError> {
+    fn check_admin_permission(&self, auth_info: &AuthInfo) -> Result<(), ExecuteError> {
         if !self.is_enabled() {
             return Ok(());
         }
-        let user = self.backend.get_user(username)?;
-        if user.has_role(ROOT_ROLE) {
+        if self.has_role(auth_info, ROOT_ROLE)? {
             return Ok(());
         }
         Err(ExecuteError::PermissionDenied)
     }
 
+    /// Check if the user has a role, the roles of an identity returned by the auth hook
+    /// are the ones the hook returned
+    fn has_role(&self, auth_info: &AuthInfo, role: &str) -> Result<bool, ExecuteError> {
+        if auth_info.external {
+            return Ok(auth_info.roles.iter().any(|r| r == role));
+        }
+        let user = self.backend.get_user(&auth_info.username)?;
+        Ok(user.has_role(role))
+    }
+
     /// check permission for a kv operation
     fn check_op_permission(
         &self,
-        username: &str,
+        auth_info: &AuthInfo,
         key: &[u8],
         range_end: &[u8],
         perm_type: Type,
     ) -> Result<(), ExecuteError> {
-        let user = self.backend.get_user(username)?;
-        if user.has_role(ROOT_ROLE) {
+        if self.has_role(auth_info, ROOT_ROLE)? {
             return Ok(());
         }
         let key_range = KeyRange::new(key, range_end);
-        if let Some(permissions) = self.permission_cache.read().user_permissions.get(username) {
-            match perm_type {
-                Type::Read => {
-                    if permissions.read.contains_range(&key_range) {
-                        return Ok(());
-                    }
-                }
-                Type::Write => {
-                    if permissions.write.contains_range(&key_range) {
-                        return Ok(());
-                    }
-                }
-                Type::Readwrite => {
-                    unreachable!("Readwrite is unreachable");
-                }
-            }
+        let contains = |permissions: &UserPermissions| match perm_type {
+            Type::Read => permissions.read.contains_range(&key_range),
+            Type::Write => permissions.write.contains_range(&key_range),
+            Type::Readwrite => unreachable!("Readwrite is unreachable"),
+        };
+        let permitted = if auth_info.external {
+            contains(&self.get_roles_permissions(&auth_info.roles, None))
+        } else {
+            self.permission_cache
+                .read()
+                .user_permissions
+                .get(&auth_info.username)
+                .map_or(false, contains)
+        };
+        if permitted {
+            return Ok(());
         }
         Err(ExecuteError::PermissionDenied)
     }
@@ -1163,17 +1269,21 @@ fn get_cn<T>(request: &tonic::Request<T>) -> Option<String> {
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::{
+        collections::HashMap,
+        time::{Duration, UNIX_EPOCH},
+    };
 
     use merged_range::MergedRange;
     use utils::config::EngineConfig;
 
     use super::*;
     use crate::{
+        clock::MockTimeProvider,
         rpc::{
             AuthRoleAddRequest, AuthRoleDeleteRequest, AuthRoleGrantPermissionRequest,
             AuthRoleRevokePermissionRequest, AuthUserAddRequest, AuthUserDeleteRequest,
-            AuthUserGrantRoleRequest, Permission,
+            AuthUserGrantRoleRequest, LeaseGrantRequest, Permission,
         },
         storage::{
             auth_store::perms::{PermissionCache, UserPermissions},
@@ -1192,6 +1302,30 @@ mod test {
         assert_eq!(auth_info.username, "xline");
     }
 
+    #[test]
+    fn token_should_expire_by_the_server_clock() {
+        let time = MockTimeProvider::new(UNIX_EPOCH + Duration::from_secs(1000));
+        let clock = Arc::new(Clock::new(Arc::clone(&time)));
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let store = init_empty_store_with_clock(db, Arc::clone(&clock));
+        let token = store.assign("xline").unwrap();
+
+        time.advance(Duration::from_secs(200));
+        assert!(store.verify(&token).is_ok());
+
+        // the token outlives a backward jump
+        time.jump_backward(Duration::from_secs(500));
+        assert!(store.verify(&token).is_ok());
+        assert_eq!(clock.jumps(), 1);
+
+        time.jump_forward(Duration::from_secs(1000));
+        assert!(matches!(
+            store.verify(&token),
+            Err(ExecuteError::InvalidAuthToken)
+        ));
+        assert_eq!(clock.jumps(), 2);
+    }
+
     #[test]
     fn test_role_grant_permission() -> Result<(), ExecuteError> {
         let db = DB::open(&EngineConfig::Memory)?;
@@ -1323,6 +1457,172 @@ mod test {
         assert!(!store.is_enabled());
     }
 
+    #[test]
+    fn test_lease_permission() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_auth_store(db);
+        let rev_gen = Arc::clone(&store.revision);
+        let req_1 = RequestWrapper::from(AuthUserAddRequest {
+            name: "root".to_owned(),
+            password: String::new(),
+            hashed_password: "123".to_owned(),
+            options: None,
+        });
+        let req_2 = RequestWrapper::from(AuthRoleAddRequest {
+            name: "root".to_owned(),
+        });
+        let req_3 = RequestWrapper::from(AuthUserGrantRoleRequest {
+            user: "root".to_owned(),
+            role: "root".to_owned(),
+        });
+        for req in [req_1, req_2, req_3] {
+            let _ignore = exe_and_sync(&store, &req, rev_gen.next())?;
+        }
+        let _ignore = exe_and_sync(&store, &RequestWrapper::from(AuthEnableRequest {}), -1)?;
+
+        let _ignore = store.lease_collection.grant(1, 10, true);
+        store.lease_collection.attach(1, b"foo".to_vec())?;
+        store.lease_collection.attach(1, b"bar".to_vec())?;
+        let _ignore = store.lease_collection.grant(2, 10, true);
+        store.lease_collection.attach(2, b"foo".to_vec())?;
+        let user = AuthInfo {
+            username: "u".to_owned(),
+            auth_revision: store.revision(),
+            external: false,
+            roles: vec![],
+        };
+        let root = AuthInfo {
+            username: "root".to_owned(),
+            auth_revision: store.revision(),
+            external: false,
+            roles: vec![],
+        };
+        let revoke = |id| {
+            RequestWrapper::from(LeaseRevokeRequest {
+                id,
+                ..Default::default()
+            })
+        };
+        let grant = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 3,
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            store.check_permission(&grant, None),
+            Err(ExecuteError::TokenNotProvided)
+        ));
+        assert!(store.check_permission(&grant, Some(&user)).is_ok());
+        assert!(matches!(
+            store.check_permission(&revoke(1), Some(&user)),
+            Err(ExecuteError::PermissionDenied)
+        ));
+        assert!(store.check_permission(&revoke(2), Some(&user)).is_ok());
+        assert!(matches!(
+            store.check_lease_read_permission(1, Some(&user)),
+            Err(ExecuteError::PermissionDenied)
+        ));
+        assert!(store.check_lease_read_permission(2, Some(&user)).is_ok());
+        assert!(matches!(
+            store.check_lease_read_permission(2, None),
+            Err(ExecuteError::TokenNotProvided)
+        ));
+
+        assert!(store.check_permission(&revoke(1), Some(&root)).is_ok());
+        assert!(store.check_lease_read_permission(1, Some(&root)).is_ok());
+
+        let _ignore = exe_and_sync(
+            &store,
+            &RequestWrapper::from(AuthDisableRequest {}),
+            rev_gen.next(),
+        )?;
+        assert!(store.check_permission(&revoke(1), None).is_ok());
+        assert!(store.check_lease_read_permission(1, None).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_identity_permission() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_auth_store(db);
+        let rev_gen = Arc::clone(&store.revision);
+        let req_1 = RequestWrapper::from(AuthUserAddRequest {
+            name: "root".to_owned(),
+            password: String::new(),
+            hashed_password: "123".to_owned(),
+            options: None,
+        });
+        let req_2 = RequestWrapper::from(AuthRoleAddRequest {
+            name: "root".to_owned(),
+        });
+        let req_3 = RequestWrapper::from(AuthUserGrantRoleRequest {
+            user: "root".to_owned(),
+            role: "root".to_owned(),
+        });
+        for req in [req_1, req_2, req_3] {
+            let _ignore = exe_and_sync(&store, &req, rev_gen.next())?;
+        }
+        let _ignore = exe_and_sync(&store, &RequestWrapper::from(AuthEnableRequest {}), -1)?;
+
+        let external = |username: &str, roles: &[&str]| AuthInfo {
+            username: username.to_owned(),
+            auth_revision: store.revision(),
+            external: true,
+            roles: roles.iter().map(|&r| r.to_owned()).collect(),
+        };
+        let range = |key: &str| {
+            RequestWrapper::from(RangeRequest {
+                key: key.into(),
+                ..Default::default()
+            })
+        };
+        let put = RequestWrapper::from(PutRequest {
+            key: "foo".into(),
+            value: "v".into(),
+            ..Default::default()
+        });
+        let user_list = RequestWrapper::from(AuthUserListRequest {});
+        let user_get = RequestWrapper::from(AuthUserGetRequest {
+            name: "u".to_owned(),
+        });
+        let role_get = RequestWrapper::from(AuthRoleGetRequest {
+            role: "r".to_owned(),
+        });
+
+        // the returned roles map onto the local ones, unknown roles grant nothing
+        let bob = external("bob", &["r", "undefined"]);
+        assert!(store.check_permission(&range("foo"), Some(&bob)).is_ok());
+        assert!(store.check_permission(&put, Some(&bob)).is_ok());
+        assert!(store.check_permission(&role_get, Some(&bob)).is_ok());
+        assert!(matches!(
+            store.check_permission(&range("bar"), Some(&bob)),
+            Err(ExecuteError::PermissionDenied)
+        ));
+        assert!(matches!(
+            store.check_permission(&user_list, Some(&bob)),
+            Err(ExecuteError::PermissionDenied)
+        ));
+
+        // an external identity never inherits the local user of the same name
+        let u = external("u", &[]);
+        for req in [&range("foo"), &user_get, &role_get] {
+            assert!(matches!(
+                store.check_permission(req, Some(&u)),
+                Err(ExecuteError::PermissionDenied)
+            ));
+        }
+        assert!(matches!(
+            store.check_permission(&user_list, Some(&external("root", &[]))),
+            Err(ExecuteError::PermissionDenied)
+        ));
+
+        let admin = external("carol", &["root"]);
+        assert!(store.check_permission(&user_list, Some(&admin)).is_ok());
+        assert!(store.check_permission(&range("bar"), Some(&admin)).is_ok());
+        Ok(())
+    }
+
     #[test]
     fn test_recover() -> Result<(), ExecuteError> {
         let db = DB::open(&EngineConfig::Memory).unwrap();
@@ -1383,10 +1683,14 @@ mod test {
     }
 
     fn init_empty_store(db: Arc<DB>) -> AuthStore {
+        init_empty_store_with_clock(db, Arc::new(Clock::system()))
+    }
+
+    fn init_empty_store_with_clock(db: Arc<DB>, clock: Arc<Clock>) -> AuthStore {
         let key_pair = test_key_pair();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
         let lease_collection = Arc::new(LeaseCollection::new(0));
-        AuthStore::new(lease_collection, key_pair, header_gen, db)
+        AuthStore::new(lease_collection, key_pair, header_gen, db, None, clock)
     }
 
     fn exe_and_sync(
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use xlineapi::{command::KeyRange, execute_error::ExecuteError};
+
+/// Key of the compaction exempt prefixes in the meta table
+pub(crate) const COMPACT_EXEMPTION_KEY: &str = "compact_exemption";
+
+/// The key prefixes whose history is kept by compactions, the compacted revision
+/// still advances for the other keys
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ExemptPrefixes {
+    /// The exempt prefixes and the revision since which the history under each is kept
+    prefixes: BTreeMap<Vec<u8>, i64>,
+}
+
+impl ExemptPrefixes {
+    /// New `ExemptPrefixes` keeping the history under the prefixes since a revision,
+    /// empty prefixes are ignored
+    pub(crate) fn new<I>(prefixes: I, since: i64) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        Self {
+            prefixes: prefixes
+                .into_iter()
+                .filter(|prefix| !prefix.is_empty())
+                .map(|prefix| (prefix, since))
+                .collect(),
+        }
+    }
+
+    /// Whether no prefix is exempt
+    pub(crate) fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The exempt prefixes in ascending order
+    pub(crate) fn prefixes(&self) -> Vec<Vec<u8>> {
+        self.prefixes.keys().cloned().collect()
+    }
+
+    /// Whether the history of the key is kept by compactions
+    pub(crate) fn is_exempt(&self, key: &[u8]) -> bool {
+        self.prefixes.keys().any(|prefix| key.starts_with(prefix))
+    }
+
+    /// The revision since which the history of every key in the range is kept, `None`
+    /// if the range doesn't lie under an exempt prefix
+    pub(crate) fn retained_since(&self, key: &[u8], range_end: &[u8]) -> Option<i64> {
+        let range = KeyRange::new(key, range_end);
+        self.prefixes
+            .iter()
+            .filter(|&(prefix, _)| {
+                KeyRange::prefix(prefix.clone())
+                    .intersection(&range)
+                    .as_ref()
+                    == Some(&range)
+            })
+            .map(|(_, &since)| since)
+            .min()
+    }
+
+    /// The compacted revision of a range, the history of a range under an exempt prefix
+    /// is compacted only below the revision it is kept since
+    pub(crate) fn compacted_revision_of(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        compacted_revision: i64,
+    ) -> i64 {
+        self.retained_since(key, range_end)
+            .map_or(compacted_revision, |since| since.min(compacted_revision))
+    }
+
+    /// Remove and add prefixes, an added prefix keeps the history since the revision
+    /// while a prefix that is exempt already keeps its own
+    pub(crate) fn update(&mut self, add: &[Vec<u8>], remove: &[Vec<u8>], revision: i64) {
+        for prefix in remove {
+            let _ignore = self.prefixes.remove(prefix);
+        }
+        for prefix in add.iter().filter(|prefix| !prefix.is_empty()) {
+            let _ignore = self.prefixes.entry(prefix.clone()).or_insert(revision);
+        }
+    }
+
+    /// Encode the prefixes to be stored in the meta table
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let entries: Vec<_> = self.prefixes.iter().collect();
+        serde_json::to_vec(&entries)
+            .unwrap_or_else(|e| unreachable!("the exempt prefixes are always encodable: {e}"))
+    }
+
+    /// Decode the prefixes stored in the meta table
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self, ExecuteError> {
+        let entries: Vec<(Vec<u8>, i64)> = serde_json::from_slice(bytes).map_err(|e| {
+            ExecuteError::DbError(format!(
+                "cannot decode the compaction exempt prefixes from META_TABLE: {e}"
+            ))
+        })?;
+        Ok(Self {
+            prefixes: entries.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_ranges_under_an_exempt_prefix_should_be_retained() {
+        let exempt = ExemptPrefixes::new([b"/audit/".to_vec()], 0);
+        assert!(exempt.is_exempt(b"/audit/a"));
+        assert!(!exempt.is_exempt(b"/audi"));
+        assert_eq!(exempt.retained_since(b"/audit/a", b""), Some(0));
+        assert_eq!(exempt.retained_since(b"/audit/", b"/audit0"), Some(0));
+        assert_eq!(exempt.retained_since(b"/audit/a", b"/audit/b"), Some(0));
+        // ranges crossing the boundary of the prefix
+        assert_eq!(exempt.retained_since(b"/audit/", b"/audit1"), None);
+        assert_eq!(exempt.retained_since(b"/", b"/audit0"), None);
+        assert_eq!(exempt.retained_since(b"/audit/", &[0]), None);
+        assert_eq!(exempt.retained_since(b"/other", b""), None);
+        assert_eq!(exempt.compacted_revision_of(b"/audit/a", b"", 10), 0);
+        assert_eq!(exempt.compacted_revision_of(b"/other", b"", 10), 10);
+    }
+
+    #[test]
+    fn added_prefixes_should_be_retained_since_the_revision_they_are_added_at() {
+        let mut exempt = ExemptPrefixes::new([b"/a/".to_vec(), Vec::new()], 0);
+        assert_eq!(exempt.prefixes(), vec![b"/a/".to_vec()]);
+        exempt.update(&[b"/a/".to_vec(), b"/b/".to_vec()], &[], 20);
+        assert_eq!(exempt.compacted_revision_of(b"/a/k", b"", 30), 0);
+        assert_eq!(exempt.compacted_revision_of(b"/b/k", b"", 30), 20);
+        assert_eq!(exempt.compacted_revision_of(b"/b/k", b"", 10), 10);
+        exempt.update(&[], &[b"/a/".to_vec()], 40);
+        assert!(!exempt.is_exempt(b"/a/k"));
+        assert_eq!(ExemptPrefixes::decode(&exempt.encode()).unwrap(), exempt);
+        exempt.update(&[], &[b"/b/".to_vec()], 50);
+        assert!(exempt.is_empty());
+    }
+}
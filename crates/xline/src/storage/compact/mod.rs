@@ -12,6 +12,7 @@ use utils::{
 };
 use xlineapi::{command::Command, execute_error::ExecuteError, RequestWrapper};
 
+pub(crate) use self::exemption::{ExemptPrefixes, COMPACT_EXEMPTION_KEY};
 use super::{
     index::{Index, IndexOperate},
     KvStore,
@@ -24,9 +25,16 @@ mod revision_compactor;
 /// mod periodic compactor;
 mod periodic_compactor;
 
+/// mod exemption
+mod exemption;
+
 /// compact task channel size
 pub(crate) const COMPACT_CHANNEL_SIZE: usize = 32;
 
+/// A compact task, the revision to compact at, the prefixes exempt when the compaction
+/// is applied and the event notified once it's finished
+pub(crate) type CompactTask = (i64, ExemptPrefixes, Option<Arc<Event>>);
+
 /// Compactor trait definition
 #[async_trait]
 pub(crate) trait Compactor<C: Compactable>: Send + Sync {
@@ -101,22 +109,22 @@ pub(crate) async fn compact_bg_task(
     index: Arc<Index>,
     batch_limit: usize,
     interval: Duration,
-    mut compact_task_rx: Receiver<(i64, Option<Arc<Event>>)>,
+    mut compact_task_rx: Receiver<CompactTask>,
     shutdown_listener: Listener,
 ) {
     loop {
-        let (revision, listener) = tokio::select! {
+        let (revision, exempt, listener) = tokio::select! {
             recv = compact_task_rx.recv() => {
-                let Some((revision, listener)) = recv else {
+                let Some(task) = recv else {
                     return;
                 };
-                (revision, listener)
+                task
             },
             _ = shutdown_listener.wait() => break,
         };
 
         let target_revisions = index
-            .compact(revision)
+            .compact(revision, &exempt)
             .into_iter()
             .map(|key_rev| key_rev.as_revision().encode_to_vec())
             .collect::<Vec<Vec<_>>>();
@@ -1,23 +1,33 @@
 use std::{collections::HashMap, path::Path, sync::Arc};
 
-use engine::{Engine, EngineType, Snapshot, StorageEngine, WriteOperation};
+use bytes::Bytes;
+use clippy_utilities::OverflowArithmetic;
+use engine::{Engine, EngineError, EngineType, Snapshot, StorageEngine, WriteOperation};
+use event_listener::Event;
+use parking_lot::Mutex;
 use prost::Message;
+use tracing::{error, info};
 use utils::{
     config::EngineConfig,
     table_names::{
-        ALARM_TABLE, AUTH_TABLE, KV_TABLE, LEASE_TABLE, META_TABLE, ROLE_TABLE, USER_TABLE,
-        XLINE_TABLES,
+        ALARM_TABLE, AUTH_TABLE, KV_TABLE, LEASE_EXPIRY_TABLE, LEASE_TABLE, META_TABLE,
+        REPLICATED_TABLES, ROLE_TABLE, USER_TABLE, XLINE_TABLES,
     },
 };
 use xlineapi::{execute_error::ExecuteError, AlarmMember};
 
 use super::{
     auth_store::{AUTH_ENABLE_KEY, AUTH_REVISION_KEY},
+    compact::COMPACT_EXEMPTION_KEY,
+    layout::{self, Migration, StoredLayout, Version, LAYOUT_KEYS, MIGRATIONS},
     revision::KeyRevision,
+    value_transformer::ValueTransformer,
 };
 use crate::{
     rpc::{KeyValue, PbLease, Role, User},
-    server::command::APPLIED_INDEX_KEY,
+    server::{
+        command::APPLIED_INDEX_KEY, state_hash::STATE_HASH_KEY, time_index::time_revision_key,
+    },
     storage::Revision,
 };
 
@@ -25,17 +35,83 @@ use crate::{
 pub(crate) const FINISHED_COMPACT_REVISION: &str = "finished_compact_revision";
 /// Key of scheduled compact revision
 pub(crate) const SCHEDULED_COMPACT_REVISION: &str = "scheduled_compact_revision";
+/// Key prefix of the markers of leases whose revocation is continued by later applies
+pub(crate) const REVOKING_LEASE_PREFIX: &[u8] = b"revoking_lease/";
+/// Key prefix of the keys of revoked leases whose tombstones are not written yet
+pub(crate) const LEASE_DELETION_PREFIX: &[u8] = b"lease_deletion/";
+/// Key prefix of the positions of the last applied commands of the partially applied
+/// batched entries
+pub(crate) const APPLIED_IN_BATCH_PREFIX: &[u8] = b"applied_in_batch/";
+/// Key prefix of the results of the proposals applied in the latest log entries
+pub(crate) const APPLIED_PROPOSAL_PREFIX: &[u8] = b"applied_proposal/";
+/// Key of the flag that the kv values are encrypted
+pub(crate) const VALUE_ENCRYPTION_KEY: &str = "value_encryption";
+/// Number of the kv pairs rewritten in a batch by `reencrypt`
+const REENCRYPT_BATCH_SIZE: usize = 1024;
+
+/// Key of the revoking marker of a lease in the meta table
+pub(crate) fn revoking_lease_key(lease_id: i64) -> Vec<u8> {
+    let mut key = REVOKING_LEASE_PREFIX.to_vec();
+    key.extend_from_slice(&lease_id.to_be_bytes());
+    key
+}
+
+/// Key of the keys of a revoked lease deleted at `revision` from `sub_revision` on in the
+/// meta table
+pub(crate) fn lease_deletion_key(revision: i64, sub_revision: i64) -> Vec<u8> {
+    let mut key = LEASE_DELETION_PREFIX.to_vec();
+    key.extend_from_slice(&revision.to_be_bytes());
+    key.extend_from_slice(&sub_revision.to_be_bytes());
+    key
+}
+
+/// Key of the position of the last applied command of a batched entry in the meta table
+pub(crate) fn applied_in_batch_key(index: u64) -> Vec<u8> {
+    let mut key = APPLIED_IN_BATCH_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Key of the results of the proposals applied from log entry `index` on in the meta table,
+/// it's a prefix of the keys of the proposals applied in the entry
+pub(crate) fn applied_proposals_from_key(index: u64) -> Vec<u8> {
+    let mut key = APPLIED_PROPOSAL_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Key of the results of a proposal applied in log entry `index` in the meta table
+pub(crate) fn applied_proposal_key(index: u64, propose_id: (u64, u64)) -> Vec<u8> {
+    let mut key = applied_proposals_from_key(index);
+    key.extend_from_slice(&propose_id.0.to_be_bytes());
+    key.extend_from_slice(&propose_id.1.to_be_bytes());
+    key
+}
+
+/// The context the value of a kv pair is bound to by the transformer, its revision and its
+/// key
+fn value_context(kv: &KeyValue) -> Vec<u8> {
+    let mut context = kv.mod_revision.to_be_bytes().to_vec();
+    context.extend_from_slice(&kv.key);
+    context
+}
 
 /// Key and value pair
 type KeyValuePair = (Vec<u8>, Vec<u8>);
 /// Key and revision pair
-type KeyRevisionPair = (Vec<u8>, KeyRevision);
+pub(crate) type KeyRevisionPair = (Vec<u8>, KeyRevision);
 
 /// Database to store revision to kv mapping
 #[derive(Debug)]
 pub struct DB {
     /// internal storage of `DB`
     engine: Arc<Engine>,
+    /// The reason of the first corruption detected at runtime
+    corruption: Mutex<Option<String>>,
+    /// Notified when a corruption is detected
+    corruption_event: Event,
+    /// Transformer of the kv values at rest, `None` if they are stored as they are
+    transformer: Option<Arc<dyn ValueTransformer>>,
 }
 
 impl DB {
@@ -45,6 +121,30 @@ impl DB {
     /// Return `ExecuteError::DbError` when open db failed
     #[inline]
     pub fn open(config: &EngineConfig) -> Result<Arc<Self>, ExecuteError> {
+        Self::open_with_transformer(config, None)
+    }
+
+    /// Create a new `DB` whose kv values are transformed at rest
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` when open db failed, or the kv values are
+    /// encrypted but there is no transformer, or the other way around
+    #[inline]
+    pub fn open_with_transformer(
+        config: &EngineConfig,
+        transformer: Option<Arc<dyn ValueTransformer>>,
+    ) -> Result<Arc<Self>, ExecuteError> {
+        let db = Self::open_engine(config, transformer)?;
+        db.check_layout(Version::running(), &MIGRATIONS)?;
+        db.check_encryption()?;
+        Ok(Arc::new(db))
+    }
+
+    /// Open the engine without checking the data
+    fn open_engine(
+        config: &EngineConfig,
+        transformer: Option<Arc<dyn ValueTransformer>>,
+    ) -> Result<Self, ExecuteError> {
         let engine_type = match *config {
             EngineConfig::Memory => EngineType::Memory,
             EngineConfig::RocksDB(ref path) => EngineType::Rocks(path.clone()),
@@ -52,17 +152,218 @@ impl DB {
         };
         let engine = Engine::new(engine_type, &XLINE_TABLES)
             .map_err(|e| ExecuteError::DbError(format!("Cannot open database: {e}")))?;
-        Ok(Arc::new(Self {
+        Ok(Self {
             engine: Arc::new(engine),
-        }))
+            corruption: Mutex::new(None),
+            corruption_event: Event::new(),
+            transformer,
+        })
+    }
+
+    /// Rewrite the kv values of the data dir with the current key of the transformer,
+    /// the values of the other keys are decrypted first, and so are the ones written
+    /// before the encryption is on, returns the number of the rewritten values
+    ///
+    /// It must run offline, the data dir is taken as encrypted once all of its values
+    /// are rewritten, so an interrupted run is resumed by running it again.
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` when open db failed, or a value cannot be decoded
+    #[inline]
+    pub fn reencrypt(
+        config: &EngineConfig,
+        transformer: Arc<dyn ValueTransformer>,
+    ) -> Result<usize, ExecuteError> {
+        let db = Self::open_engine(config, Some(Arc::clone(&transformer)))?;
+        db.check_layout(Version::running(), &MIGRATIONS)?;
+        db.rewrite_values(transformer.as_ref(), true)
+    }
+
+    /// Rewrite the plain kv values with the current key of the transformer, and the
+    /// encrypted ones too if `rotate`, then flag the data dir as encrypted, returns the
+    /// number of the rewritten values
+    ///
+    /// The values are read and written in batches. A value is taken as plain if it can't
+    /// be decoded and the data dir is not flagged as encrypted yet.
+    fn rewrite_values(
+        &self,
+        transformer: &dyn ValueTransformer,
+        rotate: bool,
+    ) -> Result<usize, ExecuteError> {
+        let encrypted = self.encrypted()?;
+        let keys = self.scan_keys(KV_TABLE, &[], &[])?;
+        let mut count: usize = 0;
+        for batch in keys.chunks(REENCRYPT_BATCH_SIZE) {
+            let values = self.get_values(KV_TABLE, batch)?;
+            let mut ops = Vec::with_capacity(batch.len());
+            for (key, value) in batch.iter().zip(values) {
+                let Some(value) = value else {
+                    continue;
+                };
+                let mut kv = KeyValue::decode(value.as_slice()).map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to decode key-value from DB, error: {e}"))
+                })?;
+                // a value authenticated by a key is encrypted, even before the data
+                // dir is taken as encrypted in an interrupted run
+                let context = value_context(&kv);
+                let plain = match transformer.decode(&kv.value, &context) {
+                    Ok(_) if !rotate => continue,
+                    Ok(plain) => plain,
+                    Err(_) if !encrypted => kv.value.to_vec(),
+                    Err(e) => return Err(e),
+                };
+                kv.value = transformer.encode(&plain, &context)?.into();
+                ops.push(WriteOperation::new_put(
+                    KV_TABLE,
+                    key.clone(),
+                    kv.encode_to_vec(),
+                ));
+            }
+            count = count.overflow_add(ops.len());
+            self.engine
+                .write_batch(ops, true)
+                .map_err(|e| self.db_error("Failed to rewrite the values", &e))?;
+        }
+        self.engine
+            .write_batch(vec![Self::encryption_flag_op()], true)
+            .map_err(|e| self.db_error("Failed to write the encryption flag", &e))?;
+        Ok(count)
+    }
+
+    /// Whether the kv values are encrypted
+    fn encrypted(&self) -> Result<bool, ExecuteError> {
+        Ok(self.get_value(META_TABLE, VALUE_ENCRYPTION_KEY)?.is_some())
+    }
+
+    /// The operation writing the flag that the kv values are encrypted
+    fn encryption_flag_op() -> WriteOperation<'static> {
+        WriteOperation::new_put(META_TABLE, VALUE_ENCRYPTION_KEY.as_bytes().to_vec(), vec![])
+    }
+
+    /// Refuse to read the encrypted values without a transformer and the plain ones
+    /// with it, an empty data dir is taken as encrypted once it's opened with one
+    fn check_encryption(&self) -> Result<(), ExecuteError> {
+        match (self.encrypted()?, self.transformer.is_some()) {
+            (true, false) => Err(ExecuteError::DbError(
+                "the kv values are encrypted, but no encryption key is configured".to_owned(),
+            )),
+            (false, true) => {
+                if !self.scan_keys(KV_TABLE, &[], &[])?.is_empty() {
+                    return Err(ExecuteError::DbError(
+                        "the kv values are not encrypted, encrypt them with xline-reencrypt first"
+                            .to_owned(),
+                    ));
+                }
+                self.engine
+                    .write_batch(vec![Self::encryption_flag_op()], true)
+                    .map_err(|e| self.db_error("Failed to write the encryption flag", &e))
+            }
+            (true, true) | (false, false) => Ok(()),
+        }
+    }
+
+    /// Decode a kv pair read from the kv table, its value is decoded by the transformer
+    pub(crate) fn decode_kv(&self, bytes: Vec<u8>) -> Result<KeyValue, ExecuteError> {
+        // Decoding from `Bytes` lets the value share the buffer read from the engine
+        let mut kv = KeyValue::decode(Bytes::from(bytes)).map_err(|e| {
+            ExecuteError::DbError(format!("Failed to decode key-value from DB, error: {e}"))
+        })?;
+        if let Some(ref transformer) = self.transformer {
+            kv.value = transformer.decode(&kv.value, &value_context(&kv))?.into();
+        }
+        Ok(kv)
+    }
+
+    /// The plaintext of a kv pair read from the kv table, it's the same on all members
+    /// whatever their keys are
+    pub(crate) fn plain_kv(&self, bytes: Vec<u8>) -> Result<Vec<u8>, ExecuteError> {
+        if self.transformer.is_none() {
+            return Ok(bytes);
+        }
+        Ok(self.decode_kv(bytes)?.encode_to_vec())
+    }
+
+    /// Encode a kv pair to be written to the kv table, its value is encoded by the
+    /// transformer
+    fn encode_kv(&self, mut kv: KeyValue) -> Result<Vec<u8>, ExecuteError> {
+        if let Some(ref transformer) = self.transformer {
+            kv.value = transformer.encode(&kv.value, &value_context(&kv))?.into();
+        }
+        Ok(kv.encode_to_vec())
+    }
+
+    /// Refuse to open a data dir that requires a newer version than `running`,
+    /// otherwise migrate it and record the layout written by `running`
+    fn check_layout(&self, running: Version, migrations: &[Migration]) -> Result<(), ExecuteError> {
+        let plan = layout::plan(StoredLayout::read(self)?, running, migrations)?;
+        for migration in plan.migrations {
+            info!("migrating the data dir to layout {}", migration.layout);
+            (migration.migrate)(self)?;
+        }
+        self.engine
+            .write_batch(plan.layout.to_ops(), true)
+            .map_err(|e| self.db_error("Failed to write the layout version", &e))
+    }
+
+    /// Mark the storage as corrupted, only the first reason is kept
+    pub(crate) fn mark_corrupted(&self, reason: String) {
+        let mut corruption = self.corruption.lock();
+        if corruption.is_some() {
+            return;
+        }
+        error!("backend corruption detected: {reason}");
+        *corruption = Some(reason);
+        let _ignore = self.corruption_event.notify(usize::MAX);
+    }
+
+    /// Get the reason of the detected corruption, if any
+    pub(crate) fn corruption(&self) -> Option<String> {
+        self.corruption.lock().clone()
+    }
+
+    /// Wait until a corruption is detected and return its reason
+    pub(crate) async fn corrupted(&self) -> String {
+        loop {
+            let listener = self.corruption_event.listen();
+            if let Some(reason) = self.corruption() {
+                return reason;
+            }
+            listener.await;
+        }
+    }
+
+    /// Convert an engine error to `ExecuteError::DbError`, marking the storage
+    /// as corrupted if the engine reports a corruption
+    fn db_error(&self, context: impl std::fmt::Display, err: &EngineError) -> ExecuteError {
+        let msg = format!("{context}: {err}");
+        if matches!(*err, EngineError::Corruption(_)) {
+            self.mark_corrupted(msg.clone());
+        }
+        ExecuteError::DbError(msg)
     }
 
-    /// Get del lease key buffer
+    /// Make all following reads of `table` fail with a corruption error
+    #[cfg(test)]
+    pub(crate) fn inject_read_fault(&self, table: &str) {
+        self.engine
+            .inject_read_fault(table)
+            .unwrap_or_else(|e| panic!("failed to inject read fault: {e}"));
+    }
+
+    /// Make all following batches writing to `table` fail with an I/O error
+    #[cfg(test)]
+    pub(crate) fn inject_write_fault(&self, table: &str) {
+        self.engine
+            .inject_write_fault(table)
+            .unwrap_or_else(|e| panic!("failed to inject write fault: {e}"));
+    }
+
+    /// Get del lease key buffer, shared by the lease table and the lease expiry table
     #[inline]
     fn get_del_lease_key_buffer(ops: &[WriteOp]) -> HashMap<i64, Vec<u8>> {
         ops.iter()
             .filter_map(|op| {
-                if let WriteOp::DeleteLease(lease_id) = *op {
+                if let WriteOp::DeleteLease(lease_id) | WriteOp::DeleteLeaseExpiry(lease_id) = *op {
                     Some((lease_id, lease_id.encode_to_vec()))
                 } else {
                     None
@@ -71,6 +372,76 @@ impl DB {
             .collect::<HashMap<_, _>>()
     }
 
+    /// Get del revoking lease key buffer
+    #[inline]
+    fn get_del_revoking_lease_key_buffer(ops: &[WriteOp]) -> HashMap<i64, Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteRevokingLease(lease_id) = *op {
+                    Some((lease_id, revoking_lease_key(lease_id)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
+    /// Get del lease deletion key buffer
+    #[inline]
+    fn get_del_lease_deletion_key_buffer(ops: &[WriteOp]) -> HashMap<(i64, i64), Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteLeaseDeletion(rev, sub_rev) = *op {
+                    Some(((rev, sub_rev), lease_deletion_key(rev, sub_rev)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
+    /// Get del applied in batch key buffer
+    #[inline]
+    fn get_del_applied_in_batch_key_buffer(ops: &[WriteOp]) -> HashMap<u64, Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteAppliedInBatch(index) = *op {
+                    Some((index, applied_in_batch_key(index)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
+    /// Get the end key of the deleted range of applied proposals
+    #[inline]
+    fn get_del_applied_proposals_buffer(ops: &[WriteOp]) -> Vec<u8> {
+        ops.iter()
+            .find_map(|op| {
+                if let WriteOp::DeleteAppliedProposals(index) = *op {
+                    Some(applied_proposals_from_key(index))
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get del time revision key buffer
+    #[inline]
+    fn get_del_time_revision_key_buffer(ops: &[WriteOp]) -> HashMap<u64, Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteTimeRevision(millis, rev) = *op {
+                    Some((millis, time_revision_key(millis, rev)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
     /// get del alarm buffer
     #[inline]
     fn get_del_alarm_buffer(ops: &[WriteOp]) -> Vec<u8> {
@@ -101,11 +472,19 @@ impl DB {
         let values = self
             .engine
             .get_multi(table, keys)
-            .map_err(|e| ExecuteError::DbError(format!("Failed to get keys {keys:?}: {e}")))?
+            .map_err(|e| self.db_error(format_args!("Failed to get keys {keys:?}"), &e))?
             .into_iter()
             .collect::<Vec<_>>();
 
-        assert_eq!(values.len(), keys.len(), "Index doesn't match with DB");
+        if values.len() != keys.len() {
+            let reason = format!(
+                "Index doesn't match with DB, expect {} values, got {}",
+                keys.len(),
+                values.len()
+            );
+            self.mark_corrupted(reason.clone());
+            return Err(ExecuteError::DbError(reason));
+        }
 
         Ok(values)
     }
@@ -125,7 +504,7 @@ impl DB {
     {
         self.engine
             .get(table, key.as_ref())
-            .map_err(|e| ExecuteError::DbError(format!("Failed to get key {key:?}: {e}")))
+            .map_err(|e| self.db_error(format_args!("Failed to get key {key:?}"), &e))
     }
 
     /// Get all values of the given table from storage
@@ -134,22 +513,43 @@ impl DB {
     ///
     /// if error occurs in storage, return `Err(error)`
     pub(crate) fn get_all(&self, table: &'static str) -> Result<Vec<KeyValuePair>, ExecuteError> {
-        self.engine.get_all(table).map_err(|e| {
-            ExecuteError::DbError(format!("Failed to get all keys from {table:?}: {e}"))
+        self.engine
+            .get_all(table)
+            .map_err(|e| self.db_error(format_args!("Failed to get all keys from {table:?}"), &e))
+    }
+
+    /// Get the keys in `[from, to)` of the given table without reading the values,
+    /// an empty `to` means the end of the table
+    ///
+    /// # Errors
+    ///
+    /// if error occurs in storage, return `Err(error)`
+    pub(crate) fn scan_keys(
+        &self,
+        table: &'static str,
+        from: &[u8],
+        to: &[u8],
+    ) -> Result<Vec<Vec<u8>>, ExecuteError> {
+        self.engine.scan_keys(table, from, to).map_err(|e| {
+            self.db_error(
+                format_args!("Failed to scan keys in [{from:?}, {to:?}) from {table:?}"),
+                &e,
+            )
         })
     }
 
-    /// Get the snapshot of the storage
+    /// Get the snapshot of the replicated tables of the storage
     pub(crate) fn get_snapshot(
         &self,
         snap_path: impl AsRef<Path>,
     ) -> Result<Snapshot, ExecuteError> {
         self.engine
-            .get_snapshot(snap_path, &XLINE_TABLES)
+            .get_snapshot(snap_path, &REPLICATED_TABLES)
             .map_err(|e| ExecuteError::DbError(format!("Failed to get snapshot, error: {e}")))
     }
 
-    /// Reset the storage by given snapshot
+    /// Reset the replicated tables of the storage by given snapshot, the node local
+    /// tables are kept
     ///
     /// # Errors
     ///
@@ -157,21 +557,31 @@ impl DB {
     pub(crate) async fn reset(&self, snapshot: Option<Snapshot>) -> Result<(), ExecuteError> {
         if let Some(snap) = snapshot {
             self.engine
-                .apply_snapshot(snap, &XLINE_TABLES)
+                .apply_snapshot(snap, &REPLICATED_TABLES)
                 .await
-                .map_err(|e| ExecuteError::DbError(format!("Failed to reset database, error: {e}")))
+                .map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to reset database, error: {e}"))
+                })?;
         } else {
             let start = vec![];
             let end = vec![0xff];
-            let ops = XLINE_TABLES
+            let ops = REPLICATED_TABLES
                 .iter()
                 .map(|table| {
                     WriteOperation::new_delete_range(table, start.as_slice(), end.as_slice())
                 })
                 .collect();
-            self.engine
-                .write_batch(ops, true)
-                .map_err(|e| ExecuteError::DbError(format!("Failed to reset database, error: {e}")))
+            self.engine.write_batch(ops, true).map_err(|e| {
+                ExecuteError::DbError(format!("Failed to reset database, error: {e}"))
+            })?;
+        }
+        // the snapshot may be taken from a member that doesn't encrypt the values, or is
+        // encrypting them, they are encrypted here instead of refused
+        match self.transformer {
+            Some(ref transformer) => self
+                .rewrite_values(transformer.as_ref(), false)
+                .map(|_count| ()),
+            None => self.check_encryption(),
         }
     }
 
@@ -183,7 +593,12 @@ impl DB {
         let mut wr_ops = Vec::new();
         let mut revs = Vec::new();
         let del_lease_key_buffer = Self::get_del_lease_key_buffer(&ops);
+        let del_revoking_lease_key_buffer = Self::get_del_revoking_lease_key_buffer(&ops);
+        let del_lease_deletion_key_buffer = Self::get_del_lease_deletion_key_buffer(&ops);
         let del_alarm_buffer = Self::get_del_alarm_buffer(&ops);
+        let del_time_revision_key_buffer = Self::get_del_time_revision_key_buffer(&ops);
+        let del_applied_in_batch_key_buffer = Self::get_del_applied_in_batch_key_buffer(&ops);
+        let del_applied_proposals_buffer = Self::get_del_applied_proposals_buffer(&ops);
         for op in ops {
             let wop = match op {
                 WriteOp::PutKeyValue(rev, value) => {
@@ -197,13 +612,36 @@ impl DB {
                         ),
                     ));
                     let key = rev.encode_to_vec();
-                    WriteOperation::new_put(KV_TABLE, key, value.encode_to_vec())
+                    WriteOperation::new_put(KV_TABLE, key, self.encode_kv(value)?)
                 }
                 WriteOp::PutAppliedIndex(index) => WriteOperation::new_put(
                     META_TABLE,
                     APPLIED_INDEX_KEY.as_bytes().to_vec(),
                     index.to_le_bytes().to_vec(),
                 ),
+                WriteOp::PutAppliedInBatch(index, pos) => WriteOperation::new_put(
+                    META_TABLE,
+                    applied_in_batch_key(index),
+                    pos.to_le_bytes().to_vec(),
+                ),
+                WriteOp::DeleteAppliedInBatch(index) => {
+                    let key = del_applied_in_batch_key_buffer
+                        .get(&index)
+                        .unwrap_or_else(|| {
+                            panic!("index({index}) is not in del_applied_in_batch_key_buffer")
+                        });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
+                WriteOp::PutAppliedProposal(index, propose_id, results) => WriteOperation::new_put(
+                    META_TABLE,
+                    applied_proposal_key(index, propose_id),
+                    results,
+                ),
+                WriteOp::DeleteAppliedProposals(_index) => WriteOperation::new_delete_range(
+                    META_TABLE,
+                    APPLIED_PROPOSAL_PREFIX,
+                    del_applied_proposals_buffer.as_ref(),
+                ),
                 WriteOp::PutLease(lease) => WriteOperation::new_put(
                     LEASE_TABLE,
                     lease.id.encode_to_vec(),
@@ -219,6 +657,25 @@ impl DB {
                     SCHEDULED_COMPACT_REVISION.as_bytes().to_vec(),
                     rev.to_le_bytes().to_vec(),
                 ),
+                WriteOp::PutCompactExemption(prefixes) => WriteOperation::new_put(
+                    META_TABLE,
+                    COMPACT_EXEMPTION_KEY.as_bytes().to_vec(),
+                    prefixes,
+                ),
+                WriteOp::PutStateHash(state) => {
+                    WriteOperation::new_put(META_TABLE, STATE_HASH_KEY.as_bytes().to_vec(), state)
+                }
+                WriteOp::PutTimeRevision(millis, rev) => {
+                    WriteOperation::new_put(META_TABLE, time_revision_key(millis, rev), vec![])
+                }
+                WriteOp::DeleteTimeRevision(millis, _rev) => {
+                    let key = del_time_revision_key_buffer
+                        .get(&millis)
+                        .unwrap_or_else(|| {
+                            panic!("time({millis}) is not in del_time_revision_key_buffer")
+                        });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
                 WriteOp::DeleteKeyValue(rev) => WriteOperation::new_delete(KV_TABLE, rev),
                 WriteOp::DeleteLease(lease_id) => {
                     let key = del_lease_key_buffer.get(&lease_id).unwrap_or_else(|| {
@@ -226,6 +683,37 @@ impl DB {
                     });
                     WriteOperation::new_delete(LEASE_TABLE, key)
                 }
+                WriteOp::PutLeaseExpiry(lease_id, value) => {
+                    WriteOperation::new_put(LEASE_EXPIRY_TABLE, lease_id.encode_to_vec(), value)
+                }
+                WriteOp::DeleteLeaseExpiry(lease_id) => {
+                    let key = del_lease_key_buffer.get(&lease_id).unwrap_or_else(|| {
+                        panic!("lease_id({lease_id}) is not in del_lease_key_buffer")
+                    });
+                    WriteOperation::new_delete(LEASE_EXPIRY_TABLE, key)
+                }
+                WriteOp::PutRevokingLease(lease_id) => {
+                    WriteOperation::new_put(META_TABLE, revoking_lease_key(lease_id), vec![])
+                }
+                WriteOp::DeleteRevokingLease(lease_id) => {
+                    let key = del_revoking_lease_key_buffer
+                        .get(&lease_id)
+                        .unwrap_or_else(|| {
+                            panic!("lease_id({lease_id}) is not in del_revoking_lease_key_buffer")
+                        });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
+                WriteOp::PutLeaseDeletion(deletion) => {
+                    WriteOperation::new_put(META_TABLE, deletion.key(), deletion.encode())
+                }
+                WriteOp::DeleteLeaseDeletion(rev, sub_rev) => {
+                    let key = del_lease_deletion_key_buffer
+                        .get(&(rev, sub_rev))
+                        .unwrap_or_else(|| {
+                            panic!("lease deletion({rev}, {sub_rev}) is not in del_lease_deletion_key_buffer")
+                        });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
                 WriteOp::PutAuthEnable(enable) => WriteOperation::new_put(
                     AUTH_TABLE,
                     AUTH_ENABLE_KEY.to_vec(),
@@ -262,21 +750,35 @@ impl DB {
         }
         self.engine
             .write_batch(wr_ops, false)
-            .map_err(|e| ExecuteError::DbError(format!("Failed to flush ops, error: {e}")))?;
+            .map_err(|e| self.db_error("Failed to flush ops", &e))?;
         Ok(revs)
     }
 
-    /// Calculate the hash of the storage
+    /// Calculate the hash of the storage, node local tables are skipped
     pub(crate) fn hash(&self) -> Result<u32, ExecuteError> {
         let mut hasher = crc32fast::Hasher::new();
-        for table in XLINE_TABLES {
+        for table in REPLICATED_TABLES {
             hasher.update(table.as_bytes());
             let kv_pairs = self.engine.get_all(table).map_err(|e| {
-                ExecuteError::DbError(format!("Failed to get all keys from {table:?}: {e}"))
+                self.db_error(format_args!("Failed to get all keys from {table:?}"), &e)
             })?;
             for (k, v) in kv_pairs {
+                // the layout fields depend on the version of each node, whether the
+                // values are encrypted depends on its config, and the execution results
+                // of the applied proposals on whether it executed them
+                if table == META_TABLE
+                    && (LAYOUT_KEYS.iter().any(|key| k == key.as_bytes())
+                        || k == VALUE_ENCRYPTION_KEY.as_bytes()
+                        || k.starts_with(APPLIED_PROPOSAL_PREFIX))
+                {
+                    continue;
+                }
                 hasher.update(&k);
-                hasher.update(&v);
+                if table == KV_TABLE {
+                    hasher.update(&self.plain_kv(v)?);
+                } else {
+                    hasher.update(&v);
+                }
             }
         }
         Ok(hasher.finalize())
@@ -295,6 +797,95 @@ impl DB {
     }
 }
 
+/// The keys of a revoked lease deleted at a revision, their tombstones are written after
+/// the revocation is applied
+///
+/// It's persisted along with the revocation and removed once the tombstones are written,
+/// so that the tombstones left are written at the same sub revisions after a restart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseKeyDeletion {
+    /// The revision the keys are deleted at
+    revision: i64,
+    /// The deleted keys along with their sub revisions, in ascending order
+    keys: Vec<(i64, Vec<u8>)>,
+}
+
+impl LeaseKeyDeletion {
+    /// New `LeaseKeyDeletion`
+    pub(crate) fn new(revision: i64, keys: Vec<(i64, Vec<u8>)>) -> Self {
+        Self { revision, keys }
+    }
+
+    /// The revision the keys are deleted at
+    pub(crate) fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// The first sub revision of the deleted keys
+    pub(crate) fn sub_revision(&self) -> i64 {
+        self.keys
+            .first()
+            .map_or(0, |&(sub_revision, _)| sub_revision)
+    }
+
+    /// The deleted keys
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.keys.iter().map(|&(_, ref key)| key.as_slice())
+    }
+
+    /// The tombstones of the deleted keys
+    pub(crate) fn tombstones(&self) -> impl Iterator<Item = (Revision, KeyValue)> + '_ {
+        self.keys.iter().map(|&(sub_revision, ref key)| {
+            let tombstone = KeyValue {
+                key: key.clone(),
+                mod_revision: self.revision,
+                ..KeyValue::default()
+            };
+            (Revision::new(self.revision, sub_revision), tombstone)
+        })
+    }
+
+    /// The writes of the tombstones, along with the removal of the deletion
+    pub(crate) fn ops(&self) -> Vec<WriteOp<'static>> {
+        self.tombstones()
+            .map(|(rev, kv)| WriteOp::PutKeyValue(rev, kv))
+            .chain([WriteOp::DeleteLeaseDeletion(
+                self.revision,
+                self.sub_revision(),
+            )])
+            .collect()
+    }
+
+    /// Key of the deletion in the meta table
+    fn key(&self) -> Vec<u8> {
+        lease_deletion_key(self.revision, self.sub_revision())
+    }
+
+    /// Encode the deleted keys
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.keys)
+            .unwrap_or_else(|e| unreachable!("the deleted keys are always encodable: {e}"))
+    }
+
+    /// Decode a deletion stored in the meta table
+    pub(crate) fn decode(key: &[u8], value: &[u8]) -> Result<Self, ExecuteError> {
+        let revision = key
+            .strip_prefix(LEASE_DELETION_PREFIX)
+            .and_then(|rest| rest.get(..8))
+            .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+            .map(i64::from_be_bytes)
+            .ok_or_else(|| {
+                ExecuteError::DbError(format!("cannot decode the lease deletion key {key:?}"))
+            })?;
+        let keys = serde_json::from_slice(value).map_err(|e| {
+            ExecuteError::DbError(format!(
+                "cannot decode the lease deletion from META_TABLE: {e}"
+            ))
+        })?;
+        Ok(Self { revision, keys })
+    }
+}
+
 /// Buffered Write Operation
 #[derive(Debug, Clone)]
 #[non_exhaustive]
@@ -303,16 +894,46 @@ pub enum WriteOp<'a> {
     PutKeyValue(Revision, KeyValue),
     /// Put the applied index to meta table
     PutAppliedIndex(u64),
+    /// Put the position of the last applied command of a batched entry to meta table
+    PutAppliedInBatch(u64, u64),
+    /// Delete the position of the last applied command of a batched entry from meta table
+    DeleteAppliedInBatch(u64),
+    /// Put the encoded results of a proposal applied in a log entry to meta table
+    PutAppliedProposal(u64, (u64, u64), Vec<u8>),
+    /// Delete the results of the proposals applied before a log entry from meta table
+    DeleteAppliedProposals(u64),
     /// Put a lease to lease table
     PutLease(PbLease),
     /// Put a finished compact revision into meta table
     PutFinishedCompactRevision(i64),
     /// Put a scheduled compact revision into meta table
     PutScheduledCompactRevision(i64),
+    /// Put the encoded compaction exempt prefixes into meta table
+    PutCompactExemption(Vec<u8>),
+    /// Put the encoded state hash, the checkpoint and the contributions folded ahead of
+    /// it, into meta table
+    PutStateHash(Vec<u8>),
+    /// Put the revision applied at a wall time in milliseconds into meta table
+    PutTimeRevision(u64, i64),
+    /// Delete the record of a wall time and its revision from meta table
+    DeleteTimeRevision(u64, i64),
     /// Delete a key-value pair from kv table
     DeleteKeyValue(&'a [u8]),
     /// Delete a lease from lease table
     DeleteLease(i64),
+    /// Put the encoded remaining time of a lease observed locally to lease expiry table
+    PutLeaseExpiry(i64, Vec<u8>),
+    /// Delete the remaining time of a lease from lease expiry table
+    DeleteLeaseExpiry(i64),
+    /// Put the marker of a lease whose revocation is continued by later applies to meta table
+    PutRevokingLease(i64),
+    /// Delete the revoking marker of a lease from meta table
+    DeleteRevokingLease(i64),
+    /// Put the keys of a revoked lease whose tombstones are written later to meta table
+    PutLeaseDeletion(Arc<LeaseKeyDeletion>),
+    /// Delete the keys of a revoked lease deleted at a revision from a sub revision on
+    /// from meta table, once their tombstones are written
+    DeleteLeaseDeletion(i64, i64),
     /// Put a auth enable flag to auth table
     PutAuthEnable(bool),
     /// Put a auth revision to auth table
@@ -339,7 +960,37 @@ mod test {
     use test_macros::abort_on_panic;
 
     use super::*;
-    use crate::storage::Revision;
+    use crate::storage::{value_transformer::AesGcm, Revision};
+
+    /// A transformer with the given key versions, each key is filled with its version
+    fn transformer(versions: &[u32]) -> Arc<dyn ValueTransformer> {
+        let keys = versions
+            .iter()
+            .map(|&version| (version, vec![u8::try_from(version).unwrap(); 32]))
+            .collect();
+        Arc::new(AesGcm::new(keys).unwrap())
+    }
+
+    /// Put a kv pair at the main revision `rev`
+    fn put(db: &DB, rev: i64, key: &str, value: &str) -> KeyValue {
+        let kv = KeyValue {
+            key: key.into(),
+            value: value.to_owned().into(),
+            mod_revision: rev,
+            ..Default::default()
+        };
+        let ops = vec![WriteOp::PutKeyValue(Revision::new(rev, 0), kv.clone())];
+        _ = db.flush_ops(ops).unwrap();
+        kv
+    }
+
+    /// Read the kv pair at the main revision `rev`, as it is stored
+    fn raw(db: &DB, rev: i64) -> Vec<u8> {
+        db.get_value(KV_TABLE, Revision::new(rev, 0).encode_to_vec())
+            .unwrap()
+            .unwrap()
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn test_reset() -> Result<(), ExecuteError> {
@@ -368,6 +1019,56 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn failed_read_should_mark_db_corrupted() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let revision = Revision::new(1, 1);
+        let ops = vec![WriteOp::PutKeyValue(revision, KeyValue::default())];
+        _ = db.flush_ops(ops)?;
+        assert!(db.corruption().is_none());
+
+        db.inject_read_fault(KV_TABLE);
+        assert!(db.get_value(KV_TABLE, revision.encode_to_vec()).is_err());
+        let reason = tokio::time::timeout(std::time::Duration::from_secs(1), db.corrupted())
+            .await
+            .unwrap();
+        assert!(reason.contains("Failed to get key"));
+
+        // only the first corruption is kept
+        assert!(db.get_all(KV_TABLE).is_err());
+        assert_eq!(db.corruption(), Some(reason));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn data_dir_requiring_newer_version_should_be_refused() -> Result<(), ExecuteError> {
+        let data_dir = PathBuf::from("/tmp/data_dir_requiring_newer_version_should_be_refused");
+        let config = EngineConfig::RocksDB(data_dir.clone());
+        let db = DB::open(&config)?;
+        let hash = db.hash()?;
+        let stored = StoredLayout::read(&db)?.unwrap();
+        assert_eq!(stored.writer, Version::running());
+
+        // a newer binary that made an incompatible change opened the data dir
+        let newer = StoredLayout {
+            layout: 2,
+            writer: Version::new(99, 0, 0),
+            min_compatible: Version::new(99, 0, 0),
+        };
+        db.engine.write_batch(newer.to_ops(), true).unwrap();
+        // the layout fields don't take part in the hash
+        assert_eq!(db.hash()?, hash);
+        drop(db);
+
+        let err = DB::open(&config).unwrap_err();
+        assert!(err.to_string().contains("requires at least xline 99.0.0"));
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+        Ok(())
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn test_db_snapshot() -> Result<(), ExecuteError> {
@@ -430,6 +1131,149 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn encrypted_values_should_round_trip() -> Result<(), ExecuteError> {
+        let db = DB::open_with_transformer(&EngineConfig::Memory, Some(transformer(&[1])))?;
+        let kv = put(&db, 1, "foo", "bar");
+        let stored = raw(&db, 1);
+        assert_ne!(stored, kv.encode_to_vec());
+        assert!(!stored.windows(3).any(|w| w == b"bar"));
+        assert_eq!(db.decode_kv(stored)?, kv);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn encrypted_values_should_be_bound_to_their_kv_pairs() -> Result<(), ExecuteError> {
+        let db = DB::open_with_transformer(&EngineConfig::Memory, Some(transformer(&[1])))?;
+        let kv = put(&db, 1, "foo", "bar");
+        let stored = KeyValue::decode(raw(&db, 1).as_slice()).unwrap();
+        // the value moved to another key, or to another revision of the key
+        for (key, rev) in [("baz", 1), ("foo", 2)] {
+            let moved = KeyValue {
+                key: key.into(),
+                mod_revision: rev,
+                ..stored.clone()
+            };
+            assert!(db.decode_kv(moved.encode_to_vec()).is_err());
+        }
+        assert_eq!(db.decode_kv(stored.encode_to_vec())?, kv);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn encrypted_data_dir_should_need_its_keys() -> Result<(), ExecuteError> {
+        let data_dir = PathBuf::from("/tmp/encrypted_data_dir_should_need_its_keys");
+        let config = EngineConfig::RocksDB(data_dir.clone());
+        let db = DB::open_with_transformer(&config, Some(transformer(&[1])))?;
+        let _kv = put(&db, 1, "foo", "bar");
+        drop(db);
+
+        let err = DB::open(&config).unwrap_err();
+        assert!(err.to_string().contains("no encryption key"), "{err}");
+        let db = DB::open_with_transformer(&config, Some(transformer(&[2])))?;
+        let err = db.decode_kv(raw(&db, 1)).unwrap_err();
+        assert!(err.to_string().contains("version 1"), "{err}");
+        drop(db);
+
+        // a plain data dir is encrypted offline first
+        let plain_dir = data_dir.join("plain");
+        let plain_config = EngineConfig::RocksDB(plain_dir);
+        let db = DB::open(&plain_config)?;
+        let _kv = put(&db, 1, "foo", "bar");
+        drop(db);
+        assert!(DB::open_with_transformer(&plain_config, Some(transformer(&[1]))).is_err());
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn encrypted_snapshot_should_be_restorable_with_the_keys() -> Result<(), ExecuteError> {
+        let dir = PathBuf::from("/tmp/encrypted_snapshot_should_be_restorable_with_the_keys");
+        let origin_db = DB::open_with_transformer(
+            &EngineConfig::RocksDB(dir.join("origin_db")),
+            Some(transformer(&[1])),
+        )?;
+        let kv = put(&origin_db, 1, "foo", "bar");
+        let snapshot = origin_db.get_snapshot(dir.join("snapshot"))?;
+
+        let new_db = DB::open_with_transformer(
+            &EngineConfig::RocksDB(dir.join("new_db")),
+            Some(transformer(&[1])),
+        )?;
+        new_db.reset(Some(snapshot)).await?;
+        assert_eq!(new_db.decode_kv(raw(&new_db, 1))?, kv);
+
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn plain_snapshot_should_be_encrypted_when_restored() -> Result<(), ExecuteError> {
+        let dir = PathBuf::from("/tmp/plain_snapshot_should_be_encrypted_when_restored");
+        let origin_db = DB::open(&EngineConfig::RocksDB(dir.join("origin_db")))?;
+        let kv = put(&origin_db, 1, "foo", "bar");
+        let snapshot = origin_db.get_snapshot(dir.join("snapshot"))?;
+
+        let new_db = DB::open_with_transformer(
+            &EngineConfig::RocksDB(dir.join("new_db")),
+            Some(transformer(&[1])),
+        )?;
+        new_db.reset(Some(snapshot)).await?;
+        let stored = KeyValue::decode(raw(&new_db, 1).as_slice()).unwrap();
+        assert_eq!(AesGcm::version_of(&stored.value)?, 1);
+        assert_eq!(new_db.decode_kv(raw(&new_db, 1))?, kv);
+
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn hash_should_not_depend_on_the_encryption() -> Result<(), ExecuteError> {
+        let plain_db = DB::open(&EngineConfig::Memory)?;
+        let encrypted_db =
+            DB::open_with_transformer(&EngineConfig::Memory, Some(transformer(&[1])))?;
+        for db in [&plain_db, &encrypted_db] {
+            let _kv = put(db, 1, "foo", "bar");
+            let _kv = put(db, 2, "foo", "baz");
+        }
+        assert_eq!(plain_db.hash()?, encrypted_db.hash()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn reencrypt_should_rotate_the_values() -> Result<(), ExecuteError> {
+        let data_dir = PathBuf::from("/tmp/reencrypt_should_rotate_the_values");
+        let config = EngineConfig::RocksDB(data_dir.clone());
+        let db = DB::open(&config)?;
+        let plain_kv = put(&db, 1, "foo", "bar");
+        drop(db);
+        assert_eq!(DB::reencrypt(&config, transformer(&[1]))?, 1);
+
+        let db = DB::open_with_transformer(&config, Some(transformer(&[1])))?;
+        let rotated_kv = put(&db, 2, "foo", "baz");
+        drop(db);
+        assert_eq!(DB::reencrypt(&config, transformer(&[1, 2]))?, 2);
+
+        // the retired key is not needed any more
+        let db = DB::open_with_transformer(&config, Some(transformer(&[2])))?;
+        for (rev, kv) in [(1, plain_kv), (2, rotated_kv)] {
+            let stored = KeyValue::decode(raw(&db, rev).as_slice()).unwrap();
+            assert_eq!(AesGcm::version_of(&stored.value)?, 2);
+            assert_eq!(db.decode_kv(raw(&db, rev))?, kv);
+        }
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+        Ok(())
+    }
+
     #[tokio::test]
     #[abort_on_panic]
     async fn test_db_write_ops() {
@@ -438,6 +1282,7 @@ mod test {
             id: 1,
             ttl: 10,
             remaining_ttl: 10,
+            owner: String::new(),
         };
         let lease_bytes = lease.encode_to_vec();
         let user = User {
@@ -503,4 +1348,30 @@ mod test {
         assert_eq!(db.get_value(USER_TABLE, b"user").unwrap(), None);
         assert_eq!(db.get_value(ROLE_TABLE, b"role").unwrap(), None);
     }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn lease_expiries_should_not_be_in_snapshot_or_reset() -> Result<(), ExecuteError> {
+        let dir = PathBuf::from("/tmp/lease_expiries_should_not_be_in_snapshot_or_reset");
+        let origin_db = DB::open(&EngineConfig::RocksDB(dir.join("origin_db")))?;
+        let kv = put(&origin_db, 1, "foo", "bar");
+        _ = origin_db.flush_ops(vec![WriteOp::PutLeaseExpiry(1, vec![1])])?;
+        let snapshot = origin_db.get_snapshot(dir.join("snapshot"))?;
+
+        let new_db = DB::open(&EngineConfig::RocksDB(dir.join("new_db")))?;
+        _ = new_db.flush_ops(vec![WriteOp::PutLeaseExpiry(2, vec![2])])?;
+        new_db.reset(Some(snapshot)).await?;
+        assert_eq!(new_db.decode_kv(raw(&new_db, 1))?, kv);
+        let local = |db: &DB| db.engine.get_all(LEASE_EXPIRY_TABLE).unwrap();
+        assert_eq!(local(&new_db), vec![(2_i64.encode_to_vec(), vec![2])]);
+
+        new_db.reset(None).await?;
+        assert!(new_db
+            .get_value(KV_TABLE, Revision::new(1, 0).encode_to_vec())?
+            .is_none());
+        assert_eq!(local(&new_db), vec![(2_i64.encode_to_vec(), vec![2])]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
 }
@@ -7,14 +7,20 @@ use parking_lot::RwLock;
 use utils::parking_lot_lock::RwLockMap;
 use xlineapi::command::KeyRange;
 
-use super::revision::{KeyRevision, Revision};
-use crate::server::command::RangeType;
+use super::{
+    compact::ExemptPrefixes,
+    revision::{KeyRevision, Revision},
+};
+use crate::rpc::{SortOrder, SortTarget};
 
 /// Keys to revisions mapping
 #[derive(Debug)]
 pub(crate) struct Index {
     /// Inner struct of `Index`
     inner: SkipMap<Vec<u8>, RwLock<Vec<KeyRevision>>>,
+    /// Held exclusively while a batch of keys is deleted and shared by range reads, so
+    /// that a range read never observes part of a batch
+    batch_lock: RwLock<()>,
 }
 
 impl Index {
@@ -22,6 +28,7 @@ impl Index {
     pub(crate) fn new() -> Self {
         Self {
             inner: SkipMap::new(),
+            batch_lock: RwLock::new(()),
         }
     }
 
@@ -35,6 +42,11 @@ impl Index {
 
     /// Get specified or last `KeyRevision` if the key is not deleted, and convert to `Revision`
     fn get_revision(revs: &[KeyRevision], revision: i64) -> Option<Revision> {
+        Self::get_key_revision(revs, revision).map(|rev| rev.as_revision())
+    }
+
+    /// Get specified or last `KeyRevision` if the key is not deleted
+    fn get_key_revision(revs: &[KeyRevision], revision: i64) -> Option<KeyRevision> {
         let rev = if revision <= 0 {
             revs.last()
         } else {
@@ -49,8 +61,76 @@ impl Index {
             };
             revs.get(idx)
         };
-        rev.filter(|kr| !kr.is_deleted())
-            .map(KeyRevision::as_revision)
+        rev.filter(|kr| !kr.is_deleted()).copied()
+    }
+
+    /// Get the `Revision` of the first key of a range in the order of the sort target, and
+    /// the number of keys in the range, without collecting the revisions of all keys.
+    ///
+    /// Keys are walked in key order and a key only replaces a strictly smaller (or greater
+    /// when descending) one, so ties are broken like a stable sort of the whole range.
+    /// Values are not in the index, so `SortTarget::Value` is not supported, neither is a
+    /// single key range.
+    pub(crate) fn get_first_sorted(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        sort_target: SortTarget,
+        sort_order: SortOrder,
+    ) -> (Option<Revision>, usize) {
+        let descend = sort_order == SortOrder::Descend;
+        let mut first: Option<KeyRevision> = None;
+        let mut total = 0_usize;
+        let _batch = self.batch_lock.read();
+        for entry in self.inner.range(KeyRange::new(key, range_end)) {
+            let Some(rev) = entry
+                .value()
+                .map_read(|revs| Self::get_key_revision(revs.as_ref(), revision))
+            else {
+                continue;
+            };
+            total = total.overflow_add(1);
+            let replace = first.as_ref().map_or(true, |cur| {
+                let ordering = match sort_target {
+                    // keys are walked in ascending order
+                    SortTarget::Key => std::cmp::Ordering::Greater,
+                    SortTarget::Version => rev.version.cmp(&cur.version),
+                    SortTarget::Create => rev.create_revision.cmp(&cur.create_revision),
+                    SortTarget::Mod => rev.mod_revision.cmp(&cur.mod_revision),
+                    SortTarget::Value => unreachable!("values are not in the index"),
+                };
+                if descend {
+                    ordering.is_gt()
+                } else {
+                    ordering.is_lt()
+                }
+            });
+            if replace {
+                first = Some(rev);
+            }
+        }
+        (first.map(|rev| rev.as_revision()), total)
+    }
+
+    /// Delete the given keys at the same revision as a whole, the key at position `i`
+    /// takes sub revision `sub_revision + i`. Range reads observe either all or none of
+    /// the deletions.
+    pub(crate) fn delete_keys(
+        &self,
+        keys: &[Vec<u8>],
+        revision: i64,
+        sub_revision: i64,
+    ) -> (Vec<(Revision, Revision)>, Vec<Vec<u8>>) {
+        let _batch = self.batch_lock.write();
+        let mut pairs = Vec::with_capacity(keys.len());
+        let mut deleted = Vec::with_capacity(keys.len());
+        for (key, sub_revision) in keys.iter().zip(sub_revision..) {
+            let (mut revisions, mut del_keys) = self.delete(key, &[], revision, sub_revision);
+            pairs.append(&mut revisions);
+            deleted.append(&mut del_keys);
+        }
+        (pairs, deleted)
     }
 
     /// Insert `KeyRevision` of deleted and generate `Revision` pair of deleted
@@ -126,14 +206,16 @@ pub(super) trait IndexOperate {
 
     /// Compact a `KeyRevision` by removing the versions with smaller or equal
     /// revision than the given atRev except the largest one (If the largest one is
-    /// a tombstone, it will not be kept).
-    fn compact(&self, at_rev: i64) -> Vec<KeyRevision>;
+    /// a tombstone, it will not be kept). The keys under the exempt prefixes keep all
+    /// of their versions.
+    fn compact(&self, at_rev: i64, exempt: &ExemptPrefixes) -> Vec<KeyRevision>;
 }
 
 impl IndexOperate for Index {
     fn get(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<Revision> {
-        match RangeType::get_range_type(key, range_end) {
-            RangeType::OneKey => self
+        let range = KeyRange::new(key, range_end);
+        if range.is_single() {
+            return self
                 .inner
                 .get(key)
                 .and_then(|entry| {
@@ -142,31 +224,23 @@ impl IndexOperate for Index {
                         .map_read(|revs| Self::get_revision(revs.as_ref(), revision))
                 })
                 .map(|rev| vec![rev])
-                .unwrap_or_default(),
-            RangeType::AllKeys => self
-                .inner
-                .iter()
-                .filter_map(|entry| {
-                    entry
-                        .value()
-                        .map_read(|revs| Self::get_revision(revs.as_ref(), revision))
-                })
-                .collect(),
-            RangeType::Range => self
-                .inner
-                .range(KeyRange::new(key, range_end))
-                .filter_map(|entry| {
-                    entry
-                        .value()
-                        .map_read(|revs| Self::get_revision(revs.as_ref(), revision))
-                })
-                .collect(),
+                .unwrap_or_default();
         }
+        let _batch = self.batch_lock.read();
+        self.inner
+            .range(range)
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .map_read(|revs| Self::get_revision(revs.as_ref(), revision))
+            })
+            .collect()
     }
 
     fn get_from_rev(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<Revision> {
-        match RangeType::get_range_type(key, range_end) {
-            RangeType::OneKey => self
+        let range = KeyRange::new(key, range_end);
+        if range.is_single() {
+            return self
                 .inner
                 .get(key)
                 .map(|entry| {
@@ -174,28 +248,18 @@ impl IndexOperate for Index {
                         .value()
                         .map_read(|revs| Self::filter_revision(revs.as_ref(), revision))
                 })
-                .unwrap_or_default(),
-            RangeType::AllKeys => self
-                .inner
-                .iter()
-                .flat_map(|entry| {
-                    entry
-                        .value()
-                        .map_read(|revs| Self::filter_revision(revs.as_ref(), revision))
-                })
-                .sorted()
-                .collect(),
-            RangeType::Range => self
-                .inner
-                .range(KeyRange::new(key, range_end))
-                .flat_map(|entry| {
-                    entry
-                        .value()
-                        .map_read(|revs| Self::filter_revision(revs.as_ref(), revision))
-                })
-                .sorted()
-                .collect(),
+                .unwrap_or_default();
         }
+        let _batch = self.batch_lock.read();
+        self.inner
+            .range(range)
+            .flat_map(|entry| {
+                entry
+                    .value()
+                    .map_read(|revs| Self::filter_revision(revs.as_ref(), revision))
+            })
+            .sorted()
+            .collect()
     }
 
     fn delete(
@@ -205,57 +269,38 @@ impl IndexOperate for Index {
         revision: i64,
         sub_revision: i64,
     ) -> (Vec<(Revision, Revision)>, Vec<Vec<u8>>) {
-        let (pairs, keys) = match RangeType::get_range_type(key, range_end) {
-            RangeType::OneKey => {
-                let pairs: Vec<(Revision, Revision)> = self
-                    .inner
-                    .get(key)
-                    .into_iter()
-                    .filter_map(|entry| {
-                        entry.value().map_write(|mut revs| {
-                            Self::gen_del_revision(revs.as_mut(), revision, sub_revision)
-                        })
-                    })
-                    .collect();
-                let keys = if pairs.is_empty() {
-                    vec![]
-                } else {
-                    vec![key.to_vec()]
-                };
-                (pairs, keys)
-            }
-            RangeType::AllKeys => self
+        let range = KeyRange::new(key, range_end);
+        if range.is_single() {
+            let pairs: Vec<(Revision, Revision)> = self
                 .inner
-                .iter()
-                .zip(0..)
-                .filter_map(|(entry, i)| {
+                .get(key)
+                .into_iter()
+                .filter_map(|entry| {
                     entry.value().map_write(|mut revs| {
-                        Self::gen_del_revision(
-                            revs.as_mut(),
-                            revision,
-                            sub_revision.overflow_add(i),
-                        )
-                        .map(|pair| (pair, entry.key().clone()))
+                        Self::gen_del_revision(revs.as_mut(), revision, sub_revision)
                     })
                 })
-                .unzip(),
-            RangeType::Range => self
-                .inner
-                .range(KeyRange::new(key, range_end))
-                .zip(0..)
-                .filter_map(|(entry, i)| {
-                    entry.value().map_write(|mut revs| {
-                        Self::gen_del_revision(
-                            revs.as_mut(),
-                            revision,
-                            sub_revision.overflow_add(i),
-                        )
-                        .map(|pair| (pair, entry.key().clone()))
-                    })
+                .collect();
+            let keys = if pairs.is_empty() {
+                vec![]
+            } else {
+                vec![key.to_vec()]
+            };
+            return (pairs, keys);
+        }
+        // Only the keys actually deleted take sub revisions, the caller continues from
+        // `sub_revision` plus the number of deleted keys
+        let mut next_sub_revision = sub_revision;
+        self.inner
+            .range(range)
+            .filter_map(|entry| {
+                entry.value().map_write(|mut revs| {
+                    let pair = Self::gen_del_revision(revs.as_mut(), revision, next_sub_revision)?;
+                    next_sub_revision = next_sub_revision.overflow_add(1);
+                    Some((pair, entry.key().clone()))
                 })
-                .unzip(),
-        };
-        (pairs, keys)
+            })
+            .unzip()
     }
 
     fn insert(&self, key_revisions: Vec<(Vec<u8>, KeyRevision)>) {
@@ -312,11 +357,14 @@ impl IndexOperate for Index {
             });
     }
 
-    fn compact(&self, at_rev: i64) -> Vec<KeyRevision> {
+    fn compact(&self, at_rev: i64, exempt: &ExemptPrefixes) -> Vec<KeyRevision> {
         let mut revs = Vec::new();
         let mut del_keys = Vec::new();
 
         self.inner.iter().for_each(|entry| {
+            if exempt.is_exempt(entry.key()) {
+                return;
+            }
             entry.value().map_write(|mut revisions| {
                 if let Some(revision) = revisions.first() {
                     if revision.mod_revision < at_rev {
@@ -506,6 +554,19 @@ mod test {
                 KeyRevision::new_deletion(11, 0),
             ],
         );
+
+        // the deleted keys should not take sub revisions
+        index.insert(vec![(
+            b"foo".to_vec(),
+            index.register_revision(b"foo", 13, 0),
+        )]);
+        assert_eq!(
+            index.delete(b"a", b"g", 14, 0),
+            (
+                vec![(Revision::new(13, 0), Revision::new(14, 0))],
+                vec![b"foo".to_vec()]
+            )
+        );
     }
 
     #[test]
@@ -524,7 +585,7 @@ mod test {
     #[test]
     fn test_compact() {
         let index = init_and_test_insert();
-        let res = index.compact(7);
+        let res = index.compact(7, &ExemptPrefixes::default());
         match_values(&index, b"key", &[KeyRevision::new(1, 3, 3, 1)]);
 
         match_values(
@@ -558,7 +619,7 @@ mod test {
             index.register_revision(b"bar", 11, 0),
         )]);
 
-        let res = index.compact(10);
+        let res = index.compact(10, &ExemptPrefixes::default());
 
         match_values(&index, b"key", &[KeyRevision::new(1, 3, 3, 1)]);
 
@@ -580,4 +641,34 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_compact_should_keep_the_history_of_exempt_keys() {
+        let index = init_and_test_insert();
+        index.delete(b"a", b"g", 10, 0);
+
+        let res = index.compact(10, &ExemptPrefixes::new([b"fo".to_vec()], 0));
+
+        match_values(
+            &index,
+            b"foo",
+            &[
+                KeyRevision::new(4, 1, 4, 5),
+                KeyRevision::new(4, 2, 6, 6),
+                KeyRevision::new(4, 3, 8, 8),
+                KeyRevision::new(0, 0, 10, 1),
+            ],
+        );
+        assert_eq!(
+            res,
+            vec![
+                KeyRevision::new(5, 1, 5, 4),
+                KeyRevision::new(5, 2, 7, 7),
+                KeyRevision::new(5, 3, 9, 9),
+                KeyRevision::new(0, 0, 10, 0),
+                KeyRevision::new(1, 1, 1, 3),
+                KeyRevision::new(1, 2, 2, 2),
+            ]
+        );
+    }
 }
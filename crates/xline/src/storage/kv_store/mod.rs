@@ -0,0 +1,3103 @@
+#![allow(clippy::multiple_inherent_impl)]
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicI64, Ordering::Relaxed},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use parking_lot::{Mutex, RwLock};
+use prost::Message;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use utils::table_names::{KV_TABLE, META_TABLE};
+use xlineapi::{
+    command::{CommandResponse, KeyRange, SyncResponse},
+    execute_error::ExecuteError,
+};
+
+use super::{
+    apply_error::ApplyError,
+    apply_fence::ApplyFence,
+    compact::{CompactTask, ExemptPrefixes, COMPACT_EXEMPTION_KEY},
+    db::{DB, LEASE_DELETION_PREFIX, SCHEDULED_COMPACT_REVISION},
+    index::{Index, IndexOperate},
+    kvwatcher::{InternalEvent, KvUpdates},
+    lease_store::LeaseCollection,
+    revision::{KeyRevision, Revision},
+};
+use crate::{
+    header_gen::HeaderGenerator,
+    revision_check::RevisionCheck,
+    revision_number::{RevisionNumberGenerator, SubRevisions},
+    rpc::{
+        CompactionExemptionRequest, CompactionExemptionResponse, CompactionRequest,
+        CompactionResponse, Compare, CompareResult, CompareTarget, DeleteRangeRequest,
+        DeleteRangeResponse, Event, EventType, KeyValue, PutRequest, PutResponse, RangeRequest,
+        RangeResponse, Request, RequestWrapper, ResponseHeader, ResponseWrapper, SortOrder,
+        SortTarget, TargetUnion, TxnRequest, TxnResponse,
+    },
+    storage::db::{LeaseKeyDeletion, WriteOp, FINISHED_COMPACT_REVISION},
+};
+
+/// Number of kv pairs read at once when the index is rebuilt
+const RECOVER_BATCH_SIZE: usize = 64;
+
+/// Max number of the deleted keys of revoked leases whose tombstones are written at once
+pub(crate) const LEASE_DELETION_BATCH_SIZE: usize = 1024;
+
+/// KV store
+#[derive(Debug)]
+pub(crate) struct KvStore {
+    /// Kv storage inner
+    inner: Arc<KvStoreInner>,
+    /// Revision
+    revision: Arc<RevisionNumberGenerator>,
+    /// Header generator
+    header_gen: Arc<HeaderGenerator>,
+    /// KV update sender
+    kv_update_tx: mpsc::Sender<KvUpdates>,
+    /// Compact task submit sender
+    compact_task_tx: mpsc::Sender<CompactTask>,
+    /// Lease collection
+    lease_collection: Arc<LeaseCollection>,
+    /// The configured compaction exempt prefixes, they are stored on the first recovery
+    /// and changed only through the log later
+    configured_exempt_prefixes: Vec<Vec<u8>>,
+}
+
+/// Progress of syncing revisions to the storage
+#[derive(Debug, Default)]
+struct SyncState {
+    /// The highest revision that has been synced
+    synced: i64,
+    /// Revisions that are being synced
+    syncing: BTreeSet<i64>,
+}
+
+/// Marks a revision as synced when dropped
+#[derive(Debug)]
+pub(crate) struct SyncGuard<'a> {
+    /// The kv store
+    kv_store: &'a KvStoreInner,
+    /// The revision being synced
+    revision: i64,
+    /// Whether the writes of the revision are lost, it's never synced then
+    abandoned: bool,
+}
+
+impl SyncGuard<'_> {
+    /// Abandon the sync as its writes failed to be persisted, the revision and those
+    /// above it are never reported as synced
+    pub(crate) fn abandon(mut self) {
+        self.abandoned = true;
+    }
+}
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        if !self.abandoned {
+            self.kv_store.finish_sync(self.revision);
+        }
+    }
+}
+
+/// Marks a revision as synced when dropped, the writes of the revision are finished by
+/// a background task, see `KvStore::defer_sync`
+#[derive(Debug)]
+pub(crate) struct DeferredSync {
+    /// The kv store
+    kv_store: Arc<KvStoreInner>,
+    /// The revision being synced
+    revision: i64,
+    /// Whether the writes of the revision are lost, it's never synced then
+    abandoned: bool,
+}
+
+impl DeferredSync {
+    /// The revision being synced
+    pub(crate) fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Abandon the sync as its writes failed to be persisted, the revision and those
+    /// above it are never reported as synced
+    pub(crate) fn abandon(mut self) {
+        self.abandoned = true;
+    }
+}
+
+impl Drop for DeferredSync {
+    fn drop(&mut self) {
+        if !self.abandoned {
+            self.kv_store.finish_sync(self.revision);
+        }
+    }
+}
+
+/// KV store inner, shared by `KvStore` and `KvWatcher`
+#[derive(Debug)]
+pub(crate) struct KvStoreInner {
+    /// Key Index
+    index: Arc<Index>,
+    /// DB to store key value
+    db: Arc<DB>,
+    /// Compacted Revision
+    compacted_rev: AtomicI64,
+    /// The prefixes whose history is kept by compactions
+    exempt_prefixes: RwLock<ExemptPrefixes>,
+    /// Progress of syncing revisions to the storage
+    sync_state: Mutex<SyncState>,
+    /// Notified when a revision is synced
+    sync_event: event_listener::Event,
+}
+
+impl KvStoreInner {
+    /// Create new `KvStoreInner`
+    pub(crate) fn new(index: Arc<Index>, db: Arc<DB>) -> Self {
+        Self {
+            index,
+            db,
+            compacted_rev: AtomicI64::new(-1),
+            exempt_prefixes: RwLock::new(ExemptPrefixes::default()),
+            sync_state: Mutex::new(SyncState::default()),
+            sync_event: event_listener::Event::new(),
+        }
+    }
+
+    /// Get `KeyValue` from the `KvStoreInner`
+    fn get_values(&self, revisions: &[Revision]) -> Result<Vec<KeyValue>, ExecuteError> {
+        let revisions = revisions
+            .iter()
+            .map(Revision::encode_to_vec)
+            .collect::<Vec<Vec<u8>>>();
+        let values = self.db.get_values(KV_TABLE, &revisions)?;
+        let kvs: Vec<KeyValue> = values
+            .into_iter()
+            .flatten()
+            .map(|v| self.db.decode_kv(v))
+            .collect::<Result<_, _>>()?;
+        // A concurrent physical compaction may remove revisions returned by the index,
+        // the caller decides whether it is a compacted read or a real inconsistency
+        if kvs.len() != revisions.len() {
+            return Err(ExecuteError::DbError(format!(
+                "index does not match with db, expected {} kvs, got {}",
+                revisions.len(),
+                kvs.len()
+            )));
+        }
+        Ok(kvs)
+    }
+
+    /// Get `KeyValue` of a range
+    ///
+    /// If `range_end` is `&[]`, this function will return one or zero `KeyValue`.
+    fn get_range(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+    ) -> Result<Vec<KeyValue>, ExecuteError> {
+        let revisions = self.index.get(key, range_end, revision);
+        self.get_values(&revisions)
+    }
+
+    /// Get `KeyValue` start from a revision and convert to `Event`
+    pub(crate) fn get_event_from_revision(
+        &self,
+        key_range: KeyRange,
+        revision: i64,
+    ) -> Result<Vec<Event>, ExecuteError> {
+        let revisions =
+            self.index
+                .get_from_rev(key_range.range_start(), key_range.range_end(), revision);
+        let events: Vec<Event> = self
+            .get_values(&revisions)?
+            .into_iter()
+            .map(|kv| {
+                // Delete
+                #[allow(clippy::as_conversions)] // This cast is always valid
+                let event_type = if kv.version == 0 && kv.create_revision == 0 {
+                    EventType::Delete
+                } else {
+                    EventType::Put
+                };
+                let mut event = Event {
+                    kv: Some(kv),
+                    prev_kv: None,
+                    ..Default::default()
+                };
+                event.set_type(event_type);
+                event
+            })
+            .collect();
+        Ok(events)
+    }
+
+    /// Revisions of the keys in a range at the synced revision, along with the revision,
+    /// the kvs are read by `get_kvs` page by page
+    pub(crate) fn range_snapshot(&self, key_range: &KeyRange) -> (i64, Vec<Revision>) {
+        let revision = self.synced_revision();
+        let revisions = self
+            .index
+            .get(key_range.range_start(), key_range.range_end(), revision);
+        (revision, revisions)
+    }
+
+    /// Get the `KeyValue`s of revisions
+    pub(crate) fn get_kvs(&self, revisions: &[Revision]) -> Result<Vec<KeyValue>, ExecuteError> {
+        self.get_values(revisions)
+    }
+
+    /// Get previous `KeyValue` of a `KeyValue`
+    pub(crate) fn get_prev_kv(&self, kv: &KeyValue) -> Option<KeyValue> {
+        self.get_range(&kv.key, &[], kv.mod_revision.overflow_sub(1))
+            .ok()?
+            .pop()
+    }
+
+    /// Start syncing the writes of a revision, the revision is synced once the guard
+    /// is dropped
+    pub(crate) fn begin_sync(&self, revision: i64) -> SyncGuard<'_> {
+        self.register_sync(revision);
+        self.resume_sync(revision)
+    }
+
+    /// Register a revision as being synced once it's reserved, so that no revision
+    /// above it is reported as synced before its writes are
+    pub(crate) fn register_sync(&self, revision: i64) {
+        let inserted = self.sync_state.lock().syncing.insert(revision);
+        debug_assert!(inserted, "revision {revision} is reserved by two entries");
+    }
+
+    /// Get the guard of a registered revision, the revision is synced once the guard
+    /// is dropped
+    pub(crate) fn resume_sync(&self, revision: i64) -> SyncGuard<'_> {
+        debug_assert!(
+            self.sync_state.lock().syncing.contains(&revision),
+            "revision {revision} is not registered"
+        );
+        SyncGuard {
+            kv_store: self,
+            revision,
+            abandoned: false,
+        }
+    }
+
+    /// Mark a registered revision as synced
+    fn finish_sync(&self, revision: i64) {
+        {
+            let mut state = self.sync_state.lock();
+            let _ignore = state.syncing.remove(&revision);
+            state.synced = state.synced.max(revision);
+        }
+        let _ignore = self.sync_event.notify(usize::MAX);
+    }
+
+    /// Drop the registered revisions, their entries will never be applied
+    pub(crate) fn clear_syncing(&self) {
+        self.sync_state.lock().syncing.clear();
+        let _ignore = self.sync_event.notify(usize::MAX);
+    }
+
+    /// Get the highest revision that the writes at or below it have all been synced
+    pub(crate) fn synced_revision(&self) -> i64 {
+        let state = self.sync_state.lock();
+        state
+            .syncing
+            .first()
+            .map_or(state.synced, |min| state.synced.min(min.overflow_sub(1)))
+    }
+
+    /// Wait until the writes at or below the revision have all been synced
+    pub(crate) async fn wait_synced(&self, revision: i64) {
+        loop {
+            let listener = self.sync_event.listen();
+            if self.synced_revision() >= revision {
+                break;
+            }
+            listener.await;
+        }
+    }
+
+    /// Get compacted revision of  KV store
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        self.compacted_rev.load(Relaxed)
+    }
+
+    /// Get `KeyValue` of a range with limit and count only, return kvs and total count
+    fn get_range_with_opts(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        limit: usize,
+        count_only: bool,
+    ) -> Result<(Vec<KeyValue>, usize), ExecuteError> {
+        let mut revisions = self.index.get(key, range_end, revision);
+        let total = revisions.len();
+        if count_only || total == 0 {
+            return Ok((vec![], total));
+        }
+        if limit != 0 {
+            revisions.truncate(limit);
+        }
+        let kvs = self.get_values(&revisions)?;
+        Ok((kvs, total))
+    }
+
+    /// Get the first `KeyValue` of a range in the order of the sort target, return it and
+    /// the total count
+    fn get_first_sorted(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        sort_target: SortTarget,
+        sort_order: SortOrder,
+    ) -> Result<(Option<KeyValue>, usize), ExecuteError> {
+        let (first, total) =
+            self.index
+                .get_first_sorted(key, range_end, revision, sort_target, sort_order);
+        let Some(first) = first else {
+            return Ok((None, total));
+        };
+        Ok((self.get_values(&[first])?.pop(), total))
+    }
+}
+
+impl KvStore {
+    /// execute a kv request
+    pub(crate) fn execute(
+        &self,
+        request: &RequestWrapper,
+    ) -> Result<CommandResponse, ExecuteError> {
+        self.handle_kv_requests(request).map(CommandResponse::new)
+    }
+
+    /// sync a kv request
+    pub(crate) async fn after_sync(
+        &self,
+        request: &RequestWrapper,
+        revision: i64,
+    ) -> Result<(SyncResponse, Vec<WriteOp>), ApplyError> {
+        self.sync_request(request, revision)
+            .await
+            .map_err(ApplyError::from)
+    }
+
+    /// Number of keys a `DeleteRangeRequest` affects at the current revision
+    pub(crate) fn delete_range_affected_keys(&self, req: &DeleteRangeRequest) -> usize {
+        self.inner.index.get(&req.key, &req.range_end, 0).len()
+    }
+
+    /// Number of keys a `TxnRequest` affects at the current revision, it's unknown which
+    /// branch will be taken, so the larger one is counted
+    pub(crate) fn txn_affected_keys(&self, req: &TxnRequest) -> usize {
+        [&req.success, &req.failure]
+            .into_iter()
+            .map(|ops| {
+                ops.iter()
+                    .filter_map(|op| op.request.as_ref())
+                    .map(|request| match *request {
+                        Request::RequestRange(_) => 0,
+                        Request::RequestPut(_) => 1,
+                        Request::RequestDeleteRange(ref req) => {
+                            self.delete_range_affected_keys(req)
+                        }
+                        Request::RequestTxn(ref req) => self.txn_affected_keys(req),
+                    })
+                    .fold(0, usize::saturating_add)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Recover data from persistent storage
+    pub(crate) async fn recover(&self) -> Result<(), ExecuteError> {
+        // the tombstones of revoked leases left by a restart are indexed with the others
+        self.write_pending_lease_deletions()?;
+        let mut key_to_lease: HashMap<Vec<u8>, i64> = HashMap::new();
+        let revisions = self.inner.db.scan_keys(KV_TABLE, &[], &[])?;
+
+        let current_rev = revisions
+            .last()
+            .map_or(1, |key| Revision::decode(key).revision());
+        self.revision.set(current_rev);
+        self.inner.sync_state.lock().synced = current_rev;
+
+        // The keys of the kv table are revisions, the user key, the lease and the
+        // versions live in the values, so the rebuild can't be key-only. The values
+        // are read in batches instead, the whole table is never held at once.
+        for batch in revisions.chunks(RECOVER_BATCH_SIZE) {
+            let values = self.inner.db.get_values(KV_TABLE, batch)?;
+            for (key, value) in batch.iter().zip(values) {
+                let Some(value) = value else {
+                    continue;
+                };
+                let rev = Revision::decode(key.as_slice());
+                // a value encrypted with a key that is not configured fails the recovery
+                let kv = self.inner.db.decode_kv(value)?;
+
+                if kv.lease == 0 {
+                    let _ignore = key_to_lease.remove(&kv.key);
+                } else {
+                    let _ignore = key_to_lease.insert(kv.key.clone(), kv.lease);
+                }
+
+                self.inner.index.restore(
+                    kv.key,
+                    rev.revision(),
+                    rev.sub_revision(),
+                    kv.create_revision,
+                    kv.version,
+                );
+            }
+        }
+
+        // A key may outlive its lease if the lease is missing from the recovered
+        // lease store, leave it to the operator instead of failing the recovery
+        let mut orphans = 0_usize;
+        for (key, lease_id) in key_to_lease {
+            match self.attach(lease_id, key) {
+                Ok(()) => {}
+                Err(ExecuteError::LeaseNotFound(_)) => orphans = orphans.overflow_add(1),
+                Err(e) => return Err(e),
+            }
+        }
+        if orphans > 0 {
+            warn!("{orphans} keys are attached to leases that no longer exist");
+        }
+        if let Some(finished_rev) = self.get_compact_revision(FINISHED_COMPACT_REVISION)? {
+            assert!(
+                finished_rev >= -1 && finished_rev <= current_rev,
+                "compacted revision corruption, which ({finished_rev}) must belong to the range [-1, {current_rev}]"
+            );
+            self.update_compacted_revision(finished_rev);
+        }
+        self.recover_exempt_prefixes()?;
+        if let Some(scheduled_rev) = self.get_compact_revision(SCHEDULED_COMPACT_REVISION)? {
+            if scheduled_rev > self.compacted_revision() {
+                let event = Arc::new(event_listener::Event::new());
+                let listener = event.listen();
+                if let Err(e) = self
+                    .compact_task_tx
+                    .send((scheduled_rev, self.exempt_prefixes(), Some(event)))
+                    .await
+                {
+                    panic!("the compactor exited unexpectedly: {e:?}");
+                }
+                listener.await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recover the compaction exempt prefixes, the configured ones are stored if none
+    /// is stored yet, their history is kept since the compacted revision
+    fn recover_exempt_prefixes(&self) -> Result<(), ExecuteError> {
+        let exempt = match self.inner.db.get_value(META_TABLE, COMPACT_EXEMPTION_KEY)? {
+            Some(bytes) => {
+                let exempt = ExemptPrefixes::decode(&bytes)?;
+                let configured = ExemptPrefixes::new(self.configured_exempt_prefixes.clone(), 0);
+                if !configured.is_empty() && configured.prefixes() != exempt.prefixes() {
+                    warn!(
+                        "the configured compaction exempt prefixes differ from the stored ones, \
+                        which are changed through the log only"
+                    );
+                }
+                exempt
+            }
+            None => {
+                let exempt = ExemptPrefixes::new(
+                    self.configured_exempt_prefixes.clone(),
+                    self.compacted_revision(),
+                );
+                if !exempt.is_empty() {
+                    _ = self
+                        .inner
+                        .db
+                        .flush_ops(vec![WriteOp::PutCompactExemption(exempt.encode())])?;
+                }
+                exempt
+            }
+        };
+        *self.inner.exempt_prefixes.write() = exempt;
+        Ok(())
+    }
+
+    /// Get compact revision from db
+    fn get_compact_revision(&self, revision_key: &str) -> Result<Option<i64>, ExecuteError> {
+        let Some(revision_bytes) = self.inner.db.get_value(META_TABLE, revision_key)? else {
+            return Ok(None);
+        };
+        let bytes = revision_bytes.try_into().map_err(|e| {
+            ExecuteError::DbError(format!(
+                "cannot decode compacted revision from META_TABLE: {e:?}"
+            ))
+        })?;
+        Ok(Some(i64::from_le_bytes(bytes)))
+    }
+}
+
+impl KvStore {
+    /// New `KvStore`
+    pub(crate) fn new(
+        inner: Arc<KvStoreInner>,
+        header_gen: Arc<HeaderGenerator>,
+        kv_update_tx: mpsc::Sender<KvUpdates>,
+        compact_task_tx: mpsc::Sender<CompactTask>,
+        lease_collection: Arc<LeaseCollection>,
+    ) -> Self {
+        let revision = header_gen.general_revision_arc();
+        inner.sync_state.lock().synced = revision.get();
+        Self {
+            inner,
+            revision,
+            header_gen,
+            kv_update_tx,
+            compact_task_tx,
+            lease_collection,
+            configured_exempt_prefixes: Vec::new(),
+        }
+    }
+
+    /// Set the configured compaction exempt prefixes, they take effect on the first
+    /// recovery of the data dir and are changed only through the log later
+    pub(crate) fn with_exempt_prefixes(mut self, prefixes: Vec<Vec<u8>>) -> Self {
+        self.configured_exempt_prefixes = prefixes;
+        self
+    }
+
+    /// Start syncing the writes of a revision, the revision is synced once the guard
+    /// is dropped
+    pub(crate) fn begin_sync(&self, revision: i64) -> SyncGuard<'_> {
+        self.inner.begin_sync(revision)
+    }
+
+    /// Register a revision as being synced once it's reserved
+    pub(crate) fn register_sync(&self, revision: i64) {
+        self.inner.register_sync(revision);
+    }
+
+    /// Get the guard of a registered revision
+    pub(crate) fn resume_sync(&self, revision: i64) -> SyncGuard<'_> {
+        self.inner.resume_sync(revision)
+    }
+
+    /// Hand the sync of a revision over to a background task finishing its writes, the
+    /// revision stays unsynced until the returned `DeferredSync` is dropped
+    pub(crate) fn defer_sync(&self, mut guard: SyncGuard<'_>) -> DeferredSync {
+        guard.abandoned = true;
+        DeferredSync {
+            kv_store: Arc::clone(&self.inner),
+            revision: guard.revision,
+            abandoned: false,
+        }
+    }
+
+    /// Drop the registered revisions, their entries will never be applied
+    pub(crate) fn clear_syncing(&self) {
+        self.inner.clear_syncing();
+    }
+
+    /// Get the highest revision that the writes at or below it have all been synced
+    pub(crate) fn synced_revision(&self) -> i64 {
+        self.inner.synced_revision()
+    }
+
+    /// Wait until the writes at or below the revision have all been synced
+    pub(crate) async fn wait_synced(&self, revision: i64) {
+        self.inner.wait_synced(revision).await;
+    }
+
+    /// Generate `ResponseHeader`
+    pub(crate) fn gen_header(&self) -> ResponseHeader {
+        self.header_gen.gen_header()
+    }
+
+    /// Generate a `ResponseHeader` with the given revision
+    pub(crate) fn gen_header_with_revision(&self, revision: i64) -> ResponseHeader {
+        self.header_gen.gen_header_with_revision(revision)
+    }
+
+    /// Get revision of KV store
+    pub(crate) fn revision(&self) -> i64 {
+        self.revision.get()
+    }
+
+    /// Get compacted revision of  KV store
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        self.inner.compacted_rev.load(Relaxed)
+    }
+
+    /// Update compacted revision of KV store
+    pub(crate) fn update_compacted_revision(&self, revision: i64) {
+        self.inner.compacted_rev.store(revision, Relaxed);
+    }
+
+    /// Get the compaction exempt prefixes
+    pub(crate) fn exempt_prefixes(&self) -> ExemptPrefixes {
+        self.inner.exempt_prefixes.read().clone()
+    }
+
+    /// Get the compacted revision of a range, a range under the exempt prefixes is
+    /// compacted only below the revision its history is kept since
+    pub(crate) fn compacted_revision_of(&self, key: &[u8], range_end: &[u8]) -> i64 {
+        self.inner.exempt_prefixes.read().compacted_revision_of(
+            key,
+            range_end,
+            self.compacted_revision(),
+        )
+    }
+
+    /// Check the revision of a request against the compacted revision of each range it
+    /// reads and the current revision
+    pub(crate) fn check_revision<R: RevisionCheck>(&self, req: R) -> Result<(), ExecuteError> {
+        req.check_revision_exempt(
+            self.compacted_revision(),
+            self.revision(),
+            &self.inner.exempt_prefixes.read(),
+        )
+    }
+
+    /// Notify KV changes to KV watcher
+    async fn notify_updates(&self, revision: i64, updates: Vec<InternalEvent>) {
+        assert!(
+            self.kv_update_tx
+                .send((revision, updates.into_iter().map(Arc::new).collect()))
+                .await
+                .is_ok(),
+            "Failed to send updates to KV watcher"
+        );
+    }
+
+    /// Sort kvs by sort target and order
+    pub(crate) fn sort_kvs(kvs: &mut [KeyValue], sort_order: SortOrder, sort_target: SortTarget) {
+        match (sort_target, sort_order) {
+            (SortTarget::Key, SortOrder::None) => {}
+            (SortTarget::Key, SortOrder::Ascend) => {
+                kvs.sort_by(|a, b| a.key.cmp(&b.key));
+            }
+            (SortTarget::Key, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.key.cmp(&a.key));
+            }
+            (SortTarget::Version, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.version.cmp(&b.version));
+            }
+            (SortTarget::Version, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.version.cmp(&a.version));
+            }
+            (SortTarget::Create, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.create_revision.cmp(&b.create_revision));
+            }
+            (SortTarget::Create, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.create_revision.cmp(&a.create_revision));
+            }
+            (SortTarget::Mod, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.mod_revision.cmp(&b.mod_revision));
+            }
+            (SortTarget::Mod, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.mod_revision.cmp(&a.mod_revision));
+            }
+            (SortTarget::Value, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.value.cmp(&b.value));
+            }
+            (SortTarget::Value, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.value.cmp(&a.value));
+            }
+        };
+    }
+
+    /// filter kvs by `{max,min}_{mod,create}_revision`
+    pub(crate) fn filter_kvs(
+        kvs: &mut Vec<KeyValue>,
+        max_mod_revision: i64,
+        min_mod_revision: i64,
+        max_create_revision: i64,
+        min_create_revision: i64,
+    ) {
+        if max_mod_revision > 0 {
+            kvs.retain(|kv| kv.mod_revision <= max_mod_revision);
+        }
+        if min_mod_revision > 0 {
+            kvs.retain(|kv| kv.mod_revision >= min_mod_revision);
+        }
+        if max_create_revision > 0 {
+            kvs.retain(|kv| kv.create_revision <= max_create_revision);
+        }
+        if min_create_revision > 0 {
+            kvs.retain(|kv| kv.create_revision >= min_create_revision);
+        }
+    }
+
+    /// Compare i64
+    fn compare_i64(val: i64, target: i64) -> CompareResult {
+        match val.cmp(&target) {
+            Ordering::Greater => CompareResult::Greater,
+            Ordering::Less => CompareResult::Less,
+            Ordering::Equal => CompareResult::Equal,
+        }
+    }
+
+    /// Compare vec<u8>
+    fn compare_vec_u8(val: &[u8], target: &[u8]) -> CompareResult {
+        match val.cmp(target) {
+            Ordering::Greater => CompareResult::Greater,
+            Ordering::Less => CompareResult::Less,
+            Ordering::Equal => CompareResult::Equal,
+        }
+    }
+
+    /// Check one `KeyValue` with `Compare`
+    fn compare_kv(cmp: &Compare, kv: &KeyValue) -> bool {
+        let result = match cmp.target() {
+            CompareTarget::Version => {
+                let rev = if let Some(TargetUnion::Version(v)) = cmp.target_union {
+                    v
+                } else {
+                    0
+                };
+                Self::compare_i64(kv.version, rev)
+            }
+            CompareTarget::Create => {
+                let rev = if let Some(TargetUnion::CreateRevision(v)) = cmp.target_union {
+                    v
+                } else {
+                    0
+                };
+                Self::compare_i64(kv.create_revision, rev)
+            }
+            CompareTarget::Mod => {
+                let rev = if let Some(TargetUnion::ModRevision(v)) = cmp.target_union {
+                    v
+                } else {
+                    0
+                };
+                Self::compare_i64(kv.mod_revision, rev)
+            }
+            CompareTarget::Value => {
+                let empty = vec![];
+                let val = if let Some(TargetUnion::Value(ref v)) = cmp.target_union {
+                    v
+                } else {
+                    &empty
+                };
+                Self::compare_vec_u8(&kv.value, val)
+            }
+            CompareTarget::Lease => {
+                let les = if let Some(TargetUnion::Lease(v)) = cmp.target_union {
+                    v
+                } else {
+                    0
+                };
+                Self::compare_i64(kv.lease, les)
+            }
+        };
+
+        match cmp.result() {
+            CompareResult::Equal => result == CompareResult::Equal,
+            CompareResult::Greater => result == CompareResult::Greater,
+            CompareResult::Less => result == CompareResult::Less,
+            CompareResult::NotEqual => result != CompareResult::Equal,
+        }
+    }
+
+    /// Check result of a `Compare` the way etcd does:
+    /// - the keys of the range that don't exist are ignored
+    /// - if no key exists, the compare is made against a `KeyValue` whose versions,
+    ///   revisions and lease are 0, so `Create(key) = 0` means the key is absent
+    /// - a `Value` compare against missing keys fails whatever the operator, as a
+    ///   missing value can't be told apart from an empty one
+    /// - a compare whose keys can't be read fails
+    fn check_compare(&self, cmp: &Compare) -> bool {
+        let Ok(kvs) = self.inner.get_range(&cmp.key, &cmp.range_end, 0) else {
+            return false;
+        };
+        if kvs.is_empty() {
+            return cmp.target() != CompareTarget::Value
+                && Self::compare_kv(cmp, &KeyValue::default());
+        }
+        kvs.iter().all(|kv| Self::compare_kv(cmp, kv))
+    }
+
+    /// Send get lease to lease store
+    fn get_lease(&self, key: &[u8]) -> i64 {
+        self.lease_collection.get_lease(key)
+    }
+
+    /// Send detach to lease store
+    fn detach(&self, lease_id: i64, key: impl AsRef<[u8]>) {
+        self.lease_collection.detach(lease_id, key.as_ref());
+    }
+
+    /// Send attach to lease store
+    fn attach(&self, lease_id: i64, key: impl Into<Vec<u8>>) -> Result<(), ExecuteError> {
+        self.lease_collection.attach(lease_id, key.into())
+    }
+
+    /// Compact kv storage
+    pub(crate) fn compact(&self, revisions: &[Vec<u8>]) -> Result<(), ExecuteError> {
+        let mut ops = Vec::new();
+        revisions
+            .iter()
+            .for_each(|rev| ops.push(WriteOp::DeleteKeyValue(rev.as_ref())));
+        _ = self.inner.db.flush_ops(ops)?;
+        Ok(())
+    }
+
+    /// Compact kv storage
+    pub(crate) fn compact_finished(&self, revision: i64) -> Result<(), ExecuteError> {
+        let ops = vec![WriteOp::PutFinishedCompactRevision(revision)];
+        _ = self.inner.db.flush_ops(ops)?;
+        // A later compaction may have been executed already, never move the compacted
+        // revision backward or reads below it would pass the revision check
+        let _prev = self.inner.compacted_rev.fetch_max(revision, Relaxed);
+        Ok(())
+    }
+
+    /// Calculate hash of kv storage at a revision, returns the hash, the compacted
+    /// revision and the hashed revision
+    ///
+    /// A revision of 0 pins the latest synced revision, so that the hash covers the
+    /// same data on every member even while writes land, and only later revisions
+    /// are still being written.
+    pub(crate) fn hash_kv(&self, mut rev: i64) -> Result<(u32, i64, i64), ExecuteError> {
+        let (compact_rev, synced_rev) = (self.compacted_revision(), self.synced_revision());
+        if rev > 0 && rev < compact_rev {
+            return Err(ExecuteError::RevisionCompacted(rev, compact_rev));
+        }
+        if rev > 0 && rev > synced_rev {
+            return Err(ExecuteError::RevisionTooLarge(rev, synced_rev));
+        }
+        if rev <= 0 {
+            rev = synced_rev;
+        }
+        let keep = self.inner.index.keep(rev);
+        let upper = Revision::new(rev.overflow_add(1), 0);
+        let lower = Revision::new(compact_rev, 0);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(KV_TABLE.as_bytes());
+        let kv_pairs = self.inner.db.get_all(KV_TABLE)?;
+        for (k, v) in kv_pairs {
+            let kr = Revision::decode(&k);
+            if upper <= kr {
+                continue;
+            }
+            if lower > kr && !keep.contains(&kr) {
+                continue;
+            }
+            hasher.update(&k);
+            // the hash covers the plaintext, so members with different keys agree
+            hasher.update(&self.inner.db.plain_kv(v)?);
+        }
+        let hash = hasher.finalize();
+        Ok((hash, compact_rev, rev))
+    }
+}
+
+/// handle and sync kv requests
+impl KvStore {
+    /// Handle kv requests
+    fn handle_kv_requests(
+        &self,
+        wrapper: &RequestWrapper,
+    ) -> Result<ResponseWrapper, ExecuteError> {
+        debug!("Execute {:?}", wrapper);
+        #[allow(clippy::wildcard_enum_match_arm)]
+        let res = match *wrapper {
+            RequestWrapper::RangeRequest(ref req) => self.handle_range_request(req).map(Into::into),
+            RequestWrapper::PutRequest(ref req) => self.handle_put_request(req).map(Into::into),
+            RequestWrapper::DeleteRangeRequest(ref req) => {
+                self.handle_delete_range_request(req).map(Into::into)
+            }
+            RequestWrapper::TxnRequest(ref req) => self.handle_txn_request(req).map(Into::into),
+            RequestWrapper::CompactionRequest(ref req) => {
+                self.handle_compaction_request(req).map(Into::into)
+            }
+            RequestWrapper::CompactionExemptionRequest(ref req) => {
+                Ok(self.handle_compaction_exemption_request(req).into())
+            }
+            _ => unreachable!("Other request should not be sent to this store"),
+        };
+        res
+    }
+
+    /// Handle `RangeRequest`
+    fn handle_range_request(&self, req: &RangeRequest) -> Result<RangeResponse, ExecuteError> {
+        self.check_revision(req)?;
+        if Self::is_first_sorted(req) {
+            return self.handle_first_sorted_range_request(req);
+        }
+
+        let storage_fetch_limit = if (req.sort_order() != SortOrder::None)
+            || (req.max_mod_revision != 0)
+            || (req.min_mod_revision != 0)
+            || (req.max_create_revision != 0)
+            || (req.min_create_revision != 0)
+            || (req.limit == 0)
+        {
+            0 // get all from storage then sort and filter
+        } else {
+            req.limit.overflow_add(1) // get one extra for "more" flag
+        };
+        let result = self.inner.get_range_with_opts(
+            &req.key,
+            &req.range_end,
+            req.revision,
+            storage_fetch_limit.numeric_cast(),
+            req.count_only,
+        );
+        // The compacted revision is bumped before the compactor removes anything, so
+        // if a compaction passed the pinned revision while reading, the result may be
+        // partial and must be rejected as a whole
+        self.check_revision(req)?;
+        let (mut kvs, total) = result?;
+        let mut response = RangeResponse {
+            header: Some(self.header_gen.gen_header()),
+            count: total.numeric_cast(),
+            ..RangeResponse::default()
+        };
+        if kvs.is_empty() {
+            return Ok(response);
+        }
+
+        Self::filter_kvs(
+            &mut kvs,
+            req.max_mod_revision,
+            req.min_mod_revision,
+            req.max_create_revision,
+            req.min_create_revision,
+        );
+        Self::sort_kvs(&mut kvs, req.sort_order(), req.sort_target());
+
+        if (req.limit > 0) && (kvs.len() > req.limit.numeric_cast()) {
+            response.more = true;
+            kvs.truncate(req.limit.numeric_cast());
+        }
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        response.kvs = kvs;
+        Ok(response)
+    }
+
+    /// Whether a `RangeRequest` only asks for the first key in the order of a sort target
+    /// that the index knows, e.g. the key with the highest mod revision under a prefix
+    fn is_first_sorted(req: &RangeRequest) -> bool {
+        req.limit == 1
+            && !req.range_end.is_empty()
+            && !req.count_only
+            && req.sort_target() != SortTarget::Value
+            && (req.sort_order() != SortOrder::None || req.sort_target() != SortTarget::Key)
+            && req.max_mod_revision == 0
+            && req.min_mod_revision == 0
+            && req.max_create_revision == 0
+            && req.min_create_revision == 0
+    }
+
+    /// Handle a `RangeRequest` of the first sorted key by tracking it in an index walk,
+    /// rather than reading and sorting all keys of the range
+    fn handle_first_sorted_range_request(
+        &self,
+        req: &RangeRequest,
+    ) -> Result<RangeResponse, ExecuteError> {
+        let result = self.inner.get_first_sorted(
+            &req.key,
+            &req.range_end,
+            req.revision,
+            req.sort_target(),
+            req.sort_order(),
+        );
+        self.check_revision(req)?;
+        let (first, total) = result?;
+        let mut kvs: Vec<KeyValue> = first.into_iter().collect();
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        Ok(RangeResponse {
+            header: Some(self.header_gen.gen_header()),
+            kvs,
+            more: total > 1,
+            count: total.numeric_cast(),
+        })
+    }
+
+    /// Check the writes of a request against the current state before its revision is
+    /// reserved, so that an entry whose execution fails reserves no revision
+    ///
+    /// The entries conflicting with the request have all been applied when it's
+    /// prepared, so the check gives the same result as its execution on every member.
+    #[allow(clippy::wildcard_enum_match_arm)] // other requests never fail to apply
+    pub(crate) fn check_writes(&self, wrapper: &RequestWrapper) -> Result<(), ExecuteError> {
+        match *wrapper {
+            RequestWrapper::PutRequest(ref req) => self.check_put(req),
+            RequestWrapper::TxnRequest(ref req) => {
+                self.check_revision(req)?;
+                let mut requests = Vec::new();
+                self.collect_txn_writes(req, &mut requests);
+                for request in &requests {
+                    if let Request::RequestPut(ref put_req) = **request {
+                        self.check_put(put_req)?;
+                    }
+                }
+                self.check_txn_attach(&requests)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the keys the writes of a txn attach to each lease together, the puts of a
+    /// txn may exceed the max number of keys of a lease although each of them fits
+    fn check_txn_attach(&self, requests: &[&Request]) -> Result<(), ExecuteError> {
+        let mut attached: BTreeMap<i64, Vec<&[u8]>> = BTreeMap::new();
+        for request in requests {
+            if let Request::RequestPut(ref put_req) = **request {
+                if put_req.lease != 0 {
+                    attached
+                        .entry(put_req.lease)
+                        .or_default()
+                        .push(put_req.key.as_slice());
+                }
+            }
+        }
+        for (lease_id, keys) in attached {
+            self.lease_collection.check_attach(lease_id, keys)?;
+        }
+        Ok(())
+    }
+
+    /// Check a put the same as its execution
+    fn check_put(&self, req: &PutRequest) -> Result<(), ExecuteError> {
+        if req.lease != 0 {
+            self.lease_collection
+                .check_attach(req.lease, [req.key.as_slice()])?;
+        }
+        if (req.ignore_lease || req.ignore_value)
+            && self.inner.get_range(&req.key, &[], 0)?.is_empty()
+        {
+            return Err(ExecuteError::KeyNotFound);
+        }
+        Ok(())
+    }
+
+    /// Handle `PutRequest`
+    fn handle_put_request(&self, req: &PutRequest) -> Result<PutResponse, ExecuteError> {
+        let mut response = PutResponse {
+            header: Some(self.header_gen.gen_header()),
+            ..Default::default()
+        };
+        if req.lease != 0 {
+            self.lease_collection
+                .check_attach(req.lease, [req.key.as_slice()])?;
+        };
+        if req.prev_kv || req.ignore_lease || req.ignore_value {
+            let prev_kv = self.inner.get_range(&req.key, &[], 0)?.pop();
+            if prev_kv.is_none() && (req.ignore_lease || req.ignore_value) {
+                return Err(ExecuteError::KeyNotFound);
+            }
+            if req.prev_kv {
+                response.prev_kv = prev_kv;
+            }
+        };
+        Ok(response)
+    }
+
+    /// Handle `DeleteRangeRequest`
+    fn handle_delete_range_request(
+        &self,
+        req: &DeleteRangeRequest,
+    ) -> Result<DeleteRangeResponse, ExecuteError> {
+        let prev_kvs = self.inner.get_range(&req.key, &req.range_end, 0)?;
+        let mut response = DeleteRangeResponse {
+            header: Some(self.header_gen.gen_header()),
+            ..DeleteRangeResponse::default()
+        };
+        response.deleted = prev_kvs.len().numeric_cast();
+        if req.prev_kv {
+            response.prev_kvs = prev_kvs;
+        }
+        Ok(response)
+    }
+
+    /// Handle `TxnRequest`
+    fn handle_txn_request(&self, req: &TxnRequest) -> Result<TxnResponse, ExecuteError> {
+        self.check_revision(req)?;
+        let mut writes = Vec::new();
+        self.collect_txn_writes(req, &mut writes);
+        self.check_txn_attach(&writes)?;
+
+        let success = req
+            .compare
+            .iter()
+            .all(|compare| self.check_compare(compare));
+        let requests = if success {
+            req.success.iter()
+        } else {
+            req.failure.iter()
+        };
+        let mut responses = Vec::with_capacity(requests.len());
+        for request_op in requests {
+            let response = self.handle_kv_requests(&request_op.clone().into())?;
+            responses.push(response.into());
+        }
+        Ok(TxnResponse {
+            header: Some(self.header_gen.gen_header()),
+            succeeded: success,
+            responses,
+        })
+    }
+
+    /// Handle `CompactionRequest`
+    fn handle_compaction_request(
+        &self,
+        req: &CompactionRequest,
+    ) -> Result<CompactionResponse, ExecuteError> {
+        req.check_revision(self.compacted_revision(), self.revision())?;
+
+        let target_revision = req.revision;
+        debug_assert!(
+            target_revision > self.compacted_revision(),
+            "required revision should not be compacted"
+        );
+        self.update_compacted_revision(target_revision);
+        Ok(CompactionResponse {
+            header: Some(self.header_gen.gen_header()),
+        })
+    }
+
+    /// Handle `CompactionExemptionRequest`, the response lists the exempt prefixes once
+    /// the request is applied
+    fn handle_compaction_exemption_request(
+        &self,
+        req: &CompactionExemptionRequest,
+    ) -> CompactionExemptionResponse {
+        let mut exempt = self.exempt_prefixes();
+        exempt.update(&req.add, &req.remove, self.synced_revision());
+        CompactionExemptionResponse {
+            header: Some(self.header_gen.gen_header()),
+            prefixes: exempt.prefixes(),
+        }
+    }
+
+    /// Sync requests in kv store
+    async fn sync_request(
+        &self,
+        wrapper: &RequestWrapper,
+        revision: i64,
+    ) -> Result<(SyncResponse, Vec<WriteOp>), ExecuteError> {
+        debug!("After Sync {:?} with revision {}", wrapper, revision);
+        let mut sync_res = SyncResponse::new(revision);
+        #[allow(clippy::wildcard_enum_match_arm)] // only kv requests can be sent to kv store
+        let (ops, events) = match *wrapper {
+            RequestWrapper::RangeRequest(_) => (Vec::new(), Vec::new()),
+            RequestWrapper::PutRequest(ref req) => self.sync_put_request(req, revision, 0)?,
+            RequestWrapper::DeleteRangeRequest(ref req) => {
+                self.sync_delete_range_request(req, revision, 0)
+            }
+            RequestWrapper::TxnRequest(ref req) => {
+                let (ops, events, sub_revisions) = self.sync_txn_request(req, revision)?;
+                sync_res = sync_res.with_sub_revisions(sub_revisions);
+                (ops, events)
+            }
+            RequestWrapper::CompactionRequest(ref req) => {
+                self.sync_compaction_request(req, revision).await?
+            }
+            RequestWrapper::CompactionExemptionRequest(ref req) => (
+                self.sync_compaction_exemption_request(req, revision),
+                Vec::new(),
+            ),
+            _ => {
+                unreachable!("only kv requests can be sent to kv store");
+            }
+        };
+        self.notify_updates(revision, events).await;
+        Ok((sync_res, ops))
+    }
+
+    /// Sync `CompactionRequest` and return if kvstore is changed
+    async fn sync_compaction_request(
+        &self,
+        req: &CompactionRequest,
+        _revision: i64,
+    ) -> Result<(Vec<WriteOp>, Vec<InternalEvent>), ExecuteError> {
+        let revision = req.revision;
+        let ops = vec![WriteOp::PutScheduledCompactRevision(revision)];
+        // TODO: Remove the physical process logic here. It's better to move into the KvServer
+        let (event, listener) = if req.physical {
+            let event = Arc::new(event_listener::Event::new());
+            let listener = event.listen();
+            (Some(event), Some(listener))
+        } else {
+            (None, None)
+        };
+        // the compaction keeps the history of the prefixes exempt when it's applied, even
+        // if they are changed before the compactor runs
+        if let Err(e) = self
+            .compact_task_tx
+            .send((revision, self.exempt_prefixes(), event))
+            .await
+        {
+            panic!("the compactor exited unexpectedly: {e:?}");
+        }
+        if let Some(listener) = listener {
+            listener.await;
+        }
+        Ok((ops, Vec::new()))
+    }
+
+    /// Sync `CompactionExemptionRequest`, the history under an added prefix is kept
+    /// since the revision of the request
+    ///
+    /// The revision is reserved for the request in the log order, so every member keeps
+    /// the same history. The compactions applied before it have all been scheduled, so
+    /// they never remove a revision at or above it that is still needed by a read there.
+    fn sync_compaction_exemption_request(
+        &self,
+        req: &CompactionExemptionRequest,
+        revision: i64,
+    ) -> Vec<WriteOp> {
+        let mut exempt = self.inner.exempt_prefixes.write();
+        exempt.update(&req.add, &req.remove, revision);
+        vec![WriteOp::PutCompactExemption(exempt.encode())]
+    }
+
+    /// Sync `TxnRequest` and return if kvstore is changed, along with the sub revisions
+    /// assigned to its mutations in order
+    fn sync_txn_request(
+        &self,
+        req: &TxnRequest,
+        revision: i64,
+    ) -> Result<(Vec<WriteOp>, Vec<InternalEvent>, Vec<i64>), ExecuteError> {
+        // Compares of the nested txns are checked before any write of the txn is applied,
+        // the same as in `handle_txn_request`
+        let mut requests = Vec::new();
+        self.collect_txn_writes(req, &mut requests);
+        // All writes of the txn share its one main revision
+        let mut sub_revisions = SubRevisions::new(revision);
+        let mut all_events = Vec::new();
+        let mut all_ops = Vec::new();
+        let mut assigned = Vec::with_capacity(requests.len());
+        for request in requests {
+            let sub_revision = sub_revisions.next();
+            assigned.push(sub_revision);
+            let (mut ops, mut events) = match *request {
+                Request::RequestPut(ref put_req) => {
+                    self.sync_put_request(put_req, revision, sub_revision)?
+                }
+                Request::RequestDeleteRange(ref del_req) => {
+                    self.sync_delete_range_request(del_req, revision, sub_revision)
+                }
+                Request::RequestRange(_) | Request::RequestTxn(_) => {
+                    unreachable!("only writes are collected from a txn")
+                }
+            };
+            sub_revisions.take(events.len());
+            all_events.append(&mut events);
+            all_ops.append(&mut ops);
+        }
+        debug_assert!(
+            all_events
+                .iter()
+                .all(|event| event.revision == sub_revisions.revision()),
+            "a write of the txn at revision {revision} takes another main revision"
+        );
+        Ok((all_ops, all_events, assigned))
+    }
+
+    /// Collect the writes of the branches a txn and its nested txns take, in the order
+    /// they appear in the txn
+    fn collect_txn_writes<'a>(&self, req: &'a TxnRequest, requests: &mut Vec<&'a Request>) {
+        let success = req
+            .compare
+            .iter()
+            .all(|compare| self.check_compare(compare));
+        let request_ops = if success { &req.success } else { &req.failure };
+        for request in request_ops.iter().filter_map(|op| op.request.as_ref()) {
+            match *request {
+                Request::RequestRange(_) => {}
+                Request::RequestPut(_) | Request::RequestDeleteRange(_) => requests.push(request),
+                Request::RequestTxn(ref txn_req) => self.collect_txn_writes(txn_req, requests),
+            }
+        }
+    }
+
+    /// Sync `PutRequest` and return if kvstore is changed
+    fn sync_put_request(
+        &self,
+        req: &PutRequest,
+        revision: i64,
+        sub_revision: i64,
+    ) -> Result<(Vec<WriteOp>, Vec<InternalEvent>), ExecuteError> {
+        let mut ops = Vec::new();
+        // Puts and revocations of the same lease conflict with each other, so the lease
+        // can't become full or be revoked after the put was prepared unless the members
+        // disagree on the limit. The put is rejected before its revision is registered,
+        // neither the key nor its attachment is stored.
+        if req.lease != 0 {
+            self.lease_collection
+                .check_attach(req.lease, [req.key.as_slice()])?;
+        }
+        let new_rev = self
+            .inner
+            .index
+            .register_revision(&req.key, revision, sub_revision);
+        let mut kv = KeyValue {
+            key: req.key.clone(),
+            value: Bytes::from(req.value.clone()),
+            create_revision: new_rev.create_revision,
+            mod_revision: new_rev.mod_revision,
+            version: new_rev.version,
+            lease: req.lease,
+        };
+        if req.ignore_lease || req.ignore_value {
+            let prev_kv = self.inner.get_range(&req.key, &[], 0)?.pop();
+            let prev = prev_kv.as_ref().ok_or(ExecuteError::KeyNotFound)?;
+            if req.ignore_lease {
+                kv.lease = prev.lease;
+            }
+            if req.ignore_value {
+                kv.value = prev.value.clone();
+            }
+        }
+
+        // A put keeping the same lease leaves the attachment untouched
+        let old_lease = self.get_lease(&kv.key);
+        if old_lease != kv.lease {
+            // The put fails rather than storing the key without the lease it asked for,
+            // the old attachment is only dropped once the new one is made
+            if kv.lease != 0 {
+                self.attach(kv.lease, kv.key.as_slice())?;
+            }
+            if old_lease != 0 {
+                self.detach(old_lease, kv.key.as_slice());
+            }
+        }
+        ops.push(WriteOp::PutKeyValue(new_rev.as_revision(), kv.clone()));
+        audit_mutation("put", &kv.key, &[], revision, sub_revision);
+        Ok((ops, vec![InternalEvent::put(kv)]))
+    }
+
+    /// create events for a deletion
+    fn new_deletion_events(revision: i64, keys: Vec<Vec<u8>>) -> Vec<InternalEvent> {
+        keys.into_iter()
+            .map(|key| InternalEvent::delete(key, revision))
+            .collect()
+    }
+
+    /// Mark deletion for keys
+    fn mark_deletions<'a>(
+        revisions: &[(Revision, Revision)],
+        keys: &[Vec<u8>],
+    ) -> Vec<WriteOp<'a>> {
+        assert_eq!(keys.len(), revisions.len(), "Index doesn't match with DB");
+        keys.iter()
+            .zip(revisions.iter())
+            .map(|(key, &(_, new_rev))| {
+                let del_kv = KeyValue {
+                    key: key.clone(),
+                    mod_revision: new_rev.revision(),
+                    ..KeyValue::default()
+                };
+                WriteOp::PutKeyValue(new_rev, del_kv)
+            })
+            .collect()
+    }
+
+    /// Sync `DeleteRangeRequest` and return if kvstore is changed
+    fn sync_delete_range_request(
+        &self,
+        req: &DeleteRangeRequest,
+        revision: i64,
+        sub_revision: i64,
+    ) -> (Vec<WriteOp>, Vec<InternalEvent>) {
+        audit_mutation(
+            "delete_range",
+            &req.key,
+            &req.range_end,
+            revision,
+            sub_revision,
+        );
+        Self::delete_keys(
+            &self.inner.index,
+            &self.lease_collection,
+            &req.key,
+            &req.range_end,
+            revision,
+            sub_revision,
+        )
+    }
+
+    /// Delete keys from index and detach them in lease collection, return all the write operations and events
+    pub(crate) fn delete_keys<'a>(
+        index: &Index,
+        lease_collection: &LeaseCollection,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        sub_revision: i64,
+    ) -> (Vec<WriteOp<'a>>, Vec<InternalEvent>) {
+        let mut ops = Vec::new();
+        let (revisions, keys) = index.delete(key, range_end, revision, sub_revision);
+        let mut del_ops = Self::mark_deletions(&revisions, &keys);
+        ops.append(&mut del_ops);
+        lease_collection.detach_keys(&keys);
+        let events = Self::new_deletion_events(revision, keys);
+        (ops, events)
+    }
+
+    /// Delete the keys of a revoked lease at `revision` as a whole, sub revisions are
+    /// assigned in the order of `keys`. A range read observes either all the keys of the
+    /// lease or none of them.
+    ///
+    /// Only the index is updated, the deleted keys are returned in batches of at most
+    /// `LEASE_DELETION_BATCH_SIZE` keys, whose tombstones are written in the background.
+    pub(crate) fn delete_lease_keys(
+        index: &Index,
+        lease_collection: &LeaseCollection,
+        keys: &[Vec<u8>],
+        revision: i64,
+    ) -> Vec<LeaseKeyDeletion> {
+        let (revisions, deleted) = index.delete_keys(keys, revision, 0);
+        assert_eq!(
+            deleted.len(),
+            revisions.len(),
+            "Index doesn't match with DB"
+        );
+        lease_collection.detach_keys(keys);
+        let deleted: Vec<_> = revisions
+            .iter()
+            .map(|&(_, new_rev)| new_rev.sub_revision())
+            .zip(deleted)
+            .collect();
+        deleted
+            .chunks(LEASE_DELETION_BATCH_SIZE)
+            .map(|batch| LeaseKeyDeletion::new(revision, batch.to_vec()))
+            .collect()
+    }
+
+    /// Write the tombstones of the deleted keys of revoked leases left by a restart
+    fn write_pending_lease_deletions(&self) -> Result<(), ExecuteError> {
+        let db = &self.inner.db;
+        let range_end = KeyRange::get_prefix(LEASE_DELETION_PREFIX);
+        let keys = db.scan_keys(META_TABLE, LEASE_DELETION_PREFIX, &range_end)?;
+        for (key, value) in keys.iter().zip(db.get_values(META_TABLE, &keys)?) {
+            let Some(value) = value else {
+                continue;
+            };
+            let deletion = LeaseKeyDeletion::decode(key, &value)?;
+            _ = db.flush_ops(deletion.ops())?;
+        }
+        Ok(())
+    }
+
+    /// Write the tombstones of the deleted keys of revoked leases batch by batch, in the
+    /// order of the sub revisions, and notify the KV watcher of them once all are written
+    ///
+    /// The batches are flushed through the `fence` of the applies, so none is written
+    /// once an apply failed to be persisted, nor any apply once a batch failed.
+    pub(crate) async fn write_lease_deletions(
+        fence: &ApplyFence,
+        kv_update_tx: &mpsc::Sender<KvUpdates>,
+        revision: i64,
+        deletions: &[Arc<LeaseKeyDeletion>],
+    ) -> Result<(), ApplyError> {
+        let mut events = Vec::new();
+        for deletion in deletions {
+            utils::fail_point_async!(
+                "lease_before_write_deletion",
+                Err(ApplyError::Storage(
+                    "injected failure of writing the deleted keys".to_owned()
+                ))
+            );
+            // the index is updated when the keys are deleted
+            _ = fence.flush(
+                &format!("the deleted keys of revision {revision}"),
+                deletion.ops(),
+                |_| {},
+            )?;
+            events.extend(
+                deletion
+                    .keys()
+                    .map(|key| Arc::new(InternalEvent::delete(key.to_vec(), revision))),
+            );
+        }
+        // the KV watcher is dropped before the storages during shutdown
+        if !events.is_empty() && kv_update_tx.send((revision, events)).await.is_err() {
+            warn!("failed to send updates to KV watcher, it may be shutting down");
+        }
+        Ok(())
+    }
+
+    /// Insert the given pairs (key, `KeyRevision`) into the index
+    #[inline]
+    pub(crate) fn insert_index(&self, key_revisions: Vec<(Vec<u8>, KeyRevision)>) {
+        self.inner.index.insert(key_revisions);
+    }
+}
+
+/// Write the audit log of a mutation, with the main revision and the sub revision it starts at
+fn audit_mutation(kind: &str, key: &[u8], range_end: &[u8], revision: i64, sub_revision: i64) {
+    info!(
+        target: "xline::audit",
+        kind,
+        key = %String::from_utf8_lossy(key),
+        range_end = %String::from_utf8_lossy(range_end),
+        revision,
+        sub_revision,
+        "mutation applied"
+    );
+}
+
+/// Tests of the kv requests against a reference model
+#[cfg(test)]
+mod model_tests;
+
+#[cfg(test)]
+mod test {
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+
+    use test_macros::abort_on_panic;
+    use tokio::{runtime::Handle, task::block_in_place};
+    use utils::{
+        config::EngineConfig,
+        task_manager::{tasks::TaskName, TaskManager},
+    };
+
+    use super::*;
+    use crate::{
+        revision_number::RevisionNumberGenerator,
+        rpc::{LeaseGrantRequest, LeaseRevokeRequest, Request as UniRequest, RequestOp},
+        storage::{
+            compact::{compact_bg_task, COMPACT_CHANNEL_SIZE},
+            db::DB,
+            kvwatcher::KvWatcher,
+            LeaseStore,
+        },
+    };
+
+    const CHANNEL_SIZE: usize = 1024;
+
+    struct StoreWrapper(Option<Arc<KvStore>>, Arc<TaskManager>);
+
+    impl Drop for StoreWrapper {
+        fn drop(&mut self) {
+            drop(self.0.take());
+            block_in_place(move || {
+                Handle::current().block_on(async move {
+                    self.1.shutdown(true).await;
+                });
+            });
+        }
+    }
+
+    impl std::ops::Deref for StoreWrapper {
+        type Target = Arc<KvStore>;
+
+        fn deref(&self) -> &Self::Target {
+            self.0.as_ref().unwrap()
+        }
+    }
+
+    impl std::ops::DerefMut for StoreWrapper {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            self.0.as_mut().unwrap()
+        }
+    }
+
+    fn sort_req(sort_order: SortOrder, sort_target: SortTarget) -> RangeRequest {
+        RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            sort_order: sort_order as i32,
+            sort_target: sort_target as i32,
+            ..Default::default()
+        }
+    }
+
+    async fn init_store(
+        db: Arc<DB>,
+    ) -> Result<(StoreWrapper, RevisionNumberGenerator), ExecuteError> {
+        let store = init_empty_store(db);
+        let keys = vec!["a", "b", "c", "d", "e", "z", "z", "z"];
+        let vals = vec!["a", "b", "c", "d", "e", "z1", "z2", "z3"];
+        let revision = RevisionNumberGenerator::default();
+        for (key, val) in keys.into_iter().zip(vals.into_iter()) {
+            let req = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: val.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision.next()).await?;
+        }
+        Ok((store, revision))
+    }
+
+    fn init_empty_store(db: Arc<DB>) -> StoreWrapper {
+        init_store_with_leases(db, LeaseCollection::new(0))
+    }
+
+    fn init_store_with_leases(db: Arc<DB>, lease_collection: LeaseCollection) -> StoreWrapper {
+        let task_manager = Arc::new(TaskManager::new());
+        let (compact_tx, compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
+        let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+        let lease_collection = Arc::new(lease_collection);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let kv_store_inner = Arc::new(KvStoreInner::new(Arc::clone(&index), db));
+        let storage = Arc::new(KvStore::new(
+            Arc::clone(&kv_store_inner),
+            header_gen,
+            kv_update_tx,
+            compact_tx,
+            lease_collection,
+        ));
+        let _watcher = KvWatcher::new_arc(
+            kv_store_inner,
+            kv_update_rx,
+            Duration::from_millis(10),
+            0,
+            0,
+            &task_manager,
+        );
+        task_manager.spawn(TaskName::CompactBg, |n| {
+            compact_bg_task(
+                Arc::clone(&storage),
+                index,
+                1000,
+                Duration::from_millis(10),
+                compact_rx,
+                n,
+            )
+        });
+        StoreWrapper(Some(storage), task_manager)
+    }
+
+    fn init_lease_store(store: &StoreWrapper, db: Arc<DB>) -> LeaseStore {
+        let (kv_update_tx, _) = mpsc::channel(1);
+        LeaseStore::new(
+            Arc::clone(&store.lease_collection),
+            Arc::new(HeaderGenerator::new(0, 0)),
+            db,
+            Arc::clone(&store.inner.index),
+            kv_update_tx,
+            true,
+            true,
+        )
+    }
+
+    async fn exe_as_and_flush(
+        store: &Arc<KvStore>,
+        request: &RequestWrapper,
+        revision: i64,
+    ) -> Result<(), ExecuteError> {
+        let (_sync_res, ops) = store.after_sync(request, revision).await?;
+        let key_revs = store.inner.db.flush_ops(ops)?;
+        store.insert_index(key_revs);
+        Ok(())
+    }
+
+    fn index_compact(store: &Arc<KvStore>, at_rev: i64) -> Vec<Vec<u8>> {
+        store
+            .inner
+            .index
+            .compact(at_rev, &store.exempt_prefixes())
+            .into_iter()
+            .map(|key_rev| key_rev.as_revision().encode_to_vec())
+            .collect::<Vec<Vec<_>>>()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_keys_only() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, _rev) = init_store(db).await?;
+        let request = RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            keys_only: true,
+            ..Default::default()
+        };
+        let response = store.handle_range_request(&request)?;
+        assert_eq!(response.kvs.len(), 6);
+        for kv in response.kvs {
+            assert!(kv.value.is_empty());
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_range_empty() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, _rev) = init_store(db).await?;
+
+        let request = RangeRequest {
+            key: "x".into(),
+            range_end: "y".into(),
+            keys_only: true,
+            ..Default::default()
+        };
+        let response = store.handle_range_request(&request)?;
+        assert_eq!(response.kvs.len(), 0);
+        assert_eq!(response.count, 0);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_range_filter() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, _rev) = init_store(db).await?;
+
+        let request = RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            max_create_revision: 3,
+            min_create_revision: 2,
+            max_mod_revision: 3,
+            min_mod_revision: 2,
+            ..Default::default()
+        };
+        let response = store.handle_range_request(&request)?;
+        assert_eq!(response.count, 6);
+        assert_eq!(response.kvs.len(), 2);
+        assert_eq!(response.kvs[0].create_revision, 2);
+        assert_eq!(response.kvs[1].create_revision, 3);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_range_sort() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, _rev) = init_store(db).await?;
+        let keys = ["a", "b", "c", "d", "e", "z"];
+        let reversed_keys = ["z", "e", "d", "c", "b", "a"];
+        let version_keys = ["z", "a", "b", "c", "d", "e"];
+
+        for order in [SortOrder::Ascend, SortOrder::Descend, SortOrder::None] {
+            for target in [
+                SortTarget::Key,
+                SortTarget::Create,
+                SortTarget::Mod,
+                SortTarget::Value,
+            ] {
+                let response = store.handle_range_request(&sort_req(order, target))?;
+                assert_eq!(response.count, 6);
+                assert_eq!(response.kvs.len(), 6);
+                let expected: [&str; 6] = match order {
+                    SortOrder::Descend => reversed_keys,
+                    SortOrder::Ascend | SortOrder::None => keys,
+                };
+                for (kv, want) in response.kvs.iter().zip(expected.iter()) {
+                    assert_eq!(
+                        kv.key,
+                        want.as_bytes(),
+                        "order: {:?}, target: {:?}, key {:?}, want {:?}",
+                        order,
+                        target,
+                        kv.key,
+                        want.as_bytes(),
+                    );
+                }
+            }
+        }
+        for order in [SortOrder::Ascend, SortOrder::Descend, SortOrder::None] {
+            let response = store.handle_range_request(&sort_req(order, SortTarget::Version))?;
+            assert_eq!(response.count, 6);
+            assert_eq!(response.kvs.len(), 6);
+            let expected = match order {
+                SortOrder::Ascend | SortOrder::None => keys,
+                SortOrder::Descend => version_keys,
+            };
+            for (kv, want) in response.kvs.iter().zip(expected.iter()) {
+                assert_eq!(
+                    kv.key,
+                    want.as_bytes(),
+                    "order: {:?}, key {:?}, want {:?}",
+                    order,
+                    kv.key,
+                    want.as_bytes(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_recover() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let ops = vec![WriteOp::PutScheduledCompactRevision(8)];
+        db.flush_ops(ops)?;
+        let (store, _rev_gen) = init_store(Arc::clone(&db)).await?;
+        assert_eq!(store.inner.index.get_from_rev(b"z", b"", 5).len(), 3);
+
+        let new_store = init_empty_store(db);
+
+        let range_req = RangeRequest {
+            key: "a".into(),
+            range_end: vec![],
+            ..Default::default()
+        };
+        let res = new_store.handle_range_request(&range_req)?;
+        assert_eq!(res.kvs.len(), 0);
+        assert_eq!(new_store.compacted_revision(), -1);
+
+        new_store.recover().await?;
+
+        let res = new_store.handle_range_request(&range_req)?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].key, b"a");
+        assert_eq!(new_store.compacted_revision(), 8);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(new_store.inner.index.get_from_rev(b"z", b"", 5).len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_first_sorted_range_should_match_full_sort() -> Result<(), ExecuteError> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let keys = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        for revision in 1..=200 {
+            let key = keys[rng.gen_range(0..keys.len())];
+            let req = if rng.gen_bool(0.2) {
+                RequestWrapper::from(DeleteRangeRequest {
+                    key: key.into(),
+                    ..Default::default()
+                })
+            } else {
+                RequestWrapper::from(PutRequest {
+                    key: key.into(),
+                    value: vec![rng.gen()],
+                    ..Default::default()
+                })
+            };
+            exe_as_and_flush(&store, &req, revision).await?;
+        }
+        store.revision.set(200);
+
+        let targets = [
+            SortTarget::Key,
+            SortTarget::Version,
+            SortTarget::Create,
+            SortTarget::Mod,
+        ];
+        let orders = [SortOrder::None, SortOrder::Ascend, SortOrder::Descend];
+        for _ in 0..500 {
+            let start = rng.gen_range(0..keys.len());
+            let end = rng.gen_range(start..=keys.len());
+            let mut req = RangeRequest {
+                key: keys[start].into(),
+                range_end: keys.get(end).map_or(vec![0], |k| k.as_bytes().to_vec()),
+                revision: if rng.gen_bool(0.5) {
+                    0
+                } else {
+                    rng.gen_range(1..=200)
+                },
+                keys_only: rng.gen_bool(0.5),
+                ..Default::default()
+            };
+            req.set_sort_target(targets[rng.gen_range(0..targets.len())]);
+            req.set_sort_order(orders[rng.gen_range(0..orders.len())]);
+            let full = store.handle_range_request(&req)?;
+            req.limit = 1;
+            let first = store.handle_range_request(&req)?;
+            assert_eq!(first.count, full.count, "{req:?}");
+            assert_eq!(first.more, full.count > 1, "{req:?}");
+            assert_eq!(
+                first.kvs.as_slice(),
+                &full.kvs[..full.kvs.len().min(1)],
+                "{req:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_recover_lease_attachments() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let _ignore = store.lease_collection.grant(1, 10, true);
+        let _ignore = store.lease_collection.grant(2, 10, true);
+        for (revision, (key, lease)) in [("foo", 1), ("bar", 2)].into_iter().enumerate() {
+            let req = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                lease,
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision.overflow_add(1).numeric_cast()).await?;
+        }
+
+        // lease 2 has been revoked while the node is down
+        let new_store = init_empty_store(db);
+        let _ignore = new_store.lease_collection.grant(1, 10, true);
+        new_store.recover().await?;
+
+        assert_eq!(
+            new_store.lease_collection.look_up(1).unwrap().keys(),
+            vec![b"foo".to_vec()]
+        );
+        assert_eq!(new_store.lease_collection.get_lease(b"foo"), 1);
+        assert_eq!(new_store.lease_collection.get_lease(b"bar"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_after_restart_should_delete_the_attached_keys() -> Result<(), ExecuteError>
+    {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let lease_store = init_lease_store(&store, Arc::clone(&db));
+        let grant = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = lease_store.execute(&grant)?;
+        let (_ignore, ops) = lease_store.after_sync(&grant, -1).await?;
+        _ = db.flush_ops(ops)?;
+        for (revision, (key, lease)) in [(1, ("foo", 1)), (2, ("bar", 1)), (3, ("baz", 0))] {
+            let req = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                lease,
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision).await?;
+        }
+
+        // the attachments only live in memory, the restarted node rebuilds them
+        let new_store = init_empty_store(Arc::clone(&db));
+        let new_lease_store = init_lease_store(&new_store, Arc::clone(&db));
+        new_lease_store.recover()?;
+        new_store.recover().await?;
+
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = new_lease_store.execute(&revoke)?;
+        let (_ignore, ops) = new_lease_store.after_sync(&revoke, 4).await?;
+        let deletions: Vec<_> = ops
+            .iter()
+            .filter_map(|op| {
+                if let WriteOp::PutLeaseDeletion(ref deletion) = *op {
+                    Some(Arc::clone(deletion))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        new_store.insert_index(db.flush_ops(ops)?);
+        new_lease_store
+            .write_key_deletions(&ApplyFence::new(Arc::clone(&db)), 4, &deletions)
+            .await?;
+
+        assert!(new_lease_store.look_up(1).is_none());
+        let res = new_store.handle_range_request(&RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            ..Default::default()
+        })?;
+        let keys: Vec<_> = res.kvs.iter().map(|kv| kv.key.as_slice()).collect();
+        assert_eq!(keys, [b"baz".as_slice()]);
+        // the tombstones are written at the revision of the revocation
+        for (sub_revision, key) in [(0, "bar"), (1, "foo")] {
+            let value = db
+                .get_value(KV_TABLE, Revision::new(4, sub_revision).encode_to_vec())?
+                .unwrap();
+            assert_eq!(db.decode_kv(value)?.key, key.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_recover_should_write_pending_lease_deletions() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let _ignore = store.lease_collection.grant(1, 10, true);
+        for (revision, key) in [(1, "foo"), (2, "bar")] {
+            let req = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                lease: 1,
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision).await?;
+        }
+
+        // the lease is revoked at revision 3, the node restarts before the tombstones
+        // are written
+        let keys = [b"bar".to_vec(), b"foo".to_vec()];
+        let deletions =
+            KvStore::delete_lease_keys(&store.inner.index, &store.lease_collection, &keys, 3);
+        let ops = deletions
+            .into_iter()
+            .map(|deletion| WriteOp::PutLeaseDeletion(Arc::new(deletion)))
+            .collect();
+        _ = db.flush_ops(ops)?;
+
+        let new_store = init_empty_store(Arc::clone(&db));
+        let _ignore = new_store.lease_collection.grant(1, 10, true);
+        new_store.recover().await?;
+        assert_eq!(new_store.revision(), 3);
+        let res = new_store.handle_range_request(&RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            ..Default::default()
+        })?;
+        assert!(res.kvs.is_empty());
+        assert_eq!(new_store.lease_collection.get_lease(b"foo"), 0);
+        // the tombstones take the sub revisions assigned by the revocation
+        for (sub_revision, key) in (0..).zip(keys) {
+            let value = db
+                .get_value(KV_TABLE, Revision::new(3, sub_revision).encode_to_vec())?
+                .unwrap();
+            assert_eq!(db.decode_kv(value)?.key, key);
+        }
+        assert!(db
+            .get_all(META_TABLE)?
+            .iter()
+            .all(|(key, _)| !key.starts_with(LEASE_DELETION_PREFIX)));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_leases_should_survive_snapshot_restore() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let lease_store = init_lease_store(&store, Arc::clone(&db));
+        for (id, ttl) in [(1, 10), (2, 20)] {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl,
+                id,
+                ..Default::default()
+            });
+            let _ignore = lease_store.execute(&req)?;
+            let (_ignore, ops) = lease_store.after_sync(&req, -1).await?;
+            _ = db.flush_ops(ops)?;
+        }
+        for (revision, (key, lease)) in [("foo", 1), ("bar", 1), ("baz", 2)].into_iter().enumerate()
+        {
+            let req = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                lease,
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision.overflow_add(1).numeric_cast()).await?;
+        }
+
+        let snapshot = db.get_snapshot("/tmp/test_leases_should_survive_snapshot_restore")?;
+        let new_db = DB::open(&EngineConfig::Memory)?;
+        new_db.reset(Some(snapshot)).await?;
+        let new_store = init_empty_store(Arc::clone(&new_db));
+        let new_lease_store = init_lease_store(&new_store, new_db);
+        new_lease_store.recover()?;
+        new_store.recover().await?;
+
+        for id in [1, 2] {
+            let lease = lease_store.look_up(id).unwrap();
+            let new_lease = new_lease_store.look_up(id).unwrap();
+            assert_eq!(lease.ttl(), new_lease.ttl());
+            let mut keys = lease_store.get_keys(id);
+            let mut new_keys = new_lease_store.get_keys(id);
+            keys.sort();
+            new_keys.sort();
+            assert_eq!(keys, new_keys);
+        }
+        assert_eq!(new_lease_store.get_keys(1).len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_chunked_lease_revoke_should_resume_after_restart() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let lease_store = init_lease_store(&store, Arc::clone(&db)).with_revoke_chunk_size(2);
+        let grant = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = lease_store.execute(&grant)?;
+        let (_ignore, ops) = lease_store.after_sync(&grant, -1).await?;
+        _ = db.flush_ops(ops)?;
+        for revision in 1..=5 {
+            let req = RequestWrapper::from(PutRequest {
+                key: format!("key{revision}").into_bytes(),
+                value: "v".into(),
+                lease: 1,
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision).await?;
+        }
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            chunk_size: 2,
+        });
+        let _ignore = lease_store.execute(&revoke)?;
+        let (_ignore, ops) = lease_store.after_sync(&revoke, 6).await?;
+        store.insert_index(db.flush_ops(ops)?);
+
+        // the node crashes before the revocation is continued
+        let new_store = init_empty_store(Arc::clone(&db));
+        let new_lease_store =
+            init_lease_store(&new_store, Arc::clone(&db)).with_revoke_chunk_size(2);
+        new_lease_store.recover()?;
+        new_store.recover().await?;
+        new_lease_store.resume_revokes()?;
+        assert_eq!(new_lease_store.revoking_leases(), vec![1]);
+        assert!(new_lease_store.look_up(1).is_none());
+        let put = RequestWrapper::from(PutRequest {
+            key: "foo".into(),
+            value: "v".into(),
+            lease: 1,
+            ..Default::default()
+        });
+        assert!(matches!(
+            new_store.execute(&put),
+            Err(ExecuteError::LeaseNotFound(1))
+        ));
+
+        for revision in [7, 8] {
+            let _ignore = new_lease_store.execute(&revoke)?;
+            let (_ignore, ops) = new_lease_store.after_sync(&revoke, revision).await?;
+            new_store.insert_index(db.flush_ops(ops)?);
+        }
+        assert!(new_lease_store.revoking_leases().is_empty());
+        assert!(!new_store.lease_collection.contains_lease(1));
+
+        // each chunk is deleted in its own revision
+        for (revision, count) in [(5, 5), (6, 3), (7, 1), (8, 0)] {
+            let range = RangeRequest {
+                key: vec![0],
+                range_end: vec![0],
+                revision,
+                ..Default::default()
+            };
+            assert_eq!(new_store.handle_range_request(&range)?.count, count);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_txn() -> Result<(), ExecuteError> {
+        let txn_req = RequestWrapper::from(TxnRequest {
+            compare: vec![Compare {
+                result: CompareResult::Equal as i32,
+                target: CompareTarget::Value as i32,
+                key: "a".into(),
+                range_end: vec![],
+                target_union: Some(TargetUnion::Value("a".into())),
+            }],
+            success: vec![RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![Compare {
+                        result: CompareResult::Equal as i32,
+                        target: CompareTarget::Value as i32,
+                        key: "b".into(),
+                        range_end: vec![],
+                        target_union: Some(TargetUnion::Value("b".into())),
+                    }],
+                    success: vec![RequestOp {
+                        request: Some(Request::RequestPut(PutRequest {
+                            key: "success".into(),
+                            value: "1".into(),
+                            ..Default::default()
+                        })),
+                    }],
+                    failure: vec![],
+                })),
+            }],
+            failure: vec![RequestOp {
+                request: Some(Request::RequestPut(PutRequest {
+                    key: "success".into(),
+                    value: "0".into(),
+                    ..Default::default()
+                })),
+            }],
+        });
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        exe_as_and_flush(&store, &txn_req, rev.next()).await?;
+        let request = RangeRequest {
+            key: "success".into(),
+            range_end: vec![],
+            ..Default::default()
+        };
+        let response = store.handle_range_request(&request)?;
+        assert_eq!(response.count, 1);
+        assert_eq!(response.kvs.len(), 1);
+        assert_eq!(response.kvs[0].value, "1".as_bytes());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn txn_should_carry_the_sub_revisions_of_its_mutations() -> Result<(), ExecuteError> {
+        let put = |key: &str| RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                ..Default::default()
+            })),
+        };
+        let delete = |key: &str, range_end: &str| RequestOp {
+            request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                key: key.into(),
+                range_end: range_end.into(),
+                ..Default::default()
+            })),
+        };
+        // the delete of "a" to "c" takes 2 sub revisions, the delete of "y" none
+        let txn_req = RequestWrapper::from(TxnRequest {
+            success: vec![
+                put("x"),
+                delete("a", "c"),
+                put("c"),
+                delete("y", ""),
+                put("z"),
+            ],
+            ..Default::default()
+        });
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        let ResponseWrapper::TxnResponse(response) = store.execute(&txn_req)?.into_inner() else {
+            panic!("the response of a txn should be a txn response");
+        };
+        let (sync_res, ops) = store.after_sync(&txn_req, rev.next()).await?;
+        assert_eq!(sync_res.sub_revisions(), [0, 1, 3, 4, 4]);
+        assert_eq!(sync_res.sub_revisions(), response.sub_revisions());
+        store.insert_index(store.inner.db.flush_ops(ops)?);
+
+        let put_req = RequestWrapper::from(PutRequest {
+            key: "w".into(),
+            ..Default::default()
+        });
+        let (sync_res, _ops) = store.after_sync(&put_req, rev.next()).await?;
+        assert!(sync_res.sub_revisions().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_compare_golden_table() -> Result<(), ExecuteError> {
+        // the outcomes of the operators `=`, `!=`, `>` and `<`, when the compared
+        // value is equal to, less than or greater than the target, or when all fail
+        const EQ: [bool; 4] = [true, false, false, false];
+        const LT: [bool; 4] = [false, true, false, true];
+        const GT: [bool; 4] = [false, true, true, false];
+        const NONE: [bool; 4] = [false, false, false, false];
+
+        let db = DB::open(&EngineConfig::Memory)?;
+        // a..e are put at revisions 2..6, z is put 3 times at revisions 7..9
+        let (store, rev) = init_store(db).await?;
+        let delete = RequestWrapper::from(DeleteRangeRequest {
+            key: "e".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &delete, rev.next()).await?;
+
+        let version = |v| Some(TargetUnion::Version(v));
+        let create = |v| Some(TargetUnion::CreateRevision(v));
+        let modify = |v| Some(TargetUnion::ModRevision(v));
+        let lease = |v| Some(TargetUnion::Lease(v));
+        let value = |v: &str| Some(TargetUnion::Value(v.into()));
+        // (target, target union, key, range end, outcomes), cross-checked against
+        // `applyCompare` and `compareKV` of etcd
+        let table = [
+            // a missing key compares as 0 for any number
+            (CompareTarget::Version, version(0), "x", "", EQ),
+            (CompareTarget::Version, version(1), "x", "", LT),
+            (CompareTarget::Create, create(0), "x", "", EQ),
+            (CompareTarget::Create, create(1), "x", "", LT),
+            (CompareTarget::Mod, modify(0), "x", "", EQ),
+            (CompareTarget::Mod, modify(1), "x", "", LT),
+            (CompareTarget::Lease, lease(0), "x", "", EQ),
+            (CompareTarget::Lease, lease(1), "x", "", LT),
+            (CompareTarget::Version, value("x"), "x", "", EQ),
+            // but a value compare fails, even against an empty or unset value
+            (CompareTarget::Value, value(""), "x", "", NONE),
+            (CompareTarget::Value, value("x"), "x", "", NONE),
+            (CompareTarget::Value, None, "x", "", NONE),
+            (CompareTarget::Value, version(0), "x", "", NONE),
+            // a deleted key is missing
+            (CompareTarget::Version, version(0), "e", "", EQ),
+            (CompareTarget::Create, create(0), "e", "", EQ),
+            (CompareTarget::Mod, modify(5), "e", "", LT),
+            (CompareTarget::Value, value("e"), "e", "", NONE),
+            // an existing key compares its own fields
+            (CompareTarget::Version, version(1), "a", "", EQ),
+            (CompareTarget::Version, version(0), "a", "", GT),
+            (CompareTarget::Version, version(2), "a", "", LT),
+            (CompareTarget::Create, create(2), "a", "", EQ),
+            (CompareTarget::Create, create(0), "a", "", GT),
+            (CompareTarget::Mod, modify(2), "a", "", EQ),
+            (CompareTarget::Mod, modify(3), "a", "", LT),
+            (CompareTarget::Lease, lease(0), "a", "", EQ),
+            (CompareTarget::Lease, lease(1), "a", "", LT),
+            (CompareTarget::Value, value("a"), "a", "", EQ),
+            (CompareTarget::Value, value(""), "a", "", GT),
+            (CompareTarget::Value, value("b"), "a", "", LT),
+            // a mismatched target union compares against the zero value of the target
+            (CompareTarget::Value, version(0), "a", "", GT),
+            (CompareTarget::Create, value("a"), "a", "", GT),
+            // a range without any key is a missing key
+            (CompareTarget::Create, create(0), "f", "y", EQ),
+            (CompareTarget::Version, version(1), "f", "y", LT),
+            (CompareTarget::Value, value(""), "f", "y", NONE),
+            // only the existing keys of a range are compared, here c, d and z
+            (CompareTarget::Create, create(0), "c", "zz", GT),
+            (CompareTarget::Create, create(5), "c", "zz", NONE),
+            (CompareTarget::Create, create(8), "c", "zz", LT),
+            (CompareTarget::Version, version(0), "c", "zz", GT),
+            (CompareTarget::Version, version(1), "c", "zz", NONE),
+            (CompareTarget::Mod, modify(10), "c", "zz", LT),
+            (CompareTarget::Lease, lease(0), "c", "zz", EQ),
+            (CompareTarget::Value, value("b"), "c", "zz", GT),
+            (CompareTarget::Value, value("c"), "c", "zz", NONE),
+            // the whole key space
+            (CompareTarget::Create, create(0), "\0", "\0", GT),
+        ];
+        let results = [
+            CompareResult::Equal,
+            CompareResult::NotEqual,
+            CompareResult::Greater,
+            CompareResult::Less,
+        ];
+        for (target, target_union, key, range_end, outcomes) in table {
+            for (result, expected) in results.into_iter().zip(outcomes) {
+                let cmp = Compare {
+                    result: result as i32,
+                    target: target as i32,
+                    key: key.into(),
+                    range_end: range_end.into(),
+                    target_union: target_union.clone(),
+                };
+                assert_eq!(store.check_compare(&cmp), expected, "{cmp:?}");
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_kv_store_index_available() {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let (store, revision) = init_store(Arc::clone(&db)).await.unwrap();
+        let handle = tokio::spawn({
+            let store = Arc::clone(&store);
+            async move {
+                for i in 0..100_u8 {
+                    let req = RequestWrapper::from(PutRequest {
+                        key: "foo".into(),
+                        value: vec![i],
+                        ..Default::default()
+                    });
+                    exe_as_and_flush(&store, &req, revision.next())
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_micros(50)).await;
+        let revs = store.inner.index.get_from_rev(b"foo", b"", 1);
+        let kvs = store.inner.get_values(&revs).unwrap();
+        assert_eq!(
+            kvs.len(),
+            revs.len(),
+            "kvs.len() != revs.len(), maybe some operations already inserted into index, but not flushed to db"
+        );
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_hash_kv_should_pin_synced_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let put = |key: &str| {
+            RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                ..Default::default()
+            })
+        };
+        for (rev, key) in [(2, "a"), (3, "b")] {
+            let _guard = store.begin_sync(rev);
+            exe_as_and_flush(&store, &put(key), rev).await?;
+        }
+        let (hash, _, hash_rev) = store.hash_kv(0)?;
+        assert_eq!(hash_rev, 3);
+
+        // revision 5 is synced before revision 4
+        let guard = store.begin_sync(4);
+        {
+            let _guard = store.begin_sync(5);
+            exe_as_and_flush(&store, &put("c"), 5).await?;
+        }
+        assert_eq!(store.synced_revision(), 3);
+        assert_eq!(store.hash_kv(0)?, (hash, -1, 3));
+        assert!(matches!(
+            store.hash_kv(4),
+            Err(ExecuteError::RevisionTooLarge(4, 3))
+        ));
+
+        exe_as_and_flush(&store, &put("d"), 4).await?;
+        drop(guard);
+        assert_eq!(store.synced_revision(), 5);
+        let (_, _, hash_rev) = store.hash_kv(0)?;
+        assert_eq!(hash_rev, 5);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn registered_revision_should_hold_back_the_synced_revision() -> Result<(), ExecuteError>
+    {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        // revision 2 is reserved but not applied yet when revision 3 is synced
+        store.register_sync(2);
+        drop(store.begin_sync(3));
+        assert_eq!(store.synced_revision(), 1);
+        drop(store.resume_sync(2));
+        assert_eq!(store.synced_revision(), 3);
+
+        // the revisions reserved before a reset are never applied
+        store.register_sync(4);
+        drop(store.begin_sync(5));
+        assert_eq!(store.synced_revision(), 3);
+        store.clear_syncing();
+        assert_eq!(store.synced_revision(), 5);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn wait_synced_should_wait_for_lower_revisions() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let guard = store.begin_sync(2);
+        drop(store.begin_sync(3));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), store.wait_synced(3))
+                .await
+                .is_err()
+        );
+
+        let sync_later = async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        };
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            futures::future::join(store.wait_synced(3), sync_later),
+        )
+        .await
+        .expect("revision 3 should be synced once revision 2 is");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_put_and_delete_should_update_lease_attachment() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        let leases = Arc::clone(&store.lease_collection);
+        let _ignore1 = leases.grant(1, 60, false);
+        let _ignore2 = leases.grant(2, 60, false);
+        let put = |lease, ignore_lease| {
+            RequestWrapper::from(PutRequest {
+                key: "foo".into(),
+                value: "bar".into(),
+                lease,
+                ignore_lease,
+                ..Default::default()
+            })
+        };
+        let keys_of = |id| leases.look_up(id).unwrap().keys();
+
+        exe_as_and_flush(&store, &put(1, false), revision.next()).await?;
+        assert_eq!(leases.get_lease(b"foo"), 1);
+        // same lease, the attachment stays as it is
+        exe_as_and_flush(&store, &put(1, false), revision.next()).await?;
+        assert_eq!(keys_of(1), vec![b"foo".to_vec()]);
+        // ignore_lease keeps the current lease
+        exe_as_and_flush(&store, &put(0, true), revision.next()).await?;
+        assert_eq!(leases.get_lease(b"foo"), 1);
+        // re-leased to another lease
+        exe_as_and_flush(&store, &put(2, false), revision.next()).await?;
+        assert_eq!(leases.get_lease(b"foo"), 2);
+        assert!(keys_of(1).is_empty());
+        // overwritten without a lease
+        exe_as_and_flush(&store, &put(0, false), revision.next()).await?;
+        assert_eq!(leases.get_lease(b"foo"), 0);
+        assert!(keys_of(2).is_empty());
+        // deleted
+        exe_as_and_flush(&store, &put(2, false), revision.next()).await?;
+        let delete = RequestWrapper::from(DeleteRangeRequest {
+            key: "foo".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &delete, revision.next()).await?;
+        assert_eq!(leases.get_lease(b"foo"), 0);
+        assert!(keys_of(2).is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_put_to_revoking_lease_should_fail_to_sync() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let leases = Arc::clone(&store.lease_collection);
+        let _ignore = leases.grant(1, 60, false);
+        let put = RequestWrapper::from(PutRequest {
+            key: "foo".into(),
+            value: "bar".into(),
+            lease: 1,
+            ..Default::default()
+        });
+        let _res = store.execute(&put)?;
+
+        // the lease starts to be revoked between the execution and the sync
+        assert!(leases.start_revoke(1).is_some());
+        assert!(matches!(
+            store.after_sync(&put, 2).await,
+            Err(ApplyError::Execute(ExecuteError::LeaseNotFound(1)))
+        ));
+        assert!(store.inner.get_range(b"foo", &[], 0)?.is_empty());
+        assert_eq!(leases.get_lease(b"foo"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_put_to_full_lease_should_be_rejected() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store_with_leases(db, LeaseCollection::new(0).with_max_keys(2));
+        let revision = RevisionNumberGenerator::default();
+        let leases = Arc::clone(&store.lease_collection);
+        let _ignore = leases.grant(1, 60, false);
+        let put = |key: &str| {
+            RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                lease: 1,
+                ..Default::default()
+            })
+        };
+        for key in ["a", "b"] {
+            exe_as_and_flush(&store, &put(key), revision.next()).await?;
+        }
+
+        assert!(matches!(
+            store.execute(&put("c")),
+            Err(ExecuteError::LeaseKeysExceeded(1))
+        ));
+        assert!(matches!(
+            exe_as_and_flush(&store, &put("c"), revision.next()).await,
+            Err(ExecuteError::LeaseKeysExceeded(1))
+        ));
+        assert!(store.inner.get_range(b"c", &[], 0)?.is_empty());
+        assert_eq!(leases.get_lease(b"c"), 0);
+        let mut keys = leases.look_up(1).unwrap().keys();
+        keys.sort();
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+
+        // keys already attached to the lease can still be updated
+        assert!(store.execute(&put("a")).is_ok());
+        exe_as_and_flush(&store, &put("a"), revision.next()).await?;
+
+        // the keys a txn attaches are counted together
+        let _ignore = leases.grant(2, 60, false);
+        let txn = |keys: &[&str]| {
+            RequestWrapper::from(TxnRequest {
+                compare: vec![],
+                success: keys
+                    .iter()
+                    .map(|key| RequestOp {
+                        request: Some(UniRequest::RequestPut(PutRequest {
+                            key: key.as_bytes().to_vec(),
+                            value: "v".into(),
+                            lease: 2,
+                            ..Default::default()
+                        })),
+                    })
+                    .collect(),
+                failure: vec![],
+            })
+        };
+        let full = txn(&["x", "y", "z"]);
+        assert!(matches!(
+            store.check_writes(&full),
+            Err(ExecuteError::LeaseKeysExceeded(2))
+        ));
+        assert!(matches!(
+            store.execute(&full),
+            Err(ExecuteError::LeaseKeysExceeded(2))
+        ));
+        let fits = txn(&["x", "y", "x"]);
+        store.check_writes(&fits)?;
+        exe_as_and_flush(&store, &fits, revision.next()).await?;
+        assert_eq!(leases.look_up(2).unwrap().keys().len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compaction() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        // sample requests: (a, 1) (b, 2) (a, 3) (del a)
+        // their revisions:     2      3      4       5
+        let requests = vec![
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "1".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: "b".into(),
+                value: "2".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "3".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(DeleteRangeRequest {
+                key: "a".into(),
+                ..Default::default()
+            }),
+        ];
+
+        for req in requests {
+            exe_as_and_flush(&store, &req, revision.next())
+                .await
+                .unwrap();
+        }
+
+        let target_revisions = index_compact(&store, 3);
+        store.compact(target_revisions.as_ref())?;
+        assert_eq!(
+            store.inner.get_range(b"a", b"", 2).unwrap().len(),
+            1,
+            "(a, 1) should not be removed"
+        );
+        assert_eq!(
+            store.inner.get_range(b"b", b"", 3).unwrap().len(),
+            1,
+            "(b, 2) should not be removed"
+        );
+
+        let target_revisions = index_compact(&store, 4);
+        store.compact(target_revisions.as_ref())?;
+        assert!(
+            store.inner.get_range(b"a", b"", 2).unwrap().is_empty(),
+            "(a, 1) should be removed"
+        );
+        assert_eq!(
+            store.inner.get_range(b"b", b"", 3).unwrap().len(),
+            1,
+            "(b, 2) should not be removed"
+        );
+        assert_eq!(
+            store.inner.get_range(b"a", b"", 4).unwrap().len(),
+            1,
+            "(a, 3) should not be removed"
+        );
+
+        let target_revisions = index_compact(&store, 5);
+        store.compact(target_revisions.as_ref())?;
+        assert!(
+            store.inner.get_range(b"a", b"", 2).unwrap().is_empty(),
+            "(a, 1) should be removed"
+        );
+        assert_eq!(
+            store.inner.get_range(b"b", b"", 3).unwrap().len(),
+            1,
+            "(b, 2) should not be removed"
+        );
+        assert!(
+            store.inner.get_range(b"a", b"", 4).unwrap().is_empty(),
+            "(a, 3) should be removed"
+        );
+        assert!(
+            store.inner.get_range(b"a", b"", 5).unwrap().is_empty(),
+            "(a, 4) should be removed"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_compaction_should_keep_exempt_history() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let put = |key: &str, value: &str| {
+            RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..Default::default()
+            })
+        };
+        let range = |key: &str, range_end: &str, revision: i64| RangeRequest {
+            key: key.into(),
+            range_end: range_end.into(),
+            revision,
+            ..Default::default()
+        };
+        for (rev, key) in [(2, "/audit/a"), (3, "/other")] {
+            let _guard = store.begin_sync(rev);
+            exe_as_and_flush(&store, &put(key, "1"), rev).await?;
+        }
+        // the history under "/audit/" is kept since revision 4 of the exemption
+        let exemption = RequestWrapper::from(CompactionExemptionRequest {
+            add: vec!["/audit/".into()],
+            remove: vec![],
+        });
+        let _res = store.execute(&exemption)?;
+        {
+            let _guard = store.begin_sync(4);
+            exe_as_and_flush(&store, &exemption, 4).await?;
+        }
+        for (rev, key) in [(5, "/audit/a"), (6, "/other")] {
+            let _guard = store.begin_sync(rev);
+            exe_as_and_flush(&store, &put(key, "2"), rev).await?;
+        }
+        let compaction = RequestWrapper::from(CompactionRequest {
+            revision: 6,
+            physical: true,
+        });
+        let _res = store.execute(&compaction)?;
+        exe_as_and_flush(&store, &compaction, 6).await?;
+
+        let res = store.handle_range_request(&range("/audit/a", "", 4))?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, b"1");
+        assert_eq!(res.kvs[0].mod_revision, 2);
+        assert_eq!(
+            store
+                .handle_range_request(&range("/audit/", "/audit0", 4))?
+                .kvs
+                .len(),
+            1
+        );
+        assert!(matches!(
+            store.handle_range_request(&range("/audit/a", "", 3)),
+            Err(ExecuteError::RevisionCompacted(3, 4))
+        ));
+        assert!(matches!(
+            store.handle_range_request(&range("/other", "", 5)),
+            Err(ExecuteError::RevisionCompacted(5, 6))
+        ));
+        // the range crosses the boundary of the exempt prefix
+        assert!(matches!(
+            store.handle_range_request(&range("/", "0", 4)),
+            Err(ExecuteError::RevisionCompacted(4, 6))
+        ));
+        assert_eq!(store.inner.get_range(b"/other", b"", 4)?.len(), 0);
+
+        // the exempt prefixes are recovered from the storage
+        let new_store = init_empty_store(db);
+        new_store.recover().await?;
+        assert_eq!(
+            new_store.exempt_prefixes().prefixes(),
+            vec![b"/audit/".to_vec()]
+        );
+        let res = new_store.handle_range_request(&range("/audit/a", "", 4))?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, b"1");
+
+        // the history below the compacted revision can't be read once the prefix is removed
+        let exemption = RequestWrapper::from(CompactionExemptionRequest {
+            add: vec![],
+            remove: vec!["/audit/".into()],
+        });
+        let res = new_store.execute(&exemption)?.into_inner();
+        let ResponseWrapper::CompactionExemptionResponse(res) = res else {
+            panic!("unexpected response: {res:?}");
+        };
+        assert!(res.prefixes.is_empty());
+        {
+            let _guard = new_store.begin_sync(7);
+            exe_as_and_flush(&new_store, &exemption, 7).await?;
+        }
+        assert!(matches!(
+            new_store.handle_range_request(&range("/audit/a", "", 4)),
+            Err(ExecuteError::RevisionCompacted(4, 6))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_historical_range_under_concurrent_compaction() -> Result<(), ExecuteError> {
+        // every compaction waits for this many more successful reads
+        const READS_PER_COMPACTION: usize = 2;
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        // 20 keys written 10 times, every key exists since revision 21
+        for round in 0..10_u8 {
+            for k in 0..20_u8 {
+                let req = RequestWrapper::from(PutRequest {
+                    key: vec![b'k', k],
+                    value: vec![round],
+                    ..Default::default()
+                });
+                exe_as_and_flush(&store, &req, revision.next()).await?;
+            }
+        }
+        let current = revision.get();
+        // key k of round r is written at revision 2 + 20 * r + k
+        let expected_at = |pinned: i64| -> Vec<KeyValue> {
+            (0..20_u8)
+                .map(|k| {
+                    let round = (pinned - 2 - i64::from(k)) / 20;
+                    KeyValue {
+                        key: vec![b'k', k],
+                        create_revision: 2 + i64::from(k),
+                        mod_revision: 2 + 20 * round + i64::from(k),
+                        version: round + 1,
+                        value: Bytes::from(vec![round.numeric_cast::<u8>()]),
+                        lease: 0,
+                    }
+                })
+                .collect()
+        };
+
+        let full_reads = Arc::new(AtomicUsize::new(0));
+        let compactions = (21..current).step_by(7).count();
+        let compactor = tokio::spawn({
+            let store = Arc::clone(&store);
+            let full_reads = Arc::clone(&full_reads);
+            async move {
+                for (n, target) in (21..current).step_by(7).enumerate() {
+                    while full_reads.load(Relaxed) < (n + 1) * READS_PER_COMPACTION {
+                        tokio::task::yield_now().await;
+                    }
+                    let req = RequestWrapper::from(CompactionRequest {
+                        revision: target,
+                        physical: true,
+                    });
+                    let _res = store.execute(&req).unwrap();
+                    exe_as_and_flush(&store, &req, current).await.unwrap();
+                }
+            }
+        });
+
+        for i in 0.. {
+            if compactor.is_finished() {
+                break;
+            }
+            let pinned = (store.compacted_revision().max(20) + i % 5).min(current);
+            let req = RangeRequest {
+                key: vec![b'k'],
+                range_end: vec![b'l'],
+                revision: pinned,
+                ..Default::default()
+            };
+            match store.handle_range_request(&req) {
+                Ok(res) => {
+                    assert_eq!(res.kvs, expected_at(pinned), "read at revision {pinned}");
+                    assert_eq!(res.count, 20);
+                    let _prev = full_reads.fetch_add(1, Relaxed);
+                }
+                Err(ExecuteError::RevisionCompacted(required, compacted)) => {
+                    assert_eq!(required, pinned);
+                    assert!(compacted > pinned);
+                }
+                Err(e) => panic!("unexpected error at revision {pinned}: {e}"),
+            }
+            tokio::task::yield_now().await;
+        }
+        compactor.await.unwrap();
+        assert!(full_reads.load(Relaxed) >= compactions * READS_PER_COMPACTION);
+        assert_eq!(
+            store.compacted_revision(),
+            (21..current).step_by(7).last().unwrap()
+        );
+        let req = RangeRequest {
+            key: vec![b'k'],
+            range_end: vec![b'l'],
+            revision: current,
+            ..Default::default()
+        };
+        assert_eq!(store.handle_range_request(&req)?.kvs, expected_at(current));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_parallel_apply_should_reserve_one_revision_per_mutating_entry(
+    ) -> Result<(), ExecuteError> {
+        const ENTRIES: usize = 64;
+        const WRITES: usize = 4;
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = Arc::new(RevisionNumberGenerator::default());
+        let initial = revision.get();
+        let op = |request| RequestOp {
+            request: Some(request),
+        };
+        // every entry writes keys of its own, so that the entries don't conflict and
+        // are applied in parallel, one in four is read-only
+        let handles: Vec<_> = (0..ENTRIES)
+            .map(|i| {
+                let ops = if i % 4 == 0 {
+                    vec![op(UniRequest::RequestRange(RangeRequest {
+                        key: format!("{i:02}/").into_bytes(),
+                        ..Default::default()
+                    }))]
+                } else {
+                    (0..WRITES)
+                        .map(|w| {
+                            op(UniRequest::RequestPut(PutRequest {
+                                key: format!("{i:02}/{w}").into_bytes(),
+                                value: b"v".to_vec(),
+                                ..Default::default()
+                            }))
+                        })
+                        .collect()
+                };
+                let req = RequestWrapper::from(TxnRequest {
+                    compare: vec![],
+                    success: ops,
+                    failure: vec![],
+                });
+                let store = Arc::clone(&store);
+                let revision = Arc::clone(&revision);
+                tokio::spawn(async move {
+                    let rev = revision.reserve(!req.skip_general_revision())?;
+                    let _guard = store.begin_sync(rev);
+                    exe_as_and_flush(&store, &req, rev).await.unwrap();
+                    Some((i, rev))
+                })
+            })
+            .collect();
+        let mut applied = vec![];
+        for handle in handles {
+            applied.extend(handle.await.unwrap());
+        }
+
+        let mutating = ENTRIES - ENTRIES / 4;
+        assert_eq!(applied.len(), mutating);
+        assert_eq!(revision.get(), initial + mutating.numeric_cast::<i64>());
+        assert_eq!(store.synced_revision(), revision.get());
+        let mut revisions: Vec<_> = applied.iter().map(|&(_, rev)| rev).collect();
+        revisions.sort_unstable();
+        revisions.dedup();
+        assert_eq!(
+            revisions.len(),
+            mutating,
+            "two entries share a main revision"
+        );
+        for (i, rev) in applied {
+            let key_revisions = store.inner.index.get(
+                format!("{i:02}/").as_bytes(),
+                format!("{i:02}0").as_bytes(),
+                0,
+            );
+            assert!(key_revisions.iter().all(|r| r.revision() == rev));
+            let sub_revisions: Vec<_> = key_revisions.iter().map(Revision::sub_revision).collect();
+            assert_eq!(
+                sub_revisions,
+                (0..WRITES)
+                    .map(NumericCast::numeric_cast)
+                    .collect::<Vec<i64>>()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_revision_will_return_correct_error_type() {
+        let request = TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(UniRequest::RequestRange(RangeRequest {
+                    key: "k".into(),
+                    revision: 3,
+                    ..Default::default()
+                })),
+            }],
+            failure: vec![],
+        };
+        assert!(matches!(
+            request.check_revision(1, 2).unwrap_err(),
+            ExecuteError::RevisionTooLarge(_, _)
+        ));
+
+        let request = RangeRequest {
+            key: "k".into(),
+            revision: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            request.check_revision(2, 3).unwrap_err(),
+            ExecuteError::RevisionCompacted(_, _)
+        ));
+    }
+
+    #[test]
+    fn check_compaction_will_return_correct_error_type() {
+        let request = CompactionRequest {
+            revision: 3,
+            physical: false,
+        };
+        assert!(matches!(
+            request.check_revision(1, 2).unwrap_err(),
+            ExecuteError::RevisionTooLarge(_, _)
+        ));
+
+        let request = CompactionRequest {
+            revision: 1,
+            physical: false,
+        };
+        assert!(matches!(
+            request.check_revision(2, 3).unwrap_err(),
+            ExecuteError::RevisionCompacted(_, _)
+        ));
+    }
+}
@@ -0,0 +1,828 @@
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use test_macros::abort_on_panic;
+use utils::config::EngineConfig;
+use xlineapi::request_validation::RequestValidator;
+
+use super::*;
+use crate::{
+    rpc::{RequestOp, Response, ResponseHeader, ResponseOp},
+    storage::compact::COMPACT_CHANNEL_SIZE,
+};
+
+/// Keys of the requests, a tiny alphabet makes the requests overlap
+const KEYS: [&[u8]; 4] = [b"a", b"b", b"c", b"d"];
+/// Values of the puts
+const VALUES: [&[u8]; 3] = [b"x", b"y", b"z"];
+/// Number of the random sequences to check
+const SEQUENCES: u64 = 200;
+/// Number of requests in a random sequence
+const SEQUENCE_LEN: usize = 30;
+/// Max depth of the nested txns
+const MAX_TXN_DEPTH: usize = 2;
+/// Size of the kv update channel
+const CHANNEL_SIZE: usize = 1024;
+
+/// The outcome of a request
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    /// The response, or the debug string of the error
+    response: Result<ResponseOp, String>,
+    /// The revision and the events sent to the watcher
+    update: Option<(i64, Vec<Event>)>,
+}
+
+/// Reference model of the kv store
+///
+/// Like the store, the reads and compares of a txn, nested ones included, see the state
+/// before the txn.
+#[derive(Debug, Default)]
+struct Model {
+    /// Versions of the keys, a deletion is a tombstone of version 0
+    keys: BTreeMap<Vec<u8>, Vec<KeyValue>>,
+    /// Current revision
+    revision: i64,
+}
+
+impl Model {
+    /// Execute a request and apply its writes
+    fn execute(&mut self, request: &Request) -> Outcome {
+        let takes_revision = takes_revision(request);
+        if takes_revision {
+            self.revision += 1;
+        }
+        let response = self.response(request).map(|response| ResponseOp {
+            response: Some(response),
+        });
+        if response.is_err() || !takes_revision {
+            return Outcome {
+                response,
+                update: None,
+            };
+        }
+        let mut writes = Vec::new();
+        self.collect_writes(request, &mut writes);
+        let mut events = Vec::new();
+        for write in writes {
+            events.append(&mut self.apply(write));
+        }
+        Outcome {
+            response,
+            update: Some((self.revision, events)),
+        }
+    }
+
+    /// The response of a request in the current state
+    fn response(&self, request: &Request) -> Result<Response, String> {
+        let header = Some(ResponseHeader {
+            revision: self.revision,
+            ..ResponseHeader::default()
+        });
+        let response = match *request {
+            Request::RequestRange(ref req) => {
+                if req.revision > self.revision {
+                    let err = ExecuteError::RevisionTooLarge(req.revision, self.revision);
+                    return Err(format!("{err:?}"));
+                }
+                Response::ResponseRange(self.range_response(req, header))
+            }
+            Request::RequestPut(ref req) => Response::ResponsePut(PutResponse {
+                header,
+                prev_kv: req.prev_kv.then(|| self.get(&req.key).cloned()).flatten(),
+            }),
+            Request::RequestDeleteRange(ref req) => {
+                let prev_kvs = self.range(&req.key, &req.range_end, 0);
+                Response::ResponseDeleteRange(DeleteRangeResponse {
+                    header,
+                    deleted: prev_kvs.len() as i64,
+                    prev_kvs: if req.prev_kv { prev_kvs } else { vec![] },
+                })
+            }
+            Request::RequestTxn(ref req) => {
+                let succeeded = self.check_compares(req);
+                let request_ops = if succeeded {
+                    &req.success
+                } else {
+                    &req.failure
+                };
+                let responses = request_ops
+                    .iter()
+                    .filter_map(|op| op.request.as_ref())
+                    .map(|request| {
+                        self.response(request).map(|response| ResponseOp {
+                            response: Some(response),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Response::ResponseTxn(TxnResponse {
+                    header,
+                    succeeded,
+                    responses,
+                })
+            }
+        };
+        Ok(response)
+    }
+
+    /// The response of a `RangeRequest`
+    fn range_response(&self, req: &RangeRequest, header: Option<ResponseHeader>) -> RangeResponse {
+        let mut kvs = self.range(&req.key, &req.range_end, req.revision);
+        let count = kvs.len() as i64;
+        if req.count_only {
+            kvs.clear();
+        }
+        kvs.retain(|kv| {
+            (req.max_mod_revision <= 0 || kv.mod_revision <= req.max_mod_revision)
+                && (req.min_mod_revision <= 0 || kv.mod_revision >= req.min_mod_revision)
+                && (req.max_create_revision <= 0 || kv.create_revision <= req.max_create_revision)
+                && (req.min_create_revision <= 0 || kv.create_revision >= req.min_create_revision)
+        });
+        let ordering = |a: &KeyValue, b: &KeyValue| match req.sort_target() {
+            SortTarget::Key => a.key.cmp(&b.key),
+            SortTarget::Version => a.version.cmp(&b.version),
+            SortTarget::Create => a.create_revision.cmp(&b.create_revision),
+            SortTarget::Mod => a.mod_revision.cmp(&b.mod_revision),
+            SortTarget::Value => a.value.cmp(&b.value),
+        };
+        match (req.sort_order(), req.sort_target()) {
+            (SortOrder::None, SortTarget::Key) => {}
+            (SortOrder::Descend, _) => kvs.sort_by(|a, b| ordering(b, a)),
+            (SortOrder::Ascend | SortOrder::None, _) => kvs.sort_by(|a, b| ordering(a, b)),
+        }
+        let limit = req.limit as usize;
+        let more = req.limit > 0 && kvs.len() > limit;
+        if req.limit > 0 {
+            kvs.truncate(limit);
+        }
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        RangeResponse {
+            header,
+            kvs,
+            more,
+            count,
+        }
+    }
+
+    /// Collect the writes of a request, in the order they are applied
+    fn collect_writes<'a>(&self, request: &'a Request, writes: &mut Vec<&'a Request>) {
+        match *request {
+            Request::RequestRange(_) => {}
+            Request::RequestPut(_) | Request::RequestDeleteRange(_) => writes.push(request),
+            Request::RequestTxn(ref req) => {
+                let request_ops = if self.check_compares(req) {
+                    &req.success
+                } else {
+                    &req.failure
+                };
+                for request in request_ops.iter().filter_map(|op| op.request.as_ref()) {
+                    self.collect_writes(request, writes);
+                }
+            }
+        }
+    }
+
+    /// Apply a write, return its events
+    fn apply(&mut self, request: &Request) -> Vec<Event> {
+        match *request {
+            Request::RequestPut(ref req) => {
+                let prev = self.get(&req.key).cloned();
+                let kv = KeyValue {
+                    key: req.key.clone(),
+                    value: req.value.clone().into(),
+                    create_revision: prev.as_ref().map_or(self.revision, |kv| kv.create_revision),
+                    mod_revision: self.revision,
+                    version: prev.as_ref().map_or(1, |kv| kv.version + 1),
+                    lease: req.lease,
+                };
+                self.keys
+                    .entry(req.key.clone())
+                    .or_default()
+                    .push(kv.clone());
+                vec![new_event(EventType::Put, kv)]
+            }
+            Request::RequestDeleteRange(ref req) => self
+                .range(&req.key, &req.range_end, 0)
+                .into_iter()
+                .map(|kv| {
+                    let tombstone = KeyValue {
+                        key: kv.key,
+                        mod_revision: self.revision,
+                        ..KeyValue::default()
+                    };
+                    self.keys
+                        .entry(tombstone.key.clone())
+                        .or_default()
+                        .push(tombstone.clone());
+                    new_event(EventType::Delete, tombstone)
+                })
+                .collect(),
+            Request::RequestRange(_) | Request::RequestTxn(_) => {
+                unreachable!("only writes are applied")
+            }
+        }
+    }
+
+    /// Check the compares of a txn
+    fn check_compares(&self, req: &TxnRequest) -> bool {
+        req.compare.iter().all(|cmp| {
+            let kvs = self.range(&cmp.key, &cmp.range_end, 0);
+            if kvs.is_empty() {
+                !matches!(cmp.target_union, Some(TargetUnion::Value(_)))
+                    && compare_kv(cmp, &KeyValue::default())
+            } else {
+                kvs.iter().all(|kv| compare_kv(cmp, kv))
+            }
+        })
+    }
+
+    /// The latest version of a key
+    fn get(&self, key: &[u8]) -> Option<&KeyValue> {
+        self.keys
+            .get(key)
+            .and_then(|versions| version_at(versions, 0))
+    }
+
+    /// The versions of the keys in a range at a revision, in key order
+    fn range(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<KeyValue> {
+        self.keys
+            .iter()
+            .filter(|&(k, _)| in_range(k, key, range_end))
+            .filter_map(|(_, versions)| version_at(versions, revision))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The version of a key at a revision, the latest one if `revision` is 0
+fn version_at(versions: &[KeyValue], revision: i64) -> Option<&KeyValue> {
+    let kv = if revision <= 0 {
+        versions.last()
+    } else {
+        versions.iter().rev().find(|kv| kv.mod_revision <= revision)
+    };
+    kv.filter(|kv| kv.version != 0)
+}
+
+/// Whether a key is in a range, `[0]` stands for no bound as in the requests
+fn in_range(key: &[u8], start: &[u8], end: &[u8]) -> bool {
+    let after_start = *start == [0] || key >= start;
+    match *end {
+        [] => key == start,
+        [0] => after_start,
+        _ => after_start && key < end,
+    }
+}
+
+/// Check a `KeyValue` against a `Compare`
+fn compare_kv(cmp: &Compare, kv: &KeyValue) -> bool {
+    let ordering = match cmp.target_union {
+        Some(TargetUnion::Version(v)) => kv.version.cmp(&v),
+        Some(TargetUnion::CreateRevision(v)) => kv.create_revision.cmp(&v),
+        Some(TargetUnion::ModRevision(v)) => kv.mod_revision.cmp(&v),
+        Some(TargetUnion::Value(ref v)) => kv.value.as_ref().cmp(v.as_slice()),
+        Some(TargetUnion::Lease(v)) => kv.lease.cmp(&v),
+        None => unreachable!("the compares always have targets"),
+    };
+    match cmp.result() {
+        CompareResult::Equal => ordering.is_eq(),
+        CompareResult::Greater => ordering.is_gt(),
+        CompareResult::Less => ordering.is_lt(),
+        CompareResult::NotEqual => ordering.is_ne(),
+    }
+}
+
+/// Whether a request takes a new revision, see `RequestWrapper::skip_general_revision`
+fn takes_revision(request: &Request) -> bool {
+    match *request {
+        Request::RequestRange(_) => false,
+        Request::RequestPut(_) | Request::RequestDeleteRange(_) => true,
+        Request::RequestTxn(ref req) => !req.is_read_only(),
+    }
+}
+
+/// Create an event
+fn new_event(event_type: EventType, kv: KeyValue) -> Event {
+    Event {
+        r#type: event_type as i32,
+        kv: Some(kv),
+        prev_kv: None,
+    }
+}
+
+/// A `KvStore` under test, with the channels it reports to
+struct StoreUnderTest {
+    /// The store
+    store: KvStore,
+    /// The db of the store
+    db: Arc<DB>,
+    /// The header generator of the store, which holds its revision
+    header_gen: Arc<HeaderGenerator>,
+    /// Receives the events of the store
+    kv_update_rx: mpsc::Receiver<KvUpdates>,
+    /// Keeps the compaction channel open
+    _compact_rx: mpsc::Receiver<(i64, Option<Arc<event_listener::Event>>)>,
+}
+
+impl StoreUnderTest {
+    /// Create a store on the db
+    fn new(db: Arc<DB>) -> Self {
+        let (compact_tx, compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
+        let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let inner = Arc::new(KvStoreInner::new(Arc::new(Index::new()), Arc::clone(&db)));
+        let store = KvStore::new(
+            inner,
+            Arc::clone(&header_gen),
+            kv_update_tx,
+            compact_tx,
+            Arc::new(LeaseCollection::new(0)),
+        );
+        Self {
+            store,
+            db,
+            header_gen,
+            kv_update_rx,
+            _compact_rx: compact_rx,
+        }
+    }
+
+    /// Execute and sync a request the way the command executor does
+    async fn execute(&mut self, request: &Request) -> Result<Outcome, String> {
+        let wrapper = RequestWrapper::from(RequestOp {
+            request: Some(request.clone()),
+        });
+        let revision = (!wrapper.skip_general_revision())
+            .then(|| self.header_gen.general_revision_arc().next());
+        let response = self
+            .store
+            .execute(&wrapper)
+            .map(|resp| ResponseOp::from(resp.into_inner()))
+            .map_err(|e| format!("{e:?}"));
+        let Some(revision) = revision.filter(|_| response.is_ok()) else {
+            return Ok(Outcome {
+                response,
+                update: None,
+            });
+        };
+        let (_sync_res, ops) = self
+            .store
+            .after_sync(&wrapper, revision)
+            .await
+            .map_err(|e| format!("after sync failed: {e:?}"))?;
+        let key_revisions = self
+            .db
+            .flush_ops(ops)
+            .map_err(|e| format!("flush failed: {e:?}"))?;
+        self.store.insert_index(key_revisions);
+        // the watchers of the gRPC watch service see the events converted back
+        let update = self.kv_update_rx.try_recv().ok().map(|(revision, events)| {
+            let events = events
+                .iter()
+                .map(|event| Event::from(event.as_ref()))
+                .collect();
+            (revision, events)
+        });
+        Ok(Outcome { response, update })
+    }
+}
+
+/// Run the requests against the store and the model, return the first divergence
+async fn check(requests: &[Request]) -> Result<(), String> {
+    let db = DB::open(&EngineConfig::Memory).map_err(|e| format!("{e:?}"))?;
+    let mut store = StoreUnderTest::new(Arc::clone(&db));
+    let mut model = Model::default();
+    for (i, request) in requests.iter().enumerate() {
+        let expected = model.execute(request);
+        let actual = store.execute(request).await?;
+        if actual != expected {
+            return Err(format!(
+                "request {i} diverges from the model\nexpected: {expected:?}\nactual: {actual:?}"
+            ));
+        }
+    }
+    // the synced writes should rebuild the same history
+    let recovered = StoreUnderTest::new(db);
+    recovered
+        .store
+        .recover()
+        .await
+        .map_err(|e| format!("recover failed: {e:?}"))?;
+    for revision in 0..=recovered.store.revision() {
+        let req = RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            revision,
+            ..RangeRequest::default()
+        };
+        let actual = recovered
+            .store
+            .handle_range_request(&req)
+            .map_err(|e| format!("range of the recovered store failed: {e:?}"))?
+            .kvs;
+        let expected = model.range(&[0], &[0], revision);
+        if actual != expected {
+            return Err(format!(
+                "recovered store diverges from the model at revision {revision}\nexpected: {expected:?}\nactual: {actual:?}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Shrink a failing sequence by dropping requests, and the compares and ops of the
+/// txns, as long as it keeps failing, return the minimal sequence with its failure
+async fn shrink(mut requests: Vec<Request>, mut failure: String) -> (Vec<Request>, String) {
+    'shrink: loop {
+        for candidate in smaller_sequences(&requests) {
+            if let Err(e) = check(&candidate).await {
+                requests = candidate;
+                failure = e;
+                continue 'shrink;
+            }
+        }
+        return (requests, failure);
+    }
+}
+
+/// Sequences one step smaller than the given one
+fn smaller_sequences(requests: &[Request]) -> Vec<Vec<Request>> {
+    let mut sequences = Vec::new();
+    for (i, request) in requests.iter().enumerate() {
+        let mut dropped = requests.to_vec();
+        let _ignore = dropped.remove(i);
+        sequences.push(dropped);
+        if let Request::RequestTxn(ref txn) = *request {
+            for smaller in smaller_txns(txn) {
+                let mut replaced = requests.to_vec();
+                replaced[i] = Request::RequestTxn(smaller);
+                sequences.push(replaced);
+            }
+        }
+    }
+    sequences
+}
+
+/// Txns one step smaller than the given one
+fn smaller_txns(txn: &TxnRequest) -> Vec<TxnRequest> {
+    let mut txns = Vec::new();
+    for i in 0..txn.compare.len() {
+        let mut smaller = txn.clone();
+        let _ignore = smaller.compare.remove(i);
+        txns.push(smaller);
+    }
+    txns.extend(
+        smaller_ops(&txn.success)
+            .into_iter()
+            .map(|success| TxnRequest {
+                success,
+                ..txn.clone()
+            }),
+    );
+    txns.extend(
+        smaller_ops(&txn.failure)
+            .into_iter()
+            .map(|failure| TxnRequest {
+                failure,
+                ..txn.clone()
+            }),
+    );
+    txns
+}
+
+/// Txn ops one step smaller than the given ones
+fn smaller_ops(ops: &[RequestOp]) -> Vec<Vec<RequestOp>> {
+    let mut smaller = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        let mut dropped = ops.to_vec();
+        let _ignore = dropped.remove(i);
+        smaller.push(dropped);
+        if let Some(Request::RequestTxn(ref txn)) = op.request {
+            for txn in smaller_txns(txn) {
+                let mut replaced = ops.to_vec();
+                replaced[i] = RequestOp {
+                    request: Some(Request::RequestTxn(txn)),
+                };
+                smaller.push(replaced);
+            }
+        }
+    }
+    smaller
+}
+
+/// Check whether a request passes the validation of the kv server
+fn is_valid(request: &Request) -> bool {
+    match *request {
+        Request::RequestRange(ref req) => req.validation().is_ok(),
+        Request::RequestPut(ref req) => req.validation().is_ok(),
+        Request::RequestDeleteRange(ref req) => req.validation().is_ok(),
+        Request::RequestTxn(ref req) => req.validation().is_ok(),
+    }
+}
+
+/// Generates random kv requests
+struct RequestGenerator {
+    /// The rng
+    rng: StdRng,
+    /// Number of the generated requests, bounds the revisions the requests refer to
+    generated: i64,
+}
+
+impl RequestGenerator {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            generated: 0,
+        }
+    }
+
+    fn sequence(&mut self, len: usize) -> Vec<Request> {
+        (0..len)
+            .map(|_| {
+                self.generated += 1;
+                loop {
+                    let request = self.request(0);
+                    if is_valid(&request) {
+                        break request;
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn request(&mut self, depth: usize) -> Request {
+        match self.rng.gen_range(0..10) {
+            0..=3 => Request::RequestPut(PutRequest {
+                key: self.key(),
+                value: self.value(),
+                prev_kv: self.rng.gen_bool(0.3),
+                ..PutRequest::default()
+            }),
+            4 | 5 => {
+                let (key, range_end) = self.range();
+                Request::RequestDeleteRange(DeleteRangeRequest {
+                    key,
+                    range_end,
+                    prev_kv: self.rng.gen_bool(0.3),
+                })
+            }
+            6 | 7 => Request::RequestRange(self.range_request(depth)),
+            _ if depth < MAX_TXN_DEPTH => Request::RequestTxn(self.txn(depth)),
+            _ => Request::RequestPut(PutRequest {
+                key: self.key(),
+                value: self.value(),
+                ..PutRequest::default()
+            }),
+        }
+    }
+
+    fn range_request(&mut self, depth: usize) -> RangeRequest {
+        let (key, range_end) = self.range();
+        RangeRequest {
+            key,
+            range_end,
+            limit: self.rng.gen_range(0..=3),
+            // ranges in txns read the latest revision, so that the writes of the txns
+            // don't fail on a future revision
+            revision: if depth == 0 { self.revision() } else { 0 },
+            sort_order: self.rng.gen_range(0..=2),
+            sort_target: self.rng.gen_range(0..=4),
+            keys_only: self.rng.gen_bool(0.2),
+            count_only: self.rng.gen_bool(0.2),
+            min_mod_revision: self.filter(),
+            max_mod_revision: self.filter(),
+            min_create_revision: self.filter(),
+            max_create_revision: self.filter(),
+            ..RangeRequest::default()
+        }
+    }
+
+    fn txn(&mut self, depth: usize) -> TxnRequest {
+        let compares = self.rng.gen_range(0..=2);
+        let successes = self.rng.gen_range(0..=3);
+        let failures = self.rng.gen_range(0..=2);
+        TxnRequest {
+            compare: (0..compares).map(|_| self.compare()).collect(),
+            success: (0..successes).map(|_| self.request_op(depth)).collect(),
+            failure: (0..failures).map(|_| self.request_op(depth)).collect(),
+        }
+    }
+
+    fn request_op(&mut self, depth: usize) -> RequestOp {
+        RequestOp {
+            request: Some(self.request(depth + 1)),
+        }
+    }
+
+    fn compare(&mut self) -> Compare {
+        let (key, range_end) = if self.rng.gen_bool(0.7) {
+            (self.key(), vec![])
+        } else {
+            self.range()
+        };
+        let (target, target_union) = match self.rng.gen_range(0..5) {
+            0 => (
+                CompareTarget::Version,
+                TargetUnion::Version(self.rng.gen_range(0..=3)),
+            ),
+            1 => (
+                CompareTarget::Create,
+                TargetUnion::CreateRevision(self.revision()),
+            ),
+            2 => (
+                CompareTarget::Mod,
+                TargetUnion::ModRevision(self.revision()),
+            ),
+            3 => (CompareTarget::Value, TargetUnion::Value(self.value())),
+            _ => (CompareTarget::Lease, TargetUnion::Lease(0)),
+        };
+        Compare {
+            result: self.rng.gen_range(0..=3),
+            target: target as i32,
+            key,
+            range_end,
+            target_union: Some(target_union),
+        }
+    }
+
+    fn key(&mut self) -> Vec<u8> {
+        KEYS.choose(&mut self.rng).unwrap().to_vec()
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        VALUES.choose(&mut self.rng).unwrap().to_vec()
+    }
+
+    /// A single key, a range or all keys
+    fn range(&mut self) -> (Vec<u8>, Vec<u8>) {
+        let start = self.rng.gen_range(0..KEYS.len());
+        let key = KEYS[start].to_vec();
+        match self.rng.gen_range(0..4) {
+            0 => (key, vec![]),
+            1 => {
+                let end = self.rng.gen_range(start + 1..=KEYS.len());
+                (key, KEYS.get(end).map_or(b"e".to_vec(), |k| k.to_vec()))
+            }
+            2 => (key, vec![0]),
+            _ => (vec![0], vec![0]),
+        }
+    }
+
+    /// A revision up to one past the latest one
+    fn revision(&mut self) -> i64 {
+        self.rng.gen_range(0..=self.generated + 1)
+    }
+
+    /// A revision filter of a range, mostly unset
+    fn filter(&mut self) -> i64 {
+        if self.rng.gen_bool(0.2) {
+            self.revision()
+        } else {
+            0
+        }
+    }
+}
+
+fn put(key: &str, value: &str) -> Request {
+    Request::RequestPut(PutRequest {
+        key: key.into(),
+        value: value.into(),
+        ..PutRequest::default()
+    })
+}
+
+fn delete_range(key: &str, range_end: &str) -> Request {
+    Request::RequestDeleteRange(DeleteRangeRequest {
+        key: key.into(),
+        range_end: range_end.into(),
+        prev_kv: true,
+    })
+}
+
+fn range(key: &str, range_end: &str) -> Request {
+    Request::RequestRange(RangeRequest {
+        key: key.into(),
+        range_end: range_end.into(),
+        ..RangeRequest::default()
+    })
+}
+
+fn txn(compare: Vec<Compare>, success: Vec<Request>, failure: Vec<Request>) -> Request {
+    let into_ops = |requests: Vec<Request>| {
+        requests
+            .into_iter()
+            .map(|request| RequestOp {
+                request: Some(request),
+            })
+            .collect()
+    };
+    Request::RequestTxn(TxnRequest {
+        compare,
+        success: into_ops(success),
+        failure: into_ops(failure),
+    })
+}
+
+fn compare(key: &str, result: CompareResult, target: CompareTarget, value: TargetUnion) -> Compare {
+    Compare {
+        result: result as i32,
+        target: target as i32,
+        key: key.into(),
+        range_end: vec![],
+        target_union: Some(value),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn random_kv_requests_should_match_the_model() {
+    for seed in 0..SEQUENCES {
+        let requests = RequestGenerator::new(seed).sequence(SEQUENCE_LEN);
+        if let Err(failure) = check(&requests).await {
+            let (minimal, failure) = shrink(requests, failure).await;
+            panic!("sequence of seed {seed} fails, the minimal failing sequence: {minimal:#?}\n{failure}");
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn known_discrepancies_should_not_regress() {
+    let sequences = [
+        (
+            "a recreated key should start from version 1",
+            vec![
+                put("a", "x"),
+                put("a", "y"),
+                delete_range("a", ""),
+                put("a", "z"),
+                txn(
+                    vec![compare(
+                        "a",
+                        CompareResult::Equal,
+                        CompareTarget::Version,
+                        TargetUnion::Version(1),
+                    )],
+                    vec![put("b", "x")],
+                    vec![],
+                ),
+                range("a", ""),
+            ],
+        ),
+        (
+            "deleted keys in a range should not take sub revisions",
+            vec![
+                put("a", "x"),
+                put("b", "x"),
+                delete_range("a", ""),
+                txn(vec![], vec![delete_range("a", "c"), put("d", "x")], vec![]),
+            ],
+        ),
+        (
+            "nested compares should see the state before the txn",
+            vec![
+                put("a", "x"),
+                txn(
+                    vec![],
+                    vec![
+                        delete_range("a", ""),
+                        txn(
+                            vec![compare(
+                                "a",
+                                CompareResult::Greater,
+                                CompareTarget::Version,
+                                TargetUnion::Version(0),
+                            )],
+                            vec![put("b", "x")],
+                            vec![put("c", "x")],
+                        ),
+                    ],
+                    vec![],
+                ),
+            ],
+        ),
+        (
+            "lease compares should check the lease",
+            vec![
+                put("a", "x"),
+                txn(
+                    vec![compare(
+                        "a",
+                        CompareResult::Equal,
+                        CompareTarget::Lease,
+                        TargetUnion::Lease(0),
+                    )],
+                    vec![put("b", "x")],
+                    vec![],
+                ),
+            ],
+        ),
+    ];
+    for (discrepancy, requests) in sequences {
+        if let Err(failure) = check(&requests).await {
+            panic!("{discrepancy}: {failure}");
+        }
+    }
+}
@@ -2,14 +2,17 @@ use std::{
     collections::{HashMap, HashSet},
     hash::Hash,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicI64, AtomicU64, Ordering},
         Arc,
     },
     time::Duration,
 };
 
+use bytes::Bytes;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use itertools::Itertools;
 use parking_lot::RwLock;
+use prost::Message;
 use tokio::{
     sync::mpsc::{self, error::TrySendError},
     time::sleep,
@@ -20,14 +23,106 @@ use utils::{
     task_manager::{tasks::TaskName, Listener, TaskManager},
     write_vec,
 };
-use xlineapi::command::KeyRange;
+use xlineapi::{command::KeyRange, execute_error::ExecuteError};
 
-use super::kv_store::KvStoreInner;
-use crate::rpc::{Event, KeyValue};
+use super::{
+    kv_store::KvStoreInner,
+    revision::Revision,
+    watch_audit::{Mutation, WatchAuditor},
+};
+use crate::{
+    metrics,
+    rpc::{Event, EventType, KeyValue},
+};
 
 /// Watch ID
 pub(crate) type WatchId = i64;
 
+/// Capacity of the channel of an internal subscription
+const INTERNAL_CHANNEL_SIZE: usize = 128;
+
+/// Changes of a revision sent to the KV watcher
+pub(crate) type KvUpdates = (i64, Vec<Arc<InternalEvent>>);
+
+/// A change of a key, built by the stores without protobuf types
+///
+/// The key and the value are shared, so that an event is allocated once for all of
+/// its consumers. Watchers of the gRPC watch service get it converted to an `Event`
+/// once, however many of them watch the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InternalEvent {
+    /// Put or delete
+    pub(crate) event_type: EventType,
+    /// The changed key
+    pub(crate) key: Bytes,
+    /// The value put, empty for a delete
+    pub(crate) value: Bytes,
+    /// The revision of the change
+    pub(crate) revision: i64,
+    /// The create revision of the key, 0 for a delete
+    pub(crate) create_revision: i64,
+    /// The version of the key, 0 for a delete
+    pub(crate) version: i64,
+    /// The lease attached to the key, 0 for none
+    pub(crate) lease: i64,
+}
+
+impl InternalEvent {
+    /// A put of the key value, the key is moved without copying
+    pub(crate) fn put(kv: KeyValue) -> Self {
+        Self {
+            event_type: EventType::Put,
+            key: kv.key.into(),
+            value: kv.value,
+            revision: kv.mod_revision,
+            create_revision: kv.create_revision,
+            version: kv.version,
+            lease: kv.lease,
+        }
+    }
+
+    /// A deletion of the key at the revision, the key is moved without copying
+    pub(crate) fn delete(key: Vec<u8>, revision: i64) -> Self {
+        Self {
+            event_type: EventType::Delete,
+            key: key.into(),
+            value: Bytes::new(),
+            revision,
+            create_revision: 0,
+            version: 0,
+            lease: 0,
+        }
+    }
+}
+
+impl From<&InternalEvent> for Event {
+    fn from(event: &InternalEvent) -> Self {
+        let mut converted = Event {
+            kv: Some(KeyValue {
+                key: event.key.to_vec(),
+                value: event.value.clone(),
+                create_revision: event.create_revision,
+                mod_revision: event.revision,
+                version: event.version,
+                lease: event.lease,
+            }),
+            prev_kv: None,
+            ..Default::default()
+        };
+        converted.set_type(event.event_type);
+        converted
+    }
+}
+
+/// An internal subscription to the changes of a key range
+#[derive(Debug)]
+struct InternalSubscriber {
+    /// Key Range
+    key_range: KeyRange,
+    /// Sender of the changes
+    event_tx: mpsc::Sender<Arc<InternalEvent>>,
+}
+
 /// Watch ID generator
 #[derive(Debug)]
 pub(crate) struct WatchIdGenerator(AtomicI64);
@@ -44,6 +139,93 @@ impl WatchIdGenerator {
     }
 }
 
+/// The memory used by the watch events buffered for all watchers
+///
+/// Events are charged when they are handed to a watcher and credited once the
+/// `WatchEvent` holding them is dropped, that is when the events are delivered
+/// to the watch stream or evicted from a victim. Events that don't fit in the
+/// budget are not buffered at all, the watcher reads them from the backend once
+/// the memory is released.
+#[derive(Debug)]
+pub(crate) struct WatchMemory {
+    /// Max bytes of the buffered events, 0 means unlimited
+    budget: u64,
+    /// Bytes of the buffered events
+    used: AtomicU64,
+}
+
+impl WatchMemory {
+    /// Create a new `WatchMemory`
+    pub(crate) fn new(budget: u64) -> Arc<Self> {
+        let memory = Arc::new(Self {
+            budget,
+            used: AtomicU64::new(0),
+        });
+        metrics::register_watch_memory(&memory);
+        memory
+    }
+
+    /// Get the bytes of the buffered events
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// The bytes used beyond the budget
+    fn excess(&self) -> u64 {
+        if self.budget == 0 {
+            return 0;
+        }
+        self.used().saturating_sub(self.budget)
+    }
+
+    /// Charge the given events to the memory and the backlog of a watcher, returns
+    /// `None` if they don't fit in the budget.
+    ///
+    /// The events are always admitted when nothing is buffered, so a single batch
+    /// larger than the budget can still be delivered.
+    fn try_charge(
+        self: &Arc<Self>,
+        backlog: &Arc<AtomicU64>,
+        events: &[Event],
+    ) -> Option<MemoryCharge> {
+        let bytes = events
+            .iter()
+            .map(|event| event.encoded_len().numeric_cast::<u64>())
+            .fold(0, u64::overflow_add);
+        let budget = self.budget;
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                let charged = used.overflow_add(bytes);
+                (budget == 0 || bytes == 0 || used == 0 || charged <= budget).then_some(charged)
+            })
+            .ok()?;
+        let _ignore = backlog.fetch_add(bytes, Ordering::Relaxed);
+        Some(MemoryCharge {
+            memory: Arc::clone(self),
+            backlog: Arc::clone(backlog),
+            bytes,
+        })
+    }
+}
+
+/// The charge of a `WatchEvent`, credited back on drop
+#[derive(Debug)]
+struct MemoryCharge {
+    /// The charged memory
+    memory: Arc<WatchMemory>,
+    /// The backlog of the watcher the events are sent to
+    backlog: Arc<AtomicU64>,
+    /// Charged bytes
+    bytes: u64,
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        let _ignore = self.memory.used.fetch_sub(self.bytes, Ordering::Relaxed);
+        let _ignore = self.backlog.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
 /// Watcher
 #[derive(Debug)]
 struct Watcher {
@@ -61,8 +243,16 @@ struct Watcher {
     event_tx: mpsc::Sender<WatchEvent>,
     /// Compacted flag
     compacted: bool,
+    /// Memory charged by the events sent to this watcher
+    memory: Arc<WatchMemory>,
+    /// Bytes of the events sent to this watcher but not delivered yet
+    backlog: Arc<AtomicU64>,
+    /// The auditor of the deliveries, if the watcher is sampled
+    auditor: Option<Arc<WatchAuditor>>,
+    /// All the events in the range up to this revision have been delivered
+    synced_revision: i64,
     /// TODO: remove it when https://github.com/xline-kv/Xline/issues/491 has been closed
-    /// Store the revision that has been notified
+    /// Store the revision that has been notified after the synced revision
     notified_set: HashSet<i64>,
 }
 
@@ -90,6 +280,7 @@ impl Watcher {
         stop_notify: Arc<event_listener::Event>,
         event_tx: mpsc::Sender<WatchEvent>,
         compacted: bool,
+        memory: Arc<WatchMemory>,
     ) -> Self {
         Self {
             key_range,
@@ -99,6 +290,10 @@ impl Watcher {
             stop_notify,
             event_tx,
             compacted,
+            memory,
+            backlog: Arc::new(AtomicU64::new(0)),
+            auditor: None,
+            synced_revision: 0,
             notified_set: HashSet::new(),
         }
     }
@@ -113,12 +308,18 @@ impl Watcher {
         &self.key_range
     }
 
+    /// Get the bytes of the events sent to this watcher but not delivered yet
+    fn backlog(&self) -> u64 {
+        self.backlog.load(Ordering::Relaxed)
+    }
+
     /// filter out events
     fn filter_events(&self, mut events: Vec<Event>) -> Vec<Event> {
         events.retain(|event| {
             self.filters.iter().all(|filter| filter != &event.r#type)
                 && (event.kv.as_ref().map_or(false, |kv| {
                     kv.mod_revision >= self.start_rev
+                        && kv.mod_revision > self.synced_revision
                         && !self.notified_set.contains(&kv.mod_revision)
                 }))
         });
@@ -126,30 +327,80 @@ impl Watcher {
     }
 
     /// Notify all passed events, please filter out events before calling this method
+    ///
+    /// If the events don't fit in the memory budget, nothing is sent and an empty
+    /// event is returned as full, the watcher has to be synced from the history.
     fn notify(
         &mut self,
         (revision, events): (i64, Vec<Event>),
     ) -> Result<(), TrySendError<WatchEvent>> {
-        let watch_id = self.watch_id();
         let events = self.filter_events(events);
-        let events_len = events.len();
-        let watch_event = WatchEvent {
-            id: watch_id,
+        let Some(charge) = self.memory.try_charge(&self.backlog, &events) else {
+            debug!(
+                watch_id = self.watch_id(),
+                revision, "watch memory budget exhausted"
+            );
+            return Err(TrySendError::Full(WatchEvent::empty(self.watch_id())));
+        };
+        self.send(WatchEvent {
+            id: self.watch_id(),
             events,
             revision,
             compacted: self.compacted,
-        };
+            charge: Some(charge),
+            progress: false,
+        })?;
+        // the events are notified in the order of the revisions, either from the
+        // history or as they are applied, so none up to the revision is missed
+        self.synced(revision);
+        Ok(())
+    }
+
+    /// Mark all the events up to the revision as delivered, the revisions notified up
+    /// to it are not needed to filter out the delivered events any more
+    fn synced(&mut self, revision: i64) {
+        if revision > self.synced_revision {
+            self.synced_revision = revision;
+            self.notified_set.retain(|&notified| notified > revision);
+        }
+    }
+
+    /// The revision the history of the watcher is read from
+    fn history_start(&self) -> i64 {
+        self.start_rev.max(self.synced_revision.overflow_add(1))
+    }
+
+    /// Send a filtered watch event
+    fn send(&mut self, watch_event: WatchEvent) -> Result<(), TrySendError<WatchEvent>> {
+        let watch_id = self.watch_id();
+        let revision = watch_event.revision;
         if !self.compacted
             && (revision < self.start_rev
+                || revision <= self.synced_revision
                 || self.notified_set.contains(&revision)
-                || 0 == events_len)
+                || watch_event.events.is_empty())
         {
             return Ok(());
         };
 
+        let delivered = watch_event
+            .events
+            .iter()
+            .filter_map(|event| event.kv.as_ref().map(|kv| kv.mod_revision))
+            .collect_vec();
+        let audited = self
+            .auditor
+            .as_ref()
+            .map(|_| Mutation::from_events(&watch_event.events));
         match self.event_tx.try_send(watch_event) {
             Ok(()) => {
+                // a history event carries the last revision only, the earlier ones
+                // must not be sent again when the watcher is synced from the history
                 let _ignore = self.notified_set.insert(revision);
+                self.notified_set.extend(delivered);
+                if let (Some(auditor), Some(mutations)) = (self.auditor.as_ref(), audited) {
+                    auditor.delivered(watch_id, mutations);
+                }
                 Ok(())
             }
             Err(TrySendError::Closed(_)) => {
@@ -175,6 +426,10 @@ pub(crate) struct KvWatcher {
     kv_store_inner: Arc<KvStoreInner>,
     /// Watch indexes
     watcher_map: Arc<RwLock<WatcherMap>>,
+    /// Memory used by the buffered watch events
+    memory: Arc<WatchMemory>,
+    /// Auditor of the watch deliveries
+    auditor: Arc<WatchAuditor>,
 }
 
 /// Store all watchers
@@ -184,8 +439,12 @@ struct WatcherMap {
     index: HashMap<KeyRange, HashSet<WatchId>>,
     /// All watchers
     watchers: HashMap<WatchId, Watcher>,
-    /// Victims
-    victims: HashMap<Watcher, (i64, Vec<Event>)>,
+    /// Victims and the event pending to be sent to them
+    victims: HashMap<Watcher, WatchEvent>,
+    /// Internal subscriptions
+    subscribers: Vec<InternalSubscriber>,
+    /// The last revision dispatched to the registered watchers
+    dispatched_revision: i64,
 }
 
 impl WatcherMap {
@@ -195,6 +454,8 @@ impl WatcherMap {
             index: HashMap::new(),
             watchers: HashMap::new(),
             victims: HashMap::new(),
+            subscribers: Vec::new(),
+            dispatched_revision: 0,
         }
     }
 
@@ -213,7 +474,7 @@ impl WatcherMap {
     }
 
     /// Move a watcher to victims, the `watch_id` must be valid.
-    fn move_to_victim(&mut self, watch_id: WatchId, updates: (i64, Vec<Event>)) {
+    fn move_to_victim(&mut self, watch_id: WatchId, watch_event: WatchEvent) {
         debug!(watch_id, "move watcher to victim");
         let Some(watcher) = self.watchers.remove(&watch_id) else {
             unreachable!("watcher should exist")
@@ -231,20 +492,68 @@ impl WatcherMap {
                 "watch_ids should exist"
             );
         }
-        let watch_event = WatchEvent {
-            id: watch_id,
-            revision: updates.0,
-            events: updates.1,
-            compacted: false,
-        };
         assert!(
-            self.victims
-                .insert(watcher, (watch_event.revision, watch_event.events))
-                .is_none(),
+            self.victims.insert(watcher, watch_event).is_none(),
             "can't insert a watcher to victims twice"
         );
     }
 
+    /// Relieve the memory pressure once the buffered events exceed the budget.
+    ///
+    /// The history of watchers is served from the backend rather than an in
+    /// memory ring, so the pressure is applied in two steps: first the pending
+    /// events of victims are evicted, which are synced from the backend later,
+    /// then the most backlogged watchers are moved to victims, so that no more
+    /// events are buffered for them until their streams are drained.
+    fn relieve_pressure(&mut self, memory: &WatchMemory) {
+        if memory.excess() == 0 {
+            return;
+        }
+        let mut victims = self
+            .victims
+            .iter_mut()
+            .filter(|&(_, ref watch_event)| !watch_event.events.is_empty())
+            .collect_vec();
+        victims.sort_unstable_by_key(|&(ref watcher, _)| std::cmp::Reverse(watcher.backlog()));
+        for (watcher, watch_event) in victims {
+            if memory.excess() == 0 {
+                return;
+            }
+            let evicted = watch_event.take_events().len();
+            watch_event.charge = None;
+            debug!(
+                watch_id = watcher.watch_id(),
+                evicted, "evict pending events of victim"
+            );
+            metrics::get()
+                .watch_events_evicted_total
+                .add(evicted.numeric_cast(), &[]);
+        }
+        // events in the channels are credited only after delivered, and the victims
+        // will not be buffered more events until then
+        let victims_backlog = self
+            .victims
+            .keys()
+            .map(Watcher::backlog)
+            .fold(0, u64::overflow_add);
+        let mut excess = memory.excess().saturating_sub(victims_backlog);
+        let backlogs = self
+            .watchers
+            .values()
+            .map(|watcher| (watcher.watch_id(), watcher.backlog()))
+            .filter(|&(_, backlog)| backlog > 0)
+            .sorted_unstable_by_key(|&(_, backlog)| std::cmp::Reverse(backlog))
+            .collect_vec();
+        for (watch_id, backlog) in backlogs {
+            if excess == 0 {
+                return;
+            }
+            excess = excess.saturating_sub(backlog);
+            self.move_to_victim(watch_id, WatchEvent::empty(watch_id));
+            metrics::get().watch_watchers_victimized_total.add(1, &[]);
+        }
+    }
+
     /// Remove a watcher
     fn remove(&mut self, watch_id: WatchId) {
         if let Some(watcher) = self.watchers.remove(&watch_id) {
@@ -299,6 +608,16 @@ pub(crate) trait KvWatcherOps {
 
     /// Get compacted revision from backend store
     fn compacted_revision(&self) -> i64;
+
+    /// Revisions of the keys in a range at the synced revision, along with the revision
+    fn range_snapshot(&self, key_range: &KeyRange) -> (i64, Vec<Revision>);
+
+    /// Get the `KeyValue`s of a page of a range snapshot
+    fn get_kvs(&self, revisions: &[Revision]) -> Result<Vec<KeyValue>, ExecuteError>;
+
+    /// The revision up to which all the events of a watcher have been sent to its
+    /// channel, None if the watcher doesn't exist or nothing can be claimed yet
+    fn progress_revision(&self, id: WatchId) -> Option<i64>;
 }
 
 #[async_trait::async_trait]
@@ -321,16 +640,21 @@ impl KvWatcherOps for KvWatcher {
             stop_notify,
             event_tx,
             compacted,
+            Arc::clone(&self.memory),
         );
         let mut watcher_map_w = self.watcher_map.write();
+        if !compacted
+            && self
+                .auditor
+                .register(id, &key_range, start_rev, &watcher.filters)
+        {
+            watcher.auditor = Some(Arc::clone(&self.auditor));
+        }
         if compacted {
             debug!("The revision {watcher:?} required has been compacted");
             if let Err(TrySendError::Full(watch_event)) = watcher.notify((0, vec![])) {
                 assert!(
-                    watcher_map_w
-                        .victims
-                        .insert(watcher, (watch_event.revision, watch_event.events))
-                        .is_none(),
+                    watcher_map_w.victims.insert(watcher, watch_event).is_none(),
                     "can't insert a watcher to victims twice"
                 );
             };
@@ -353,10 +677,7 @@ impl KvWatcherOps for KvWatcher {
                 watcher.notify((last_revision, initial_events))
             {
                 assert!(
-                    watcher_map_w
-                        .victims
-                        .insert(watcher, (watch_event.revision, watch_event.events))
-                        .is_none(),
+                    watcher_map_w.victims.insert(watcher, watch_event).is_none(),
                     "can't insert a watcher to victims twice"
                 );
                 return;
@@ -368,6 +689,7 @@ impl KvWatcherOps for KvWatcher {
 
     fn cancel(&self, watch_id: WatchId) {
         self.watcher_map.write().remove(watch_id);
+        self.auditor.remove(watch_id);
     }
 
     fn get_prev_kv(&self, kv: &KeyValue) -> Option<KeyValue> {
@@ -377,20 +699,48 @@ impl KvWatcherOps for KvWatcher {
     fn compacted_revision(&self) -> i64 {
         self.kv_store_inner.compacted_revision()
     }
+
+    fn range_snapshot(&self, key_range: &KeyRange) -> (i64, Vec<Revision>) {
+        self.kv_store_inner.range_snapshot(key_range)
+    }
+
+    fn get_kvs(&self, revisions: &[Revision]) -> Result<Vec<KeyValue>, ExecuteError> {
+        self.kv_store_inner.get_kvs(revisions)
+    }
+
+    fn progress_revision(&self, id: WatchId) -> Option<i64> {
+        let watcher_map = self.watcher_map.read();
+        if let Some(watcher) = watcher_map.watchers.get(&id) {
+            // a registered watcher is sent every revision dispatched after it's
+            // registered, or it would have been moved to the victims
+            return Some(watcher_map.dispatched_revision.max(watcher.synced_revision));
+        }
+        // a victim falls behind since the event pending to be sent to it
+        watcher_map
+            .victims
+            .keys()
+            .find(|watcher| watcher.watch_id() == id && !watcher.compacted)
+            .map(|watcher| watcher.synced_revision)
+            .filter(|&revision| revision > 0)
+    }
 }
 
 impl KvWatcher {
     /// Create a new `Arc<KvWatcher>`
     pub(crate) fn new_arc(
         kv_store_inner: Arc<KvStoreInner>,
-        kv_update_rx: mpsc::Receiver<(i64, Vec<Event>)>,
+        kv_update_rx: mpsc::Receiver<KvUpdates>,
         sync_victims_interval: Duration,
+        memory_budget: u64,
+        audit_sample_percent: u8,
         task_manager: &TaskManager,
     ) -> Arc<Self> {
         let watcher_map = Arc::new(RwLock::new(WatcherMap::new()));
         let kv_watcher = Arc::new(Self {
             kv_store_inner,
             watcher_map,
+            memory: WatchMemory::new(memory_budget),
+            auditor: WatchAuditor::new(audit_sample_percent),
         });
         task_manager.spawn(TaskName::SyncVictims, |n| {
             Self::sync_victims_task(Arc::clone(&kv_watcher), sync_victims_interval, n)
@@ -405,7 +755,7 @@ impl KvWatcher {
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
     async fn kv_updates_task(
         kv_watcher: Arc<KvWatcher>,
-        mut kv_update_rx: mpsc::Receiver<(i64, Vec<Event>)>,
+        mut kv_update_rx: mpsc::Receiver<KvUpdates>,
         shutdown_listener: Listener,
     ) {
         loop {
@@ -441,20 +791,29 @@ impl KvWatcher {
                 .watcher_map
                 .map_write(|mut m| m.victims.drain().collect::<Vec<_>>());
             let mut new_victims = HashMap::new();
-            for (mut watcher, res) in victims {
+            for (mut watcher, watch_event) in victims {
                 // needn't to filter updates and get prev_kv, because the watcher is already filtered before inserted into victims
-                if let Err(TrySendError::Full(watch_event)) = watcher.notify(res) {
+                if let Err(TrySendError::Full(watch_event)) = watcher.send(watch_event) {
                     assert!(
-                        new_victims
-                            .insert(watcher, (watch_event.revision, watch_event.events))
-                            .is_none(),
+                        new_victims.insert(watcher, watch_event).is_none(),
                         "can't insert a watcher to new_victims twice"
                     );
                 } else {
+                    if !watcher.compacted && watcher.event_tx.capacity() == 0 {
+                        // the stream is not drained yet, don't buffer the history for it
+                        let watch_id = watcher.watch_id();
+                        assert!(
+                            new_victims
+                                .insert(watcher, WatchEvent::empty(watch_id))
+                                .is_none(),
+                            "can't insert a watcher to new_victims twice"
+                        );
+                        continue;
+                    }
                     let mut watcher_map_w = kv_watcher.watcher_map.write();
                     let initial_events = kv_watcher
                         .kv_store_inner
-                        .get_event_from_revision(watcher.key_range.clone(), watcher.start_rev)
+                        .get_event_from_revision(watcher.key_range.clone(), watcher.history_start())
                         .unwrap_or_else(|e| {
                             warn!("failed to get initial events for watcher: {:?}", e);
                             vec![]
@@ -465,12 +824,10 @@ impl KvWatcher {
                             watcher.notify((last_revision, initial_events))
                         {
                             assert!(
-                                new_victims
-                                    .insert(watcher, (watch_event.revision, watch_event.events))
-                                    .is_none(),
+                                new_victims.insert(watcher, watch_event).is_none(),
                                 "can't insert a watcher to new_victims twice"
                             );
-                            break;
+                            continue;
                         };
                     }
                     debug!(
@@ -483,38 +840,83 @@ impl KvWatcher {
                 }
             }
             if !new_victims.is_empty() {
-                kv_watcher.watcher_map.write().victims.extend(new_victims);
+                let mut watcher_map_w = kv_watcher.watcher_map.write();
+                watcher_map_w.victims.extend(new_victims);
+                watcher_map_w.relieve_pressure(&kv_watcher.memory);
             }
         }
     }
 
+    /// Subscribe to the changes of a key range applied after the call, for in-process
+    /// consumers
+    ///
+    /// The subscription is closed if its consumer falls behind by more than the
+    /// capacity of the channel, the consumer should re-read the state it depends on
+    /// and subscribe again.
+    pub(crate) fn subscribe_internal(
+        &self,
+        key_range: KeyRange,
+    ) -> mpsc::Receiver<Arc<InternalEvent>> {
+        let (event_tx, event_rx) = mpsc::channel(INTERNAL_CHANNEL_SIZE);
+        self.watcher_map
+            .write()
+            .subscribers
+            .push(InternalSubscriber {
+                key_range,
+                event_tx,
+            });
+        event_rx
+    }
+
+    /// Get the number of the watchers, including the victims
+    pub(crate) fn watcher_count(&self) -> usize {
+        let watcher_map = self.watcher_map.read();
+        watcher_map
+            .watchers
+            .len()
+            .saturating_add(watcher_map.victims.len())
+    }
+
     /// Handle KV store updates
-    fn handle_kv_updates(&self, (revision, all_events): (i64, Vec<Event>)) {
+    fn handle_kv_updates(&self, (revision, all_events): KvUpdates) {
         self.watcher_map.map_write(|mut watcher_map_w| {
+            self.auditor.record_applied(revision, &all_events);
+            watcher_map_w.subscribers.retain(|subscriber| {
+                for event in &all_events {
+                    if !subscriber.key_range.contains(&event.key) {
+                        continue;
+                    }
+                    if let Err(e) = subscriber.event_tx.try_send(Arc::clone(event)) {
+                        if matches!(e, TrySendError::Full(_)) {
+                            warn!("internal subscriber falls behind, closing the subscription");
+                        }
+                        return false;
+                    }
+                }
+                !subscriber.event_tx.is_closed()
+            });
             let mut watcher_events: HashMap<WatchId, Vec<Event>> = HashMap::new();
             for event in all_events {
                 let watch_ids = watcher_map_w
                     .index
                     .iter()
-                    .filter_map(|(k, v)| {
-                        k.contains_key(
-                            &event
-                                .kv
-                                .as_ref()
-                                .unwrap_or_else(|| panic!("Receive Event with empty kv"))
-                                .key,
-                        )
-                        .then_some(v)
-                    })
+                    .filter_map(|(k, v)| k.contains(&event.key).then_some(v))
                     .flatten()
                     .copied()
                     .collect_vec();
-                for watch_id in watch_ids {
+                // the watchers of the gRPC watch service get protobuf events, converted
+                // once and moved to the last watcher
+                let event = Event::from(event.as_ref());
+                let Some((&last, rest)) = watch_ids.split_last() else {
+                    continue;
+                };
+                for &watch_id in rest {
                     watcher_events
                         .entry(watch_id)
                         .or_default()
                         .push(event.clone());
                 }
+                watcher_events.entry(last).or_default().push(event);
             }
             for (watch_id, events) in watcher_events {
                 let watcher = watcher_map_w
@@ -522,10 +924,11 @@ impl KvWatcher {
                     .get_mut(&watch_id)
                     .unwrap_or_else(|| panic!("watcher index and watchers doesn't match"));
                 if let Err(TrySendError::Full(watch_event)) = watcher.notify((revision, events)) {
-                    watcher_map_w
-                        .move_to_victim(watch_id, (watch_event.revision, watch_event.events));
+                    watcher_map_w.move_to_victim(watch_id, watch_event);
                 }
             }
+            watcher_map_w.dispatched_revision = watcher_map_w.dispatched_revision.max(revision);
+            watcher_map_w.relieve_pressure(&self.memory);
         });
     }
 }
@@ -540,14 +943,18 @@ pub(crate) struct WatchEvent {
     revision: i64,
     /// Compacted WatchEvent
     compacted: bool,
+    /// Memory charged by the events, credited once this event is dropped
+    charge: Option<MemoryCharge>,
+    /// Progress marker, all the events up to the revision have been sent before it
+    progress: bool,
 }
 
 impl std::fmt::Debug for WatchEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "WatchEvent {{ id: {}, revision: {}, compacted: {}, ",
-            self.id, self.revision, self.compacted,
+            "WatchEvent {{ id: {}, revision: {}, compacted: {}, progress: {}, ",
+            self.id, self.revision, self.compacted, self.progress,
         )?;
         write_vec!(f, "events", self.events);
         write!(f, " }}")
@@ -555,6 +962,28 @@ impl std::fmt::Debug for WatchEvent {
 }
 
 impl WatchEvent {
+    /// A `WatchEvent` without events
+    fn empty(id: WatchId) -> Self {
+        Self {
+            id,
+            events: vec![],
+            revision: 0,
+            compacted: false,
+            charge: None,
+            progress: false,
+        }
+    }
+
+    /// A progress marker of a watcher, or of all the watchers of a connection with
+    /// `WatchId` -1, at a revision
+    pub(crate) fn progress(id: WatchId, revision: i64) -> Self {
+        Self {
+            revision,
+            progress: true,
+            ..Self::empty(id)
+        }
+    }
+
     /// Get revision
     pub(crate) fn revision(&self) -> i64 {
         self.revision
@@ -574,6 +1003,11 @@ impl WatchEvent {
     pub(crate) fn compacted(&self) -> bool {
         self.compacted
     }
+
+    /// Check whether the `WatchEvent` is a progress marker or not.
+    pub(crate) fn is_progress(&self) -> bool {
+        self.progress
+    }
 }
 
 /// Get the last revision of a event slice
@@ -609,6 +1043,13 @@ mod test {
     };
 
     fn init_empty_store(task_manager: &TaskManager) -> (Arc<KvStore>, Arc<DB>, Arc<KvWatcher>) {
+        init_empty_store_with_budget(task_manager, 0)
+    }
+
+    fn init_empty_store_with_budget(
+        task_manager: &TaskManager,
+        memory_budget: u64,
+    ) -> (Arc<KvStore>, Arc<DB>, Arc<KvWatcher>) {
         let (compact_tx, _compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
         let db = DB::open(&EngineConfig::Memory).unwrap();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
@@ -628,6 +1069,8 @@ mod test {
             kv_store_inner,
             kv_update_rx,
             sync_victims_interval,
+            memory_budget,
+            100,
             task_manager,
         );
         (store, db, kv_watcher)
@@ -643,7 +1086,7 @@ mod test {
         let stop_notify = Arc::new(event_listener::Event::new());
         kv_watcher.watch(
             123,
-            KeyRange::new_one_key("foo"),
+            KeyRange::single("foo"),
             10,
             vec![],
             stop_notify,
@@ -685,6 +1128,7 @@ mod test {
         for (k, count) in map {
             assert_eq!(count, 1, "key {k} should be notified once");
         }
+        assert_eq!(kv_watcher.auditor.violations(), 0);
         handle.abort();
         drop(store);
         task_manager.shutdown(true).await;
@@ -701,7 +1145,7 @@ mod test {
 
         kv_watcher.watch(
             123,
-            KeyRange::new_one_key("foo"),
+            KeyRange::single("foo"),
             0,
             vec![],
             stop_notify,
@@ -733,25 +1177,108 @@ mod test {
             .await;
         }
         handle.await.unwrap();
+        assert_eq!(kv_watcher.auditor.violations(), 0);
         drop(store);
         task_manager.shutdown(true).await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
-    async fn test_cancel_watcher() {
+    async fn progress_of_victim_should_not_run_ahead_of_pending_events() {
         let task_manager = Arc::new(TaskManager::new());
-        let (store, _db, kv_watcher) = init_empty_store(&task_manager);
-        let (event_tx, _event_rx) = mpsc::channel(1);
-        let stop_notify = Arc::new(event_listener::Event::new());
+        let (store, db, kv_watcher) = init_empty_store(&task_manager);
+        let (event_tx, mut event_rx) = mpsc::channel(1);
         kv_watcher.watch(
-            1,
-            KeyRange::new_one_key("foo"),
+            123,
+            KeyRange::single("foo"),
             0,
             vec![],
-            stop_notify,
+            Arc::new(event_listener::Event::new()),
             event_tx,
         );
+
+        put(store.as_ref(), db.as_ref(), "foo", vec![1], 2).await;
+        put(store.as_ref(), db.as_ref(), "foo", vec![2], 3).await;
+        put(store.as_ref(), db.as_ref(), "bar", vec![3], 4).await;
+        timeout(Duration::from_secs(3), async {
+            while kv_watcher.watcher_map.read().dispatched_revision < 4 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // revision 3 is pending in the victims while revision 4 has been dispatched
+        assert_eq!(kv_watcher.progress_revision(123), Some(2));
+        assert_eq!(kv_watcher.progress_revision(456), None);
+
+        assert_eq!(event_rx.recv().await.unwrap().revision(), 2);
+        let pending = timeout(Duration::from_secs(3), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.revision(), 3);
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn synced_watcher_should_not_get_delivered_revisions_again() {
+        let (event_tx, mut event_rx) = mpsc::channel(1);
+        let mut watcher = Watcher::new(
+            KeyRange::prefix("foo"),
+            1,
+            1,
+            vec![],
+            Arc::new(event_listener::Event::new()),
+            event_tx,
+            false,
+            WatchMemory::new(0),
+        );
+        let event = |key: &str, revision| Event {
+            r#type: EventType::Put.into(),
+            kv: Some(KeyValue {
+                key: key.into(),
+                mod_revision: revision,
+                ..Default::default()
+            }),
+            prev_kv: None,
+        };
+
+        watcher.notify((2, vec![event("foo", 2)])).unwrap();
+        let Err(TrySendError::Full(pending)) = watcher.notify((3, vec![event("foo", 3)])) else {
+            panic!("the stream should be full");
+        };
+        assert_eq!(watcher.synced_revision, 2);
+        assert_eq!(event_rx.recv().await.unwrap().revision, 2);
+        // the pending event of a victim is delivered apart from the history
+        watcher.send(pending).unwrap();
+        assert_eq!(watcher.history_start(), 3);
+        assert_eq!(event_rx.recv().await.unwrap().revision, 3);
+
+        // the history from the synced revision carries the delivered revision again
+        watcher
+            .notify((4, vec![event("foo", 3), event("foo1", 4)]))
+            .unwrap();
+        assert_eq!(
+            event_rx.recv().await.unwrap().events,
+            vec![event("foo1", 4)]
+        );
+        assert_eq!(watcher.synced_revision, 4);
+        assert!(watcher.notified_set.is_empty());
+        // neither is a revision of the history applied after it's read
+        watcher.notify((4, vec![event("foo1", 4)])).unwrap();
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_cancel_watcher() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, _db, kv_watcher) = init_empty_store(&task_manager);
+        let (event_tx, _event_rx) = mpsc::channel(1);
+        let stop_notify = Arc::new(event_listener::Event::new());
+        kv_watcher.watch(1, KeyRange::single("foo"), 0, vec![], stop_notify, event_tx);
         assert!(!kv_watcher.watcher_map.read().index.is_empty());
         assert!(!kv_watcher.watcher_map.read().watchers.is_empty());
         kv_watcher.cancel(1);
@@ -761,6 +1288,148 @@ mod test {
         task_manager.shutdown(true).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_slow_watchers_should_be_bounded_by_memory_budget() {
+        const WATCHERS: i64 = 100;
+        const CAPACITY: usize = 16;
+        const BUDGET: u64 = 4096;
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, db, kv_watcher) = init_empty_store_with_budget(&task_manager, BUDGET);
+        let mut event_rxs = vec![];
+        for id in 0..WATCHERS {
+            let (event_tx, event_rx) = mpsc::channel(CAPACITY);
+            let stop_notify = Arc::new(event_listener::Event::new());
+            kv_watcher.watch(
+                id,
+                KeyRange::single("foo"),
+                0,
+                vec![],
+                stop_notify,
+                event_tx,
+            );
+            event_rxs.push(event_rx);
+        }
+
+        // none of the watchers drains its stream
+        for i in 0..100_u8 {
+            put(
+                store.as_ref(),
+                db.as_ref(),
+                "foo",
+                vec![i],
+                i.overflow_add(1).numeric_cast(),
+            )
+            .await;
+        }
+        sleep(Duration::from_millis(100)).await;
+        let used = kv_watcher.memory.used();
+        assert!(used > 0);
+        for i in 100..200_u8 {
+            put(
+                store.as_ref(),
+                db.as_ref(),
+                "foo",
+                vec![i],
+                i.overflow_add(1).numeric_cast(),
+            )
+            .await;
+        }
+        sleep(Duration::from_millis(100)).await;
+        // every slow watcher has been moved to victims, so no more events are buffered
+        assert_eq!(kv_watcher.memory.used(), used);
+        assert!(kv_watcher.watcher_map.read().watchers.is_empty());
+        assert!(used <= BUDGET);
+
+        // the victims catch up from the backend once their streams are drained
+        let handles = event_rxs
+            .into_iter()
+            .map(|mut event_rx| {
+                tokio::spawn(async move {
+                    let mut expect = 0;
+                    'outer: while let Some(watch_events) =
+                        timeout(Duration::from_secs(3), event_rx.recv())
+                            .await
+                            .unwrap()
+                    {
+                        for event in watch_events.events {
+                            let val = event.kv.as_ref().unwrap().value[0];
+                            assert_eq!(val, expect);
+                            if val == 199 {
+                                break 'outer;
+                            }
+                            expect += 1;
+                        }
+                    }
+                })
+            })
+            .collect_vec();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(kv_watcher.memory.used(), 0);
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn internal_subscribers_should_share_events() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, db, kv_watcher) = init_empty_store(&task_manager);
+        let mut foo_rx1 = kv_watcher.subscribe_internal(KeyRange::single("foo"));
+        let mut foo_rx2 = kv_watcher.subscribe_internal(KeyRange::single("foo"));
+        let (event_tx, mut event_rx) = mpsc::channel(128);
+        let stop_notify = Arc::new(event_listener::Event::new());
+        kv_watcher.watch(1, KeyRange::single("foo"), 0, vec![], stop_notify, event_tx);
+
+        put(store.as_ref(), db.as_ref(), "bar", vec![0], 1).await;
+        put(store.as_ref(), db.as_ref(), "foo", vec![1], 2).await;
+        let event = recv(&mut foo_rx1).await.unwrap();
+        // the event is allocated once for all in-process consumers
+        assert!(Arc::ptr_eq(&event, &recv(&mut foo_rx2).await.unwrap()));
+        assert_eq!(event.event_type, EventType::Put);
+        assert_eq!(
+            (event.key.as_ref(), event.value.as_ref()),
+            (b"foo".as_ref(), [1].as_ref())
+        );
+        assert_eq!(
+            (event.revision, event.create_revision, event.version),
+            (2, 2, 1)
+        );
+        // the watchers of the gRPC watch service see the same change
+        let watch_event = timeout(Duration::from_secs(3), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(watch_event.events, vec![Event::from(event.as_ref())]);
+
+        // dropped and lagging subscriptions are closed
+        drop(foo_rx2);
+        for i in 0..=INTERNAL_CHANNEL_SIZE {
+            put(
+                store.as_ref(),
+                db.as_ref(),
+                "foo",
+                vec![0],
+                i.overflow_add(3).numeric_cast(),
+            )
+            .await;
+        }
+        let mut received = 0;
+        while recv(&mut foo_rx1).await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, INTERNAL_CHANNEL_SIZE);
+        assert!(kv_watcher.watcher_map.read().subscribers.is_empty());
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    async fn recv(rx: &mut mpsc::Receiver<Arc<InternalEvent>>) -> Option<Arc<InternalEvent>> {
+        timeout(Duration::from_secs(3), rx.recv()).await.unwrap()
+    }
+
     async fn put(
         store: &KvStore,
         db: &DB,
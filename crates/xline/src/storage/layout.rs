@@ -0,0 +1,306 @@
+//! Versioning of the layout of the backend data directory.
+//!
+//! Every startup records the layout version of the data and the version of the
+//! running binary in the meta table, next to the minimum version able to open the
+//! data. A binary older than that minimum refuses to open the data dir instead of
+//! misreading it.
+
+use std::fmt;
+
+use engine::WriteOperation;
+use utils::table_names::META_TABLE;
+use xlineapi::execute_error::ExecuteError;
+
+use super::db::DB;
+
+/// Key of the layout version in the meta table
+pub(crate) const LAYOUT_VERSION_KEY: &str = "layout_version";
+/// Key of the version of the binary that last opened the data dir
+pub(crate) const WRITER_VERSION_KEY: &str = "writer_version";
+/// Key of the minimum version of the binary able to open the data dir
+pub(crate) const MIN_COMPATIBLE_VERSION_KEY: &str = "min_compatible_version";
+/// Meta keys describing the data dir itself rather than the replicated state
+pub(crate) const LAYOUT_KEYS: [&str; 3] = [
+    LAYOUT_VERSION_KEY,
+    WRITER_VERSION_KEY,
+    MIN_COMPATIBLE_VERSION_KEY,
+];
+
+/// The layout of data dirs written before the layout was versioned
+const BASE_LAYOUT: u32 = 1;
+
+/// Layout migrations, ordered by the layout they migrate to
+///
+/// A migration is `incompatible` only if binaries before the version it's
+/// introduced in would misread the migrated data, that version then becomes the
+/// minimum compatible version of the data dir.
+pub(crate) const MIGRATIONS: [Migration; 0] = [];
+
+/// A change of the layout of the data dir
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Migration {
+    /// The layout after the migration
+    pub(crate) layout: u32,
+    /// The version the migration is introduced in
+    pub(crate) since: Version,
+    /// Whether older binaries can't read the migrated data
+    pub(crate) incompatible: bool,
+    /// Migrate the data to `layout`
+    pub(crate) migrate: fn(&DB) -> Result<(), ExecuteError>,
+}
+
+/// A `major.minor.patch` version of xline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Version {
+    /// Major version
+    major: u64,
+    /// Minor version
+    minor: u64,
+    /// Patch version
+    patch: u64,
+}
+
+impl Version {
+    /// The version every binary is compatible with
+    const ZERO: Self = Self::new(0, 0, 0);
+
+    /// Create a new `Version`
+    pub(crate) const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// The version of the running binary
+    pub(crate) fn running() -> Self {
+        Self::parse(env!("CARGO_PKG_VERSION"))
+            .unwrap_or_else(|| unreachable!("the package version should be valid"))
+    }
+
+    /// Parse a version, any pre-release or build suffix is ignored
+    fn parse(s: &str) -> Option<Self> {
+        let core = s.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(str::parse);
+        let (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The layout fields stored in the meta table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StoredLayout {
+    /// The layout version of the data
+    pub(crate) layout: u32,
+    /// The version of the binary that last opened the data dir
+    pub(crate) writer: Version,
+    /// The minimum version of the binary able to open the data dir
+    pub(crate) min_compatible: Version,
+}
+
+impl StoredLayout {
+    /// Read the layout fields from the meta table, `None` if the data dir is new or
+    /// was written before the layout was versioned
+    pub(crate) fn read(db: &DB) -> Result<Option<Self>, ExecuteError> {
+        let values = db.get_values(META_TABLE, &LAYOUT_KEYS)?;
+        let [Some(layout), Some(writer), Some(min_compatible)] = values.as_slice() else {
+            return Ok(None);
+        };
+        let corrupted = |key: &str| ExecuteError::DbError(format!("cannot decode {key}"));
+        let layout = <[u8; 4]>::try_from(layout.as_slice())
+            .map(u32::from_le_bytes)
+            .map_err(|_e| corrupted(LAYOUT_VERSION_KEY))?;
+        let parse_version = |value: &[u8], key: &str| {
+            std::str::from_utf8(value)
+                .ok()
+                .and_then(Version::parse)
+                .ok_or_else(|| corrupted(key))
+        };
+        Ok(Some(Self {
+            layout,
+            writer: parse_version(writer, WRITER_VERSION_KEY)?,
+            min_compatible: parse_version(min_compatible, MIN_COMPATIBLE_VERSION_KEY)?,
+        }))
+    }
+
+    /// The write operations persisting the layout fields
+    pub(crate) fn to_ops(self) -> Vec<WriteOperation<'static>> {
+        vec![
+            WriteOperation::new_put(
+                META_TABLE,
+                LAYOUT_VERSION_KEY.as_bytes().to_vec(),
+                self.layout.to_le_bytes().to_vec(),
+            ),
+            WriteOperation::new_put(
+                META_TABLE,
+                WRITER_VERSION_KEY.as_bytes().to_vec(),
+                self.writer.to_string().into_bytes(),
+            ),
+            WriteOperation::new_put(
+                META_TABLE,
+                MIN_COMPATIBLE_VERSION_KEY.as_bytes().to_vec(),
+                self.min_compatible.to_string().into_bytes(),
+            ),
+        ]
+    }
+}
+
+/// What to do to open a data dir
+#[derive(Debug)]
+pub(crate) struct LayoutPlan<'a> {
+    /// Migrations to run, in order
+    pub(crate) migrations: Vec<&'a Migration>,
+    /// The layout fields to persist once the migrations are done
+    pub(crate) layout: StoredLayout,
+}
+
+/// Decide whether the `running` binary, knowing `migrations`, can open a data dir
+/// with the `stored` layout fields, and what to run and persist if it can
+///
+/// # Errors
+///
+/// Return `ExecuteError::DbError` if the data dir requires a newer binary
+pub(crate) fn plan(
+    stored: Option<StoredLayout>,
+    running: Version,
+    migrations: &[Migration],
+) -> Result<LayoutPlan<'_>, ExecuteError> {
+    let stored = stored.unwrap_or(StoredLayout {
+        layout: BASE_LAYOUT,
+        writer: Version::ZERO,
+        min_compatible: Version::ZERO,
+    });
+    if stored.min_compatible > running {
+        return Err(ExecuteError::DbError(format!(
+            "the data dir was written by xline {} and requires at least xline {}, \
+            refusing to open it with xline {running}; to downgrade, restore a snapshot \
+            taken before the upgrade with `xlineutl snapshot restore`",
+            stored.writer, stored.min_compatible
+        )));
+    }
+    let current = migrations.last().map_or(BASE_LAYOUT, |m| m.layout);
+    let pending: Vec<_> = migrations
+        .iter()
+        .filter(|m| m.layout > stored.layout)
+        .collect();
+    let own_min = migrations
+        .iter()
+        .filter(|m| m.incompatible)
+        .map(|m| m.since)
+        .max()
+        .unwrap_or(Version::ZERO);
+    // a data dir of a newer but compatible layout is opened as is, and it keeps the
+    // minimum compatible version set by the newer binary
+    Ok(LayoutPlan {
+        migrations: pending,
+        layout: StoredLayout {
+            layout: stored.layout.max(current),
+            writer: running,
+            min_compatible: stored.min_compatible.max(own_min),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    fn stored(layout: u32, writer: &str, min_compatible: &str) -> StoredLayout {
+        StoredLayout {
+            layout,
+            writer: v(writer),
+            min_compatible: v(min_compatible),
+        }
+    }
+
+    fn migration(layout: u32, since: &str, incompatible: bool) -> Migration {
+        Migration {
+            layout,
+            since: v(since),
+            incompatible,
+            migrate: |_db| Ok(()),
+        }
+    }
+
+    #[test]
+    fn version_should_be_parsed_and_ordered() {
+        assert_eq!(v("0.6.1"), Version::new(0, 6, 1));
+        assert_eq!(v("1.2.3-rc.1+build"), Version::new(1, 2, 3));
+        assert!(Version::parse("1.2").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+        assert!(Version::parse("a.b.c").is_none());
+        assert!(v("0.10.0") > v("0.9.9"));
+        assert_eq!(Version::running().to_string(), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn open_or_refuse_should_follow_min_compatible_version() {
+        // (stored min compatible, running, should open)
+        for (min_compatible, running, open) in [
+            ("0.0.0", "0.6.1", true),
+            ("0.6.1", "0.6.1", true),
+            ("0.6.1", "0.7.0", true),
+            ("0.7.0", "0.6.1", false),
+            ("0.6.2", "0.6.1", false),
+            ("1.0.0", "0.99.99", false),
+        ] {
+            let res = plan(Some(stored(2, "0.8.0", min_compatible)), v(running), &[]);
+            assert_eq!(res.is_ok(), open, "min {min_compatible}, running {running}");
+            if let Err(ExecuteError::DbError(msg)) = res {
+                assert!(msg.contains(min_compatible) && msg.contains(running));
+                assert!(msg.contains("0.8.0") && msg.contains("snapshot restore"));
+            }
+        }
+    }
+
+    #[test]
+    fn unversioned_data_dir_should_be_opened_as_base_layout() {
+        let plan = plan(None, v("0.6.1"), &[]).unwrap();
+        assert!(plan.migrations.is_empty());
+        assert_eq!(plan.layout, stored(BASE_LAYOUT, "0.6.1", "0.0.0"));
+    }
+
+    #[test]
+    fn only_incompatible_migrations_should_bump_min_compatible_version() {
+        let compatible = [migration(2, "0.7.0", false)];
+        let plan1 = plan(Some(stored(1, "0.6.1", "0.0.0")), v("0.7.0"), &compatible).unwrap();
+        assert_eq!(plan1.migrations.len(), 1);
+        assert_eq!(plan1.layout, stored(2, "0.7.0", "0.0.0"));
+
+        let incompatible = [migration(2, "0.7.0", false), migration(3, "0.8.0", true)];
+        let plan2 = plan(Some(stored(2, "0.7.0", "0.0.0")), v("0.8.0"), &incompatible).unwrap();
+        assert_eq!(
+            plan2
+                .migrations
+                .iter()
+                .map(|m| m.layout)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(plan2.layout, stored(3, "0.8.0", "0.8.0"));
+    }
+
+    #[test]
+    fn older_compatible_binary_should_keep_newer_layout() {
+        let migrations = [migration(2, "0.7.0", false)];
+        let plan = plan(Some(stored(3, "0.9.0", "0.7.0")), v("0.7.1"), &migrations).unwrap();
+        assert!(plan.migrations.is_empty());
+        assert_eq!(plan.layout, stored(3, "0.7.1", "0.7.0"));
+    }
+}
@@ -1,9 +1,62 @@
 use std::{
     collections::HashSet,
-    ops::Add,
+    ops::{Add, Range},
     time::{Duration, Instant},
 };
 
+/// Summary of a lease, cheap to copy out of the lease collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LeaseInfo {
+    /// Lease id
+    pub(crate) id: i64,
+    /// Lease ttl
+    pub(crate) ttl: Duration,
+    /// Remaining time of lease
+    pub(crate) remaining: Duration,
+    /// Number of keys attached to the lease
+    pub(crate) keys_count: usize,
+}
+
+/// Remaining time of a lease observed by the leader, persisted to the local lease expiry
+/// table so that the leader can restore it when it's re-elected after a restart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LeaseExpiry {
+    /// Term of the leader when the remaining time was observed
+    pub(crate) term: u64,
+    /// Unix time in milliseconds when the remaining time was observed
+    pub(crate) observed_at: u64,
+    /// Remaining time in milliseconds
+    pub(crate) remaining: u64,
+}
+
+impl LeaseExpiry {
+    /// Encode to the value stored in the lease expiry table
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        [self.term, self.observed_at, self.remaining]
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect()
+    }
+
+    /// Decode from the value stored in the lease expiry table
+    pub(crate) fn decode(value: &[u8]) -> Option<Self> {
+        if value.len() != 24 {
+            return None;
+        }
+        let field = |range: Range<usize>| {
+            value
+                .get(range)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+        };
+        Some(Self {
+            term: field(0..8)?,
+            observed_at: field(8..16)?,
+            remaining: field(16..24)?,
+        })
+    }
+}
+
 /// Lease
 #[derive(Debug, Clone)]
 pub(crate) struct Lease {
@@ -13,6 +66,9 @@ pub(crate) struct Lease {
     ttl: Duration,
     /// Remaining time of lease
     remaining_ttl: Duration,
+    /// When the `remaining_ttl` was checkpointed by the leader, `None` if no
+    /// checkpoint has been applied since it was set locally
+    checkpointed_at: Option<Instant>,
     /// Keys attached to this lease
     keys_set: HashSet<Vec<u8>>,
     /// Expiration time
@@ -26,6 +82,7 @@ impl Lease {
             id,
             ttl: Duration::from_secs(ttl),
             remaining_ttl: Duration::from_secs(0),
+            checkpointed_at: None,
             keys_set: HashSet::new(),
             expiry: None,
         }
@@ -55,6 +112,11 @@ impl Lease {
         }
     }
 
+    /// Expiration time, `None` if the lease never expires
+    pub(crate) fn expiry(&self) -> Option<Instant> {
+        self.expiry
+    }
+
     /// Check if the lease is expired
     pub(crate) fn expired(&self) -> bool {
         self.remaining() <= Duration::from_secs(0)
@@ -69,6 +131,54 @@ impl Lease {
         }
     }
 
+    /// Set the checkpointed remaining ttl, `Duration::ZERO` falls back to the full ttl
+    pub(crate) fn set_remaining_ttl(&mut self, remaining_ttl: Duration) {
+        self.remaining_ttl = remaining_ttl;
+        self.checkpointed_at = None;
+    }
+
+    /// Apply a checkpoint of the remaining ttl received from the leader
+    pub(crate) fn checkpoint(&mut self, remaining_ttl: Duration) {
+        self.remaining_ttl = remaining_ttl;
+        self.checkpointed_at = Some(Instant::now());
+    }
+
+    /// Restore the remaining time observed by the local node `age` ago, unless a newer
+    /// checkpoint has been applied. The restored remaining ttl never exceeds the ttl.
+    pub(crate) fn restore_observed(&mut self, remaining: Duration, age: Duration) {
+        if self.checkpointed_at.is_some_and(|at| at.elapsed() < age) {
+            return;
+        }
+        // Zero falls back to the full ttl, keep an exhausted lease expired instead
+        let remaining_ttl = remaining
+            .saturating_sub(age)
+            .min(self.ttl)
+            .max(Duration::from_millis(1));
+        self.checkpoint(remaining_ttl);
+    }
+
+    /// Checkpoint the remaining time observed by the leader before it gives up the expiry.
+    /// It never exceeds the remaining ttl, so the extension granted on promotion doesn't
+    /// pile up across leader changes.
+    pub(crate) fn checkpoint_observed(&mut self) {
+        if self.is_forever() {
+            return;
+        }
+        // Zero falls back to the full ttl, keep an exhausted lease expired instead
+        let remaining_ttl = self
+            .remaining()
+            .min(self.remaining_ttl())
+            .max(Duration::from_millis(1));
+        self.checkpoint(remaining_ttl);
+    }
+
+    /// Remaining ttl derived from the latest checkpoint and the time elapsed since then,
+    /// `None` if no checkpoint has been applied
+    pub(crate) fn checkpointed_remaining(&self) -> Option<Duration> {
+        self.checkpointed_at
+            .map(|at| self.remaining_ttl.saturating_sub(at.elapsed()))
+    }
+
     /// Refresh expiry and return new expiry
     pub(crate) fn refresh(&mut self, extend: Duration) -> Instant {
         let new_expiry = Instant::now().add(extend).add(self.remaining_ttl());
@@ -76,11 +186,36 @@ impl Lease {
         new_expiry
     }
 
+    /// Check if the lease never expires, which is the case on followers
+    pub(crate) fn is_forever(&self) -> bool {
+        self.expiry.is_none()
+    }
+
     /// Set expiry to `None`
     pub(crate) fn forever(&mut self) {
         self.expiry = None;
     }
 
+    /// Summary of this lease
+    pub(crate) fn info(&self) -> LeaseInfo {
+        LeaseInfo {
+            id: self.id,
+            ttl: self.ttl,
+            remaining: self.remaining(),
+            keys_count: self.keys_set.len(),
+        }
+    }
+
+    /// Whether the key is attached to this lease
+    pub(crate) fn has_key(&self, key: &[u8]) -> bool {
+        self.keys_set.contains(key)
+    }
+
+    /// Number of keys attached to this lease
+    pub(crate) fn keys_count(&self) -> usize {
+        self.keys_set.len()
+    }
+
     /// Insert a key to lease
     pub(crate) fn insert_key(&mut self, key: Vec<u8>) {
         let _ignore = self.keys_set.insert(key);
@@ -1,183 +1,587 @@
 use std::{
-    collections::HashMap,
+    collections::HashSet,
+    ops::{Add, Bound},
     time::{Duration, Instant},
 };
 
-use clippy_utilities::NumericCast;
-use itertools::Itertools;
-use parking_lot::RwLock;
-use utils::parking_lot_lock::RwLockMap;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use crossbeam_skiplist::SkipMap;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use tracing::info;
 use xlineapi::execute_error::ExecuteError;
 
-use super::{lease_queue::LeaseQueue, Lease};
+use super::{lease::LeaseInfo, lease_queue::LeaseQueue, Lease};
 use crate::rpc::PbLease;
 
 /// Collection of lease related data
+///
+/// Leases are locked one by one and keys are sharded by their hash, so renewals and
+/// attachments of different leases don't contend with each other. The lock of a lease
+/// is always taken before the lock of the expired queue or a key shard.
 #[derive(Debug)]
 pub(crate) struct LeaseCollection {
-    /// Inner data of `LeaseCollection`
-    inner: RwLock<LeaseCollectionInner>,
+    /// lease id to lease, ordered by id so that leases can be listed page by page
+    lease_map: SkipMap<i64, Mutex<LeaseEntry>>,
+    /// key to lease id
+    item_map: DashMap<Vec<u8>, i64>,
+    /// lease queue, an expiry in it may be earlier than the expiry of the lease, which
+    /// is adjusted when it's due
+    expired_queue: Mutex<LeaseQueue>,
     /// Min lease ttl
     min_ttl: i64,
+    /// Ttl of leases granted without a positive ttl, zero means the min lease ttl
+    default_ttl: i64,
+    /// Extension of leases when the current node becomes the leader
+    promote_extend: Duration,
+    /// Max number of keys attached to a lease, zero means unlimited
+    max_keys: usize,
+    /// Max number of active leases of an owner, zero means unlimited
+    max_leases_per_owner: usize,
+    /// Owner to the number of its active leases
+    owner_leases: DashMap<String, usize>,
+    /// Notified when the earliest expiry may move earlier or the primary state changes
+    expiry_changed: event_listener::Event,
 }
 
+/// A lease and its state in the collection
 #[derive(Debug)]
-/// Inner data of `LeaseCollection`
-struct LeaseCollectionInner {
-    /// lease id to lease
-    lease_map: HashMap<i64, Lease>,
-    /// key to lease id
-    item_map: HashMap<Vec<u8>, i64>,
-    /// lease queue
-    expired_queue: LeaseQueue,
+struct LeaseEntry {
+    /// The lease
+    lease: Lease,
+    /// The client granted the lease, empty if it's unknown
+    owner: String,
+    /// Expiry of the lease in the expired queue, `None` if it's not in the queue
+    queued: Option<Instant>,
+    /// Whether the lease is being revoked, keys can't be attached to it any more
+    revoking: bool,
+    /// Whether the lease has been removed from the collection
+    removed: bool,
+}
+
+impl LeaseEntry {
+    /// New `LeaseEntry`
+    fn new(lease: Lease, owner: String) -> Self {
+        Self {
+            lease,
+            owner,
+            queued: None,
+            revoking: false,
+            removed: false,
+        }
+    }
+
+    /// Put the lease into the expired queue with the given expiry
+    fn enqueue(&mut self, queue: &Mutex<LeaseQueue>, expiry: Instant) {
+        let _ignore = queue.lock().insert(self.lease.id(), expiry);
+        self.queued = Some(expiry);
+    }
+
+    /// The lease as a `PbLease`
+    fn pb_lease(&self) -> PbLease {
+        PbLease {
+            id: self.lease.id(),
+            ttl: self.lease.ttl().as_secs().numeric_cast(),
+            remaining_ttl: self.lease.remaining_ttl().as_secs().numeric_cast(),
+            owner: self.owner.clone(),
+        }
+    }
 }
 
 impl LeaseCollection {
     /// New `LeaseCollection`
     pub(crate) fn new(min_ttl: i64) -> Self {
         Self {
-            inner: RwLock::new(LeaseCollectionInner {
-                lease_map: HashMap::new(),
-                item_map: HashMap::new(),
-                expired_queue: LeaseQueue::new(),
-            }),
+            lease_map: SkipMap::new(),
+            item_map: DashMap::new(),
+            expired_queue: Mutex::new(LeaseQueue::new()),
             min_ttl,
+            default_ttl: 0,
+            promote_extend: Duration::ZERO,
+            max_keys: 0,
+            max_leases_per_owner: 0,
+            owner_leases: DashMap::new(),
+            expiry_changed: event_listener::Event::new(),
         }
     }
 
-    /// Find expired leases
-    pub(crate) fn find_expired_leases(&self) -> Vec<i64> {
+    /// New `LeaseCollection` whose min ttl and promote extension are derived from the
+    /// election timeout, so that a lease won't expire during a leader election
+    pub(crate) fn with_election_timeout(election_timeout: Duration) -> Self {
+        let min_ttl = election_timeout.saturating_mul(3) / 2;
+        // Safe ceiling
+        let min_ttl_secs = min_ttl
+            .as_secs()
+            .saturating_add(u64::from(min_ttl.subsec_nanos() > 0))
+            .max(1);
+        let mut collection = Self::new(min_ttl_secs.numeric_cast());
+        collection.promote_extend = election_timeout;
+        collection
+    }
+
+    /// Scale the extension of leases on promotion, which is one election timeout
+    pub(crate) fn with_promote_extend_multiplier(self, multiplier: u32) -> Self {
+        Self {
+            promote_extend: self.promote_extend.saturating_mul(multiplier),
+            ..self
+        }
+    }
+
+    /// Set the ttl of leases granted without a positive ttl
+    pub(crate) fn with_default_ttl(self, default_ttl: Duration) -> Self {
+        Self {
+            default_ttl: default_ttl.as_secs().numeric_cast(),
+            ..self
+        }
+    }
+
+    /// Limit the number of keys attached to a lease, zero means unlimited
+    pub(crate) fn with_max_keys(self, max_keys: usize) -> Self {
+        Self { max_keys, ..self }
+    }
+
+    /// Limit the number of active leases of an owner, zero means unlimited
+    pub(crate) fn with_max_leases_per_owner(self, max_leases_per_owner: usize) -> Self {
+        Self {
+            max_leases_per_owner,
+            ..self
+        }
+    }
+
+    /// Min lease ttl, a granted lease lives at least for this ttl
+    #[cfg(test)]
+    pub(crate) fn min_ttl(&self) -> i64 {
+        self.min_ttl
+    }
+
+    /// Normalize the ttl of a grant, a non-positive ttl becomes the default ttl, and
+    /// the ttl is raised to the min ttl
+    pub(crate) fn normalize_ttl(&self, ttl: i64) -> i64 {
+        let ttl = if ttl > 0 { ttl } else { self.default_ttl };
+        ttl.max(self.min_ttl)
+    }
+
+    /// Earliest expiry of all leases, only available on the leader. It may be earlier
+    /// than the real one, in which case the expiry is adjusted by `find_expired_leases`.
+    pub(crate) fn next_expiry(&self) -> Option<Instant> {
+        self.expired_queue.lock().peek().copied()
+    }
+
+    /// Listen to the changes of the earliest expiry
+    pub(crate) fn expiry_listener(&self) -> event_listener::EventListener {
+        self.expiry_changed.listen()
+    }
+
+    /// Put a lease back into the expired queue, so that a failed revocation will be retried
+    pub(crate) fn requeue(&self, lease_id: i64, retry_after: Duration) {
+        if let Some(entry) = self.lease_map.get(&lease_id) {
+            let mut entry = entry.value().lock();
+            if !entry.removed && !entry.revoking {
+                entry.enqueue(&self.expired_queue, Instant::now().add(retry_after));
+            }
+        }
+    }
+
+    /// Find at most `limit` expired leases, earliest expiry first, zero means unlimited.
+    /// The expired leases beyond the limit stay queued for the next call. Leases renewed
+    /// since they were queued are queued again with their new expiry, even if it's due,
+    /// so that they are returned in the order of their real expiries.
+    pub(crate) fn find_expired_leases(&self, limit: usize) -> Vec<i64> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let now = Instant::now();
         let mut expired_leases = vec![];
-        let mut inner = self.inner.write();
-        while let Some(expiry) = inner.expired_queue.peek() {
-            if *expiry <= Instant::now() {
-                #[allow(clippy::unwrap_used)] // queue.peek() returns Some
-                let id = inner.expired_queue.pop().unwrap();
-                if inner.lease_map.contains_key(&id) {
-                    expired_leases.push(id);
+        while expired_leases.len() < limit {
+            let mut due = vec![];
+            {
+                let mut queue = self.expired_queue.lock();
+                while due.len() < limit.saturating_sub(expired_leases.len())
+                    && queue.peek().is_some_and(|expiry| *expiry <= now)
+                {
+                    #[allow(clippy::unwrap_used)] // queue.peek() returns Some
+                    due.push(queue.pop().unwrap());
                 }
-            } else {
+            }
+            if due.is_empty() {
                 break;
             }
+            for id in due {
+                // a lease may be revoked between the pop and the lookup, its id is
+                // filtered out in all builds
+                let Some(entry) = self.lease_map.get(&id) else {
+                    continue;
+                };
+                let mut entry = entry.value().lock();
+                if entry.removed || entry.revoking {
+                    entry.queued = None;
+                    continue;
+                }
+                let queued = entry.queued;
+                match entry.lease.expiry() {
+                    Some(expiry) if expiry > now || queued.is_some_and(|q| expiry > q) => {
+                        entry.enqueue(&self.expired_queue, expiry);
+                    }
+                    Some(_) => {
+                        entry.queued = None;
+                        expired_leases.push(id);
+                    }
+                    None => entry.queued = None,
+                }
+            }
         }
         expired_leases
     }
 
     /// Renew lease
+    ///
+    /// The expired queue is only updated when the lease expires earlier than it's queued,
+    /// otherwise the queued expiry is adjusted lazily when it's due.
     pub(crate) fn renew(&self, lease_id: i64) -> Result<i64, ExecuteError> {
-        let mut inner = self.inner.write();
-        let (expiry, ttl) = {
-            let Some(lease) = inner.lease_map.get_mut(&lease_id) else {
-                return Err(ExecuteError::LeaseNotFound(lease_id));
-            };
-            if lease.expired() {
-                return Err(ExecuteError::LeaseExpired(lease_id));
-            }
-            let expiry = lease.refresh(Duration::default());
-            let ttl = lease.ttl().as_secs().numeric_cast();
-            (expiry, ttl)
+        let Some(entry) = self.lease_map.get(&lease_id) else {
+            return Err(ExecuteError::LeaseNotFound(lease_id));
         };
-        let _ignore = inner.expired_queue.update(lease_id, expiry);
-        Ok(ttl)
+        let mut entry = entry.value().lock();
+        if entry.removed || entry.revoking {
+            return Err(ExecuteError::LeaseNotFound(lease_id));
+        }
+        if entry.lease.expired() {
+            return Err(ExecuteError::LeaseExpired(lease_id));
+        }
+        // a renewed lease starts over from the full ttl
+        entry.lease.set_remaining_ttl(Duration::ZERO);
+        let expiry = entry.lease.refresh(Duration::default());
+        if entry.queued.is_some_and(|queued| expiry < queued) {
+            entry.enqueue(&self.expired_queue, expiry);
+        }
+        Ok(entry.lease.ttl().as_secs().numeric_cast())
     }
 
-    /// Attach key to lease
+    /// Attach key to lease, a lease being revoked is treated as not found
+    ///
+    /// The max number of keys isn't enforced here but by `check_attach`, so keys
+    /// recovered from the storage stay attached even if the limit has been lowered.
     pub(crate) fn attach(&self, lease_id: i64, key: Vec<u8>) -> Result<(), ExecuteError> {
-        let mut inner = self.inner.write();
-        let Some(lease) = inner.lease_map.get_mut(&lease_id) else {
+        let Some(entry) = self.lease_map.get(&lease_id) else {
             return Err(ExecuteError::LeaseNotFound(lease_id));
         };
-        lease.insert_key(key.clone());
-        let _ignore = inner.item_map.insert(key, lease_id);
+        let mut entry = entry.value().lock();
+        if entry.revoking || entry.removed {
+            return Err(ExecuteError::LeaseNotFound(lease_id));
+        }
+        entry.lease.insert_key(key.clone());
+        let _ignore = self.item_map.insert(key, lease_id);
         Ok(())
     }
 
-    /// Detach key from lease
-    pub(crate) fn detach(&self, lease_id: i64, key: &[u8]) -> Result<(), ExecuteError> {
-        let mut inner = self.inner.write();
-        let Some(lease) = inner.lease_map.get_mut(&lease_id) else {
+    /// Check whether the keys can be attached to the lease together, attaching keys
+    /// that aren't attached yet beyond the max number of keys of the lease is rejected
+    pub(crate) fn check_attach<'k>(
+        &self,
+        lease_id: i64,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) -> Result<(), ExecuteError> {
+        let Some(entry) = self.lease_map.get(&lease_id) else {
             return Err(ExecuteError::LeaseNotFound(lease_id));
         };
-        lease.remove_key(key);
-        let _ignore = inner.item_map.remove(key);
+        let entry = entry.value().lock();
+        if entry.revoking || entry.removed {
+            return Err(ExecuteError::LeaseNotFound(lease_id));
+        }
+        if self.max_keys > 0 {
+            let new_keys = keys
+                .into_iter()
+                .filter(|key| !entry.lease.has_key(key))
+                .collect::<HashSet<_>>()
+                .len();
+            if new_keys > 0 && entry.lease.keys_count().overflow_add(new_keys) > self.max_keys {
+                return Err(ExecuteError::LeaseKeysExceeded(lease_id));
+            }
+        }
         Ok(())
     }
 
+    /// Detach key from lease, it's a no-op for a missing lease or a lease being revoked,
+    /// whose keys are detached by the revocation
+    pub(crate) fn detach(&self, lease_id: i64, key: &[u8]) {
+        let Some(entry) = self.lease_map.get(&lease_id) else {
+            return;
+        };
+        let mut entry = entry.value().lock();
+        if entry.revoking || entry.removed {
+            return;
+        }
+        entry.lease.remove_key(key);
+        let _ignore = self.item_map.remove(key);
+    }
+
+    /// Detach keys from the leases they are attached to
+    pub(crate) fn detach_keys(&self, keys: &[Vec<u8>]) {
+        for key in keys {
+            // the key shard is released before the lease is locked
+            let Some((_, lease_id)) = self.item_map.remove(key) else {
+                continue;
+            };
+            if let Some(entry) = self.lease_map.get(&lease_id) {
+                entry.value().lock().lease.remove_key(key);
+            }
+        }
+    }
+
     /// Get lease id by given key
     pub(crate) fn get_lease(&self, key: &[u8]) -> i64 {
-        self.inner.read().item_map.get(key).copied().unwrap_or(0)
+        self.item_map.get(key).map_or(0, |lease_id| *lease_id)
     }
 
-    /// Get Lease by lease id
+    /// Get Lease by lease id, a lease being revoked is treated as not found
     pub(crate) fn look_up(&self, lease_id: i64) -> Option<Lease> {
-        self.inner.read().lease_map.get(&lease_id).cloned()
+        let entry = self.lease_map.get(&lease_id)?;
+        let entry = entry.value().lock();
+        (!entry.revoking).then(|| entry.lease.clone())
     }
 
-    /// Get all leases
-    pub(crate) fn leases(&self) -> Vec<Lease> {
-        let mut leases = self
-            .inner
-            .read()
+    /// Get at most `limit` leases with ids greater than `start_after` in id order, and
+    /// whether there are more leases after them, leases being revoked are skipped
+    pub(crate) fn leases_page(
+        &self,
+        start_after: Option<i64>,
+        limit: usize,
+    ) -> (Vec<LeaseInfo>, bool) {
+        let lower = start_after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut iter = self
             .lease_map
-            .values()
-            .cloned()
-            .collect::<Vec<_>>();
-        leases.sort_by_key(Lease::remaining);
-        leases
+            .range((lower, Bound::Unbounded))
+            .filter_map(|entry| {
+                let entry = entry.value().lock();
+                (!entry.revoking).then(|| entry.lease.info())
+            });
+        let page = iter.by_ref().take(limit).collect();
+        let more = iter.next().is_some();
+        (page, more)
+    }
+
+    /// Get the number of leases
+    pub(crate) fn lease_count(&self) -> usize {
+        self.lease_map.len()
     }
 
     /// Check if a lease exists
     pub(crate) fn contains_lease(&self, lease_id: i64) -> bool {
-        self.inner.read().lease_map.contains_key(&lease_id)
+        self.lease_map.contains_key(&lease_id)
     }
 
     /// Grant a lease
     pub(crate) fn grant(&self, lease_id: i64, ttl: i64, is_leader: bool) -> PbLease {
-        let mut lease = Lease::new(lease_id, ttl.max(self.min_ttl).numeric_cast());
-        self.inner.map_write(|mut inner| {
+        self.grant_owned(lease_id, ttl, String::new(), is_leader)
+    }
+
+    /// Check whether the owner may be granted one more lease
+    pub(crate) fn check_owner_quota(&self, owner: &str) -> Result<(), ExecuteError> {
+        if self.max_leases_per_owner == 0 || owner.is_empty() {
+            return Ok(());
+        }
+        if self.owner_lease_count(owner) >= self.max_leases_per_owner {
+            return Err(ExecuteError::LeaseQuotaExceeded(owner.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// The number of active leases of the owner
+    pub(crate) fn owner_lease_count(&self, owner: &str) -> usize {
+        self.owner_leases.get(owner).map_or(0, |count| *count)
+    }
+
+    /// The number of active leases of each owner
+    pub(crate) fn owner_lease_counts(&self) -> Vec<(String, usize)> {
+        self.owner_leases
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Grant a lease on behalf of its owner, which is counted against the quota of it
+    pub(crate) fn grant_owned(
+        &self,
+        lease_id: i64,
+        ttl: i64,
+        owner: String,
+        is_leader: bool,
+    ) -> PbLease {
+        let lease = Lease::new(lease_id, self.normalize_ttl(ttl).numeric_cast());
+        if let Some(prev) = self.lease_map.get(&lease_id) {
+            self.release_owner(&prev.value().lock().owner);
+        }
+        if !owner.is_empty() {
+            let mut count = self.owner_leases.entry(owner.clone()).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+        let entry = self
+            .lease_map
+            .insert(lease_id, Mutex::new(LeaseEntry::new(lease, owner)));
+        let pb_lease = {
+            let mut entry = entry.value().lock();
             if is_leader {
-                let expiry = lease.refresh(Duration::ZERO);
-                let _ignore = inner.expired_queue.insert(lease_id, expiry);
+                let expiry = entry.lease.refresh(Duration::ZERO);
+                entry.enqueue(&self.expired_queue, expiry);
             } else {
-                lease.forever();
+                entry.lease.forever();
             }
-            let _ignore = inner.lease_map.insert(lease_id, lease.clone());
-        });
-        PbLease {
-            id: lease.id(),
-            ttl: lease.ttl().as_secs().numeric_cast(),
-            remaining_ttl: lease.remaining_ttl().as_secs().numeric_cast(),
+            entry.pb_lease()
+        };
+        if is_leader {
+            let _ignore = self.expiry_changed.notify(usize::MAX);
+        }
+        pb_lease
+    }
+
+    /// Remaining ttl of all leases in seconds, rounded up, only available on the leader
+    pub(crate) fn remaining_ttls(&self) -> Vec<(i64, i64)> {
+        self.remainings()
+            .into_iter()
+            .map(|(id, remaining)| {
+                let secs = remaining
+                    .as_secs()
+                    .saturating_add(u64::from(remaining.subsec_nanos() > 0));
+                (id, secs.numeric_cast())
+            })
+            .collect()
+    }
+
+    /// Remaining time of leases, only available on the leader
+    pub(crate) fn remainings(&self) -> Vec<(i64, Duration)> {
+        self.lease_map
+            .iter()
+            .filter_map(|entry| {
+                let entry = entry.value().lock();
+                (!entry.lease.is_forever() && !entry.revoking)
+                    .then(|| (entry.lease.id(), entry.lease.remaining()))
+            })
+            .collect()
+    }
+
+    /// Checkpoint the remaining ttl of a lease, returns the updated lease if it exists
+    pub(crate) fn checkpoint(&self, lease_id: i64, remaining_ttl: i64) -> Option<PbLease> {
+        let entry = self.lease_map.get(&lease_id)?;
+        let mut entry = entry.value().lock();
+        entry
+            .lease
+            .checkpoint(Duration::from_secs(remaining_ttl.max(0).numeric_cast()));
+        Some(entry.pb_lease())
+    }
+
+    /// Restore the persisted remaining ttl of a lease
+    pub(crate) fn restore_remaining_ttl(&self, lease_id: i64, remaining_ttl: i64) {
+        if let Some(entry) = self.lease_map.get(&lease_id) {
+            entry
+                .value()
+                .lock()
+                .lease
+                .set_remaining_ttl(Duration::from_secs(remaining_ttl.max(0).numeric_cast()));
         }
     }
 
+    /// Restore the remaining time of a lease observed by the local node `age` ago
+    pub(crate) fn restore_observed(&self, lease_id: i64, remaining: Duration, age: Duration) {
+        if let Some(entry) = self.lease_map.get(&lease_id) {
+            entry.value().lock().lease.restore_observed(remaining, age);
+        }
+    }
+
+    /// Mark a lease as being revoked and return its keys and whether the revocation was
+    /// started by an earlier apply, `None` if the lease doesn't exist. No key can be
+    /// attached to the lease from now on, so the returned keys are all the keys the
+    /// revocation has to delete.
+    pub(crate) fn start_revoke(&self, lease_id: i64) -> Option<(Vec<Vec<u8>>, bool)> {
+        let entry = self.lease_map.get(&lease_id)?;
+        let mut entry = entry.value().lock();
+        let continued = entry.revoking;
+        entry.revoking = true;
+        Some((entry.lease.keys(), continued))
+    }
+
+    /// Leave a lease being revoked in the collection, the keys left are deleted by the
+    /// following revocations proposed by the leader
+    pub(crate) fn defer_revoke(&self, lease_id: i64) {
+        if let Some(entry) = self.lease_map.get(&lease_id) {
+            entry.value().lock().queued = None;
+        }
+        let _ignore = self.expired_queue.lock().remove(lease_id);
+        let _ignore = self.expiry_changed.notify(usize::MAX);
+    }
+
+    /// Ids of the leases whose revocation has not been finished
+    pub(crate) fn revoking_leases(&self) -> Vec<i64> {
+        self.lease_map
+            .iter()
+            .filter(|entry| {
+                let entry = entry.value().lock();
+                entry.revoking && !entry.removed
+            })
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
     /// Revokes a lease
     pub(crate) fn revoke(&self, lease_id: i64) -> Option<Lease> {
-        self.inner.write().lease_map.remove(&lease_id)
+        let Some(entry) = self.lease_map.remove(&lease_id) else {
+            let _ignore = self.expired_queue.lock().remove(lease_id);
+            return None;
+        };
+        let mut entry = entry.value().lock();
+        entry.removed = true;
+        let _ignore = self.expired_queue.lock().remove(lease_id);
+        self.release_owner(&entry.owner);
+        Some(entry.lease.clone())
+    }
+
+    /// Release a lease of the owner from its quota
+    fn release_owner(&self, owner: &str) {
+        if owner.is_empty() {
+            return;
+        }
+        let _ignore = self.owner_leases.remove_if_mut(owner, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
     }
 
     /// Demote current node
     pub(crate) fn demote(&self) {
-        let mut inner = self.inner.write();
-        inner.lease_map.values_mut().for_each(Lease::forever);
-        inner.expired_queue.clear();
+        for entry in self.lease_map.iter() {
+            let mut entry = entry.value().lock();
+            entry.lease.checkpoint_observed();
+            entry.lease.forever();
+            entry.queued = None;
+        }
+        self.expired_queue.lock().clear();
+        let _ignore = self.expiry_changed.notify(usize::MAX);
     }
 
     /// Promote current node
-    pub(crate) fn promote(&self, extend: Duration) {
-        let mut inner = self.inner.write();
-        let pairs = inner
-            .lease_map
-            .values_mut()
-            .map(|l| (l.id(), l.refresh(extend)))
-            .collect_vec();
-        for (lease_id, expiry) in pairs {
-            let _ignore = inner.expired_queue.insert(lease_id, expiry);
+    pub(crate) fn promote(&self) {
+        let mut count = 0_usize;
+        for entry in self.lease_map.iter() {
+            let mut entry = entry.value().lock();
+            if entry.revoking {
+                continue;
+            }
+            let expiry = entry.lease.refresh(self.promote_extend);
+            entry.enqueue(&self.expired_queue, expiry);
+            count = count.saturating_add(1);
         }
+        info!(
+            "promoted with {count} leases extended by {:?}",
+            self.promote_extend
+        );
+        let _ignore = self.expiry_changed.notify(usize::MAX);
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use itertools::Itertools;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
     use super::*;
     #[test]
     fn test_grant_less_than_min_ttl() {
@@ -187,4 +591,300 @@ mod test {
         assert!(l.is_some());
         assert_eq!(l.unwrap().ttl(), Duration::from_secs(3));
     }
+
+    #[test]
+    fn test_min_ttl_from_election_timeout() {
+        let c = LeaseCollection::with_election_timeout(Duration::from_secs(10));
+        assert_eq!(c.min_ttl(), 15);
+        c.grant(1, 5, false);
+        assert_eq!(c.look_up(1).unwrap().ttl(), Duration::from_secs(15));
+
+        let c = LeaseCollection::with_election_timeout(Duration::from_millis(300));
+        assert_eq!(c.min_ttl(), 1);
+    }
+
+    #[test]
+    fn test_normalize_ttl() {
+        let c = LeaseCollection::with_election_timeout(Duration::from_secs(10));
+        assert_eq!(c.normalize_ttl(0), 15);
+        assert_eq!(c.normalize_ttl(-5), 15);
+        assert_eq!(c.normalize_ttl(14), 15);
+        assert_eq!(c.normalize_ttl(16), 16);
+
+        let c = c.with_default_ttl(Duration::from_secs(20));
+        assert_eq!(c.normalize_ttl(0), 20);
+        assert_eq!(c.normalize_ttl(-5), 20);
+        assert_eq!(c.normalize_ttl(14), 15);
+    }
+
+    #[test]
+    fn test_checkpointed_remaining_counts_down_from_checkpoint() {
+        let c = LeaseCollection::new(0);
+        c.grant(1, 10, false);
+        assert!(c.look_up(1).unwrap().checkpointed_remaining().is_none());
+
+        assert!(c.checkpoint(1, 5).is_some());
+        std::thread::sleep(Duration::from_millis(500));
+        let remaining = c.look_up(1).unwrap().checkpointed_remaining().unwrap();
+        assert!(remaining < Duration::from_secs(5));
+        assert!(remaining > Duration::from_secs(4));
+
+        // a restored ttl is not a fresh checkpoint
+        c.restore_remaining_ttl(1, 5);
+        assert!(c.look_up(1).unwrap().checkpointed_remaining().is_none());
+    }
+
+    #[test]
+    fn test_promote_uses_checkpointed_ttl() {
+        let c = LeaseCollection::new(0);
+        c.grant(1, 10, true);
+        std::thread::sleep(Duration::from_millis(1100));
+        let remaining = c.remaining_ttls();
+        assert_eq!(remaining, vec![(1, 9)]);
+        for (id, ttl) in remaining {
+            assert!(c.checkpoint(id, ttl).is_some());
+        }
+
+        c.demote();
+        assert!(c.remaining_ttls().is_empty());
+        c.promote();
+        let lease = c.look_up(1).unwrap();
+        assert!(lease.remaining() <= Duration::from_secs(9));
+
+        // repeated failovers should not extend the lease
+        c.demote();
+        c.promote();
+        assert!(c.look_up(1).unwrap().remaining() <= Duration::from_secs(9));
+
+        assert_eq!(c.renew(1).unwrap(), 10);
+        assert!(c.look_up(1).unwrap().remaining() > Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_rapid_leader_changes_should_not_stack_extensions() {
+        let extend = Duration::from_secs(1);
+        let c = LeaseCollection::with_election_timeout(extend);
+        c.grant(1, 10, true);
+
+        for _ in 0..2 {
+            c.demote();
+            c.promote();
+            let remaining = c.look_up(1).unwrap().remaining();
+            assert!(
+                remaining <= Duration::from_secs(10).add(extend),
+                "remaining grows to {remaining:?}"
+            );
+            assert!(remaining > Duration::from_secs(10));
+        }
+
+        let c = LeaseCollection::with_election_timeout(extend).with_promote_extend_multiplier(3);
+        c.grant(1, 10, true);
+        c.demote();
+        c.promote();
+        assert!(c.look_up(1).unwrap().remaining() > Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_expired_queue_only_holds_live_leases() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let c = LeaseCollection::new(0);
+        for _ in 0..5000 {
+            let lease_id = rng.gen_range(1..=64);
+            match rng.gen_range(0..4) {
+                0 => {
+                    let _ignore = c.grant(lease_id, rng.gen_range(0..3), true);
+                }
+                1 => {
+                    let _ignore = c.renew(lease_id);
+                }
+                2 => {
+                    let _ignore = c.revoke(lease_id);
+                }
+                _ => {
+                    for id in c.find_expired_leases(rng.gen_range(0..4)) {
+                        assert!(c.contains_lease(id));
+                        if rng.gen_bool(0.5) {
+                            let _ignore = c.revoke(id);
+                        } else {
+                            c.requeue(id, Duration::ZERO);
+                        }
+                    }
+                }
+            }
+            assert!(c.expired_queue.lock().len() <= c.lease_count());
+        }
+        for id in c.leases_page(None, usize::MAX).0.iter().map(|l| l.id) {
+            let _ignore = c.revoke(id);
+        }
+        assert_eq!(c.expired_queue.lock().len(), 0);
+        assert!(c.next_expiry().is_none());
+    }
+
+    #[test]
+    fn test_queued_ids_without_lease_are_not_found_expired() {
+        let c = LeaseCollection::new(0);
+        let _ignore = c.grant(1, 0, true);
+        // an id left in the queue by a lease removed in between
+        let _ignore = c.expired_queue.lock().insert(2, Instant::now());
+        assert_eq!(c.find_expired_leases(0), vec![1]);
+        assert_eq!(c.expired_queue.lock().len(), 0);
+    }
+
+    #[test]
+    fn test_leases_page() {
+        let c = LeaseCollection::new(0);
+        for id in (1..=100_000).rev() {
+            let _ignore = c.grant(id, 10, false);
+        }
+        for key in 0..1000_u32 {
+            c.attach(1, key.to_be_bytes().to_vec()).unwrap();
+        }
+
+        let (page, more) = c.leases_page(None, 100);
+        assert!(more);
+        assert_eq!(page.len(), 100);
+        assert_eq!(page[0].id, 1);
+        assert_eq!(page[0].keys_count, 1000);
+        assert!(page.iter().skip(1).all(|l| l.keys_count == 0));
+
+        let (mut start_after, mut count) = (None, 0);
+        loop {
+            let (page, more) = c.leases_page(start_after, 4096);
+            for lease in &page {
+                assert!(start_after.map_or(true, |id| lease.id > id));
+                start_after = Some(lease.id);
+            }
+            count += page.len();
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(count, 100_000);
+
+        let (page, more) = c.leases_page(Some(100_000), 100);
+        assert!(page.is_empty());
+        assert!(!more);
+    }
+
+    #[test]
+    fn test_parallel_renewals_of_many_leases() {
+        const LEASES: i64 = 100_000;
+        const THREADS: i64 = 8;
+        const TTL: i64 = 3600;
+        // the odd leases get a zero ttl, so that they have expired by the time they are
+        // looked for without waiting for a deadline
+        let c = Arc::new(LeaseCollection::new(0).with_default_ttl(Duration::ZERO));
+        for id in 1..=LEASES {
+            let _ignore = c.grant(id, if id % 2 == 0 { TTL } else { 0 }, true);
+        }
+
+        // renew the even leases and attach keys to the odd ones in parallel
+        let start = Instant::now();
+        let handles = (0..THREADS)
+            .map(|t| {
+                let c = Arc::clone(&c);
+                std::thread::spawn(move || {
+                    for id in (1..=LEASES).filter(|id| id % THREADS == t) {
+                        if id % 2 == 0 {
+                            assert_eq!(c.renew(id).unwrap(), TTL);
+                        } else {
+                            c.attach(id, id.to_be_bytes().to_vec()).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect_vec();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let expired = c.find_expired_leases(0).into_iter().sorted().collect_vec();
+        assert_eq!(expired, (1..=LEASES).filter(|id| id % 2 == 1).collect_vec());
+        for id in &expired {
+            assert_eq!(c.get_lease(&id.to_be_bytes()), *id);
+        }
+
+        // the renewed leases stay queued and expire after their renewal
+        let renewed_expiry = start + Duration::from_secs(TTL.numeric_cast());
+        assert_eq!(c.expired_queue.lock().len(), 50_000);
+        assert!((1..=LEASES).filter(|id| id % 2 == 0).all(|id| c
+            .look_up(id)
+            .unwrap()
+            .expiry()
+            .unwrap()
+            >= renewed_expiry));
+        assert!(c.find_expired_leases(0).is_empty());
+    }
+
+    #[test]
+    fn test_expired_leases_are_found_in_batches_in_expiry_order() {
+        const LEASES: i64 = 10_000;
+        const BATCH: usize = 100;
+        let c = Arc::new(LeaseCollection::new(0));
+        let mut rng = StdRng::seed_from_u64(0);
+        let granted_at = Instant::now();
+        // grant in a shuffled order, so that the ids don't follow the expiries
+        let mut ids = (1..=LEASES).collect_vec();
+        for i in (1..ids.len()).rev() {
+            ids.swap(i, rng.gen_range(0..=i));
+        }
+        for &id in &ids {
+            let _ignore = c.grant(id, 1, true);
+        }
+        // renewed leases are queued with their old expiry until it's due
+        std::thread::sleep(Duration::from_millis(300));
+        for id in (1..=LEASES).filter(|id| id % 2 == 0) {
+            assert_eq!(c.renew(id).unwrap(), 1);
+        }
+        let deadline = granted_at + Duration::from_millis(1500);
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+
+        // grants and renewals of other leases go on while the expired ones are revoked
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let workload = {
+            let c = Arc::clone(&c);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                let mut ops = 0_i64;
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    let id = LEASES + 1 + ops % 100;
+                    let _ignore = c.grant(id, 60, true);
+                    assert_eq!(c.renew(id).unwrap(), 60);
+                    ops += 1;
+                }
+                ops
+            })
+        };
+
+        let mut revoked = vec![];
+        let mut last_expiry = None;
+        loop {
+            let batch = c.find_expired_leases(BATCH);
+            assert!(batch.len() <= BATCH);
+            if batch.is_empty() {
+                break;
+            }
+            for id in batch {
+                let expiry = c.look_up(id).unwrap().expiry().unwrap();
+                assert!(last_expiry.map_or(true, |last| last <= expiry));
+                last_expiry = Some(expiry);
+                assert!(c.revoke(id).is_some());
+                revoked.push(id);
+            }
+            std::thread::yield_now();
+        }
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert!(workload.join().unwrap() > 0);
+
+        assert_eq!(revoked.len(), LEASES.numeric_cast::<usize>());
+        assert_eq!(
+            revoked.iter().sorted().copied().collect_vec(),
+            (1..=LEASES).collect_vec()
+        );
+        // the renewed leases expire after all the others
+        let (odd, even) = revoked.split_at(revoked.len() / 2);
+        assert!(odd.iter().all(|id| id % 2 == 1));
+        assert!(even.iter().all(|id| id % 2 == 0));
+        assert!(c.find_expired_leases(BATCH).is_empty());
+    }
 }
@@ -39,6 +39,17 @@ impl LeaseQueue {
         self.inner.pop().map(|(k, _)| k)
     }
 
+    /// Remove lease, returns its expiry if it was in the queue
+    pub(super) fn remove(&mut self, lease_id: i64) -> Option<Instant> {
+        self.inner.remove(&lease_id).map(|(_, v)| v.0)
+    }
+
+    /// Number of leases in the queue
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.inner.len()
+    }
+
     /// Clear the lease heap
     pub(super) fn clear(&mut self) {
         self.inner.clear();
@@ -68,5 +79,10 @@ mod test {
         assert_eq!(hp.update(1, expiry3), Some(expiry1));
         assert_eq!(hp.inner.len(), 2);
         assert_eq!(hp.peek(), Some(&expiry3));
+
+        assert_eq!(hp.remove(1), Some(expiry3));
+        assert_eq!(hp.remove(1), None);
+        assert_eq!(hp.inner.len(), 1);
+        assert_eq!(hp.peek(), Some(&expiry2));
     }
 }
@@ -13,37 +13,56 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use log::debug;
-use parking_lot::RwLock;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use curp::members::{ClusterInfo, Feature};
+use itertools::Itertools;
+use log::{debug, warn};
+use parking_lot::{Mutex, RwLock};
 use prost::Message;
 use tokio::sync::mpsc;
-use utils::table_names::LEASE_TABLE;
+use utils::{
+    config::default_lease_revoke_chunk_size,
+    table_names::{LEASE_EXPIRY_TABLE, LEASE_TABLE, META_TABLE},
+};
 use xlineapi::{
-    command::{CommandResponse, SyncResponse},
+    command::{CommandResponse, KeyRange, SyncResponse},
     execute_error::ExecuteError,
 };
 
-pub(crate) use self::{lease::Lease, lease_collection::LeaseCollection};
+pub(crate) use self::{
+    lease::{Lease, LeaseExpiry, LeaseInfo},
+    lease_collection::LeaseCollection,
+};
 use super::{
-    db::{WriteOp, DB},
+    apply_error::ApplyError,
+    apply_fence::ApplyFence,
+    db::{LeaseKeyDeletion, WriteOp, DB, REVOKING_LEASE_PREFIX},
     index::Index,
+    kv_store::DeferredSync,
+    kvwatcher::KvUpdates,
 };
 use crate::{
+    clock::Clock,
     header_gen::HeaderGenerator,
+    metrics::LeaseMetrics,
     rpc::{
-        Event, LeaseGrantRequest, LeaseGrantResponse, LeaseLeasesRequest, LeaseLeasesResponse,
-        LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus, PbLease, RequestWrapper,
-        ResponseHeader, ResponseWrapper,
+        LeaseCheckpoint, LeaseCheckpointRequest, LeaseCheckpointResponse, LeaseGrantRequest,
+        LeaseGrantResponse, LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeBatchRequest,
+        LeaseRevokeBatchResponse, LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus, PbLease,
+        RequestWrapper, ResponseHeader, ResponseWrapper,
     },
-    storage::KvStore,
+    storage::{ApplyError, KvStore},
 };
 
 /// Max lease ttl
 const MAX_LEASE_TTL: i64 = 9_000_000_000;
 
+/// Number of leases read from the lease collection at a time when listing all leases
+const LEASES_PAGE_SIZE: usize = 1024;
+
 /// Lease store
 #[derive(Debug)]
 pub(crate) struct LeaseStore {
@@ -56,13 +75,26 @@ pub(crate) struct LeaseStore {
     /// Header generator
     header_gen: Arc<HeaderGenerator>,
     /// KV update sender
-    kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
+    kv_update_tx: mpsc::Sender<KvUpdates>,
     /// Primary flag
     is_primary: AtomicBool,
     /// cache unsynced lease id
     unsynced_cache: Arc<RwLock<HashSet<i64>>>,
     /// notify sync event
     sync_event: event_listener::Event,
+    /// Whether checkpointed remaining ttl should be persisted
+    checkpoint_persist: bool,
+    /// Ids of leases in the local lease expiry table
+    persisted_expiries: Mutex<HashSet<i64>>,
+    /// Max number of keys deleted by a single apply of a revocation, 0 means unlimited
+    revoke_chunk_size: usize,
+    /// Cluster info gating the chunked and batched revocations, `None` means every
+    /// feature is enabled
+    cluster_info: Option<Arc<ClusterInfo>>,
+    /// The clock the observed lease expiries are stamped with
+    clock: Arc<Clock>,
+    /// Lease metrics
+    metrics: LeaseMetrics,
 }
 
 impl LeaseStore {
@@ -72,9 +104,11 @@ impl LeaseStore {
         header_gen: Arc<HeaderGenerator>,
         db: Arc<DB>,
         index: Arc<Index>,
-        kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
+        kv_update_tx: mpsc::Sender<KvUpdates>,
         is_leader: bool,
+        checkpoint_persist: bool,
     ) -> Self {
+        let metrics = LeaseMetrics::new(&lease_collection);
         Self {
             lease_collection,
             db,
@@ -84,9 +118,63 @@ impl LeaseStore {
             is_primary: AtomicBool::new(is_leader),
             unsynced_cache: Arc::new(RwLock::new(HashSet::new())),
             sync_event: event_listener::Event::new(),
+            checkpoint_persist,
+            persisted_expiries: Mutex::new(HashSet::new()),
+            revoke_chunk_size: default_lease_revoke_chunk_size(),
+            cluster_info: None,
+            clock: Arc::new(Clock::system()),
+            metrics,
+        }
+    }
+
+    /// Set the max number of keys deleted by a single apply of a revocation
+    pub(crate) fn with_revoke_chunk_size(mut self, revoke_chunk_size: usize) -> Self {
+        self.revoke_chunk_size = revoke_chunk_size;
+        self
+    }
+
+    /// Set the cluster info whose cluster server version gates the chunked revocation
+    pub(crate) fn with_cluster_info(mut self, cluster_info: Arc<ClusterInfo>) -> Self {
+        self.cluster_info = Some(cluster_info);
+        self
+    }
+
+    /// Set the clock of the server
+    pub(crate) fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock of the server
+    pub(crate) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// The max number of keys deleted by a single apply of a revocation proposed by this
+    /// member, members older than the chunked revocation would never finish a chunked one
+    ///
+    /// It's carried by the revocation, so that every member deletes the same keys
+    /// whatever chunk size it's configured with.
+    pub(crate) fn revoke_chunk_size(&self) -> usize {
+        let enabled = self
+            .cluster_info
+            .as_ref()
+            .map_or(true, |c| c.feature_enabled(Feature::ChunkedLeaseRevoke));
+        if enabled {
+            self.revoke_chunk_size
+        } else {
+            0
         }
     }
 
+    /// Whether the expired leases can be revoked by batched revocations, members older
+    /// than the batched revocation can't apply them
+    pub(crate) fn batched_revoke_enabled(&self) -> bool {
+        self.cluster_info
+            .as_ref()
+            .map_or(true, |c| c.feature_enabled(Feature::BatchedLeaseRevoke))
+    }
+
     /// execute a lease request
     pub(crate) fn execute(
         &self,
@@ -96,15 +184,29 @@ impl LeaseStore {
             .map(CommandResponse::new)
     }
 
+    /// Check a lease request the same as its execution before its revision is reserved
+    #[allow(clippy::wildcard_enum_match_arm)] // other requests never fail to apply
+    pub(crate) fn check_writes(&self, request: &RequestWrapper) -> Result<(), ExecuteError> {
+        match *request {
+            RequestWrapper::LeaseRevokeRequest(ref req)
+                if !self.lease_collection.contains_lease(req.id) =>
+            {
+                Err(ExecuteError::LeaseNotFound(req.id))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// sync a lease request
     pub(crate) async fn after_sync(
         &self,
         request: &RequestWrapper,
         revision: i64,
-    ) -> Result<(SyncResponse, Vec<WriteOp>), ExecuteError> {
+    ) -> Result<(SyncResponse, Vec<WriteOp>), ApplyError> {
         self.sync_request(request, revision)
             .await
             .map(|(rev, ops)| (SyncResponse::new(rev), ops))
+            .map_err(ApplyError::from)
     }
 
     /// Get lease by id
@@ -112,14 +214,96 @@ impl LeaseStore {
         self.lease_collection.look_up(lease_id)
     }
 
-    /// Get all leases
-    pub(crate) fn leases(&self) -> Vec<Lease> {
-        self.lease_collection.leases()
+    /// Normalize the ttl of a grant, it must be done before the grant is proposed so
+    /// that all replicas store the same ttl
+    pub(crate) fn normalize_ttl(&self, ttl: i64) -> i64 {
+        self.lease_collection.normalize_ttl(ttl)
+    }
+
+    /// Get at most `limit` leases with ids greater than `start_after` in id order, and
+    /// whether there are more leases after them
+    pub(crate) fn leases_page(
+        &self,
+        start_after: Option<i64>,
+        limit: usize,
+    ) -> (Vec<LeaseInfo>, bool) {
+        self.lease_collection.leases_page(start_after, limit)
+    }
+
+    /// Get the ids of all leases in ascending order, the leases are read page by page
+    /// so that the lease collection is not locked for the whole listing
+    ///
+    /// Each page starts after the last id of the previous one, so the ids are strictly
+    /// ascending even if leases are granted or revoked during the listing. A lease
+    /// granted or revoked meanwhile may or may not be listed.
+    pub(crate) fn lease_ids(&self) -> Vec<i64> {
+        let mut ids = vec![];
+        loop {
+            let (page, more) = self.leases_page(ids.last().copied(), LEASES_PAGE_SIZE);
+            ids.extend(page.iter().map(|lease| lease.id));
+            if !more {
+                return ids;
+            }
+        }
+    }
+
+    /// Find at most `limit` expired leases, earliest expiry first, zero means unlimited
+    pub(crate) fn find_expired_leases(&self, limit: usize) -> Vec<i64> {
+        self.lease_collection.find_expired_leases(limit)
+    }
+
+    /// Earliest expiry of all leases, only available on the leader
+    pub(crate) fn next_expiry(&self) -> Option<Instant> {
+        self.lease_collection.next_expiry()
+    }
+
+    /// Listen to the changes of the earliest expiry or the primary state
+    pub(crate) fn expiry_listener(&self) -> event_listener::EventListener {
+        self.lease_collection.expiry_listener()
+    }
+
+    /// Ids of the leases whose revocation has to be continued
+    pub(crate) fn revoking_leases(&self) -> Vec<i64> {
+        self.lease_collection.revoking_leases()
+    }
+
+    /// Retry the revocation of a lease later
+    pub(crate) fn requeue_expired(&self, lease_id: i64, retry_after: Duration) {
+        self.lease_collection.requeue(lease_id, retry_after);
+    }
+
+    /// Remaining ttl of leases to be checkpointed to followers
+    pub(crate) fn checkpoints(&self) -> Vec<LeaseCheckpoint> {
+        self.lease_collection
+            .remaining_ttls()
+            .into_iter()
+            .map(|(id, remaining_ttl)| LeaseCheckpoint { id, remaining_ttl })
+            .collect()
     }
 
-    /// Find expired leases
-    pub(crate) fn find_expired_leases(&self) -> Vec<i64> {
-        self.lease_collection.find_expired_leases()
+    /// Persist the remaining time of leases observed by the leader to the local lease
+    /// expiry table, records of the leases gone since the last call are deleted
+    pub(crate) fn persist_expiries(&self) -> Result<(), ExecuteError> {
+        let term = self.header_gen.term();
+        let observed_at = self.clock.unix_millis();
+        let remainings = self.lease_collection.remainings();
+        let live: HashSet<i64> = remainings.iter().map(|&(id, _)| id).collect();
+        let mut persisted = self.persisted_expiries.lock();
+        let mut ops: Vec<_> = persisted
+            .difference(&live)
+            .map(|&id| WriteOp::DeleteLeaseExpiry(id))
+            .collect();
+        ops.extend(remainings.into_iter().map(|(id, remaining)| {
+            let expiry = LeaseExpiry {
+                term,
+                observed_at,
+                remaining: remaining.as_millis().numeric_cast(),
+            };
+            WriteOp::PutLeaseExpiry(id, expiry.encode())
+        }));
+        _ = self.db.flush_ops(ops)?;
+        *persisted = live;
+        Ok(())
     }
 
     /// Get keys attached to a lease
@@ -132,8 +316,12 @@ impl LeaseStore {
             .unwrap_or_default()
     }
 
-    /// Keep alive a lease
+    /// Keep alive a lease, only the primary is able to renew leases
     pub(crate) fn keep_alive(&self, lease_id: i64) -> Result<i64, ExecuteError> {
+        if !self.is_primary() {
+            return Err(ExecuteError::NotLeader);
+        }
+        self.metrics.renewed_total.add(1, &[]);
         self.lease_collection.renew(lease_id)
     }
 
@@ -149,16 +337,48 @@ impl LeaseStore {
     }
 
     /// Promote current node
-    pub(crate) fn promote(&self, extend: Duration) {
+    pub(crate) fn promote(&self) {
         self.is_primary.store(true, Ordering::Release);
-        self.lease_collection.promote(extend);
+        if let Err(e) = self.restore_expiries() {
+            warn!("failed to restore the persisted lease expiries: {e}");
+        }
+        self.lease_collection.promote();
     }
 
     /// Recover data form persistent storage
     pub(crate) fn recover(&self) -> Result<(), ExecuteError> {
         let leases = self.get_all()?;
         for lease in leases {
-            let _ignore = self.lease_collection.grant(lease.id, lease.ttl, false);
+            let _ignore =
+                self.lease_collection
+                    .grant_owned(lease.id, lease.ttl, lease.owner, false);
+            if lease.remaining_ttl > 0 && lease.remaining_ttl < lease.ttl {
+                self.lease_collection
+                    .restore_remaining_ttl(lease.id, lease.remaining_ttl);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resume the revocations interrupted by a restart. It must be done after the kv
+    /// storage is recovered, so that the keys left are attached to the leases again.
+    pub(crate) fn resume_revokes(&self) -> Result<(), ExecuteError> {
+        let range_end = KeyRange::get_prefix(REVOKING_LEASE_PREFIX);
+        for key in self
+            .db
+            .scan_keys(META_TABLE, REVOKING_LEASE_PREFIX, &range_end)?
+        {
+            let Some(id) = key.strip_prefix(REVOKING_LEASE_PREFIX) else {
+                continue;
+            };
+            let id = <[u8; 8]>::try_from(id)
+                .map(i64::from_be_bytes)
+                .map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to decode revoking lease id, error: {e}"))
+                })?;
+            if self.lease_collection.start_revoke(id).is_some() {
+                self.lease_collection.defer_revoke(id);
+            }
         }
         Ok(())
     }
@@ -170,16 +390,22 @@ impl LeaseStore {
 
     /// Make lease synced, remove it from `unsynced_cache`
     pub(crate) fn mark_lease_synced(&self, wrapper: &RequestWrapper) {
-        #[allow(clippy::wildcard_enum_match_arm)] // only the following two type are allowed
-        let lease_id = match *wrapper {
-            RequestWrapper::LeaseGrantRequest(ref req) => req.id,
-            RequestWrapper::LeaseRevokeRequest(ref req) => req.id,
+        #[allow(clippy::wildcard_enum_match_arm)] // only the following types are allowed
+        let lease_ids = match *wrapper {
+            RequestWrapper::LeaseGrantRequest(ref req) => std::slice::from_ref(&req.id),
+            RequestWrapper::LeaseRevokeRequest(ref req) => std::slice::from_ref(&req.id),
+            RequestWrapper::LeaseRevokeBatchRequest(ref req) => req.ids.as_slice(),
             _ => {
                 return;
             }
         };
 
-        _ = self.unsynced_cache.write().remove(&lease_id);
+        {
+            let mut unsynced_cache = self.unsynced_cache.write();
+            for lease_id in lease_ids {
+                _ = unsynced_cache.remove(lease_id);
+            }
+        }
         let _ignore = self.sync_event.notify(usize::MAX);
     }
 
@@ -213,10 +439,21 @@ impl LeaseStore {
                 debug!("Receive LeaseRevokeRequest {:?}", req);
                 self.handle_lease_revoke_request(req).map(Into::into)
             }
+            RequestWrapper::LeaseRevokeBatchRequest(ref req) => {
+                debug!("Receive LeaseRevokeBatchRequest {:?}", req);
+                Ok(self.handle_lease_revoke_batch_request(req).into())
+            }
             RequestWrapper::LeaseLeasesRequest(ref req) => {
                 debug!("Receive LeaseLeasesRequest {:?}", req);
                 Ok(self.handle_lease_leases_request(req).into())
             }
+            RequestWrapper::LeaseCheckpointRequest(ref req) => {
+                debug!("Receive LeaseCheckpointRequest {:?}", req);
+                Ok(LeaseCheckpointResponse {
+                    header: Some(self.header_gen.gen_header()),
+                }
+                .into())
+            }
             _ => unreachable!("Other request should not be sent to this store"),
         };
         res
@@ -236,13 +473,14 @@ impl LeaseStore {
         if self.lease_collection.contains_lease(req.id) {
             return Err(ExecuteError::LeaseAlreadyExists(req.id));
         }
+        self.lease_collection.check_owner_quota(&req.owner)?;
 
         _ = self.unsynced_cache.write().insert(req.id);
 
         Ok(LeaseGrantResponse {
             header: Some(self.header_gen.gen_header()),
             id: req.id,
-            ttl: req.ttl,
+            ttl: self.lease_collection.normalize_ttl(req.ttl),
             error: String::new(),
         })
     }
@@ -263,16 +501,36 @@ impl LeaseStore {
         }
     }
 
-    /// Handle `LeaseRevokeRequest`
+    /// Handle `LeaseRevokeBatchRequest`, the leases not found are skipped as they may
+    /// have been revoked since they are expired
+    fn handle_lease_revoke_batch_request(
+        &self,
+        req: &LeaseRevokeBatchRequest,
+    ) -> LeaseRevokeBatchResponse {
+        {
+            let mut unsynced_cache = self.unsynced_cache.write();
+            for &id in &req.ids {
+                if self.lease_collection.contains_lease(id) {
+                    _ = unsynced_cache.insert(id);
+                }
+            }
+        }
+        LeaseRevokeBatchResponse {
+            header: Some(self.header_gen.gen_header()),
+        }
+    }
+
+    /// Handle `LeaseLeasesRequest`
     fn handle_lease_leases_request(&self, _req: &LeaseLeasesRequest) -> LeaseLeasesResponse {
-        let leases = self
-            .leases()
+        let leases: Vec<_> = self
+            .lease_ids()
             .into_iter()
-            .map(|lease| LeaseStatus { id: lease.id() })
+            .map(|id| LeaseStatus { id })
             .collect();
 
         LeaseLeasesResponse {
             header: Some(self.header_gen.gen_header()),
+            count: leases.len().numeric_cast(),
             leases,
         }
     }
@@ -287,27 +545,91 @@ impl LeaseStore {
         let ops = match *wrapper {
             RequestWrapper::LeaseGrantRequest(ref req) => {
                 debug!("Sync LeaseGrantRequest {:?}", req);
-                self.sync_lease_grant_request(req)
+                self.sync_lease_grant_request(req).map_err(|e| {
+                    // the grant is never synced, don't let anyone wait for it
+                    self.mark_lease_synced(wrapper);
+                    e
+                })?
             }
             RequestWrapper::LeaseRevokeRequest(ref req) => {
                 debug!("Sync LeaseRevokeRequest {:?}", req);
                 self.sync_lease_revoke_request(req, revision).await?
             }
+            RequestWrapper::LeaseRevokeBatchRequest(ref req) => {
+                debug!("Sync LeaseRevokeBatchRequest {:?}", req);
+                self.sync_lease_revoke_batch_request(req, revision).await
+            }
             RequestWrapper::LeaseLeasesRequest(ref req) => {
                 debug!("Sync LeaseLeasesRequest {:?}", req);
                 vec![]
             }
+            RequestWrapper::LeaseCheckpointRequest(ref req) => {
+                debug!("Sync LeaseCheckpointRequest {:?}", req);
+                self.sync_lease_checkpoint_request(req)
+            }
             _ => unreachable!("Other request should not be sent to this store"),
         };
         Ok((revision, ops))
     }
 
     /// Sync `LeaseGrantRequest`
-    fn sync_lease_grant_request(&self, req: &LeaseGrantRequest) -> Vec<WriteOp> {
-        let lease = self
-            .lease_collection
-            .grant(req.id, req.ttl, self.is_primary());
-        vec![WriteOp::PutLease(lease)]
+    fn sync_lease_grant_request(
+        &self,
+        req: &LeaseGrantRequest,
+    ) -> Result<Vec<WriteOp>, ExecuteError> {
+        // checked again as the grants of the owner may be applied after it's executed
+        self.lease_collection.check_owner_quota(&req.owner)?;
+        let lease = self.lease_collection.grant_owned(
+            req.id,
+            req.ttl,
+            req.owner.clone(),
+            self.is_primary(),
+        );
+        self.metrics.granted_total.add(1, &[]);
+        self.metrics.ttl.record(lease.ttl.numeric_cast(), &[]);
+        Ok(vec![WriteOp::PutLease(lease)])
+    }
+
+    /// Sync `LeaseCheckpointRequest`
+    fn sync_lease_checkpoint_request(&self, req: &LeaseCheckpointRequest) -> Vec<WriteOp> {
+        req.checkpoints
+            .iter()
+            .filter_map(|cp| self.lease_collection.checkpoint(cp.id, cp.remaining_ttl))
+            .filter(|_| self.checkpoint_persist)
+            .map(WriteOp::PutLease)
+            .collect()
+    }
+
+    /// Restore the remaining time of leases persisted by the current node as the leader of
+    /// the previous term, no other leader could have renewed the leases since then. All
+    /// records are stale afterwards, so they are deleted.
+    fn restore_expiries(&self) -> Result<(), ExecuteError> {
+        let term = self.header_gen.term();
+        let now = self.clock.unix_millis();
+        let mut persisted = self.persisted_expiries.lock();
+        persisted.clear();
+        let mut ops = vec![];
+        for (key, value) in self.db.get_all(LEASE_EXPIRY_TABLE)? {
+            let id = i64::decode(key.as_slice()).map_err(|e| {
+                ExecuteError::DbError(format!("Failed to decode lease id, error: {e}"))
+            })?;
+            ops.push(WriteOp::DeleteLeaseExpiry(id));
+            let Some(expiry) = LeaseExpiry::decode(&value) else {
+                warn!("invalid persisted expiry of lease {id}");
+                continue;
+            };
+            if expiry.term.overflow_add(1) != term {
+                continue;
+            }
+            let age = Duration::from_millis(now.saturating_sub(expiry.observed_at));
+            self.lease_collection.restore_observed(
+                id,
+                Duration::from_millis(expiry.remaining),
+                age,
+            );
+        }
+        _ = self.db.flush_ops(ops)?;
+        Ok(())
     }
 
     /// Get all `PbLease`
@@ -325,56 +647,210 @@ impl LeaseStore {
     }
 
     /// Sync `LeaseRevokeRequest`
+    ///
+    /// At most `chunk_size` keys of the request are deleted by a single apply. If more keys are
+    /// left, the lease is marked as revoking in the meta table and stays in the lease
+    /// collection, where it's treated as not found and no key can be attached to it. The
+    /// leader then proposes the revocation again until all keys are deleted, so watchers
+    /// see the deletions of such a lease in multiple revisions.
+    ///
+    /// The keys are deleted from the index by the apply, while their tombstones are only
+    /// persisted as pending along with it and written in the background.
     async fn sync_lease_revoke_request(
         &self,
         req: &LeaseRevokeRequest,
         revision: i64,
     ) -> Result<Vec<WriteOp>, ExecuteError> {
-        let mut ops = Vec::new();
-        let mut updates = Vec::new();
-        ops.push(WriteOp::DeleteLease(req.id));
-
-        let del_keys = match self.lease_collection.look_up(req.id) {
-            Some(l) => l.keys(),
-            None => return Err(ExecuteError::LeaseNotFound(req.id)),
+        let Some((mut del_keys, finished, mut ops)) =
+            self.start_revoke_chunk(req.id, Self::revoke_chunk_keys(req.chunk_size))
+        else {
+            return Err(ExecuteError::LeaseNotFound(req.id));
         };
 
         if del_keys.is_empty() {
             let _ignore = self.lease_collection.revoke(req.id);
-            return Ok(Vec::new());
-        }
-
-        for (key, sub_revision) in del_keys.iter().zip(0..) {
-            let (mut del_ops, mut del_event) = KvStore::delete_keys(
-                &self.index,
-                &self.lease_collection,
-                key,
-                &[],
-                revision,
-                sub_revision,
-            );
-            ops.append(&mut del_ops);
-            updates.append(&mut del_event);
+            return Ok(ops);
         }
 
-        let _ignore = self.lease_collection.revoke(req.id);
-        assert!(
-            self.kv_update_tx.send((revision, updates)).await.is_ok(),
-            "Failed to send updates to KV watcher"
+        utils::fail_point_async!(
+            "lease_revoke_before_cascade_delete",
+            Err(ExecuteError::DbError(
+                "injected failure before the cascade delete".to_owned()
+            ))
         );
+        // Sorted so that every replica assigns the same sub revisions
+        del_keys.sort_unstable();
+        let deletions =
+            KvStore::delete_lease_keys(&self.index, &self.lease_collection, &del_keys, revision);
+        ops.extend(Self::deletion_ops(deletions));
+        self.finish_revoke_chunk(req.id, finished);
         Ok(ops)
     }
+
+    /// Sync `LeaseRevokeBatchRequest`
+    ///
+    /// The same as revoking the leases one by one in id order, except that the keys of
+    /// all the leases are deleted in one revision. The leases not found are skipped. The
+    /// leases share the bound of `chunk_size` keys of a single apply, those left
+    /// with keys are continued by the leader like a chunked revocation.
+    async fn sync_lease_revoke_batch_request(
+        &self,
+        req: &LeaseRevokeBatchRequest,
+        revision: i64,
+    ) -> Vec<WriteOp> {
+        let mut ops = Vec::new();
+        let mut del_keys = Vec::new();
+        let mut revoked = Vec::new();
+        let mut budget = Self::revoke_chunk_keys(req.chunk_size);
+        for id in req.ids.iter().copied().sorted_unstable().dedup() {
+            let Some((mut keys, finished, mut lease_ops)) = self.start_revoke_chunk(id, budget)
+            else {
+                continue;
+            };
+            budget = budget.saturating_sub(keys.len());
+            ops.append(&mut lease_ops);
+            del_keys.append(&mut keys);
+            revoked.push((id, finished));
+        }
+
+        if !del_keys.is_empty() {
+            utils::fail_point_async!("lease_revoke_before_cascade_delete");
+        }
+        // Sorted so that every replica assigns the same sub revisions, the keys of a lease
+        // are attached to no other lease
+        del_keys.sort_unstable();
+        let deletions =
+            KvStore::delete_lease_keys(&self.index, &self.lease_collection, &del_keys, revision);
+        ops.extend(Self::deletion_ops(deletions));
+        for (id, finished) in revoked {
+            self.finish_revoke_chunk(id, finished);
+        }
+        ops
+    }
+
+    /// The most keys deleted by a single apply of revocations with the chunk size of
+    /// their request, zero means unlimited
+    fn revoke_chunk_keys(chunk_size: u64) -> usize {
+        match chunk_size {
+            0 => usize::MAX,
+            size => usize::try_from(size).unwrap_or(usize::MAX),
+        }
+    }
+
+    /// Start revoking the next chunk of at most `max_keys` keys of a lease, returns the
+    /// keys to delete, whether the revocation finishes with them and the ops of the lease
+    fn start_revoke_chunk(
+        &self,
+        lease_id: i64,
+        max_keys: usize,
+    ) -> Option<(Vec<Vec<u8>>, bool, Vec<WriteOp>)> {
+        let (mut del_keys, continued) = self.lease_collection.start_revoke(lease_id)?;
+        if !continued {
+            self.metrics.revoked_total.add(1, &[]);
+        }
+
+        // Every replica deletes the same smallest keys, so that the keys left are the same
+        let finished = del_keys.len() <= max_keys;
+        if !finished {
+            let _ignore = del_keys.select_nth_unstable(max_keys);
+            del_keys.truncate(max_keys);
+        }
+        let ops = if finished {
+            let mut ops = vec![WriteOp::DeleteLease(lease_id)];
+            if continued {
+                ops.push(WriteOp::DeleteRevokingLease(lease_id));
+            }
+            ops
+        } else if continued {
+            vec![]
+        } else {
+            vec![WriteOp::PutRevokingLease(lease_id)]
+        };
+        Some((del_keys, finished, ops))
+    }
+
+    /// Finish a chunk of a revocation after its keys are deleted
+    fn finish_revoke_chunk(&self, lease_id: i64, finished: bool) {
+        if finished {
+            let _ignore = self.lease_collection.revoke(lease_id);
+        } else {
+            self.lease_collection.defer_revoke(lease_id);
+        }
+    }
+
+    /// The writes persisting the deleted keys of a revocation along with it, their
+    /// tombstones are written later by `spawn_key_deletions`
+    fn deletion_ops<'a>(deletions: Vec<LeaseKeyDeletion>) -> impl Iterator<Item = WriteOp<'a>> {
+        deletions
+            .into_iter()
+            .map(|deletion| WriteOp::PutLeaseDeletion(Arc::new(deletion)))
+    }
+
+    /// Write the tombstones of the deleted keys of revocations at `revision`, the
+    /// KV watcher is notified of the deletions once they are all written
+    pub(crate) async fn write_key_deletions(
+        &self,
+        fence: &ApplyFence,
+        revision: i64,
+        deletions: &[Arc<LeaseKeyDeletion>],
+    ) -> Result<(), ApplyError> {
+        KvStore::write_lease_deletions(fence, &self.kv_update_tx, revision, deletions).await
+    }
+
+    /// Write the tombstones of the deleted keys of revocations in the background, so
+    /// that the apply of a lease with many keys doesn't wait for them. The revision
+    /// of the revocations is synced once they are all written.
+    ///
+    /// A failure is handled as one of an apply: the `fence` stops every apply after it
+    /// and the storage is marked as corrupted, the deletions left are persisted and
+    /// written once restarted.
+    pub(crate) fn spawn_key_deletions(
+        self: &Arc<Self>,
+        deletions: Vec<Arc<LeaseKeyDeletion>>,
+        sync: DeferredSync,
+        fence: Arc<ApplyFence>,
+    ) {
+        let store = Arc::clone(self);
+        let _ignore = tokio::spawn(async move {
+            let revision = sync.revision();
+            if let Err(e) = store
+                .write_key_deletions(&fence, revision, &deletions)
+                .await
+            {
+                sync.abandon();
+                fence.fail(format!(
+                    "failed to write the deleted keys of revision {revision}: {e}"
+                ));
+            }
+        });
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{error::Error, time::Duration};
-
+    use std::{
+        collections::HashMap,
+        error::Error,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
     use test_macros::abort_on_panic;
-    use utils::config::EngineConfig;
+    use utils::{
+        config::EngineConfig,
+        failpoint::{FailAction, FailScenario},
+    };
 
     use super::*;
-    use crate::storage::db::DB;
+    use crate::{
+        clock::MockTimeProvider,
+        storage::{
+            db::{DB, LEASE_DELETION_PREFIX},
+            index::IndexOperate,
+            kv_store::LEASE_DELETION_BATCH_SIZE,
+        },
+    };
 
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
@@ -383,28 +859,46 @@ mod test {
         let lease_store = init_store(db);
         let revision_gen = lease_store.header_gen.general_revision_arc();
 
-        let req1 = RequestWrapper::from(LeaseGrantRequest { ttl: 10, id: 1 });
+        let req1 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
         let _ignore1 = exe_and_sync_req(&lease_store, &req1, -1).await?;
 
         let lo = lease_store.look_up(1).unwrap();
         assert_eq!(lo.id(), 1);
         assert_eq!(lo.ttl(), Duration::from_secs(10));
-        assert_eq!(lease_store.leases().len(), 1);
+        assert_eq!(lease_store.lease_ids().len(), 1);
 
         let attach_non_existing_lease = lease_store.lease_collection.attach(0, "key".into());
         assert!(attach_non_existing_lease.is_err());
         let attach_existing_lease = lease_store.lease_collection.attach(1, "key".into());
         assert!(attach_existing_lease.is_ok());
-        lease_store.lease_collection.detach(1, "key".as_bytes())?;
+        lease_store.lease_collection.detach(1, "key".as_bytes());
 
-        let req2 = RequestWrapper::from(LeaseRevokeRequest { id: 1 });
+        let req2 = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
         let _ignore2 = exe_and_sync_req(&lease_store, &req2, revision_gen.next()).await?;
         assert!(lease_store.look_up(1).is_none());
-        assert!(lease_store.leases().is_empty());
-
-        let req3 = RequestWrapper::from(LeaseGrantRequest { ttl: 10, id: 3 });
-        let req4 = RequestWrapper::from(LeaseGrantRequest { ttl: 10, id: 4 });
-        let req5 = RequestWrapper::from(LeaseRevokeRequest { id: 3 });
+        assert!(lease_store.lease_ids().is_empty());
+
+        let req3 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 3,
+            ..Default::default()
+        });
+        let req4 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 4,
+            ..Default::default()
+        });
+        let req5 = RequestWrapper::from(LeaseRevokeRequest {
+            id: 3,
+            ..Default::default()
+        });
         let req6 = RequestWrapper::from(LeaseLeasesRequest {});
         let _ignore3 = exe_and_sync_req(&lease_store, &req3, -1).await?;
         let _ignore4 = exe_and_sync_req(&lease_store, &req4, -1).await?;
@@ -432,7 +926,11 @@ mod test {
         let lease_store = init_store(db);
         let wait_duration = Duration::from_millis(1);
 
-        let req1 = RequestWrapper::from(LeaseGrantRequest { ttl: 10, id: 1 });
+        let req1 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
         let _ignore1 = lease_store.execute(&req1)?;
 
         assert!(
@@ -453,7 +951,10 @@ mod test {
             "the future should complete immediately after the lease is synced"
         );
 
-        let req2 = RequestWrapper::from(LeaseRevokeRequest { id: 1 });
+        let req2 = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
         let _ignore2 = lease_store.execute(&req2)?;
 
         assert!(
@@ -483,7 +984,11 @@ mod test {
         let db = DB::open(&EngineConfig::Memory)?;
         let store = init_store(Arc::clone(&db));
 
-        let req1 = RequestWrapper::from(LeaseGrantRequest { ttl: 10, id: 1 });
+        let req1 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
         let _ignore1 = exe_and_sync_req(&store, &req1, -1).await?;
         store.lease_collection.attach(1, "key".into())?;
 
@@ -502,12 +1007,1147 @@ mod test {
         Ok(())
     }
 
-    fn init_store(db: Arc<DB>) -> LeaseStore {
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_checkpoint_should_not_extend_lease_across_leader_change(
+    ) -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store(Arc::clone(&db));
+
+        let req1 = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore1 = exe_and_sync_req(&store, &req1, -1).await?;
+        let req2 = RequestWrapper::from(LeaseCheckpointRequest {
+            checkpoints: vec![LeaseCheckpoint {
+                id: 1,
+                remaining_ttl: 4,
+            }],
+        });
+        let _ignore2 = exe_and_sync_req(&store, &req2, -1).await?;
+
+        store.demote();
+        store.promote();
+        let remaining = store.look_up(1).unwrap().remaining();
+        assert!(
+            remaining <= Duration::from_secs(4),
+            "remaining ttl grows to {remaining:?}"
+        );
+
+        // the checkpoint is persisted, a restarted node should keep it as well
+        let new_store = init_store(db);
+        new_store.recover()?;
+        new_store.promote();
+        assert!(new_store.look_up(1).unwrap().remaining() <= Duration::from_secs(4));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_batched_revoke_should_equal_sequential_revokes() -> Result<(), Box<dyn Error>> {
+        const LEASES: i64 = 10_000;
+        const LEASES_PER_REVOKE: usize = 128;
+        let mut outcomes = vec![];
+        for batched in [false, true] {
+            let db = DB::open(&EngineConfig::Memory)?;
+            let (kv_update_tx, mut kv_update_rx) = mpsc::channel(10_000);
+            let index = Arc::new(Index::new());
+            let store = LeaseStore::new(
+                Arc::new(LeaseCollection::new(0)),
+                Arc::new(HeaderGenerator::new(0, 0)),
+                db,
+                Arc::clone(&index),
+                kv_update_tx,
+                true,
+                true,
+            );
+            for id in 1..=LEASES {
+                let req = RequestWrapper::from(LeaseGrantRequest {
+                    ttl: 60,
+                    id,
+                    ..Default::default()
+                });
+                let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+            }
+            // every tenth lease has two keys attached
+            let attached: Vec<(i64, Vec<u8>)> = (1..=LEASES)
+                .filter(|id| id % 10 == 0)
+                .flat_map(|id| ["a", "b"].map(|k| (id, format!("{id}/{k}").into_bytes())))
+                .collect();
+            index.insert(
+                attached
+                    .iter()
+                    .zip(0..)
+                    .map(|((_, key), sub_revision)| {
+                        (key.clone(), index.register_revision(key, 2, sub_revision))
+                    })
+                    .collect(),
+            );
+            for (id, key) in &attached {
+                store.lease_collection.attach(*id, key.clone())?;
+            }
+
+            // the leases expire in the reverse order, along with a lease revoked already
+            let expired: Vec<i64> = (1..=LEASES).rev().collect();
+            let requests: Vec<_> = if batched {
+                expired
+                    .chunks(LEASES_PER_REVOKE)
+                    .map(|ids| {
+                        let mut ids = ids.to_vec();
+                        ids.push(LEASES + 1);
+                        RequestWrapper::from(LeaseRevokeBatchRequest {
+                            ids,
+                            ..Default::default()
+                        })
+                    })
+                    .collect()
+            } else {
+                expired
+                    .iter()
+                    .map(|&id| {
+                        RequestWrapper::from(LeaseRevokeRequest {
+                            id,
+                            ..Default::default()
+                        })
+                    })
+                    .collect()
+            };
+            for (req, revision) in requests.iter().zip(3..) {
+                let _ignore = exe_and_sync_req(&store, req, revision).await?;
+            }
+
+            let mut updates = 0;
+            let mut deleted = vec![];
+            while let Ok((_, events)) = kv_update_rx.try_recv() {
+                updates += 1;
+                deleted.extend(events.iter().map(|e| e.key.to_vec()));
+            }
+            deleted.sort_unstable();
+            assert!(store.leases().is_empty());
+            assert!(store.get_all()?.is_empty());
+            assert!(store.lease_collection.attach(10, b"10/c".to_vec()).is_err());
+            outcomes.push((requests.len(), updates, deleted));
+        }
+
+        let (sequential, batched) = (&outcomes[0], &outcomes[1]);
+        assert_eq!(sequential.0, 10_000);
+        assert_eq!(batched.0, 79);
+        // the keys of a batch are deleted in one revision
+        assert_eq!(sequential.1, 1000);
+        assert_eq!(batched.1, 79);
+        assert_eq!(sequential.2.len(), 2000);
+        assert_eq!(sequential.2, batched.2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_restore_persisted_expiries_after_restart() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store(Arc::clone(&db));
+        store.header_gen.set_term(1);
+
+        for id in [1, 2] {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 4,
+                id,
+                ..Default::default()
+            });
+            let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        }
+        store.persist_expiries()?;
+        let req = RequestWrapper::from(LeaseRevokeRequest {
+            id: 2,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        store.persist_expiries()?;
+        assert_eq!(db.get_all(LEASE_EXPIRY_TABLE)?.len(), 1);
+
+        // the leader crashes, restarts and is re-elected in the next term
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let new_store = init_store(Arc::clone(&db));
+        new_store.recover()?;
+        new_store.header_gen.set_term(2);
+        new_store.promote();
+        let remaining = new_store.look_up(1).unwrap().remaining();
+        assert!(
+            remaining <= Duration::from_secs(2) && remaining > Duration::from_secs(1),
+            "remaining ttl is {remaining:?}"
+        );
+        assert!(db.get_all(LEASE_EXPIRY_TABLE)?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn persisted_expiries_should_follow_the_server_clock() -> Result<(), ExecuteError> {
+        let time = MockTimeProvider::new(UNIX_EPOCH + Duration::from_secs(1000));
+        let clock = Arc::new(Clock::new(Arc::clone(&time)));
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store(Arc::clone(&db)).with_clock(Arc::clone(&clock));
+        store.header_gen.set_term(1);
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 4,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        store.persist_expiries()?;
+
+        // the age of the record is measured with the clock of the server
+        time.advance(Duration::from_secs(1));
+        let new_store = init_store(Arc::clone(&db)).with_clock(Arc::clone(&clock));
+        new_store.recover()?;
+        new_store.header_gen.set_term(2);
+        new_store.promote();
+        let remaining = new_store.look_up(1).unwrap().remaining();
+        assert!(
+            remaining <= Duration::from_secs(3) && remaining > Duration::from_secs(2),
+            "remaining ttl is {remaining:?}"
+        );
+
+        // a backward jump neither extends nor shortens the lease
+        new_store.persist_expiries()?;
+        time.jump_backward(Duration::from_secs(3600));
+        assert!(new_store.look_up(1).unwrap().remaining() > Duration::from_secs(2));
+        let third_store = init_store(Arc::clone(&db)).with_clock(Arc::clone(&clock));
+        third_store.recover()?;
+        third_store.header_gen.set_term(3);
+        third_store.promote();
+        let remaining = third_store.look_up(1).unwrap().remaining();
+        assert!(
+            remaining <= Duration::from_secs(3) && remaining > Duration::from_secs(2),
+            "remaining ttl is {remaining:?}"
+        );
+        assert_eq!(clock.jumps(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_persisted_expiries_should_not_override_newer_states() -> Result<(), ExecuteError>
+    {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store(Arc::clone(&db));
+        store.header_gen.set_term(1);
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 4,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        store.persist_expiries()?;
+
+        // another leader may have renewed the lease in the terms between
+        let new_store = init_store(Arc::clone(&db));
+        new_store.recover()?;
+        new_store.header_gen.set_term(3);
+        new_store.promote();
+        assert!(new_store.look_up(1).unwrap().remaining() > Duration::from_secs(3));
+        assert!(db.get_all(LEASE_EXPIRY_TABLE)?.is_empty());
+
+        // a checkpoint applied after the record is newer
+        store.persist_expiries()?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let new_store = init_store(Arc::clone(&db));
+        new_store.recover()?;
+        let req = RequestWrapper::from(LeaseCheckpointRequest {
+            checkpoints: vec![LeaseCheckpoint {
+                id: 1,
+                remaining_ttl: 1,
+            }],
+        });
+        let _ignore = exe_and_sync_req(&new_store, &req, -1).await?;
+        new_store.header_gen.set_term(2);
+        new_store.promote();
+        assert!(new_store.look_up(1).unwrap().remaining() <= Duration::from_secs(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_grant_ttl_is_clamped_to_min_ttl() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(LeaseCollection::with_election_timeout(
+            Duration::from_secs(10),
+        ));
+        let (kv_update_tx, _) = mpsc::channel(1);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            index,
+            kv_update_tx,
+            true,
+            false,
+        );
+
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 5,
+            id: 1,
+            ..Default::default()
+        });
+        let ResponseWrapper::LeaseGrantResponse(res) = exe_and_sync_req(&store, &req, -1).await?
+        else {
+            panic!("wrong response type");
+        };
+        assert_eq!(res.ttl, 15);
+        assert_eq!(store.look_up(1).unwrap().ttl(), Duration::from_secs(15));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_grant_ttl_is_normalized() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(
+            LeaseCollection::with_election_timeout(Duration::from_secs(10))
+                .with_default_ttl(Duration::from_secs(20)),
+        );
+        let (kv_update_tx, _) = mpsc::channel(1);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            index,
+            kv_update_tx,
+            true,
+            false,
+        );
+
+        for (id, ttl, expected) in [(1, 0, 20), (2, -5, 20), (3, 14, 15)] {
+            let ttl = store.normalize_ttl(ttl);
+            assert_eq!(ttl, expected);
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl,
+                id,
+                ..Default::default()
+            });
+            let ResponseWrapper::LeaseGrantResponse(res) =
+                exe_and_sync_req(&store, &req, -1).await?
+            else {
+                panic!("wrong response type");
+            };
+            assert_eq!(res.ttl, expected);
+            assert_eq!(
+                store.look_up(id).unwrap().ttl(),
+                Duration::from_secs(expected.numeric_cast())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_after_sync_without_execute() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store(Arc::clone(&db));
+
+        // entries replayed from the curp log are synced without a prior execute
+        let grant = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
+        let (_ignore, ops) = store.after_sync(&grant, -1).await?;
+        _ = db.flush_ops(ops)?;
+        assert!(store.look_up(1).is_some());
+
+        let revoke_unknown = RequestWrapper::from(LeaseRevokeRequest {
+            id: 2,
+            ..Default::default()
+        });
+        assert!(matches!(
+            store.after_sync(&revoke_unknown, -1).await,
+            Err(ApplyError::Execute(ExecuteError::LeaseNotFound(2)))
+        ));
+
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let (_ignore, ops) = store.after_sync(&revoke, -1).await?;
+        _ = db.flush_ops(ops)?;
+        assert!(store.look_up(1).is_none());
+
+        // the revoked lease should not come back after a restart
+        let new_store = init_store(db);
+        new_store.recover()?;
+        assert!(new_store.look_up(1).is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_active_leases_per_owner_should_be_limited() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_store_with(
+            Arc::clone(&db),
+            LeaseCollection::new(0).with_max_leases_per_owner(2),
+        );
+        let grant = |id, owner: &str| {
+            RequestWrapper::from(LeaseGrantRequest {
+                ttl: 10,
+                id,
+                owner: owner.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        for id in 1..=2 {
+            let _ignore = exe_and_sync_req(&store, &grant(id, "storm"), -1).await?;
+        }
+        assert!(matches!(
+            store.execute(&grant(3, "storm")),
+            Err(ExecuteError::LeaseQuotaExceeded(ref owner)) if owner == "storm"
+        ));
+        // the quota is enforced on the replicated state as well
+        assert!(matches!(
+            store.after_sync(&grant(3, "storm"), -1).await,
+            Err(ApplyError::Execute(ExecuteError::LeaseQuotaExceeded(_)))
+        ));
+        assert!(store.look_up(3).is_none());
+        // other owners are not affected
+        for id in 4..=5 {
+            let _ignore = exe_and_sync_req(&store, &grant(id, "normal"), -1).await?;
+        }
+
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &revoke, -1).await?;
+        let _ignore = exe_and_sync_req(&store, &grant(3, "storm"), -1).await?;
+
+        // the active leases of the owners are recovered after a restart
+        let new_store = init_store_with(db, LeaseCollection::new(0).with_max_leases_per_owner(2));
+        new_store.recover()?;
+        assert!(matches!(
+            new_store.execute(&grant(6, "storm")),
+            Err(ExecuteError::LeaseQuotaExceeded(_))
+        ));
+        assert!(matches!(
+            new_store.execute(&grant(6, "normal")),
+            Err(ExecuteError::LeaseQuotaExceeded(_))
+        ));
+        assert!(new_store.execute(&grant(6, "other")).is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_lease_with_many_keys_should_not_block_grants() -> Result<(), Box<dyn Error>>
+    {
+        const KEYS: usize = 50_000;
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(1);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = Arc::new(
+            LeaseStore::new(
+                lease_collection,
+                header_gen,
+                db,
+                Arc::clone(&index),
+                kv_update_tx,
+                true,
+                true,
+            )
+            .with_revoke_chunk_size(0),
+        );
+
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 60,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        let keys: Vec<Vec<u8>> = (0..KEYS)
+            .map(|i| format!("key{i:05}").into_bytes())
+            .collect();
+        index.insert(
+            keys.iter()
+                .zip(0..)
+                .map(|(key, sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for key in keys {
+            store.lease_collection.attach(1, key)?;
+        }
+
+        // the apply persists the deleted keys as pending, the tombstones are written
+        // after it in the background
+        let revoke_store = Arc::clone(&store);
+        let revoke = tokio::spawn(async move {
+            let req = RequestWrapper::from(LeaseRevokeRequest {
+                id: 1,
+                ..Default::default()
+            });
+            let (_ignore, ops) = revoke_store.after_sync(&req, 3).await?;
+            let deletions = pending_deletions(&ops);
+            let tombstones = ops
+                .iter()
+                .filter(|op| matches!(**op, WriteOp::PutKeyValue(..)))
+                .count();
+            _ = revoke_store.db.flush_ops(ops)?;
+            let fence = ApplyFence::new(Arc::clone(&revoke_store.db));
+            revoke_store
+                .write_key_deletions(&fence, 3, &deletions)
+                .await?;
+            Ok::<_, ExecuteError>((tombstones, deletions))
+        });
+        let mut max_delay = Duration::ZERO;
+        for id in 2.. {
+            if revoke.is_finished() {
+                break;
+            }
+            let start = std::time::Instant::now();
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id,
+                ..Default::default()
+            });
+            let _ignore = store.after_sync(&req, -1).await?;
+            max_delay = max_delay.max(start.elapsed());
+            tokio::task::yield_now().await;
+        }
+
+        let (tombstones, deletions) = revoke.await??;
+        assert_eq!(tombstones, 0);
+        assert_eq!(deletions.len(), KEYS.div_ceil(LEASE_DELETION_BATCH_SIZE));
+        assert!(deletions
+            .iter()
+            .all(|d| d.keys().count() <= LEASE_DELETION_BATCH_SIZE));
+        assert!(deletions
+            .windows(2)
+            .all(|w| w[0].sub_revision() < w[1].sub_revision()));
+        assert!(store
+            .db
+            .get_all(META_TABLE)?
+            .iter()
+            .all(|(key, _)| !key.starts_with(LEASE_DELETION_PREFIX)));
+        let (revision, events) = kv_update_rx.recv().await.unwrap();
+        assert_eq!(revision, 3);
+        assert_eq!(events.len(), KEYS);
+        assert!(
+            events.windows(2).all(|w| w[0].key < w[1].key),
+            "keys should be deleted in order"
+        );
+        assert_eq!(store.lease_collection.get_lease(b"key00000"), 0);
+        assert!(
+            max_delay < Duration::from_millis(100),
+            "grants were delayed by {max_delay:?}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_attach_during_revoke_should_not_leave_dangling_keys() -> Result<(), Box<dyn Error>>
+    {
+        const KEYS: usize = 100;
+        let scenario = FailScenario::setup();
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let (kv_update_tx, _kv_update_rx) = mpsc::channel(1);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = Arc::new(LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            Arc::clone(&index),
+            kv_update_tx,
+            true,
+            true,
+        ));
+
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 60,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        let keys: Vec<Vec<u8>> = (0..KEYS)
+            .map(|i| format!("key{i:05}").into_bytes())
+            .collect();
+        index.insert(
+            keys.iter()
+                .zip(0..)
+                .map(|(key, sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for key in keys {
+            store.lease_collection.attach(1, key)?;
+        }
+
+        // the revocation pauses between marking the lease and deleting its keys
+        scenario.cfg_local("lease_revoke_before_cascade_delete", FailAction::Pause);
+        let req = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let racing_key = b"racing".to_vec();
+        let race = async {
+            scenario
+                .reached("lease_revoke_before_cascade_delete", 1)
+                .await;
+            let attached = store.lease_collection.attach(1, racing_key.clone());
+            // detaching from a lease being revoked is a no-op
+            store.lease_collection.detach(1, &racing_key);
+            scenario.remove("lease_revoke_before_cascade_delete");
+            attached
+        };
+        let (revoked, attached) = tokio::join!(store.after_sync(&req, 3), race);
+        let _ignore = revoked?;
+
+        assert!(attached.is_err(), "attached to a lease being revoked");
+        assert!(store.look_up(1).is_none());
+        assert_eq!(store.lease_collection.get_lease(&racing_key), 0);
+        assert!(matches!(
+            store.lease_collection.attach(1, b"foo".to_vec()),
+            Err(ExecuteError::LeaseNotFound(1))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_attach_during_batched_revoke_should_not_leave_dangling_keys(
+    ) -> Result<(), Box<dyn Error>> {
+        let scenario = FailScenario::setup();
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let (kv_update_tx, _kv_update_rx) = mpsc::channel(1);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = Arc::new(LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            Arc::clone(&index),
+            kv_update_tx,
+            true,
+            true,
+        ));
+
+        for (id, key) in [(1, b"foo".to_vec()), (2, b"bar".to_vec())] {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id,
+                ..Default::default()
+            });
+            let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+            index.insert(vec![(key.clone(), index.register_revision(&key, 2, id))]);
+            store.lease_collection.attach(id, key)?;
+        }
+
+        // the batch pauses between marking the leases and deleting their keys
+        scenario.cfg_local("lease_revoke_before_cascade_delete", FailAction::Pause);
+        let req = RequestWrapper::from(LeaseRevokeBatchRequest {
+            ids: vec![2, 1],
+            ..Default::default()
+        });
+        let racing_key = b"racing".to_vec();
+        let race = async {
+            scenario
+                .reached("lease_revoke_before_cascade_delete", 1)
+                .await;
+            let attached = [1, 2].map(|id| store.lease_collection.attach(id, racing_key.clone()));
+            scenario.remove("lease_revoke_before_cascade_delete");
+            attached
+        };
+        let (revoked, attached) = tokio::join!(store.after_sync(&req, 3), race);
+        let _ignore = revoked?;
+
+        assert!(
+            attached.iter().all(Result::is_err),
+            "attached to a lease being revoked"
+        );
+        assert!(store.look_up(1).is_none());
+        assert!(store.look_up(2).is_none());
+        assert_eq!(store.lease_collection.get_lease(&racing_key), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_after_kv_watcher_dropped_should_not_panic() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        // the receiver of kv updates is dropped by `init_store`
+        let lease_store = init_store(db);
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 10,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&lease_store, &req, -1).await?;
+        let index_rev = lease_store.index.register_revision(b"foo", 2, 0);
+        lease_store.index.insert(vec![(b"foo".to_vec(), index_rev)]);
+        lease_store.lease_collection.attach(1, b"foo".to_vec())?;
+
+        let req = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&lease_store, &req, 3).await?;
+        assert!(lease_store.look_up(1).is_none());
+        assert_eq!(lease_store.lease_collection.get_lease(b"foo"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_lease_metrics_after_grant_and_revoke() -> Result<(), Box<dyn Error>> {
+        let registry = prometheus::Registry::new();
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()?;
+        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+        let db = DB::open(&EngineConfig::Memory)?;
+        let mut lease_store = init_store(db);
+        lease_store.metrics =
+            LeaseMetrics::with_meter(&provider.meter("xline"), &lease_store.lease_collection);
+
+        for id in 1..=2 {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 10,
+                id,
+                ..Default::default()
+            });
+            let _ignore = exe_and_sync_req(&lease_store, &req, -1).await?;
+        }
+        let _ignore = lease_store.keep_alive(1)?;
+        let req = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&lease_store, &req, 2).await?;
+
+        let families = registry.gather();
+        let family = |name: &str| {
+            families
+                .iter()
+                .find(|f| f.get_name() == name)
+                .unwrap_or_else(|| panic!("metric {name} not found"))
+                .get_metric()[0]
+                .clone()
+        };
+        assert_eq!(
+            family("etcd_debugging_lease_granted_total")
+                .get_counter()
+                .get_value(),
+            2.0
+        );
+        assert_eq!(
+            family("etcd_debugging_lease_revoked_total")
+                .get_counter()
+                .get_value(),
+            1.0
+        );
+        assert_eq!(
+            family("etcd_debugging_lease_renewed_total")
+                .get_counter()
+                .get_value(),
+            1.0
+        );
+        assert_eq!(
+            family("etcd_debugging_lease_count").get_gauge().get_value(),
+            1.0
+        );
+        let ttl = family("etcd_debugging_lease_ttl_total");
+        assert_eq!(ttl.get_histogram().get_sample_count(), 2);
+        assert_eq!(ttl.get_histogram().get_sample_sum(), 20.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_lease_metrics_should_unregister_the_count_callback_on_drop(
+    ) -> Result<(), Box<dyn Error>> {
+        let registry = prometheus::Registry::new();
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()?;
+        let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let _ignore = lease_collection.grant(1, 10, false);
+
+        let metrics = LeaseMetrics::with_meter(&provider.meter("xline"), &lease_collection);
+        assert_eq!(Arc::weak_count(&lease_collection), 1);
+        assert!(registry
+            .gather()
+            .iter()
+            .any(|f| f.get_name() == "etcd_debugging_lease_count"));
+
+        drop(metrics);
+        // the callback holding the collection is gone, not just observing nothing
+        assert_eq!(Arc::weak_count(&lease_collection), 0);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_should_delete_keys_in_chunks() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
         let lease_collection = Arc::new(LeaseCollection::new(0));
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(4);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let store = LeaseStore::new(
+            lease_collection,
+            header_gen,
+            Arc::clone(&db),
+            Arc::clone(&index),
+            kv_update_tx,
+            true,
+            true,
+        )
+        .with_revoke_chunk_size(2);
+
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 60,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        let keys: Vec<Vec<u8>> = (0..5).map(|i| format!("key{i}").into_bytes()).collect();
+        index.insert(
+            keys.iter()
+                .zip(0..)
+                .map(|(key, sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for key in keys.iter().rev() {
+            store.lease_collection.attach(1, key.clone())?;
+        }
+
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            chunk_size: store.revoke_chunk_size().numeric_cast(),
+        });
+        let _ignore = exe_and_sync_req(&store, &revoke, 3).await?;
+        // the lease is revoked from the first chunk on
+        assert!(store.look_up(1).is_none());
+        assert!(store.lease_ids().is_empty());
+        assert!(store.keep_alive(1).is_err());
+        assert!(store.lease_collection.attach(1, b"foo".to_vec()).is_err());
+        assert_eq!(store.revoking_leases(), vec![1]);
+        assert!(store.find_expired_leases(0).is_empty());
+
+        let _ignore = exe_and_sync_req(&store, &revoke, 4).await?;
+        let _ignore = exe_and_sync_req(&store, &revoke, 5).await?;
+        assert!(store.revoking_leases().is_empty());
+        assert!(!store.lease_collection.contains_lease(1));
+        assert!(matches!(
+            store.execute(&revoke),
+            Err(ExecuteError::LeaseNotFound(1))
+        ));
+        assert!(store.get_all()?.is_empty());
+        assert!(db
+            .get_all(META_TABLE)?
+            .iter()
+            .all(|(key, _)| !key.starts_with(REVOKING_LEASE_PREFIX)));
+
+        // watchers see the deletions of the smallest keys first, one revision per chunk
+        for (revision, expected) in [(3, &keys[..2]), (4, &keys[2..4]), (5, &keys[4..])] {
+            let (rev, events) = kv_update_rx.recv().await.unwrap();
+            assert_eq!(rev, revision);
+            let deleted: Vec<_> = events.iter().map(|e| e.key.to_vec()).collect();
+            assert_eq!(deleted, expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_should_use_the_chunk_size_of_the_request() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(4);
+        let index = Arc::new(Index::new());
+        // this member is configured with another chunk size than the proposer
+        let store = LeaseStore::new(
+            Arc::new(LeaseCollection::new(0)),
+            Arc::new(HeaderGenerator::new(0, 0)),
+            db,
+            Arc::clone(&index),
+            kv_update_tx,
+            false,
+            true,
+        )
+        .with_revoke_chunk_size(1);
+
+        let req = RequestWrapper::from(LeaseGrantRequest {
+            ttl: 60,
+            id: 1,
+            ..Default::default()
+        });
+        let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        let keys: Vec<Vec<u8>> = (0..3).map(|i| format!("key{i}").into_bytes()).collect();
+        index.insert(
+            keys.iter()
+                .zip(0..)
+                .map(|(key, sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for key in &keys {
+            store.lease_collection.attach(1, key.clone())?;
+        }
+
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            chunk_size: 2,
+        });
+        for revision in [3, 4] {
+            let _ignore = exe_and_sync_req(&store, &revoke, revision).await?;
+        }
+        assert!(store.revoking_leases().is_empty());
+        for (revision, count) in [(3, 2), (4, 1)] {
+            let (rev, events) = kv_update_rx.recv().await.unwrap();
+            assert_eq!(rev, revision);
+            assert_eq!(events.len(), count);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_batched_revoke_should_share_the_chunk_size() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(4);
+        let index = Arc::new(Index::new());
+        let store = LeaseStore::new(
+            Arc::new(LeaseCollection::new(0)),
+            Arc::new(HeaderGenerator::new(0, 0)),
+            db,
+            Arc::clone(&index),
+            kv_update_tx,
+            true,
+            true,
+        )
+        .with_revoke_chunk_size(2);
+
+        for id in 1..=4 {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id,
+                ..Default::default()
+            });
+            let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        }
+        // lease 1 has one key, lease 2 three keys, lease 3 one key and lease 4 none
+        let attached: Vec<(i64, Vec<u8>)> = [(1, "a"), (2, "b"), (2, "c"), (2, "d"), (3, "e")]
+            .into_iter()
+            .map(|(id, key)| (id, key.as_bytes().to_vec()))
+            .collect();
+        index.insert(
+            attached
+                .iter()
+                .zip(0..)
+                .map(|((_, key), sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for (id, key) in &attached {
+            store.lease_collection.attach(*id, key.clone())?;
+        }
+
+        let batch = RequestWrapper::from(LeaseRevokeBatchRequest {
+            ids: vec![4, 3, 2, 1],
+            chunk_size: store.revoke_chunk_size().numeric_cast(),
+        });
+        let _ignore = exe_and_sync_req(&store, &batch, 3).await?;
+        // the budget runs out in lease 2, lease 3 is continued without deleting a key
+        let mut revoking = store.revoking_leases();
+        revoking.sort_unstable();
+        assert_eq!(revoking, vec![2, 3]);
+        assert!(store.lease_ids().is_empty());
+        for (id, revision) in [(2, 4), (3, 5)] {
+            let revoke = RequestWrapper::from(LeaseRevokeRequest {
+                id,
+                chunk_size: store.revoke_chunk_size().numeric_cast(),
+            });
+            let _ignore = exe_and_sync_req(&store, &revoke, revision).await?;
+        }
+        assert!(store.revoking_leases().is_empty());
+        assert!(store.get_all()?.is_empty());
+
+        for (revision, expected) in [(3, vec!["a", "b"]), (4, vec!["c", "d"]), (5, vec!["e"])] {
+            let (rev, events) = kv_update_rx.recv().await.unwrap();
+            assert_eq!(rev, revision);
+            let deleted: Vec<_> = events.iter().map(|e| e.key.to_vec()).collect();
+            let expected: Vec<_> = expected.iter().map(|k| k.as_bytes().to_vec()).collect();
+            assert_eq!(deleted, expected);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_revoke_should_not_be_chunked_before_cluster_supports_it(
+    ) -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let (kv_update_tx, mut kv_update_rx) = mpsc::channel(4);
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let index = Arc::new(Index::new());
+        let cluster_info = Arc::new(ClusterInfo::from_members_map(
+            HashMap::from([("n0".to_owned(), vec!["http://127.0.0.1:2380".to_owned()])]),
+            [],
+            "n0",
+        ));
+        // an old member is still in the cluster
+        cluster_info.set_cluster_server_version(0);
+        let store = LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            Arc::clone(&index),
+            kv_update_tx,
+            true,
+            true,
+        )
+        .with_revoke_chunk_size(2)
+        .with_cluster_info(Arc::clone(&cluster_info));
+
+        for id in [1, 2] {
+            let req = RequestWrapper::from(LeaseGrantRequest {
+                ttl: 60,
+                id,
+                ..Default::default()
+            });
+            let _ignore = exe_and_sync_req(&store, &req, -1).await?;
+        }
+        let keys: Vec<Vec<u8>> = (0..6).map(|i| format!("key{i}").into_bytes()).collect();
+        index.insert(
+            keys.iter()
+                .zip(0..)
+                .map(|(key, sub_revision)| {
+                    (key.clone(), index.register_revision(key, 2, sub_revision))
+                })
+                .collect(),
+        );
+        for (i, key) in keys.iter().enumerate() {
+            store
+                .lease_collection
+                .attach(if i < 3 { 1 } else { 2 }, key.clone())?;
+        }
+
+        assert!(!store.batched_revoke_enabled());
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 1,
+            chunk_size: store.revoke_chunk_size().numeric_cast(),
+        });
+        let _ignore = exe_and_sync_req(&store, &revoke, 3).await?;
+        assert!(store.revoking_leases().is_empty());
+        assert!(!store.lease_collection.contains_lease(1));
+        let (_, events) = kv_update_rx.recv().await.unwrap();
+        assert_eq!(events.len(), 3);
+
+        // the old member is upgraded
+        cluster_info.set_cluster_server_version(Feature::ChunkedLeaseRevoke.since());
+        assert!(store.batched_revoke_enabled());
+        let revoke = RequestWrapper::from(LeaseRevokeRequest {
+            id: 2,
+            chunk_size: store.revoke_chunk_size().numeric_cast(),
+        });
+        let _ignore = exe_and_sync_req(&store, &revoke, 4).await?;
+        assert_eq!(store.revoking_leases(), vec![2]);
+        let (_, events) = kv_update_rx.recv().await.unwrap();
+        assert_eq!(events.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lease_leases_should_be_sorted_during_grants_and_revokes() -> Result<(), Box<dyn Error>>
+    {
+        // the listing spans pages, and the churning leases interleave the kept ones
+        const KEPT: i64 = 3000;
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = Arc::new(init_store(db));
+        for id in (1..=KEPT).map(|i| i * 2) {
+            let _ignore = store.lease_collection.grant(id, 60, false);
+        }
+
+        let churn_store = Arc::clone(&store);
+        let churn = std::thread::spawn(move || {
+            for round in 0..20 {
+                for id in (0..KEPT).map(|i| i * 2 + 1) {
+                    if round % 2 == 0 {
+                        let _ignore = churn_store.lease_collection.grant(id, 60, false);
+                    } else {
+                        let _ignore = churn_store.lease_collection.revoke(id);
+                    }
+                }
+            }
+        });
+        while !churn.is_finished() {
+            let res = store.handle_lease_leases_request(&LeaseLeasesRequest {});
+            assert!(res.leases.windows(2).all(|w| w[0].id < w[1].id));
+            assert_eq!(res.count, res.leases.len().numeric_cast::<i64>());
+            let kept = res.leases.iter().filter(|lease| lease.id % 2 == 0).count();
+            assert_eq!(kept, KEPT.numeric_cast::<usize>());
+        }
+        churn.join().unwrap();
+        assert_eq!(store.lease_ids().len(), KEPT.numeric_cast::<usize>());
+
+        Ok(())
+    }
+
+    fn init_store(db: Arc<DB>) -> LeaseStore {
+        init_store_with(db, LeaseCollection::new(0))
+    }
+
+    fn init_store_with(db: Arc<DB>, lease_collection: LeaseCollection) -> LeaseStore {
+        let lease_collection = Arc::new(lease_collection);
         let (kv_update_tx, _) = mpsc::channel(1);
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
         let index = Arc::new(Index::new());
-        LeaseStore::new(lease_collection, header_gen, db, index, kv_update_tx, true)
+        LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            index,
+            kv_update_tx,
+            true,
+            true,
+        )
     }
 
     async fn exe_and_sync_req(
@@ -517,7 +2157,22 @@ mod test {
     ) -> Result<ResponseWrapper, ExecuteError> {
         let cmd_res = ls.execute(req)?;
         let (_ignore, ops) = ls.after_sync(req, revision).await?;
+        let deletions = pending_deletions(&ops);
         _ = ls.db.flush_ops(ops)?;
+        ls.write_key_deletions(&ApplyFence::new(Arc::clone(&ls.db)), revision, &deletions)
+            .await?;
         Ok(cmd_res.into_inner())
     }
+
+    fn pending_deletions(ops: &[WriteOp]) -> Vec<Arc<LeaseKeyDeletion>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::PutLeaseDeletion(ref deletion) = *op {
+                    Some(Arc::clone(deletion))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
 }
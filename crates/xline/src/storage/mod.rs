@@ -1,5 +1,9 @@
 /// Storage for alarm
 pub(crate) mod alarm_store;
+/// Errors of applying commands
+pub(crate) mod apply_error;
+/// The fence stopping the applies after a storage failure
+pub(crate) mod apply_fence;
 /// Storage for Auth
 pub(crate) mod auth_store;
 /// Compact module
@@ -12,12 +16,23 @@ pub(crate) mod index;
 pub(crate) mod kv_store;
 /// KV watcher module
 pub(crate) mod kvwatcher;
+/// Layout versioning of the data dir
+pub(crate) mod layout;
 /// Storage for lease
 pub(crate) mod lease_store;
 /// Revision module
 pub(crate) mod revision;
+/// Transformation of the kv values at rest
+pub mod value_transformer;
+/// Audit of the watch deliveries against the applied revisions
+pub mod watch_audit;
 
 pub use self::revision::Revision;
 pub(crate) use self::{
-    alarm_store::AlarmStore, auth_store::AuthStore, kv_store::KvStore, lease_store::LeaseStore,
+    alarm_store::AlarmStore,
+    apply_error::ApplyError,
+    apply_fence::ApplyFence,
+    auth_store::{AuthHook, AuthStore},
+    kv_store::KvStore,
+    lease_store::LeaseStore,
 };
@@ -0,0 +1,407 @@
+//! Transformation of the kv values at rest.
+//!
+//! A [`ValueTransformer`] encodes the value of a kv pair before it's written to the
+//! engine and decodes it after it's read, the keys, the revisions and the other tables
+//! are stored as they are. [`AesGcm`] encrypts the values with AES-256-GCM, each value
+//! is prefixed with a header of the version of its key and its nonce:
+//!
+//! | magic `XENC` | key version, u32 BE | nonce, 12 bytes | ciphertext and tag |
+//!
+//! so a key is rotated by adding a version, the new values are written with the latest
+//! one while the values of all configured versions stay readable. The header is
+//! authenticated with the context of the value, the key and the revision of its kv pair,
+//! so a value moved to another kv pair is not decoded.
+
+use std::{collections::BTreeMap, fmt::Debug, fs, path::PathBuf, sync::Arc};
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use utils::config::EncryptionConfig;
+use xlineapi::execute_error::ExecuteError;
+
+/// Magic of the header of an encrypted value
+const MAGIC: &[u8; 4] = b"XENC";
+/// Length of the magic and the key version, which are authenticated with the value and
+/// its context
+const AAD_LEN: usize = 8;
+/// Length of the header of an encrypted value
+const HEADER_LEN: usize = AAD_LEN + NONCE_LEN;
+/// Length of the keys
+const KEY_LEN: usize = 32;
+
+/// Transformation of the kv values at rest
+pub trait ValueTransformer: Debug + Send + Sync {
+    /// Encode a value before it's written to the engine, the value is bound to the
+    /// `context` it's stored in
+    ///
+    /// # Errors
+    ///
+    /// Return error if the value cannot be encoded
+    fn encode(&self, value: &[u8], context: &[u8]) -> Result<Vec<u8>, ExecuteError>;
+
+    /// Decode a value read from the engine, the `context` must be the one it's encoded
+    /// with
+    ///
+    /// # Errors
+    ///
+    /// Return error if the value cannot be decoded, such as with a missing key or
+    /// another context
+    fn decode(&self, value: &[u8], context: &[u8]) -> Result<Vec<u8>, ExecuteError>;
+}
+
+/// Source of the versioned keys
+pub trait KeyProvider {
+    /// Fetch the keys by their versions
+    ///
+    /// # Errors
+    ///
+    /// Return error if the keys cannot be fetched
+    fn keys(&self) -> Result<BTreeMap<u32, Vec<u8>>, ExecuteError>;
+}
+
+/// Keys read from a file, one `<version> <hex of the key>` per line, the empty lines
+/// and the lines starting with `#` are skipped
+#[derive(Debug, Clone)]
+pub struct KeyFile {
+    /// Path of the file
+    path: PathBuf,
+}
+
+impl KeyFile {
+    /// New `KeyFile`
+    #[inline]
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl KeyProvider for KeyFile {
+    #[inline]
+    fn keys(&self) -> Result<BTreeMap<u32, Vec<u8>>, ExecuteError> {
+        let content = fs::read_to_string(&self.path).map_err(|e| {
+            ExecuteError::DbError(format!(
+                "failed to read the key file {}: {e}",
+                self.path.display()
+            ))
+        })?;
+        let mut keys = BTreeMap::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                ExecuteError::DbError(format!(
+                    "invalid line of the key file {}, expect `<version> <hex key>`",
+                    self.path.display()
+                ))
+            };
+            let (version, hex) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let version: u32 = version.parse().map_err(|_e| invalid())?;
+            let key = decode_hex(hex.trim()).ok_or_else(invalid)?;
+            if keys.insert(version, key).is_some() {
+                return Err(ExecuteError::DbError(format!(
+                    "key version {version} is duplicated in the key file {}",
+                    self.path.display()
+                )));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// Keys fetched from a KMS, fetching is not supported yet
+#[derive(Debug, Clone)]
+pub struct Kms {
+    /// Url of the KMS
+    url: String,
+}
+
+impl Kms {
+    /// New `Kms`
+    #[inline]
+    #[must_use]
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+}
+
+impl KeyProvider for Kms {
+    #[inline]
+    fn keys(&self) -> Result<BTreeMap<u32, Vec<u8>>, ExecuteError> {
+        Err(ExecuteError::DbError(format!(
+            "fetching the keys from the KMS {} is not supported yet",
+            self.url
+        )))
+    }
+}
+
+/// Decode a hex string, returns `None` if it's not valid hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+/// AES-256-GCM encryption of the values with versioned keys
+pub struct AesGcm {
+    /// The keys by their versions
+    keys: BTreeMap<u32, LessSafeKey>,
+    /// Version of the key the values are encrypted with
+    current: u32,
+    /// Source of the nonces
+    rng: SystemRandom,
+}
+
+impl Debug for AesGcm {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AesGcm")
+            .field("versions", &self.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.current)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AesGcm {
+    /// New `AesGcm`, the values are encrypted with the key of the latest version
+    ///
+    /// # Errors
+    ///
+    /// Return error if there is no key or a key is not of 32 bytes
+    #[inline]
+    pub fn new(keys: BTreeMap<u32, Vec<u8>>) -> Result<Self, ExecuteError> {
+        let Some(&current) = keys.keys().next_back() else {
+            return Err(ExecuteError::DbError(
+                "no key is configured for the value encryption".to_owned(),
+            ));
+        };
+        let keys = keys
+            .into_iter()
+            .map(|(version, key)| {
+                if key.len() != KEY_LEN {
+                    return Err(ExecuteError::DbError(format!(
+                        "key version {version} is of {} bytes, expect {KEY_LEN}",
+                        key.len()
+                    )));
+                }
+                UnboundKey::new(&AES_256_GCM, &key)
+                    .map(|key| (version, LessSafeKey::new(key)))
+                    .map_err(|e| {
+                        ExecuteError::DbError(format!("invalid key version {version}: {e}"))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            keys,
+            current,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Version of the key the values are encrypted with
+    #[inline]
+    #[must_use]
+    pub fn current_version(&self) -> u32 {
+        self.current
+    }
+
+    /// Version of the key an encrypted value is encrypted with
+    ///
+    /// # Errors
+    ///
+    /// Return error if the value is not encrypted
+    #[inline]
+    pub fn version_of(value: &[u8]) -> Result<u32, ExecuteError> {
+        let header = value
+            .get(..AAD_LEN)
+            .filter(|header| header.starts_with(MAGIC))
+            .ok_or_else(|| ExecuteError::DbError("the value is not encrypted".to_owned()))?;
+        let version = header
+            .get(MAGIC.len()..)
+            .and_then(|version| version.try_into().ok())
+            .unwrap_or_else(|| unreachable!("the header is of {AAD_LEN} bytes"));
+        Ok(u32::from_be_bytes(version))
+    }
+}
+
+impl ValueTransformer for AesGcm {
+    #[inline]
+    fn encode(&self, value: &[u8], context: &[u8]) -> Result<Vec<u8>, ExecuteError> {
+        let Some(key) = self.keys.get(&self.current) else {
+            unreachable!("the current version is one of the keys");
+        };
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|e| ExecuteError::DbError(format!("failed to generate a nonce: {e}")))?;
+        let mut encoded = Vec::with_capacity(
+            HEADER_LEN
+                .saturating_add(value.len())
+                .saturating_add(AES_256_GCM.tag_len()),
+        );
+        encoded.extend_from_slice(MAGIC);
+        encoded.extend_from_slice(&self.current.to_be_bytes());
+        encoded.extend_from_slice(&nonce);
+        let mut sealed = value.to_vec();
+        let aad = aad(encoded.get(..AAD_LEN).unwrap_or_default(), context);
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut sealed,
+        )
+        .map_err(|e| ExecuteError::DbError(format!("failed to encrypt a value: {e}")))?;
+        encoded.extend_from_slice(&sealed);
+        Ok(encoded)
+    }
+
+    #[inline]
+    fn decode(&self, value: &[u8], context: &[u8]) -> Result<Vec<u8>, ExecuteError> {
+        let version = Self::version_of(value)?;
+        let key = self.keys.get(&version).ok_or_else(|| {
+            ExecuteError::DbError(format!(
+                "a value is encrypted with key version {version}, which is not configured"
+            ))
+        })?;
+        let (Some(header), Some(nonce), Some(sealed)) = (
+            value.get(..AAD_LEN),
+            value.get(AAD_LEN..HEADER_LEN),
+            value.get(HEADER_LEN..),
+        ) else {
+            return Err(ExecuteError::DbError(
+                "an encrypted value is truncated".to_owned(),
+            ));
+        };
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|e| ExecuteError::DbError(format!("invalid nonce of a value: {e}")))?;
+        let mut opened = sealed.to_vec();
+        let plain_len = key
+            .open_in_place(nonce, Aad::from(aad(header, context)), &mut opened)
+            .map_err(|e| ExecuteError::DbError(format!("failed to decrypt a value: {e}")))?
+            .len();
+        opened.truncate(plain_len);
+        Ok(opened)
+    }
+}
+
+/// The additional authenticated data of a value, its header followed by its context
+fn aad(header: &[u8], context: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len().saturating_add(context.len()));
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(context);
+    aad
+}
+
+/// Build the transformer of the config, returns `None` if the encryption is off
+///
+/// # Errors
+///
+/// Return error if the keys cannot be fetched or are invalid
+#[inline]
+pub fn from_config(
+    config: &EncryptionConfig,
+) -> Result<Option<Arc<dyn ValueTransformer>>, ExecuteError> {
+    let keys = match (config.key_file(), config.kms_url()) {
+        (&Some(ref path), _) => KeyFile::new(path.clone()).keys()?,
+        (&None, &Some(ref url)) => Kms::new(url.clone()).keys()?,
+        (&None, &None) => return Ok(None),
+    };
+    Ok(Some(Arc::new(AesGcm::new(keys)?)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use super::*;
+
+    fn key(byte: u8) -> Vec<u8> {
+        vec![byte; KEY_LEN]
+    }
+
+    #[test]
+    fn values_should_round_trip() {
+        let aes = AesGcm::new(BTreeMap::from([(1, key(1))])).unwrap();
+        for value in [&b""[..], b"v", &[0xff; 4096]] {
+            let encoded = aes.encode(value, b"ctx").unwrap();
+            assert!(encoded.starts_with(MAGIC));
+            assert_ne!(encoded.get(HEADER_LEN..), Some(value));
+            assert_eq!(aes.decode(&encoded, b"ctx").unwrap(), value);
+        }
+        // the nonces are random
+        assert_ne!(
+            aes.encode(b"v", b"ctx").unwrap(),
+            aes.encode(b"v", b"ctx").unwrap()
+        );
+        let mut tampered = aes.encode(b"value", b"ctx").unwrap();
+        if let Some(last) = tampered.last_mut() {
+            *last ^= 1;
+        }
+        assert!(aes.decode(&tampered, b"ctx").is_err());
+        assert!(aes.decode(b"value", b"ctx").is_err());
+    }
+
+    #[test]
+    fn rotated_keys_should_read_all_versions() {
+        let old = AesGcm::new(BTreeMap::from([(1, key(1))])).unwrap();
+        let old_value = old.encode(b"old", b"ctx").unwrap();
+        let rotated = AesGcm::new(BTreeMap::from([(1, key(1)), (2, key(2))])).unwrap();
+        assert_eq!(rotated.current_version(), 2);
+        let new_value = rotated.encode(b"new", b"ctx").unwrap();
+        assert_eq!(AesGcm::version_of(&old_value).unwrap(), 1);
+        assert_eq!(AesGcm::version_of(&new_value).unwrap(), 2);
+        assert_eq!(rotated.decode(&old_value, b"ctx").unwrap(), b"old");
+        assert_eq!(rotated.decode(&new_value, b"ctx").unwrap(), b"new");
+        // the old key is missing
+        let new_only = AesGcm::new(BTreeMap::from([(2, key(2))])).unwrap();
+        assert!(new_only.decode(&old_value, b"ctx").is_err());
+        assert_eq!(new_only.decode(&new_value, b"ctx").unwrap(), b"new");
+    }
+
+    #[test]
+    fn values_should_be_bound_to_their_context() {
+        let aes = AesGcm::new(BTreeMap::from([(1, key(1))])).unwrap();
+        let encoded = aes.encode(b"v", b"ctx").unwrap();
+        assert!(aes.decode(&encoded, b"other").is_err());
+        assert!(aes.decode(&encoded, b"").is_err());
+        assert_eq!(aes.decode(&encoded, b"ctx").unwrap(), b"v");
+    }
+
+    #[test]
+    fn key_file_should_be_parsed() {
+        let path = temp_dir().join(format!("xline-keys-{}", uuid::Uuid::new_v4()));
+        let hex = "01".repeat(KEY_LEN);
+        fs::write(
+            &path,
+            format!("# comment\n\n1 {hex}\n2\t{}\n", "ab".repeat(KEY_LEN)),
+        )
+        .unwrap();
+        let keys = KeyFile::new(path.clone()).keys().unwrap();
+        assert_eq!(keys.get(&1), Some(&key(1)));
+        assert_eq!(keys.get(&2), Some(&key(0xab)));
+        let config = EncryptionConfig::new(Some(path.clone()), None);
+        assert!(from_config(&config).unwrap().is_some());
+
+        fs::write(&path, format!("1 {hex}\n1 {hex}\n")).unwrap();
+        assert!(KeyFile::new(path.clone()).keys().is_err());
+        fs::write(&path, "1 xyz\n").unwrap();
+        assert!(KeyFile::new(path.clone()).keys().is_err());
+        fs::write(&path, "1 0102\n").unwrap();
+        assert!(from_config(&config).is_err());
+        fs::remove_file(path).unwrap();
+
+        assert!(from_config(&EncryptionConfig::default()).unwrap().is_none());
+        let kms = EncryptionConfig::new(None, Some("http://kms".to_owned()));
+        assert!(from_config(&kms).is_err());
+    }
+}
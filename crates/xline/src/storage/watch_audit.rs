@@ -0,0 +1,509 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use clippy_utilities::OverflowArithmetic;
+use itertools::Itertools;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use tracing::{debug, warn};
+use xlineapi::command::KeyRange;
+
+use super::kvwatcher::{InternalEvent, WatchId};
+use crate::{metrics, rpc::Event};
+
+/// Number of the latest applied revisions the deliveries are audited against
+const AUDIT_WINDOW: usize = 4096;
+
+/// Max number of the deliveries of a watcher ahead of the recorded revisions, the
+/// watcher is no longer audited beyond it
+const MAX_UNCONFIRMED_DELIVERIES: usize = 256;
+
+/// Number of the violations found by all auditors of the process
+static VIOLATIONS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Number of the watch delivery violations found by all auditors of the process,
+/// for the tests running the whole cluster in one process
+#[inline]
+#[must_use]
+pub fn violations_total() -> u64 {
+    VIOLATIONS_TOTAL.load(Ordering::Relaxed)
+}
+
+/// A change of a key, either applied or delivered to a watcher
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Mutation {
+    /// The revision of the change
+    revision: i64,
+    /// The changed key
+    key: Vec<u8>,
+    /// Put or delete
+    event_type: i32,
+}
+
+impl Mutation {
+    /// The mutations of the events delivered to a watcher, in delivery order
+    pub(crate) fn from_events(events: &[Event]) -> Vec<Self> {
+        events
+            .iter()
+            .filter_map(|event| {
+                event.kv.as_ref().map(|kv| Self {
+                    revision: kv.mod_revision,
+                    key: kv.key.clone(),
+                    event_type: event.r#type,
+                })
+            })
+            .collect()
+    }
+}
+
+impl From<&InternalEvent> for Mutation {
+    fn from(event: &InternalEvent) -> Self {
+        Self {
+            revision: event.revision,
+            key: event.key.to_vec(),
+            event_type: event.event_type.into(),
+        }
+    }
+}
+
+/// The way a delivery violates the applied revisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Violation {
+    /// An applied mutation in the range is skipped
+    Gap,
+    /// A mutation is delivered more than once
+    Duplicate,
+    /// The revisions of a delivery go backwards
+    OutOfOrder,
+    /// A delivered mutation is not applied in the range
+    Unexpected,
+}
+
+impl Violation {
+    /// The name of the violation in logs and metrics
+    fn as_str(self) -> &'static str {
+        match self {
+            Violation::Gap => "gap",
+            Violation::Duplicate => "duplicate",
+            Violation::OutOfOrder => "out_of_order",
+            Violation::Unexpected => "unexpected",
+        }
+    }
+}
+
+/// The audit state of a sampled watcher
+#[derive(Debug)]
+struct AuditedWatcher {
+    /// Key range of the watcher
+    key_range: KeyRange,
+    /// Event filters of the watcher
+    filters: Vec<i32>,
+    /// The revision up to which the deliveries are audited, `None` if the watcher is
+    /// registered before any revision is recorded
+    audited_up_to: Option<i64>,
+    /// The deliveries ahead of the recorded revisions, audited once recorded
+    unconfirmed: VecDeque<Vec<Mutation>>,
+}
+
+impl AuditedWatcher {
+    /// Whether the watcher expects an applied mutation
+    fn expects(&self, mutation: &Mutation) -> bool {
+        self.key_range.contains(&mutation.key)
+            && self
+                .filters
+                .iter()
+                .all(|filter| filter != &mutation.event_type)
+    }
+}
+
+/// The result of auditing a delivery
+#[derive(Debug)]
+enum Audit {
+    /// The delivery is audited, along with the violations found
+    Done(Vec<(Violation, Mutation)>, Vec<Mutation>),
+    /// The delivery starts before the recorded revisions and can't be audited
+    Unauditable,
+}
+
+/// The applied revisions and the audited watchers
+#[derive(Debug, Default)]
+struct AuditState {
+    /// The mutations of the latest applied revisions, in revision order
+    applied: VecDeque<(i64, Vec<Mutation>)>,
+    /// The first and the last recorded revision, every revision in between is
+    /// recorded if it changes any key
+    recorded: Option<(i64, i64)>,
+    /// The sampled watchers
+    watchers: HashMap<WatchId, AuditedWatcher>,
+}
+
+impl AuditState {
+    /// Audit a delivery of a watcher, every delivered revision must be recorded
+    fn audit(&self, watcher: &mut AuditedWatcher, delivered: Vec<Mutation>) -> Audit {
+        let Some((first, _)) = self.recorded else {
+            unreachable!("the deliveries are audited after the revisions are recorded")
+        };
+        let up_to = watcher
+            .audited_up_to
+            .unwrap_or_else(|| first.overflow_sub(1));
+        if up_to < first.overflow_sub(1) {
+            return Audit::Unauditable;
+        }
+        let last = delivered
+            .iter()
+            .map(|mutation| mutation.revision)
+            .max()
+            .unwrap_or(up_to)
+            .max(up_to);
+        let mut violations = Vec::new();
+        if let Some((_, behind)) = delivered
+            .iter()
+            .tuple_windows()
+            .find(|&(prev, next)| next.revision < prev.revision)
+        {
+            violations.push((Violation::OutOfOrder, behind.clone()));
+        }
+        let (stale, mut fresh): (Vec<_>, Vec<_>) = delivered
+            .iter()
+            .cloned()
+            .partition(|mutation| mutation.revision <= up_to);
+        violations.extend(stale.into_iter().map(|m| (Violation::Duplicate, m)));
+        let start = self
+            .applied
+            .partition_point(|&(revision, _)| revision <= up_to);
+        let mut expected = self
+            .applied
+            .range(start..)
+            .take_while(|&&(revision, _)| revision <= last)
+            .flat_map(|&(_, ref mutations)| mutations)
+            .filter(|mutation| watcher.expects(mutation))
+            .cloned()
+            .collect_vec();
+        expected.sort_unstable();
+        fresh.sort_unstable();
+        violations.extend(
+            difference(&expected, &fresh)
+                .into_iter()
+                .map(|m| (Violation::Gap, m)),
+        );
+        violations.extend(difference(&fresh, &expected).into_iter().map(|m| {
+            if expected.binary_search(&m).is_ok() {
+                (Violation::Duplicate, m)
+            } else {
+                (Violation::Unexpected, m)
+            }
+        }));
+        watcher.audited_up_to = Some(last);
+        Audit::Done(violations, expected)
+    }
+}
+
+/// Audits the events delivered to the sampled watchers against the applied revisions,
+/// every watcher must get exactly the mutations applied in its range since its start
+/// revision, in revision order
+pub(crate) struct WatchAuditor {
+    /// Percentage of the watchers audited, 0 disables the audit
+    sample_percent: u8,
+    /// Hasher sampling the watchers
+    hasher: RandomState,
+    /// The applied revisions and the audited watchers
+    state: Mutex<AuditState>,
+    /// Number of the violations found
+    violations: AtomicU64,
+}
+
+impl WatchAuditor {
+    /// New `Arc<WatchAuditor>` auditing a percentage of the watchers
+    pub(crate) fn new(sample_percent: u8) -> Arc<Self> {
+        Arc::new(Self {
+            sample_percent,
+            hasher: RandomState::new(),
+            state: Mutex::new(AuditState::default()),
+            violations: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether any watcher is audited
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.sample_percent > 0
+    }
+
+    /// Number of the violations found
+    pub(crate) fn violations(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    /// Record the mutations of an applied revision, must be called in revision order
+    /// before the revision is dispatched to the watchers
+    pub(crate) fn record_applied(&self, revision: i64, events: &[Arc<InternalEvent>]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.applied.push_back((
+            revision,
+            events.iter().map(|event| event.as_ref().into()).collect(),
+        ));
+        let recorded = state.recorded;
+        let first = match recorded {
+            Some((first, _)) if state.applied.len() > AUDIT_WINDOW => state
+                .applied
+                .pop_front()
+                .map_or(first, |(evicted, _)| evicted.overflow_add(1)),
+            Some((first, _)) => first,
+            None => revision,
+        };
+        state.recorded = Some((first, revision));
+        let mut watchers = std::mem::take(&mut state.watchers);
+        watchers.retain(|&watch_id, watcher| {
+            while watcher
+                .unconfirmed
+                .front()
+                .is_some_and(|delivered| last_revision(delivered) <= revision)
+            {
+                let Some(delivered) = watcher.unconfirmed.pop_front() else {
+                    unreachable!("the front delivery should exist")
+                };
+                if !self.audit(&state, watch_id, watcher, delivered) {
+                    return false;
+                }
+            }
+            true
+        });
+        state.watchers = watchers;
+    }
+
+    /// Sample a new watcher, must be called before its history is sent, returns
+    /// whether its deliveries are audited
+    pub(crate) fn register(
+        &self,
+        watch_id: WatchId,
+        key_range: &KeyRange,
+        start_rev: i64,
+        filters: &[i32],
+    ) -> bool {
+        if !self.is_enabled()
+            || self.hasher.hash_one(watch_id).overflow_rem(100) >= u64::from(self.sample_percent)
+        {
+            return false;
+        }
+        let mut state = self.state.lock();
+        // a watcher starting from 0 gets the revisions dispatched after it registers
+        let audited_up_to = if start_rev == 0 {
+            state.recorded.map(|(_, last)| last)
+        } else {
+            Some(start_rev.overflow_sub(1))
+        };
+        let _ignore = state.watchers.insert(
+            watch_id,
+            AuditedWatcher {
+                key_range: key_range.clone(),
+                filters: filters.to_vec(),
+                audited_up_to,
+                unconfirmed: VecDeque::new(),
+            },
+        );
+        true
+    }
+
+    /// Audit the mutations delivered to a watcher
+    pub(crate) fn delivered(&self, watch_id: WatchId, delivered: Vec<Mutation>) {
+        if delivered.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock();
+        let recorded_up_to = state.recorded.map(|(_, last)| last);
+        let Some(mut watcher) = state.watchers.remove(&watch_id) else {
+            return;
+        };
+        if !watcher.unconfirmed.is_empty()
+            || recorded_up_to.map_or(true, |last| last < last_revision(&delivered))
+        {
+            // the history may contain the revisions applied but not dispatched yet
+            if watcher.unconfirmed.len() >= MAX_UNCONFIRMED_DELIVERIES {
+                debug!(watch_id, "too many unconfirmed deliveries, stop auditing");
+                return;
+            }
+            watcher.unconfirmed.push_back(delivered);
+        } else if !self.audit(&state, watch_id, &mut watcher, delivered) {
+            return;
+        }
+        let _ignore = state.watchers.insert(watch_id, watcher);
+    }
+
+    /// Stop auditing a canceled watcher
+    pub(crate) fn remove(&self, watch_id: WatchId) {
+        if self.is_enabled() {
+            let _ignore = self.state.lock().watchers.remove(&watch_id);
+        }
+    }
+
+    /// Audit a delivery and report the violations, returns whether the watcher is
+    /// still audited
+    fn audit(
+        &self,
+        state: &AuditState,
+        watch_id: WatchId,
+        watcher: &mut AuditedWatcher,
+        delivered: Vec<Mutation>,
+    ) -> bool {
+        let (violations, expected) = match state.audit(watcher, delivered.clone()) {
+            Audit::Done(violations, expected) => (violations, expected),
+            Audit::Unauditable => {
+                debug!(
+                    watch_id,
+                    "delivery starts before the recorded revisions, stop auditing"
+                );
+                return false;
+            }
+        };
+        for (violation, mutation) in violations {
+            warn!(
+                watch_id,
+                key_range = ?watcher.key_range,
+                violation = violation.as_str(),
+                ?mutation,
+                ?expected,
+                ?delivered,
+                "watch delivery violates the applied revisions"
+            );
+            let _ignore = self.violations.fetch_add(1, Ordering::Relaxed);
+            let _ignore = VIOLATIONS_TOTAL.fetch_add(1, Ordering::Relaxed);
+            metrics::get()
+                .watch_audit_violations_total
+                .add(1, &[KeyValue::new("violation", violation.as_str())]);
+        }
+        true
+    }
+}
+
+impl std::fmt::Debug for WatchAuditor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchAuditor")
+            .field("sample_percent", &self.sample_percent)
+            .field("violations", &self.violations())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The last revision of a delivery
+fn last_revision(delivered: &[Mutation]) -> i64 {
+    delivered
+        .iter()
+        .map(|mutation| mutation.revision)
+        .max()
+        .unwrap_or_default()
+}
+
+/// The mutations of a sorted slice missing from another sorted slice, the repeated
+/// mutations are counted
+fn difference(lhs: &[Mutation], rhs: &[Mutation]) -> Vec<Mutation> {
+    let mut rhs = rhs.iter().peekable();
+    lhs.iter()
+        .filter(|mutation| {
+            while rhs.next_if(|other| other < mutation).is_some() {}
+            rhs.next_if_eq(mutation).is_none()
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::rpc::{EventType, KeyValue as PbKeyValue};
+
+    fn put(key: &str, revision: i64) -> Arc<InternalEvent> {
+        Arc::new(InternalEvent {
+            event_type: EventType::Put,
+            key: Bytes::copy_from_slice(key.as_bytes()),
+            value: Bytes::new(),
+            revision,
+            create_revision: revision,
+            version: 1,
+            lease: 0,
+        })
+    }
+
+    fn delivery(events: &[Arc<InternalEvent>]) -> Vec<Mutation> {
+        let events = events
+            .iter()
+            .map(|event| Event {
+                kv: Some(PbKeyValue {
+                    key: event.key.to_vec(),
+                    mod_revision: event.revision,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect_vec();
+        Mutation::from_events(&events)
+    }
+
+    fn auditor_with_watcher(start_rev: i64) -> Arc<WatchAuditor> {
+        let auditor = WatchAuditor::new(100);
+        assert!(auditor.register(1, &KeyRange::new("a", ""), start_rev, &[]));
+        auditor
+    }
+
+    #[test]
+    fn exact_deliveries_should_pass_the_audit() {
+        let auditor = auditor_with_watcher(0);
+        auditor.record_applied(2, &[put("a", 2)]);
+        auditor.record_applied(3, &[put("b", 3)]);
+        auditor.delivered(1, delivery(&[put("a", 2)]));
+        // a history delivery ahead of the recorded revisions is audited once recorded
+        auditor.delivered(1, delivery(&[put("a", 4), put("a", 5)]));
+        auditor.record_applied(4, &[put("a", 4)]);
+        auditor.record_applied(5, &[put("a", 5)]);
+        assert_eq!(auditor.violations(), 0);
+        assert!(auditor
+            .state
+            .lock()
+            .watchers
+            .get(&1)
+            .unwrap()
+            .unconfirmed
+            .is_empty());
+    }
+
+    #[test]
+    fn gaps_duplicates_and_reordering_should_be_reported() {
+        let auditor = auditor_with_watcher(2);
+        for revision in 2..=6 {
+            auditor.record_applied(revision, &[put("a", revision)]);
+        }
+        // revision 2 is skipped
+        auditor.delivered(1, delivery(&[put("a", 3)]));
+        assert_eq!(auditor.violations(), 1);
+        // revision 3 is delivered twice
+        auditor.delivered(1, delivery(&[put("a", 3), put("a", 4)]));
+        assert_eq!(auditor.violations(), 2);
+        // revisions 5 and 6 go backwards
+        auditor.delivered(1, delivery(&[put("a", 6), put("a", 5)]));
+        assert_eq!(auditor.violations(), 3);
+        // a key out of the range
+        auditor.record_applied(7, &[put("b", 7)]);
+        auditor.delivered(1, delivery(&[put("b", 7)]));
+        assert_eq!(auditor.violations(), 4);
+    }
+
+    #[test]
+    fn watchers_out_of_the_window_should_not_be_audited() {
+        let auditor = WatchAuditor::new(100);
+        auditor.record_applied(10, &[put("a", 10)]);
+        assert!(auditor.register(1, &KeyRange::new("a", ""), 5, &[]));
+        auditor.delivered(1, delivery(&[put("a", 10)]));
+        assert_eq!(auditor.violations(), 0);
+        assert!(auditor.state.lock().watchers.is_empty());
+        assert!(!WatchAuditor::new(0).register(1, &KeyRange::new("a", ""), 0, &[]));
+    }
+}
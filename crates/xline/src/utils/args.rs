@@ -5,23 +5,39 @@ use clap::Parser;
 use tokio::fs;
 use utils::{
     config::{
-        default_batch_max_size, default_batch_timeout, default_candidate_timeout_ticks,
+        default_auth_hook_cache_ttl, default_auth_hook_timeout, default_batch_max_size,
+        default_batch_timeout, default_candidate_timeout_ticks,
         default_client_id_keep_alive_interval, default_client_wait_synced_timeout,
         default_cmd_workers, default_compact_batch_size, default_compact_sleep_interval,
         default_compact_timeout, default_follower_timeout_ticks, default_gc_interval,
-        default_heartbeat_interval, default_initial_retry_timeout, default_log_entries_cap,
-        default_log_level, default_max_retry_timeout, default_metrics_enable, default_metrics_path,
+        default_heartbeat_interval, default_initial_retry_timeout, default_journal_max_age,
+        default_journal_max_size, default_learner_promote_gap, default_lease_checkpoint_interval,
+        default_lease_default_ttl, default_lease_expiry_persist_interval,
+        default_lease_grant_burst, default_lease_grant_rate,
+        default_lease_promote_extend_multiplier, default_lease_revoke_batch_size,
+        default_lease_revoke_chunk_size, default_listener_acceptors, default_listener_backlog,
+        default_log_entries_cap, default_log_level, default_max_inflight_proposals,
+        default_max_keys_per_lease, default_max_keys_per_request, default_max_leases_per_client,
+        default_max_retry_timeout, default_metrics_enable, default_metrics_path,
         default_metrics_port, default_metrics_push_endpoint, default_metrics_push_protocol,
-        default_propose_timeout, default_quota, default_range_retry_timeout, default_retry_count,
-        default_rotation, default_rpc_timeout, default_server_wait_synced_timeout,
-        default_sync_victims_interval, default_watch_progress_notify_interval, AuthConfig,
-        AutoCompactConfig, ClientConfig, ClusterConfig, CompactConfig, CurpConfigBuilder,
-        EngineConfig, InitialClusterState, LevelConfig, LogConfig, MetricsConfig,
-        MetricsPushProtocol, RotationConfig, ServerTimeout, StorageConfig, TlsConfig, TraceConfig,
-        XlineServerConfig,
+        default_peer_warmup_timeout, default_propose_batch_max_delay,
+        default_propose_batch_max_size, default_propose_timeout, default_quota,
+        default_range_retry_timeout, default_retry_count, default_rotation, default_rpc_timeout,
+        default_server_wait_synced_timeout, default_slow_apply_threshold,
+        default_snapshot_max_concurrent_transfers, default_snapshot_read_rate_limit,
+        default_snapshot_send_rate_limit, default_state_hash_interval,
+        default_sync_victims_interval, default_tcp_keepalive, default_tcp_nodelay,
+        default_wal_max_segment_size, default_wal_sync_bytes, default_watch_create_burst,
+        default_watch_create_rate, default_watch_memory_budget,
+        default_watch_progress_notify_interval, AuthConfig, AuthHookConfig, AutoCompactConfig,
+        ClientConfig, ClusterConfig, ClusterConfigBuilder, CompactConfig, CurpConfigBuilder,
+        EncryptionConfig, EngineConfig, InitialClusterState, JournalConfig, LevelConfig,
+        ListenerConfig, LogConfig, MetricsConfig, MetricsPushProtocol, RotationConfig,
+        ServerLimitsBuilder, ServerTimeout, ServerTimeoutBuilder, StorageConfig, TlsConfig,
+        TraceConfig, XlineServerConfig,
     },
     parse_batch_bytes, parse_duration, parse_log_file, parse_log_level, parse_members,
-    parse_metrics_push_protocol, parse_rotation, parse_state, ConfigFileError,
+    parse_metrics_push_protocol, parse_rotation, parse_state, parse_url, ConfigFileError,
 };
 
 /// Xline server config path env name
@@ -38,16 +54,16 @@ pub struct ServerArgs {
     #[clap(long)]
     name: String,
     /// Node peer listen urls
-    #[clap(long, required = true, num_args = 1.., value_delimiter = ',')]
+    #[clap(long, required = true, num_args = 1.., value_delimiter = ',', value_parser = parse_url)]
     peer_listen_urls: Vec<String>,
     /// Node peer advertise urls
-    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    #[clap(long, num_args = 1.., value_delimiter = ',', value_parser = parse_url)]
     peer_advertise_urls: Vec<String>,
     /// Node client listen urls
-    #[clap(long, required = true, num_args = 1.., value_delimiter = ',')]
+    #[clap(long, required = true, num_args = 1.., value_delimiter = ',', value_parser = parse_url)]
     client_listen_urls: Vec<String>,
     /// Node client advertise urls
-    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    #[clap(long, num_args = 1.., value_delimiter = ',', value_parser = parse_url)]
     client_advertise_urls: Vec<String>,
     /// Cluster peers. eg: node1=192.168.x.x:8080,192.168.x.x:8081,node2=192.168.x.x:8083
     #[clap(long, value_parser = parse_members)]
@@ -61,6 +77,18 @@ pub struct ServerArgs {
     /// Public key used to verify the token
     #[clap(long)]
     auth_public_key: Option<PathBuf>,
+    /// Url of an external webhook validating tokens instead of the local users
+    #[clap(long)]
+    auth_hook_url: Option<String>,
+    /// How long the identity returned by the auth hook is cached [default: 60s]
+    #[clap(long, value_parser = parse_duration)]
+    auth_hook_cache_ttl: Option<Duration>,
+    /// Timeout of a call to the auth hook [default: 1s]
+    #[clap(long, value_parser = parse_duration)]
+    auth_hook_timeout: Option<Duration>,
+    /// Fall back to local validation when the auth hook fails instead of rejecting the request
+    #[clap(long)]
+    auth_hook_fail_open: bool,
     /// Open jaeger offline
     #[clap(long)]
     jaeger_offline: bool,
@@ -73,6 +101,9 @@ pub struct ServerArgs {
     /// Trace level of jaeger
     #[clap(long, value_parser = parse_log_level, default_value_t = default_log_level())]
     jaeger_level: LevelConfig,
+    /// The OTLP collector endpoint to export spans to
+    #[clap(long)]
+    otlp_endpoint: Option<String>,
     /// Whether to enable metrics
     #[clap(long, default_value_t = default_metrics_enable())]
     metrics_enable: bool,
@@ -130,6 +161,39 @@ pub struct ServerArgs {
     /// Number of log entries to keep in memory
     #[clap(long, default_value_t = default_log_entries_cap())]
     log_entries_cap: usize,
+    /// Max gap of log entries between the leader and a learner to be promoted
+    #[clap(long, default_value_t = default_learner_promote_gap())]
+    learner_promote_gap: u64,
+    /// How long a starting node waits for a quorum of peers before campaigning [default: 3s]
+    #[clap(long, value_parser = parse_duration)]
+    peer_warmup_timeout: Option<Duration>,
+    /// Max number of commands the leader coalesces into one log entry, 1 disables batching
+    #[clap(long, default_value_t = default_propose_batch_max_size())]
+    propose_batch_max_size: usize,
+    /// Max delay of a command waiting to be batched [default: 1ms]
+    #[clap(long, value_parser = parse_duration)]
+    propose_batch_max_delay: Option<Duration>,
+    /// Log a warning for a command applied later than this, 0 disables it [default: 100ms]
+    #[clap(long, value_parser = parse_duration)]
+    slow_apply_threshold: Option<Duration>,
+    /// Interval of reporting the state hashes to the leader, 0 disables it [default: 10s]
+    #[clap(long, value_parser = parse_duration)]
+    state_hash_interval: Option<Duration>,
+    /// Max bytes per second of reading a snapshot to send it, 0 means unlimited
+    #[clap(long, default_value_t = default_snapshot_read_rate_limit())]
+    snapshot_read_rate_limit: u64,
+    /// Max bytes per second of sending snapshots to followers, 0 means unlimited
+    #[clap(long, default_value_t = default_snapshot_send_rate_limit())]
+    snapshot_send_rate_limit: u64,
+    /// Max number of snapshots sent at the same time
+    #[clap(long, default_value_t = default_snapshot_max_concurrent_transfers())]
+    snapshot_max_concurrent_transfers: usize,
+    /// Size in bytes of a segment of the curp WAL
+    #[clap(long, default_value_t = default_wal_max_segment_size())]
+    wal_max_segment_size: u64,
+    /// Bytes appended to the curp WAL between two fsyncs, 0 fsyncs every append
+    #[clap(long, default_value_t = default_wal_sync_bytes())]
+    wal_sync_bytes: u64,
     /// Curp client wait synced timeout [default: 2s]
     #[clap(long, value_parser = parse_duration)]
     client_wait_synced_timeout: Option<Duration>,
@@ -163,6 +227,45 @@ pub struct ServerArgs {
     /// How often should watch progress notify send a response [default: 600s]
     #[clap(long, value_parser = parse_duration)]
     watch_progress_notify_interval: Option<Duration>,
+    /// How often should the leader checkpoint remaining lease ttl [default: 300s]
+    #[clap(long, value_parser = parse_duration)]
+    lease_checkpoint_interval: Option<Duration>,
+    /// Persist the checkpointed remaining lease ttl to the backend
+    #[clap(long)]
+    lease_checkpoint_persist: bool,
+    /// How often should the leader persist the remaining lease ttls to a local table, 0 means disabled [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    lease_expiry_persist_interval: Option<Duration>,
+    /// Ttl of leases granted without a positive ttl, 0 means the min lease ttl [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    lease_default_ttl: Option<Duration>,
+    /// Number of election timeouts by which leases are extended on promotion
+    #[clap(long, default_value_t = default_lease_promote_extend_multiplier())]
+    lease_promote_extend_multiplier: u32,
+    /// Max number of keys deleted by a single apply of a lease revocation, 0 means unlimited
+    #[clap(long, default_value_t = default_lease_revoke_chunk_size())]
+    lease_revoke_chunk_size: usize,
+    /// Max number of expired leases revoked in one pass, 0 means unlimited
+    #[clap(long, default_value_t = default_lease_revoke_batch_size())]
+    lease_revoke_batch_size: usize,
+    /// Max number of keys attached to a lease, 0 means unlimited
+    #[clap(long, default_value_t = default_max_keys_per_lease())]
+    max_keys_per_lease: usize,
+    /// Max rate of watch creations per second of a client, 0 means unlimited
+    #[clap(long, default_value_t = default_watch_create_rate())]
+    watch_create_rate: u32,
+    /// Max number of watch creations a client could make in a burst
+    #[clap(long, default_value_t = default_watch_create_burst())]
+    watch_create_burst: u32,
+    /// Max rate of lease grants per second of a client, 0 means unlimited
+    #[clap(long, default_value_t = default_lease_grant_rate())]
+    lease_grant_rate: u32,
+    /// Max number of lease grants a client could make in a burst
+    #[clap(long, default_value_t = default_lease_grant_burst())]
+    lease_grant_burst: u32,
+    /// Max number of active leases granted by a client, 0 means unlimited
+    #[clap(long, default_value_t = default_max_leases_per_client())]
+    max_leases_per_client: usize,
     /// Storage engine
     #[clap(long)]
     storage_engine: String,
@@ -189,12 +292,65 @@ pub struct ServerArgs {
     /// Auto revision compact retention
     #[clap(long)]
     auto_revision_retention: Option<i64>,
+    /// Key prefixes whose history is kept by compactions, separated by commas, they take
+    /// effect on a new data dir and must be the same on all members
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    compact_exempt_prefixes: Vec<String>,
     /// Initial cluster state
     #[clap(long,value_parser = parse_state)]
     initial_cluster_state: Option<InitialClusterState>,
+    /// Serve reads only, reject mutating requests and never campaign for leadership
+    #[clap(long)]
+    read_only: bool,
+    /// Max number of mutating requests proposed at the same time, 0 means unlimited
+    #[clap(long, default_value_t = default_max_inflight_proposals())]
+    max_inflight_proposals: usize,
+    /// Max bytes of the buffered watch events, 0 means unlimited [default: 256MB]
+    #[clap(long)]
+    watch_memory_budget: Option<u64>,
+    /// Max number of keys a single delete range or txn request may affect, 0 means unlimited
+    #[clap(long, default_value_t = default_max_keys_per_request())]
+    max_keys_per_request: usize,
+    /// Percentage of the watchers whose deliveries are audited, 0 disables the audit
+    #[clap(long, default_value_t = 0)]
+    watch_audit_sample_percent: u8,
+    /// Set `SO_REUSEPORT` on the client and peer listeners
+    #[clap(long)]
+    listener_reuse_port: bool,
+    /// Number of acceptors bound to each listen address, more than 1 requires `--listener-reuse-port`
+    #[clap(long, default_value_t = default_listener_acceptors())]
+    listener_acceptors: usize,
+    /// Max number of pending connections in the accept queue of an acceptor
+    #[clap(long, default_value_t = default_listener_backlog())]
+    listener_backlog: u32,
+    /// Whether to set `TCP_NODELAY` on the accepted sockets
+    #[clap(long, default_value_t = default_tcp_nodelay())]
+    tcp_nodelay: bool,
+    /// Interval of the TCP keepalive probes of the accepted sockets, 0 means disabled [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    tcp_keepalive: Option<Duration>,
     /// Quota
     #[clap(long)]
     quota: Option<u64>,
+    /// Path of the request journal, out of the data directory, the journal is off if it's not set
+    #[clap(long)]
+    journal_path: Option<PathBuf>,
+    /// Max size of the request journal in bytes [default: 256MB]
+    #[clap(long)]
+    journal_max_size: Option<u64>,
+    /// Max age of the request journal records [default: 7d]
+    #[clap(long, value_parser = parse_duration)]
+    journal_max_age: Option<Duration>,
+    /// Redact the values of the puts and the passwords in the request journal
+    #[clap(long)]
+    journal_redact_values: bool,
+    /// File of the keys the kv values are encrypted with at rest, one `<version> <hex key>`
+    /// per line, the values are not encrypted if no key source is set
+    #[clap(long, conflicts_with = "encryption_kms_url")]
+    encryption_key_file: Option<PathBuf>,
+    /// Url of the KMS the keys the kv values are encrypted with are fetched from
+    #[clap(long)]
+    encryption_kms_url: Option<String>,
     /// Server ca certificate path, used to verify client certificate
     #[clap(long)]
     peer_ca_cert_path: Option<PathBuf>,
@@ -233,7 +389,20 @@ impl From<ServerArgs> for XlineServerConfig {
             &_ => unreachable!("xline only supports memory and rocksdb engine"),
         };
 
-        let storage = StorageConfig::new(engine, args.quota.unwrap_or_else(default_quota));
+        let journal = JournalConfig::new(
+            args.journal_path,
+            args.journal_max_size
+                .unwrap_or_else(default_journal_max_size),
+            args.journal_max_age.unwrap_or_else(default_journal_max_age),
+            args.journal_redact_values,
+        );
+        let encryption = EncryptionConfig::new(args.encryption_key_file, args.encryption_kms_url);
+        let storage = StorageConfig::new(
+            engine,
+            args.quota.unwrap_or_else(default_quota),
+            journal,
+            encryption,
+        );
         let Ok(curp_config) = CurpConfigBuilder::default()
             .heartbeat_interval(
                 args.heartbeat_interval
@@ -251,6 +420,30 @@ impl From<ServerArgs> for XlineServerConfig {
             .engine_cfg(curp_engine)
             .gc_interval(args.gc_interval.unwrap_or_else(default_gc_interval))
             .cmd_workers(args.cmd_workers)
+            .log_entries_cap(args.log_entries_cap)
+            .learner_promote_gap(args.learner_promote_gap)
+            .peer_warmup_timeout(
+                args.peer_warmup_timeout
+                    .unwrap_or_else(default_peer_warmup_timeout),
+            )
+            .propose_batch_max_size(args.propose_batch_max_size)
+            .propose_batch_max_delay(
+                args.propose_batch_max_delay
+                    .unwrap_or_else(default_propose_batch_max_delay),
+            )
+            .snapshot_read_rate_limit(args.snapshot_read_rate_limit)
+            .snapshot_send_rate_limit(args.snapshot_send_rate_limit)
+            .snapshot_max_concurrent_transfers(args.snapshot_max_concurrent_transfers)
+            .slow_apply_threshold(
+                args.slow_apply_threshold
+                    .unwrap_or_else(default_slow_apply_threshold),
+            )
+            .state_hash_interval(
+                args.state_hash_interval
+                    .unwrap_or_else(default_state_hash_interval),
+            )
+            .wal_max_segment_size(args.wal_max_segment_size)
+            .wal_sync_bytes(args.wal_sync_bytes)
             .build()
         else {
             panic!("failed to create curp config")
@@ -269,7 +462,7 @@ impl From<ServerArgs> for XlineServerConfig {
             args.client_keep_alive_interval
                 .unwrap_or_else(default_client_id_keep_alive_interval),
         );
-        let server_timeout = ServerTimeout::new(
+        let Ok(server_timeout) = ServerTimeoutBuilder::from(ServerTimeout::new(
             args.range_retry_timeout
                 .unwrap_or_else(default_range_retry_timeout),
             args.compact_timeout.unwrap_or_else(default_compact_timeout),
@@ -277,9 +470,39 @@ impl From<ServerArgs> for XlineServerConfig {
                 .unwrap_or_else(default_sync_victims_interval),
             args.watch_progress_notify_interval
                 .unwrap_or_else(default_watch_progress_notify_interval),
-        );
+        ))
+        .lease_checkpoint_interval(
+            args.lease_checkpoint_interval
+                .unwrap_or_else(default_lease_checkpoint_interval),
+        )
+        .lease_checkpoint_persist(args.lease_checkpoint_persist)
+        .lease_expiry_persist_interval(
+            args.lease_expiry_persist_interval
+                .unwrap_or_else(default_lease_expiry_persist_interval),
+        )
+        .lease_default_ttl(
+            args.lease_default_ttl
+                .unwrap_or_else(default_lease_default_ttl),
+        )
+        .lease_promote_extend_multiplier(args.lease_promote_extend_multiplier)
+        .build() else {
+            panic!("failed to create server timeout config")
+        };
+        let Ok(server_limits) = ServerLimitsBuilder::default()
+            .lease_revoke_chunk_size(args.lease_revoke_chunk_size)
+            .lease_revoke_batch_size(args.lease_revoke_batch_size)
+            .max_keys_per_lease(args.max_keys_per_lease)
+            .watch_create_rate(args.watch_create_rate)
+            .watch_create_burst(args.watch_create_burst)
+            .lease_grant_rate(args.lease_grant_rate)
+            .lease_grant_burst(args.lease_grant_burst)
+            .max_leases_per_client(args.max_leases_per_client)
+            .build()
+        else {
+            panic!("failed to create server limits config")
+        };
         let initial_cluster_state = args.initial_cluster_state.unwrap_or_default();
-        let cluster = ClusterConfig::new(
+        let Ok(cluster) = ClusterConfigBuilder::from(ClusterConfig::new(
             args.name,
             args.peer_listen_urls,
             args.peer_advertise_urls,
@@ -291,15 +514,45 @@ impl From<ServerArgs> for XlineServerConfig {
             client_config,
             server_timeout,
             initial_cluster_state,
-        );
+        ))
+        .server_limits(server_limits)
+        .read_only(args.read_only)
+        .max_inflight_proposals(args.max_inflight_proposals)
+        .watch_memory_budget(
+            args.watch_memory_budget
+                .unwrap_or_else(default_watch_memory_budget),
+        )
+        .max_keys_per_request(args.max_keys_per_request)
+        .watch_audit_sample_percent(args.watch_audit_sample_percent)
+        .listener_config(ListenerConfig::new(
+            args.listener_reuse_port,
+            args.listener_acceptors,
+            args.listener_backlog,
+            args.tcp_nodelay,
+            args.tcp_keepalive.unwrap_or_else(default_tcp_keepalive),
+        ))
+        .build() else {
+            panic!("failed to create cluster config")
+        };
         let log = LogConfig::new(args.log_file, args.log_rotate, args.log_level);
         let trace = TraceConfig::new(
             args.jaeger_online,
             args.jaeger_offline,
             args.jaeger_output_dir,
             args.jaeger_level,
+            args.otlp_endpoint,
         );
-        let auth = AuthConfig::new(args.auth_public_key, args.auth_private_key);
+        let auth_hook = args.auth_hook_url.map(|url| {
+            AuthHookConfig::new(
+                url,
+                args.auth_hook_cache_ttl
+                    .unwrap_or_else(default_auth_hook_cache_ttl),
+                args.auth_hook_timeout
+                    .unwrap_or_else(default_auth_hook_timeout),
+                args.auth_hook_fail_open,
+            )
+        });
+        let auth = AuthConfig::new(args.auth_public_key, args.auth_private_key, auth_hook);
         let auto_compactor_cfg = if let Some(mode) = args.auto_compact_mode {
             match mode.as_str() {
                 "periodic" => {
@@ -326,6 +579,7 @@ impl From<ServerArgs> for XlineServerConfig {
             args.compact_sleep_interval
                 .unwrap_or_else(default_compact_sleep_interval),
             auto_compactor_cfg,
+            args.compact_exempt_prefixes,
         );
         let tls = TlsConfig::new(
             args.peer_ca_cert_path,
@@ -0,0 +1,166 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use fs2::FileExt;
+use tracing::{info, warn};
+
+/// Name of the lock file inside the data directory
+const LOCK_FILE_NAME: &str = "xline.lock";
+
+/// Advisory lock on a data directory, held exclusively for the lifetime of the server
+///
+/// The lock file records the pid of the holder, it's cleared on a clean shutdown so that
+/// a non-empty file found at startup indicates the previous process didn't exit cleanly.
+#[derive(Debug)]
+pub(crate) struct DataDirLock {
+    /// The locked file, the OS releases the lock when it's closed
+    file: File,
+    /// Path of the lock file
+    path: PathBuf,
+}
+
+impl DataDirLock {
+    /// Lock the data directory, creating it if it doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Return error if the directory is locked by another process or the lock file
+    /// cannot be accessed
+    pub(crate) fn acquire(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e.into());
+            }
+            let holder = Self::read_holder(&mut file)
+                .ok()
+                .flatten()
+                .map_or_else(|| "unknown".to_owned(), |pid| pid.to_string());
+            return Err(anyhow!(
+                "data directory {} is already in use by another xline process (pid {holder})",
+                dir.display()
+            ));
+        }
+        match Self::read_holder(&mut file) {
+            Ok(Some(pid)) => warn!(
+                "found stale lock of pid {pid} in {}, the previous server may not have shut down cleanly, recovering",
+                dir.display()
+            ),
+            Ok(None) => {}
+            Err(e) => warn!("failed to read stale lock file {}: {e}", path.display()),
+        }
+        Self::write_holder(&mut file, Some(std::process::id()))?;
+        info!("locked data directory {}", dir.display());
+        Ok(Self { file, path })
+    }
+
+    /// Read the pid recorded in the lock file
+    fn read_holder(file: &mut File) -> io::Result<Option<u32>> {
+        let mut content = String::new();
+        let _ignore = file.seek(SeekFrom::Start(0))?;
+        let _ignore = file.read_to_string(&mut content)?;
+        Ok(content.trim().parse().ok())
+    }
+
+    /// Overwrite the pid recorded in the lock file
+    fn write_holder(file: &mut File, pid: Option<u32>) -> io::Result<()> {
+        file.set_len(0)?;
+        let _ignore = file.seek(SeekFrom::Start(0))?;
+        if let Some(pid) = pid {
+            write!(file, "{pid}")?;
+        }
+        file.sync_all()
+    }
+}
+
+impl Drop for DataDirLock {
+    #[inline]
+    fn drop(&mut self) {
+        if let Err(e) = Self::write_holder(&mut self.file, None) {
+            warn!("failed to clear lock file {}: {e}", self.path.display());
+        }
+        if let Err(e) = self.file.unlock() {
+            warn!("failed to unlock {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+
+    use prost::Message;
+    use utils::{config::EngineConfig, table_names::KV_TABLE};
+
+    use super::*;
+    use crate::{
+        rpc::KeyValue,
+        storage::{
+            db::{WriteOp, DB},
+            Revision,
+        },
+    };
+
+    #[test]
+    fn test_second_lock_on_same_dir_should_fail() {
+        let dir = temp_dir().join(format!("xline-lock-{}", uuid::Uuid::new_v4()));
+        let lock = DataDirLock::acquire(&dir).unwrap();
+        let db = DB::open(&EngineConfig::RocksDB(dir.join("db"))).unwrap();
+        let revision = Revision::new(1, 1);
+        let kv = KeyValue {
+            key: "key".into(),
+            ..Default::default()
+        };
+        let _ignore = db
+            .flush_ops(vec![WriteOp::PutKeyValue(revision, kv.clone())])
+            .unwrap();
+
+        let err = DataDirLock::acquire(&dir).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("pid {}", std::process::id())),
+            "unexpected error: {err}"
+        );
+        // the first instance is still usable
+        assert_eq!(
+            db.get_value(KV_TABLE, revision.encode_to_vec()).unwrap(),
+            Some(kv.encode_to_vec())
+        );
+
+        drop(db);
+        drop(lock);
+        let _lock = DataDirLock::acquire(&dir).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_lock_should_be_recovered() {
+        let dir = temp_dir().join(format!("xline-lock-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // an unclean shutdown leaves the pid in the file without holding the lock
+        fs::write(dir.join(LOCK_FILE_NAME), "4194305").unwrap();
+
+        let lock = DataDirLock::acquire(&dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(LOCK_FILE_NAME)).unwrap(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+        assert!(fs::read_to_string(dir.join(LOCK_FILE_NAME))
+            .unwrap()
+            .is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
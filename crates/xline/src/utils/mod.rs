@@ -1,5 +1,7 @@
 /// Xline command line arguments
 mod args;
+/// Advisory lock of the data directory
+mod data_dir_lock;
 /// Xline tracing init
 mod trace;
 
@@ -7,5 +9,6 @@ mod trace;
 mod metrics;
 
 pub use args::{parse_config, ServerArgs};
+pub(crate) use data_dir_lock::DataDirLock;
 pub use metrics::init_metrics;
 pub use trace::init_subscriber;
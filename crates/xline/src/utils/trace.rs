@@ -1,5 +1,6 @@
 use anyhow::{Ok, Result};
 use opentelemetry_contrib::trace::exporter::jaeger_json::JaegerJsonExporter;
+use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::runtime::Tokio;
 use tracing::warn;
 use tracing_appender::non_blocking::WorkerGuard;
@@ -28,10 +29,13 @@ pub fn init_subscriber(
     trace_config: &TraceConfig,
 ) -> Result<Option<WorkerGuard>> {
     let jaeger_level = *trace_config.jaeger_level();
-    let jaeger_online_layer = trace_config
-        .jaeger_online()
+    let otlp_endpoint = trace_config.otlp_endpoint();
+    let jaeger_online_layer = (*trace_config.jaeger_online() || otlp_endpoint.is_some())
         .then(|| {
-            let otlp_exporter = opentelemetry_otlp::new_exporter().tonic();
+            let mut otlp_exporter = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(ref endpoint) = *otlp_endpoint {
+                otlp_exporter = otlp_exporter.with_endpoint(endpoint);
+            }
             opentelemetry_otlp::new_pipeline()
                 .tracing()
                 .with_exporter(otlp_exporter)
@@ -0,0 +1,350 @@
+//! Tests activating failpoints for all members of a cluster, the points are global to
+//! the process so these tests are kept out of the other test binaries.
+
+use std::{collections::HashSet, error::Error, time::Duration};
+
+use futures::future;
+use test_macros::abort_on_panic;
+use tracing::info;
+use utils::{
+    config::default_lease_revoke_batch_size,
+    failpoint::{FailAction, FailScenario},
+};
+use xline_test_utils::{
+    types::{
+        cluster::{MemberListRequest, MemberUpdateRequest},
+        kv::{PutRequest, RangeRequest},
+        lease::{LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest},
+        watch::{WatchEvent, WatchRequest},
+    },
+    Client, ClientOptions, Cluster,
+};
+
+/// Point before a keep alive response is sent
+const KEEP_ALIVE_RESPONSE: &str = "lease_before_keep_alive_response";
+/// Point between the revocation of a lease and the deletion of its keys
+const REVOKE_CASCADE_DELETE: &str = "lease_revoke_before_cascade_delete";
+/// Point before a conf change is applied
+const APPLY_CONF_CHANGE: &str = "curp_before_apply_conf_change";
+/// Point before the leader takes the next batch of expired leases
+const REVOKE_BATCH: &str = "lease_before_revoke_batch";
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_keep_alive() -> Result<(), Box<dyn Error>> {
+    let scenario = FailScenario::setup();
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let non_leader_ep = cluster.get_client_url(1);
+    let client = cluster.client().await;
+
+    let res = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(1))
+        .await?;
+    let lease_id = res.id;
+    assert!(lease_id > 0);
+
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
+
+    // a zero delay only counts the keep alive responses
+    scenario.cfg(KEEP_ALIVE_RESPONSE, FailAction::Delay(Duration::ZERO));
+    let mut c = Client::connect(vec![non_leader_ep], ClientOptions::default())
+        .await?
+        .lease_client();
+    let (mut keeper, mut stream) = c.keep_alive(LeaseKeepAliveRequest::new(lease_id)).await?;
+    let handle = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let _ = keeper.keep_alive();
+            if let Ok(Some(r)) = stream.message().await {
+                info!("keep alive response: {:?}", r);
+            };
+        }
+    });
+
+    // the lease is kept alive for three times of its ttl
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        scenario.reached(KEEP_ALIVE_RESPONSE, 6),
+    )
+    .await?;
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
+
+    handle.abort();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !client
+            .kv_client()
+            .range(RangeRequest::new("foo"))
+            .await
+            .unwrap()
+            .kvs
+            .is_empty()
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_revoke_should_delete_keys_atomically() -> Result<(), Box<dyn Error>> {
+    const KEYS_PER_RANGE: usize = 100;
+    let scenario = FailScenario::setup();
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    // the keys of the lease are spread across ranges, between keys without a lease
+    for prefix in ["a", "m", "z"] {
+        for i in 0..KEYS_PER_RANGE {
+            let _ = client
+                .kv_client()
+                .put(PutRequest::new(format!("{prefix}/lease/{i:03}"), "v").with_lease(lease_id))
+                .await?;
+            let _ = client
+                .kv_client()
+                .put(PutRequest::new(format!("{prefix}/free/{i:03}"), "v"))
+                .await?;
+        }
+    }
+    let leased = 3 * KEYS_PER_RANGE;
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(WatchRequest::new("a").with_range_end("{"))
+        .await?;
+    let count_leased = |kvs: &[xlineapi::KeyValue]| {
+        kvs.iter()
+            .filter(|kv| kv.key.windows(7).any(|w| w == b"/lease/"))
+            .count()
+    };
+
+    // every member pauses between revoking the lease and deleting its keys
+    scenario.cfg(REVOKE_CASCADE_DELETE, FailAction::Pause);
+    let mut revoke_client = client.lease_client();
+    let revoke = tokio::spawn(async move {
+        revoke_client
+            .revoke(LeaseRevokeRequest::new(lease_id))
+            .await
+            .unwrap()
+    });
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        scenario.reached(REVOKE_CASCADE_DELETE, 1),
+    )
+    .await?;
+    let writer_client = client.kv_client();
+    let writer = tokio::spawn(async move {
+        for i in 0..200 {
+            writer_client
+                .put(PutRequest::new("m/writer", i.to_string()))
+                .await
+                .unwrap();
+        }
+    });
+    for _ in 0..10 {
+        let res = client
+            .kv_client()
+            .range(
+                RangeRequest::new("a")
+                    .with_range_end("{")
+                    .with_serializable(true),
+            )
+            .await?;
+        assert_eq!(
+            count_leased(&res.kvs),
+            leased,
+            "read a partially revoked lease"
+        );
+    }
+    scenario.remove(REVOKE_CASCADE_DELETE);
+    let _ = tokio::time::timeout(Duration::from_secs(10), revoke).await??;
+    writer.await?;
+    let res = client
+        .kv_client()
+        .range(RangeRequest::new("a").with_range_end("{"))
+        .await?;
+    assert_eq!(count_leased(&res.kvs), 0);
+
+    // all deletions carry the same revision, in the order of the keys
+    let mut deleted = vec![];
+    while deleted.len() < leased {
+        let event = tokio::time::timeout(Duration::from_secs(3), stream.message())
+            .await??
+            .unwrap();
+        let WatchEvent::Events { events, .. } = event else {
+            continue;
+        };
+        for event in events {
+            if event.r#type == xlineapi::EventType::Delete as i32 {
+                let kv = event.kv.unwrap();
+                deleted.push((kv.mod_revision, kv.key));
+            }
+        }
+    }
+    assert_eq!(deleted.len(), leased);
+    assert!(deleted.iter().all(|(rev, _)| *rev == deleted[0].0));
+    assert!(deleted.windows(2).all(|w| w[0].1 < w[1].1));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_put_during_conf_change_apply_should_complete() -> Result<(), Box<dyn Error>> {
+    let scenario = FailScenario::setup();
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut cluster_client = client.cluster_client();
+    let members = cluster_client
+        .member_list(MemberListRequest::new(false))
+        .await?
+        .members;
+    let update_id = members[0].id;
+    let port = members[0]
+        .peer_ur_ls
+        .first()
+        .unwrap()
+        .split(':')
+        .last()
+        .unwrap()
+        .parse::<u16>()
+        .unwrap();
+    let new_url = format!("http://localhost:{port}");
+
+    // every member pauses right before applying the conf change
+    scenario.cfg(APPLY_CONF_CHANGE, FailAction::Pause);
+    let mut update_client = client.cluster_client();
+    let update_req = MemberUpdateRequest::new(update_id, vec![new_url.clone()]);
+    let update = tokio::spawn(async move { update_client.member_update(update_req).await });
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        scenario.reached(APPLY_CONF_CHANGE, 1),
+    )
+    .await?;
+    let put_client = client.kv_client();
+    let put = tokio::spawn(async move { put_client.put(PutRequest::new("foo", "bar")).await });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(
+        !update.is_finished(),
+        "the conf change is applied while paused"
+    );
+
+    scenario.remove(APPLY_CONF_CHANGE);
+    let _ = tokio::time::timeout(Duration::from_secs(10), update).await???;
+    let _ = tokio::time::timeout(Duration::from_secs(10), put).await???;
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
+    let members = cluster_client
+        .member_list(MemberListRequest::new(false))
+        .await?
+        .members;
+    let updated = members.iter().find(|m| m.id == update_id).unwrap();
+    assert_eq!(updated.peer_ur_ls, vec![new_url]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_expired_leases_are_revoked_batch_by_batch_in_expiry_order(
+) -> Result<(), Box<dyn Error>> {
+    const WAVES: usize = 10;
+    const LEASES_PER_WAVE: usize = 1000;
+    let batch_size = default_lease_revoke_batch_size();
+    let scenario = FailScenario::setup();
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    // the revoker of the leader pauses before each batch, the test lets it go on one
+    // batch at a time
+    scenario.cfg(REVOKE_BATCH, FailAction::Pause);
+    // a wave is granted after the previous one, its leases expire after all of them
+    let mut waves = vec![];
+    for _ in 0..WAVES {
+        let lease_client = client.lease_client();
+        let grants = (0..LEASES_PER_WAVE).map(|_| lease_client.grant(LeaseGrantRequest::new(1)));
+        let ids: HashSet<_> = future::try_join_all(grants)
+            .await?
+            .into_iter()
+            .map(|res| res.id)
+            .collect();
+        assert_eq!(ids.len(), LEASES_PER_WAVE);
+        waves.push(ids);
+    }
+    let kept_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let (mut keeper, mut stream) = client
+        .lease_client()
+        .keep_alive(LeaseKeepAliveRequest::new(kept_id))
+        .await?;
+
+    let mut revoked = 0;
+    loop {
+        tokio::time::timeout(Duration::from_secs(10), scenario.reached(REVOKE_BATCH, 1)).await?;
+        let alive: HashSet<_> = client
+            .lease_client()
+            .leases()
+            .await?
+            .leases
+            .into_iter()
+            .map(|lease| lease.id)
+            .collect();
+        let revoked_per_wave: Vec<_> = waves
+            .iter()
+            .map(|ids| ids.iter().filter(|id| !alive.contains(id)).count())
+            .collect();
+        let total: usize = revoked_per_wave.iter().sum();
+        assert!(total >= revoked && total - revoked <= batch_size);
+        revoked = total;
+        // a lease is revoked only after all the leases of the earlier waves
+        if let Some(last) = revoked_per_wave.iter().rposition(|&n| n > 0) {
+            assert!(revoked_per_wave[..last]
+                .iter()
+                .all(|&n| n == LEASES_PER_WAVE));
+        }
+
+        // grants and renewals go on between the batches
+        let _ = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(60))
+            .await?;
+        keeper.keep_alive()?;
+        let res = tokio::time::timeout(Duration::from_secs(3), stream.message())
+            .await??
+            .unwrap();
+        assert_eq!(res.id, kept_id);
+        assert!(res.ttl > 0);
+
+        if revoked == WAVES * LEASES_PER_WAVE {
+            break;
+        }
+        // replacing the paused point releases the revoker, it pauses again before the
+        // next batch
+        scenario.cfg(REVOKE_BATCH, FailAction::Pause);
+    }
+
+    Ok(())
+}
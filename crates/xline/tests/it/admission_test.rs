@@ -0,0 +1,60 @@
+use std::error::Error;
+
+use test_macros::abort_on_panic;
+use xline_test_utils::Cluster;
+
+/// Error message returned when too many proposals are in flight
+const TOO_MANY_REQUESTS_ERR_MSG: &str = "etcdserver: too many requests";
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_flood_of_puts_should_be_rejected_beyond_the_limit() -> Result<(), Box<dyn Error>> {
+    let config = Cluster::config_with(|cluster| cluster.max_inflight_proposals(1));
+    let mut cluster = Cluster::new_with_configs(vec![config; 3]).await;
+    cluster.start().await;
+    let kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let value = vec![b'x'; 64 * 1024];
+    let handles: Vec<_> = (0..64)
+        .map(|i| {
+            let mut kv_client = kv_client.clone();
+            let value = value.clone();
+            tokio::spawn(async move {
+                kv_client
+                    .put(xlineapi::PutRequest {
+                        key: format!("flood-{i}").into_bytes(),
+                        value,
+                        ..Default::default()
+                    })
+                    .await
+            })
+        })
+        .collect();
+    let mut rejected = 0;
+    for handle in handles {
+        if let Err(status) = handle.await? {
+            assert_eq!(status.code(), tonic::Code::ResourceExhausted, "{status:?}");
+            assert_eq!(status.message(), TOO_MANY_REQUESTS_ERR_MSG);
+            rejected += 1;
+        }
+    }
+    assert!(rejected > 0, "no put was rejected");
+
+    // reads bypass the limit and the permits are given back once the flood is over
+    let mut kv_client = kv_client;
+    let _resp = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"flood-0".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let _resp = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(())
+}
@@ -10,6 +10,7 @@ use xline_test_utils::{
     types::{
         auth::{AuthRoleDeleteRequest, AuthUserAddRequest, AuthUserGetRequest},
         kv::{PutRequest, RangeRequest},
+        lease::{LeaseGrantRequest, LeaseRevokeRequest, LeaseTimeToLiveRequest},
     },
     Client, ClientOptions, Cluster,
 };
@@ -143,6 +144,125 @@ async fn test_kv_authorization() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_stats_keys_authorization() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    set_user(client, "u1", "123", "r1", b"foo", &[]).await?;
+    set_user(
+        client,
+        "u2",
+        "123",
+        "r2",
+        b"/__xline/stats/",
+        b"/__xline/stats0",
+    )
+    .await?;
+    enable_auth(client).await?;
+
+    let u1_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("u1", "123"),
+    )
+    .await?
+    .kv_client();
+    let u2_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("u2", "123"),
+    )
+    .await?
+    .kv_client();
+
+    // the statistics are not readable without the permission of the prefix
+    let result = u1_client
+        .range(RangeRequest::new("/__xline/stats/revision"))
+        .await;
+    assert!(result.is_err());
+    let resp = u2_client
+        .range(RangeRequest::new("/__xline/stats/revision"))
+        .await?;
+    assert_eq!(resp.kvs.len(), 1);
+    // nor writable with it
+    let result = u2_client
+        .put(PutRequest::new("/__xline/stats/revision", "1"))
+        .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_authorization() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    set_user(client, "u1", "123", "r1", b"foo", &[]).await?;
+    enable_auth(client).await?;
+
+    let root_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("root", "123"),
+    )
+    .await?;
+    let u1_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("u1", "123"),
+    )
+    .await?;
+
+    let lease_id = root_client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let root_kv_client = root_client.kv_client();
+    let _resp = root_kv_client
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
+    let _resp = root_kv_client
+        .put(PutRequest::new("zoo", "bar").with_lease(lease_id))
+        .await?;
+
+    let mut u1_lease_client = u1_client.lease_client();
+    let result = u1_lease_client
+        .time_to_live(LeaseTimeToLiveRequest::new(lease_id))
+        .await;
+    assert!(result.is_err());
+    let result = u1_lease_client
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await;
+    assert!(result.is_err());
+    let resp = root_kv_client.range(RangeRequest::new("zoo")).await?;
+    assert_eq!(resp.kvs.len(), 1);
+
+    // a lease holding only permitted keys can be revoked
+    let u1_lease_id = u1_lease_client.grant(LeaseGrantRequest::new(60)).await?.id;
+    let _resp = u1_client
+        .kv_client()
+        .put(PutRequest::new("foo", "baz").with_lease(u1_lease_id))
+        .await?;
+    let _resp = u1_lease_client
+        .time_to_live(LeaseTimeToLiveRequest::new(u1_lease_id))
+        .await?;
+    let _resp = u1_lease_client
+        .revoke(LeaseRevokeRequest::new(u1_lease_id))
+        .await?;
+
+    let _resp = root_client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await?;
+    let resp = root_kv_client.range(RangeRequest::new("zoo")).await?;
+    assert!(resp.kvs.is_empty());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
 async fn test_role_delete() -> Result<(), Box<dyn Error>> {
@@ -238,7 +358,7 @@ fn configs_with_auth(size: usize) -> Vec<XlineServerConfig> {
             StorageConfig::default(),
             LogConfig::default(),
             TraceConfig::default(),
-            AuthConfig::new(auth_public_key, auth_private_key),
+            AuthConfig::new(auth_public_key, auth_private_key, None),
             CompactConfig::default(),
             TlsConfig::default(),
             MetricsConfig::default(),
@@ -101,3 +101,39 @@ async fn xline_update_node() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_add_node_with_bad_url_should_be_rejected() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let existing_url = format!("{}/", cluster.get_peer_url(1).to_uppercase());
+    let mut cluster_client = cluster.client().await.cluster_client();
+    let cases = [
+        (vec!["localhost".to_owned()], "missing port"),
+        (
+            vec!["http://127.0.0.1:2380/path".to_owned()],
+            "should not contain a path",
+        ),
+        (
+            vec![
+                "http://127.0.0.1:2380".to_owned(),
+                "HTTP://127.0.0.1:2380/".to_owned(),
+            ],
+            "duplicated in the request",
+        ),
+        (vec![existing_url], "already used by member"),
+    ];
+    for (urls, rule) in cases {
+        let err = cluster_client
+            .member_add(MemberAddRequest::new(urls, false))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(rule), "unexpected error: {err}");
+    }
+    let list_res = cluster_client
+        .member_list(MemberListRequest::new(true))
+        .await?;
+    assert_eq!(list_res.members.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::{error::Error, time::Duration};
+
+use test_macros::abort_on_panic;
+use utils::config::XlineServerConfig;
+use xline::embedded::XlineEmbedded;
+use xline_test_utils::types::{
+    kv::{Compare, CompareResult, DeleteRangeRequest, PutRequest, RangeRequest, TxnOp, TxnRequest},
+    lease::{LeaseGrantRequest, LeaseKeepAliveRequest, LeaseTimeToLiveRequest},
+    watch::{WatchEvent, WatchRequest},
+};
+use xlineapi::EventType;
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_embedded_kv() -> Result<(), Box<dyn Error>> {
+    let xline = XlineEmbedded::start(XlineServerConfig::default()).await?;
+    let client = xline.client().kv_client();
+
+    let _ = client.put(PutRequest::new("foo", "bar")).await?;
+    let res = client.range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
+
+    let res = client
+        .txn(
+            TxnRequest::new()
+                .when(&[Compare::value("foo", CompareResult::Equal, "bar")][..])
+                .and_then(&[TxnOp::put(PutRequest::new("foo", "baz"))][..]),
+        )
+        .await?;
+    assert!(res.succeeded);
+
+    let res = client
+        .delete(DeleteRangeRequest::new("foo").with_prev_kv(true))
+        .await?;
+    assert_eq!(res.prev_kvs[0].value, b"baz".as_slice());
+    let res = client.range(RangeRequest::new("foo")).await?;
+    assert!(res.kvs.is_empty());
+
+    xline.stop().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_embedded_lease() -> Result<(), Box<dyn Error>> {
+    let xline = XlineEmbedded::start(XlineServerConfig::default()).await?;
+    let client = xline.client();
+    let mut lease_client = client.lease_client();
+
+    let lease_id = lease_client.grant(LeaseGrantRequest::new(1)).await?.id;
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
+
+    let (mut keeper, mut stream) = lease_client
+        .keep_alive(LeaseKeepAliveRequest::new(lease_id))
+        .await?;
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        keeper.keep_alive()?;
+        assert!(stream.message().await?.is_some());
+    }
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+
+    drop(keeper);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert!(res.kvs.is_empty());
+    assert!(lease_client
+        .time_to_live(LeaseTimeToLiveRequest::new(lease_id))
+        .await
+        .is_err());
+
+    xline.stop().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_embedded_watch() -> Result<(), Box<dyn Error>> {
+    let xline = XlineEmbedded::start(XlineServerConfig::default()).await?;
+    let client = xline.client();
+    let kv_client = client.kv_client();
+
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(WatchRequest::new("foo"))
+        .await?;
+    let _ = kv_client.put(PutRequest::new("foo", "bar")).await?;
+    let _ = kv_client.delete(DeleteRangeRequest::new("foo")).await?;
+
+    let mut types = vec![];
+    while types.len() < 2 {
+        if let Some(WatchEvent::Events { events, .. }) = stream.message().await? {
+            types.extend(events.iter().map(|e| e.r#type));
+        }
+    }
+    assert_eq!(types, [EventType::Put as i32, EventType::Delete as i32]);
+
+    xline.stop().await;
+    Ok(())
+}
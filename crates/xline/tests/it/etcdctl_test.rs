@@ -0,0 +1,427 @@
+//! etcd compatibility tests driven by the official `etcdctl` binary.
+//!
+//! The binary is taken from `ETCDCTL_BIN` if set, otherwise the pinned release
+//! is downloaded into the system temp dir on first use.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    time::Duration,
+};
+
+use test_macros::abort_on_panic;
+use xline_test_utils::Cluster;
+
+/// Pinned etcdctl release, keep in sync with `scripts/common.sh`
+const ETCDCTL_VERSION: &str = "v3.5.9";
+
+/// Get the etcdctl binary, downloading the pinned release if necessary
+fn etcdctl_bin() -> PathBuf {
+    if let Ok(bin) = env::var("ETCDCTL_BIN") {
+        return PathBuf::from(bin);
+    }
+    let name = format!("etcd-{ETCDCTL_VERSION}-linux-amd64");
+    let dir = env::temp_dir().join("xline-etcdctl-compat");
+    let bin = dir.join(&name).join("etcdctl");
+    if bin.exists() {
+        return bin;
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    let url = format!(
+        "https://github.com/etcd-io/etcd/releases/download/{ETCDCTL_VERSION}/{name}.tar.gz"
+    );
+    let archive = dir.join(format!("{name}.tar.gz"));
+    let status = Command::new("curl")
+        .args(["-fsSL", "-o"])
+        .arg(&archive)
+        .arg(&url)
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to download etcdctl from {url}");
+    let status = Command::new("tar")
+        .arg("xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to extract {}", archive.display());
+    bin
+}
+
+/// A thin wrapper around the etcdctl binary
+struct Etcdctl {
+    /// Path of the binary
+    bin: PathBuf,
+    /// Endpoints passed by `--endpoints`
+    endpoints: String,
+    /// Credentials passed by `--user`
+    user: Option<String>,
+}
+
+impl Etcdctl {
+    /// New `Etcdctl` connecting to the given endpoints
+    fn new(endpoints: &[String]) -> Self {
+        let endpoints = endpoints
+            .iter()
+            .map(|ep| ep.trim_start_matches("http://"))
+            .collect::<Vec<_>>()
+            .join(",");
+        Self {
+            bin: etcdctl_bin(),
+            endpoints,
+            user: None,
+        }
+    }
+
+    /// Build the command for the given args
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.bin);
+        let _ignore = cmd
+            .env("ETCDCTL_API", "3")
+            .arg(format!("--endpoints={}", self.endpoints));
+        if let Some(ref user) = self.user {
+            let _ignore = cmd.arg(format!("--user={user}"));
+        }
+        let _ignore = cmd.args(args);
+        cmd
+    }
+
+    /// Invocation string used for reproduction
+    fn invocation(&self, args: &[&str]) -> String {
+        let user = self
+            .user
+            .as_ref()
+            .map(|u| format!(" --user={u}"))
+            .unwrap_or_default();
+        format!(
+            "ETCDCTL_API=3 {} --endpoints={}{user} {}",
+            self.bin.display(),
+            self.endpoints,
+            args.join(" ")
+        )
+    }
+
+    /// Run etcdctl and return the raw output
+    fn output(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Run etcdctl, assert it succeeds and return stdout
+    fn ok(&self, args: &[&str]) -> String {
+        let out = self.output(args);
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(
+            out.status.success(),
+            "etcdctl failed: `{}`\nstdout: {stdout}\nstderr: {}",
+            self.invocation(args),
+            String::from_utf8_lossy(&out.stderr)
+        );
+        stdout
+    }
+
+    /// Run etcdctl, assert it fails and return stderr
+    fn err(&self, args: &[&str]) -> String {
+        let out = self.output(args);
+        let stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+        assert!(
+            !out.status.success(),
+            "etcdctl should fail: `{}`\nstdout: {}",
+            self.invocation(args),
+            String::from_utf8_lossy(&out.stdout)
+        );
+        stderr
+    }
+
+    /// Run etcdctl, assert stdout equals the given lines
+    fn expect(&self, args: &[&str], lines: &[&str]) {
+        let stdout = self.ok(args);
+        let got = stdout.lines().collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            lines,
+            "unexpected output of `{}`",
+            self.invocation(args)
+        );
+    }
+
+    /// Run a long running command (e.g. watch) for `dur` and return its stdout
+    fn run_for(&self, args: &[&str], dur: Duration) -> String {
+        let mut child = self
+            .command(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(dur);
+        let _ignore = child.kill();
+        let out = child.wait_with_output().unwrap();
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    }
+}
+
+/// Parse the lease id printed by `etcdctl lease grant`
+fn parse_lease_id(grant_output: &str) -> String {
+    // output: lease 694d77aa9e4c1a0b granted with TTL(60s)
+    grant_output
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_else(|| panic!("unexpected lease grant output: {grant_output}"))
+        .to_owned()
+}
+
+/// Parse the revision from `etcdctl get -w fields`
+fn parse_revision(fields_output: &str) -> i64 {
+    fields_output
+        .lines()
+        .find_map(|l| l.strip_prefix("\"Revision\" : "))
+        .unwrap_or_else(|| panic!("revision not found in: {fields_output}"))
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+async fn start_cluster() -> (Cluster, Etcdctl) {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let ctl = Etcdctl::new(&cluster.all_client_addrs());
+    (cluster, ctl)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_kv_should_work() {
+    let (_cluster, ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        ctl.expect(&["put", "foo", "bar"], &["OK"]);
+        ctl.expect(&["put", "foo1", "bar1"], &["OK"]);
+        ctl.expect(&["put", "foo2", "bar2"], &["OK"]);
+        ctl.expect(&["get", "foo"], &["foo", "bar"]);
+        ctl.expect(&["get", "foo", "--print-value-only"], &["bar"]);
+        ctl.expect(
+            &["get", "foo", "--prefix", "--keys-only"],
+            &["foo", "", "foo1", "", "foo2", ""],
+        );
+        ctl.expect(
+            &["get", "foo", "foo2", "--keys-only"],
+            &["foo", "", "foo1", ""],
+        );
+        ctl.expect(
+            &[
+                "get",
+                "foo",
+                "--prefix",
+                "--limit=1",
+                "--sort-by=KEY",
+                "--order=DESCEND",
+            ],
+            &["foo2", "bar2"],
+        );
+        ctl.expect(&["put", "foo", "baz", "--prev-kv"], &["OK", "foo", "bar"]);
+        ctl.expect(&["del", "foo1"], &["1"]);
+        ctl.expect(
+            &["del", "foo", "--prefix", "--prev-kv"],
+            &["2", "foo", "baz", "foo2", "bar2"],
+        );
+        ctl.expect(&["get", "foo", "--prefix"], &[]);
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_txn_should_work() {
+    let (_cluster, ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        ctl.expect(&["put", "key", "v1"], &["OK"]);
+        let mut child = ctl
+            .command(&["txn"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        {
+            use std::io::Write;
+            let stdin = child.stdin.as_mut().unwrap();
+            stdin
+                .write_all(b"value(\"key\") = \"v1\"\n\nput key v2\n\nput key v3\n\n")
+                .unwrap();
+        }
+        let out = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            out.status.success(),
+            "etcdctl failed: `{}`\nstderr: {}",
+            ctl.invocation(&["txn"]),
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(stdout.lines().collect::<Vec<_>>(), ["SUCCESS", "", "OK"]);
+        ctl.expect(&["get", "key", "--print-value-only"], &["v2"]);
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_lease_should_work() {
+    let (_cluster, ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        let id = parse_lease_id(&ctl.ok(&["lease", "grant", "60"]));
+        let ttl = ctl.ok(&["lease", "timetolive", &id]);
+        assert!(
+            ttl.contains(&format!("lease {id} granted with TTL(60s)")),
+            "unexpected output of `{}`: {ttl}",
+            ctl.invocation(&["lease", "timetolive", &id])
+        );
+        let list = ctl.ok(&["lease", "list"]);
+        assert!(
+            list.contains(&id),
+            "unexpected output of `{}`: {list}",
+            ctl.invocation(&["lease", "list"])
+        );
+        ctl.expect(&["put", "leased", "v", &format!("--lease={id}")], &["OK"]);
+        let keep_alive = ctl.ok(&["lease", "keep-alive", "--once", &id]);
+        assert!(
+            keep_alive.contains(&format!("lease {id} keepalived with TTL(60)")),
+            "unexpected output of `{}`: {keep_alive}",
+            ctl.invocation(&["lease", "keep-alive", "--once", &id])
+        );
+        ctl.expect(&["lease", "revoke", &id], &[&format!("lease {id} revoked")]);
+        ctl.expect(&["get", "leased"], &[]);
+        let stderr = ctl.err(&["lease", "revoke", &id]);
+        assert!(
+            stderr.contains("requested lease not found"),
+            "unexpected error of `{}`: {stderr}",
+            ctl.invocation(&["lease", "revoke", &id])
+        );
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_watch_with_resume_should_work() {
+    let (_cluster, ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        ctl.expect(&["put", "watched", "v1"], &["OK"]);
+        let rev = parse_revision(&ctl.ok(&["get", "watched", "-w", "fields"]));
+        ctl.expect(&["put", "watched", "v2"], &["OK"]);
+        ctl.expect(&["del", "watched"], &["1"]);
+        let out = ctl.run_for(
+            &["watch", "watched", &format!("--rev={rev}")],
+            Duration::from_secs(2),
+        );
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            ["PUT", "watched", "v1", "PUT", "watched", "v2", "DELETE", "watched"],
+            "unexpected output of `{}`",
+            ctl.invocation(&["watch", "watched", &format!("--rev={rev}")])
+        );
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_auth_should_work() {
+    let (_cluster, mut ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        ctl.expect(&["user", "add", "root:123"], &["User root created"]);
+        ctl.expect(&["role", "add", "root"], &["Role root created"]);
+        ctl.expect(
+            &["user", "grant-role", "root", "root"],
+            &["Role root is granted to user root"],
+        );
+        ctl.expect(&["user", "add", "u:123"], &["User u created"]);
+        ctl.expect(&["role", "add", "r"], &["Role r created"]);
+        ctl.expect(
+            &["role", "grant-permission", "r", "readwrite", "allowed"],
+            &["Role r updated"],
+        );
+        ctl.expect(
+            &["user", "grant-role", "u", "r"],
+            &["Role r is granted to user u"],
+        );
+        ctl.expect(&["auth", "enable"], &["Authentication Enabled"]);
+
+        let stderr = ctl.err(&["put", "allowed", "v"]);
+        assert!(
+            stderr.contains("user name is empty"),
+            "unexpected error of `{}`: {stderr}",
+            ctl.invocation(&["put", "allowed", "v"])
+        );
+
+        ctl.user = Some("u:123".to_owned());
+        ctl.expect(&["put", "allowed", "v"], &["OK"]);
+        let stderr = ctl.err(&["put", "denied", "v"]);
+        assert!(
+            stderr.contains("permission denied"),
+            "unexpected error of `{}`: {stderr}",
+            ctl.invocation(&["put", "denied", "v"])
+        );
+
+        ctl.user = Some("root:123".to_owned());
+        ctl.expect(&["auth", "disable"], &["Authentication Disabled"]);
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn etcdctl_maintenance_should_work() {
+    let (_cluster, ctl) = start_cluster().await;
+    tokio::task::block_in_place(|| {
+        let members = ctl.ok(&["member", "list"]);
+        assert_eq!(
+            members.lines().count(),
+            3,
+            "unexpected output of `{}`: {members}",
+            ctl.invocation(&["member", "list"])
+        );
+
+        for v in ["v1", "v2", "v3"] {
+            ctl.expect(&["put", "compact", v], &["OK"]);
+        }
+        let rev = parse_revision(&ctl.ok(&["get", "compact", "-w", "fields"]));
+        let compact_rev = (rev - 1).to_string();
+        ctl.expect(
+            &["compaction", &compact_rev],
+            &[&format!("compacted revision {compact_rev}")],
+        );
+        let stderr = ctl.err(&["get", "compact", &format!("--rev={}", rev - 2)]);
+        assert!(
+            stderr.contains("required revision has been compacted"),
+            "unexpected error of `{}`: {stderr}",
+            ctl.invocation(&["get", "compact", &format!("--rev={}", rev - 2)])
+        );
+
+        let dir = tempdir();
+        let path = dir.join("snapshot.db");
+        let path_str = path.to_string_lossy().into_owned();
+        let single = Etcdctl {
+            endpoints: ctl.endpoints.split(',').next().unwrap().to_owned(),
+            bin: ctl.bin.clone(),
+            user: None,
+        };
+        let out = single.ok(&["snapshot", "save", &path_str]);
+        assert!(
+            out.contains(&format!("Snapshot saved at {path_str}")),
+            "unexpected output of `{}`: {out}",
+            single.invocation(&["snapshot", "save", &path_str])
+        );
+        assert!(
+            path.metadata().unwrap().len() > 0,
+            "snapshot should not be empty"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    });
+}
+
+/// Create a fresh temp directory
+fn tempdir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("xline-etcdctl-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(
+        Path::new(&dir).is_dir(),
+        "failed to create {}",
+        dir.display()
+    );
+    dir
+}
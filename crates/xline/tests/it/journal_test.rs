@@ -0,0 +1,85 @@
+use std::{env::temp_dir, error::Error, time::Duration};
+
+use test_macros::abort_on_panic;
+use xline::journal::{read_journal, replay_journal};
+use xline_test_utils::{
+    types::{
+        kv::{Compare, CompareResult, DeleteRangeRequest, PutRequest, TxnOp, TxnRequest},
+        lease::{LeaseGrantRequest, LeaseRevokeRequest},
+    },
+    Client, ClientOptions, Cluster,
+};
+use xlineapi::HashKvRequest;
+
+/// Number of the journal records of the workload
+const RECORDS: usize = 8;
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_replayed_journal_should_reproduce_the_hash_kv() -> Result<(), Box<dyn Error>> {
+    let dir = temp_dir().join(format!("xline-journal-{}", uuid::Uuid::new_v4()));
+    let journal_path = dir.join("journal");
+    let mut cluster = Cluster::new_with_configs(vec![
+        Cluster::journal_config(journal_path.clone()),
+        Cluster::journal_config(dir.join("journal-1")),
+        Cluster::journal_config(dir.join("journal-2")),
+    ])
+    .await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let kv_client = client.kv_client();
+    let mut lease_client = client.lease_client();
+
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+        let _ = kv_client.put(PutRequest::new(key, value)).await?;
+    }
+    let txn = TxnRequest::new()
+        .when(&[Compare::value("a", CompareResult::Equal, "1")][..])
+        .and_then(&[TxnOp::put(PutRequest::new("a", "4"))][..])
+        .or_else(&[TxnOp::put(PutRequest::new("a", "5"))][..]);
+    let _ = kv_client.txn(txn).await?;
+    let _ = kv_client.delete(DeleteRangeRequest::new("b")).await?;
+    let lease_id = lease_client.grant(LeaseGrantRequest::new(60)).await?.id;
+    let _ = kv_client
+        .put(PutRequest::new("d", "6").with_lease(lease_id))
+        .await?;
+    let _ = kv_client
+        .put(PutRequest::new("e", "7").with_lease(lease_id))
+        .await?;
+    let _ = lease_client
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await?;
+
+    // the member may not have applied the last requests yet
+    let mut records = vec![];
+    for _ in 0..100 {
+        records = read_journal(&journal_path)?;
+        if records.len() >= RECORDS {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(records.len() >= RECORDS, "{records:?}");
+    assert!(records.windows(2).all(|w| w[0].index < w[1].index));
+
+    let mut target = Cluster::new(3).await;
+    target.start().await;
+    let report = replay_journal(&records, target.get_client_url(0)).await?;
+    assert_eq!(report.divergence, None);
+    assert!(report.replayed >= RECORDS);
+
+    let mut hashes = vec![];
+    for url in [cluster.get_client_url(0), target.get_client_url(0)] {
+        let mut maintenance_client = Client::connect(vec![url], ClientOptions::default())
+            .await?
+            .maintenance_client();
+        let resp = maintenance_client
+            .hash_kv(HashKvRequest { revision: 0 })
+            .await?;
+        hashes.push((resp.header.unwrap().revision, resp.hash));
+    }
+    assert_eq!(hashes[0], hashes[1]);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
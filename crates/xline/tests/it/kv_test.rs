@@ -179,7 +179,7 @@ async fn test_range_redirect() -> Result<(), Box<dyn Error>> {
     tokio::time::sleep(Duration::from_millis(300)).await;
     let res = kv_client.range(RangeRequest::new("foo")).await?;
     assert_eq!(res.kvs.len(), 1);
-    assert_eq!(res.kvs[0].value, b"bar");
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
 
     Ok(())
 }
@@ -297,7 +297,7 @@ async fn test_txn() -> Result<(), Box<dyn Error>> {
         {
             assert_eq!(get_res.kvs.len(), 1);
             assert_eq!(get_res.kvs[0].key, b"b");
-            assert_eq!(get_res.kvs[0].value, b"bar");
+            assert_eq!(get_res.kvs[0].value, b"bar".as_slice());
         } else {
             unreachable!("receive unexpected op response in a read-only transaction");
         }
@@ -320,7 +320,7 @@ async fn test_txn() -> Result<(), Box<dyn Error>> {
         {
             assert_eq!(get_res.kvs.len(), 1);
             assert_eq!(get_res.kvs[0].key, b"c");
-            assert_eq!(get_res.kvs[0].value, b"bar");
+            assert_eq!(get_res.kvs[0].value, b"bar".as_slice());
         } else {
             unreachable!("receive unexpected op response in a read-only transaction");
         }
@@ -330,3 +330,323 @@ async fn test_txn() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_range_below_compacted_revision_should_fail() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let url = cluster.get_client_url(0);
+    let mut kv_client = xlineapi::KvClient::connect(url.clone()).await?;
+
+    for i in 0..100 {
+        let _resp = kv_client
+            .put(xlineapi::PutRequest {
+                key: b"foo".to_vec(),
+                value: i.to_string().into_bytes(),
+                ..Default::default()
+            })
+            .await?;
+    }
+    let _resp = kv_client
+        .compact(xlineapi::CompactionRequest {
+            revision: 100,
+            physical: true,
+        })
+        .await?;
+
+    let err = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"foo".to_vec(),
+            revision: 50,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::OutOfRange);
+    assert_eq!(
+        err.message(),
+        "etcdserver: mvcc: required revision has been compacted"
+    );
+    assert_eq!(err.metadata().get("compact-revision").unwrap(), "100");
+
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url).await?;
+    let resp = maintenance_client
+        .status(xlineapi::StatusRequest::default())
+        .await?;
+    assert_eq!(resp.metadata().get("compact-revision").unwrap(), "100");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_txn_with_sub_revisions() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let txn_req = xlineapi::TxnRequest {
+        success: (0..5)
+            .map(|i| xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestPut(xlineapi::PutRequest {
+                    key: format!("key{i}").into_bytes(),
+                    value: b"value".to_vec(),
+                    ..Default::default()
+                })),
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let mut request = tonic::Request::new(txn_req.clone());
+    let _ignore = request
+        .metadata_mut()
+        .insert(xlineapi::WITH_SUB_REVISIONS_KEY, "true".parse()?);
+    let resp = kv_client.txn(request).await?;
+    assert_eq!(
+        resp.metadata().get(xlineapi::SUB_REVISIONS_KEY).unwrap(),
+        "0,1,2,3,4"
+    );
+    let revision = resp.get_ref().header.as_ref().unwrap().revision;
+    for i in 0..5 {
+        let range_resp = kv_client
+            .range(xlineapi::RangeRequest {
+                key: format!("key{i}").into_bytes(),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(range_resp.get_ref().kvs[0].mod_revision, revision);
+    }
+
+    let resp = kv_client.txn(txn_req).await?;
+    assert!(resp.metadata().get(xlineapi::SUB_REVISIONS_KEY).is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_delete_range_and_txn_should_be_rejected_beyond_the_key_limit(
+) -> Result<(), Box<dyn Error>> {
+    let config = Cluster::config_with(|cluster| cluster.max_keys_per_request(10));
+    let mut cluster = Cluster::new_with_configs(vec![config; 3]).await;
+    cluster.start().await;
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    for i in 0..11 {
+        let _resp = kv_client
+            .put(xlineapi::PutRequest {
+                key: format!("key{i:02}").into_bytes(),
+                value: b"value".to_vec(),
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    let prefix_delete = xlineapi::DeleteRangeRequest {
+        key: b"key".to_vec(),
+        range_end: b"kez".to_vec(),
+        ..Default::default()
+    };
+    let err = kv_client
+        .delete_range(prefix_delete.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument, "{err:?}");
+
+    let err = kv_client
+        .txn(xlineapi::TxnRequest {
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestDeleteRange(prefix_delete.clone())),
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument, "{err:?}");
+
+    // a delete of exactly the limit is accepted, the rest can be deleted afterwards
+    let resp = kv_client
+        .delete_range(xlineapi::DeleteRangeRequest {
+            key: b"key00".to_vec(),
+            range_end: b"key10".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(resp.get_ref().deleted, 10);
+    let resp = kv_client.delete_range(prefix_delete).await?;
+    assert_eq!(resp.get_ref().deleted, 1);
+
+    Ok(())
+}
+
+/// Read a statistics key of the member
+async fn read_stat(
+    kv_client: &mut xlineapi::KvClient<tonic::transport::Channel>,
+    name: &str,
+) -> Result<(u64, i64), Box<dyn Error>> {
+    let resp = kv_client
+        .range(xlineapi::RangeRequest {
+            key: format!("/__xline/stats/{name}").into_bytes(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(resp.kvs.len(), 1, "stat {name} is missing");
+    let value = String::from_utf8(resp.kvs[0].value.clone())?.parse()?;
+    Ok((value, resp.header.unwrap().revision))
+}
+
+/// Wait until a statistics key of the member reaches the expected value
+async fn wait_stat(
+    kv_client: &mut xlineapi::KvClient<tonic::transport::Channel>,
+    name: &str,
+    expected: u64,
+) -> Result<(), Box<dyn Error>> {
+    for _ in 0..50 {
+        if read_stat(kv_client, name).await?.0 == expected {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("stat {name} doesn't reach {expected}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_stats_keys_should_track_the_counters() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let url = cluster.get_client_url(0);
+    let mut kv_client = xlineapi::KvClient::connect(url.clone()).await?;
+    let mut lease_client = xlineapi::LeaseClient::connect(url.clone()).await?;
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url.clone()).await?;
+
+    let status = maintenance_client
+        .status(xlineapi::StatusRequest::default())
+        .await?
+        .into_inner();
+    assert_eq!(
+        read_stat(&mut kv_client, "leader_id").await?.0,
+        status.leader
+    );
+    assert!(read_stat(&mut kv_client, "db_size").await?.0 > 0);
+
+    let put_revision = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .header
+        .unwrap()
+        .revision;
+    let (revision, header_revision) = read_stat(&mut kv_client, "revision").await?;
+    assert_eq!(i64::try_from(revision)?, header_revision);
+    assert!(header_revision >= put_revision);
+
+    wait_stat(&mut kv_client, "lease_count", 0).await?;
+    for _ in 0..2 {
+        let _resp = lease_client
+            .lease_grant(xlineapi::LeaseGrantRequest {
+                ttl: 60,
+                ..Default::default()
+            })
+            .await?;
+    }
+    wait_stat(&mut kv_client, "lease_count", 2).await?;
+
+    wait_stat(&mut kv_client, "watcher_count", 0).await?;
+    let mut watch_client = xlineapi::WatchClient::connect(url).await?;
+    let (watch_tx, watch_rx) = tokio::sync::mpsc::channel(2);
+    let mut watch_stream = watch_client
+        .watch(tokio_stream::wrappers::ReceiverStream::new(watch_rx))
+        .await?
+        .into_inner();
+    for key in [b"foo".to_vec(), b"/__xline/stats/revision".to_vec()] {
+        watch_tx
+            .send(xlineapi::WatchRequest {
+                request_union: Some(xlineapi::RequestUnion::CreateRequest(
+                    xlineapi::WatchCreateRequest {
+                        key,
+                        ..Default::default()
+                    },
+                )),
+            })
+            .await?;
+    }
+    let created = watch_stream.message().await?.unwrap();
+    assert!(created.created && !created.canceled);
+    // watches on the statistics are rejected
+    let rejected = watch_stream.message().await?.unwrap();
+    assert!(rejected.canceled, "{rejected:?}");
+    wait_stat(&mut kv_client, "watcher_count", 1).await?;
+
+    // a range over the prefix lists all the statistics
+    let all = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"/__xline/stats/".to_vec(),
+            range_end: b"/__xline/stats0".to_vec(),
+            keys_only: true,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(all.count, 5);
+    assert!(all.kvs.iter().all(|kv| kv.value.is_empty()));
+
+    // the sorting applies to the statistics as well
+    let descend = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"/__xline/stats/".to_vec(),
+            range_end: b"/__xline/stats0".to_vec(),
+            sort_order: xlineapi::SortOrder::Descend.into(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    let keys = descend
+        .kvs
+        .iter()
+        .map(|kv| kv.key.clone())
+        .collect::<Vec<_>>();
+    let mut expected = all.kvs.iter().map(|kv| kv.key.clone()).collect::<Vec<_>>();
+    expected.reverse();
+    assert_eq!(keys, expected);
+
+    // ranges crossing the prefix are rejected
+    let err = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"/__xline/stats/".to_vec(),
+            range_end: vec![0],
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument, "{err:?}");
+
+    // writes are rejected
+    let err = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"/__xline/stats/revision".to_vec(),
+            value: b"1".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument, "{err:?}");
+    let err = kv_client
+        .delete_range(xlineapi::DeleteRangeRequest {
+            key: b"/__xline/stats/".to_vec(),
+            range_end: b"/__xline/stats0".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument, "{err:?}");
+
+    Ok(())
+}
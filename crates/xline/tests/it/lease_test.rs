@@ -1,13 +1,18 @@
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use test_macros::abort_on_panic;
-use tracing::info;
+use utils::config::{ServerTimeoutBuilder, XlineServerConfig};
 use xline_test_utils::{
     types::{
+        cluster::MemberListRequest,
         kv::{PutRequest, RangeRequest},
-        lease::{LeaseGrantRequest, LeaseKeepAliveRequest},
+        lease::{LeaseGrantRequest, LeaseRevokeRequest, LeaseTimeToLiveRequest},
+        watch::{WatchEvent, WatchRequest},
     },
-    Client, ClientOptions, Cluster,
+    Cluster,
 };
 
 #[tokio::test(flavor = "multi_thread")]
@@ -30,7 +35,7 @@ async fn test_lease_expired() -> Result<(), Box<dyn Error>> {
         .await?;
     let res = client.kv_client().range(RangeRequest::new("foo")).await?;
     assert_eq!(res.kvs.len(), 1);
-    assert_eq!(res.kvs[0].value, b"bar");
+    assert_eq!(res.kvs[0].value, b"bar".as_slice());
 
     tokio::time::sleep(Duration::from_secs(3)).await;
 
@@ -42,50 +47,685 @@ async fn test_lease_expired() -> Result<(), Box<dyn Error>> {
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
-async fn test_lease_keep_alive() -> Result<(), Box<dyn Error>> {
+async fn test_lease_leases_on_all_members() -> Result<(), Box<dyn Error>> {
     let mut cluster = Cluster::new(3).await;
     cluster.start().await;
-    let non_leader_ep = cluster.get_client_url(1);
     let client = cluster.client().await;
 
-    let res = client
+    for id in [1, 2, 3] {
+        let _ = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(60).with_id(id))
+            .await?;
+    }
+    let _ = client
         .lease_client()
-        .grant(LeaseGrantRequest::new(1))
+        .revoke(LeaseRevokeRequest::new(2))
         .await?;
-    let lease_id = res.id;
-    assert!(lease_id > 0);
+    // TODO: use `propose_index` and remove this sleep after we finished our client.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for url in cluster.all_client_addrs() {
+        let mut etcd_client = etcd_client::Client::connect([url], None).await?;
+        let res = etcd_client.leases().await?;
+        let mut ids: Vec<_> = res.leases().iter().map(|l| l.id()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 3]);
+        assert!(res.header().is_some());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_leases_should_be_sorted_during_churn() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    for id in (1..=20).map(|i| i * 2) {
+        let _ = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(60).with_id(id))
+            .await?;
+    }
+    let mut churn_client = client.lease_client();
+    let churn = tokio::spawn(async move {
+        for id in (0..20).map(|i| i * 2 + 1) {
+            let _ = churn_client
+                .grant(LeaseGrantRequest::new(60).with_id(id))
+                .await
+                .unwrap();
+            let _ = churn_client
+                .revoke(LeaseRevokeRequest::new(id))
+                .await
+                .unwrap();
+        }
+    });
+    while !churn.is_finished() {
+        let res = client.lease_client().leases().await?;
+        assert!(res.leases.windows(2).all(|w| w[0].id < w[1].id));
+        assert_eq!(res.count, res.leases.len() as i64);
+        let kept = res.leases.iter().filter(|lease| lease.id % 2 == 0).count();
+        assert_eq!(kept, 20);
+    }
+    churn.await?;
+
+    let mut cluster_client = client.cluster_client();
+    let res = cluster_client
+        .member_list(MemberListRequest::new(false))
+        .await?;
+    assert!(res.members.windows(2).all(|w| w[0].id < w[1].id));
+    assert_eq!(res.count, 3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_keep_alive_unknown_lease_should_not_close_stream() -> Result<(), Box<dyn Error>>
+{
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
 
+    for id in [1, 2] {
+        let _ = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(60).with_id(id))
+            .await?;
+    }
+
+    let mut lease_client = xlineapi::LeaseClient::connect(cluster.get_client_url(0)).await?;
+    let requests = [1, 404, 2, 1]
+        .map(|id| xlineapi::LeaseKeepAliveRequest { id })
+        .to_vec();
+    let mut stream = lease_client
+        .lease_keep_alive(tokio_stream::iter(requests))
+        .await?
+        .into_inner();
+
+    let mut responses = Vec::new();
+    while let Some(res) = stream.message().await? {
+        assert!(res.header.is_some());
+        responses.push((res.id, res.ttl));
+    }
+    assert_eq!(responses, [(1, 60), (404, 0), (2, 60), (1, 60)]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_revoke_should_not_delete_keys_moved_to_another_lease() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let lease_a = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let lease_b = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
     let _ = client
         .kv_client()
-        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .put(PutRequest::new("foo", "bar").with_lease(lease_a))
+        .await?;
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "baz").with_lease(lease_b))
         .await?;
+
+    let _ = client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(lease_a))
+        .await?;
+
     let res = client.kv_client().range(RangeRequest::new("foo")).await?;
     assert_eq!(res.kvs.len(), 1);
-    assert_eq!(res.kvs[0].value, b"bar");
+    assert_eq!(res.kvs[0].value, b"baz".as_slice());
+    assert_eq!(res.kvs[0].lease, lease_b);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_grant_with_zero_id_generates_distinct_ids() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+
+    let mut handles = Vec::new();
+    for url in cluster.all_client_addrs() {
+        for _ in 0..4 {
+            let url = url.clone();
+            handles.push(tokio::spawn(async move {
+                let mut etcd_client = etcd_client::Client::connect([url], None).await?;
+                let mut ids = Vec::new();
+                for _ in 0..25 {
+                    ids.push(etcd_client.lease_grant(60, None).await?.id());
+                }
+                Ok::<_, etcd_client::Error>(ids)
+            }));
+        }
+    }
+    let mut ids = Vec::new();
+    for handle in handles {
+        ids.extend(handle.await??);
+    }
+
+    let total = ids.len();
+    assert!(ids.iter().all(|id| *id > 0));
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), total, "lease ids should be distinct");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_expired_lease_keys_are_deleted_for_watchers() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
 
-    let mut c = Client::connect(vec![non_leader_ep], ClientOptions::default())
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(1))
         .await?
-        .lease_client();
-    let (mut keeper, mut stream) = c.keep_alive(LeaseKeepAliveRequest::new(lease_id)).await?;
-    let handle = tokio::spawn(async move {
-        loop {
+        .id;
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
+
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(WatchRequest::new("foo"))
+        .await?;
+    let event = tokio::time::timeout(Duration::from_secs(5), stream.message())
+        .await?
+        .unwrap()
+        .unwrap();
+    let WatchEvent::Events { events, .. } = event else {
+        panic!("the deletion should be received");
+    };
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].r#type, xlineapi::EventType::Delete as i32);
+    assert_eq!(events[0].kv.as_ref().unwrap().key, b"foo");
+
+    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert!(res.kvs.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_revoke_revision_matches_delete_events() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    for key in ["foo1", "foo2"] {
+        let _ = client
+            .kv_client()
+            .put(PutRequest::new(key, "bar").with_lease(lease_id))
+            .await?;
+    }
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(WatchRequest::new("foo").with_prefix())
+        .await?;
+
+    let res = client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await?;
+    let revision = res.header.unwrap().revision;
+    let mut deleted = 0;
+    while deleted < 2 {
+        let event = tokio::time::timeout(Duration::from_secs(3), stream.message())
+            .await??
+            .unwrap();
+        let WatchEvent::Events { events, .. } = event else {
+            continue;
+        };
+        for event in events {
+            assert_eq!(event.r#type, xlineapi::EventType::Delete as i32);
+            assert_eq!(event.kv.unwrap().mod_revision, revision);
+            deleted += 1;
+        }
+    }
+
+    // a revoke without keys reports the current revision
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let res = client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await?;
+    let range_res = client.kv_client().range(RangeRequest::new("foo")).await?;
+    assert_eq!(
+        res.header.unwrap().revision,
+        range_res.header.unwrap().revision
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_from_revoke_revision_sees_only_lease_deletions() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo0", "bar"))
+        .await?;
+    let range_res = client.kv_client().range(RangeRequest::new("foo0")).await?;
+    // a grant doesn't bump the revision, it reports the current one
+    let grant_res = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?;
+    assert_eq!(
+        grant_res.header.unwrap().revision,
+        range_res.header.unwrap().revision
+    );
+    for key in ["foo1", "foo2"] {
+        let _ = client
+            .kv_client()
+            .put(PutRequest::new(key, "bar").with_lease(grant_res.id))
+            .await?;
+    }
+
+    let revoke_res = client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(grant_res.id))
+        .await?;
+    let revision = revoke_res.header.unwrap().revision;
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch(
+            WatchRequest::new("foo")
+                .with_prefix()
+                .with_start_revision(revision),
+        )
+        .await?;
+
+    let mut deleted = vec![];
+    while deleted.len() < 2 {
+        let event = tokio::time::timeout(Duration::from_secs(3), stream.message())
+            .await??
+            .unwrap();
+        let WatchEvent::Events { events, .. } = event else {
+            continue;
+        };
+        for event in events {
+            assert_eq!(event.r#type, xlineapi::EventType::Delete as i32);
+            let kv = event.kv.unwrap();
+            assert_eq!(kv.mod_revision, revision);
+            deleted.push(kv.key);
+        }
+    }
+    deleted.sort();
+    assert_eq!(deleted, vec![b"foo1".to_vec(), b"foo2".to_vec()]);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), stream.message())
+            .await
+            .is_err(),
+        "no event should follow the deletions of the lease"
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_keep_alive_and_watch_survive_leader_change() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let leader_url = cluster.get_client_url(0);
+    let client = cluster.client().await;
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(3))
+        .await?
+        .id;
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
+
+    // both streams are hosted on the original leader
+    let mut watch_client = xlineapi::WatchClient::connect(leader_url.clone()).await?;
+    let (watch_tx, watch_rx) = tokio::sync::mpsc::channel(1);
+    watch_tx
+        .send(xlineapi::WatchRequest {
+            request_union: Some(xlineapi::RequestUnion::CreateRequest(
+                xlineapi::WatchCreateRequest {
+                    key: b"watched".to_vec(),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await?;
+    let mut watch_stream = watch_client
+        .watch(tokio_stream::wrappers::ReceiverStream::new(watch_rx))
+        .await?
+        .into_inner();
+    assert!(watch_stream.message().await?.unwrap().created);
+
+    let mut lease_client = xlineapi::LeaseClient::connect(leader_url.clone()).await?;
+    let (keep_alive_tx, keep_alive_rx) = tokio::sync::mpsc::channel(1);
+    let mut keep_alive_stream = lease_client
+        .lease_keep_alive(tokio_stream::wrappers::ReceiverStream::new(keep_alive_rx))
+        .await?
+        .into_inner();
+    let keep_alive_handle = tokio::spawn(async move {
+        for _ in 0..16 {
+            keep_alive_tx
+                .send(xlineapi::LeaseKeepAliveRequest { id: lease_id })
+                .await
+                .unwrap();
+            let res = keep_alive_stream.message().await.unwrap().unwrap();
+            assert_eq!(res.id, lease_id);
+            assert!(res.ttl > 0, "lease expired during leader change");
             tokio::time::sleep(Duration::from_millis(500)).await;
-            let _ = keeper.keep_alive();
-            if let Ok(Some(r)) = stream.message().await {
-                info!("keep alive response: {:?}", r);
-            };
         }
     });
 
-    tokio::time::sleep(Duration::from_secs(3)).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let mut cluster_client = xlineapi::ClusterClient::connect(leader_url.clone()).await?;
+    let members = cluster_client
+        .member_list(xlineapi::MemberListRequest::default())
+        .await?
+        .into_inner()
+        .members;
+    let target_id = members
+        .iter()
+        .find(|m| m.name == "server1")
+        .map(|m| m.id)
+        .unwrap();
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(leader_url).await?;
+    let _ = maintenance_client
+        .move_leader(xlineapi::MoveLeaderRequest { target_id })
+        .await?;
+
+    keep_alive_handle.await?;
+
     let res = client.kv_client().range(RangeRequest::new("foo")).await?;
-    assert_eq!(res.kvs.len(), 1);
-    assert_eq!(res.kvs[0].value, b"bar");
+    assert_eq!(res.kvs.len(), 1, "lease should survive the leader change");
+
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("watched", "value"))
+        .await?;
+    let res = tokio::time::timeout(Duration::from_secs(3), watch_stream.message())
+        .await??
+        .unwrap();
+    assert_eq!(res.events.len(), 1);
+    assert_eq!(
+        res.events[0].kv.as_ref().unwrap().value,
+        b"value".as_slice()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_deadline_grants_and_keep_alives_are_forwarded_by_follower(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+
+    let mut follower_url = None;
+    for url in cluster.all_client_addrs() {
+        let mut etcd_client = etcd_client::Client::connect([&url], None).await?;
+        let status = etcd_client.status().await?;
+        if status.leader() != status.header().unwrap().member_id() {
+            follower_url = Some(url);
+            break;
+        }
+    }
+    let mut lease_client = xlineapi::LeaseClient::connect(follower_url.unwrap()).await?;
+
+    // both grants go through the channel the follower keeps to the leader
+    let now_ms: i64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis()
+        .try_into()?;
+    let deadline_ms = now_ms + 60_000;
+    let mut leases = Vec::new();
+    for _ in 0..2 {
+        let res = lease_client
+            .lease_grant(xlineapi::LeaseGrantRequest {
+                deadline_ms,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        assert!((59..=60).contains(&res.ttl), "unexpected ttl {}", res.ttl);
+        leases.push((res.id, res.ttl));
+    }
+
+    // the follower forwards every keep alive of the stream over one stream to the leader
+    let requests: Vec<_> = leases
+        .iter()
+        .chain(&leases)
+        .map(|&(id, _)| xlineapi::LeaseKeepAliveRequest { id })
+        .collect();
+    let mut stream = lease_client
+        .lease_keep_alive(tokio_stream::iter(requests))
+        .await?
+        .into_inner();
+    let mut responses = Vec::new();
+    while let Some(res) = stream.message().await? {
+        responses.push((res.id, res.ttl));
+    }
+    assert_eq!(responses, [leases.clone(), leases].concat());
+
+    Ok(())
+}
 
-    handle.abort();
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_time_to_live_on_follower_uses_checkpoint() -> Result<(), Box<dyn Error>> {
+    let config = lease_checkpoint_config(Duration::from_millis(500));
+    let mut cluster = Cluster::new_with_configs(vec![config; 3]).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(10))
+        .await?
+        .id;
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    let mut followers = 0;
+    for url in cluster.all_client_addrs() {
+        let mut etcd_client = etcd_client::Client::connect([url], None).await?;
+        let status = etcd_client.status().await?;
+        if status.leader() == status.header().unwrap().member_id() {
+            continue;
+        }
+        followers += 1;
+        let res = etcd_client.lease_time_to_live(lease_id, None).await?;
+        assert_eq!(res.granted_ttl(), 10);
+        // half of the ttl remains, within the checkpoint interval and rounding
+        assert!((3..=6).contains(&res.ttl()), "unexpected ttl {}", res.ttl());
+    }
+    assert_eq!(followers, 2);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_serializable_lease_time_to_live_on_follower() -> Result<(), Box<dyn Error>> {
+    let config = lease_checkpoint_config(Duration::from_millis(500));
+    let mut cluster = Cluster::new_with_configs(vec![config; 3]).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(10))
+        .await?
+        .id;
+    let _ = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar").with_lease(lease_id))
+        .await?;
     tokio::time::sleep(Duration::from_secs(2)).await;
-    let res = client.kv_client().range(RangeRequest::new("foo")).await?;
-    assert_eq!(res.kvs.len(), 0);
+
+    let leader_res = client
+        .lease_client()
+        .time_to_live(LeaseTimeToLiveRequest::new(lease_id).with_keys(true))
+        .await?;
+    let mut followers = 0;
+    for url in cluster.all_client_addrs() {
+        let mut etcd_client = etcd_client::Client::connect([&url], None).await?;
+        let status = etcd_client.status().await?;
+        if status.leader() == status.header().unwrap().member_id() {
+            continue;
+        }
+        followers += 1;
+        let mut lease_client = xlineapi::LeaseClient::connect(url).await?;
+        let res = lease_client
+            .lease_time_to_live(xlineapi::LeaseTimeToLiveRequest {
+                id: lease_id,
+                keys: true,
+                serializable: true,
+            })
+            .await?
+            .into_inner();
+        assert_eq!(res.granted_ttl, leader_res.granted_ttl);
+        assert_eq!(res.keys, leader_res.keys);
+        assert!(
+            (res.ttl - leader_res.ttl).abs() <= 1,
+            "follower ttl {} differs from leader ttl {}",
+            res.ttl,
+            leader_res.ttl
+        );
+        assert_eq!(
+            res.header.unwrap().revision,
+            leader_res.header.as_ref().unwrap().revision
+        );
+    }
+    assert_eq!(followers, 2);
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_response_header_should_follow_term_changes() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let url = cluster.get_client_url(0);
+
+    let mut cluster_client = xlineapi::ClusterClient::connect(url.clone()).await?;
+    let members = cluster_client
+        .member_list(xlineapi::MemberListRequest::default())
+        .await?
+        .into_inner()
+        .members;
+    let member_id = |name: &str| members.iter().find(|m| m.name == name).unwrap().id;
+
+    let mut lease_client = xlineapi::LeaseClient::connect(url.clone()).await?;
+    let header = lease_client
+        .lease_grant(xlineapi::LeaseGrantRequest {
+            ttl: 60,
+            id: 0,
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .header
+        .unwrap();
+    assert_ne!(header.cluster_id, 0);
+    assert!(header.raft_term > 0);
+    let (cluster_id, term) = (header.cluster_id, header.raft_term);
+
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url).await?;
+    let _ = maintenance_client
+        .move_leader(xlineapi::MoveLeaderRequest {
+            target_id: member_id("server1"),
+        })
+        .await?;
+
+    let header = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let header = lease_client
+                .lease_leases(xlineapi::LeaseLeasesRequest {})
+                .await
+                .unwrap()
+                .into_inner()
+                .header
+                .unwrap();
+            if header.raft_term > term {
+                break header;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    assert_eq!(header.cluster_id, cluster_id);
+    assert_eq!(header.member_id, member_id("server0"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_grant_non_positive_ttl_should_return_actual_ttl() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    for ttl in [0, -5] {
+        let res = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(ttl))
+            .await?;
+        assert!(res.ttl > 0, "granted ttl is {}", res.ttl);
+        for url in cluster.all_client_addrs() {
+            let mut etcd_client = etcd_client::Client::connect([url], None).await?;
+            let ttl_res = etcd_client.lease_time_to_live(res.id, None).await?;
+            assert_eq!(ttl_res.granted_ttl(), res.ttl);
+        }
+    }
+
+    Ok(())
+}
+
+/// Default config with the given lease checkpoint interval
+fn lease_checkpoint_config(interval: Duration) -> XlineServerConfig {
+    let server_timeout = ServerTimeoutBuilder::default()
+        .lease_checkpoint_interval(interval)
+        .build()
+        .unwrap();
+    Cluster::config_with(|cluster| cluster.server_timeout(server_timeout))
+}
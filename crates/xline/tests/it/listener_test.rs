@@ -0,0 +1,67 @@
+use std::{error::Error, time::Duration};
+
+use test_macros::abort_on_panic;
+use utils::config::ListenerConfig;
+use xline_test_utils::Cluster;
+
+/// Number of connect/request/disconnect cycles of a churn
+const CYCLES: usize = 2000;
+/// Number of clients churning at the same time
+const CONCURRENCY: usize = 16;
+
+/// Churn short-lived connections, each sending one range, returns the number of the
+/// failed cycles and the number of the succeeded ones
+async fn churn(url: String) -> (usize, usize) {
+    let handles: Vec<_> = (0..CONCURRENCY)
+        .map(|_| {
+            let url = url.clone();
+            tokio::spawn(async move {
+                let mut failed = 0;
+                let mut succeeded = 0;
+                for _ in 0..CYCLES / CONCURRENCY {
+                    let Ok(mut client) = xlineapi::KvClient::connect(url.clone()).await else {
+                        failed += 1;
+                        continue;
+                    };
+                    let res = client
+                        .range(xlineapi::RangeRequest {
+                            key: b"foo".to_vec(),
+                            serializable: true,
+                            ..Default::default()
+                        })
+                        .await;
+                    match res {
+                        Ok(_) => succeeded += 1,
+                        Err(_) => failed += 1,
+                    }
+                }
+                (failed, succeeded)
+            })
+        })
+        .collect();
+    let mut failed = 0;
+    let mut succeeded = 0;
+    for handle in handles {
+        let (f, s) = handle.await.unwrap();
+        failed += f;
+        succeeded += s;
+    }
+    (failed, succeeded)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_connection_churn_should_not_fail_accepts() -> Result<(), Box<dyn Error>> {
+    let untuned = ListenerConfig::new(false, 1, 1024, false, Duration::ZERO);
+    let tuned = ListenerConfig::new(false, 1, 4096, true, Duration::from_secs(30));
+    for listener_config in [untuned, tuned] {
+        let config = Cluster::config_with(|cluster| cluster.listener_config(listener_config));
+        let mut cluster = Cluster::new_with_configs(vec![config]).await;
+        cluster.start().await;
+        let (failed, succeeded) = churn(cluster.get_client_url(0)).await;
+        assert_eq!(failed, 0, "{failed} cycles failed with {listener_config:?}");
+        assert_eq!(succeeded, CYCLES / CONCURRENCY * CONCURRENCY);
+    }
+
+    Ok(())
+}
@@ -1,8 +1,17 @@
+mod admission_test;
 mod auth_test;
 mod cluster_test;
+#[cfg(feature = "embedded")]
+mod embedded_test;
+#[cfg(feature = "etcdctl-compat")]
+mod etcdctl_test;
+mod journal_test;
 mod kv_test;
 mod lease_test;
+mod listener_test;
 mod lock_test;
 mod maintenance_test;
+mod read_only_test;
 mod tls_test;
+mod tracing_test;
 mod watch_test;
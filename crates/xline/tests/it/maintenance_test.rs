@@ -1,4 +1,11 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use test_macros::abort_on_panic;
 use tokio::io::AsyncWriteExt;
@@ -9,7 +16,10 @@ use xline_test_utils::{
     types::kv::{PutRequest, RangeRequest},
     Client, ClientOptions, Cluster,
 };
-use xlineapi::{execute_error::ExecuteError, AlarmAction, AlarmRequest, AlarmType};
+use xlineapi::{
+    execute_error::ExecuteError, AlarmAction, AlarmRequest, AlarmType, CompactionExemptionRequest,
+    HashKvRequest, TimeToRevisionRequest,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -48,7 +58,7 @@ async fn test_snapshot_and_restore() -> Result<(), Box<dyn std::error::Error>> {
     let res = client.range(RangeRequest::new("key")).await?;
     assert_eq!(res.kvs.len(), 1);
     assert_eq!(res.kvs[0].key, b"key");
-    assert_eq!(res.kvs[0].value, b"value");
+    assert_eq!(res.kvs[0].value, b"value".as_slice());
     tokio::fs::remove_dir_all(&dir).await?;
     Ok(())
 }
@@ -121,3 +131,162 @@ async fn test_status() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn hash_kv_should_match_at_the_same_revision_during_writes(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cluster = Cluster::new_rocks(3).await;
+    cluster.start().await;
+    let kv_client = cluster.client().await.kv_client();
+    let stop = Arc::new(AtomicBool::new(false));
+    let workload = tokio::spawn({
+        let stop = Arc::clone(&stop);
+        async move {
+            let mut i = 0u64;
+            while !stop.load(Ordering::Relaxed) {
+                let key = format!("key{}", i % 64);
+                let _ignore = kv_client.put(PutRequest::new(key, i.to_string())).await;
+                i += 1;
+            }
+        }
+    });
+    let mut maintenance_clients = Vec::new();
+    for i in 0..3 {
+        let client = Client::connect(vec![cluster.get_client_url(i)], ClientOptions::default())
+            .await?
+            .maintenance_client();
+        maintenance_clients.push(client);
+    }
+
+    for _ in 0..10 {
+        let resp = maintenance_clients[0]
+            .hash_kv(HashKvRequest { revision: 0 })
+            .await?;
+        let revision = resp.header.unwrap().revision;
+        for client in &mut maintenance_clients[1..] {
+            // the member may not have synced the revision yet
+            let mut other = None;
+            for _ in 0..100 {
+                if let Ok(resp) = client.hash_kv(HashKvRequest { revision }).await {
+                    other = Some(resp);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let other = other.expect("the member should sync the revision");
+            assert_eq!(other.header.unwrap().revision, revision);
+            assert_eq!(
+                other.hash, resp.hash,
+                "hashes differ at revision {revision}"
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    workload.await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn range_at_time_should_see_the_state_of_that_time() -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut cluster = Cluster::new_rocks(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let kv_client = client.kv_client();
+    let mut maintenance_client = client.maintenance_client();
+
+    let before = maintenance_client
+        .time_to_revision(TimeToRevisionRequest { time_ms: 0 })
+        .await;
+    assert!(
+        before.is_err(),
+        "nothing is recorded before the first write"
+    );
+
+    let _resp = kv_client.put(PutRequest::new("key", "v1")).await?;
+    // wait for the member to apply the first write
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let at = u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis())?;
+    let _resp = kv_client.put(PutRequest::new("key", "v2")).await?;
+
+    let resolved = maintenance_client
+        .time_to_revision(TimeToRevisionRequest { time_ms: at })
+        .await?;
+    assert!(resolved.resolution_ms > 0);
+    assert!(resolved.recorded_at_ms <= at);
+    let resp = kv_client
+        .range(RangeRequest::new("key").with_revision(resolved.revision))
+        .await?;
+    assert_eq!(resp.kvs[0].value, b"v1");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn compaction_should_keep_the_history_of_exempt_prefixes(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let url = cluster.get_client_url(0);
+    let mut kv_client = xlineapi::KvClient::connect(url.clone()).await?;
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url).await?;
+
+    let resp = maintenance_client
+        .compaction_exemption(CompactionExemptionRequest {
+            add: vec![b"/audit/".to_vec()],
+            remove: vec![],
+        })
+        .await?
+        .into_inner();
+    assert_eq!(resp.prefixes, vec![b"/audit/".to_vec()]);
+
+    // the exemption takes revision 2, then 3..=12 for "/audit/a" and 13..=22 for "/other"
+    for key in ["/audit/a", "/other"] {
+        for i in 0..10 {
+            let _resp = kv_client
+                .put(xlineapi::PutRequest {
+                    key: key.into(),
+                    value: i.to_string().into_bytes(),
+                    ..Default::default()
+                })
+                .await?;
+        }
+    }
+    let _resp = kv_client
+        .compact(xlineapi::CompactionRequest {
+            revision: 22,
+            physical: true,
+        })
+        .await?;
+
+    let resp = kv_client
+        .range(xlineapi::RangeRequest {
+            key: b"/audit/a".to_vec(),
+            revision: 6,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(resp.kvs[0].value, b"3");
+
+    for (key, range_end, revision) in [("/other", "", 21), ("/", "0", 6)] {
+        let err = kv_client
+            .range(xlineapi::RangeRequest {
+                key: key.into(),
+                range_end: range_end.into(),
+                revision,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::OutOfRange);
+        assert_eq!(err.metadata().get("compact-revision").unwrap(), "22");
+    }
+
+    Ok(())
+}
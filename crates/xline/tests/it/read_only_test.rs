@@ -0,0 +1,122 @@
+use std::{error::Error, time::Duration};
+
+use test_macros::abort_on_panic;
+use utils::config::XlineServerConfig;
+use xline_test_utils::{types::kv::PutRequest, Cluster};
+
+/// Error message returned by a read-only node
+const READ_ONLY_ERR_MSG: &str = "xline: node is in read-only mode";
+
+fn assert_read_only_err(status: &tonic::Status) {
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition, "{status:?}");
+    assert_eq!(status.message(), READ_ONLY_ERR_MSG);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_read_only_node_rejects_mutations() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(vec![
+        XlineServerConfig::default(),
+        XlineServerConfig::default(),
+        Cluster::config_with(|cluster| cluster.read_only(true)),
+    ])
+    .await;
+    cluster.start().await;
+    let read_only_url = cluster.get_client_url(2);
+
+    let mut kv_client = xlineapi::KvClient::connect(read_only_url.clone()).await?;
+    let err = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_read_only_err(&err);
+    let err = kv_client
+        .delete_range(xlineapi::DeleteRangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_read_only_err(&err);
+
+    let mut lease_client = xlineapi::LeaseClient::connect(read_only_url.clone()).await?;
+    let err = lease_client
+        .lease_grant(xlineapi::LeaseGrantRequest {
+            ttl: 60,
+            id: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_read_only_err(&err);
+    let err = lease_client
+        .lease_keep_alive(tokio_stream::iter([xlineapi::LeaseKeepAliveRequest {
+            id: 1,
+        }]))
+        .await
+        .unwrap_err();
+    assert_read_only_err(&err);
+
+    let mut cluster_client = xlineapi::ClusterClient::connect(read_only_url.clone()).await?;
+    let err = cluster_client
+        .member_remove(xlineapi::MemberRemoveRequest { id: 1 })
+        .await
+        .unwrap_err();
+    assert_read_only_err(&err);
+
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(read_only_url).await?;
+    let status = maintenance_client
+        .status(xlineapi::StatusRequest::default())
+        .await?
+        .into_inner();
+    assert!(status.errors.iter().any(|e| e == READ_ONLY_ERR_MSG));
+    assert_ne!(status.leader, status.header.unwrap().member_id);
+    let _hash = maintenance_client
+        .hash_kv(xlineapi::HashKvRequest { revision: 0 })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_read_only_node_applies_entries_from_others() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(vec![
+        XlineServerConfig::default(),
+        XlineServerConfig::default(),
+        Cluster::config_with(|cluster| cluster.read_only(true)),
+    ])
+    .await;
+    cluster.start().await;
+    let read_only_url = cluster.get_client_url(2);
+    let client = cluster.client().await;
+    for i in 0..10 {
+        let _ignore = client
+            .kv_client()
+            .put(PutRequest::new(format!("key{i}"), "value"))
+            .await?;
+    }
+
+    let mut kv_client = xlineapi::KvClient::connect(read_only_url).await?;
+    let range_req = xlineapi::RangeRequest {
+        key: b"key".to_vec(),
+        range_end: b"kez".to_vec(),
+        serializable: true,
+        ..Default::default()
+    };
+    let mut count = 0;
+    for _ in 0..50 {
+        count = kv_client.range(range_req.clone()).await?.into_inner().count;
+        if count == 10 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(count, 10);
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use opentelemetry::{
+    global,
+    trace::{TraceContextExt, TraceId, TracerProvider as _},
+};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+use test_macros::abort_on_panic;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+use xline_test_utils::Cluster;
+
+/// A span recorded by the `SpanCollector`
+#[derive(Debug, Clone)]
+struct CollectedSpan {
+    /// Name of the span
+    name: &'static str,
+    /// Id of the parent span
+    parent: Option<u64>,
+    /// The `propose_id` field of the span
+    propose_id: Option<String>,
+    /// Whether the span is closed
+    closed: bool,
+    /// The trace id of the span, taken once it's closed
+    trace_id: Option<TraceId>,
+}
+
+/// Layer that keeps all created spans in memory
+#[derive(Debug, Clone, Default)]
+struct SpanCollector {
+    /// Spans indexed by their ids
+    spans: Arc<Mutex<HashMap<u64, CollectedSpan>>>,
+}
+
+/// Visitor that picks the `propose_id` field
+struct ProposeIdVisitor(Option<String>);
+
+impl Visit for ProposeIdVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "propose_id" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl<S> Layer<S> for SpanCollector
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = ProposeIdVisitor(None);
+        attrs.record(&mut visitor);
+        let parent = ctx
+            .span(id)
+            .and_then(|span| span.parent())
+            .map(|parent| parent.id().into_u64());
+        let span = CollectedSpan {
+            name: attrs.metadata().name(),
+            parent,
+            propose_id: visitor.0,
+            closed: false,
+            trace_id: None,
+        };
+        let _ignore = self.spans.lock().unwrap().insert(id.into_u64(), span);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        // the remote parent of a span may be set after it's created, so the trace id is
+        // taken when it's closed, before the opentelemetry layer drops its data
+        let trace_id = ctx.span(&id).and_then(|span| {
+            span.extensions().get::<OtelData>().map(|data| {
+                if data.parent_cx.has_active_span() {
+                    data.parent_cx.span().span_context().trace_id()
+                } else {
+                    data.builder.trace_id.unwrap_or(TraceId::INVALID)
+                }
+            })
+        });
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.closed = true;
+            span.trace_id = trace_id;
+        }
+    }
+}
+
+impl SpanCollector {
+    /// Check whether the span `id` is a descendant of a span matching `pred`
+    fn has_ancestor(
+        spans: &HashMap<u64, CollectedSpan>,
+        id: u64,
+        pred: impl Fn(&CollectedSpan) -> bool,
+    ) -> bool {
+        let mut parent = spans.get(&id).and_then(|span| span.parent);
+        while let Some(pid) = parent {
+            let Some(span) = spans.get(&pid) else {
+                return false;
+            };
+            if pred(span) {
+                return true;
+            }
+            parent = span.parent;
+        }
+        false
+    }
+
+    /// Check whether every stage of the proposal has a span under its propose span
+    fn all_stages_traced(&self) -> Option<String> {
+        let spans = self.spans.lock().unwrap();
+        let (_, client_propose) = spans.iter().find(|&(&id, span)| {
+            span.name == "client_propose" && Self::has_ancestor(&spans, id, |s| s.name == "put")
+        })?;
+        let propose_id = client_propose.propose_id.clone()?;
+        let is_propose_of = |s: &CollectedSpan| {
+            s.name == "curp_propose" && s.propose_id.as_ref() == Some(&propose_id)
+        };
+        let rpc_propose_traced = spans.iter().any(|(&id, span)| {
+            is_propose_of(span)
+                && Self::has_ancestor(&spans, id, |s| {
+                    s.name == "client_propose" && s.propose_id.as_ref() == Some(&propose_id)
+                })
+        });
+        if !rpc_propose_traced {
+            return None;
+        }
+        let stage_traced = |name: &str| {
+            spans.iter().any(|(&id, span)| {
+                span.name == name
+                    && span.propose_id.as_ref() == Some(&propose_id)
+                    && Self::has_ancestor(&spans, id, is_propose_of)
+            })
+        };
+        ["curp_replicate", "curp_execute", "curp_after_sync"]
+            .into_iter()
+            .all(stage_traced)
+            .then_some(propose_id)
+    }
+
+    /// Get the trace ids of the propose spans of a proposal and of the stages under
+    /// them, `None` if some of them are not closed yet
+    fn closed_trace_ids(&self, propose_id: &str) -> Option<Vec<(&'static str, TraceId)>> {
+        let spans = self.spans.lock().unwrap();
+        let is_propose_of = |s: &CollectedSpan| {
+            s.name == "curp_propose" && s.propose_id.as_deref() == Some(propose_id)
+        };
+        spans
+            .iter()
+            .filter(|&(&id, span)| {
+                span.propose_id.as_deref() == Some(propose_id)
+                    && (matches!(span.name, "client_propose" | "curp_propose")
+                        || Self::has_ancestor(&spans, id, is_propose_of))
+            })
+            .map(|(_, span)| {
+                span.closed
+                    .then(|| (span.name, span.trace_id.unwrap_or(TraceId::INVALID)))
+            })
+            .collect()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_put_is_traced_from_rpc_to_after_sync() -> Result<(), Box<dyn Error>> {
+    const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = TracerProvider::builder().build();
+    let collector = SpanCollector::default();
+    // the collector is closer to the registry, it sees a closed span before the
+    // opentelemetry layer
+    tracing_subscriber::registry()
+        .with(collector.clone())
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("tracing_test")))
+        .try_init()?;
+
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let mut request = tonic::Request::new(xlineapi::PutRequest {
+        key: b"foo".to_vec(),
+        value: b"bar".to_vec(),
+        ..Default::default()
+    });
+    let _ignore = request.metadata_mut().insert(
+        "traceparent",
+        format!("00-{TRACE_ID}-b7ad6b7169203331-01").parse()?,
+    );
+    let _resp = kv_client.put(request).await?;
+
+    // the after sync may finish after the fast path response
+    let mut propose_id = None;
+    for _ in 0..50 {
+        propose_id = collector.all_stages_traced();
+        if propose_id.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let propose_id = propose_id.expect("stages of the put are not traced");
+
+    // the propose spans of all members and the stages under them are closed, and carry
+    // the trace of the client
+    let mut trace_ids = None;
+    for _ in 0..50 {
+        trace_ids = collector.closed_trace_ids(&propose_id);
+        if trace_ids.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let trace_ids = trace_ids.expect("spans of the put are not closed");
+    let expected = TraceId::from_hex(TRACE_ID)?;
+    for (name, trace_id) in trace_ids {
+        assert_eq!(
+            trace_id, expected,
+            "span {name} is not in the trace of the client"
+        );
+    }
+
+    Ok(())
+}
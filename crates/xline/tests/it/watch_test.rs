@@ -4,7 +4,7 @@ use test_macros::abort_on_panic;
 use xline_test_utils::{
     types::{
         kv::{DeleteRangeRequest, PutRequest},
-        watch::WatchRequest,
+        watch::{WatchEvent, WatchRequest},
     },
     Cluster,
 };
@@ -29,19 +29,19 @@ async fn test_watch() -> Result<(), Box<dyn Error>> {
 
     let (_watcher, mut stream) = watch_client.watch(WatchRequest::new("foo")).await?;
     let handle = tokio::spawn(async move {
-        if let Ok(Some(res)) = stream.message().await {
-            let event = res.events.get(0).unwrap();
+        if let Ok(Some(WatchEvent::Events { events, .. })) = stream.message().await {
+            let event = events.get(0).unwrap();
             assert_eq!(event_type(event.r#type), EventType::Put);
             let kv = event.kv.clone().unwrap();
             assert_eq!(kv.key, b"foo");
-            assert_eq!(kv.value, b"bar");
+            assert_eq!(kv.value, b"bar".as_slice());
         }
-        if let Ok(Some(res)) = stream.message().await {
-            let event = res.events.get(0).unwrap();
+        if let Ok(Some(WatchEvent::Events { events, .. })) = stream.message().await {
+            let event = events.get(0).unwrap();
             let kv = event.kv.clone().unwrap();
             assert_eq!(event_type(event.r#type), EventType::Delete);
             assert_eq!(kv.key, b"foo");
-            assert_eq!(kv.value, b"");
+            assert_eq!(kv.value, b"".as_slice());
         }
     });
 
@@ -0,0 +1,60 @@
+#![cfg(bench)]
+#![feature(test)]
+
+extern crate test;
+extern crate xlineapi;
+
+use std::hint::black_box;
+
+use curp_external_api::cmd::PbCodec;
+use prost::Message;
+use test::Bencher;
+use xlineapi::{
+    command::Command, PbCommand, PutRequest, Request, RequestOp, RequestWrapper, TxnRequest,
+};
+
+/// Size of the values put by the benchmarked requests
+const VALUE_SIZE: usize = 64 * 1024;
+
+/// Number of puts in the benchmarked txn
+const PUTS: usize = 16;
+
+fn txn_request() -> RequestWrapper {
+    let success = (0..PUTS)
+        .map(|i| RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: format!("key{i}").into_bytes(),
+                value: vec![0; VALUE_SIZE],
+                ..Default::default()
+            })),
+        })
+        .collect();
+    RequestWrapper::TxnRequest(TxnRequest {
+        success,
+        ..Default::default()
+    })
+}
+
+/// A command carrying its conflict metadata, the pools use it as is
+#[bench]
+fn bench_decode_with_carried_meta(b: &mut Bencher) {
+    let buf = Command::new(txn_request()).encode();
+    b.iter(|| {
+        let cmd = Command::decode(black_box(&buf)).unwrap();
+        black_box(cmd.conflict_meta().intervals().len())
+    });
+}
+
+/// A command from an older client, the metadata is computed from the request
+#[bench]
+fn bench_decode_and_compute_meta(b: &mut Bencher) {
+    let buf = PbCommand {
+        request_wrapper: Some(txn_request()),
+        ..Default::default()
+    }
+    .encode_to_vec();
+    b.iter(|| {
+        let cmd = Command::decode(black_box(&buf)).unwrap();
+        black_box(cmd.conflict_meta().intervals().len())
+    });
+}
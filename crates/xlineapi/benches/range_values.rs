@@ -0,0 +1,73 @@
+#![cfg(bench)]
+#![feature(test)]
+
+extern crate test;
+extern crate xlineapi;
+
+use std::hint::black_box;
+
+use bytes::Bytes;
+use prost::Message;
+use test::Bencher;
+use xlineapi::{KeyValue, RangeResponse};
+
+/// Size of the values of the ranged key-values
+const VALUE_SIZE: usize = 64 * 1024;
+
+/// Number of key-values in the benchmarked range
+const KVS: usize = 16;
+
+/// The key-values of the range as they are read from the engine
+fn stored_kvs() -> Vec<Bytes> {
+    (0..KVS)
+        .map(|i| {
+            KeyValue {
+                key: format!("key{i}").into_bytes(),
+                value: vec![0; VALUE_SIZE].into(),
+                create_revision: 1,
+                mod_revision: i as i64 + 1,
+                version: 1,
+                ..Default::default()
+            }
+            .encode_to_vec()
+            .into()
+        })
+        .collect()
+}
+
+/// Encode a range response of the decoded key-values
+fn encode_range(kvs: Vec<KeyValue>) -> Vec<u8> {
+    RangeResponse {
+        count: kvs.len() as i64,
+        kvs,
+        ..Default::default()
+    }
+    .encode_to_vec()
+}
+
+/// The values are decoded from the shared buffers read from the engine
+#[bench]
+fn bench_range_shared_values(b: &mut Bencher) {
+    let stored = stored_kvs();
+    b.iter(|| {
+        let kvs = black_box(&stored)
+            .iter()
+            .map(|kv| KeyValue::decode(kv.clone()).unwrap())
+            .collect();
+        black_box(encode_range(kvs))
+    });
+}
+
+/// The values are copied out of the buffers read from the engine, as they were before
+/// `KeyValue` held them as `Bytes`
+#[bench]
+fn bench_range_copied_values(b: &mut Bencher) {
+    let stored = stored_kvs();
+    b.iter(|| {
+        let kvs = black_box(&stored)
+            .iter()
+            .map(|kv| KeyValue::decode(kv.as_ref()).unwrap())
+            .collect();
+        black_box(encode_range(kvs))
+    });
+}
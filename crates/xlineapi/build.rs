@@ -1,7 +1,11 @@
 fn main() {
+    // Values are shared between storage, watch events and responses without copying
+    let mut prost_config = prost_build::Config::new();
+    prost_config.bytes([".mvccpb.KeyValue.value"]);
     tonic_build::configure()
         .type_attribute(".", "#[derive(serde::Deserialize, serde::Serialize)]")
-        .compile(
+        .compile_with_config(
+            prost_config,
             &[
                 "proto/src/kv.proto",
                 "proto/src/rpc.proto",
@@ -1,17 +1,20 @@
 use std::{
     collections::{HashSet, VecDeque},
     ops::{Bound, RangeBounds},
+    sync::OnceLock,
 };
 
-use curp::{client::ClientApi, cmd::Command as CurpCommand};
+use curp::{client::ClientApi, cmd::Command as CurpCommand, rpc::ProposeId};
 use curp_external_api::cmd::{ConflictCheck, PbCodec, PbSerializeError};
 use itertools::Itertools;
 use prost::Message;
 use serde::{Deserialize, Serialize};
+use utils::interval_map::Interval;
 
 use crate::{
-    execute_error::ExecuteError, AuthInfo, PbCommand, PbCommandResponse, PbKeyRange,
-    PbSyncResponse, Request, RequestWrapper, ResponseWrapper,
+    execute_error::ExecuteError, interval::BytesAffine, AuthInfo, PbCommand, PbCommandResponse,
+    PbConflictMeta, PbKeyRange, PbSyncResponse, Request, RequestBackend, RequestWrapper,
+    ResponseWrapper, Ticket,
 };
 
 /// The curp client trait object on the command of xline
@@ -50,13 +53,30 @@ impl KeyRange {
         KeyRange { key, range_end }
     }
 
+    /// New canonical `KeyRange`, the range is taken as valid, the empty key with the
+    /// `\0` range end is the same as all keys and the `\0` key with the empty range end
+    /// is the single key `\0`
+    #[inline]
+    pub fn canonical(start: impl Into<Vec<u8>>, end: impl Into<Vec<u8>>) -> Self {
+        let key = start.into();
+        let range_end = end.into();
+        match (key.as_slice(), range_end.as_slice()) {
+            ([] | UNBOUNDED, UNBOUNDED) => Self::all(),
+            (UNBOUNDED, ONE_KEY) => Self {
+                key: Bound::Included(key.clone()),
+                range_end: Bound::Included(key),
+            },
+            _ => Self::new(key, range_end),
+        }
+    }
+
     /// New `KeyRange` only contains one key
     ///
     /// # Panics
     ///
     /// Will panic if key is equal to `UNBOUNDED`
     #[inline]
-    pub fn new_one_key(key: impl Into<Vec<u8>>) -> Self {
+    pub fn single(key: impl Into<Vec<u8>>) -> Self {
         let key_vec = key.into();
         assert!(
             key_vec.as_slice() != UNBOUNDED,
@@ -68,10 +88,70 @@ impl KeyRange {
         }
     }
 
-    /// Return if `KeyRange` is conflicted with another
+    /// New `KeyRange` only contains one key
+    ///
+    /// # Panics
+    ///
+    /// Will panic if key is equal to `UNBOUNDED`
+    #[deprecated(note = "use `KeyRange::single` instead")]
+    #[inline]
+    pub fn new_one_key(key: impl Into<Vec<u8>>) -> Self {
+        Self::single(key)
+    }
+
+    /// New `KeyRange` contains all keys with the given prefix, an empty prefix
+    /// matches all keys
+    #[inline]
+    pub fn prefix(prefix: impl Into<Vec<u8>>) -> Self {
+        let key = prefix.into();
+        if key.is_empty() {
+            return Self::all();
+        }
+        let range_end = Self::get_prefix(&key);
+        Self::new(key, range_end)
+    }
+
+    /// New `KeyRange` contains all keys that are equal or greater than the given key
+    #[inline]
+    pub fn from_key(key: impl Into<Vec<u8>>) -> Self {
+        let key = key.into();
+        if key.is_empty() {
+            return Self::all();
+        }
+        Self::new(key, UNBOUNDED)
+    }
+
+    /// New `KeyRange` contains all keys
     #[must_use]
     #[inline]
-    pub fn is_conflicted(&self, other: &Self) -> bool {
+    pub fn all() -> Self {
+        Self {
+            key: Bound::Unbounded,
+            range_end: Bound::Unbounded,
+        }
+    }
+
+    /// Whether the `KeyRange` contains only one key
+    #[must_use]
+    #[inline]
+    pub fn is_single(&self) -> bool {
+        matches!(self.range_end, Bound::Included(_))
+    }
+
+    /// Whether the `KeyRange` contains all keys
+    #[must_use]
+    #[inline]
+    pub fn is_all(&self) -> bool {
+        matches!(
+            (&self.key, &self.range_end),
+            (Bound::Unbounded, Bound::Unbounded)
+        )
+    }
+
+    /// Return if `KeyRange` has any key in common with another
+    #[must_use]
+    #[inline]
+    pub fn intersects(&self, other: &Self) -> bool {
         // s1 < s2 ?
         if match (self.start_bound(), other.start_bound()) {
             (Bound::Included(s1), Bound::Included(s2)) => {
@@ -109,10 +189,18 @@ impl KeyRange {
         }
     }
 
+    /// Return if `KeyRange` has any key in common with another
+    #[deprecated(note = "use `KeyRange::intersects` instead")]
+    #[must_use]
+    #[inline]
+    pub fn is_conflicted(&self, other: &Self) -> bool {
+        self.intersects(other)
+    }
+
     /// Check if `KeyRange` contains a key
     #[must_use]
     #[inline]
-    pub fn contains_key(&self, key: &[u8]) -> bool {
+    pub fn contains(&self, key: &[u8]) -> bool {
         (match self.start_bound() {
             Bound::Included(start) => start.as_slice() <= key,
             Bound::Excluded(start) => start.as_slice() < key,
@@ -124,6 +212,40 @@ impl KeyRange {
         })
     }
 
+    /// Check if `KeyRange` contains a key
+    #[deprecated(note = "use `KeyRange::contains` instead")]
+    #[must_use]
+    #[inline]
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.contains(key)
+    }
+
+    /// Get the keys in common with another `KeyRange`, return `None` if there is none
+    #[must_use]
+    #[inline]
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.intersects(other) {
+            return None;
+        }
+        // both start bounds are `Included` or `Unbounded`, take the larger one
+        let key = match (&self.key, &other.key) {
+            (&Bound::Unbounded, k) | (k, &Bound::Unbounded) => k.clone(),
+            (&Bound::Included(ref k1), &Bound::Included(ref k2)) => {
+                Bound::Included(k1.max(k2).clone())
+            }
+            _ => unreachable!("KeyRange::start_bound() cannot be Excluded"),
+        };
+        // a single key range lies inside the other one as they intersect
+        let range_end = match (&self.range_end, &other.range_end) {
+            (e @ &Bound::Included(_), _) | (_, e @ &Bound::Included(_)) => e.clone(),
+            (&Bound::Unbounded, e) | (e, &Bound::Unbounded) => e.clone(),
+            (&Bound::Excluded(ref e1), &Bound::Excluded(ref e2)) => {
+                Bound::Excluded(e1.min(e2).clone())
+            }
+        };
+        Some(Self { key, range_end })
+    }
+
     /// Get end of range with prefix
     /// User will provide a start key when prefix is true, we need calculate the end key of `KeyRange`
     #[allow(clippy::indexing_slicing)] // end[i] is always valid
@@ -209,7 +331,7 @@ impl From<KeyRange> for PbKeyRange {
 }
 
 /// Command to run consensus protocol
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Command {
     /// Request data
     request: RequestWrapper,
@@ -217,6 +339,158 @@ pub struct Command {
     compact_id: u64,
     /// Auth info
     auth_info: Option<AuthInfo>,
+    /// Conflict metadata derived from the request, it's serialized apart from the command
+    /// so that the encoding of the command stays the same. A decoded command takes the
+    /// metadata carried along with it, or computes it again on first use.
+    #[serde(skip)]
+    conflict_meta: OnceLock<ConflictMeta>,
+}
+
+impl PartialEq for Command {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        // the conflict metadata is derived from the request
+        self.request == other.request
+            && self.compact_id == other.compact_id
+            && self.auth_info == other.auth_info
+    }
+}
+
+/// What the conflict pools need to know about a command, it's computed once when the
+/// command is built, so that the pools never walk the request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictMeta {
+    /// Key ranges of the request as intervals
+    intervals: Vec<Interval<BytesAffine>>,
+    /// Lease ids of the request, sorted
+    lease_ids: Vec<i64>,
+    /// Backend of the request
+    backend: RequestBackend,
+    /// Whether the request conflicts with all other requests
+    exclusive: bool,
+}
+
+impl ConflictMeta {
+    /// Compute the conflict metadata of a request
+    #[inline]
+    #[must_use]
+    pub fn new(request: &RequestWrapper) -> Self {
+        Self {
+            intervals: request.keys().into_iter().map(Into::into).collect(),
+            lease_ids: get_lease_ids(request).into_iter().sorted().collect(),
+            backend: request.backend(),
+            exclusive: is_exclusive_request(request),
+        }
+    }
+
+    /// Key ranges of the request as intervals
+    #[inline]
+    #[must_use]
+    pub fn intervals(&self) -> &[Interval<BytesAffine>] {
+        &self.intervals
+    }
+
+    /// Lease ids of the request, sorted
+    #[inline]
+    #[must_use]
+    pub fn lease_ids(&self) -> &[i64] {
+        &self.lease_ids
+    }
+
+    /// Backend of the request
+    #[inline]
+    #[must_use]
+    pub fn backend(&self) -> RequestBackend {
+        self.backend
+    }
+
+    /// Whether the request conflicts with all other requests
+    #[inline]
+    #[must_use]
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Decode the conflict metadata carried in a `PbCommand`, returns `None` if its
+    /// backend is unknown or one of its ranges is empty
+    fn from_pb(meta: PbConflictMeta) -> Option<Self> {
+        let backend = match meta.backend {
+            0 => RequestBackend::Kv,
+            1 => RequestBackend::Auth,
+            2 => RequestBackend::Lease,
+            3 => RequestBackend::Alarm,
+            _ => return None,
+        };
+        let mut intervals = Vec::with_capacity(meta.ranges.len());
+        for range in meta.ranges {
+            let low = BytesAffine::Bytes(range.key);
+            let high = if range.range_end.as_slice() == UNBOUNDED {
+                BytesAffine::Unbounded
+            } else {
+                BytesAffine::Bytes(range.range_end)
+            };
+            if low >= high {
+                return None;
+            }
+            intervals.push(Interval::new(low, high));
+        }
+        Some(Self {
+            intervals,
+            lease_ids: meta.lease_ids,
+            backend,
+            exclusive: meta.exclusive,
+        })
+    }
+}
+
+impl From<&ConflictMeta> for PbConflictMeta {
+    #[inline]
+    fn from(meta: &ConflictMeta) -> Self {
+        let bytes = |affine: &BytesAffine| match *affine {
+            BytesAffine::Bytes(ref bytes) => bytes.clone(),
+            BytesAffine::Unbounded => UNBOUNDED.to_vec(),
+        };
+        Self {
+            ranges: meta
+                .intervals
+                .iter()
+                .map(|interval| PbKeyRange {
+                    key: bytes(&interval.low),
+                    range_end: bytes(&interval.high),
+                })
+                .collect(),
+            lease_ids: meta.lease_ids.clone(),
+            backend: match meta.backend {
+                RequestBackend::Kv => 0,
+                RequestBackend::Auth => 1,
+                RequestBackend::Lease => 2,
+                RequestBackend::Alarm => 3,
+            },
+            exclusive: meta.exclusive,
+        }
+    }
+}
+
+/// Returns `true` if the request conflicts with all other requests
+fn is_exclusive_request(request: &RequestWrapper) -> bool {
+    matches!(
+        *request,
+        RequestWrapper::CompactionRequest(_)
+            | RequestWrapper::CompactionExemptionRequest(_)
+            | RequestWrapper::AuthEnableRequest(_)
+            | RequestWrapper::AuthDisableRequest(_)
+            | RequestWrapper::AuthRoleAddRequest(_)
+            | RequestWrapper::AuthRoleDeleteRequest(_)
+            | RequestWrapper::AuthRoleGrantPermissionRequest(_)
+            | RequestWrapper::AuthRoleRevokePermissionRequest(_)
+            | RequestWrapper::AuthUserAddRequest(_)
+            | RequestWrapper::AuthUserChangePasswordRequest(_)
+            | RequestWrapper::AuthUserDeleteRequest(_)
+            | RequestWrapper::AuthUserGrantRoleRequest(_)
+            | RequestWrapper::AuthUserRevokeRoleRequest(_)
+            | RequestWrapper::AuthenticateRequest(_)
+            | RequestWrapper::AlarmRequest(_)
+    )
 }
 
 /// get all lease ids in the request wrapper
@@ -224,6 +498,10 @@ pub fn get_lease_ids(wrapper: &RequestWrapper) -> HashSet<i64> {
     match *wrapper {
         RequestWrapper::LeaseGrantRequest(ref req) => HashSet::from_iter(vec![req.id]),
         RequestWrapper::LeaseRevokeRequest(ref req) => HashSet::from_iter(vec![req.id]),
+        RequestWrapper::LeaseCheckpointRequest(ref req) => {
+            req.checkpoints.iter().map(|cp| cp.id).collect()
+        }
+        RequestWrapper::LeaseRevokeBatchRequest(ref req) => req.ids.iter().copied().collect(),
         RequestWrapper::PutRequest(ref req) if req.lease != 0 => {
             HashSet::from_iter(vec![req.lease])
         }
@@ -258,6 +536,7 @@ pub fn get_lease_ids(wrapper: &RequestWrapper) -> HashSet<i64> {
         | RequestWrapper::RangeRequest(_)
         | RequestWrapper::DeleteRangeRequest(_)
         | RequestWrapper::CompactionRequest(_)
+        | RequestWrapper::CompactionExemptionRequest(_)
         | RequestWrapper::AuthEnableRequest(_)
         | RequestWrapper::AuthDisableRequest(_)
         | RequestWrapper::AuthStatusRequest(_)
@@ -339,7 +618,7 @@ impl ConflictCheck for Command {
             .keys()
             .iter()
             .cartesian_product(other.keys().iter())
-            .any(|(k1, k2)| k1.is_conflicted(k2));
+            .any(|(k1, k2)| k1.intersects(k2));
         lease_conflict || key_conflict
     }
 }
@@ -347,7 +626,7 @@ impl ConflictCheck for Command {
 impl ConflictCheck for KeyRange {
     #[inline]
     fn is_conflict(&self, other: &Self) -> bool {
-        self.is_conflicted(other)
+        self.intersects(other)
     }
 }
 
@@ -356,11 +635,7 @@ impl Command {
     #[must_use]
     #[inline]
     pub fn new(request: RequestWrapper) -> Self {
-        Self {
-            request,
-            compact_id: 0,
-            auth_info: None,
-        }
+        Self::new_with_auth_info(request, None)
     }
 
     /// New `Command` with auth info
@@ -368,6 +643,7 @@ impl Command {
     #[inline]
     pub fn new_with_auth_info(request: RequestWrapper, auth_info: Option<AuthInfo>) -> Self {
         Self {
+            conflict_meta: OnceLock::from(ConflictMeta::new(&request)),
             request,
             compact_id: 0,
             auth_info,
@@ -396,6 +672,14 @@ impl Command {
         &self.request
     }
 
+    /// Get the conflict metadata
+    #[must_use]
+    #[inline]
+    pub fn conflict_meta(&self) -> &ConflictMeta {
+        self.conflict_meta
+            .get_or_init(|| ConflictMeta::new(&self.request))
+    }
+
     /// get auth_info
     #[must_use]
     #[inline]
@@ -409,6 +693,12 @@ impl Command {
         self.auth_info = Some(auth_info)
     }
 
+    /// clear auth_info
+    #[inline]
+    pub fn clear_auth_info(&mut self) {
+        self.auth_info = None;
+    }
+
     /// need check quota
     #[must_use]
     #[inline]
@@ -469,25 +759,45 @@ impl CommandResponse {
 
 /// Sync Response
 #[cfg_attr(test, derive(PartialEq, Eq))]
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncResponse {
     /// Revision of this request
     revision: i64,
+    /// Sub revisions assigned to the mutations of a txn in order, empty for the others
+    sub_revisions: Vec<i64>,
 }
 impl SyncResponse {
     /// New `SyncRequest`
     #[inline]
     #[must_use]
     pub fn new(revision: i64) -> Self {
-        Self { revision }
+        Self {
+            revision,
+            sub_revisions: Vec::new(),
+        }
+    }
+
+    /// Set the sub revisions of the mutations of a txn
+    #[inline]
+    #[must_use]
+    pub fn with_sub_revisions(mut self, sub_revisions: Vec<i64>) -> Self {
+        self.sub_revisions = sub_revisions;
+        self
     }
 
     /// Get revision field
     #[inline]
     #[must_use]
-    pub fn revision(self) -> i64 {
+    pub fn revision(&self) -> i64 {
         self.revision
     }
+
+    /// Get the sub revisions of the mutations of a txn
+    #[inline]
+    #[must_use]
+    pub fn sub_revisions(&self) -> &[i64] {
+        &self.sub_revisions
+    }
 }
 
 impl From<PbSyncResponse> for SyncResponse {
@@ -495,6 +805,7 @@ impl From<PbSyncResponse> for SyncResponse {
     fn from(resp: PbSyncResponse) -> Self {
         Self {
             revision: resp.revision,
+            sub_revisions: resp.sub_revisions,
         }
     }
 }
@@ -504,14 +815,32 @@ impl From<SyncResponse> for PbSyncResponse {
     fn from(resp: SyncResponse) -> Self {
         Self {
             revision: resp.revision,
+            sub_revisions: resp.sub_revisions,
+        }
+    }
+}
+
+impl From<ProposeId> for Ticket {
+    #[inline]
+    fn from(id: ProposeId) -> Self {
+        Self {
+            client_id: id.0,
+            seq_num: id.1,
         }
     }
 }
 
+impl From<Ticket> for ProposeId {
+    #[inline]
+    fn from(ticket: Ticket) -> Self {
+        Self(ticket.client_id, ticket.seq_num)
+    }
+}
+
 impl PbCodec for SyncResponse {
     #[inline]
     fn encode(&self) -> Vec<u8> {
-        PbSyncResponse::from(*self).encode_to_vec()
+        PbSyncResponse::from(self.clone()).encode_to_vec()
     }
 
     #[inline]
@@ -527,16 +856,38 @@ impl CurpCommand for Command {
     type PR = i64;
     type ER = CommandResponse;
     type ASR = SyncResponse;
+    type ConflictMeta = ConflictMeta;
 
     #[inline]
     fn keys(&self) -> Vec<Self::K> {
         self.request().keys()
     }
 
+    #[inline]
+    fn conflict_meta(&self) -> &ConflictMeta {
+        Command::conflict_meta(self)
+    }
+
+    #[inline]
+    fn with_conflict_meta(self, meta: ConflictMeta) -> Self {
+        let _ignore = self.conflict_meta.set(meta);
+        self
+    }
+
     #[inline]
     fn is_read_only(&self) -> bool {
         self.request().is_read_only()
     }
+
+    #[inline]
+    fn kind(&self) -> &'static str {
+        match self.request().backend() {
+            RequestBackend::Kv => "kv",
+            RequestBackend::Auth => "auth",
+            RequestBackend::Lease => "lease",
+            RequestBackend::Alarm => "alarm",
+        }
+    }
 }
 
 impl PbCodec for Command {
@@ -546,6 +897,7 @@ impl PbCodec for Command {
             compact_id: self.compact_id,
             auth_info: self.auth_info.clone(),
             request_wrapper: Some(self.request.clone()),
+            conflict_meta: Some(self.conflict_meta().into()),
         };
         rpc_cmd.encode_to_vec()
     }
@@ -553,12 +905,19 @@ impl PbCodec for Command {
     #[inline]
     fn decode(buf: &[u8]) -> Result<Self, PbSerializeError> {
         let rpc_cmd = PbCommand::decode(buf)?;
+        let request = rpc_cmd
+            .request_wrapper
+            .ok_or(PbSerializeError::EmptyField)?;
+        // commands from older clients don't carry the metadata, it's computed on first use
+        let conflict_meta = rpc_cmd
+            .conflict_meta
+            .and_then(ConflictMeta::from_pb)
+            .map_or_else(OnceLock::new, OnceLock::from);
         Ok(Self {
+            request,
             compact_id: rpc_cmd.compact_id,
             auth_info: rpc_cmd.auth_info,
-            request: rpc_cmd
-                .request_wrapper
-                .ok_or(PbSerializeError::EmptyField)?,
+            conflict_meta,
         })
     }
 }
@@ -575,8 +934,8 @@ mod test {
     #[test]
     fn test_key_range_conflict() {
         let kr1 = KeyRange::new("a", "e");
-        let kr2 = KeyRange::new_one_key("c");
-        let kr3 = KeyRange::new_one_key("z");
+        let kr2 = KeyRange::single("c");
+        let kr3 = KeyRange::single("z");
         assert!(kr1.is_conflict(&kr2));
         assert!(!kr1.is_conflict(&kr3));
     }
@@ -591,17 +950,181 @@ mod test {
     #[test]
     fn test_key_range_contains() {
         let kr1 = KeyRange::new("a", "e");
-        assert!(kr1.contains_key(b"b"));
-        assert!(!kr1.contains_key(b"e"));
-        let kr2 = KeyRange::new_one_key("c");
-        assert!(kr2.contains_key(b"c"));
-        assert!(!kr2.contains_key(b"d"));
+        assert!(kr1.contains(b"b"));
+        assert!(!kr1.contains(b"e"));
+        let kr2 = KeyRange::single("c");
+        assert!(kr2.contains(b"c"));
+        assert!(!kr2.contains(b"d"));
         let kr3 = KeyRange::new("c", [0]);
-        assert!(kr3.contains_key(b"d"));
-        assert!(!kr3.contains_key(b"a"));
+        assert!(kr3.contains(b"d"));
+        assert!(!kr3.contains(b"a"));
         let kr4 = KeyRange::new([0], "e");
-        assert!(kr4.contains_key(b"d"));
-        assert!(!kr4.contains_key(b"e"));
+        assert!(kr4.contains(b"d"));
+        assert!(!kr4.contains(b"e"));
+    }
+
+    /// All non-empty keys of length at most `max_len` built from `alphabet`, the empty
+    /// key is not a valid key
+    fn all_keys(alphabet: &[u8], max_len: usize) -> Vec<Vec<u8>> {
+        let mut keys = vec![];
+        let mut last = vec![vec![]];
+        for _ in 0..max_len {
+            last = last
+                .iter()
+                .flat_map(|k: &Vec<u8>| {
+                    alphabet.iter().map(move |&b| {
+                        let mut next = k.clone();
+                        next.push(b);
+                        next
+                    })
+                })
+                .collect();
+            keys.extend(last.iter().cloned());
+        }
+        keys
+    }
+
+    /// All kinds of ranges built from the given keys
+    fn all_ranges(keys: &[Vec<u8>]) -> Vec<KeyRange> {
+        let mut ranges = vec![KeyRange::all()];
+        for key in keys {
+            if key.as_slice() != UNBOUNDED {
+                ranges.push(KeyRange::single(key.clone()));
+            }
+            ranges.push(KeyRange::prefix(key.clone()));
+            ranges.push(KeyRange::from_key(key.clone()));
+            for end in keys.iter().filter(|end| !end.is_empty() && *end > key) {
+                ranges.push(KeyRange::new(key.clone(), end.clone()));
+            }
+        }
+        ranges
+    }
+
+    #[test]
+    fn test_key_range_prefix_contains_exactly_prefixed_keys() {
+        let keys = all_keys(&[0x00, 0x01, 0x7f, 0xfe, 0xff], 3);
+        for prefix in &keys {
+            let range = KeyRange::prefix(prefix.clone());
+            for key in &keys {
+                assert_eq!(
+                    range.contains(key),
+                    key.starts_with(prefix),
+                    "prefix {prefix:?}, key {key:?}"
+                );
+            }
+        }
+        assert_eq!(KeyRange::get_prefix(&[0x61, 0xff, 0xff]), vec![0x62]);
+        assert_eq!(
+            KeyRange::prefix([0xff, 0xff]),
+            KeyRange::from_key([0xff, 0xff])
+        );
+        assert_eq!(KeyRange::prefix(""), KeyRange::all());
+    }
+
+    #[test]
+    fn test_canonical_key_range_should_match_as_the_raw_one() {
+        let keys = all_keys(&[0x00, 0x01, 0x7f, 0xfe, 0xff], 3);
+        for prefix in &keys {
+            let range_end = KeyRange::get_prefix(prefix);
+            let raw = KeyRange::new(prefix.clone(), range_end.clone());
+            let canonical = KeyRange::canonical(prefix.clone(), range_end);
+            for key in &keys {
+                assert_eq!(
+                    canonical.contains(key),
+                    raw.contains(key),
+                    "prefix {prefix:?}, key {key:?}"
+                );
+                assert_eq!(canonical.contains(key), key.starts_with(prefix));
+            }
+        }
+        assert_eq!(KeyRange::canonical("", [0]), KeyRange::all());
+        assert_eq!(KeyRange::canonical([0], [0]), KeyRange::all());
+        let zero = KeyRange::canonical([0], "");
+        assert!(zero.is_single());
+        assert!(keys
+            .iter()
+            .all(|key| zero.contains(key) == (key.as_slice() == [0])));
+    }
+
+    #[test]
+    fn test_key_range_constructors() {
+        let keys = all_keys(&[0x00, 0x7f, 0xff], 3);
+        let single = KeyRange::single([0x7f, 0xff]);
+        let from = KeyRange::from_key([0x7f, 0xff]);
+        for key in &keys {
+            assert_eq!(single.contains(key), key.as_slice() == [0x7f, 0xff]);
+            assert_eq!(
+                from.contains(key),
+                key.as_slice() >= [0x7f, 0xff].as_slice()
+            );
+            assert!(KeyRange::all().contains(key));
+        }
+        assert!(single.is_single());
+        assert!(KeyRange::all().is_all());
+        assert!(KeyRange::from_key("").is_all());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_key_range_deprecated_shims() {
+        let keys = all_keys(&[0x00, 0x7f, 0xff], 2);
+        let ranges = all_ranges(&keys);
+        assert_eq!(KeyRange::new_one_key("a"), KeyRange::single("a"));
+        for range in &ranges {
+            for key in &keys {
+                assert_eq!(range.contains_key(key), range.contains(key));
+            }
+            for other in &ranges {
+                assert_eq!(range.is_conflicted(other), range.intersects(other));
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_range_pb_round_trip() {
+        let keys = all_keys(&[0x00, 0x7f, 0xff], 2);
+        for range in all_ranges(&keys) {
+            let pb = PbKeyRange::from(range.clone());
+            let restored = KeyRange::from(pb.clone());
+            for key in &keys {
+                assert_eq!(range.contains(key), restored.contains(key), "{pb:?}");
+            }
+        }
+        assert_eq!(
+            PbKeyRange::from(KeyRange::all()),
+            PbKeyRange {
+                key: vec![0],
+                range_end: vec![0]
+            }
+        );
+        assert_eq!(
+            PbKeyRange::from(KeyRange::single("a")),
+            PbKeyRange {
+                key: b"a".to_vec(),
+                range_end: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_range_intersection() {
+        let keys = all_keys(&[0x00, 0x7f, 0xff], 2);
+        let ranges = all_ranges(&keys);
+        for r1 in &ranges {
+            for r2 in &ranges {
+                let intersection = r1.intersection(r2);
+                assert_eq!(r1.intersects(r2), intersection.is_some(), "{r1:?} {r2:?}");
+                assert_eq!(r1.intersects(r2), r2.intersects(r1), "{r1:?} {r2:?}");
+                for key in &keys {
+                    let in_both = r1.contains(key) && r2.contains(key);
+                    assert_eq!(
+                        intersection.as_ref().is_some_and(|r| r.contains(key)),
+                        in_both,
+                        "{r1:?} {r2:?} {key:?}"
+                    );
+                }
+            }
+        }
     }
 
     #[test]
@@ -625,14 +1148,17 @@ mod test {
         let cmd5 = Command::new(RequestWrapper::LeaseGrantRequest(LeaseGrantRequest {
             ttl: 1,
             id: 1,
+            ..Default::default()
         }));
         let cmd6 = Command::new(RequestWrapper::LeaseRevokeRequest(LeaseRevokeRequest {
             id: 1,
+            ..Default::default()
         }));
 
         let lease_grant_cmd = Command::new(RequestWrapper::LeaseGrantRequest(LeaseGrantRequest {
             ttl: 1,
             id: 123,
+            ..Default::default()
         }));
         let put_with_lease_cmd = Command::new(RequestWrapper::PutRequest(PutRequest {
             key: b"foo".to_vec(),
@@ -770,6 +1296,11 @@ mod test {
         let decoded_sync_resp =
             <SyncResponse as PbCodec>::decode(&sync_resp.encode()).expect("decode should success");
         assert_eq!(sync_resp, decoded_sync_resp);
+
+        let sync_resp = SyncResponse::new(2).with_sub_revisions(vec![0, 1, 3]);
+        let decoded_sync_resp =
+            <SyncResponse as PbCodec>::decode(&sync_resp.encode()).expect("decode should success");
+        assert_eq!(decoded_sync_resp.sub_revisions(), [0, 1, 3]);
     }
 
     #[test]
@@ -813,10 +1344,46 @@ mod test {
         };
 
         let keys = txn_req.keys();
-        assert!(keys.contains(&KeyRange::new_one_key("a")));
+        assert!(keys.contains(&KeyRange::single("a")));
         assert!(keys.contains(&KeyRange::new("b", "e")));
-        assert!(keys.contains(&KeyRange::new_one_key("1")));
-        assert!(keys.contains(&KeyRange::new_one_key("2")));
+        assert!(keys.contains(&KeyRange::single("1")));
+        assert!(keys.contains(&KeyRange::single("2")));
         assert!(keys.contains(&KeyRange::new("3", "4")));
     }
+
+    #[test]
+    fn decoded_command_should_take_the_carried_conflict_meta() {
+        let cmd = Command::new(RequestWrapper::TxnRequest(TxnRequest {
+            success: vec![
+                RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: b"a".to_vec(),
+                        lease: 1,
+                        ..Default::default()
+                    })),
+                },
+                RequestOp {
+                    request: Some(Request::RequestRange(RangeRequest {
+                        key: b"b".to_vec(),
+                        range_end: vec![0],
+                        ..Default::default()
+                    })),
+                },
+            ],
+            ..Default::default()
+        }));
+        let decoded = Command::decode(&cmd.encode()).unwrap();
+        assert!(decoded.conflict_meta.get().is_some());
+        assert_eq!(decoded.conflict_meta(), cmd.conflict_meta());
+
+        // commands from older clients compute the metadata on first use
+        let old = PbCommand {
+            request_wrapper: Some(cmd.request().clone()),
+            ..Default::default()
+        };
+        let decoded = Command::decode(&old.encode_to_vec()).unwrap();
+        assert!(decoded.conflict_meta.get().is_none());
+        assert_eq!(decoded.conflict_meta(), cmd.conflict_meta());
+        assert_eq!(decoded, cmd);
+    }
 }
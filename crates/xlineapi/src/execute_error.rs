@@ -7,6 +7,10 @@ use thiserror::Error;
 
 use crate::{PbExecuteError, PbExecuteErrorOuter, PbRevisions, PbUserRole};
 
+/// Key of the metadata carrying the compacted revision, it is attached to the
+/// `ErrCompacted` status and to the response of the maintenance status
+pub const COMPACT_REVISION_KEY: &str = "compact-revision";
+
 /// Error met when executing commands
 #[cfg_attr(test, derive(strum_macros::EnumIter))]
 #[derive(Error, Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +38,12 @@ pub enum ExecuteError {
     /// Lease already exists
     #[error("lease {0} already exists")]
     LeaseAlreadyExists(i64),
+    /// Lease has reached the max number of attached keys
+    #[error("lease {0} has reached the max number of attached keys")]
+    LeaseKeysExceeded(i64),
+    /// Client has reached the max number of active leases
+    #[error("client {0} has reached the max number of active leases")]
+    LeaseQuotaExceeded(String),
 
     // AuthErrors
     /// Auth is not enabled
@@ -99,6 +109,10 @@ pub enum ExecuteError {
     /// no space left in quota
     #[error("no space left in quota")]
     Nospace,
+
+    /// The request must be served by the leader
+    #[error("not leader")]
+    NotLeader,
 }
 
 impl From<PbExecuteError> for ExecuteError {
@@ -114,6 +128,8 @@ impl From<PbExecuteError> for ExecuteError {
             }
             PbExecuteError::LeaseNotFound(l) => ExecuteError::LeaseNotFound(l),
             PbExecuteError::LeaseExpired(l) => ExecuteError::LeaseExpired(l),
+            PbExecuteError::LeaseKeysExceeded(l) => ExecuteError::LeaseKeysExceeded(l),
+            PbExecuteError::LeaseQuotaExceeded(c) => ExecuteError::LeaseQuotaExceeded(c),
             PbExecuteError::LeaseTtlTooLarge(l) => ExecuteError::LeaseTtlTooLarge(l),
             PbExecuteError::LeaseAlreadyExists(l) => ExecuteError::LeaseAlreadyExists(l),
             PbExecuteError::AuthNotEnabled(_) => ExecuteError::AuthNotEnabled,
@@ -140,6 +156,7 @@ impl From<PbExecuteError> for ExecuteError {
             PbExecuteError::DbError(e) => ExecuteError::DbError(e),
             PbExecuteError::PermissionDenied(_) => ExecuteError::PermissionDenied,
             PbExecuteError::Nospace(_) => ExecuteError::Nospace,
+            PbExecuteError::NotLeader(_) => ExecuteError::NotLeader,
         }
     }
 }
@@ -163,6 +180,8 @@ impl From<ExecuteError> for PbExecuteError {
             }
             ExecuteError::LeaseNotFound(l) => PbExecuteError::LeaseNotFound(l),
             ExecuteError::LeaseExpired(l) => PbExecuteError::LeaseExpired(l),
+            ExecuteError::LeaseKeysExceeded(l) => PbExecuteError::LeaseKeysExceeded(l),
+            ExecuteError::LeaseQuotaExceeded(c) => PbExecuteError::LeaseQuotaExceeded(c),
             ExecuteError::LeaseTtlTooLarge(l) => PbExecuteError::LeaseTtlTooLarge(l),
             ExecuteError::LeaseAlreadyExists(l) => PbExecuteError::LeaseAlreadyExists(l),
             ExecuteError::AuthNotEnabled => PbExecuteError::AuthNotEnabled(()),
@@ -192,6 +211,7 @@ impl From<ExecuteError> for PbExecuteError {
             ExecuteError::DbError(e) => PbExecuteError::DbError(e),
             ExecuteError::PermissionDenied => PbExecuteError::PermissionDenied(()),
             ExecuteError::Nospace => PbExecuteError::Nospace(()),
+            ExecuteError::NotLeader => PbExecuteError::NotLeader(()),
         }
     }
 }
@@ -221,6 +241,11 @@ impl PbCodec for ExecuteError {
 impl From<ExecuteError> for tonic::Status {
     #[inline]
     fn from(err: ExecuteError) -> Self {
+        let compact_revision = if let ExecuteError::RevisionCompacted(_, rev) = err {
+            Some(rev)
+        } else {
+            None
+        };
         let (code, message) = match err {
             ExecuteError::KeyNotFound => (
                 tonic::Code::InvalidArgument,
@@ -302,7 +327,14 @@ impl From<ExecuteError> for tonic::Status {
                 tonic::Code::ResourceExhausted,
                 "etcdserver: mvcc: database space exceeded".to_owned(),
             ),
+            ExecuteError::NotLeader => (
+                tonic::Code::Unavailable,
+                "etcdserver: not leader".to_owned(),
+            ),
             ExecuteError::LeaseExpired(_) => (tonic::Code::DeadlineExceeded, err.to_string()),
+            ExecuteError::LeaseKeysExceeded(_) | ExecuteError::LeaseQuotaExceeded(_) => {
+                (tonic::Code::ResourceExhausted, err.to_string())
+            }
             ExecuteError::UserAlreadyHasRole(_, _)
             | ExecuteError::NoPasswordUser
             | ExecuteError::TokenManagerNotInit => {
@@ -312,7 +344,14 @@ impl From<ExecuteError> for tonic::Status {
             ExecuteError::DbError(_) => (tonic::Code::Internal, err.to_string()),
         };
 
-        tonic::Status::new(code, message)
+        let mut status = tonic::Status::new(code, message);
+        // etcd clients match the message exactly, so the compacted revision goes to the metadata
+        if let Some(rev) = compact_revision {
+            let _ignore = status
+                .metadata_mut()
+                .insert(COMPACT_REVISION_KEY, rev.into());
+        }
+        status
     }
 }
 
@@ -330,4 +369,94 @@ mod test {
             assert!(matches!(err, _decoded_err));
         }
     }
+
+    #[test]
+    fn errors_should_map_to_etcd_statuses() {
+        let cases = [
+            (
+                ExecuteError::LeaseNotFound(1),
+                tonic::Code::NotFound,
+                "etcdserver: requested lease not found",
+            ),
+            (
+                ExecuteError::LeaseAlreadyExists(1),
+                tonic::Code::FailedPrecondition,
+                "etcdserver: lease already exists",
+            ),
+            (
+                ExecuteError::LeaseTtlTooLarge(1),
+                tonic::Code::OutOfRange,
+                "etcdserver: too large lease TTL",
+            ),
+            (
+                ExecuteError::LeaseExpired(1),
+                tonic::Code::DeadlineExceeded,
+                "lease 1 is expired",
+            ),
+            (
+                ExecuteError::LeaseKeysExceeded(1),
+                tonic::Code::ResourceExhausted,
+                "lease 1 has reached the max number of attached keys",
+            ),
+            (
+                ExecuteError::LeaseQuotaExceeded("alice".to_owned()),
+                tonic::Code::ResourceExhausted,
+                "client alice has reached the max number of active leases",
+            ),
+            (
+                ExecuteError::NotLeader,
+                tonic::Code::Unavailable,
+                "etcdserver: not leader",
+            ),
+            (
+                ExecuteError::KeyNotFound,
+                tonic::Code::InvalidArgument,
+                "etcdserver: key not found",
+            ),
+            (
+                ExecuteError::UserNotFound("u".to_owned()),
+                tonic::Code::FailedPrecondition,
+                "etcdserver: user name not found",
+            ),
+            (
+                ExecuteError::RoleAlreadyExists("r".to_owned()),
+                tonic::Code::FailedPrecondition,
+                "etcdserver: role name already exists",
+            ),
+            (
+                ExecuteError::AuthFailed,
+                tonic::Code::InvalidArgument,
+                "etcdserver: authentication failed, invalid user ID or password",
+            ),
+            (
+                ExecuteError::PermissionDenied,
+                tonic::Code::PermissionDenied,
+                "etcdserver: permission denied",
+            ),
+            (
+                ExecuteError::Nospace,
+                tonic::Code::ResourceExhausted,
+                "etcdserver: mvcc: database space exceeded",
+            ),
+        ];
+        for (err, code, message) in cases {
+            let status = tonic::Status::from(err);
+            assert_eq!(status.code(), code);
+            assert_eq!(status.message(), message);
+        }
+    }
+
+    #[test]
+    fn compacted_status_should_carry_the_compacted_revision() {
+        let status = tonic::Status::from(ExecuteError::RevisionCompacted(50, 100));
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+        assert_eq!(
+            status.message(),
+            "etcdserver: mvcc: required revision has been compacted"
+        );
+        assert_eq!(status.metadata().get(COMPACT_REVISION_KEY).unwrap(), "100");
+
+        let status = tonic::Status::from(ExecuteError::RevisionTooLarge(50, 10));
+        assert!(status.metadata().get(COMPACT_REVISION_KEY).is_none());
+    }
 }
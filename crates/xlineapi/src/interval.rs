@@ -1,5 +1,6 @@
 use std::cmp;
 
+use serde::{Deserialize, Serialize};
 use utils::interval_map::Interval;
 
 use crate::command::KeyRange;
@@ -20,7 +21,7 @@ impl From<KeyRange> for Interval<BytesAffine> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BytesAffine {
     /// Bytes
     Bytes(Vec<u8>),
@@ -76,7 +77,7 @@ mod tests {
     #[test]
     fn convert_from_key_range_is_ok() {
         let range0 = KeyRange::new("a", "e");
-        let range1 = KeyRange::new_one_key("f");
+        let range1 = KeyRange::single("f");
         let interval0: Interval<BytesAffine> = range0.into();
         let interval1: Interval<BytesAffine> = range1.into();
         assert_eq!(interval0.low, BytesAffine::new_key("a"));
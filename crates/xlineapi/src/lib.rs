@@ -208,6 +208,7 @@ mod errorpb {
 use std::fmt::Display;
 
 use command::KeyRange;
+use serde::{Deserialize, Serialize};
 use utils::write_vec;
 
 pub use self::{
@@ -215,8 +216,8 @@ pub use self::{
     commandpb::{
         command::{AuthInfo, RequestWrapper},
         command_response::ResponseWrapper,
-        Command as PbCommand, CommandResponse as PbCommandResponse, KeyRange as PbKeyRange,
-        SyncResponse as PbSyncResponse,
+        Command as PbCommand, CommandResponse as PbCommandResponse, ConflictMeta as PbConflictMeta,
+        KeyRange as PbKeyRange, SyncResponse as PbSyncResponse,
     },
     errorpb::{
         execute_error::Error as PbExecuteError, ExecuteError as PbExecuteErrorOuter,
@@ -252,18 +253,22 @@ pub use self::{
         AuthUserGetRequest, AuthUserGetResponse, AuthUserGrantRoleRequest,
         AuthUserGrantRoleResponse, AuthUserListRequest, AuthUserListResponse,
         AuthUserRevokeRoleRequest, AuthUserRevokeRoleResponse, AuthenticateRequest,
-        AuthenticateResponse, CompactionRequest, CompactionResponse, Compare, DefragmentRequest,
-        DefragmentResponse, DeleteRangeRequest, DeleteRangeResponse, DowngradeRequest,
-        DowngradeResponse, HashKvRequest, HashKvResponse, HashRequest, HashResponse,
-        LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest, LeaseKeepAliveResponse,
-        LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeRequest, LeaseRevokeResponse,
-        LeaseStatus, LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, Member, MemberAddRequest,
+        AuthenticateResponse, CompactionExemptionRequest, CompactionExemptionResponse,
+        CompactionRequest, CompactionResponse, Compare, DebugStatsRequest, DebugStatsResponse,
+        DefragmentRequest, DefragmentResponse, DeleteRangeRequest, DeleteRangeResponse,
+        DowngradeRequest, DowngradeResponse, HashKvRequest, HashKvResponse, HashRequest,
+        HashResponse, IdentityUsage, LeaseCheckpoint, LeaseCheckpointRequest,
+        LeaseCheckpointResponse, LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest,
+        LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeBatchRequest,
+        LeaseRevokeBatchResponse, LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus,
+        LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, Member, MemberAddRequest,
         MemberAddResponse, MemberListRequest, MemberListResponse, MemberPromoteRequest,
         MemberPromoteResponse, MemberRemoveRequest, MemberRemoveResponse, MemberUpdateRequest,
         MemberUpdateResponse, MoveLeaderRequest, MoveLeaderResponse, PutRequest, PutResponse,
         RangeRequest, RangeResponse, RequestOp, ResponseHeader, ResponseOp, SnapshotRequest,
-        SnapshotResponse, StatusRequest, StatusResponse, TxnRequest, TxnResponse,
-        WatchCancelRequest, WatchCreateRequest, WatchProgressRequest, WatchRequest, WatchResponse,
+        SnapshotResponse, StatusRequest, StatusResponse, Ticket, TimeToRevisionRequest,
+        TimeToRevisionResponse, TxnRequest, TxnResponse, WaitAppliedRequest, WatchCancelRequest,
+        WatchCreateRequest, WatchProgressRequest, WatchRequest, WatchResponse,
     },
     leasepb::Lease as PbLease,
     mvccpb::{event::EventType, Event, KeyValue},
@@ -289,6 +294,7 @@ impl ResponseWrapper {
             ResponseWrapper::DeleteRangeResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::TxnResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::CompactionResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::CompactionExemptionResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthEnableResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthDisableResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthStatusResponse(ref mut resp) => &mut resp.header,
@@ -309,6 +315,8 @@ impl ResponseWrapper {
             ResponseWrapper::LeaseGrantResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::LeaseRevokeResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::LeaseLeasesResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::LeaseCheckpointResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::LeaseRevokeBatchResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AlarmResponse(ref mut resp) => &mut resp.header,
         };
         if let Some(ref mut header) = *header {
@@ -318,7 +326,7 @@ impl ResponseWrapper {
 }
 
 /// Backend store of request
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestBackend {
     /// Kv backend
     Kv,
@@ -347,7 +355,7 @@ impl CommandKeys for RangeRequest {
 
 impl CommandKeys for PutRequest {
     fn keys(&self) -> Vec<KeyRange> {
-        vec![KeyRange::new_one_key(self.key.as_slice())]
+        vec![KeyRange::single(self.key.as_slice())]
     }
 }
 
@@ -379,9 +387,7 @@ impl CommandKeys for TxnRequest {
                 Request::RequestRange(ref req) => {
                     keys.push(KeyRange::new(req.key.as_slice(), req.range_end.as_slice()));
                 }
-                Request::RequestPut(ref req) => {
-                    keys.push(KeyRange::new_one_key(req.key.as_slice()))
-                }
+                Request::RequestPut(ref req) => keys.push(KeyRange::single(req.key.as_slice())),
                 Request::RequestDeleteRange(ref req) => {
                     keys.push(KeyRange::new(req.key.as_slice(), req.range_end.as_slice()))
                 }
@@ -413,7 +419,8 @@ impl RequestWrapper {
             | RequestWrapper::RangeRequest(_)
             | RequestWrapper::DeleteRangeRequest(_)
             | RequestWrapper::TxnRequest(_)
-            | RequestWrapper::CompactionRequest(_) => RequestBackend::Kv,
+            | RequestWrapper::CompactionRequest(_)
+            | RequestWrapper::CompactionExemptionRequest(_) => RequestBackend::Kv,
             RequestWrapper::AuthEnableRequest(_)
             | RequestWrapper::AuthDisableRequest(_)
             | RequestWrapper::AuthStatusRequest(_)
@@ -433,7 +440,9 @@ impl RequestWrapper {
             | RequestWrapper::AuthenticateRequest(_) => RequestBackend::Auth,
             RequestWrapper::LeaseGrantRequest(_)
             | RequestWrapper::LeaseRevokeRequest(_)
-            | RequestWrapper::LeaseLeasesRequest(_) => RequestBackend::Lease,
+            | RequestWrapper::LeaseLeasesRequest(_)
+            | RequestWrapper::LeaseCheckpointRequest(_)
+            | RequestWrapper::LeaseRevokeBatchRequest(_) => RequestBackend::Lease,
             RequestWrapper::AlarmRequest(_) => RequestBackend::Alarm,
         }
     }
@@ -456,6 +465,7 @@ impl RequestWrapper {
             | RequestWrapper::DeleteRangeRequest(_)
             | RequestWrapper::TxnRequest(_)
             | RequestWrapper::CompactionRequest(_)
+            | RequestWrapper::CompactionExemptionRequest(_)
             | RequestWrapper::AuthEnableRequest(_)
             | RequestWrapper::AuthDisableRequest(_)
             | RequestWrapper::AuthRoleAddRequest(_)
@@ -470,6 +480,8 @@ impl RequestWrapper {
             | RequestWrapper::AuthenticateRequest(_)
             | RequestWrapper::LeaseGrantRequest(_)
             | RequestWrapper::LeaseRevokeRequest(_)
+            | RequestWrapper::LeaseCheckpointRequest(_)
+            | RequestWrapper::LeaseRevokeBatchRequest(_)
             | RequestWrapper::AlarmRequest(_) => false,
         }
     }
@@ -500,6 +512,7 @@ impl RequestWrapper {
         match self {
             RequestWrapper::RangeRequest(_)
             | RequestWrapper::LeaseGrantRequest(_)
+            | RequestWrapper::LeaseCheckpointRequest(_)
             | RequestWrapper::CompactionRequest(_) => true,
             RequestWrapper::TxnRequest(req) => req.is_read_only(),
             _ => false,
@@ -590,6 +603,7 @@ impl_from_requests!(
     DeleteRangeRequest,
     TxnRequest,
     CompactionRequest,
+    CompactionExemptionRequest,
     AuthEnableRequest,
     AuthDisableRequest,
     AuthStatusRequest,
@@ -610,6 +624,8 @@ impl_from_requests!(
     LeaseGrantRequest,
     LeaseRevokeRequest,
     LeaseLeasesRequest,
+    LeaseCheckpointRequest,
+    LeaseRevokeBatchRequest,
     AlarmRequest
 );
 
@@ -619,6 +635,7 @@ impl_from_responses!(
     DeleteRangeResponse,
     TxnResponse,
     CompactionResponse,
+    CompactionExemptionResponse,
     AuthEnableResponse,
     AuthDisableResponse,
     AuthStatusResponse,
@@ -639,6 +656,8 @@ impl_from_responses!(
     LeaseGrantResponse,
     LeaseRevokeResponse,
     LeaseLeasesResponse,
+    LeaseCheckpointResponse,
+    LeaseRevokeBatchResponse,
     AlarmResponse
 );
 
@@ -685,6 +704,56 @@ impl Event {
     }
 }
 
+/// Metadata key of the opt-in flag of a `TxnRequest`, the `TxnResponse` carries the
+/// sub-revisions of its mutations in the `SUB_REVISIONS_KEY` metadata when it's set
+pub const WITH_SUB_REVISIONS_KEY: &str = "with-sub-revisions";
+
+/// Metadata key of the sub-revisions of the mutations in a `TxnResponse`, separated by commas
+pub const SUB_REVISIONS_KEY: &str = "sub-revisions";
+
+/// Metadata key of the client urls of the last known leader, separated by commas, carried
+/// by the status of a request a follower failed to forward to the leader
+pub const LEADER_HINT_KEY: &str = "leader-hint";
+
+/// Metadata key of the milliseconds a throttled client should wait before retrying
+pub const RETRY_AFTER_KEY: &str = "retry-after-ms";
+
+impl TxnResponse {
+    /// Sub-revisions assigned to the mutations of the txn in order, including the ones in
+    /// the nested txns. A put takes one sub-revision and a delete range takes one for each
+    /// deleted key, so a delete range that deletes nothing shares the sub-revision of the
+    /// next mutation.
+    #[inline]
+    #[must_use]
+    pub fn sub_revisions(&self) -> Vec<i64> {
+        let mut sub_revisions = Vec::new();
+        let _next = self.collect_sub_revisions(0, &mut sub_revisions);
+        sub_revisions
+    }
+
+    /// Collect the sub-revisions of the mutations starting from `next`, returns the next
+    /// unassigned sub-revision
+    fn collect_sub_revisions(&self, mut next: i64, sub_revisions: &mut Vec<i64>) -> i64 {
+        for response in self.responses.iter().filter_map(|op| op.response.as_ref()) {
+            match *response {
+                Response::ResponseRange(_) => {}
+                Response::ResponsePut(_) => {
+                    sub_revisions.push(next);
+                    next += 1;
+                }
+                Response::ResponseDeleteRange(ref res) => {
+                    sub_revisions.push(next);
+                    next += res.deleted;
+                }
+                Response::ResponseTxn(ref res) => {
+                    next = res.collect_sub_revisions(next, sub_revisions);
+                }
+            }
+        }
+        next
+    }
+}
+
 impl TxnRequest {
     /// Checks whether a given `TxnRequest` is read-only or not.
     pub fn is_read_only(&self) -> bool {
@@ -1225,6 +1294,45 @@ mod test {
         assert!(!mixed_nested_txn_req.is_serializable());
     }
 
+    #[test]
+    fn txn_response_sub_revisions_should_follow_mutations() {
+        let put = || ResponseOp {
+            response: Some(Response::ResponsePut(PutResponse::default())),
+        };
+        let delete = |deleted| ResponseOp {
+            response: Some(Response::ResponseDeleteRange(DeleteRangeResponse {
+                deleted,
+                ..Default::default()
+            })),
+        };
+        let range = ResponseOp {
+            response: Some(Response::ResponseRange(RangeResponse::default())),
+        };
+
+        let txn_res = TxnResponse {
+            responses: vec![put(), put(), put(), put(), put()],
+            ..Default::default()
+        };
+        assert_eq!(txn_res.sub_revisions(), vec![0, 1, 2, 3, 4]);
+
+        let nested_txn_res = TxnResponse {
+            responses: vec![
+                put(),
+                range,
+                delete(2),
+                ResponseOp {
+                    response: Some(Response::ResponseTxn(TxnResponse {
+                        responses: vec![delete(0), put()],
+                        ..Default::default()
+                    })),
+                },
+                put(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(nested_txn_res.sub_revisions(), vec![0, 1, 3, 3, 4]);
+    }
+
     #[test]
     fn test_alarm_member_display() {
         let am = AlarmMember::new(10276657743932975437, AlarmType::Nospace);
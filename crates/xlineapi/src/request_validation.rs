@@ -5,8 +5,8 @@ use thiserror::Error;
 
 use crate::{
     command::KeyRange, AuthRoleAddRequest, AuthRoleGrantPermissionRequest, AuthUserAddRequest,
-    DeleteRangeRequest, PutRequest, RangeRequest, Request, RequestOp, SortOrder, SortTarget,
-    TxnRequest,
+    CompactionExemptionRequest, DeleteRangeRequest, PutRequest, RangeRequest, Request, RequestOp,
+    SortOrder, SortTarget, TxnRequest, WatchCreateRequest,
 };
 
 /// Default max txn ops
@@ -92,6 +92,21 @@ impl RequestValidator for TxnRequest {
     }
 }
 
+impl RequestValidator for WatchCreateRequest {
+    fn validation(&self) -> Result<(), ValidationError> {
+        match (self.key.as_slice(), self.range_end.as_slice()) {
+            // all keys, or all keys from the key
+            (_, [0]) => Ok(()),
+            ([], []) => Err(ValidationError::EmptyKey),
+            ([], _) => Err(ValidationError::RangeEndWithoutKey),
+            (_, []) => Ok(()),
+            (key, range_end) if range_end < key => Err(ValidationError::InvertedRange),
+            (key, range_end) if range_end == key => Err(ValidationError::EmptyRange),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Check if puts and deletes overlap
 fn check_intervals(ops: &[RequestOp]) -> Result<(HashSet<&[u8]>, Vec<KeyRange>), ValidationError> {
     // TODO: use interval tree is better?
@@ -118,7 +133,7 @@ fn check_intervals(ops: &[RequestOp]) -> Result<(HashSet<&[u8]>, Vec<KeyRange>),
                 if !puts.insert(k) {
                     return Err(ValidationError::DuplicateKey);
                 }
-                if dels.iter().any(|del| del.contains_key(k)) {
+                if dels.iter().any(|del| del.contains(k)) {
                     return Err(ValidationError::DuplicateKey);
                 }
             }
@@ -166,6 +181,17 @@ impl RequestValidator for AuthRoleAddRequest {
     }
 }
 
+impl RequestValidator for CompactionExemptionRequest {
+    fn validation(&self) -> Result<(), ValidationError> {
+        // an empty prefix would exempt every key and turn compactions into no-ops
+        if self.add.iter().any(Vec::is_empty) {
+            return Err(ValidationError::EmptyKey);
+        }
+
+        Ok(())
+    }
+}
+
 impl RequestValidator for AuthRoleGrantPermissionRequest {
     fn validation(&self) -> Result<(), ValidationError> {
         if self.perm.is_none() {
@@ -214,6 +240,18 @@ pub enum ValidationError {
     /// Permission not given
     #[error("permission not given")]
     PermissionNotGiven,
+    /// Too many keys affected by a single request
+    #[error("too many keys affected by a single request")]
+    TooManyKeys,
+    /// Range end is given without a key
+    #[error("range end is given without a key")]
+    RangeEndWithoutKey,
+    /// Range end is smaller than the key
+    #[error("range end is smaller than the key")]
+    InvertedRange,
+    /// Range end is equal to the key, the range contains no key
+    #[error("range end is equal to the key, the range contains no key")]
+    EmptyRange,
 }
 
 // The etcd client relies on GRPC error messages for error type interpretation.
@@ -260,9 +298,16 @@ impl From<ValidationError> for tonic::Status {
                 tonic::Code::InvalidArgument,
                 "etcdserver: permission not given".to_owned(),
             ),
-            ValidationError::RequestNotProvided | ValidationError::PasswordEmpty => {
-                (tonic::Code::InvalidArgument, err.to_string())
-            }
+            ValidationError::TooManyKeys => (
+                tonic::Code::InvalidArgument,
+                "etcdserver: too many keys affected by a single request, split it into smaller ranges"
+                    .to_owned(),
+            ),
+            ValidationError::RequestNotProvided
+            | ValidationError::PasswordEmpty
+            | ValidationError::RangeEndWithoutKey
+            | ValidationError::InvertedRange
+            | ValidationError::EmptyRange => (tonic::Code::InvalidArgument, err.to_string()),
         };
 
         tonic::Status::new(code, message)
@@ -445,6 +490,19 @@ mod test {
         run_test(testcases);
     }
 
+    #[test]
+    fn invalid_compaction_exemption_request_should_have_correct_error_msg() {
+        let testcases = vec![TestCase {
+            req: CompactionExemptionRequest {
+                add: vec![b"/audit/".to_vec(), vec![]],
+                remove: vec![],
+            },
+            expected_err: ValidationError::EmptyKey,
+        }];
+
+        run_test(testcases);
+    }
+
     #[test]
     fn invalid_role_grant_perm_request_should_have_correct_error_msg() {
         let testcases = vec![TestCase {
@@ -651,4 +709,48 @@ mod test {
 
         run_test(testcases);
     }
+
+    #[test]
+    fn invalid_watch_create_request_should_have_correct_error_msg() {
+        let watch = |key: &[u8], range_end: &[u8]| WatchCreateRequest {
+            key: key.to_vec(),
+            range_end: range_end.to_vec(),
+            ..Default::default()
+        };
+        let testcases = vec![
+            TestCase {
+                req: watch(b"", b""),
+                expected_err: ValidationError::EmptyKey,
+            },
+            TestCase {
+                req: watch(b"", b"foo"),
+                expected_err: ValidationError::RangeEndWithoutKey,
+            },
+            TestCase {
+                req: watch(b"foo", b"bar"),
+                expected_err: ValidationError::InvertedRange,
+            },
+            TestCase {
+                req: watch(b"foo", b"fo"),
+                expected_err: ValidationError::InvertedRange,
+            },
+            TestCase {
+                req: watch(b"foo", b"foo"),
+                expected_err: ValidationError::EmptyRange,
+            },
+        ];
+
+        run_test(testcases);
+
+        for req in [
+            watch(b"foo", b""),
+            watch(b"foo", b"fop"),
+            watch(b"foo", &[0]),
+            watch(b"", &[0]),
+            watch(&[0], &[0]),
+            watch(&[0], b""),
+        ] {
+            assert_eq!(req.validation(), Ok(()), "{req:?}");
+        }
+    }
 }
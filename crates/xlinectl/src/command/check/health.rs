@@ -0,0 +1,556 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    future::Future,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use clap::{arg, ArgMatches, Command};
+use tokio::{signal::ctrl_c, sync::mpsc, task::JoinHandle};
+use xline_client::{
+    types::{
+        kv::{
+            Compare, CompareResult, DeleteRangeRequest, PutRequest, RangeRequest, TxnOp, TxnRequest,
+        },
+        lease::{
+            LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest, LeaseTimeToLiveRequest,
+        },
+        watch::{WatchEvent, WatchRequest},
+    },
+    Client,
+};
+use xlineapi::{Event, EventType};
+
+/// The root of the default prefixes of the canaries
+const PREFIX_ROOT: &str = "/xlinectl/check/health/";
+/// TTL of the lease the keys of a canary are attached to, in seconds
+const LEASE_TTL: i64 = 10;
+/// Interval between two keep alive requests of the lease
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(3);
+/// Pause between two iterations of the workload
+const ITERATION_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the watcher may lag behind the writes when the workload finishes
+const WATCH_WAIT: Duration = Duration::from_secs(5);
+/// The reported percentiles of the latencies
+const PERCENTILES: [usize; 3] = [50, 90, 99];
+
+/// Definition of `health` command
+pub(super) fn command() -> Command {
+    Command::new("health")
+        .about("Runs a write/read/watch canary against the cluster and reports the latencies, its keys and lease are removed afterwards")
+        .arg(
+            arg!(--duration <DURATION> "How long the workload runs")
+                .value_parser(|arg: &str| ext_utils::parse_duration(arg))
+                .default_value("30s"),
+        )
+        .arg(arg!(--prefix <PREFIX> "The prefix of the keys written by the canary, a unique one by default"))
+}
+
+/// Options of the canary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct CheckOptions {
+    /// How long the workload runs
+    duration: Duration,
+    /// The prefix of the keys written by the canary
+    prefix: String,
+}
+
+/// Build options from matches
+pub(super) fn build_request(matches: &ArgMatches) -> CheckOptions {
+    let duration = *matches.get_one::<Duration>("duration").expect("required");
+    let prefix = matches.get_one::<String>("prefix").map_or_else(
+        || {
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            format!("{PREFIX_ROOT}{}-{millis}/", std::process::id())
+        },
+        Clone::clone,
+    );
+    CheckOptions { duration, prefix }
+}
+
+/// An expectation of the canary violated by the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Violation {
+    /// The watcher didn't see a write in its order, `None` if it saw nothing in time
+    MissedWatchEvent {
+        /// The expected event
+        expected: String,
+        /// The event seen in its place
+        got: Option<String>,
+    },
+    /// A read didn't return the latest write
+    StaleRead {
+        /// The read key
+        key: String,
+        /// The latest value written
+        expected: String,
+        /// The value read, `None` if the key is not found
+        got: Option<String>,
+    },
+    /// A conditional txn whose condition holds took the else branch
+    TxnNotSucceeded {
+        /// The compared key
+        key: String,
+    },
+    /// The lease expired while it was kept alive
+    LeaseLost {
+        /// Id of the lease
+        lease_id: i64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::MissedWatchEvent {
+                ref expected,
+                got: Some(ref got),
+            } => write!(f, "missed watch event `{expected}`, got `{got}` instead"),
+            Violation::MissedWatchEvent {
+                ref expected,
+                got: None,
+            } => write!(
+                f,
+                "missed watch event `{expected}`, nothing is seen in time"
+            ),
+            Violation::StaleRead {
+                ref key,
+                ref expected,
+                got: Some(ref got),
+            } => write!(
+                f,
+                "stale read of `{key}`: expected `{expected}`, got `{got}`"
+            ),
+            Violation::StaleRead {
+                ref key,
+                ref expected,
+                got: None,
+            } => write!(
+                f,
+                "stale read of `{key}`: expected `{expected}`, got nothing"
+            ),
+            Violation::TxnNotSucceeded { ref key } => {
+                write!(f, "the conditional txn on `{key}` did not succeed")
+            }
+            Violation::LeaseLost { lease_id } => write!(f, "lease {lease_id:016x} is lost"),
+        }
+    }
+}
+
+/// The result of a step of the workload, a violation stops the canary
+type Step<T> = Result<std::result::Result<T, Violation>>;
+
+/// The description of a watch event, in the same form as the expected ones
+fn describe(event: &Event) -> String {
+    let (key, value) = event.kv.as_ref().map_or_else(
+        || (String::new(), String::new()),
+        |kv| {
+            (
+                String::from_utf8_lossy(&kv.key).into_owned(),
+                String::from_utf8_lossy(&kv.value).into_owned(),
+            )
+        },
+    );
+    match event.r#type() {
+        EventType::Put => format!("put {key}={value}"),
+        EventType::Delete => format!("delete {key}"),
+    }
+}
+
+/// The writes the watcher is expected to see, in order
+#[derive(Debug, Default)]
+struct ExpectedEvents {
+    /// The descriptions of the writes not seen yet
+    pending: VecDeque<String>,
+}
+
+impl ExpectedEvents {
+    /// Expect a put of `key`
+    fn push_put(&mut self, key: &str, value: &str) {
+        self.pending.push_back(format!("put {key}={value}"));
+    }
+
+    /// Check a seen event against the first expected one
+    fn check(&mut self, seen: String) -> std::result::Result<(), Violation> {
+        match self.pending.pop_front() {
+            Some(expected) if expected == seen => Ok(()),
+            Some(expected) => Err(Violation::MissedWatchEvent {
+                expected,
+                got: Some(seen),
+            }),
+            None => Err(Violation::MissedWatchEvent {
+                expected: "no more events".to_owned(),
+                got: Some(seen),
+            }),
+        }
+    }
+
+    /// The first write not seen yet
+    fn first(&self) -> Option<&String> {
+        self.pending.front()
+    }
+}
+
+/// The latencies of the operations of the workload
+#[derive(Debug, Default)]
+struct Latencies {
+    /// The latencies of each operation
+    samples: BTreeMap<&'static str, Vec<Duration>>,
+}
+
+impl Latencies {
+    /// Record a latency of `op`
+    fn record(&mut self, op: &'static str, latency: Duration) {
+        self.samples.entry(op).or_default().push(latency);
+    }
+
+    /// The reported lines, the count and the percentiles of each operation
+    fn report(&mut self) -> Vec<String> {
+        self.samples
+            .iter_mut()
+            .map(|(op, samples)| {
+                samples.sort_unstable();
+                let percentiles = PERCENTILES
+                    .iter()
+                    .map(|&p| format!("p{p} {:?}", percentile(samples, p)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{op}: count {}, {percentiles}", samples.len())
+            })
+            .collect()
+    }
+}
+
+/// The `p`th percentile of the sorted latencies
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let index = sorted
+        .len()
+        .saturating_mul(p)
+        .checked_div(100)
+        .unwrap_or_default()
+        .min(sorted.len().saturating_sub(1));
+    sorted.get(index).copied().unwrap_or_default()
+}
+
+/// Aborts the task when it's dropped, so an interrupted canary doesn't leave it behind
+#[derive(Debug)]
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The canary of a prefix
+#[derive(Debug)]
+struct Canary<'a> {
+    /// The client of the cluster
+    client: &'a Client,
+    /// The prefix of the keys
+    prefix: &'a str,
+    /// The lease the keys are attached to
+    lease_id: i64,
+    /// The latencies of the operations
+    latencies: Latencies,
+}
+
+impl Canary<'_> {
+    /// The key written by the `i`th iteration
+    fn key(&self, i: u64) -> String {
+        format!("{}{i:08}", self.prefix)
+    }
+
+    /// Time an operation
+    async fn timed<T, F>(&mut self, op: &'static str, fut: F) -> Result<T>
+    where
+        F: Future<Output = xline_client::error::Result<T>>,
+    {
+        let start = Instant::now();
+        let resp = fut.await?;
+        self.latencies.record(op, start.elapsed());
+        Ok(resp)
+    }
+
+    /// Run the workload for `duration`, returns the number of iterations
+    async fn run(&mut self, duration: Duration) -> Step<u64> {
+        let client = self.client;
+        let header = client
+            .kv_client()
+            .range(
+                RangeRequest::new(self.prefix)
+                    .with_prefix()
+                    .with_count_only(true),
+            )
+            .await?
+            .header;
+        let start_revision = header.map_or(0, |h| h.revision).saturating_add(1);
+        let (_watcher, mut stream) = client
+            .watch_client()
+            .watch(
+                WatchRequest::new(self.prefix)
+                    .with_prefix()
+                    .with_start_revision(start_revision),
+            )
+            .await?;
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+        // the channel is closed once the stream ends
+        let _watch_task = AbortOnDrop(tokio::spawn(async move {
+            loop {
+                match stream.message().await {
+                    Ok(Some(WatchEvent::Events { events, .. })) => {
+                        for event in &events {
+                            if seen_tx.send(describe(event)).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(Some(WatchEvent::Progress { .. })) => {}
+                    // a canceled or broken watcher misses the following writes
+                    _ => return,
+                }
+            }
+        }));
+        let (mut keeper, mut keep_alive_stream) = client
+            .lease_client()
+            .keep_alive(LeaseKeepAliveRequest::new(self.lease_id))
+            .await?;
+
+        let mut expected = ExpectedEvents::default();
+        let started_at = Instant::now();
+        let mut kept_alive_at = started_at;
+        let mut i: u64 = 0;
+        while started_at.elapsed() < duration {
+            if kept_alive_at.elapsed() >= KEEP_ALIVE_INTERVAL {
+                keeper.keep_alive()?;
+                let resp = self
+                    .timed("keep-alive", keep_alive_stream.message())
+                    .await?;
+                if resp.map_or(true, |r| r.ttl <= 0) {
+                    return Ok(Err(Violation::LeaseLost {
+                        lease_id: self.lease_id,
+                    }));
+                }
+                kept_alive_at = Instant::now();
+            }
+            if let Err(violation) = self.iterate(i, &mut expected).await? {
+                return Ok(Err(violation));
+            }
+            while let Ok(seen) = seen_rx.try_recv() {
+                if let Err(violation) = expected.check(seen) {
+                    return Ok(Err(violation));
+                }
+            }
+            i = i.saturating_add(1);
+            tokio::time::sleep(ITERATION_INTERVAL).await;
+        }
+
+        while let Some(first) = expected.first().cloned() {
+            match tokio::time::timeout(WATCH_WAIT, seen_rx.recv()).await {
+                Ok(Some(seen)) => {
+                    if let Err(violation) = expected.check(seen) {
+                        return Ok(Err(violation));
+                    }
+                }
+                Ok(None) | Err(_) => {
+                    return Ok(Err(Violation::MissedWatchEvent {
+                        expected: first,
+                        got: None,
+                    }))
+                }
+            }
+        }
+        let ttl = client
+            .lease_client()
+            .time_to_live(LeaseTimeToLiveRequest::new(self.lease_id))
+            .await?
+            .ttl;
+        if ttl <= 0 {
+            return Ok(Err(Violation::LeaseLost {
+                lease_id: self.lease_id,
+            }));
+        }
+        Ok(Ok(i))
+    }
+
+    /// Put a key, read it back, and overwrite it by a conditional txn
+    async fn iterate(&mut self, i: u64, expected: &mut ExpectedEvents) -> Step<()> {
+        let client = self.client;
+        let key = self.key(i);
+        let value = i.to_string();
+        let put = PutRequest::new(key.as_str(), value.as_str()).with_lease(self.lease_id);
+        let _resp = self.timed("put", client.kv_client().put(put)).await?;
+        expected.push_put(&key, &value);
+
+        let resp = self
+            .timed(
+                "range",
+                client.kv_client().range(RangeRequest::new(key.as_str())),
+            )
+            .await?;
+        let got = resp
+            .kvs
+            .first()
+            .map(|kv| String::from_utf8_lossy(&kv.value).into_owned());
+        if got.as_deref() != Some(value.as_str()) {
+            return Ok(Err(Violation::StaleRead {
+                key,
+                expected: value,
+                got,
+            }));
+        }
+
+        let txn_value = format!("{value}-txn");
+        let txn = TxnRequest::new()
+            .when([Compare::value(
+                key.as_str(),
+                CompareResult::Equal,
+                value.as_str(),
+            )])
+            .and_then([TxnOp::put(
+                PutRequest::new(key.as_str(), txn_value.as_str()).with_lease(self.lease_id),
+            )]);
+        let resp = self.timed("txn", client.kv_client().txn(txn)).await?;
+        if !resp.succeeded {
+            return Ok(Err(Violation::TxnNotSucceeded { key }));
+        }
+        expected.push_put(&key, &txn_value);
+        Ok(Ok(()))
+    }
+
+    /// Remove the keys and the lease of the canary, the failures are only reported
+    async fn teardown(&self) {
+        let delete = DeleteRangeRequest::new(self.prefix).with_prefix();
+        if let Err(e) = self.client.kv_client().delete(delete).await {
+            eprintln!("failed to delete the keys under {}: {e}", self.prefix);
+        }
+        let revoke = LeaseRevokeRequest::new(self.lease_id);
+        if let Err(e) = self.client.lease_client().revoke(revoke).await {
+            eprintln!("failed to revoke lease {:016x}: {e}", self.lease_id);
+        }
+    }
+}
+
+/// Execute the command
+pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    let options = build_request(matches);
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(LEASE_TTL))
+        .await?
+        .id;
+    let mut canary = Canary {
+        client,
+        prefix: &options.prefix,
+        lease_id,
+        latencies: Latencies::default(),
+    };
+    let started_at = Instant::now();
+    #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)]
+    // introduced by tokio::select
+    let outcome = tokio::select! {
+        outcome = canary.run(options.duration) => Some(outcome),
+        _ = ctrl_c() => None,
+    };
+    // the keys and the lease are removed whatever the outcome is
+    canary.teardown().await;
+
+    for line in canary.latencies.report() {
+        println!("{line}");
+    }
+    match outcome {
+        Some(Ok(Ok(iterations))) => {
+            println!(
+                "PASS: {iterations} iterations under {} in {:?}",
+                options.prefix,
+                started_at.elapsed()
+            );
+            Ok(())
+        }
+        Some(Ok(Err(violation))) => {
+            println!("FAIL: {violation}");
+            bail!("the health check failed");
+        }
+        Some(Err(e)) => Err(e),
+        None => bail!("the health check is interrupted"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case_struct;
+
+    test_case_struct!(CheckOptions);
+
+    #[test]
+    fn command_parse_should_be_valid() {
+        let test_cases = vec![
+            TestCase::new(
+                vec!["health", "--prefix", "/canary/"],
+                Some(CheckOptions {
+                    duration: Duration::from_secs(30),
+                    prefix: "/canary/".to_owned(),
+                }),
+            ),
+            TestCase::new(
+                vec!["health", "--duration", "500ms", "--prefix", "/canary/"],
+                Some(CheckOptions {
+                    duration: Duration::from_millis(500),
+                    prefix: "/canary/".to_owned(),
+                }),
+            ),
+            TestCase::new(vec!["health", "--duration", "soon"], None),
+        ];
+
+        for case in test_cases {
+            case.run_test();
+        }
+    }
+
+    #[test]
+    fn default_prefix_should_be_unique_under_the_root() {
+        let matches = command().try_get_matches_from(["health"]).unwrap();
+        let prefix = build_request(&matches).prefix;
+        assert!(prefix.starts_with(PREFIX_ROOT), "{prefix}");
+        assert!(prefix.contains(&std::process::id().to_string()), "{prefix}");
+        assert!(prefix.ends_with('/'), "{prefix}");
+    }
+
+    #[test]
+    fn watch_events_should_be_seen_in_order() {
+        let mut expected = ExpectedEvents::default();
+        expected.push_put("/p/0", "0");
+        expected.push_put("/p/0", "0-txn");
+        assert_eq!(expected.check("put /p/0=0".to_owned()), Ok(()));
+        assert_eq!(
+            expected.check("put /p/1=1".to_owned()),
+            Err(Violation::MissedWatchEvent {
+                expected: "put /p/0=0-txn".to_owned(),
+                got: Some("put /p/1=1".to_owned()),
+            })
+        );
+        assert!(expected.check("delete /p/0".to_owned()).is_err());
+    }
+
+    #[test]
+    fn percentiles_should_be_reported_by_operation() {
+        let mut latencies = Latencies::default();
+        for millis in (1..=100).rev() {
+            latencies.record("put", Duration::from_millis(millis));
+        }
+        latencies.record("range", Duration::from_millis(7));
+        assert_eq!(
+            latencies.report(),
+            [
+                "put: count 100, p50 51ms, p90 91ms, p99 100ms",
+                "range: count 1, p50 7ms, p90 7ms, p99 7ms",
+            ]
+        );
+        assert_eq!(percentile(&[], 99), Duration::ZERO);
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use clap::{ArgMatches, Command};
+use xline_client::Client;
+
+use crate::handle_matches;
+
+/// `health` command
+mod health;
+
+/// Definition of `check` command
+pub(crate) fn command() -> Command {
+    Command::new("check")
+        .about("Commands checking the cluster end to end")
+        .subcommand(health::command())
+}
+
+/// Get matches and generate request
+pub(crate) async fn execute(mut client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    handle_matches!(matches, client, { health });
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use clap::{arg, value_parser, ArgMatches, Command};
+use xline_client::{error::Result, Client};
+use xlineapi::HashKvRequest;
+
+use crate::utils::printer::Printer;
+
+/// Definition of `hashkv` command
+pub(super) fn command() -> Command {
+    Command::new("hashkv")
+        .about("Prints the KV history hash of the endpoint at a revision")
+        .arg(
+            arg!(--rev <REVISION> "The revision to hash, 0 for the latest synced revision")
+                .value_parser(value_parser!(i64))
+                .default_value("0"),
+        )
+}
+
+/// Build request from matches
+pub(super) fn build_request(matches: &ArgMatches) -> HashKvRequest {
+    let revision = *matches.get_one::<i64>("rev").expect("required");
+
+    HashKvRequest { revision }
+}
+
+/// Execute the command
+pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    let request = build_request(matches);
+    let resp = client.maintenance_client().hash_kv(request).await?;
+    resp.print();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case_struct;
+
+    test_case_struct!(HashKvRequest);
+
+    #[test]
+    fn command_parse_should_be_valid() {
+        let test_cases = vec![
+            TestCase::new(vec!["hashkv"], Some(HashKvRequest { revision: 0 })),
+            TestCase::new(
+                vec!["hashkv", "--rev", "100"],
+                Some(HashKvRequest { revision: 100 }),
+            ),
+            TestCase::new(vec!["hashkv", "--rev", "abc"], None),
+        ];
+
+        for case in test_cases {
+            case.run_test();
+        }
+    }
+}
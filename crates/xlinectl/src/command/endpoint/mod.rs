@@ -0,0 +1,20 @@
+use clap::{ArgMatches, Command};
+use xline_client::{error::Result, Client};
+
+use crate::handle_matches;
+
+/// `hashkv` command
+mod hashkv;
+
+/// Definition of `endpoint` command
+pub(crate) fn command() -> Command {
+    Command::new("endpoint")
+        .about("Endpoint related commands")
+        .subcommand(hashkv::command())
+}
+
+/// Get matches and generate request
+pub(crate) async fn execute(mut client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    handle_matches!(matches, client, { hashkv });
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Result};
+use clap::{arg, value_parser, ArgMatches, Command};
+use xline_client::{types::kv::RangeRequest, Client};
+
+use crate::utils::archive::{write_line, ArchiveEntry, ArchiveHeader, ARCHIVE_VERSION};
+
+/// Definition of `export` command
+pub(crate) fn command() -> Command {
+    Command::new("export")
+        .about("Export the keys with a prefix to a portable archive, without the leases")
+        .arg(arg!(--prefix <PREFIX> "The prefix of the keys to export").required(true))
+        .arg(arg!(--output <FILE> "The file to write the archive to").required(true))
+        .arg(
+            arg!(--with_revisions "Keep the create and mod revisions of the keys as metadata")
+                .alias("with-revisions"),
+        )
+        .arg(
+            arg!(--batch_size <SIZE> "Number of keys fetched by each range request")
+                .alias("batch-size")
+                .value_parser(value_parser!(i64).range(1..))
+                .default_value("1000"),
+        )
+}
+
+/// Execute the command
+pub(crate) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    let prefix = matches.get_one::<String>("prefix").expect("required");
+    let output = PathBuf::from(matches.get_one::<String>("output").expect("required"));
+    let with_revisions = matches.get_flag("with_revisions");
+    let batch_size = *matches.get_one::<i64>("batch_size").expect("required");
+    if output.exists() {
+        bail!("file exist: {}", output.display());
+    }
+
+    let range = RangeRequest::new(prefix.as_bytes()).with_prefix();
+    let range_end = range.range_end().to_vec();
+    // written to a temporary file first, so that a failed export leaves no archive
+    let tmp = output.with_extension("exporting");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let mut resp = client
+        .kv_client()
+        .range(range.with_limit(batch_size))
+        .await?;
+    // all pages are read at the revision of the first one
+    let revision = resp.header.as_ref().map_or(0, |h| h.revision);
+    let header = ArchiveHeader {
+        version: ARCHIVE_VERSION,
+        prefix: prefix.as_bytes().to_vec(),
+        revision,
+    };
+    write_line(&mut writer, &header)?;
+    let mut exported: usize = 0;
+    loop {
+        for kv in &resp.kvs {
+            let entry = ArchiveEntry {
+                key: kv.key.clone(),
+                value: kv.value.clone(),
+                create_revision: with_revisions.then_some(kv.create_revision),
+                mod_revision: with_revisions.then_some(kv.mod_revision),
+            };
+            write_line(&mut writer, &entry)?;
+        }
+        exported = exported.saturating_add(resp.kvs.len());
+        let Some(last) = resp.kvs.last().filter(|_| resp.more) else {
+            break;
+        };
+        eprintln!("exported {exported} keys");
+        let mut next = last.key.clone();
+        next.push(0);
+        resp = client
+            .kv_client()
+            .range(
+                RangeRequest::new(next)
+                    .with_range_end(range_end.as_slice())
+                    .with_limit(batch_size)
+                    .with_revision(revision),
+            )
+            .await?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&tmp, &output)?;
+    println!(
+        "exported {exported} keys at revision {revision} to: {}",
+        output.display()
+    );
+
+    Ok(())
+}
@@ -1,9 +1,12 @@
 use anyhow::Result;
 use clap::{arg, value_parser, ArgMatches, Command};
-use xline_client::{types::kv::RangeRequest, Client};
+use xline_client::{
+    types::{kv::RangeRequest, maintenance::TimeToRevisionRequest},
+    Client,
+};
 use xlineapi::{SortOrder, SortTarget};
 
-use crate::utils::printer::Printer;
+use crate::utils::{parser::parse_rfc3339, printer::Printer};
 
 /// Definition of `get` command
 pub(crate) fn command() -> Command {
@@ -43,6 +46,11 @@ pub(crate) fn command() -> Command {
                 .value_parser(value_parser!(i64))
                 .default_value("0")
         )
+        .arg(
+            arg!(--at_time <TIME> "Get the keys as of a RFC 3339 time, resolved to the latest revision recorded before it")
+                .alias("at-time")
+                .conflicts_with("rev")
+        )
         .arg(
             arg!(--keys_only "Get only the keys")
         )
@@ -108,7 +116,21 @@ pub(crate) fn build_request(matches: &ArgMatches) -> RangeRequest {
 
 /// Execute the command
 pub(crate) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
-    let req = build_request(matches);
+    let mut req = build_request(matches);
+    if let Some(at_time) = matches.get_one::<String>("at_time") {
+        let time_ms = parse_rfc3339(at_time)?;
+        let resolved = client
+            .maintenance_client()
+            .time_to_revision(TimeToRevisionRequest { time_ms })
+            .await?;
+        eprintln!(
+            "{at_time} is resolved to revision {} recorded {}ms earlier, with a resolution of {}ms",
+            resolved.revision,
+            time_ms.saturating_sub(resolved.recorded_at_ms),
+            resolved.resolution_ms
+        );
+        req = req.with_revision(resolved.revision);
+    }
     let resp = client.kv_client().range(req).await?;
     resp.print();
 
@@ -161,6 +183,10 @@ mod tests {
                 vec!["get", "key", "--rev", "5"],
                 Some(RangeRequest::new("key".as_bytes()).with_revision(5)),
             ),
+            TestCase::new(
+                vec!["get", "key", "--at-time", "2024-01-02T03:04:05Z"],
+                Some(RangeRequest::new("key".as_bytes())),
+            ),
             TestCase::new(
                 vec!["get", "key", "--keys_only"],
                 Some(RangeRequest::new("key".as_bytes()).with_keys_only(true)),
@@ -182,6 +208,17 @@ mod tests {
             TestCase::new(vec!["get", "key", "key2", "--from_key"], None),
             TestCase::new(vec!["get", "key", "key2", "--prefix"], None),
             TestCase::new(vec!["get", "key", "--from_key", "--prefix"], None),
+            TestCase::new(
+                vec![
+                    "get",
+                    "key",
+                    "--rev",
+                    "5",
+                    "--at_time",
+                    "2024-01-02T03:04:05Z",
+                ],
+                None,
+            ),
         ];
 
         for case in test_cases {
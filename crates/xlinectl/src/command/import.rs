@@ -0,0 +1,140 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::{arg, value_parser, ArgMatches, Command};
+use xline_client::{
+    types::kv::{PutRequest, TxnOp, TxnRequest},
+    Client,
+};
+
+use crate::utils::{
+    archive::{read_entries, read_header, rewrite_prefix, write_line, ImportProgress},
+    parser::parse_prefix_rewrite,
+};
+
+/// Definition of `import` command
+pub(crate) fn command() -> Command {
+    Command::new("import")
+        .about("Import an archive written by `export`, the keys are written as new revisions")
+        .arg(arg!(<file> "The archive to import"))
+        .arg(
+            arg!(--prefix_rewrite <REWRITE> "Replace the prefix of the keys, in the form of `old=new`")
+                .alias("prefix-rewrite"),
+        )
+        .arg(
+            arg!(--resume "Resume an interrupted import after the last imported key")
+        )
+        .arg(
+            arg!(--batch_size <SIZE> "Number of keys written by each transaction")
+                .alias("batch-size")
+                .value_parser(value_parser!(u64).range(1..=128))
+                .default_value("100"),
+        )
+}
+
+/// The file recording the progress of importing an archive
+fn progress_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".progress");
+    PathBuf::from(path)
+}
+
+/// Read the last imported key of an interrupted import
+fn read_progress(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let progress: ImportProgress = serde_json::from_slice(&fs::read(path)?)?;
+    Ok(Some(progress.last_key))
+}
+
+/// Write the last imported key
+fn write_progress(path: &Path, last_key: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("progress.tmp");
+    let mut buf = vec![];
+    write_line(
+        &mut buf,
+        &ImportProgress {
+            last_key: last_key.to_vec(),
+        },
+    )?;
+    fs::write(&tmp, buf)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Write a batch of keys in a transaction and record the last one of them
+async fn flush(
+    client: &Client,
+    progress: &Path,
+    batch: &mut Vec<(Vec<u8>, TxnOp)>,
+) -> Result<usize> {
+    let Some(last_key) = batch.last().map(|(key, _)| key.clone()) else {
+        return Ok(0);
+    };
+    let ops: Vec<_> = batch.drain(..).map(|(_, op)| op).collect();
+    let count = ops.len();
+    let _resp = client
+        .kv_client()
+        .txn(TxnRequest::new().and_then(ops))
+        .await?;
+    write_progress(progress, &last_key)?;
+    Ok(count)
+}
+
+/// Execute the command
+pub(crate) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    let file = PathBuf::from(matches.get_one::<String>("file").expect("required"));
+    let rewrite = matches
+        .get_one::<String>("prefix_rewrite")
+        .map(|arg| parse_prefix_rewrite(arg))
+        .transpose()?;
+    let resume = matches.get_flag("resume");
+    let batch_size: usize = (*matches.get_one::<u64>("batch_size").expect("required"))
+        .try_into()
+        .unwrap_or(usize::MAX);
+    let progress = progress_path(&file);
+    let resume_after = if resume {
+        read_progress(&progress)?
+    } else {
+        None
+    };
+
+    let mut reader = BufReader::new(File::open(&file)?);
+    let header = read_header(&mut reader)?;
+    if let Some(ref last_key) = resume_after {
+        eprintln!("resuming after key {}", String::from_utf8_lossy(last_key));
+    }
+    let mut imported: usize = 0;
+    let mut batch = Vec::with_capacity(batch_size);
+    for entry in read_entries(reader) {
+        let entry = entry?;
+        // the entries are in ascending order of the keys
+        if resume_after.as_ref().is_some_and(|last| entry.key <= *last) {
+            continue;
+        }
+        let key = rewrite.as_ref().map_or_else(
+            || entry.key.clone(),
+            |(old, new)| rewrite_prefix(&entry.key, old, new),
+        );
+        batch.push((entry.key, TxnOp::put(PutRequest::new(key, entry.value))));
+        if batch.len() >= batch_size {
+            imported = imported.saturating_add(flush(client, &progress, &mut batch).await?);
+            eprintln!("imported {imported} keys");
+        }
+    }
+    imported = imported.saturating_add(flush(client, &progress, &mut batch).await?);
+    if progress.exists() {
+        fs::remove_file(&progress)?;
+    }
+    println!(
+        "imported {imported} keys exported at revision {}",
+        header.revision
+    );
+
+    Ok(())
+}
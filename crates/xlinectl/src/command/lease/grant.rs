@@ -7,7 +7,11 @@ use crate::utils::printer::Printer;
 pub(super) fn command() -> Command {
     Command::new("grant")
         .about("Create a lease with a given TTL")
-        .arg(arg!(<ttl> "time to live of the lease").value_parser(value_parser!(i64)))
+        .arg(
+            arg!(<ttl> "time to live of the lease in seconds, 0 means the default ttl")
+                .value_parser(value_parser!(i64).range(0..))
+                .allow_negative_numbers(true),
+        )
 }
 
 /// Build request from matches
@@ -34,10 +38,13 @@ mod tests {
 
     #[test]
     fn command_parse_should_be_valid() {
-        let test_cases = vec![TestCase::new(
-            vec!["grant", "100"],
-            Some(LeaseGrantRequest::new(100)),
-        )];
+        let test_cases = vec![
+            TestCase::new(vec!["grant", "100"], Some(LeaseGrantRequest::new(100))),
+            TestCase::new(vec!["grant", "0"], Some(LeaseGrantRequest::new(0))),
+            TestCase::new(vec!["grant", "-1"], None),
+            TestCase::new(vec!["grant", "ten"], None),
+            TestCase::new(vec!["grant"], None),
+        ];
 
         for case in test_cases {
             case.run_test();
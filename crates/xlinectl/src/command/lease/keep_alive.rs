@@ -1,22 +1,25 @@
 use std::time::Duration;
 
-use clap::{arg, value_parser, ArgMatches, Command};
+use clap::{arg, ArgMatches, Command};
 use tokio::signal::ctrl_c;
-use tonic::Streaming;
 use xline_client::{
     error::{Result, XlineClientError},
-    types::lease::{LeaseKeepAliveRequest, LeaseKeeper},
+    types::lease::{LeaseKeepAliveRequest, LeaseKeepAliveStream, LeaseKeeper},
     Client,
 };
-use xlineapi::LeaseKeepAliveResponse;
+use xlineapi::execute_error::ExecuteError;
 
-use crate::utils::printer::Printer;
+use crate::utils::{parser::parse_lease_id, printer::Printer};
+
+/// Interval between two attempts of reconnecting a broken keep alive stream
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Definition of `keep_alive` command
 pub(super) fn command() -> Command {
     Command::new("keep_alive")
+        .visible_alias("keep-alive")
         .about("Lease keep alive periodically")
-        .arg(arg!(<leaseId> "Lease Id to keep alive").value_parser(value_parser!(i64)))
+        .arg(arg!(<leaseId> "Lease Id to keep alive, in hexadecimal").value_parser(parse_lease_id))
         .arg(arg!(--once "keep alive once"))
 }
 
@@ -53,25 +56,46 @@ pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result
     Ok(())
 }
 
-/// keep alive forever unless encounter error
-async fn keep_alive_loop(
-    mut keeper: LeaseKeeper,
-    mut stream: Streaming<LeaseKeepAliveResponse>,
-) -> Result<()> {
+/// Keep alive forever until the lease is gone, a broken stream, e.g. because of a
+/// leader change, is reconnected
+async fn keep_alive_loop(mut keeper: LeaseKeeper, mut stream: LeaseKeepAliveStream) -> Result<()> {
     loop {
-        keeper.keep_alive()?;
-        if let Some(resp) = stream.message().await? {
-            resp.print();
-            if resp.ttl < 0 {
-                return Err(XlineClientError::InvalidArgs(String::from(
-                    "lease keepalive response has negative ttl",
-                )));
+        // the channel of a broken stream is closed, it's replaced when the stream is
+        // reopened by `message`
+        if let Err(e) = keeper.keep_alive() {
+            eprintln!("failed to send keep alive request: {e}");
+        }
+        match stream.message().await {
+            Ok(Some(resp)) => {
+                resp.print();
+                if resp.ttl <= 0 {
+                    return Err(ExecuteError::LeaseExpired(keeper.id()).into());
+                }
+                tokio::time::sleep(Duration::from_secs(resp.ttl.unsigned_abs() / 3)).await;
+            }
+            Ok(None) => tokio::time::sleep(RECONNECT_INTERVAL).await,
+            Err(e) if is_lease_gone(&e) => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "keep alive stream of lease {:016x} is broken, reconnecting: {e}",
+                    keeper.id()
+                );
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
             }
-            tokio::time::sleep(Duration::from_secs(resp.ttl.unsigned_abs() / 3)).await;
         }
     }
 }
 
+/// Whether the error means the lease doesn't exist any more, so that reconnecting won't help
+fn is_lease_gone(err: &XlineClientError<xlineapi::command::Command>) -> bool {
+    matches!(
+        *err,
+        XlineClientError::ExecuteError(
+            ExecuteError::LeaseNotFound(_) | ExecuteError::LeaseExpired(_)
+        )
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -84,12 +108,13 @@ mod tests {
         let test_cases = vec![
             TestCase::new(
                 vec!["keep_alive", "123"],
-                Some(LeaseKeepAliveRequest::new(123)),
+                Some(LeaseKeepAliveRequest::new(0x123)),
             ),
             TestCase::new(
-                vec!["keep_alive", "456", "--once"],
-                Some(LeaseKeepAliveRequest::new(456)),
+                vec!["keep-alive", "456", "--once"],
+                Some(LeaseKeepAliveRequest::new(0x456)),
             ),
+            TestCase::new(vec!["keep-alive", "forever"], None),
         ];
 
         for case in test_cases {
@@ -1,13 +1,13 @@
-use clap::{arg, value_parser, ArgMatches, Command};
+use clap::{arg, ArgMatches, Command};
 use xline_client::{error::Result, types::lease::LeaseRevokeRequest, Client};
 
-use crate::utils::printer::Printer;
+use crate::utils::{parser::parse_lease_id, printer::Printer};
 
 /// Definition of `revoke` command
 pub(super) fn command() -> Command {
     Command::new("revoke")
         .about("Revoke a lease")
-        .arg(arg!(<leaseId> "Lease Id to revoke").value_parser(value_parser!(i64)))
+        .arg(arg!(<leaseId> "Lease Id to revoke, in hexadecimal").value_parser(parse_lease_id))
 }
 
 /// Build request from matches
@@ -34,10 +34,15 @@ mod tests {
 
     #[test]
     fn command_parse_should_be_valid() {
-        let test_cases = vec![TestCase::new(
-            vec!["revoke", "123"],
-            Some(LeaseRevokeRequest::new(123)),
-        )];
+        let test_cases = vec![
+            TestCase::new(vec!["revoke", "123"], Some(LeaseRevokeRequest::new(0x123))),
+            TestCase::new(
+                vec!["revoke", "0x7b0000000000abcd"],
+                Some(LeaseRevokeRequest::new(0x7b00_0000_0000_abcd)),
+            ),
+            TestCase::new(vec!["revoke", "lease1"], None),
+            TestCase::new(vec!["revoke", "ffffffffffffffff"], None),
+        ];
 
         for case in test_cases {
             case.run_test();
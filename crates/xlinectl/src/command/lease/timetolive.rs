@@ -1,19 +1,21 @@
-use clap::{arg, value_parser, ArgMatches, Command};
+use clap::{arg, ArgMatches, Command};
 use xline_client::{error::Result, types::lease::LeaseTimeToLiveRequest, Client};
 
-use crate::utils::printer::Printer;
+use crate::utils::{parser::parse_lease_id, printer::Printer};
 
 /// Definition of `timetolive` command
 pub(super) fn command() -> Command {
     Command::new("timetolive")
         .about("Get lease ttl information")
-        .arg(arg!(<leaseId> "Lease id to get").value_parser(value_parser!(i64)))
+        .arg(arg!(<leaseId> "Lease id to get, in hexadecimal").value_parser(parse_lease_id))
+        .arg(arg!(--keys "Get keys attached to this lease"))
 }
 
 /// Build request from matches
 pub(super) fn build_request(matches: &ArgMatches) -> LeaseTimeToLiveRequest {
     let lease_id = matches.get_one::<i64>("leaseId").expect("required");
-    LeaseTimeToLiveRequest::new(*lease_id)
+    let keys = matches.get_flag("keys");
+    LeaseTimeToLiveRequest::new(*lease_id).with_keys(keys)
 }
 
 /// Execute the command
@@ -34,10 +36,17 @@ mod tests {
 
     #[test]
     fn command_parse_should_be_valid() {
-        let test_cases = vec![TestCase::new(
-            vec!["timetolive", "123"],
-            Some(LeaseTimeToLiveRequest::new(123)),
-        )];
+        let test_cases = vec![
+            TestCase::new(
+                vec!["timetolive", "123"],
+                Some(LeaseTimeToLiveRequest::new(0x123)),
+            ),
+            TestCase::new(
+                vec!["timetolive", "7b", "--keys"],
+                Some(LeaseTimeToLiveRequest::new(123).with_keys(true)),
+            ),
+            TestCase::new(vec!["timetolive", "-7b"], None),
+        ];
 
         for case in test_cases {
             case.run_test();
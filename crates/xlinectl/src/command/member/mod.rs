@@ -1,5 +1,6 @@
+use anyhow::Result;
 use clap::{ArgMatches, Command};
-use xline_client::{error::Result, Client};
+use xline_client::Client;
 
 use crate::handle_matches;
 
@@ -11,6 +12,8 @@ mod list;
 mod promote;
 /// `remove` command
 mod remove;
+/// `replace` command
+mod replace;
 /// `update` command
 mod update;
 
@@ -23,16 +26,17 @@ pub(crate) fn command() -> Command {
         .subcommand(list::command())
         .subcommand(remove::command())
         .subcommand(promote::command())
+        .subcommand(replace::command())
 }
 
 /// Get matches and generate request
 pub(crate) async fn execute(mut client: &mut Client, matches: &ArgMatches) -> Result<()> {
-    handle_matches!(matches, client, { add, update, list, remove, promote });
+    handle_matches!(matches, client, { add, update, list, remove, promote, replace });
     Ok(())
 }
 
 #[allow(clippy::unnecessary_wraps)] // The Result is required by clap
 /// Parse comma separated peer urls
-pub(super) fn parse_peer_urls(arg: &str) -> Result<Vec<String>> {
+pub(super) fn parse_peer_urls(arg: &str) -> xline_client::error::Result<Vec<String>> {
     Ok(arg.split(',').map(ToOwned::to_owned).collect())
 }
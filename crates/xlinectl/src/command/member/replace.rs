@@ -0,0 +1,457 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use clap::{arg, value_parser, ArgMatches, Command};
+use ext_utils::ConfigParseError;
+use tokio::{
+    fs,
+    time::{sleep, timeout},
+};
+use tonic::transport::{Certificate, ClientTlsConfig};
+use xline_client::{
+    clients::MaintenanceClient,
+    types::cluster::{
+        Member, MemberAddRequest, MemberListRequest, MemberPromoteRequest, MemberRemoveRequest,
+    },
+    Client,
+};
+use xlineapi::StatusResponse;
+
+/// Interval between two polls of the replication status of the learner
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Timeout of the status request to a member
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Definition of `replace` command
+pub(super) fn command() -> Command {
+    Command::new("replace")
+        .about("Replaces a member by adding the new one as a learner, promoting it once it catches up and removing the old one, re-running continues from the current state")
+        .arg(
+            arg!(--old <ID> "The ID of the member to replace")
+                .required(true)
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--new_peer_urls <URLS> "Comma separated peer URLs for the new member")
+                .required(true)
+                .value_parser(parse_urls),
+        )
+        .arg(
+            arg!(--learner_wait <DURATION> "How long to wait for the learner to catch up")
+                .value_parser(|arg: &str| ext_utils::parse_duration(arg))
+                .default_value("5m"),
+        )
+        .arg(
+            arg!(--max_lag <ENTRIES> "The max number of log entries the learner may lag behind the leader to be promoted")
+                .value_parser(value_parser!(u64))
+                .default_value("500"),
+        )
+}
+
+/// Parse comma separated urls, they are normalized as the server stores them
+fn parse_urls(arg: &str) -> Result<Vec<String>, ConfigParseError> {
+    arg.split(',').map(ext_utils::parse_url).collect()
+}
+
+/// Options of the replacement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ReplaceOptions {
+    /// The ID of the member to replace
+    old: u64,
+    /// Peer URLs of the new member
+    new_peer_urls: Vec<String>,
+    /// How long to wait for the learner to catch up
+    learner_wait: Duration,
+    /// The max number of log entries the learner may lag behind the leader
+    max_lag: u64,
+}
+
+/// Build options from matches
+pub(super) fn build_request(matches: &ArgMatches) -> ReplaceOptions {
+    let old = *matches.get_one::<u64>("old").expect("required");
+    let new_peer_urls = matches
+        .get_one::<Vec<String>>("new_peer_urls")
+        .expect("required")
+        .clone();
+    let learner_wait = *matches
+        .get_one::<Duration>("learner_wait")
+        .expect("required");
+    let max_lag = *matches.get_one::<u64>("max_lag").expect("required");
+
+    ReplaceOptions {
+        old,
+        new_peer_urls,
+        learner_wait,
+        max_lag,
+    }
+}
+
+/// The step the replacement continues from, detected from the members
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// The new member is to be added as a learner
+    AddLearner,
+    /// The new member is a learner to be promoted
+    Promote(u64),
+    /// The new member is a voter, the old one is to be removed
+    RemoveOld(u64),
+    /// The old member is removed and the new one is a voter
+    Done(u64),
+}
+
+/// Detect the step to continue from, the new member is the one with the new peer urls
+fn detect_step(members: &[Member], old: u64, new_peer_urls: &[String]) -> Result<Step> {
+    let new_urls: BTreeSet<_> = new_peer_urls.iter().collect();
+    let new = members
+        .iter()
+        .find(|m| m.peer_ur_ls.iter().collect::<BTreeSet<_>>() == new_urls);
+    let old_exists = members.iter().any(|m| m.id == old);
+    match (old_exists, new) {
+        (_, Some(new)) if new.id == old => {
+            bail!("the new peer urls belong to member {old} itself")
+        }
+        (_, Some(new)) if new.is_learner => Ok(Step::Promote(new.id)),
+        (true, Some(new)) => Ok(Step::RemoveOld(new.id)),
+        (false, Some(new)) => Ok(Step::Done(new.id)),
+        (true, None) => Ok(Step::AddLearner),
+        (false, None) => bail!("member {old} is not found"),
+    }
+}
+
+/// Check that a quorum of the voters stays healthy after a change of the voters
+fn check_quorum(action: &str, voters: usize, healthy: usize) -> Result<()> {
+    let quorum = voters.checked_div(2).unwrap_or(0).saturating_add(1);
+    if healthy < quorum {
+        bail!(
+            "{action} is unsafe: {healthy} of the {voters} voters would be healthy, below the quorum {quorum}"
+        );
+    }
+    Ok(())
+}
+
+/// The replacement of a member
+struct Replacement<'a> {
+    /// The client of the cluster
+    client: &'a mut Client,
+    /// Options of the replacement
+    options: ReplaceOptions,
+    /// TLS config to reach each member
+    tls_config: Option<ClientTlsConfig>,
+}
+
+impl Replacement<'_> {
+    /// List the members linearizably
+    async fn members(&mut self) -> Result<Vec<Member>> {
+        Ok(self
+            .client
+            .cluster_client()
+            .member_list(MemberListRequest::new(true))
+            .await?
+            .members)
+    }
+
+    /// Status of a member, `None` if none of its client urls is reachable
+    async fn status(&self, member: &Member) -> Option<StatusResponse> {
+        for url in &member.client_ur_ls {
+            let Ok(endpoint) = ext_utils::build_endpoint(url, self.tls_config.as_ref()) else {
+                continue;
+            };
+            let status = async {
+                let channel = endpoint.connect().await.ok()?;
+                MaintenanceClient::new(channel, None).status().await.ok()
+            };
+            if let Ok(Some(resp)) = timeout(STATUS_TIMEOUT, status).await {
+                return Some(resp);
+            }
+        }
+        None
+    }
+
+    /// Number of the voters that are reachable and see a leader, the member in
+    /// `except` is not counted
+    async fn healthy_voters(&self, members: &[Member], except: u64) -> usize {
+        let mut healthy = 0_usize;
+        for member in members.iter().filter(|m| !m.is_learner && m.id != except) {
+            if self.status(member).await.is_some_and(|s| s.leader != 0) {
+                healthy = healthy.saturating_add(1);
+            }
+        }
+        healthy
+    }
+
+    /// The number of log entries the learner lags behind the leader, `None` if the
+    /// learner or the leader is not reachable
+    async fn lag(&self, members: &[Member], learner: u64) -> Option<u64> {
+        let learner = self
+            .status(members.iter().find(|m| m.id == learner)?)
+            .await?;
+        let leader = members.iter().find(|m| m.id == learner.leader)?;
+        let leader = self.status(leader).await?;
+        Some(leader.raft_index.saturating_sub(learner.raft_applied_index))
+    }
+
+    /// Add the new member as a learner
+    async fn add_learner(&mut self) -> Result<u64> {
+        let resp = self
+            .client
+            .cluster_client()
+            .member_add(MemberAddRequest::new(
+                self.options.new_peer_urls.clone(),
+                true,
+            ))
+            .await?;
+        let Some(learner) = resp.member else {
+            bail!("the added learner is missing in the response");
+        };
+        println!("added learner {}", learner.id);
+        Ok(learner.id)
+    }
+
+    /// Wait for the learner to catch up and promote it
+    async fn promote(&mut self, learner: u64) -> Result<()> {
+        let started_at = Instant::now();
+        let mut last_progress = String::new();
+        loop {
+            let members = self.members().await?;
+            let progress = match self.lag(&members, learner).await {
+                Some(lag) if lag <= self.options.max_lag => {
+                    let voters = members.iter().filter(|m| !m.is_learner).count();
+                    let healthy = self.healthy_voters(&members, learner).await;
+                    check_quorum(
+                        &format!("promoting learner {learner}"),
+                        voters.saturating_add(1),
+                        healthy.saturating_add(1),
+                    )?;
+                    match self
+                        .client
+                        .cluster_client()
+                        .member_promote(MemberPromoteRequest::new(learner))
+                        .await
+                    {
+                        Ok(_resp) => {
+                            println!("promoted learner {learner}, {lag} entries behind the leader");
+                            return Ok(());
+                        }
+                        Err(e) => format!("promoting learner {learner} is rejected: {e}"),
+                    }
+                }
+                Some(lag) => format!("learner {learner} is {lag} entries behind the leader"),
+                None => format!("waiting for learner {learner} to start"),
+            };
+            if progress != last_progress {
+                println!("{progress}");
+                last_progress = progress;
+            }
+            if started_at.elapsed() >= self.options.learner_wait {
+                bail!(
+                    "learner {learner} did not catch up in {:?}, re-run the command to continue",
+                    self.options.learner_wait
+                );
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Remove the old member once the new one is a healthy voter
+    async fn remove_old(&mut self, new: u64) -> Result<()> {
+        let old = self.options.old;
+        let members = self.members().await?;
+        let voters = members
+            .iter()
+            .filter(|m| !m.is_learner && m.id != old)
+            .count();
+        let healthy = self.healthy_voters(&members, old).await;
+        let new_is_healthy = match members.iter().find(|m| m.id == new) {
+            Some(member) => self.status(member).await.is_some_and(|s| s.leader != 0),
+            None => false,
+        };
+        if !new_is_healthy {
+            bail!("removing member {old} is unsafe: the new member {new} is not healthy");
+        }
+        check_quorum(&format!("removing member {old}"), voters, healthy)?;
+        let _resp = self
+            .client
+            .cluster_client()
+            .member_remove(MemberRemoveRequest::new(old))
+            .await?;
+        println!("removed member {old}");
+        Ok(())
+    }
+
+    /// Run the replacement from the detected step
+    async fn run(&mut self) -> Result<u64> {
+        let old = self.options.old;
+        let members = self.members().await?;
+        let mut step = detect_step(&members, old, &self.options.new_peer_urls)?;
+        loop {
+            step = match step {
+                Step::AddLearner => Step::Promote(self.add_learner().await?),
+                Step::Promote(learner) => {
+                    self.promote(learner).await?;
+                    Step::RemoveOld(learner)
+                }
+                Step::RemoveOld(new) => {
+                    self.remove_old(new).await?;
+                    Step::Done(new)
+                }
+                Step::Done(new) => return Ok(new),
+            };
+        }
+    }
+}
+
+/// TLS config of the global `--ca_cert_pem_path` option
+async fn tls_config(matches: &ArgMatches) -> Result<Option<ClientTlsConfig>> {
+    let Some(path) = matches
+        .try_get_one::<PathBuf>("ca_cert_pem_path")
+        .ok()
+        .flatten()
+    else {
+        return Ok(None);
+    };
+    let ca = Certificate::from_pem(fs::read_to_string(path).await?);
+    Ok(Some(ClientTlsConfig::new().ca_certificate(ca)))
+}
+
+/// Execute the command
+pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
+    let options = build_request(matches);
+    let old = options.old;
+    let mut replacement = Replacement {
+        client,
+        options,
+        tls_config: tls_config(matches).await?,
+    };
+    let members = replacement.members().await?;
+    match detect_step(&members, old, &replacement.options.new_peer_urls)? {
+        Step::AddLearner => println!(
+            "replacing member {old} with a new member at {}",
+            replacement.options.new_peer_urls.join(",")
+        ),
+        Step::Promote(learner) => {
+            println!("resuming: learner {learner} is added, waiting for it to catch up");
+        }
+        Step::RemoveOld(new) => {
+            println!("resuming: member {new} is promoted, removing member {old}");
+        }
+        Step::Done(new) => {
+            println!("member {old} is already replaced by {new}, nothing to do");
+            return Ok(());
+        }
+    }
+    let new = replacement.run().await?;
+    println!("member {old} is replaced by {new}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_case_struct;
+
+    test_case_struct!(ReplaceOptions);
+
+    fn member(id: u64, peer_url: &str, is_learner: bool) -> Member {
+        Member {
+            id,
+            name: format!("node{id}"),
+            peer_ur_ls: vec![peer_url.to_owned()],
+            client_ur_ls: vec![],
+            is_learner,
+        }
+    }
+
+    #[test]
+    fn command_parse_should_be_valid() {
+        let test_cases = vec![
+            TestCase::new(
+                vec![
+                    "replace",
+                    "--old",
+                    "1",
+                    "--new_peer_urls",
+                    "http://127.0.0.1:2380,http://127.0.0.1:2381",
+                ],
+                Some(ReplaceOptions {
+                    old: 1,
+                    new_peer_urls: vec![
+                        "http://127.0.0.1:2380".to_owned(),
+                        "http://127.0.0.1:2381".to_owned(),
+                    ],
+                    learner_wait: Duration::from_secs(300),
+                    max_lag: 500,
+                }),
+            ),
+            TestCase::new(
+                vec![
+                    "replace",
+                    "--old",
+                    "1",
+                    "--new_peer_urls",
+                    "http://127.0.0.1:2380",
+                    "--learner_wait",
+                    "10s",
+                    "--max_lag",
+                    "10",
+                ],
+                Some(ReplaceOptions {
+                    old: 1,
+                    new_peer_urls: vec!["http://127.0.0.1:2380".to_owned()],
+                    learner_wait: Duration::from_secs(10),
+                    max_lag: 10,
+                }),
+            ),
+            TestCase::new(
+                vec!["replace", "--old", "1", "--new_peer_urls", "127.0.0.1:2380"],
+                None,
+            ),
+            TestCase::new(vec!["replace", "--new_peer_urls", "http://a:1"], None),
+        ];
+
+        for case in test_cases {
+            case.run_test();
+        }
+    }
+
+    #[test]
+    fn step_should_be_detected_from_the_members() {
+        let new_urls = vec!["http://new:2380".to_owned()];
+        let mut members = vec![
+            member(1, "http://a:2380", false),
+            member(2, "http://b:2380", false),
+            member(3, "http://c:2380", false),
+        ];
+        assert_eq!(
+            detect_step(&members, 3, &new_urls).unwrap(),
+            Step::AddLearner
+        );
+        members.push(member(4, "http://new:2380", true));
+        assert_eq!(
+            detect_step(&members, 3, &new_urls).unwrap(),
+            Step::Promote(4)
+        );
+        members[3].is_learner = false;
+        assert_eq!(
+            detect_step(&members, 3, &new_urls).unwrap(),
+            Step::RemoveOld(4)
+        );
+        let _old = members.remove(2);
+        assert_eq!(detect_step(&members, 3, &new_urls).unwrap(), Step::Done(4));
+        assert!(detect_step(&members, 5, &["http://d:2380".to_owned()]).is_err());
+        assert!(detect_step(&members, 1, &["http://a:2380".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn changes_below_the_quorum_should_be_rejected() {
+        assert!(check_quorum("promoting", 4, 3).is_ok());
+        assert!(check_quorum("promoting", 4, 2).is_err());
+        assert!(check_quorum("removing", 3, 2).is_ok());
+        assert!(check_quorum("removing", 3, 1).is_err());
+        assert!(check_quorum("removing", 1, 1).is_ok());
+    }
+}
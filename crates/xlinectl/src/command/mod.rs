@@ -1,11 +1,19 @@
 /// Auth command
 pub(crate) mod auth;
+/// Check command
+pub(crate) mod check;
 /// Compaction command
 pub(crate) mod compaction;
 /// Delete command
 pub(crate) mod delete;
+/// Endpoint command
+pub(crate) mod endpoint;
+/// Export command
+pub(crate) mod export;
 /// Get command
 pub(crate) mod get;
+/// Import command
+pub(crate) mod import;
 /// Lease command
 pub(crate) mod lease;
 /// Lock command
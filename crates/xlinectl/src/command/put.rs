@@ -1,8 +1,8 @@
 use anyhow::Result;
-use clap::{arg, value_parser, ArgMatches, Command};
+use clap::{arg, ArgMatches, Command};
 use xline_client::{types::kv::PutRequest, Client};
 
-use crate::utils::printer::Printer;
+use crate::utils::{parser::parse_lease_id, printer::Printer};
 
 /// Definition of `get` command
 pub(crate) fn command() -> Command {
@@ -12,8 +12,8 @@ pub(crate) fn command() -> Command {
         // TODO: support reading value from stdin
         .arg(arg!(<value> "The value"))
         .arg(
-            arg!(--lease <ID> "lease ID to attach to the key")
-                .value_parser(value_parser!(i64))
+            arg!(--lease <ID> "lease ID in hexadecimal to attach to the key")
+                .value_parser(parse_lease_id)
                 .default_value("0"),
         )
         .arg(arg!(--prev_kv "return the previous key-value pair before modification"))
@@ -11,6 +11,7 @@ use crate::utils::printer::Printer;
 /// Definition of `grant_perm` command
 pub(super) fn command() -> Command {
     Command::new("grant_perm")
+        .visible_alias("grant-permission")
         .about("Grant permission to a role")
         .arg(arg!(<name> "The name of the role"))
         .arg(arg!(<perm_type> "The type of the permission").value_parser(["Read", "Write", "ReadWrite"]))
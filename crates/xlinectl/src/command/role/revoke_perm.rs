@@ -6,10 +6,16 @@ use crate::utils::printer::Printer;
 /// Definition of `revoke_perm` command
 pub(super) fn command() -> Command {
     Command::new("revoke_perm")
+        .visible_alias("revoke-permission")
         .about("Revoke permission from a role")
         .arg(arg!(<name> "The name of the role"))
         .arg(arg!(<key> "The Key"))
         .arg(arg!([range_end] "Range end of the key"))
+        .arg(arg!(--prefix "Revoke the permission on keys with matching prefix").conflicts_with("range_end"))
+        .arg(
+            arg!(--from_key "Revoke the permission on keys that are greater than or equal to the given key using byte compare")
+                .conflicts_with_all(["range_end", "prefix"])
+        )
 }
 
 /// Build request from matches
@@ -17,6 +23,8 @@ pub(super) fn build_request(matches: &ArgMatches) -> AuthRoleRevokePermissionReq
     let name = matches.get_one::<String>("name").expect("required");
     let key = matches.get_one::<String>("key").expect("required");
     let range_end = matches.get_one::<String>("range_end");
+    let prefix = matches.get_flag("prefix");
+    let from_key = matches.get_flag("from_key");
 
     let mut request = AuthRoleRevokePermissionRequest::new(name, key.as_bytes());
 
@@ -24,6 +32,14 @@ pub(super) fn build_request(matches: &ArgMatches) -> AuthRoleRevokePermissionReq
         request = request.with_range_end(range_end.as_bytes());
     };
 
+    if prefix {
+        request = request.with_prefix();
+    }
+
+    if from_key {
+        request = request.with_from_key();
+    }
+
     request
 }
 
@@ -54,6 +70,14 @@ mod tests {
                 vec!["revoke_perm", "Admin", "key3"],
                 Some(AuthRoleRevokePermissionRequest::new("Admin", "key3")),
             ),
+            TestCase::new(
+                vec!["revoke_perm", "Admin", "key4", "--prefix"],
+                Some(AuthRoleRevokePermissionRequest::new("Admin", "key4").with_prefix()),
+            ),
+            TestCase::new(
+                vec!["revoke_perm", "Admin", "key5", "--from_key"],
+                Some(AuthRoleRevokePermissionRequest::new("Admin", "key5").with_from_key()),
+            ),
         ];
 
         for case in test_cases {
@@ -1,7 +1,11 @@
+use anyhow::Result;
 use clap::{arg, ArgMatches, Command};
-use xline_client::{error::Result, types::auth::AuthUserAddRequest, Client};
+use xline_client::{types::auth::AuthUserAddRequest, Client};
 
-use crate::utils::printer::Printer;
+use crate::utils::{
+    parser::{read_new_password, read_password},
+    printer::Printer,
+};
 
 /// Definition of `add` command
 pub(super) fn command() -> Command {
@@ -10,27 +14,42 @@ pub(super) fn command() -> Command {
         .arg(arg!(<name> "The name of the user"))
         .arg(
             arg!([password] "Password of the user")
-                .required_if_eq("no_password", "false")
-                .required_unless_present("no_password"),
+                .required_unless_present_any(["no_password", "interactive", "new_user_password_stdin"]),
         )
         .arg(arg!(--no_password "Create without password"))
+        .arg(
+            arg!(--interactive "Prompt for the password instead of reading it from the command line")
+                .conflicts_with_all(["password", "no_password"]),
+        )
+        .arg(
+            arg!(--new_user_password_stdin "Read the password from the first line of stdin")
+                .alias("new-user-password-stdin")
+                .conflicts_with_all(["password", "no_password", "interactive"]),
+        )
 }
 
 /// Build request from matches
-pub(super) fn build_request(matches: &ArgMatches) -> AuthUserAddRequest {
+pub(super) fn build_request(matches: &ArgMatches) -> Result<AuthUserAddRequest> {
     let name = matches.get_one::<String>("name").expect("required");
-    let no_password = matches.get_flag("no_password");
-    if no_password {
-        AuthUserAddRequest::new(name)
-    } else {
-        let password = matches.get_one::<String>("password").expect("required");
-        AuthUserAddRequest::new(name).with_pwd(password)
+    if matches.get_flag("no_password") {
+        return Ok(AuthUserAddRequest::new(name));
     }
+    let password = if matches.get_flag("interactive") {
+        read_new_password(name)?
+    } else if matches.get_flag("new_user_password_stdin") {
+        read_password(None)?
+    } else {
+        matches
+            .get_one::<String>("password")
+            .expect("required")
+            .clone()
+    };
+    Ok(AuthUserAddRequest::new(name).with_pwd(password))
 }
 
 /// Execute the command
 pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
-    let req = build_request(matches);
+    let req = build_request(matches)?;
     let resp = client.auth_client().user_add(req).await?;
     resp.print();
 
@@ -42,7 +61,11 @@ mod tests {
     use super::*;
     use crate::test_case_struct;
 
-    test_case_struct!(AuthUserAddRequest);
+    fn build(matches: &ArgMatches) -> AuthUserAddRequest {
+        build_request(matches).unwrap()
+    }
+
+    test_case_struct!(AuthUserAddRequest, build);
 
     #[test]
     fn command_parse_should_be_valid() {
@@ -64,7 +87,22 @@ mod tests {
 
     #[test]
     fn command_parse_should_be_invalid() {
-        let test_cases = vec![TestCase::new(vec!["add", "JaneSmith"], None)];
+        let test_cases = vec![
+            TestCase::new(vec!["add", "JaneSmith"], None),
+            TestCase::new(
+                vec!["add", "--interactive", "JaneSmith", "password123"],
+                None,
+            ),
+            TestCase::new(
+                vec![
+                    "add",
+                    "--new_user_password_stdin",
+                    "--no_password",
+                    "JaneSmith",
+                ],
+                None,
+            ),
+        ];
 
         for case in test_cases {
             case.run_test();
@@ -6,6 +6,7 @@ use crate::utils::printer::Printer;
 /// Definition of `grant_role` command
 pub(super) fn command() -> Command {
     Command::new("grant_role")
+        .visible_alias("grant-role")
         .about("Grant role to a user")
         .arg(arg!(<name> "The name of the user"))
         .arg(arg!(<role> "The name of the role"))
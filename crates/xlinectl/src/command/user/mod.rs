@@ -1,5 +1,6 @@
+use anyhow::Result;
 use clap::{ArgMatches, Command};
-use xline_client::{error::Result, Client};
+use xline_client::Client;
 
 use crate::handle_matches;
 
@@ -1,27 +1,51 @@
+use anyhow::Result;
 use clap::{arg, ArgMatches, Command};
-use xline_client::{error::Result, types::auth::AuthUserChangePasswordRequest, Client};
+use xline_client::{types::auth::AuthUserChangePasswordRequest, Client};
 
-use crate::utils::printer::Printer;
+use crate::utils::{
+    parser::{read_new_password, read_password},
+    printer::Printer,
+};
 
 /// Definition of `passwd` command
-// TODO: interactive mode
 pub(super) fn command() -> Command {
     Command::new("passwd")
         .about("Change the password of a user")
         .arg(arg!(<name> "The name of the user"))
-        .arg(arg!(<password> "Password to change"))
+        .arg(
+            arg!([password] "Password to change")
+                .required_unless_present_any(["interactive", "new_user_password_stdin"]),
+        )
+        .arg(
+            arg!(--interactive "Prompt for the password instead of reading it from the command line")
+                .conflicts_with("password"),
+        )
+        .arg(
+            arg!(--new_user_password_stdin "Read the password from the first line of stdin")
+                .alias("new-user-password-stdin")
+                .conflicts_with_all(["password", "interactive"]),
+        )
 }
 
 /// Build request from matches
-pub(super) fn build_request(matches: &ArgMatches) -> AuthUserChangePasswordRequest {
+pub(super) fn build_request(matches: &ArgMatches) -> Result<AuthUserChangePasswordRequest> {
     let name = matches.get_one::<String>("name").expect("required");
-    let password = matches.get_one::<String>("password").expect("required");
-    AuthUserChangePasswordRequest::new(name, password)
+    let password = if matches.get_flag("interactive") {
+        read_new_password(name)?
+    } else if matches.get_flag("new_user_password_stdin") {
+        read_password(None)?
+    } else {
+        matches
+            .get_one::<String>("password")
+            .expect("required")
+            .clone()
+    };
+    Ok(AuthUserChangePasswordRequest::new(name, password))
 }
 
 /// Execute the command
 pub(super) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result<()> {
-    let req = build_request(matches);
+    let req = build_request(matches)?;
     let resp = client.auth_client().user_change_password(req).await?;
     resp.print();
 
@@ -33,7 +57,11 @@ mod tests {
     use super::*;
     use crate::test_case_struct;
 
-    test_case_struct!(AuthUserChangePasswordRequest);
+    fn build(matches: &ArgMatches) -> AuthUserChangePasswordRequest {
+        build_request(matches).unwrap()
+    }
+
+    test_case_struct!(AuthUserChangePasswordRequest, build);
 
     #[test]
     fn command_parse_should_be_valid() {
@@ -49,4 +77,19 @@ mod tests {
             case.run_test();
         }
     }
+
+    #[test]
+    fn command_parse_should_be_invalid() {
+        let test_cases = vec![
+            TestCase::new(vec!["passwd", "JohnDoe"], None),
+            TestCase::new(
+                vec!["passwd", "--interactive", "JohnDoe", "new_password"],
+                None,
+            ),
+        ];
+
+        for case in test_cases {
+            case.run_test();
+        }
+    }
 }
@@ -6,6 +6,7 @@ use crate::utils::printer::Printer;
 /// Definition of `revoke_role` command
 pub(super) fn command() -> Command {
     Command::new("revoke_role")
+        .visible_alias("revoke-role")
         .about("Revoke role from a user")
         .arg(arg!(<name> "The name of the user"))
         .arg(arg!(<role> "The name of the role"))
@@ -4,10 +4,10 @@ use anyhow::{anyhow, Result};
 use clap::{arg, value_parser, ArgMatches, Command};
 use xline_client::{
     error::XlineClientError,
-    types::watch::{WatchRequest, Watcher},
+    types::watch::{WatchEvent, WatchRequest, Watcher},
     Client,
 };
-use xlineapi::command::Command as XlineCommand;
+use xlineapi::{command::Command as XlineCommand, ResponseHeader, WatchResponse};
 
 use crate::utils::printer::Printer;
 
@@ -77,13 +77,46 @@ pub(crate) async fn execute(client: &mut Client, matches: &ArgMatches) -> Result
             .await
             .map_err(|e| XlineClientError::<XlineCommand>::WatchError(e.to_string()))?
         {
-            resp.print();
+            print_event(resp);
         }
     }
 
     Ok(())
 }
 
+/// Print an item of the watch stream in the format of a watch response
+fn print_event(event: WatchEvent) {
+    let resp = match event {
+        WatchEvent::Events { watch_id, events } => WatchResponse {
+            watch_id,
+            events,
+            ..Default::default()
+        },
+        WatchEvent::Progress { watch_id, revision } => WatchResponse {
+            header: Some(ResponseHeader {
+                revision,
+                ..Default::default()
+            }),
+            watch_id,
+            ..Default::default()
+        },
+        WatchEvent::Canceled {
+            watch_id,
+            reason,
+            compact_revision,
+        } => WatchResponse {
+            watch_id,
+            canceled: true,
+            cancel_reason: reason,
+            compact_revision,
+            ..Default::default()
+        },
+        // unknown items are not printed
+        _ => return,
+    };
+    resp.print();
+}
+
 /// Execute the command in interactive mode
 async fn exec_interactive(client: &mut Client, matches: &ArgMatches) -> Result<()> {
     let req_builder = build_request(matches);
@@ -122,7 +155,7 @@ async fn exec_interactive(client: &mut Client, matches: &ArgMatches) -> Result<(
                 watcher = Some(new_watcher);
                 let _handle = tokio::spawn(async move {
                     while let Some(resp) = stream.message().await? {
-                        resp.print();
+                        print_event(resp);
                     }
                     Ok::<(), XlineClientError<XlineCommand>>(())
                 });
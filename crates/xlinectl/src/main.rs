@@ -168,7 +168,10 @@ use tonic::transport::{Certificate, ClientTlsConfig};
 use xline_client::{Client, ClientOptions};
 
 use crate::{
-    command::{auth, delete, get, lease, lock, member, put, role, snapshot, txn, user, watch},
+    command::{
+        auth, check, delete, endpoint, export, get, import, lease, lock, member, put, role,
+        snapshot, txn, user, watch,
+    },
     utils::{
         parser::parse_user,
         printer::{set_printer_type, PrinterType},
@@ -253,6 +256,8 @@ fn cli() -> Command {
         .subcommand(delete::command())
         .subcommand(lease::command())
         .subcommand(snapshot::command())
+        .subcommand(export::command())
+        .subcommand(import::command())
         .subcommand(auth::command())
         .subcommand(user::command())
         .subcommand(role::command())
@@ -261,6 +266,8 @@ fn cli() -> Command {
         .subcommand(watch::command())
         .subcommand(lock::command())
         .subcommand(member::command())
+        .subcommand(endpoint::command())
+        .subcommand(check::command())
 }
 
 #[tokio::main]
@@ -300,7 +307,7 @@ async fn main() -> Result<()> {
     set_printer_type(printer_type);
 
     let mut client = Client::connect(endpoints, options).await?;
-    handle_matches!(matches, client, { get, put, delete, txn, compaction, lease, snapshot, auth, user, role, watch, lock, member });
+    handle_matches!(matches, client, { get, put, delete, txn, compaction, lease, snapshot, export, import, auth, user, role, watch, lock, member, endpoint, check });
 
     Ok(())
 }
@@ -0,0 +1,173 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Version of the archive format written by `export`
+pub(crate) const ARCHIVE_VERSION: u32 = 1;
+
+/// Header of an archive, the first line of the file
+///
+/// An archive is a sequence of json lines, the header followed by one entry per key
+/// in ascending order of the keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ArchiveHeader {
+    /// Version of the format
+    pub(crate) version: u32,
+    /// The exported prefix
+    #[serde(with = "hex")]
+    pub(crate) prefix: Vec<u8>,
+    /// The revision the keys are exported at
+    pub(crate) revision: i64,
+}
+
+/// An exported key-value, leases are not exported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ArchiveEntry {
+    /// The key
+    #[serde(with = "hex")]
+    pub(crate) key: Vec<u8>,
+    /// The value
+    #[serde(with = "hex")]
+    pub(crate) value: Vec<u8>,
+    /// Create revision in the source cluster, only informational
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) create_revision: Option<i64>,
+    /// Mod revision in the source cluster, only informational
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mod_revision: Option<i64>,
+}
+
+/// Progress of an import, kept next to the archive so that an interrupted import
+/// can be resumed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ImportProgress {
+    /// The last imported key, before the prefix is rewritten
+    #[serde(with = "hex")]
+    pub(crate) last_key: Vec<u8>,
+}
+
+/// Write a line of an archive
+pub(crate) fn write_line<T: Serialize>(writer: &mut impl Write, line: &T) -> Result<()> {
+    serde_json::to_writer(&mut *writer, line)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Read the header of an archive, archives of a newer version are rejected
+pub(crate) fn read_header(reader: &mut impl BufRead) -> Result<ArchiveHeader> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("the archive is empty");
+    }
+    let header: ArchiveHeader =
+        serde_json::from_str(&line).context("cannot decode the archive header")?;
+    if header.version > ARCHIVE_VERSION {
+        bail!(
+            "the archive is of version {}, only versions up to {ARCHIVE_VERSION} are supported",
+            header.version
+        );
+    }
+    Ok(header)
+}
+
+/// Read the entries of an archive after its header
+pub(crate) fn read_entries(reader: impl BufRead) -> impl Iterator<Item = Result<ArchiveEntry>> {
+    reader
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|line| serde_json::from_str(&line?).context("cannot decode an entry of the archive"))
+}
+
+/// Replace the prefix `old` of a key with `new`, keys without the prefix are kept
+pub(crate) fn rewrite_prefix(key: &[u8], old: &[u8], new: &[u8]) -> Vec<u8> {
+    key.strip_prefix(old)
+        .map_or_else(|| key.to_vec(), |rest| [new, rest].concat())
+}
+
+/// Serde of bytes as hexadecimal strings, so that the archive stays a readable text
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    /// Serialize bytes
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    /// Deserialize bytes
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() & 1 == 1 || !hex.is_ascii() {
+            return Err(D::Error::custom(format!("invalid hex string `{hex}`")));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i.wrapping_add(2))
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    .ok_or_else(|| D::Error::custom(format!("invalid hex string `{hex}`")))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_should_round_trip() {
+        let header = ArchiveHeader {
+            version: ARCHIVE_VERSION,
+            prefix: b"foo/".to_vec(),
+            revision: 42,
+        };
+        let entries = vec![
+            ArchiveEntry {
+                key: b"foo/a".to_vec(),
+                value: vec![0, 255, b'\n'],
+                create_revision: Some(2),
+                mod_revision: Some(3),
+            },
+            ArchiveEntry {
+                key: b"foo/b".to_vec(),
+                value: vec![],
+                create_revision: None,
+                mod_revision: None,
+            },
+        ];
+        let mut buf = vec![];
+        write_line(&mut buf, &header).unwrap();
+        for entry in &entries {
+            write_line(&mut buf, entry).unwrap();
+        }
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 3);
+
+        let mut reader = buf.as_slice();
+        assert_eq!(read_header(&mut reader).unwrap(), header);
+        let read: Vec<_> = read_entries(reader).collect::<Result<_>>().unwrap();
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn newer_or_broken_archive_should_be_rejected() {
+        let newer = format!(
+            "{{\"version\":{},\"prefix\":\"\",\"revision\":1}}\n",
+            ARCHIVE_VERSION + 1
+        );
+        assert!(read_header(&mut newer.as_bytes()).is_err());
+        assert!(read_header(&mut "".as_bytes()).is_err());
+        let broken = "{\"key\":\"0g\",\"value\":\"\"}\n";
+        assert!(read_entries(broken.as_bytes()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn prefix_should_be_rewritten() {
+        assert_eq!(rewrite_prefix(b"old/a", b"old/", b"new/"), b"new/a");
+        assert_eq!(rewrite_prefix(b"old/a", b"old/", b""), b"a");
+        assert_eq!(rewrite_prefix(b"other/a", b"old/", b"new/"), b"other/a");
+    }
+}
@@ -15,6 +15,9 @@ macro_rules! handle_matches {
 #[macro_export]
 macro_rules! test_case_struct {
     ($req:ident) => {
+        $crate::test_case_struct!($req, build_request);
+    };
+    ($req:ident, $build:ident) => {
         struct TestCase {
             arg: Vec<&'static str>,
             req: Option<$req>,
@@ -38,7 +41,7 @@ macro_rules! test_case_struct {
                         return;
                     }
                 };
-                let req = build_request(&matches);
+                let req = $build(&matches);
                 assert_eq!(Some(req), self.req);
             }
         }
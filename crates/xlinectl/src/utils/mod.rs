@@ -1,3 +1,5 @@
+/// Archive format of export and import
+pub(crate) mod archive;
 /// Macro utils
 pub(crate) mod macros;
 /// Parser util
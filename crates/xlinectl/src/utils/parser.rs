@@ -1,5 +1,12 @@
-use anyhow::{bail, Result};
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    iter,
+};
+
+use anyhow::{bail, Context, Result};
 use clap::ArgMatches;
+use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+use regex::Regex;
 
 /// Parser user name and password
 pub(crate) fn parse_user(matches: &ArgMatches) -> Result<Option<(String, String)>> {
@@ -32,3 +39,235 @@ pub(crate) fn parse_user(matches: &ArgMatches) -> Result<Option<(String, String)
         Ok(None)
     }
 }
+
+/// Parse a lease id in hexadecimal like etcdctl, with or without the `0x` prefix
+pub(crate) fn parse_lease_id(arg: &str) -> Result<i64> {
+    let digits = arg.strip_prefix("0x").unwrap_or(arg);
+    let Ok(id) = u64::from_str_radix(digits, 16) else {
+        bail!("invalid lease id `{arg}`, it should be in hexadecimal");
+    };
+    let Ok(id) = i64::try_from(id) else {
+        bail!("lease id `{arg}` is out of range");
+    };
+    Ok(id)
+}
+
+/// Parse a RFC 3339 time like `2024-01-02T03:04:05.678+08:00` to the milliseconds
+/// since the unix epoch, the digits of the fraction beyond milliseconds are truncated
+pub(crate) fn parse_rfc3339(arg: &str) -> Result<u64> {
+    let re = Regex::new(
+        r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.(\d+))?(?:[Zz]|([+-])(\d{2}):(\d{2}))$",
+    )
+    .expect("the regex should be valid");
+    let Some(caps) = re.captures(arg) else {
+        bail!("invalid time `{arg}`, it should be in RFC 3339 like 2024-01-02T03:04:05Z");
+    };
+    let field = |i: usize| -> i64 {
+        caps.get(i)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(0)
+    };
+    let (year, month, day) = (field(1), field(2), field(3));
+    let (hour, minute, second) = (field(4), field(5), field(6));
+    let (offset_hour, offset_minute) = (field(9), field(10));
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+        || offset_hour > 23
+        || offset_minute > 59
+    {
+        bail!("invalid time `{arg}`, some fields are out of range");
+    }
+    let millis: i64 = caps
+        .get(7)
+        .map_or("", |m| m.as_str())
+        .chars()
+        .chain(iter::repeat('0'))
+        .take(3)
+        .collect::<String>()
+        .parse()?;
+    let mut offset = offset_hour
+        .wrapping_mul(3600)
+        .wrapping_add(offset_minute.wrapping_mul(60));
+    if caps.get(8).is_some_and(|sign| sign.as_str() == "-") {
+        offset = offset.wrapping_neg();
+    }
+    let secs = days_from_civil(year, month, day)
+        .wrapping_mul(86_400)
+        .wrapping_add(hour.wrapping_mul(3600))
+        .wrapping_add(minute.wrapping_mul(60))
+        .wrapping_add(second)
+        .wrapping_sub(offset);
+    let Ok(time) = u64::try_from(secs.wrapping_mul(1000).wrapping_add(millis)) else {
+        bail!("invalid time `{arg}`, it's before the unix epoch");
+    };
+    Ok(time)
+}
+
+/// Number of days in a month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year.rem_euclid(4) == 0
+            && (year.rem_euclid(100) != 0 || year.rem_euclid(400) == 0) =>
+        {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the unix epoch of a date in the proleptic Gregorian calendar, see
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // years start in March, so that the leap day is the last day of a year
+    let year = if month <= 2 {
+        year.wrapping_sub(1)
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = if month > 2 {
+        month.wrapping_sub(3)
+    } else {
+        month.wrapping_add(9)
+    };
+    let day_of_year = month
+        .wrapping_mul(153)
+        .wrapping_add(2)
+        .wrapping_div(5)
+        .wrapping_add(day)
+        .wrapping_sub(1);
+    let day_of_era = year_of_era
+        .wrapping_mul(365)
+        .wrapping_add(year_of_era.wrapping_div(4))
+        .wrapping_sub(year_of_era.wrapping_div(100))
+        .wrapping_add(day_of_year);
+    era.wrapping_mul(146_097)
+        .wrapping_add(day_of_era)
+        .wrapping_sub(719_468)
+}
+
+/// Parse a prefix rewrite in the form of `old=new`
+pub(crate) fn parse_prefix_rewrite(arg: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let Some((old, new)) = arg.split_once('=') else {
+        bail!("invalid prefix rewrite `{arg}`, it should be in the form of `old=new`");
+    };
+    if old.is_empty() {
+        bail!("invalid prefix rewrite `{arg}`, the old prefix should not be empty");
+    }
+    Ok((old.as_bytes().to_vec(), new.as_bytes().to_vec()))
+}
+
+/// Turns off the echo of the terminal on stdin until it's dropped
+struct EchoOff {
+    /// The attributes of the terminal before the echo is turned off
+    saved: Termios,
+}
+
+impl EchoOff {
+    /// Turn off the echo, returns `None` if stdin is not a terminal
+    fn new() -> Result<Option<Self>> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Ok(None);
+        }
+        let saved = termios::tcgetattr(&stdin).context("failed to get the terminal attributes")?;
+        let mut attrs = saved.clone();
+        // the newline is still echoed, so that the output starts on its own line
+        attrs.local_flags.remove(LocalFlags::ECHO);
+        attrs.local_flags.insert(LocalFlags::ECHONL);
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, &attrs)
+            .context("failed to turn off the echo of the terminal")?;
+        Ok(Some(Self { saved }))
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        let _ignore = termios::tcsetattr(io::stdin(), SetArg::TCSANOW, &self.saved);
+    }
+}
+
+/// Read a password line from stdin, the prompt is written to stderr so that it
+/// won't mix with the printed result, and the input is not echoed while it's typed
+pub(crate) fn read_password(prompt: Option<&str>) -> Result<String> {
+    let _echo_off = EchoOff::new()?;
+    if let Some(prompt) = prompt {
+        eprint!("{prompt}");
+        io::stderr().flush().context("failed to write the prompt")?;
+    }
+    let mut line = String::new();
+    let n = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("failed to read password from stdin")?;
+    if n == 0 {
+        bail!("no password is read from stdin");
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Prompt for a new password twice and check that both inputs match
+pub(crate) fn read_new_password(name: &str) -> Result<String> {
+    let password = read_password(Some(&format!("Password of {name}: ")))?;
+    let confirm = read_password(Some(&format!(
+        "Type password of {name} again for confirmation: "
+    )))?;
+    if password != confirm {
+        bail!("passwords of {name} do not match");
+    }
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_should_be_parsed_to_unix_millis() {
+        let cases = [
+            ("1970-01-01T00:00:00Z", 0),
+            ("1970-01-01T00:00:01.5Z", 1500),
+            ("2000-02-29T12:00:00Z", 951_825_600_000),
+            ("2024-01-02T03:04:05.678901Z", 1_704_164_645_678),
+            ("2024-01-02T11:04:05.678+08:00", 1_704_164_645_678),
+            ("2024-01-01t22:04:05.678-05:00", 1_704_164_645_678),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(parse_rfc3339(arg).unwrap(), expected, "{arg}");
+        }
+    }
+
+    #[test]
+    fn invalid_rfc3339_should_be_rejected() {
+        for arg in [
+            "2024-01-02",
+            "2024-01-02T03:04:05",
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-01-01T24:00:00Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert!(parse_rfc3339(arg).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    fn prefix_rewrite_should_be_parsed() {
+        assert_eq!(
+            parse_prefix_rewrite("old/=new/").unwrap(),
+            (b"old/".to_vec(), b"new/".to_vec())
+        );
+        assert_eq!(
+            parse_prefix_rewrite("old/=").unwrap(),
+            (b"old/".to_vec(), vec![])
+        );
+        assert!(parse_prefix_rewrite("old/").is_err());
+        assert!(parse_prefix_rewrite("=new/").is_err());
+    }
+}
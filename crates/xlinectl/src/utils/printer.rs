@@ -7,10 +7,11 @@ use xlineapi::{
     AuthRoleRevokePermissionResponse, AuthStatusResponse, AuthUserAddResponse,
     AuthUserChangePasswordResponse, AuthUserDeleteResponse, AuthUserGetResponse,
     AuthUserGrantRoleResponse, AuthUserListResponse, AuthUserRevokeRoleResponse,
-    CompactionResponse, DeleteRangeResponse, KeyValue, LeaseGrantResponse, LeaseKeepAliveResponse,
-    LeaseLeasesResponse, LeaseRevokeResponse, LeaseTimeToLiveResponse, LockResponse, Member,
-    MemberAddResponse, MemberListResponse, MemberPromoteResponse, MemberRemoveResponse,
-    MemberUpdateResponse, PutResponse, RangeResponse, ResponseHeader, TxnResponse, WatchResponse,
+    CompactionResponse, DeleteRangeResponse, HashKvResponse, KeyValue, LeaseGrantResponse,
+    LeaseKeepAliveResponse, LeaseLeasesResponse, LeaseRevokeResponse, LeaseTimeToLiveResponse,
+    LockResponse, Member, MemberAddResponse, MemberListResponse, MemberPromoteResponse,
+    MemberRemoveResponse, MemberUpdateResponse, PutResponse, RangeResponse, ResponseHeader,
+    TxnResponse, WatchResponse,
 };
 
 /// The global printer type config
@@ -150,12 +151,12 @@ impl Printer for CompactionResponse {
 
 impl Printer for LeaseGrantResponse {
     fn simple(&self) {
-        println!("{}", self.id);
+        println!("{:016x}", self.id);
     }
 
     fn field(&self) {
         FieldPrinter::header(self.header.as_ref());
-        println!("lease id: {}, granted ttl: {}", self.id, self.ttl);
+        println!("lease id: {:016x}, granted ttl: {}", self.id, self.ttl);
     }
 }
 
@@ -166,21 +167,25 @@ impl Printer for LeaseKeepAliveResponse {
 
     fn field(&self) {
         FieldPrinter::header(self.header.as_ref());
-        println!("lease id: {} keepalived with TTL: {}", self.id, self.ttl);
+        println!(
+            "lease id: {:016x} keepalived with TTL: {}",
+            self.id, self.ttl
+        );
     }
 }
 
 impl Printer for LeaseLeasesResponse {
     fn simple(&self) {
         for lease in &self.leases {
-            println!("{}", lease.id);
+            println!("{:016x}", lease.id);
         }
     }
 
     fn field(&self) {
         FieldPrinter::header(self.header.as_ref());
+        println!("count: {}", self.count);
         for lease in &self.leases {
-            println!("lease: {}", lease.id);
+            println!("lease: {:016x}", lease.id);
         }
     }
 }
@@ -199,12 +204,15 @@ impl Printer for LeaseRevokeResponse {
 impl Printer for LeaseTimeToLiveResponse {
     fn simple(&self) {
         println!("{}", self.ttl);
+        for key in &self.keys {
+            SimplePrinter::utf8(key);
+        }
     }
 
     fn field(&self) {
         FieldPrinter::header(self.header.as_ref());
         println!(
-            "lease id: {}, ttl: {}, granted_ttl: {}",
+            "lease id: {:016x}, ttl: {}, granted_ttl: {}",
             self.id, self.ttl, self.granted_ttl
         );
 
@@ -524,6 +532,7 @@ impl Printer for MemberListResponse {
 
     fn field(&self) {
         FieldPrinter::header(self.header.as_ref());
+        println!("count: {}", self.count);
         println!("members:");
         for member in &self.members {
             FieldPrinter::member(member);
@@ -542,6 +551,19 @@ impl Printer for MemberPromoteResponse {
     }
 }
 
+impl Printer for HashKvResponse {
+    fn simple(&self) {
+        let revision = self.header.as_ref().map_or(0, |header| header.revision);
+        println!("{}, {revision}", self.hash);
+    }
+
+    fn field(&self) {
+        FieldPrinter::header(self.header.as_ref());
+        println!("hash: {}", self.hash);
+        println!("compact revision: {}", self.compact_revision);
+    }
+}
+
 /// convert event type to string
 fn event_type(event: i32) -> String {
     match event {
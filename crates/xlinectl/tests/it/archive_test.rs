@@ -0,0 +1,102 @@
+use std::{env, fs};
+
+use test_macros::abort_on_panic;
+use xline_client::types::kv::{PutRequest, RangeRequest, TxnOp, TxnRequest};
+use xline_test_utils::Cluster;
+
+use super::common::xlinectl_ok;
+
+const KEYS: usize = 50_000;
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_export_and_import_round_trip() {
+    let mut source = Cluster::new(3).await;
+    source.start().await;
+    let mut target = Cluster::new(3).await;
+    target.start().await;
+    let source_ep = source.all_client_addrs().join(",");
+    let target_ep = target.all_client_addrs().join(",");
+
+    let client = source.client().await.kv_client();
+    let keys: Vec<_> = (0..KEYS).map(|i| format!("src/{i:05}")).collect();
+    for chunk in keys.chunks(128) {
+        let ops: Vec<_> = chunk
+            .iter()
+            .map(|key| TxnOp::put(PutRequest::new(key.as_str(), format!("value of {key}"))))
+            .collect();
+        let _resp = client.txn(TxnRequest::new().and_then(ops)).await.unwrap();
+    }
+    let _resp = client.put(PutRequest::new("other", "value")).await.unwrap();
+
+    let dir = env::temp_dir().join(format!("xlinectl-archive-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let archive = dir.join("src.archive");
+    let archive = archive.to_str().unwrap();
+
+    // the blocking process calls must not stall the clusters running on this runtime
+    tokio::task::block_in_place(|| {
+        let out = xlinectl_ok(
+            &source_ep,
+            &[
+                "export",
+                "--prefix",
+                "src/",
+                "--output",
+                archive,
+                "--with_revisions",
+            ],
+            None,
+        );
+        assert!(out.starts_with(&format!("exported {KEYS} keys")), "{out}");
+        let out = xlinectl_ok(
+            &target_ep,
+            &["import", archive, "--prefix-rewrite", "src/=dst/"],
+            None,
+        );
+        assert!(out.starts_with(&format!("imported {KEYS} keys")), "{out}");
+    });
+
+    let client = target.client().await.kv_client();
+    let resp = client
+        .range(RangeRequest::new("dst/").with_prefix())
+        .await
+        .unwrap();
+    assert_eq!(resp.kvs.len(), KEYS);
+    for (kv, key) in resp.kvs.iter().zip(&keys) {
+        let src_key = key.as_str();
+        assert_eq!(kv.key, src_key.replacen("src/", "dst/", 1).as_bytes());
+        assert_eq!(kv.value, format!("value of {src_key}").as_bytes());
+        assert_eq!(kv.lease, 0);
+    }
+    let resp = client.range(RangeRequest::new("other")).await.unwrap();
+    assert!(resp.kvs.is_empty());
+
+    // an interrupted import is resumed after the last imported key
+    let progress = format!("{archive}.progress");
+    let half = &keys[KEYS / 2 - 1];
+    let hex: String = half.bytes().map(|b| format!("{b:02x}")).collect();
+    fs::write(&progress, format!("{{\"last_key\":\"{hex}\"}}\n")).unwrap();
+    tokio::task::block_in_place(|| {
+        let _ = xlinectl_ok(
+            &target_ep,
+            &[
+                "import",
+                archive,
+                "--prefix-rewrite",
+                "src/=resumed/",
+                "--resume",
+            ],
+            None,
+        );
+    });
+    assert!(fs::metadata(&progress).is_err());
+    let resp = client
+        .range(RangeRequest::new("resumed/").with_prefix())
+        .await
+        .unwrap();
+    assert_eq!(resp.kvs.len(), KEYS / 2);
+    assert_eq!(resp.kvs[0].key, b"resumed/25000");
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,51 @@
+use test_macros::abort_on_panic;
+use xline_test_utils::Cluster;
+
+use super::common::xlinectl_ok;
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_check_health_should_pass_and_clean_up() {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let endpoints = cluster.all_client_addrs().join(",");
+    let ep = endpoints.as_str();
+
+    // the blocking process calls must not stall the cluster running on this runtime
+    tokio::task::block_in_place(|| {
+        let report = xlinectl_ok(
+            ep,
+            &[
+                "check",
+                "health",
+                "--duration",
+                "4s",
+                "--prefix",
+                "/canary/",
+            ],
+            None,
+        );
+        let lines: Vec<_> = report.lines().collect();
+        assert!(
+            lines.last().unwrap().starts_with("PASS: "),
+            "the canary should pass: {report}"
+        );
+        for op in ["put", "range", "txn", "keep-alive"] {
+            assert!(
+                lines
+                    .iter()
+                    .any(|l| l.starts_with(&format!("{op}: count ")) && l.contains("p99")),
+                "latencies of {op} should be reported: {report}"
+            );
+        }
+
+        // neither the keys nor the lease are left behind
+        let keys = xlinectl_ok(ep, &["get", "/canary/", "--prefix"], None);
+        assert!(keys.trim().is_empty(), "{keys}");
+        let leases = xlinectl_ok(ep, &["lease", "list"], None);
+        assert!(
+            !leases.lines().any(|l| l.len() == 16),
+            "the lease should be revoked: {leases}"
+        );
+    });
+}
@@ -0,0 +1,35 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Run `xlinectl` against the given endpoints, feeding `stdin` to the process
+pub(crate) fn xlinectl(endpoints: &str, args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xlinectl"))
+        .arg("--endpoints")
+        .arg(endpoints)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let mut child_stdin = child.stdin.take().unwrap();
+        if let Some(input) = stdin {
+            child_stdin.write_all(input.as_bytes()).unwrap();
+        }
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// Run `xlinectl` and assert it succeeds, returns the stdout
+pub(crate) fn xlinectl_ok(endpoints: &str, args: &[&str], stdin: Option<&str>) -> String {
+    let output = xlinectl(endpoints, args, stdin);
+    assert!(
+        output.status.success(),
+        "xlinectl {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
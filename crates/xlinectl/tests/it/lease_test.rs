@@ -0,0 +1,62 @@
+use test_macros::abort_on_panic;
+use xline_test_utils::Cluster;
+
+use super::common::{xlinectl, xlinectl_ok};
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_commands() {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let endpoints = cluster.all_client_addrs().join(",");
+    let ep = endpoints.as_str();
+
+    // the blocking process calls must not stall the cluster running on this runtime
+    tokio::task::block_in_place(|| {
+        let id = xlinectl_ok(ep, &["lease", "grant", "60"], None);
+        let id = id.trim();
+        assert_eq!(id.len(), 16, "lease id {id} should be printed in hex");
+        let _ = xlinectl_ok(ep, &["put", "foo", "bar", "--lease", id], None);
+
+        let ttl = xlinectl_ok(ep, &["lease", "timetolive", id, "--keys"], None);
+        let lines: Vec<_> = ttl.lines().collect();
+        assert!(lines[0].parse::<i64>().unwrap() > 0);
+        assert_eq!(lines[1..], ["foo"]);
+
+        let list = xlinectl_ok(ep, &["lease", "list"], None);
+        assert!(list.lines().any(|l| l == id), "{list}");
+
+        let resp = xlinectl_ok(
+            ep,
+            &[
+                "--printer_type",
+                "JSON",
+                "lease",
+                "keep-alive",
+                id,
+                "--once",
+            ],
+            None,
+        );
+        let resp: serde_json::Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["ttl"], 60);
+        assert_eq!(
+            resp["id"].as_i64().unwrap(),
+            i64::from_str_radix(id, 16).unwrap()
+        );
+
+        assert_eq!(
+            xlinectl_ok(ep, &["lease", "revoke", id], None).trim(),
+            "Revoked"
+        );
+        assert!(!xlinectl(ep, &["lease", "timetolive", id], None)
+            .status
+            .success());
+        assert!(!xlinectl(ep, &["lease", "revoke", "not-a-lease"], None)
+            .status
+            .success());
+        assert!(!xlinectl(ep, &["lease", "grant", "-1"], None)
+            .status
+            .success());
+    });
+}
@@ -0,0 +1,6 @@
+mod archive_test;
+mod check_test;
+mod common;
+mod lease_test;
+mod member_test;
+mod rbac_test;
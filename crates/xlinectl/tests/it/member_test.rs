@@ -0,0 +1,74 @@
+use test_macros::abort_on_panic;
+use tokio::{net::TcpListener, task::block_in_place};
+use xline_test_utils::{types::cluster::MemberListRequest, Cluster};
+
+use super::common::{xlinectl, xlinectl_ok};
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_member_replace_should_resume_and_replace_the_stopped_member() {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let stopped_peer_url = cluster.get_peer_url(2);
+    let old = cluster
+        .client()
+        .await
+        .cluster_client()
+        .member_list(MemberListRequest::new(true))
+        .await
+        .unwrap()
+        .members
+        .into_iter()
+        .find(|m| m.peer_ur_ls.contains(&stopped_peer_url))
+        .unwrap()
+        .id;
+    cluster.stop_node(2).await;
+    let endpoints = cluster.all_client_addrs()[..2].join(",");
+    let ep = endpoints.as_str();
+
+    let client_listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let peer_listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let new_peer_url = format!("http://{}", peer_listener.local_addr().unwrap());
+    let old_id = old.to_string();
+    let replace = [
+        "member",
+        "replace",
+        "--old",
+        old_id.as_str(),
+        "--new_peer_urls",
+        new_peer_url.as_str(),
+    ];
+
+    // the blocking process calls must not stall the cluster running on this runtime
+    block_in_place(|| {
+        // the new member is not started yet, so the learner never catches up
+        let output = xlinectl(
+            ep,
+            &[&replace[..], &["--learner_wait", "2s"]].concat(),
+            None,
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!output.status.success(), "{stdout}");
+        assert!(stdout.contains("added learner "), "{stdout}");
+    });
+
+    cluster.run_node(client_listener, peer_listener).await;
+    block_in_place(|| {
+        let report = xlinectl_ok(ep, &replace, None);
+        assert!(
+            report.starts_with("resuming: learner "),
+            "the replacement should continue from the learner: {report}"
+        );
+        assert!(report.contains("promoted learner "), "{report}");
+        assert!(
+            report.contains(&format!("removed member {old}")),
+            "{report}"
+        );
+        let members = xlinectl_ok(ep, &["member", "list"], None);
+        assert_eq!(members.lines().count(), 3, "{members}");
+        assert!(!members.lines().any(|l| l == old_id), "{members}");
+
+        let report = xlinectl_ok(ep, &replace, None);
+        assert!(report.contains("nothing to do"), "{report}");
+    });
+}
@@ -0,0 +1,68 @@
+use test_macros::abort_on_panic;
+use xline_test_utils::Cluster;
+
+use super::common::{xlinectl, xlinectl_ok};
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_rbac_setup_flow() {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let endpoints = cluster.all_client_addrs().join(",");
+    let ep = endpoints.as_str();
+
+    // the blocking process calls must not stall the cluster running on this runtime
+    tokio::task::block_in_place(|| {
+        let _ = xlinectl_ok(
+            ep,
+            &["user", "add", "root", "--new-user-password-stdin"],
+            Some("rootpw\n"),
+        );
+        let _ = xlinectl_ok(ep, &["role", "add", "root"], None);
+        let _ = xlinectl_ok(ep, &["user", "grant-role", "root", "root"], None);
+        let _ = xlinectl_ok(
+            ep,
+            &["user", "add", "alice", "--interactive"],
+            Some("alicepw\nalicepw\n"),
+        );
+        let _ = xlinectl_ok(ep, &["role", "add", "reader"], None);
+        let _ = xlinectl_ok(
+            ep,
+            &[
+                "role",
+                "grant-permission",
+                "reader",
+                "Read",
+                "foo",
+                "--prefix",
+            ],
+            None,
+        );
+        let _ = xlinectl_ok(ep, &["user", "grant-role", "alice", "reader"], None);
+
+        let user = xlinectl_ok(
+            ep,
+            &["--printer_type", "JSON", "user", "get", "alice"],
+            None,
+        );
+        let user: serde_json::Value = serde_json::from_str(&user).unwrap();
+        assert_eq!(user["roles"], serde_json::json!(["reader"]));
+
+        let _ = xlinectl_ok(ep, &["auth", "enable"], None);
+        let status = xlinectl_ok(ep, &["--user", "root:rootpw", "auth", "status"], None);
+        assert!(status.starts_with("enabled: true"), "{status}");
+
+        let _ = xlinectl_ok(ep, &["--user", "root:rootpw", "put", "foo1", "bar"], None);
+
+        let denied = xlinectl(ep, &["--user", "alice:alicepw", "put", "foo1", "baz"], None);
+        assert!(
+            !denied.status.success(),
+            "alice should not be able to write"
+        );
+
+        let value = xlinectl_ok(ep, &["--user", "alice:alicepw", "get", "foo1"], None);
+        assert_eq!(value.lines().collect::<Vec<_>>(), ["foo1", "bar"]);
+
+        let _ = xlinectl_ok(ep, &["--user", "root:rootpw", "auth", "disable"], None);
+    });
+}
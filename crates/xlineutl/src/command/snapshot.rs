@@ -8,7 +8,7 @@ use clap::{arg, ArgMatches, Command};
 use engine::{Engine, EngineType, StorageEngine};
 use serde::Serialize;
 use tempfile::tempdir;
-use utils::table_names::{KV_TABLE, XLINE_TABLES};
+use utils::table_names::{KV_TABLE, REPLICATED_TABLES, XLINE_TABLES};
 use xline::storage::Revision;
 
 use crate::printer::Printer;
@@ -56,7 +56,7 @@ async fn handle_restore<P: AsRef<Path>, D: Into<PathBuf>>(
 ) -> Result<()> {
     let restore_rocks_engine = Engine::new(EngineType::Rocks(data_dir.into()), &XLINE_TABLES)?;
     restore_rocks_engine
-        .apply_snapshot_from_file(snapshot_path, &XLINE_TABLES)
+        .apply_snapshot_from_file(snapshot_path, &REPLICATED_TABLES)
         .await?;
     Ok(())
 }
@@ -84,14 +84,14 @@ async fn handle_status<P: AsRef<Path>>(snapshot_path: P) -> Result<()> {
         &XLINE_TABLES,
     )?;
     restore_rocks_engine
-        .apply_snapshot_from_file(snapshot_path, &XLINE_TABLES)
+        .apply_snapshot_from_file(snapshot_path, &REPLICATED_TABLES)
         .await?;
     let mut status = Status {
         total_size: restore_rocks_engine.file_size()?,
         ..Default::default()
     };
     let mut hasher = crc32fast::Hasher::new();
-    for table in XLINE_TABLES {
+    for table in REPLICATED_TABLES {
         hasher.write(table.as_bytes());
         let kv_pairs = restore_rocks_engine.get_all(table)?;
         let is_kv_table = table == KV_TABLE;